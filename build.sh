@@ -181,7 +181,12 @@ function build() {
     ################################################################################
 
     if [[ "$(uname -s)" == "MINGW"* ]]; then
-        CPP_BUILD_MODES="Release" ./build_windows_modules.sh "$@"
+        local cpp_build_target="x64"
+        if [[ "$current_target" == "aarch64-pc-windows-msvc" ]]; then
+            cpp_build_target="ARM64"
+        fi
+        CPP_BUILD_MODES="Release" CPP_BUILD_TARGETS="$cpp_build_target" \
+            ./build_windows_modules.sh "$@"
     fi
 
     ################################################################################
@@ -4,7 +4,7 @@ set -eu
 # Default configurations generated by Visual Studio are "Release" and "Debug".
 CPP_BUILD_MODES=${CPP_BUILD_MODES:-"Debug"}
 # List of target platforms to build for.
-# Common platforms include "x86" and "x64".
+# Common platforms include "x86", "x64" and "ARM64".
 CPP_BUILD_TARGETS=${CPP_BUILD_TARGETS:-"x64"}
 # Override this to set a different cargo target directory
 CARGO_TARGET_DIR=${CARGO_TARGET_DIR:-"./target/"}
@@ -79,6 +79,9 @@ function get_solution_output_path
     "x64")
       echo "$solution_root/bin/x64-$build_mode"
       ;;
+    "ARM64")
+      echo "$solution_root/bin/ARM64-$build_mode"
+      ;;
     *)
       echo Unkown build target $build_target
       exit 1
@@ -137,6 +140,9 @@ function arch_from_build_target
     "x64")
       echo "x86_64"
       ;;
+    "ARM64")
+      echo "aarch64"
+      ;;
     *)
       echo $build_target
       ;;
@@ -3,11 +3,23 @@ set -eu
 
 chmod u+s "/usr/bin/mullvad-exclude"
 
-if which systemctl &> /dev/null; then
+if which systemctl &> /dev/null && [ -d /run/systemd/system ]; then
     systemctl enable "/opt/Mullvad VPN/resources/mullvad-daemon.service"
+    systemctl enable "/opt/Mullvad VPN/resources/mullvad-early-boot-blocking.service"
     systemctl start mullvad-daemon.service
-elif /sbin/init --version | grep upstart &> /dev/null; then
+elif which rc-update &> /dev/null; then
+    install -m 755 "/opt/Mullvad VPN/resources/mullvad-daemon.openrc" /etc/init.d/mullvad-daemon
+    install -m 755 "/opt/Mullvad VPN/resources/mullvad-early-boot-blocking.openrc" \
+        /etc/init.d/mullvad-early-boot-blocking
+    rc-update add mullvad-early-boot-blocking boot
+    rc-update add mullvad-daemon default
+    rc-service mullvad-daemon start
+elif /sbin/init --version 2>/dev/null | grep upstart &> /dev/null; then
     ln -s "/opt/Mullvad VPN/resources/mullvad-daemon.conf" /etc/init/
     initctl reload-configuration
     start mullvad-daemon
+else
+    echo "Unknown init system. Start '/opt/Mullvad VPN/resources/mullvad-daemon' as root at boot,"
+    echo "and optionally run '/opt/Mullvad VPN/resources/mullvad-setup initialize-early-boot-firewall'"
+    echo "before the network is brought up to block traffic until the daemon has started."
 fi
@@ -14,11 +14,17 @@ if [[ "$1" == "upgrade" ]]; then
     exit 0;
 fi
 
-if which systemctl &> /dev/null; then
+if which systemctl &> /dev/null && [ -d /run/systemd/system ]; then
     # the user might've disabled or stopped the service themselves already
     systemctl stop mullvad-daemon.service || true
     systemctl disable mullvad-daemon.service || true
-elif /sbin/init --version | grep upstart &> /dev/null; then
+    systemctl disable mullvad-early-boot-blocking.service || true
+elif which rc-update &> /dev/null; then
+    rc-service mullvad-daemon stop || true
+    rc-update del mullvad-daemon default || true
+    rc-update del mullvad-early-boot-blocking boot || true
+    rm -f /etc/init.d/mullvad-daemon /etc/init.d/mullvad-early-boot-blocking
+elif /sbin/init --version 2>/dev/null | grep upstart &> /dev/null; then
     stop mullvad-daemon
     rm -f /etc/init/mullvad-daemon.conf
 fi
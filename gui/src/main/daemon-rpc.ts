@@ -36,10 +36,13 @@ import {
   TunnelType,
   IProxyEndpoint,
   ProxyType,
+  IObfuscationEndpoint,
+  ObfuscationType,
   KeygenEvent,
   IWireguardPublicKey,
   ISettings,
   ConnectionConfig,
+  ConflictKind,
   DaemonEvent,
   RelaySettings,
   RelaySettingsUpdate,
@@ -53,7 +56,7 @@ import log from '../shared/logging';
 
 import * as managementInterface from './management_interface/management_interface_grpc_pb';
 import * as grpcTypes from './management_interface/management_interface_pb';
-import { CommunicationError, InvalidAccountError } from './errors';
+import { CommunicationError, InvalidAccountError, RateLimitedError } from './errors';
 
 const NETWORK_CALL_TIMEOUT = 10000;
 const CHANNEL_STATE_TIMEOUT = 1000 * 60 * 60;
@@ -189,6 +192,10 @@ export class DaemonRpc {
       return { expiry };
     } catch (e) {
       const error = e as grpc.ServiceError;
+      const rateLimit = getRateLimit(error);
+      if (rateLimit) {
+        throw new RateLimitedError(rateLimit.retryAfter);
+      }
       if (error.code) {
         switch (error.code) {
           case grpc.status.UNAUTHENTICATED:
@@ -230,12 +237,18 @@ export class DaemonRpc {
       };
     } catch (e) {
       const error = e as grpc.ServiceError;
+      const rateLimit = getRateLimit(error);
+      if (rateLimit) {
+        return { type: 'rate_limited', retryAfter: rateLimit.retryAfter };
+      }
       if (error.code) {
         switch (error.code) {
           case grpc.status.NOT_FOUND:
             return { type: 'invalid' };
           case grpc.status.RESOURCE_EXHAUSTED:
             return { type: 'already_used' };
+          case grpc.status.FAILED_PRECONDITION:
+            return { type: 'expired' };
         }
       }
       return { type: 'error' };
@@ -399,7 +412,9 @@ export class DaemonRpc {
   }
 
   public subscribeDaemonEventListener(listener: SubscriptionListener<DaemonEvent>) {
-    const call = this.isConnected && this.client.eventsListen(new Empty());
+    const request = new grpcTypes.EventsListenRequest();
+    request.setIncludeFullSettings(true);
+    const call = this.isConnected && this.client.eventsListen(request);
     if (!call) {
       throw noConnectionError;
     }
@@ -651,6 +666,27 @@ export class DaemonRpc {
   }
 }
 
+// Rate limited requests have an API error with the time to wait attached to the status details.
+function getRateLimit(error: grpc.ServiceError): { retryAfter?: number } | undefined {
+  if (error.code !== grpc.status.RESOURCE_EXHAUSTED || !error.metadata) {
+    return undefined;
+  }
+  const details = error.metadata.get('grpc-status-details-bin')[0];
+  if (details === undefined || typeof details === 'string') {
+    return undefined;
+  }
+  try {
+    const apiError = grpcTypes.ApiError.deserializeBinary(details);
+    if (apiError.getCode() !== grpcTypes.ApiError.Code.RATE_LIMITED) {
+      return undefined;
+    }
+    const retryAfter = apiError.getRetryAfter();
+    return { retryAfter: retryAfter > 0 ? retryAfter : undefined };
+  } catch {
+    return undefined;
+  }
+}
+
 function liftConstraint<T>(constraint: Constraint<T> | undefined): T | undefined {
   if (constraint !== undefined && constraint !== 'any') {
     return constraint.only;
@@ -773,6 +809,7 @@ function convertFromTunnelState(tunnelState: grpcTypes.TunnelState): TunnelState
         relayInfo && {
           state: 'connected',
           details: relayInfo,
+          featureIndicators: tunnelStateObject.connected?.featureIndicators,
         }
       );
     }
@@ -819,14 +856,24 @@ function convertFromTunnelStateErrorCause(
         [grpcTypes.ErrorState.GenerationError.NO_WIREGUARD_KEY]: 'no_wireguard_key',
         [grpcTypes.ErrorState.GenerationError.CUSTOM_TUNNEL_HOST_RESOLUTION_ERROR]:
           'custom_tunnel_host_resultion_error',
+        [grpcTypes.ErrorState.GenerationError.UNKNOWN_GENERATION_ERROR]: 'unknown',
+      };
+      return {
+        reason: 'tunnel_parameter_error',
+        details: parameterErrorMap[state.parameterError] ?? 'unknown',
       };
-      return { reason: 'tunnel_parameter_error', details: parameterErrorMap[state.parameterError] };
     }
     case grpcTypes.ErrorState.Cause.SPLIT_TUNNEL_ERROR:
       return { reason: 'split_tunnel_error' };
+    case grpcTypes.ErrorState.Cause.CAPTIVE_PORTAL_LOGIN:
+      return { reason: 'captive_portal_login' };
     case grpcTypes.ErrorState.Cause.VPN_PERMISSION_DENIED:
       // VPN_PERMISSION_DENIED is only ever created on Android
       throw invalidErrorStateCause;
+    case grpcTypes.ErrorState.Cause.UNKNOWN_CAUSE:
+    default:
+      // The cause may have been added in a newer version of the daemon
+      return { reason: 'unknown' };
   }
 }
 
@@ -841,6 +888,9 @@ function convertFromFirewallPolicyError(
       const name = error.lockName;
       return { reason: 'locked', details: pid && name ? { pid, name } : undefined };
     }
+    case grpcTypes.ErrorState.FirewallPolicyError.ErrorType.UNKNOWN:
+    default:
+      return { reason: 'unknown' };
   }
 }
 
@@ -855,6 +905,9 @@ function convertFromTunnelStateRelayInfo(
         tunnelType: convertFromTunnelType(state.tunnelEndpoint.tunnelType),
         protocol: convertFromTransportProtocol(state.tunnelEndpoint.protocol),
         proxy: state.tunnelEndpoint.proxy && convertFromProxyEndpoint(state.tunnelEndpoint.proxy),
+        obfuscation:
+          state.tunnelEndpoint.obfuscation &&
+          convertFromObfuscationEndpoint(state.tunnelEndpoint.obfuscation),
       },
     };
   }
@@ -883,6 +936,25 @@ function convertFromProxyEndpoint(proxyEndpoint: grpcTypes.ProxyEndpoint.AsObjec
   };
 }
 
+function convertFromObfuscationEndpoint(
+  obfuscationEndpoint: grpcTypes.ObfuscationEndpoint.AsObject,
+): IObfuscationEndpoint {
+  const obfuscationTypeMap: Record<
+    grpcTypes.ObfuscationEndpoint.ObfuscationType,
+    ObfuscationType
+  > = {
+    [grpcTypes.ObfuscationEndpoint.ObfuscationType.UDP2TCP]: 'udp2tcp',
+    [grpcTypes.ObfuscationEndpoint.ObfuscationType.QUIC]: 'quic',
+    [grpcTypes.ObfuscationEndpoint.ObfuscationType.SHADOWSOCKS]: 'shadowsocks',
+  };
+
+  return {
+    ...obfuscationEndpoint,
+    protocol: convertFromTransportProtocol(obfuscationEndpoint.protocol),
+    obfuscationType: obfuscationTypeMap[obfuscationEndpoint.obfuscationType],
+  };
+}
+
 function convertFromSettings(settings: grpcTypes.Settings): ISettings | undefined {
   const settingsObject = settings.toObject();
   const bridgeState = convertFromBridgeState(settingsObject.bridgeState!.state!);
@@ -1092,7 +1164,7 @@ function convertFromDaemonEvent(data: grpcTypes.DaemonEvent): DaemonEvent {
     return { tunnelState: convertFromTunnelState(tunnelState)! };
   }
 
-  const settings = data.getSettings();
+  const settings = data.getSettingsChanged()?.getSettings();
   if (settings !== undefined) {
     return { settings: convertFromSettings(settings)! };
   }
@@ -1117,11 +1189,64 @@ function convertFromDaemonEvent(data: grpcTypes.DaemonEvent): DaemonEvent {
     };
   }
 
+  const deviceState = data.getDeviceState();
+  if (deviceState !== undefined) {
+    return {
+      deviceState:
+        deviceState.getState() === grpcTypes.DeviceState.State.REVOKED ? 'revoked' : 'active',
+    };
+  }
+
+  const accountExpiry = data.getAccountExpiry();
+  if (accountExpiry !== undefined) {
+    const expiry = accountExpiry.getExpiry()!.toDate().toISOString();
+    return {
+      accountExpiry:
+        accountExpiry.getKind() === grpcTypes.AccountExpiryEvent.Kind.EXPIRED
+          ? { type: 'expired', expiry }
+          : { type: 'expires_soon', expiry, threshold: accountExpiry.getThreshold() },
+    };
+  }
+
+  const conflictingSoftware = data.getConflictingSoftware();
+  if (conflictingSoftware !== undefined) {
+    return {
+      conflictingSoftware: conflictingSoftware.getConflictsList().map((conflict) => ({
+        kind: convertFromConflictKind(conflict.getKind()),
+        name: conflict.getName(),
+      })),
+    };
+  }
+
+  const deviceIdentity = data.getDeviceIdentity();
+  if (deviceIdentity !== undefined) {
+    return {
+      deviceIdentity: {
+        id: deviceIdentity.getId(),
+        previousName: deviceIdentity.getPreviousName(),
+        name: deviceIdentity.getName(),
+      },
+    };
+  }
+
   return {
     appVersionInfo: data.getVersionInfo()!.toObject(),
   };
 }
 
+function convertFromConflictKind(kind: grpcTypes.ConflictingSoftware.Kind): ConflictKind {
+  switch (kind) {
+    case grpcTypes.ConflictingSoftware.Kind.WFP_PROVIDER:
+      return 'wfp_provider';
+    case grpcTypes.ConflictingSoftware.Kind.TUNNEL_INTERFACE:
+      return 'tunnel_interface';
+    case grpcTypes.ConflictingSoftware.Kind.NETWORK_MANAGER_VPN:
+      return 'network_manager_vpn';
+    case grpcTypes.ConflictingSoftware.Kind.SYSTEM_VPN:
+      return 'system_vpn';
+  }
+}
+
 function convertFromKeygenEvent(data: grpcTypes.KeygenEvent): KeygenEvent {
   switch (data.getEvent()) {
     case grpcTypes.KeygenEvent.KeygenEvent.TOO_MANY_KEYS:
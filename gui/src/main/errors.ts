@@ -10,6 +10,13 @@ export class InvalidAccountError extends Error {
   }
 }
 
+export class RateLimitedError extends Error {
+  // Number of seconds to wait before trying again, if known.
+  constructor(public readonly retryAfter?: number) {
+    super('Too many requests');
+  }
+}
+
 export class CommunicationError extends Error {
   constructor() {
     super('api.mullvad.net is blocked, please check your firewall');
@@ -27,6 +27,7 @@ import {
   DaemonEvent,
   IAccountData,
   IAppVersionInfo,
+  IConflictingSoftware,
   IDnsOptions,
   IRelayList,
   ISettings,
@@ -46,6 +47,7 @@ import { ICurrentAppVersionInfo } from '../shared/ipc-types';
 import {
   AccountExpiredNotificationProvider,
   CloseToAccountExpiryNotificationProvider,
+  ConflictingSoftwareNotificationProvider,
   InconsistentVersionNotificationProvider,
   UnsupportedVersionNotificationProvider,
   UpdateAvailableNotificationProvider,
@@ -54,7 +56,7 @@ import { Scheduler } from '../shared/scheduler';
 import AccountDataCache from './account-data-cache';
 import { getOpenAtLogin, setOpenAtLogin } from './autostart';
 import { ConnectionObserver, DaemonRpc, SubscriptionListener } from './daemon-rpc';
-import { InvalidAccountError } from './errors';
+import { InvalidAccountError, RateLimitedError } from './errors';
 import Expectation from './expectation';
 import GuiSettings from './gui-settings';
 import { findIconPath } from './linux-desktop-entry';
@@ -707,6 +709,18 @@ class ApplicationMain {
           this.handleWireguardKeygenEvent(daemonEvent.wireguardKey);
         } else if ('appVersionInfo' in daemonEvent) {
           this.setLatestVersion(daemonEvent.appVersionInfo);
+        } else if ('deviceState' in daemonEvent) {
+          log.info(`Device state changed: ${daemonEvent.deviceState}`);
+        } else if ('accountExpiry' in daemonEvent) {
+          log.info(
+            `Account expiry event: ${daemonEvent.accountExpiry.type}, expires at ${daemonEvent.accountExpiry.expiry}`,
+          );
+        } else if ('conflictingSoftware' in daemonEvent) {
+          this.handleConflictingSoftware(daemonEvent.conflictingSoftware);
+        } else if ('deviceIdentity' in daemonEvent) {
+          log.info(
+            `Device renamed from ${daemonEvent.deviceIdentity.previousName} to ${daemonEvent.deviceIdentity.name}`,
+          );
         }
       },
       (error: Error) => {
@@ -742,6 +756,17 @@ class ApplicationMain {
     }
   }
 
+  private handleConflictingSoftware(conflicts: IConflictingSoftware[]) {
+    log.info(
+      `Conflicting software: ${conflicts.map((conflict) => conflict.name).join(', ') || 'none'}`,
+    );
+
+    const notificationProvider = new ConflictingSoftwareNotificationProvider({ conflicts });
+    if (notificationProvider.mayDisplay()) {
+      this.notificationController.notify(notificationProvider.getSystemNotification());
+    }
+  }
+
   private handleWireguardKeygenEvent(event: KeygenEvent) {
     switch (event) {
       case 'too_many_keys':
@@ -1401,6 +1426,17 @@ class ApplicationMain {
 
       if (error instanceof InvalidAccountError) {
         throw Error(messages.gettext('Invalid account number'));
+      } else if (error instanceof RateLimitedError) {
+        throw Error(
+          error.retryAfter !== undefined
+            ? sprintf(
+                // TRANSLATORS: Available placeholders:
+                // TRANSLATORS: %(seconds)s - the number of seconds to wait
+                messages.gettext('Too many login attempts. Try again in %(seconds)s seconds.'),
+                { seconds: error.retryAfter },
+              )
+            : messages.gettext('Too many login attempts. Try again later.'),
+        );
       } else {
         throw error;
       }
@@ -1459,7 +1495,7 @@ class ApplicationMain {
       this.accountDataCache.fetch(accountToken, {
         onFinish: () => resolve({ status: 'verified' }),
         onError: (error) => {
-          if (error instanceof InvalidAccountError) {
+          if (error instanceof InvalidAccountError || error instanceof RateLimitedError) {
             reject(error);
           } else {
             resolve({ status: 'deferred', error });
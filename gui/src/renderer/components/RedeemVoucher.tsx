@@ -183,6 +183,28 @@ export function RedeemVoucherResponse() {
             {messages.pgettext('redeem-voucher-view', 'Voucher code has already been used.')}
           </StyledErrorResponse>
         );
+      case 'expired':
+        return (
+          <StyledErrorResponse>
+            {messages.pgettext('redeem-voucher-view', 'Voucher code has expired.')}
+          </StyledErrorResponse>
+        );
+      case 'rate_limited':
+        return (
+          <StyledErrorResponse>
+            {response.retryAfter !== undefined
+              ? sprintf(
+                  // TRANSLATORS: Available placeholders:
+                  // TRANSLATORS: %(seconds)s - the number of seconds to wait
+                  messages.pgettext(
+                    'redeem-voucher-view',
+                    'Too many attempts. Try again in %(seconds)s seconds.',
+                  ),
+                  { seconds: response.retryAfter },
+                )
+              : messages.pgettext('redeem-voucher-view', 'Too many attempts. Try again later.')}
+          </StyledErrorResponse>
+        );
       case 'error':
         return (
           <StyledErrorResponse>
@@ -18,7 +18,7 @@ export interface ILocation {
 }
 
 export type FirewallPolicyError =
-  | { reason: 'generic' }
+  | { reason: 'generic' | 'unknown' }
   | {
       reason: 'locked';
       details?: {
@@ -31,7 +31,8 @@ export type TunnelParameterError =
   | 'no_matching_relay'
   | 'no_matching_bridge_relay'
   | 'no_wireguard_key'
-  | 'custom_tunnel_host_resultion_error';
+  | 'custom_tunnel_host_resultion_error'
+  | 'unknown';
 
 export type ErrorStateCause =
   | {
@@ -40,7 +41,9 @@ export type ErrorStateCause =
         | 'set_dns_error'
         | 'start_tunnel_error'
         | 'is_offline'
-        | 'split_tunnel_error';
+        | 'split_tunnel_error'
+        | 'captive_portal_login'
+        | 'unknown';
     }
   | { reason: 'set_firewall_policy_error'; details: FirewallPolicyError }
   | { reason: 'tunnel_parameter_error'; details: TunnelParameterError }
@@ -86,6 +89,7 @@ export interface ITunnelEndpoint {
   protocol: RelayProtocol;
   tunnelType: TunnelType;
   proxy?: IProxyEndpoint;
+  obfuscation?: IObfuscationEndpoint;
 }
 
 export interface IProxyEndpoint {
@@ -94,22 +98,65 @@ export interface IProxyEndpoint {
   proxyType: ProxyType;
 }
 
+export type ObfuscationType = 'udp2tcp' | 'quic' | 'shadowsocks';
+
+export interface IObfuscationEndpoint {
+  address: string;
+  protocol: RelayProtocol;
+  obfuscationType: ObfuscationType;
+}
+
 export type DaemonEvent =
   | { tunnelState: TunnelState }
   | { settings: ISettings }
   | { relayList: IRelayList }
   | { wireguardKey: KeygenEvent }
-  | { appVersionInfo: IAppVersionInfo };
+  | { appVersionInfo: IAppVersionInfo }
+  | { deviceState: DeviceState }
+  | { accountExpiry: AccountExpiryEvent }
+  | { conflictingSoftware: IConflictingSoftware[] }
+  | { deviceIdentity: IDeviceIdentityChange };
+
+export interface IDeviceIdentityChange {
+  id: string;
+  previousName: string;
+  name: string;
+}
+
+export type DeviceState = 'active' | 'revoked';
+
+export type ConflictKind =
+  | 'wfp_provider'
+  | 'tunnel_interface'
+  | 'network_manager_vpn'
+  | 'system_vpn';
+
+export interface IConflictingSoftware {
+  kind: ConflictKind;
+  name: string;
+}
+
+export type AccountExpiryEvent =
+  | { type: 'expires_soon'; expiry: string; threshold: number }
+  | { type: 'expired'; expiry: string };
 
 export interface ITunnelStateRelayInfo {
   endpoint: ITunnelEndpoint;
   location?: ILocation;
 }
 
+export interface IFeatureIndicators {
+  lockdownMode: boolean;
+  splitTunneling: boolean;
+  customDns: boolean;
+  obfuscation: boolean;
+  multihop: boolean;
+}
+
 export type TunnelState =
   | { state: 'disconnected' }
   | { state: 'connecting'; details?: ITunnelStateRelayInfo }
-  | { state: 'connected'; details: ITunnelStateRelayInfo }
+  | { state: 'connected'; details: ITunnelStateRelayInfo; featureIndicators?: IFeatureIndicators }
   | { state: 'disconnecting'; details: AfterDisconnect }
   | { state: 'error'; details: IErrorState };
 
@@ -358,7 +405,8 @@ export interface ISocketAddress {
 
 export type VoucherResponse =
   | { type: 'success'; newExpiry: string; secondsAdded: number }
-  | { type: 'invalid' | 'already_used' | 'error' };
+  | { type: 'rate_limited'; retryAfter?: number }
+  | { type: 'invalid' | 'already_used' | 'expired' | 'error' };
 
 export function parseSocketAddress(socketAddrStr: string): ISocketAddress {
   const re = new RegExp(/(.+):(\d+)$/);
@@ -0,0 +1,34 @@
+import { sprintf } from 'sprintf-js';
+import { IConflictingSoftware } from '../daemon-rpc-types';
+import { messages } from '../../shared/gettext';
+import { SystemNotification, SystemNotificationProvider } from './notification';
+
+interface ConflictingSoftwareNotificationContext {
+  conflicts: IConflictingSoftware[];
+}
+
+export class ConflictingSoftwareNotificationProvider implements SystemNotificationProvider {
+  public constructor(private context: ConflictingSoftwareNotificationContext) {}
+
+  public mayDisplay = () => this.context.conflicts.length > 0;
+
+  public getSystemNotification(): SystemNotification {
+    const message = sprintf(
+      // TRANSLATORS: The system notification displayed to the user when other VPN software is running.
+      // TRANSLATORS: Available placeholder:
+      // TRANSLATORS: %(names)s - comma separated names of the software, e.g. "NordVPN, tun0"
+      messages.pgettext(
+        'notifications',
+        'Other VPN software is running and may block the connection: %(names)s',
+      ),
+      {
+        names: this.context.conflicts.map((conflict) => conflict.name).join(', '),
+      },
+    );
+
+    return {
+      message,
+      critical: false,
+    };
+  }
+}
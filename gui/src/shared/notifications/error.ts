@@ -51,7 +51,10 @@ export class ErrorNotificationProvider
 
       return {
         indicator:
-          this.context.tunnelState.details.cause.reason === 'is_offline' ? 'warning' : 'error',
+          this.context.tunnelState.details.cause.reason === 'is_offline' ||
+          this.context.tunnelState.details.cause.reason === 'captive_portal_login'
+            ? 'warning'
+            : 'error',
         title: !this.context.tunnelState.details.blockFailure
           ? messages.pgettext('in-app-notifications', 'BLOCKING INTERNET')
           : messages.pgettext('in-app-notifications', 'NETWORK TRAFFIC MIGHT BE LEAKING'),
@@ -143,6 +146,16 @@ function getMessage(errorDetails: IErrorState, accountExpiry?: string): string {
           'notifications',
           'Unable to communicate with Mullvad kernel driver. Try reconnecting or contact support.',
         );
+      case 'captive_portal_login':
+        return messages.pgettext(
+          'notifications',
+          'Only the traffic needed to log in to the captive portal is allowed. Log in using your browser.',
+        );
+      case 'unknown':
+        return messages.pgettext(
+          'notifications',
+          'Unable to start tunnel connection. Please contact support.',
+        );
     }
   }
 }
@@ -166,5 +179,10 @@ function getTunnelParameterMessage(err: TunnelParameterError): string {
         'notifications',
         'Unable to resolve host of custom tunnel. Try changing your settings.',
       );
+    case 'unknown':
+      return messages.pgettext(
+        'notifications',
+        'Unable to start tunnel connection. Please contact support.',
+      );
   }
 }
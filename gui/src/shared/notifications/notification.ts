@@ -36,6 +36,7 @@ export interface InAppNotificationProvider extends NotificationProvider {
 
 export * from './account-expired';
 export * from './close-to-account-expiry';
+export * from './conflicting-software';
 export * from './block-when-disconnected';
 export * from './connected';
 export * from './connecting';
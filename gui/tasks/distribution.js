@@ -20,6 +20,7 @@ const config = {
   extraResources: [
     { from: distAssets('ca.crt'), to: '.' },
     { from: distAssets('relays.json'), to: '.' },
+    { from: distAssets('connection-profiles.json'), to: '.' },
     { from: distAssets('api-ip-address.txt'), to: '.' },
     { from: root('CHANGELOG.md'), to: '.' },
   ],
@@ -133,6 +134,9 @@ const config = {
       { from: distAssets('binaries/x86_64-unknown-linux-gnu/sslocal'), to: '.' },
       { from: distAssets('linux/mullvad-daemon.conf'), to: '.' },
       { from: distAssets('linux/mullvad-daemon.service'), to: '.' },
+      { from: distAssets('linux/mullvad-early-boot-blocking.service'), to: '.' },
+      { from: distAssets('linux/mullvad-daemon.openrc'), to: '.' },
+      { from: distAssets('linux/mullvad-early-boot-blocking.openrc'), to: '.' },
     ],
   },
 
@@ -151,6 +155,7 @@ const config = {
       distAssets('mullvad') + '=/usr/bin/',
       distAssets('mullvad-exclude') + '=/usr/bin/',
       distAssets('linux/problem-report-link') + '=/usr/bin/mullvad-problem-report',
+      distAssets('linux/org.mullvad.vpn.conf') + '=/usr/share/dbus-1/system.d/',
       distAssets('shell-completions/mullvad.bash') +
         '=/usr/share/bash-completion/completions/mullvad',
       distAssets('shell-completions/_mullvad') + '=/usr/local/share/zsh/site-functions/_mullvad',
@@ -177,6 +182,7 @@ const config = {
       distAssets('mullvad') + '=/usr/bin/',
       distAssets('mullvad-exclude') + '=/usr/bin/',
       distAssets('linux/problem-report-link') + '=/usr/bin/mullvad-problem-report',
+      distAssets('linux/org.mullvad.vpn.conf') + '=/usr/share/dbus-1/system.d/',
       distAssets('shell-completions/mullvad.bash') +
         '=/usr/share/bash-completion/completions/mullvad',
       distAssets('shell-completions/_mullvad') + '=/usr/share/zsh/site-functions/_mullvad',
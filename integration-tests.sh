@@ -19,11 +19,22 @@ esac
 
 MULLVAD_DIR="$(cd "$(dirname "$0")"; pwd -P)"
 
+# Tests can be run in a different network topology by setting `ENVIRONMENT` to the name of a script
+# in `mullvad-tests/environments`, such as `ipv6-only`
+TEST_ENVIRONMENT=""
+if [ -n "${ENVIRONMENT:-}" ]; then
+    TEST_ENVIRONMENT="$MULLVAD_DIR/mullvad-tests/environments/$ENVIRONMENT.sh"
+    if [ ! -x "$TEST_ENVIRONMENT" ]; then
+        echo "ERROR: Unknown test environment $ENVIRONMENT" >&2
+        exit 1
+    fi
+fi
+
 pushd "$MULLVAD_DIR"
 
 cargo build \
     && cd mullvad-tests \
-    && cargo test --features "integration-tests" -- --test-threads=1
+    && $TEST_ENVIRONMENT cargo test --features "integration-tests" -- --test-threads=1
 
 RESULT="$?"
 popd
@@ -1,9 +1,17 @@
-use crate::{new_rpc_client, Command, Error, Result};
+use crate::{format, new_rpc_client, Command, Error, Result};
 use clap::value_t_or_exit;
 use itertools::Itertools;
-use mullvad_management_interface::{types::Timestamp, Code};
-use mullvad_types::account::AccountToken;
-use std::io::{self, Write};
+use mullvad_management_interface::types::{
+    account_expiry_settings::Action as ExpiryAction, api_error, AccountExpirySettings, ApiError,
+    Timestamp,
+};
+use mullvad_types::{
+    account::AccountToken, settings::validate_identity_rotation_interval, units::HumanDuration,
+};
+use std::{
+    io::{self, Write},
+    time::Duration,
+};
 
 pub struct Account;
 
@@ -47,6 +55,22 @@ impl Command for Account {
                             .required(true),
                     ),
             )
+            .subcommand(
+                clap::SubCommand::with_name("privacy")
+                    .about(
+                        "Control whether the account number is only kept in memory. When on, it \
+                         is not stored on disk and has to be entered again after logging out or \
+                         restarting the daemon",
+                    )
+                    .arg(
+                        clap::Arg::with_name("policy")
+                            .required(true)
+                            .possible_values(&["on", "off"]),
+                    ),
+            )
+            .subcommand(create_history_subcommand())
+            .subcommand(create_device_subcommand())
+            .subcommand(create_expiry_subcommand())
     }
 
     async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
@@ -68,7 +92,7 @@ impl Command for Account {
             token = token.split_whitespace().join("").to_string();
             self.set(Some(token)).await
         } else if let Some(_matches) = matches.subcommand_matches("get") {
-            self.get().await
+            self.get(matches.is_present("json")).await
         } else if let Some(_matches) = matches.subcommand_matches("unset") {
             self.set(None).await
         } else if let Some(_matches) = matches.subcommand_matches("create") {
@@ -76,12 +100,211 @@ impl Command for Account {
         } else if let Some(matches) = matches.subcommand_matches("redeem") {
             let voucher = value_t_or_exit!(matches.value_of("voucher"), String);
             self.redeem_voucher(voucher).await
+        } else if let Some(matches) = matches.subcommand_matches("privacy") {
+            let policy = value_t_or_exit!(matches.value_of("policy"), String);
+            self.set_privacy_mode(policy == "on").await
+        } else if let Some(matches) = matches.subcommand_matches("history") {
+            match matches.subcommand() {
+                ("list", Some(_)) => self.list_history().await,
+                ("clear", Some(_)) => self.clear_history().await,
+                ("remove", Some(remove_matches)) => {
+                    let token = value_t_or_exit!(remove_matches.value_of("token"), String);
+                    self.remove_from_history(token).await
+                }
+                ("keep", Some(keep_matches)) => {
+                    let policy = value_t_or_exit!(keep_matches.value_of("policy"), String);
+                    self.set_keep_history(policy == "on").await
+                }
+                _ => unreachable!("No account history command given"),
+            }
+        } else if let Some(matches) = matches.subcommand_matches("device") {
+            match matches.subcommand() {
+                ("list", Some(_)) => self.list_devices().await,
+                ("rename", Some(rename_matches)) => {
+                    let name = value_t_or_exit!(rename_matches.value_of("name"), String);
+                    self.rename_device(name).await
+                }
+                ("remove", Some(remove_matches)) => {
+                    let id = value_t_or_exit!(remove_matches.value_of("id"), String);
+                    self.remove_device(id).await
+                }
+                ("reregister", Some(_)) => self.reregister_device().await,
+                ("identity-rotation", Some(rotation_matches)) => {
+                    match rotation_matches.subcommand() {
+                        ("get", Some(_)) => self.get_identity_rotation().await,
+                        ("set", Some(set_matches)) => {
+                            let interval = set_matches
+                                .value_of("interval")
+                                .unwrap()
+                                .parse::<HumanDuration>()
+                                .expect("Interval should have been validated");
+                            self.set_identity_rotation(Some(interval)).await
+                        }
+                        ("off", Some(_)) => self.set_identity_rotation(None).await,
+                        _ => unreachable!("No identity rotation command given"),
+                    }
+                }
+                _ => unreachable!("No account device command given"),
+            }
+        } else if let Some(matches) = matches.subcommand_matches("expiry") {
+            match matches.subcommand() {
+                ("get", Some(_)) => self.get_expiry_settings().await,
+                ("warnings", Some(warnings_matches)) => {
+                    let hours = warnings_matches
+                        .values_of("hours")
+                        .map(|values| {
+                            values
+                                .map(|value| value.parse::<u64>().unwrap())
+                                .collect::<Vec<_>>()
+                        })
+                        .unwrap_or_default();
+                    self.set_expiry_warnings(hours).await
+                }
+                ("action", Some(action_matches)) => {
+                    let action = match action_matches.value_of("action").unwrap() {
+                        "disconnect" => ExpiryAction::Disconnect,
+                        _ => ExpiryAction::Block,
+                    };
+                    self.set_expiry_action(action).await
+                }
+                _ => unreachable!("No account expiry command given"),
+            }
         } else {
             unreachable!("No account command given");
         }
     }
 }
 
+fn create_history_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("history")
+        .about("Manage the accounts that have been used on this device")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("list")
+                .about("List previously used accounts, most recently used first"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("clear").about("Forget all previously used accounts"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("remove")
+                .about("Forget a single account")
+                .arg(
+                    clap::Arg::with_name("token")
+                        .help("The Mullvad account token to remove from the history")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("keep")
+                .about(
+                    "Control whether several previously used accounts are remembered. When off, \
+                     only the most recently used account is remembered",
+                )
+                .arg(
+                    clap::Arg::with_name("policy")
+                        .required(true)
+                        .possible_values(&["on", "off"]),
+                ),
+        )
+}
+
+fn create_device_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("device")
+        .about("Manage the devices that are registered with the account")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("list").about("List the devices of the account"))
+        .subcommand(
+            clap::SubCommand::with_name("rename")
+                .about("Rename this device")
+                .arg(
+                    clap::Arg::with_name("name")
+                        .help("The new name of this device")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("remove")
+                .about("Remove another device from the account, freeing up a device slot")
+                .arg(
+                    clap::Arg::with_name("id")
+                        .help("The ID of the device, as shown by `mullvad account device list`")
+                        .required(true),
+                ),
+        )
+        .subcommand(clap::SubCommand::with_name("reregister").about(
+            "Register this device with the account again after it has been removed, using a \
+                 new WireGuard key",
+        ))
+        .subcommand(create_identity_rotation_subcommand())
+}
+
+fn create_identity_rotation_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("identity-rotation")
+        .about("Give this device a new name and WireGuard key on a schedule")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get").about("Display the rotation interval"))
+        .subcommand(
+            clap::SubCommand::with_name("set")
+                .about("Rotate the identity of this device at the given interval")
+                .arg(
+                    clap::Arg::with_name("interval")
+                        .help("The interval, such as \"7d\" or \"30 days\". At least one day")
+                        .required(true)
+                        .validator(identity_rotation_interval_validator),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("off").about("Keep the name and key of this device"),
+        )
+}
+
+fn identity_rotation_interval_validator(value: String) -> std::result::Result<(), String> {
+    let interval = value
+        .parse::<HumanDuration>()
+        .map_err(|error| error.to_string())?;
+    validate_identity_rotation_interval(Some(interval)).map_err(|error| error.to_string())
+}
+
+fn create_expiry_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("expiry")
+        .about("Control the warnings about the account running out of time")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("get")
+                .about("Display the warning thresholds and the action taken at expiry"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("warnings")
+                .about(
+                    "Set how many hours before the account runs out of time that warnings are \
+                     emitted. Leave out the values to disable the warnings",
+                )
+                .arg(
+                    clap::Arg::with_name("hours")
+                        .multiple(true)
+                        .validator(|value| {
+                            value
+                                .parse::<u64>()
+                                .map(|_| ())
+                                .map_err(|_| "the threshold must be a number of hours".to_owned())
+                        }),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("action")
+                .about(
+                    "Set what to do when the account runs out of time. 'block' keeps blocking \
+                     traffic until time is added, 'disconnect' disconnects the tunnel",
+                )
+                .arg(
+                    clap::Arg::with_name("action")
+                        .required(true)
+                        .possible_values(&["block", "disconnect"]),
+                ),
+        )
+}
+
 impl Account {
     async fn set(&self, token: Option<AccountToken>) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
@@ -94,9 +317,28 @@ impl Account {
         Ok(())
     }
 
-    async fn get(&self) -> Result<()> {
+    async fn get(&self, json: bool) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
         let settings = rpc.get_settings(()).await?.into_inner();
+        if json {
+            let account_data = if settings.account_token != "" {
+                Some(
+                    rpc.get_account_data(settings.account_token.clone())
+                        .await
+                        .map_err(|error| {
+                            Error::RpcFailedExt("Failed to fetch account data", error)
+                        })?
+                        .into_inner(),
+                )
+            } else {
+                None
+            };
+            format::print_json(&serde_json::json!({
+                "account_token": Some(settings.account_token).filter(|token| token != ""),
+                "account_data": account_data,
+            }));
+            return Ok(());
+        }
         if settings.account_token != "" {
             println!("Mullvad account: {}", settings.account_token);
             let expiry = rpc
@@ -106,7 +348,7 @@ impl Account {
                 .into_inner();
             println!(
                 "Expires at     : {}",
-                Self::format_expiry(&expiry.expiry.unwrap())
+                Self::format_timestamp(&expiry.expiry.unwrap())
             );
         } else {
             println!("No account configured");
@@ -114,6 +356,90 @@ impl Account {
         Ok(())
     }
 
+    async fn list_history(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let history = rpc.get_account_history(()).await?.into_inner();
+        if history.accounts.is_empty() {
+            println!("No previously used accounts");
+        }
+        for account in history.accounts {
+            println!("{}", account);
+        }
+        Ok(())
+    }
+
+    async fn clear_history(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.clear_account_history(()).await?;
+        println!("Cleared account history");
+        Ok(())
+    }
+
+    async fn remove_from_history(&self, token: AccountToken) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.remove_account_from_history(token.clone()).await?;
+        println!("Removed \"{}\" from the account history", token);
+        Ok(())
+    }
+
+    async fn set_privacy_mode(&self, enabled: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_account_privacy_mode(enabled).await?;
+        println!("Changed account privacy mode setting");
+        Ok(())
+    }
+
+    async fn set_keep_history(&self, keep_history: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_keep_account_history(keep_history).await?;
+        println!("Changed account history setting");
+        Ok(())
+    }
+
+    async fn get_expiry_settings(&self) -> Result<()> {
+        let settings = Self::get_account_expiry_settings().await?;
+        let warnings = if settings.warning_thresholds.is_empty() {
+            "none".to_owned()
+        } else {
+            settings
+                .warning_thresholds
+                .iter()
+                .map(|threshold| Self::format_duration(*threshold))
+                .join(", ")
+        };
+        println!("Warnings before expiry: {}", warnings);
+        let action = match ExpiryAction::from_i32(settings.action) {
+            Some(ExpiryAction::Disconnect) => "disconnect",
+            _ => "block",
+        };
+        println!("Action at expiry      : {}", action);
+        Ok(())
+    }
+
+    async fn set_expiry_warnings(&self, hours: Vec<u64>) -> Result<()> {
+        let mut settings = Self::get_account_expiry_settings().await?;
+        settings.warning_thresholds = hours.into_iter().map(|hours| hours * 60 * 60).collect();
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_account_expiry_settings(settings).await?;
+        println!("Changed account expiry warnings");
+        Ok(())
+    }
+
+    async fn set_expiry_action(&self, action: ExpiryAction) -> Result<()> {
+        let mut settings = Self::get_account_expiry_settings().await?;
+        settings.action = i32::from(action);
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_account_expiry_settings(settings).await?;
+        println!("Changed the action taken when the account runs out of time");
+        Ok(())
+    }
+
+    async fn get_account_expiry_settings() -> Result<AccountExpirySettings> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc.get_settings(()).await?.into_inner();
+        Ok(settings.account_expiry.unwrap_or_default())
+    }
+
     async fn create(&self) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
         rpc.create_new_account(()).await?;
@@ -134,13 +460,17 @@ impl Account {
                 );
                 println!(
                     "New expiry date: {}",
-                    Self::format_expiry(&submission.new_expiry.unwrap())
+                    Self::format_timestamp(&submission.new_expiry.unwrap())
                 );
                 Ok(())
             }
             Err(err) => {
-                match err.code() {
-                    Code::NotFound | Code::ResourceExhausted => {
+                match ApiError::from_status(&err).map(|api_error| api_error.code()) {
+                    Some(api_error::Code::InvalidVoucher)
+                    | Some(api_error::Code::VoucherUsed)
+                    | Some(api_error::Code::VoucherExpired)
+                    | Some(api_error::Code::RateLimited)
+                    | Some(api_error::Code::ClockSkew) => {
                         eprintln!("Failed to submit voucher: {}", err.message());
                     }
                     _ => return Err(Error::RpcFailed(err)),
@@ -163,7 +493,86 @@ impl Account {
         }
     }
 
-    fn format_expiry(expiry: &Timestamp) -> String {
+    async fn list_devices(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let list = rpc
+            .list_devices(())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to list devices", error))?
+            .into_inner();
+        for device in list.devices {
+            let current = if device.id == list.current_device {
+                " (this device)"
+            } else {
+                ""
+            };
+            println!("{}{}", device.name, current);
+            println!("  ID     : {}", device.id);
+            println!("  Pubkey : {}", base64::encode(&device.pubkey));
+            if let Some(created) = &device.created {
+                println!("  Created: {}", Self::format_timestamp(created));
+            }
+        }
+        Ok(())
+    }
+
+    async fn rename_device(&self, name: String) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.rename_device(name.clone())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to rename device", error))?;
+        println!("Renamed this device to \"{}\"", name);
+        Ok(())
+    }
+
+    async fn remove_device(&self, id: String) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.remove_device(id)
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to remove device", error))?;
+        println!("Removed device");
+        Ok(())
+    }
+
+    async fn reregister_device(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.reregister_device(())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to register device", error))?;
+        println!("Registered this device with the account again");
+        Ok(())
+    }
+
+    async fn get_identity_rotation(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc.get_settings(()).await?.into_inner();
+        match settings.identity_rotation_interval {
+            0 => println!("Identity rotation: off"),
+            seconds => println!(
+                "Identity rotation: every {}",
+                HumanDuration(Duration::from_secs(seconds))
+            ),
+        }
+        Ok(())
+    }
+
+    async fn set_identity_rotation(&self, interval: Option<HumanDuration>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_identity_rotation_interval(
+            interval
+                .map(|interval| interval.as_duration().as_secs())
+                .unwrap_or(0),
+        )
+        .await
+        .map_err(|error| Error::RpcFailedExt("Failed to set identity rotation interval", error))?;
+        match interval {
+            Some(interval) => println!("Rotating the identity of this device every {}", interval),
+            None => println!("Turned off identity rotation"),
+        }
+        Ok(())
+    }
+
+    fn format_timestamp(expiry: &Timestamp) -> String {
         let ndt = chrono::NaiveDateTime::from_timestamp(expiry.seconds, expiry.nanos as u32);
         let utc = chrono::DateTime::<chrono::Utc>::from_utc(ndt, chrono::Utc);
         utc.with_timezone(&chrono::Local).to_string()
@@ -0,0 +1,514 @@
+use crate::{format, new_rpc_client, Command, Error, Result};
+use clap::value_t;
+use mullvad_management_interface::types::{self, Timestamp};
+use mullvad_types::api_access::{
+    is_builtin_access_method, ApiAccessMethod, ApiProxy, ProxyAuth, BRIDGES_ACCESS_METHOD,
+    DIRECT_ACCESS_METHOD,
+};
+use std::{convert::TryFrom, net::SocketAddr};
+
+pub struct ApiAccess;
+
+#[mullvad_management_interface::async_trait]
+impl Command for ApiAccess {
+    fn name(&self) -> &'static str {
+        "api-access"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Inspect how the Mullvad API is being reached")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("status")
+                    .about("Display the API endpoints and which one was last reachable"),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("resolver")
+                    .about(
+                        "Set how the API hostname is resolved. Resolved addresses are tried in \
+                         addition to the addresses bundled with the app",
+                    )
+                    .arg(
+                        clap::Arg::with_name("strategy")
+                            .required(true)
+                            .possible_values(&["static", "system", "doh"]),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("tasks")
+                    .about("Display the periodic API tasks and when they will run next"),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("trace")
+                    .about(
+                        "Record recent API requests, to be included in problem reports. \
+                         Only the method, path, timing and outcome of requests are recorded",
+                    )
+                    .arg(
+                        clap::Arg::with_name("action")
+                            .required(true)
+                            .possible_values(&["on", "off", "show"]),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("clock-check")
+                    .about(
+                        "Check the system clock against the time of the API when the API \
+                         certificate is rejected because of its validity period, so that a \
+                         wrong clock is reported as such",
+                    )
+                    .arg(
+                        clap::Arg::with_name("policy")
+                            .required(true)
+                            .possible_values(&["on", "off"]),
+                    ),
+            )
+            .subcommand(
+                create_proxy_args(
+                    clap::SubCommand::with_name("add")
+                        .about(
+                            "Add a proxy to send API traffic through. \
+                             The API must be reachable through it before it is enabled",
+                        )
+                        .arg(
+                            clap::Arg::with_name("name")
+                                .help("A unique name for the proxy")
+                                .required(true)
+                                .index(1),
+                        ),
+                    2,
+                )
+                .arg(
+                    clap::Arg::with_name("disabled")
+                        .long("disabled")
+                        .help("Add the proxy without enabling it"),
+                ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("remove")
+                    .about("Remove a proxy. The built-in access methods cannot be removed")
+                    .arg(clap::Arg::with_name("name").required(true)),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("enable")
+                    .about(
+                        "Enable an access method. When the API cannot be reached, the enabled \
+                         access methods are tried in turn",
+                    )
+                    .arg(clap::Arg::with_name("name").required(true)),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("disable")
+                    .about("Disable an access method")
+                    .arg(clap::Arg::with_name("name").required(true)),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("use")
+                    .about(
+                        "Switch to an access method if the API can be reached through it, and \
+                         try it first from now on",
+                    )
+                    .arg(clap::Arg::with_name("name").required(true)),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("list")
+                    .about("List the access methods and which one is in use"),
+            )
+            .subcommand(create_proxy_args(
+                clap::SubCommand::with_name("test")
+                    .about("Check whether the API can be reached through a proxy"),
+                1,
+            ))
+            .subcommand(clap::SubCommand::with_name("forwarder").about(
+                "Open a SOCKS5 proxy on localhost through which the API can be reached using \
+                 the current access method, and print its address. It is closed once it has \
+                 been idle for a while",
+            ))
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if let Some(_matches) = matches.subcommand_matches("status") {
+            self.status().await
+        } else if let Some(resolver_matches) = matches.subcommand_matches("resolver") {
+            self.set_resolution_strategy(resolver_matches.value_of("strategy").unwrap())
+                .await
+        } else if let Some(_matches) = matches.subcommand_matches("tasks") {
+            self.tasks().await
+        } else if let Some(trace_matches) = matches.subcommand_matches("trace") {
+            match trace_matches.value_of("action").unwrap() {
+                "show" => self.show_trace().await,
+                action => self.set_trace_enabled(action == "on").await,
+            }
+        } else if let Some(clock_check_matches) = matches.subcommand_matches("clock-check") {
+            self.set_clock_check(clock_check_matches.value_of("policy").unwrap() == "on")
+                .await
+        } else if let Some(add_matches) = matches.subcommand_matches("add") {
+            self.add(add_matches).await
+        } else if let Some(remove_matches) = matches.subcommand_matches("remove") {
+            self.remove(remove_matches.value_of("name").unwrap()).await
+        } else if let Some(enable_matches) = matches.subcommand_matches("enable") {
+            self.set_enabled(enable_matches.value_of("name").unwrap(), true)
+                .await
+        } else if let Some(disable_matches) = matches.subcommand_matches("disable") {
+            self.set_enabled(disable_matches.value_of("name").unwrap(), false)
+                .await
+        } else if let Some(use_matches) = matches.subcommand_matches("use") {
+            self.use_method(use_matches.value_of("name").unwrap()).await
+        } else if let Some(_matches) = matches.subcommand_matches("list") {
+            self.list().await
+        } else if let Some(test_matches) = matches.subcommand_matches("test") {
+            self.test(test_matches).await
+        } else if let Some(_matches) = matches.subcommand_matches("forwarder") {
+            self.open_forwarder().await
+        } else {
+            unreachable!("No api-access command given");
+        }
+    }
+}
+
+impl ApiAccess {
+    async fn status(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let status = rpc
+            .get_api_access_status(())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to obtain API access status", error))?
+            .into_inner();
+        println!("Current API endpoint: {}", status.current_address);
+        if let Some(method) = &status.access_method {
+            println!(
+                "Access method: {}",
+                format::format_api_access_method(method)
+            );
+        }
+        match status.last_working {
+            Some(success) => println!(
+                "Last working API endpoint: {} (at {})",
+                success.address,
+                success
+                    .timestamp
+                    .as_ref()
+                    .map(Self::format_timestamp)
+                    .unwrap_or_default()
+            ),
+            None => println!("Last working API endpoint: none"),
+        }
+        println!("Known API endpoints:");
+        for address in &status.addresses {
+            println!("\t{}", address);
+        }
+        if let Some(strategy) = status.resolution_strategy {
+            println!(
+                "API hostname resolution: {}",
+                Self::format_resolution_strategy(&strategy)
+            );
+        }
+        if let Some(resolution) = status.last_resolution {
+            let timestamp = resolution
+                .timestamp
+                .as_ref()
+                .map(Self::format_timestamp)
+                .unwrap_or_default();
+            if resolution.error.is_empty() {
+                println!(
+                    "Last resolution: {} (at {})",
+                    resolution.addresses.join(", "),
+                    timestamp
+                );
+            } else {
+                println!(
+                    "Last resolution: failed: {} (at {})",
+                    resolution.error, timestamp
+                );
+            }
+        }
+        Ok(())
+    }
+
+    async fn set_resolution_strategy(&self, strategy: &str) -> Result<()> {
+        let strategy = match strategy {
+            "static" => types::api_resolution_strategy::Strategy::Static,
+            "system" => types::api_resolution_strategy::Strategy::System,
+            "doh" => types::api_resolution_strategy::Strategy::DnsOverHttps,
+            _ => unreachable!("Unknown resolution strategy"),
+        };
+        let strategy = types::ApiResolutionStrategy {
+            strategy: i32::from(strategy),
+        };
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_api_resolution_strategy(strategy.clone())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to set API resolution strategy", error))?;
+        println!(
+            "Changed API hostname resolution to {}",
+            Self::format_resolution_strategy(&strategy)
+        );
+        Ok(())
+    }
+
+    fn format_resolution_strategy(strategy: &types::ApiResolutionStrategy) -> &'static str {
+        match types::api_resolution_strategy::Strategy::from_i32(strategy.strategy) {
+            Some(types::api_resolution_strategy::Strategy::Static) => {
+                "static (bundled and cached addresses only)"
+            }
+            Some(types::api_resolution_strategy::Strategy::System) => "system resolver",
+            Some(types::api_resolution_strategy::Strategy::DnsOverHttps) => "DNS over HTTPS",
+            None => "unknown",
+        }
+    }
+
+    async fn tasks(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let tasks = rpc
+            .get_scheduled_tasks(())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to obtain scheduled tasks", error))?
+            .into_inner()
+            .tasks;
+        for task in &tasks {
+            println!("{}", task.name);
+            if let Some(interval) = &task.interval {
+                println!("	Interval: {} minutes", interval.seconds / 60);
+            }
+            if task.running {
+                println!("	Next run: running now");
+            } else if let Some(next_run) = &task.next_run {
+                println!("	Next run: {}", Self::format_timestamp(next_run));
+            }
+            match &task.last_run {
+                Some(last_run) => println!("	Last run: {}", Self::format_timestamp(last_run)),
+                None => println!("	Last run: never"),
+            }
+        }
+        Ok(())
+    }
+
+    async fn set_trace_enabled(&self, enabled: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_api_trace_enabled(enabled)
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to set API trace mode", error))?;
+        println!(
+            "Changed API trace mode to {}",
+            if enabled { "on" } else { "off" }
+        );
+        Ok(())
+    }
+
+    async fn show_trace(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let trace = rpc
+            .get_api_trace(())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to obtain API trace", error))?
+            .into_inner();
+        if !trace.enabled {
+            println!("API trace mode is off");
+            return Ok(());
+        }
+        for entry in &trace.entries {
+            println!("{}", entry);
+        }
+        Ok(())
+    }
+
+    async fn set_clock_check(&self, enabled: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_api_clock_check(enabled)
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to set clock check", error))?;
+        println!(
+            "Changed clock check setting to {}",
+            if enabled { "on" } else { "off" }
+        );
+        Ok(())
+    }
+
+    async fn add(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let method = ApiAccessMethod {
+            name: matches.value_of("name").unwrap().to_owned(),
+            enabled: !matches.is_present("disabled"),
+            proxy: parse_proxy_args(matches),
+        };
+        if method.enabled {
+            println!("Checking that the API can be reached through the proxy...");
+        }
+        let mut rpc = new_rpc_client().await?;
+        rpc.add_api_access_method(types::ApiAccessMethod::from(method.clone()))
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to add proxy", error))?;
+        println!("Added proxy \"{}\": {}", method.name, method.proxy);
+        Ok(())
+    }
+
+    async fn remove(&self, name: &str) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.remove_api_access_method(name.to_owned())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to remove proxy", error))?;
+        println!("Removed proxy \"{}\"", name);
+        Ok(())
+    }
+
+    async fn set_enabled(&self, name: &str, enabled: bool) -> Result<()> {
+        if enabled && !is_builtin_access_method(name) {
+            println!("Checking that the API can be reached through the proxy...");
+        }
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_api_access_method_enabled(types::ApiAccessMethodState {
+            name: name.to_owned(),
+            enabled,
+        })
+        .await
+        .map_err(|error| Error::RpcFailedExt("Failed to update access method", error))?;
+        println!(
+            "{} access method \"{}\"",
+            if enabled { "Enabled" } else { "Disabled" },
+            name
+        );
+        Ok(())
+    }
+
+    async fn use_method(&self, name: &str) -> Result<()> {
+        println!(
+            "Checking that the API can be reached through \"{}\"...",
+            name
+        );
+        let mut rpc = new_rpc_client().await?;
+        rpc.use_api_access_method(name.to_owned())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to switch access method", error))?;
+        println!("Switched to access method \"{}\"", name);
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc.get_settings(()).await?.into_inner();
+        let current = rpc
+            .get_api_access_status(())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to obtain API access status", error))?
+            .into_inner()
+            .access_method
+            .map(|method| method.name)
+            .unwrap_or_default();
+        let api_access = settings.api_access.unwrap_or_default();
+
+        let print_method = |name: &str, enabled: bool, description: &str| {
+            let mut flags = vec![];
+            if !enabled {
+                flags.push("disabled");
+            }
+            if name == api_access.preferred {
+                flags.push("preferred");
+            }
+            if name == current {
+                flags.push("in use");
+            }
+            let flags = if flags.is_empty() {
+                String::new()
+            } else {
+                format!(" ({})", flags.join(", "))
+            };
+            println!("{}{}: {}", name, flags, description);
+        };
+
+        for method in settings.api_access_methods {
+            let method = ApiAccessMethod::try_from(method).map_err(|error| {
+                Error::RpcFailedExt("Received an invalid access method", error.into())
+            })?;
+            print_method(&method.name, method.enabled, &method.proxy.to_string());
+        }
+        print_method(
+            DIRECT_ACCESS_METHOD,
+            api_access.direct_enabled,
+            "connect to the API directly",
+        );
+        print_method(
+            BRIDGES_ACCESS_METHOD,
+            api_access.bridges_enabled,
+            "connect through a Mullvad bridge",
+        );
+        Ok(())
+    }
+
+    async fn test(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let proxy = parse_proxy_args(matches);
+        let mut rpc = new_rpc_client().await?;
+        rpc.test_api_proxy(types::ApiProxy::from(proxy))
+            .await
+            .map_err(|error| Error::RpcFailedExt("The API cannot be reached", error))?;
+        println!("The API can be reached through the proxy");
+        Ok(())
+    }
+
+    async fn open_forwarder(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let address = rpc
+            .open_api_forwarder(())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to open API forwarder", error))?
+            .into_inner();
+        println!("{}", address);
+        Ok(())
+    }
+
+    fn format_timestamp(timestamp: &Timestamp) -> String {
+        let ndt = chrono::NaiveDateTime::from_timestamp(timestamp.seconds, timestamp.nanos as u32);
+        let utc = chrono::DateTime::<chrono::Utc>::from_utc(ndt, chrono::Utc);
+        utc.with_timezone(&chrono::Local).to_string()
+    }
+}
+
+/// Adds the arguments that describe a proxy, starting at the positional argument `index`.
+fn create_proxy_args(
+    subcommand: clap::App<'static, 'static>,
+    index: u64,
+) -> clap::App<'static, 'static> {
+    subcommand
+        .arg(
+            clap::Arg::with_name("type")
+                .help("The type of the proxy")
+                .required(true)
+                .possible_values(&["socks5", "http"])
+                .index(index),
+        )
+        .arg(
+            clap::Arg::with_name("address")
+                .help("The IP address and port of the proxy")
+                .required(true)
+                .index(index + 1),
+        )
+        .arg(
+            clap::Arg::with_name("username")
+                .long("username")
+                .takes_value(true)
+                .requires("password")
+                .help("The username to authenticate to the proxy with"),
+        )
+        .arg(
+            clap::Arg::with_name("password")
+                .long("password")
+                .takes_value(true)
+                .requires("username")
+                .help("The password to authenticate to the proxy with"),
+        )
+}
+
+fn parse_proxy_args(matches: &clap::ArgMatches<'_>) -> ApiProxy {
+    let address = value_t!(matches.value_of("address"), SocketAddr).unwrap_or_else(|e| e.exit());
+    let auth = match (matches.value_of("username"), matches.value_of("password")) {
+        (Some(username), Some(password)) => Some(ProxyAuth {
+            username: username.to_owned(),
+            password: password.to_owned(),
+        }),
+        _ => None,
+    };
+    match matches.value_of("type").unwrap() {
+        "socks5" => ApiProxy::Socks5 { address, auth },
+        "http" => ApiProxy::HttpConnect { address, auth },
+        _ => unreachable!("Unknown proxy type"),
+    }
+}
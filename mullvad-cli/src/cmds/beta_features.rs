@@ -0,0 +1,75 @@
+use crate::{new_rpc_client, Command, Error, Result};
+use clap::value_t_or_exit;
+use mullvad_management_interface::types::BetaFeatureState;
+
+pub struct BetaFeatures;
+
+#[mullvad_management_interface::async_trait]
+impl Command for BetaFeatures {
+    fn name(&self) -> &'static str {
+        "beta-features"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Turn experimental features on or off")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("list")
+                    .about("List the experimental features and whether they are turned on"),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("set")
+                    .about("Turn an experimental feature on or off")
+                    .arg(
+                        clap::Arg::with_name("feature")
+                            .help("Name of the feature, as shown by 'mullvad beta-features list'")
+                            .required(true),
+                    )
+                    .arg(
+                        clap::Arg::with_name("policy")
+                            .required(true)
+                            .possible_values(&["on", "off"]),
+                    ),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("list", Some(_)) => {
+                let mut rpc = new_rpc_client().await?;
+                let features = rpc
+                    .get_beta_features(())
+                    .await
+                    .map_err(|error| Error::RpcFailedExt("Failed to list beta features", error))?
+                    .into_inner()
+                    .features;
+                for feature in features {
+                    println!(
+                        "{}: {}",
+                        feature.name,
+                        if feature.enabled { "on" } else { "off" }
+                    );
+                    println!("    {}", feature.description);
+                }
+                Ok(())
+            }
+            ("set", Some(matches)) => {
+                let name = value_t_or_exit!(matches.value_of("feature"), String);
+                let enabled = matches.value_of("policy") == Some("on");
+                let mut rpc = new_rpc_client().await?;
+                rpc.set_beta_feature(BetaFeatureState {
+                    name: name.clone(),
+                    enabled,
+                })
+                .await
+                .map_err(|error| Error::RpcFailedExt("Failed to set beta feature", error))?;
+                println!("{}: {}", name, if enabled { "on" } else { "off" });
+                Ok(())
+            }
+            _ => {
+                unreachable!("unhandled comand");
+            }
+        }
+    }
+}
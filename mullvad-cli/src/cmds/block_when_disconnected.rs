@@ -26,6 +26,8 @@ impl Command for BlockWhenDisconnected {
                 clap::SubCommand::with_name("get")
                     .about("Display the current always require VPN setting"),
             )
+            .subcommand(create_system_services_subcommand())
+            .subcommand(create_persistent_subcommand())
     }
 
     async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
@@ -34,12 +36,83 @@ impl Command for BlockWhenDisconnected {
             self.set(block_when_disconnected == "on").await
         } else if let Some(_matches) = matches.subcommand_matches("get") {
             self.get().await
+        } else if let Some(services_matches) = matches.subcommand_matches("system-services") {
+            match services_matches.subcommand() {
+                ("set", Some(set_matches)) => self.set_system_services(set_matches).await,
+                ("get", Some(_)) => self.get_system_services().await,
+                _ => unreachable!("No system-services command given"),
+            }
+        } else if let Some(persistent_matches) = matches.subcommand_matches("persistent") {
+            match persistent_matches.subcommand() {
+                ("set", Some(set_matches)) => {
+                    let persistent = value_t_or_exit!(set_matches.value_of("policy"), String);
+                    self.set_persistent(persistent == "on").await
+                }
+                ("get", Some(_)) => self.get_persistent().await,
+                _ => unreachable!("No persistent command given"),
+            }
         } else {
             unreachable!("No block-when-disconnected command given");
         }
     }
 }
 
+fn create_system_services_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("system-services")
+        .about(
+            "Control which essential system services may send traffic while network access is \
+             blocked, either by this setting or because of an error. DHCP is always allowed",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("set")
+                .about("Allow or block system services")
+                .setting(clap::AppSettings::ArgRequiredElseHelp)
+                .arg(
+                    clap::Arg::with_name("ntp")
+                        .help("Allow NTP requests, so that the clock stays in sync")
+                        .long("ntp")
+                        .takes_value(true)
+                        .possible_values(&["on", "off"]),
+                )
+                .arg(
+                    clap::Arg::with_name("captive-portal-detection")
+                        .help(
+                            "Allow the captive portal detection of the operating system. The \
+                             hosts it uses are looked up while connected",
+                        )
+                        .long("captive-portal-detection")
+                        .takes_value(true)
+                        .possible_values(&["on", "off"]),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("get")
+                .about("Display which system services are allowed while blocked"),
+        )
+}
+
+fn create_persistent_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("persistent")
+        .about(
+            "Control whether network access stays blocked while the system service is not \
+             running, including at boot. This has no effect unless always require VPN is on",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("set")
+                .about("Change the persistent setting")
+                .arg(
+                    clap::Arg::with_name("policy")
+                        .required(true)
+                        .possible_values(&["on", "off"]),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("get").about("Display the current persistent setting"),
+        )
+}
+
 impl BlockWhenDisconnected {
     async fn set(&self, block_when_disconnected: bool) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
@@ -66,4 +139,67 @@ impl BlockWhenDisconnected {
         );
         Ok(())
     }
+
+    async fn set_persistent(&self, persistent: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_persistent_lockdown(persistent).await?;
+        println!("Changed persistent always require VPN setting");
+        Ok(())
+    }
+
+    async fn get_persistent(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc.get_settings(()).await?.into_inner();
+        println!(
+            "Network traffic will {}be blocked while the system service is not running",
+            if settings.persistent_lockdown && settings.block_when_disconnected {
+                ""
+            } else {
+                "not "
+            }
+        );
+        if settings.persistent_lockdown && !settings.block_when_disconnected {
+            println!("Persistent blocking is on, but has no effect until always require VPN is on");
+        }
+        Ok(())
+    }
+
+    async fn set_system_services(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let mut services = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .allowed_system_services
+            .unwrap_or_default();
+        if let Some(ntp) = matches.value_of("ntp") {
+            services.ntp = ntp == "on";
+        }
+        if let Some(detection) = matches.value_of("captive-portal-detection") {
+            services.captive_portal_detection = detection == "on";
+        }
+        rpc.set_allowed_system_services(services).await?;
+        println!("Changed the system services allowed while blocked");
+        Ok(())
+    }
+
+    async fn get_system_services(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let services = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .allowed_system_services
+            .unwrap_or_default();
+        println!("NTP: {}", if services.ntp { "allowed" } else { "blocked" });
+        println!(
+            "Captive portal detection: {}",
+            if services.captive_portal_detection {
+                "allowed"
+            } else {
+                "blocked"
+            }
+        );
+        Ok(())
+    }
 }
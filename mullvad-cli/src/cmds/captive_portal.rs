@@ -0,0 +1,80 @@
+use crate::{new_rpc_client, Command, Error, Result};
+use mullvad_management_interface::types::captive_portal_status::Status;
+
+pub struct CaptivePortal;
+
+#[mullvad_management_interface::async_trait]
+impl Command for CaptivePortal {
+    fn name(&self) -> &'static str {
+        "captive-portal"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Detect and log in to captive portals, such as on hotel or airport Wi-Fi")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("detect")
+                    .about("Check whether a captive portal is intercepting web traffic"),
+            )
+            .subcommand(clap::SubCommand::with_name("login").about(
+                "Block all traffic except DNS and web traffic, so that the captive portal \
+                         can be logged in to in a browser. The previous state is entered again \
+                         once the login is complete, or after five minutes",
+            ))
+            .subcommand(
+                clap::SubCommand::with_name("stop-login")
+                    .about("Stop letting through the traffic needed to log in"),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("detect", Some(_)) => self.detect().await,
+            ("login", Some(_)) => self.login().await,
+            ("stop-login", Some(_)) => self.stop_login().await,
+            _ => unreachable!("No captive-portal command given"),
+        }
+    }
+}
+
+impl CaptivePortal {
+    async fn detect(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let status = rpc
+            .detect_captive_portal(())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to detect captive portal", error))?
+            .into_inner();
+        match Status::from_i32(status.status) {
+            Some(Status::Online) => println!("No captive portal detected"),
+            Some(Status::Detected) => {
+                println!("A captive portal is intercepting web traffic");
+                println!("Run 'mullvad captive-portal login' to be able to log in to it");
+            }
+            Some(Status::Unreachable) | None => {
+                println!("Unable to tell: the detection hosts could not be reached")
+            }
+        }
+        Ok(())
+    }
+
+    async fn login(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.start_portal_login(())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to start captive portal login", error))?;
+        println!("DNS and web traffic is now let through for up to five minutes");
+        println!("Log in to the captive portal in a browser");
+        Ok(())
+    }
+
+    async fn stop_login(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.stop_portal_login(())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to stop captive portal login", error))?;
+        println!("Stopped the captive portal login");
+        Ok(())
+    }
+}
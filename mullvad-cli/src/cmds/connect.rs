@@ -19,11 +19,28 @@ impl Command for Connect {
                     .short("w")
                     .help("Wait until connected before exiting"),
             )
+            .arg(
+                clap::Arg::with_name("until-exit")
+                    .long("until-exit")
+                    .help(
+                        "Stay connected until this command is interrupted, such as with Ctrl+C, \
+                         and then restore the previous tunnel state",
+                    )
+                    .conflicts_with("wait"),
+            )
     }
 
     async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
 
+        if matches.is_present("until-exit") {
+            let mut lease = state::hold_connection(rpc).await?;
+            println!("Connected. Press Ctrl+C to restore the previous tunnel state");
+            // The stream only ends if the daemon stops
+            while lease.message().await?.is_some() {}
+            return Err(Error::CommandFailed("connect"));
+        }
+
         let receiver_option = if matches.is_present("wait") {
             Some(state::state_listen(rpc.clone()))
         } else {
@@ -35,9 +52,9 @@ impl Command for Connect {
                 while let Some(state) = receiver.next().await {
                     let state = state?;
                     format::print_state(&state);
-                    match state.state.unwrap() {
-                        State::Connected(_) => return Ok(()),
-                        State::Error(_) => return Err(Error::CommandFailed("connect")),
+                    match state.state {
+                        Some(State::Connected(_)) => return Ok(()),
+                        Some(State::Error(_)) => return Err(Error::CommandFailed("connect")),
                         _ => {}
                     }
                 }
@@ -0,0 +1,371 @@
+use crate::{new_rpc_client, Command, Error, Result};
+use clap::value_t_or_exit;
+use mullvad_management_interface::types::{
+    ConnectTimeHistogram, RelayConnectionStatistics, RelayHealth,
+};
+use mullvad_types::units::HumanDuration;
+use std::{convert::TryFrom, fs, io, time::Duration};
+
+pub struct Debug;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Debug {
+    fn name(&self) -> &'static str {
+        "debug"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Troubleshooting tools")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("stats")
+                    .about(
+                        "Display the connection statistics collected for each relay. \
+                         The statistics never leave this device",
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("set")
+                            .about("Enable or disable collecting connection statistics")
+                            .arg(
+                                clap::Arg::with_name("policy")
+                                    .required(true)
+                                    .possible_values(&["on", "off"]),
+                            ),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("clear")
+                            .about("Remove all collected connection statistics"),
+                    )
+                    .subcommand(clap::SubCommand::with_name("connect-times").about(
+                        "Display how long the most recent successful connection attempts \
+                         took, in total and in each phase, for each version of the app",
+                    )),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("relays")
+                    .about(
+                        "Display the health of relays that have recently been connected to. \
+                         Relays that keep failing are temporarily avoided, and relays that are \
+                         quick to connect to are preferred",
+                    )
+                    .arg(
+                        clap::Arg::with_name("blacklisted")
+                            .long("blacklisted")
+                            .help("Only display relays that are currently blacklisted"),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("clear")
+                            .about("Forget the health of all relays and lift all blacklistings"),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("firewall-rules")
+                    .about("Display the rules that the firewall of the daemon has in place"),
+            )
+            .subcommand(clap::SubCommand::with_name("cleanup").about(
+                "Remove the routes and firewall objects that were left behind by a previous \
+                 version of the daemon, such as one that crashed. Routes can only be \
+                 identified on Linux and macOS",
+            ))
+            .subcommand(
+                clap::SubCommand::with_name("export-wg-config")
+                    .about(
+                        "Print the configuration of the connected WireGuard tunnel, to compare \
+                         with the output of `wg show`. The private key is redacted",
+                    )
+                    .arg(
+                        clap::Arg::with_name("include-private-key")
+                            .long("include-private-key")
+                            .help("Include the private key. Only administrators can do this"),
+                    ),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("stats", Some(stats_matches)) => match stats_matches.subcommand() {
+                ("set", Some(set_matches)) => {
+                    let policy = value_t_or_exit!(set_matches.value_of("policy"), String);
+                    self.set_stats(policy == "on").await
+                }
+                ("clear", Some(_)) => self.clear_stats().await,
+                ("connect-times", Some(_)) => self.get_connect_times().await,
+                _ => self.get_stats().await,
+            },
+            ("relays", Some(relays_matches)) => match relays_matches.subcommand() {
+                ("clear", Some(_)) => self.clear_relay_health().await,
+                _ => {
+                    self.get_relay_health(relays_matches.is_present("blacklisted"))
+                        .await
+                }
+            },
+            ("firewall-rules", Some(_)) => self.get_firewall_rules().await,
+            ("cleanup", Some(_)) => self.remove_orphaned_objects().await,
+            ("export-wg-config", Some(export_matches)) => {
+                self.export_wg_config(export_matches.is_present("include-private-key"))
+                    .await
+            }
+            _ => unreachable!("No debug command given"),
+        }
+    }
+}
+
+impl Debug {
+    async fn set_stats(&self, enabled: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_connection_statistics(enabled).await?;
+        println!("Changed connection statistics setting");
+        Ok(())
+    }
+
+    async fn clear_stats(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.clear_connection_statistics(()).await?;
+        println!("Removed all connection statistics");
+        Ok(())
+    }
+
+    async fn export_wg_config(&self, include_private_key: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let export = rpc
+            .export_wireguard_config(include_private_key)
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to export WireGuard config", error))?
+            .into_inner();
+        if export.private_config_path.is_empty() {
+            print!("{}", export.config);
+            return Ok(());
+        }
+
+        // The daemon writes the full configuration to a file that only administrators can read
+        let path = export.private_config_path;
+        let config = match fs::read_to_string(&path) {
+            Ok(config) => config,
+            Err(error) if error.kind() == io::ErrorKind::PermissionDenied => {
+                return Err(Error::CommandFailed(
+                    "Only administrators can include the private key",
+                ));
+            }
+            Err(error) => return Err(Error::ReadInputError(path, error)),
+        };
+        let _ = fs::remove_file(&path);
+        print!("{}", config);
+        Ok(())
+    }
+
+    async fn get_firewall_rules(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let rules = rpc
+            .get_firewall_rules(())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to list firewall rules", error))?
+            .into_inner();
+        println!("Backend: {}", rules.backend);
+        if rules.policy.is_empty() {
+            println!("Policy: none");
+        } else {
+            println!("Policy: {}", rules.policy);
+        }
+        for rule in &rules.rules {
+            println!("{}", rule);
+        }
+        Ok(())
+    }
+
+    async fn remove_orphaned_objects(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let orphans = rpc
+            .remove_orphaned_objects(())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to remove orphaned objects", error))?
+            .into_inner();
+        if orphans.routes.is_empty() && orphans.firewall_objects.is_empty() {
+            println!("No orphaned routes or firewall objects were found");
+        }
+        for route in &orphans.routes {
+            println!("Removed route: {}", route);
+        }
+        for object in &orphans.firewall_objects {
+            println!("Removed firewall object: {}", object);
+        }
+        for error in &orphans.errors {
+            eprintln!("Error: {}", error);
+        }
+        if !orphans.errors.is_empty() {
+            return Err(Error::CommandFailed(
+                "Some orphaned objects could not be removed",
+            ));
+        }
+        Ok(())
+    }
+
+    async fn clear_relay_health(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.clear_relay_health(()).await?;
+        println!("Forgot the health of all relays");
+        Ok(())
+    }
+
+    async fn get_relay_health(&self, only_blacklisted: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let relays: Vec<_> = rpc
+            .get_relay_health(())
+            .await?
+            .into_inner()
+            .relays
+            .into_iter()
+            .filter(|relay| !only_blacklisted || relay.blacklisted_for.is_some())
+            .collect();
+        if relays.is_empty() {
+            if only_blacklisted {
+                println!("No relays are blacklisted");
+            } else {
+                println!("No relays have been connected to recently");
+            }
+            return Ok(());
+        }
+        for relay in &relays {
+            print_relay_health(relay);
+        }
+        Ok(())
+    }
+
+    async fn get_stats(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let stats = rpc.get_connection_statistics(()).await?.into_inner();
+        println!(
+            "Connection statistics: {}",
+            if stats.enabled { "on" } else { "off" }
+        );
+        if stats.relays.is_empty() {
+            if !stats.enabled {
+                println!("Enable them with `mullvad debug stats set on`");
+            }
+            return Ok(());
+        }
+        for relay in &stats.relays {
+            print_relay_stats(relay);
+        }
+        Ok(())
+    }
+
+    async fn get_connect_times(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let times = rpc.get_connect_times(()).await?.into_inner();
+        if times.versions.is_empty() {
+            println!("No connect times have been recorded");
+            if !times.enabled {
+                println!("Enable connection statistics with `mullvad debug stats set on`");
+            }
+            return Ok(());
+        }
+        for version in &times.versions {
+            let total = version.total.clone().unwrap_or_default();
+            println!(
+                "{} ({} connections)",
+                version.version,
+                total.buckets.iter().sum::<u32>()
+            );
+            println!(
+                "\t{:<22}{:>10}{:>10}{:>10}",
+                "Phase", "Median", "90%", "Max"
+            );
+            print_connect_time_row("total", &total);
+            for phase in &version.phases {
+                print_connect_time_row(&phase.phase, &phase.histogram.clone().unwrap_or_default());
+            }
+            println!("\tTotal time to connect:");
+            print_connect_time_buckets(&times.bucket_bounds_ms, &total);
+        }
+        Ok(())
+    }
+}
+
+fn print_connect_time_row(phase: &str, histogram: &ConnectTimeHistogram) {
+    let millis = |duration: &Option<mullvad_management_interface::types::Duration>| {
+        duration
+            .clone()
+            .and_then(|duration| Duration::try_from(duration).ok())
+            .map(|duration| format!("{} ms", duration.as_millis()))
+            .unwrap_or_else(|| "-".to_owned())
+    };
+    println!(
+        "\t{:<22}{:>10}{:>10}{:>10}",
+        phase,
+        millis(&histogram.median),
+        millis(&histogram.p90),
+        millis(&histogram.max)
+    );
+}
+
+fn print_connect_time_buckets(bounds_ms: &[u64], histogram: &ConnectTimeHistogram) {
+    let most = histogram.buckets.iter().copied().max().unwrap_or(0).max(1);
+    for (i, count) in histogram.buckets.iter().enumerate() {
+        let label = match bounds_ms.get(i) {
+            Some(bound) => format!("<= {} ms", bound),
+            None => format!("> {} ms", bounds_ms.last().copied().unwrap_or(0)),
+        };
+        let bar = "#".repeat((*count * 40 / most) as usize);
+        println!("\t{:>12} {:>5} {}", label, count, bar);
+    }
+}
+
+fn print_relay_stats(relay: &RelayConnectionStatistics) {
+    println!("{}", relay.hostname);
+    let success_rate = if relay.attempts > 0 {
+        100 * relay.successes / relay.attempts
+    } else {
+        0
+    };
+    println!(
+        "\tSuccessful connections: {} of {} ({}%)",
+        relay.successes, relay.attempts, success_rate
+    );
+    if let Some(mean_connect_time) = relay
+        .mean_connect_time
+        .clone()
+        .and_then(|duration| Duration::try_from(duration).ok())
+    {
+        let millis = Duration::from_millis(mean_connect_time.as_millis() as u64);
+        println!("\tMean time to connect: {}", HumanDuration(millis));
+    }
+    if relay.peak_throughput > 0 {
+        println!(
+            "\tPeak throughput: {:.1} Mbit/s",
+            relay.peak_throughput as f64 * 8.0 / 1_000_000.0
+        );
+    }
+    if let Some(ref last_attempt) = relay.last_attempt {
+        let ndt =
+            chrono::NaiveDateTime::from_timestamp(last_attempt.seconds, last_attempt.nanos as u32);
+        let last_attempt = chrono::DateTime::<chrono::Utc>::from_utc(ndt, chrono::Utc)
+            .with_timezone(&chrono::Local);
+        println!("\tLast attempt: {}", last_attempt);
+    }
+}
+
+fn print_relay_health(relay: &RelayHealth) {
+    println!("{}", relay.hostname);
+    println!("\tHealth score: {:.2}", relay.score);
+    if let Some(blacklisted_for) = relay
+        .blacklisted_for
+        .clone()
+        .and_then(|duration| Duration::try_from(duration).ok())
+    {
+        let secs = Duration::from_secs(blacklisted_for.as_secs());
+        println!("\tBlacklisted for: {}", HumanDuration(secs));
+    }
+    println!(
+        "\tConnections: {} successful, {} failed",
+        relay.successes, relay.failures
+    );
+    if let Some(latency) = relay
+        .latency
+        .clone()
+        .and_then(|duration| Duration::try_from(duration).ok())
+    {
+        let millis = Duration::from_millis(latency.as_millis() as u64);
+        println!("\tLatency: {}", HumanDuration(millis));
+    }
+}
@@ -35,8 +35,8 @@ impl Command for Disconnect {
                 while let Some(state) = receiver.next().await {
                     let state = state?;
                     format::print_state(&state);
-                    match state.state.unwrap() {
-                        Disconnected(_) => return Ok(()),
+                    match state.state {
+                        Some(Disconnected(_)) => return Ok(()),
                         _ => {}
                     }
                 }
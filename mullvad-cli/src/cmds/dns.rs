@@ -1,7 +1,10 @@
-use crate::{new_rpc_client, Command, Result};
+use crate::{new_rpc_client, Command, Error, Result};
 use mullvad_management_interface::types;
-use mullvad_types::settings::{DnsOptions, DnsState};
-use std::convert::TryInto;
+use mullvad_types::settings::{DnsOptions, DnsServerAddress, DnsServerKind, DnsState};
+use std::{
+    convert::{TryFrom, TryInto},
+    net::IpAddr,
+};
 
 pub struct Dns;
 
@@ -18,6 +21,10 @@ impl Command for Dns {
             .subcommand(
                 clap::SubCommand::with_name("get").about("Display the current DNS settings"),
             )
+            .subcommand(clap::SubCommand::with_name("detect-forwarders").about(
+                "List the DNS forwarders, such as dnscrypt-proxy or stubby, that are running \
+                 on this device",
+            ))
             .subcommand(
                 clap::SubCommand::with_name("set")
                     .about("Set DNS servers to use")
@@ -47,6 +54,50 @@ impl Command for Dns {
                                     .help("One or more IP addresses pointing to DNS resolvers.")
                                     .required(true),
                             ),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("encrypted")
+                            .about(
+                                "Forward DNS queries to a resolver over DNS-over-TLS or \
+                                 DNS-over-HTTPS. Queries are sent through the tunnel",
+                            )
+                            .arg(
+                                clap::Arg::with_name("protocol")
+                                    .required(true)
+                                    .possible_values(&["tls", "https"]),
+                            )
+                            .arg(
+                                clap::Arg::with_name("address")
+                                    .help("IP address of the resolver")
+                                    .required(true),
+                            )
+                            .arg(
+                                clap::Arg::with_name("hostname")
+                                    .help("Name that the certificate of the resolver is issued for")
+                                    .required(true),
+                            ),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("local-forwarder")
+                            .about(
+                                "Send DNS queries to a DNS forwarder on this device, such as \
+                                 dnscrypt-proxy or stubby",
+                            )
+                            .arg(clap::Arg::with_name("address").help(
+                                "Loopback address that the forwarder listens on. Detected if \
+                                 not given",
+                            ))
+                            .arg(
+                                clap::Arg::with_name("upstream")
+                                    .long("upstream")
+                                    .multiple(true)
+                                    .number_of_values(1)
+                                    .help(
+                                        "Resolver that the forwarder sends plain DNS queries to, \
+                                         such as a bootstrap resolver. These are allowed through \
+                                         the tunnel",
+                                    ),
+                            ),
                     ),
             )
     }
@@ -64,9 +115,30 @@ impl Command for Dns {
                 ("custom", Some(matches)) => {
                     self.set_custom(matches.values_of_lossy("servers")).await
                 }
+                ("encrypted", Some(matches)) => {
+                    let protocol = match matches.value_of("protocol").unwrap() {
+                        "tls" => types::encrypted_dns_server::Protocol::Tls,
+                        "https" => types::encrypted_dns_server::Protocol::Https,
+                        _ => unreachable!("Invalid encrypted DNS protocol"),
+                    };
+                    self.set_encrypted(
+                        protocol,
+                        matches.value_of("address").unwrap(),
+                        matches.value_of("hostname").unwrap(),
+                    )
+                    .await
+                }
+                ("local-forwarder", Some(matches)) => {
+                    self.set_local_forwarder(
+                        matches.value_of("address"),
+                        matches.values_of_lossy("upstream").unwrap_or_default(),
+                    )
+                    .await
+                }
                 _ => unreachable!("No custom-dns server command given"),
             },
             ("get", _) => self.get().await,
+            ("detect-forwarders", _) => self.detect_forwarders().await,
             _ => unreachable!("No custom-dns command given"),
         }
     }
@@ -92,24 +164,108 @@ impl Dns {
     async fn set_custom(&self, servers: Option<Vec<String>>) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
         let settings = rpc.get_settings(()).await?.into_inner();
+        let servers = servers.unwrap_or_default();
         rpc.set_dns_options(types::DnsOptions {
             state: types::dns_options::DnsState::Custom as i32,
             custom_options: Some(types::CustomDnsOptions {
-                addresses: servers.unwrap_or_default(),
+                addresses: servers.clone(),
             }),
             ..settings.tunnel_options.unwrap().dns_options.unwrap()
         })
         .await?;
         println!("Updated DNS settings");
+
+        for server in servers {
+            let server = server
+                .parse::<IpAddr>()
+                .ok()
+                .and_then(|server| DnsServerAddress::try_from(server).ok());
+            if let Some(note) =
+                server.and_then(|server| dns_server_note(server, settings.allow_lan))
+            {
+                println!("{}", note);
+            }
+        }
+        Ok(())
+    }
+
+    async fn set_encrypted(
+        &self,
+        protocol: types::encrypted_dns_server::Protocol,
+        address: &str,
+        hostname: &str,
+    ) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc.get_settings(()).await?.into_inner();
+        rpc.set_dns_options(types::DnsOptions {
+            state: types::dns_options::DnsState::Encrypted as i32,
+            encrypted_options: Some(types::EncryptedDnsOptions {
+                server: Some(types::EncryptedDnsServer {
+                    protocol: protocol as i32,
+                    address: address.to_owned(),
+                    hostname: hostname.to_owned(),
+                }),
+            }),
+            ..settings.tunnel_options.unwrap().dns_options.unwrap()
+        })
+        .await?;
+        println!("Updated DNS settings");
+        Ok(())
+    }
+
+    async fn set_local_forwarder(
+        &self,
+        address: Option<&str>,
+        upstream_servers: Vec<String>,
+    ) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let address = match address {
+            Some(address) => address.to_owned(),
+            None => {
+                let forwarders = rpc.detect_dns_forwarders(()).await?.into_inner().forwarders;
+                match forwarders.into_iter().next() {
+                    Some(forwarder) => {
+                        println!("Using {}", format_forwarder(&forwarder));
+                        forwarder.address
+                    }
+                    None => {
+                        return Err(Error::CommandFailed(
+                            "No DNS forwarder is running on this device",
+                        ))
+                    }
+                }
+            }
+        };
+        let settings = rpc.get_settings(()).await?.into_inner();
+        rpc.set_dns_options(types::DnsOptions {
+            state: types::dns_options::DnsState::LocalForwarder as i32,
+            local_forwarder_options: Some(types::LocalDnsForwarderOptions {
+                address,
+                upstream_servers,
+            }),
+            ..settings.tunnel_options.unwrap().dns_options.unwrap()
+        })
+        .await?;
+        println!("Updated DNS settings");
+        Ok(())
+    }
+
+    async fn detect_forwarders(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let forwarders = rpc.detect_dns_forwarders(()).await?.into_inner().forwarders;
+        if forwarders.is_empty() {
+            println!("No DNS forwarder is running on this device");
+        }
+        for forwarder in &forwarders {
+            println!("{}", format_forwarder(forwarder));
+        }
         Ok(())
     }
 
     async fn get(&self) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
-        let options: DnsOptions = rpc
-            .get_settings(())
-            .await?
-            .into_inner()
+        let settings = rpc.get_settings(()).await?.into_inner();
+        let options: DnsOptions = settings
             .tunnel_options
             .unwrap()
             .dns_options
@@ -127,6 +283,26 @@ impl Dns {
                 println!("Custom DNS: yes\nServers:");
                 for server in &options.custom_options.addresses {
                     println!("{}", server);
+                    if let Some(note) = dns_server_note(*server, settings.allow_lan) {
+                        println!("    {}", note);
+                    }
+                }
+            }
+            DnsState::Encrypted => match &options.encrypted_options.server {
+                Some(server) => println!("Encrypted DNS: {}", server),
+                None => println!("Encrypted DNS: no server is set"),
+            },
+            DnsState::LocalForwarder => {
+                let forwarder_options = &options.local_forwarder_options;
+                match &forwarder_options.address {
+                    Some(address) => println!("Local DNS forwarder: {}", address),
+                    None => println!("Local DNS forwarder: no address is set"),
+                }
+                if !forwarder_options.upstream_servers.is_empty() {
+                    println!("Upstream servers:");
+                    for server in &forwarder_options.upstream_servers {
+                        println!("{}", server);
+                    }
                 }
             }
         }
@@ -134,3 +310,30 @@ impl Dns {
         Ok(())
     }
 }
+
+/// Returns a warning if the DNS server may not be reachable while connected.
+fn dns_server_note(server: DnsServerAddress, allow_lan: bool) -> Option<String> {
+    match server.kind() {
+        DnsServerKind::Public => None,
+        DnsServerKind::Lan if allow_lan => None,
+        DnsServerKind::Lan => Some(format!(
+            "Warning: {} is on the local network and is only reachable when local network \
+             sharing is allowed. Allow it with \"mullvad lan set allow\".",
+            server
+        )),
+        DnsServerKind::Loopback => Some(format!(
+            "Note: {} is a loopback address. It only works if a DNS server is running on this \
+             device. Use \"mullvad dns set local-forwarder\" for forwarders such as \
+             dnscrypt-proxy.",
+            server
+        )),
+    }
+}
+
+fn format_forwarder(forwarder: &types::DnsForwarder) -> String {
+    if forwarder.program.is_empty() {
+        forwarder.address.clone()
+    } else {
+        format!("{} ({})", forwarder.address, forwarder.program)
+    }
+}
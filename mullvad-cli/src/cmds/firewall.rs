@@ -0,0 +1,157 @@
+use crate::{new_rpc_client, Command, Error, Result};
+use clap::value_t;
+use mullvad_management_interface::types;
+use std::convert::TryFrom;
+use talpid_types::net::{AllowedEndpoint, TransportProtocol};
+
+pub struct Firewall;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Firewall {
+    fn name(&self) -> &'static str {
+        "firewall"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Control the firewall rules that block traffic outside the tunnel")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("exception")
+                    .about(
+                        "Allow traffic to and from a network outside the tunnel, in every \
+                         tunnel state. DNS requests are blocked regardless",
+                    )
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(create_exception_args(
+                        clap::SubCommand::with_name("add").about("Add a firewall exception"),
+                    ))
+                    .subcommand(create_exception_args(
+                        clap::SubCommand::with_name("remove").about("Remove a firewall exception"),
+                    ))
+                    .subcommand(
+                        create_exception_args(clap::SubCommand::with_name("add-temporary").about(
+                            "Add a firewall exception that is removed after some time. It \
+                             is not saved in the settings",
+                        ))
+                        .arg(
+                            clap::Arg::with_name("seconds")
+                                .long("seconds")
+                                .takes_value(true)
+                                .default_value("60")
+                                .help("How long to allow the traffic for"),
+                        ),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("list").about("List the firewall exceptions"),
+                    ),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("exception", Some(matches)) => match matches.subcommand() {
+                ("add", Some(matches)) => self.add_exception(matches).await,
+                ("remove", Some(matches)) => self.remove_exception(matches).await,
+                ("add-temporary", Some(matches)) => self.add_temporary_exception(matches).await,
+                ("list", _) => self.list_exceptions().await,
+                _ => unreachable!("unhandled command"),
+            },
+            _ => unreachable!("unhandled command"),
+        }
+    }
+}
+
+impl Firewall {
+    async fn add_exception(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let exception = parse_exception_args(matches);
+        let mut rpc = new_rpc_client().await?;
+        rpc.add_firewall_exception(exception)
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to add firewall exception", error))?;
+        println!("Added firewall exception");
+        Ok(())
+    }
+
+    async fn remove_exception(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let exception = parse_exception_args(matches);
+        let mut rpc = new_rpc_client().await?;
+        rpc.remove_firewall_exception(exception)
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to remove firewall exception", error))?;
+        println!("Removed firewall exception");
+        Ok(())
+    }
+
+    async fn add_temporary_exception(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let exception = parse_exception_args(matches);
+        let seconds = value_t!(matches.value_of("seconds"), u32).unwrap_or_else(|e| e.exit());
+        let mut rpc = new_rpc_client().await?;
+        rpc.allow_temporarily(types::TemporaryFirewallException {
+            exception: Some(exception),
+            seconds,
+        })
+        .await
+        .map_err(|error| {
+            Error::RpcFailedExt("Failed to add temporary firewall exception", error)
+        })?;
+        println!("Added firewall exception for {} seconds", seconds);
+        Ok(())
+    }
+
+    async fn list_exceptions(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc.get_settings(()).await?.into_inner();
+        if settings.firewall_exceptions.is_empty() {
+            println!("No firewall exceptions");
+            return Ok(());
+        }
+        for exception in settings.firewall_exceptions {
+            println!("{}", AllowedEndpoint::try_from(exception).unwrap());
+        }
+        Ok(())
+    }
+}
+
+fn create_exception_args(subcommand: clap::App<'static, 'static>) -> clap::App<'static, 'static> {
+    subcommand
+        .arg(
+            clap::Arg::with_name("network")
+                .help("The network to allow, such as 192.168.100.0/24 or 10.1.2.3")
+                .required(true),
+        )
+        .arg(
+            clap::Arg::with_name("port")
+                .long("port")
+                .takes_value(true)
+                .help("Only allow this port of the hosts in the network"),
+        )
+        .arg(
+            clap::Arg::with_name("protocol")
+                .long("protocol")
+                .takes_value(true)
+                .possible_values(&["tcp", "udp"])
+                .help("Only allow this protocol. By default, both TCP and UDP are allowed"),
+        )
+}
+
+fn parse_exception_args(matches: &clap::ArgMatches<'_>) -> types::FirewallException {
+    let port = if matches.is_present("port") {
+        value_t!(matches.value_of("port"), u16).unwrap_or_else(|e| e.exit())
+    } else {
+        0
+    };
+    let protocol =
+        matches
+            .value_of("protocol")
+            .map(|protocol| types::TransportProtocolConstraint {
+                protocol: i32::from(types::TransportProtocol::from(
+                    protocol.parse::<TransportProtocol>().unwrap(),
+                )),
+            });
+    types::FirewallException {
+        network: matches.value_of("network").unwrap().to_owned(),
+        port: u32::from(port),
+        protocol,
+    }
+}
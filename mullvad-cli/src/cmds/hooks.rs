@@ -0,0 +1,155 @@
+use crate::{new_rpc_client, Command, Error, Result};
+use mullvad_management_interface::types;
+use mullvad_types::{
+    settings::{MAX_HOOK_TIMEOUT, MIN_HOOK_TIMEOUT},
+    units::HumanDuration,
+};
+use std::{path::Path, time::Duration};
+
+pub struct Hooks;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Hooks {
+    fn name(&self) -> &'static str {
+        "hooks"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about(
+                "Manage the scripts that are run when the tunnel connects and disconnects. The \
+                 post-up script is given the tunnel interface and IP addresses in the \
+                 MULLVAD_TUNNEL_INTERFACE and MULLVAD_TUNNEL_IPS environment variables. The \
+                 output of the scripts is written to the daemon log. Scripts are only run if \
+                 they cannot be changed by users other than administrators",
+            )
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(clap::SubCommand::with_name("get").about("Display the hook settings"))
+            .subcommand(
+                clap::SubCommand::with_name("set")
+                    .about("Set the script to run at a point in the life of the tunnel")
+                    .arg(hook_arg())
+                    .arg(
+                        clap::Arg::with_name("script")
+                            .help("The absolute path to the script")
+                            .required(true),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("unset")
+                    .about("Stop running a script")
+                    .arg(hook_arg()),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("timeout")
+                    .about("Set how long a script may run for before it is stopped")
+                    .arg(
+                        clap::Arg::with_name("timeout")
+                            .help("The timeout, such as \"30s\" or \"5m\"")
+                            .required(true),
+                    ),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("get", Some(_)) => self.get().await,
+            ("set", Some(set_matches)) => {
+                let hook = set_matches.value_of("hook").unwrap();
+                let script = set_matches.value_of("script").unwrap();
+                if !Path::new(script).is_absolute() {
+                    return Err(Error::InvalidCommand(
+                        "The path to the script must be absolute",
+                    ));
+                }
+                self.update(|settings| *script_mut(settings, hook) = script.to_owned())
+                    .await
+            }
+            ("unset", Some(unset_matches)) => {
+                let hook = unset_matches.value_of("hook").unwrap();
+                self.update(|settings| script_mut(settings, hook).clear())
+                    .await
+            }
+            ("timeout", Some(timeout_matches)) => {
+                let timeout = parse_timeout(timeout_matches.value_of("timeout").unwrap())?;
+                self.update(|settings| settings.timeout = timeout.as_secs())
+                    .await
+            }
+            _ => unreachable!("No hooks command given"),
+        }
+    }
+}
+
+impl Hooks {
+    async fn update(&self, change: impl FnOnce(&mut types::HookSettings)) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let mut settings = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .hooks
+            .unwrap_or_default();
+        change(&mut settings);
+        rpc.set_hook_settings(settings).await?;
+        println!("Updated hook settings");
+        Ok(())
+    }
+
+    async fn get(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .hooks
+            .unwrap_or_default();
+        let script = |script: &str| {
+            if script.is_empty() {
+                "none".to_owned()
+            } else {
+                script.to_owned()
+            }
+        };
+        println!("pre-up: {}", script(&settings.pre_up));
+        println!("post-up: {}", script(&settings.post_up));
+        println!("post-down: {}", script(&settings.post_down));
+        println!(
+            "Timeout: {}",
+            HumanDuration(Duration::from_secs(settings.timeout))
+        );
+        Ok(())
+    }
+}
+
+fn hook_arg() -> clap::Arg<'static, 'static> {
+    clap::Arg::with_name("hook")
+        .required(true)
+        .possible_values(&["pre-up", "post-up", "post-down"])
+        .help(
+            "pre-up runs when the tunnel starts connecting after having been disconnected, \
+             post-up every time that the tunnel has connected, and post-down when the tunnel has \
+             been disconnected",
+        )
+}
+
+fn script_mut<'a>(settings: &'a mut types::HookSettings, hook: &str) -> &'a mut String {
+    match hook {
+        "pre-up" => &mut settings.pre_up,
+        "post-up" => &mut settings.post_up,
+        "post-down" => &mut settings.post_down,
+        _ => unreachable!("invalid hook"),
+    }
+}
+
+fn parse_timeout(value: &str) -> Result<Duration> {
+    let timeout = value
+        .parse::<HumanDuration>()
+        .map(Duration::from)
+        .map_err(|_| Error::InvalidCommand("Invalid timeout"))?;
+    if timeout < MIN_HOOK_TIMEOUT || timeout > MAX_HOOK_TIMEOUT {
+        return Err(Error::InvalidCommand(
+            "The timeout must be between 1 second and 10 minutes",
+        ));
+    }
+    Ok(timeout)
+}
@@ -1,4 +1,4 @@
-use crate::{new_rpc_client, Command, Result};
+use crate::{new_rpc_client, Command, Error, Result};
 use clap::value_t_or_exit;
 
 pub struct Lan;
@@ -10,7 +10,7 @@ impl Command for Lan {
     }
 
     fn clap_subcommand(&self) -> clap::App<'static, 'static> {
-        clap::SubCommand::with_name(self.name())
+        let subcmd = clap::SubCommand::with_name(self.name())
             .about("Control the allow local network sharing setting")
             .setting(clap::AppSettings::SubcommandRequiredElseHelp)
             .subcommand(
@@ -26,6 +26,11 @@ impl Command for Lan {
                 clap::SubCommand::with_name("get")
                     .about("Display the current local network sharing setting"),
             )
+            .subcommand(create_proxy_subcommand())
+            .subcommand(create_mdns_subcommand());
+        #[cfg(windows)]
+        let subcmd = subcmd.subcommand(create_virtual_networks_subcommand());
+        subcmd
     }
 
     async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
@@ -34,12 +39,106 @@ impl Command for Lan {
             self.set(allow_lan == "allow").await
         } else if let Some(_matches) = matches.subcommand_matches("get") {
             self.get().await
+        } else if let Some(proxy_matches) = matches.subcommand_matches("proxy") {
+            match proxy_matches.subcommand() {
+                ("set", Some(set_matches)) => self.set_proxy(set_matches).await,
+                ("get", Some(_)) => self.get_proxy().await,
+                _ => unreachable!("No lan proxy command given"),
+            }
+        } else if let Some(mdns_matches) = matches.subcommand_matches("mdns") {
+            match mdns_matches.subcommand() {
+                ("set", Some(set_matches)) => {
+                    let enabled = value_t_or_exit!(set_matches.value_of("policy"), String);
+                    self.set_mdns_reflector(enabled == "on").await
+                }
+                ("get", Some(_)) => self.get_mdns_reflector().await,
+                _ => unreachable!("No lan mdns command given"),
+            }
         } else {
+            #[cfg(windows)]
+            if let Some(vnet_matches) = matches.subcommand_matches("virtual-networks") {
+                return match vnet_matches.subcommand() {
+                    ("set", Some(set_matches)) => {
+                        let policy = value_t_or_exit!(set_matches.value_of("policy"), String);
+                        self.set_virtual_networks(policy == "allow").await
+                    }
+                    ("get", Some(_)) => self.get_virtual_networks().await,
+                    _ => unreachable!("No lan virtual-networks command given"),
+                };
+            }
             unreachable!("No lan command given");
         }
     }
 }
 
+fn create_proxy_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("proxy")
+        .about(
+            "Manage the SOCKS5 and HTTP CONNECT proxy that lets other devices on the local \
+             network use the tunnel. It only runs while local network sharing is allowed",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("set")
+                .about("Enable or disable the proxy")
+                .arg(
+                    clap::Arg::with_name("policy")
+                        .required(true)
+                        .possible_values(&["on", "off"]),
+                )
+                .arg(
+                    clap::Arg::with_name("port")
+                        .help("The TCP port to listen on")
+                        .long("port")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(clap::SubCommand::with_name("get").about("Display the proxy settings"))
+}
+
+fn create_mdns_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("mdns")
+        .about(
+            "Relay mDNS service discovery, used to find devices such as printers and media \
+             players, between the local network and this device while connected. It only runs \
+             while local network sharing is allowed",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("set")
+                .about("Enable or disable the mDNS reflector")
+                .arg(
+                    clap::Arg::with_name("policy")
+                        .required(true)
+                        .possible_values(&["on", "off"]),
+                ),
+        )
+        .subcommand(clap::SubCommand::with_name("get").about("Display the mDNS reflector setting"))
+}
+
+#[cfg(windows)]
+fn create_virtual_networks_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("virtual-networks")
+        .about(
+            "Control whether the subnets of the Hyper-V NAT switches, such as the one used by \
+             WSL2, are reachable in every tunnel state. This does not depend on the local \
+             network sharing setting",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("set")
+                .about("Change the virtual networks setting")
+                .arg(
+                    clap::Arg::with_name("policy")
+                        .required(true)
+                        .possible_values(&["allow", "block"]),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("get").about("Display the virtual networks setting"),
+        )
+}
+
 impl Lan {
     async fn set(&self, allow_lan: bool) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
@@ -57,4 +156,80 @@ impl Lan {
         );
         Ok(())
     }
+
+    async fn set_proxy(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let mut settings = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .lan_proxy
+            .unwrap_or_default();
+        settings.enabled = value_t_or_exit!(matches.value_of("policy"), String) == "on";
+        if let Some(port) = matches.value_of("port") {
+            let port = port
+                .parse::<u16>()
+                .ok()
+                .filter(|port| *port != 0)
+                .ok_or(Error::InvalidCommand("Invalid port. Must be in [1-65535]."))?;
+            settings.port = u32::from(port);
+        }
+        rpc.set_lan_proxy_settings(settings).await?;
+        println!("Updated LAN proxy settings");
+        Ok(())
+    }
+
+    async fn get_proxy(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc.get_settings(()).await?.into_inner();
+        let proxy = settings.lan_proxy.unwrap_or_default();
+        println!("LAN proxy: {}", if proxy.enabled { "on" } else { "off" });
+        println!("LAN proxy port: {}", proxy.port);
+        if proxy.enabled && !settings.allow_lan {
+            println!("The proxy is not running, since local network sharing is blocked");
+        }
+        Ok(())
+    }
+
+    async fn set_mdns_reflector(&self, enabled: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_mdns_reflector(enabled).await?;
+        println!("Changed mDNS reflector setting");
+        Ok(())
+    }
+
+    async fn get_mdns_reflector(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc.get_settings(()).await?.into_inner();
+        println!(
+            "mDNS reflector: {}",
+            if settings.mdns_reflector { "on" } else { "off" }
+        );
+        if settings.mdns_reflector && !settings.allow_lan {
+            println!("The reflector is not running, since local network sharing is blocked");
+        }
+        Ok(())
+    }
+    #[cfg(windows)]
+    async fn set_virtual_networks(&self, allow: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_allow_virtual_networks(allow).await?;
+        println!("Changed virtual networks setting");
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    async fn get_virtual_networks(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc.get_settings(()).await?.into_inner();
+        println!(
+            "Virtual networks: {}",
+            if settings.allow_virtual_networks {
+                "allow"
+            } else {
+                "block"
+            }
+        );
+        Ok(())
+    }
 }
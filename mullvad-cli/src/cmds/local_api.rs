@@ -0,0 +1,94 @@
+use crate::{new_rpc_client, Command, Error, Result};
+use clap::value_t_or_exit;
+
+pub struct LocalApi;
+
+#[mullvad_management_interface::async_trait]
+impl Command for LocalApi {
+    fn name(&self) -> &'static str {
+        "local-api"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about(
+                "Manage the read-only HTTP API on localhost that reports the tunnel state, the \
+                 relay in use and the remaining account time, for integrations such as status \
+                 bars. Requests must carry the token in an \"Authorization: Bearer\" header",
+            )
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("set")
+                    .about("Enable or disable the local API")
+                    .arg(
+                        clap::Arg::with_name("policy")
+                            .required(true)
+                            .possible_values(&["on", "off"]),
+                    )
+                    .arg(
+                        clap::Arg::with_name("port")
+                            .help("The TCP port on localhost to listen on")
+                            .long("port")
+                            .takes_value(true),
+                    ),
+            )
+            .subcommand(clap::SubCommand::with_name("get").about("Display the local API settings"))
+            .subcommand(
+                clap::SubCommand::with_name("token")
+                    .about("Display the token that clients of the local API must present"),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("set", Some(set_matches)) => self.set(set_matches).await,
+            ("get", Some(_)) => self.get().await,
+            ("token", Some(_)) => self.token().await,
+            _ => unreachable!("No local-api command given"),
+        }
+    }
+}
+
+impl LocalApi {
+    async fn set(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let mut settings = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .local_api
+            .unwrap_or_default();
+        settings.enabled = value_t_or_exit!(matches.value_of("policy"), String) == "on";
+        if let Some(port) = matches.value_of("port") {
+            let port = port
+                .parse::<u16>()
+                .ok()
+                .filter(|port| *port != 0)
+                .ok_or(Error::InvalidCommand("Invalid port. Must be in [1-65535]."))?;
+            settings.port = u32::from(port);
+        }
+        rpc.set_local_api_settings(settings).await?;
+        println!("Updated local API settings");
+        Ok(())
+    }
+
+    async fn get(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .local_api
+            .unwrap_or_default();
+        println!("Local API: {}", if settings.enabled { "on" } else { "off" });
+        println!("Local API address: http://127.0.0.1:{}/v1/", settings.port);
+        Ok(())
+    }
+
+    async fn token(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let token = rpc.get_local_api_token(()).await?.into_inner();
+        println!("{}", token);
+        Ok(())
+    }
+}
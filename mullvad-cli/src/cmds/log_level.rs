@@ -0,0 +1,159 @@
+use crate::{new_rpc_client, Command, Result};
+use mullvad_management_interface::types::{self, LogLevelDirective, LogLevelOverrides};
+use mullvad_types::units::HumanDuration;
+use std::{convert::TryFrom, time::Duration};
+
+const LOG_LEVELS: &[&str] = &["off", "error", "warn", "info", "debug", "trace"];
+const DEFAULT_REVERT_AFTER: &str = "30m";
+
+pub struct LogLevel;
+
+#[mullvad_management_interface::async_trait]
+impl Command for LogLevel {
+    fn name(&self) -> &'static str {
+        "log-level"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Temporarily change how much the daemon logs, without restarting it")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("set")
+                    .about("Override the log levels. Previous overrides are replaced")
+                    .arg(
+                        clap::Arg::with_name("directives")
+                            .help(
+                                "Log levels such as talpid_core::firewall=trace. A level \
+                                 without a module applies to all modules",
+                            )
+                            .required(true)
+                            .multiple(true)
+                            .validator(|value| parse_directive(&value).map(|_| ())),
+                    )
+                    .arg(
+                        clap::Arg::with_name("revert after")
+                            .help(
+                                "Revert to the normal log levels after this long, such as 1h. \
+                                 0 keeps the levels until they are reset or the daemon restarts",
+                            )
+                            .long("revert-after")
+                            .takes_value(true)
+                            .default_value(DEFAULT_REVERT_AFTER)
+                            .validator(|value| parse_revert_after(&value).map(|_| ())),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("get").about("Display the overridden log levels"),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("reset").about("Revert to the normal log levels"),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("set", Some(set_matches)) => {
+                let directives = set_matches
+                    .values_of("directives")
+                    .unwrap()
+                    .map(|value| parse_directive(value).unwrap())
+                    .collect();
+                let revert_after =
+                    parse_revert_after(set_matches.value_of("revert after").unwrap()).unwrap();
+                self.set(directives, revert_after).await
+            }
+            ("get", Some(_)) => self.get().await,
+            ("reset", Some(_)) => self.reset().await,
+            _ => unreachable!("No log level command given"),
+        }
+    }
+}
+
+/// Parses a directive written as `module=level`, or only `level` for all modules.
+fn parse_directive(value: &str) -> std::result::Result<LogLevelDirective, String> {
+    let (module, level) = match value.rfind('=') {
+        Some(index) => (&value[..index], &value[index + 1..]),
+        None => ("", value),
+    };
+    let level = level.trim().to_lowercase();
+    if !LOG_LEVELS.contains(&level.as_str()) {
+        return Err(format!(
+            "Invalid log level \"{}\", expected one of {}",
+            level,
+            LOG_LEVELS.join(", ")
+        ));
+    }
+    Ok(LogLevelDirective {
+        module: module.trim().to_owned(),
+        level,
+    })
+}
+
+/// Parses the time until the levels revert, where 0 means that they do not revert.
+fn parse_revert_after(value: &str) -> std::result::Result<Option<Duration>, String> {
+    if value.trim() == "0" {
+        return Ok(None);
+    }
+    value
+        .trim()
+        .parse::<HumanDuration>()
+        .map(|duration| Some(Duration::from(duration)))
+        .map_err(|error| error.to_string())
+}
+
+impl LogLevel {
+    async fn set(
+        &self,
+        directives: Vec<LogLevelDirective>,
+        revert_after: Option<Duration>,
+    ) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_log_level_overrides(LogLevelOverrides {
+            directives,
+            revert_after: revert_after.map(types::Duration::from),
+        })
+        .await?;
+        match revert_after {
+            Some(revert_after) => println!(
+                "Changed log levels until {} from now",
+                HumanDuration(revert_after)
+            ),
+            None => println!("Changed log levels"),
+        }
+        Ok(())
+    }
+
+    async fn get(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let overrides = rpc.get_log_level_overrides(()).await?.into_inner();
+        if overrides.directives.is_empty() {
+            println!("The log levels are not overridden");
+            return Ok(());
+        }
+        for directive in &overrides.directives {
+            if directive.module.is_empty() {
+                println!("(all modules): {}", directive.level);
+            } else {
+                println!("{}: {}", directive.module, directive.level);
+            }
+        }
+        if let Some(remaining) = overrides
+            .revert_after
+            .and_then(|remaining| Duration::try_from(remaining).ok())
+        {
+            println!(
+                "Reverting in {}",
+                HumanDuration(Duration::from_secs(remaining.as_secs()))
+            );
+        }
+        Ok(())
+    }
+
+    async fn reset(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.reset_log_level_overrides(()).await?;
+        println!("Reverted to the normal log levels");
+        Ok(())
+    }
+}
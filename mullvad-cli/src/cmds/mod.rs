@@ -4,9 +4,15 @@ use std::collections::HashMap;
 mod account;
 pub use self::account::Account;
 
+mod api_access;
+pub use self::api_access::ApiAccess;
+
 mod auto_connect;
 pub use self::auto_connect::AutoConnect;
 
+mod beta_features;
+pub use self::beta_features::BetaFeatures;
+
 mod beta_program;
 pub use self::beta_program::BetaProgram;
 
@@ -16,18 +22,48 @@ pub use self::block_when_disconnected::BlockWhenDisconnected;
 mod bridge;
 pub use self::bridge::Bridge;
 
+mod captive_portal;
+pub use self::captive_portal::CaptivePortal;
+
 mod connect;
 pub use self::connect::Connect;
 
+mod debug;
+pub use self::debug::Debug;
+
 mod disconnect;
 pub use self::disconnect::Disconnect;
 
 mod dns;
 pub use self::dns::Dns;
 
+mod firewall;
+pub use self::firewall::Firewall;
+
+mod hooks;
+pub use self::hooks::Hooks;
+
 mod lan;
 pub use self::lan::Lan;
 
+mod local_api;
+pub use self::local_api::LocalApi;
+
+mod log_level;
+pub use self::log_level::LogLevel;
+
+mod network_rules;
+pub use self::network_rules::NetworkRules;
+
+mod obfuscation;
+pub use self::obfuscation::Obfuscation;
+
+mod port_forward;
+pub use self::port_forward::PortForward;
+
+mod profile;
+pub use self::profile::Profile;
+
 mod reconnect;
 pub use self::reconnect::Reconnect;
 
@@ -37,6 +73,15 @@ pub use self::relay::Relay;
 mod reset;
 pub use self::reset::Reset;
 
+mod run;
+pub use self::run::Run;
+
+mod settings;
+pub use self::settings::Settings;
+
+mod setup;
+pub use self::setup::Setup;
+
 #[cfg(any(target_os = "linux", windows))]
 mod split_tunnel;
 #[cfg(any(target_os = "linux", windows))]
@@ -55,17 +100,32 @@ pub use self::version::Version;
 pub fn get_commands() -> HashMap<&'static str, Box<dyn Command>> {
     let commands: Vec<Box<dyn Command>> = vec![
         Box::new(Account),
+        Box::new(ApiAccess),
         Box::new(AutoConnect),
+        Box::new(BetaFeatures),
         Box::new(BetaProgram),
         Box::new(BlockWhenDisconnected),
         Box::new(Bridge),
+        Box::new(CaptivePortal),
         Box::new(Connect),
+        Box::new(Debug),
         Box::new(Disconnect),
         Box::new(Dns),
+        Box::new(Firewall),
+        Box::new(Hooks),
         Box::new(Reconnect),
         Box::new(Lan),
+        Box::new(LocalApi),
+        Box::new(LogLevel),
+        Box::new(NetworkRules),
+        Box::new(Obfuscation),
+        Box::new(PortForward),
+        Box::new(Profile),
         Box::new(Relay),
         Box::new(Reset),
+        Box::new(Run),
+        Box::new(Settings),
+        Box::new(Setup),
         #[cfg(any(target_os = "linux", windows))]
         Box::new(SplitTunnel),
         Box::new(Status),
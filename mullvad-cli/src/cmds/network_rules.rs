@@ -0,0 +1,212 @@
+use crate::{new_rpc_client, Command, Error, Result};
+use mullvad_management_interface::types;
+use mullvad_types::network_rules::{NetworkAction, NetworkMatch, NetworkRule};
+use std::convert::TryFrom;
+
+pub struct NetworkRules;
+
+#[mullvad_management_interface::async_trait]
+impl Command for NetworkRules {
+    fn name(&self) -> &'static str {
+        "network-rules"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about(
+                "Connect or disconnect the tunnel when certain networks are joined. The rules are \
+                 only applied when the host joins another network",
+            )
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                create_network_args(
+                    clap::SubCommand::with_name("add")
+                        .about("Add a rule, replacing any rule for the same networks"),
+                )
+                .arg(action_arg()),
+            )
+            .subcommand(create_network_args(
+                clap::SubCommand::with_name("remove").about("Remove the rule for some networks"),
+            ))
+            .subcommand(
+                clap::SubCommand::with_name("default")
+                    .about("Set what to do on networks that no rule matches")
+                    .arg(
+                        clap::Arg::with_name("action")
+                            .required(true)
+                            .possible_values(&["connect", "disconnect", "nothing"]),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("list")
+                    .about("List the rules, in the order that they are checked in"),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("current")
+                    .about("Display the network that the rules are matched against"),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("add", Some(matches)) => self.add(matches).await,
+            ("remove", Some(matches)) => self.remove(matches).await,
+            ("default", Some(matches)) => self.set_default(matches).await,
+            ("list", _) => self.list().await,
+            ("current", _) => self.current().await,
+            _ => unreachable!("unhandled command"),
+        }
+    }
+}
+
+impl NetworkRules {
+    async fn add(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let action = parse_action(matches.value_of("action").unwrap());
+        let rule = types::NetworkRule {
+            network: Some(parse_network_args(matches)),
+            action: Some(types::NetworkAction::from(action)),
+        };
+        let mut rpc = new_rpc_client().await?;
+        rpc.add_network_rule(rule)
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to add network rule", error))?;
+        println!("Added network rule");
+        Ok(())
+    }
+
+    async fn remove(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.remove_network_rule(parse_network_args(matches))
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to remove network rule", error))?;
+        println!("Removed network rule");
+        Ok(())
+    }
+
+    async fn set_default(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let action = match matches.value_of("action").unwrap() {
+            "nothing" => None,
+            action => Some(types::NetworkAction::from(parse_action(action))),
+        };
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_default_network_action(types::DefaultNetworkAction { action })
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to set default network action", error))?;
+        println!("Changed default network action");
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc.get_settings(()).await?.into_inner();
+        let network_rules = settings.network_rules.unwrap();
+        if network_rules.rules.is_empty() {
+            println!("No network rules");
+        }
+        for rule in network_rules.rules {
+            let rule = NetworkRule::try_from(rule).unwrap();
+            println!("{}: {}", rule.network, rule.action);
+        }
+        let default_action = network_rules
+            .default_action
+            .and_then(|default_action| default_action.action)
+            .map(|action| NetworkAction::try_from(action).unwrap());
+        match default_action {
+            Some(action) => println!("Other networks: {}", action),
+            None => println!("Other networks: nothing"),
+        }
+        Ok(())
+    }
+
+    async fn current(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let network = rpc
+            .get_current_network(())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to get the current network", error))?
+            .into_inner();
+        if network.ssids.is_empty() {
+            println!("Wi-Fi networks: none");
+        } else {
+            println!("Wi-Fi networks: {}", network.ssids.join(", "));
+        }
+        if network.networks.is_empty() {
+            println!("On-link networks: none");
+        } else {
+            println!("On-link networks: {}", network.networks.join(", "));
+        }
+        if let Some(fingerprint) = network.fingerprint {
+            use types::network_fingerprint::InterfaceType;
+            let interface_type = match InterfaceType::from_i32(fingerprint.interface_type) {
+                Some(InterfaceType::Wifi) => "Wi-Fi",
+                Some(InterfaceType::Ethernet) => "Ethernet",
+                Some(InterfaceType::Cellular) => "cellular",
+                _ => "other",
+            };
+            println!("Default route interface: {}", interface_type);
+            println!("Gateway: {}", or_unknown(&fingerprint.gateway_mac));
+            println!("Country: {}", or_unknown(&fingerprint.country));
+        }
+        Ok(())
+    }
+}
+
+fn create_network_args(subcommand: clap::App<'static, 'static>) -> clap::App<'static, 'static> {
+    subcommand
+        .arg(
+            clap::Arg::with_name("kind")
+                .required(true)
+                .possible_values(&["ssid", "subnet", "gateway"])
+                .help(
+                    "Whether to match a Wi-Fi network by its SSID, networks within a subnet, or \
+                     the network whose default gateway has the hash shown by \"network-rules \
+                     current\"",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("network")
+                .required(true)
+                .help("The SSID, subnet or gateway, such as \"Home\" or 192.168.1.0/24"),
+        )
+}
+
+fn action_arg() -> clap::Arg<'static, 'static> {
+    clap::Arg::with_name("action")
+        .required(true)
+        .possible_values(&["connect", "disconnect"])
+}
+
+fn parse_network_args(matches: &clap::ArgMatches<'_>) -> types::NetworkMatch {
+    let network = matches.value_of("network").unwrap();
+    let network = match matches.value_of("kind").unwrap() {
+        "ssid" => NetworkMatch::Ssid(network.to_owned()),
+        "subnet" => NetworkMatch::Subnet(network.parse().unwrap_or_else(|_| {
+            clap::Error::with_description(
+                &format!("Invalid subnet: {}", network),
+                clap::ErrorKind::ValueValidation,
+            )
+            .exit()
+        })),
+        "gateway" => NetworkMatch::Gateway(network.parse().unwrap_or_else(|error| {
+            clap::Error::with_description(
+                &format!("{}: {}", error, network),
+                clap::ErrorKind::ValueValidation,
+            )
+            .exit()
+        })),
+        _ => unreachable!("invalid network kind"),
+    };
+    types::NetworkMatch::from(&network)
+}
+
+fn or_unknown(value: &str) -> &str {
+    if value.is_empty() {
+        "unknown"
+    } else {
+        value
+    }
+}
+
+fn parse_action(action: &str) -> NetworkAction {
+    action.parse().unwrap()
+}
@@ -0,0 +1,244 @@
+use crate::{new_rpc_client, Command, Error, Result};
+use clap::value_t_or_exit;
+use mullvad_management_interface::types::{
+    obfuscation_settings::Mode, CustomObfuscationSettings, ObfuscationSettings,
+    QuicObfuscationSettings, ShadowsocksObfuscationSettings, Udp2TcpObfuscationSettings,
+};
+use std::net::SocketAddr;
+
+pub struct Obfuscation;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Obfuscation {
+    fn name(&self) -> &'static str {
+        "obfuscation"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Manage use of obfuscation protocols for WireGuard")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(create_obfuscation_set_subcommand())
+            .subcommand(
+                clap::SubCommand::with_name("get").about("Display the obfuscation settings"),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("set", Some(set_matches)) => self.handle_set(set_matches).await,
+            ("get", Some(_)) => self.get().await,
+            _ => unreachable!("No obfuscation command given"),
+        }
+    }
+}
+
+fn create_obfuscation_set_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("set")
+        .about("Set obfuscation settings")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("mode")
+                .about("Specifies which obfuscation protocol to use, if any")
+                .arg(
+                    clap::Arg::with_name("mode")
+                        .required(true)
+                        .possible_values(&[
+                            "auto",
+                            "off",
+                            "udp2tcp",
+                            "shadowsocks",
+                            "custom",
+                            "quic",
+                        ]),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("udp2tcp")
+                .about("Specifies the config for the udp2tcp obfuscator")
+                .arg(
+                    clap::Arg::with_name("port")
+                        .help("TCP port of the relay to connect to")
+                        .long("port")
+                        .takes_value(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("shadowsocks")
+                .about("Specifies the config for the Shadowsocks obfuscator")
+                .arg(
+                    clap::Arg::with_name("port")
+                        .help("Shadowsocks port of the relay to send traffic to")
+                        .long("port")
+                        .takes_value(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("custom")
+                .about("Specifies the endpoint of a custom obfuscator")
+                .arg(
+                    clap::Arg::with_name("endpoint")
+                        .help("Address and port of the obfuscator, or \"none\"")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("quic")
+                .about(
+                    "Specifies the QUIC proxy to send WireGuard traffic through. This is \
+                     experimental, requires a daemon built with QUIC obfuscation and is only \
+                     used while the quic-obfuscation beta feature is on",
+                )
+                .arg(
+                    clap::Arg::with_name("endpoint")
+                        .help("Address and port of the proxy, or \"none\"")
+                        .required(true),
+                )
+                .arg(
+                    clap::Arg::with_name("server-name")
+                        .help("Name used to verify the TLS certificate of the proxy")
+                        .long("server-name")
+                        .takes_value(true),
+                ),
+        )
+}
+
+impl Obfuscation {
+    async fn handle_set(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let mut settings = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .obfuscation_settings
+            .unwrap_or_default();
+
+        match matches.subcommand() {
+            ("mode", Some(mode_matches)) => {
+                let mode = match value_t_or_exit!(mode_matches.value_of("mode"), String).as_str() {
+                    "auto" => Mode::Auto,
+                    "off" => Mode::Off,
+                    "udp2tcp" => Mode::Udp2tcp,
+                    "shadowsocks" => Mode::Shadowsocks,
+                    "custom" => Mode::Custom,
+                    "quic" => Mode::Quic,
+                    _ => unreachable!("Invalid obfuscation mode"),
+                };
+                settings.mode = i32::from(mode);
+            }
+            ("udp2tcp", Some(udp2tcp_matches)) => {
+                let port = match udp2tcp_matches.value_of("port").unwrap() {
+                    "any" => 0,
+                    port => port.parse::<u16>().map_err(|_| {
+                        Error::InvalidCommand("Invalid port. Must be \"any\" or [1-65535].")
+                    })?,
+                };
+                settings.udp2tcp = Some(Udp2TcpObfuscationSettings {
+                    port: u32::from(port),
+                });
+            }
+            ("shadowsocks", Some(shadowsocks_matches)) => {
+                let port = match shadowsocks_matches.value_of("port").unwrap() {
+                    "any" => 0,
+                    port => port.parse::<u16>().map_err(|_| {
+                        Error::InvalidCommand("Invalid port. Must be \"any\" or [1-65535].")
+                    })?,
+                };
+                settings.shadowsocks = Some(ShadowsocksObfuscationSettings {
+                    port: u32::from(port),
+                });
+            }
+            ("custom", Some(custom_matches)) => {
+                settings.custom = match custom_matches.value_of("endpoint").unwrap() {
+                    "none" => None,
+                    endpoint => {
+                        let endpoint: SocketAddr = endpoint.parse().map_err(|_| {
+                            Error::InvalidCommand(
+                                "Invalid endpoint. Must be an IP address and port.",
+                            )
+                        })?;
+                        Some(CustomObfuscationSettings {
+                            endpoint: endpoint.to_string(),
+                        })
+                    }
+                };
+            }
+            ("quic", Some(quic_matches)) => {
+                settings.quic =
+                    match quic_matches.value_of("endpoint").unwrap() {
+                        "none" => None,
+                        endpoint => {
+                            let endpoint: SocketAddr = endpoint.parse().map_err(|_| {
+                                Error::InvalidCommand(
+                                    "Invalid endpoint. Must be an IP address and port.",
+                                )
+                            })?;
+                            let server_name = quic_matches.value_of("server-name").ok_or(
+                                Error::InvalidCommand("A server name must be given for the proxy."),
+                            )?;
+                            Some(QuicObfuscationSettings {
+                                endpoint: endpoint.to_string(),
+                                server_name: server_name.to_owned(),
+                            })
+                        }
+                    };
+            }
+            _ => unreachable!("No obfuscation set command given"),
+        }
+
+        rpc.set_obfuscation_settings(settings).await?;
+        println!("Updated obfuscation settings");
+        Ok(())
+    }
+
+    async fn get(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .obfuscation_settings
+            .unwrap_or_default();
+        print_obfuscation_settings(&settings);
+        Ok(())
+    }
+}
+
+fn print_obfuscation_settings(settings: &ObfuscationSettings) {
+    let mode = match Mode::from_i32(settings.mode) {
+        Some(Mode::Auto) => "auto",
+        Some(Mode::Off) => "off",
+        Some(Mode::Udp2tcp) => "udp2tcp",
+        Some(Mode::Shadowsocks) => "shadowsocks",
+        Some(Mode::Custom) => "custom",
+        Some(Mode::Quic) => "quic",
+        None => "unknown",
+    };
+    println!("Obfuscation mode: {}", mode);
+
+    match settings.udp2tcp.as_ref().map(|udp2tcp| udp2tcp.port) {
+        Some(0) | None => println!("udp2tcp port: any"),
+        Some(port) => println!("udp2tcp port: {}", port),
+    }
+
+    match settings
+        .shadowsocks
+        .as_ref()
+        .map(|shadowsocks| shadowsocks.port)
+    {
+        Some(0) | None => println!("Shadowsocks port: any"),
+        Some(port) => println!("Shadowsocks port: {}", port),
+    }
+
+    match &settings.custom {
+        Some(custom) => println!("Custom obfuscator: {}", custom.endpoint),
+        None => println!("Custom obfuscator: none"),
+    }
+
+    match &settings.quic {
+        Some(quic) => println!("QUIC proxy: {} ({})", quic.endpoint, quic.server_name),
+        None => println!("QUIC proxy: none"),
+    }
+}
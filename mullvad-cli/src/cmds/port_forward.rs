@@ -0,0 +1,144 @@
+use crate::{new_rpc_client, Command, Error, Result};
+use clap::value_t_or_exit;
+use mullvad_management_interface::types::PortForwardLocation;
+
+pub struct PortForward;
+
+#[mullvad_management_interface::async_trait]
+impl Command for PortForward {
+    fn name(&self) -> &'static str {
+        "port-forward"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Manage ports forwarded to this device")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("list")
+                    .about("List the ports that are forwarded to this device"),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("add")
+                    .about("Forward a new port to this device from relays in the given city")
+                    .arg(
+                        clap::Arg::with_name("country")
+                            .help("The two letter country code")
+                            .required(true),
+                    )
+                    .arg(
+                        clap::Arg::with_name("city")
+                            .help("The three letter city code")
+                            .required(true),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("remove")
+                    .about("Stop forwarding a port")
+                    .arg(
+                        clap::Arg::with_name("id")
+                            .help("The ID of the port forward, as shown by 'list'")
+                            .required(true),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("set")
+                    .about(
+                        "Only connect to relays where a port is forwarded to this device, when \
+                         turned on",
+                    )
+                    .arg(
+                        clap::Arg::with_name("policy")
+                            .required(true)
+                            .possible_values(&["on", "off"]),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("get")
+                    .about("Display the current port forwarding setting"),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("list", Some(_)) => self.list().await,
+            ("add", Some(add_matches)) => {
+                let country = value_t_or_exit!(add_matches.value_of("country"), String);
+                let city = value_t_or_exit!(add_matches.value_of("city"), String);
+                self.add(country, city).await
+            }
+            ("remove", Some(remove_matches)) => {
+                let id = value_t_or_exit!(remove_matches.value_of("id"), String);
+                self.remove(id).await
+            }
+            ("set", Some(set_matches)) => {
+                let enabled = value_t_or_exit!(set_matches.value_of("policy"), String);
+                self.set(enabled == "on").await
+            }
+            ("get", Some(_)) => self.get().await,
+            _ => unreachable!("No port-forward command given"),
+        }
+    }
+}
+
+impl PortForward {
+    async fn list(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let list = rpc
+            .list_port_forwards(())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to list port forwards", error))?
+            .into_inner();
+        if list.port_forwards.is_empty() {
+            println!("No ports are forwarded to this device");
+        }
+        for port_forward in list.port_forwards {
+            println!(
+                "Port {} in {}, {}",
+                port_forward.port, port_forward.city_code, port_forward.country_code
+            );
+            println!("  ID: {}", port_forward.id);
+        }
+        Ok(())
+    }
+
+    async fn add(&self, country: String, city: String) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let port_forward = rpc
+            .add_port_forward(PortForwardLocation {
+                country_code: country,
+                city_code: city,
+            })
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to add port forward", error))?
+            .into_inner();
+        println!(
+            "Forwarded port {} in {}, {}",
+            port_forward.port, port_forward.city_code, port_forward.country_code
+        );
+        Ok(())
+    }
+
+    async fn remove(&self, id: String) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.remove_port_forward(id)
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to remove port forward", error))?;
+        println!("Removed port forward");
+        Ok(())
+    }
+
+    async fn set(&self, enabled: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_port_forwarding(enabled).await?;
+        println!("Changed port forwarding setting");
+        Ok(())
+    }
+
+    async fn get(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let enabled = rpc.get_settings(()).await?.into_inner().port_forwarding;
+        println!("Port forwarding: {}", if enabled { "on" } else { "off" });
+        Ok(())
+    }
+}
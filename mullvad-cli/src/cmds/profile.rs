@@ -0,0 +1,116 @@
+use crate::{input, new_rpc_client, Command, Error, Result};
+use mullvad_management_interface::types::{
+    settings_profile_import::ConflictResolution, SettingsProfileImport,
+};
+
+pub struct Profile;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Profile {
+    fn name(&self) -> &'static str {
+        "profile"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about(
+                "Copy the relay, bridge, obfuscation, DNS and firewall settings between devices. \
+                 The account, the WireGuard key and custom tunnel endpoints are not included",
+            )
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("export")
+                    .about("Print the settings of this device as a profile"),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("import")
+                    .about("Apply a profile that was exported on another device")
+                    .arg(
+                        clap::Arg::with_name("file")
+                            .help("File to read the profile from. Use - to read from stdin")
+                            .required(true),
+                    )
+                    .arg(
+                        clap::Arg::with_name("on-conflict")
+                            .long("on-conflict")
+                            .help(
+                                "What to do with settings that have been changed on this device \
+                                 to something other than what the profile has",
+                            )
+                            .takes_value(true)
+                            .possible_values(&["abort", "overwrite", "keep-local"])
+                            .default_value("abort"),
+                    ),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("export", Some(_)) => self.export().await,
+            ("import", Some(import_matches)) => self.import(import_matches).await,
+            _ => unreachable!("No profile command given"),
+        }
+    }
+}
+
+impl Profile {
+    async fn export(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let profile = rpc
+            .export_settings_profile(())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to export the settings profile", error))?
+            .into_inner();
+        println!("{}", profile);
+        Ok(())
+    }
+
+    async fn import(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let profile = input::read_to_string(matches.value_of("file").unwrap())?;
+        let conflict_resolution = match matches.value_of("on-conflict").unwrap() {
+            "overwrite" => ConflictResolution::Overwrite,
+            "keep-local" => ConflictResolution::KeepLocal,
+            _ => ConflictResolution::Abort,
+        };
+
+        let mut rpc = new_rpc_client().await?;
+        let result = rpc
+            .import_settings_profile(SettingsProfileImport {
+                profile,
+                conflict_resolution: conflict_resolution as i32,
+            })
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to import the settings profile", error))?
+            .into_inner();
+
+        if !result.applied {
+            println!("These settings have been changed on this device:");
+            for conflict in &result.conflicts {
+                println!("    {}", conflict);
+            }
+            return Err(Error::CommandFailed(
+                "The profile was not applied. Use --on-conflict to overwrite or keep them",
+            ));
+        }
+
+        if !result.conflicts.is_empty() {
+            let action = match conflict_resolution {
+                ConflictResolution::KeepLocal => "Kept",
+                _ => "Overwrote",
+            };
+            println!("{} the settings of this device for:", action);
+            for conflict in &result.conflicts {
+                println!("    {}", conflict);
+            }
+        }
+        if result.changed_settings.is_empty() {
+            println!("The settings already matched the profile");
+        } else {
+            println!("Changed settings:");
+            for setting in &result.changed_settings {
+                println!("    {}", setting);
+            }
+        }
+        Ok(())
+    }
+}
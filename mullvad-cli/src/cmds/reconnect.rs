@@ -35,9 +35,9 @@ impl Command for Reconnect {
                 while let Some(state) = receiver.next().await {
                     let state = state?;
                     format::print_state(&state);
-                    match state.state.unwrap() {
-                        State::Connected(_) => return Ok(()),
-                        State::Error(_) => return Err(Error::CommandFailed("reconnect")),
+                    match state.state {
+                        Some(State::Connected(_)) => return Ok(()),
+                        Some(State::Error(_)) => return Err(Error::CommandFailed("reconnect")),
                         _ => {}
                     }
                 }
@@ -1,17 +1,26 @@
-use crate::{location, new_rpc_client, Command, Error, Result};
+use crate::{format, input, location, new_rpc_client, Command, Error, Result};
 use clap::{value_t, values_t};
 use itertools::Itertools;
 use std::{
+    collections::HashMap,
     convert::TryFrom,
     io::{self, BufRead},
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     str::FromStr,
+    time::Duration,
 };
 
 use mullvad_management_interface::types;
-use mullvad_types::relay_constraints::{Constraint, RelaySettings};
+use mullvad_types::{
+    openvpn_profile,
+    relay_constraints::{Constraint, RelaySettings},
+    relay_filter::RelayFilter,
+};
 use talpid_types::net::all_of_the_internet;
 
+/// The number of relays that are listed as the fastest after the latency has been measured.
+const NUM_FASTEST_RELAYS: usize = 5;
+
 pub struct Relay;
 
 #[mullvad_management_interface::async_trait]
@@ -75,6 +84,15 @@ impl Command for Relay {
                                         .long("v6-gateway")
                                         .takes_value(true),
                                 )
+                                .arg(
+                                    clap::Arg::with_name("allowed-ip")
+                                        .help("Network to route through the peer. Can be given \
+                                               multiple times. Defaults to all of the internet")
+                                        .long("allowed-ip")
+                                        .takes_value(true)
+                                        .multiple(true)
+                                        .number_of_values(1),
+                                )
                             )
                             .subcommand(clap::SubCommand::with_name("openvpn")
                                 .arg(
@@ -105,6 +123,29 @@ impl Command for Relay {
                                         .possible_values(&["udp", "tcp"]),
                                 )
                             )
+                            .subcommand(clap::SubCommand::with_name("openvpn-profile")
+                                .about("Use a server that is not run by Mullvad, as described by \
+                                       an OpenVPN client profile (.ovpn). Certificates and keys \
+                                       must be included inline in the profile")
+                                .arg(
+                                    clap::Arg::with_name("file")
+                                        .help("Path to the profile, or '-' to read it from \
+                                               standard input")
+                                        .required(true),
+                                )
+                                .arg(
+                                    clap::Arg::with_name("username")
+                                        .help("Username, if the profile uses auth-user-pass")
+                                        .long("username")
+                                        .takes_value(true),
+                                )
+                                .arg(
+                                    clap::Arg::with_name("password")
+                                        .help("Password, if the profile uses auth-user-pass")
+                                        .long("password")
+                                        .takes_value(true),
+                                )
+                            )
                     )
                     .subcommand(
                         location::get_subcommand()
@@ -132,6 +173,22 @@ impl Command for Relay {
                                 .required(true)
                             )
                     )
+                    .subcommand(
+                        clap::SubCommand::with_name("filter")
+                            .about("Advanced: Only select relays that match an expression, in \
+                                   addition to the other constraints. For example: \
+                                   '(country == de || country == nl) && owned && \
+                                   protocol == wireguard'. The fields country, city, hostname \
+                                   and provider can be compared with == and !=, as can protocol \
+                                   with wireguard or openvpn. 'owned' matches relays owned by \
+                                   Mullvad.")
+                            .arg(
+                                clap::Arg::with_name("expression")
+                                    .help("The filter expression, or 'any' to remove the filter.")
+                                    .required(true)
+                                    .validator(relay_filter_validator),
+                            )
+                    )
                     .subcommand(
                         clap::SubCommand::with_name("tunnel")
                             .about("Set tunnel protocol-specific constraints.")
@@ -201,7 +258,24 @@ impl Command for Relay {
             )
             .subcommand(clap::SubCommand::with_name("get"))
             .subcommand(
-                clap::SubCommand::with_name("list").about("List available countries and cities"),
+                clap::SubCommand::with_name("export-profile")
+                    .about("Print the OpenVPN client profile of the current custom relay, if it \
+                           was imported from a profile. Credentials are not included"),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("list")
+                    .about("List available countries and cities")
+                    .arg(
+                        clap::Arg::with_name("latency")
+                            .long("latency")
+                            .help(
+                                "Measure the latency to each relay with an ICMP echo request \
+                                 sent outside the tunnel. Relays that are measured to be close \
+                                 by are preferred when connecting if the \
+                                 latency-based-selection beta feature is on. On platforms \
+                                 other than Linux, this requires the tunnel to be disconnected",
+                            ),
+                    ),
             )
             .subcommand(
                 clap::SubCommand::with_name("update")
@@ -214,8 +288,14 @@ impl Command for Relay {
             self.set(set_matches).await
         } else if matches.subcommand_matches("get").is_some() {
             self.get().await
-        } else if matches.subcommand_matches("list").is_some() {
-            self.list().await
+        } else if matches.subcommand_matches("export-profile").is_some() {
+            self.export_profile().await
+        } else if let Some(list_matches) = matches.subcommand_matches("list") {
+            self.list(
+                matches.is_present("json"),
+                list_matches.is_present("latency"),
+            )
+            .await
         } else if matches.subcommand_matches("update").is_some() {
             self.update().await
         } else {
@@ -243,6 +323,8 @@ impl Relay {
             self.set_hostname(relay_matches).await
         } else if let Some(providers_matches) = matches.subcommand_matches("provider") {
             self.set_providers(providers_matches).await
+        } else if let Some(filter_matches) = matches.subcommand_matches("filter") {
+            self.set_filter(filter_matches).await
         } else if let Some(matches) = matches.subcommand_matches("tunnel") {
             if let Some(tunnel_matches) = matches.subcommand_matches("openvpn") {
                 self.set_openvpn_constraints(tunnel_matches).await
@@ -261,6 +343,9 @@ impl Relay {
     async fn set_custom(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
         let custom_endpoint = match matches.subcommand() {
             ("openvpn", Some(openvpn_matches)) => Self::read_custom_openvpn_relay(openvpn_matches),
+            ("openvpn-profile", Some(profile_matches)) => {
+                Self::read_openvpn_profile_relay(profile_matches)?
+            }
             ("wireguard", Some(wg_matches)) => Self::read_custom_wireguard_relay(wg_matches),
             (_unknown_tunnel, _) => unreachable!("No set relay command given"),
         };
@@ -296,6 +381,23 @@ impl Relay {
         }
     }
 
+    fn read_openvpn_profile_relay(
+        matches: &clap::ArgMatches<'_>,
+    ) -> Result<types::CustomRelaySettings> {
+        let path = matches.value_of("file").unwrap();
+        let username = matches.value_of("username").map(str::to_owned);
+        let password = matches.value_of("password").map(str::to_owned);
+
+        let profile = input::read_to_string(path)?;
+        let relay = openvpn_profile::parse(&profile, username, password)
+            .map_err(Error::InvalidOpenVpnProfile)?;
+
+        Ok(types::CustomRelaySettings {
+            host: relay.host,
+            config: Some(types::ConnectionConfig::from(relay.config)),
+        })
+    }
+
     fn read_custom_wireguard_relay(matches: &clap::ArgMatches<'_>) -> types::CustomRelaySettings {
         use types::connection_config::wireguard_config;
 
@@ -313,6 +415,13 @@ impl Relay {
                 _ => e.exit(),
             },
         };
+        let allowed_ips: Vec<String> = match matches.values_of("allowed-ip") {
+            Some(allowed_ips) => allowed_ips.map(str::to_owned).collect(),
+            None => all_of_the_internet()
+                .iter()
+                .map(|network| network.to_string())
+                .collect(),
+        };
         let protocol = value_t!(matches.value_of("protocol"), String).unwrap_or_else(|e| e.exit());
         let protocol = Self::validate_transport_protocol(&protocol);
         let mut private_key_str = String::new();
@@ -338,10 +447,7 @@ impl Relay {
                         }),
                         peer: Some(wireguard_config::PeerConfig {
                             public_key: peer_public_key.to_vec(),
-                            allowed_ips: all_of_the_internet()
-                                .iter()
-                                .map(|address| address.to_string())
-                                .collect(),
+                            allowed_ips,
                             endpoint: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port)
                                 .to_string(),
                             protocol: protocol as i32,
@@ -513,6 +619,23 @@ impl Relay {
         .await
     }
 
+    async fn set_filter(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let filter = match matches.value_of("expression").unwrap() {
+            "any" => String::new(),
+            expression => expression.to_owned(),
+        };
+
+        self.update_constraints(types::RelaySettingsUpdate {
+            r#type: Some(types::relay_settings_update::Type::Normal(
+                types::NormalRelaySettingsUpdate {
+                    filter: Some(types::RelayFilterUpdate { filter }),
+                    ..Default::default()
+                },
+            )),
+        })
+        .await
+    }
+
     async fn set_openvpn_constraints(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
         let port = parse_transport_port(matches)?;
         self.update_constraints(types::RelaySettingsUpdate {
@@ -590,17 +713,60 @@ impl Relay {
         Ok(())
     }
 
-    async fn list(&self) -> Result<()> {
+    async fn export_profile(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let relay_settings = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .relay_settings
+            .unwrap();
+
+        let profile = match RelaySettings::try_from(relay_settings).unwrap() {
+            RelaySettings::CustomTunnelEndpoint(relay) => openvpn_profile::export(&relay),
+            RelaySettings::Normal(_) => None,
+        };
+        match profile {
+            Some(profile) => {
+                print!("{}", profile);
+                Ok(())
+            }
+            None => Err(Error::CommandFailed(
+                "The current relay was not imported from an OpenVPN profile",
+            )),
+        }
+    }
+
+    async fn list(&self, json: bool, latency: bool) -> Result<()> {
+        if json && latency {
+            return Err(Error::InvalidCommand(
+                "The latency cannot be included in the JSON output",
+            ));
+        }
         let mut countries = Self::get_filtered_relays().await?;
         countries.sort_by(|c1, c2| natord::compare_ignore_case(&c1.name, &c2.name));
-        for mut country in countries {
+        for country in &mut countries {
             country
                 .cities
                 .sort_by(|c1, c2| natord::compare_ignore_case(&c1.name, &c2.name));
-            println!("{} ({})", country.name, country.code);
-            for mut city in country.cities {
+            for city in &mut country.cities {
                 city.relays
                     .sort_by(|r1, r2| natord::compare_ignore_case(&r1.hostname, &r2.hostname));
+            }
+        }
+        if json {
+            format::print_json(&countries);
+            return Ok(());
+        }
+        let latencies = if latency {
+            Self::measure_latencies(&countries).await?
+        } else {
+            HashMap::new()
+        };
+
+        for country in countries {
+            println!("{} ({})", country.name, country.code);
+            for city in country.cities {
                 println!(
                     "\t{} ({}) @ {:.5}°N, {:.5}°W",
                     city.name, city.code, city.latitude, city.longitude
@@ -619,20 +785,72 @@ impl Relay {
                     if !relay.ipv6_addr_in.is_empty() {
                         addresses.push(&relay.ipv6_addr_in);
                     }
+                    let latency = match latencies.get(&relay.hostname) {
+                        Some(Some(latency)) => format!(" - {} ms", latency.as_millis()),
+                        Some(None) => " - no reply".to_owned(),
+                        None => String::new(),
+                    };
                     println!(
-                        "\t\t{} ({}) - {}, hosted by {}",
+                        "\t\t{} ({}) - {}, hosted by {}{}",
                         relay.hostname,
                         addresses.iter().join(", "),
                         support_msg,
-                        relay.provider
+                        relay.provider,
+                        latency
                     );
                 }
             }
             println!();
         }
+
+        let fastest: Vec<(&String, Duration)> = latencies
+            .iter()
+            .filter_map(|(hostname, latency)| latency.map(|latency| (hostname, latency)))
+            .sorted_by_key(|(_, latency)| *latency)
+            .take(NUM_FASTEST_RELAYS)
+            .collect();
+        if !fastest.is_empty() {
+            println!("Fastest relays:");
+            for (hostname, latency) in fastest {
+                println!("\t{} - {} ms", hostname, latency.as_millis());
+            }
+        }
         Ok(())
     }
 
+    /// Measures the latency to the given relays, and returns it by hostname. The latency is `None`
+    /// for relays that did not reply.
+    async fn measure_latencies(
+        countries: &[types::RelayListCountry],
+    ) -> Result<HashMap<String, Option<Duration>>> {
+        let hostnames = countries
+            .iter()
+            .flat_map(|country| &country.cities)
+            .flat_map(|city| &city.relays)
+            .map(|relay| relay.hostname.clone())
+            .collect();
+        eprintln!("Measuring the latency to the relays...");
+        let mut rpc = new_rpc_client().await?;
+        let latencies = rpc
+            .get_relay_latencies(types::RelayLatencyRequest {
+                hostnames,
+                measure: true,
+            })
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to measure relay latencies", error))?
+            .into_inner();
+        Ok(latencies
+            .relays
+            .into_iter()
+            .map(|relay| {
+                let latency = relay
+                    .latency
+                    .and_then(|latency| Duration::try_from(latency).ok());
+                (relay.hostname, latency)
+            })
+            .collect())
+    }
+
     async fn update(&self) -> Result<()> {
         new_rpc_client().await?.update_relay_locations(()).await?;
         println!("Updating relay list in the background...");
@@ -738,3 +956,12 @@ fn parse_transport_port(matches: &clap::ArgMatches<'_>) -> Result<Option<types::
         )),
     }
 }
+
+fn relay_filter_validator(expression: String) -> std::result::Result<(), String> {
+    if expression == "any" {
+        return Ok(());
+    }
+    RelayFilter::from_str(&expression)
+        .map(|_| ())
+        .map_err(|error| error.to_string())
+}
@@ -0,0 +1,50 @@
+use crate::{new_rpc_client, state, Command, Error, Result};
+use std::{ffi::OsString, process};
+
+pub struct Run;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Run {
+    fn name(&self) -> &'static str {
+        "run"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about(
+                "Connect, run a command and restore the previous tunnel state once it exits. The \
+                 exit code of the command is returned",
+            )
+            .setting(clap::AppSettings::TrailingVarArg)
+            .arg(
+                clap::Arg::with_name("command")
+                    .help("The command to run, followed by its arguments, such as: -- curl ...")
+                    .required(true)
+                    .multiple(true),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let mut command: Vec<OsString> = matches
+            .values_of_os("command")
+            .unwrap()
+            .map(ToOwned::to_owned)
+            .collect();
+        let program = command.remove(0);
+
+        let rpc = new_rpc_client().await?;
+        let lease = state::hold_connection(rpc).await?;
+
+        let name = program.to_string_lossy().into_owned();
+        let status = tokio::task::spawn_blocking(move || {
+            process::Command::new(program).args(command).status()
+        })
+        .await
+        .expect("Command runner panicked")
+        .map_err(|error| Error::RunCommandError(name, error))?;
+
+        // Release the lease before exiting, so that the previous state is restored right away
+        drop(lease);
+        process::exit(status.code().unwrap_or(1));
+    }
+}
@@ -0,0 +1,574 @@
+use crate::{format, new_rpc_client, Command, Result};
+use clap::value_t_or_exit;
+use mullvad_management_interface::types::{
+    credential_storage, log_privacy, setting_constraint::Constraint, CredentialStorage, LogPrivacy,
+    LogRotationSettings, SettingConstraint, SettingDescription,
+};
+use mullvad_types::units::{ByteSize, HumanDuration};
+use std::time::Duration;
+
+pub struct Settings;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Settings {
+    fn name(&self) -> &'static str {
+        "settings"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        let subcommand = clap::SubCommand::with_name(self.name())
+            .about("Inspect and manage the daemon settings")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(clap::SubCommand::with_name("get").about("Display all settings"))
+            .subcommand(
+                clap::SubCommand::with_name("schema")
+                    .about("Describe all settings along with their default values"),
+            )
+            .subcommand(create_encryption_subcommand())
+            .subcommand(create_credential_storage_subcommand())
+            .subcommand(create_log_rotation_subcommand())
+            .subcommand(create_log_privacy_subcommand())
+            .subcommand(create_system_log_subcommand())
+            .subcommand(create_json_logs_subcommand())
+            .subcommand(create_connection_profiles_subcommand());
+        #[cfg(target_os = "linux")]
+        let subcommand = subcommand.subcommand(create_dbus_subcommand());
+        subcommand
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("get", Some(_)) => self.get(matches.is_present("json")).await,
+            ("schema", Some(_)) => self.schema().await,
+            ("encryption", Some(encryption_matches)) => match encryption_matches.subcommand() {
+                ("set", Some(set_matches)) => {
+                    let enabled = value_t_or_exit!(set_matches.value_of("policy"), String);
+                    self.set_encryption(enabled == "on").await
+                }
+                ("get", Some(_)) => self.get_encryption().await,
+                _ => unreachable!("No settings encryption command given"),
+            },
+            ("credential-storage", Some(storage_matches)) => match storage_matches.subcommand() {
+                ("set", Some(set_matches)) => {
+                    let storage = value_t_or_exit!(set_matches.value_of("storage"), String);
+                    self.set_credential_storage(&storage).await
+                }
+                ("get", Some(_)) => self.get_credential_storage().await,
+                _ => unreachable!("No credential storage command given"),
+            },
+            ("log-rotation", Some(log_rotation_matches)) => {
+                match log_rotation_matches.subcommand() {
+                    ("set", Some(set_matches)) => self.set_log_rotation(set_matches).await,
+                    ("get", Some(_)) => self.get_log_rotation().await,
+                    _ => unreachable!("No log rotation command given"),
+                }
+            }
+            ("log-privacy", Some(privacy_matches)) => match privacy_matches.subcommand() {
+                ("set", Some(set_matches)) => {
+                    let level = value_t_or_exit!(set_matches.value_of("level"), String);
+                    self.set_log_privacy(&level).await
+                }
+                ("get", Some(_)) => self.get_log_privacy().await,
+                _ => unreachable!("No log privacy command given"),
+            },
+            ("system-log", Some(system_log_matches)) => match system_log_matches.subcommand() {
+                ("set", Some(set_matches)) => {
+                    let enabled = value_t_or_exit!(set_matches.value_of("policy"), String);
+                    self.set_system_log(enabled == "on").await
+                }
+                ("get", Some(_)) => self.get_system_log().await,
+                _ => unreachable!("No system log command given"),
+            },
+            ("json-logs", Some(json_logs_matches)) => match json_logs_matches.subcommand() {
+                ("set", Some(set_matches)) => {
+                    let enabled = value_t_or_exit!(set_matches.value_of("policy"), String);
+                    self.set_json_logs(enabled == "on").await
+                }
+                ("get", Some(_)) => self.get_json_logs().await,
+                _ => unreachable!("No JSON logs command given"),
+            },
+            ("connection-profiles", Some(profiles_matches)) => {
+                match profiles_matches.subcommand() {
+                    ("set", Some(set_matches)) => {
+                        let enabled = value_t_or_exit!(set_matches.value_of("policy"), String);
+                        self.set_connection_profiles(enabled == "on").await
+                    }
+                    ("get", Some(_)) => self.get_connection_profiles().await,
+                    _ => unreachable!("No connection profiles command given"),
+                }
+            }
+            #[cfg(target_os = "linux")]
+            ("dbus", Some(dbus_matches)) => match dbus_matches.subcommand() {
+                ("set", Some(set_matches)) => {
+                    let enabled = value_t_or_exit!(set_matches.value_of("policy"), String);
+                    self.set_dbus_service(enabled == "on").await
+                }
+                ("get", Some(_)) => self.get_dbus_service().await,
+                _ => unreachable!("No D-Bus command given"),
+            },
+            _ => unreachable!("No settings command given"),
+        }
+    }
+}
+
+fn create_encryption_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("encryption")
+        .about(
+            "Control whether the account number, WireGuard key and API proxy credentials are \
+             encrypted in the settings file using the keystore of the operating system",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("set")
+                .about("Enable or disable encryption of sensitive settings")
+                .arg(
+                    clap::Arg::with_name("policy")
+                        .required(true)
+                        .possible_values(&["on", "off"]),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("get")
+                .about("Display whether sensitive settings are encrypted"),
+        )
+}
+
+fn create_credential_storage_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("credential-storage")
+        .about("Control where the account number and the WireGuard key are stored")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("set")
+                .about(
+                    "Store the account number and the WireGuard key in the settings file, in the \
+                     keystore of the operating system, or in a separate file that only the \
+                     daemon can read",
+                )
+                .arg(
+                    clap::Arg::with_name("storage")
+                        .required(true)
+                        .possible_values(&["settings", "keystore", "file"]),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("get")
+                .about("Display where the account number and the WireGuard key are stored"),
+        )
+}
+
+fn create_log_rotation_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("log-rotation")
+        .about("Control how large the daemon and tunnel logs grow and how many old logs are kept")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("set")
+                .about("Change the log rotation limits. Limits that are not given are kept")
+                .arg(
+                    clap::Arg::with_name("max size")
+                        .help("The size at which a log is rotated, such as 10MiB")
+                        .long("max-size")
+                        .takes_value(true)
+                        .validator(|value| parse_value::<ByteSize>(&value).map(|_| ())),
+                )
+                .arg(
+                    clap::Arg::with_name("generations")
+                        .help("The number of old logs to keep for each log")
+                        .long("generations")
+                        .takes_value(true)
+                        .validator(|value| parse_value::<u32>(&value).map(|_| ())),
+                )
+                .arg(
+                    clap::Arg::with_name("max age")
+                        .help(
+                            "Remove old logs that have not been written to for this long, such \
+                             as 14d. 0 keeps old logs regardless of their age",
+                        )
+                        .long("max-age")
+                        .takes_value(true)
+                        .validator(|value| parse_max_age(&value).map(|_| ())),
+                )
+                .arg(
+                    clap::Arg::with_name("compress")
+                        .help("Whether to compress old logs, except for the most recent one")
+                        .long("compress")
+                        .takes_value(true)
+                        .possible_values(&["on", "off"]),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("get").about("Display the current log rotation limits"),
+        )
+}
+
+fn create_log_privacy_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("log-privacy")
+        .about("Control how much network detail, such as IP addresses, is written to the log")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("set")
+                .about(
+                    "Log everything, replace IP addresses and host names with [REDACTED], or \
+                     also silence the modules that mostly log network details",
+                )
+                .arg(
+                    clap::Arg::with_name("level")
+                        .required(true)
+                        .possible_values(&["full", "redacted", "minimal"]),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("get")
+                .about("Display how much network detail is written to the log"),
+        )
+}
+
+fn create_system_log_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("system-log")
+        .about(
+            "Control whether important events, such as when all traffic is blocked, are also \
+             sent to the log of the operating system",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("set")
+                .about("Enable or disable sending events to the system log")
+                .arg(
+                    clap::Arg::with_name("policy")
+                        .required(true)
+                        .possible_values(&["on", "off"]),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("get")
+                .about("Display whether events are sent to the system log"),
+        )
+}
+
+fn create_json_logs_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("json-logs")
+        .about("Control whether the daemon log is written as JSON objects, one per line")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("set")
+                .about("Enable or disable JSON logs")
+                .arg(
+                    clap::Arg::with_name("policy")
+                        .required(true)
+                        .possible_values(&["on", "off"]),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("get")
+                .about("Display whether the daemon log is written as JSON"),
+        )
+}
+
+fn create_connection_profiles_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("connection-profiles")
+        .about(
+            "Control whether the first connection attempts use the protocol and port that are \
+             known to work best in the country that the device is in, such as TCP on port 443 \
+             where UDP is blocked",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("set")
+                .about("Enable or disable connection profiles")
+                .arg(
+                    clap::Arg::with_name("policy")
+                        .required(true)
+                        .possible_values(&["on", "off"]),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("get")
+                .about("Display whether connection profiles are used"),
+        )
+}
+
+#[cfg(target_os = "linux")]
+fn create_dbus_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("dbus")
+        .about(
+            "Control whether the daemon provides the org.mullvad.vpn service on the system bus, \
+             which desktop applets can use to show and change the tunnel state",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("set")
+                .about("Enable or disable the D-Bus service")
+                .arg(
+                    clap::Arg::with_name("policy")
+                        .required(true)
+                        .possible_values(&["on", "off"]),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("get")
+                .about("Display whether the D-Bus service is enabled"),
+        )
+}
+
+fn parse_value<T: std::str::FromStr>(value: &str) -> std::result::Result<T, String>
+where
+    T::Err: std::fmt::Display,
+{
+    value.trim().parse::<T>().map_err(|error| error.to_string())
+}
+
+/// Parses a maximum age, where 0 means that there is no limit.
+fn parse_max_age(value: &str) -> std::result::Result<Duration, String> {
+    if value.trim() == "0" {
+        return Ok(Duration::from_secs(0));
+    }
+    parse_value::<HumanDuration>(value).map(Duration::from)
+}
+
+impl Settings {
+    async fn get(&self, json: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc.get_settings(()).await?.into_inner();
+        if json {
+            format::print_json(&settings);
+        } else {
+            println!("{:#?}", settings);
+        }
+        Ok(())
+    }
+
+    async fn set_log_rotation(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let mut log_rotation = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .log_rotation
+            .unwrap_or_default();
+        if let Some(max_size) = matches.value_of("max size") {
+            log_rotation.max_size = parse_value::<ByteSize>(max_size).unwrap().as_bytes();
+        }
+        if let Some(generations) = matches.value_of("generations") {
+            log_rotation.generations = parse_value(generations).unwrap();
+        }
+        if let Some(max_age) = matches.value_of("max age") {
+            log_rotation.max_age = parse_max_age(max_age).unwrap().as_secs();
+        }
+        if let Some(compress) = matches.value_of("compress") {
+            log_rotation.compress = compress == "on";
+        }
+        rpc.set_log_rotation_settings(log_rotation).await?;
+        println!("Changed log rotation settings");
+        Ok(())
+    }
+
+    async fn get_log_rotation(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let log_rotation: LogRotationSettings = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .log_rotation
+            .unwrap_or_default();
+        println!("Maximum size : {}", ByteSize(log_rotation.max_size));
+        println!("Old logs kept: {}", log_rotation.generations);
+        if log_rotation.max_age == 0 {
+            println!("Maximum age  : none");
+        } else {
+            println!(
+                "Maximum age  : {}",
+                HumanDuration(Duration::from_secs(log_rotation.max_age))
+            );
+        }
+        println!(
+            "Compression  : {}",
+            if log_rotation.compress { "on" } else { "off" }
+        );
+        Ok(())
+    }
+
+    async fn set_encryption(&self, enabled: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_encrypt_sensitive_settings(enabled).await?;
+        println!("Changed settings encryption");
+        Ok(())
+    }
+
+    async fn get_encryption(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let enabled = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .encrypt_sensitive_settings;
+        println!(
+            "Settings encryption: {}",
+            if enabled { "on" } else { "off" }
+        );
+        Ok(())
+    }
+
+    async fn set_credential_storage(&self, storage: &str) -> Result<()> {
+        let storage = match storage {
+            "settings" => credential_storage::Storage::Settings,
+            "keystore" => credential_storage::Storage::Keystore,
+            "file" => credential_storage::Storage::File,
+            _ => unreachable!("Invalid credential storage"),
+        };
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_credential_storage(CredentialStorage {
+            storage: i32::from(storage),
+        })
+        .await?;
+        println!("Changed credential storage");
+        Ok(())
+    }
+
+    async fn get_credential_storage(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let storage = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .credential_storage
+            .unwrap();
+        let storage = match credential_storage::Storage::from_i32(storage.storage) {
+            Some(credential_storage::Storage::Settings) => "settings file",
+            Some(credential_storage::Storage::Keystore) => "keystore",
+            Some(credential_storage::Storage::File) => "separate file",
+            None => "unknown",
+        };
+        println!("Credential storage: {}", storage);
+        Ok(())
+    }
+
+    async fn set_log_privacy(&self, level: &str) -> Result<()> {
+        let level = match level {
+            "full" => log_privacy::Level::Full,
+            "redacted" => log_privacy::Level::Redacted,
+            "minimal" => log_privacy::Level::Minimal,
+            _ => unreachable!("Invalid log privacy level"),
+        };
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_log_privacy(LogPrivacy {
+            level: i32::from(level),
+        })
+        .await?;
+        println!("Changed log privacy level");
+        Ok(())
+    }
+
+    async fn get_log_privacy(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let privacy = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .log_privacy
+            .unwrap();
+        let level = match log_privacy::Level::from_i32(privacy.level) {
+            Some(log_privacy::Level::Full) => "full",
+            Some(log_privacy::Level::Redacted) => "redacted",
+            Some(log_privacy::Level::Minimal) => "minimal",
+            None => "unknown",
+        };
+        println!("Log privacy: {}", level);
+        Ok(())
+    }
+
+    async fn set_system_log(&self, enabled: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_system_log(enabled).await?;
+        println!("Changed system log setting");
+        Ok(())
+    }
+
+    async fn get_system_log(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let enabled = rpc.get_settings(()).await?.into_inner().system_log;
+        println!("System log: {}", if enabled { "on" } else { "off" });
+        Ok(())
+    }
+
+    async fn set_json_logs(&self, enabled: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_json_logs(enabled).await?;
+        println!("Changed JSON logs setting");
+        Ok(())
+    }
+
+    async fn get_json_logs(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let enabled = rpc.get_settings(()).await?.into_inner().json_logs;
+        println!("JSON logs: {}", if enabled { "on" } else { "off" });
+        Ok(())
+    }
+
+    async fn set_connection_profiles(&self, enabled: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_connection_profiles(enabled).await?;
+        println!("Changed connection profiles setting");
+        Ok(())
+    }
+
+    async fn get_connection_profiles(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let enabled = rpc.get_settings(()).await?.into_inner().connection_profiles;
+        println!(
+            "Connection profiles: {}",
+            if enabled { "on" } else { "off" }
+        );
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn set_dbus_service(&self, enabled: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_dbus_service(enabled).await?;
+        println!("Changed D-Bus service setting");
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn get_dbus_service(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let enabled = rpc.get_settings(()).await?.into_inner().dbus_service;
+        println!("D-Bus service: {}", if enabled { "on" } else { "off" });
+        Ok(())
+    }
+
+    async fn schema(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let schema = rpc.get_settings_schema(()).await?.into_inner();
+        for setting in &schema.settings {
+            print_setting(setting);
+        }
+        Ok(())
+    }
+}
+
+fn print_setting(setting: &SettingDescription) {
+    println!("{}", setting.name);
+    if !setting.description.is_empty() {
+        println!("    {}", setting.description);
+    }
+    println!("    Type: {}", setting.type_name);
+    println!("    Default: {}", setting.default_value);
+    for constraint in &setting.constraints {
+        if let Some(constraint) = format_constraint(constraint) {
+            println!("    {}", constraint);
+        }
+    }
+    if setting.requires_reconnect {
+        println!("    Changing this setting reconnects the tunnel");
+    }
+}
+
+fn format_constraint(constraint: &SettingConstraint) -> Option<String> {
+    match constraint.constraint.as_ref()? {
+        Constraint::Min(min) => Some(format!("Minimum: {}", min)),
+        Constraint::Max(max) => Some(format!("Maximum: {}", max)),
+        Constraint::OneOf(allowed) => {
+            Some(format!("Allowed values: {}", allowed.values.join(", ")))
+        }
+    }
+}
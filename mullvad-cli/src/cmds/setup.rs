@@ -0,0 +1,132 @@
+use crate::{new_rpc_client, Command, Result};
+use mullvad_management_interface::types::Settings;
+
+pub struct Setup;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Setup {
+    fn name(&self) -> &'static str {
+        "setup"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Apply a set of settings suited for a particular kind of host")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("server")
+                    .about(
+                        "Put the daemon in server mode, for a headless server such as a VPS or \
+                         seedbox. The tunnel is connected when the daemon starts, network access \
+                         is blocked whenever the tunnel is down, the daemon log is written as \
+                         JSON, the local API serves its metrics endpoint and important events \
+                         are sent to the system log. Settings that are not part of the profile \
+                         are left as they are",
+                    )
+                    .arg(
+                        clap::Arg::with_name("dry run")
+                            .long("dry-run")
+                            .help("Only show the settings that would be changed"),
+                    )
+                    .arg(
+                        clap::Arg::with_name("disable")
+                            .long("disable")
+                            .conflicts_with("dry run")
+                            .help(
+                                "Leave server mode. The settings of the profile are left as \
+                                 they are",
+                            ),
+                    ),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("server", Some(server_matches)) => {
+                if server_matches.is_present("disable") {
+                    self.disable_server_mode().await
+                } else {
+                    self.setup_server(server_matches.is_present("dry run"))
+                        .await
+                }
+            }
+            _ => unreachable!("No setup command given"),
+        }
+    }
+}
+
+/// The settings that make up the server profile.
+#[derive(Clone, Copy)]
+enum ServerSetting {
+    AutoConnect,
+    BlockWhenDisconnected,
+    JsonLogs,
+    LocalApi,
+    SystemLog,
+}
+
+const SERVER_PROFILE: [ServerSetting; 5] = [
+    ServerSetting::AutoConnect,
+    ServerSetting::BlockWhenDisconnected,
+    ServerSetting::JsonLogs,
+    ServerSetting::LocalApi,
+    ServerSetting::SystemLog,
+];
+
+impl ServerSetting {
+    fn description(self) -> &'static str {
+        match self {
+            ServerSetting::AutoConnect => "Auto-connect",
+            ServerSetting::BlockWhenDisconnected => "Always require VPN",
+            ServerSetting::JsonLogs => "JSON logs",
+            ServerSetting::LocalApi => "Local API and metrics endpoint",
+            ServerSetting::SystemLog => "System log",
+        }
+    }
+
+    fn is_enabled(self, settings: &Settings) -> bool {
+        match self {
+            ServerSetting::AutoConnect => settings.auto_connect,
+            ServerSetting::BlockWhenDisconnected => settings.block_when_disconnected,
+            ServerSetting::JsonLogs => settings.json_logs,
+            ServerSetting::LocalApi => settings
+                .local_api
+                .as_ref()
+                .map(|local_api| local_api.enabled)
+                .unwrap_or(false),
+            ServerSetting::SystemLog => settings.system_log,
+        }
+    }
+}
+
+impl Setup {
+    async fn setup_server(&self, dry_run: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc.get_settings(()).await?.into_inner();
+        if settings.server_mode {
+            println!("The daemon is already in server mode");
+            return Ok(());
+        }
+
+        for setting in SERVER_PROFILE.iter().copied() {
+            let state = match (setting.is_enabled(&settings), dry_run) {
+                (true, _) => "on",
+                (false, true) => "off, would be turned on",
+                (false, false) => "turned on",
+            };
+            println!("{}: {}", setting.description(), state);
+        }
+        if !dry_run {
+            rpc.set_server_mode(true).await?;
+            println!("The daemon is now in server mode");
+        }
+        Ok(())
+    }
+
+    async fn disable_server_mode(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_server_mode(false).await?;
+        println!("The daemon is no longer in server mode");
+        Ok(())
+    }
+}
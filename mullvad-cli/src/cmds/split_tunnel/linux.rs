@@ -17,16 +17,50 @@ impl Command for SplitTunnel {
             )
             .setting(clap::AppSettings::SubcommandRequiredElseHelp)
             .subcommand(create_pid_subcommand())
+            .subcommand(create_app_subcommand())
+            .subcommand(
+                clap::SubCommand::with_name("set")
+                    .about("Enable or disable the exclusion of applications")
+                    .arg(
+                        clap::Arg::with_name("policy")
+                            .required(true)
+                            .possible_values(&["on", "off"]),
+                    ),
+            )
+            .subcommand(clap::SubCommand::with_name("get").about("Display the split tunnel status"))
     }
 
     async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
         match matches.subcommand() {
             ("pid", Some(pid_matches)) => Self::handle_pid_cmd(pid_matches).await,
+            ("app", Some(app_matches)) => Self::handle_app_cmd(app_matches).await,
+            ("get", _) => self.get().await,
+            ("set", Some(matches)) => {
+                let enabled = value_t_or_exit!(matches.value_of("policy"), String);
+                self.set(enabled == "on").await
+            }
             _ => unreachable!("unhandled comand"),
         }
     }
 }
 
+fn create_app_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("app")
+        .about(
+            "Manage applications to exclude from the tunnel. Processes running an excluded \
+            application are excluded shortly after they start.",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("list"))
+        .subcommand(
+            clap::SubCommand::with_name("add").arg(clap::Arg::with_name("path").required(true)),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("remove").arg(clap::Arg::with_name("path").required(true)),
+        )
+        .subcommand(clap::SubCommand::with_name("clear"))
+}
+
 fn create_pid_subcommand() -> clap::App<'static, 'static> {
     clap::SubCommand::with_name("pid")
         .about("Manage processes to exclude from the tunnel")
@@ -84,4 +118,67 @@ impl SplitTunnel {
             _ => unreachable!("unhandled command"),
         }
     }
+
+    async fn handle_app_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("list", Some(_)) => {
+                let paths = new_rpc_client()
+                    .await?
+                    .get_settings(())
+                    .await?
+                    .into_inner()
+                    .split_tunnel
+                    .unwrap()
+                    .apps;
+
+                println!("Excluded applications:");
+                for path in &paths {
+                    println!("    {}", path);
+                }
+
+                Ok(())
+            }
+            ("add", Some(matches)) => {
+                let path = value_t_or_exit!(matches.value_of("path"), String);
+                new_rpc_client().await?.add_split_tunnel_app(path).await?;
+                Ok(())
+            }
+            ("remove", Some(matches)) => {
+                let path = value_t_or_exit!(matches.value_of("path"), String);
+                new_rpc_client()
+                    .await?
+                    .remove_split_tunnel_app(path)
+                    .await?;
+                Ok(())
+            }
+            ("clear", Some(_)) => {
+                new_rpc_client().await?.clear_split_tunnel_apps(()).await?;
+                Ok(())
+            }
+            _ => unreachable!("unhandled command"),
+        }
+    }
+
+    async fn set(&self, enabled: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_split_tunnel_state(enabled).await?;
+        println!("Changed split tunnel setting");
+        Ok(())
+    }
+
+    async fn get(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let enabled = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .split_tunnel
+            .unwrap()
+            .enable_exclusions;
+        println!(
+            "Split tunnel status: {}",
+            if enabled { "on" } else { "off" }
+        );
+        Ok(())
+    }
 }
@@ -1,5 +1,6 @@
-use crate::{new_rpc_client, Command, Result};
+use crate::{input, new_rpc_client, Command, Result};
 use clap::value_t_or_exit;
+use mullvad_management_interface::types::SplitTunnelApps;
 
 pub struct SplitTunnel;
 
@@ -53,6 +54,20 @@ fn create_app_subcommand() -> clap::App<'static, 'static> {
             clap::SubCommand::with_name("remove").arg(clap::Arg::with_name("path").required(true)),
         )
         .subcommand(clap::SubCommand::with_name("clear"))
+        .subcommand(
+            clap::SubCommand::with_name("import")
+                .about(
+                    "Exclude all applications listed in a file, one path per line. \
+                    Use '-' to read the list from stdin. The list is applied as a whole, \
+                    so either all or none of the applications are excluded.",
+                )
+                .arg(clap::Arg::with_name("file").required(true))
+                .arg(
+                    clap::Arg::with_name("replace")
+                        .long("replace")
+                        .help("Replace the current list instead of adding to it"),
+                ),
+        )
 }
 
 impl SplitTunnel {
@@ -92,6 +107,26 @@ impl SplitTunnel {
                 new_rpc_client().await?.clear_split_tunnel_apps(()).await?;
                 Ok(())
             }
+            ("import", Some(matches)) => {
+                let file = matches.value_of("file").unwrap();
+                let mut apps = input::read_entries(file)?;
+
+                let mut rpc = new_rpc_client().await?;
+                if !matches.is_present("replace") {
+                    let current_apps = rpc
+                        .get_settings(())
+                        .await?
+                        .into_inner()
+                        .split_tunnel
+                        .unwrap()
+                        .apps;
+                    apps.extend(current_apps);
+                }
+
+                rpc.set_split_tunnel_apps(SplitTunnelApps { apps }).await?;
+                println!("Updated list of excluded applications");
+                Ok(())
+            }
             _ => unreachable!("unhandled subcommand"),
         }
     }
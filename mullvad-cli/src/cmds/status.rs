@@ -1,6 +1,10 @@
 use crate::{format, format::print_keygen_event, new_rpc_client, Command, Error, Result};
 use mullvad_management_interface::{
-    types::daemon_event::Event as EventType, ManagementServiceClient,
+    types::{
+        daemon_event::Event as EventType, tunnel_state, ActiveApiAccessMethod, EventsListenRequest,
+        GeoIpLocation, TunnelState, TunnelStats,
+    },
+    ManagementServiceClient,
 };
 
 pub struct Status;
@@ -20,6 +24,17 @@ impl Command for Status {
                     .short("l")
                     .help("Prints the current location and IP. Based on GeoIP lookups"),
             )
+            .arg(
+                clap::Arg::with_name("verbose")
+                    .long("verbose")
+                    .short("v")
+                    .help("Also prints the traffic through the tunnel and how the API is reached"),
+            )
+            .arg(
+                clap::Arg::with_name("stats")
+                    .long("stats")
+                    .help("Prints the traffic through the tunnel and the current transfer rates"),
+            )
             .subcommand(
                 clap::SubCommand::with_name("listen")
                     .about("Listen for VPN tunnel state changes")
@@ -29,39 +44,97 @@ impl Command for Status {
                             .help("Enables verbose output"),
                     ),
             )
+            .subcommand(clap::SubCommand::with_name("check").about(
+                "Check whether traffic exits through Mullvad and whether DNS requests leak, using \
+                 am.i.mullvad.net. The check is made by the daemon, so it is not affected by the \
+                 DNS cache of a browser",
+            ))
     }
 
     async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let json = matches.is_present("json");
         let mut rpc = new_rpc_client().await?;
+        if matches.subcommand_matches("check").is_some() {
+            return print_connection_check(&mut rpc, json).await;
+        }
+
         let state = rpc.get_tunnel_state(()).await?.into_inner();
+        let listen_matches = matches.subcommand_matches("listen");
 
-        format::print_state(&state);
-        if matches.is_present("location") {
-            print_location(&mut rpc).await?;
+        if json {
+            let status = get_status_json(&mut rpc, matches, &state).await?;
+            if listen_matches.is_some() {
+                format::print_json_line(&status);
+            } else {
+                format::print_json(&status);
+            }
+        } else {
+            format::print_state(&state);
+            if matches.is_present("location") {
+                print_location(&mut rpc).await?;
+            }
+            if matches.is_present("verbose") || matches.is_present("stats") {
+                print_tunnel_stats(&mut rpc).await?;
+            }
+            if matches.is_present("verbose") {
+                if let Some(method) = get_api_access_method(&mut rpc).await? {
+                    format::print_api_access_method(&method);
+                }
+            }
+            let conflicts = rpc.get_conflicting_software(()).await?.into_inner();
+            if !conflicts.conflicts.is_empty() {
+                format::print_conflicting_software(&conflicts);
+            }
+            let missing = rpc.get_missing_capabilities(()).await?.into_inner();
+            if !missing.capabilities.is_empty() {
+                format::print_missing_capabilities(&missing);
+            }
+            if missing.read_only_settings {
+                println!("The settings directory is read-only. Settings cannot be changed");
+            }
         }
 
-        if let Some(listen_matches) = matches.subcommand_matches("listen") {
+        if let Some(listen_matches) = listen_matches {
             let verbose = listen_matches.is_present("verbose");
 
-            let mut events = rpc.events_listen(()).await?.into_inner();
+            let mut events = rpc
+                .events_listen(EventsListenRequest {
+                    include_full_settings: verbose,
+                })
+                .await?
+                .into_inner();
 
             while let Some(event) = events.message().await? {
-                match event.event.unwrap() {
+                let event = event.event.unwrap();
+                if json {
+                    if verbose || !is_verbose_event(&event) {
+                        format::print_json_line(&event);
+                    }
+                    if let EventType::TunnelState(new_state) = &event {
+                        if matches.is_present("location") && is_connected_or_disconnected(new_state)
+                        {
+                            let location = get_location(&mut rpc).await?;
+                            format::print_json_line(&serde_json::json!({ "location": location }));
+                        }
+                    }
+                    continue;
+                }
+
+                match event {
                     EventType::TunnelState(new_state) => {
                         format::print_state(&new_state);
-                        use mullvad_management_interface::types::tunnel_state::State::*;
-                        match new_state.state.unwrap() {
-                            Connected(..) | Disconnected(..) => {
-                                if matches.is_present("location") {
-                                    print_location(&mut rpc).await?;
-                                }
-                            }
-                            _ => {}
+                        if is_connected_or_disconnected(&new_state)
+                            && matches.is_present("location")
+                        {
+                            print_location(&mut rpc).await?;
                         }
                     }
-                    EventType::Settings(settings) => {
+                    EventType::SettingsChanged(settings_changed) => {
                         if verbose {
-                            println!("New settings: {:#?}", settings);
+                            println!("Changed settings: {}", settings_changed.keys.join(", "));
+                            if let Some(settings) = settings_changed.settings {
+                                println!("New settings: {:#?}", settings);
+                            }
                         }
                     }
                     EventType::RelayList(relay_list) => {
@@ -80,6 +153,24 @@ impl Command for Status {
                             print_keygen_event(&key_event);
                         }
                     }
+                    EventType::DeviceState(device_state) => {
+                        format::print_device_state(&device_state);
+                    }
+                    EventType::AccountExpiry(event) => {
+                        format::print_account_expiry_event(&event);
+                    }
+                    EventType::ConflictingSoftware(conflicts) => {
+                        format::print_conflicting_software(&conflicts);
+                    }
+                    EventType::DeviceIdentity(change) => {
+                        format::print_device_identity_change(&change);
+                    }
+                    EventType::ExitIpMismatch(mismatch) => {
+                        format::print_exit_ip_mismatch(&mismatch);
+                    }
+                    EventType::ApiAccessMethod(method) => {
+                        format::print_api_access_method(&method);
+                    }
                 }
             }
         }
@@ -88,17 +179,83 @@ impl Command for Status {
     }
 }
 
+/// Returns whether an event is only printed by `status listen` when verbose output is enabled.
+fn is_verbose_event(event: &EventType) -> bool {
+    matches!(
+        event,
+        EventType::SettingsChanged(_)
+            | EventType::RelayList(_)
+            | EventType::VersionInfo(_)
+            | EventType::KeyEvent(_)
+    )
+}
+
+fn is_connected_or_disconnected(state: &TunnelState) -> bool {
+    matches!(
+        state.state,
+        Some(tunnel_state::State::Connected(..)) | Some(tunnel_state::State::Disconnected(..))
+    )
+}
+
+/// Collects the state that `status` prints into a single JSON object.
+async fn get_status_json(
+    rpc: &mut ManagementServiceClient,
+    matches: &clap::ArgMatches<'_>,
+    state: &TunnelState,
+) -> Result<serde_json::Value> {
+    let mut status = serde_json::json!({ "tunnel_state": state });
+    if matches.is_present("location") {
+        status["location"] = serde_json::json!(get_location(rpc).await?);
+    }
+    if matches.is_present("verbose") || matches.is_present("stats") {
+        status["tunnel_stats"] = serde_json::json!(get_tunnel_stats(rpc).await?);
+    }
+    if matches.is_present("verbose") {
+        status["api_access_method"] = serde_json::json!(get_api_access_method(rpc).await?);
+    }
+    let conflicts = rpc.get_conflicting_software(()).await?.into_inner();
+    status["conflicting_software"] = serde_json::json!(conflicts);
+    let missing = rpc.get_missing_capabilities(()).await?.into_inner();
+    status["missing_capabilities"] = serde_json::json!(missing);
+    Ok(status)
+}
+
+/// Returns the current location, or `None` if it is not known.
+async fn get_location(rpc: &mut ManagementServiceClient) -> Result<Option<GeoIpLocation>> {
+    match rpc.get_current_location(()).await {
+        Ok(response) => Ok(Some(response.into_inner())),
+        Err(status) if status.code() == mullvad_management_interface::Code::NotFound => Ok(None),
+        Err(status) => Err(Error::RpcFailed(status)),
+    }
+}
+
+/// Returns the access method that API traffic is sent through.
+async fn get_api_access_method(
+    rpc: &mut ManagementServiceClient,
+) -> Result<Option<ActiveApiAccessMethod>> {
+    let status = rpc
+        .get_api_access_status(())
+        .await
+        .map_err(|error| Error::RpcFailedExt("Failed to obtain API access status", error))?
+        .into_inner();
+    Ok(status.access_method)
+}
+
+/// Returns the traffic through the tunnel, or `None` if there is no tunnel.
+async fn get_tunnel_stats(rpc: &mut ManagementServiceClient) -> Result<Option<TunnelStats>> {
+    match rpc.get_tunnel_stats(()).await {
+        Ok(response) => Ok(Some(response.into_inner())),
+        Err(status) if status.code() == mullvad_management_interface::Code::NotFound => Ok(None),
+        Err(status) => Err(Error::RpcFailed(status)),
+    }
+}
+
 async fn print_location(rpc: &mut ManagementServiceClient) -> Result<()> {
-    let location = rpc.get_current_location(()).await;
-    let location = match location {
-        Ok(response) => response.into_inner(),
-        Err(status) => {
-            if status.code() == mullvad_management_interface::Code::NotFound {
-                println!("Location data unavailable");
-                return Ok(());
-            } else {
-                return Err(Error::RpcFailed(status));
-            }
+    let location = match get_location(rpc).await? {
+        Some(location) => location,
+        None => {
+            println!("Location data unavailable");
+            return Ok(());
         }
     };
     if !location.hostname.is_empty() {
@@ -123,3 +280,83 @@ async fn print_location(rpc: &mut ManagementServiceClient) -> Result<()> {
     );
     Ok(())
 }
+
+async fn print_tunnel_stats(rpc: &mut ManagementServiceClient) -> Result<()> {
+    let stats = match get_tunnel_stats(rpc).await? {
+        Some(stats) => stats,
+        None => return Ok(()),
+    };
+    println!(
+        "Received: {:.1} MB ({:.2} Mbit/s)",
+        stats.rx_bytes as f64 / 1_000_000.0,
+        stats.rx_rate as f64 * 8.0 / 1_000_000.0
+    );
+    println!(
+        "Sent: {:.1} MB ({:.2} Mbit/s)",
+        stats.tx_bytes as f64 / 1_000_000.0,
+        stats.tx_rate as f64 * 8.0 / 1_000_000.0
+    );
+    Ok(())
+}
+
+async fn print_connection_check(rpc: &mut ManagementServiceClient, json: bool) -> Result<()> {
+    let check = rpc.check_connection(()).await?.into_inner();
+    if json {
+        format::print_json(&check);
+        return Ok(());
+    }
+
+    println!("Exit IP: {}", check.exit_ip);
+    if check.mullvad_exit_ip {
+        println!("You are connected to Mullvad");
+    } else {
+        println!("You are not connected to Mullvad");
+    }
+    if !check.exit_hostname.is_empty() {
+        println!("Exit relay: {}", check.exit_hostname);
+    }
+    if !check.expected_hostname.is_empty() && !check.hostname_matches {
+        println!(
+            "Warning: traffic does not exit through the connected relay {}",
+            check.expected_hostname
+        );
+    }
+    if check.exit_city.is_empty() {
+        println!("Exit location: {}", check.exit_country);
+    } else {
+        println!("Exit location: {}, {}", check.exit_city, check.exit_country);
+    }
+    if !check.expected_country.is_empty() && !check.location_matches {
+        println!(
+            "Warning: traffic exits in {} rather than in {}, where the connected relay is",
+            check.exit_country, check.expected_country
+        );
+    }
+    if check.blacklisted {
+        println!("Exit IP is blacklisted by: {}", check.blacklists.join(", "));
+    } else {
+        println!("Exit IP is not blacklisted");
+    }
+
+    if !check.dns_checked {
+        println!("DNS servers: could not be determined");
+        return Ok(());
+    }
+    println!("DNS servers:");
+    for server in &check.dns_servers {
+        let owner = if server.mullvad_dns {
+            format!("Mullvad ({})", server.hostname)
+        } else if !server.organization.is_empty() {
+            server.organization.clone()
+        } else {
+            "unknown".to_string()
+        };
+        println!("\t{} - {}", server.ip, owner);
+    }
+    if check.dns_servers.iter().any(|server| !server.mullvad_dns) {
+        println!("Warning: DNS requests leak to servers that are not run by Mullvad");
+    } else {
+        println!("DNS requests do not leak");
+    }
+    Ok(())
+}
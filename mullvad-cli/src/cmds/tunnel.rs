@@ -1,8 +1,12 @@
 use crate::{format::print_keygen_event, new_rpc_client, Command, Error, Result};
 use clap::value_t;
 use mullvad_management_interface::types::{self, Timestamp, TunnelOptions};
-use mullvad_types::wireguard::DEFAULT_ROTATION_INTERVAL;
+use mullvad_types::{
+    units::{HumanDuration, ParseError},
+    wireguard::DEFAULT_ROTATION_INTERVAL,
+};
 use std::{convert::TryFrom, time::Duration};
+use talpid_types::net::wireguard::{ICMP_CHECK_INTERVAL_RANGE, PACKET_LOSS_THRESHOLD_RANGE};
 
 pub struct Tunnel;
 
@@ -19,6 +23,11 @@ impl Command for Tunnel {
             .subcommand(create_openvpn_subcommand())
             .subcommand(create_wireguard_subcommand())
             .subcommand(create_ipv6_subcommand())
+            .subcommand(create_ipv6_failure_subcommand())
+            .subcommand(create_interface_name_subcommand())
+            .subcommand(create_overrides_subcommand())
+            .subcommand(create_reconnect_backoff_subcommand())
+            .subcommand(create_exit_ip_check_subcommand())
     }
 
     async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
@@ -26,6 +35,21 @@ impl Command for Tunnel {
             ("openvpn", Some(openvpn_matches)) => Self::handle_openvpn_cmd(openvpn_matches).await,
             ("wireguard", Some(wg_matches)) => Self::handle_wireguard_cmd(wg_matches).await,
             ("ipv6", Some(ipv6_matches)) => Self::handle_ipv6_cmd(ipv6_matches).await,
+            ("ipv6-failure", Some(failure_matches)) => {
+                Self::handle_ipv6_failure_cmd(failure_matches).await
+            }
+            ("interface-name", Some(name_matches)) => {
+                Self::handle_interface_name_cmd(name_matches).await
+            }
+            ("overrides", Some(overrides_matches)) => {
+                Self::handle_overrides_cmd(overrides_matches).await
+            }
+            ("reconnect-backoff", Some(backoff_matches)) => {
+                Self::handle_reconnect_backoff_cmd(backoff_matches).await
+            }
+            ("exit-ip-check", Some(check_matches)) => {
+                Self::handle_exit_ip_check_cmd(check_matches).await
+            }
             _ => {
                 unreachable!("unhandled comand");
             }
@@ -38,15 +62,34 @@ fn create_wireguard_subcommand() -> clap::App<'static, 'static> {
         .about("Manage options for Wireguard tunnels")
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .subcommand(create_wireguard_mtu_subcommand())
-        .subcommand(create_wireguard_keys_subcommand());
+        .subcommand(create_wireguard_icmp_check_subcommand())
+        .subcommand(create_wireguard_packet_loss_subcommand())
+        .subcommand(create_wireguard_keys_subcommand())
+        .subcommand(create_wireguard_keys_rotation_interval_subcommand())
+        .subcommand(create_wireguard_quantum_resistant_subcommand());
     #[cfg(windows)]
-    {
-        subcmd.subcommand(create_wireguard_use_wg_nt_subcommand())
-    }
-    #[cfg(not(windows))]
-    {
-        subcmd
-    }
+    let subcmd = subcmd.subcommand(create_wireguard_use_wg_nt_subcommand());
+    #[cfg(target_os = "linux")]
+    let subcmd = subcmd.subcommand(create_wireguard_implementation_subcommand());
+    subcmd
+}
+
+fn create_wireguard_quantum_resistant_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("quantum-resistant")
+        .about(
+            "Configure whether to negotiate a post-quantum preshared key with the relay when \
+             connecting. 'auto' follows the quantum-resistant-tunnel beta feature",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(
+            clap::SubCommand::with_name("set").arg(
+                clap::Arg::with_name("state")
+                    .required(true)
+                    .takes_value(true)
+                    .possible_values(&["on", "off", "auto"]),
+            ),
+        )
 }
 
 fn create_wireguard_mtu_subcommand() -> clap::App<'static, 'static> {
@@ -60,6 +103,51 @@ fn create_wireguard_mtu_subcommand() -> clap::App<'static, 'static> {
         )
 }
 
+fn create_wireguard_icmp_check_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("icmp-check")
+        .about(
+            "Check the tunnel connectivity by pinging the gateway at a fixed interval, instead of \
+             inferring it from the traffic. This may help on networks where working tunnels are \
+             mistakenly considered broken",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(
+            clap::SubCommand::with_name("unset")
+                .about("Infer the connectivity from the traffic, which is the default"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("set").arg(
+                clap::Arg::with_name("interval")
+                    .help("The ping interval in seconds")
+                    .required(true)
+                    .validator(icmp_check_interval_validator),
+            ),
+        )
+}
+
+fn create_wireguard_packet_loss_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("packet-loss")
+        .about(
+            "Reconnect to another relay when the packet loss in the tunnel stays above a \
+             threshold for a while",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(
+            clap::SubCommand::with_name("unset")
+                .about("Never reconnect because of packet loss, which is the default"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("set").arg(
+                clap::Arg::with_name("threshold")
+                    .help("The packet loss in percent")
+                    .required(true)
+                    .validator(packet_loss_threshold_validator),
+            ),
+        )
+}
+
 fn create_wireguard_keys_subcommand() -> clap::App<'static, 'static> {
     clap::SubCommand::with_name("key")
         .about("Manage your wireguard key")
@@ -69,6 +157,25 @@ fn create_wireguard_keys_subcommand() -> clap::App<'static, 'static> {
         .subcommand(create_wireguard_keys_rotation_interval_subcommand())
 }
 
+#[cfg(target_os = "linux")]
+fn create_wireguard_implementation_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("implementation")
+        .about(
+            "Choose between the WireGuard kernel module and the userspace implementation. \
+            The userspace implementation is used if the kernel module is unavailable",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(
+            clap::SubCommand::with_name("set").arg(
+                clap::Arg::with_name("implementation")
+                    .required(true)
+                    .takes_value(true)
+                    .possible_values(&["kernel", "userspace"]),
+            ),
+        )
+}
+
 #[cfg(windows)]
 fn create_wireguard_use_wg_nt_subcommand() -> clap::App<'static, 'static> {
     clap::SubCommand::with_name("use-wireguard-nt")
@@ -87,21 +194,80 @@ fn create_wireguard_use_wg_nt_subcommand() -> clap::App<'static, 'static> {
 
 fn create_wireguard_keys_rotation_interval_subcommand() -> clap::App<'static, 'static> {
     clap::SubCommand::with_name("rotation-interval")
-        .about("Manage automatic key rotation (given in hours)")
+        .about(
+            "Manage automatic key rotation. The new key is added before the old one is removed, \
+             which happens once the tunnel has reconnected with the new key",
+        )
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .subcommand(clap::SubCommand::with_name("get"))
         .subcommand(clap::SubCommand::with_name("reset").about("Use the default rotation interval"))
         .subcommand(
-            clap::SubCommand::with_name("set").arg(clap::Arg::with_name("interval").required(true)),
+            clap::SubCommand::with_name("set").arg(
+                clap::Arg::with_name("interval")
+                    .help(
+                        "The rotation interval, e.g. \"36h\" or \"3d\". \
+                         A number without a unit is given in hours",
+                    )
+                    .required(true)
+                    .validator(rotation_interval_validator),
+            ),
         )
 }
 
 
 fn create_openvpn_subcommand() -> clap::App<'static, 'static> {
-    clap::SubCommand::with_name("openvpn")
+    let subcmd = clap::SubCommand::with_name("openvpn")
         .about("Manage options for OpenVPN tunnels")
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
-        .subcommand(create_openvpn_mssfix_subcommand())
+        .subcommand(create_openvpn_mssfix_subcommand());
+    #[cfg(windows)]
+    {
+        subcmd
+            .subcommand(create_openvpn_use_dco_subcommand())
+            .subcommand(create_openvpn_wintun_adapter_subcommand())
+    }
+    #[cfg(not(windows))]
+    {
+        subcmd
+    }
+}
+
+#[cfg(windows)]
+fn create_openvpn_use_dco_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("use-dco")
+        .about(
+            "Enable or disable the ovpn-dco-win driver. Wintun is used if the driver is not \
+             installed or fails",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(
+            clap::SubCommand::with_name("set").arg(
+                clap::Arg::with_name("policy")
+                    .required(true)
+                    .takes_value(true)
+                    .possible_values(&["on", "off"]),
+            ),
+        )
+}
+
+#[cfg(windows)]
+fn create_openvpn_wintun_adapter_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("wintun-adapter")
+        .about(
+            "Configure what happens to the Wintun adapter once the tunnel has closed. Adapters \
+             that are kept are reused by later tunnels",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(
+            clap::SubCommand::with_name("set").arg(
+                clap::Arg::with_name("policy")
+                    .required(true)
+                    .takes_value(true)
+                    .possible_values(&["keep", "delete-on-shutdown", "delete-after-use"]),
+            ),
+        )
 }
 
 fn create_openvpn_mssfix_subcommand() -> clap::App<'static, 'static> {
@@ -117,15 +283,173 @@ fn create_openvpn_mssfix_subcommand() -> clap::App<'static, 'static> {
 
 fn create_ipv6_subcommand() -> clap::App<'static, 'static> {
     clap::SubCommand::with_name("ipv6")
+        .about(
+            "Configure whether IPv6 addresses, routes and DNS servers are set up inside the \
+             tunnel. IPv6 traffic is blocked when this is off",
+        )
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .subcommand(clap::SubCommand::with_name("get"))
         .subcommand(
-            clap::SubCommand::with_name("set").arg(
-                clap::Arg::with_name("policy")
-                    .required(true)
-                    .takes_value(true)
-                    .possible_values(&["on", "off"]),
-            ),
+            clap::SubCommand::with_name("set")
+                .about("Turn IPv6 in the tunnel on or off")
+                .arg(
+                    clap::Arg::with_name("policy")
+                        .required(true)
+                        .takes_value(true)
+                        .possible_values(&["on", "off"]),
+                ),
+        )
+}
+
+fn create_ipv6_failure_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("ipv6-failure")
+        .about(
+            "Configure what happens when IPv6 is on but cannot be set up inside the tunnel, such \
+             as when IPv6 is disabled on the device",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(
+            clap::SubCommand::with_name("set")
+                .about(
+                    "Either connect with IPv4 only and block IPv6 traffic, or fail the connection \
+                     attempt and block all traffic",
+                )
+                .arg(
+                    clap::Arg::with_name("policy")
+                        .required(true)
+                        .takes_value(true)
+                        .possible_values(&["ipv4-only", "block"]),
+                ),
+        )
+}
+
+fn create_interface_name_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("interface-name")
+        .about("Configure the name of the tunnel network interface")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(clap::SubCommand::with_name("unset").about("Use the default interface name"))
+        .subcommand(
+            clap::SubCommand::with_name("set").arg(clap::Arg::with_name("name").required(true)),
+        )
+}
+
+fn create_overrides_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("overrides")
+        .about(
+            "Manage changes that are applied last to the parameters of every WireGuard \
+             connection attempt. The overrides that are applied are logged by the daemon",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(clap::SubCommand::with_name("clear").about("Remove all overrides"))
+        .subcommand(
+            clap::SubCommand::with_name("wireguard-port")
+                .about("Connect to WireGuard relays on this port, unless the traffic is obfuscated")
+                .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(clap::SubCommand::with_name("unset"))
+                .subcommand(
+                    clap::SubCommand::with_name("set")
+                        .arg(clap::Arg::with_name("port").required(true)),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("allowed-ip")
+                .about("Route extra networks to the WireGuard peer that traffic exits through")
+                .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    clap::SubCommand::with_name("add")
+                        .arg(clap::Arg::with_name("network").required(true)),
+                )
+                .subcommand(
+                    clap::SubCommand::with_name("remove")
+                        .arg(clap::Arg::with_name("network").required(true)),
+                ),
+        )
+}
+
+fn create_reconnect_backoff_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("reconnect-backoff")
+        .about(
+            "Manage how long to wait before retrying after a failed connection attempt. Every \
+             retry waits longer than the one before it, up to a limit, and the delays start over \
+             once the tunnel has connected or the network has changed",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(clap::SubCommand::with_name("reset").about("Use the default delays"))
+        .subcommand(
+            clap::SubCommand::with_name("set")
+                .arg(
+                    clap::Arg::with_name("initial-delay")
+                        .long("initial-delay")
+                        .takes_value(true)
+                        .help("The delay before the first retry, such as \"1s\""),
+                )
+                .arg(
+                    clap::Arg::with_name("multiplier")
+                        .long("multiplier")
+                        .takes_value(true)
+                        .help("How many times longer every delay is than the one before it"),
+                )
+                .arg(
+                    clap::Arg::with_name("max-delay")
+                        .long("max-delay")
+                        .takes_value(true)
+                        .help("The longest delay, such as \"30s\""),
+                )
+                .arg(
+                    clap::Arg::with_name("jitter")
+                        .long("jitter")
+                        .takes_value(true)
+                        .help("How large a random part of every delay, in percent, may be cut off"),
+                )
+                .group(
+                    clap::ArgGroup::with_name("parameters")
+                        .args(&["initial-delay", "multiplier", "max-delay", "jitter"])
+                        .multiple(true)
+                        .required(true),
+                ),
+        )
+}
+
+fn create_exit_ip_check_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("exit-ip-check")
+        .about(
+            "Manage periodic checks, while connected, that traffic exits through the relay that \
+             the tunnel is connected to. Mismatches are shown by 'mullvad status listen'",
+        )
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(
+            clap::SubCommand::with_name("set")
+                .arg(
+                    clap::Arg::with_name("enabled")
+                        .long("enabled")
+                        .takes_value(true)
+                        .possible_values(&["on", "off"])
+                        .help("Whether to check the exit IP"),
+                )
+                .arg(
+                    clap::Arg::with_name("interval")
+                        .long("interval")
+                        .takes_value(true)
+                        .help("How long to wait between two checks, such as \"15m\""),
+                )
+                .arg(
+                    clap::Arg::with_name("reconnect")
+                        .long("reconnect")
+                        .takes_value(true)
+                        .possible_values(&["on", "off"])
+                        .help("Whether to reconnect when traffic exits somewhere else"),
+                )
+                .group(
+                    clap::ArgGroup::with_name("parameters")
+                        .args(&["enabled", "interval", "reconnect"])
+                        .multiple(true)
+                        .required(true),
+                ),
         )
 }
 
@@ -135,6 +459,18 @@ impl Tunnel {
             ("mssfix", Some(mssfix_matches)) => {
                 Self::handle_openvpn_mssfix_cmd(mssfix_matches).await
             }
+            #[cfg(windows)]
+            ("use-dco", Some(matches)) => match matches.subcommand() {
+                ("get", _) => Self::process_openvpn_use_dco_get().await,
+                ("set", Some(matches)) => Self::process_openvpn_use_dco_set(matches).await,
+                _ => unreachable!("unhandled command"),
+            },
+            #[cfg(windows)]
+            ("wintun-adapter", Some(matches)) => match matches.subcommand() {
+                ("get", _) => Self::process_openvpn_wintun_adapter_get().await,
+                ("set", Some(matches)) => Self::process_openvpn_wintun_adapter_set(matches).await,
+                _ => unreachable!("unhandled command"),
+            },
             _ => unreachable!("unhandled command"),
         }
     }
@@ -157,17 +493,38 @@ impl Tunnel {
                 _ => unreachable!("unhandled command"),
             },
 
+            ("icmp-check", Some(matches)) => match matches.subcommand() {
+                ("get", _) => Self::process_wireguard_icmp_check_get().await,
+                ("set", Some(matches)) => Self::process_wireguard_icmp_check_set(matches).await,
+                ("unset", _) => Self::process_wireguard_icmp_check_unset().await,
+                _ => unreachable!("unhandled command"),
+            },
+
+            ("packet-loss", Some(matches)) => match matches.subcommand() {
+                ("get", _) => Self::process_wireguard_packet_loss_get().await,
+                ("set", Some(matches)) => Self::process_wireguard_packet_loss_set(matches).await,
+                ("unset", _) => Self::process_wireguard_packet_loss_unset().await,
+                _ => unreachable!("unhandled command"),
+            },
+
             ("key", Some(matches)) => match matches.subcommand() {
                 ("check", _) => Self::process_wireguard_key_check().await,
                 ("regenerate", _) => Self::process_wireguard_key_generate().await,
-                ("rotation-interval", Some(matches)) => match matches.subcommand() {
-                    ("get", _) => Self::process_wireguard_rotation_interval_get().await,
-                    ("set", Some(matches)) => {
-                        Self::process_wireguard_rotation_interval_set(matches).await
-                    }
-                    ("reset", _) => Self::process_wireguard_rotation_interval_reset().await,
-                    _ => unreachable!("unhandled command"),
-                },
+                ("rotation-interval", Some(matches)) => {
+                    Self::handle_wireguard_rotation_interval_cmd(matches).await
+                }
+                _ => unreachable!("unhandled command"),
+            },
+
+            ("rotation-interval", Some(matches)) => {
+                Self::handle_wireguard_rotation_interval_cmd(matches).await
+            }
+
+            ("quantum-resistant", Some(matches)) => match matches.subcommand() {
+                ("get", _) => Self::process_wireguard_quantum_resistant_get().await,
+                ("set", Some(matches)) => {
+                    Self::process_wireguard_quantum_resistant_set(matches).await
+                }
                 _ => unreachable!("unhandled command"),
             },
 
@@ -178,6 +535,22 @@ impl Tunnel {
                 _ => unreachable!("unhandled command"),
             },
 
+            #[cfg(target_os = "linux")]
+            ("implementation", Some(matches)) => match matches.subcommand() {
+                ("get", _) => Self::process_wireguard_implementation_get().await,
+                ("set", Some(matches)) => Self::process_wireguard_implementation_set(matches).await,
+                _ => unreachable!("unhandled command"),
+            },
+
+            _ => unreachable!("unhandled command"),
+        }
+    }
+
+    async fn handle_wireguard_rotation_interval_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("get", _) => Self::process_wireguard_rotation_interval_get().await,
+            ("set", Some(matches)) => Self::process_wireguard_rotation_interval_set(matches).await,
+            ("reset", _) => Self::process_wireguard_rotation_interval_reset().await,
             _ => unreachable!("unhandled command"),
         }
     }
@@ -211,6 +584,60 @@ impl Tunnel {
         Ok(())
     }
 
+    async fn process_wireguard_icmp_check_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options().await?;
+        let interval = tunnel_options.wireguard.unwrap().icmp_check_interval;
+        if interval != 0 {
+            println!("Pinging the gateway every {} seconds", interval);
+        } else {
+            println!("Inferring connectivity from the traffic");
+        }
+        Ok(())
+    }
+
+    async fn process_wireguard_icmp_check_set(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let interval = value_t!(matches.value_of("interval"), u16).unwrap_or_else(|e| e.exit());
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_icmp_check_interval(u32::from(interval))
+            .await?;
+        println!("Wireguard connectivity check has been updated");
+        Ok(())
+    }
+
+    async fn process_wireguard_icmp_check_unset() -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_icmp_check_interval(0).await?;
+        println!("Wireguard connectivity check has been reset");
+        Ok(())
+    }
+
+    async fn process_wireguard_packet_loss_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options().await?;
+        let threshold = tunnel_options.wireguard.unwrap().packet_loss_threshold;
+        if threshold != 0 {
+            println!("Reconnecting when the packet loss exceeds {}%", threshold);
+        } else {
+            println!("Never reconnecting because of packet loss");
+        }
+        Ok(())
+    }
+
+    async fn process_wireguard_packet_loss_set(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let threshold = value_t!(matches.value_of("threshold"), u8).unwrap_or_else(|e| e.exit());
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_packet_loss_threshold(u32::from(threshold))
+            .await?;
+        println!("Wireguard packet loss threshold has been updated");
+        Ok(())
+    }
+
+    async fn process_wireguard_packet_loss_unset() -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_packet_loss_threshold(0).await?;
+        println!("Wireguard packet loss threshold has been reset");
+        Ok(())
+    }
+
     #[cfg(windows)]
     async fn process_wireguard_use_wg_nt_get() -> Result<()> {
         let tunnel_options = Self::get_tunnel_options().await?;
@@ -231,6 +658,86 @@ impl Tunnel {
         Ok(())
     }
 
+    #[cfg(target_os = "linux")]
+    async fn process_wireguard_implementation_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options().await?;
+        if tunnel_options.wireguard.unwrap().use_userspace_wireguard {
+            println!("userspace");
+        } else {
+            println!("kernel");
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn process_wireguard_implementation_set(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let use_userspace = matches.value_of("implementation").unwrap() == "userspace";
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_use_userspace_wireguard(use_userspace).await?;
+        println!("Updated WireGuard implementation setting");
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    async fn process_openvpn_use_dco_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options().await?;
+        if tunnel_options.openvpn.unwrap().use_ovpn_dco {
+            println!("enabled");
+        } else {
+            println!("disabled");
+        }
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    async fn process_openvpn_use_dco_set(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let new_state = matches.value_of("policy").unwrap() == "on";
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_use_openvpn_dco(new_state).await?;
+        println!("Updated ovpn-dco setting");
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    async fn process_openvpn_wintun_adapter_get() -> Result<()> {
+        use mullvad_management_interface::types::wintun_adapter_policy::Policy;
+
+        let tunnel_options = Self::get_tunnel_options().await?;
+        let policy = tunnel_options
+            .openvpn
+            .unwrap()
+            .wintun_adapter_policy
+            .and_then(|policy| Policy::from_i32(policy.policy))
+            .unwrap_or(Policy::Keep);
+        match policy {
+            Policy::Keep => println!("keep"),
+            Policy::DeleteOnShutdown => println!("delete-on-shutdown"),
+            Policy::DeleteAfterUse => println!("delete-after-use"),
+        }
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    async fn process_openvpn_wintun_adapter_set(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        use mullvad_management_interface::types::{
+            wintun_adapter_policy::Policy, WintunAdapterPolicy,
+        };
+
+        let policy = match matches.value_of("policy").unwrap() {
+            "keep" => Policy::Keep,
+            "delete-on-shutdown" => Policy::DeleteOnShutdown,
+            "delete-after-use" => Policy::DeleteAfterUse,
+            _ => unreachable!("invalid Wintun adapter policy"),
+        };
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wintun_adapter_policy(WintunAdapterPolicy {
+            policy: i32::from(policy),
+        })
+        .await?;
+        println!("Updated Wintun adapter setting");
+        Ok(())
+    }
+
     async fn process_wireguard_key_check() -> Result<()> {
         let mut rpc = new_rpc_client().await?;
         let key = rpc.get_wireguard_key(()).await;
@@ -275,26 +782,27 @@ impl Tunnel {
         let tunnel_options = Self::get_tunnel_options().await?;
         match tunnel_options.wireguard.unwrap().rotation_interval {
             Some(interval) => {
-                let hours = duration_hours(&Duration::try_from(interval).unwrap());
-                println!("Rotation interval: {} hour(s)", hours);
+                let interval = HumanDuration(Duration::try_from(interval).unwrap());
+                println!("Rotation interval: {}", interval);
             }
             None => println!(
-                "Rotation interval: default ({} hours)",
-                duration_hours(&DEFAULT_ROTATION_INTERVAL)
+                "Rotation interval: default ({})",
+                HumanDuration(DEFAULT_ROTATION_INTERVAL)
             ),
         }
         Ok(())
     }
 
     async fn process_wireguard_rotation_interval_set(matches: &clap::ArgMatches<'_>) -> Result<()> {
-        let rotate_interval =
-            value_t!(matches.value_of("interval"), u64).unwrap_or_else(|e| e.exit());
+        let rotate_interval = parse_rotation_interval(matches.value_of("interval").unwrap())
+            .expect("Rotation interval should have been validated");
         let mut rpc = new_rpc_client().await?;
-        rpc.set_wireguard_rotation_interval(types::Duration::from(Duration::from_secs(
-            60 * 60 * rotate_interval,
-        )))
-        .await?;
-        println!("Set key rotation interval: {} hour(s)", rotate_interval);
+        rpc.set_wireguard_rotation_interval(types::Duration::from(rotate_interval))
+            .await?;
+        println!(
+            "Set key rotation interval: {}",
+            HumanDuration(rotate_interval)
+        );
         Ok(())
     }
 
@@ -302,12 +810,50 @@ impl Tunnel {
         let mut rpc = new_rpc_client().await?;
         rpc.reset_wireguard_rotation_interval(()).await?;
         println!(
-            "Set key rotation interval: default ({} hours)",
-            duration_hours(&DEFAULT_ROTATION_INTERVAL)
+            "Set key rotation interval: default ({})",
+            HumanDuration(DEFAULT_ROTATION_INTERVAL)
         );
         Ok(())
     }
 
+    async fn process_wireguard_quantum_resistant_get() -> Result<()> {
+        use mullvad_management_interface::types::quantum_resistant_state::State;
+
+        let tunnel_options = Self::get_tunnel_options().await?;
+        let state = tunnel_options
+            .wireguard
+            .unwrap()
+            .quantum_resistant
+            .and_then(|state| State::from_i32(state.state))
+            .unwrap_or(State::Auto);
+        match state {
+            State::Auto => println!("Quantum-resistant tunnel: auto"),
+            State::On => println!("Quantum-resistant tunnel: on"),
+            State::Off => println!("Quantum-resistant tunnel: off"),
+        }
+        Ok(())
+    }
+
+    async fn process_wireguard_quantum_resistant_set(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        use mullvad_management_interface::types::{
+            quantum_resistant_state::State, QuantumResistantState,
+        };
+
+        let state = match matches.value_of("state").unwrap() {
+            "auto" => State::Auto,
+            "on" => State::On,
+            "off" => State::Off,
+            _ => unreachable!("invalid quantum-resistant state"),
+        };
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_quantum_resistant_tunnel(QuantumResistantState {
+            state: i32::from(state),
+        })
+        .await?;
+        println!("Updated quantum-resistant tunnel setting");
+        Ok(())
+    }
+
     async fn handle_ipv6_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
         if matches.subcommand_matches("get").is_some() {
             Self::process_ipv6_get().await
@@ -318,6 +864,173 @@ impl Tunnel {
         }
     }
 
+    async fn handle_ipv6_failure_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("get", Some(_)) => Self::process_ipv6_failure_get().await,
+            ("set", Some(set_matches)) => Self::process_ipv6_failure_set(set_matches).await,
+            _ => unreachable!("unhandled command"),
+        }
+    }
+
+    async fn handle_interface_name_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("get", Some(_)) => Self::process_interface_name_get().await,
+            ("set", Some(set_matches)) => {
+                let name = set_matches.value_of("name").unwrap().to_string();
+                Self::process_interface_name_set(name).await
+            }
+            ("unset", Some(_)) => Self::process_interface_name_set(String::new()).await,
+            _ => unreachable!("unhandled command"),
+        }
+    }
+
+    async fn handle_overrides_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let mut overrides = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .tunnel_parameter_overrides
+            .unwrap_or_default();
+        match matches.subcommand() {
+            ("get", _) => {
+                if overrides.wireguard_port != 0 {
+                    println!("WireGuard port: {}", overrides.wireguard_port);
+                } else {
+                    println!("WireGuard port: not overridden");
+                }
+                for network in &overrides.wireguard_extra_allowed_ips {
+                    println!("Extra allowed IP: {}", network);
+                }
+                return Ok(());
+            }
+            ("clear", _) => overrides = types::TunnelParameterOverrides::default(),
+            ("wireguard-port", Some(matches)) => match matches.subcommand() {
+                ("set", Some(matches)) => {
+                    let port = value_t!(matches.value_of("port"), u16).unwrap_or_else(|e| e.exit());
+                    overrides.wireguard_port = u32::from(port);
+                }
+                ("unset", _) => overrides.wireguard_port = 0,
+                _ => unreachable!("unhandled command"),
+            },
+            ("allowed-ip", Some(matches)) => match matches.subcommand() {
+                ("add", Some(matches)) => {
+                    let network = matches.value_of("network").unwrap().to_owned();
+                    if !overrides.wireguard_extra_allowed_ips.contains(&network) {
+                        overrides.wireguard_extra_allowed_ips.push(network);
+                    }
+                }
+                ("remove", Some(matches)) => {
+                    let network = matches.value_of("network").unwrap();
+                    overrides
+                        .wireguard_extra_allowed_ips
+                        .retain(|existing| existing != network);
+                }
+                _ => unreachable!("unhandled command"),
+            },
+            _ => unreachable!("unhandled command"),
+        }
+        rpc.set_tunnel_parameter_overrides(overrides).await?;
+        println!("Tunnel parameter overrides have been updated");
+        Ok(())
+    }
+
+    async fn handle_reconnect_backoff_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let mut backoff = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .reconnect_backoff
+            .unwrap_or_default();
+        match matches.subcommand() {
+            ("get", _) => {
+                let delay = |delay: Option<types::Duration>| {
+                    delay
+                        .and_then(|delay| Duration::try_from(delay).ok())
+                        .map(|delay| HumanDuration(delay).to_string())
+                        .unwrap_or_else(|| "unknown".to_owned())
+                };
+                println!("Initial delay: {}", delay(backoff.initial_delay));
+                println!("Multiplier: {}", backoff.multiplier);
+                println!("Max delay: {}", delay(backoff.max_delay));
+                println!("Jitter: {}%", backoff.jitter_percent);
+                return Ok(());
+            }
+            ("reset", _) => {
+                backoff = types::ReconnectBackoffSettings::from(
+                    &mullvad_types::settings::ReconnectBackoffSettings::default(),
+                );
+            }
+            ("set", Some(matches)) => {
+                if let Some(delay) = matches.value_of("initial-delay") {
+                    backoff.initial_delay = Some(parse_backoff_delay(delay)?);
+                }
+                if matches.is_present("multiplier") {
+                    backoff.multiplier =
+                        value_t!(matches.value_of("multiplier"), u32).unwrap_or_else(|e| e.exit());
+                }
+                if let Some(delay) = matches.value_of("max-delay") {
+                    backoff.max_delay = Some(parse_backoff_delay(delay)?);
+                }
+                if matches.is_present("jitter") {
+                    backoff.jitter_percent =
+                        value_t!(matches.value_of("jitter"), u32).unwrap_or_else(|e| e.exit());
+                }
+            }
+            _ => unreachable!("unhandled command"),
+        }
+        rpc.set_reconnect_backoff(backoff)
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to set the reconnection delays", error))?;
+        println!("Reconnection delays have been updated");
+        Ok(())
+    }
+
+    async fn handle_exit_ip_check_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let mut exit_ip_check = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .exit_ip_check
+            .unwrap_or_default();
+        let on_off = |enabled: bool| if enabled { "on" } else { "off" };
+        match matches.subcommand() {
+            ("get", _) => {
+                let interval = exit_ip_check
+                    .interval
+                    .and_then(|interval| Duration::try_from(interval).ok())
+                    .map(|interval| HumanDuration(interval).to_string())
+                    .unwrap_or_else(|| "unknown".to_owned());
+                println!("Enabled: {}", on_off(exit_ip_check.enabled));
+                println!("Interval: {}", interval);
+                println!("Reconnect: {}", on_off(exit_ip_check.reconnect));
+                return Ok(());
+            }
+            ("set", Some(matches)) => {
+                if let Some(enabled) = matches.value_of("enabled") {
+                    exit_ip_check.enabled = enabled == "on";
+                }
+                if let Some(interval) = matches.value_of("interval") {
+                    let interval = interval
+                        .parse::<HumanDuration>()
+                        .map_err(|_| Error::InvalidCommand("Invalid interval"))?;
+                    exit_ip_check.interval = Some(types::Duration::from(interval.as_duration()));
+                }
+                if let Some(reconnect) = matches.value_of("reconnect") {
+                    exit_ip_check.reconnect = reconnect == "on";
+                }
+            }
+            _ => unreachable!("unhandled command"),
+        }
+        rpc.set_exit_ip_check(exit_ip_check)
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to set the exit IP check", error))?;
+        println!("Exit IP check settings have been updated");
+        Ok(())
+    }
+
     async fn process_openvpn_mssfix_get() -> Result<()> {
         let tunnel_options = Self::get_tunnel_options().await?;
         let mssfix = tunnel_options.openvpn.unwrap().mssfix;
@@ -383,6 +1096,62 @@ impl Tunnel {
         Ok(())
     }
 
+    async fn process_ipv6_failure_get() -> Result<()> {
+        use mullvad_management_interface::types::ipv6_failure_policy::Policy;
+
+        let tunnel_options = Self::get_tunnel_options().await?;
+        let policy = tunnel_options
+            .generic
+            .unwrap()
+            .ipv6_failure_policy
+            .and_then(|policy| Policy::from_i32(policy.policy))
+            .unwrap_or(Policy::Ipv4Only);
+        match policy {
+            Policy::Ipv4Only => println!("IPv6 failure: ipv4-only"),
+            Policy::Block => println!("IPv6 failure: block"),
+        }
+        Ok(())
+    }
+
+    async fn process_ipv6_failure_set(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        use mullvad_management_interface::types::{ipv6_failure_policy::Policy, Ipv6FailurePolicy};
+
+        let policy = match matches.value_of("policy").unwrap() {
+            "ipv4-only" => Policy::Ipv4Only,
+            "block" => Policy::Block,
+            _ => unreachable!("invalid IPv6 failure policy"),
+        };
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_ipv6_failure_policy(Ipv6FailurePolicy {
+            policy: i32::from(policy),
+        })
+        .await?;
+        println!("Updated IPv6 failure setting");
+        Ok(())
+    }
+
+    async fn process_interface_name_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options().await?;
+        let name = tunnel_options.generic.unwrap().interface_name;
+        println!(
+            "Interface name: {}",
+            if name.is_empty() { "default" } else { &name }
+        );
+        Ok(())
+    }
+
+    async fn process_interface_name_set(name: String) -> Result<()> {
+        let unset = name.is_empty();
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_tunnel_interface_name(name).await?;
+        if unset {
+            println!("The default interface name will be used");
+        } else {
+            println!("Interface name has been updated");
+        }
+        Ok(())
+    }
+
     fn format_key_timestamp(timestamp: &Timestamp) -> String {
         let ndt = chrono::NaiveDateTime::from_timestamp(timestamp.seconds, timestamp.nanos as u32);
         let utc = chrono::DateTime::<chrono::Utc>::from_utc(ndt, chrono::Utc);
@@ -390,6 +1159,49 @@ impl Tunnel {
     }
 }
 
-fn duration_hours(duration: &Duration) -> u64 {
-    duration.as_secs() / 60 / 60
+/// Parses a rotation interval. Plain numbers are interpreted as hours for compatibility with
+/// earlier versions of the CLI.
+fn parse_rotation_interval(value: &str) -> std::result::Result<Duration, ParseError> {
+    match value.trim().parse::<u64>() {
+        Ok(hours) => hours
+            .checked_mul(60 * 60)
+            .map(Duration::from_secs)
+            .ok_or(ParseError::Overflow),
+        Err(_) => value.parse::<HumanDuration>().map(Duration::from),
+    }
+}
+
+fn rotation_interval_validator(value: String) -> std::result::Result<(), String> {
+    parse_rotation_interval(&value)
+        .map(|_| ())
+        .map_err(|error| error.to_string())
+}
+
+fn packet_loss_threshold_validator(value: String) -> std::result::Result<(), String> {
+    match value.parse::<u8>() {
+        Ok(threshold) if PACKET_LOSS_THRESHOLD_RANGE.contains(&threshold) => Ok(()),
+        _ => Err(format!(
+            "The threshold must be between {} and {} percent",
+            PACKET_LOSS_THRESHOLD_RANGE.start(),
+            PACKET_LOSS_THRESHOLD_RANGE.end()
+        )),
+    }
+}
+
+fn icmp_check_interval_validator(value: String) -> std::result::Result<(), String> {
+    match value.parse::<u16>() {
+        Ok(interval) if ICMP_CHECK_INTERVAL_RANGE.contains(&interval) => Ok(()),
+        _ => Err(format!(
+            "The interval must be between {} and {} seconds",
+            ICMP_CHECK_INTERVAL_RANGE.start(),
+            ICMP_CHECK_INTERVAL_RANGE.end()
+        )),
+    }
+}
+
+fn parse_backoff_delay(value: &str) -> Result<types::Duration> {
+    value
+        .parse::<HumanDuration>()
+        .map(|delay| types::Duration::from(delay.as_duration()))
+        .map_err(|_| Error::InvalidCommand("Invalid delay"))
 }
@@ -1,15 +1,151 @@
 use mullvad_management_interface::types::{
+    account_expiry_event::Kind as AccountExpiryEventKind,
+    conflicting_software::Kind as ConflictKind,
+    device_state::State as DeviceStateKind,
     error_state::{
         firewall_policy_error::ErrorType as FirewallPolicyErrorType, Cause as ErrorStateCause,
         FirewallPolicyError, GenerationError,
     },
+    exit_ip_mismatch::Problem as ExitIpProblem,
+    missing_capability::Kind as CapabilityKind,
+    obfuscation_endpoint::ObfuscationType,
     tunnel_state,
     tunnel_state::State::*,
-    ErrorState, KeygenEvent, ProxyType, TransportProtocol, TunnelEndpoint, TunnelState, TunnelType,
+    AccountExpiryEvent, ActiveApiAccessMethod, ConflictingSoftwareList, DeviceIdentityChange,
+    DeviceState, ErrorState, ExitIpMismatch, FeatureIndicators, KeygenEvent, MissingCapabilityList,
+    ProxyEndpoint, ProxyType, TransportProtocol, TunnelEndpoint, TunnelState, TunnelType,
 };
-use mullvad_types::auth_failed::AuthFailed;
+use mullvad_types::{auth_failed::AuthFailed, states::TUNNEL_STATE_VERSION};
 use std::fmt::Write;
 
+/// Prints a value as pretty-printed JSON, for the `--json` output mode.
+pub fn print_json<T: serde::Serialize>(value: &T) {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).expect("Failed to serialize value")
+    );
+}
+
+/// Prints a value as JSON on a single line, so that streamed values can be read line by line.
+pub fn print_json_line<T: serde::Serialize>(value: &T) {
+    println!(
+        "{}",
+        serde_json::to_string(value).expect("Failed to serialize value")
+    );
+}
+
+pub fn print_device_state(device_state: &DeviceState) {
+    match DeviceStateKind::from_i32(device_state.state) {
+        Some(DeviceStateKind::Active) => println!("This device is registered with the account"),
+        Some(DeviceStateKind::Revoked) => println!(
+            "This device has been removed from the account. Register it again using \
+             `mullvad account device reregister`"
+        ),
+        None => println!("Unknown device state"),
+    }
+}
+
+pub fn print_account_expiry_event(event: &AccountExpiryEvent) {
+    let expiry = event
+        .expiry
+        .as_ref()
+        .map(|expiry| {
+            let ndt = chrono::NaiveDateTime::from_timestamp(expiry.seconds, expiry.nanos as u32);
+            chrono::DateTime::<chrono::Utc>::from_utc(ndt, chrono::Utc)
+                .with_timezone(&chrono::Local)
+                .to_string()
+        })
+        .unwrap_or_else(|| "unknown".to_owned());
+    match AccountExpiryEventKind::from_i32(event.kind) {
+        Some(AccountExpiryEventKind::ExpiresSoon) => println!(
+            "The account has less than {} hours left and expires at {}",
+            event.threshold / (60 * 60),
+            expiry
+        ),
+        Some(AccountExpiryEventKind::Expired) => {
+            println!("The account ran out of time at {}", expiry)
+        }
+        None => println!("Unknown account expiry event"),
+    }
+}
+
+pub fn print_conflicting_software(list: &ConflictingSoftwareList) {
+    if list.conflicts.is_empty() {
+        println!("No other VPN software is detected");
+        return;
+    }
+    println!("Other VPN software is running, which may keep the tunnel from working:");
+    for conflict in &list.conflicts {
+        let kind = match ConflictKind::from_i32(conflict.kind) {
+            Some(ConflictKind::WfpProvider) => "WFP provider",
+            Some(ConflictKind::TunnelInterface) => "tunnel interface",
+            Some(ConflictKind::NetworkManagerVpn) => "NetworkManager VPN connection",
+            Some(ConflictKind::SystemVpn) => "system VPN service",
+            None => "unknown",
+        };
+        println!("\t{} ({})", conflict.name, kind);
+    }
+}
+
+pub fn print_missing_capabilities(list: &MissingCapabilityList) {
+    if list.capabilities.is_empty() {
+        println!("The daemon has every capability it needs");
+        return;
+    }
+    println!("The daemon is missing capabilities that it needs to connect:");
+    for capability in &list.capabilities {
+        let kind = match CapabilityKind::from_i32(capability.kind) {
+            Some(CapabilityKind::Privilege) => "privilege",
+            Some(CapabilityKind::Device) => "device",
+            Some(CapabilityKind::Firewall) => "firewall access",
+            Some(CapabilityKind::Driver) => "driver",
+            None => "unknown",
+        };
+        println!("\t{} ({}): {}", capability.name, kind, capability.reason);
+    }
+}
+
+pub fn print_device_identity_change(change: &DeviceIdentityChange) {
+    println!(
+        "This device was renamed from \"{}\" to \"{}\" and given a new WireGuard key",
+        change.previous_name, change.name
+    );
+}
+
+pub fn print_exit_ip_mismatch(mismatch: &ExitIpMismatch) {
+    let problem = match ExitIpProblem::from_i32(mismatch.problem) {
+        Some(ExitIpProblem::NotMullvad) => "which does not belong to a Mullvad relay".to_owned(),
+        Some(ExitIpProblem::OtherRelay) => format!("which belongs to {}", mismatch.exit_hostname),
+        Some(ExitIpProblem::Changed) => format!("which changed from {}", mismatch.previous_exit_ip),
+        None => "for an unknown reason".to_owned(),
+    };
+    println!(
+        "Traffic through {} exits from {}, {}",
+        mismatch.expected_hostname, mismatch.exit_ip, problem
+    );
+    if mismatch.reconnecting {
+        println!("Reconnecting");
+    }
+}
+
+pub fn print_api_access_method(method: &ActiveApiAccessMethod) {
+    println!("API access method: {}", format_api_access_method(method));
+}
+
+/// Describes how API traffic is sent, such as `mullvad-bridges via se-got-br-001 (1.2.3.4:443)`.
+pub fn format_api_access_method(method: &ActiveApiAccessMethod) -> String {
+    let mut description = method.name.clone();
+    if !method.bridge_hostname.is_empty() {
+        write!(&mut description, " via {}", method.bridge_hostname).unwrap();
+        if !method.endpoint.is_empty() {
+            write!(&mut description, " ({})", method.endpoint).unwrap();
+        }
+    } else if !method.endpoint.is_empty() {
+        write!(&mut description, " via {}", method.endpoint).unwrap();
+    }
+    description
+}
+
 pub fn print_keygen_event(key_event: &KeygenEvent) {
     use mullvad_management_interface::types::keygen_event::KeygenEvent as EventType;
 
@@ -31,9 +167,25 @@ pub fn print_keygen_event(key_event: &KeygenEvent) {
 
 pub fn print_state(state: &TunnelState) {
     print!("Tunnel status: ");
-    match state.state.as_ref().unwrap() {
-        Error(error) => print_error_state(error.error_state.as_ref().unwrap()),
-        Connected(tunnel_state::Connected { relay_info }) => {
+    let inner_state = match state.state.as_ref() {
+        Some(inner_state) => inner_state,
+        None => {
+            println!("Unknown");
+            if state.version > TUNNEL_STATE_VERSION {
+                println!("The daemon is newer than the CLI. Update the CLI to see the state");
+            }
+            return;
+        }
+    };
+    match inner_state {
+        Error(error) => match error.error_state.as_ref() {
+            Some(error_state) => print_error_state(error_state),
+            None => println!("Blocked: Unknown error"),
+        },
+        Connected(tunnel_state::Connected {
+            relay_info,
+            feature_indicators,
+        }) => {
             let endpoint = relay_info
                 .as_ref()
                 .unwrap()
@@ -41,21 +193,61 @@ pub fn print_state(state: &TunnelState) {
                 .as_ref()
                 .unwrap();
             println!("Connected to {}", format_endpoint(&endpoint));
+            if let Some(features) = feature_indicators
+                .as_ref()
+                .and_then(format_feature_indicators)
+            {
+                println!("Active features: {}", features);
+            }
         }
-        Connecting(tunnel_state::Connecting { relay_info }) => {
+        Connecting(tunnel_state::Connecting {
+            relay_info,
+            retry_at,
+        }) => {
             let endpoint = relay_info
                 .as_ref()
                 .unwrap()
                 .tunnel_endpoint
                 .as_ref()
                 .unwrap();
-            println!("Connecting to {}...", format_endpoint(&endpoint));
+            let retry_in = retry_at
+                .as_ref()
+                .map(|retry_at| retry_at.seconds - chrono::Utc::now().timestamp())
+                .filter(|seconds| *seconds > 0);
+            match retry_in {
+                Some(seconds) => println!(
+                    "Connecting to {}, retrying in {}s...",
+                    format_endpoint(&endpoint),
+                    seconds
+                ),
+                None => println!("Connecting to {}...", format_endpoint(&endpoint)),
+            }
         }
         Disconnected(_) => println!("Disconnected"),
         Disconnecting(_) => println!("Disconnecting..."),
     }
 }
 
+fn format_feature_indicators(indicators: &FeatureIndicators) -> Option<String> {
+    let features = [
+        (indicators.lockdown_mode, "lockdown mode"),
+        (indicators.split_tunneling, "split tunneling"),
+        (indicators.custom_dns, "custom DNS"),
+        (indicators.obfuscation, "obfuscation"),
+        (indicators.multihop, "multihop"),
+    ];
+    let active: Vec<&str> = features
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, name)| *name)
+        .collect();
+    if active.is_empty() {
+        None
+    } else {
+        Some(active.join(", "))
+    }
+}
+
 fn format_endpoint(endpoint: &TunnelEndpoint) -> String {
     let tunnel_type = TunnelType::from_i32(endpoint.tunnel_type).expect("invalid tunnel protocol");
     let mut out = format!(
@@ -73,20 +265,7 @@ fn format_endpoint(endpoint: &TunnelEndpoint) -> String {
     match tunnel_type {
         TunnelType::Openvpn => {
             if let Some(ref proxy) = endpoint.proxy {
-                write!(
-                    &mut out,
-                    " via {} {} over {}",
-                    match ProxyType::from_i32(proxy.proxy_type).expect("invalid proxy type") {
-                        ProxyType::Shadowsocks => "Shadowsocks",
-                        ProxyType::Custom => "custom bridge",
-                    },
-                    proxy.address,
-                    format_protocol(
-                        TransportProtocol::from_i32(proxy.protocol)
-                            .expect("invalid transport protocol")
-                    ),
-                )
-                .unwrap();
+                write!(&mut out, " via {}", format_proxy_endpoint(proxy)).unwrap();
             }
         }
         TunnelType::Wireguard => {
@@ -102,12 +281,48 @@ fn format_endpoint(endpoint: &TunnelEndpoint) -> String {
                 )
                 .unwrap();
             }
+            if let Some(ref obfuscation) = endpoint.obfuscation {
+                write!(
+                    &mut out,
+                    " via {} {} over {}",
+                    match ObfuscationType::from_i32(obfuscation.obfuscation_type)
+                        .expect("invalid obfuscation type")
+                    {
+                        ObfuscationType::Udp2tcp => "udp2tcp",
+                        ObfuscationType::Quic => "QUIC",
+                        ObfuscationType::Shadowsocks => "Shadowsocks",
+                    },
+                    obfuscation.address,
+                    format_protocol(
+                        TransportProtocol::from_i32(obfuscation.protocol)
+                            .expect("invalid transport protocol")
+                    ),
+                )
+                .unwrap();
+            }
+            if let Some(ref proxy) = endpoint.proxy {
+                write!(&mut out, " via {}", format_proxy_endpoint(proxy)).unwrap();
+            }
         }
     }
 
     out
 }
 
+fn format_proxy_endpoint(proxy: &ProxyEndpoint) -> String {
+    format!(
+        "{} {} over {}",
+        match ProxyType::from_i32(proxy.proxy_type).expect("invalid proxy type") {
+            ProxyType::Shadowsocks => "Shadowsocks",
+            ProxyType::Custom => "custom bridge",
+        },
+        proxy.address,
+        format_protocol(
+            TransportProtocol::from_i32(proxy.protocol).expect("invalid transport protocol")
+        ),
+    )
+}
+
 fn print_error_state(error_state: &ErrorState) {
     if error_state.blocking_error.is_some() {
         eprintln!("Mullvad daemon failed to setup firewall rules!");
@@ -133,8 +348,11 @@ fn print_error_state(error_state: &ErrorState) {
 fn error_state_to_string(error_state: &ErrorState) -> String {
     use ErrorStateCause::*;
 
-    let error_str = match ErrorStateCause::from_i32(error_state.cause).expect("unknown error cause")
-    {
+    let cause = match ErrorStateCause::from_i32(error_state.cause) {
+        Some(cause) => cause,
+        None => return "Unknown error".to_string(),
+    };
+    let error_str = match cause {
         AuthFailed => {
             return if error_state.auth_fail_reason.is_empty() {
                 "Authentication with remote server failed".to_string()
@@ -147,7 +365,10 @@ fn error_state_to_string(error_state: &ErrorState) -> String {
         }
         Ipv6Unavailable => "Failed to configure IPv6 because it's disabled in the platform",
         SetFirewallPolicyError => {
-            return policy_error_to_string(error_state.policy_error.as_ref().unwrap())
+            return match error_state.policy_error.as_ref() {
+                Some(policy_error) => policy_error_to_string(policy_error),
+                None => "Failed to set firewall policy".to_string(),
+            };
         }
         SetDnsError => "Failed to set system DNS server",
         StartTunnelError => "Failed to start connection to remote server",
@@ -162,33 +383,35 @@ fn error_state_to_string(error_state: &ErrorState) -> String {
         VpnPermissionDenied => "The Android VPN permission was denied when creating the tunnel",
         #[cfg(target_os = "windows")]
         SplitTunnelError => "The split tunneling module reported an error",
-        #[cfg(not(target_os = "android"))]
-        _ => unreachable!("unknown error cause"),
+        CaptivePortalLogin => "Waiting for the captive portal login to complete",
+        _ => "Unknown error",
     };
 
     error_str.to_string()
 }
 
 fn tunnel_parameter_error_to_string(parameter_error: i32) -> &'static str {
-    match GenerationError::from_i32(parameter_error).expect("unknown generation error") {
-        GenerationError::NoMatchingRelay => "Failure to select a matching tunnel relay",
-        GenerationError::NoMatchingBridgeRelay => "Failure to select a matching bridge relay",
-        GenerationError::NoWireguardKey => "No wireguard key available",
-        GenerationError::CustomTunnelHostResolutionError => {
+    match GenerationError::from_i32(parameter_error) {
+        Some(GenerationError::NoMatchingRelay) => "Failure to select a matching tunnel relay",
+        Some(GenerationError::NoMatchingBridgeRelay) => "Failure to select a matching bridge relay",
+        Some(GenerationError::NoWireguardKey) => "No wireguard key available",
+        Some(GenerationError::CustomTunnelHostResolutionError) => {
             "Can't resolve hostname for custom tunnel host"
         }
+        Some(GenerationError::UnknownGenerationError) | None => "Unknown error",
     }
 }
 
 fn policy_error_to_string(policy_error: &FirewallPolicyError) -> String {
-    let cause = match FirewallPolicyErrorType::from_i32(policy_error.r#type)
-        .expect("unknown policy error")
-    {
-        FirewallPolicyErrorType::Generic => return "Failed to set firewall policy".to_string(),
-        FirewallPolicyErrorType::Locked => format!(
+    let cause = match FirewallPolicyErrorType::from_i32(policy_error.r#type) {
+        Some(FirewallPolicyErrorType::Locked) => format!(
             "An application prevented the firewall policy from being set: {} (pid {})",
             policy_error.lock_name, policy_error.lock_pid
         ),
+        Some(FirewallPolicyErrorType::Generic) => {
+            return "Failed to set firewall policy".to_string()
+        }
+        Some(FirewallPolicyErrorType::Unknown) | None => "Unknown error".to_string(),
     };
     format!("Failed to set firewall policy: {}", cause)
 }
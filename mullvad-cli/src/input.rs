@@ -0,0 +1,76 @@
+use crate::{Error, Result};
+use std::{
+    fs,
+    io::{self, Read},
+};
+
+/// Argument value which denotes that input should be read from stdin rather than from a file.
+pub const STDIN_PATH: &str = "-";
+
+/// Reads the contents of the file at `path`, or of stdin if `path` is `-`.
+pub fn read_to_string(path: &str) -> Result<String> {
+    if path == STDIN_PATH {
+        let mut contents = String::new();
+        io::stdin()
+            .read_to_string(&mut contents)
+            .map_err(|error| Error::ReadInputError("stdin".to_owned(), error))?;
+        Ok(contents)
+    } else {
+        fs::read_to_string(path).map_err(|error| Error::ReadInputError(path.to_owned(), error))
+    }
+}
+
+/// Reads a newline-separated list of entries from the file at `path`, or from stdin if `path` is
+/// `-`. Leading and trailing whitespace is trimmed from each entry. Empty lines and lines starting
+/// with `#` are ignored, and only the first occurrence of an entry is kept.
+#[cfg(any(windows, test))]
+pub fn read_entries(path: &str) -> Result<Vec<String>> {
+    Ok(parse_entries(&read_to_string(path)?))
+}
+
+#[cfg(any(windows, test))]
+fn parse_entries(contents: &str) -> Vec<String> {
+    let mut entries: Vec<String> = Vec::new();
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || entries.iter().any(|entry| entry == line) {
+            continue;
+        }
+        entries.push(line.to_owned());
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_entries() {
+        let cases: &[(&str, &[&str])] = &[
+            ("", &[]),
+            ("\n  \n\t\n", &[]),
+            ("# comment\n  # indented comment\n", &[]),
+            ("C:\\a.exe\r\nC:\\b.exe", &["C:\\a.exe", "C:\\b.exe"]),
+            ("  C:\\a.exe  \n\n# C:\\b.exe\n", &["C:\\a.exe"]),
+            (
+                "C:\\a.exe\nC:\\b.exe\n C:\\a.exe\n",
+                &["C:\\a.exe", "C:\\b.exe"],
+            ),
+            ("C:\\my #1 app.exe", &["C:\\my #1 app.exe"]),
+        ];
+        for (contents, expected) in cases {
+            assert_eq!(parse_entries(contents), *expected, "input: {:?}", contents);
+        }
+    }
+
+    #[test]
+    fn test_read_entries_invalid_path() {
+        let path = std::env::temp_dir().join("mullvad-cli-input-test-does-not-exist");
+        let path = path.to_str().unwrap();
+        match read_entries(path) {
+            Err(Error::ReadInputError(error_path, _)) => assert_eq!(error_path, path),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        assert!(read_entries("").is_err());
+    }
+}
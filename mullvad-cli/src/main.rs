@@ -9,6 +9,7 @@ pub use mullvad_management_interface::{self, new_rpc_client};
 
 mod cmds;
 mod format;
+mod input;
 mod location;
 mod state;
 
@@ -43,6 +44,15 @@ pub enum Error {
 
     #[error(display = "Failed to listen for status updates")]
     StatusListenerFailed,
+
+    #[error(display = "Failed to read input from {}", _0)]
+    ReadInputError(String, #[error(source)] io::Error),
+
+    #[error(display = "Failed to run {}", _0)]
+    RunCommandError(String, #[error(source)] io::Error),
+
+    #[error(display = "Failed to import the OpenVPN profile")]
+    InvalidOpenVpnProfile(#[error(source)] mullvad_types::openvpn_profile::Error),
 }
 
 #[tokio::main]
@@ -126,6 +136,12 @@ fn build_cli(commands: &HashMap<&'static str, Box<dyn Command>>) -> clap::App<'s
             clap::AppSettings::DisableHelpSubcommand,
             clap::AppSettings::VersionlessSubcommands,
         ])
+        .arg(
+            clap::Arg::with_name("json")
+                .long("json")
+                .global(true)
+                .help("Print machine-readable JSON instead of text, where supported"),
+        )
         .subcommands(commands.values().map(|cmd| cmd.clap_subcommand()))
 }
 
@@ -1,11 +1,14 @@
-use crate::{Error, Result};
+use crate::{format, Error, Result};
 use futures::{
     channel::{mpsc, mpsc::Receiver},
-    SinkExt,
+    SinkExt, StreamExt,
 };
 use mullvad_management_interface::{
-    types::{daemon_event::Event as EventType, TunnelState},
-    ManagementServiceClient,
+    types::{
+        daemon_event::Event as EventType, tunnel_state::State, ConnectionLease,
+        EventsListenRequest, TunnelState,
+    },
+    ManagementServiceClient, Streaming,
 };
 
 // Spawns a new task that listens for tunnel state changes and forwards it through the returned
@@ -13,7 +16,7 @@ use mullvad_management_interface::{
 pub fn state_listen(mut rpc: ManagementServiceClient) -> Receiver<Result<TunnelState>> {
     let (mut sender, receiver) = mpsc::channel::<Result<TunnelState>>(1);
     tokio::spawn(async move {
-        match rpc.events_listen(()).await {
+        match rpc.events_listen(EventsListenRequest::default()).await {
             Ok(events) => {
                 let mut events = events.into_inner();
                 loop {
@@ -39,3 +42,33 @@ pub fn state_listen(mut rpc: ManagementServiceClient) -> Receiver<Result<TunnelS
 
     receiver
 }
+
+/// Acquires a connection lease and waits until the tunnel is connected. The tunnel is kept secured
+/// for as long as the returned stream is open. The daemon restores the previous tunnel state once
+/// the stream is dropped, which also happens when the process exits.
+pub async fn hold_connection(
+    mut rpc: ManagementServiceClient,
+) -> Result<Streaming<ConnectionLease>> {
+    let mut receiver = state_listen(rpc.clone());
+    let mut lease = rpc.acquire_connection_lease(()).await?.into_inner();
+    if lease.message().await?.is_none() {
+        return Err(Error::CommandFailed("acquire connection lease"));
+    }
+
+    let state = rpc.get_tunnel_state(()).await?.into_inner();
+    if let Some(State::Connected(_)) = state.state {
+        return Ok(lease);
+    }
+    while let Some(state) = receiver.next().await {
+        let state = state?;
+        match state.state {
+            Some(State::Connected(_)) => return Ok(lease),
+            Some(State::Error(_)) => {
+                format::print_state(&state);
+                return Err(Error::CommandFailed("connect"));
+            }
+            _ => {}
+        }
+    }
+    Err(Error::StatusListenerFailed)
+}
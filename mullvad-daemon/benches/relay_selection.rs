@@ -0,0 +1,148 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use mullvad_daemon::relays::RelaySelector;
+use mullvad_types::{
+    relay_constraints::{BridgeState, Constraint, LocationConstraint, RelayConstraints},
+    relay_list::{
+        OpenVpnEndpointData, Relay, RelayBridges, RelayList, RelayListCity, RelayListCountry,
+        RelayMetadata, RelayTunnels, ShadowsocksEndpointData, WireguardEndpointData,
+    },
+};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use talpid_types::net::{wireguard::PublicKey, TransportProtocol};
+
+const COUNTRIES: usize = 40;
+const CITIES_PER_COUNTRY: usize = 3;
+const RELAYS_PER_CITY: usize = 8;
+
+/// Returns a relay list about the size of the one served by the API.
+fn full_relay_list() -> RelayList {
+    let mut relay_id = 0u32;
+    let countries = (0..COUNTRIES)
+        .map(|country| RelayListCountry {
+            name: format!("Country {}", country),
+            code: format!("c{}", country),
+            cities: (0..CITIES_PER_COUNTRY)
+                .map(|city| RelayListCity {
+                    name: format!("City {}", city),
+                    code: format!("city{}", city),
+                    latitude: country as f64,
+                    longitude: city as f64,
+                    relays: (0..RELAYS_PER_CITY)
+                        .map(|_| {
+                            relay_id += 1;
+                            relay(country, relay_id)
+                        })
+                        .collect(),
+                })
+                .collect(),
+        })
+        .collect();
+    RelayList {
+        etag: None,
+        countries,
+    }
+}
+
+fn relay(country: usize, id: u32) -> Relay {
+    let is_wireguard = id % 2 == 0;
+    let mut public_key = [0u8; 32];
+    public_key[..4].copy_from_slice(&id.to_le_bytes());
+    Relay {
+        hostname: format!("c{}-{:04}", country, id),
+        ipv4_addr_in: Ipv4Addr::from(0x0a00_0000 + id),
+        ipv6_addr_in: Some(Ipv6Addr::new(0xfc00, 0, 0, 0, 0, 0, 0, id as u16)),
+        include_in_country: true,
+        active: id % 20 != 0,
+        owned: id % 3 == 0,
+        provider: format!("provider{}", id % 10),
+        weight: 100,
+        tunnels: RelayTunnels {
+            openvpn: if is_wireguard {
+                vec![]
+            } else {
+                vec![
+                    OpenVpnEndpointData {
+                        port: 1194,
+                        protocol: TransportProtocol::Udp,
+                    },
+                    OpenVpnEndpointData {
+                        port: 443,
+                        protocol: TransportProtocol::Tcp,
+                    },
+                ]
+            },
+            wireguard: if is_wireguard {
+                vec![WireguardEndpointData {
+                    port_ranges: vec![(53, 53), (4000, 33433), (33565, 51820), (52000, 60000)],
+                    ipv4_gateway: "10.64.0.1".parse().unwrap(),
+                    ipv6_gateway: "fc00:bbbb:bbbb:bb01::1".parse().unwrap(),
+                    public_key: PublicKey::from(public_key),
+                    shadowsocks: vec![],
+                    protocol: TransportProtocol::Udp,
+                }]
+            } else {
+                vec![]
+            },
+        },
+        bridges: RelayBridges {
+            shadowsocks: if is_wireguard {
+                vec![]
+            } else {
+                vec![ShadowsocksEndpointData {
+                    port: 443,
+                    cipher: "aes-256-gcm".to_string(),
+                    password: "mullvad".to_string(),
+                    protocol: TransportProtocol::Tcp,
+                }]
+            },
+        },
+        location: None,
+        metadata: RelayMetadata::default(),
+    }
+}
+
+fn relay_selection(c: &mut Criterion) {
+    let mut relay_selector = RelaySelector::from_relay_list(full_relay_list());
+
+    let locations = [
+        ("any", Constraint::Any),
+        (
+            "country",
+            Constraint::Only(LocationConstraint::Country("c7".to_string())),
+        ),
+        (
+            "city",
+            Constraint::Only(LocationConstraint::City(
+                "c7".to_string(),
+                "city1".to_string(),
+            )),
+        ),
+        (
+            "hostname",
+            Constraint::Only(LocationConstraint::Hostname(
+                "c7".to_string(),
+                "city1".to_string(),
+                "c7-0178".to_string(),
+            )),
+        ),
+    ];
+
+    let mut group = c.benchmark_group("get_tunnel_endpoint");
+    for (name, location) in locations.iter() {
+        let constraints = RelayConstraints {
+            location: location.clone(),
+            ..RelayConstraints::default()
+        };
+        group.bench_function(*name, |b| {
+            b.iter(|| {
+                relay_selector
+                    .get_tunnel_endpoint(&constraints, BridgeState::Off, 0, true)
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, relay_selection);
+criterion_main!(benches);
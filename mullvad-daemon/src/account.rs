@@ -2,11 +2,19 @@ use chrono::{DateTime, Utc};
 use futures::future::{abortable, AbortHandle};
 use mullvad_rpc::{
     availability::ApiAvailabilityHandle,
-    rest::{self, Error as RestError, MullvadRestHandle},
+    rest::{self, ApiErrorCode, Error as RestError, MullvadRestHandle},
     AccountsProxy,
 };
-use mullvad_types::account::{AccountToken, VoucherSubmission};
-use std::{future::Future, time::Duration};
+use mullvad_types::{
+    account::{AccountToken, VoucherSubmission},
+    device::{Device, DeviceId},
+    port_forward::{PortForward, PortForwardId},
+};
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 use talpid_core::future_retry::{
     constant_interval, retry_future, retry_future_n, ExponentialBackoff, Jittered,
 };
@@ -18,6 +26,8 @@ const RETRY_EXPIRY_CHECK_INTERVAL_INITIAL: Duration = Duration::from_secs(4);
 const RETRY_EXPIRY_CHECK_INTERVAL_FACTOR: u32 = 5;
 const RETRY_EXPIRY_CHECK_INTERVAL_MAX: Duration = Duration::from_secs(24 * 60 * 60);
 
+/// How long to hold off on account requests after being rate limited, if the API did not say.
+const RATE_LIMIT_DEFAULT_COOLDOWN: Duration = Duration::from_secs(60);
 
 pub struct Account(());
 
@@ -26,18 +36,19 @@ pub struct AccountHandle {
     api_availability: ApiAvailabilityHandle,
     initial_check_abort_handle: AbortHandle,
     proxy: AccountsProxy,
+    rate_limit: RateLimit,
 }
 
 impl AccountHandle {
     pub fn create_account(&self) -> impl Future<Output = Result<AccountToken, rest::Error>> {
         let mut proxy = self.proxy.clone();
         let api_handle = self.api_availability.clone();
-        retry_future_n(
+        self.rate_limit.guard(retry_future_n(
             move || proxy.create_account(),
             move |result| Self::should_retry(result, &api_handle),
             constant_interval(RETRY_ACTION_INTERVAL),
             RETRY_ACTION_MAX_RETRIES,
-        )
+        ))
     }
 
     pub fn get_www_auth_token(
@@ -46,24 +57,124 @@ impl AccountHandle {
     ) -> impl Future<Output = Result<String, rest::Error>> {
         let proxy = self.proxy.clone();
         let api_handle = self.api_availability.clone();
-        retry_future_n(
+        self.rate_limit.guard(retry_future_n(
             move || proxy.get_www_auth_token(account.clone()),
             move |result| Self::should_retry(result, &api_handle),
             constant_interval(RETRY_ACTION_INTERVAL),
             RETRY_ACTION_MAX_RETRIES,
-        )
+        ))
     }
 
-    pub async fn check_expiry(&self, token: AccountToken) -> Result<DateTime<Utc>, rest::Error> {
+    pub fn list_devices(
+        &self,
+        account: AccountToken,
+    ) -> impl Future<Output = Result<Vec<Device>, rest::Error>> {
         let proxy = self.proxy.clone();
         let api_handle = self.api_availability.clone();
-        let result = retry_future_n(
-            move || proxy.get_expiry(token.clone()),
+        self.rate_limit.guard(retry_future_n(
+            move || proxy.list_devices(account.clone()),
             move |result| Self::should_retry(result, &api_handle),
             constant_interval(RETRY_ACTION_INTERVAL),
             RETRY_ACTION_MAX_RETRIES,
-        )
-        .await;
+        ))
+    }
+
+    pub fn rename_device(
+        &self,
+        account: AccountToken,
+        id: DeviceId,
+        name: String,
+    ) -> impl Future<Output = Result<(), rest::Error>> {
+        let proxy = self.proxy.clone();
+        let api_handle = self.api_availability.clone();
+        self.rate_limit.guard(retry_future_n(
+            move || proxy.rename_device(account.clone(), id.clone(), name.clone()),
+            move |result| Self::should_retry(result, &api_handle),
+            constant_interval(RETRY_ACTION_INTERVAL),
+            RETRY_ACTION_MAX_RETRIES,
+        ))
+    }
+
+    pub fn remove_device(
+        &self,
+        account: AccountToken,
+        id: DeviceId,
+    ) -> impl Future<Output = Result<(), rest::Error>> {
+        let proxy = self.proxy.clone();
+        let api_handle = self.api_availability.clone();
+        self.rate_limit.guard(retry_future_n(
+            move || proxy.remove_device(account.clone(), id.clone()),
+            move |result| Self::should_retry(result, &api_handle),
+            constant_interval(RETRY_ACTION_INTERVAL),
+            RETRY_ACTION_MAX_RETRIES,
+        ))
+    }
+
+    pub fn list_port_forwards(
+        &self,
+        account: AccountToken,
+    ) -> impl Future<Output = Result<Vec<PortForward>, rest::Error>> {
+        let proxy = self.proxy.clone();
+        let api_handle = self.api_availability.clone();
+        self.rate_limit.guard(retry_future_n(
+            move || proxy.list_port_forwards(account.clone()),
+            move |result| Self::should_retry(result, &api_handle),
+            constant_interval(RETRY_ACTION_INTERVAL),
+            RETRY_ACTION_MAX_RETRIES,
+        ))
+    }
+
+    pub fn add_port_forward(
+        &self,
+        account: AccountToken,
+        pubkey: talpid_types::net::wireguard::PublicKey,
+        country_code: String,
+        city_code: String,
+    ) -> impl Future<Output = Result<PortForward, rest::Error>> {
+        let proxy = self.proxy.clone();
+        let api_handle = self.api_availability.clone();
+        self.rate_limit.guard(retry_future_n(
+            move || {
+                proxy.add_port_forward(
+                    account.clone(),
+                    pubkey.clone(),
+                    country_code.clone(),
+                    city_code.clone(),
+                )
+            },
+            move |result| Self::should_retry(result, &api_handle),
+            constant_interval(RETRY_ACTION_INTERVAL),
+            RETRY_ACTION_MAX_RETRIES,
+        ))
+    }
+
+    pub fn remove_port_forward(
+        &self,
+        account: AccountToken,
+        id: PortForwardId,
+    ) -> impl Future<Output = Result<(), rest::Error>> {
+        let proxy = self.proxy.clone();
+        let api_handle = self.api_availability.clone();
+        self.rate_limit.guard(retry_future_n(
+            move || proxy.remove_port_forward(account.clone(), id.clone()),
+            move |result| Self::should_retry(result, &api_handle),
+            constant_interval(RETRY_ACTION_INTERVAL),
+            RETRY_ACTION_MAX_RETRIES,
+        ))
+    }
+
+    pub async fn check_expiry(&self, token: AccountToken) -> Result<DateTime<Utc>, rest::Error> {
+        let proxy = self.proxy.clone();
+        let api_handle = self.api_availability.clone();
+        let result = self
+            .rate_limit
+            .guard(retry_future_n(
+                move || proxy.get_expiry(token.clone()),
+                move |result| Self::should_retry(result, &api_handle),
+                constant_interval(RETRY_ACTION_INTERVAL),
+                RETRY_ACTION_MAX_RETRIES,
+            ))
+            .await;
         if handle_expiry_result_inner(&result, &self.api_availability) {
             self.initial_check_abort_handle.abort();
         }
@@ -77,13 +188,15 @@ impl AccountHandle {
     ) -> Result<VoucherSubmission, rest::Error> {
         let mut proxy = self.proxy.clone();
         let api_handle = self.api_availability.clone();
-        let result = retry_future_n(
-            move || proxy.submit_voucher(account_token.clone(), voucher.clone()),
-            move |result| Self::should_retry(result, &api_handle),
-            constant_interval(RETRY_ACTION_INTERVAL),
-            RETRY_ACTION_MAX_RETRIES,
-        )
-        .await;
+        let result = self
+            .rate_limit
+            .guard(retry_future_n(
+                move || proxy.submit_voucher(account_token.clone(), voucher.clone()),
+                move |result| Self::should_retry(result, &api_handle),
+                constant_interval(RETRY_ACTION_INTERVAL),
+                RETRY_ACTION_MAX_RETRIES,
+            ))
+            .await;
         if result.is_ok() {
             self.initial_check_abort_handle.abort();
             self.api_availability.resume();
@@ -99,6 +212,48 @@ impl AccountHandle {
     }
 }
 
+/// Local cooldown that is entered when the API rate limits account requests. Requests made during
+/// the cooldown fail immediately instead of being sent, so that the API is not hammered by
+/// clients retrying.
+#[derive(Clone, Default)]
+struct RateLimit {
+    blocked_until: Arc<Mutex<Option<Instant>>>,
+}
+
+impl RateLimit {
+    fn guard<T>(
+        &self,
+        request: impl Future<Output = Result<T, RestError>>,
+    ) -> impl Future<Output = Result<T, RestError>> {
+        let blocked_until = self.blocked_until.clone();
+        async move {
+            if let Some(remaining) = Self::remaining(&blocked_until) {
+                return Err(RestError::RateLimited(Some(remaining)));
+            }
+            let result = request.await;
+            if let Err(RestError::RateLimited(retry_after)) = &result {
+                let cooldown = retry_after.unwrap_or(RATE_LIMIT_DEFAULT_COOLDOWN);
+                log::warn!(
+                    "Account requests are rate limited. Not retrying for {} seconds",
+                    cooldown.as_secs()
+                );
+                *blocked_until.lock().unwrap() = Some(Instant::now() + cooldown);
+                return Err(RestError::RateLimited(Some(cooldown)));
+            }
+            result
+        }
+    }
+
+    fn remaining(blocked_until: &Mutex<Option<Instant>>) -> Option<Duration> {
+        let mut blocked_until = blocked_until.lock().unwrap();
+        let remaining = blocked_until?.checked_duration_since(Instant::now());
+        if remaining.is_none() {
+            *blocked_until = None;
+        }
+        remaining
+    }
+}
+
 impl Account {
     pub fn new(
         runtime: tokio::runtime::Handle,
@@ -145,6 +300,7 @@ impl Account {
             api_availability: api_availability_copy,
             initial_check_abort_handle,
             proxy: accounts_proxy_copy,
+            rate_limit: RateLimit::default(),
         }
     }
 }
@@ -162,13 +318,13 @@ fn handle_expiry_result_inner(
             api_availability.pause();
             true
         }
-        Err(mullvad_rpc::rest::Error::ApiError(_status, code)) => {
-            if code == mullvad_rpc::INVALID_ACCOUNT || code == mullvad_rpc::INVALID_AUTH {
+        Err(mullvad_rpc::rest::Error::ApiError(_status, code)) => match code {
+            ApiErrorCode::InvalidAccount | ApiErrorCode::InvalidAuth => {
                 api_availability.pause();
-                return true;
+                true
             }
-            false
-        }
+            _ => false,
+        },
         Err(_) => false,
     }
 }
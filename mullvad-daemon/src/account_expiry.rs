@@ -0,0 +1,296 @@
+//! Keeps track of when the current account runs out of time, and notifies the daemon when the
+//! remaining time drops below the configured warning thresholds and when the account has expired.
+use crate::{account::AccountHandle, DaemonEventSender};
+use chrono::{DateTime, Utc};
+use futures::{
+    channel::{mpsc, oneshot},
+    future::{BoxFuture, Fuse, FusedFuture},
+    FutureExt, StreamExt,
+};
+use mullvad_types::account::{AccountExpiryEvent, AccountToken};
+use std::time::Duration;
+use talpid_core::mpsc::Sender;
+use talpid_types::ErrorExt;
+
+/// How often the expiry is fetched from the API, so that time added elsewhere is noticed.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+/// How long to wait before fetching the expiry again after a failed attempt.
+const REFRESH_INTERVAL_ERROR: Duration = Duration::from_secs(15 * 60);
+
+type RefreshResult = (
+    AccountToken,
+    Result<DateTime<Utc>, mullvad_rpc::rest::Error>,
+);
+
+enum ExpiryMonitorCommand {
+    SetAccount(Option<AccountToken>),
+    UpdateExpiry(AccountToken, DateTime<Utc>),
+    SetWarningThresholds(Vec<u64>),
+    GetExpiry(oneshot::Sender<Option<DateTime<Utc>>>),
+}
+
+#[derive(Clone)]
+pub(crate) struct ExpiryMonitorHandle {
+    tx: mpsc::UnboundedSender<ExpiryMonitorCommand>,
+}
+
+impl ExpiryMonitorHandle {
+    /// Starts monitoring a different account, or stops monitoring if `None`.
+    pub fn set_account(&self, account_token: Option<AccountToken>) {
+        self.send(ExpiryMonitorCommand::SetAccount(account_token));
+    }
+
+    /// Uses an expiry obtained elsewhere, such as in response to redeeming a voucher.
+    pub fn update_expiry(&self, account_token: AccountToken, expiry: DateTime<Utc>) {
+        self.send(ExpiryMonitorCommand::UpdateExpiry(account_token, expiry));
+    }
+
+    pub fn set_warning_thresholds(&self, thresholds: Vec<u64>) {
+        self.send(ExpiryMonitorCommand::SetWarningThresholds(thresholds));
+    }
+
+    /// Returns the expiry of the current account, if it is known.
+    pub async fn expiry(&self) -> Option<DateTime<Utc>> {
+        let (tx, rx) = oneshot::channel();
+        self.send(ExpiryMonitorCommand::GetExpiry(tx));
+        rx.await.ok().flatten()
+    }
+
+    fn send(&self, command: ExpiryMonitorCommand) {
+        if self.tx.unbounded_send(command).is_err() {
+            log::error!("Account expiry monitor is down");
+        }
+    }
+}
+
+pub(crate) struct ExpiryMonitor {
+    account: AccountHandle,
+    /// The remaining time is compared against the thresholds on every scheduler tick. The wall
+    /// clock is used rather than sleeping until the next threshold, since a sleeping timer does
+    /// not advance while the computer is suspended.
+    ticks: Option<mpsc::UnboundedReceiver<()>>,
+    event_tx: DaemonEventSender<AccountExpiryEvent>,
+    rx: Option<mpsc::UnboundedReceiver<ExpiryMonitorCommand>>,
+    account_token: Option<AccountToken>,
+    expiry: Option<DateTime<Utc>>,
+    /// When the expiry was last fetched or updated, or a fetch last failed.
+    last_check: Option<DateTime<Utc>>,
+    next_refresh: DateTime<Utc>,
+    /// Sorted in descending order.
+    warning_thresholds: Vec<u64>,
+    /// The smallest threshold that has been reported for the current expiry.
+    last_warning: Option<u64>,
+    expired_reported: bool,
+}
+
+impl ExpiryMonitor {
+    pub fn new(
+        account: AccountHandle,
+        ticks: mpsc::UnboundedReceiver<()>,
+        event_tx: DaemonEventSender<AccountExpiryEvent>,
+        account_token: Option<AccountToken>,
+        warning_thresholds: Vec<u64>,
+    ) -> (Self, ExpiryMonitorHandle) {
+        let (tx, rx) = mpsc::unbounded();
+        (
+            Self {
+                account,
+                ticks: Some(ticks),
+                event_tx,
+                rx: Some(rx),
+                account_token,
+                expiry: None,
+                last_check: None,
+                next_refresh: Utc::now(),
+                warning_thresholds: normalize_thresholds(warning_thresholds),
+                last_warning: None,
+                expired_reported: false,
+            },
+            ExpiryMonitorHandle { tx },
+        )
+    }
+
+    pub async fn run(mut self) {
+        let mut rx = self.rx.take().unwrap();
+        let mut ticks = self.ticks.take().unwrap();
+        let mut refresh: Fuse<BoxFuture<'static, RefreshResult>> = Fuse::terminated();
+
+        loop {
+            if refresh.is_terminated() && self.should_refresh() {
+                if let Some(account_token) = self.account_token.clone() {
+                    let account = self.account.clone();
+                    refresh = async move {
+                        let result = account.check_expiry(account_token.clone()).await;
+                        (account_token, result)
+                    }
+                    .boxed()
+                    .fuse();
+                }
+            }
+            self.check_expiry();
+
+            futures::select! {
+                _tick = ticks.next() => {}
+
+                (account_token, result) = refresh => {
+                    self.handle_refresh_result(account_token, result);
+                }
+
+                command = rx.next() => match command {
+                    Some(ExpiryMonitorCommand::SetAccount(account_token)) => {
+                        if account_token != self.account_token {
+                            self.account_token = account_token;
+                            self.reset();
+                            refresh = Fuse::terminated();
+                        }
+                    }
+                    Some(ExpiryMonitorCommand::UpdateExpiry(account_token, expiry)) => {
+                        if Some(&account_token) == self.account_token.as_ref() {
+                            self.set_expiry(expiry);
+                        }
+                    }
+                    Some(ExpiryMonitorCommand::SetWarningThresholds(thresholds)) => {
+                        self.warning_thresholds = normalize_thresholds(thresholds);
+                    }
+                    Some(ExpiryMonitorCommand::GetExpiry(tx)) => {
+                        let _ = tx.send(self.expiry);
+                    }
+                    None => {
+                        log::debug!("Account expiry monitor shutting down");
+                        return;
+                    }
+                },
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.expiry = None;
+        self.last_check = None;
+        self.next_refresh = Utc::now();
+        self.last_warning = None;
+        self.expired_reported = false;
+    }
+
+    fn handle_refresh_result(
+        &mut self,
+        account_token: AccountToken,
+        result: Result<DateTime<Utc>, mullvad_rpc::rest::Error>,
+    ) {
+        if Some(&account_token) != self.account_token.as_ref() {
+            return;
+        }
+        match result {
+            Ok(expiry) => self.set_expiry(expiry),
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to fetch the account expiry")
+                );
+                let now = Utc::now();
+                if self.expiry.is_some() {
+                    self.last_check = Some(now);
+                }
+                self.next_refresh = now + to_chrono(REFRESH_INTERVAL_ERROR);
+            }
+        }
+    }
+
+    fn set_expiry(&mut self, expiry: DateTime<Utc>) {
+        let now = Utc::now();
+        if self
+            .expiry
+            .map(|old_expiry| expiry > old_expiry)
+            .unwrap_or(false)
+        {
+            // Time was added, so the warnings apply anew
+            self.last_warning = None;
+            self.expired_reported = false;
+        }
+        self.expiry = Some(expiry);
+        self.last_check = Some(now);
+        self.next_refresh = now + to_chrono(REFRESH_INTERVAL);
+    }
+
+    fn should_refresh(&self) -> bool {
+        let now = Utc::now();
+        if now >= self.next_refresh {
+            return true;
+        }
+        // Confirm that the account has expired before acting on it, in case time was added since
+        // the expiry was last fetched.
+        match (self.expiry, self.last_check) {
+            (Some(expiry), Some(last_check)) => {
+                !self.expired_reported && expiry <= now && last_check < expiry
+            }
+            _ => false,
+        }
+    }
+
+    fn check_expiry(&mut self) {
+        let (expiry, last_check) = match (self.expiry, self.last_check) {
+            (Some(expiry), Some(last_check)) => (expiry, last_check),
+            _ => return,
+        };
+        let now = Utc::now();
+
+        if expiry <= now {
+            if !self.expired_reported && last_check >= expiry {
+                self.expired_reported = true;
+                self.last_warning = self.warning_thresholds.last().cloned();
+                self.send(AccountExpiryEvent::Expired { expiry });
+            }
+            return;
+        }
+
+        let remaining = (expiry - now).num_seconds().max(0) as u64;
+        let crossed_threshold = self
+            .warning_thresholds
+            .iter()
+            .rev()
+            .find(|threshold| remaining < **threshold)
+            .cloned();
+        if let Some(threshold) = crossed_threshold {
+            if self
+                .last_warning
+                .map(|last_warning| threshold < last_warning)
+                .unwrap_or(true)
+            {
+                self.last_warning = Some(threshold);
+                self.send(AccountExpiryEvent::ExpiresSoon { expiry, threshold });
+            }
+        }
+    }
+
+    fn send(&self, event: AccountExpiryEvent) {
+        if self.event_tx.send(event).is_err() {
+            log::error!("Failed to send account expiry event to the daemon");
+        }
+    }
+}
+
+/// Sorts the thresholds in descending order and removes duplicates and zeroes.
+pub(crate) fn normalize_thresholds(mut thresholds: Vec<u64>) -> Vec<u64> {
+    thresholds.retain(|threshold| *threshold > 0);
+    thresholds.sort_unstable_by(|a, b| b.cmp(a));
+    thresholds.dedup();
+    thresholds
+}
+
+fn to_chrono(duration: Duration) -> chrono::Duration {
+    chrono::Duration::from_std(duration).unwrap_or_else(|_| chrono::Duration::max_value())
+}
+
+#[cfg(test)]
+mod test {
+    use super::normalize_thresholds;
+
+    #[test]
+    fn test_normalize_thresholds() {
+        assert_eq!(
+            normalize_thresholds(vec![3600, 0, 259200, 86400, 3600]),
+            vec![259200, 86400, 3600]
+        );
+        assert_eq!(normalize_thresholds(vec![]), Vec::<u64>::new());
+    }
+}
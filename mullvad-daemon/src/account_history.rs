@@ -29,9 +29,19 @@ pub enum Error {
 
 static ACCOUNT_HISTORY_FILE: &str = "account-history.json";
 
+/// The maximum number of accounts kept in the history.
+pub const MAX_ACCOUNT_HISTORY_SIZE: usize = 5;
+
+/// Accounts that have been used on this device, with the most recently used account first.
+///
+/// Unless `keep_account_history` is enabled in the settings, only the most recently used account
+/// is remembered. No accounts are remembered if `account_privacy_mode` is enabled. The history
+/// file is only accessible to the daemon.
 pub struct AccountHistory {
     file: Arc<Mutex<io::BufWriter<fs::File>>>,
-    token: Option<AccountToken>,
+    accounts: Vec<AccountToken>,
+    keep_history: bool,
+    privacy_mode: bool,
 }
 
 lazy_static::lazy_static! {
@@ -60,7 +70,7 @@ impl AccountHistory {
             options.share_mode(0);
         }
         let path = settings_dir.join(ACCOUNT_HISTORY_FILE);
-        let (file, token) = if path.is_file() {
+        let (file, accounts) = if path.is_file() {
             log::info!("Opening account history file in {}", path.display());
             let mut reader = options
                 .write(true)
@@ -70,9 +80,11 @@ impl AccountHistory {
                 .map_err(Error::Read)?;
 
             let mut buffer = String::new();
-            let token: Option<AccountToken> = match reader.read_to_string(&mut buffer) {
-                Ok(0) => None,
-                Ok(_) if ACCOUNT_REGEX.is_match(&buffer) => Some(buffer),
+            let accounts = match reader.read_to_string(&mut buffer) {
+                Ok(0) => vec![],
+                Ok(_) if Self::is_valid_format(&buffer) => {
+                    buffer.lines().map(str::to_owned).collect()
+                }
                 Ok(_) | Err(_) => {
                     log::warn!("Failed to parse account history. Trying old formats",);
                     match Self::try_format_v2(&mut reader)? {
@@ -85,14 +97,14 @@ impl AccountHistory {
                                     )
                                 );
                             }
-                            Some(token)
+                            vec![token]
                         }
                         None => Self::try_format_v1(&mut reader)?,
                     }
                 }
             };
 
-            (reader.into_inner(), token)
+            (reader.into_inner(), accounts)
         } else {
             log::info!("Creating account history file in {}", path.display());
             (
@@ -101,14 +113,17 @@ impl AccountHistory {
                     .create(true)
                     .open(path)
                     .map_err(Error::Read)?,
-                settings.get_account_token(),
+                settings.get_account_token().into_iter().collect(),
             )
         };
         let file = io::BufWriter::new(file);
         let mut history = AccountHistory {
             file: Arc::new(Mutex::new(file)),
-            token,
+            accounts,
+            keep_history: settings.keep_account_history,
+            privacy_mode: settings.account_privacy_mode,
         };
+        history.truncate();
         if let Err(e) = history.save_to_disk().await {
             log::error!("Failed to save account cache after opening it: {}", e);
         }
@@ -134,15 +149,20 @@ impl AccountHistory {
         }
     }
 
-    fn try_format_v1(reader: &mut io::BufReader<fs::File>) -> Result<Option<AccountToken>> {
+    /// The current format is one account token per line, most recently used first.
+    fn is_valid_format(content: &str) -> bool {
+        content.lines().all(|line| ACCOUNT_REGEX.is_match(line))
+    }
+
+    fn try_format_v1(reader: &mut io::BufReader<fs::File>) -> Result<Vec<AccountToken>> {
         #[derive(Deserialize)]
         struct OldFormat {
             accounts: Vec<AccountToken>,
         }
         reader.seek(io::SeekFrom::Start(0)).map_err(Error::Read)?;
         Ok(serde_json::from_reader(reader)
-            .map(|old_format: OldFormat| old_format.accounts.first().cloned())
-            .unwrap_or_else(|_| None))
+            .map(|old_format: OldFormat| old_format.accounts)
+            .unwrap_or_default())
     }
 
     fn try_format_v2(
@@ -163,34 +183,83 @@ impl AccountHistory {
             .unwrap_or_else(|_| None))
     }
 
-    /// Gets the account token in the history
-    pub fn get(&self) -> Option<AccountToken> {
-        self.token.clone()
+    /// Returns the accounts in the history, most recently used first.
+    pub fn get(&self) -> Vec<AccountToken> {
+        self.accounts.clone()
     }
 
-    /// Replace the account token in the history
-    pub async fn set(&mut self, new_entry: AccountToken) -> Result<()> {
-        self.token = Some(new_entry);
+    /// Moves an account to the front of the history, removing the oldest entries if the history
+    /// is full.
+    pub async fn add(&mut self, new_entry: AccountToken) -> Result<()> {
+        if new_entry.is_empty() || self.privacy_mode {
+            return Ok(());
+        }
+        self.accounts.retain(|account| *account != new_entry);
+        self.accounts.insert(0, new_entry);
+        self.truncate();
         self.save_to_disk().await
     }
 
+    /// Removes a single account from the history. Returns `false` if it was not in the history.
+    pub async fn remove(&mut self, account: &str) -> Result<bool> {
+        let num_accounts = self.accounts.len();
+        self.accounts.retain(|entry| entry != account);
+        if self.accounts.len() == num_accounts {
+            return Ok(false);
+        }
+        self.save_to_disk().await?;
+        Ok(true)
+    }
+
+    /// Sets whether more than the most recently used account is remembered. Disabling it
+    /// immediately forgets all other accounts.
+    pub async fn set_keep_history(&mut self, keep_history: bool) -> Result<()> {
+        self.keep_history = keep_history;
+        if self.truncate() {
+            self.save_to_disk().await?;
+        }
+        Ok(())
+    }
+
+    /// Sets whether accounts are remembered at all. Enabling privacy mode immediately forgets
+    /// all accounts.
+    pub async fn set_privacy_mode(&mut self, privacy_mode: bool) -> Result<()> {
+        self.privacy_mode = privacy_mode;
+        if self.truncate() {
+            self.save_to_disk().await?;
+        }
+        Ok(())
+    }
+
     /// Remove account history
     pub async fn clear(&mut self) -> Result<()> {
-        self.token = None;
+        self.accounts.clear();
         self.save_to_disk().await
     }
 
+    /// Removes the entries that exceed the size limit. Returns whether any entry was removed.
+    fn truncate(&mut self) -> bool {
+        let max_size = if self.privacy_mode {
+            0
+        } else if self.keep_history {
+            MAX_ACCOUNT_HISTORY_SIZE
+        } else {
+            1
+        };
+        let num_accounts = self.accounts.len();
+        self.accounts.truncate(max_size);
+        self.accounts.len() != num_accounts
+    }
+
     async fn save_to_disk(&mut self) -> Result<()> {
         let file = self.file.clone();
-        let token = self.token.clone();
+        let content = self.accounts.join("\n");
 
         tokio::task::spawn_blocking(move || {
             let mut file = file.lock().unwrap();
             file.get_mut().set_len(0).map_err(Error::Write)?;
             file.seek(io::SeekFrom::Start(0)).map_err(Error::Write)?;
-            if let Some(token) = token {
-                write!(&mut file, "{}", token).map_err(Error::Write)?;
-            }
+            write!(&mut file, "{}", content).map_err(Error::Write)?;
             file.flush().map_err(Error::Write)?;
             file.get_mut().sync_all().map_err(Error::Write)
         })
@@ -0,0 +1,217 @@
+//! The ways that API traffic can be sent: directly, through a Mullvad bridge, or through one of
+//! the proxies that the user has added. When requests keep failing, the daemon tries the other
+//! enabled access methods in turn, and switches to the first one that the API can be reached
+//! through.
+
+#[cfg(not(target_os = "android"))]
+use mullvad_types::api_access::BRIDGES_ACCESS_METHOD;
+use mullvad_types::{
+    api_access::{ActiveApiAccessMethod, ApiProxy, DIRECT_ACCESS_METHOD},
+    settings::Settings,
+};
+use std::net::SocketAddr;
+#[cfg(not(target_os = "android"))]
+use std::{io, net::Ipv4Addr};
+#[cfg(not(target_os = "android"))]
+use talpid_core::proxy::{self, ProxyMonitor, ProxyResourceData};
+#[cfg(not(target_os = "android"))]
+use talpid_types::{net::openvpn::ProxySettings, ErrorExt};
+
+/// A way of reaching the API.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccessMethod {
+    /// Connect to the API directly.
+    Direct,
+    /// Connect through a Shadowsocks bridge run by Mullvad.
+    #[cfg(not(target_os = "android"))]
+    Bridges,
+    /// Connect through a proxy added by the user.
+    Proxy(ApiProxy),
+}
+
+/// Returns the enabled access methods and their names, in the order they are tried. Direct access
+/// is used if every access method is disabled.
+pub fn enabled_methods(settings: &Settings) -> Vec<(String, AccessMethod)> {
+    let mut methods: Vec<_> = settings
+        .api_access_methods
+        .iter()
+        .filter(|method| method.enabled)
+        .map(|method| {
+            (
+                method.name.clone(),
+                AccessMethod::Proxy(method.proxy.clone()),
+            )
+        })
+        .collect();
+    if settings.api_access.direct_enabled {
+        methods.push((DIRECT_ACCESS_METHOD.to_owned(), AccessMethod::Direct));
+    }
+    #[cfg(not(target_os = "android"))]
+    if settings.api_access.bridges_enabled {
+        methods.push((BRIDGES_ACCESS_METHOD.to_owned(), AccessMethod::Bridges));
+    }
+
+    if let Some(preferred) = &settings.api_access.preferred {
+        if let Some(index) = methods.iter().position(|(name, _)| name == preferred) {
+            let method = methods.remove(index);
+            methods.insert(0, method);
+        }
+    }
+    if methods.is_empty() {
+        methods.push((DIRECT_ACCESS_METHOD.to_owned(), AccessMethod::Direct));
+    }
+    methods
+}
+
+/// Returns the access method named `name`, whether it is enabled or not.
+pub fn find_method(settings: &Settings, name: &str) -> Option<AccessMethod> {
+    match name {
+        DIRECT_ACCESS_METHOD => Some(AccessMethod::Direct),
+        #[cfg(not(target_os = "android"))]
+        BRIDGES_ACCESS_METHOD => Some(AccessMethod::Bridges),
+        _ => settings
+            .api_access_methods
+            .iter()
+            .find(|method| method.name == name)
+            .map(|method| AccessMethod::Proxy(method.proxy.clone())),
+    }
+}
+
+/// An access method that API traffic can be sent through.
+pub struct ActiveMethod {
+    pub name: String,
+    /// The proxy that API traffic is sent through, or `None` if it is sent directly to the API.
+    pub proxy: Option<ApiProxy>,
+    /// The bridge client that API traffic is sent through, if the method uses a bridge.
+    #[cfg(not(target_os = "android"))]
+    pub bridge: Option<BridgeClient>,
+}
+
+impl ActiveMethod {
+    pub fn direct() -> Self {
+        ActiveMethod {
+            name: DIRECT_ACCESS_METHOD.to_owned(),
+            proxy: None,
+            #[cfg(not(target_os = "android"))]
+            bridge: None,
+        }
+    }
+
+    /// The remote address that API traffic is sent to, if it is not sent directly to the API.
+    /// This address must be allowed through the firewall instead of the API address.
+    pub fn endpoint(&self) -> Option<SocketAddr> {
+        #[cfg(not(target_os = "android"))]
+        if let Some(bridge) = &self.bridge {
+            return Some(bridge.endpoint);
+        }
+        self.proxy.as_ref().map(ApiProxy::address)
+    }
+
+    /// Describes the method to frontends.
+    pub fn describe(&self) -> ActiveApiAccessMethod {
+        #[cfg(not(target_os = "android"))]
+        let bridge_hostname = self.bridge.as_ref().map(|bridge| bridge.hostname.clone());
+        #[cfg(target_os = "android")]
+        let bridge_hostname = None;
+        ActiveApiAccessMethod {
+            name: self.name.clone(),
+            endpoint: self.endpoint(),
+            bridge_hostname,
+        }
+    }
+}
+
+/// A Shadowsocks client that relays API traffic to a Mullvad bridge. It is stopped when dropped.
+#[cfg(not(target_os = "android"))]
+pub struct BridgeClient {
+    monitor: Box<dyn ProxyMonitor>,
+    /// The address of the bridge.
+    pub endpoint: SocketAddr,
+    /// The hostname of the relay that runs the bridge.
+    pub hostname: String,
+}
+
+#[cfg(not(target_os = "android"))]
+impl BridgeClient {
+    /// Starts a client for the bridge in `settings`, which runs on the relay `hostname`. This
+    /// blocks until the client is listening for connections.
+    pub fn start(
+        settings: &ProxySettings,
+        hostname: String,
+        resource_data: &ProxyResourceData,
+    ) -> io::Result<Self> {
+        let monitor = proxy::start_proxy(settings, resource_data)?;
+        Ok(BridgeClient {
+            monitor,
+            endpoint: settings.get_endpoint().endpoint.address,
+            hostname,
+        })
+    }
+
+    /// The local SOCKS5 proxy that the client provides.
+    pub fn proxy(&self) -> ApiProxy {
+        ApiProxy::Socks5 {
+            address: SocketAddr::new(Ipv4Addr::LOCALHOST.into(), self.monitor.port()),
+            auth: None,
+        }
+    }
+}
+
+#[cfg(not(target_os = "android"))]
+impl Drop for BridgeClient {
+    fn drop(&mut self) {
+        if let Err(error) = self.monitor.close_handle().close() {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to stop the API bridge client")
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_types::api_access::ApiAccessMethod;
+
+    fn proxy_method(name: &str, enabled: bool) -> ApiAccessMethod {
+        ApiAccessMethod {
+            name: name.to_owned(),
+            enabled,
+            proxy: ApiProxy::Socks5 {
+                address: "10.0.0.1:1080".parse().unwrap(),
+                auth: None,
+            },
+        }
+    }
+
+    fn names(settings: &Settings) -> Vec<String> {
+        enabled_methods(settings)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    #[test]
+    fn test_enabled_methods_order() {
+        let mut settings = Settings::default();
+        settings.api_access_methods = vec![proxy_method("a", true), proxy_method("b", false)];
+        settings.api_access.bridges_enabled = false;
+        assert_eq!(names(&settings), vec!["a", DIRECT_ACCESS_METHOD]);
+
+        settings.api_access.preferred = Some(DIRECT_ACCESS_METHOD.to_owned());
+        assert_eq!(names(&settings), vec![DIRECT_ACCESS_METHOD, "a"]);
+
+        // A preferred method that is disabled is not used
+        settings.api_access.preferred = Some("b".to_owned());
+        assert_eq!(names(&settings), vec!["a", DIRECT_ACCESS_METHOD]);
+    }
+
+    #[test]
+    fn test_direct_access_when_all_disabled() {
+        let mut settings = Settings::default();
+        settings.api_access.direct_enabled = false;
+        settings.api_access.bridges_enabled = false;
+        assert_eq!(names(&settings), vec![DIRECT_ACCESS_METHOD]);
+    }
+}
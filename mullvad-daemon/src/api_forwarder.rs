@@ -0,0 +1,318 @@
+//! A SOCKS5 proxy on the loopback interface that lets clients of the daemon, such as the GUI,
+//! reach the API the same way that the daemon does: through the current API access method. This
+//! way, requests made by clients work exactly when the daemon's own requests do. Only connections
+//! to the API are forwarded, and the forwarder stops once it has not been used for a while.
+
+use crate::lan_proxy::{
+    socks5_reply, SOCKS_ATYP_DOMAIN, SOCKS_ATYP_IPV4, SOCKS_ATYP_IPV6, SOCKS_CMD_CONNECT,
+    SOCKS_NO_ACCEPTABLE_METHODS, SOCKS_NO_AUTHENTICATION, SOCKS_REPLY_ADDRESS_TYPE_NOT_SUPPORTED,
+    SOCKS_REPLY_COMMAND_NOT_SUPPORTED, SOCKS_REPLY_GENERAL_FAILURE, SOCKS_REPLY_NOT_ALLOWED,
+    SOCKS_REPLY_SUCCEEDED, SOCKS_VERSION,
+};
+use futures::future::{abortable, AbortHandle};
+use mullvad_rpc::{proxy, AddressCache, ProxyHandle, API_HOST};
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use talpid_types::ErrorExt;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::Semaphore,
+    time::Instant,
+};
+
+/// How long the forwarder keeps running after it was last requested or used.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// How long to wait for a connection to the API, including the proxy handshake.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Maximum number of connections that are forwarded at the same time.
+const MAX_CONNECTIONS: usize = 32;
+
+/// A running forwarder. It is stopped when dropped, or once it has been idle for [`IDLE_TIMEOUT`].
+pub struct ApiForwarder {
+    address: SocketAddr,
+    activity: Arc<Mutex<Activity>>,
+    abort_handle: AbortHandle,
+}
+
+struct Activity {
+    last_used: Instant,
+    stopped: bool,
+}
+
+impl ApiForwarder {
+    /// Starts listening on an ephemeral port on the loopback interface. Connections are made to
+    /// the current address in `address_cache`, through the proxy in `proxy` if one is set.
+    pub async fn start(address_cache: AddressCache, proxy: ProxyHandle) -> io::Result<Self> {
+        let listener = TcpListener::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)).await?;
+        let address = listener.local_addr()?;
+        let activity = Arc::new(Mutex::new(Activity {
+            last_used: Instant::now(),
+            stopped: false,
+        }));
+        let connector = Arc::new(ApiConnector {
+            address_cache,
+            proxy,
+        });
+
+        let (accept_future, abort_handle) =
+            abortable(Self::accept_loop(listener, activity.clone(), connector));
+        tokio::spawn(accept_future);
+
+        log::info!("Started API forwarder on {}", address);
+
+        Ok(Self {
+            address,
+            activity,
+            abort_handle,
+        })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Keeps the forwarder running for at least another [`IDLE_TIMEOUT`]. Returns `false` if it has
+    /// already stopped, in which case a new forwarder must be started.
+    pub fn keep_alive(&self) -> bool {
+        let mut activity = self.activity.lock().unwrap();
+        activity.last_used = Instant::now();
+        !activity.stopped
+    }
+
+    async fn accept_loop(
+        listener: TcpListener,
+        activity: Arc<Mutex<Activity>>,
+        connector: Arc<ApiConnector>,
+    ) {
+        let connection_limit = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+        loop {
+            let deadline = activity.lock().unwrap().last_used + IDLE_TIMEOUT;
+            let (client, client_addr) = match tokio::time::timeout_at(deadline, listener.accept())
+                .await
+            {
+                Ok(Ok(result)) => result,
+                Ok(Err(error)) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("API forwarder failed to accept connection")
+                    );
+                    continue;
+                }
+                Err(_) => {
+                    let mut state = activity.lock().unwrap();
+                    if connection_limit.available_permits() < MAX_CONNECTIONS {
+                        state.last_used = Instant::now();
+                    } else if state.last_used + IDLE_TIMEOUT <= Instant::now() {
+                        state.stopped = true;
+                        log::info!("Stopped idle API forwarder");
+                        return;
+                    }
+                    continue;
+                }
+            };
+            activity.lock().unwrap().last_used = Instant::now();
+
+            let permit = match connection_limit.clone().try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    log::debug!(
+                        "API forwarder: Too many connections. Rejecting {}",
+                        client_addr
+                    );
+                    continue;
+                }
+            };
+
+            let connector = connector.clone();
+            tokio::spawn(async move {
+                if let Err(error) = handle_client(client, &connector).await {
+                    log::debug!(
+                        "{}",
+                        error.display_chain_with_msg("API forwarder: Connection failed")
+                    );
+                }
+                drop(permit);
+            });
+        }
+    }
+}
+
+impl Drop for ApiForwarder {
+    fn drop(&mut self) {
+        self.abort_handle.abort();
+    }
+}
+
+/// Opens connections to the API through the current access method.
+struct ApiConnector {
+    address_cache: AddressCache,
+    proxy: ProxyHandle,
+}
+
+impl ApiConnector {
+    async fn connect(&self) -> io::Result<TcpStream> {
+        let api_address = self.address_cache.peek_address();
+        let proxy = self.proxy.get();
+        let connect = async move {
+            match proxy {
+                Some(proxy) => {
+                    let mut server = TcpStream::connect(proxy.address()).await?;
+                    proxy::handshake(&mut server, &proxy, api_address).await?;
+                    Ok(server)
+                }
+                None => TcpStream::connect(api_address).await,
+            }
+        };
+        let server = tokio::time::timeout(CONNECT_TIMEOUT, connect)
+            .await
+            .map_err(|_| {
+                io::Error::new(io::ErrorKind::TimedOut, "Timed out connecting to API")
+            })??;
+        let _ = server.set_nodelay(true);
+        Ok(server)
+    }
+}
+
+/// A destination requested by a SOCKS client.
+#[derive(Debug)]
+enum Destination {
+    Address(SocketAddr),
+    Domain(String, u16),
+}
+
+impl Destination {
+    /// Returns whether the destination is the API, which is reached at `api_address`.
+    fn is_api(&self, api_address: SocketAddr) -> bool {
+        match self {
+            Destination::Address(address) => *address == api_address,
+            Destination::Domain(domain, port) => {
+                domain.eq_ignore_ascii_case(API_HOST) && *port == api_address.port()
+            }
+        }
+    }
+}
+
+async fn handle_client(mut client: TcpStream, connector: &ApiConnector) -> io::Result<()> {
+    let _ = client.set_nodelay(true);
+    let mut server = match socks5_handshake(&mut client, connector).await? {
+        Some(server) => server,
+        None => return Ok(()),
+    };
+    tokio::io::copy_bidirectional(&mut client, &mut server)
+        .await
+        .map(|_| ())
+}
+
+/// Performs a SOCKS5 handshake. Returns the connection to the API, or `None` if the request was
+/// refused.
+async fn socks5_handshake(
+    client: &mut TcpStream,
+    connector: &ApiConnector,
+) -> io::Result<Option<TcpStream>> {
+    if client.read_u8().await? != SOCKS_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Invalid SOCKS version",
+        ));
+    }
+    let method_count = client.read_u8().await?;
+    let mut methods = vec![0u8; usize::from(method_count)];
+    client.read_exact(&mut methods).await?;
+    if !methods.contains(&SOCKS_NO_AUTHENTICATION) {
+        client
+            .write_all(&[SOCKS_VERSION, SOCKS_NO_ACCEPTABLE_METHODS])
+            .await?;
+        return Ok(None);
+    }
+    client
+        .write_all(&[SOCKS_VERSION, SOCKS_NO_AUTHENTICATION])
+        .await?;
+
+    let mut header = [0u8; 4];
+    client.read_exact(&mut header).await?;
+    let [version, command, _reserved, address_type] = header;
+    if version != SOCKS_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Invalid SOCKS version",
+        ));
+    }
+
+    let destination = match address_type {
+        SOCKS_ATYP_IPV4 => {
+            let mut octets = [0u8; 4];
+            client.read_exact(&mut octets).await?;
+            let port = client.read_u16().await?;
+            Destination::Address(SocketAddr::new(Ipv4Addr::from(octets).into(), port))
+        }
+        SOCKS_ATYP_IPV6 => {
+            let mut octets = [0u8; 16];
+            client.read_exact(&mut octets).await?;
+            let port = client.read_u16().await?;
+            Destination::Address(SocketAddr::new(Ipv6Addr::from(octets).into(), port))
+        }
+        SOCKS_ATYP_DOMAIN => {
+            let length = client.read_u8().await?;
+            let mut domain = vec![0u8; usize::from(length)];
+            client.read_exact(&mut domain).await?;
+            let port = client.read_u16().await?;
+            Destination::Domain(String::from_utf8_lossy(&domain).into_owned(), port)
+        }
+        _ => {
+            socks5_reply(client, SOCKS_REPLY_ADDRESS_TYPE_NOT_SUPPORTED, None).await?;
+            return Ok(None);
+        }
+    };
+
+    if command != SOCKS_CMD_CONNECT {
+        socks5_reply(client, SOCKS_REPLY_COMMAND_NOT_SUPPORTED, None).await?;
+        return Ok(None);
+    }
+    if !destination.is_api(connector.address_cache.peek_address()) {
+        log::debug!("API forwarder: Refusing connection to {:?}", destination);
+        socks5_reply(client, SOCKS_REPLY_NOT_ALLOWED, None).await?;
+        return Ok(None);
+    }
+
+    match connector.connect().await {
+        Ok(server) => {
+            let local_addr = server.local_addr().ok();
+            socks5_reply(client, SOCKS_REPLY_SUCCEEDED, local_addr).await?;
+            Ok(Some(server))
+        }
+        Err(error) => {
+            log::debug!(
+                "{}",
+                error.display_chain_with_msg("API forwarder: Failed to connect to the API")
+            );
+            socks5_reply(client, SOCKS_REPLY_GENERAL_FAILURE, None).await?;
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_only_api_is_allowed() {
+        let api_address: SocketAddr = "193.138.218.78:443".parse().unwrap();
+
+        assert!(Destination::Address(api_address).is_api(api_address));
+        assert!(Destination::Domain("api.mullvad.net".to_owned(), 443).is_api(api_address));
+        assert!(Destination::Domain("API.Mullvad.net".to_owned(), 443).is_api(api_address));
+
+        assert!(!Destination::Address("193.138.218.78:80".parse().unwrap()).is_api(api_address));
+        assert!(!Destination::Address("198.51.100.1:443".parse().unwrap()).is_api(api_address));
+        assert!(!Destination::Domain("example.com".to_owned(), 443).is_api(api_address));
+        assert!(!Destination::Domain("api.mullvad.net".to_owned(), 80).is_api(api_address));
+    }
+}
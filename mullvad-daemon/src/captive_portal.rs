@@ -0,0 +1,263 @@
+//! Detection of captive portals. The hosts that the operating system probes to detect captive
+//! portals are resolved whenever the tunnel is connected, since names cannot be resolved while
+//! traffic is blocked, and the addresses are reused for the firewall rules of the blocking states.
+//!
+//! The daemon sends the same probes itself to tell whether a captive portal is intercepting web
+//! traffic, and to find out when a captive portal login has been completed.
+use futures::future::join_all;
+use std::{collections::BTreeMap, net::IpAddr, time::Duration};
+#[cfg(not(target_os = "android"))]
+use std::{io, net::SocketAddr, time::Instant};
+use talpid_types::ErrorExt;
+#[cfg(not(target_os = "android"))]
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+/// How long to wait for a single host to resolve.
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long to wait for a probe host to accept the connection and respond.
+#[cfg(not(target_os = "android"))]
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Responses are truncated to this size. Probe responses are tiny, but a portal may respond with
+/// a whole login page.
+#[cfg(not(target_os = "android"))]
+const MAX_RESPONSE_SIZE: u64 = 16 * 1024;
+
+/// How long traffic needed to log in to a captive portal is let through.
+#[cfg(not(target_os = "android"))]
+pub const PORTAL_LOGIN_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+/// How often to check whether a captive portal login has been completed.
+#[cfg(not(target_os = "android"))]
+const PORTAL_LOGIN_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A plain HTTP request that gets a known response when no captive portal is intercepting it.
+#[cfg_attr(target_os = "android", allow(dead_code))]
+struct Probe {
+    host: &'static str,
+    path: &'static str,
+    status: u16,
+    body: &'static str,
+}
+
+/// The probes that the captive portal detection of this platform sends.
+#[cfg(target_os = "linux")]
+const PROBES: &[Probe] = &[
+    // NetworkManager, as configured by common distributions
+    Probe {
+        host: "connectivity-check.ubuntu.com",
+        path: "/",
+        status: 204,
+        body: "",
+    },
+    Probe {
+        host: "nmcheck.gnome.org",
+        path: "/check_network_status.txt",
+        status: 200,
+        body: "NetworkManager is online",
+    },
+    Probe {
+        host: "fedoraproject.org",
+        path: "/static/hotspot.txt",
+        status: 200,
+        body: "OK",
+    },
+    Probe {
+        host: "ping.archlinux.org",
+        path: "/nm-check.txt",
+        status: 200,
+        body: "NetworkManager is online",
+    },
+];
+#[cfg(target_os = "macos")]
+const PROBES: &[Probe] = &[Probe {
+    host: "captive.apple.com",
+    path: "/hotspot-detect.html",
+    status: 200,
+    body: "Success",
+}];
+#[cfg(windows)]
+const PROBES: &[Probe] = &[
+    Probe {
+        host: "www.msftconnecttest.com",
+        path: "/connecttest.txt",
+        status: 200,
+        body: "Microsoft Connect Test",
+    },
+    Probe {
+        host: "ipv6.msftconnecttest.com",
+        path: "/connecttest.txt",
+        status: 200,
+        body: "Microsoft Connect Test",
+    },
+];
+#[cfg(target_os = "android")]
+const PROBES: &[Probe] = &[];
+
+/// Addresses of the probe hosts, by host name.
+pub type ResolvedHosts = BTreeMap<&'static str, Vec<IpAddr>>;
+
+/// Whether a captive portal is intercepting web traffic.
+#[cfg(not(target_os = "android"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortalStatus {
+    /// A probe got the expected response.
+    Online,
+    /// A probe got another response, such as a redirect to a login page.
+    Detected,
+    /// None of the probe hosts could be reached.
+    Unreachable,
+}
+
+/// Returns the addresses of all resolved hosts.
+pub fn addresses(hosts: &ResolvedHosts) -> Vec<IpAddr> {
+    let mut addrs: Vec<IpAddr> = hosts.values().flatten().copied().collect();
+    addrs.sort();
+    addrs.dedup();
+    addrs
+}
+
+/// Resolves the captive portal detection hosts of this platform. Hosts that fail to resolve are
+/// skipped.
+pub async fn resolve_hosts() -> ResolvedHosts {
+    let lookups = PROBES
+        .iter()
+        .map(|probe| async move { (probe.host, resolve(probe.host).await) });
+
+    join_all(lookups)
+        .await
+        .into_iter()
+        .filter(|(_, addrs)| !addrs.is_empty())
+        .collect()
+}
+
+async fn resolve(host: &str) -> Vec<IpAddr> {
+    match tokio::time::timeout(RESOLVE_TIMEOUT, tokio::net::lookup_host((host, 80))).await {
+        Ok(Ok(addrs)) => addrs.map(|addr| addr.ip()).collect(),
+        Ok(Err(error)) => {
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg(&format!("Failed to resolve {}", host))
+            );
+            vec![]
+        }
+        Err(_) => {
+            log::warn!("Timed out resolving {}", host);
+            vec![]
+        }
+    }
+}
+
+/// Sends the probes of this platform until one of them gets a response. The probe hosts are
+/// resolved again if possible, and are otherwise reached at the addresses in `cached_hosts`.
+/// If the tunnel is up, the probes are sent through it.
+#[cfg(not(target_os = "android"))]
+pub async fn detect(cached_hosts: &ResolvedHosts) -> PortalStatus {
+    for probe in PROBES {
+        let mut addrs = resolve(probe.host).await;
+        if addrs.is_empty() {
+            addrs = cached_hosts.get(probe.host).cloned().unwrap_or_default();
+        }
+        for addr in addrs {
+            match probe.send(addr).await {
+                Ok(response) => {
+                    return if probe.matches(&response) {
+                        PortalStatus::Online
+                    } else {
+                        log::debug!("Unexpected response from {} at {}", probe.host, addr);
+                        PortalStatus::Detected
+                    };
+                }
+                Err(error) => log::debug!(
+                    "{}",
+                    error.display_chain_with_msg(&format!(
+                        "Failed to probe {} at {}",
+                        probe.host, addr
+                    ))
+                ),
+            }
+        }
+    }
+    PortalStatus::Unreachable
+}
+
+/// Probes until the captive portal lets the probes through, or until the login times out.
+/// Returns the result of the last probe.
+#[cfg(not(target_os = "android"))]
+pub async fn wait_for_login(cached_hosts: ResolvedHosts) -> PortalStatus {
+    let deadline = Instant::now() + PORTAL_LOGIN_TIMEOUT;
+    let mut status = PortalStatus::Unreachable;
+    while Instant::now() < deadline {
+        tokio::time::sleep(PORTAL_LOGIN_CHECK_INTERVAL).await;
+        status = detect(&cached_hosts).await;
+        if status == PortalStatus::Online {
+            break;
+        }
+    }
+    status
+}
+
+#[cfg(not(target_os = "android"))]
+impl Probe {
+    async fn send(&self, addr: IpAddr) -> io::Result<Vec<u8>> {
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            self.path, self.host
+        );
+        let exchange = async {
+            let mut stream = TcpStream::connect(SocketAddr::new(addr, 80)).await?;
+            stream.write_all(request.as_bytes()).await?;
+            let mut response = vec![];
+            stream
+                .take(MAX_RESPONSE_SIZE)
+                .read_to_end(&mut response)
+                .await?;
+            Ok::<_, io::Error>(response)
+        };
+        tokio::time::timeout(PROBE_TIMEOUT, exchange)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "The probe timed out"))?
+    }
+
+    /// Returns whether `response` has the status and body of a response that was not
+    /// intercepted.
+    fn matches(&self, response: &[u8]) -> bool {
+        let response = String::from_utf8_lossy(response);
+        let (head, body) = match response.find("\r\n\r\n") {
+            Some(index) => (&response[..index], &response[index + 4..]),
+            None => (&response[..], ""),
+        };
+        let status = head
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse::<u16>().ok());
+        status == Some(self.status) && body.contains(self.body)
+    }
+}
+
+#[cfg(all(test, not(target_os = "android")))]
+mod test {
+    use super::*;
+
+    const PROBE: Probe = Probe {
+        host: "example.com",
+        path: "/check.txt",
+        status: 200,
+        body: "online",
+    };
+
+    #[test]
+    fn test_expected_response() {
+        assert!(PROBE.matches(b"HTTP/1.1 200 OK\r\nContent-Length: 7\r\n\r\nonline\n"));
+    }
+
+    #[test]
+    fn test_intercepted_response() {
+        assert!(
+            !PROBE.matches(b"HTTP/1.1 302 Found\r\nLocation: http://portal.example/login\r\n\r\n")
+        );
+        assert!(!PROBE.matches(b"HTTP/1.1 200 OK\r\n\r\n<html>Log in to continue</html>"));
+        assert!(!PROBE.matches(b""));
+    }
+}
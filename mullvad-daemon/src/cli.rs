@@ -57,6 +57,13 @@ lazy_static::lazy_static! {
     MULLVAD_RPC_SOCKET_PATH    Location of the management interface device.
                                It refers to Unix domain socket on Unix based platforms, and named pipe on Windows.
                                [Default: {}]
+    MULLVAD_OFFLINE_STRATEGY   How to detect that the host is offline on Linux. Either \"route\", which
+                               checks for a route to a public address, or \"link\", which checks for a
+                               network interface with a carrier. [Default: route]
+    MULLVAD_OFFLINE_REACHABILITY_CHECK
+                               Comma-separated IP:port endpoints that are connected to over TCP on Linux
+                               when the host seems to be offline. The host is considered online if any
+                               of them can be reached.
 
 ",
         mullvad_paths::get_default_resource_dir().display(),
@@ -0,0 +1,308 @@
+//! A rolling record of how long the most recent successful connection attempts took, in total and
+//! in each phase, so that connecting getting slower between releases can be spotted. The record is
+//! only kept if connection statistics are enabled, and is never sent anywhere.
+use crate::version::PRODUCT_VERSION;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use talpid_core::connection_trace::{self, Attempt, Span};
+use talpid_types::ErrorExt;
+
+const CONNECT_TIMES_FILE: &str = "connect-times.json";
+
+/// Number of successful attempts that are kept. Older attempts are forgotten.
+const MAX_SAMPLES: usize = 500;
+
+/// Upper bounds of the histogram buckets. Times above the last bound fall in an extra bucket.
+pub const BUCKET_BOUNDS: [Duration; 8] = [
+    Duration::from_millis(100),
+    Duration::from_millis(250),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_secs(2),
+    Duration::from_secs(5),
+    Duration::from_secs(10),
+    Duration::from_secs(30),
+];
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to serialize connect times")]
+    Serialize(#[error(source)] serde_json::Error),
+
+    #[error(display = "Failed to write connect times")]
+    Write(#[error(source)] io::Error),
+
+    #[error(display = "Failed to remove connect times")]
+    Remove(#[error(source)] io::Error),
+}
+
+/// How long a single successful attempt took.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Sample {
+    /// The version of the daemon that made the attempt.
+    version: String,
+    timestamp: DateTime<Utc>,
+    total_ms: u64,
+    /// The time spent in each phase, by the name of the phase. Phases that were entered several
+    /// times, such as applying the firewall policy, are summed.
+    phases: BTreeMap<String, u64>,
+}
+
+/// The distribution of the times of a phase, or of whole attempts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    /// The number of times within each bound of [`BUCKET_BOUNDS`] and above the one before it,
+    /// followed by the number of times above the last bound.
+    pub buckets: Vec<u32>,
+    pub median: Duration,
+    pub p90: Duration,
+    pub max: Duration,
+}
+
+impl Histogram {
+    /// Returns the distribution of `times_ms`, which must not be empty.
+    fn new(mut times_ms: Vec<u64>) -> Self {
+        times_ms.sort_unstable();
+        let mut buckets = vec![0; BUCKET_BOUNDS.len() + 1];
+        for time in &times_ms {
+            let bucket = BUCKET_BOUNDS
+                .iter()
+                .position(|bound| *time <= bound.as_millis() as u64)
+                .unwrap_or(BUCKET_BOUNDS.len());
+            buckets[bucket] += 1;
+        }
+        let percentile =
+            |percent: usize| Duration::from_millis(times_ms[(times_ms.len() - 1) * percent / 100]);
+        Histogram {
+            buckets,
+            median: percentile(50),
+            p90: percentile(90),
+            max: Duration::from_millis(*times_ms.last().unwrap()),
+        }
+    }
+
+    /// Returns the number of times in the histogram.
+    pub fn count(&self) -> u32 {
+        self.buckets.iter().sum()
+    }
+}
+
+/// The times of the attempts that were made by one version of the daemon.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VersionConnectTimes {
+    pub version: String,
+    pub total: Histogram,
+    /// The times of each phase, by the name of the phase.
+    pub phases: BTreeMap<String, Histogram>,
+}
+
+pub struct ConnectTimes {
+    path: PathBuf,
+    enabled: bool,
+    samples: VecDeque<Sample>,
+    /// The id of the last attempt that was recorded, so that no attempt is recorded twice.
+    last_attempt_id: Option<u64>,
+}
+
+impl ConnectTimes {
+    /// Loads the record from the cache directory. A missing or unreadable file results in an empty
+    /// record.
+    pub async fn load(cache_dir: &Path, enabled: bool) -> Self {
+        let path = cache_dir.join(CONNECT_TIMES_FILE);
+        let samples = if enabled {
+            match tokio::fs::read(&path).await {
+                Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|error| {
+                    log::warn!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to parse connect times")
+                    );
+                    VecDeque::new()
+                }),
+                Err(_) => VecDeque::new(),
+            }
+        } else {
+            VecDeque::new()
+        };
+        Self::new(path, enabled, samples)
+    }
+
+    fn new(path: PathBuf, enabled: bool, samples: VecDeque<Sample>) -> Self {
+        ConnectTimes {
+            path,
+            enabled,
+            samples,
+            last_attempt_id: None,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Starts or stops recording. The record is removed when recording is stopped.
+    pub async fn set_enabled(&mut self, enabled: bool) -> Result<(), Error> {
+        self.enabled = enabled;
+        if !enabled {
+            self.clear().await?;
+        }
+        Ok(())
+    }
+
+    /// Removes all recorded times.
+    pub async fn clear(&mut self) -> Result<(), Error> {
+        self.samples.clear();
+        match tokio::fs::remove_file(&self.path).await {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(Error::Remove(error)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Records the times of `attempt` if it ended with the tunnel connected and has not been
+    /// recorded before.
+    pub async fn record(&mut self, attempt: &Attempt) {
+        if attempt.outcome.as_deref() != Some(connection_trace::CONNECTED_OUTCOME) {
+            return;
+        }
+        let duration = match attempt.duration {
+            Some(duration) => duration,
+            None => return,
+        };
+        if self.add(attempt.id, duration, &attempt.spans, PRODUCT_VERSION) {
+            if let Err(error) = self.save().await {
+                log::error!("{}", error.display_chain());
+            }
+        }
+    }
+
+    /// Adds the times of an attempt. Returns whether it was added.
+    fn add(&mut self, attempt_id: u64, duration: Duration, spans: &[Span], version: &str) -> bool {
+        if !self.enabled || self.last_attempt_id == Some(attempt_id) {
+            return false;
+        }
+        self.last_attempt_id = Some(attempt_id);
+
+        let mut phases = BTreeMap::new();
+        for span in spans {
+            *phases.entry(span.phase.to_string()).or_insert(0) += span.duration.as_millis() as u64;
+        }
+        if self.samples.len() >= MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample {
+            version: version.to_owned(),
+            timestamp: Utc::now(),
+            total_ms: duration.as_millis() as u64,
+            phases,
+        });
+        true
+    }
+
+    /// Returns the distribution of the recorded times for each version of the daemon, starting
+    /// with the version that most recently connected.
+    pub fn by_version(&self) -> Vec<VersionConnectTimes> {
+        let mut versions: Vec<&str> = vec![];
+        for sample in self.samples.iter().rev() {
+            if !versions.contains(&sample.version.as_str()) {
+                versions.push(&sample.version);
+            }
+        }
+
+        versions
+            .into_iter()
+            .map(|version| {
+                let samples: Vec<&Sample> = self
+                    .samples
+                    .iter()
+                    .filter(|sample| sample.version == version)
+                    .collect();
+                let mut phase_times: BTreeMap<String, Vec<u64>> = BTreeMap::new();
+                for sample in &samples {
+                    for (phase, time) in &sample.phases {
+                        phase_times.entry(phase.clone()).or_default().push(*time);
+                    }
+                }
+                VersionConnectTimes {
+                    version: version.to_owned(),
+                    total: Histogram::new(samples.iter().map(|sample| sample.total_ms).collect()),
+                    phases: phase_times
+                        .into_iter()
+                        .map(|(phase, times)| (phase, Histogram::new(times)))
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+
+    async fn save(&self) -> Result<(), Error> {
+        let content = serde_json::to_vec_pretty(&self.samples).map_err(Error::Serialize)?;
+        tokio::fs::write(&self.path, content)
+            .await
+            .map_err(Error::Write)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use talpid_core::connection_trace::Phase;
+
+    fn times(enabled: bool) -> ConnectTimes {
+        ConnectTimes::new(PathBuf::from(CONNECT_TIMES_FILE), enabled, VecDeque::new())
+    }
+
+    fn span(phase: Phase, millis: u64) -> Span {
+        Span {
+            phase,
+            offset: Duration::from_secs(0),
+            duration: Duration::from_millis(millis),
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_histogram() {
+        let histogram = Histogram::new(vec![3000, 50, 120, 40_000, 90]);
+        assert_eq!(histogram.buckets, vec![2, 1, 0, 0, 0, 1, 0, 0, 1]);
+        assert_eq!(histogram.count(), 5);
+        assert_eq!(histogram.median, Duration::from_millis(120));
+        assert_eq!(histogram.max, Duration::from_millis(40_000));
+    }
+
+    #[test]
+    fn test_by_version() {
+        let mut times = times(true);
+        let spans = [
+            span(Phase::Firewall, 10),
+            span(Phase::Handshake, 400),
+            span(Phase::Firewall, 20),
+        ];
+        assert!(times.add(0, Duration::from_millis(500), &spans, "2021.1"));
+        assert!(!times.add(0, Duration::from_millis(500), &spans, "2021.1"));
+        assert!(times.add(1, Duration::from_millis(900), &spans[1..2], "2021.2"));
+
+        let versions = times.by_version();
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[0].version, "2021.2");
+        assert_eq!(versions[0].total.max, Duration::from_millis(900));
+        assert!(!versions[0].phases.contains_key("firewall"));
+        assert_eq!(versions[1].version, "2021.1");
+        assert_eq!(
+            versions[1].phases["firewall"].max,
+            Duration::from_millis(30)
+        );
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut times = times(false);
+        assert!(!times.add(0, Duration::from_millis(500), &[], "2021.1"));
+        assert!(times.by_version().is_empty());
+    }
+}
@@ -0,0 +1,162 @@
+//! Keeps the connection profiles up to date and tracks the country that the device is in, so that
+//! the relay selector can start out with the connection parameters that are known to work there.
+//! The country is learned from GeoIP lookups made while disconnected, since the location is that
+//! of the relay while the tunnel is up.
+use mullvad_rpc::ConnectionProfilesProxy;
+use mullvad_types::connection_profile::{ConnectionProfile, ConnectionProfiles};
+use parking_lot::Mutex;
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use talpid_types::ErrorExt;
+
+const PROFILES_FILENAME: &str = "connection-profiles.json";
+
+/// How often the profiles are fetched from the API.
+pub(crate) const UPDATE_INTERVAL: Duration = Duration::from_secs(60 * 60 * 24);
+/// Delay before the profiles are first fetched after the daemon has started.
+pub(crate) const UPDATE_INITIAL_DELAY: Duration = Duration::from_secs(60 * 10);
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to read the connection profiles")]
+    Read(#[error(source)] io::Error),
+
+    #[error(display = "Failed to write the connection profiles to the cache")]
+    Write(#[error(source)] io::Error),
+
+    #[error(display = "Failed to parse the connection profiles")]
+    Parse(#[error(source)] serde_json::Error),
+
+    #[error(display = "Failed to serialize the connection profiles")]
+    Serialize(#[error(source)] serde_json::Error),
+
+    #[error(display = "Failed to fetch the connection profiles")]
+    Download(#[error(source)] mullvad_rpc::rest::Error),
+}
+
+/// The profiles and the country of the device, shared between the daemon and the relay selector.
+#[derive(Default)]
+pub struct ConnectionProfileState {
+    profiles: ConnectionProfiles,
+    country: Option<String>,
+    enabled: bool,
+}
+
+impl ConnectionProfileState {
+    /// Sets whether the profiles are used at all.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn set_profiles(&mut self, profiles: ConnectionProfiles) {
+        self.profiles = profiles;
+    }
+
+    /// Sets the country that the device is in. This must not be the location of a relay.
+    pub fn set_country(&mut self, country: String) {
+        if self.enabled && self.country.as_ref() != Some(&country) {
+            if let Some(profile) = self.profiles.find(&country) {
+                log::info!(
+                    "Using the connection profile for {}: {}{}{}",
+                    profile.country,
+                    profile.protocol,
+                    profile
+                        .port
+                        .map(|port| format!(" port {}", port))
+                        .unwrap_or_default(),
+                    if profile.obfuscation {
+                        " with obfuscation"
+                    } else {
+                        ""
+                    }
+                );
+            }
+        }
+        self.country = Some(country);
+    }
+
+    /// Returns the country that the device is in, if it has been learned.
+    pub fn country(&self) -> Option<&str> {
+        self.country.as_deref()
+    }
+
+    /// Returns the profile to apply, if enabled and if there is one for the current country.
+    pub fn active_profile(&self) -> Option<&ConnectionProfile> {
+        if !self.enabled {
+            return None;
+        }
+        self.profiles.find(self.country.as_ref()?)
+    }
+}
+
+/// Returns the cached profiles, or the ones shipped with the app if none have been fetched.
+pub async fn load(cache_dir: &Path, resource_dir: &Path) -> ConnectionProfiles {
+    for path in &[
+        cache_dir.join(PROFILES_FILENAME),
+        resource_dir.join(PROFILES_FILENAME),
+    ] {
+        match read_profiles(path).await {
+            Ok(profiles) => return profiles,
+            Err(Error::Read(error)) if error.kind() == io::ErrorKind::NotFound => (),
+            Err(error) => log::error!(
+                "{}",
+                error.display_chain_with_msg(&format!(
+                    "Failed to load connection profiles from {}",
+                    path.display()
+                ))
+            ),
+        }
+    }
+    ConnectionProfiles::default()
+}
+
+async fn read_profiles(path: &Path) -> Result<ConnectionProfiles, Error> {
+    let contents = tokio::fs::read(path).await.map_err(Error::Read)?;
+    serde_json::from_slice(&contents).map_err(Error::Parse)
+}
+
+/// Fetches the profiles from the API, caches them and hands them to the relay selector.
+pub async fn update(
+    proxy: ConnectionProfilesProxy,
+    cache_dir: PathBuf,
+    state: Arc<Mutex<ConnectionProfileState>>,
+) -> Result<(), Error> {
+    let profiles = proxy.connection_profiles().await.map_err(Error::Download)?;
+    let contents = serde_json::to_vec_pretty(&profiles).map_err(Error::Serialize)?;
+    state.lock().set_profiles(profiles);
+    tokio::fs::write(cache_dir.join(PROFILES_FILENAME), contents)
+        .await
+        .map_err(Error::Write)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use talpid_types::net::TransportProtocol;
+
+    #[test]
+    fn test_active_profile() {
+        let mut state = ConnectionProfileState::default();
+        state.set_profiles(ConnectionProfiles {
+            profiles: vec![ConnectionProfile {
+                country: "Freedonia".to_owned(),
+                protocol: TransportProtocol::Tcp,
+                port: Some(443),
+                obfuscation: false,
+            }],
+        });
+        state.set_country("Freedonia".to_owned());
+        assert!(state.active_profile().is_none());
+
+        state.set_enabled(true);
+        assert_eq!(state.active_profile().unwrap().port, Some(443));
+
+        state.set_country("Sweden".to_owned());
+        assert!(state.active_profile().is_none());
+    }
+}
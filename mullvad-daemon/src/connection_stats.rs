@@ -0,0 +1,390 @@
+//! Statistics about how well connections to each relay work, such as how often they succeed, how
+//! long it takes to connect and the highest observed throughput. The statistics are only
+//! collected if enabled in the settings, and are never sent anywhere.
+use chrono::{DateTime, Utc};
+use mullvad_types::states::TunnelState;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use talpid_core::tunnel::TrafficCounters;
+use talpid_types::{tunnel::ActionAfterDisconnect, ErrorExt};
+
+const STATS_FILE: &str = "connection-stats.json";
+
+/// How often the traffic counters of the tunnel are read while connected, unless someone is
+/// subscribed to the tunnel stats. Throughput is always measured over this interval.
+pub const THROUGHPUT_SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Relays that have not been connected to for this many days are forgotten.
+const MAX_RELAY_AGE_DAYS: i64 = 90;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to serialize connection statistics")]
+    Serialize(#[error(source)] serde_json::Error),
+
+    #[error(display = "Failed to write connection statistics")]
+    Write(#[error(source)] io::Error),
+
+    #[error(display = "Failed to remove connection statistics")]
+    Remove(#[error(source)] io::Error),
+}
+
+/// Statistics about the connections to a single relay.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RelayStats {
+    /// The number of times that a connection to the relay was attempted. Attempts that were
+    /// aborted by the user are not counted.
+    pub attempts: u32,
+    /// The number of attempts that resulted in a working tunnel.
+    pub successes: u32,
+    /// The sum of the times it took to connect, over all successful attempts.
+    total_connect_time_ms: u64,
+    /// The highest throughput observed through the tunnel, in bytes per second in both
+    /// directions combined.
+    pub peak_throughput: u64,
+    pub last_attempt: DateTime<Utc>,
+}
+
+impl RelayStats {
+    fn new() -> Self {
+        RelayStats {
+            attempts: 0,
+            successes: 0,
+            total_connect_time_ms: 0,
+            peak_throughput: 0,
+            last_attempt: Utc::now(),
+        }
+    }
+
+    /// Returns the mean time it took to connect, over all successful attempts.
+    pub fn mean_connect_time(&self) -> Option<Duration> {
+        if self.successes == 0 {
+            return None;
+        }
+        Some(Duration::from_millis(
+            self.total_connect_time_ms / u64::from(self.successes),
+        ))
+    }
+}
+
+/// Identifies a connected tunnel, so that traffic samples from an earlier tunnel are ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionId(u64);
+
+struct Attempt {
+    hostname: String,
+    started: Instant,
+}
+
+struct Session {
+    id: ConnectionId,
+    hostname: String,
+    last_sample: Option<(TrafficCounters, Instant)>,
+}
+
+pub struct ConnectionStats {
+    path: PathBuf,
+    enabled: bool,
+    relays: BTreeMap<String, RelayStats>,
+    attempt: Option<Attempt>,
+    session: Option<Session>,
+    next_id: u64,
+    changed: bool,
+}
+
+impl ConnectionStats {
+    /// Loads the statistics from the cache directory. A missing or unreadable file results in
+    /// empty statistics.
+    pub async fn load(cache_dir: &Path, enabled: bool) -> Self {
+        let path = cache_dir.join(STATS_FILE);
+        let relays = if enabled {
+            match tokio::fs::read(&path).await {
+                Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|error| {
+                    log::warn!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to parse connection statistics")
+                    );
+                    BTreeMap::new()
+                }),
+                Err(_) => BTreeMap::new(),
+            }
+        } else {
+            BTreeMap::new()
+        };
+        Self::new(path, enabled, relays)
+    }
+
+    fn new(path: PathBuf, enabled: bool, relays: BTreeMap<String, RelayStats>) -> Self {
+        ConnectionStats {
+            path,
+            enabled,
+            relays,
+            attempt: None,
+            session: None,
+            next_id: 0,
+            changed: false,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Starts or stops collecting statistics. The collected statistics are removed when
+    /// collection is stopped.
+    pub async fn set_enabled(&mut self, enabled: bool) -> Result<(), Error> {
+        self.enabled = enabled;
+        self.attempt = None;
+        self.session = None;
+        if !enabled {
+            self.clear().await?;
+        }
+        Ok(())
+    }
+
+    /// Removes all collected statistics.
+    pub async fn clear(&mut self) -> Result<(), Error> {
+        self.relays.clear();
+        self.changed = false;
+        match tokio::fs::remove_file(&self.path).await {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(Error::Remove(error)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the statistics for each relay, by hostname.
+    pub fn relays(&self) -> &BTreeMap<String, RelayStats> {
+        &self.relays
+    }
+
+    /// Updates the statistics when the tunnel changes state. `hostname` is the relay that is
+    /// being connected to, if it is known. Returns an ID if a tunnel was connected, which should
+    /// be used to sample its traffic.
+    pub async fn record_state(
+        &mut self,
+        state: &TunnelState,
+        hostname: Option<&str>,
+    ) -> Option<ConnectionId> {
+        let connection_id = self.update_state(state, hostname);
+        if self.changed {
+            if let Err(error) = self.save().await {
+                log::error!("{}", error.display_chain());
+            }
+        }
+        connection_id
+    }
+
+    fn update_state(
+        &mut self,
+        state: &TunnelState,
+        hostname: Option<&str>,
+    ) -> Option<ConnectionId> {
+        if !self.enabled {
+            return None;
+        }
+        self.session = None;
+
+        match state {
+            TunnelState::Connecting { .. } => {
+                if let Some(attempt) = self.attempt.take() {
+                    self.record_failure(attempt);
+                }
+                self.attempt = hostname.map(|hostname| Attempt {
+                    hostname: hostname.to_owned(),
+                    started: Instant::now(),
+                });
+                None
+            }
+            TunnelState::Connected { .. } => {
+                let attempt = self.attempt.take()?;
+                let id = ConnectionId(self.next_id);
+                self.next_id += 1;
+                self.session = Some(Session {
+                    id,
+                    hostname: attempt.hostname.clone(),
+                    last_sample: None,
+                });
+                self.record_success(attempt);
+                Some(id)
+            }
+            // The tunnel reconnects when an attempt times out or the tunnel fails to start
+            TunnelState::Error(_)
+            | TunnelState::Disconnecting(ActionAfterDisconnect::Reconnect) => {
+                if let Some(attempt) = self.attempt.take() {
+                    self.record_failure(attempt);
+                }
+                None
+            }
+            // Attempts that are aborted by disconnecting say nothing about the relay
+            TunnelState::Disconnecting(_) | TunnelState::Disconnected => {
+                self.attempt = None;
+                None
+            }
+        }
+    }
+
+    /// Updates the highest observed throughput of the connected relay.
+    pub fn record_traffic(&mut self, id: ConnectionId, counters: TrafficCounters, time: Instant) {
+        let session = match self.session.as_mut() {
+            Some(session) if session.id == id => session,
+            _ => return,
+        };
+        if let Some((last_counters, last_time)) = session.last_sample {
+            let elapsed = time.saturating_duration_since(last_time).as_secs_f64();
+            // Counters that wrapped around or were reset are ignored
+            let transferred = counters
+                .rx_bytes
+                .checked_sub(last_counters.rx_bytes)
+                .and_then(|rx| Some(rx + counters.tx_bytes.checked_sub(last_counters.tx_bytes)?));
+            if let (Some(transferred), true) = (transferred, elapsed > 0.0) {
+                let throughput = (transferred as f64 / elapsed) as u64;
+                if let Some(stats) = self.relays.get_mut(&session.hostname) {
+                    if throughput > stats.peak_throughput {
+                        stats.peak_throughput = throughput;
+                        self.changed = true;
+                    }
+                }
+            }
+        }
+        session.last_sample = Some((counters, time));
+    }
+
+    fn record_success(&mut self, attempt: Attempt) {
+        let connect_time = attempt.started.elapsed().as_millis() as u64;
+        let stats = self.relay_stats(attempt.hostname);
+        stats.attempts += 1;
+        stats.successes += 1;
+        stats.total_connect_time_ms += connect_time;
+    }
+
+    fn record_failure(&mut self, attempt: Attempt) {
+        self.relay_stats(attempt.hostname).attempts += 1;
+    }
+
+    fn relay_stats(&mut self, hostname: String) -> &mut RelayStats {
+        self.changed = true;
+        let stats = self.relays.entry(hostname).or_insert_with(RelayStats::new);
+        stats.last_attempt = Utc::now();
+        stats
+    }
+
+    async fn save(&mut self) -> Result<(), Error> {
+        let oldest = Utc::now() - chrono::Duration::days(MAX_RELAY_AGE_DAYS);
+        self.relays.retain(|_, stats| stats.last_attempt >= oldest);
+
+        let content = serde_json::to_vec_pretty(&self.relays).map_err(Error::Serialize)?;
+        tokio::fs::write(&self.path, content)
+            .await
+            .map_err(Error::Write)?;
+        self.changed = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use talpid_types::net::{Endpoint, TransportProtocol, TunnelEndpoint, TunnelType};
+
+    fn stats(enabled: bool) -> ConnectionStats {
+        ConnectionStats::new(PathBuf::from(STATS_FILE), enabled, BTreeMap::new())
+    }
+
+    fn endpoint() -> TunnelEndpoint {
+        TunnelEndpoint {
+            endpoint: Endpoint::new(std::net::Ipv4Addr::LOCALHOST, 51820, TransportProtocol::Udp),
+            tunnel_type: TunnelType::Wireguard,
+            proxy: None,
+            entry_endpoint: None,
+            obfuscation: None,
+        }
+    }
+
+    fn connecting() -> TunnelState {
+        TunnelState::Connecting {
+            endpoint: endpoint(),
+            location: None,
+            retry_at: None,
+        }
+    }
+
+    fn connected() -> TunnelState {
+        TunnelState::Connected {
+            endpoint: endpoint(),
+            location: None,
+            feature_indicators: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_record_attempts() {
+        let mut stats = stats(true);
+
+        stats.update_state(&connecting(), Some("se-got-001"));
+        stats.update_state(&connecting(), Some("se-got-002"));
+        assert!(stats
+            .update_state(&connected(), Some("se-got-002"))
+            .is_some());
+        stats.update_state(&connecting(), Some("se-got-003"));
+        stats.update_state(
+            &TunnelState::Disconnecting(ActionAfterDisconnect::Reconnect),
+            None,
+        );
+        stats.update_state(&connecting(), Some("se-got-004"));
+        stats.update_state(
+            &TunnelState::Disconnecting(ActionAfterDisconnect::Nothing),
+            None,
+        );
+
+        let first = &stats.relays()["se-got-001"];
+        assert_eq!((first.attempts, first.successes), (1, 0));
+        assert_eq!(first.mean_connect_time(), None);
+        let second = &stats.relays()["se-got-002"];
+        assert_eq!((second.attempts, second.successes), (1, 1));
+        assert!(second.mean_connect_time().is_some());
+        assert_eq!(stats.relays()["se-got-003"].attempts, 1);
+        assert!(!stats.relays().contains_key("se-got-004"));
+    }
+
+    #[test]
+    fn test_record_traffic() {
+        let mut stats = stats(true);
+
+        stats.update_state(&connecting(), Some("se-got-001"));
+        let id = stats
+            .update_state(&connected(), Some("se-got-001"))
+            .unwrap();
+
+        let start = Instant::now();
+        let counters = |rx_bytes, tx_bytes| TrafficCounters { rx_bytes, tx_bytes };
+        stats.record_traffic(id, counters(1000, 1000), start);
+        stats.record_traffic(id, counters(11_000, 3000), start + Duration::from_secs(2));
+        stats.record_traffic(id, counters(0, 0), start + Duration::from_secs(4));
+        assert_eq!(stats.relays()["se-got-001"].peak_throughput, 6000);
+
+        stats.update_state(&connecting(), Some("se-got-002"));
+        stats.record_traffic(
+            id,
+            counters(1_000_000, 1_000_000),
+            start + Duration::from_secs(6),
+        );
+        assert_eq!(stats.relays()["se-got-001"].peak_throughput, 6000);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut stats = stats(false);
+
+        stats.update_state(&connecting(), Some("se-got-001"));
+        assert!(stats
+            .update_state(&connected(), Some("se-got-001"))
+            .is_none());
+        assert!(stats.relays().is_empty());
+    }
+}
@@ -0,0 +1,74 @@
+//! Stores secrets in plain files in a directory that is only accessible to the daemon, for
+//! systems without a usable keystore.
+use super::{CredentialStore, Error};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// The directory in the settings directory that holds the secrets.
+const CREDENTIALS_DIR: &str = "credentials";
+
+/// One file per secret, in a directory next to the settings file.
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(settings_dir: &Path) -> Self {
+        FileStore {
+            dir: settings_dir.join(CREDENTIALS_DIR),
+        }
+    }
+
+    /// Returns a store that keeps its secrets in `dir`.
+    pub fn with_dir(dir: PathBuf) -> Self {
+        FileStore { dir }
+    }
+
+    fn create_dir(&self) -> io::Result<()> {
+        let mut builder = fs::DirBuilder::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+        builder.recursive(true).create(&self.dir)
+    }
+}
+
+impl CredentialStore for FileStore {
+    fn read(&self, name: &str) -> Result<Option<Vec<u8>>, Error> {
+        match fs::read(self.dir.join(name)) {
+            Ok(secret) => Ok(Some(secret)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(Error::File(error)),
+        }
+    }
+
+    fn write(&self, name: &str, secret: &[u8]) -> Result<(), Error> {
+        self.create_dir().map_err(Error::File)?;
+
+        let mut options = fs::OpenOptions::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(self.dir.join(name))
+            .map_err(Error::File)?;
+        io::Write::write_all(&mut file, secret).map_err(Error::File)?;
+        file.sync_all().map_err(Error::File)
+    }
+
+    fn remove(&self, name: &str) -> Result<(), Error> {
+        match fs::remove_file(self.dir.join(name)) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(Error::File(error)),
+            _ => Ok(()),
+        }
+    }
+}
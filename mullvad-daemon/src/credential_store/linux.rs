@@ -0,0 +1,62 @@
+//! Stores secrets in files that only root can access. The Secret Service is tied to the session
+//! bus of a logged in user and can't be reached by the daemon, which runs as a system service.
+use super::{file::FileStore, CredentialStore, Error};
+use std::{
+    fs, io,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+/// The directory in the settings directory that holds the secrets.
+const KEYSTORE_DIR: &str = "keystore";
+
+/// A directory owned by root that is not accessible to anyone else, with one file per secret.
+pub struct Keystore {
+    dir: PathBuf,
+    store: FileStore,
+}
+
+impl Keystore {
+    pub fn new(settings_dir: &Path) -> Self {
+        let dir = settings_dir.join(KEYSTORE_DIR);
+        Keystore {
+            store: FileStore::with_dir(dir.clone()),
+            dir,
+        }
+    }
+
+    /// Refuses to use the keystore unless the directory and the file of the secret, if they
+    /// exist, are owned by root and can't be accessed by other users.
+    fn check_permissions(&self, name: &str) -> Result<(), Error> {
+        for path in &[self.dir.clone(), self.dir.join(name)] {
+            let metadata = match fs::metadata(path) {
+                Ok(metadata) => metadata,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+                Err(error) => return Err(Error::File(error)),
+            };
+            if metadata.uid() != 0 || metadata.permissions().mode() & 0o077 != 0 {
+                return Err(Error::File(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("{} is not private to root", path.display()),
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CredentialStore for Keystore {
+    fn read(&self, name: &str) -> Result<Option<Vec<u8>>, Error> {
+        self.check_permissions(name)?;
+        self.store.read(name)
+    }
+
+    fn write(&self, name: &str, secret: &[u8]) -> Result<(), Error> {
+        self.check_permissions(name)?;
+        self.store.write(name, secret)
+    }
+
+    fn remove(&self, name: &str) -> Result<(), Error> {
+        self.store.remove(name)
+    }
+}
@@ -0,0 +1,43 @@
+//! Stores secrets as generic passwords in the system keychain.
+use super::{CredentialStore, Error};
+use security_framework::passwords::{
+    delete_generic_password, get_generic_password, set_generic_password,
+};
+use std::path::Path;
+
+/// The keychain service of the items. The name of a secret is used as the account.
+const KEYCHAIN_SERVICE: &str = "net.mullvad.vpn";
+
+/// `errSecItemNotFound`
+const ERR_SEC_ITEM_NOT_FOUND: i32 = -25300;
+
+/// The system keychain.
+pub struct Keystore(());
+
+impl Keystore {
+    pub fn new(_settings_dir: &Path) -> Self {
+        Keystore(())
+    }
+}
+
+impl CredentialStore for Keystore {
+    fn read(&self, name: &str) -> Result<Option<Vec<u8>>, Error> {
+        match get_generic_password(KEYCHAIN_SERVICE, name) {
+            Ok(secret) => Ok(Some(secret)),
+            Err(error) if error.code() == ERR_SEC_ITEM_NOT_FOUND => Ok(None),
+            Err(error) => Err(Error::Keychain(error)),
+        }
+    }
+
+    fn write(&self, name: &str, secret: &[u8]) -> Result<(), Error> {
+        set_generic_password(KEYCHAIN_SERVICE, name, secret).map_err(Error::Keychain)
+    }
+
+    fn remove(&self, name: &str) -> Result<(), Error> {
+        match delete_generic_password(KEYCHAIN_SERVICE, name) {
+            Ok(()) => Ok(()),
+            Err(error) if error.code() == ERR_SEC_ITEM_NOT_FOUND => Ok(()),
+            Err(error) => Err(Error::Keychain(error)),
+        }
+    }
+}
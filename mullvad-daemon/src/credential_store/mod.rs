@@ -0,0 +1,245 @@
+//! Storage of secrets outside of the settings file. The account number and the WireGuard key of
+//! the device can be kept in the keystore of the operating system or in a separate file, as
+//! selected by the `credential_storage` setting. The keystore also holds the settings encryption
+//! key.
+use mullvad_types::settings::CredentialStorage;
+use serde_json::Value;
+use std::{collections::HashMap, path::Path};
+
+mod file;
+
+#[cfg(target_os = "linux")]
+mod linux;
+
+#[cfg(target_os = "linux")]
+use linux as imp;
+
+#[cfg(target_os = "macos")]
+mod macos;
+
+#[cfg(target_os = "macos")]
+use macos as imp;
+
+#[cfg(windows)]
+mod windows;
+
+#[cfg(windows)]
+use windows as imp;
+
+pub use file::FileStore;
+
+/// The settings that are kept in the credential store, and the names of their secrets.
+const CREDENTIALS: &[(&str, &str)] = &[
+    ("account_token", "account-token"),
+    ("wireguard", "wireguard"),
+];
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "There is no keystore on this platform")]
+    Unsupported,
+
+    #[cfg(target_os = "macos")]
+    #[error(display = "Failed to access the keychain")]
+    Keychain(#[error(source)] security_framework::base::Error),
+
+    #[cfg(windows)]
+    #[error(display = "Failed to protect or unprotect a secret using DPAPI")]
+    Dpapi(#[error(source)] std::io::Error),
+
+    #[error(display = "Failed to read or write a secret file")]
+    File(#[error(source)] std::io::Error),
+
+    #[error(display = "A stored credential is invalid")]
+    InvalidCredential(#[error(source)] serde_json::Error),
+}
+
+/// Stores secrets by name. Accessing a store may block.
+pub trait CredentialStore: Send {
+    /// Reads a secret. Returns `Ok(None)` if it has not been stored.
+    fn read(&self, name: &str) -> Result<Option<Vec<u8>>, Error>;
+
+    /// Stores a secret, replacing any previous value.
+    fn write(&self, name: &str, secret: &[u8]) -> Result<(), Error>;
+
+    /// Removes a secret. Removing a secret that has not been stored succeeds.
+    fn remove(&self, name: &str) -> Result<(), Error>;
+}
+
+/// Returns the keystore of the operating system.
+#[cfg(any(target_os = "linux", target_os = "macos", windows))]
+pub fn keystore(settings_dir: &Path) -> Result<Box<dyn CredentialStore>, Error> {
+    Ok(Box::new(imp::Keystore::new(settings_dir)))
+}
+
+/// Returns the keystore of the operating system.
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn keystore(_settings_dir: &Path) -> Result<Box<dyn CredentialStore>, Error> {
+    Err(Error::Unsupported)
+}
+
+/// Returns the store that the credentials are kept in, or `None` if they are kept in the settings
+/// file.
+pub fn open(
+    storage: CredentialStorage,
+    settings_dir: &Path,
+) -> Result<Option<Box<dyn CredentialStore>>, Error> {
+    match storage {
+        CredentialStorage::Settings => Ok(None),
+        CredentialStorage::Keystore => keystore(settings_dir).map(Some),
+        CredentialStorage::File => Ok(Some(Box::new(FileStore::new(settings_dir)))),
+    }
+}
+
+/// Serialized credentials, by the name of the setting. Credentials that are not set are `null`.
+pub type Credentials = HashMap<&'static str, Value>;
+
+/// Returns where the serialized `settings` say that the credentials are kept.
+pub fn storage(settings: &Value) -> CredentialStorage {
+    settings
+        .get("credential_storage")
+        .and_then(|storage| serde_json::from_value(storage.clone()).ok())
+        .unwrap_or_default()
+}
+
+/// Removes the credentials from the serialized `settings` and returns them.
+pub fn take_credentials(settings: &mut Value) -> Credentials {
+    CREDENTIALS
+        .iter()
+        .map(|(setting, _)| {
+            let value = settings
+                .as_object_mut()
+                .and_then(|object| object.remove(*setting))
+                .unwrap_or(Value::Null);
+            (*setting, value)
+        })
+        .collect()
+}
+
+/// Reads the credentials in `store` into the serialized `settings`, and returns them. Credentials
+/// that are missing from the store are left as they are in `settings`.
+pub fn read_credentials(
+    store: &dyn CredentialStore,
+    settings: &mut Value,
+) -> Result<Credentials, Error> {
+    let mut credentials = Credentials::new();
+    for (setting, name) in CREDENTIALS {
+        let value = match store.read(name)? {
+            Some(secret) => serde_json::from_slice(&secret).map_err(Error::InvalidCredential)?,
+            None => Value::Null,
+        };
+        if !value.is_null() {
+            if let Some(object) = settings.as_object_mut() {
+                object.insert((*setting).to_owned(), value.clone());
+            }
+        }
+        credentials.insert(*setting, value);
+    }
+    Ok(credentials)
+}
+
+/// Writes `credentials` to `store`. Credentials that are `null` are removed from it, and
+/// credentials that are missing from `credentials` are left as they are.
+pub fn write_credentials(
+    store: &dyn CredentialStore,
+    credentials: &Credentials,
+) -> Result<(), Error> {
+    for (setting, name) in CREDENTIALS {
+        match credentials.get(setting) {
+            Some(Value::Null) => store.remove(name)?,
+            Some(value) => store.write(name, value.to_string().as_bytes())?,
+            None => (),
+        }
+    }
+    Ok(())
+}
+
+/// Removes all credentials from `store`.
+pub fn remove_credentials(store: &dyn CredentialStore) -> Result<(), Error> {
+    let credentials = CREDENTIALS
+        .iter()
+        .map(|(setting, _)| (*setting, Value::Null))
+        .collect();
+    write_credentials(store, &credentials)
+}
+
+/// Returns whether the serialized `settings` contain any credentials.
+pub fn contains_credentials(settings: &Value) -> bool {
+    CREDENTIALS.iter().any(|(setting, _)| {
+        settings
+            .get(setting)
+            .map(|value| !value.is_null())
+            .unwrap_or(false)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+    use std::{collections::BTreeMap, sync::Mutex};
+
+    #[derive(Default)]
+    struct MemoryStore(Mutex<BTreeMap<String, Vec<u8>>>);
+
+    impl CredentialStore for MemoryStore {
+        fn read(&self, name: &str) -> Result<Option<Vec<u8>>, Error> {
+            Ok(self.0.lock().unwrap().get(name).cloned())
+        }
+
+        fn write(&self, name: &str, secret: &[u8]) -> Result<(), Error> {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(name.to_owned(), secret.to_vec());
+            Ok(())
+        }
+
+        fn remove(&self, name: &str) -> Result<(), Error> {
+            self.0.lock().unwrap().remove(name);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_credentials_round_trip() {
+        let original = json!({
+            "account_token": "1234123412341234",
+            "wireguard": { "private_key": "secret" },
+            "allow_lan": true,
+        });
+        let mut settings = original.clone();
+        let credentials = take_credentials(&mut settings);
+        assert_eq!(settings, json!({ "allow_lan": true }));
+
+        let store = MemoryStore::default();
+        write_credentials(&store, &credentials).unwrap();
+        assert_eq!(
+            store.read("account-token").unwrap().unwrap(),
+            b"\"1234123412341234\"".to_vec()
+        );
+
+        assert_eq!(
+            read_credentials(&store, &mut settings).unwrap(),
+            credentials
+        );
+        assert_eq!(settings, original);
+    }
+
+    #[test]
+    fn test_null_credentials_are_removed() {
+        let store = MemoryStore::default();
+        store.write("account-token", b"\"1234\"").unwrap();
+
+        let mut settings = json!({ "account_token": null, "wireguard": null });
+        let credentials = take_credentials(&mut settings);
+        write_credentials(&store, &credentials).unwrap();
+        assert!(store.0.lock().unwrap().is_empty());
+
+        // Credentials in the settings are kept if the store has none
+        let mut settings = json!({ "account_token": "1234" });
+        read_credentials(&store, &mut settings).unwrap();
+        assert_eq!(settings, json!({ "account_token": "1234" }));
+    }
+}
@@ -0,0 +1,118 @@
+//! Protects secrets with DPAPI and stores them next to the settings file. Since the daemon runs
+//! as `SYSTEM`, the secrets can only be unprotected by processes running as `SYSTEM` on this
+//! machine.
+use super::{CredentialStore, Error};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    ptr, slice,
+};
+use winapi::um::{
+    dpapi::{CryptProtectData, CryptUnprotectData, CRYPTPROTECT_UI_FORBIDDEN},
+    winbase::LocalFree,
+    wincrypt::DATA_BLOB,
+};
+
+/// Extension of the files that hold the protected secrets.
+const SECRET_FILE_EXTENSION: &str = "dpapi";
+
+/// DPAPI-protected files in the settings directory.
+pub struct Keystore {
+    dir: PathBuf,
+}
+
+impl Keystore {
+    pub fn new(settings_dir: &Path) -> Self {
+        Keystore {
+            dir: settings_dir.to_owned(),
+        }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name).with_extension(SECRET_FILE_EXTENSION)
+    }
+}
+
+impl CredentialStore for Keystore {
+    fn read(&self, name: &str) -> Result<Option<Vec<u8>>, Error> {
+        let protected = match fs::read(self.path(name)) {
+            Ok(protected) => protected,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(Error::File(error)),
+        };
+        unprotect(&protected).map(Some).map_err(Error::Dpapi)
+    }
+
+    fn write(&self, name: &str, secret: &[u8]) -> Result<(), Error> {
+        let protected = protect(secret).map_err(Error::Dpapi)?;
+        fs::write(self.path(name), protected).map_err(Error::File)
+    }
+
+    fn remove(&self, name: &str) -> Result<(), Error> {
+        match fs::remove_file(self.path(name)) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(Error::File(error)),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn protect(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut input = blob_from_slice(data);
+    let mut output = empty_blob();
+    let result = unsafe {
+        CryptProtectData(
+            &mut input,
+            ptr::null(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            CRYPTPROTECT_UI_FORBIDDEN,
+            &mut output,
+        )
+    };
+    if result == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(take_blob(output))
+}
+
+fn unprotect(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut input = blob_from_slice(data);
+    let mut output = empty_blob();
+    let result = unsafe {
+        CryptUnprotectData(
+            &mut input,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            CRYPTPROTECT_UI_FORBIDDEN,
+            &mut output,
+        )
+    };
+    if result == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(take_blob(output))
+}
+
+fn blob_from_slice(data: &[u8]) -> DATA_BLOB {
+    DATA_BLOB {
+        cbData: data.len() as u32,
+        pbData: data.as_ptr() as *mut u8,
+    }
+}
+
+fn empty_blob() -> DATA_BLOB {
+    DATA_BLOB {
+        cbData: 0,
+        pbData: ptr::null_mut(),
+    }
+}
+
+/// Copies the contents of a blob allocated by DPAPI and frees it.
+fn take_blob(blob: DATA_BLOB) -> Vec<u8> {
+    let data = unsafe { slice::from_raw_parts(blob.pbData, blob.cbData as usize) }.to_vec();
+    unsafe { LocalFree(blob.pbData as *mut _) };
+    data
+}
@@ -0,0 +1,244 @@
+//! A small D-Bus service on the system bus, so that desktop applets and other tooling can follow
+//! and control the tunnel without speaking the management interface protocol. Only the tunnel
+//! state, connecting and disconnecting, and the events that are also sent to the system log are
+//! exposed.
+use crate::{system_log::SystemLogEvent, DaemonCommand, DaemonEventSender};
+use dbus::{
+    blocking::{stdintf::org_freedesktop_dbus::RequestNameReply, Connection},
+    channel::{MatchingReceiver, Sender as _},
+    message::MatchRule,
+    Message, MethodErr,
+};
+use futures::channel::oneshot;
+use mullvad_types::states::{TargetState, TunnelState};
+use std::{
+    sync::{mpsc, Arc, Mutex},
+    time::Duration,
+};
+use talpid_core::mpsc::Sender;
+use talpid_types::{tunnel::ActionAfterDisconnect, ErrorExt};
+
+const BUS_NAME: &str = "org.mullvad.vpn";
+const OBJECT_PATH: &str = "/org/mullvad/vpn";
+const INTERFACE: &str = "org.mullvad.vpn";
+const INTROSPECTABLE_INTERFACE: &str = "org.freedesktop.DBus.Introspectable";
+
+/// How long to wait for D-Bus messages before checking for events to emit.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+const INTROSPECTION_XML: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.mullvad.vpn">
+    <method name="Connect">
+      <arg name="issued" type="b" direction="out"/>
+    </method>
+    <method name="Disconnect">
+      <arg name="issued" type="b" direction="out"/>
+    </method>
+    <method name="Reconnect">
+      <arg name="issued" type="b" direction="out"/>
+    </method>
+    <method name="GetTunnelState">
+      <arg name="state" type="s" direction="out"/>
+    </method>
+    <signal name="TunnelStateChanged">
+      <arg name="state" type="s"/>
+    </signal>
+    <signal name="Notification">
+      <arg name="kind" type="s"/>
+      <arg name="message" type="s"/>
+    </signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml_data" type="s" direction="out"/>
+    </method>
+  </interface>
+</node>
+"#;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to connect to the system bus")]
+    Connect(#[error(source)] dbus::Error),
+
+    #[error(display = "Failed to request the bus name {}", BUS_NAME)]
+    RequestName(#[error(source)] dbus::Error),
+
+    #[error(display = "The bus name {} is already taken", BUS_NAME)]
+    NameTaken,
+
+    #[error(display = "Failed to spawn the D-Bus service thread")]
+    SpawnThread(#[error(source)] std::io::Error),
+}
+
+enum Event {
+    TunnelState(&'static str),
+    Notification(&'static str, String),
+}
+
+/// Runs the D-Bus service until dropped.
+pub struct DbusService {
+    events_tx: mpsc::Sender<Event>,
+    tunnel_state: Arc<Mutex<&'static str>>,
+}
+
+impl DbusService {
+    pub fn start(
+        command_tx: DaemonEventSender<DaemonCommand>,
+        tunnel_state: &TunnelState,
+    ) -> Result<Self, Error> {
+        let connection = Connection::new_system().map_err(Error::Connect)?;
+        let reply = connection
+            .request_name(BUS_NAME, false, false, true)
+            .map_err(Error::RequestName)?;
+        if reply != RequestNameReply::PrimaryOwner {
+            return Err(Error::NameTaken);
+        }
+
+        let tunnel_state = Arc::new(Mutex::new(state_name(tunnel_state)));
+        let handler = MethodHandler {
+            command_tx,
+            tunnel_state: tunnel_state.clone(),
+        };
+        connection.start_receive(
+            MatchRule::new_method_call(),
+            Box::new(move |message, connection| {
+                if message.path().as_deref() == Some(OBJECT_PATH) {
+                    let reply = handler.handle(&message);
+                    if connection.send(reply).is_err() {
+                        log::error!("Failed to send D-Bus reply");
+                    }
+                }
+                true
+            }),
+        );
+
+        let (events_tx, events_rx) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("dbus-service".to_owned())
+            .spawn(move || run(connection, events_rx))
+            .map_err(Error::SpawnThread)?;
+
+        log::info!("Providing the D-Bus service {}", BUS_NAME);
+        Ok(DbusService {
+            events_tx,
+            tunnel_state,
+        })
+    }
+
+    /// Updates the tunnel state and emits the `TunnelStateChanged` signal.
+    pub fn notify_tunnel_state(&self, tunnel_state: &TunnelState) {
+        let state = state_name(tunnel_state);
+        *self.tunnel_state.lock().unwrap() = state;
+        let _ = self.events_tx.send(Event::TunnelState(state));
+    }
+
+    /// Emits the `Notification` signal.
+    pub fn notify_event(&self, event: &SystemLogEvent) {
+        let _ = self
+            .events_tx
+            .send(Event::Notification(event.name(), event.to_string()));
+    }
+}
+
+fn run(connection: Connection, events_rx: mpsc::Receiver<Event>) {
+    loop {
+        if let Err(error) = connection.process(POLL_INTERVAL) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("D-Bus service connection failed")
+            );
+            return;
+        }
+        loop {
+            let message = match events_rx.try_recv() {
+                Ok(Event::TunnelState(state)) => {
+                    Message::new_signal(OBJECT_PATH, INTERFACE, "TunnelStateChanged")
+                        .map(|signal| signal.append1(state))
+                }
+                Ok(Event::Notification(kind, message)) => {
+                    Message::new_signal(OBJECT_PATH, INTERFACE, "Notification")
+                        .map(|signal| signal.append2(kind, message))
+                }
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    log::debug!("Stopping the D-Bus service");
+                    return;
+                }
+            };
+            match message {
+                Ok(signal) => {
+                    if connection.send(signal).is_err() {
+                        log::error!("Failed to emit D-Bus signal");
+                    }
+                }
+                Err(error) => log::error!("Failed to create D-Bus signal: {}", error),
+            }
+        }
+    }
+}
+
+struct MethodHandler {
+    command_tx: DaemonEventSender<DaemonCommand>,
+    tunnel_state: Arc<Mutex<&'static str>>,
+}
+
+impl MethodHandler {
+    fn handle(&self, message: &Message) -> Message {
+        let interface = message.interface();
+        let member = message.member();
+        let member = member.as_deref().unwrap_or_default();
+        let result = match interface.as_deref() {
+            Some(INTROSPECTABLE_INTERFACE) if member == "Introspect" => {
+                Ok(message.method_return().append1(INTROSPECTION_XML))
+            }
+            // The interface is optional in method calls
+            Some(INTERFACE) | None => match member {
+                "Connect" => self
+                    .send_command(|tx| DaemonCommand::SetTargetState(tx, TargetState::Secured))
+                    .map(|issued| message.method_return().append1(issued)),
+                "Disconnect" => self
+                    .send_command(|tx| DaemonCommand::SetTargetState(tx, TargetState::Unsecured))
+                    .map(|issued| message.method_return().append1(issued)),
+                "Reconnect" => self
+                    .send_command(DaemonCommand::Reconnect)
+                    .map(|issued| message.method_return().append1(issued)),
+                "GetTunnelState" => {
+                    let state = *self.tunnel_state.lock().unwrap();
+                    Ok(message.method_return().append1(state))
+                }
+                _ => Err(MethodErr::no_method(&member)),
+            },
+            Some(_) => Err(MethodErr::no_method(&member)),
+        };
+        result.unwrap_or_else(|error| error.to_message(message))
+    }
+
+    fn send_command(
+        &self,
+        make_command: impl FnOnce(oneshot::Sender<bool>) -> DaemonCommand,
+    ) -> Result<bool, MethodErr> {
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(make_command(tx))
+            .map_err(|_| MethodErr::failed(&"The daemon is shutting down"))?;
+        futures::executor::block_on(rx)
+            .map_err(|_| MethodErr::failed(&"The daemon did not respond"))
+    }
+}
+
+/// Returns the name of the state, as exposed over D-Bus.
+fn state_name(tunnel_state: &TunnelState) -> &'static str {
+    match tunnel_state {
+        TunnelState::Disconnected => "disconnected",
+        TunnelState::Connecting { .. } => "connecting",
+        TunnelState::Connected { .. } => "connected",
+        TunnelState::Disconnecting(ActionAfterDisconnect::Reconnect) => "reconnecting",
+        TunnelState::Disconnecting(_) => "disconnecting",
+        TunnelState::Error(error_state) if error_state.is_blocking() => "blocked",
+        TunnelState::Error(_) => "error",
+    }
+}
@@ -0,0 +1,318 @@
+//! Information that is included in problem reports, so that they can be triaged without reading
+//! through the logs. Secrets such as the account number and keys are never included.
+use crate::settings_encryption;
+use chrono::{DateTime, Utc};
+use mullvad_types::{
+    obfuscation::ObfuscationMode,
+    relay_constraints::{BridgeState, RelaySettings},
+    settings::{CredentialStorage, DnsState, LogPrivacyLevel, Settings},
+    states::TunnelState,
+};
+use std::collections::VecDeque;
+use talpid_core::{connection_trace::Attempt, firewall::FirewallRules};
+use talpid_types::tunnel::ActionAfterDisconnect;
+
+/// Maximum number of tunnel state transitions that are remembered.
+const MAX_STATE_TRANSITIONS: usize = 32;
+
+/// Information that is included in problem reports.
+#[derive(Clone, Debug)]
+pub struct Diagnostics {
+    /// The settings, with sensitive values redacted.
+    pub settings: serde_json::Value,
+    pub enabled_features: Vec<&'static str>,
+    /// The most recent tunnel states, oldest first.
+    pub state_transitions: Vec<StateTransition>,
+    /// Whether the settings directory is read-only, so that settings cannot be changed.
+    pub read_only_settings: bool,
+    /// The redacted fingerprint of the network that the host is on, if it is known.
+    pub network: Option<String>,
+    /// The capabilities that the daemon was found to be missing when it started.
+    pub missing_capabilities: Vec<String>,
+}
+
+impl Diagnostics {
+    /// Returns the fields that are added to the manifest of a problem report. They are the same
+    /// as the ones that the problem report tool adds when it collects a report.
+    pub fn into_manifest_fields(self) -> serde_json::Map<String, serde_json::Value> {
+        let state_transitions = self
+            .state_transitions
+            .into_iter()
+            .map(|transition| {
+                serde_json::json!({
+                    "timestamp": transition.timestamp.timestamp(),
+                    "state": transition.state,
+                })
+            })
+            .collect();
+
+        let mut fields = serde_json::Map::new();
+        fields.insert(
+            "enabled_features".to_owned(),
+            serde_json::json!(self.enabled_features),
+        );
+        fields.insert("settings".to_owned(), self.settings);
+        fields.insert(
+            "state_transitions".to_owned(),
+            serde_json::Value::Array(state_transitions),
+        );
+        fields.insert(
+            "read_only_settings".to_owned(),
+            serde_json::Value::Bool(self.read_only_settings),
+        );
+        fields.insert("network".to_owned(), serde_json::json!(self.network));
+        fields.insert(
+            "missing_capabilities".to_owned(),
+            serde_json::json!(self.missing_capabilities),
+        );
+        fields
+    }
+}
+
+/// A tunnel state that the daemon entered.
+#[derive(Clone, Debug)]
+pub struct StateTransition {
+    pub timestamp: DateTime<Utc>,
+    /// A short description of the state, without any addresses.
+    pub state: String,
+}
+
+/// The most recent tunnel state transitions, oldest first.
+#[derive(Debug, Default)]
+pub struct StateHistory {
+    transitions: VecDeque<StateTransition>,
+}
+
+impl StateHistory {
+    pub fn record(&mut self, state: &TunnelState) {
+        if self.transitions.len() >= MAX_STATE_TRANSITIONS {
+            self.transitions.pop_front();
+        }
+        self.transitions.push_back(StateTransition {
+            timestamp: Utc::now(),
+            state: describe_state(state),
+        });
+    }
+
+    pub fn get(&self) -> Vec<StateTransition> {
+        self.transitions.iter().cloned().collect()
+    }
+}
+
+fn describe_state(state: &TunnelState) -> String {
+    match state {
+        TunnelState::Disconnected => "disconnected".to_owned(),
+        TunnelState::Connecting { endpoint, .. } => {
+            format!("connecting ({})", endpoint.tunnel_type)
+        }
+        TunnelState::Connected { endpoint, .. } => format!("connected ({})", endpoint.tunnel_type),
+        TunnelState::Disconnecting(after_disconnect) => {
+            let after_disconnect = match after_disconnect {
+                ActionAfterDisconnect::Nothing => "disconnect",
+                ActionAfterDisconnect::Block => "block",
+                ActionAfterDisconnect::Reconnect => "reconnect",
+            };
+            format!("disconnecting (then {})", after_disconnect)
+        }
+        TunnelState::Error(error_state) => {
+            let blocking = if error_state.is_blocking() {
+                "blocking"
+            } else {
+                "not blocking"
+            };
+            format!("error ({}): {}", blocking, error_state.cause())
+        }
+    }
+}
+
+/// Returns the settings as they are stored in the settings file, with all sensitive values
+/// replaced.
+pub fn redacted_settings(settings: &Settings) -> serde_json::Value {
+    let mut settings = serde_json::to_value(settings).unwrap_or(serde_json::Value::Null);
+    settings_encryption::redact_values(&mut settings);
+    settings
+}
+
+/// Returns the names of the features that are turned on in the settings.
+pub fn enabled_features(settings: &Settings) -> Vec<&'static str> {
+    let mut features = vec![];
+    let mut add = |enabled: bool, feature| {
+        if enabled {
+            features.push(feature);
+        }
+    };
+
+    match settings.get_relay_settings() {
+        RelaySettings::CustomTunnelEndpoint(_) => add(true, "custom_tunnel_endpoint"),
+        RelaySettings::Normal(constraints) => {
+            add(
+                constraints.wireguard_constraints.entry_location.is_some(),
+                "multihop",
+            );
+            add(constraints.filter.is_only(), "relay_filter");
+        }
+    }
+    add(settings.get_bridge_state() == BridgeState::On, "bridge");
+    add(
+        settings.obfuscation_settings.mode != ObfuscationMode::Off,
+        "obfuscation",
+    );
+    add(settings.connection_profiles, "connection_profiles");
+    add(settings.allow_lan, "allow_lan");
+    add(settings.block_when_disconnected, "block_when_disconnected");
+    add(
+        settings.block_when_disconnected && settings.persistent_lockdown,
+        "persistent_lockdown",
+    );
+    add(settings.auto_connect, "auto_connect");
+    add(
+        settings.tunnel_options.dns_options.state == DnsState::Custom,
+        "custom_dns",
+    );
+    add(
+        settings.tunnel_options.dns_options.state == DnsState::Encrypted,
+        "encrypted_dns",
+    );
+    add(
+        settings.tunnel_options.dns_options.state == DnsState::LocalForwarder,
+        "local_dns_forwarder",
+    );
+    add(settings.tunnel_options.generic.enable_ipv6, "ipv6");
+    add(
+        settings
+            .tunnel_options
+            .wireguard
+            .options
+            .icmp_check_interval
+            .is_some(),
+        "wireguard_icmp_check",
+    );
+    add(
+        settings
+            .tunnel_options
+            .wireguard
+            .options
+            .packet_loss_threshold
+            .is_some(),
+        "wireguard_packet_loss_reconnect",
+    );
+    add(
+        settings
+            .api_access_methods
+            .iter()
+            .any(|method| method.enabled),
+        "api_proxy",
+    );
+    add(settings.show_beta_releases, "beta_releases");
+    add(
+        settings.encrypt_sensitive_settings,
+        "encrypt_sensitive_settings",
+    );
+    add(
+        settings.credential_storage == CredentialStorage::Keystore,
+        "keystore_credentials",
+    );
+    add(
+        settings.credential_storage == CredentialStorage::File,
+        "file_credentials",
+    );
+    add(settings.keep_account_history, "keep_account_history");
+    add(settings.account_privacy_mode, "account_privacy_mode");
+    add(settings.port_forwarding, "port_forwarding");
+    add(settings.connection_statistics, "connection_statistics");
+    add(settings.system_log, "system_log");
+    add(settings.json_logs, "json_logs");
+    add(settings.server_mode, "server_mode");
+    add(
+        settings.log_privacy == LogPrivacyLevel::Redacted,
+        "redacted_logs",
+    );
+    add(
+        settings.log_privacy == LogPrivacyLevel::Minimal,
+        "minimal_logs",
+    );
+    add(settings.api_clock_check, "api_clock_check");
+    add(settings.lan_proxy.enabled, "lan_proxy");
+    add(settings.local_api.enabled, "local_api");
+    add(
+        settings.hooks.pre_up.is_some()
+            || settings.hooks.post_up.is_some()
+            || settings.hooks.post_down.is_some(),
+        "hook_scripts",
+    );
+    add(settings.mdns_reflector, "mdns_reflector");
+    add(settings.allowed_system_services.ntp, "blocked_ntp");
+    add(
+        settings.allowed_system_services.captive_portal_detection,
+        "blocked_captive_portal_detection",
+    );
+    add(
+        !settings.tunnel_parameter_overrides.is_empty(),
+        "tunnel_parameter_overrides",
+    );
+    add(
+        !settings.firewall_exceptions.is_empty(),
+        "firewall_exceptions",
+    );
+    #[cfg(target_os = "linux")]
+    add(settings.dbus_service, "dbus_service");
+    #[cfg(windows)]
+    add(!settings.allow_virtual_networks, "block_virtual_networks");
+    #[cfg(any(windows, target_os = "linux"))]
+    add(settings.split_tunnel.enable_exclusions, "split_tunnel");
+    #[cfg(windows)]
+    add(settings.tunnel_options.openvpn.use_ovpn_dco, "ovpn_dco");
+    #[cfg(target_os = "linux")]
+    add(
+        settings
+            .tunnel_options
+            .wireguard
+            .options
+            .use_userspace_wireguard,
+        "userspace_wireguard",
+    );
+
+    features
+}
+
+/// Describes the connection attempts for a problem report, one line per attempt and phase.
+pub fn format_connection_attempts(attempts: &[Attempt]) -> String {
+    let mut lines = vec![];
+    for attempt in attempts {
+        let outcome = match (&attempt.outcome, attempt.duration) {
+            (Some(outcome), Some(duration)) => {
+                format!("{} after {} ms", outcome, duration.as_millis())
+            }
+            _ => "in progress".to_owned(),
+        };
+        lines.push(format!(
+            "Attempt {} at {}: {}",
+            attempt.id,
+            attempt.timestamp.format("%Y-%m-%d %H:%M:%S%.3f"),
+            outcome
+        ));
+        for span in &attempt.spans {
+            let mut line = format!(
+                "  +{} ms {} ({} ms)",
+                span.offset.as_millis(),
+                span.phase,
+                span.duration.as_millis()
+            );
+            if let Some(error) = &span.error {
+                line.push_str(&format!(" failed: {}", error));
+            }
+            lines.push(line);
+        }
+    }
+    lines.join("\n")
+}
+
+/// Describes the rules that the firewall has in place for a problem report.
+pub fn format_firewall_rules(rules: &FirewallRules) -> String {
+    let mut lines = vec![
+        format!("Backend: {}", rules.backend),
+        format!("Policy: {}", rules.policy.as_deref().unwrap_or("none")),
+    ];
+    lines.extend(rules.rules.iter().cloned());
+    lines.join("\n")
+}
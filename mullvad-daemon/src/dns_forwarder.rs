@@ -0,0 +1,213 @@
+//! Detection of DNS forwarders on this device, such as dnscrypt-proxy or stubby, that can be used
+//! as the DNS server while connected. A forwarder is detected by sending it a DNS query on each of
+//! the loopback addresses that such forwarders commonly listen on.
+use futures::future::join_all;
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::net::UdpSocket;
+
+/// The loopback addresses that forwarders listen on by default. 127.0.0.53 is left out, since it
+/// belongs to systemd-resolved, which the daemon configures itself.
+const CANDIDATES: &[IpAddr] = &[
+    IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+    IpAddr::V4(Ipv4Addr::new(127, 0, 2, 1)),
+    IpAddr::V6(Ipv6Addr::LOCALHOST),
+];
+
+/// How long to wait for the reply from a forwarder.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// A DNS forwarder that replied to a query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DnsForwarder {
+    /// The loopback address that the forwarder listens on, on port 53.
+    pub address: IpAddr,
+    /// The name of the program that listens on the address, if it could be found.
+    pub program: Option<String>,
+}
+
+/// Returns the forwarders that reply to DNS queries. `ignored` is left out, which is used for the
+/// encrypted DNS proxy of the daemon.
+pub async fn detect(ignored: Option<IpAddr>) -> Vec<DnsForwarder> {
+    let probes = CANDIDATES
+        .iter()
+        .copied()
+        .filter(|address| Some(*address) != ignored)
+        .enumerate()
+        .map(|(index, address)| async move {
+            // The IDs only have to differ between the probes
+            let id = 0x4d56 + index as u16;
+            if probe(SocketAddr::new(address, 53), id).await {
+                Some(DnsForwarder {
+                    address,
+                    program: program_name(address),
+                })
+            } else {
+                None
+            }
+        });
+    join_all(probes).await.into_iter().flatten().collect()
+}
+
+/// Returns whether a DNS server listening on `address` replies to a query.
+async fn probe(address: SocketAddr, id: u16) -> bool {
+    let bind_address = match address {
+        SocketAddr::V4(_) => SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0),
+        SocketAddr::V6(_) => SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 0),
+    };
+    let socket = match UdpSocket::bind(bind_address).await {
+        Ok(socket) => socket,
+        Err(_) => return false,
+    };
+    if socket.connect(address).await.is_err() || socket.send(&query(id)).await.is_err() {
+        return false;
+    }
+    let mut buffer = [0u8; 512];
+    match tokio::time::timeout(PROBE_TIMEOUT, socket.recv(&mut buffer)).await {
+        Ok(Ok(len)) => is_response(&buffer[..len], id),
+        _ => false,
+    }
+}
+
+/// Returns a query for the SOA record of the root zone. Any forwarder can answer it, and its
+/// answer is never empty.
+fn query(id: u16) -> Vec<u8> {
+    let mut query = Vec::with_capacity(17);
+    query.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired
+    query.extend_from_slice(&[0x01, 0x00]);
+    // One question, no answers or other records
+    query.extend_from_slice(&[0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    // The root name, type SOA, class IN
+    query.extend_from_slice(&[0x00, 0x00, 0x06, 0x00, 0x01]);
+    query
+}
+
+/// Returns whether `packet` is a response to the query with the given ID. The response code does
+/// not matter, since even a refusal means that a DNS server is listening.
+fn is_response(packet: &[u8], id: u16) -> bool {
+    packet.len() >= 12 && packet[0..2] == id.to_be_bytes() && packet[2] & 0x80 != 0
+}
+
+/// Returns the name of the program that has a UDP socket bound to port 53 on `address`.
+#[cfg(target_os = "linux")]
+fn program_name(address: IpAddr) -> Option<String> {
+    let table = match address {
+        IpAddr::V4(_) => "/proc/net/udp",
+        IpAddr::V6(_) => "/proc/net/udp6",
+    };
+    let sockets = std::fs::read_to_string(table).ok()?;
+    let inode = sockets
+        .lines()
+        .skip(1)
+        .find_map(|line| socket_inode(line, address))?;
+    let link = format!("socket:[{}]", inode);
+
+    for process in std::fs::read_dir("/proc").ok()?.flatten() {
+        let fds = match std::fs::read_dir(process.path().join("fd")) {
+            Ok(fds) => fds,
+            Err(_) => continue,
+        };
+        let owns_socket = fds.flatten().any(|fd| {
+            std::fs::read_link(fd.path())
+                .map(|target| target.as_os_str() == link.as_str())
+                .unwrap_or(false)
+        });
+        if owns_socket {
+            return std::fs::read_to_string(process.path().join("comm"))
+                .ok()
+                .map(|name| name.trim().to_owned());
+        }
+    }
+    None
+}
+
+#[cfg(not(target_os = "linux"))]
+fn program_name(_address: IpAddr) -> Option<String> {
+    None
+}
+
+/// Returns the inode of the socket on a line of `/proc/net/udp` or `/proc/net/udp6`, if the socket
+/// is bound to port 53 on `address` or on the unspecified address.
+#[cfg(any(target_os = "linux", test))]
+fn socket_inode(line: &str, address: IpAddr) -> Option<u64> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let mut local_address = fields.get(1)?.splitn(2, ':');
+    let (local_address, port) = (local_address.next()?, local_address.next()?);
+    if u16::from_str_radix(port, 16).ok()? != 53 {
+        return None;
+    }
+    let local_address = parse_proc_address(local_address)?;
+    if local_address != address && !local_address.is_unspecified() {
+        return None;
+    }
+    fields.get(9)?.parse().ok()
+}
+
+/// Parses an address in the format of `/proc/net/udp`, where each 32-bit word is printed as a
+/// native-endian integer.
+#[cfg(any(target_os = "linux", test))]
+fn parse_proc_address(hex: &str) -> Option<IpAddr> {
+    let mut bytes = Vec::with_capacity(16);
+    for word in 0..hex.len() / 8 {
+        let word = u32::from_str_radix(hex.get(word * 8..word * 8 + 8)?, 16).ok()?;
+        bytes.extend_from_slice(&word.to_ne_bytes());
+    }
+    match bytes.len() {
+        4 => Some(IpAddr::from([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&bytes);
+            Some(IpAddr::from(octets))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_query() {
+        let query = query(0x1234);
+        assert_eq!(&query[0..2], &[0x12, 0x34]);
+        assert_eq!(query.len(), 17);
+
+        let mut response = query.clone();
+        assert!(!is_response(&response, 0x1234));
+        response[2] |= 0x80;
+        assert!(is_response(&response, 0x1234));
+        assert!(!is_response(&response, 0x1235));
+        assert!(!is_response(&response[..11], 0x1234));
+    }
+
+    #[test]
+    fn test_socket_inode() {
+        let localhost = u32::from_ne_bytes([127, 0, 2, 1]);
+        let line = format!(
+            "  12: {:08X}:0035 00000000:0000 07 00000000:00000000 00:00000000 00000000   \
+             0        0 31337 2 0000000000000000 0",
+            localhost
+        );
+        assert_eq!(
+            socket_inode(&line, "127.0.2.1".parse().unwrap()),
+            Some(31337)
+        );
+        assert_eq!(socket_inode(&line, "127.0.0.1".parse().unwrap()), None);
+
+        let wildcard = line.replace(&format!("{:08X}", localhost), "00000000");
+        assert_eq!(
+            socket_inode(&wildcard, "127.0.0.1".parse().unwrap()),
+            Some(31337)
+        );
+
+        let other_port = line.replace(":0035", ":0036");
+        assert_eq!(
+            socket_inode(&other_port, "127.0.2.1".parse().unwrap()),
+            None
+        );
+    }
+}
@@ -0,0 +1,71 @@
+//! Periodic checks, while the tunnel is connected, that traffic exits through the relay that the
+//! tunnel is connected to. Relays can in rare cases start sending traffic out through another
+//! address than their own, which users otherwise only notice by checking a third-party site.
+use crate::{geoip, DaemonEventSender};
+use futures::future::{abortable, AbortHandle};
+use mullvad_rpc::rest::RequestServiceHandle;
+use mullvad_types::{
+    location::{ExitIpMismatch, Hostname, Location},
+    settings::ExitIpCheckSettings,
+};
+use std::{net::IpAddr, time::Duration};
+use talpid_core::mpsc::Sender;
+use talpid_types::ErrorExt;
+
+/// How long after connecting the first check is made. Gives the tunnel time to settle, since
+/// traffic is not always routed through it right away.
+const FIRST_CHECK_DELAY: Duration = Duration::from_secs(10);
+
+/// Starts checking the exit IP at the interval in `settings`, until the returned handle is
+/// aborted. `hostname` and `location` belong to the relay that the tunnel is connected to. Each
+/// check that finds a problem is sent to the daemon, which decides whether to reconnect.
+pub fn spawn(
+    settings: &ExitIpCheckSettings,
+    hostname: Hostname,
+    location: Option<Location>,
+    request_sender: RequestServiceHandle,
+    daemon_tx: DaemonEventSender<ExitIpMismatch>,
+) -> AbortHandle {
+    let interval = settings.interval.as_duration();
+    let (future, abort_handle) = abortable(async move {
+        let mut previous_exit_ip: Option<IpAddr> = None;
+        let mut delay = FIRST_CHECK_DELAY;
+        loop {
+            tokio::time::sleep(delay).await;
+            delay = interval;
+
+            let check = match geoip::send_exit_ip_check(
+                request_sender.clone(),
+                Some((hostname.clone(), location.clone())),
+            )
+            .await
+            {
+                Ok(check) => check,
+                Err(error) => {
+                    log::warn!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to check the exit IP")
+                    );
+                    continue;
+                }
+            };
+
+            if let Some(problem) = check.exit_ip_problem(previous_exit_ip) {
+                let mismatch = ExitIpMismatch {
+                    expected_hostname: hostname.clone(),
+                    exit_ip: check.exit_ip,
+                    problem,
+                    reconnecting: false,
+                };
+                if daemon_tx.send(mismatch).is_err() {
+                    return;
+                }
+            }
+            if check.mullvad_exit_ip {
+                previous_exit_ip = Some(check.exit_ip);
+            }
+        }
+    });
+    tokio::spawn(future);
+    abort_handle
+}
@@ -1,13 +1,22 @@
-use futures::join;
+use futures::{future, join};
 use mullvad_rpc::{
     self,
     rest::{Error, RequestServiceHandle},
 };
-use mullvad_types::location::{AmIMullvad, GeoIpLocation};
+use mullvad_types::location::{
+    AmIMullvad, AmIMullvadDnsServer, ConnectionCheck, DnsServerCheck, GeoIpLocation, Hostname,
+    Location,
+};
+use serde::de::DeserializeOwned;
 use talpid_types::ErrorExt;
 
 const URI_V4: &str = "https://ipv4.am.i.mullvad.net/json";
 const URI_V6: &str = "https://ipv6.am.i.mullvad.net/json";
+const URI_CHECK: &str = "https://am.i.mullvad.net/json";
+const DNS_LEAK_DOMAIN: &str = "dnsleak.am.i.mullvad.net";
+/// Number of unique hostnames that are looked up by the DNS leak check. Each lookup may be
+/// answered by a different resolver, so more than one is needed to find all of them.
+const DNS_LEAK_LOOKUPS: usize = 3;
 
 pub async fn send_location_request(
     request_sender: RequestServiceHandle,
@@ -43,13 +52,80 @@ pub async fn send_location_request(
     }
 }
 
+/// Checks where traffic from the daemon exits, using whichever IP version the host prefers, and
+/// which DNS servers resolve its hostnames. `expected_relay` is the hostname and location of the
+/// relay that the daemon is connected to, if any.
+pub async fn send_connection_check(
+    request_sender: RequestServiceHandle,
+    expected_relay: Option<(Hostname, Option<Location>)>,
+) -> Result<ConnectionCheck, Error> {
+    let (response, dns_servers) = join!(
+        send_location_request_internal(URI_CHECK, request_sender.clone()),
+        send_dns_leak_check(request_sender)
+    );
+    let dns_servers = match dns_servers {
+        Ok(servers) => Some(servers),
+        Err(error) => {
+            log::warn!("{}", error.display_chain_with_msg("DNS leak check failed"));
+            None
+        }
+    };
+    Ok(ConnectionCheck::new(response?, expected_relay, dns_servers))
+}
+
+/// Checks where traffic from the daemon exits, like [`send_connection_check`], but without
+/// checking which DNS servers are used.
+pub async fn send_exit_ip_check(
+    request_sender: RequestServiceHandle,
+    expected_relay: Option<(Hostname, Option<Location>)>,
+) -> Result<ConnectionCheck, Error> {
+    let response = send_location_request_internal(URI_CHECK, request_sender).await?;
+    Ok(ConnectionCheck::new(response, expected_relay, None))
+}
+
+/// Looks up unique subdomains of the DNS leak domain. Its name servers record which resolvers
+/// asked for each subdomain, which is then returned when the subdomain is requested. Succeeds if
+/// any of the lookups did.
+async fn send_dns_leak_check(service: RequestServiceHandle) -> Result<Vec<DnsServerCheck>, Error> {
+    let lookups = (0..DNS_LEAK_LOOKUPS).map(|_| {
+        let uri = format!("https://{}.{}/", uuid::Uuid::new_v4(), DNS_LEAK_DOMAIN);
+        let service = service.clone();
+        async move { send_json_request::<Vec<AmIMullvadDnsServer>>(&uri, service).await }
+    });
+
+    let mut servers: Vec<DnsServerCheck> = vec![];
+    let mut last_error = None;
+    for result in future::join_all(lookups).await {
+        match result {
+            Ok(found) => {
+                for server in found {
+                    if !servers.iter().any(|known| known.ip == server.ip) {
+                        servers.push(DnsServerCheck::from(server));
+                    }
+                }
+            }
+            Err(error) => last_error = Some(error),
+        }
+    }
+    match last_error {
+        Some(error) if servers.is_empty() => Err(error),
+        _ => Ok(servers),
+    }
+}
+
 async fn send_location_request_internal(
     uri: &'static str,
     service: RequestServiceHandle,
 ) -> Result<AmIMullvad, Error> {
-    let future_service = service.clone();
+    send_json_request(uri, service).await
+}
+
+async fn send_json_request<T: DeserializeOwned>(
+    uri: &str,
+    service: RequestServiceHandle,
+) -> Result<T, Error> {
     let request = mullvad_rpc::rest::RestRequest::get(uri)?;
-    let response = future_service.request(request).await?;
+    let response = service.request(request).await?;
     mullvad_rpc::rest::deserialize_body(response).await
 }
 
@@ -0,0 +1,421 @@
+//! Runs the scripts that the user has configured to be run when the tunnel connects and
+//! disconnects. Scripts are run one at a time, in the order that the tunnel states were entered
+//! in, with a clean environment and without input. Their output is written to the daemon log.
+//! Since the scripts are run with the privileges of the daemon, they are only run if no one but
+//! administrators can change them.
+use futures::{
+    channel::{mpsc, oneshot},
+    StreamExt,
+};
+use mullvad_types::{settings::HookSettings, states::TunnelState};
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Weak,
+    time::Duration,
+};
+use talpid_core::{
+    tunnel::TunnelMetadata,
+    tunnel_state_machine::{TunnelCommand, TunnelCommandSender},
+};
+use talpid_types::ErrorExt;
+use tokio::process::Command;
+
+/// The search path that scripts are run with.
+#[cfg(unix)]
+const SCRIPT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// The script or a directory that it is in could not be read.
+    #[error(display = "Failed to read the permissions of {}", _0)]
+    ReadPermissions(String, #[error(source)] io::Error),
+
+    /// Users other than administrators can change the script.
+    #[error(display = "{} can be changed by users other than administrators", _0)]
+    InsecurePermissions(String),
+
+    /// The script could not be started.
+    #[error(display = "Failed to start the script")]
+    Start(#[error(source)] io::Error),
+
+    /// The script could not be waited for.
+    #[error(display = "Failed to wait for the script to finish")]
+    Wait(#[error(source)] io::Error),
+
+    /// The script exited with an error.
+    #[error(display = "The script failed with {}", _0)]
+    Failed(String),
+
+    /// The script ran for longer than allowed, and was stopped.
+    #[error(display = "The script did not finish within {} seconds", _0)]
+    Timeout(u64),
+}
+
+/// A point in the life of the tunnel at which a script can be run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    /// The tunnel started connecting after having been disconnected.
+    PreUp,
+    /// The tunnel connected.
+    PostUp,
+    /// The tunnel was disconnected.
+    PostDown,
+}
+
+impl Hook {
+    fn script<'a>(&self, settings: &'a HookSettings) -> Option<&'a PathBuf> {
+        match self {
+            Hook::PreUp => settings.pre_up.as_ref(),
+            Hook::PostUp => settings.post_up.as_ref(),
+            Hook::PostDown => settings.post_down.as_ref(),
+        }
+    }
+}
+
+impl fmt::Display for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Hook::PreUp => "pre-up".fmt(f),
+            Hook::PostUp => "post-up".fmt(f),
+            Hook::PostDown => "post-down".fmt(f),
+        }
+    }
+}
+
+/// A script that is waiting to be run.
+struct HookRun {
+    hook: Hook,
+    script: PathBuf,
+    timeout: Duration,
+}
+
+/// Decides which scripts to run as the tunnel state changes.
+pub struct Hooks {
+    run_tx: mpsc::UnboundedSender<HookRun>,
+    /// Whether the tunnel has started connecting since it was last disconnected.
+    tunnel_active: bool,
+}
+
+impl Hooks {
+    /// Starts the task that runs the scripts. The interface and addresses of the tunnel are
+    /// looked up through `tunnel_command_tx` when the post-up script is run.
+    pub fn new(tunnel_command_tx: Weak<TunnelCommandSender>) -> Self {
+        let (run_tx, run_rx) = mpsc::unbounded();
+        tokio::spawn(run_scripts(run_rx, tunnel_command_tx));
+        Hooks {
+            run_tx,
+            tunnel_active: false,
+        }
+    }
+
+    /// Queues the script that is due when the tunnel enters `tunnel_state`, if one is set.
+    pub fn tunnel_state_changed(&mut self, tunnel_state: &TunnelState, settings: &HookSettings) {
+        let hook = match tunnel_state {
+            TunnelState::Connecting { .. } if !self.tunnel_active => Hook::PreUp,
+            TunnelState::Connected { .. } => Hook::PostUp,
+            TunnelState::Disconnected if self.tunnel_active => Hook::PostDown,
+            _ => return,
+        };
+        self.tunnel_active = hook != Hook::PostDown;
+
+        if let Some(script) = hook.script(settings) {
+            let _ = self.run_tx.unbounded_send(HookRun {
+                hook,
+                script: script.clone(),
+                timeout: settings.timeout.as_duration(),
+            });
+        }
+    }
+}
+
+async fn run_scripts(
+    mut run_rx: mpsc::UnboundedReceiver<HookRun>,
+    tunnel_command_tx: Weak<TunnelCommandSender>,
+) {
+    while let Some(run) = run_rx.next().await {
+        let metadata = if run.hook == Hook::PostUp {
+            match tunnel_metadata(&tunnel_command_tx).await {
+                Some(metadata) => Some(metadata),
+                None => {
+                    log::debug!(
+                        "Not running the {} script since the tunnel is no longer connected",
+                        run.hook
+                    );
+                    continue;
+                }
+            }
+        } else {
+            None
+        };
+
+        log::info!("Running the {} script {}", run.hook, run.script.display());
+        match run_script(&run, metadata.as_ref()).await {
+            Ok(()) => log::info!("The {} script finished", run.hook),
+            Err(error) => log::error!(
+                "{}",
+                error.display_chain_with_msg(&format!("The {} script failed", run.hook))
+            ),
+        }
+    }
+}
+
+async fn tunnel_metadata(tunnel_command_tx: &Weak<TunnelCommandSender>) -> Option<TunnelMetadata> {
+    let tunnel_command_tx = tunnel_command_tx.upgrade()?;
+    let (tx, rx) = oneshot::channel();
+    tunnel_command_tx
+        .send(TunnelCommand::GetTunnelMetadata(tx))
+        .await
+        .ok()?;
+    rx.await.ok()
+}
+
+async fn run_script(run: &HookRun, metadata: Option<&TunnelMetadata>) -> Result<(), Error> {
+    check_permissions(&run.script).await?;
+
+    let mut command = Command::new(&run.script);
+    command
+        .env_clear()
+        .env("MULLVAD_HOOK", run.hook.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    #[cfg(unix)]
+    command.env("PATH", SCRIPT_PATH).current_dir("/");
+    #[cfg(windows)]
+    for variable in &["PATH", "PATHEXT", "SystemRoot", "ComSpec"] {
+        if let Some(value) = std::env::var_os(variable) {
+            command.env(variable, value);
+        }
+    }
+    if let Some(metadata) = metadata {
+        let ips = metadata
+            .ips
+            .iter()
+            .map(|ip| ip.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        command
+            .env("MULLVAD_TUNNEL_INTERFACE", &metadata.interface)
+            .env("MULLVAD_TUNNEL_IPS", ips)
+            .env(
+                "MULLVAD_TUNNEL_IPV4_GATEWAY",
+                metadata.ipv4_gateway.to_string(),
+            );
+        if let Some(ipv6_gateway) = metadata.ipv6_gateway {
+            command.env("MULLVAD_TUNNEL_IPV6_GATEWAY", ipv6_gateway.to_string());
+        }
+    }
+
+    let child = command.spawn().map_err(Error::Start)?;
+    let output = tokio::time::timeout(run.timeout, child.wait_with_output())
+        .await
+        .map_err(|_| Error::Timeout(run.timeout.as_secs()))?
+        .map_err(Error::Wait)?;
+
+    log_output(run.hook, "output", &output.stdout);
+    log_output(run.hook, "error output", &output.stderr);
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(Error::Failed(output.status.to_string()))
+    }
+}
+
+fn log_output(hook: Hook, stream: &str, output: &[u8]) {
+    for line in String::from_utf8_lossy(output).lines() {
+        if !line.trim().is_empty() {
+            log::info!("[{} {}] {}", hook, stream, line);
+        }
+    }
+}
+
+/// Returns an error unless the script and every directory that it is in are owned by root and
+/// cannot be written to by other users.
+#[cfg(unix)]
+async fn check_permissions(script: &Path) -> Result<(), Error> {
+    use std::os::unix::fs::MetadataExt;
+
+    let script = tokio::fs::canonicalize(script)
+        .await
+        .map_err(|error| Error::ReadPermissions(script.display().to_string(), error))?;
+    for path in script.ancestors() {
+        let metadata = tokio::fs::metadata(path)
+            .await
+            .map_err(|error| Error::ReadPermissions(path.display().to_string(), error))?;
+        if metadata.uid() != 0 || metadata.mode() & 0o022 != 0 {
+            return Err(Error::InsecurePermissions(path.display().to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Returns an error unless the script and every directory that it is in are owned by SYSTEM,
+/// administrators or TrustedInstaller, and cannot be changed by other users.
+#[cfg(windows)]
+async fn check_permissions(script: &Path) -> Result<(), Error> {
+    let script = tokio::fs::canonicalize(script)
+        .await
+        .map_err(|error| Error::ReadPermissions(script.display().to_string(), error))?;
+    tokio::task::spawn_blocking(move || {
+        for path in script.ancestors() {
+            match windows::is_secure(path, path != script) {
+                Ok(true) => (),
+                Ok(false) => return Err(Error::InsecurePermissions(path.display().to_string())),
+                Err(error) => {
+                    return Err(Error::ReadPermissions(path.display().to_string(), error))
+                }
+            }
+        }
+        Ok(())
+    })
+    .await
+    .expect("Permission check panicked")
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::{io, os::windows::ffi::OsStrExt, path::Path, ptr, slice};
+    use winapi::{
+        shared::{minwindef::TRUE, sddl::ConvertSidToStringSidW, winerror::ERROR_SUCCESS},
+        um::{
+            accctrl::SE_FILE_OBJECT,
+            aclapi::GetNamedSecurityInfoW,
+            securitybaseapi::{GetAce, IsWellKnownSid},
+            winbase::LocalFree,
+            winnt::{
+                WinBuiltinAdministratorsSid, WinLocalSystemSid, ACCESS_ALLOWED_ACE,
+                ACCESS_ALLOWED_ACE_TYPE, ACE_HEADER, DACL_SECURITY_INFORMATION, DELETE,
+                FILE_APPEND_DATA, FILE_DELETE_CHILD, FILE_WRITE_DATA, GENERIC_ALL, GENERIC_WRITE,
+                INHERIT_ONLY_ACE, OWNER_SECURITY_INFORMATION, PACL, PSECURITY_DESCRIPTOR, PSID,
+                WRITE_DAC, WRITE_OWNER,
+            },
+        },
+    };
+
+    /// The SID of the TrustedInstaller service, which owns the program files directory.
+    const TRUSTED_INSTALLER_SID: &str =
+        "S-1-5-80-956008885-3418522649-1831038044-1851839-2271478464";
+
+    /// Access rights that let a file be changed or replaced.
+    const FILE_WRITE_RIGHTS: u32 = FILE_WRITE_DATA
+        | FILE_APPEND_DATA
+        | DELETE
+        | WRITE_DAC
+        | WRITE_OWNER
+        | GENERIC_WRITE
+        | GENERIC_ALL;
+
+    /// Access rights that let a directory, or the files in it, be replaced.
+    const DIRECTORY_WRITE_RIGHTS: u32 =
+        FILE_DELETE_CHILD | DELETE | WRITE_DAC | WRITE_OWNER | GENERIC_WRITE | GENERIC_ALL;
+
+    /// Returns whether `path` is owned by a trusted account and its DACL does not let anyone
+    /// else change it.
+    pub fn is_secure(path: &Path, is_dir: bool) -> io::Result<bool> {
+        let security_info = SecurityInformation::from_file(path)?;
+        if security_info.owner.is_null() || !is_trusted(security_info.owner)? {
+            return Ok(false);
+        }
+        // A missing DACL gives everyone full access
+        let dacl = security_info.dacl;
+        if dacl.is_null() {
+            return Ok(false);
+        }
+
+        let write_rights = if is_dir {
+            DIRECTORY_WRITE_RIGHTS
+        } else {
+            FILE_WRITE_RIGHTS
+        };
+        for index in 0..u32::from(unsafe { (*dacl).AceCount }) {
+            let mut ace = ptr::null_mut();
+            if unsafe { GetAce(dacl, index, &mut ace) } != TRUE {
+                return Err(io::Error::last_os_error());
+            }
+            let header = unsafe { &*(ace as *const ACE_HEADER) };
+            // Inherit-only entries only apply to the files and directories in a directory
+            if header.AceType != ACCESS_ALLOWED_ACE_TYPE || header.AceFlags & INHERIT_ONLY_ACE != 0
+            {
+                continue;
+            }
+            let ace = unsafe { &*(ace as *const ACCESS_ALLOWED_ACE) };
+            let sid = &ace.SidStart as *const u32 as PSID;
+            if ace.Mask & write_rights != 0 && !is_trusted(sid)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn is_trusted(sid: PSID) -> io::Result<bool> {
+        let is_well_known = |sid_type| unsafe { IsWellKnownSid(sid, sid_type) == TRUE };
+        if is_well_known(WinLocalSystemSid) || is_well_known(WinBuiltinAdministratorsSid) {
+            return Ok(true);
+        }
+        Ok(sid_string(sid)? == TRUSTED_INSTALLER_SID)
+    }
+
+    fn sid_string(sid: PSID) -> io::Result<String> {
+        let mut buffer = ptr::null_mut();
+        if unsafe { ConvertSidToStringSidW(sid, &mut buffer) } != TRUE {
+            return Err(io::Error::last_os_error());
+        }
+        let string = unsafe {
+            let len = (0..).take_while(|&i| *buffer.offset(i) != 0).count();
+            let string = String::from_utf16_lossy(slice::from_raw_parts(buffer, len));
+            LocalFree(buffer as *mut _);
+            string
+        };
+        Ok(string)
+    }
+
+    /// The owner and DACL of a file, which point into the security descriptor that they were
+    /// read from.
+    struct SecurityInformation {
+        security_descriptor: PSECURITY_DESCRIPTOR,
+        owner: PSID,
+        dacl: PACL,
+    }
+
+    impl SecurityInformation {
+        fn from_file(path: &Path) -> io::Result<Self> {
+            let mut u16_path: Vec<u16> = path.as_os_str().encode_wide().collect();
+            u16_path.push(0u16);
+
+            let mut security_descriptor = ptr::null_mut();
+            let mut owner = ptr::null_mut();
+            let mut dacl = ptr::null_mut();
+            let status = unsafe {
+                GetNamedSecurityInfoW(
+                    u16_path.as_ptr(),
+                    SE_FILE_OBJECT,
+                    OWNER_SECURITY_INFORMATION | DACL_SECURITY_INFORMATION,
+                    &mut owner,
+                    ptr::null_mut(),
+                    &mut dacl,
+                    ptr::null_mut(),
+                    &mut security_descriptor,
+                )
+            };
+            if status != ERROR_SUCCESS {
+                return Err(io::Error::from_raw_os_error(status as i32));
+            }
+
+            Ok(SecurityInformation {
+                security_descriptor,
+                owner,
+                dacl,
+            })
+        }
+    }
+
+    impl Drop for SecurityInformation {
+        fn drop(&mut self) {
+            unsafe { LocalFree(self.security_descriptor) };
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! Gives the device a new name and WireGuard key on a schedule chosen by the user, so that the
+//! device cannot be recognized by its public identifiers for longer than that. The time of the
+//! most recent rotation is kept in the cache directory, so that restarting the daemon does not
+//! postpone the next one.
+use crate::{DaemonEventSender, InternalDaemonEvent};
+use chrono::{DateTime, Utc};
+use futures::{
+    channel::mpsc,
+    future::{abortable, AbortHandle},
+    StreamExt,
+};
+use rand::seq::SliceRandom;
+use std::{path::Path, time::Duration};
+use talpid_core::mpsc::Sender;
+use talpid_types::ErrorExt;
+use tokio::fs;
+
+/// The file in the cache directory that holds the time of the most recent rotation.
+const LAST_ROTATION_FILE: &str = "identity-rotated";
+
+/// How long to wait before trying again after a rotation has failed.
+pub const RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+const ADJECTIVES: &[&str] = &[
+    "Agile", "Amber", "Bold", "Brave", "Bright", "Calm", "Clever", "Cosmic", "Crisp", "Daring",
+    "Eager", "Fancy", "Gentle", "Golden", "Happy", "Humble", "Jolly", "Keen", "Lively", "Lucky",
+    "Mellow", "Nimble", "Noble", "Proud", "Quick", "Quiet", "Rapid", "Silent", "Sleek", "Steady",
+    "Swift", "Witty",
+];
+
+const ANIMALS: &[&str] = &[
+    "Badger", "Beaver", "Bison", "Crane", "Dolphin", "Eagle", "Falcon", "Ferret", "Fox", "Gecko",
+    "Heron", "Ibex", "Jaguar", "Koala", "Lemur", "Lynx", "Marmot", "Mole", "Moose", "Newt",
+    "Otter", "Owl", "Panda", "Puffin", "Raven", "Salmon", "Seal", "Stork", "Tapir", "Turtle",
+    "Walrus", "Yak",
+];
+
+/// Returns a random device name, such as "Quiet Otter".
+pub fn random_name() -> String {
+    let mut rng = rand::thread_rng();
+    format!(
+        "{} {}",
+        ADJECTIVES.choose(&mut rng).unwrap(),
+        ANIMALS.choose(&mut rng).unwrap()
+    )
+}
+
+/// Reads the time of the most recent rotation, if there has been one.
+pub async fn load_last_rotation(cache_dir: &Path) -> Option<DateTime<Utc>> {
+    let contents = fs::read_to_string(cache_dir.join(LAST_ROTATION_FILE))
+        .await
+        .ok()?;
+    match DateTime::parse_from_rfc3339(contents.trim()) {
+        Ok(time) => Some(time.with_timezone(&Utc)),
+        Err(error) => {
+            log::warn!(
+                "Ignoring invalid time of the last identity rotation: {}",
+                error
+            );
+            None
+        }
+    }
+}
+
+/// Stores the time of the most recent rotation.
+pub async fn store_last_rotation(cache_dir: &Path, time: DateTime<Utc>) {
+    if let Err(error) = fs::write(cache_dir.join(LAST_ROTATION_FILE), time.to_rfc3339()).await {
+        log::error!(
+            "{}",
+            error.display_chain_with_msg("Failed to store the time of the last identity rotation")
+        );
+    }
+}
+
+/// Sends `InternalDaemonEvent::IdentityRotationDue` to the daemon once the rotation is due. The
+/// wall clock is checked on every scheduler tick, since a sleeping timer does not advance while
+/// the computer is suspended. The timer stops when this is dropped.
+pub struct RotationTimer {
+    abort_handle: AbortHandle,
+}
+
+impl RotationTimer {
+    pub(crate) fn start(
+        mut ticks: mpsc::UnboundedReceiver<()>,
+        daemon_tx: DaemonEventSender,
+        due: DateTime<Utc>,
+    ) -> Self {
+        log::debug!("Next identity rotation is due at {}", due);
+        let (timer, abort_handle) = abortable(async move {
+            while ticks.next().await.is_some() {
+                if Utc::now() >= due {
+                    let _ = daemon_tx.send(InternalDaemonEvent::IdentityRotationDue);
+                    return;
+                }
+            }
+        });
+        tokio::spawn(timer);
+        Self { abort_handle }
+    }
+}
+
+impl Drop for RotationTimer {
+    fn drop(&mut self) {
+        self.abort_handle.abort();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_random_name() {
+        let name = random_name();
+        let mut words = name.split(' ');
+        assert!(ADJECTIVES.contains(&words.next().unwrap()));
+        assert!(ANIMALS.contains(&words.next().unwrap()));
+        assert_eq!(words.next(), None);
+    }
+}
@@ -0,0 +1,475 @@
+//! A SOCKS5 and HTTP CONNECT proxy that lets other devices on the local network send their
+//! traffic through the tunnel. The proxy only accepts clients on private networks, never connects
+//! to the local network itself, and only forwards connections while the tunnel is connected. Open
+//! connections are closed as soon as the tunnel goes down, so that no proxied traffic leaks outside
+//! the tunnel.
+
+use futures::future::{self, abortable, AbortHandle, Either};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+use talpid_types::{net::TunnelEndpoint, ErrorExt};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::{watch, Semaphore},
+};
+
+/// Maximum number of connections that are proxied at the same time.
+const MAX_CONNECTIONS: usize = 256;
+
+/// Maximum size of the request header of an HTTP CONNECT request.
+const MAX_HTTP_HEADER_SIZE: usize = 8 * 1024;
+
+pub(crate) const SOCKS_VERSION: u8 = 5;
+pub(crate) const SOCKS_NO_AUTHENTICATION: u8 = 0x00;
+pub(crate) const SOCKS_NO_ACCEPTABLE_METHODS: u8 = 0xff;
+pub(crate) const SOCKS_CMD_CONNECT: u8 = 0x01;
+pub(crate) const SOCKS_ATYP_IPV4: u8 = 0x01;
+pub(crate) const SOCKS_ATYP_DOMAIN: u8 = 0x03;
+pub(crate) const SOCKS_ATYP_IPV6: u8 = 0x04;
+
+pub(crate) const SOCKS_REPLY_SUCCEEDED: u8 = 0x00;
+pub(crate) const SOCKS_REPLY_GENERAL_FAILURE: u8 = 0x01;
+pub(crate) const SOCKS_REPLY_NOT_ALLOWED: u8 = 0x02;
+const SOCKS_REPLY_HOST_UNREACHABLE: u8 = 0x04;
+const SOCKS_REPLY_CONNECTION_REFUSED: u8 = 0x05;
+pub(crate) const SOCKS_REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+pub(crate) const SOCKS_REPLY_ADDRESS_TYPE_NOT_SUPPORTED: u8 = 0x08;
+
+/// Which connections the proxy may forward.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ForwardingPolicy {
+    /// Whether the tunnel is connected. Nothing is forwarded otherwise.
+    tunnel_up: bool,
+    /// Addresses that are reached outside the tunnel, such as the relay. Connections to these
+    /// are refused, since they would bypass the tunnel.
+    excluded_addrs: Vec<IpAddr>,
+}
+
+impl ForwardingPolicy {
+    /// Nothing is forwarded.
+    pub fn blocked() -> Self {
+        Self::default()
+    }
+
+    /// Connections are forwarded through the tunnel at `endpoint`.
+    pub fn connected(endpoint: &TunnelEndpoint) -> Self {
+        let mut excluded_addrs = vec![endpoint.endpoint.address.ip()];
+        if let Some(entry_endpoint) = &endpoint.entry_endpoint {
+            excluded_addrs.push(entry_endpoint.address.ip());
+        }
+        if let Some(proxy) = &endpoint.proxy {
+            excluded_addrs.push(proxy.endpoint.address.ip());
+        }
+        if let Some(obfuscation) = &endpoint.obfuscation {
+            excluded_addrs.push(obfuscation.endpoint.address.ip());
+        }
+        Self {
+            tunnel_up: true,
+            excluded_addrs,
+        }
+    }
+
+    /// Destinations on the local network are refused, since they are not reached through the
+    /// tunnel.
+    fn allows(&self, destination: &SocketAddr) -> bool {
+        let ip = destination.ip();
+        self.tunnel_up
+            && !is_lan_address(&ip)
+            && !ip.is_unspecified()
+            && !ip.is_multicast()
+            && !self.excluded_addrs.contains(&ip)
+    }
+}
+
+/// A running proxy. The listener and all proxied connections are closed when this is dropped.
+pub struct LanProxy {
+    port: u16,
+    policy_tx: watch::Sender<ForwardingPolicy>,
+    abort_handle: AbortHandle,
+}
+
+impl LanProxy {
+    /// Starts listening on `port` on all IPv4 interfaces.
+    pub async fn start(port: u16, policy: ForwardingPolicy) -> io::Result<Self> {
+        let listener =
+            TcpListener::bind(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port)).await?;
+        let (policy_tx, policy_rx) = watch::channel(policy);
+
+        let (accept_future, abort_handle) = abortable(Self::accept_loop(listener, policy_rx));
+        tokio::spawn(accept_future);
+
+        log::info!("Started LAN proxy on port {}", port);
+
+        Ok(Self {
+            port,
+            policy_tx,
+            abort_handle,
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Updates which connections may be forwarded. Open connections are closed if the tunnel
+    /// went down.
+    pub fn set_policy(&self, policy: ForwardingPolicy) {
+        let _ = self.policy_tx.send(policy);
+    }
+
+    async fn accept_loop(listener: TcpListener, policy_rx: watch::Receiver<ForwardingPolicy>) {
+        let connection_limit = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+        loop {
+            let (client, client_addr) = match listener.accept().await {
+                Ok(result) => result,
+                Err(error) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("LAN proxy failed to accept connection")
+                    );
+                    continue;
+                }
+            };
+            if !is_lan_address(&client_addr.ip()) {
+                log::debug!("LAN proxy: Rejecting client at {}", client_addr);
+                continue;
+            }
+            let permit = match connection_limit.clone().try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    log::debug!("LAN proxy: Too many connections. Rejecting {}", client_addr);
+                    continue;
+                }
+            };
+
+            let policy_rx = policy_rx.clone();
+            tokio::spawn(async move {
+                if let Err(error) = Self::handle_client(client, policy_rx).await {
+                    log::debug!(
+                        "{}",
+                        error.display_chain_with_msg(&format!(
+                            "LAN proxy: Connection from {} failed",
+                            client_addr
+                        ))
+                    );
+                }
+                drop(permit);
+            });
+        }
+    }
+
+    async fn handle_client(
+        client: TcpStream,
+        mut policy_rx: watch::Receiver<ForwardingPolicy>,
+    ) -> io::Result<()> {
+        let _ = client.set_nodelay(true);
+        let mut client = BufReader::new(client);
+
+        let first_byte = client.read_u8().await?;
+        let server = if first_byte == SOCKS_VERSION {
+            socks5_handshake(&mut client, &policy_rx).await?
+        } else {
+            http_connect_handshake(&mut client, first_byte, &policy_rx).await?
+        };
+        let mut server = match server {
+            Some(server) => server,
+            None => return Ok(()),
+        };
+
+        let forward = tokio::io::copy_bidirectional(&mut client, &mut server);
+        let tunnel_down = async move {
+            loop {
+                if policy_rx.changed().await.is_err() || !policy_rx.borrow().tunnel_up {
+                    return;
+                }
+            }
+        };
+        match future::select(Box::pin(forward), Box::pin(tunnel_down)).await {
+            Either::Left((result, _)) => result.map(|_| ()),
+            Either::Right(_) => Ok(()),
+        }
+    }
+}
+
+impl Drop for LanProxy {
+    fn drop(&mut self) {
+        self.abort_handle.abort();
+        log::info!("Stopped LAN proxy");
+    }
+}
+
+/// Performs the rest of a SOCKS5 handshake after the version byte. Returns the connection to the
+/// destination, or `None` if the request was refused.
+async fn socks5_handshake(
+    client: &mut BufReader<TcpStream>,
+    policy_rx: &watch::Receiver<ForwardingPolicy>,
+) -> io::Result<Option<TcpStream>> {
+    let method_count = client.read_u8().await?;
+    let mut methods = vec![0u8; usize::from(method_count)];
+    client.read_exact(&mut methods).await?;
+    if !methods.contains(&SOCKS_NO_AUTHENTICATION) {
+        client
+            .write_all(&[SOCKS_VERSION, SOCKS_NO_ACCEPTABLE_METHODS])
+            .await?;
+        return Ok(None);
+    }
+    client
+        .write_all(&[SOCKS_VERSION, SOCKS_NO_AUTHENTICATION])
+        .await?;
+
+    let mut header = [0u8; 4];
+    client.read_exact(&mut header).await?;
+    let [version, command, _reserved, address_type] = header;
+    if version != SOCKS_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Invalid SOCKS version",
+        ));
+    }
+
+    let destinations = match address_type {
+        SOCKS_ATYP_IPV4 => {
+            let mut octets = [0u8; 4];
+            client.read_exact(&mut octets).await?;
+            let port = client.read_u16().await?;
+            vec![SocketAddr::new(Ipv4Addr::from(octets).into(), port)]
+        }
+        SOCKS_ATYP_IPV6 => {
+            let mut octets = [0u8; 16];
+            client.read_exact(&mut octets).await?;
+            let port = client.read_u16().await?;
+            vec![SocketAddr::new(Ipv6Addr::from(octets).into(), port)]
+        }
+        SOCKS_ATYP_DOMAIN => {
+            let length = client.read_u8().await?;
+            let mut domain = vec![0u8; usize::from(length)];
+            client.read_exact(&mut domain).await?;
+            let port = client.read_u16().await?;
+            let domain = String::from_utf8_lossy(&domain).into_owned();
+            match tokio::net::lookup_host((domain.as_str(), port)).await {
+                Ok(addrs) => addrs.collect(),
+                Err(_) => vec![],
+            }
+        }
+        _ => {
+            socks5_reply(client, SOCKS_REPLY_ADDRESS_TYPE_NOT_SUPPORTED, None).await?;
+            return Ok(None);
+        }
+    };
+
+    if command != SOCKS_CMD_CONNECT {
+        socks5_reply(client, SOCKS_REPLY_COMMAND_NOT_SUPPORTED, None).await?;
+        return Ok(None);
+    }
+
+    match connect(&destinations, policy_rx).await {
+        Ok(server) => {
+            let local_addr = server.local_addr().ok();
+            socks5_reply(client, SOCKS_REPLY_SUCCEEDED, local_addr).await?;
+            Ok(Some(server))
+        }
+        Err(error) => {
+            let reply = match error {
+                ConnectError::NotAllowed => SOCKS_REPLY_NOT_ALLOWED,
+                ConnectError::NoAddress => SOCKS_REPLY_HOST_UNREACHABLE,
+                ConnectError::Io(error) if error.kind() == io::ErrorKind::ConnectionRefused => {
+                    SOCKS_REPLY_CONNECTION_REFUSED
+                }
+                ConnectError::Io(_) => SOCKS_REPLY_GENERAL_FAILURE,
+            };
+            socks5_reply(client, reply, None).await?;
+            Ok(None)
+        }
+    }
+}
+
+pub(crate) async fn socks5_reply(
+    client: &mut (impl AsyncWrite + Unpin),
+    reply: u8,
+    bound_addr: Option<SocketAddr>,
+) -> io::Result<()> {
+    let bound_addr = bound_addr.unwrap_or_else(|| SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0));
+    let mut message = vec![SOCKS_VERSION, reply, 0];
+    match bound_addr.ip() {
+        IpAddr::V4(ip) => {
+            message.push(SOCKS_ATYP_IPV4);
+            message.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            message.push(SOCKS_ATYP_IPV6);
+            message.extend_from_slice(&ip.octets());
+        }
+    }
+    message.extend_from_slice(&bound_addr.port().to_be_bytes());
+    client.write_all(&message).await
+}
+
+/// Handles an HTTP CONNECT request, given the first byte of it. Returns the connection to the
+/// destination, or `None` if the request was refused.
+async fn http_connect_handshake(
+    client: &mut BufReader<TcpStream>,
+    first_byte: u8,
+    policy_rx: &watch::Receiver<ForwardingPolicy>,
+) -> io::Result<Option<TcpStream>> {
+    let header = read_http_header(client, first_byte).await?;
+    let header = String::from_utf8_lossy(&header);
+    let request_line = header.lines().next().unwrap_or("");
+    let mut parts = request_line.split_whitespace();
+
+    let (method, target) = (parts.next(), parts.next());
+    let target = match (method, target) {
+        (Some("CONNECT"), Some(target)) => target,
+        _ => {
+            http_reply(client, "405 Method Not Allowed").await?;
+            return Ok(None);
+        }
+    };
+
+    let destinations = match tokio::net::lookup_host(target).await {
+        Ok(addrs) => addrs.collect(),
+        Err(_) => vec![],
+    };
+    match connect(&destinations, policy_rx).await {
+        Ok(server) => {
+            http_reply(client, "200 Connection established").await?;
+            Ok(Some(server))
+        }
+        Err(ConnectError::NotAllowed) => {
+            http_reply(client, "403 Forbidden").await?;
+            Ok(None)
+        }
+        Err(_) => {
+            http_reply(client, "502 Bad Gateway").await?;
+            Ok(None)
+        }
+    }
+}
+
+/// Reads the header line by line. Any data following it stays in the buffer of `client`.
+async fn read_http_header(
+    client: &mut BufReader<TcpStream>,
+    first_byte: u8,
+) -> io::Result<Vec<u8>> {
+    let mut header = vec![first_byte];
+    while !header.ends_with(b"\r\n\r\n") {
+        let remaining = MAX_HTTP_HEADER_SIZE.saturating_sub(header.len());
+        if remaining == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "HTTP header is too large",
+            ));
+        }
+        let read = (&mut *client)
+            .take(remaining as u64)
+            .read_until(b'\n', &mut header)
+            .await?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+    }
+    Ok(header)
+}
+
+async fn http_reply(client: &mut BufReader<TcpStream>, status: &str) -> io::Result<()> {
+    client
+        .write_all(format!("HTTP/1.1 {}\r\n\r\n", status).as_bytes())
+        .await
+}
+
+enum ConnectError {
+    NotAllowed,
+    NoAddress,
+    Io(io::Error),
+}
+
+/// Connects to the first reachable destination that the policy allows.
+async fn connect(
+    destinations: &[SocketAddr],
+    policy_rx: &watch::Receiver<ForwardingPolicy>,
+) -> Result<TcpStream, ConnectError> {
+    let policy = policy_rx.borrow().clone();
+    let allowed: Vec<_> = destinations
+        .iter()
+        .filter(|destination| policy.allows(destination))
+        .collect();
+    if allowed.is_empty() {
+        return Err(if destinations.is_empty() {
+            ConnectError::NoAddress
+        } else {
+            ConnectError::NotAllowed
+        });
+    }
+
+    let mut last_error = None;
+    for destination in allowed {
+        match TcpStream::connect(destination).await {
+            Ok(server) => {
+                let _ = server.set_nodelay(true);
+                return Ok(server);
+            }
+            Err(error) => last_error = Some(error),
+        }
+    }
+    Err(ConnectError::Io(last_error.unwrap()))
+}
+
+/// Returns whether `address` may belong to a device on the local network.
+fn is_lan_address(address: &IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => {
+            address.is_private() || address.is_link_local() || address.is_loopback()
+        }
+        IpAddr::V6(address) => {
+            if address.is_loopback() {
+                return true;
+            }
+            if let Some(address) = address.to_ipv4() {
+                return is_lan_address(&IpAddr::V4(address));
+            }
+            let first_segment = address.segments()[0];
+            // Unique local (fc00::/7) and link-local (fe80::/10) addresses
+            (first_segment & 0xfe00) == 0xfc00 || (first_segment & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_lan_address() {
+        assert!(is_lan_address(&"192.168.1.10".parse().unwrap()));
+        assert!(is_lan_address(&"10.0.0.1".parse().unwrap()));
+        assert!(is_lan_address(&"169.254.3.4".parse().unwrap()));
+        assert!(is_lan_address(&"fe80::1".parse().unwrap()));
+        assert!(is_lan_address(&"fd00::1".parse().unwrap()));
+        assert!(is_lan_address(&"::ffff:192.168.1.10".parse().unwrap()));
+        assert!(!is_lan_address(&"8.8.8.8".parse().unwrap()));
+        assert!(!is_lan_address(&"2001:db8::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_forwarding_policy() {
+        let destination: SocketAddr = "203.0.113.1:443".parse().unwrap();
+        assert!(!ForwardingPolicy::blocked().allows(&destination));
+
+        let policy = ForwardingPolicy {
+            tunnel_up: true,
+            excluded_addrs: vec!["198.51.100.1".parse().unwrap()],
+        };
+        assert!(policy.allows(&destination));
+        assert!(!policy.allows(&"198.51.100.1:443".parse().unwrap()));
+        assert!(!policy.allows(&"127.0.0.1:8080".parse().unwrap()));
+        assert!(!policy.allows(&"192.168.1.1:80".parse().unwrap()));
+        assert!(!policy.allows(&"10.0.0.1:22".parse().unwrap()));
+        assert!(!policy.allows(&"172.16.0.1:443".parse().unwrap()));
+        assert!(!policy.allows(&"169.254.169.254:80".parse().unwrap()));
+        assert!(!policy.allows(&"[fe80::1]:80".parse().unwrap()));
+        assert!(!policy.allows(&"[::1]:80".parse().unwrap()));
+    }
+}
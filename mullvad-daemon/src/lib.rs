@@ -6,17 +6,48 @@ extern crate serde;
 
 
 mod account;
+mod account_expiry;
 pub mod account_history;
+mod api_access;
+#[cfg(not(target_os = "android"))]
+mod api_forwarder;
+mod captive_portal;
+mod connect_times;
+mod connection_profiles;
+mod connection_stats;
+mod credential_store;
+#[cfg(target_os = "linux")]
+mod dbus_service;
+pub mod diagnostics;
+#[cfg(not(target_os = "android"))]
+pub mod dns_forwarder;
 pub mod exception_logging;
+mod exit_ip_check;
 mod geoip;
+#[cfg(not(target_os = "android"))]
+mod hooks;
+mod identity_rotation;
+mod lan_proxy;
+mod local_api;
 pub mod logging;
 #[cfg(not(target_os = "android"))]
 pub mod management_interface;
-mod relays;
+#[cfg(windows)]
+pub mod migration;
+mod migrations;
+mod problem_report_outbox;
+mod relay_health;
+mod relay_latency;
+pub mod relays;
 #[cfg(not(target_os = "android"))]
 pub mod rpc_uniqueness_check;
 pub mod runtime;
+mod scheduler;
 pub mod settings;
+mod settings_encryption;
+mod settings_profile;
+mod system_log;
+mod tunnel_overrides;
 pub mod version;
 mod version_check;
 
@@ -28,53 +59,100 @@ use futures::{
 use log::{debug, error, info, warn};
 use mullvad_rpc::availability::ApiAvailabilityHandle;
 use mullvad_types::{
-    account::{AccountData, AccountToken, VoucherSubmission},
+    account::{
+        AccountData, AccountExpiryEvent, AccountExpirySettings, AccountToken, ExpiryAction,
+        VoucherSubmission,
+    },
+    api_access::{
+        is_builtin_access_method, ActiveApiAccessMethod, ApiAccessMethod, ApiAccessStatus,
+        ApiProxy, ApiResolutionStrategy,
+    },
+    beta_features::BetaFeature,
+    device::{Device, DeviceId, DeviceIdentityChange, DeviceList, DeviceState},
     endpoint::MullvadEndpoint,
-    location::GeoIpLocation,
+    location::{ConnectionCheck, ExitIpMismatch, ExitIpProblem, GeoIpLocation},
+    network_rules::{NetworkAction, NetworkMatch, NetworkRule},
+    obfuscation::{ObfuscationMode, ObfuscationSettings},
+    port_forward::{PortForward, PortForwardId},
     relay_constraints::{
-        BridgeSettings, BridgeState, Constraint, InternalBridgeConstraints, RelaySettings,
-        RelaySettingsUpdate,
+        BridgeSettings, BridgeState, Constraint, InternalBridgeConstraints, RelayConstraints,
+        RelaySettings, RelaySettingsUpdate,
     },
+    relay_filter::RelayFilter,
     relay_list::{Relay, RelayList},
-    settings::{DnsOptions, DnsState, Settings},
-    states::{TargetState, TunnelState},
+    settings::{
+        AllowedSystemServices, CredentialStorage, DnsOptions, DnsState, EncryptedDnsProtocol,
+        ExitIpCheckSettings, HookSettings, LanProxySettings, LocalApiSettings, LogPrivacyLevel,
+        LogRotationSettings, ReconnectBackoffSettings, Settings, TunnelParameterOverrides,
+    },
+    states::{FeatureIndicators, TargetState, TunnelState},
+    units::HumanDuration,
     version::{AppVersion, AppVersionInfo},
-    wireguard::{KeygenEvent, RotationInterval},
+    wireguard::{KeygenEvent, QuantumResistantState, RotationInterval},
 };
 use settings::SettingsPersister;
+#[cfg(target_os = "windows")]
+use std::ffi::OsString;
 #[cfg(target_os = "android")]
 use std::os::unix::io::RawFd;
-#[cfg(target_os = "windows")]
-use std::{collections::HashSet, ffi::OsString};
 use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
     marker::PhantomData,
     mem,
-    net::IpAddr,
+    net::{IpAddr, SocketAddr},
     path::{Path, PathBuf},
     pin::Pin,
-    sync::{mpsc as sync_mpsc, Arc, Weak},
-    time::Duration,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc as sync_mpsc, Arc, Mutex, Weak,
+    },
+    time::{Duration, Instant},
 };
+use system_log::SystemLogEvent;
 #[cfg(any(target_os = "linux", windows))]
 use talpid_core::split_tunnel;
 use talpid_core::{
+    conflicting_software::{self, ConflictingSoftware},
+    connection_trace::{self, ConnectionTrace, Phase},
     mpsc::Sender,
-    tunnel_state_machine::{self, TunnelCommand, TunnelParametersGenerator},
+    preflight::{self, MissingCapability},
+    tunnel_state_machine::{
+        self, Reconfiguration, TunnelCommand, TunnelCommandSender, TunnelParametersGenerator,
+        TunnelStateChange,
+    },
 };
 #[cfg(target_os = "android")]
 use talpid_types::android::AndroidContext;
+#[cfg(not(target_os = "android"))]
+use talpid_types::net::NetworkIdentity;
 use talpid_types::{
-    net::{openvpn, Endpoint, TransportProtocol, TunnelEndpoint, TunnelParameters, TunnelType},
-    tunnel::{ErrorStateCause, ParameterGenerationError, TunnelStateTransition},
+    cancel::CancelToken,
+    net::{
+        openvpn, AllowedEndpoint, Connectivity, Endpoint, Ipv6FailurePolicy,
+        SystemServiceExemptions, TransportProtocol, TunnelEndpoint, TunnelParameters, TunnelType,
+    },
+    tunnel::{ErrorStateCause, ParameterGenerationError, TransitionReason, TunnelStateTransition},
     ErrorExt,
 };
-use tokio::{fs, io};
+use tokio::{
+    fs,
+    io::{self, AsyncWriteExt},
+};
 
 #[path = "wireguard.rs"]
 mod wireguard;
 
 const TARGET_START_STATE_FILE: &str = "target-start-state.json";
 
+/// File in the settings directory that the WireGuard configuration is exported to when the
+/// private key is included. Like the settings, it is only readable by administrators.
+const WIREGUARD_CONFIG_EXPORT_FILE: &str = "wireguard-config-export.conf";
+
+/// File in the cache directory that problem reports collected by the daemon are written to. Each
+/// report replaces the previous one.
+#[cfg(not(target_os = "android"))]
+const PROBLEM_REPORT_FILE: &str = "problem-report.txt";
+
 const TUNNEL_STATE_MACHINE_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// Timeout for first WireGuard key pushing
@@ -83,6 +161,19 @@ const FIRST_KEY_PUSH_TIMEOUT: Duration = Duration::from_secs(5);
 /// Delay between generating a new WireGuard key and reconnecting
 const WG_RECONNECT_DELAY: Duration = Duration::from_secs(4 * 60);
 
+/// Number of failed connection attempts between checks of whether this device has been revoked
+const DEVICE_CHECK_ATTEMPT_INTERVAL: u32 = 3;
+
+/// How often to resolve the API hostname, unless only static API addresses are used
+const API_RESOLVE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often the traffic counters of the tunnel are read while the tunnel stats are subscribed to
+const LIVE_STATS_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Address that the encrypted DNS proxy listens on. The system resolver is pointed at it.
+#[cfg(not(target_os = "android"))]
+const DNS_PROXY_IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+
 lazy_static::lazy_static! {
     static ref DNS_AD_BLOCKING_SERVERS: [IpAddr; 1] = ["100.64.0.1".parse().unwrap()];
     static ref DNS_TRACKER_BLOCKING_SERVERS: [IpAddr; 1] = ["100.64.0.2".parse().unwrap()];
@@ -106,6 +197,31 @@ pub enum Error {
     #[error(display = "REST request failed")]
     RestError(#[error(source)] mullvad_rpc::rest::Error),
 
+    #[error(display = "An API access method named \"{}\" already exists", _0)]
+    ApiAccessMethodExists(String),
+
+    #[error(display = "There is no API access method named \"{}\"", _0)]
+    ApiAccessMethodNotFound(String),
+
+    #[error(display = "Unable to reach the API through the proxy")]
+    ApiProxyProbeError(#[error(source)] io::Error),
+
+    #[cfg(not(target_os = "android"))]
+    #[error(display = "Failed to start the API forwarder")]
+    ApiForwarderError(#[error(source)] io::Error),
+
+    #[error(display = "The built-in API access method \"{}\" cannot be removed", _0)]
+    BuiltinApiAccessMethod(String),
+
+    #[error(display = "Unable to reach the API through access method \"{}\"", _0)]
+    ApiAccessMethodUnreachable(String, #[error(source)] io::Error),
+
+    #[error(display = "The API access method was changed while it was being checked")]
+    ApiAccessMethodSuperseded,
+
+    #[error(display = "Another network interface is named \"{}\"", _0)]
+    TunnelInterfaceNameInUse(String),
+
     #[error(display = "API availability check failed")]
     ApiCheckError(#[error(source)] mullvad_rpc::availability::Error),
 
@@ -119,7 +235,7 @@ pub enum Error {
     #[error(display = "The account has too many wireguard keys")]
     TooManyKeys,
 
-    #[cfg(windows)]
+    #[cfg(any(windows, target_os = "linux"))]
     #[error(display = "Split tunneling error")]
     SplitTunnelError(#[error(source)] split_tunnel::Error),
 
@@ -138,12 +254,61 @@ pub enum Error {
     #[error(display = "No account history available for the token")]
     NoAccountTokenHistory,
 
+    #[error(display = "This device is not registered with the account")]
+    NoCurrentDevice,
+
+    #[error(display = "The current device cannot be removed. Log out instead")]
+    RemoveCurrentDevice,
+
+    #[error(display = "The device name must not be empty")]
+    EmptyDeviceName,
+
+    #[error(display = "This device has not been revoked")]
+    DeviceNotRevoked,
+
+    #[error(display = "This device has been revoked, so it cannot connect")]
+    DeviceRevoked,
+
+    #[error(display = "There are no relays in the city \"{}\" in \"{}\"", _1, _0)]
+    UnknownPortForwardCity(String, String),
+
+    #[error(display = "No WireGuard tunnel is connected")]
+    NoWireguardTunnel,
+
+    #[error(display = "Failed to write the WireGuard configuration")]
+    ExportWireguardConfig(#[error(source)] io::Error),
+
+    #[error(display = "Failed to replace the WireGuard key")]
+    ReplaceWireguardKey(#[error(source)] wireguard::Error),
+
+    #[error(display = "Failed to read or create the local API token")]
+    LocalApiToken(#[error(source)] io::Error),
+
+    #[error(display = "Invalid settings profile")]
+    SettingsProfile(#[error(source)] settings_profile::Error),
+
+    #[error(display = "A setting in the settings profile could not be applied")]
+    SettingsProfileInterrupted,
+
     #[error(display = "Settings error")]
     SettingsError(#[error(source)] settings::Error),
 
     #[error(display = "Account history error")]
     AccountHistory(#[error(source)] account_history::Error),
 
+    #[error(display = "Connection statistics error")]
+    ConnectionStats(#[error(source)] connection_stats::Error),
+
+    #[error(display = "Connect times error")]
+    ConnectTimes(#[error(source)] connect_times::Error),
+
+    #[error(display = "Problem report outbox error")]
+    ProblemReportOutbox(#[error(source)] problem_report_outbox::Error),
+
+    #[cfg(not(target_os = "android"))]
+    #[error(display = "Failed to collect a problem report")]
+    CollectProblemReport(#[error(source)] mullvad_problem_report::Error),
+
     #[error(display = "Failed to clear cache directory")]
     ClearCacheError,
 
@@ -191,12 +356,19 @@ pub enum Error {
 pub enum DaemonCommand {
     /// Set target state. Does nothing if the daemon already has the state that is being set.
     SetTargetState(oneshot::Sender<bool>, TargetState),
+    /// Acquire a lease that keeps the tunnel secured until it is released. The target state from
+    /// before the first lease was acquired is restored once every lease has been released.
+    AcquireConnectionLease(ResponseTx<u64, Error>),
+    /// Release a connection lease
+    ReleaseConnectionLease(u64),
     /// Reconnect the tunnel, if one is connecting/connected.
     Reconnect(oneshot::Sender<bool>),
     /// Request the current state.
     GetState(oneshot::Sender<TunnelState>),
     /// Get the current geographical location.
     GetCurrentLocation(oneshot::Sender<Option<GeoIpLocation>>),
+    /// Check where traffic exits using am.i.mullvad.net.
+    CheckConnection(ResponseTx<ConnectionCheck, Error>),
     CreateNewAccount(ResponseTx<String, Error>),
     /// Request the metadata for an account.
     GetAccountData(
@@ -205,12 +377,32 @@ pub enum DaemonCommand {
     ),
     /// Request www auth token for an account
     GetWwwAuthToken(ResponseTx<String, Error>),
+    /// List the devices registered with the current account
+    ListDevices(ResponseTx<DeviceList, Error>),
+    /// Rename the current device
+    RenameDevice(ResponseTx<(), Error>, String),
+    /// Remove a device other than the current one from the current account
+    RemoveDevice(ResponseTx<(), Error>, DeviceId),
+    /// Get whether this device has been revoked
+    GetDeviceState(oneshot::Sender<DeviceState>),
+    /// Register this device again after it has been revoked
+    ReregisterDevice(ResponseTx<(), Error>),
+    /// List the ports that are forwarded to this device
+    ListPortForwards(ResponseTx<Vec<PortForward>, Error>),
+    /// Forward a port to this device from the relays in a city, given by country and city code
+    AddPortForward(ResponseTx<PortForward, Error>, String, String),
+    /// Stop forwarding a port to this device
+    RemovePortForward(ResponseTx<(), Error>, PortForwardId),
+    /// Set whether only relays in cities with forwarded ports are used
+    SetPortForwarding(ResponseTx<(), settings::Error>, bool),
     /// Submit voucher to add time to the current account. Returns time added in seconds
     SubmitVoucher(ResponseTx<VoucherSubmission, Error>, String),
-    /// Request account history
-    GetAccountHistory(oneshot::Sender<Option<AccountToken>>),
-    /// Remove the last used account, if there is one
+    /// Request account history, most recently used account first
+    GetAccountHistory(oneshot::Sender<Vec<AccountToken>>),
+    /// Remove all accounts from the account history
     ClearAccountHistory(ResponseTx<(), Error>),
+    /// Remove a single account from the account history
+    RemoveAccountFromHistory(ResponseTx<(), Error>, AccountToken),
     /// Get the list of countries and cities where there are relays.
     GetRelayLocations(oneshot::Sender<RelayList>),
     /// Trigger an asynchronous relay list update. This returns before the relay list is actually
@@ -222,12 +414,52 @@ pub enum DaemonCommand {
     UpdateRelaySettings(ResponseTx<(), settings::Error>, RelaySettingsUpdate),
     /// Set the allow LAN setting.
     SetAllowLan(ResponseTx<(), settings::Error>, bool),
+    /// Set whether the subnets of the Hyper-V NAT switches are reachable in every state.
+    #[cfg(windows)]
+    SetAllowVirtualNetworks(ResponseTx<(), settings::Error>, bool),
+    /// Allow traffic to and from a network outside the tunnel in every state.
+    AddFirewallException(ResponseTx<(), settings::Error>, AllowedEndpoint),
+    /// Remove a network that traffic is allowed to and from outside the tunnel.
+    RemoveFirewallException(ResponseTx<(), settings::Error>, AllowedEndpoint),
+    /// Allow traffic to and from a network outside the tunnel in every state, until the duration
+    /// has passed. The exception is not saved in the settings
+    AllowTemporarily(
+        oneshot::Sender<Result<(), talpid_core::firewall::Error>>,
+        AllowedEndpoint,
+        Duration,
+    ),
+    /// Enable or disable relaying of mDNS traffic between the LAN and the host.
+    SetMdnsReflector(ResponseTx<(), settings::Error>, bool),
     /// Set the beta program setting.
     SetShowBetaReleases(ResponseTx<(), settings::Error>, bool),
+    /// Enable or disable encryption of sensitive settings.
+    SetEncryptSensitiveSettings(ResponseTx<(), settings::Error>, bool),
+    /// Set where the account number and the WireGuard key are stored.
+    SetCredentialStorage(ResponseTx<(), settings::Error>, CredentialStorage),
+    /// Set whether more than the last used account is kept in the account history.
+    SetKeepAccountHistory(ResponseTx<(), settings::Error>, bool),
+    /// Set whether the account number is kept in memory only, rather than stored on disk.
+    SetAccountPrivacyMode(ResponseTx<(), settings::Error>, bool),
+    /// Set how often the device is given a new name and WireGuard key, or stop doing so. The
+    /// interval must have been validated
+    SetIdentityRotationInterval(ResponseTx<(), settings::Error>, Option<HumanDuration>),
+    /// Set the account expiry warning thresholds and the action taken when the account expires.
+    SetAccountExpirySettings(ResponseTx<(), settings::Error>, AccountExpirySettings),
     /// Set the block_when_disconnected setting.
     SetBlockWhenDisconnected(ResponseTx<(), settings::Error>, bool),
+    /// Set whether blocking continues while the daemon is not running.
+    SetPersistentLockdown(ResponseTx<(), settings::Error>, bool),
     /// Set the auto-connect setting.
     SetAutoConnect(ResponseTx<(), settings::Error>, bool),
+    /// Add a network rule, replacing any rule for the same networks.
+    AddNetworkRule(ResponseTx<(), settings::Error>, NetworkRule),
+    /// Remove the network rule for the given networks.
+    RemoveNetworkRule(ResponseTx<(), settings::Error>, NetworkMatch),
+    /// Set what to do on networks that no network rule matches.
+    SetDefaultNetworkAction(ResponseTx<(), settings::Error>, Option<NetworkAction>),
+    /// Get the network that the host is on, if it is known.
+    #[cfg(not(target_os = "android"))]
+    GetCurrentNetwork(oneshot::Sender<Option<NetworkIdentity>>),
     /// Set the mssfix argument for OpenVPN
     SetOpenVpnMssfix(ResponseTx<(), settings::Error>, Option<u16>),
     /// Set proxy details for OpenVPN
@@ -236,12 +468,133 @@ pub enum DaemonCommand {
     SetBridgeState(ResponseTx<(), settings::Error>, BridgeState),
     /// Set if IPv6 should be enabled in the tunnel
     SetEnableIpv6(ResponseTx<(), settings::Error>, bool),
+    /// Set whether to connect with IPv4 only if IPv6 cannot be set up in the tunnel
+    SetIpv6FailurePolicy(ResponseTx<(), settings::Error>, Ipv6FailurePolicy),
+    /// Set the name of the tunnel interface, or use the default name if `None`
+    #[cfg(not(target_os = "android"))]
+    SetTunnelInterfaceName(ResponseTx<(), Error>, Option<String>),
     /// Set DNS options or servers to use
     SetDnsOptions(ResponseTx<(), settings::Error>, DnsOptions),
+    /// Return the DNS forwarders, such as dnscrypt-proxy, that are running on this device
+    #[cfg(not(target_os = "android"))]
+    DetectDnsForwarders(oneshot::Sender<Vec<dns_forwarder::DnsForwarder>>),
+    /// Set the size and number of logs that are kept
+    SetLogRotationSettings(ResponseTx<(), settings::Error>, LogRotationSettings),
+    /// Set how much network detail is written to the daemon log
+    SetLogPrivacy(ResponseTx<(), settings::Error>, LogPrivacyLevel),
+    /// Start or stop collecting connection statistics. Stopping removes the statistics
+    SetConnectionStatistics(ResponseTx<(), settings::Error>, bool),
+    /// Enable or disable sending important events to the system log
+    SetSystemLog(ResponseTx<(), settings::Error>, bool),
+    /// Enable or disable writing the daemon log as JSON
+    SetJsonLogs(ResponseTx<(), settings::Error>, bool),
+    /// Apply the server mode profile, or leave server mode
+    SetServerMode(ResponseTx<(), settings::Error>, bool),
+    /// Start or stop providing the D-Bus service
+    #[cfg(target_os = "linux")]
+    SetDbusService(ResponseTx<(), settings::Error>, bool),
+    /// Configure the proxy that lets devices on the local network use the tunnel. The settings
+    /// must have been validated
+    SetLanProxySettings(ResponseTx<(), settings::Error>, LanProxySettings),
+    /// Configure the read-only API on localhost. The settings must have been validated
+    SetLocalApiSettings(ResponseTx<(), settings::Error>, LocalApiSettings),
+    /// Get the token that clients of the local API must present, creating it if needed
+    GetLocalApiToken(ResponseTx<String, Error>),
+    /// Set the scripts that are run when the tunnel connects and disconnects. The settings must
+    /// have been validated
+    SetHookSettings(ResponseTx<(), settings::Error>, HookSettings),
+    /// Set how long to wait before retrying after a failed connection attempt. The settings must
+    /// have been validated
+    SetReconnectBackoff(ResponseTx<(), settings::Error>, ReconnectBackoffSettings),
+    /// Set how the exit IP is checked while connected. The settings must have been validated
+    SetExitIpCheck(ResponseTx<(), settings::Error>, ExitIpCheckSettings),
+    /// Turn an experimental feature on or off
+    SetBetaFeature(ResponseTx<(), settings::Error>, BetaFeature, bool),
+    /// Set which system services may send traffic while network access is blocked.
+    SetAllowedSystemServices(ResponseTx<(), settings::Error>, AllowedSystemServices),
+    /// Set the changes to apply to the tunnel parameters of every connection attempt. The
+    /// overrides must have been validated
+    SetTunnelParameterOverrides(ResponseTx<(), settings::Error>, TunnelParameterOverrides),
+    /// Check whether a captive portal is intercepting web traffic
+    #[cfg(not(target_os = "android"))]
+    DetectCaptivePortal(oneshot::Sender<captive_portal::PortalStatus>),
+    /// Block all traffic except what is needed to log in to a captive portal, until the login
+    /// has been completed or has timed out. The target state is then entered again
+    #[cfg(not(target_os = "android"))]
+    StartPortalLogin(oneshot::Sender<()>),
+    /// End the captive portal login early
+    #[cfg(not(target_os = "android"))]
+    StopPortalLogin(oneshot::Sender<()>),
+    /// Get the traffic through the connected tunnel
+    GetTunnelStats(oneshot::Sender<Option<talpid_core::tunnel::TunnelStats>>),
+    /// Subscribe to the traffic through the connected tunnel. The stats are sent every time the
+    /// traffic counters are read, which is done more often while there are subscribers. The
+    /// subscriber is removed once the receiver is dropped or full.
+    TunnelStatsListen(tokio::sync::mpsc::Sender<talpid_core::tunnel::TunnelStats>),
+    /// Get whether connection statistics are collected, and the statistics for each relay
+    GetConnectionStatistics(
+        oneshot::Sender<(bool, BTreeMap<String, connection_stats::RelayStats>)>,
+    ),
+    /// Remove the collected connection statistics
+    ClearConnectionStatistics(ResponseTx<(), Error>),
+    /// Get whether connect times are recorded, and their distribution for each version of the
+    /// daemon
+    GetConnectTimes(oneshot::Sender<(bool, Vec<connect_times::VersionConnectTimes>)>),
+    /// Get the health of the relays that have recently been connected to
+    GetRelayHealth(oneshot::Sender<Vec<relay_health::RelayHealthStatus>>),
+    /// Forget the health of all relays
+    ClearRelayHealth(oneshot::Sender<()>),
+    /// Get the latency to the relays with the given hostnames, or to every active relay if none
+    /// are given. Relays that have not been measured recently are measured first if the bool is
+    /// set
+    #[cfg(not(target_os = "android"))]
+    GetRelayLatencies(
+        oneshot::Sender<Result<Vec<relay_latency::RelayLatency>, relay_latency::Error>>,
+        Vec<String>,
+        bool,
+    ),
+    /// Get the rules that the firewall currently has in place
+    GetFirewallRules(oneshot::Sender<io::Result<talpid_core::firewall::FirewallRules>>),
+    /// Remove the routes and firewall objects left behind by a previous daemon, such as one that
+    /// crashed
+    RemoveOrphanedObjects(oneshot::Sender<tunnel_state_machine::OrphanedObjects>),
+    /// Get the phases of the most recent connection attempts
+    GetConnectionAttempts(oneshot::Sender<Vec<connection_trace::Attempt>>),
+    /// Get the other VPN software that was detected, which is likely to keep the tunnel from
+    /// working
+    GetConflictingSoftware(oneshot::Sender<Vec<ConflictingSoftware>>),
+    /// Get the capabilities that the daemon was found to be missing when it started, without which
+    /// connecting is expected to fail, and whether the settings directory is read-only
+    GetMissingCapabilities(oneshot::Sender<(Vec<MissingCapability>, bool)>),
+    /// Get the configuration of the connected WireGuard tunnel with the private key redacted. If
+    /// the flag is set, the full configuration is also written to a file that only administrators
+    /// can read, and the path of the file is returned.
+    ExportWireguardConfig(ResponseTx<(String, Option<PathBuf>), Error>, bool),
+    /// Get the settings that can be replicated on other devices, serialized as a settings profile
+    ExportSettingsProfile(ResponseTx<String, Error>),
+    /// Apply a settings profile that was exported by `ExportSettingsProfile`, resolving conflicts
+    /// with local settings as requested
+    ImportSettingsProfile(
+        ResponseTx<settings_profile::ImportReport, Error>,
+        String,
+        settings_profile::ConflictResolution,
+    ),
     /// Set MTU for wireguard tunnels
     SetWireguardMtu(ResponseTx<(), settings::Error>, Option<u16>),
+    /// Set the interval, in seconds, at which to ping the gateway to check the connectivity of
+    /// wireguard tunnels. If unset, connectivity is inferred from the traffic
+    SetWireguardIcmpCheckInterval(ResponseTx<(), settings::Error>, Option<u16>),
+    /// Set the packet loss, in percent, above which to reconnect wireguard tunnels to another
+    /// relay. If unset, packet loss never causes a reconnect
+    SetWireguardPacketLossThreshold(ResponseTx<(), settings::Error>, Option<u8>),
     /// Set automatic key rotation interval for wireguard tunnels
     SetWireguardRotationInterval(ResponseTx<(), settings::Error>, Option<RotationInterval>),
+    /// Set whether wireguard tunnels negotiate a post-quantum preshared key with the relay
+    SetQuantumResistantTunnel(ResponseTx<(), settings::Error>, QuantumResistantState),
+    /// Set obfuscation settings. The settings must have been validated
+    SetObfuscationSettings(ResponseTx<(), settings::Error>, ObfuscationSettings),
+    /// Set whether the first connection attempts use the connection profile of the country
+    SetConnectionProfiles(ResponseTx<(), settings::Error>, bool),
     /// Get the daemon settings
     GetSettings(oneshot::Sender<Settings>),
     /// Generate new wireguard key
@@ -254,6 +607,57 @@ pub enum DaemonCommand {
     GetVersionInfo(oneshot::Sender<Option<AppVersionInfo>>),
     /// Get current version of the app
     GetCurrentVersion(oneshot::Sender<AppVersion>),
+    /// Get information about the API endpoints that are being used
+    GetApiAccessStatus(oneshot::Sender<ApiAccessStatus>),
+    /// Get the periodic API tasks and when they will run next
+    GetScheduledTasks(oneshot::Sender<Vec<scheduler::ScheduledTaskInfo>>),
+    /// Enable or disable recording of API requests
+    SetApiTraceEnabled(oneshot::Sender<()>, bool),
+    /// Get whether API requests are being recorded, and the recorded requests
+    GetApiTrace(oneshot::Sender<(bool, Vec<mullvad_rpc::trace::TraceEntry>)>),
+    /// Enable or disable checking the system clock when the API certificate is rejected
+    SetApiClockCheck(ResponseTx<(), settings::Error>, bool),
+    /// Temporarily change the log levels of the daemon, optionally reverting them after a while
+    SetLogLevelOverrides(
+        oneshot::Sender<()>,
+        Vec<logging::LogLevelDirective>,
+        Option<Duration>,
+    ),
+    /// Get the log levels that are currently overridden
+    GetLogLevelOverrides(oneshot::Sender<logging::LogLevelOverrides>),
+    /// Revert to the log levels that the daemon was started with
+    ResetLogLevelOverrides(oneshot::Sender<()>),
+    /// Add a proxy for API traffic. If it is enabled, it is probed before being saved
+    AddApiAccessMethod(ResponseTx<(), Error>, ApiAccessMethod),
+    /// Remove the API access method with the given name
+    RemoveApiAccessMethod(ResponseTx<(), Error>, String),
+    /// Enable or disable the API access method with the given name. It is probed before being
+    /// enabled
+    SetApiAccessMethodEnabled(ResponseTx<(), Error>, String, bool),
+    /// Check whether the API can be reached through the given proxy
+    TestApiProxy(ResponseTx<(), Error>, ApiProxy),
+    /// Open a SOCKS5 proxy on localhost through which clients can reach the API using the current
+    /// API access method, and return its address
+    #[cfg(not(target_os = "android"))]
+    OpenApiForwarder(ResponseTx<SocketAddr, Error>),
+    /// Send API traffic through the API access method with the given name, and try it first from
+    /// now on. The method is enabled, and checked before it is used
+    UseApiAccessMethod(ResponseTx<(), Error>, String),
+    /// Set how the API hostname is resolved
+    SetApiResolutionStrategy(ResponseTx<(), settings::Error>, ApiResolutionStrategy),
+    /// Send a problem report to the API, using the same access method as other API requests.
+    /// If the API cannot be reached, the report is queued and its ID is returned.
+    SubmitProblemReport(ResponseTx<Option<String>, Error>, ProblemReport),
+    /// Get the problem reports that are waiting to be sent
+    GetPendingProblemReports(oneshot::Sender<Vec<problem_report_outbox::PendingReport>>),
+    /// Remove a problem report that is waiting to be sent
+    CancelPendingProblemReport(ResponseTx<(), Error>, String),
+    /// Get the information that is included in problem reports
+    GetDiagnostics(oneshot::Sender<diagnostics::Diagnostics>),
+    /// Collect the logs and the state of the daemon into a problem report in the cache directory
+    /// and return its path. The given strings are redacted in addition to the account numbers
+    #[cfg(not(target_os = "android"))]
+    CollectProblemReport(ResponseTx<PathBuf, Error>, Vec<String>),
     /// Remove settings and clear the cache
     #[cfg(not(target_os = "android"))]
     FactoryReset(ResponseTx<(), Error>),
@@ -270,20 +674,36 @@ pub enum DaemonCommand {
     #[cfg(target_os = "linux")]
     ClearSplitTunnelProcesses(ResponseTx<(), split_tunnel::Error>),
     /// Exclude traffic of an application from the tunnel
-    #[cfg(windows)]
+    #[cfg(any(windows, target_os = "linux"))]
     AddSplitTunnelApp(ResponseTx<(), Error>, PathBuf),
     /// Remove application from list of apps to exclude from the tunnel
-    #[cfg(windows)]
+    #[cfg(any(windows, target_os = "linux"))]
     RemoveSplitTunnelApp(ResponseTx<(), Error>, PathBuf),
     /// Clear list of apps to exclude from the tunnel
-    #[cfg(windows)]
+    #[cfg(any(windows, target_os = "linux"))]
     ClearSplitTunnelApps(ResponseTx<(), Error>),
+    /// Replace the list of apps to exclude from the tunnel
+    #[cfg(any(windows, target_os = "linux"))]
+    SetSplitTunnelApps(ResponseTx<(), Error>, HashSet<PathBuf>),
     /// Disable split tunnel
-    #[cfg(windows)]
+    #[cfg(any(windows, target_os = "linux"))]
     SetSplitTunnelState(ResponseTx<(), Error>, bool),
     /// Toggle wireguard-nt on or off
     #[cfg(target_os = "windows")]
     UseWireGuardNt(ResponseTx<(), Error>, bool),
+    /// Toggle the ovpn-dco-win driver for OpenVPN on or off
+    #[cfg(target_os = "windows")]
+    UseOvpnDco(ResponseTx<(), Error>, bool),
+    /// Set what to do with the Wintun adapter once an OpenVPN tunnel has closed
+    #[cfg(target_os = "windows")]
+    SetWintunAdapterPolicy(ResponseTx<(), Error>, openvpn::WintunAdapterPolicy),
+    /// Bring the state that outlives the daemon in line with the current version. Requested by
+    /// the installer, whose version is given if known.
+    #[cfg(windows)]
+    RunMigration(oneshot::Sender<migration::MigrationReport>, Option<String>),
+    /// Toggle between the WireGuard kernel module and wireguard-go
+    #[cfg(target_os = "linux")]
+    UseUserspaceWireguard(ResponseTx<(), Error>, bool),
     /// Makes the daemon exit the main loop and quit.
     Shutdown,
     /// Saves the target tunnel state and enters a blocking state. The state is restored
@@ -293,15 +713,35 @@ pub enum DaemonCommand {
     BypassSocket(RawFd, oneshot::Sender<()>),
 }
 
+/// A problem report that is submitted to the API by the daemon on behalf of a client.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProblemReport {
+    pub email: String,
+    pub message: String,
+    pub log: String,
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// The new WireGuard key of the device, and the new name unless renaming the device failed.
+type IdentityRotationResult = Result<
+    (
+        mullvad_types::wireguard::WireguardData,
+        Option<DeviceIdentityChange>,
+    ),
+    Error,
+>;
+
 /// All events that can happen in the daemon. Sent from various threads and exposed interfaces.
 pub(crate) enum InternalDaemonEvent {
     /// Tunnel has changed state.
-    TunnelStateTransition(TunnelStateTransition),
+    TunnelStateTransition(TunnelStateTransition, Option<TransitionReason>),
     /// Request from the `MullvadTunnelParametersGenerator` to obtain a new relay.
     GenerateTunnelParameters(
         sync_mpsc::Sender<Result<TunnelParameters, ParameterGenerationError>>,
         u32,
     ),
+    /// The relay list cached on disk has been read in the background.
+    RelayListLoaded,
     /// A command sent to the daemon.
     Command(DaemonCommand),
     /// Daemon shutdown triggered by a signal, ctrl-c or similar.
@@ -313,15 +753,118 @@ pub(crate) enum InternalDaemonEvent {
             Result<mullvad_types::wireguard::WireguardData, wireguard::Error>,
         ),
     ),
+    /// Automatic key rotation added a new WireGuard key to the account. The old key, which is
+    /// included, is still valid until it is removed.
+    WgKeyRotated(
+        AccountToken,
+        mullvad_types::wireguard::WireguardData,
+        talpid_types::net::wireguard::PublicKey,
+    ),
+    /// The device is due to be given a new name and WireGuard key.
+    IdentityRotationDue,
+    /// The WireGuard key of the device was replaced by identity rotation. The device was also
+    /// renamed, unless that failed.
+    IdentityRotated(AccountToken, IdentityRotationResult),
     /// New Account created
     NewAccountEvent(AccountToken, oneshot::Sender<Result<String, Error>>),
     /// The background job fetching new `AppVersionInfo`s got a new info object.
     NewAppVersionInfo(AppVersionInfo),
+    /// The proxy of an API access method was successfully probed, and the change can be applied.
+    ApiAccessMethodProbed(ApiAccessMethodChange, ResponseTx<(), Error>),
+    /// API requests keep failing because of network errors, through the current access method.
+    ApiUnreachable,
+    /// An API access method was checked as part of the switch with the given ID.
+    ApiAccessMethodChecked(u64, String, io::Result<api_access::ActiveMethod>),
+    /// The API no longer recognizes the WireGuard key of the account, meaning that this device
+    /// has been revoked.
+    DeviceRevoked(AccountToken, talpid_types::net::wireguard::PublicKey),
+    /// The account is about to run out of time, or has run out of time.
+    AccountExpiry(AccountExpiryEvent),
+    /// The ports forwarded to the devices of an account were fetched.
+    PortForwardsFetched(AccountToken, Vec<PortForward>),
+    /// The traffic counters of the connected tunnel were read. The connection ID is set if
+    /// connection statistics are collected and the sample is due for them.
+    TrafficSample(
+        Option<connection_stats::ConnectionId>,
+        talpid_core::tunnel::TrafficCounters,
+        Instant,
+    ),
+    /// A problem report could not be sent because the API could not be reached.
+    QueueProblemReport(
+        ProblemReport,
+        mullvad_rpc::rest::IdempotencyToken,
+        String,
+        ResponseTx<Option<String>, Error>,
+    ),
+    /// An attempt was made at sending a queued problem report.
+    ProblemReportRetried(String, problem_report_outbox::RetryResult),
+    /// A round of attempts at sending the queued problem reports has finished.
+    ProblemReportRetriesFinished,
+    /// The captive portal detection hosts of the platform were resolved.
+    CaptivePortalHostsResolved(captive_portal::ResolvedHosts),
+    /// The captive portal login has been completed or has timed out.
+    #[cfg(not(target_os = "android"))]
+    PortalLoginFinished(captive_portal::PortalStatus),
+    /// A power or session event was reported by the service control manager.
+    #[cfg(windows)]
+    SystemEvent(SystemEvent),
+    /// The connectivity of the host changed, such as when it joined another network.
+    ConnectivityChanged,
+    /// The host joined another network.
+    #[cfg(not(target_os = "android"))]
+    NetworkChanged(NetworkIdentity),
+    /// A search for conflicting software has finished.
+    ConflictingSoftwareDetected(Vec<ConflictingSoftware>),
+    /// A check of the exit IP found that traffic does not exit through the connected relay.
+    ExitIpMismatchDetected(ExitIpMismatch),
+}
+
+/// Power and user session events that the Windows service receives. Sessions are identified by
+/// their session ID.
+#[cfg(windows)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemEvent {
+    /// The machine is about to be suspended or hibernated.
+    Suspend,
+    /// The machine has resumed from suspension or hibernation.
+    Resume,
+    SessionLogon(u32),
+    SessionLogoff(u32),
+    SessionLock(u32),
+    SessionUnlock(u32),
+    /// A remote desktop client connected to a session.
+    RemoteConnect(u32),
+    /// A remote desktop client disconnected from a session.
+    RemoteDisconnect(u32),
+}
+
+/// A captive portal login in progress.
+#[cfg(not(target_os = "android"))]
+struct PortalLogin {
+    /// Checks whether the login has been completed. It is started once traffic is blocked for the
+    /// login.
+    job: Option<AbortHandle>,
+}
+
+/// A change to the API access methods that requires the proxy to be probed first.
+pub(crate) enum ApiAccessMethodChange {
+    Add(ApiAccessMethod),
+    Enable(String),
+}
+
+/// A switch of the API access method that is in progress. The methods are checked in turn, and
+/// API traffic is sent through the first one that the API can be reached through.
+struct ApiAccessSwitch {
+    id: u64,
+    /// The methods that remain to be checked.
+    remaining: VecDeque<(String, api_access::AccessMethod)>,
+    /// Set if the user asked to use the method, which then becomes the preferred method.
+    requested: Option<ResponseTx<(), Error>>,
 }
 
-impl From<TunnelStateTransition> for InternalDaemonEvent {
-    fn from(tunnel_state_transition: TunnelStateTransition) -> Self {
-        InternalDaemonEvent::TunnelStateTransition(tunnel_state_transition)
+impl From<TunnelStateChange> for InternalDaemonEvent {
+    fn from((tunnel_state_transition, reason): TunnelStateChange) -> Self {
+        InternalDaemonEvent::TunnelStateTransition(tunnel_state_transition, reason)
     }
 }
 
@@ -337,6 +880,18 @@ impl From<AppVersionInfo> for InternalDaemonEvent {
     }
 }
 
+impl From<AccountExpiryEvent> for InternalDaemonEvent {
+    fn from(event: AccountExpiryEvent) -> Self {
+        InternalDaemonEvent::AccountExpiry(event)
+    }
+}
+
+impl From<ExitIpMismatch> for InternalDaemonEvent {
+    fn from(mismatch: ExitIpMismatch) -> Self {
+        InternalDaemonEvent::ExitIpMismatchDetected(mismatch)
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 enum DaemonExecutionState {
     Running,
@@ -485,8 +1040,8 @@ where
 
 /// Trait representing something that can broadcast daemon events.
 pub trait EventListener {
-    /// Notify that the tunnel state changed.
-    fn notify_new_state(&self, new_state: TunnelState);
+    /// Notify that the tunnel state changed, and what caused it if that is known.
+    fn notify_new_state(&self, new_state: TunnelState, reason: Option<TransitionReason>);
 
     /// Notify that the settings changed.
     fn notify_settings(&self, settings: Settings);
@@ -500,10 +1055,29 @@ pub trait EventListener {
 
     /// Notify clients of a key generation event.
     fn notify_key_event(&self, key_event: KeygenEvent);
+
+    /// Notify that this device was revoked or registered again.
+    fn notify_device_state(&self, device_state: DeviceState);
+
+    /// Notify that this device was given a new name by identity rotation.
+    fn notify_device_identity(&self, change: DeviceIdentityChange);
+
+    /// Notify that the account is about to run out of time, or has run out of time.
+    fn notify_account_expiry(&self, event: AccountExpiryEvent);
+
+    /// Notify that the other VPN software that is running changed. The list is empty once none
+    /// is detected anymore.
+    fn notify_conflicting_software(&self, conflicts: Vec<ConflictingSoftware>);
+
+    /// Notify that traffic was found to exit somewhere else than through the connected relay.
+    fn notify_exit_ip_mismatch(&self, mismatch: ExitIpMismatch);
+
+    /// Notify that API traffic is sent through another access method, such as a bridge.
+    fn notify_api_access_method(&self, method: ActiveApiAccessMethod);
 }
 
 pub struct Daemon<L: EventListener> {
-    tunnel_command_tx: Arc<mpsc::UnboundedSender<TunnelCommand>>,
+    tunnel_command_tx: Arc<TunnelCommandSender>,
     tunnel_state: TunnelState,
     target_state: TargetState,
     lock_target_cache: bool,
@@ -517,17 +1091,116 @@ pub struct Daemon<L: EventListener> {
     settings: SettingsPersister,
     account_history: account_history::AccountHistory,
     account: account::AccountHandle,
+    expiry_monitor: account_expiry::ExpiryMonitorHandle,
     rpc_runtime: mullvad_rpc::MullvadRpcRuntime,
     rpc_handle: mullvad_rpc::rest::MullvadRestHandle,
     wireguard_key_manager: wireguard::KeyManager,
+    device_state: DeviceState,
+    /// Ports forwarded to this device, as last fetched from the API.
+    port_forwards: Vec<PortForward>,
+    /// Addresses of the captive portal detection hosts, as last resolved while connected.
+    captive_portal_hosts: captive_portal::ResolvedHosts,
+    /// The captive portal login in progress, if any.
+    #[cfg(not(target_os = "android"))]
+    portal_login: Option<PortalLogin>,
+    state_history: diagnostics::StateHistory,
+    connection_stats: connection_stats::ConnectionStats,
+    connect_times: connect_times::ConnectTimes,
+    /// Reads the traffic counters of the connected tunnel.
+    throughput_sampler: Option<AbortHandle>,
+    /// Checks the exit IP of the connected tunnel.
+    exit_ip_checker: Option<AbortHandle>,
+    tunnel_stats: talpid_core::tunnel::TunnelStatsMonitor,
+    tunnel_stats_listeners: Vec<tokio::sync::mpsc::Sender<talpid_core::tunnel::TunnelStats>>,
+    /// Whether the throughput sampler should read the counters at the live stats interval.
+    live_tunnel_stats: Arc<AtomicBool>,
+    system_log: system_log::SystemLog,
+    problem_report_outbox: problem_report_outbox::Outbox,
+    /// Sends the queued problem reports once the API can be reached.
+    problem_report_retry_job: Option<AbortHandle>,
+    problem_report_retry_delay: Duration,
+    /// Whether to connect once the device has been registered again, because the tunnel was
+    /// secured when the device was revoked.
+    reconnect_after_reregistration: bool,
     version_updater_handle: version_check::VersionUpdaterHandle,
+    scheduler_handle: scheduler::SchedulerHandle,
+    /// Address of the proxy used for API traffic, if any. It is allowed through the firewall
+    /// instead of the API address.
+    api_proxy_endpoint: Arc<Mutex<Option<SocketAddr>>>,
+    /// The access method that API traffic is sent through.
+    api_access_method: api_access::ActiveMethod,
+    /// The switch to another API access method that is in progress, if any.
+    api_access_switch: Option<ApiAccessSwitch>,
+    next_api_access_switch_id: u64,
+    /// Where the Shadowsocks client used to reach the API through bridges is found and logs to.
+    #[cfg(not(target_os = "android"))]
+    api_bridge_resources: talpid_core::proxy::ProxyResourceData,
+    /// Proxy on localhost that lets clients reach the API the same way as the daemon, if one
+    /// has been requested.
+    #[cfg(not(target_os = "android"))]
+    api_forwarder: Option<api_forwarder::ApiForwarder>,
     relay_selector: relays::RelaySelector,
+    /// Proxy that lets devices on the local network use the tunnel, if enabled.
+    lan_proxy: Option<lan_proxy::LanProxy>,
+    /// Read-only API on localhost for third-party integrations, if enabled.
+    local_api: Option<local_api::LocalApi>,
+    /// The tunnel state as reported by the local API.
+    local_api_status: local_api::StatusHandle,
+    /// Runs the scripts that are set to run when the tunnel connects and disconnects.
+    #[cfg(not(target_os = "android"))]
+    hooks: hooks::Hooks,
+    /// Local resolver that forwards queries over an encrypted protocol, if enabled.
+    #[cfg(not(target_os = "android"))]
+    dns_proxy: Option<talpid_dns_proxy::DnsProxy>,
+    /// D-Bus service for desktop integrations, if enabled.
+    #[cfg(target_os = "linux")]
+    dbus_service: Option<dbus_service::DbusService>,
+    /// Watches the network that the host is on, so that the network rules can be applied.
+    #[cfg(not(target_os = "android"))]
+    network_watcher: Option<talpid_core::network_identity::NetworkWatcher>,
+    /// The network that the host is on, once it has been looked up.
+    #[cfg(not(target_os = "android"))]
+    current_network: Option<NetworkIdentity>,
+    /// Whether the relay selector has read the relay list cached on disk.
+    relay_list_loaded: bool,
+    /// Requests for tunnel parameters that arrived before the relay list was loaded.
+    pending_tunnel_parameter_requests: Vec<(
+        sync_mpsc::Sender<Result<TunnelParameters, ParameterGenerationError>>,
+        u32,
+    )>,
     last_generated_relay: Option<Relay>,
     last_generated_bridge_relay: Option<Relay>,
+    /// The tunnel parameters that were most recently handed to the tunnel state machine.
+    last_generated_tunnel_parameters: Option<TunnelParameters>,
+    /// A WireGuard key that was replaced by automatic rotation, and the account it belongs to. It
+    /// is removed once the tunnel has connected with the new key, or right away if no tunnel
+    /// depends on it.
+    retired_wireguard_key: Option<(AccountToken, talpid_types::net::wireguard::PublicKey)>,
+    /// Waits for the next identity rotation, if identity rotation is enabled.
+    identity_rotation_timer: Option<identity_rotation::RotationTimer>,
+    identity_rotation_in_progress: bool,
+    /// When the device was last given a new name and WireGuard key, or when identity rotation was
+    /// enabled.
+    last_identity_rotation: Option<chrono::DateTime<chrono::Utc>>,
+    /// The phases of the most recent connection attempts.
+    connection_trace: ConnectionTrace,
+    /// Other VPN software that was detected the last time it was searched for.
+    conflicting_software: Vec<ConflictingSoftware>,
+    /// The capabilities that the preflight check found to be missing when the daemon started.
+    missing_capabilities: Vec<MissingCapability>,
+    /// The connection leases that are held. The tunnel is kept secured while any lease is held.
+    connection_leases: HashSet<u64>,
+    next_connection_lease: u64,
+    /// The target state to restore once the last connection lease has been released.
+    target_state_before_leases: Option<TargetState>,
     app_version_info: Option<AppVersionInfo>,
     shutdown_tasks: Vec<Pin<Box<dyn Future<Output = ()>>>>,
     /// oneshot channel that completes once the tunnel state machine has been shut down
     tunnel_state_machine_shutdown_signal: oneshot::Receiver<()>,
+    /// Cancelled once the daemon starts shutting down, so that the tunnel and the monitors of the
+    /// tunnel state machine stop waiting on things that would delay the shutdown.
+    shutdown_token: CancelToken,
+    settings_dir: PathBuf,
     cache_dir: PathBuf,
 }
 
@@ -546,12 +1219,30 @@ where
     ) -> Result<Self, Error> {
         let (tunnel_state_machine_shutdown_tx, tunnel_state_machine_shutdown_signal) =
             oneshot::channel();
+        let shutdown_token = CancelToken::new();
         let runtime = tokio::runtime::Handle::current();
 
+        // Check for missing privileges and drivers up front, since the OS errors that they cause
+        // while connecting are hard to make sense of
+        let missing_capabilities = {
+            let resource_dir = resource_dir.clone();
+            tokio::task::spawn_blocking(move || preflight::check(&resource_dir))
+                .await
+                .expect("Preflight check panicked")
+        };
+        for capability in &missing_capabilities {
+            log::error!(
+                "The daemon is missing a capability that is needed to connect: {}",
+                capability
+            );
+        }
+
         let (internal_event_tx, internal_event_rx) = command_channel.destructure();
         let (address_change_tx, mut address_change_rx) = mpsc::channel(0);
         let address_change_tx = std::sync::Mutex::new(address_change_tx);
         let address_change_runtime = runtime.clone();
+        let api_proxy_endpoint = Arc::new(Mutex::new(None));
+        let listener_proxy_endpoint = api_proxy_endpoint.clone();
 
         let mut rpc_runtime = mullvad_rpc::MullvadRpcRuntime::with_cache(
             runtime.clone(),
@@ -559,6 +1250,7 @@ where
             &cache_dir,
             true,
             move |address| {
+                let address = listener_proxy_endpoint.lock().unwrap().unwrap_or(address);
                 let (result_tx, result_rx) = oneshot::channel();
 
                 let mut tx = address_change_tx.lock().unwrap().clone();
@@ -584,9 +1276,15 @@ where
             relay_list_listener.notify_relay_list(relay_list.clone());
         };
 
+        let relay_list_loaded_tx = internal_event_tx.clone();
+        let on_relay_list_loaded = move || {
+            let _ = relay_list_loaded_tx.send(InternalDaemonEvent::RelayListLoaded);
+        };
+
         let relay_selector = relays::RelaySelector::new(
             rpc_handle.clone(),
             on_relay_list_update,
+            on_relay_list_loaded,
             &resource_dir,
             &cache_dir,
             api_availability.clone(),
@@ -594,12 +1292,31 @@ where
 
 
         let mut settings = SettingsPersister::load(&settings_dir).await;
+        talpid_core::logging::set_rotation_limits(logging::rotation_limits(&settings.log_rotation));
+        logging::set_log_privacy(settings.log_privacy);
+        logging::set_json_output(settings.json_logs);
+        let profile_state = relay_selector.connection_profiles_handle();
+        let profiles = connection_profiles::load(&cache_dir, &resource_dir).await;
+        {
+            let mut state = profile_state.lock();
+            state.set_profiles(profiles);
+            state.set_enabled(settings.connection_profiles);
+        }
+        relay_selector.relay_latencies_handle().lock().set_enabled(
+            settings
+                .beta_features
+                .is_enabled(BetaFeature::LatencyBasedSelection),
+        );
+        rpc_runtime
+            .clock_check()
+            .set_enabled(settings.api_clock_check);
 
         if version::is_beta_version() {
             let _ = settings.set_show_beta_releases(true).await;
         }
 
         let app_version_info = version_check::load_cache(&cache_dir).await;
+        let last_identity_rotation = identity_rotation::load_last_rotation(&cache_dir).await;
         let (version_updater, version_updater_handle) = version_check::VersionUpdater::new(
             rpc_handle.clone(),
             api_availability.clone(),
@@ -609,10 +1326,122 @@ where
             settings.show_beta_releases,
         );
         tokio::spawn(version_updater.run());
+
+        let (mut scheduler, scheduler_handle) = scheduler::Scheduler::new(api_availability.clone());
+        let relay_list_updater = relay_selector.updater_handle();
+        scheduler.add_task(
+            "relay-list",
+            relays::UPDATE_INTERVAL,
+            relays::UPDATE_INTERVAL,
+            true,
+            Box::new(move || {
+                let mut updater = relay_list_updater.clone();
+                Box::pin(async move {
+                    if let Err(error) = updater.update_relay_list_deferred().await {
+                        log::error!(
+                            "{}",
+                            error.display_chain_with_msg("Failed to update relay list")
+                        );
+                    }
+                })
+            }),
+        );
+        let scheduled_version_updater = version_updater_handle.clone();
+        scheduler.add_task(
+            "version-check",
+            version_check::UPDATE_INTERVAL,
+            version_check::UPDATE_INITIAL_DELAY,
+            true,
+            Box::new(move || {
+                let mut updater = scheduled_version_updater.clone();
+                Box::pin(async move { updater.run_background_version_check().await })
+            }),
+        );
+        let profiles_proxy = mullvad_rpc::ConnectionProfilesProxy::new(rpc_handle.clone());
+        let profiles_cache_dir = cache_dir.clone();
+        scheduler.add_task(
+            "connection-profiles",
+            connection_profiles::UPDATE_INTERVAL,
+            connection_profiles::UPDATE_INITIAL_DELAY,
+            false,
+            Box::new(move || {
+                let update = connection_profiles::update(
+                    profiles_proxy.clone(),
+                    profiles_cache_dir.clone(),
+                    profile_state.clone(),
+                );
+                Box::pin(async move {
+                    if let Err(error) = update.await {
+                        log::error!(
+                            "{}",
+                            error.display_chain_with_msg("Failed to update connection profiles")
+                        );
+                    }
+                })
+            }),
+        );
+        let api_resolver = rpc_runtime.api_resolver();
+        api_resolver.set_strategy(settings.api_resolution_strategy);
+        scheduler.add_task(
+            "api-resolve",
+            API_RESOLVE_INTERVAL,
+            Duration::from_secs(0),
+            false,
+            Box::new(move || {
+                let api_resolver = api_resolver.clone();
+                Box::pin(async move { api_resolver.update_addresses().await })
+            }),
+        );
+        let address_rpc_handle = rpc_handle.clone();
+        let address_availability = api_availability.clone();
+        let address_scheduler_handle = scheduler_handle.clone();
+        scheduler.add_task(
+            "api-addresses",
+            mullvad_rpc::rest::API_IP_CHECK_INTERVAL,
+            mullvad_rpc::rest::API_IP_CHECK_DELAY,
+            false,
+            Box::new(move || {
+                let rpc_handle = address_rpc_handle.clone();
+                let api_availability = address_availability.clone();
+                let scheduler_handle = address_scheduler_handle.clone();
+                Box::pin(async move {
+                    let result = match api_availability.wait_available().await {
+                        Ok(()) => rpc_handle.update_api_addresses().await.map_err(|error| {
+                            error.display_chain_with_msg("Failed to fetch new API addresses")
+                        }),
+                        Err(error) => Err(error.display_chain_with_msg(
+                            "Failed while waiting for the API to become available",
+                        )),
+                    };
+                    if let Err(message) = result {
+                        log::error!("{}", message);
+                        scheduler_handle.retry_task(
+                            "api-addresses",
+                            mullvad_rpc::rest::API_IP_CHECK_ERROR_INTERVAL,
+                        );
+                    }
+                })
+            }),
+        );
+        tokio::spawn(scheduler.run());
+
+        // The account history is kept with the caches while the settings directory is read-only
+        let account_history_dir = if settings.is_read_only() {
+            &cache_dir
+        } else {
+            &settings_dir
+        };
         let account_history =
-            account_history::AccountHistory::new(&cache_dir, &settings_dir, &mut settings)
+            account_history::AccountHistory::new(&cache_dir, account_history_dir, &mut settings)
                 .await
                 .map_err(Error::LoadAccountHistory)?;
+        let connection_stats =
+            connection_stats::ConnectionStats::load(&cache_dir, settings.connection_statistics)
+                .await;
+        let connect_times =
+            connect_times::ConnectTimes::load(&cache_dir, settings.connection_statistics).await;
+        let system_log = system_log::SystemLog::new(settings.system_log);
+        let problem_report_outbox = problem_report_outbox::Outbox::load(&cache_dir).await;
 
         // Restore the tunnel to a previous state
         let target_cache = cache_dir.join(TARGET_START_STATE_FILE);
@@ -647,7 +1476,13 @@ where
         };
 
 
-        let initial_target_state = if settings.get_account_token().is_some() {
+        // A custom tunnel endpoint can be connected to without an account
+        let can_connect = settings.get_account_token().is_some()
+            || matches!(
+                settings.get_relay_settings(),
+                RelaySettings::CustomTunnelEndpoint(_)
+            );
+        let initial_target_state = if can_connect {
             if settings.auto_connect {
                 // Note: Auto-connect overrides the cached target state
                 info!("Automatically connecting since auto-connect is turned on");
@@ -660,10 +1495,18 @@ where
         };
         Self::cache_target_state(&cache_dir, initial_target_state).await;
 
+        let api_access_method = Self::initial_api_access_method(&settings);
+        *api_proxy_endpoint.lock().unwrap() = api_access_method.endpoint();
         let initial_api_endpoint = Endpoint::from_socket_address(
-            rpc_runtime.address_cache.peek_address(),
+            api_access_method
+                .endpoint()
+                .unwrap_or_else(|| rpc_runtime.address_cache.peek_address()),
             TransportProtocol::Tcp,
         );
+        rpc_runtime
+            .proxy_handle()
+            .set(api_access_method.proxy.clone());
+        Self::forward_api_unreachable_events(&rpc_runtime, internal_event_tx.clone());
         #[cfg(windows)]
         let exclude_paths = if settings.split_tunnel.enable_exclusions {
             settings
@@ -676,18 +1519,44 @@ where
             vec![]
         };
 
+        Self::warn_about_unreachable_dns_servers(&settings);
+
         let (offline_state_tx, offline_state_rx) = mpsc::unbounded();
+        let connection_trace = ConnectionTrace::new();
+
+        #[cfg(not(target_os = "android"))]
+        let api_bridge_resources = talpid_core::proxy::ProxyResourceData {
+            resource_dir: resource_dir.clone(),
+            log_dir: Self::api_bridge_log_dir(log_dir.as_deref()).await,
+        };
 
         let tunnel_command_tx = tunnel_state_machine::spawn(
             runtime.clone(),
             tunnel_state_machine::InitialTunnelState {
                 allow_lan: settings.allow_lan,
+                mdns_reflector: settings.mdns_reflector,
+                system_service_exemptions: SystemServiceExemptions {
+                    ntp: settings.allowed_system_services.ntp,
+                    captive_portal_hosts: vec![],
+                    portal_login: false,
+                },
                 block_when_disconnected: settings.block_when_disconnected,
                 dns_servers: Self::get_dns_resolvers(&settings.tunnel_options.dns_options),
+                #[cfg(not(target_os = "android"))]
+                dns_upstream_servers: Self::get_dns_upstream_servers(
+                    &settings.tunnel_options.dns_options,
+                ),
                 allowed_endpoint: initial_api_endpoint,
                 reset_firewall: initial_target_state != TargetState::Secured,
                 #[cfg(windows)]
                 exclude_paths,
+                #[cfg(windows)]
+                allow_virtual_networks: settings.allow_virtual_networks,
+                firewall_exceptions: settings.firewall_exceptions.clone(),
+                #[cfg(target_os = "linux")]
+                offline_monitor: Self::offline_monitor_config(),
+                connection_trace: connection_trace.clone(),
+                reconnect_backoff: Self::reconnect_backoff(&settings.reconnect_backoff),
             },
             tunnel_parameters_generator,
             log_dir,
@@ -696,19 +1565,27 @@ where
             internal_event_tx.to_specialized_sender(),
             offline_state_tx,
             tunnel_state_machine_shutdown_tx,
+            shutdown_token.child(),
             #[cfg(target_os = "android")]
             android_context,
         )
         .await
         .map_err(Error::TunnelError)?;
 
-        Self::forward_offline_state(&runtime, api_availability.clone(), offline_state_rx).await;
+        Self::forward_offline_state(
+            &runtime,
+            api_availability.clone(),
+            relay_selector.connectivity_handle(),
+            offline_state_rx,
+            internal_event_tx.clone(),
+        )
+        .await;
 
         let tsm_api_address_change_tx = Arc::downgrade(&tunnel_command_tx);
         tokio::spawn(async move {
             while let Some(address_change) = address_change_rx.next().await {
                 if let Some(tx) = tsm_api_address_change_tx.upgrade() {
-                    let _ = tx.unbounded_send(address_change);
+                    let _ = tx.send(address_change).await;
                 } else {
                     return;
                 }
@@ -719,6 +1596,7 @@ where
             internal_event_tx.clone(),
             api_availability.clone(),
             rpc_handle.clone(),
+            scheduler_handle.clone(),
         );
 
         let account = account::Account::new(
@@ -728,6 +1606,15 @@ where
             api_availability.clone(),
         );
 
+        let (expiry_monitor, expiry_monitor_handle) = account_expiry::ExpiryMonitor::new(
+            account.clone(),
+            scheduler_handle.ticks(),
+            internal_event_tx.to_specialized_sender(),
+            settings.get_account_token(),
+            settings.account_expiry.warning_thresholds.clone(),
+        );
+        tokio::spawn(expiry_monitor.run());
+
         // Attempt to download a fresh relay list
         let mut relay_handle = relay_selector.updater_handle();
         relay_handle
@@ -735,6 +1622,22 @@ where
             .await
             .expect("Relay list updated thread has stopped unexpectedly");
 
+        #[cfg(target_os = "linux")]
+        let exclude_pids = split_tunnel::PidManager::new().map_err(Error::InitSplitTunneling)?;
+        #[cfg(target_os = "linux")]
+        if settings.split_tunnel.enable_exclusions {
+            let apps: Vec<_> = settings.split_tunnel.apps.iter().collect();
+            if let Err(error) = exclude_pids.set_paths(&apps) {
+                error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to exclude applications")
+                );
+            }
+        }
+
+        #[cfg(not(target_os = "android"))]
+        let hooks = hooks::Hooks::new(Arc::downgrade(&tunnel_command_tx));
+
         let mut daemon = Daemon {
             tunnel_command_tx,
             tunnel_state: TunnelState::Disconnected,
@@ -742,7 +1645,7 @@ where
             lock_target_cache: false,
             state: DaemonExecutionState::Running,
             #[cfg(target_os = "linux")]
-            exclude_pids: split_tunnel::PidManager::new().map_err(Error::InitSplitTunneling)?,
+            exclude_pids,
             rx: internal_event_rx,
             tx: internal_event_tx,
             reconnection_job: None,
@@ -750,24 +1653,106 @@ where
             settings,
             account_history,
             account,
+            expiry_monitor: expiry_monitor_handle,
             rpc_runtime,
             rpc_handle,
             wireguard_key_manager,
+            device_state: DeviceState::Active,
+            port_forwards: vec![],
+            captive_portal_hosts: captive_portal::ResolvedHosts::new(),
+            #[cfg(not(target_os = "android"))]
+            portal_login: None,
+            state_history: diagnostics::StateHistory::default(),
+            connection_stats,
+            connect_times,
+            throughput_sampler: None,
+            exit_ip_checker: None,
+            tunnel_stats: talpid_core::tunnel::TunnelStatsMonitor::new(),
+            tunnel_stats_listeners: vec![],
+            live_tunnel_stats: Arc::new(AtomicBool::new(false)),
+            system_log,
+            problem_report_outbox,
+            problem_report_retry_job: None,
+            problem_report_retry_delay: problem_report_outbox::MIN_RETRY_DELAY,
+            reconnect_after_reregistration: false,
             version_updater_handle,
+            scheduler_handle,
+            api_proxy_endpoint,
+            api_access_method,
+            api_access_switch: None,
+            next_api_access_switch_id: 0,
+            #[cfg(not(target_os = "android"))]
+            api_bridge_resources,
+            #[cfg(not(target_os = "android"))]
+            api_forwarder: None,
             relay_selector,
+            lan_proxy: None,
+            local_api: None,
+            local_api_status: local_api::StatusHandle::default(),
+            #[cfg(not(target_os = "android"))]
+            hooks,
+            #[cfg(not(target_os = "android"))]
+            dns_proxy: None,
+            #[cfg(target_os = "linux")]
+            dbus_service: None,
+            #[cfg(not(target_os = "android"))]
+            network_watcher: None,
+            #[cfg(not(target_os = "android"))]
+            current_network: None,
+            relay_list_loaded: false,
+            pending_tunnel_parameter_requests: vec![],
             last_generated_relay: None,
             last_generated_bridge_relay: None,
+            last_generated_tunnel_parameters: None,
+            retired_wireguard_key: None,
+            identity_rotation_timer: None,
+            identity_rotation_in_progress: false,
+            last_identity_rotation,
+            connection_trace,
+            conflicting_software: vec![],
+            missing_capabilities,
+            connection_leases: HashSet::new(),
+            next_connection_lease: 0,
+            target_state_before_leases: None,
             app_version_info,
             shutdown_tasks: vec![],
             tunnel_state_machine_shutdown_signal,
+            shutdown_token,
+            settings_dir,
             cache_dir,
         };
 
         daemon.ensure_wireguard_keys_for_current_account().await;
+        daemon.fetch_port_forwards();
+        daemon.schedule_problem_report_retry();
+        daemon.update_lan_proxy().await;
+        daemon.update_local_api().await;
+        daemon.update_identity_rotation().await;
+        daemon.update_api_access_method();
+        daemon.detect_conflicting_software();
+        #[cfg(not(target_os = "android"))]
+        daemon.update_dns_proxy().await;
+        #[cfg(not(target_os = "android"))]
+        daemon.update_persistent_firewall();
+        #[cfg(target_os = "linux")]
+        daemon.update_dbus_service();
+        #[cfg(not(target_os = "android"))]
+        daemon.start_network_watcher().await;
 
         Ok(daemon)
     }
 
+    fn reconnect_backoff(
+        settings: &ReconnectBackoffSettings,
+    ) -> tunnel_state_machine::ReconnectBackoff {
+        tunnel_state_machine::ReconnectBackoff {
+            initial_delay: settings.initial_delay.as_duration(),
+            multiplier: settings.multiplier,
+            max_delay: settings.max_delay.as_duration(),
+            jitter_percent: settings.jitter_percent,
+        }
+    }
+
     fn get_dns_resolvers(options: &DnsOptions) -> Option<Vec<IpAddr>> {
         match options.state {
             DnsState::Default => {
@@ -787,15 +1772,80 @@ where
                 if options.custom_options.addresses.is_empty() {
                     None
                 } else {
-                    Some(options.custom_options.addresses.clone())
+                    Some(options.custom_options.ip_addresses())
+                }
+            }
+            #[cfg(not(target_os = "android"))]
+            DnsState::Encrypted if options.encrypted_options.server.is_some() => {
+                Some(vec![DNS_PROXY_IP])
+            }
+            DnsState::Encrypted => None,
+            DnsState::LocalForwarder => options
+                .local_forwarder_options
+                .address
+                .map(|address| vec![address.into()]),
+        }
+    }
+
+    /// Returns the resolvers that a DNS forwarder on this device sends queries to, if one is used.
+    #[cfg(not(target_os = "android"))]
+    fn get_dns_upstream_servers(options: &DnsOptions) -> Vec<IpAddr> {
+        match options.state {
+            DnsState::LocalForwarder if options.local_forwarder_options.address.is_some() => {
+                options.local_forwarder_options.upstream_ip_addresses()
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Reads how the offline state should be inferred from the environment. Invalid values are
+    /// logged and ignored.
+    #[cfg(target_os = "linux")]
+    fn offline_monitor_config() -> tunnel_state_machine::OfflineMonitorConfig {
+        let mut config = tunnel_state_machine::OfflineMonitorConfig::default();
+        if let Ok(strategy) = std::env::var("MULLVAD_OFFLINE_STRATEGY") {
+            match strategy.parse() {
+                Ok(strategy) => config.strategy = strategy,
+                Err(error) => error!("Ignoring MULLVAD_OFFLINE_STRATEGY: {}", error),
+            }
+        }
+        if let Ok(endpoints) = std::env::var("MULLVAD_OFFLINE_REACHABILITY_CHECK") {
+            for endpoint in endpoints
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+            {
+                match endpoint.parse::<SocketAddr>() {
+                    Ok(endpoint) => config.reachability_endpoints.push(endpoint),
+                    Err(error) => error!(
+                        "Ignoring invalid reachability check endpoint \"{}\": {}",
+                        endpoint, error
+                    ),
                 }
             }
         }
+        config
+    }
+
+    fn warn_about_unreachable_dns_servers(settings: &Settings) {
+        let dns_options = &settings.tunnel_options.dns_options;
+        for address in dns_options.unreachable_custom_servers(settings.allow_lan) {
+            log::warn!(
+                "Custom DNS server {} is on the local network and cannot be reached unless local \
+                 network sharing is allowed",
+                address
+            );
+        }
     }
 
     /// Consume the `Daemon` and run the main event loop. Blocks until an error happens or a
     /// shutdown event is received.
     pub async fn run(mut self) -> Result<(), Error> {
+        #[cfg(windows)]
+        if let Some(request) = migration::pending_request(&self.settings_dir).await {
+            self.run_migration(request.requested_by).await;
+        }
+
         if self.target_state == TargetState::Secured {
             self.connect_tunnel();
         }
@@ -841,6 +1891,8 @@ where
         }
 
         mem::drop(event_listener);
+        // Requests that are still in flight would otherwise keep running in the background
+        rpc_runtime.cancel_token().cancel();
         mem::drop(rpc_runtime);
 
         #[cfg(any(target_os = "macos", target_os = "linux"))]
@@ -893,38 +1945,92 @@ where
     async fn handle_event(&mut self, event: InternalDaemonEvent) {
         use self::InternalDaemonEvent::*;
         match event {
-            TunnelStateTransition(transition) => {
-                self.handle_tunnel_state_transition(transition).await
+            TunnelStateTransition(transition, reason) => {
+                self.handle_tunnel_state_transition(transition, reason)
+                    .await
             }
             GenerateTunnelParameters(tunnel_parameters_tx, retry_attempt) => {
                 self.handle_generate_tunnel_parameters(&tunnel_parameters_tx, retry_attempt)
                     .await
             }
+            RelayListLoaded => self.handle_relay_list_loaded().await,
             Command(command) => self.handle_command(command).await,
             TriggerShutdown => self.trigger_shutdown_event(),
             WgKeyEvent(key_event) => self.handle_wireguard_key_event(key_event).await,
+            WgKeyRotated(account, data, old_key) => {
+                self.handle_wireguard_key_rotation(account, data, old_key)
+                    .await
+            }
+            IdentityRotationDue => self.handle_identity_rotation_due(),
+            IdentityRotated(account, result) => {
+                self.handle_identity_rotated(account, result).await
+            }
             NewAccountEvent(account_token, tx) => {
                 self.handle_new_account_event(account_token, tx).await
             }
             NewAppVersionInfo(app_version_info) => {
                 self.handle_new_app_version_info(app_version_info)
             }
+            ApiAccessMethodProbed(change, tx) => {
+                self.handle_api_access_method_probed(change, tx).await
+            }
+            ApiUnreachable => self.handle_api_unreachable(),
+            ApiAccessMethodChecked(id, name, result) => {
+                self.handle_api_access_method_checked(id, name, result)
+                    .await
+            }
+            DeviceRevoked(account_token, public_key) => {
+                self.handle_device_revoked(account_token, public_key).await
+            }
+            AccountExpiry(event) => self.handle_account_expiry_event(event).await,
+            PortForwardsFetched(account_token, port_forwards) => {
+                self.handle_port_forwards_fetched(account_token, port_forwards)
+            }
+            TrafficSample(id, counters, time) => self.handle_traffic_sample(id, counters, time),
+            QueueProblemReport(report, idempotency_token, error, tx) => {
+                self.handle_queue_problem_report(report, idempotency_token, error, tx)
+                    .await
+            }
+            ProblemReportRetried(id, result) => {
+                self.handle_problem_report_retried(id, result).await
+            }
+            ProblemReportRetriesFinished => {
+                self.problem_report_retry_job = None;
+                self.problem_report_retry_delay =
+                    problem_report_outbox::next_retry_delay(self.problem_report_retry_delay);
+                self.schedule_problem_report_retry();
+            }
+            CaptivePortalHostsResolved(hosts) => self.handle_captive_portal_hosts_resolved(hosts),
+            #[cfg(not(target_os = "android"))]
+            PortalLoginFinished(status) => self.handle_portal_login_finished(status),
+            #[cfg(windows)]
+            SystemEvent(event) => self.handle_system_event(event),
+            ConnectivityChanged => self.detect_conflicting_software(),
+            #[cfg(not(target_os = "android"))]
+            NetworkChanged(network) => self.handle_network_changed(network).await,
+            ConflictingSoftwareDetected(conflicts) => {
+                self.handle_conflicting_software_detected(conflicts)
+            }
+            ExitIpMismatchDetected(mismatch) => self.handle_exit_ip_mismatch(mismatch),
         }
     }
 
     async fn handle_tunnel_state_transition(
         &mut self,
         tunnel_state_transition: TunnelStateTransition,
+        reason: Option<TransitionReason>,
     ) {
         self.reset_rpc_sockets_on_tunnel_state_transition(&tunnel_state_transition)
             .await;
         let tunnel_state = match tunnel_state_transition {
             TunnelStateTransition::Disconnected => TunnelState::Disconnected,
-            TunnelStateTransition::Connecting(endpoint) => TunnelState::Connecting {
+            TunnelStateTransition::Connecting { endpoint, retry_at } => TunnelState::Connecting {
                 endpoint,
                 location: self.build_location_from_relay(),
+                retry_at: retry_at.map(chrono::DateTime::<chrono::Utc>::from),
             },
             TunnelStateTransition::Connected(endpoint) => TunnelState::Connected {
+                feature_indicators: self.build_feature_indicators(&endpoint),
                 endpoint,
                 location: self.build_location_from_relay(),
             },
@@ -938,20 +2044,61 @@ where
         self.unschedule_reconnect();
 
         debug!("New tunnel state: {:?}", tunnel_state);
+        if let Some(reason) = &reason {
+            debug!("Tunnel state transition reason: {}", reason);
+        }
+        self.state_history.record(&tunnel_state);
+        self.record_connection_stats(&tunnel_state).await;
+        self.record_relay_health(&tunnel_state);
+        self.update_exit_ip_checker(&tunnel_state);
+        if let Some(TransitionReason::PacketLoss { .. }) = reason {
+            if let Some(relay) = &self.last_generated_relay {
+                self.relay_selector.record_packet_loss(&relay.hostname);
+            }
+        }
         match tunnel_state {
-            TunnelState::Disconnected => self.state.disconnected(),
+            TunnelState::Disconnected => {
+                self.state.disconnected();
+                self.remove_retired_wireguard_key();
+            }
+            TunnelState::Connected { ref endpoint, .. } => {
+                self.scheduler_handle.tunnel_connected();
+                self.resolve_captive_portal_hosts();
+                let uses_current_key = endpoint.tunnel_type != TunnelType::Wireguard
+                    || self.uses_current_wireguard_key();
+                if uses_current_key {
+                    self.remove_retired_wireguard_key();
+                } else if self.retired_wireguard_key.is_some() {
+                    // The connection was set up before the key was rotated
+                    self.schedule_reconnect(WG_RECONNECT_DELAY).await;
+                }
+            }
             TunnelState::Error(ref error_state) => {
                 if error_state.is_blocking() {
                     info!(
                         "Blocking all network connections, reason: {}",
                         error_state.cause()
                     );
+                    self.log_system_event(SystemLogEvent::Blocking {
+                        cause: error_state.cause().to_string(),
+                    });
                 } else {
                     error!(
                         "FAILED TO BLOCK NETWORK CONNECTIONS, ENTERED ERROR STATE BECAUSE: {}",
                         error_state.cause()
                     );
                 }
+                if let Some(block_failure) = error_state.block_failure() {
+                    self.log_system_event(SystemLogEvent::FirewallFailure {
+                        cause: block_failure.to_string(),
+                    });
+                } else if let ErrorStateCause::SetFirewallPolicyError(ref error) =
+                    error_state.cause()
+                {
+                    self.log_system_event(SystemLogEvent::FirewallFailure {
+                        cause: error.to_string(),
+                    });
+                }
 
                 if let ErrorStateCause::AuthFailed(_) = error_state.cause() {
                     self.schedule_reconnect(Duration::from_secs(60)).await
@@ -961,90 +2108,556 @@ where
         }
 
         self.tunnel_state = tunnel_state.clone();
-        self.event_listener.notify_new_state(tunnel_state);
+        #[cfg(not(target_os = "android"))]
+        self.update_portal_login();
+        if let Some(lan_proxy) = &self.lan_proxy {
+            lan_proxy.set_policy(self.lan_proxy_policy());
+        }
+        self.local_api_status
+            .update(&tunnel_state, self.last_generated_relay.as_ref());
+        #[cfg(not(target_os = "android"))]
+        self.hooks
+            .tunnel_state_changed(&tunnel_state, &self.settings.hooks);
+        #[cfg(not(target_os = "android"))]
+        if let Some(dns_proxy) = &self.dns_proxy {
+            dns_proxy.set_forwarding(matches!(self.tunnel_state, TunnelState::Connected { .. }));
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(dbus_service) = &self.dbus_service {
+            dbus_service.notify_tunnel_state(&tunnel_state);
+        }
+        self.event_listener.notify_new_state(tunnel_state, reason);
     }
 
-    async fn reset_rpc_sockets_on_tunnel_state_transition(
-        &mut self,
-        tunnel_state_transition: &TunnelStateTransition,
-    ) {
-        match (&self.tunnel_state, &tunnel_state_transition) {
-            // only reset the API sockets if when connected or leaving the connected state
-            (&TunnelState::Connected { .. }, _) | (_, &TunnelStateTransition::Connected(_)) => {
-                self.rpc_handle.service().reset().await;
-            }
-            _ => (),
-        };
+    /// Sends an important event to the system log, and to D-Bus listeners.
+    fn log_system_event(&mut self, event: SystemLogEvent) {
+        #[cfg(target_os = "linux")]
+        if let Some(dbus_service) = &self.dbus_service {
+            dbus_service.notify_event(&event);
+        }
+        self.system_log.log(event);
     }
 
-    async fn handle_generate_tunnel_parameters(
-        &mut self,
-        tunnel_parameters_tx: &sync_mpsc::Sender<
+    /// Starts or stops the D-Bus service to match the settings.
+    #[cfg(target_os = "linux")]
+    fn update_dbus_service(&mut self) {
+        if !self.settings.dbus_service {
+            self.dbus_service = None;
+        } else if self.dbus_service.is_none() {
+            match dbus_service::DbusService::start(
+                self.tx.to_specialized_sender(),
+                &self.tunnel_state,
+            ) {
+                Ok(dbus_service) => self.dbus_service = Some(dbus_service),
+                Err(error) => error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to start the D-Bus service")
+                ),
+            }
+        }
+    }
+
+    /// Starts or stops the LAN proxy to match the settings. It only runs while local network
+    /// sharing is allowed, since the firewall blocks the clients otherwise.
+    async fn update_lan_proxy(&mut self) {
+        let settings = self.settings.lan_proxy;
+        let should_run = settings.enabled && self.settings.allow_lan;
+        if let Some(lan_proxy) = &self.lan_proxy {
+            if should_run && lan_proxy.port() == settings.port {
+                return;
+            }
+        }
+        // Stop the old proxy before binding to the port again
+        self.lan_proxy = None;
+        if !should_run {
+            return;
+        }
+        match lan_proxy::LanProxy::start(settings.port, self.lan_proxy_policy()).await {
+            Ok(lan_proxy) => self.lan_proxy = Some(lan_proxy),
+            Err(error) => {
+                error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to start LAN proxy")
+                );
+            }
+        }
+    }
+
+    /// Starts or stops the local API to match the settings.
+    async fn update_local_api(&mut self) {
+        let settings = self.settings.local_api;
+        if let Some(local_api) = &self.local_api {
+            if settings.enabled && local_api.port() == settings.port {
+                return;
+            }
+        }
+        // Stop the old server before binding to the port again
+        self.local_api = None;
+        if !settings.enabled {
+            return;
+        }
+        let token = match local_api::load_or_create_token(self.writable_dir()).await {
+            Ok(token) => token,
+            Err(error) => {
+                error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to read or create the local API token")
+                );
+                return;
+            }
+        };
+        self.local_api_status
+            .update(&self.tunnel_state, self.last_generated_relay.as_ref());
+        match local_api::LocalApi::start(
+            settings.port,
+            token,
+            self.local_api_status.clone(),
+            self.expiry_monitor.clone(),
+        )
+        .await
+        {
+            Ok(local_api) => self.local_api = Some(local_api),
+            Err(error) => {
+                error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to start the local API")
+                );
+            }
+        }
+    }
+
+    /// Installs or removes the persistent firewall policy to match the settings. The policy is
+    /// only installed while both lockdown mode and persistent lockdown are enabled.
+    #[cfg(not(target_os = "android"))]
+    fn update_persistent_firewall(&mut self) {
+        use talpid_core::firewall::persist;
+
+        let result = if self.settings.block_when_disconnected && self.settings.persistent_lockdown {
+            persist::install(&persist::PersistentPolicy {
+                allow_lan: self.settings.allow_lan,
+                allowed_endpoints: self.settings.firewall_exceptions.clone(),
+            })
+        } else if persist::is_installed() {
+            persist::uninstall()
+        } else {
+            Ok(())
+        };
+        if let Err(error) = result {
+            error!(
+                "{}",
+                error.display_chain_with_msg("Failed to update the persistent firewall policy")
+            );
+        }
+    }
+
+    /// Starts, reconfigures or stops the encrypted DNS proxy to match the DNS settings.
+    #[cfg(not(target_os = "android"))]
+    async fn update_dns_proxy(&mut self) {
+        let dns_options = &self.settings.tunnel_options.dns_options;
+        let upstream = match &dns_options.encrypted_options.server {
+            Some(server) if dns_options.state == DnsState::Encrypted => {
+                talpid_dns_proxy::Upstream {
+                    protocol: match server.protocol {
+                        EncryptedDnsProtocol::Tls => talpid_dns_proxy::Protocol::Tls,
+                        EncryptedDnsProtocol::Https => talpid_dns_proxy::Protocol::Https,
+                    },
+                    address: server.address.into(),
+                    hostname: server.hostname.clone(),
+                }
+            }
+            _ => {
+                self.dns_proxy = None;
+                return;
+            }
+        };
+
+        if let Some(dns_proxy) = &mut self.dns_proxy {
+            if dns_proxy.upstream() != &upstream {
+                if let Err(error) = dns_proxy.set_upstream(upstream) {
+                    error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to change the encrypted DNS server")
+                    );
+                }
+            }
+            return;
+        }
+        match talpid_dns_proxy::DnsProxy::start(SocketAddr::new(DNS_PROXY_IP, 53), upstream).await {
+            Ok(dns_proxy) => {
+                dns_proxy
+                    .set_forwarding(matches!(self.tunnel_state, TunnelState::Connected { .. }));
+                self.dns_proxy = Some(dns_proxy);
+            }
+            Err(error) => {
+                error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to start the encrypted DNS proxy")
+                );
+            }
+        }
+    }
+
+    /// Returns which connections the LAN proxy may forward in the current tunnel state. Nothing
+    /// is forwarded unless the tunnel is connected, so that traffic never leaves outside of it.
+    fn lan_proxy_policy(&self) -> lan_proxy::ForwardingPolicy {
+        match &self.tunnel_state {
+            TunnelState::Connected { endpoint, .. } => {
+                lan_proxy::ForwardingPolicy::connected(endpoint)
+            }
+            _ => lan_proxy::ForwardingPolicy::blocked(),
+        }
+    }
+
+    async fn record_connection_stats(&mut self, tunnel_state: &TunnelState) {
+        if let Some(sampler) = self.throughput_sampler.take() {
+            sampler.abort();
+        }
+        self.tunnel_stats.reset();
+        let hostname = self
+            .last_generated_relay
+            .as_ref()
+            .map(|relay| relay.hostname.as_str());
+        let id = self
+            .connection_stats
+            .record_state(tunnel_state, hostname)
+            .await;
+        if let TunnelState::Connected { .. } = tunnel_state {
+            self.spawn_throughput_sampler(id);
+            if let Some(attempt) = self.connection_trace.attempts().last() {
+                self.connect_times.record(attempt).await;
+            }
+        }
+    }
+
+    fn handle_traffic_sample(
+        &mut self,
+        id: Option<connection_stats::ConnectionId>,
+        counters: talpid_core::tunnel::TrafficCounters,
+        time: Instant,
+    ) {
+        self.tunnel_stats.record(counters, time);
+        if let Some(id) = id {
+            self.connection_stats.record_traffic(id, counters, time);
+        }
+
+        if let Some(stats) = self.tunnel_stats.stats() {
+            self.tunnel_stats_listeners
+                .retain(|tx| tx.try_send(stats).is_ok());
+            if self.tunnel_stats_listeners.is_empty() {
+                self.live_tunnel_stats.store(false, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Starts checking the exit IP if the tunnel is connected and the checks are enabled, and
+    /// stops any checks that were running for an earlier connection.
+    fn update_exit_ip_checker(&mut self, tunnel_state: &TunnelState) {
+        if let Some(checker) = self.exit_ip_checker.take() {
+            checker.abort();
+        }
+        let relay = match (tunnel_state, &self.last_generated_relay) {
+            (TunnelState::Connected { .. }, Some(relay)) if self.settings.exit_ip_check.enabled => {
+                relay
+            }
+            _ => return,
+        };
+        self.exit_ip_checker = Some(exit_ip_check::spawn(
+            &self.settings.exit_ip_check,
+            relay.hostname.clone(),
+            relay.location.clone(),
+            self.rpc_runtime.rest_handle(),
+            self.tx.to_specialized_sender(),
+        ));
+    }
+
+    fn handle_exit_ip_mismatch(&mut self, mut mismatch: ExitIpMismatch) {
+        let still_connected = matches!(self.tunnel_state, TunnelState::Connected { .. })
+            && self
+                .last_generated_relay
+                .as_ref()
+                .map(|relay| relay.hostname == mismatch.expected_hostname)
+                .unwrap_or(false);
+        if !still_connected {
+            return;
+        }
+
+        let problem = match &mismatch.problem {
+            ExitIpProblem::NotMullvad => "which does not belong to a Mullvad relay".to_owned(),
+            ExitIpProblem::OtherRelay(hostname) => format!("which belongs to {}", hostname),
+            ExitIpProblem::Changed(previous) => format!("which changed from {}", previous),
+        };
+        mismatch.reconnecting = self.settings.exit_ip_check.reconnect;
+        log::warn!(
+            "Traffic through {} exits from {}, {}{}",
+            mismatch.expected_hostname,
+            mismatch.exit_ip,
+            problem,
+            if mismatch.reconnecting {
+                ". Reconnecting"
+            } else {
+                ""
+            }
+        );
+        let reconnect = mismatch.reconnecting;
+        self.event_listener.notify_exit_ip_mismatch(mismatch);
+        if reconnect {
+            self.reconnect_tunnel();
+        }
+    }
+
+    fn record_relay_health(&self, tunnel_state: &TunnelState) {
+        let hostname = self
+            .last_generated_relay
+            .as_ref()
+            .map(|relay| relay.hostname.as_str());
+        self.relay_selector.record_tunnel_state(tunnel_state, hostname);
+    }
+
+    /// Periodically reads the traffic counters of the tunnel until it is no longer connected. They
+    /// are read more often while the tunnel stats are subscribed to, but the connection statistics
+    /// are only given samples at the regular interval, so that the peak throughput is measured the
+    /// same way regardless.
+    fn spawn_throughput_sampler(&mut self, id: Option<connection_stats::ConnectionId>) {
+        let tunnel_command_tx = Arc::downgrade(&self.tunnel_command_tx);
+        let daemon_tx = self.tx.clone();
+        let live_stats = self.live_tunnel_stats.clone();
+        let (future, abort_handle) = abortable(Box::pin(async move {
+            let mut last_throughput_sample: Option<Instant> = None;
+            loop {
+                let (tx, rx) = oneshot::channel();
+                let command_tx = match tunnel_command_tx.upgrade() {
+                    Some(command_tx) => command_tx,
+                    None => return,
+                };
+                if command_tx
+                    .send(TunnelCommand::GetTrafficCounters(tx))
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+                drop(command_tx);
+                let counters = match rx.await {
+                    Ok(counters) => counters,
+                    Err(_) => return,
+                };
+                let now = Instant::now();
+                let throughput_id = match last_throughput_sample {
+                    Some(last_sample)
+                        if now.saturating_duration_since(last_sample)
+                            < connection_stats::THROUGHPUT_SAMPLE_INTERVAL =>
+                    {
+                        None
+                    }
+                    _ => {
+                        last_throughput_sample = Some(now);
+                        id
+                    }
+                };
+                if daemon_tx
+                    .send(InternalDaemonEvent::TrafficSample(
+                        throughput_id,
+                        counters,
+                        now,
+                    ))
+                    .is_err()
+                {
+                    return;
+                }
+                let interval = if live_stats.load(Ordering::Relaxed) {
+                    LIVE_STATS_SAMPLE_INTERVAL
+                } else {
+                    connection_stats::THROUGHPUT_SAMPLE_INTERVAL
+                };
+                tokio::time::sleep(interval).await;
+            }
+        }));
+
+        tokio::spawn(future);
+        self.throughput_sampler = Some(abort_handle);
+    }
+
+    async fn reset_rpc_sockets_on_tunnel_state_transition(
+        &mut self,
+        tunnel_state_transition: &TunnelStateTransition,
+    ) {
+        match (&self.tunnel_state, &tunnel_state_transition) {
+            // only reset the API sockets if when connected or leaving the connected state
+            (&TunnelState::Connected { .. }, _) | (_, &TunnelStateTransition::Connected(_)) => {
+                self.rpc_handle.service().reset().await;
+            }
+            _ => (),
+        };
+    }
+
+    async fn handle_relay_list_loaded(&mut self) {
+        self.relay_list_loaded = true;
+        for (tunnel_parameters_tx, retry_attempt) in
+            std::mem::take(&mut self.pending_tunnel_parameter_requests)
+        {
+            self.handle_generate_tunnel_parameters(&tunnel_parameters_tx, retry_attempt)
+                .await;
+        }
+    }
+
+    async fn handle_generate_tunnel_parameters(
+        &mut self,
+        tunnel_parameters_tx: &sync_mpsc::Sender<
             Result<TunnelParameters, ParameterGenerationError>,
         >,
         retry_attempt: u32,
     ) {
-        if let Some(account_token) = self.settings.get_account_token() {
-            let result = match self.settings.get_relay_settings() {
-                RelaySettings::CustomTunnelEndpoint(custom_relay) => {
-                    self.last_generated_relay = None;
-                    custom_relay
-                        // TODO(emilsp): generate proxy settings for custom tunnels
-                        .to_tunnel_parameters(self.settings.tunnel_options.clone(), None)
-                        .map_err(|e| {
-                            log::error!("Failed to resolve hostname for custom tunnel config: {}", e);
-                            ParameterGenerationError::CustomTunnelHostResultionError
-                        })
-                }
-                RelaySettings::Normal(constraints) => {
-                    let endpoint = self
-                        .relay_selector
+        if !self.relay_list_loaded {
+            if let RelaySettings::Normal(_) = self.settings.get_relay_settings() {
+                log::debug!("Waiting for the relay list to be loaded before selecting a relay");
+                self.pending_tunnel_parameter_requests
+                    .push((tunnel_parameters_tx.clone(), retry_attempt));
+                return;
+            }
+        }
+        if retry_attempt > 0 && retry_attempt % DEVICE_CHECK_ATTEMPT_INTERVAL == 0 {
+            self.check_device_revoked();
+        }
+        let result = match self.settings.get_relay_settings() {
+            RelaySettings::CustomTunnelEndpoint(custom_relay) => {
+                self.last_generated_relay = None;
+                let mut span = self.connection_trace.enter(Phase::ParameterGeneration);
+                custom_relay
+                    // TODO(emilsp): generate proxy settings for custom tunnels
+                    .to_tunnel_parameters(self.settings.tunnel_options.clone(), None)
+                    .map_err(|e| {
+                        log::error!("Failed to resolve hostname for custom tunnel config: {}", e);
+                        span.set_error(&e);
+                        ParameterGenerationError::CustomTunnelHostResultionError
+                    })
+            }
+            RelaySettings::Normal(constraints) => {
+                let account_token = match self.settings.get_account_token() {
+                    Some(account_token) => account_token,
+                    None => {
+                        error!("No account token configured");
+                        return;
+                    }
+                };
+                let wg_key_exists = self.settings.get_wireguard().is_some();
+                let mut selection_span = self.connection_trace.enter(Phase::RelaySelection);
+                let port_forwarding_endpoint = self
+                    .port_forwarding_constraints(&constraints)
+                    .and_then(|port_forwarding_constraints| {
+                        let result = self.relay_selector.get_tunnel_endpoint(
+                            &port_forwarding_constraints,
+                            self.settings.get_bridge_state(),
+                            retry_attempt,
+                            wg_key_exists,
+                        );
+                        if result.is_err() {
+                            log::warn!(
+                                "No relays with forwarded ports match the constraints. \
+                                 Ignoring the forwarded ports"
+                            );
+                        }
+                        result.ok()
+                    });
+                let endpoint = port_forwarding_endpoint.or_else(|| {
+                    self.relay_selector
                         .get_tunnel_endpoint(
                             &constraints,
                             self.settings.get_bridge_state(),
                             retry_attempt,
-                            self.settings.get_wireguard().is_some(),
+                            wg_key_exists,
                         )
-                        .ok();
-                    if let Some((relay, endpoint)) = endpoint {
-                        let result = self
-                            .create_tunnel_parameters(
-                                &relay,
-                                endpoint,
-                                account_token,
-                                retry_attempt,
-                            )
-                            .await;
-                        self.last_generated_relay = Some(relay);
-                        match result {
-                            Ok(result) => Ok(result),
-                            Err(Error::NoKeyAvailable) => {
-                                Err(ParameterGenerationError::NoWireguardKey)
-                            }
-                            Err(Error::NoBridgeAvailable) => {
-                                Err(ParameterGenerationError::NoMatchingBridgeRelay)
-                            }
-                            Err(err) => {
-                                log::error!(
-                                    "{}",
-                                    err.display_chain_with_msg(
-                                        "Failed to generate tunnel parameters"
-                                    )
-                                );
-                                Err(ParameterGenerationError::NoMatchingRelay)
-                            }
+                        .ok()
+                });
+                if endpoint.is_none() {
+                    selection_span.set_error("no matching relay");
+                }
+                drop(selection_span);
+                if let Some((relay, endpoint)) = endpoint {
+                    let mut span = self.connection_trace.enter(Phase::ParameterGeneration);
+                    let result = self
+                        .create_tunnel_parameters(&relay, endpoint, account_token, retry_attempt)
+                        .await;
+                    if let Err(error) = &result {
+                        span.set_error(error);
+                    }
+                    drop(span);
+                    self.last_generated_relay = Some(relay);
+                    match result {
+                        Ok(result) => Ok(result),
+                        Err(Error::NoKeyAvailable) => Err(ParameterGenerationError::NoWireguardKey),
+                        Err(Error::NoBridgeAvailable) => {
+                            Err(ParameterGenerationError::NoMatchingBridgeRelay)
+                        }
+                        Err(err) => {
+                            log::error!(
+                                "{}",
+                                err.display_chain_with_msg("Failed to generate tunnel parameters")
+                            );
+                            Err(ParameterGenerationError::NoMatchingRelay)
                         }
-                    } else {
-                        Err(ParameterGenerationError::NoMatchingRelay)
                     }
+                } else {
+                    Err(ParameterGenerationError::NoMatchingRelay)
                 }
-            };
-            if tunnel_parameters_tx.send(result).is_err() {
-                log::error!("Failed to send tunnel parameters");
             }
-        } else {
-            error!("No account token configured");
+        };
+        let result = result.map(|mut parameters| {
+            self.apply_tunnel_parameter_overrides(&mut parameters);
+            parameters
+        });
+        self.last_generated_tunnel_parameters = result.as_ref().ok().cloned();
+        if tunnel_parameters_tx.send(result).is_err() {
+            log::error!("Failed to send tunnel parameters");
+        }
+    }
+
+    /// Applies the tunnel parameter overrides in the settings, and logs which of them changed the
+    /// parameters of this connection attempt.
+    fn apply_tunnel_parameter_overrides(&self, parameters: &mut TunnelParameters) {
+        let applied =
+            tunnel_overrides::apply(&self.settings.tunnel_parameter_overrides, parameters);
+        if applied.is_empty() {
+            return;
+        }
+        let hostname = self
+            .last_generated_relay
+            .as_ref()
+            .map(|relay| relay.hostname.as_str())
+            .unwrap_or("custom tunnel endpoint");
+        log::info!(
+            "Applied tunnel parameter overrides for {}: {}",
+            hostname,
+            applied.join(", ")
+        );
+    }
+
+    /// Restricts the relay constraints to WireGuard relays in the cities that ports are forwarded
+    /// from, if enabled. Ports are forwarded to the WireGuard key of the device, so they are not
+    /// reachable over OpenVPN. The firewall already accepts incoming connections on the tunnel
+    /// interface, so the ports need no further configuration.
+    fn port_forwarding_constraints(
+        &self,
+        constraints: &RelayConstraints,
+    ) -> Option<RelayConstraints> {
+        if !self.settings.port_forwarding
+            || constraints.tunnel_protocol == Constraint::Only(TunnelType::OpenVpn)
+        {
+            return None;
         }
+        let cities = RelayFilter::any_city(self.port_forwards.iter().map(|port_forward| {
+            (
+                port_forward.country_code.as_str(),
+                port_forward.city_code.as_str(),
+            )
+        }))?;
+
+        let mut constraints = constraints.clone();
+        constraints.tunnel_protocol = Constraint::Only(TunnelType::Wireguard);
+        constraints.filter = Constraint::Only(match constraints.filter {
+            Constraint::Any => cities,
+            Constraint::Only(filter) => filter.and(cities),
+        });
+        Some(constraints)
     }
 
     async fn create_tunnel_parameters(
@@ -1059,61 +2672,13 @@ where
         self.last_generated_bridge_relay = None;
         match endpoint {
             MullvadEndpoint::OpenVpn(endpoint) => {
-                let proxy_settings = match &self.settings.bridge_settings {
-                    BridgeSettings::Normal(settings) => {
-                        let bridge_constraints = InternalBridgeConstraints {
-                            location: settings.location.clone(),
-                            providers: settings.providers.clone(),
-                            // FIXME: This is temporary while talpid-core only supports TCP proxies
-                            transport_protocol: Constraint::Only(TransportProtocol::Tcp),
-                        };
-                        match self.settings.get_bridge_state() {
-                            BridgeState::On => {
-                                let (bridge_settings, bridge_relay) = self
-                                    .relay_selector
-                                    .get_proxy_settings(&bridge_constraints, location)
-                                    .ok_or(Error::NoBridgeAvailable)?;
-                                self.last_generated_bridge_relay = Some(bridge_relay);
-                                Some(bridge_settings)
-                            }
-                            BridgeState::Auto => {
-                                if let Some((bridge_settings, bridge_relay)) =
-                                    self.relay_selector.get_auto_proxy_settings(
-                                        &bridge_constraints,
-                                        location,
-                                        retry_attempt,
-                                    )
-                                {
-                                    self.last_generated_bridge_relay = Some(bridge_relay);
-                                    Some(bridge_settings)
-                                } else {
-                                    None
-                                }
-                            }
-                            BridgeState::Off => None,
-                        }
-                    }
-                    BridgeSettings::Custom(proxy_settings) => {
-                        match self.settings.get_bridge_state() {
-                            BridgeState::On => Some(proxy_settings.clone()),
-                            BridgeState::Auto => {
-                                if self.relay_selector.should_use_bridge(retry_attempt) {
-                                    Some(proxy_settings.clone())
-                                } else {
-                                    None
-                                }
-                            }
-                            BridgeState::Off => None,
-                        }
-                    }
-                };
+                let proxy_settings = self.bridge_settings(location, retry_attempt)?;
+                let mut config =
+                    openvpn::ConnectionConfig::new(endpoint, account_token, "-".to_string());
+                config.handshake_timeout = relay.metadata.handshake_timeout();
 
                 Ok(openvpn::TunnelParameters {
-                    config: openvpn::ConnectionConfig::new(
-                        endpoint,
-                        account_token,
-                        "-".to_string(),
-                    ),
+                    config,
                     options: tunnel_options.openvpn,
                     generic_options: tunnel_options.generic,
                     proxy: proxy_settings,
@@ -1134,6 +2699,7 @@ where
                         wg_data.addresses.ipv6_address.ip().into(),
                     ],
                 };
+                let obfuscation = self.obfuscator(&peer, location, retry_attempt)?;
                 Ok(wireguard::TunnelParameters {
                     connection: wireguard::ConnectionConfig {
                         tunnel,
@@ -1141,6 +2707,9 @@ where
                         exit_peer,
                         ipv4_gateway,
                         ipv6_gateway: Some(ipv6_gateway),
+                        obfuscation,
+                        handshake_timeout: relay.metadata.handshake_timeout(),
+                        quantum_resistant: self.quantum_resistant_tunnel(exit_peer.is_some()),
                     },
                     options: tunnel_options.wireguard.options,
                     generic_options: tunnel_options.generic,
@@ -1150,6 +2719,163 @@ where
         }
     }
 
+    /// Returns whether WireGuard tunnels should negotiate a post-quantum preshared key with the
+    /// relay. Multihop tunnels cannot, so connecting fails if it is turned on for them, while
+    /// `Auto` turns it off.
+    fn quantum_resistant_tunnel(&self, multihop: bool) -> bool {
+        match self.settings.tunnel_options.wireguard.quantum_resistant {
+            QuantumResistantState::On => true,
+            QuantumResistantState::Off => false,
+            QuantumResistantState::Auto => {
+                !multihop
+                    && self
+                        .settings
+                        .beta_features
+                        .is_enabled(BetaFeature::QuantumResistantTunnel)
+            }
+        }
+    }
+
+    /// Returns the bridge to connect through, if bridges should be used for this attempt. Bridges
+    /// only carry TCP traffic.
+    fn bridge_settings(
+        &mut self,
+        location: &Location,
+        retry_attempt: u32,
+    ) -> Result<Option<openvpn::ProxySettings>, Error> {
+        match &self.settings.bridge_settings {
+            BridgeSettings::Normal(settings) => {
+                let bridge_constraints = InternalBridgeConstraints {
+                    location: settings.location.clone(),
+                    providers: settings.providers.clone(),
+                    // FIXME: This is temporary while talpid-core only supports TCP proxies
+                    transport_protocol: Constraint::Only(TransportProtocol::Tcp),
+                };
+                match self.settings.get_bridge_state() {
+                    BridgeState::On => {
+                        let (bridge_settings, bridge_relay) = self
+                            .relay_selector
+                            .get_proxy_settings(&bridge_constraints, location)
+                            .ok_or(Error::NoBridgeAvailable)?;
+                        self.last_generated_bridge_relay = Some(bridge_relay);
+                        Ok(Some(bridge_settings))
+                    }
+                    BridgeState::Auto => {
+                        if let Some((bridge_settings, bridge_relay)) =
+                            self.relay_selector.get_auto_proxy_settings(
+                                &bridge_constraints,
+                                location,
+                                retry_attempt,
+                            )
+                        {
+                            self.last_generated_bridge_relay = Some(bridge_relay);
+                            Ok(Some(bridge_settings))
+                        } else {
+                            Ok(None)
+                        }
+                    }
+                    BridgeState::Off => Ok(None),
+                }
+            }
+            BridgeSettings::Custom(proxy_settings) => match self.settings.get_bridge_state() {
+                BridgeState::On => Ok(Some(proxy_settings.clone())),
+                BridgeState::Auto => {
+                    if self.relay_selector.should_use_bridge(retry_attempt) {
+                        Ok(Some(proxy_settings.clone()))
+                    } else {
+                        Ok(None)
+                    }
+                }
+                BridgeState::Off => Ok(None),
+            },
+        }
+    }
+
+    /// Returns the obfuscator to send the traffic for `peer` through, if one is selected and
+    /// supported. Bridges take precedence over obfuscators for peers that are reached over TCP.
+    fn obfuscator(
+        &mut self,
+        peer: &wireguard::PeerConfig,
+        location: &Location,
+        retry_attempt: u32,
+    ) -> Result<Option<wireguard::ObfuscatorConfig>, Error> {
+        let bridge = if peer.protocol == TransportProtocol::Tcp {
+            self.bridge_settings(location, retry_attempt)?
+                .map(wireguard::ObfuscatorConfig::Bridge)
+        } else {
+            None
+        };
+        let obfuscator = match bridge.or_else(|| self.selected_obfuscator(peer)) {
+            Some(obfuscator) => obfuscator,
+            None => return Ok(None),
+        };
+        if !talpid_core::obfuscation::is_supported(&obfuscator) {
+            log::warn!("Ignoring obfuscation since it is not supported on this platform or build");
+            return Ok(None);
+        }
+        Ok(Some(obfuscator))
+    }
+
+    fn selected_obfuscator(
+        &mut self,
+        peer: &wireguard::PeerConfig,
+    ) -> Option<wireguard::ObfuscatorConfig> {
+        let obfuscation_settings = &self.settings.obfuscation_settings;
+        match obfuscation_settings.mode {
+            ObfuscationMode::Shadowsocks => {
+                if peer.protocol == TransportProtocol::Tcp {
+                    log::warn!(
+                        "Ignoring Shadowsocks obfuscation since the relay is reached over TCP"
+                    );
+                    return None;
+                }
+                let port = obfuscation_settings.shadowsocks.port;
+                let obfuscator = self.relay_selector.get_shadowsocks_obfuscator(peer, port);
+                if obfuscator.is_none() {
+                    log::warn!("No Shadowsocks endpoint on the relay matches the settings");
+                }
+                obfuscator
+            }
+            ObfuscationMode::Quic => {
+                if !self
+                    .settings
+                    .beta_features
+                    .is_enabled(BetaFeature::QuicObfuscation)
+                {
+                    log::warn!(
+                        "Ignoring QUIC obfuscation since the {} beta feature is turned off",
+                        BetaFeature::QuicObfuscation
+                    );
+                    return None;
+                }
+                if peer.protocol == TransportProtocol::Tcp {
+                    log::warn!("Ignoring QUIC obfuscation since the relay is reached over TCP");
+                    return None;
+                }
+                obfuscation_settings
+                    .quic
+                    .as_ref()
+                    .map(|quic| wireguard::ObfuscatorConfig::Quic {
+                        endpoint: quic.endpoint,
+                        server_name: quic.server_name.clone(),
+                    })
+            }
+            ObfuscationMode::Auto if self.relay_selector.profile_recommends_obfuscation() => {
+                if peer.protocol == TransportProtocol::Tcp {
+                    return None;
+                }
+                let obfuscator = self
+                    .relay_selector
+                    .get_shadowsocks_obfuscator(peer, Constraint::Any);
+                if obfuscator.is_some() {
+                    log::debug!("Obfuscating the traffic as recommended for this network");
+                }
+                obfuscator
+            }
+            _ => None,
+        }
+    }
+
     async fn schedule_reconnect(&mut self, delay: Duration) {
         self.unschedule_reconnect();
 
@@ -1182,43 +2908,222 @@ where
         }
         match command {
             SetTargetState(tx, state) => self.on_set_target_state(tx, state).await,
+            AcquireConnectionLease(tx) => self.on_acquire_connection_lease(tx).await,
+            ReleaseConnectionLease(lease) => self.on_release_connection_lease(lease).await,
             Reconnect(tx) => self.on_reconnect(tx),
             GetState(tx) => self.on_get_state(tx),
             GetCurrentLocation(tx) => self.on_get_current_location(tx).await,
+            CheckConnection(tx) => self.on_check_connection(tx),
             CreateNewAccount(tx) => self.on_create_new_account(tx).await,
             GetAccountData(tx, account_token) => self.on_get_account_data(tx, account_token).await,
             GetWwwAuthToken(tx) => self.on_get_www_auth_token(tx).await,
+            ListDevices(tx) => self.on_list_devices(tx),
+            RenameDevice(tx, name) => self.on_rename_device(tx, name),
+            RemoveDevice(tx, id) => self.on_remove_device(tx, id),
+            GetDeviceState(tx) => self.on_get_device_state(tx),
+            ReregisterDevice(tx) => self.on_reregister_device(tx).await,
+            ListPortForwards(tx) => self.on_list_port_forwards(tx),
+            AddPortForward(tx, country_code, city_code) => {
+                self.on_add_port_forward(tx, country_code, city_code)
+            }
+            RemovePortForward(tx, id) => self.on_remove_port_forward(tx, id),
+            SetPortForwarding(tx, enabled) => self.on_set_port_forwarding(tx, enabled).await,
             SubmitVoucher(tx, voucher) => self.on_submit_voucher(tx, voucher).await,
             GetRelayLocations(tx) => self.on_get_relay_locations(tx),
             UpdateRelayLocations => self.on_update_relay_locations().await,
             SetAccount(tx, account_token) => self.on_set_account(tx, account_token).await,
             GetAccountHistory(tx) => self.on_get_account_history(tx),
             ClearAccountHistory(tx) => self.on_clear_account_history(tx).await,
+            RemoveAccountFromHistory(tx, account) => {
+                self.on_remove_account_from_history(tx, account).await
+            }
             UpdateRelaySettings(tx, update) => self.on_update_relay_settings(tx, update).await,
             SetAllowLan(tx, allow_lan) => self.on_set_allow_lan(tx, allow_lan).await,
+            #[cfg(windows)]
+            SetAllowVirtualNetworks(tx, allow) => {
+                self.on_set_allow_virtual_networks(tx, allow).await
+            }
+            AddFirewallException(tx, exception) => {
+                self.on_add_firewall_exception(tx, exception).await
+            }
+            RemoveFirewallException(tx, exception) => {
+                self.on_remove_firewall_exception(tx, exception).await
+            }
+            AllowTemporarily(tx, exception, duration) => {
+                self.on_allow_temporarily(tx, exception, duration)
+            }
+            SetMdnsReflector(tx, enabled) => self.on_set_mdns_reflector(tx, enabled).await,
             SetShowBetaReleases(tx, enabled) => self.on_set_show_beta_releases(tx, enabled).await,
+            SetEncryptSensitiveSettings(tx, enabled) => {
+                self.on_set_encrypt_sensitive_settings(tx, enabled).await
+            }
+            SetCredentialStorage(tx, storage) => {
+                self.on_set_credential_storage(tx, storage).await
+            }
+            SetKeepAccountHistory(tx, enabled) => {
+                self.on_set_keep_account_history(tx, enabled).await
+            }
+            SetAccountPrivacyMode(tx, enabled) => {
+                self.on_set_account_privacy_mode(tx, enabled).await
+            }
+            SetIdentityRotationInterval(tx, interval) => {
+                self.on_set_identity_rotation_interval(tx, interval).await
+            }
+            SetAccountExpirySettings(tx, account_expiry) => {
+                self.on_set_account_expiry_settings(tx, account_expiry)
+                    .await
+            }
             SetBlockWhenDisconnected(tx, block_when_disconnected) => {
                 self.on_set_block_when_disconnected(tx, block_when_disconnected)
                     .await
             }
+            SetPersistentLockdown(tx, persistent_lockdown) => {
+                self.on_set_persistent_lockdown(tx, persistent_lockdown)
+                    .await
+            }
             SetAutoConnect(tx, auto_connect) => self.on_set_auto_connect(tx, auto_connect).await,
+            AddNetworkRule(tx, rule) => self.on_add_network_rule(tx, rule).await,
+            RemoveNetworkRule(tx, network) => self.on_remove_network_rule(tx, network).await,
+            SetDefaultNetworkAction(tx, action) => {
+                self.on_set_default_network_action(tx, action).await
+            }
+            #[cfg(not(target_os = "android"))]
+            GetCurrentNetwork(tx) => self.on_get_current_network(tx),
             SetOpenVpnMssfix(tx, mssfix_arg) => self.on_set_openvpn_mssfix(tx, mssfix_arg).await,
             SetBridgeSettings(tx, bridge_settings) => {
                 self.on_set_bridge_settings(tx, bridge_settings).await
             }
             SetBridgeState(tx, bridge_state) => self.on_set_bridge_state(tx, bridge_state).await,
             SetEnableIpv6(tx, enable_ipv6) => self.on_set_enable_ipv6(tx, enable_ipv6).await,
+            SetIpv6FailurePolicy(tx, policy) => self.on_set_ipv6_failure_policy(tx, policy).await,
+            #[cfg(not(target_os = "android"))]
+            SetTunnelInterfaceName(tx, name) => self.on_set_tunnel_interface_name(tx, name).await,
             SetDnsOptions(tx, dns_servers) => self.on_set_dns_options(tx, dns_servers).await,
+            #[cfg(not(target_os = "android"))]
+            DetectDnsForwarders(tx) => self.on_detect_dns_forwarders(tx),
+            SetLogRotationSettings(tx, log_rotation) => {
+                self.on_set_log_rotation_settings(tx, log_rotation).await
+            }
+            SetLogPrivacy(tx, log_privacy) => self.on_set_log_privacy(tx, log_privacy).await,
+            SetConnectionStatistics(tx, enabled) => {
+                self.on_set_connection_statistics(tx, enabled).await
+            }
+            GetTunnelStats(tx) => self.on_get_tunnel_stats(tx),
+            TunnelStatsListen(tx) => self.on_tunnel_stats_listen(tx),
+            GetConnectionStatistics(tx) => self.on_get_connection_statistics(tx),
+            ClearConnectionStatistics(tx) => self.on_clear_connection_statistics(tx).await,
+            GetConnectTimes(tx) => self.on_get_connect_times(tx),
+            GetRelayHealth(tx) => self.on_get_relay_health(tx),
+            ClearRelayHealth(tx) => self.on_clear_relay_health(tx),
+            #[cfg(not(target_os = "android"))]
+            GetRelayLatencies(tx, hostnames, measure) => {
+                self.on_get_relay_latencies(tx, hostnames, measure)
+            }
+            GetFirewallRules(tx) => self.on_get_firewall_rules(tx),
+            RemoveOrphanedObjects(tx) => self.on_remove_orphaned_objects(tx),
+            GetConnectionAttempts(tx) => self.on_get_connection_attempts(tx),
+            GetConflictingSoftware(tx) => self.on_get_conflicting_software(tx),
+            GetMissingCapabilities(tx) => self.on_get_missing_capabilities(tx),
+            ExportWireguardConfig(tx, include_private_key) => {
+                self.on_export_wireguard_config(tx, include_private_key)
+                    .await
+            }
+            ExportSettingsProfile(tx) => self.on_export_settings_profile(tx),
+            ImportSettingsProfile(tx, profile, resolution) => {
+                self.on_import_settings_profile(tx, profile, resolution)
+                    .await
+            }
+            SetSystemLog(tx, enabled) => self.on_set_system_log(tx, enabled).await,
+            SetJsonLogs(tx, enabled) => self.on_set_json_logs(tx, enabled).await,
+            SetServerMode(tx, enabled) => self.on_set_server_mode(tx, enabled).await,
+            #[cfg(target_os = "linux")]
+            SetDbusService(tx, enabled) => self.on_set_dbus_service(tx, enabled).await,
+            SetLanProxySettings(tx, lan_proxy) => {
+                self.on_set_lan_proxy_settings(tx, lan_proxy).await
+            }
+            SetLocalApiSettings(tx, local_api) => {
+                self.on_set_local_api_settings(tx, local_api).await
+            }
+            GetLocalApiToken(tx) => self.on_get_local_api_token(tx).await,
+            SetHookSettings(tx, hooks) => self.on_set_hook_settings(tx, hooks).await,
+            SetReconnectBackoff(tx, reconnect_backoff) => {
+                self.on_set_reconnect_backoff(tx, reconnect_backoff).await
+            }
+            SetExitIpCheck(tx, exit_ip_check) => self.on_set_exit_ip_check(tx, exit_ip_check).await,
+            SetBetaFeature(tx, feature, enabled) => {
+                self.on_set_beta_feature(tx, feature, enabled).await
+            }
+            SetAllowedSystemServices(tx, services) => {
+                self.on_set_allowed_system_services(tx, services).await
+            }
+            SetTunnelParameterOverrides(tx, overrides) => {
+                self.on_set_tunnel_parameter_overrides(tx, overrides).await
+            }
+            #[cfg(not(target_os = "android"))]
+            DetectCaptivePortal(tx) => self.on_detect_captive_portal(tx),
+            #[cfg(not(target_os = "android"))]
+            StartPortalLogin(tx) => self.on_start_portal_login(tx),
+            #[cfg(not(target_os = "android"))]
+            StopPortalLogin(tx) => self.on_stop_portal_login(tx),
             SetWireguardMtu(tx, mtu) => self.on_set_wireguard_mtu(tx, mtu).await,
+            SetWireguardIcmpCheckInterval(tx, interval) => {
+                self.on_set_wireguard_icmp_check_interval(tx, interval)
+                    .await
+            }
+            SetWireguardPacketLossThreshold(tx, threshold) => {
+                self.on_set_wireguard_packet_loss_threshold(tx, threshold)
+                    .await
+            }
             SetWireguardRotationInterval(tx, interval) => {
                 self.on_set_wireguard_rotation_interval(tx, interval).await
             }
+            SetQuantumResistantTunnel(tx, state) => {
+                self.on_set_quantum_resistant_tunnel(tx, state).await
+            }
+            SetObfuscationSettings(tx, obfuscation_settings) => {
+                self.on_set_obfuscation_settings(tx, obfuscation_settings)
+                    .await
+            }
+            SetConnectionProfiles(tx, enabled) => {
+                self.on_set_connection_profiles(tx, enabled).await
+            }
             GetSettings(tx) => self.on_get_settings(tx),
             GenerateWireguardKey(tx) => self.on_generate_wireguard_key(tx).await,
             GetWireguardKey(tx) => self.on_get_wireguard_key(tx).await,
             VerifyWireguardKey(tx) => self.on_verify_wireguard_key(tx).await,
             GetVersionInfo(tx) => self.on_get_version_info(tx).await,
             GetCurrentVersion(tx) => self.on_get_current_version(tx),
+            GetApiAccessStatus(tx) => self.on_get_api_access_status(tx),
+            GetScheduledTasks(tx) => self.on_get_scheduled_tasks(tx),
+            SetApiTraceEnabled(tx, enabled) => self.on_set_api_trace_enabled(tx, enabled),
+            GetApiTrace(tx) => self.on_get_api_trace(tx),
+            SetApiClockCheck(tx, enabled) => self.on_set_api_clock_check(tx, enabled).await,
+            SetLogLevelOverrides(tx, directives, revert_after) => {
+                self.on_set_log_level_overrides(tx, directives, revert_after)
+            }
+            GetLogLevelOverrides(tx) => self.on_get_log_level_overrides(tx),
+            ResetLogLevelOverrides(tx) => self.on_reset_log_level_overrides(tx),
+            AddApiAccessMethod(tx, method) => self.on_add_api_access_method(tx, method).await,
+            RemoveApiAccessMethod(tx, name) => self.on_remove_api_access_method(tx, name).await,
+            SetApiAccessMethodEnabled(tx, name, enabled) => {
+                self.on_set_api_access_method_enabled(tx, name, enabled)
+                    .await
+            }
+            TestApiProxy(tx, proxy) => self.on_test_api_proxy(tx, proxy),
+            #[cfg(not(target_os = "android"))]
+            OpenApiForwarder(tx) => self.on_open_api_forwarder(tx).await,
+            UseApiAccessMethod(tx, name) => self.on_use_api_access_method(tx, name).await,
+            SetApiResolutionStrategy(tx, strategy) => {
+                self.on_set_api_resolution_strategy(tx, strategy).await
+            }
+            SubmitProblemReport(tx, report) => self.on_submit_problem_report(tx, report),
+            GetPendingProblemReports(tx) => self.on_get_pending_problem_reports(tx),
+            CancelPendingProblemReport(tx, id) => {
+                self.on_cancel_pending_problem_report(tx, id).await
+            }
+            GetDiagnostics(tx) => self.on_get_diagnostics(tx),
+            #[cfg(not(target_os = "android"))]
+            CollectProblemReport(tx, redact) => self.on_collect_problem_report(tx, redact),
             #[cfg(not(target_os = "android"))]
             FactoryReset(tx) => self.on_factory_reset(tx).await,
             #[cfg(target_os = "linux")]
@@ -1229,16 +3134,28 @@ where
             RemoveSplitTunnelProcess(tx, pid) => self.on_remove_split_tunnel_process(tx, pid),
             #[cfg(target_os = "linux")]
             ClearSplitTunnelProcesses(tx) => self.on_clear_split_tunnel_processes(tx),
-            #[cfg(windows)]
+            #[cfg(any(windows, target_os = "linux"))]
             AddSplitTunnelApp(tx, path) => self.on_add_split_tunnel_app(tx, path).await,
-            #[cfg(windows)]
+            #[cfg(any(windows, target_os = "linux"))]
             RemoveSplitTunnelApp(tx, path) => self.on_remove_split_tunnel_app(tx, path).await,
-            #[cfg(windows)]
+            #[cfg(any(windows, target_os = "linux"))]
             ClearSplitTunnelApps(tx) => self.on_clear_split_tunnel_apps(tx).await,
-            #[cfg(windows)]
+            #[cfg(any(windows, target_os = "linux"))]
+            SetSplitTunnelApps(tx, paths) => self.on_set_split_tunnel_apps(tx, paths).await,
+            #[cfg(any(windows, target_os = "linux"))]
             SetSplitTunnelState(tx, enabled) => self.on_set_split_tunnel_state(tx, enabled).await,
             #[cfg(target_os = "windows")]
             UseWireGuardNt(tx, state) => self.on_use_wireguard_nt(tx, state).await,
+            #[cfg(windows)]
+            UseOvpnDco(tx, state) => self.on_use_ovpn_dco(tx, state).await,
+            #[cfg(windows)]
+            SetWintunAdapterPolicy(tx, policy) => {
+                self.on_set_wintun_adapter_policy(tx, policy).await
+            }
+            #[cfg(windows)]
+            RunMigration(tx, requested_by) => self.on_run_migration(tx, requested_by).await,
+            #[cfg(target_os = "linux")]
+            UseUserspaceWireguard(tx, state) => self.on_use_userspace_wireguard(tx, state).await,
             Shutdown => self.trigger_shutdown_event(),
             PrepareRestart => self.on_prepare_restart(),
             #[cfg(target_os = "android")]
@@ -1280,6 +3197,7 @@ where
                         if is_first_key {
                             self.ensure_key_rotation().await;
                         }
+                        self.fetch_port_forwards();
                     }
                     Err(e) => {
                         log::error!(
@@ -1308,6 +3226,195 @@ where
         }
     }
 
+    async fn handle_wireguard_key_rotation(
+        &mut self,
+        account: AccountToken,
+        data: mullvad_types::wireguard::WireguardData,
+        old_key: talpid_types::net::wireguard::PublicKey,
+    ) {
+        // Keep the old key until the tunnel has connected with the new one, so that the tunnel
+        // keeps working while the new key propagates to the relays.
+        self.remove_retired_wireguard_key();
+        let is_current_account = self.settings.get_account_token().as_ref() == Some(&account);
+        self.retired_wireguard_key = Some((account.clone(), old_key));
+        self.handle_wireguard_key_event((account, Ok(data))).await;
+        if !is_current_account || self.get_target_tunnel_type() != Some(TunnelType::Wireguard) {
+            self.remove_retired_wireguard_key();
+        }
+    }
+
+    /// Returns whether the most recent WireGuard tunnel parameters use the key that is stored in
+    /// the settings.
+    fn uses_current_wireguard_key(&self) -> bool {
+        let current_key = match self.settings.get_wireguard() {
+            Some(data) => data.private_key.public_key(),
+            None => return false,
+        };
+        match &self.last_generated_tunnel_parameters {
+            Some(TunnelParameters::Wireguard(params)) => {
+                params.connection.tunnel.private_key.public_key() == current_key
+            }
+            _ => false,
+        }
+    }
+
+    /// Removes the WireGuard key that was replaced by automatic rotation, if any, from its
+    /// account.
+    fn remove_retired_wireguard_key(&mut self) {
+        let (account, key) = match self.retired_wireguard_key.take() {
+            Some(retired_key) => retired_key,
+            None => return,
+        };
+        log::debug!("Removing the WireGuard key that was replaced by key rotation");
+        let remove_key = self
+            .wireguard_key_manager
+            .remove_key_with_backoff(account, key);
+        tokio::spawn(async move {
+            if let Err(error) = remove_key.await {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to remove the rotated WireGuard key")
+                );
+            }
+        });
+    }
+
+    /// Starts or stops waiting for the next identity rotation to match the settings.
+    async fn update_identity_rotation(&mut self) {
+        self.identity_rotation_timer = None;
+        let interval = match self.settings.identity_rotation_interval {
+            Some(interval)
+                if !self.identity_rotation_in_progress
+                    && self.settings.get_account_token().is_some() =>
+            {
+                interval
+            }
+            _ => return,
+        };
+        let last_rotation = match self.last_identity_rotation {
+            Some(last_rotation) => last_rotation,
+            None => {
+                // The first rotation happens one interval after it was enabled
+                let now = chrono::Utc::now();
+                identity_rotation::store_last_rotation(&self.cache_dir, now).await;
+                self.last_identity_rotation = Some(now);
+                now
+            }
+        };
+        let due = chrono::Duration::from_std(interval.as_duration())
+            .ok()
+            .and_then(|interval| last_rotation.checked_add_signed(interval));
+        if let Some(due) = due {
+            self.identity_rotation_timer = Some(identity_rotation::RotationTimer::start(
+                self.scheduler_handle.ticks(),
+                self.tx.clone(),
+                due,
+            ));
+        }
+    }
+
+    fn retry_identity_rotation(&mut self) {
+        if self.settings.identity_rotation_interval.is_none() {
+            return;
+        }
+        let due = chrono::Utc::now()
+            + chrono::Duration::from_std(identity_rotation::RETRY_DELAY)
+                .expect("Retry delay is too large");
+        self.identity_rotation_timer = Some(identity_rotation::RotationTimer::start(
+            self.scheduler_handle.ticks(),
+            self.tx.clone(),
+            due,
+        ));
+    }
+
+    /// Replaces the WireGuard key of the device and gives the device a new random name.
+    fn handle_identity_rotation_due(&mut self) {
+        self.identity_rotation_timer = None;
+        let (account_token, old_key) = match (
+            self.settings.get_account_token(),
+            self.settings.get_wireguard(),
+        ) {
+            (Some(account_token), Some(data)) => (account_token, data.get_public_key()),
+            _ => {
+                log::debug!("Postponing identity rotation since there is no WireGuard key");
+                self.retry_identity_rotation();
+                return;
+            }
+        };
+        log::info!("Rotating the name and WireGuard key of the device");
+        self.identity_rotation_in_progress = true;
+
+        let account = self.account.clone();
+        let replace_key = self
+            .wireguard_key_manager
+            .replace_key_in_background(account_token.clone(), old_key.clone());
+        let daemon_tx = self.tx.clone();
+        tokio::spawn(async move {
+            let result = async {
+                let device = account
+                    .list_devices(account_token.clone())
+                    .await
+                    .map_err(Error::RestError)?
+                    .into_iter()
+                    .find(|device| device.pubkey == old_key.key)
+                    .ok_or(Error::NoCurrentDevice)?;
+                let data = replace_key.await.map_err(Error::ReplaceWireguardKey)?;
+
+                let name = identity_rotation::random_name();
+                let rename_result = account
+                    .rename_device(account_token.clone(), device.id.clone(), name.clone())
+                    .await;
+                let change = match rename_result {
+                    Ok(()) => Some(DeviceIdentityChange {
+                        id: device.id,
+                        previous_name: device.name,
+                        name,
+                    }),
+                    Err(error) => {
+                        log::error!(
+                            "{}",
+                            error.display_chain_with_msg("Failed to give the device a new name")
+                        );
+                        None
+                    }
+                };
+                Ok((data, change))
+            };
+            let result = result.await;
+            let _ = daemon_tx.send(InternalDaemonEvent::IdentityRotated(account_token, result));
+        });
+    }
+
+    async fn handle_identity_rotated(
+        &mut self,
+        account: AccountToken,
+        result: IdentityRotationResult,
+    ) {
+        self.identity_rotation_in_progress = false;
+        match result {
+            Ok((data, change)) => {
+                self.handle_wireguard_key_event((account, Ok(data))).await;
+                // Automatic key rotation has to continue from the new key
+                self.ensure_key_rotation().await;
+                if let Some(change) = change {
+                    log::info!("The device was renamed to \"{}\"", change.name);
+                    self.event_listener.notify_device_identity(change);
+                }
+                let now = chrono::Utc::now();
+                identity_rotation::store_last_rotation(&self.cache_dir, now).await;
+                self.last_identity_rotation = Some(now);
+                self.update_identity_rotation().await;
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to rotate the identity of the device")
+                );
+                self.retry_identity_rotation();
+            }
+        }
+    }
+
     async fn ensure_key_rotation(&mut self) {
         let token = match self.settings.get_account_token() {
             Some(token) => token,
@@ -1351,12 +3458,117 @@ where
         self.event_listener.notify_app_version(app_version_info);
     }
 
+    async fn handle_account_expiry_event(&mut self, event: AccountExpiryEvent) {
+        match &event {
+            AccountExpiryEvent::ExpiresSoon { expiry, .. } => {
+                log::info!("The account expires soon, at {}", expiry);
+            }
+            AccountExpiryEvent::Expired { .. } => {
+                log::warn!("The account has run out of time");
+                if self.settings.account_expiry.action == ExpiryAction::Disconnect
+                    && self.target_state == TargetState::Secured
+                {
+                    info!("Disconnecting because the account has run out of time");
+                    self.set_target_state(TargetState::Unsecured).await;
+                }
+            }
+        }
+        self.event_listener.notify_account_expiry(event);
+    }
+
+    /// Searches for other VPN software in the background.
+    fn detect_conflicting_software(&self) {
+        let tunnel_interface = self
+            .settings
+            .tunnel_options
+            .generic
+            .interface_name
+            .clone()
+            .or_else(|| talpid_core::tunnel::DEFAULT_INTERFACE_NAME.map(str::to_owned));
+        let daemon_tx = self.tx.clone();
+        tokio::task::spawn_blocking(move || {
+            let conflicts = conflicting_software::detect(tunnel_interface.as_deref());
+            let _ = daemon_tx.send(InternalDaemonEvent::ConflictingSoftwareDetected(conflicts));
+        });
+    }
+
+    #[cfg(not(target_os = "android"))]
+    async fn start_network_watcher(&mut self) {
+        let (network_tx, mut network_rx) = mpsc::unbounded();
+        match talpid_core::network_identity::spawn_watcher(network_tx).await {
+            Ok(watcher) => self.network_watcher = Some(watcher),
+            Err(error) => {
+                error!(
+                    "{}",
+                    error.display_chain_with_msg(
+                        "Failed to start watching the network. Network rules will not be applied"
+                    )
+                );
+                return;
+            }
+        }
+        let daemon_tx = self.tx.clone();
+        tokio::spawn(async move {
+            while let Some(network) = network_rx.next().await {
+                if daemon_tx
+                    .send(InternalDaemonEvent::NetworkChanged(network))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+    }
+
+    #[cfg(not(target_os = "android"))]
+    async fn handle_network_changed(&mut self, network: NetworkIdentity) {
+        if self.current_network.as_ref() == Some(&network) {
+            return;
+        }
+        let action = self.settings.network_rules.action(&network);
+        self.current_network = Some(network);
+        match action {
+            Some(NetworkAction::Connect) if self.target_state != TargetState::Secured => {
+                info!("Connecting since a network rule says so for the current network");
+                self.set_target_state(TargetState::Secured).await;
+            }
+            Some(NetworkAction::Disconnect) if self.target_state != TargetState::Unsecured => {
+                info!("Disconnecting since a network rule says so for the current network");
+                self.set_target_state(TargetState::Unsecured).await;
+            }
+            _ => (),
+        }
+    }
+
+    fn handle_conflicting_software_detected(&mut self, conflicts: Vec<ConflictingSoftware>) {
+        if conflicts == self.conflicting_software {
+            return;
+        }
+        for conflict in &conflicts {
+            if !self.conflicting_software.contains(conflict) {
+                log::warn!(
+                    "Detected other VPN software, which may keep the tunnel from working: {}",
+                    conflict
+                );
+            }
+        }
+        self.conflicting_software = conflicts.clone();
+        self.event_listener.notify_conflicting_software(conflicts);
+    }
+
     async fn on_set_target_state(
         &mut self,
         tx: oneshot::Sender<bool>,
         new_target_state: TargetState,
     ) {
         if self.state.is_running() {
+            if !self.connection_leases.is_empty() {
+                // The state that was asked for last is restored once the leases are released
+                self.target_state_before_leases = Some(new_target_state);
+                if !self.lock_target_cache {
+                    Self::cache_target_state(&self.cache_dir, new_target_state).await;
+                }
+            }
             let state_change_initated = self.set_target_state(new_target_state).await;
             Self::oneshot_send(tx, state_change_initated, "state change initiated");
         } else {
@@ -1364,6 +3576,60 @@ where
         }
     }
 
+    async fn on_acquire_connection_lease(&mut self, tx: ResponseTx<u64, Error>) {
+        if !self.state.is_running() {
+            warn!("Ignoring connection lease request due to shutdown");
+            return;
+        }
+        if self.device_state == DeviceState::Revoked {
+            Self::oneshot_send(
+                tx,
+                Err(Error::DeviceRevoked),
+                "acquire_connection_lease response",
+            );
+            return;
+        }
+
+        let lease = self.next_connection_lease;
+        self.next_connection_lease += 1;
+        if self.connection_leases.is_empty() {
+            self.target_state_before_leases = Some(self.target_state);
+        }
+        self.connection_leases.insert(lease);
+        info!(
+            "Acquired connection lease {}. Leases held: {}",
+            lease,
+            self.connection_leases.len()
+        );
+
+        if self.target_state != TargetState::Secured {
+            self.set_target_state(TargetState::Secured).await;
+            // The tunnel is only secured for as long as the lease is held, so it should not be
+            // secured again if the daemon restarts in the meantime
+            if !self.lock_target_cache {
+                Self::cache_target_state(&self.cache_dir, TargetState::Unsecured).await;
+            }
+        }
+        Self::oneshot_send(tx, Ok(lease), "acquire_connection_lease response");
+    }
+
+    async fn on_release_connection_lease(&mut self, lease: u64) {
+        if !self.connection_leases.remove(&lease) {
+            return;
+        }
+        info!(
+            "Released connection lease {}. Leases held: {}",
+            lease,
+            self.connection_leases.len()
+        );
+        if !self.connection_leases.is_empty() || !self.state.is_running() {
+            return;
+        }
+        if let Some(target_state) = self.target_state_before_leases.take() {
+            self.set_target_state(target_state).await;
+        }
+    }
+
     fn on_reconnect(&mut self, tx: oneshot::Sender<bool>) {
         if self.target_state == TargetState::Secured || self.tunnel_state.is_in_error_state() {
             self.connect_tunnel();
@@ -1384,8 +3650,15 @@ where
         match &self.tunnel_state {
             Disconnected => {
                 let location = self.get_geo_location();
-                tokio::spawn(async {
-                    Self::oneshot_send(tx, location.await.ok(), "current location");
+                let profile_state = self.relay_selector.connection_profiles_handle();
+                tokio::spawn(async move {
+                    let location = location.await.ok();
+                    if let Some(location) = &location {
+                        // This is the location of the device itself, so it tells which
+                        // connection profile applies
+                        profile_state.lock().set_country(location.country.clone());
+                    }
+                    Self::oneshot_send(tx, location, "current location");
                 });
             }
             Connecting { location, .. } => {
@@ -1417,6 +3690,23 @@ where
         }
     }
 
+    fn on_check_connection(&mut self, tx: ResponseTx<ConnectionCheck, Error>) {
+        let expected_relay = match self.tunnel_state {
+            TunnelState::Connected { .. } => self
+                .last_generated_relay
+                .as_ref()
+                .map(|relay| (relay.hostname.clone(), relay.location.clone())),
+            _ => None,
+        };
+        let rpc_service = self.rpc_runtime.rest_handle();
+        tokio::spawn(async move {
+            let result = geoip::send_connection_check(rpc_service, expected_relay)
+                .await
+                .map_err(Error::RestError);
+            Self::oneshot_send(tx, result, "connection check");
+        });
+    }
+
     fn get_geo_location(&mut self) -> impl Future<Output = Result<GeoIpLocation, ()>> {
         let rpc_service = self.rpc_runtime.rest_handle();
         async {
@@ -1450,6 +3740,46 @@ where
         })
     }
 
+    fn build_feature_indicators(&self, endpoint: &TunnelEndpoint) -> FeatureIndicators {
+        let settings = &self.settings;
+
+        #[cfg(windows)]
+        let split_tunneling =
+            settings.split_tunnel.enable_exclusions && !settings.split_tunnel.apps.is_empty();
+        #[cfg(target_os = "linux")]
+        let split_tunneling = self
+            .exclude_pids
+            .list()
+            .map(|pids| !pids.is_empty())
+            .unwrap_or(false);
+        #[cfg(not(any(windows, target_os = "linux")))]
+        let split_tunneling = false;
+
+        FeatureIndicators {
+            lockdown_mode: settings.block_when_disconnected,
+            split_tunneling,
+            custom_dns: match settings.tunnel_options.dns_options.state {
+                DnsState::Custom => !settings
+                    .tunnel_options
+                    .dns_options
+                    .custom_options
+                    .addresses
+                    .is_empty(),
+                DnsState::LocalForwarder => settings
+                    .tunnel_options
+                    .dns_options
+                    .local_forwarder_options
+                    .address
+                    .is_some(),
+                _ => false,
+            },
+            // WireGuard over TCP is tunneled through udp2tcp
+            obfuscation: endpoint.tunnel_type == TunnelType::Wireguard
+                && endpoint.endpoint.protocol == TransportProtocol::Tcp,
+            multihop: endpoint.entry_endpoint.is_some(),
+        }
+    }
+
     async fn on_create_new_account(&mut self, tx: ResponseTx<String, Error>) {
         let daemon_tx = self.tx.clone();
         let future = self.account.create_account();
@@ -1471,8 +3801,12 @@ where
         account_token: AccountToken,
     ) {
         let account = self.account.clone();
+        let expiry_monitor = self.expiry_monitor.clone();
         tokio::spawn(async move {
-            let result = account.check_expiry(account_token).await;
+            let result = account.check_expiry(account_token.clone()).await;
+            if let Ok(expiry) = &result {
+                expiry_monitor.update_expiry(account_token, *expiry);
+            }
             Self::oneshot_send(
                 tx,
                 result.map(|expiry| AccountData { expiry }),
@@ -1501,34 +3835,415 @@ where
         }
     }
 
-    async fn on_submit_voucher(
-        &mut self,
-        tx: ResponseTx<VoucherSubmission, Error>,
-        voucher: String,
-    ) {
-        if let Some(account_token) = self.settings.get_account_token() {
-            let mut account = self.account.clone();
-            tokio::spawn(async move {
-                Self::oneshot_send(
-                    tx,
-                    account
-                        .submit_voucher(account_token, voucher)
-                        .await
-                        .map_err(Error::RestError),
-                    "submit_voucher response",
-                );
-            });
-        } else {
-            Self::oneshot_send(tx, Err(Error::NoAccountToken), "submit_voucher response");
-        }
-    }
-
-    fn on_get_relay_locations(&mut self, tx: oneshot::Sender<RelayList>) {
-        Self::oneshot_send(tx, self.relay_selector.get_locations(), "relay locations");
-    }
-
-    async fn on_update_relay_locations(&mut self) {
-        self.relay_selector.update().await;
+    fn on_list_devices(&mut self, tx: ResponseTx<DeviceList, Error>) {
+        let account_token = match self.settings.get_account_token() {
+            Some(account_token) => account_token,
+            None => {
+                Self::oneshot_send(tx, Err(Error::NoAccountToken), "list_devices response");
+                return;
+            }
+        };
+        let pubkey = self.current_public_key();
+        let future = self.account.list_devices(account_token);
+        tokio::spawn(async move {
+            let result = future
+                .await
+                .map(|devices| DeviceList {
+                    current: find_device_by_pubkey(&devices, pubkey.as_ref()),
+                    devices,
+                })
+                .map_err(Error::RestError);
+            Self::oneshot_send(tx, result, "list_devices response");
+        });
+    }
+
+    fn on_rename_device(&mut self, tx: ResponseTx<(), Error>, name: String) {
+        let name = name.trim().to_owned();
+        if name.is_empty() {
+            Self::oneshot_send(tx, Err(Error::EmptyDeviceName), "rename_device response");
+            return;
+        }
+        let account_token = match self.settings.get_account_token() {
+            Some(account_token) => account_token,
+            None => {
+                Self::oneshot_send(tx, Err(Error::NoAccountToken), "rename_device response");
+                return;
+            }
+        };
+        let pubkey = self.current_public_key();
+        let account = self.account.clone();
+        tokio::spawn(async move {
+            let result = async {
+                let devices = account
+                    .list_devices(account_token.clone())
+                    .await
+                    .map_err(Error::RestError)?;
+                let id = find_device_by_pubkey(&devices, pubkey.as_ref())
+                    .ok_or(Error::NoCurrentDevice)?;
+                account
+                    .rename_device(account_token, id, name)
+                    .await
+                    .map_err(Error::RestError)
+            };
+            Self::oneshot_send(tx, result.await, "rename_device response");
+        });
+    }
+
+    fn on_remove_device(&mut self, tx: ResponseTx<(), Error>, id: DeviceId) {
+        let account_token = match self.settings.get_account_token() {
+            Some(account_token) => account_token,
+            None => {
+                Self::oneshot_send(tx, Err(Error::NoAccountToken), "remove_device response");
+                return;
+            }
+        };
+        let pubkey = self.current_public_key();
+        let account = self.account.clone();
+        tokio::spawn(async move {
+            let result = async {
+                let devices = account
+                    .list_devices(account_token.clone())
+                    .await
+                    .map_err(Error::RestError)?;
+                if find_device_by_pubkey(&devices, pubkey.as_ref()).as_ref() == Some(&id) {
+                    return Err(Error::RemoveCurrentDevice);
+                }
+                account
+                    .remove_device(account_token, id)
+                    .await
+                    .map_err(Error::RestError)
+            };
+            Self::oneshot_send(tx, result.await, "remove_device response");
+        });
+    }
+
+    fn on_list_port_forwards(&mut self, tx: ResponseTx<Vec<PortForward>, Error>) {
+        let account_token = match self.settings.get_account_token() {
+            Some(account_token) => account_token,
+            None => {
+                Self::oneshot_send(
+                    tx,
+                    Err(Error::NoAccountToken),
+                    "list_port_forwards response",
+                );
+                return;
+            }
+        };
+        let pubkey = self.current_public_key();
+        let future = self.account.list_port_forwards(account_token.clone());
+        let daemon_tx = self.tx.clone();
+        tokio::spawn(async move {
+            let result = future.await.map_err(Error::RestError).map(|port_forwards| {
+                let _ = daemon_tx.send(InternalDaemonEvent::PortForwardsFetched(
+                    account_token,
+                    port_forwards.clone(),
+                ));
+                filter_port_forwards(port_forwards, pubkey.as_ref())
+            });
+            Self::oneshot_send(tx, result, "list_port_forwards response");
+        });
+    }
+
+    fn on_add_port_forward(
+        &mut self,
+        tx: ResponseTx<PortForward, Error>,
+        country_code: String,
+        city_code: String,
+    ) {
+        let account_token = match self.settings.get_account_token() {
+            Some(account_token) => account_token,
+            None => {
+                Self::oneshot_send(tx, Err(Error::NoAccountToken), "add_port_forward response");
+                return;
+            }
+        };
+        let pubkey = match self.current_public_key() {
+            Some(pubkey) => pubkey,
+            None => {
+                Self::oneshot_send(tx, Err(Error::NoKeyAvailable), "add_port_forward response");
+                return;
+            }
+        };
+        let city_exists = self
+            .relay_selector
+            .get_locations()
+            .countries
+            .iter()
+            .filter(|country| country.code.eq_ignore_ascii_case(&country_code))
+            .flat_map(|country| country.cities.iter())
+            .any(|city| city.code.eq_ignore_ascii_case(&city_code));
+        if !city_exists {
+            Self::oneshot_send(
+                tx,
+                Err(Error::UnknownPortForwardCity(country_code, city_code)),
+                "add_port_forward response",
+            );
+            return;
+        }
+
+        let future = self.account.add_port_forward(
+            account_token.clone(),
+            pubkey,
+            country_code.to_lowercase(),
+            city_code.to_lowercase(),
+        );
+        let account = self.account.clone();
+        let daemon_tx = self.tx.clone();
+        tokio::spawn(async move {
+            let result = future.await.map_err(Error::RestError);
+            let added = result.is_ok();
+            Self::oneshot_send(tx, result, "add_port_forward response");
+            if added {
+                Self::fetch_port_forwards_inner(account, account_token, daemon_tx).await;
+            }
+        });
+    }
+
+    fn on_remove_port_forward(&mut self, tx: ResponseTx<(), Error>, id: PortForwardId) {
+        let account_token = match self.settings.get_account_token() {
+            Some(account_token) => account_token,
+            None => {
+                Self::oneshot_send(
+                    tx,
+                    Err(Error::NoAccountToken),
+                    "remove_port_forward response",
+                );
+                return;
+            }
+        };
+        let future = self.account.remove_port_forward(account_token.clone(), id);
+        let account = self.account.clone();
+        let daemon_tx = self.tx.clone();
+        tokio::spawn(async move {
+            let result = future.await.map_err(Error::RestError);
+            let removed = result.is_ok();
+            Self::oneshot_send(tx, result, "remove_port_forward response");
+            if removed {
+                Self::fetch_port_forwards_inner(account, account_token, daemon_tx).await;
+            }
+        });
+    }
+
+    /// Fetches the ports that are forwarded to this device in the background.
+    fn fetch_port_forwards(&self) {
+        if let Some(account_token) = self.settings.get_account_token() {
+            tokio::spawn(Self::fetch_port_forwards_inner(
+                self.account.clone(),
+                account_token,
+                self.tx.clone(),
+            ));
+        }
+    }
+
+    async fn fetch_port_forwards_inner(
+        account: account::AccountHandle,
+        account_token: AccountToken,
+        daemon_tx: DaemonEventSender,
+    ) {
+        match account.list_port_forwards(account_token.clone()).await {
+            Ok(port_forwards) => {
+                let _ = daemon_tx.send(InternalDaemonEvent::PortForwardsFetched(
+                    account_token,
+                    port_forwards,
+                ));
+            }
+            Err(error) => log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to fetch forwarded ports")
+            ),
+        }
+    }
+
+    fn handle_port_forwards_fetched(
+        &mut self,
+        account_token: AccountToken,
+        port_forwards: Vec<PortForward>,
+    ) {
+        if self.settings.get_account_token() != Some(account_token) {
+            return;
+        }
+        let port_forwards = filter_port_forwards(port_forwards, self.current_public_key().as_ref());
+        if port_forwards != self.port_forwards {
+            log::debug!("Ports forwarded to this device: {:?}", port_forwards);
+            self.port_forwards = port_forwards;
+            if self.settings.port_forwarding {
+                self.reconnect_tunnel();
+            }
+        }
+    }
+
+    async fn on_set_port_forwarding(&mut self, tx: ResponseTx<(), settings::Error>, enabled: bool) {
+        let save_result = self.settings.set_port_forwarding(enabled).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_port_forwarding response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if !self.port_forwards.is_empty() {
+                        info!("Initiating tunnel restart because port forwarding was toggled");
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_port_forwarding response");
+            }
+        }
+    }
+
+    fn on_get_device_state(&self, tx: oneshot::Sender<DeviceState>) {
+        Self::oneshot_send(tx, self.device_state, "get_device_state response");
+    }
+
+    async fn on_reregister_device(&mut self, tx: ResponseTx<(), Error>) {
+        let result = self.reregister_device().await;
+        if let Err(error) = &result {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to register the device again")
+            );
+        }
+        Self::oneshot_send(tx, result, "reregister_device response");
+    }
+
+    async fn reregister_device(&mut self) -> Result<(), Error> {
+        if self.device_state != DeviceState::Revoked {
+            return Err(Error::DeviceNotRevoked);
+        }
+        let account_token = self
+            .settings
+            .get_account_token()
+            .ok_or(Error::NoAccountToken)?;
+
+        let wireguard_data = match self
+            .wireguard_key_manager
+            .generate_key_sync(account_token.clone())
+            .await
+        {
+            Ok(wireguard_data) => wireguard_data,
+            Err(wireguard::Error::TooManyKeys) => return Err(Error::TooManyKeys),
+            Err(wireguard::Error::RestError(error)) => return Err(Error::RestError(error)),
+            Err(wireguard::Error::ApiCheckError(error)) => return Err(Error::ApiCheckError(error)),
+        };
+        let public_key = wireguard_data.get_public_key();
+        self.settings
+            .set_wireguard(Some(wireguard_data))
+            .await
+            .map_err(Error::SettingsError)?;
+        self.event_listener
+            .notify_key_event(KeygenEvent::NewKey(public_key.clone()));
+        self.wireguard_key_manager
+            .set_rotation_interval(
+                public_key,
+                account_token,
+                self.settings.tunnel_options.wireguard.rotation_interval,
+            )
+            .await;
+
+        log::info!("Registered this device again");
+        self.device_state = DeviceState::Active;
+        self.event_listener.notify_device_state(DeviceState::Active);
+        if mem::take(&mut self.reconnect_after_reregistration) {
+            self.set_target_state(TargetState::Secured).await;
+        }
+        Ok(())
+    }
+
+    /// Checks in the background whether the API still recognizes the WireGuard key of this
+    /// device. If it does not, the device has been revoked.
+    fn check_device_revoked(&self) {
+        if self.device_state == DeviceState::Revoked {
+            return;
+        }
+        let (account_token, public_key) =
+            match (self.settings.get_account_token(), self.current_public_key()) {
+                (Some(account_token), Some(public_key)) => (account_token, public_key),
+                _ => return,
+            };
+        let verification = self
+            .wireguard_key_manager
+            .verify_wireguard_key(account_token.clone(), public_key.clone());
+        let daemon_tx = self.tx.clone();
+        tokio::spawn(async move {
+            match verification.await {
+                Ok(false) => {
+                    let _ = daemon_tx.send(InternalDaemonEvent::DeviceRevoked(
+                        account_token,
+                        public_key,
+                    ));
+                }
+                Ok(true) => (),
+                Err(error) => log::debug!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to check whether the device is revoked")
+                ),
+            }
+        });
+    }
+
+    /// Disconnects and stops using the WireGuard key, since it has been removed from the
+    /// account. Whether traffic is blocked while disconnected follows the lockdown setting.
+    async fn handle_device_revoked(
+        &mut self,
+        account_token: AccountToken,
+        public_key: talpid_types::net::wireguard::PublicKey,
+    ) {
+        if self.device_state == DeviceState::Revoked
+            || self.settings.get_account_token() != Some(account_token)
+            || self.current_public_key() != Some(public_key)
+        {
+            return;
+        }
+
+        log::warn!("This device has been revoked from the account");
+        self.log_system_event(SystemLogEvent::DeviceRevoked);
+        self.wireguard_key_manager.reset();
+        self.reconnect_after_reregistration = self.target_state == TargetState::Secured;
+        self.set_target_state(TargetState::Unsecured).await;
+        self.device_state = DeviceState::Revoked;
+        self.event_listener
+            .notify_device_state(DeviceState::Revoked);
+    }
+
+    /// Returns the public WireGuard key of this device, which identifies it among the devices of
+    /// the account.
+    fn current_public_key(&self) -> Option<talpid_types::net::wireguard::PublicKey> {
+        self.settings
+            .get_wireguard()
+            .map(|data| data.private_key.public_key())
+    }
+
+    async fn on_submit_voucher(
+        &mut self,
+        tx: ResponseTx<VoucherSubmission, Error>,
+        voucher: String,
+    ) {
+        if let Some(account_token) = self.settings.get_account_token() {
+            let mut account = self.account.clone();
+            let expiry_monitor = self.expiry_monitor.clone();
+            tokio::spawn(async move {
+                let result = account
+                    .submit_voucher(account_token.clone(), voucher)
+                    .await
+                    .map_err(Error::RestError);
+                if let Ok(submission) = &result {
+                    expiry_monitor.update_expiry(account_token, submission.new_expiry);
+                }
+                Self::oneshot_send(tx, result, "submit_voucher response");
+            });
+        } else {
+            Self::oneshot_send(tx, Err(Error::NoAccountToken), "submit_voucher response");
+        }
+    }
+
+    fn on_get_relay_locations(&mut self, tx: oneshot::Sender<RelayList>) {
+        Self::oneshot_send(tx, self.relay_selector.get_locations(), "relay locations");
+    }
+
+    async fn on_update_relay_locations(&mut self) {
+        self.relay_selector.update().await;
     }
 
     async fn on_set_account(
@@ -1572,18 +4287,36 @@ where
             self.event_listener
                 .notify_settings(self.settings.to_settings());
 
-            let history_token = match account_token {
+            self.expiry_monitor.set_account(account_token.clone());
+            self.port_forwards.clear();
+            self.remove_retired_wireguard_key();
+            self.update_identity_rotation().await;
+
+            self.reconnect_after_reregistration = false;
+            if self.device_state == DeviceState::Revoked {
+                self.device_state = DeviceState::Active;
+                self.event_listener.notify_device_state(DeviceState::Active);
+            }
+
+            let history_token = match account_token.clone() {
                 Some(token) => token,
                 None => previous_token.clone().unwrap_or("".to_string()),
             };
-            if let Err(error) = self.account_history.set(history_token).await {
+            if let Err(error) = self.account_history.add(history_token).await {
                 log::error!(
                     "{}",
                     error.display_chain_with_msg("Failed to update account history")
                 );
             }
 
-            if let Some(previous_token) = previous_token {
+            // In privacy mode, a key may be left over from an account number that was forgotten
+            // when the daemon restarted. It most likely belongs to the account being logged in to.
+            let key_owner = previous_token.or_else(|| {
+                account_token
+                    .clone()
+                    .filter(|_| self.settings.account_privacy_mode)
+            });
+            if let Some(previous_token) = key_owner {
                 if let Some(previous_key) = self
                     .settings
                     .get_wireguard()
@@ -1615,7 +4348,7 @@ where
         Ok(account_changed)
     }
 
-    fn on_get_account_history(&mut self, tx: oneshot::Sender<Option<AccountToken>>) {
+    fn on_get_account_history(&mut self, tx: oneshot::Sender<Vec<AccountToken>>) {
         Self::oneshot_send(
             tx,
             self.account_history.get(),
@@ -1632,6 +4365,20 @@ where
         Self::oneshot_send(tx, result, "clear_account_history response");
     }
 
+    async fn on_remove_account_from_history(
+        &mut self,
+        tx: ResponseTx<(), Error>,
+        account: AccountToken,
+    ) {
+        let result = self
+            .account_history
+            .remove(&account)
+            .await
+            .map(|_| ())
+            .map_err(Error::AccountHistory);
+        Self::oneshot_send(tx, result, "remove_account_from_history response");
+    }
+
     // Remove the key associated with the current account, if there is one.
     // This does not modify settings or account history.
     #[cfg(not(target_os = "android"))]
@@ -1700,407 +4447,2940 @@ where
         );
     }
 
-    #[cfg(not(target_os = "android"))]
-    async fn on_factory_reset(&mut self, tx: ResponseTx<(), Error>) {
-        let mut last_error = Ok(());
+    fn on_get_api_access_status(&mut self, tx: oneshot::Sender<ApiAccessStatus>) {
+        let mut status = self.rpc_runtime.api_access_status();
+        status.access_method = Some(self.api_access_method.describe());
+        Self::oneshot_send(tx, status, "get_api_access_status response");
+    }
 
-        let remove_key = self.remove_current_key_rpc();
+    fn on_get_scheduled_tasks(&mut self, tx: oneshot::Sender<Vec<scheduler::ScheduledTaskInfo>>) {
+        let scheduler_handle = self.scheduler_handle.clone();
         tokio::spawn(async move {
-            if let Err(error) = remove_key.await {
-                log::error!(
-                    "{}",
-                    error.display_chain_with_msg(
-                        "Failed to remove WireGuard key for previous account"
-                    )
-                );
-            }
-        });
-
-        if let Err(error) = self.account_history.clear().await {
-            log::error!(
-                "{}",
-                error.display_chain_with_msg("Failed to clear account history")
+            Self::oneshot_send(
+                tx,
+                scheduler_handle.scheduled_tasks().await,
+                "get_scheduled_tasks response",
             );
-            last_error = Err(Error::ClearAccountHistoryError(error));
-        }
+        });
+    }
 
-        if let Err(e) = self.settings.reset().await {
-            log::error!("Failed to reset settings - {}", e);
-            last_error = Err(Error::ClearSettingsError(e));
-        }
+    fn on_set_api_trace_enabled(&mut self, tx: oneshot::Sender<()>, enabled: bool) {
+        log::info!(
+            "{} API request tracing",
+            if enabled { "Enabling" } else { "Disabling" }
+        );
+        self.rpc_runtime.api_trace().set_enabled(enabled);
+        Self::oneshot_send(tx, (), "set_api_trace_enabled response");
+    }
 
-        // Shut the daemon down.
-        self.trigger_shutdown_event();
+    fn on_get_api_trace(
+        &mut self,
+        tx: oneshot::Sender<(bool, Vec<mullvad_rpc::trace::TraceEntry>)>,
+    ) {
+        let api_trace = self.rpc_runtime.api_trace();
+        Self::oneshot_send(
+            tx,
+            (api_trace.is_enabled(), api_trace.entries()),
+            "get_api_trace response",
+        );
+    }
 
-        self.shutdown_tasks.push(Box::pin(async move {
-            if let Err(e) = Self::clear_cache_directory().await {
-                log::error!(
-                    "{}",
-                    e.display_chain_with_msg("Failed to clear cache directory")
-                );
-                last_error = Err(Error::ClearCacheError);
+    async fn on_set_api_clock_check(&mut self, tx: ResponseTx<(), settings::Error>, enabled: bool) {
+        let save_result = self.settings.set_api_clock_check(enabled).await;
+        match save_result {
+            Ok(settings_changed) => {
+                if settings_changed {
+                    self.rpc_runtime.clock_check().set_enabled(enabled);
+                }
+                Self::oneshot_send(tx, Ok(()), "set_api_clock_check response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
             }
-
-            if let Err(e) = Self::clear_log_directory().await {
-                log::error!(
-                    "{}",
-                    e.display_chain_with_msg("Failed to clear log directory")
-                );
-                last_error = Err(Error::ClearLogsError);
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_api_clock_check response");
             }
-            Self::oneshot_send(tx, last_error, "factory_reset response");
-        }));
+        }
     }
 
-    #[cfg(target_os = "linux")]
-    fn on_get_split_tunnel_processes(&mut self, tx: ResponseTx<Vec<i32>, split_tunnel::Error>) {
-        let result = self.exclude_pids.list().map_err(|error| {
-            error!("{}", error.display_chain_with_msg("Unable to obtain PIDs"));
-            error
-        });
-        Self::oneshot_send(tx, result, "get_split_tunnel_processes response");
+    fn on_set_log_level_overrides(
+        &mut self,
+        tx: oneshot::Sender<()>,
+        directives: Vec<logging::LogLevelDirective>,
+        revert_after: Option<Duration>,
+    ) {
+        log::info!(
+            "Overriding log levels: {}",
+            directives
+                .iter()
+                .map(|directive| format!("{}={}", directive.module, directive.level))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let id = logging::set_log_level_overrides(directives, revert_after);
+        if let Some(revert_after) = revert_after {
+            tokio::spawn(async move {
+                tokio::time::sleep(revert_after).await;
+                if logging::revert_log_level_overrides(Some(id)) {
+                    log::info!("Reverted log level overrides");
+                }
+            });
+        }
+        Self::oneshot_send(tx, (), "set_log_level_overrides response");
     }
 
-    #[cfg(target_os = "linux")]
-    fn on_add_split_tunnel_process(&mut self, tx: ResponseTx<(), split_tunnel::Error>, pid: i32) {
-        let result = self.exclude_pids.add(pid).map_err(|error| {
-            error!("{}", error.display_chain_with_msg("Unable to add PID"));
-            error
-        });
-        Self::oneshot_send(tx, result, "add_split_tunnel_process response");
+    fn on_get_log_level_overrides(&mut self, tx: oneshot::Sender<logging::LogLevelOverrides>) {
+        Self::oneshot_send(
+            tx,
+            logging::log_level_overrides(),
+            "get_log_level_overrides response",
+        );
     }
 
-    #[cfg(target_os = "linux")]
-    fn on_remove_split_tunnel_process(
+    fn on_reset_log_level_overrides(&mut self, tx: oneshot::Sender<()>) {
+        if logging::revert_log_level_overrides(None) {
+            log::info!("Reverted log level overrides");
+        }
+        Self::oneshot_send(tx, (), "reset_log_level_overrides response");
+    }
+
+    async fn on_add_api_access_method(
         &mut self,
-        tx: ResponseTx<(), split_tunnel::Error>,
-        pid: i32,
+        tx: ResponseTx<(), Error>,
+        method: ApiAccessMethod,
     ) {
-        let result = self.exclude_pids.remove(pid).map_err(|error| {
-            error!("{}", error.display_chain_with_msg("Unable to remove PID"));
-            error
-        });
-        Self::oneshot_send(tx, result, "remove_split_tunnel_process response");
+        if self.api_access_method_exists(&method.name) {
+            Self::oneshot_send(
+                tx,
+                Err(Error::ApiAccessMethodExists(method.name)),
+                "add_api_access_method response",
+            );
+            return;
+        }
+        if method.enabled {
+            let proxy = method.proxy.clone();
+            self.probe_api_proxy(proxy, ApiAccessMethodChange::Add(method), tx);
+        } else {
+            self.handle_api_access_method_probed(ApiAccessMethodChange::Add(method), tx)
+                .await;
+        }
     }
 
-    #[cfg(target_os = "linux")]
-    fn on_clear_split_tunnel_processes(&mut self, tx: ResponseTx<(), split_tunnel::Error>) {
-        let result = self.exclude_pids.clear().map_err(|error| {
-            error!("{}", error.display_chain_with_msg("Unable to clear PIDs"));
-            error
-        });
-        Self::oneshot_send(tx, result, "clear_split_tunnel_processes response");
+    async fn on_remove_api_access_method(&mut self, tx: ResponseTx<(), Error>, name: String) {
+        if is_builtin_access_method(&name) {
+            Self::oneshot_send(
+                tx,
+                Err(Error::BuiltinApiAccessMethod(name)),
+                "remove_api_access_method response",
+            );
+            return;
+        }
+        if !self.api_access_method_exists(&name) {
+            Self::oneshot_send(
+                tx,
+                Err(Error::ApiAccessMethodNotFound(name)),
+                "remove_api_access_method response",
+            );
+            return;
+        }
+        let result = self.settings.remove_api_access_method(&name).await;
+        self.on_api_access_methods_changed(tx, result, "remove_api_access_method response")
+            .await;
     }
 
-    /// Update the split app paths in both the settings and tunnel
-    #[cfg(windows)]
-    async fn set_split_tunnel_paths(
+    async fn on_set_api_access_method_enabled(
         &mut self,
         tx: ResponseTx<(), Error>,
-        response_msg: &'static str,
-        settings: Settings,
-        new_list: HashSet<PathBuf>,
+        name: String,
+        enabled: bool,
     ) {
-        if new_list == settings.split_tunnel.apps {
-            Self::oneshot_send(tx, Ok(()), response_msg);
+        // The built-in methods are not probed, since they may be the only way to reach the API
+        if is_builtin_access_method(&name) {
+            let result = self
+                .settings
+                .set_api_access_method_enabled(&name, enabled)
+                .await;
+            self.on_api_access_methods_changed(
+                tx,
+                result,
+                "set_api_access_method_enabled response",
+            )
+            .await;
             return;
         }
-
-        if settings.split_tunnel.enable_exclusions {
-            let (result_tx, result_rx) = oneshot::channel();
-            self.send_tunnel_command(TunnelCommand::SetExcludedApps(
-                result_tx,
-                new_list.iter().map(|s| OsString::from(s)).collect(),
-            ));
-            match result_rx.await {
-                Ok(Ok(_)) => (),
-                Ok(Err(error)) => {
-                    log::error!(
-                        "{}",
-                        error.display_chain_with_msg("Failed to set excluded apps list")
-                    );
-                    Self::oneshot_send(tx, Err(Error::SplitTunnelError(error)), response_msg);
-                    return;
-                }
-                Err(_) => {
-                    log::error!("The tunnel failed to return a result");
-                    return;
-                }
-            }
-        }
-
-        let save_result = self
+        let method = self
             .settings
-            .set_split_tunnel_apps(new_list)
-            .await
-            .map_err(Error::SettingsError);
-        match save_result {
-            Ok(true) => {
-                Self::oneshot_send(tx, Ok(()), response_msg);
-                self.event_listener
-                    .notify_settings(self.settings.to_settings());
-            }
-            Err(error) => {
-                Self::oneshot_send(tx, Err(error), response_msg);
+            .api_access_methods
+            .iter()
+            .find(|method| method.name == name)
+            .cloned();
+        match method {
+            None => Self::oneshot_send(
+                tx,
+                Err(Error::ApiAccessMethodNotFound(name)),
+                "set_api_access_method_enabled response",
+            ),
+            Some(method) if enabled && !method.enabled => {
+                self.probe_api_proxy(method.proxy, ApiAccessMethodChange::Enable(name), tx)
             }
-            Ok(false) => {
-                // unreachable!("new_list != settings.split_tunnel_apps")
-                error!("BUG: new_list != settings.split_tunnel_apps");
+            Some(_) => {
+                let result = self
+                    .settings
+                    .set_api_access_method_enabled(&name, enabled)
+                    .await;
+                self.on_api_access_methods_changed(
+                    tx,
+                    result,
+                    "set_api_access_method_enabled response",
+                )
+                .await;
             }
         }
     }
 
-    #[cfg(windows)]
-    async fn on_add_split_tunnel_app(&mut self, tx: ResponseTx<(), Error>, path: PathBuf) {
-        let settings = self.settings.to_settings();
-
-        let mut new_list = settings.split_tunnel.apps.clone();
-        new_list.insert(path);
-
-        self.set_split_tunnel_paths(tx, "add_split_tunnel_app response", settings, new_list)
-            .await;
+    fn on_test_api_proxy(&mut self, tx: ResponseTx<(), Error>, proxy: ApiProxy) {
+        let probe = self
+            .rpc_runtime
+            .probe_api_access(mullvad_rpc::ProxyHandle::new(Some(proxy)));
+        tokio::spawn(async move {
+            Self::oneshot_send(
+                tx,
+                probe.await.map_err(Error::ApiProxyProbeError),
+                "test_api_proxy response",
+            );
+        });
     }
 
-    #[cfg(windows)]
-    async fn on_remove_split_tunnel_app(&mut self, tx: ResponseTx<(), Error>, path: PathBuf) {
-        let settings = self.settings.to_settings();
-
-        let mut new_list = settings.split_tunnel.apps.clone();
-        new_list.remove(&path);
-
-        self.set_split_tunnel_paths(tx, "remove_split_tunnel_app response", settings, new_list)
-            .await;
+    #[cfg(not(target_os = "android"))]
+    async fn on_open_api_forwarder(&mut self, tx: ResponseTx<SocketAddr, Error>) {
+        if let Some(forwarder) = &self.api_forwarder {
+            if forwarder.keep_alive() {
+                Self::oneshot_send(tx, Ok(forwarder.address()), "open_api_forwarder response");
+                return;
+            }
+        }
+        let result = api_forwarder::ApiForwarder::start(
+            self.rpc_runtime.address_cache.clone(),
+            self.rpc_runtime.proxy_handle(),
+        )
+        .await
+        .map(|forwarder| {
+            let address = forwarder.address();
+            self.api_forwarder = Some(forwarder);
+            address
+        })
+        .map_err(Error::ApiForwarderError);
+        Self::oneshot_send(tx, result, "open_api_forwarder response");
     }
 
-    #[cfg(windows)]
-    async fn on_clear_split_tunnel_apps(&mut self, tx: ResponseTx<(), Error>) {
-        let settings = self.settings.to_settings();
-        let new_list = HashSet::new();
-        self.set_split_tunnel_paths(tx, "clear_split_tunnel_apps response", settings, new_list)
-            .await;
+    /// Probes `proxy` in the background, and applies `change` if the API can be reached through
+    /// it.
+    fn probe_api_proxy(
+        &mut self,
+        proxy: ApiProxy,
+        change: ApiAccessMethodChange,
+        tx: ResponseTx<(), Error>,
+    ) {
+        let probe = self
+            .rpc_runtime
+            .probe_api_access(mullvad_rpc::ProxyHandle::new(Some(proxy)));
+        let daemon_tx = self.tx.clone();
+        tokio::spawn(async move {
+            match probe.await {
+                Ok(()) => {
+                    let _ = daemon_tx.send(InternalDaemonEvent::ApiAccessMethodProbed(change, tx));
+                }
+                Err(error) => {
+                    let _ = tx.send(Err(Error::ApiProxyProbeError(error)));
+                }
+            }
+        });
     }
 
-    #[cfg(windows)]
-    async fn on_set_split_tunnel_state(&mut self, tx: ResponseTx<(), Error>, enabled: bool) {
-        let settings = self.settings.to_settings();
-
-        if enabled != settings.split_tunnel.enable_exclusions {
-            let new_list = if enabled {
-                settings.split_tunnel.apps.clone()
-            } else {
-                HashSet::new()
-            };
-            if !settings.split_tunnel.apps.is_empty() {
-                let (result_tx, result_rx) = oneshot::channel();
-                self.send_tunnel_command(TunnelCommand::SetExcludedApps(
-                    result_tx,
-                    new_list.iter().map(|app| OsString::from(app)).collect(),
-                ));
-                match result_rx.await {
-                    Ok(Ok(_)) => (),
-                    Ok(Err(error)) => {
-                        log::error!(
-                            "{}",
-                            error.display_chain_with_msg("Failed to set excluded apps list")
-                        );
-                        Self::oneshot_send(
-                            tx,
-                            Err(Error::SplitTunnelError(error)),
-                            "set_split_tunnel_state response",
-                        );
-                        return;
-                    }
-                    Err(_) => {
-                        log::error!("The tunnel failed to return a result");
-                        return;
-                    }
+    async fn handle_api_access_method_probed(
+        &mut self,
+        change: ApiAccessMethodChange,
+        tx: ResponseTx<(), Error>,
+    ) {
+        let result = match change {
+            ApiAccessMethodChange::Add(method) => {
+                // The method may have been added while the proxy was being probed
+                if self.api_access_method_exists(&method.name) {
+                    Self::oneshot_send(
+                        tx,
+                        Err(Error::ApiAccessMethodExists(method.name)),
+                        "add_api_access_method response",
+                    );
+                    return;
                 }
+                self.settings.add_api_access_method(method).await
+            }
+            ApiAccessMethodChange::Enable(name) => {
+                self.settings
+                    .set_api_access_method_enabled(&name, true)
+                    .await
             }
+        };
+        self.on_api_access_methods_changed(tx, result, "api access method response")
+            .await;
+    }
 
-            let save_result = self
-                .settings
-                .set_split_tunnel_state(enabled)
-                .await
-                .map_err(Error::SettingsError);
-            match save_result {
-                Ok(true) => {
-                    Self::oneshot_send(tx, Ok(()), "set_split_tunnel_state response");
+    async fn on_api_access_methods_changed(
+        &mut self,
+        tx: ResponseTx<(), Error>,
+        result: Result<bool, settings::Error>,
+        response_msg: &'static str,
+    ) {
+        match result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), response_msg);
+                if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                }
-                Err(error) => {
-                    error!(
-                        "{}",
-                        error.display_chain_with_msg("Unable to save settings")
-                    );
-                    Self::oneshot_send(tx, Err(error), "set_split_tunnel_state response");
-                }
-                Ok(false) => {
-                    // unreachable!("enabled != settings.split_tunnel"),
-                    error!("BUG: enabled != settings.split_tunnel");
+                    self.update_api_access_method();
                 }
             }
-        } else {
-            Self::oneshot_send(tx, Ok(()), "set_split_tunnel_state response");
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(Error::SettingsError(e)), response_msg);
+            }
         }
     }
 
-    #[cfg(windows)]
-    async fn on_use_wireguard_nt(&mut self, tx: ResponseTx<(), Error>, state: bool) {
-        let save_result = self
+    /// Returns the first enabled API access method, if it can be used without being checked
+    /// first. Direct access is used otherwise, until the daemon has started and switches to the
+    /// first enabled method.
+    fn initial_api_access_method(settings: &Settings) -> api_access::ActiveMethod {
+        match api_access::enabled_methods(settings).into_iter().next() {
+            Some((name, api_access::AccessMethod::Proxy(proxy))) => api_access::ActiveMethod {
+                name,
+                proxy: Some(proxy),
+                #[cfg(not(target_os = "android"))]
+                bridge: None,
+            },
+            _ => api_access::ActiveMethod::direct(),
+        }
+    }
+
+    /// The Shadowsocks client used for API traffic logs to a directory of its own, since the
+    /// tunnel may use another client that logs to the log directory at the same time.
+    #[cfg(not(target_os = "android"))]
+    async fn api_bridge_log_dir(log_dir: Option<&Path>) -> Option<PathBuf> {
+        let api_bridge_log_dir = log_dir?.join("api-bridge");
+        match fs::create_dir_all(&api_bridge_log_dir).await {
+            Ok(()) => Some(api_bridge_log_dir),
+            Err(error) => {
+                log::warn!(
+                    "{}",
+                    error.display_chain_with_msg(&format!(
+                        "Failed to create {}",
+                        api_bridge_log_dir.display()
+                    ))
+                );
+                None
+            }
+        }
+    }
+
+    fn forward_api_unreachable_events(
+        rpc_runtime: &mullvad_rpc::MullvadRpcRuntime,
+        daemon_tx: DaemonEventSender,
+    ) {
+        use tokio::sync::broadcast::error::RecvError;
+
+        let mut unreachable_rx = rpc_runtime.proxy_handle().unreachable_listener();
+        tokio::spawn(async move {
+            loop {
+                match unreachable_rx.recv().await {
+                    Ok(()) | Err(RecvError::Lagged(_)) => {
+                        if daemon_tx.send(InternalDaemonEvent::ApiUnreachable).is_err() {
+                            break;
+                        }
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    /// Switches to the first enabled API access method if the current one has been disabled or
+    /// changed.
+    fn update_api_access_method(&mut self) {
+        let methods = api_access::enabled_methods(&self.settings);
+        let current = &self.api_access_method;
+        let current_is_enabled = methods.iter().any(|(name, method)| {
+            *name == current.name
+                && match method {
+                    api_access::AccessMethod::Proxy(proxy) => current.proxy.as_ref() == Some(proxy),
+                    _ => true,
+                }
+        });
+        if current_is_enabled {
+            return;
+        }
+        log::info!(
+            "API access method \"{}\" is no longer enabled",
+            self.api_access_method.name
+        );
+        self.switch_api_access_method(methods.into(), None);
+    }
+
+    fn handle_api_unreachable(&mut self) {
+        if self.api_access_switch.is_some() {
+            return;
+        }
+        let current = self.api_access_method.name.clone();
+        // Try the methods that come after the current one first
+        let mut methods = api_access::enabled_methods(&self.settings);
+        if let Some(index) = methods.iter().position(|(name, _)| *name == current) {
+            methods.rotate_left(index + 1);
+            methods.pop();
+        }
+        if methods.is_empty() {
+            log::warn!(
+                "The API cannot be reached through access method \"{}\", and there is no other \
+                 access method to try",
+                current
+            );
+            return;
+        }
+        log::warn!(
+            "The API cannot be reached through access method \"{}\". Trying the other access \
+             methods",
+            current
+        );
+        self.switch_api_access_method(methods.into(), None);
+    }
+
+    /// Checks `methods` in turn, and sends API traffic through the first one that the API can be
+    /// reached through. A switch that is already in progress is abandoned.
+    fn switch_api_access_method(
+        &mut self,
+        methods: VecDeque<(String, api_access::AccessMethod)>,
+        requested: Option<ResponseTx<(), Error>>,
+    ) {
+        if let Some(switch) = self.api_access_switch.take() {
+            if let Some(tx) = switch.requested {
+                let _ = tx.send(Err(Error::ApiAccessMethodSuperseded));
+            }
+        }
+        let id = self.next_api_access_switch_id;
+        self.next_api_access_switch_id += 1;
+        self.api_access_switch = Some(ApiAccessSwitch {
+            id,
+            remaining: methods,
+            requested,
+        });
+        self.check_next_api_access_method();
+    }
+
+    fn check_next_api_access_method(&mut self) {
+        let (id, name, method) = match &mut self.api_access_switch {
+            Some(switch) => match switch.remaining.pop_front() {
+                Some((name, method)) => (switch.id, name, method),
+                None => return,
+            },
+            None => return,
+        };
+        log::debug!("Checking API access method \"{}\"", name);
+
+        // The proxy is set once the method is ready to be probed
+        let proxy_handle = mullvad_rpc::ProxyHandle::new(None);
+        let probe = self.rpc_runtime.probe_api_access(proxy_handle.clone());
+        let daemon_tx = self.tx.clone();
+        match method {
+            api_access::AccessMethod::Direct => {
+                self.allow_api_endpoint(None);
+                tokio::spawn(async move {
+                    let result = probe.await.map(|()| api_access::ActiveMethod::direct());
+                    let _ = daemon_tx.send(InternalDaemonEvent::ApiAccessMethodChecked(
+                        id, name, result,
+                    ));
+                });
+            }
+            api_access::AccessMethod::Proxy(proxy) => {
+                self.allow_api_endpoint(Some(proxy.address()));
+                proxy_handle.set(Some(proxy.clone()));
+                let method_name = name.clone();
+                tokio::spawn(async move {
+                    let result = probe.await.map(|()| api_access::ActiveMethod {
+                        name: method_name,
+                        proxy: Some(proxy),
+                        #[cfg(not(target_os = "android"))]
+                        bridge: None,
+                    });
+                    let _ = daemon_tx.send(InternalDaemonEvent::ApiAccessMethodChecked(
+                        id, name, result,
+                    ));
+                });
+            }
+            #[cfg(not(target_os = "android"))]
+            api_access::AccessMethod::Bridges => {
+                let (bridge_settings, hostname) = match self.relay_selector.get_api_bridge() {
+                    Some((bridge_settings, relay)) => (bridge_settings, relay.hostname),
+                    None => {
+                        let error =
+                            io::Error::new(io::ErrorKind::NotFound, "No bridge is available");
+                        let _ = daemon_tx.send(InternalDaemonEvent::ApiAccessMethodChecked(
+                            id,
+                            name,
+                            Err(error),
+                        ));
+                        return;
+                    }
+                };
+                self.allow_api_endpoint(Some(bridge_settings.get_endpoint().endpoint.address));
+                let resources = self.api_bridge_resources.clone();
+                let method_name = name.clone();
+                tokio::spawn(async move {
+                    let result = async move {
+                        let bridge = tokio::task::spawn_blocking(move || {
+                            api_access::BridgeClient::start(&bridge_settings, hostname, &resources)
+                        })
+                        .await
+                        .map_err(|error| io::Error::new(io::ErrorKind::Other, error))??;
+                        proxy_handle.set(Some(bridge.proxy()));
+                        probe.await?;
+                        Ok(api_access::ActiveMethod {
+                            name: method_name,
+                            proxy: Some(bridge.proxy()),
+                            bridge: Some(bridge),
+                        })
+                    }
+                    .await;
+                    let _ = daemon_tx.send(InternalDaemonEvent::ApiAccessMethodChecked(
+                        id, name, result,
+                    ));
+                });
+            }
+        }
+    }
+
+    async fn handle_api_access_method_checked(
+        &mut self,
+        id: u64,
+        name: String,
+        result: io::Result<api_access::ActiveMethod>,
+    ) {
+        let switch = match self.api_access_switch.take() {
+            Some(switch) if switch.id == id => switch,
+            // The switch was abandoned. A bridge client that was started for it is stopped here
+            other => {
+                self.api_access_switch = other;
+                return;
+            }
+        };
+        match result {
+            Ok(method) => {
+                log::info!("Sending API traffic through access method \"{}\"", name);
+                self.set_api_access_method(method).await;
+                if let Some(tx) = switch.requested {
+                    let result = self.settings.prefer_api_access_method(&name).await;
+                    self.on_api_access_methods_changed(tx, result, "use_api_access_method response")
+                        .await;
+                }
+            }
+            Err(error) => {
+                log::warn!(
+                    "{}",
+                    error.display_chain_with_msg(&format!(
+                        "Unable to reach the API through access method \"{}\"",
+                        name
+                    ))
+                );
+                if !switch.remaining.is_empty() {
+                    self.api_access_switch = Some(switch);
+                    self.check_next_api_access_method();
+                    return;
+                }
+                // Keep using the current method
+                self.allow_api_endpoint(self.api_access_method.endpoint());
+                if let Some(tx) = switch.requested {
+                    Self::oneshot_send(
+                        tx,
+                        Err(Error::ApiAccessMethodUnreachable(name, error)),
+                        "use_api_access_method response",
+                    );
+                }
+            }
+        }
+    }
+
+    /// Sends API traffic through `method` from now on.
+    async fn set_api_access_method(&mut self, method: api_access::ActiveMethod) {
+        self.allow_api_endpoint(method.endpoint());
+        self.rpc_runtime.proxy_handle().set(method.proxy.clone());
+        // Any bridge client of the previous method is stopped when it is dropped
+        self.api_access_method = method;
+        // Drop existing connections so that new requests go through the new route
+        self.rpc_handle.service().reset().await;
+        self.event_listener
+            .notify_api_access_method(self.api_access_method.describe());
+    }
+
+    /// Allows API traffic to `endpoint` through the firewall, or to the API itself if it is
+    /// `None`.
+    fn allow_api_endpoint(&mut self, endpoint: Option<SocketAddr>) {
+        *self.api_proxy_endpoint.lock().unwrap() = endpoint;
+        let endpoint = endpoint.unwrap_or_else(|| self.rpc_runtime.address_cache.peek_address());
+        let (result_tx, _result_rx) = oneshot::channel();
+        self.send_tunnel_command(TunnelCommand::AllowEndpoint(
+            Endpoint::from_socket_address(endpoint, TransportProtocol::Tcp),
+            result_tx,
+        ));
+    }
+
+    async fn on_use_api_access_method(&mut self, tx: ResponseTx<(), Error>, name: String) {
+        match api_access::find_method(&self.settings, &name) {
+            Some(method) => self.switch_api_access_method(vec![(name, method)].into(), Some(tx)),
+            None => Self::oneshot_send(
+                tx,
+                Err(Error::ApiAccessMethodNotFound(name)),
+                "use_api_access_method response",
+            ),
+        }
+    }
+
+    fn api_access_method_exists(&self, name: &str) -> bool {
+        is_builtin_access_method(name)
+            || self
+                .settings
+                .api_access_methods
+                .iter()
+                .any(|method| method.name == name)
+    }
+
+    fn on_submit_problem_report(
+        &mut self,
+        tx: ResponseTx<Option<String>, Error>,
+        report: ProblemReport,
+    ) {
+        let proxy = mullvad_rpc::ProblemReportProxy::new(self.rpc_handle.clone());
+        let daemon_tx = self.tx.clone();
+        tokio::spawn(async move {
+            let idempotency_token = mullvad_rpc::rest::IdempotencyToken::new();
+            let result = proxy
+                .problem_report(
+                    &report.email,
+                    &report.message,
+                    &report.log,
+                    &report.metadata,
+                    idempotency_token.clone(),
+                )
+                .await;
+            match result {
+                Ok(()) => Self::oneshot_send(tx, Ok(None), "submit_problem_report response"),
+                Err(error) if problem_report_outbox::should_queue(&error) => {
+                    let _ = daemon_tx.send(InternalDaemonEvent::QueueProblemReport(
+                        report,
+                        idempotency_token,
+                        error.display_chain(),
+                        tx,
+                    ));
+                }
+                Err(error) => Self::oneshot_send(
+                    tx,
+                    Err(Error::RestError(error)),
+                    "submit_problem_report response",
+                ),
+            }
+        });
+    }
+
+    async fn handle_queue_problem_report(
+        &mut self,
+        report: ProblemReport,
+        idempotency_token: mullvad_rpc::rest::IdempotencyToken,
+        error: String,
+        tx: ResponseTx<Option<String>, Error>,
+    ) {
+        let result = self
+            .problem_report_outbox
+            .add(report, idempotency_token, error)
+            .await
+            .map(Some)
+            .map_err(Error::ProblemReportOutbox);
+        match result {
+            Ok(Some(ref id)) => {
+                log::info!(
+                    "Queued problem report {} since the API could not be reached",
+                    id
+                );
+                self.schedule_problem_report_retry();
+            }
+            Err(ref error) => log::error!("{}", error.display_chain()),
+            _ => (),
+        }
+        Self::oneshot_send(tx, result, "submit_problem_report response");
+    }
+
+    async fn handle_problem_report_retried(
+        &mut self,
+        id: String,
+        result: problem_report_outbox::RetryResult,
+    ) {
+        use problem_report_outbox::RetryResult;
+        match result {
+            RetryResult::Sent => {
+                log::info!("Sent queued problem report {}", id);
+                self.problem_report_retry_delay = problem_report_outbox::MIN_RETRY_DELAY;
+            }
+            RetryResult::Failed(error) => {
+                log::debug!("Failed to send queued problem report {}: {}", id, error);
+                self.problem_report_outbox.record_failure(&id, error);
+                return;
+            }
+            RetryResult::Rejected(error) => {
+                log::error!("Dropping queued problem report {}: {}", id, error);
+            }
+        }
+        match self.problem_report_outbox.remove(&id).await {
+            // The report may have been cancelled while it was being sent
+            Ok(()) | Err(problem_report_outbox::Error::NotFound(_)) => (),
+            Err(error) => log::error!("{}", error.display_chain()),
+        }
+    }
+
+    /// Handles power and session events. Policies that react to them, such as reconnecting after
+    /// resuming, hook in here.
+    #[cfg(windows)]
+    fn handle_system_event(&mut self, event: SystemEvent) {
+        log::debug!("System event: {:?}", event);
+    }
+
+    /// Starts sending the queued problem reports once the API can be reached, unless this is
+    /// already in progress.
+    fn schedule_problem_report_retry(&mut self) {
+        if self.problem_report_retry_job.is_some() || self.problem_report_outbox.is_empty() {
+            return;
+        }
+
+        let reports = self.problem_report_outbox.paths();
+        let delay = self.problem_report_retry_delay;
+        let api_availability = self.rpc_runtime.availability_handle();
+        let proxy = mullvad_rpc::ProblemReportProxy::new(self.rpc_handle.clone());
+        let daemon_tx = self.tx.clone();
+
+        let (future, abort_handle) = abortable(Box::pin(async move {
+            tokio::time::sleep(delay).await;
+            if let Err(error) = api_availability.wait_online().await {
+                log::error!("{}", error.display_chain());
+            }
+            for (id, path) in reports {
+                let result = match problem_report_outbox::read_report(&path).await {
+                    Ok(queued) => match proxy
+                        .problem_report(
+                            &queued.report.email,
+                            &queued.report.message,
+                            &queued.report.log,
+                            &queued.report.metadata,
+                            queued.idempotency_token,
+                        )
+                        .await
+                    {
+                        Ok(()) => problem_report_outbox::RetryResult::Sent,
+                        Err(error) if problem_report_outbox::should_queue(&error) => {
+                            problem_report_outbox::RetryResult::Failed(error.display_chain())
+                        }
+                        Err(error) => {
+                            problem_report_outbox::RetryResult::Rejected(error.display_chain())
+                        }
+                    },
+                    Err(error) => {
+                        problem_report_outbox::RetryResult::Rejected(error.display_chain())
+                    }
+                };
+                let _ = daemon_tx.send(InternalDaemonEvent::ProblemReportRetried(id, result));
+            }
+            let _ = daemon_tx.send(InternalDaemonEvent::ProblemReportRetriesFinished);
+        }));
+
+        tokio::spawn(future);
+        self.problem_report_retry_job = Some(abort_handle);
+    }
+
+    fn on_get_pending_problem_reports(
+        &self,
+        tx: oneshot::Sender<Vec<problem_report_outbox::PendingReport>>,
+    ) {
+        Self::oneshot_send(
+            tx,
+            self.problem_report_outbox.pending(),
+            "get_pending_problem_reports response",
+        );
+    }
+
+    async fn on_cancel_pending_problem_report(&mut self, tx: ResponseTx<(), Error>, id: String) {
+        let result = self
+            .problem_report_outbox
+            .remove(&id)
+            .await
+            .map_err(Error::ProblemReportOutbox);
+        if self.problem_report_outbox.is_empty() {
+            if let Some(job) = self.problem_report_retry_job.take() {
+                job.abort();
+            }
+            self.problem_report_retry_delay = problem_report_outbox::MIN_RETRY_DELAY;
+        }
+        Self::oneshot_send(tx, result, "cancel_pending_problem_report response");
+    }
+
+    fn on_get_diagnostics(&self, tx: oneshot::Sender<diagnostics::Diagnostics>) {
+        Self::oneshot_send(tx, self.diagnostics(), "get_diagnostics response");
+    }
+
+    fn diagnostics(&self) -> diagnostics::Diagnostics {
+        let settings = self.settings.to_settings();
+        diagnostics::Diagnostics {
+            settings: diagnostics::redacted_settings(&settings),
+            enabled_features: diagnostics::enabled_features(&settings),
+            state_transitions: self.state_history.get(),
+            read_only_settings: self.settings.is_read_only(),
+            #[cfg(not(target_os = "android"))]
+            network: self
+                .current_network()
+                .map(|network| network.fingerprint.redacted()),
+            #[cfg(target_os = "android")]
+            network: None,
+            missing_capabilities: self
+                .missing_capabilities
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+        }
+    }
+
+    #[cfg(not(target_os = "android"))]
+    fn on_collect_problem_report(&mut self, tx: ResponseTx<PathBuf, Error>, redact: Vec<String>) {
+        let mut account_numbers = self.account_history.get();
+        account_numbers.extend(self.settings.get_account_token());
+
+        // The tunnel state includes the cause of the last error, and the connection attempts
+        // include the errors that the phases of each attempt failed with
+        let mut sections = vec![(
+            "Tunnel state".to_owned(),
+            format!("{:#?}", self.tunnel_state),
+        )];
+        let attempts = self.connection_trace.attempts();
+        if !attempts.is_empty() {
+            sections.push((
+                "Connection attempts".to_owned(),
+                diagnostics::format_connection_attempts(&attempts),
+            ));
+        }
+        let api_trace = self.rpc_runtime.api_trace();
+        if api_trace.is_enabled() {
+            let entries: Vec<_> = api_trace
+                .entries()
+                .iter()
+                .map(|entry| entry.to_string())
+                .collect();
+            sections.push(("API trace".to_owned(), entries.join("\n")));
+        }
+
+        let daemon_state = mullvad_problem_report::DaemonState {
+            account_numbers,
+            diagnostics: self.diagnostics().into_manifest_fields(),
+            sections,
+        };
+        let (rules_tx, rules_rx) = oneshot::channel();
+        self.send_tunnel_command(TunnelCommand::GetFirewallRules(rules_tx));
+        let path = self.cache_dir.join(PROBLEM_REPORT_FILE);
+
+        tokio::spawn(async move {
+            let mut daemon_state = daemon_state;
+            match rules_rx.await {
+                Ok(Ok(rules)) => daemon_state.sections.push((
+                    "Firewall rules".to_owned(),
+                    diagnostics::format_firewall_rules(&rules),
+                )),
+                Ok(Err(error)) => daemon_state.sections.push((
+                    "Failed to list the firewall rules".to_owned(),
+                    error.display_chain(),
+                )),
+                Err(_) => log::warn!("The firewall rules are left out of the problem report"),
+            }
+
+            // Reading the logs and the network configuration blocks
+            let result = tokio::task::spawn_blocking(move || {
+                Self::remove_problem_report(&path);
+                mullvad_problem_report::collect_daemon_report(daemon_state, &path, redact)
+                    .map(|()| path)
+                    .map_err(Error::CollectProblemReport)
+            })
+            .await;
+            match result {
+                Ok(result) => Self::oneshot_send(tx, result, "collect_problem_report response"),
+                Err(error) => log::error!("Failed to collect a problem report: {}", error),
+            }
+        });
+    }
+
+    /// Removes a previously collected report, which is read-only.
+    #[cfg(not(target_os = "android"))]
+    fn remove_problem_report(path: &Path) {
+        // Read-only files cannot be removed on Windows
+        #[cfg(windows)]
+        if let Ok(metadata) = std::fs::metadata(path) {
+            let mut permissions = metadata.permissions();
+            permissions.set_readonly(false);
+            let _ = std::fs::set_permissions(path, permissions);
+        }
+        match std::fs::remove_file(path) {
+            Ok(()) => (),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => (),
+            Err(error) => log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to remove the previous problem report")
+            ),
+        }
+    }
+
+    #[cfg(not(target_os = "android"))]
+    async fn on_factory_reset(&mut self, tx: ResponseTx<(), Error>) {
+        let mut last_error = Ok(());
+
+        let remove_key = self.remove_current_key_rpc();
+        tokio::spawn(async move {
+            if let Err(error) = remove_key.await {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg(
+                        "Failed to remove WireGuard key for previous account"
+                    )
+                );
+            }
+        });
+
+        if let Err(error) = self.account_history.clear().await {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to clear account history")
+            );
+            last_error = Err(Error::ClearAccountHistoryError(error));
+        }
+
+        if let Err(e) = self.settings.reset().await {
+            log::error!("Failed to reset settings - {}", e);
+            last_error = Err(Error::ClearSettingsError(e));
+        }
+        #[cfg(not(target_os = "android"))]
+        self.update_persistent_firewall();
+
+        // Shut the daemon down.
+        self.trigger_shutdown_event();
+
+        self.shutdown_tasks.push(Box::pin(async move {
+            if let Err(e) = Self::clear_cache_directory().await {
+                log::error!(
+                    "{}",
+                    e.display_chain_with_msg("Failed to clear cache directory")
+                );
+                last_error = Err(Error::ClearCacheError);
+            }
+
+            if let Err(e) = Self::clear_log_directory().await {
+                log::error!(
+                    "{}",
+                    e.display_chain_with_msg("Failed to clear log directory")
+                );
+                last_error = Err(Error::ClearLogsError);
+            }
+            Self::oneshot_send(tx, last_error, "factory_reset response");
+        }));
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_get_split_tunnel_processes(&mut self, tx: ResponseTx<Vec<i32>, split_tunnel::Error>) {
+        let result = self.exclude_pids.list().map_err(|error| {
+            error!("{}", error.display_chain_with_msg("Unable to obtain PIDs"));
+            error
+        });
+        Self::oneshot_send(tx, result, "get_split_tunnel_processes response");
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_add_split_tunnel_process(&mut self, tx: ResponseTx<(), split_tunnel::Error>, pid: i32) {
+        let result = self.exclude_pids.add(pid).map_err(|error| {
+            error!("{}", error.display_chain_with_msg("Unable to add PID"));
+            error
+        });
+        Self::oneshot_send(tx, result, "add_split_tunnel_process response");
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_remove_split_tunnel_process(
+        &mut self,
+        tx: ResponseTx<(), split_tunnel::Error>,
+        pid: i32,
+    ) {
+        let result = self.exclude_pids.remove(pid).map_err(|error| {
+            error!("{}", error.display_chain_with_msg("Unable to remove PID"));
+            error
+        });
+        Self::oneshot_send(tx, result, "remove_split_tunnel_process response");
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_clear_split_tunnel_processes(&mut self, tx: ResponseTx<(), split_tunnel::Error>) {
+        let result = self.exclude_pids.clear().map_err(|error| {
+            error!("{}", error.display_chain_with_msg("Unable to clear PIDs"));
+            error
+        });
+        Self::oneshot_send(tx, result, "clear_split_tunnel_processes response");
+    }
+
+    /// Excludes the given applications from the tunnel. Returns `None` if the tunnel state
+    /// machine did not respond.
+    #[cfg(any(windows, target_os = "linux"))]
+    async fn apply_excluded_apps(
+        &mut self,
+        paths: &HashSet<PathBuf>,
+    ) -> Option<Result<(), split_tunnel::Error>> {
+        #[cfg(windows)]
+        {
+            let (result_tx, result_rx) = oneshot::channel();
+            self.send_tunnel_command(TunnelCommand::SetExcludedApps(
+                result_tx,
+                paths.iter().map(|app| OsString::from(app)).collect(),
+            ));
+            result_rx.await.ok()
+        }
+        #[cfg(target_os = "linux")]
+        {
+            Some(
+                self.exclude_pids
+                    .set_paths(&paths.iter().collect::<Vec<_>>()),
+            )
+        }
+    }
+
+    /// Update the split app paths in both the settings and tunnel
+    #[cfg(any(windows, target_os = "linux"))]
+    async fn set_split_tunnel_paths(
+        &mut self,
+        tx: ResponseTx<(), Error>,
+        response_msg: &'static str,
+        settings: Settings,
+        new_list: HashSet<PathBuf>,
+    ) {
+        if new_list == settings.split_tunnel.apps {
+            Self::oneshot_send(tx, Ok(()), response_msg);
+            return;
+        }
+
+        if settings.split_tunnel.enable_exclusions {
+            match self.apply_excluded_apps(&new_list).await {
+                Some(Ok(_)) => (),
+                Some(Err(error)) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to set excluded apps list")
+                    );
+                    Self::oneshot_send(tx, Err(Error::SplitTunnelError(error)), response_msg);
+                    return;
+                }
+                None => {
+                    log::error!("The tunnel failed to return a result");
+                    return;
+                }
+            }
+        }
+
+        let save_result = self
+            .settings
+            .set_split_tunnel_apps(new_list)
+            .await
+            .map_err(Error::SettingsError);
+        match save_result {
+            Ok(true) => {
+                Self::oneshot_send(tx, Ok(()), response_msg);
+                self.event_listener
+                    .notify_settings(self.settings.to_settings());
+            }
+            Err(error) => {
+                Self::oneshot_send(tx, Err(error), response_msg);
+            }
+            Ok(false) => {
+                // unreachable!("new_list != settings.split_tunnel_apps")
+                error!("BUG: new_list != settings.split_tunnel_apps");
+            }
+        }
+    }
+
+    #[cfg(any(windows, target_os = "linux"))]
+    async fn on_add_split_tunnel_app(&mut self, tx: ResponseTx<(), Error>, path: PathBuf) {
+        let settings = self.settings.to_settings();
+
+        let mut new_list = settings.split_tunnel.apps.clone();
+        new_list.insert(path);
+
+        self.set_split_tunnel_paths(tx, "add_split_tunnel_app response", settings, new_list)
+            .await;
+    }
+
+    #[cfg(any(windows, target_os = "linux"))]
+    async fn on_remove_split_tunnel_app(&mut self, tx: ResponseTx<(), Error>, path: PathBuf) {
+        let settings = self.settings.to_settings();
+
+        let mut new_list = settings.split_tunnel.apps.clone();
+        new_list.remove(&path);
+
+        self.set_split_tunnel_paths(tx, "remove_split_tunnel_app response", settings, new_list)
+            .await;
+    }
+
+    #[cfg(any(windows, target_os = "linux"))]
+    async fn on_clear_split_tunnel_apps(&mut self, tx: ResponseTx<(), Error>) {
+        let settings = self.settings.to_settings();
+        let new_list = HashSet::new();
+        self.set_split_tunnel_paths(tx, "clear_split_tunnel_apps response", settings, new_list)
+            .await;
+    }
+
+    #[cfg(any(windows, target_os = "linux"))]
+    async fn on_set_split_tunnel_apps(
+        &mut self,
+        tx: ResponseTx<(), Error>,
+        paths: HashSet<PathBuf>,
+    ) {
+        let settings = self.settings.to_settings();
+        self.set_split_tunnel_paths(tx, "set_split_tunnel_apps response", settings, paths)
+            .await;
+    }
+
+    #[cfg(any(windows, target_os = "linux"))]
+    async fn on_set_split_tunnel_state(&mut self, tx: ResponseTx<(), Error>, enabled: bool) {
+        let settings = self.settings.to_settings();
+
+        if enabled != settings.split_tunnel.enable_exclusions {
+            let new_list = if enabled {
+                settings.split_tunnel.apps.clone()
+            } else {
+                HashSet::new()
+            };
+            if !settings.split_tunnel.apps.is_empty() {
+                match self.apply_excluded_apps(&new_list).await {
+                    Some(Ok(_)) => (),
+                    Some(Err(error)) => {
+                        log::error!(
+                            "{}",
+                            error.display_chain_with_msg("Failed to set excluded apps list")
+                        );
+                        Self::oneshot_send(
+                            tx,
+                            Err(Error::SplitTunnelError(error)),
+                            "set_split_tunnel_state response",
+                        );
+                        return;
+                    }
+                    None => {
+                        log::error!("The tunnel failed to return a result");
+                        return;
+                    }
+                }
+            }
+
+            let save_result = self
+                .settings
+                .set_split_tunnel_state(enabled)
+                .await
+                .map_err(Error::SettingsError);
+            match save_result {
+                Ok(true) => {
+                    Self::oneshot_send(tx, Ok(()), "set_split_tunnel_state response");
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+                Err(error) => {
+                    error!(
+                        "{}",
+                        error.display_chain_with_msg("Unable to save settings")
+                    );
+                    Self::oneshot_send(tx, Err(error), "set_split_tunnel_state response");
+                }
+                Ok(false) => {
+                    // unreachable!("enabled != settings.split_tunnel"),
+                    error!("BUG: enabled != settings.split_tunnel");
+                }
+            }
+        } else {
+            Self::oneshot_send(tx, Ok(()), "set_split_tunnel_state response");
+        }
+    }
+
+    #[cfg(windows)]
+    async fn on_use_wireguard_nt(&mut self, tx: ResponseTx<(), Error>, state: bool) {
+        let save_result = self
+            .settings
+            .set_use_wireguard_nt(state)
+            .await
+            .map_err(Error::SettingsError);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "use_wireguard_nt response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
+                        info!("Initiating tunnel restart");
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(error) => {
+                error!(
+                    "{}",
+                    error.display_chain_with_msg("Unable to save settings")
+                );
+                Self::oneshot_send(tx, Err(error), "use_wireguard_nt response");
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn on_use_userspace_wireguard(&mut self, tx: ResponseTx<(), Error>, state: bool) {
+        let save_result = self
+            .settings
+            .set_use_userspace_wireguard(state)
+            .await
+            .map_err(Error::SettingsError);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "use_userspace_wireguard response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
+                        info!("Initiating tunnel restart");
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(error) => {
+                error!(
+                    "{}",
+                    error.display_chain_with_msg("Unable to save settings")
+                );
+                Self::oneshot_send(tx, Err(error), "use_userspace_wireguard response");
+            }
+        }
+    }
+
+    #[cfg(windows)]
+    async fn on_use_ovpn_dco(&mut self, tx: ResponseTx<(), Error>, state: bool) {
+        let save_result = self
+            .settings
+            .set_use_ovpn_dco(state)
+            .await
+            .map_err(Error::SettingsError);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "use_ovpn_dco response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::OpenVpn) = self.get_connected_tunnel_type() {
+                        info!("Initiating tunnel restart");
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(error) => {
+                error!(
+                    "{}",
+                    error.display_chain_with_msg("Unable to save settings")
+                );
+                Self::oneshot_send(tx, Err(error), "use_ovpn_dco response");
+            }
+        }
+    }
+
+    #[cfg(windows)]
+    async fn on_set_wintun_adapter_policy(
+        &mut self,
+        tx: ResponseTx<(), Error>,
+        policy: openvpn::WintunAdapterPolicy,
+    ) {
+        let save_result = self
+            .settings
+            .set_wintun_adapter_policy(policy)
+            .await
+            .map_err(Error::SettingsError);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_wintun_adapter_policy response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(error) => {
+                error!(
+                    "{}",
+                    error.display_chain_with_msg("Unable to save settings")
+                );
+                Self::oneshot_send(tx, Err(error), "set_wintun_adapter_policy response");
+            }
+        }
+    }
+
+    async fn on_update_relay_settings(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        update: RelaySettingsUpdate,
+    ) {
+        let save_result = self.settings.update_relay_settings(update).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "update_relay_settings response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    info!("Initiating tunnel restart because the relay settings changed");
+                    self.reconnect_tunnel();
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "update_relay_settings response");
+            }
+        }
+    }
+
+    async fn on_set_allow_lan(&mut self, tx: ResponseTx<(), settings::Error>, allow_lan: bool) {
+        let save_result = self.settings.set_allow_lan(allow_lan).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_allow_lan response");
+                if settings_changed {
+                    let settings = self.settings.to_settings();
+                    Self::warn_about_unreachable_dns_servers(&settings);
+                    self.event_listener.notify_settings(settings);
+                    self.send_tunnel_command(TunnelCommand::Reconfigure(Reconfiguration {
+                        allow_lan: Some(allow_lan),
+                        ..Reconfiguration::default()
+                    }));
+                    self.update_lan_proxy().await;
+                    #[cfg(not(target_os = "android"))]
+                    self.update_persistent_firewall();
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_allow_lan response");
+            }
+        }
+    }
+
+    #[cfg(windows)]
+    async fn on_set_allow_virtual_networks(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        allow_virtual_networks: bool,
+    ) {
+        let save_result = self
+            .settings
+            .set_allow_virtual_networks(allow_virtual_networks)
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_allow_virtual_networks response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::AllowVirtualNetworks(
+                        allow_virtual_networks,
+                    ));
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_allow_virtual_networks response");
+            }
+        }
+    }
+
+    async fn on_add_firewall_exception(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        exception: AllowedEndpoint,
+    ) {
+        let save_result = self.settings.add_firewall_exception(exception).await;
+        self.on_firewall_exceptions_changed(tx, save_result, "add_firewall_exception response");
+    }
+
+    async fn on_remove_firewall_exception(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        exception: AllowedEndpoint,
+    ) {
+        let save_result = self.settings.remove_firewall_exception(exception).await;
+        self.on_firewall_exceptions_changed(tx, save_result, "remove_firewall_exception response");
+    }
+
+    fn on_firewall_exceptions_changed(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        save_result: Result<bool, settings::Error>,
+        response_msg: &'static str,
+    ) {
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), response_msg);
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::Reconfigure(Reconfiguration {
+                        firewall_exceptions: Some(self.settings.firewall_exceptions.clone()),
+                        ..Reconfiguration::default()
+                    }));
+                    #[cfg(not(target_os = "android"))]
+                    self.update_persistent_firewall();
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), response_msg);
+            }
+        }
+    }
+
+    async fn on_set_mdns_reflector(&mut self, tx: ResponseTx<(), settings::Error>, enabled: bool) {
+        let save_result = self.settings.set_mdns_reflector(enabled).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_mdns_reflector response");
+                if settings_changed {
+                    if enabled && !self.settings.allow_lan {
+                        log::warn!("The mDNS reflector will not run unless LAN access is allowed");
+                    }
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::MdnsReflector(enabled));
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_mdns_reflector response");
+            }
+        }
+    }
+
+    async fn on_set_show_beta_releases(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        enabled: bool,
+    ) {
+        let save_result = self.settings.set_show_beta_releases(enabled).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_show_beta_releases response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    let mut handle = self.version_updater_handle.clone();
+                    handle.set_show_beta_releases(enabled).await;
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_show_beta_releases response");
+            }
+        }
+    }
+
+    async fn on_set_encrypt_sensitive_settings(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        enabled: bool,
+    ) {
+        let save_result = self.settings.set_encrypt_sensitive_settings(enabled).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_encrypt_sensitive_settings response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_encrypt_sensitive_settings response");
+            }
+        }
+    }
+
+    async fn on_set_credential_storage(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        storage: CredentialStorage,
+    ) {
+        let save_result = self.settings.set_credential_storage(storage).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_credential_storage response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_credential_storage response");
+            }
+        }
+    }
+
+    async fn on_set_keep_account_history(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        enabled: bool,
+    ) {
+        let save_result = self.settings.set_keep_account_history(enabled).await;
+        match save_result {
+            Ok(settings_changed) => {
+                if let Err(error) = self.account_history.set_keep_history(enabled).await {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to update account history")
+                    );
+                }
+                Self::oneshot_send(tx, Ok(()), "set_keep_account_history response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_keep_account_history response");
+            }
+        }
+    }
+
+    async fn on_set_account_privacy_mode(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        enabled: bool,
+    ) {
+        let save_result = self.settings.set_account_privacy_mode(enabled).await;
+        match save_result {
+            Ok(settings_changed) => {
+                if let Err(error) = self.account_history.set_privacy_mode(enabled).await {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to update account history")
+                    );
+                }
+                Self::oneshot_send(tx, Ok(()), "set_account_privacy_mode response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_account_privacy_mode response");
+            }
+        }
+    }
+
+    async fn on_set_identity_rotation_interval(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        interval: Option<HumanDuration>,
+    ) {
+        let was_enabled = self.settings.identity_rotation_interval.is_some();
+        let save_result = self.settings.set_identity_rotation_interval(interval).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_identity_rotation_interval response");
+                if settings_changed {
+                    if !was_enabled {
+                        self.last_identity_rotation = None;
+                    }
+                    self.update_identity_rotation().await;
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_identity_rotation_interval response");
+            }
+        }
+    }
+
+    async fn on_set_account_expiry_settings(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        mut account_expiry: AccountExpirySettings,
+    ) {
+        account_expiry.warning_thresholds =
+            account_expiry::normalize_thresholds(account_expiry.warning_thresholds);
+        let thresholds = account_expiry.warning_thresholds.clone();
+        let save_result = self
+            .settings
+            .set_account_expiry_settings(account_expiry)
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_account_expiry_settings response");
+                if settings_changed {
+                    self.expiry_monitor.set_warning_thresholds(thresholds);
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_account_expiry_settings response");
+            }
+        }
+    }
+
+    async fn on_set_api_resolution_strategy(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        strategy: ApiResolutionStrategy,
+    ) {
+        let save_result = self.settings.set_api_resolution_strategy(strategy).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_api_resolution_strategy response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    let api_resolver = self.rpc_runtime.api_resolver();
+                    api_resolver.set_strategy(strategy);
+                    tokio::spawn(async move { api_resolver.update_addresses().await });
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_api_resolution_strategy response");
+            }
+        }
+    }
+
+    async fn on_set_block_when_disconnected(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        block_when_disconnected: bool,
+    ) {
+        let save_result = self
+            .settings
+            .set_block_when_disconnected(block_when_disconnected)
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_block_when_disconnected response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::BlockWhenDisconnected(
+                        block_when_disconnected,
+                    ));
+                    #[cfg(not(target_os = "android"))]
+                    self.update_persistent_firewall();
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_block_when_disconnected response");
+            }
+        }
+    }
+
+    async fn on_set_persistent_lockdown(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        persistent_lockdown: bool,
+    ) {
+        let save_result = self
+            .settings
+            .set_persistent_lockdown(persistent_lockdown)
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_persistent_lockdown response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    #[cfg(not(target_os = "android"))]
+                    self.update_persistent_firewall();
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_persistent_lockdown response");
+            }
+        }
+    }
+
+    async fn on_set_auto_connect(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        auto_connect: bool,
+    ) {
+        let save_result = self.settings.set_auto_connect(auto_connect).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set auto-connect response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set auto-connect response");
+            }
+        }
+    }
+
+    async fn on_add_network_rule(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        rule: NetworkRule,
+    ) {
+        let save_result = self.settings.add_network_rule(rule).await;
+        self.on_network_rules_changed(tx, save_result, "add_network_rule response");
+    }
+
+    async fn on_remove_network_rule(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        network: NetworkMatch,
+    ) {
+        let save_result = self.settings.remove_network_rule(&network).await;
+        self.on_network_rules_changed(tx, save_result, "remove_network_rule response");
+    }
+
+    async fn on_set_default_network_action(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        action: Option<NetworkAction>,
+    ) {
+        let save_result = self.settings.set_default_network_action(action).await;
+        self.on_network_rules_changed(tx, save_result, "set_default_network_action response");
+    }
+
+    fn on_network_rules_changed(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        save_result: Result<bool, settings::Error>,
+        response_msg: &'static str,
+    ) {
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), response_msg);
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), response_msg);
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "android"))]
+    fn on_get_current_network(&self, tx: oneshot::Sender<Option<NetworkIdentity>>) {
+        Self::oneshot_send(tx, self.current_network(), "current network");
+    }
+
+    /// Returns the network that the host is on, with the country of the device filled in if it
+    /// has been learned.
+    #[cfg(not(target_os = "android"))]
+    fn current_network(&self) -> Option<NetworkIdentity> {
+        let mut network = self.current_network.clone()?;
+        network.fingerprint.country = self
+            .relay_selector
+            .connection_profiles_handle()
+            .lock()
+            .country()
+            .map(str::to_owned);
+        Some(network)
+    }
+
+    async fn on_set_openvpn_mssfix(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        mssfix_arg: Option<u16>,
+    ) {
+        let save_result = self.settings.set_openvpn_mssfix(mssfix_arg).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_openvpn_mssfix response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::OpenVpn) = self.get_connected_tunnel_type() {
+                        info!(
+                            "Initiating tunnel restart because the OpenVPN mssfix setting changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_openvpn_mssfix response");
+            }
+        }
+    }
+
+    async fn on_set_bridge_settings(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        new_settings: BridgeSettings,
+    ) {
+        match self.settings.set_bridge_settings(new_settings).await {
+            Ok(settings_changes) => {
+                if settings_changes {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.reconnect_tunnel();
+                };
+                Self::oneshot_send(tx, Ok(()), "set_bridge_settings");
+            }
+
+            Err(e) => {
+                log::error!(
+                    "{}",
+                    e.display_chain_with_msg("Failed to set new bridge settings")
+                );
+                Self::oneshot_send(tx, Err(e), "set_bridge_settings");
+            }
+        }
+    }
+
+    async fn on_set_bridge_state(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        bridge_state: BridgeState,
+    ) {
+        let result = match self.settings.set_bridge_state(bridge_state).await {
+            Ok(settings_changed) => {
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    log::info!("Initiating tunnel restart because bridge state changed");
+                    self.reconnect_tunnel();
+                }
+                Ok(())
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to set new bridge state")
+                );
+                Err(error)
+            }
+        };
+        Self::oneshot_send(tx, result, "on_set_bridge_state response");
+    }
+
+
+    async fn on_set_enable_ipv6(&mut self, tx: ResponseTx<(), settings::Error>, enable_ipv6: bool) {
+        let save_result = self.settings.set_enable_ipv6(enable_ipv6).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_enable_ipv6 response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    info!("Initiating tunnel restart because the enable IPv6 setting changed");
+                    self.reconnect_tunnel();
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_enable_ipv6 response");
+            }
+        }
+    }
+
+    async fn on_set_ipv6_failure_policy(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        policy: Ipv6FailurePolicy,
+    ) {
+        let save_result = self.settings.set_ipv6_failure_policy(policy).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_ipv6_failure_policy response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    // The policy is applied when connecting, so only a failed tunnel is affected
+                    if let TunnelState::Error(_) = self.tunnel_state {
+                        info!("Reconnecting because the IPv6 failure policy changed");
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_ipv6_failure_policy response");
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "android"))]
+    async fn on_set_tunnel_interface_name(
+        &mut self,
+        tx: ResponseTx<(), Error>,
+        interface_name: Option<String>,
+    ) {
+        if let Some(name) = &interface_name {
+            // Interfaces with the current or default name may belong to the tunnel
+            let is_own_interface = self
+                .settings
+                .tunnel_options
+                .generic
+                .interface_name
+                .as_deref()
+                .or(talpid_core::tunnel::DEFAULT_INTERFACE_NAME)
+                == Some(name.as_str());
+            if !is_own_interface && talpid_core::tunnel::interface_exists(name) {
+                Self::oneshot_send(
+                    tx,
+                    Err(Error::TunnelInterfaceNameInUse(name.clone())),
+                    "set_tunnel_interface_name response",
+                );
+                return;
+            }
+        }
+
+        let save_result = self
             .settings
-            .set_use_wireguard_nt(state)
+            .set_tunnel_interface_name(interface_name)
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_tunnel_interface_name response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    info!("Initiating tunnel restart because the tunnel interface name changed");
+                    self.reconnect_tunnel();
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(
+                    tx,
+                    Err(Error::SettingsError(e)),
+                    "set_tunnel_interface_name response",
+                );
+            }
+        }
+    }
+
+    async fn on_set_dns_options(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        dns_options: DnsOptions,
+    ) {
+        let save_result = self.settings.set_dns_options(dns_options.clone()).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_dns_options response");
+                if settings_changed {
+                    let settings = self.settings.to_settings();
+                    Self::warn_about_unreachable_dns_servers(&settings);
+                    #[cfg(not(target_os = "android"))]
+                    self.update_dns_proxy().await;
+                    let resolvers = Self::get_dns_resolvers(&settings.tunnel_options.dns_options);
+                    #[cfg(not(target_os = "android"))]
+                    let upstream_servers =
+                        Self::get_dns_upstream_servers(&settings.tunnel_options.dns_options);
+                    self.event_listener.notify_settings(settings);
+                    self.send_tunnel_command(TunnelCommand::Reconfigure(Reconfiguration {
+                        dns_servers: Some(resolvers),
+                        #[cfg(not(target_os = "android"))]
+                        dns_upstream_servers: Some(upstream_servers),
+                        ..Reconfiguration::default()
+                    }));
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_dns_options response");
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "android"))]
+    fn on_detect_dns_forwarders(&self, tx: oneshot::Sender<Vec<dns_forwarder::DnsForwarder>>) {
+        // The encrypted DNS proxy would otherwise be mistaken for a forwarder
+        let ignored = self.dns_proxy.as_ref().map(|_| DNS_PROXY_IP);
+        tokio::spawn(async move {
+            let _ = tx.send(dns_forwarder::detect(ignored).await);
+        });
+    }
+
+    async fn on_set_connection_statistics(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        enabled: bool,
+    ) {
+        let save_result = self.settings.set_connection_statistics(enabled).await;
+        match save_result {
+            Ok(settings_changed) => {
+                if settings_changed {
+                    if let Err(error) = self.connection_stats.set_enabled(enabled).await {
+                        log::error!("{}", error.display_chain());
+                    }
+                    if let Err(error) = self.connect_times.set_enabled(enabled).await {
+                        log::error!("{}", error.display_chain());
+                    }
+                    if let Some(sampler) = self.throughput_sampler.take() {
+                        sampler.abort();
+                    }
+                    if let TunnelState::Connected { .. } = self.tunnel_state {
+                        self.spawn_throughput_sampler(None);
+                    }
+                }
+                Self::oneshot_send(tx, Ok(()), "set_connection_statistics response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_connection_statistics response");
+            }
+        }
+    }
+
+    async fn on_set_system_log(&mut self, tx: ResponseTx<(), settings::Error>, enabled: bool) {
+        let save_result = self.settings.set_system_log(enabled).await;
+        match save_result {
+            Ok(settings_changed) => {
+                if settings_changed {
+                    self.system_log.set_enabled(enabled);
+                }
+                Self::oneshot_send(tx, Ok(()), "set_system_log response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_system_log response");
+            }
+        }
+    }
+
+    async fn on_set_json_logs(&mut self, tx: ResponseTx<(), settings::Error>, enabled: bool) {
+        let save_result = self.settings.set_json_logs(enabled).await;
+        match save_result {
+            Ok(settings_changed) => {
+                if settings_changed {
+                    logging::set_json_output(enabled);
+                }
+                Self::oneshot_send(tx, Ok(()), "set_json_logs response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_json_logs response");
+            }
+        }
+    }
+
+    async fn on_set_server_mode(&mut self, tx: ResponseTx<(), settings::Error>, enabled: bool) {
+        let old_block_when_disconnected = self.settings.block_when_disconnected;
+        let save_result = self.settings.set_server_mode(enabled).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_server_mode response");
+                if !settings_changed {
+                    return;
+                }
+                logging::set_json_output(self.settings.json_logs);
+                self.system_log.set_enabled(self.settings.system_log);
+                if self.settings.block_when_disconnected != old_block_when_disconnected {
+                    self.send_tunnel_command(TunnelCommand::BlockWhenDisconnected(
+                        self.settings.block_when_disconnected,
+                    ));
+                    #[cfg(not(target_os = "android"))]
+                    self.update_persistent_firewall();
+                }
+                self.update_local_api().await;
+                self.event_listener
+                    .notify_settings(self.settings.to_settings());
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_server_mode response");
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn on_set_dbus_service(&mut self, tx: ResponseTx<(), settings::Error>, enabled: bool) {
+        let save_result = self.settings.set_dbus_service(enabled).await;
+        match save_result {
+            Ok(settings_changed) => {
+                if settings_changed {
+                    self.update_dbus_service();
+                }
+                Self::oneshot_send(tx, Ok(()), "set_dbus_service response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_dbus_service response");
+            }
+        }
+    }
+
+    async fn on_set_lan_proxy_settings(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        lan_proxy: LanProxySettings,
+    ) {
+        let save_result = self.settings.set_lan_proxy_settings(lan_proxy).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_lan_proxy_settings response");
+                if settings_changed {
+                    if lan_proxy.enabled && !self.settings.allow_lan {
+                        log::warn!(
+                            "The LAN proxy only runs while local network sharing is allowed"
+                        );
+                    }
+                    self.update_lan_proxy().await;
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_lan_proxy_settings response");
+            }
+        }
+    }
+
+    async fn on_set_local_api_settings(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        local_api: LocalApiSettings,
+    ) {
+        let save_result = self.settings.set_local_api_settings(local_api).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_local_api_settings response");
+                if settings_changed {
+                    self.update_local_api().await;
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_local_api_settings response");
+            }
+        }
+    }
+
+    async fn on_set_hook_settings(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        hooks: HookSettings,
+    ) {
+        let save_result = self.settings.set_hook_settings(hooks).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_hook_settings response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_hook_settings response");
+            }
+        }
+    }
+
+    async fn on_set_reconnect_backoff(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        reconnect_backoff: ReconnectBackoffSettings,
+    ) {
+        let save_result = self.settings.set_reconnect_backoff(reconnect_backoff).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_reconnect_backoff response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::Reconfigure(Reconfiguration {
+                        reconnect_backoff: Some(Self::reconnect_backoff(
+                            &self.settings.reconnect_backoff,
+                        )),
+                        ..Reconfiguration::default()
+                    }));
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_reconnect_backoff response");
+            }
+        }
+    }
+
+    async fn on_set_exit_ip_check(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        exit_ip_check: ExitIpCheckSettings,
+    ) {
+        let save_result = self.settings.set_exit_ip_check(exit_ip_check).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_exit_ip_check response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    let tunnel_state = self.tunnel_state.clone();
+                    self.update_exit_ip_checker(&tunnel_state);
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_exit_ip_check response");
+            }
+        }
+    }
+
+    async fn on_set_beta_feature(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        feature: BetaFeature,
+        enabled: bool,
+    ) {
+        let save_result = self.settings.set_beta_feature(feature, enabled).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_beta_feature response");
+                if settings_changed {
+                    log::info!(
+                        "Turned {} the {} beta feature",
+                        if enabled { "on" } else { "off" },
+                        feature
+                    );
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    match feature {
+                        BetaFeature::LatencyBasedSelection => self
+                            .relay_selector
+                            .relay_latencies_handle()
+                            .lock()
+                            .set_enabled(enabled),
+                        // Takes effect on the next connection attempt
+                        BetaFeature::QuicObfuscation | BetaFeature::QuantumResistantTunnel => (),
+                    }
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_beta_feature response");
+            }
+        }
+    }
+
+    async fn on_get_local_api_token(&mut self, tx: ResponseTx<String, Error>) {
+        let result = local_api::load_or_create_token(self.writable_dir())
             .await
-            .map_err(Error::SettingsError);
+            .map_err(Error::LocalApiToken);
+        Self::oneshot_send(tx, result, "get_local_api_token response");
+    }
+
+    async fn on_set_allowed_system_services(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        services: AllowedSystemServices,
+    ) {
+        let save_result = self.settings.set_allowed_system_services(services).await;
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, Ok(()), "use_wireguard_nt response");
+                Self::oneshot_send(tx, Ok(()), "set_allowed_system_services response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if self.captive_portal_hosts.is_empty() {
+                        self.resolve_captive_portal_hosts();
+                    }
+                    self.send_tunnel_command(TunnelCommand::SystemServiceExemptions(
+                        self.system_service_exemptions(),
+                    ));
+                }
+            }
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_allowed_system_services response");
+            }
+        }
+    }
+
+    async fn on_set_tunnel_parameter_overrides(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        overrides: TunnelParameterOverrides,
+    ) {
+        let save_result = self
+            .settings
+            .set_tunnel_parameter_overrides(overrides)
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_tunnel_parameter_overrides response");
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
                     if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
-                        info!("Initiating tunnel restart");
+                        info!("Initiating tunnel restart to apply the tunnel parameter overrides");
                         self.reconnect_tunnel();
                     }
                 }
             }
-            Err(error) => {
-                error!(
-                    "{}",
-                    error.display_chain_with_msg("Unable to save settings")
+            Err(e) => {
+                error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_tunnel_parameter_overrides response");
+            }
+        }
+    }
+
+    /// Returns the traffic that the firewall should let through in the blocking states.
+    fn system_service_exemptions(&self) -> SystemServiceExemptions {
+        let services = self.settings.allowed_system_services;
+        SystemServiceExemptions {
+            ntp: services.ntp,
+            captive_portal_hosts: if services.captive_portal_detection {
+                captive_portal::addresses(&self.captive_portal_hosts)
+            } else {
+                vec![]
+            },
+            #[cfg(not(target_os = "android"))]
+            portal_login: self.portal_login.is_some(),
+            #[cfg(target_os = "android")]
+            portal_login: false,
+        }
+    }
+
+    /// Looks up the captive portal detection hosts in the background, if they are allowed while
+    /// blocked. This only succeeds while DNS requests are not blocked.
+    fn resolve_captive_portal_hosts(&self) {
+        if !self.settings.allowed_system_services.captive_portal_detection {
+            return;
+        }
+        let daemon_tx = self.tx.clone();
+        tokio::spawn(async move {
+            let hosts = captive_portal::resolve_hosts().await;
+            if !hosts.is_empty() {
+                let _ = daemon_tx.send(InternalDaemonEvent::CaptivePortalHostsResolved(hosts));
+            }
+        });
+    }
+
+    fn handle_captive_portal_hosts_resolved(&mut self, hosts: captive_portal::ResolvedHosts) {
+        if hosts != self.captive_portal_hosts {
+            log::debug!("Captive portal detection hosts: {:?}", hosts);
+            self.captive_portal_hosts = hosts;
+            if self.settings.allowed_system_services.captive_portal_detection {
+                self.send_tunnel_command(TunnelCommand::SystemServiceExemptions(
+                    self.system_service_exemptions(),
+                ));
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "android"))]
+    fn on_detect_captive_portal(&self, tx: oneshot::Sender<captive_portal::PortalStatus>) {
+        let cached_hosts = self.captive_portal_hosts.clone();
+        tokio::spawn(async move {
+            let status = captive_portal::detect(&cached_hosts).await;
+            log::debug!("Captive portal status: {:?}", status);
+            Self::oneshot_send(tx, status, "detect_captive_portal response");
+        });
+    }
+
+    #[cfg(not(target_os = "android"))]
+    fn on_start_portal_login(&mut self, tx: oneshot::Sender<()>) {
+        if let Some(PortalLogin { job: Some(job) }) = self.portal_login.take() {
+            job.abort();
+        }
+        info!(
+            "Allowing DNS and web traffic for up to {} seconds to log in to a captive portal",
+            captive_portal::PORTAL_LOGIN_TIMEOUT.as_secs()
+        );
+        self.portal_login = Some(PortalLogin { job: None });
+        self.send_tunnel_command(TunnelCommand::SystemServiceExemptions(
+            self.system_service_exemptions(),
+        ));
+        self.send_tunnel_command(TunnelCommand::Block(ErrorStateCause::CaptivePortalLogin));
+        Self::oneshot_send(tx, (), "start_portal_login response");
+    }
+
+    #[cfg(not(target_os = "android"))]
+    fn on_stop_portal_login(&mut self, tx: oneshot::Sender<()>) {
+        if self.portal_login.is_some() {
+            info!("Ending the captive portal login");
+            self.finish_portal_login();
+        }
+        Self::oneshot_send(tx, (), "stop_portal_login response");
+    }
+
+    /// Starts checking whether the captive portal has been logged in to once traffic is blocked
+    /// for the login, and ends the login if the tunnel leaves that state.
+    #[cfg(not(target_os = "android"))]
+    fn update_portal_login(&mut self) {
+        let job_started = match &self.portal_login {
+            Some(login) => login.job.is_some(),
+            None => return,
+        };
+        let blocked_for_login = match &self.tunnel_state {
+            TunnelState::Error(error_state) => {
+                *error_state.cause() == ErrorStateCause::CaptivePortalLogin
+            }
+            _ => false,
+        };
+        if blocked_for_login {
+            if !job_started {
+                let cached_hosts = self.captive_portal_hosts.clone();
+                let daemon_tx = self.tx.clone();
+                let (future, abort_handle) = abortable(Box::pin(async move {
+                    let status = captive_portal::wait_for_login(cached_hosts).await;
+                    let _ = daemon_tx.send(InternalDaemonEvent::PortalLoginFinished(status));
+                }));
+                tokio::spawn(future);
+                self.portal_login = Some(PortalLogin {
+                    job: Some(abort_handle),
+                });
+            }
+        } else if job_started && !matches!(self.tunnel_state, TunnelState::Disconnecting(_)) {
+            // Transitions made before traffic was blocked for the login are ignored
+            self.finish_portal_login();
+        }
+    }
+
+    #[cfg(not(target_os = "android"))]
+    fn handle_portal_login_finished(&mut self, status: captive_portal::PortalStatus) {
+        if self.portal_login.is_none() {
+            return;
+        }
+        match status {
+            captive_portal::PortalStatus::Online => info!("The captive portal login is complete"),
+            _ => warn!(
+                "Timed out waiting for the captive portal login, status: {:?}",
+                status
+            ),
+        }
+        self.finish_portal_login();
+    }
+
+    /// Stops letting through the traffic needed to log in to a captive portal, and enters the
+    /// target state again if traffic is still blocked for the login.
+    #[cfg(not(target_os = "android"))]
+    fn finish_portal_login(&mut self) {
+        if let Some(PortalLogin { job: Some(job) }) = self.portal_login.take() {
+            job.abort();
+        }
+        self.send_tunnel_command(TunnelCommand::SystemServiceExemptions(
+            self.system_service_exemptions(),
+        ));
+        if let TunnelState::Error(ref error_state) = self.tunnel_state {
+            if *error_state.cause() == ErrorStateCause::CaptivePortalLogin {
+                match self.target_state {
+                    TargetState::Secured => self.connect_tunnel(),
+                    TargetState::Unsecured => self.disconnect_tunnel(),
+                }
+            }
+        }
+    }
+
+    fn on_get_tunnel_stats(
+        &mut self,
+        tx: oneshot::Sender<Option<talpid_core::tunnel::TunnelStats>>,
+    ) {
+        Self::oneshot_send(tx, self.tunnel_stats.stats(), "get_tunnel_stats response");
+    }
+
+    fn on_tunnel_stats_listen(
+        &mut self,
+        tx: tokio::sync::mpsc::Sender<talpid_core::tunnel::TunnelStats>,
+    ) {
+        if let Some(stats) = self.tunnel_stats.stats() {
+            if tx.try_send(stats).is_err() {
+                return;
+            }
+        }
+        self.tunnel_stats_listeners.push(tx);
+        self.live_tunnel_stats.store(true, Ordering::Relaxed);
+    }
+
+    fn on_get_connection_statistics(
+        &mut self,
+        tx: oneshot::Sender<(bool, BTreeMap<String, connection_stats::RelayStats>)>,
+    ) {
+        Self::oneshot_send(
+            tx,
+            (
+                self.connection_stats.is_enabled(),
+                self.connection_stats.relays().clone(),
+            ),
+            "get_connection_statistics response",
+        );
+    }
+
+    async fn on_clear_connection_statistics(&mut self, tx: ResponseTx<(), Error>) {
+        let result = match self.connection_stats.clear().await {
+            Ok(()) => self
+                .connect_times
+                .clear()
+                .await
+                .map_err(Error::ConnectTimes),
+            Err(error) => Err(Error::ConnectionStats(error)),
+        };
+        Self::oneshot_send(tx, result, "clear_connection_statistics response");
+    }
+
+    fn on_get_connect_times(
+        &mut self,
+        tx: oneshot::Sender<(bool, Vec<connect_times::VersionConnectTimes>)>,
+    ) {
+        Self::oneshot_send(
+            tx,
+            (
+                self.connect_times.is_enabled(),
+                self.connect_times.by_version(),
+            ),
+            "get_connect_times response",
+        );
+    }
+
+    fn on_get_relay_health(
+        &mut self,
+        tx: oneshot::Sender<Vec<relay_health::RelayHealthStatus>>,
+    ) {
+        Self::oneshot_send(tx, self.relay_selector.relay_health(), "get_relay_health response");
+    }
+
+    fn on_clear_relay_health(&mut self, tx: oneshot::Sender<()>) {
+        self.relay_selector.clear_relay_health();
+        Self::oneshot_send(tx, (), "clear_relay_health response");
+    }
+
+    #[cfg(not(target_os = "android"))]
+    fn on_get_relay_latencies(
+        &mut self,
+        tx: oneshot::Sender<Result<Vec<relay_latency::RelayLatency>, relay_latency::Error>>,
+        hostnames: Vec<String>,
+        measure: bool,
+    ) {
+        let relays = self.relay_selector.relay_addresses(&hostnames);
+        let latencies = self.relay_selector.relay_latencies_handle();
+        if !measure {
+            let cached = latencies
+                .lock()
+                .get(relays.iter().map(|(hostname, _)| hostname.as_str()));
+            Self::oneshot_send(tx, Ok(cached), "get_relay_latencies response");
+            return;
+        }
+        // The echo requests can only be kept out of the tunnel on Linux. Elsewhere, they would
+        // measure the latency through the relay that the tunnel is connected to
+        #[cfg(not(target_os = "linux"))]
+        {
+            if !matches!(
+                self.tunnel_state,
+                TunnelState::Disconnected | TunnelState::Error(_)
+            ) {
+                Self::oneshot_send(
+                    tx,
+                    Err(relay_latency::Error::TunnelUp),
+                    "get_relay_latencies response",
                 );
-                Self::oneshot_send(tx, Err(error), "use_wireguard_nt response");
+                return;
+            }
+        }
+        let tunnel_command_tx = Arc::downgrade(&self.tunnel_command_tx);
+        tokio::spawn(async move {
+            let _ = tx.send(relay_latency::measure(latencies, relays, tunnel_command_tx).await);
+        });
+    }
+
+    fn on_get_firewall_rules(
+        &mut self,
+        tx: oneshot::Sender<io::Result<talpid_core::firewall::FirewallRules>>,
+    ) {
+        // The firewall is owned by the tunnel state machine, which responds directly
+        self.send_tunnel_command(TunnelCommand::GetFirewallRules(tx));
+    }
+
+    fn on_allow_temporarily(
+        &mut self,
+        tx: oneshot::Sender<Result<(), talpid_core::firewall::Error>>,
+        exception: AllowedEndpoint,
+        duration: Duration,
+    ) {
+        // The firewall is owned by the tunnel state machine, which responds directly and removes
+        // the exception once it has expired
+        self.send_tunnel_command(TunnelCommand::AllowTemporarily(
+            vec![exception],
+            duration,
+            tx,
+        ));
+    }
+
+    fn on_remove_orphaned_objects(
+        &mut self,
+        tx: oneshot::Sender<tunnel_state_machine::OrphanedObjects>,
+    ) {
+        // The routes and the firewall are owned by the tunnel state machine, which responds
+        // directly
+        self.send_tunnel_command(TunnelCommand::RemoveOrphanedObjects(tx));
+    }
+
+    fn on_get_connection_attempts(&mut self, tx: oneshot::Sender<Vec<connection_trace::Attempt>>) {
+        Self::oneshot_send(
+            tx,
+            self.connection_trace.attempts(),
+            "get_connection_attempts response",
+        );
+    }
+
+    fn on_get_conflicting_software(&mut self, tx: oneshot::Sender<Vec<ConflictingSoftware>>) {
+        Self::oneshot_send(
+            tx,
+            self.conflicting_software.clone(),
+            "get_conflicting_software response",
+        );
+    }
+
+    fn on_get_missing_capabilities(&mut self, tx: oneshot::Sender<(Vec<MissingCapability>, bool)>) {
+        Self::oneshot_send(
+            tx,
+            (
+                self.missing_capabilities.clone(),
+                self.settings.is_read_only(),
+            ),
+            "get_missing_capabilities response",
+        );
+    }
+
+    async fn on_export_wireguard_config(
+        &mut self,
+        tx: ResponseTx<(String, Option<PathBuf>), Error>,
+        include_private_key: bool,
+    ) {
+        let result = self.export_wireguard_config(include_private_key).await;
+        Self::oneshot_send(tx, result, "export_wireguard_config response");
+    }
+
+    async fn export_wireguard_config(
+        &self,
+        include_private_key: bool,
+    ) -> Result<(String, Option<PathBuf>), Error> {
+        let params = match (&self.tunnel_state, &self.last_generated_tunnel_parameters) {
+            (
+                TunnelState::Connected { endpoint, .. },
+                Some(TunnelParameters::Wireguard(params)),
+            ) if endpoint.tunnel_type == TunnelType::Wireguard => params,
+            _ => return Err(Error::NoWireguardTunnel),
+        };
+        let config = mullvad_types::wireguard_config::export(params, false);
+        if !include_private_key {
+            return Ok((config, None));
+        }
+
+        let path = self.writable_dir().join(WIREGUARD_CONFIG_EXPORT_FILE);
+        let full_config = mullvad_types::wireguard_config::export(params, true);
+        // Remove any previous export, so that it is not reused with looser permissions
+        let _ = fs::remove_file(&path).await;
+        let mut options = fs::OpenOptions::new();
+        #[cfg(unix)]
+        {
+            options.mode(0o600);
+        }
+        let mut file = options
+            .create_new(true)
+            .write(true)
+            .open(&path)
+            .await
+            .map_err(Error::ExportWireguardConfig)?;
+        file.write_all(full_config.as_bytes())
+            .await
+            .map_err(Error::ExportWireguardConfig)?;
+        Ok((config, Some(path)))
+    }
+
+    fn on_export_settings_profile(&mut self, tx: ResponseTx<String, Error>) {
+        let result = settings_profile::export(&self.settings).map_err(Error::SettingsProfile);
+        Self::oneshot_send(tx, result, "export_settings_profile response");
+    }
+
+    async fn on_import_settings_profile(
+        &mut self,
+        tx: ResponseTx<settings_profile::ImportReport, Error>,
+        profile: String,
+        resolution: settings_profile::ConflictResolution,
+    ) {
+        let result = self.import_settings_profile(&profile, resolution).await;
+        Self::oneshot_send(tx, result, "import_settings_profile response");
+    }
+
+    /// Applies the settings of a profile one at a time, with the same handlers as when they are
+    /// set individually, so that each takes effect right away. Settings that were applied before
+    /// a failing one are kept.
+    async fn import_settings_profile(
+        &mut self,
+        profile: &str,
+        resolution: settings_profile::ConflictResolution,
+    ) -> Result<settings_profile::ImportReport, Error> {
+        let profile = settings_profile::parse(profile).map_err(Error::SettingsProfile)?;
+        let (profile, conflicts) =
+            settings_profile::resolve_conflicts(profile, &self.settings, resolution);
+        let profile = match profile {
+            Some(profile) => profile,
+            None => {
+                log::info!(
+                    "Not importing the settings profile, since it conflicts with: {}",
+                    conflicts.join(", ")
+                );
+                return Ok(settings_profile::ImportReport {
+                    applied: false,
+                    changed_settings: vec![],
+                    conflicts,
+                });
             }
+        };
+
+        let old_settings = self.settings.to_settings();
+        if let Some(relay_settings) = profile.relay_settings {
+            let (tx, rx) = oneshot::channel();
+            let update = settings_profile::relay_settings_update(relay_settings);
+            self.on_update_relay_settings(tx, update).await;
+            Self::settings_profile_result(rx).await?;
+        }
+        if let Some(bridge_settings) = profile.bridge_settings {
+            let (tx, rx) = oneshot::channel();
+            self.on_set_bridge_settings(tx, bridge_settings).await;
+            Self::settings_profile_result(rx).await?;
+        }
+        if let Some(bridge_state) = profile.bridge_state {
+            let (tx, rx) = oneshot::channel();
+            self.on_set_bridge_state(tx, bridge_state).await;
+            Self::settings_profile_result(rx).await?;
+        }
+        if let Some(obfuscation_settings) = profile.obfuscation_settings {
+            let (tx, rx) = oneshot::channel();
+            self.on_set_obfuscation_settings(tx, obfuscation_settings)
+                .await;
+            Self::settings_profile_result(rx).await?;
         }
+        if let Some(dns_options) = profile.dns_options {
+            let (tx, rx) = oneshot::channel();
+            self.on_set_dns_options(tx, dns_options).await;
+            Self::settings_profile_result(rx).await?;
+        }
+        if let Some(allow_lan) = profile.allow_lan {
+            let (tx, rx) = oneshot::channel();
+            self.on_set_allow_lan(tx, allow_lan).await;
+            Self::settings_profile_result(rx).await?;
+        }
+        if let Some(block_when_disconnected) = profile.block_when_disconnected {
+            let (tx, rx) = oneshot::channel();
+            self.on_set_block_when_disconnected(tx, block_when_disconnected)
+                .await;
+            Self::settings_profile_result(rx).await?;
+        }
+        if let Some(persistent_lockdown) = profile.persistent_lockdown {
+            let (tx, rx) = oneshot::channel();
+            self.on_set_persistent_lockdown(tx, persistent_lockdown)
+                .await;
+            Self::settings_profile_result(rx).await?;
+        }
+        if let Some(allowed_system_services) = profile.allowed_system_services {
+            let (tx, rx) = oneshot::channel();
+            self.on_set_allowed_system_services(tx, allowed_system_services)
+                .await;
+            Self::settings_profile_result(rx).await?;
+        }
+        if let Some(firewall_exceptions) = profile.firewall_exceptions {
+            let (tx, rx) = oneshot::channel();
+            let save_result = self
+                .settings
+                .set_firewall_exceptions(firewall_exceptions)
+                .await;
+            self.on_firewall_exceptions_changed(
+                tx,
+                save_result,
+                "set_firewall_exceptions response",
+            );
+            Self::settings_profile_result(rx).await?;
+        }
+
+        let changed_settings = Settings::changed_keys(&old_settings, &self.settings);
+        log::info!(
+            "Imported a settings profile, which changed: {}",
+            changed_settings.join(", ")
+        );
+        Ok(settings_profile::ImportReport {
+            applied: true,
+            changed_settings,
+            conflicts,
+        })
     }
 
-    async fn on_update_relay_settings(
+    async fn settings_profile_result(
+        rx: oneshot::Receiver<Result<(), settings::Error>>,
+    ) -> Result<(), Error> {
+        rx.await
+            .map_err(|_| Error::SettingsProfileInterrupted)?
+            .map_err(Error::SettingsError)
+    }
+
+    async fn on_set_log_rotation_settings(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
-        update: RelaySettingsUpdate,
+        log_rotation: LogRotationSettings,
     ) {
-        let save_result = self.settings.update_relay_settings(update).await;
-        match save_result {
-            Ok(settings_changed) => {
-                Self::oneshot_send(tx, Ok(()), "update_relay_settings response");
-                if settings_changed {
-                    self.event_listener
-                        .notify_settings(self.settings.to_settings());
-                    info!("Initiating tunnel restart because the relay settings changed");
-                    self.reconnect_tunnel();
-                }
-            }
-            Err(e) => {
-                error!("{}", e.display_chain_with_msg("Unable to save settings"));
-                Self::oneshot_send(tx, Err(e), "update_relay_settings response");
-            }
-        }
-    }
-
-    async fn on_set_allow_lan(&mut self, tx: ResponseTx<(), settings::Error>, allow_lan: bool) {
-        let save_result = self.settings.set_allow_lan(allow_lan).await;
+        let limits = logging::rotation_limits(&log_rotation);
+        let save_result = self.settings.set_log_rotation_settings(log_rotation).await;
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, Ok(()), "set_allow_lan response");
+                Self::oneshot_send(tx, Ok(()), "set_log_rotation_settings response");
                 if settings_changed {
+                    talpid_core::logging::set_rotation_limits(limits);
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    self.send_tunnel_command(TunnelCommand::AllowLan(allow_lan));
                 }
             }
             Err(e) => {
                 error!("{}", e.display_chain_with_msg("Unable to save settings"));
-                Self::oneshot_send(tx, Err(e), "set_allow_lan response");
+                Self::oneshot_send(tx, Err(e), "set_log_rotation_settings response");
             }
         }
     }
 
-    async fn on_set_show_beta_releases(
+    async fn on_set_log_privacy(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
-        enabled: bool,
+        log_privacy: LogPrivacyLevel,
     ) {
-        let save_result = self.settings.set_show_beta_releases(enabled).await;
+        let save_result = self.settings.set_log_privacy(log_privacy).await;
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, Ok(()), "set_show_beta_releases response");
+                Self::oneshot_send(tx, Ok(()), "set_log_privacy response");
                 if settings_changed {
+                    logging::set_log_privacy(log_privacy);
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    let mut handle = self.version_updater_handle.clone();
-                    handle.set_show_beta_releases(enabled).await;
                 }
             }
             Err(e) => {
                 error!("{}", e.display_chain_with_msg("Unable to save settings"));
-                Self::oneshot_send(tx, Err(e), "set_show_beta_releases response");
+                Self::oneshot_send(tx, Err(e), "set_log_privacy response");
             }
         }
     }
 
-    async fn on_set_block_when_disconnected(
+    async fn on_set_wireguard_mtu(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
-        block_when_disconnected: bool,
+        mtu: Option<u16>,
     ) {
-        let save_result = self
-            .settings
-            .set_block_when_disconnected(block_when_disconnected)
-            .await;
+        let save_result = self.settings.set_wireguard_mtu(mtu).await;
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, Ok(()), "set_block_when_disconnected response");
+                Self::oneshot_send(tx, Ok(()), "set_wireguard_mtu response");
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    self.send_tunnel_command(TunnelCommand::BlockWhenDisconnected(
-                        block_when_disconnected,
-                    ));
+                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
+                        info!(
+                            "Initiating tunnel restart because the WireGuard MTU setting changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
                 }
             }
             Err(e) => {
                 error!("{}", e.display_chain_with_msg("Unable to save settings"));
-                Self::oneshot_send(tx, Err(e), "set_block_when_disconnected response");
+                Self::oneshot_send(tx, Err(e), "set_wireguard_mtu response");
             }
         }
     }
 
-    async fn on_set_auto_connect(
+    async fn on_set_wireguard_icmp_check_interval(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
-        auto_connect: bool,
+        interval: Option<u16>,
     ) {
-        let save_result = self.settings.set_auto_connect(auto_connect).await;
+        let save_result = self
+            .settings
+            .set_wireguard_icmp_check_interval(interval)
+            .await;
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, Ok(()), "set auto-connect response");
+                Self::oneshot_send(tx, Ok(()), "set_wireguard_icmp_check_interval response");
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
+                        info!(
+                            "Initiating tunnel restart because the WireGuard connectivity check \
+                             changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
                 }
             }
             Err(e) => {
                 error!("{}", e.display_chain_with_msg("Unable to save settings"));
-                Self::oneshot_send(tx, Err(e), "set auto-connect response");
+                Self::oneshot_send(tx, Err(e), "set_wireguard_icmp_check_interval response");
             }
         }
     }
 
-    async fn on_set_openvpn_mssfix(
+    async fn on_set_wireguard_packet_loss_threshold(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
-        mssfix_arg: Option<u16>,
+        threshold: Option<u8>,
     ) {
-        let save_result = self.settings.set_openvpn_mssfix(mssfix_arg).await;
+        let save_result = self
+            .settings
+            .set_wireguard_packet_loss_threshold(threshold)
+            .await;
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, Ok(()), "set_openvpn_mssfix response");
+                Self::oneshot_send(tx, Ok(()), "set_wireguard_packet_loss_threshold response");
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    if let Some(TunnelType::OpenVpn) = self.get_connected_tunnel_type() {
+                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
                         info!(
-                            "Initiating tunnel restart because the OpenVPN mssfix setting changed"
+                            "Initiating tunnel restart because the WireGuard packet loss \
+                             threshold changed"
                         );
                         self.reconnect_tunnel();
                     }
@@ -2108,153 +7388,109 @@ where
             }
             Err(e) => {
                 error!("{}", e.display_chain_with_msg("Unable to save settings"));
-                Self::oneshot_send(tx, Err(e), "set_openvpn_mssfix response");
-            }
-        }
-    }
-
-    async fn on_set_bridge_settings(
-        &mut self,
-        tx: ResponseTx<(), settings::Error>,
-        new_settings: BridgeSettings,
-    ) {
-        match self.settings.set_bridge_settings(new_settings).await {
-            Ok(settings_changes) => {
-                if settings_changes {
-                    self.event_listener
-                        .notify_settings(self.settings.to_settings());
-                    self.reconnect_tunnel();
-                };
-                Self::oneshot_send(tx, Ok(()), "set_bridge_settings");
-            }
-
-            Err(e) => {
-                log::error!(
-                    "{}",
-                    e.display_chain_with_msg("Failed to set new bridge settings")
-                );
-                Self::oneshot_send(tx, Err(e), "set_bridge_settings");
+                Self::oneshot_send(tx, Err(e), "set_wireguard_packet_loss_threshold response");
             }
         }
     }
 
-    async fn on_set_bridge_state(
+    async fn on_set_wireguard_rotation_interval(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
-        bridge_state: BridgeState,
+        interval: Option<RotationInterval>,
     ) {
-        let result = match self.settings.set_bridge_state(bridge_state).await {
-            Ok(settings_changed) => {
-                if settings_changed {
-                    self.event_listener
-                        .notify_settings(self.settings.to_settings());
-                    log::info!("Initiating tunnel restart because bridge state changed");
-                    self.reconnect_tunnel();
-                }
-                Ok(())
-            }
-            Err(error) => {
-                log::error!(
-                    "{}",
-                    error.display_chain_with_msg("Failed to set new bridge state")
-                );
-                Err(error)
-            }
-        };
-        Self::oneshot_send(tx, result, "on_set_bridge_state response");
-    }
-
-
-    async fn on_set_enable_ipv6(&mut self, tx: ResponseTx<(), settings::Error>, enable_ipv6: bool) {
-        let save_result = self.settings.set_enable_ipv6(enable_ipv6).await;
+        let save_result = self
+            .settings
+            .set_wireguard_rotation_interval(interval)
+            .await;
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, Ok(()), "set_enable_ipv6 response");
+                Self::oneshot_send(tx, Ok(()), "set_wireguard_rotation_interval response");
                 if settings_changed {
+                    self.ensure_key_rotation().await;
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    info!("Initiating tunnel restart because the enable IPv6 setting changed");
-                    self.reconnect_tunnel();
                 }
             }
             Err(e) => {
                 error!("{}", e.display_chain_with_msg("Unable to save settings"));
-                Self::oneshot_send(tx, Err(e), "set_enable_ipv6 response");
+                Self::oneshot_send(tx, Err(e), "set_wireguard_rotation_interval response");
             }
         }
     }
 
-    async fn on_set_dns_options(
+    async fn on_set_quantum_resistant_tunnel(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
-        dns_options: DnsOptions,
+        state: QuantumResistantState,
     ) {
-        let save_result = self.settings.set_dns_options(dns_options.clone()).await;
+        let save_result = self.settings.set_quantum_resistant_tunnel(state).await;
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, Ok(()), "set_dns_options response");
+                Self::oneshot_send(tx, Ok(()), "set_quantum_resistant_tunnel response");
                 if settings_changed {
-                    let settings = self.settings.to_settings();
-                    let resolvers = Self::get_dns_resolvers(&settings.tunnel_options.dns_options);
-                    self.event_listener.notify_settings(settings);
-                    self.send_tunnel_command(TunnelCommand::Dns(resolvers));
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
+                        info!(
+                            "Initiating tunnel restart because the quantum-resistant tunnel \
+                             setting changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
                 }
             }
             Err(e) => {
                 error!("{}", e.display_chain_with_msg("Unable to save settings"));
-                Self::oneshot_send(tx, Err(e), "set_dns_options response");
+                Self::oneshot_send(tx, Err(e), "set_quantum_resistant_tunnel response");
             }
         }
     }
 
-    async fn on_set_wireguard_mtu(
+    async fn on_set_obfuscation_settings(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
-        mtu: Option<u16>,
+        obfuscation_settings: ObfuscationSettings,
     ) {
-        let save_result = self.settings.set_wireguard_mtu(mtu).await;
+        let save_result = self
+            .settings
+            .set_obfuscation_settings(obfuscation_settings)
+            .await;
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, Ok(()), "set_wireguard_mtu response");
+                Self::oneshot_send(tx, Ok(()), "set_obfuscation_settings response");
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
-                        info!(
-                            "Initiating tunnel restart because the WireGuard MTU setting changed"
-                        );
-                        self.reconnect_tunnel();
-                    }
                 }
             }
             Err(e) => {
                 error!("{}", e.display_chain_with_msg("Unable to save settings"));
-                Self::oneshot_send(tx, Err(e), "set_wireguard_mtu response");
+                Self::oneshot_send(tx, Err(e), "set_obfuscation_settings response");
             }
         }
     }
 
-    async fn on_set_wireguard_rotation_interval(
+    async fn on_set_connection_profiles(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
-        interval: Option<RotationInterval>,
+        enabled: bool,
     ) {
-        let save_result = self
-            .settings
-            .set_wireguard_rotation_interval(interval)
-            .await;
+        let save_result = self.settings.set_connection_profiles(enabled).await;
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, Ok(()), "set_wireguard_rotation_interval response");
+                Self::oneshot_send(tx, Ok(()), "set_connection_profiles response");
                 if settings_changed {
-                    self.ensure_key_rotation().await;
+                    self.relay_selector
+                        .connection_profiles_handle()
+                        .lock()
+                        .set_enabled(enabled);
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
                 }
             }
             Err(e) => {
                 error!("{}", e.display_chain_with_msg("Unable to save settings"));
-                Self::oneshot_send(tx, Err(e), "set_wireguard_rotation_interval response");
+                Self::oneshot_send(tx, Err(e), "set_connection_profiles response");
             }
         }
     }
@@ -2368,11 +7604,18 @@ where
 
         let verification_rpc = self
             .wireguard_key_manager
-            .verify_wireguard_key(account, public_key);
+            .verify_wireguard_key(account.clone(), public_key.clone());
+        let daemon_tx = self.tx.clone();
 
         tokio::spawn(async move {
             let result = match verification_rpc.await {
-                Ok(is_valid) => Ok(is_valid),
+                Ok(is_valid) => {
+                    if !is_valid {
+                        let _ =
+                            daemon_tx.send(InternalDaemonEvent::DeviceRevoked(account, public_key));
+                    }
+                    Ok(is_valid)
+                }
                 Err(wireguard::Error::RestError(error)) => Err(Error::RestError(error)),
                 Err(wireguard::Error::ApiCheckError(error)) => Err(Error::ApiCheckError(error)),
                 Err(wireguard::Error::TooManyKeys) => return,
@@ -2385,6 +7628,16 @@ where
         Self::oneshot_send(tx, self.settings.to_settings(), "get_settings response");
     }
 
+    /// The directory that files created by the daemon, such as the local API token, are kept in.
+    /// This is the cache directory while the settings directory is read-only.
+    fn writable_dir(&self) -> &Path {
+        if self.settings.is_read_only() {
+            &self.cache_dir
+        } else {
+            &self.settings_dir
+        }
+    }
+
     fn oneshot_send<T>(tx: oneshot::Sender<T>, t: T, msg: &'static str) {
         if tx.send(t).is_err() {
             warn!("Unable to send {} to the daemon command sender", msg);
@@ -2394,6 +7647,78 @@ where
     fn trigger_shutdown_event(&mut self) {
         self.state.shutdown(&self.tunnel_state);
         self.disconnect_tunnel();
+        self.shutdown_token.cancel();
+    }
+
+    #[cfg(windows)]
+    async fn on_run_migration(
+        &mut self,
+        tx: oneshot::Sender<migration::MigrationReport>,
+        requested_by: Option<String>,
+    ) {
+        let report = self.run_migration(requested_by).await;
+        Self::oneshot_send(tx, report, "run_migration response");
+    }
+
+    /// Runs every migration step in order, stopping at the first one that fails.
+    #[cfg(windows)]
+    async fn run_migration(&mut self, requested_by: Option<String>) -> migration::MigrationReport {
+        info!(
+            "Running the migration requested by installer {}",
+            requested_by.as_deref().unwrap_or("(unknown)")
+        );
+        let mut report = migration::MigrationReport::new(requested_by);
+        for step in migration::MigrationStep::ALL.iter() {
+            let result = self.run_migration_step(*step).await;
+            if let Err(error) = &result {
+                error!("Migration step \"{}\" failed: {}", step, error);
+            }
+            if !report.record(*step, result) {
+                break;
+            }
+        }
+        if report.succeeded() {
+            info!("Migration completed");
+        }
+        if let Err(error) = migration::finish(&self.settings_dir, &report).await {
+            error!(
+                "{}",
+                error.display_chain_with_msg("Failed to save the migration report")
+            );
+        }
+        report
+    }
+
+    #[cfg(windows)]
+    async fn run_migration_step(&mut self, step: migration::MigrationStep) -> Result<(), String> {
+        const NO_RESPONSE: &str = "The tunnel state machine did not respond";
+        match step {
+            migration::MigrationStep::FirewallObjects => {
+                let (tx, rx) = oneshot::channel();
+                self.send_tunnel_command(TunnelCommand::RepairFirewall(tx));
+                match rx.await {
+                    Ok(result) => result.map_err(|error| error.display_chain()),
+                    Err(_) => Err(NO_RESPONSE.to_owned()),
+                }
+            }
+            migration::MigrationStep::SplitTunnelDriver => {
+                let settings = self.settings.to_settings();
+                let paths = if settings.split_tunnel.enable_exclusions {
+                    settings.split_tunnel.apps
+                } else {
+                    HashSet::new()
+                };
+                match self.apply_excluded_apps(&paths).await {
+                    Some(result) => result.map_err(|error| error.display_chain()),
+                    None => Err(NO_RESPONSE.to_owned()),
+                }
+            }
+            migration::MigrationStep::Settings => self
+                .settings
+                .rewrite()
+                .await
+                .map_err(|error| error.display_chain()),
+        }
     }
 
     fn on_prepare_restart(&mut self) {
@@ -2438,16 +7763,22 @@ where
     async fn forward_offline_state(
         runtime: &tokio::runtime::Handle,
         api_availability: ApiAvailabilityHandle,
-        mut offline_state_rx: mpsc::UnboundedReceiver<bool>,
+        relay_connectivity: Arc<parking_lot::Mutex<Connectivity>>,
+        mut offline_state_rx: mpsc::UnboundedReceiver<Connectivity>,
+        daemon_tx: DaemonEventSender,
     ) {
         let initial_state = offline_state_rx
             .next()
             .await
             .expect("missing initial offline state");
-        api_availability.set_offline(initial_state);
+        api_availability.set_offline(initial_state.is_offline());
+        *relay_connectivity.lock() = initial_state;
         runtime.spawn(async move {
-            while let Some(is_offline) = offline_state_rx.next().await {
-                api_availability.set_offline(is_offline);
+            while let Some(connectivity) = offline_state_rx.next().await {
+                debug!("Connectivity: {}", connectivity);
+                api_availability.set_offline(connectivity.is_offline());
+                *relay_connectivity.lock() = connectivity;
+                let _ = daemon_tx.send(InternalDaemonEvent::ConnectivityChanged);
             }
         });
     }
@@ -2456,6 +7787,10 @@ where
     /// progress towards that state.
     /// Returns a bool representing whether or not a state change was initiated.
     async fn set_target_state(&mut self, new_state: TargetState) -> bool {
+        if new_state == TargetState::Secured && self.device_state == DeviceState::Revoked {
+            warn!("Ignoring request to connect since this device has been revoked");
+            return false;
+        }
         if new_state != self.target_state || self.tunnel_state.is_in_error_state() {
             debug!("Target state {:?} => {:?}", self.target_state, new_state);
 
@@ -2538,9 +7873,15 @@ where
     }
 
     fn send_tunnel_command(&mut self, command: TunnelCommand) {
-        self.tunnel_command_tx
-            .unbounded_send(command)
-            .expect("Tunnel state machine has stopped");
+        match self.tunnel_command_tx.try_send(command) {
+            Ok(()) => (),
+            Err(tokio::sync::mpsc::error::TrySendError::Full(_)) => {
+                log::error!("Tunnel state machine is not handling commands, dropping command");
+            }
+            Err(tokio::sync::mpsc::error::TrySendError::Closed(_)) => {
+                panic!("Tunnel state machine has stopped");
+            }
+        }
     }
 
     #[cfg(not(target_os = "android"))]
@@ -2601,6 +7942,13 @@ where
             tx: self.tx.clone(),
         }
     }
+
+    #[cfg(windows)]
+    pub fn system_event_handle(&self) -> DaemonSystemEventHandle {
+        DaemonSystemEventHandle {
+            tx: self.tx.clone(),
+        }
+    }
 }
 
 pub struct DaemonShutdownHandle {
@@ -2613,6 +7961,19 @@ impl DaemonShutdownHandle {
     }
 }
 
+/// Forwards power and session events to the daemon.
+#[cfg(windows)]
+pub struct DaemonSystemEventHandle {
+    tx: DaemonEventSender,
+}
+
+#[cfg(windows)]
+impl DaemonSystemEventHandle {
+    pub fn send(&self, event: SystemEvent) {
+        let _ = self.tx.send(InternalDaemonEvent::SystemEvent(event));
+    }
+}
+
 struct MullvadTunnelParametersGenerator {
     tx: DaemonEventSender,
 }
@@ -2644,3 +8005,26 @@ impl TunnelParametersGenerator for MullvadTunnelParametersGenerator {
         }
     }
 }
+
+/// Returns the ID of the device that uses `pubkey`, if any.
+/// Returns the forwarded ports that belong to the device with the given key.
+fn filter_port_forwards(
+    port_forwards: Vec<PortForward>,
+    pubkey: Option<&talpid_types::net::wireguard::PublicKey>,
+) -> Vec<PortForward> {
+    port_forwards
+        .into_iter()
+        .filter(|port_forward| Some(&port_forward.pubkey) == pubkey)
+        .collect()
+}
+
+fn find_device_by_pubkey(
+    devices: &[Device],
+    pubkey: Option<&talpid_types::net::wireguard::PublicKey>,
+) -> Option<DeviceId> {
+    let pubkey = pubkey?;
+    devices
+        .iter()
+        .find(|device| device.pubkey == *pubkey)
+        .map(|device| device.id.clone())
+}
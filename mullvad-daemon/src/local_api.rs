@@ -0,0 +1,453 @@
+//! A read-only HTTP/JSON API on localhost, for status bars, stream decks, home automation and
+//! other integrations that cannot speak the management interface protocol. Only the tunnel state,
+//! the relay in use, the remaining account time and a few counters for monitoring are exposed.
+//!
+//! Every request must carry the token in the token file in an `Authorization: Bearer` header.
+//! The token is generated the first time the API is started. Since browsers do not send that
+//! header to other origins without a CORS preflight, which is never answered, web pages cannot
+//! read the API. Requests are rate limited, so that a misbehaving client cannot keep the daemon
+//! busy.
+
+use crate::account_expiry::ExpiryMonitorHandle;
+use chrono::{DateTime, Utc};
+use futures::future::{abortable, AbortHandle};
+use mullvad_types::{relay_list::Relay, states::TunnelState};
+use parking_lot::Mutex;
+use rand::Rng;
+use serde::Serialize;
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr},
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use talpid_types::{tunnel::ActionAfterDisconnect, ErrorExt};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::Semaphore,
+};
+
+/// Name of the file in the settings directory that the token is stored in.
+pub const TOKEN_FILE: &str = "local-api-token";
+
+/// Maximum number of requests that are handled at the same time.
+const MAX_CONNECTIONS: usize = 16;
+
+/// Maximum size of the request header.
+const MAX_HEADER_SIZE: usize = 8 * 1024;
+
+/// How long a client may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Number of requests that may be made in a burst.
+const RATE_LIMIT_BURST: f64 = 20.0;
+
+/// Number of requests per second that may be made once a burst is used up.
+const RATE_LIMIT_PER_SECOND: f64 = 5.0;
+
+/// What the API reports about the tunnel. It is updated by the daemon as the tunnel state
+/// changes, so requests never have to wait for the daemon.
+#[derive(Debug, Clone, Serialize)]
+pub struct TunnelStatus {
+    state: &'static str,
+    relay: Option<RelayStatus>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct RelayStatus {
+    hostname: String,
+    country: Option<String>,
+    city: Option<String>,
+}
+
+impl TunnelStatus {
+    /// `relay` is the relay that was selected for the current connection attempt, if any.
+    pub fn new(tunnel_state: &TunnelState, relay: Option<&Relay>) -> Self {
+        let relay = match tunnel_state {
+            TunnelState::Connecting { .. } | TunnelState::Connected { .. } => {
+                relay.map(|relay| RelayStatus {
+                    hostname: relay.hostname.clone(),
+                    country: relay
+                        .location
+                        .as_ref()
+                        .map(|location| location.country.clone()),
+                    city: relay
+                        .location
+                        .as_ref()
+                        .map(|location| location.city.clone()),
+                })
+            }
+            _ => None,
+        };
+        TunnelStatus {
+            state: state_name(tunnel_state),
+            relay,
+        }
+    }
+}
+
+impl Default for TunnelStatus {
+    fn default() -> Self {
+        Self::new(&TunnelState::Disconnected, None)
+    }
+}
+
+/// Counters for monitoring unattended hosts, kept since the daemon started.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+struct Metrics {
+    /// Number of times the tunnel has connected.
+    connections: u64,
+    /// Number of times the tunnel has entered the error state.
+    errors: u64,
+    /// When the tunnel connected, if it is connected.
+    connected_since: Option<DateTime<Utc>>,
+}
+
+impl Metrics {
+    fn record_transition(&mut self, old_state: &str, new_state: &str, now: DateTime<Utc>) {
+        if old_state == new_state {
+            return;
+        }
+        match new_state {
+            "connected" => {
+                self.connections += 1;
+                self.connected_since = Some(now);
+            }
+            "blocked" | "error" => {
+                self.errors += 1;
+                self.connected_since = None;
+            }
+            _ => self.connected_since = None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Status {
+    tunnel: TunnelStatus,
+    metrics: Metrics,
+}
+
+/// Shared handle to the status that the API reports.
+#[derive(Clone, Default)]
+pub struct StatusHandle(Arc<Mutex<Status>>);
+
+impl StatusHandle {
+    pub fn update(&self, tunnel_state: &TunnelState, relay: Option<&Relay>) {
+        let mut status = self.0.lock();
+        let tunnel = TunnelStatus::new(tunnel_state, relay);
+        let old_state = status.tunnel.state;
+        status
+            .metrics
+            .record_transition(old_state, tunnel.state, Utc::now());
+        status.tunnel = tunnel;
+    }
+
+    fn get(&self) -> TunnelStatus {
+        self.0.lock().tunnel.clone()
+    }
+
+    fn metrics(&self) -> Metrics {
+        self.0.lock().metrics.clone()
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ExpiryStatus {
+    expiry: Option<DateTime<Utc>>,
+    days_left: Option<i64>,
+}
+
+impl ExpiryStatus {
+    fn new(expiry: Option<DateTime<Utc>>, now: DateTime<Utc>) -> Self {
+        ExpiryStatus {
+            expiry,
+            days_left: expiry.map(|expiry| (expiry - now).num_days().max(0)),
+        }
+    }
+}
+
+/// Returns the name of the state, as exposed by the API.
+fn state_name(tunnel_state: &TunnelState) -> &'static str {
+    match tunnel_state {
+        TunnelState::Disconnected => "disconnected",
+        TunnelState::Connecting { .. } => "connecting",
+        TunnelState::Connected { .. } => "connected",
+        TunnelState::Disconnecting(ActionAfterDisconnect::Reconnect) => "reconnecting",
+        TunnelState::Disconnecting(_) => "disconnecting",
+        TunnelState::Error(error_state) if error_state.is_blocking() => "blocked",
+        TunnelState::Error(_) => "error",
+    }
+}
+
+/// Reads the token from the token file in `dir`, or generates a new one and writes it to the file
+/// if there is none. The file is only readable by the owner.
+pub async fn load_or_create_token(dir: &Path) -> io::Result<String> {
+    let path = dir.join(TOKEN_FILE);
+    match fs::read_to_string(&path).await {
+        Ok(token) if !token.trim().is_empty() => return Ok(token.trim().to_owned()),
+        Ok(_) => fs::remove_file(&path).await?,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => (),
+        Err(error) => return Err(error),
+    }
+
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    let token: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let mut options = fs::OpenOptions::new();
+    #[cfg(unix)]
+    {
+        options.mode(0o600);
+    }
+    let mut file = options.create_new(true).write(true).open(&path).await?;
+    file.write_all(token.as_bytes()).await?;
+    log::info!("Generated a new token for the local API");
+    Ok(token)
+}
+
+/// Limits requests to a burst of `RATE_LIMIT_BURST`, refilled at `RATE_LIMIT_PER_SECOND`.
+struct RateLimiter {
+    available: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    fn new(now: Instant) -> Self {
+        RateLimiter {
+            available: RATE_LIMIT_BURST,
+            last_refill: now,
+        }
+    }
+
+    fn try_acquire(&mut self, now: Instant) -> bool {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.available = (self.available + elapsed * RATE_LIMIT_PER_SECOND).min(RATE_LIMIT_BURST);
+        self.last_refill = now;
+        if self.available >= 1.0 {
+            self.available -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+struct Context {
+    token: String,
+    status: StatusHandle,
+    expiry_monitor: ExpiryMonitorHandle,
+    rate_limiter: Mutex<RateLimiter>,
+}
+
+/// A running API server. It stops listening when this is dropped.
+pub struct LocalApi {
+    port: u16,
+    abort_handle: AbortHandle,
+}
+
+impl LocalApi {
+    /// Starts listening on `port` on the IPv4 loopback interface.
+    pub(crate) async fn start(
+        port: u16,
+        token: String,
+        status: StatusHandle,
+        expiry_monitor: ExpiryMonitorHandle,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port)).await?;
+        let context = Arc::new(Context {
+            token,
+            status,
+            expiry_monitor,
+            rate_limiter: Mutex::new(RateLimiter::new(Instant::now())),
+        });
+
+        let (accept_future, abort_handle) = abortable(Self::accept_loop(listener, context));
+        tokio::spawn(accept_future);
+
+        log::info!("Started local API on port {}", port);
+
+        Ok(Self { port, abort_handle })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    async fn accept_loop(listener: TcpListener, context: Arc<Context>) {
+        let connection_limit = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+        loop {
+            let (client, client_addr) = match listener.accept().await {
+                Ok(result) => result,
+                Err(error) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Local API failed to accept connection")
+                    );
+                    continue;
+                }
+            };
+            let permit = match connection_limit.clone().try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    log::debug!("Local API: Too many connections. Rejecting {}", client_addr);
+                    continue;
+                }
+            };
+
+            let context = context.clone();
+            tokio::spawn(async move {
+                if let Err(error) = Self::handle_client(client, &context).await {
+                    log::debug!(
+                        "{}",
+                        error.display_chain_with_msg("Local API: Failed to handle request")
+                    );
+                }
+                drop(permit);
+            });
+        }
+    }
+
+    async fn handle_client(mut client: TcpStream, context: &Context) -> io::Result<()> {
+        let header = tokio::time::timeout(REQUEST_TIMEOUT, read_header(&mut client))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Timed out reading request"))??;
+        let (status, body) = Self::respond(&String::from_utf8_lossy(&header), context).await;
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+             Cache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        client.write_all(response.as_bytes()).await?;
+        client.shutdown().await
+    }
+
+    /// Returns the status line and the JSON body of the response to a request.
+    async fn respond(header: &str, context: &Context) -> (&'static str, String) {
+        let mut lines = header.lines();
+        let mut request_line = lines.next().unwrap_or("").split_whitespace();
+        let (method, path) = (request_line.next(), request_line.next());
+
+        let authorized = lines
+            .filter_map(|line| {
+                let mut parts = line.splitn(2, ':');
+                Some((parts.next()?, parts.next()?))
+            })
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("authorization"))
+            .and_then(|(_, value)| value.trim().strip_prefix("Bearer "))
+            .map(|token| {
+                ring::constant_time::verify_slices_are_equal(
+                    token.trim().as_bytes(),
+                    context.token.as_bytes(),
+                )
+                .is_ok()
+            })
+            .unwrap_or(false);
+        if !authorized {
+            return ("401 Unauthorized", error_body("missing or invalid token"));
+        }
+        if !context.rate_limiter.lock().try_acquire(Instant::now()) {
+            return ("429 Too Many Requests", error_body("too many requests"));
+        }
+        if method != Some("GET") {
+            return ("405 Method Not Allowed", error_body("the API is read-only"));
+        }
+
+        let body = match path {
+            Some("/v1/state") => serde_json::to_string(&context.status.get()),
+            Some("/v1/relay") => serde_json::to_string(&context.status.get().relay),
+            Some("/v1/metrics") => serde_json::to_string(&context.status.metrics()),
+            Some("/v1/expiry") => {
+                let expiry = context.expiry_monitor.expiry().await;
+                serde_json::to_string(&ExpiryStatus::new(expiry, Utc::now()))
+            }
+            _ => return ("404 Not Found", error_body("unknown endpoint")),
+        };
+        match body {
+            Ok(body) => ("200 OK", body),
+            Err(_) => (
+                "500 Internal Server Error",
+                error_body("failed to serialize"),
+            ),
+        }
+    }
+}
+
+impl Drop for LocalApi {
+    fn drop(&mut self) {
+        self.abort_handle.abort();
+        log::info!("Stopped local API");
+    }
+}
+
+fn error_body(message: &str) -> String {
+    serde_json::json!({ "error": message }).to_string()
+}
+
+async fn read_header(client: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let mut header = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !header.windows(4).any(|window| window == b"\r\n\r\n") {
+        if header.len() >= MAX_HEADER_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "HTTP header is too large",
+            ));
+        }
+        let read = client.read(&mut buffer).await?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        header.extend_from_slice(&buffer[..read]);
+    }
+    Ok(header)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(start);
+        for _ in 0..RATE_LIMIT_BURST as usize {
+            assert!(limiter.try_acquire(start));
+        }
+        assert!(!limiter.try_acquire(start));
+
+        let later = start + Duration::from_secs_f64(1.0 / RATE_LIMIT_PER_SECOND);
+        assert!(limiter.try_acquire(later));
+        assert!(!limiter.try_acquire(later));
+    }
+
+    #[test]
+    fn test_expiry_status() {
+        let now = Utc::now();
+        let status = ExpiryStatus::new(Some(now + chrono::Duration::hours(50)), now);
+        assert_eq!(status.days_left, Some(2));
+        let status = ExpiryStatus::new(Some(now - chrono::Duration::days(3)), now);
+        assert_eq!(status.days_left, Some(0));
+        assert_eq!(ExpiryStatus::new(None, now).days_left, None);
+    }
+
+    #[test]
+    fn test_metrics() {
+        let now = Utc::now();
+        let mut metrics = Metrics::default();
+        metrics.record_transition("disconnected", "connecting", now);
+        metrics.record_transition("connecting", "connected", now);
+        metrics.record_transition("connected", "connected", now);
+        assert_eq!(metrics.connections, 1);
+        assert_eq!(metrics.connected_since, Some(now));
+
+        metrics.record_transition("connected", "blocked", now);
+        assert_eq!(metrics.errors, 1);
+        assert_eq!(metrics.connected_since, None);
+    }
+}
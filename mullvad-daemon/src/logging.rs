@@ -3,19 +3,19 @@ use fern::{
     Output,
 };
 use log;
-use std::{fmt, io, path::PathBuf};
-use talpid_core::logging::rotate_log;
+use mullvad_problem_report::redact::Redactor;
+use mullvad_types::settings::{LogPrivacyLevel, LogRotationSettings};
+use parking_lot::RwLock;
+use std::{
+    fmt, io,
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+use talpid_core::logging::{RotatingLogFile, RotationLimits};
 
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
-    /// Unable to open log file for writing
-    #[error(display = "Unable to open log file for writing: {}", path)]
-    WriteFile {
-        path: String,
-        #[error(source)]
-        source: io::Error,
-    },
-
     #[error(display = "Unable to rotate daemon log file")]
     RotateLog(#[error(source)] talpid_core::logging::RotateLogError),
 
@@ -42,6 +42,20 @@ pub const SILENCED_CRATES: &[&str] = &[
     "tracing",
 ];
 const SLIGHTLY_SILENCED_CRATES: &[&str] = &["mnl", "nftnl"];
+/// Modules that mostly log details about the network of the user. They only log warnings and
+/// errors when the log privacy level is `Minimal`.
+const NETWORK_DETAIL_MODULES: &[&str] = &[
+    "talpid_core::dns",
+    "talpid_core::firewall",
+    "talpid_core::interface_watcher",
+    "talpid_core::lan_watcher",
+    "talpid_core::mdns_reflector",
+    "talpid_core::network_identity",
+    "talpid_core::network_interface",
+    "talpid_core::offline",
+    "talpid_core::routing",
+    "talpid_core::split_tunnel",
+];
 
 const COLORS: ColoredLevelConfig = ColoredLevelConfig {
     error: Color::Red,
@@ -59,21 +73,172 @@ const LINE_SEPARATOR: &str = "\r\n";
 
 const DATE_TIME_FORMAT_STR: &str = "[%Y-%m-%d %H:%M:%S%.3f]";
 
+/// Whether messages are written as JSON objects rather than as plain text.
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static! {
+    static ref LOG_LEVELS: RwLock<LogLevels> = RwLock::new(LogLevels::new(log::LevelFilter::Info));
+    static ref LOG_PRIVACY: RwLock<LogPrivacyLevel> = RwLock::new(LogPrivacyLevel::default());
+    static ref NETWORK_DETAILS_REDACTOR: Redactor = Redactor::network_details();
+}
+
+/// The log level of a module, such as `talpid_core::firewall`. An empty module applies to all
+/// modules that have no level of their own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogLevelDirective {
+    pub module: String,
+    pub level: log::LevelFilter,
+}
+
+/// Log levels that are temporarily used instead of the ones the daemon was started with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogLevelOverrides {
+    pub directives: Vec<LogLevelDirective>,
+    /// The time left until the levels revert, if they revert automatically.
+    pub remaining: Option<Duration>,
+}
+
+/// Identifies a set of overrides, so that a delayed reversion does not undo newer overrides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OverridesId(u64);
+
+struct LogLevels {
+    default: log::LevelFilter,
+    modules: Vec<LogLevelDirective>,
+    overrides: Vec<LogLevelDirective>,
+    overrides_id: u64,
+    overrides_expire: Option<Instant>,
+}
+
+impl LogLevels {
+    fn new(default: log::LevelFilter) -> Self {
+        let mut modules = Vec::new();
+        let mut add_modules = |crates: &[&str], level| {
+            modules.extend(crates.iter().map(|module| LogLevelDirective {
+                module: module.to_string(),
+                level,
+            }))
+        };
+        add_modules(WARNING_SILENCED_CRATES, log::LevelFilter::Error);
+        add_modules(SILENCED_CRATES, log::LevelFilter::Warn);
+        add_modules(SLIGHTLY_SILENCED_CRATES, one_level_quieter(default));
+
+        LogLevels {
+            default,
+            modules,
+            overrides: Vec::new(),
+            overrides_id: 0,
+            overrides_expire: None,
+        }
+    }
+
+    /// Returns the level of the most specific module that `target` belongs to. Overrides take
+    /// precedence over the startup levels of the same module.
+    fn level_for(&self, target: &str) -> log::LevelFilter {
+        let mut level = self.default;
+        let mut matched_len = 0;
+        for directive in &self.modules {
+            if is_in_module(target, &directive.module) && directive.module.len() > matched_len {
+                level = directive.level;
+                matched_len = directive.module.len();
+            }
+        }
+        for directive in &self.overrides {
+            if is_in_module(target, &directive.module) && directive.module.len() >= matched_len {
+                level = directive.level;
+                matched_len = directive.module.len();
+            }
+        }
+        level
+    }
+
+    fn max_level(&self) -> log::LevelFilter {
+        self.modules
+            .iter()
+            .chain(self.overrides.iter())
+            .map(|directive| directive.level)
+            .fold(self.default, std::cmp::max)
+    }
+}
+
+fn is_in_module(target: &str, module: &str) -> bool {
+    module.is_empty()
+        || (target.starts_with(module)
+            && (target.len() == module.len() || target[module.len()..].starts_with("::")))
+}
+
+/// Temporarily uses the given log levels instead of the ones the daemon was started with. Any
+/// previous overrides are replaced. Use the returned ID to revert them after `revert_after`.
+pub fn set_log_level_overrides(
+    directives: Vec<LogLevelDirective>,
+    revert_after: Option<Duration>,
+) -> OverridesId {
+    let mut levels = LOG_LEVELS.write();
+    levels.overrides = directives;
+    levels.overrides_id += 1;
+    levels.overrides_expire = revert_after.map(|duration| Instant::now() + duration);
+    log::set_max_level(levels.max_level());
+    OverridesId(levels.overrides_id)
+}
+
+/// Reverts to the log levels the daemon was started with. If `id` is given, the overrides are
+/// only reverted if they have not been replaced since. Returns whether anything was reverted.
+pub fn revert_log_level_overrides(id: Option<OverridesId>) -> bool {
+    let mut levels = LOG_LEVELS.write();
+    if levels.overrides.is_empty() || id.map(|id| id.0 != levels.overrides_id).unwrap_or(false) {
+        return false;
+    }
+    levels.overrides.clear();
+    levels.overrides_expire = None;
+    log::set_max_level(levels.max_level());
+    true
+}
+
+/// Sets how much network detail is written to the log. This applies to every message logged
+/// after the call, including those of the modules with overridden log levels.
+pub fn set_log_privacy(log_privacy: LogPrivacyLevel) {
+    *LOG_PRIVACY.write() = log_privacy;
+}
+
+/// Sets whether messages are written as JSON objects, one per line, rather than as plain text.
+pub fn set_json_output(enabled: bool) {
+    JSON_OUTPUT.store(enabled, Ordering::Relaxed);
+}
+
+fn is_enabled(metadata: &log::Metadata<'_>) -> bool {
+    metadata.level() <= LOG_LEVELS.read().level_for(metadata.target())
+        && is_allowed_by_privacy(metadata)
+}
+
+fn is_allowed_by_privacy(metadata: &log::Metadata<'_>) -> bool {
+    *LOG_PRIVACY.read() != LogPrivacyLevel::Minimal
+        || metadata.level() <= log::Level::Warn
+        || !NETWORK_DETAIL_MODULES
+            .iter()
+            .any(|module| is_in_module(metadata.target(), module))
+}
+
+/// Returns the log levels that are currently overridden.
+pub fn log_level_overrides() -> LogLevelOverrides {
+    let levels = LOG_LEVELS.read();
+    LogLevelOverrides {
+        directives: levels.overrides.clone(),
+        remaining: levels
+            .overrides_expire
+            .map(|expire| expire.saturating_duration_since(Instant::now())),
+    }
+}
+
 pub fn init_logger(
     log_level: log::LevelFilter,
     log_file: Option<&PathBuf>,
     output_timestamp: bool,
 ) -> Result<(), Error> {
-    let mut top_dispatcher = fern::Dispatch::new().level(log_level);
-    for silenced_crate in WARNING_SILENCED_CRATES {
-        top_dispatcher = top_dispatcher.level_for(*silenced_crate, log::LevelFilter::Error);
-    }
-    for silenced_crate in SILENCED_CRATES {
-        top_dispatcher = top_dispatcher.level_for(*silenced_crate, log::LevelFilter::Warn);
-    }
-    for silenced_crate in SLIGHTLY_SILENCED_CRATES {
-        top_dispatcher = top_dispatcher.level_for(*silenced_crate, one_level_quieter(log_level));
-    }
+    *LOG_LEVELS.write() = LogLevels::new(log_level);
+    // The levels are checked by the filter, since they can change while the daemon is running
+    let mut top_dispatcher = fern::Dispatch::new()
+        .level(log::LevelFilter::Trace)
+        .filter(is_enabled);
 
     let stdout_formatter = Formatter {
         output_timestamp,
@@ -85,18 +250,14 @@ pub fn init_logger(
     top_dispatcher = top_dispatcher.chain(stdout_dispatcher);
 
     if let Some(ref log_file) = log_file {
-        rotate_log(log_file).map_err(Error::RotateLog)?;
+        let file = RotatingLogFile::create(log_file).map_err(Error::RotateLog)?;
         let file_formatter = Formatter {
             output_timestamp: true,
             output_color: false,
         };
-        let f = fern::log_file(log_file).map_err(|source| Error::WriteFile {
-            path: log_file.display().to_string(),
-            source,
-        })?;
         let file_dispatcher = fern::Dispatch::new()
             .format(move |out, message, record| file_formatter.output_msg(out, message, record))
-            .chain(Output::file(f, LINE_SEPARATOR));
+            .chain(Output::writer(Box::new(file), LINE_SEPARATOR));
         top_dispatcher = top_dispatcher.chain(file_dispatcher);
     }
     #[cfg(all(target_os = "android", debug_assertions))]
@@ -108,9 +269,20 @@ pub fn init_logger(
         top_dispatcher = top_dispatcher.chain(logger);
     }
     top_dispatcher.apply().map_err(Error::SetLoggerError)?;
+    log::set_max_level(LOG_LEVELS.read().max_level());
     Ok(())
 }
 
+/// Returns the limits that the logs are rotated with, as given by the settings.
+pub fn rotation_limits(settings: &LogRotationSettings) -> RotationLimits {
+    RotationLimits {
+        max_size: settings.max_size.as_bytes(),
+        generations: settings.generations as usize,
+        max_age: settings.max_age.map(|max_age| max_age.as_duration()),
+        compress: settings.compress,
+    }
+}
+
 fn one_level_quieter(level: log::LevelFilter) -> log::LevelFilter {
     use log::LevelFilter::*;
     match level {
@@ -152,7 +324,22 @@ impl Formatter {
         message: &fmt::Arguments<'_>,
         record: &log::Record<'_>,
     ) {
-        let message = escape_newlines(format!("{}", message));
+        let message = format!("{}", message);
+        let message = if *LOG_PRIVACY.read() == LogPrivacyLevel::Full {
+            message
+        } else {
+            NETWORK_DETAILS_REDACTOR.redact(&message)
+        };
+        if JSON_OUTPUT.load(Ordering::Relaxed) {
+            let entry = json_entry(
+                chrono::Utc::now(),
+                record.level(),
+                record.target(),
+                &message,
+            );
+            return out.finish(format_args!("{}", entry));
+        }
+        let message = escape_newlines(message);
 
         out.finish(format_args!(
             "{}[{}][{}] {}",
@@ -164,6 +351,20 @@ impl Formatter {
     }
 }
 
+fn json_entry(
+    time: chrono::DateTime<chrono::Utc>,
+    level: log::Level,
+    target: &str,
+    message: &str,
+) -> serde_json::Value {
+    serde_json::json!({
+        "time": time.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        "level": level.to_string(),
+        "target": target,
+        "message": message,
+    })
+}
+
 #[cfg(not(windows))]
 fn escape_newlines(text: String) -> String {
     text
@@ -173,3 +374,92 @@ fn escape_newlines(text: String) -> String {
 fn escape_newlines(text: String) -> String {
     text.replace("\n", LINE_SEPARATOR)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn directive(module: &str, level: log::LevelFilter) -> LogLevelDirective {
+        LogLevelDirective {
+            module: module.to_owned(),
+            level,
+        }
+    }
+
+    fn metadata(target: &str, level: log::Level) -> log::Metadata<'_> {
+        log::Metadata::builder().target(target).level(level).build()
+    }
+
+    #[test]
+    fn test_level_for_module() {
+        let mut levels = LogLevels::new(log::LevelFilter::Info);
+        assert_eq!(levels.level_for("mullvad_daemon"), log::LevelFilter::Info);
+        assert_eq!(levels.level_for("hyper::client"), log::LevelFilter::Warn);
+        assert_eq!(levels.level_for("hyperx"), log::LevelFilter::Info);
+
+        levels.overrides = vec![
+            directive("talpid_core::firewall", log::LevelFilter::Trace),
+            directive("hyper", log::LevelFilter::Debug),
+        ];
+        assert_eq!(
+            levels.level_for("talpid_core::firewall::linux"),
+            log::LevelFilter::Trace
+        );
+        assert_eq!(
+            levels.level_for("talpid_core::firewall_x"),
+            log::LevelFilter::Info
+        );
+        assert_eq!(levels.level_for("hyper::client"), log::LevelFilter::Debug);
+        assert_eq!(levels.max_level(), log::LevelFilter::Trace);
+    }
+
+    #[test]
+    fn test_minimal_privacy_silences_network_modules() {
+        set_log_privacy(LogPrivacyLevel::Minimal);
+        assert!(!is_allowed_by_privacy(&metadata(
+            "talpid_core::routing::unix",
+            log::Level::Info
+        )));
+        assert!(is_allowed_by_privacy(&metadata(
+            "talpid_core::routing::unix",
+            log::Level::Warn
+        )));
+        assert!(is_allowed_by_privacy(&metadata(
+            "mullvad_daemon",
+            log::Level::Info
+        )));
+
+        set_log_privacy(LogPrivacyLevel::Redacted);
+        assert!(is_allowed_by_privacy(&metadata(
+            "talpid_core::routing::unix",
+            log::Level::Info
+        )));
+        set_log_privacy(LogPrivacyLevel::default());
+    }
+
+    #[test]
+    fn test_default_override_keeps_silenced_crates() {
+        let mut levels = LogLevels::new(log::LevelFilter::Info);
+        levels.overrides = vec![directive("", log::LevelFilter::Debug)];
+        assert_eq!(levels.level_for("mullvad_daemon"), log::LevelFilter::Debug);
+        assert_eq!(levels.level_for("h2"), log::LevelFilter::Warn);
+    }
+
+    #[test]
+    fn test_json_entry() {
+        use chrono::TimeZone;
+
+        let time = chrono::Utc.ymd(2022, 3, 4).and_hms_milli(5, 6, 7, 89);
+        let entry = json_entry(time, log::Level::Warn, "mullvad_daemon", "one\ntwo");
+        assert_eq!(
+            entry,
+            serde_json::json!({
+                "time": "2022-03-04T05:06:07.089Z",
+                "level": "WARN",
+                "target": "mullvad_daemon",
+                "message": "one\ntwo",
+            })
+        );
+        assert!(!entry.to_string().contains('\n'));
+    }
+}
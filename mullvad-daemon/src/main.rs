@@ -6,7 +6,7 @@ use mullvad_daemon::{
     management_interface::{ManagementInterfaceEventBroadcaster, ManagementInterfaceServer},
     rpc_uniqueness_check,
     runtime::new_runtime_builder,
-    version, Daemon, DaemonCommandChannel, DaemonCommandSender,
+    settings, version, Daemon, DaemonCommandChannel, DaemonCommandSender,
 };
 use std::{path::PathBuf, thread, time::Duration};
 use talpid_types::ErrorExt;
@@ -46,6 +46,12 @@ fn init_logging(config: &cli::Config) -> Result<Option<PathBuf>, String> {
     let log_dir = get_log_dir(config)?;
     let log_file = log_dir.as_ref().map(|dir| dir.join(DAEMON_LOG_FILENAME));
 
+    if let Ok(settings_dir) = mullvad_paths::settings_dir() {
+        let (log_rotation, log_privacy) = settings::read_logging_settings(&settings_dir);
+        talpid_core::logging::set_rotation_limits(logging::rotation_limits(&log_rotation));
+        logging::set_log_privacy(log_privacy);
+    }
+
     logging::init_logger(
         config.log_level,
         log_file.as_ref(),
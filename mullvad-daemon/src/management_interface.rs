@@ -1,32 +1,64 @@
-use crate::{account_history, settings, DaemonCommand, DaemonCommandSender, EventListener};
+use crate::{
+    account_history, captive_portal::PortalStatus, connect_times, logging, relay_latency, settings,
+    settings_profile::ConflictResolution, DaemonCommand, DaemonCommandSender, EventListener,
+    ProblemReport,
+};
 use futures::channel::oneshot;
 use mullvad_management_interface::{
     types::{self, daemon_event, management_service_server::ManagementService},
     Code, Request, Response, Status,
 };
 use mullvad_paths;
-use mullvad_rpc::{rest::Error as RestError, StatusCode};
+use mullvad_rpc::{
+    clock::ClockSkew,
+    rest::{ApiErrorCode, Error as RestError},
+    StatusCode,
+};
 #[cfg(not(target_os = "android"))]
 use mullvad_types::settings::DnsOptions;
 use mullvad_types::{
-    account::AccountToken,
+    account::{AccountExpiryEvent, AccountExpirySettings, AccountToken},
+    api_access::{ActiveApiAccessMethod, ApiAccessMethod, ApiProxy, ApiResolutionStrategy},
+    beta_features::BetaFeature,
+    device::{DeviceIdentityChange, DeviceState},
+    location::ExitIpMismatch,
+    network_rules::{NetworkAction, NetworkMatch, NetworkRule},
+    obfuscation::ObfuscationSettings,
     relay_constraints::{BridgeSettings, BridgeState, RelaySettingsUpdate},
     relay_list::RelayList,
-    settings::Settings,
+    settings::{
+        schema::SettingsSchema, validate_identity_rotation_interval, AllowedSystemServices,
+        CredentialStorage, ExitIpCheckSettings, HookSettings, LanProxySettings, LocalApiSettings,
+        LogPrivacyLevel, LogRotationSettings, ReconnectBackoffSettings, Settings,
+        TunnelParameterOverrides,
+    },
     states::{TargetState, TunnelState},
+    units::{FieldError, HumanDuration},
     version,
     wireguard::{RotationInterval, RotationIntervalError},
 };
-use parking_lot::RwLock;
-#[cfg(windows)]
-use std::path::PathBuf;
+use parking_lot::{Mutex, RwLock};
 use std::{
     cmp,
     convert::{TryFrom, TryInto},
     sync::{mpsc, Arc},
     time::Duration,
 };
-use talpid_types::ErrorExt;
+#[cfg(any(windows, target_os = "linux"))]
+use std::{collections::HashSet, path::PathBuf};
+use talpid_core::{
+    conflicting_software::{ConflictKind, ConflictingSoftware},
+    preflight::{CapabilityKind, MissingCapability},
+};
+use talpid_types::{
+    net::{
+        validate_interface_name,
+        wireguard::{ICMP_CHECK_INTERVAL_RANGE, PACKET_LOSS_THRESHOLD_RANGE},
+        AllowedEndpoint,
+    },
+    tunnel::TransitionReason,
+    ErrorExt,
+};
 use tokio_stream::wrappers::{ReceiverStream, UnboundedReceiverStream};
 
 #[derive(err_derive::Error, Debug)]
@@ -39,21 +71,63 @@ pub enum Error {
 
 struct ManagementServiceImpl {
     daemon_tx: DaemonCommandSender,
-    subscriptions: Arc<RwLock<Vec<EventsListenerSender>>>,
+    subscriptions: Arc<RwLock<Vec<EventsListener>>>,
+    transition_subscriptions: Arc<RwLock<Vec<TransitionsListenerSender>>>,
 }
 
 pub type ServiceResult<T> = std::result::Result<Response<T>, Status>;
-type EventsListenerReceiver = UnboundedReceiverStream<Result<types::DaemonEvent, Status>>;
-type EventsListenerSender = tokio::sync::mpsc::UnboundedSender<Result<types::DaemonEvent, Status>>;
+type EventsListenerReceiver = ReceiverStream<Result<types::DaemonEvent, Status>>;
+type EventsListenerSender = tokio::sync::mpsc::Sender<Result<types::DaemonEvent, Status>>;
+type TransitionsListenerReceiver = ReceiverStream<Result<types::TunnelTransition, Status>>;
+type TransitionsListenerSender = tokio::sync::mpsc::Sender<Result<types::TunnelTransition, Status>>;
+
+/// Maximum number of events that may be waiting to be read by a subscriber. Subscribers that fall
+/// this far behind are disconnected, rather than letting their events pile up in memory. They can
+/// subscribe again and fetch the current state.
+const EVENT_QUEUE_SIZE: usize = 128;
+
+/// Number of tunnel stats samples that may be queued for a subscriber before it is disconnected.
+const TUNNEL_STATS_QUEUE_SIZE: usize = 16;
+
+/// Longest time that a temporary firewall exception may be added for, in seconds.
+const MAX_TEMPORARY_EXCEPTION_SECONDS: u32 = 60 * 60;
+
+/// A subscriber of daemon events.
+struct EventsListener {
+    tx: EventsListenerSender,
+    /// Whether the complete settings are included in settings events, rather than only the names
+    /// of the changed settings.
+    include_full_settings: bool,
+}
+
+impl EventsListener {
+    /// Queues an event for the subscriber. Returns `false` if the subscriber should be removed.
+    fn send(&self, event: &types::DaemonEvent) -> bool {
+        match self.tx.try_send(Ok(event.clone())) {
+            Ok(()) => true,
+            Err(tokio::sync::mpsc::error::TrySendError::Full(_)) => {
+                log::warn!(
+                    "Disconnecting management interface subscriber that is not reading events"
+                );
+                false
+            }
+            Err(tokio::sync::mpsc::error::TrySendError::Closed(_)) => false,
+        }
+    }
+}
 
 const INVALID_VOUCHER_MESSAGE: &str = "This voucher code is invalid";
 const USED_VOUCHER_MESSAGE: &str = "This voucher code has already been used";
+const EXPIRED_VOUCHER_MESSAGE: &str = "This voucher code has expired";
 
 #[mullvad_management_interface::async_trait]
 impl ManagementService for ManagementServiceImpl {
     type GetRelayLocationsStream = ReceiverStream<Result<types::RelayListCountry, Status>>;
     type GetSplitTunnelProcessesStream = UnboundedReceiverStream<Result<i32, Status>>;
     type EventsListenStream = EventsListenerReceiver;
+    type TunnelTransitionsListenStream = TransitionsListenerReceiver;
+    type TunnelStatsListenStream = ReceiverStream<Result<types::TunnelStats, Status>>;
+    type AcquireConnectionLeaseStream = ReceiverStream<Result<types::ConnectionLease, Status>>;
 
     // Control and get the tunnel state
     //
@@ -84,6 +158,29 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(reconnect_issued))
     }
 
+    async fn acquire_connection_lease(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<Self::AcquireConnectionLeaseStream> {
+        log::debug!("acquire_connection_lease");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::AcquireConnectionLease(tx))?;
+        let lease = self.wait_for_result(rx).await?.map_err(map_daemon_error)?;
+
+        let (stream_tx, stream_rx) = tokio::sync::mpsc::channel(1);
+        let daemon_tx = self.daemon_tx.clone();
+        tokio::spawn(async move {
+            // The lease is held until the client closes the stream, such as by exiting
+            let _ = stream_tx
+                .send(Ok(types::ConnectionLease { id: lease }))
+                .await;
+            stream_tx.closed().await;
+            let _ = daemon_tx.send(DaemonCommand::ReleaseConnectionLease(lease));
+        });
+
+        Ok(Response::new(ReceiverStream::new(stream_rx)))
+    }
+
     async fn get_tunnel_state(&self, _: Request<()>) -> ServiceResult<types::TunnelState> {
         log::debug!("get_tunnel_state");
         let (tx, rx) = oneshot::channel();
@@ -92,16 +189,65 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(types::TunnelState::from(state)))
     }
 
+    async fn detect_captive_portal(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::CaptivePortalStatus> {
+        log::debug!("detect_captive_portal");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::DetectCaptivePortal(tx))?;
+        let status = match self.wait_for_result(rx).await? {
+            PortalStatus::Online => types::captive_portal_status::Status::Online,
+            PortalStatus::Detected => types::captive_portal_status::Status::Detected,
+            PortalStatus::Unreachable => types::captive_portal_status::Status::Unreachable,
+        };
+        Ok(Response::new(types::CaptivePortalStatus {
+            status: i32::from(status),
+        }))
+    }
+
+    async fn start_portal_login(&self, _: Request<()>) -> ServiceResult<()> {
+        log::debug!("start_portal_login");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::StartPortalLogin(tx))?;
+        self.wait_for_result(rx).await?;
+        Ok(Response::new(()))
+    }
+
+    async fn stop_portal_login(&self, _: Request<()>) -> ServiceResult<()> {
+        log::debug!("stop_portal_login");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::StopPortalLogin(tx))?;
+        self.wait_for_result(rx).await?;
+        Ok(Response::new(()))
+    }
+
     // Control the daemon and receive events
     //
 
-    async fn events_listen(&self, _: Request<()>) -> ServiceResult<Self::EventsListenStream> {
-        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    async fn events_listen(
+        &self,
+        request: Request<types::EventsListenRequest>,
+    ) -> ServiceResult<Self::EventsListenStream> {
+        let include_full_settings = request.into_inner().include_full_settings;
+        let (tx, rx) = tokio::sync::mpsc::channel(EVENT_QUEUE_SIZE);
 
         let mut subscriptions = self.subscriptions.write();
-        subscriptions.push(tx);
+        subscriptions.push(EventsListener {
+            tx,
+            include_full_settings,
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
 
-        Ok(Response::new(UnboundedReceiverStream::new(rx)))
+    async fn tunnel_transitions_listen(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<Self::TunnelTransitionsListenStream> {
+        let (tx, rx) = tokio::sync::mpsc::channel(EVENT_QUEUE_SIZE);
+        self.transition_subscriptions.write().push(tx);
+        Ok(Response::new(ReceiverStream::new(rx)))
     }
 
     async fn prepare_restart(&self, _: Request<()>) -> ServiceResult<()> {
@@ -110,6 +256,23 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(()))
     }
 
+    #[cfg(windows)]
+    async fn run_migration(
+        &self,
+        request: Request<String>,
+    ) -> ServiceResult<types::MigrationReport> {
+        let requested_by = Some(request.into_inner()).filter(|version| !version.is_empty());
+        log::debug!("run_migration({:?})", requested_by);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::RunMigration(tx, requested_by))?;
+        let report = self.wait_for_result(rx).await?;
+        Ok(Response::new(convert_migration_report(report)))
+    }
+    #[cfg(not(windows))]
+    async fn run_migration(&self, _: Request<String>) -> ServiceResult<types::MigrationReport> {
+        Err(Status::unimplemented("Migrations are only run on Windows"))
+    }
+
     async fn shutdown(&self, _: Request<()>) -> ServiceResult<()> {
         log::debug!("shutdown");
         self.send_command_to_daemon(DaemonCommand::Shutdown)?;
@@ -153,213 +316,1487 @@ impl ManagementService for ManagementServiceImpl {
             .map(Response::new)
     }
 
-    // Relays and tunnel constraints
+    // API access
     //
 
-    async fn update_relay_locations(&self, _: Request<()>) -> ServiceResult<()> {
-        log::debug!("update_relay_locations");
-        self.send_command_to_daemon(DaemonCommand::UpdateRelayLocations)?;
-        Ok(Response::new(()))
+    async fn get_api_access_status(&self, _: Request<()>) -> ServiceResult<types::ApiAccessStatus> {
+        log::debug!("get_api_access_status");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetApiAccessStatus(tx))?;
+        let status = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::ApiAccessStatus::from(status)))
     }
 
-    async fn update_relay_settings(
-        &self,
-        request: Request<types::RelaySettingsUpdate>,
-    ) -> ServiceResult<()> {
-        log::debug!("update_relay_settings");
+    async fn get_scheduled_tasks(&self, _: Request<()>) -> ServiceResult<types::ScheduledTasks> {
+        log::debug!("get_scheduled_tasks");
         let (tx, rx) = oneshot::channel();
-        let constraints_update = RelaySettingsUpdate::try_from(request.into_inner())?;
+        self.send_command_to_daemon(DaemonCommand::GetScheduledTasks(tx))?;
+        let tasks = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::ScheduledTasks {
+            tasks: tasks
+                .into_iter()
+                .map(|task| types::ScheduledTask {
+                    name: task.name.to_owned(),
+                    interval: Some(types::Duration::from(task.interval)),
+                    next_run: Some(types::Timestamp::from(task.next_run)),
+                    last_run: task.last_run.map(types::Timestamp::from),
+                    running: task.running,
+                })
+                .collect(),
+        }))
+    }
 
-        let message = DaemonCommand::UpdateRelaySettings(tx, constraints_update);
-        self.send_command_to_daemon(message)?;
+    async fn set_api_trace_enabled(&self, request: Request<bool>) -> ServiceResult<()> {
+        let enabled = request.into_inner();
+        log::debug!("set_api_trace_enabled({})", enabled);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetApiTraceEnabled(tx, enabled))?;
+        self.wait_for_result(rx).await?;
+        Ok(Response::new(()))
+    }
+
+    async fn set_api_clock_check(&self, request: Request<bool>) -> ServiceResult<()> {
+        let enabled = request.into_inner();
+        log::debug!("set_api_clock_check({})", enabled);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetApiClockCheck(tx, enabled))?;
         self.wait_for_result(rx)
             .await?
             .map(Response::new)
             .map_err(map_settings_error)
     }
 
-    async fn get_relay_locations(
+    async fn get_api_trace(&self, _: Request<()>) -> ServiceResult<types::ApiTrace> {
+        log::debug!("get_api_trace");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetApiTrace(tx))?;
+        let (enabled, entries) = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::ApiTrace {
+            enabled,
+            entries: entries
+                .into_iter()
+                .map(|entry| types::ApiTraceEntry {
+                    timestamp: Some(types::Timestamp {
+                        seconds: entry.timestamp.timestamp(),
+                        nanos: entry.timestamp.timestamp_subsec_nanos() as i32,
+                    }),
+                    method: entry.method,
+                    path: entry.path,
+                    duration: Some(types::Duration::from(entry.duration)),
+                    outcome: entry.outcome,
+                    retried: entry.retried,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn get_diagnostics(&self, _: Request<()>) -> ServiceResult<types::Diagnostics> {
+        log::debug!("get_diagnostics");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetDiagnostics(tx))?;
+        let diagnostics = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::Diagnostics {
+            settings: diagnostics.settings.to_string(),
+            enabled_features: diagnostics
+                .enabled_features
+                .into_iter()
+                .map(str::to_owned)
+                .collect(),
+            state_transitions: diagnostics
+                .state_transitions
+                .into_iter()
+                .map(|transition| types::StateTransition {
+                    timestamp: Some(types::Timestamp {
+                        seconds: transition.timestamp.timestamp(),
+                        nanos: transition.timestamp.timestamp_subsec_nanos() as i32,
+                    }),
+                    state: transition.state,
+                })
+                .collect(),
+            read_only_settings: diagnostics.read_only_settings,
+            network: diagnostics.network.unwrap_or_default(),
+            missing_capabilities: diagnostics.missing_capabilities,
+        }))
+    }
+
+    async fn collect_problem_report(
         &self,
-        _: Request<()>,
-    ) -> ServiceResult<Self::GetRelayLocationsStream> {
-        log::debug!("get_relay_locations");
+        request: Request<types::ProblemReportCollection>,
+    ) -> ServiceResult<String> {
+        log::debug!("collect_problem_report");
+        let redact = request.into_inner().redact;
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::CollectProblemReport(tx, redact))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(|path| Response::new(path.display().to_string()))
+            .map_err(map_daemon_error)
+    }
 
+    async fn get_tunnel_stats(&self, _: Request<()>) -> ServiceResult<types::TunnelStats> {
+        log::debug!("get_tunnel_stats");
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::GetRelayLocations(tx))?;
-        let locations = self.wait_for_result(rx).await?;
+        self.send_command_to_daemon(DaemonCommand::GetTunnelStats(tx))?;
+        match self.wait_for_result(rx).await? {
+            Some(stats) => Ok(Response::new(convert_tunnel_stats(stats))),
+            None => Err(Status::not_found("the tunnel is not connected")),
+        }
+    }
 
-        let (stream_tx, stream_rx) =
-            tokio::sync::mpsc::channel(cmp::max(1, locations.countries.len()));
+    async fn tunnel_stats_listen(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<Self::TunnelStatsListenStream> {
+        log::debug!("tunnel_stats_listen");
+        let (stats_tx, mut stats_rx) = tokio::sync::mpsc::channel(TUNNEL_STATS_QUEUE_SIZE);
+        self.send_command_to_daemon(DaemonCommand::TunnelStatsListen(stats_tx))?;
 
+        let (tx, rx) = tokio::sync::mpsc::channel(TUNNEL_STATS_QUEUE_SIZE);
         tokio::spawn(async move {
-            for country in locations.countries.into_iter() {
-                if let Err(error) = stream_tx
-                    .send(Ok(types::RelayListCountry::from(country)))
-                    .await
-                {
-                    log::error!(
-                        "Error while sending relays to client: {}",
-                        error.display_chain()
-                    );
+            while let Some(stats) = stats_rx.recv().await {
+                if tx.send(Ok(convert_tunnel_stats(stats))).await.is_err() {
+                    break;
                 }
             }
         });
 
-        Ok(Response::new(ReceiverStream::new(stream_rx)))
+        Ok(Response::new(ReceiverStream::new(rx)))
     }
 
-    async fn get_current_location(&self, _: Request<()>) -> ServiceResult<types::GeoIpLocation> {
-        log::debug!("get_current_location");
+    async fn get_connection_statistics(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::ConnectionStatistics> {
+        log::debug!("get_connection_statistics");
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::GetCurrentLocation(tx))?;
-        let result = self.wait_for_result(rx).await?;
-        match result {
-            Some(geoip) => Ok(Response::new(types::GeoIpLocation::from(geoip))),
-            None => Err(Status::not_found("no location was found")),
-        }
+        self.send_command_to_daemon(DaemonCommand::GetConnectionStatistics(tx))?;
+        let (enabled, relays) = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::ConnectionStatistics {
+            enabled,
+            relays: relays
+                .into_iter()
+                .map(|(hostname, stats)| types::RelayConnectionStatistics {
+                    hostname,
+                    attempts: stats.attempts,
+                    successes: stats.successes,
+                    mean_connect_time: stats.mean_connect_time().map(types::Duration::from),
+                    peak_throughput: stats.peak_throughput,
+                    last_attempt: Some(types::Timestamp {
+                        seconds: stats.last_attempt.timestamp(),
+                        nanos: stats.last_attempt.timestamp_subsec_nanos() as i32,
+                    }),
+                })
+                .collect(),
+        }))
     }
 
-    async fn set_bridge_settings(
+    async fn get_connect_times(&self, _: Request<()>) -> ServiceResult<types::ConnectTimes> {
+        log::debug!("get_connect_times");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetConnectTimes(tx))?;
+        let (enabled, versions) = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::ConnectTimes {
+            enabled,
+            bucket_bounds_ms: connect_times::BUCKET_BOUNDS
+                .iter()
+                .map(|bound| bound.as_millis() as u64)
+                .collect(),
+            versions: versions
+                .into_iter()
+                .map(|times| types::VersionConnectTimes {
+                    version: times.version,
+                    total: Some(convert_connect_time_histogram(times.total)),
+                    phases: times
+                        .phases
+                        .into_iter()
+                        .map(|(phase, histogram)| types::PhaseConnectTimes {
+                            phase,
+                            histogram: Some(convert_connect_time_histogram(histogram)),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }))
+    }
+
+    async fn clear_connection_statistics(&self, _: Request<()>) -> ServiceResult<()> {
+        log::debug!("clear_connection_statistics");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ClearConnectionStatistics(tx))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
+    async fn get_relay_health(&self, _: Request<()>) -> ServiceResult<types::RelayHealthList> {
+        log::debug!("get_relay_health");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetRelayHealth(tx))?;
+        let relays = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::RelayHealthList {
+            relays: relays
+                .into_iter()
+                .map(|relay| types::RelayHealth {
+                    hostname: relay.hostname,
+                    score: relay.score,
+                    blacklisted_for: relay.blacklisted_for.map(types::Duration::from),
+                    latency: relay.latency.map(types::Duration::from),
+                    successes: relay.successes,
+                    failures: relay.failures,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn clear_relay_health(&self, _: Request<()>) -> ServiceResult<()> {
+        log::debug!("clear_relay_health");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ClearRelayHealth(tx))?;
+        self.wait_for_result(rx).await?;
+        Ok(Response::new(()))
+    }
+
+    async fn get_relay_latencies(
         &self,
-        request: Request<types::BridgeSettings>,
-    ) -> ServiceResult<()> {
-        let settings = BridgeSettings::try_from(request.into_inner())?;
+        request: Request<types::RelayLatencyRequest>,
+    ) -> ServiceResult<types::RelayLatencyList> {
+        let request = request.into_inner();
+        log::debug!("get_relay_latencies(measure: {})", request.measure);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetRelayLatencies(
+            tx,
+            request.hostnames,
+            request.measure,
+        ))?;
+        let relays = self
+            .wait_for_result(rx)
+            .await?
+            .map_err(map_relay_latency_error)?;
+        Ok(Response::new(types::RelayLatencyList {
+            relays: relays
+                .into_iter()
+                .map(|relay| types::RelayLatency {
+                    hostname: relay.hostname,
+                    latency: relay.latency.map(types::Duration::from),
+                    measured_at: Some(types::Timestamp::from(relay.measured_at)),
+                })
+                .collect(),
+        }))
+    }
 
-        log::debug!("set_bridge_settings({:?})", settings);
+    async fn get_firewall_rules(&self, _: Request<()>) -> ServiceResult<types::FirewallRules> {
+        log::debug!("get_firewall_rules");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetFirewallRules(tx))?;
+        let rules = self.wait_for_result(rx).await?.map_err(|error| {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to list the firewall rules")
+            );
+            Status::internal(error.to_string())
+        })?;
+        Ok(Response::new(types::FirewallRules {
+            backend: rules.backend,
+            policy: rules.policy.unwrap_or_default(),
+            rules: rules.rules,
+        }))
+    }
 
+    async fn remove_orphaned_objects(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::OrphanedObjects> {
+        log::debug!("remove_orphaned_objects");
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::SetBridgeSettings(tx, settings))?;
-        let settings_result = self.wait_for_result(rx).await?;
-        settings_result
-            .map(Response::new)
-            .map_err(map_settings_error)
+        self.send_command_to_daemon(DaemonCommand::RemoveOrphanedObjects(tx))?;
+        let orphans = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::OrphanedObjects {
+            routes: orphans.routes,
+            firewall_objects: orphans.firewall_objects,
+            errors: orphans.errors,
+        }))
     }
 
-    async fn set_bridge_state(&self, request: Request<types::BridgeState>) -> ServiceResult<()> {
-        let bridge_state = BridgeState::try_from(request.into_inner())?;
+    async fn get_connection_attempts(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::ConnectionAttempts> {
+        log::debug!("get_connection_attempts");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetConnectionAttempts(tx))?;
+        let attempts = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::ConnectionAttempts {
+            attempts: attempts
+                .into_iter()
+                .map(|attempt| types::ConnectionAttempt {
+                    id: attempt.id,
+                    timestamp: Some(types::Timestamp {
+                        seconds: attempt.timestamp.timestamp(),
+                        nanos: attempt.timestamp.timestamp_subsec_nanos() as i32,
+                    }),
+                    spans: attempt
+                        .spans
+                        .into_iter()
+                        .map(|span| types::ConnectionAttemptSpan {
+                            phase: span.phase.to_string(),
+                            offset: Some(types::Duration::from(span.offset)),
+                            duration: Some(types::Duration::from(span.duration)),
+                            error: span.error.unwrap_or_default(),
+                        })
+                        .collect(),
+                    outcome: attempt.outcome.unwrap_or_default(),
+                    duration: attempt.duration.map(types::Duration::from),
+                })
+                .collect(),
+        }))
+    }
 
-        log::debug!("set_bridge_state({:?})", bridge_state);
+    async fn get_conflicting_software(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::ConflictingSoftwareList> {
+        log::debug!("get_conflicting_software");
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::SetBridgeState(tx, bridge_state))?;
-        let settings_result = self.wait_for_result(rx).await?;
-        settings_result
-            .map(Response::new)
-            .map_err(map_settings_error)
+        self.send_command_to_daemon(DaemonCommand::GetConflictingSoftware(tx))?;
+        let conflicts = self.wait_for_result(rx).await?;
+        Ok(Response::new(convert_conflicting_software(conflicts)))
     }
 
-    // Settings
-    //
+    async fn get_missing_capabilities(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::MissingCapabilityList> {
+        log::debug!("get_missing_capabilities");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetMissingCapabilities(tx))?;
+        let (missing, read_only_settings) = self.wait_for_result(rx).await?;
+        Ok(Response::new(convert_missing_capabilities(
+            missing,
+            read_only_settings,
+        )))
+    }
 
-    async fn get_settings(&self, _: Request<()>) -> ServiceResult<types::Settings> {
-        log::debug!("get_settings");
+    async fn export_wireguard_config(
+        &self,
+        request: Request<bool>,
+    ) -> ServiceResult<types::WireguardConfigExport> {
+        let include_private_key = request.into_inner();
+        log::debug!("export_wireguard_config({})", include_private_key);
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::GetSettings(tx))?;
-        self.wait_for_result(rx)
-            .await
-            .map(|settings| Response::new(types::Settings::from(&settings)))
+        self.send_command_to_daemon(DaemonCommand::ExportWireguardConfig(
+            tx,
+            include_private_key,
+        ))?;
+        let (config, private_config_path) =
+            self.wait_for_result(rx).await?.map_err(map_daemon_error)?;
+        Ok(Response::new(types::WireguardConfigExport {
+            config,
+            private_config_path: private_config_path
+                .map(|path| path.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        }))
     }
 
-    async fn set_allow_lan(&self, request: Request<bool>) -> ServiceResult<()> {
-        let allow_lan = request.into_inner();
-        log::debug!("set_allow_lan({})", allow_lan);
+    async fn set_log_level_overrides(
+        &self,
+        request: Request<types::LogLevelOverrides>,
+    ) -> ServiceResult<()> {
+        let overrides = request.into_inner();
+        let directives = overrides
+            .directives
+            .into_iter()
+            .map(|directive| {
+                let level = directive.level.parse().map_err(|_| {
+                    Status::invalid_argument(format!("invalid log level: {}", directive.level))
+                })?;
+                Ok(logging::LogLevelDirective {
+                    module: directive.module,
+                    level,
+                })
+            })
+            .collect::<Result<Vec<_>, Status>>()?;
+        let revert_after = overrides
+            .revert_after
+            .map(Duration::try_from)
+            .transpose()
+            .map_err(|_| Status::invalid_argument("unexpected negative duration"))?;
+        log::debug!(
+            "set_log_level_overrides({:?}, {:?})",
+            directives,
+            revert_after
+        );
+
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::SetAllowLan(tx, allow_lan))?;
+        self.send_command_to_daemon(DaemonCommand::SetLogLevelOverrides(
+            tx,
+            directives,
+            revert_after,
+        ))?;
+        self.wait_for_result(rx).await?;
+        Ok(Response::new(()))
+    }
+
+    async fn get_log_level_overrides(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::LogLevelOverrides> {
+        log::debug!("get_log_level_overrides");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetLogLevelOverrides(tx))?;
+        let overrides = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::LogLevelOverrides {
+            directives: overrides
+                .directives
+                .into_iter()
+                .map(|directive| types::LogLevelDirective {
+                    module: directive.module,
+                    level: directive.level.to_string().to_lowercase(),
+                })
+                .collect(),
+            revert_after: overrides.remaining.map(types::Duration::from),
+        }))
+    }
+
+    async fn reset_log_level_overrides(&self, _: Request<()>) -> ServiceResult<()> {
+        log::debug!("reset_log_level_overrides");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ResetLogLevelOverrides(tx))?;
+        self.wait_for_result(rx).await?;
+        Ok(Response::new(()))
+    }
+
+    async fn add_api_access_method(
+        &self,
+        request: Request<types::ApiAccessMethod>,
+    ) -> ServiceResult<()> {
+        let method = ApiAccessMethod::try_from(request.into_inner())?;
+        log::debug!("add_api_access_method({})", method.name);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::AddApiAccessMethod(tx, method))?;
         self.wait_for_result(rx)
             .await?
             .map(Response::new)
-            .map_err(map_settings_error)
+            .map_err(map_daemon_error)
     }
 
-    async fn set_show_beta_releases(&self, request: Request<bool>) -> ServiceResult<()> {
-        let enabled = request.into_inner();
-        log::debug!("set_show_beta_releases({})", enabled);
+    async fn remove_api_access_method(&self, request: Request<String>) -> ServiceResult<()> {
+        let name = request.into_inner();
+        log::debug!("remove_api_access_method({})", name);
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::SetShowBetaReleases(tx, enabled))?;
+        self.send_command_to_daemon(DaemonCommand::RemoveApiAccessMethod(tx, name))?;
         self.wait_for_result(rx)
             .await?
             .map(Response::new)
-            .map_err(map_settings_error)
+            .map_err(map_daemon_error)
     }
 
-    async fn set_block_when_disconnected(&self, request: Request<bool>) -> ServiceResult<()> {
-        let block_when_disconnected = request.into_inner();
-        log::debug!("set_block_when_disconnected({})", block_when_disconnected);
+    async fn set_api_access_method_enabled(
+        &self,
+        request: Request<types::ApiAccessMethodState>,
+    ) -> ServiceResult<()> {
+        let state = request.into_inner();
+        log::debug!(
+            "set_api_access_method_enabled({}, {})",
+            state.name,
+            state.enabled
+        );
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::SetBlockWhenDisconnected(
+        self.send_command_to_daemon(DaemonCommand::SetApiAccessMethodEnabled(
             tx,
-            block_when_disconnected,
+            state.name,
+            state.enabled,
         ))?;
         self.wait_for_result(rx)
             .await?
             .map(Response::new)
-            .map_err(map_settings_error)
+            .map_err(map_daemon_error)
     }
 
-    async fn set_auto_connect(&self, request: Request<bool>) -> ServiceResult<()> {
-        let auto_connect = request.into_inner();
-        log::debug!("set_auto_connect({})", auto_connect);
+    async fn test_api_proxy(&self, request: Request<types::ApiProxy>) -> ServiceResult<()> {
+        let proxy = ApiProxy::try_from(request.into_inner())?;
+        log::debug!("test_api_proxy({})", proxy);
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::SetAutoConnect(tx, auto_connect))?;
+        self.send_command_to_daemon(DaemonCommand::TestApiProxy(tx, proxy))?;
         self.wait_for_result(rx)
             .await?
             .map(Response::new)
-            .map_err(map_settings_error)
+            .map_err(map_daemon_error)
     }
 
-    async fn set_openvpn_mssfix(&self, request: Request<u32>) -> ServiceResult<()> {
-        let mssfix = request.into_inner();
-        let mssfix = if mssfix != 0 {
-            Some(mssfix as u16)
-        } else {
-            None
-        };
-        log::debug!("set_openvpn_mssfix({:?})", mssfix);
+    async fn use_api_access_method(&self, request: Request<String>) -> ServiceResult<()> {
+        let name = request.into_inner();
+        log::debug!("use_api_access_method({})", name);
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::SetOpenVpnMssfix(tx, mssfix))?;
+        self.send_command_to_daemon(DaemonCommand::UseApiAccessMethod(tx, name))?;
         self.wait_for_result(rx)
             .await?
             .map(Response::new)
-            .map_err(map_settings_error)
+            .map_err(map_daemon_error)
+    }
+
+    async fn set_api_resolution_strategy(
+        &self,
+        request: Request<types::ApiResolutionStrategy>,
+    ) -> ServiceResult<()> {
+        let strategy = ApiResolutionStrategy::try_from(request.into_inner())?;
+        log::debug!("set_api_resolution_strategy({})", strategy);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetApiResolutionStrategy(tx, strategy))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    #[cfg(not(target_os = "android"))]
+    async fn open_api_forwarder(&self, _: Request<()>) -> ServiceResult<String> {
+        log::debug!("open_api_forwarder");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::OpenApiForwarder(tx))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(|address| Response::new(address.to_string()))
+            .map_err(map_daemon_error)
+    }
+
+    #[cfg(target_os = "android")]
+    async fn open_api_forwarder(&self, _: Request<()>) -> ServiceResult<String> {
+        Err(Status::unimplemented(
+            "The API forwarder is not available on Android",
+        ))
+    }
+
+    // Problem reports
+    //
+
+    async fn submit_problem_report(
+        &self,
+        request: Request<types::ProblemReport>,
+    ) -> ServiceResult<types::ProblemReportSubmission> {
+        log::debug!("submit_problem_report");
+        let report = request.into_inner();
+        let report = ProblemReport {
+            email: report.email,
+            message: report.message,
+            log: report.log,
+            metadata: report.metadata.into_iter().collect(),
+        };
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SubmitProblemReport(tx, report))?;
+        let queued_id = self.wait_for_result(rx).await?.map_err(map_daemon_error)?;
+        Ok(Response::new(types::ProblemReportSubmission {
+            queued: queued_id.is_some(),
+            id: queued_id.unwrap_or_default(),
+        }))
+    }
+
+    async fn get_pending_problem_reports(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::PendingProblemReports> {
+        log::debug!("get_pending_problem_reports");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetPendingProblemReports(tx))?;
+        let reports = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::PendingProblemReports {
+            reports: reports
+                .into_iter()
+                .map(|report| types::PendingProblemReport {
+                    id: report.id,
+                    created: Some(types::Timestamp {
+                        seconds: report.created.timestamp(),
+                        nanos: report.created.timestamp_subsec_nanos() as i32,
+                    }),
+                    attempts: report.attempts,
+                    last_error: report.last_error.unwrap_or_default(),
+                    size: report.size,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn cancel_pending_problem_report(&self, request: Request<String>) -> ServiceResult<()> {
+        let id = request.into_inner();
+        log::debug!("cancel_pending_problem_report({})", id);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::CancelPendingProblemReport(tx, id))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
+    // Relays and tunnel constraints
+    //
+
+    async fn update_relay_locations(&self, _: Request<()>) -> ServiceResult<()> {
+        log::debug!("update_relay_locations");
+        self.send_command_to_daemon(DaemonCommand::UpdateRelayLocations)?;
+        Ok(Response::new(()))
+    }
+
+    async fn update_relay_settings(
+        &self,
+        request: Request<types::RelaySettingsUpdate>,
+    ) -> ServiceResult<()> {
+        log::debug!("update_relay_settings");
+        let (tx, rx) = oneshot::channel();
+        let constraints_update = RelaySettingsUpdate::try_from(request.into_inner())?;
+
+        let message = DaemonCommand::UpdateRelaySettings(tx, constraints_update);
+        self.send_command_to_daemon(message)?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn get_relay_locations(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<Self::GetRelayLocationsStream> {
+        log::debug!("get_relay_locations");
+
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetRelayLocations(tx))?;
+        let locations = self.wait_for_result(rx).await?;
+
+        let (stream_tx, stream_rx) =
+            tokio::sync::mpsc::channel(cmp::max(1, locations.countries.len()));
+
+        tokio::spawn(async move {
+            for country in locations.countries.into_iter() {
+                if let Err(error) = stream_tx
+                    .send(Ok(types::RelayListCountry::from(country)))
+                    .await
+                {
+                    log::error!(
+                        "Error while sending relays to client: {}",
+                        error.display_chain()
+                    );
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(stream_rx)))
+    }
+
+    async fn get_current_location(&self, _: Request<()>) -> ServiceResult<types::GeoIpLocation> {
+        log::debug!("get_current_location");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetCurrentLocation(tx))?;
+        let result = self.wait_for_result(rx).await?;
+        match result {
+            Some(geoip) => Ok(Response::new(types::GeoIpLocation::from(geoip))),
+            None => Err(Status::not_found("no location was found")),
+        }
+    }
+
+    async fn check_connection(&self, _: Request<()>) -> ServiceResult<types::ConnectionCheck> {
+        log::debug!("check_connection");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::CheckConnection(tx))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(|check| Response::new(types::ConnectionCheck::from(check)))
+            .map_err(map_daemon_error)
+    }
+
+    async fn set_bridge_settings(
+        &self,
+        request: Request<types::BridgeSettings>,
+    ) -> ServiceResult<()> {
+        let settings = BridgeSettings::try_from(request.into_inner())?;
+
+        log::debug!("set_bridge_settings({:?})", settings);
+
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetBridgeSettings(tx, settings))?;
+        let settings_result = self.wait_for_result(rx).await?;
+        settings_result
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_bridge_state(&self, request: Request<types::BridgeState>) -> ServiceResult<()> {
+        let bridge_state = BridgeState::try_from(request.into_inner())?;
+
+        log::debug!("set_bridge_state({:?})", bridge_state);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetBridgeState(tx, bridge_state))?;
+        let settings_result = self.wait_for_result(rx).await?;
+        settings_result
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    // Settings
+    //
+
+    async fn get_settings(&self, _: Request<()>) -> ServiceResult<types::Settings> {
+        log::debug!("get_settings");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetSettings(tx))?;
+        self.wait_for_result(rx)
+            .await
+            .map(|settings| Response::new(types::Settings::from(&settings)))
+    }
+
+    async fn get_settings_schema(&self, _: Request<()>) -> ServiceResult<types::SettingsSchema> {
+        log::debug!("get_settings_schema");
+        Ok(Response::new(types::SettingsSchema::from(
+            Settings::schema(),
+        )))
+    }
+
+    async fn export_settings_profile(&self, _: Request<()>) -> ServiceResult<String> {
+        log::debug!("export_settings_profile");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ExportSettingsProfile(tx))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
+    async fn import_settings_profile(
+        &self,
+        request: Request<types::SettingsProfileImport>,
+    ) -> ServiceResult<types::SettingsProfileImportResult> {
+        use types::settings_profile_import::ConflictResolution as ProtoResolution;
+
+        let request = request.into_inner();
+        let resolution = match ProtoResolution::from_i32(request.conflict_resolution) {
+            Some(ProtoResolution::Abort) => ConflictResolution::Abort,
+            Some(ProtoResolution::Overwrite) => ConflictResolution::Overwrite,
+            Some(ProtoResolution::KeepLocal) => ConflictResolution::KeepLocal,
+            None => return Err(Status::invalid_argument("unknown conflict resolution")),
+        };
+        log::debug!("import_settings_profile({:?})", resolution);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ImportSettingsProfile(
+            tx,
+            request.profile,
+            resolution,
+        ))?;
+        let report = self.wait_for_result(rx).await?.map_err(map_daemon_error)?;
+        Ok(Response::new(types::SettingsProfileImportResult {
+            applied: report.applied,
+            changed_settings: report.changed_settings,
+            conflicts: report.conflicts,
+        }))
+    }
+
+    async fn set_allow_lan(&self, request: Request<bool>) -> ServiceResult<()> {
+        let allow_lan = request.into_inner();
+        log::debug!("set_allow_lan({})", allow_lan);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetAllowLan(tx, allow_lan))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    #[cfg(windows)]
+    async fn set_allow_virtual_networks(&self, request: Request<bool>) -> ServiceResult<()> {
+        let allow = request.into_inner();
+        log::debug!("set_allow_virtual_networks({})", allow);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetAllowVirtualNetworks(tx, allow))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+    #[cfg(not(windows))]
+    async fn set_allow_virtual_networks(&self, _: Request<bool>) -> ServiceResult<()> {
+        Ok(Response::new(()))
+    }
+
+    async fn add_firewall_exception(
+        &self,
+        request: Request<types::FirewallException>,
+    ) -> ServiceResult<()> {
+        let exception = AllowedEndpoint::try_from(request.into_inner())?;
+        log::debug!("add_firewall_exception({})", exception);
+        if exception.network.prefix() == 0 {
+            return Err(Status::invalid_argument(
+                "an exception for all addresses would disable the firewall",
+            ));
+        }
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::AddFirewallException(tx, exception))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn remove_firewall_exception(
+        &self,
+        request: Request<types::FirewallException>,
+    ) -> ServiceResult<()> {
+        let exception = AllowedEndpoint::try_from(request.into_inner())?;
+        log::debug!("remove_firewall_exception({})", exception);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::RemoveFirewallException(tx, exception))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn allow_temporarily(
+        &self,
+        request: Request<types::TemporaryFirewallException>,
+    ) -> ServiceResult<()> {
+        let request = request.into_inner();
+        let exception = AllowedEndpoint::try_from(
+            request
+                .exception
+                .ok_or_else(|| Status::invalid_argument("missing firewall exception"))?,
+        )?;
+        log::debug!("allow_temporarily({}, {}s)", exception, request.seconds);
+        if exception.network.prefix() == 0 && exception.port.is_none() {
+            return Err(Status::invalid_argument(
+                "an exception for all traffic would disable the firewall",
+            ));
+        }
+        if request.seconds == 0 || request.seconds > MAX_TEMPORARY_EXCEPTION_SECONDS {
+            return Err(Status::invalid_argument(format!(
+                "the duration must be between 1 and {} seconds",
+                MAX_TEMPORARY_EXCEPTION_SECONDS
+            )));
+        }
+        let duration = Duration::from_secs(u64::from(request.seconds));
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::AllowTemporarily(tx, exception, duration))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(|error| {
+                Status::internal(
+                    error.display_chain_with_msg("Failed to add the temporary firewall exception"),
+                )
+            })
+    }
+
+    async fn set_mdns_reflector(&self, request: Request<bool>) -> ServiceResult<()> {
+        let enabled = request.into_inner();
+        log::debug!("set_mdns_reflector({})", enabled);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetMdnsReflector(tx, enabled))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn set_dbus_service(&self, request: Request<bool>) -> ServiceResult<()> {
+        let enabled = request.into_inner();
+        log::debug!("set_dbus_service({})", enabled);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetDbusService(tx, enabled))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+    #[cfg(not(target_os = "linux"))]
+    async fn set_dbus_service(&self, _: Request<bool>) -> ServiceResult<()> {
+        Ok(Response::new(()))
+    }
+
+    async fn set_show_beta_releases(&self, request: Request<bool>) -> ServiceResult<()> {
+        let enabled = request.into_inner();
+        log::debug!("set_show_beta_releases({})", enabled);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetShowBetaReleases(tx, enabled))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_encrypt_sensitive_settings(&self, request: Request<bool>) -> ServiceResult<()> {
+        let enabled = request.into_inner();
+        log::debug!("set_encrypt_sensitive_settings({})", enabled);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetEncryptSensitiveSettings(tx, enabled))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_credential_storage(
+        &self,
+        request: Request<types::CredentialStorage>,
+    ) -> ServiceResult<()> {
+        let storage = CredentialStorage::try_from(request.into_inner())?;
+        log::debug!("set_credential_storage({})", storage);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetCredentialStorage(tx, storage))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_keep_account_history(&self, request: Request<bool>) -> ServiceResult<()> {
+        let enabled = request.into_inner();
+        log::debug!("set_keep_account_history({})", enabled);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetKeepAccountHistory(tx, enabled))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_account_privacy_mode(&self, request: Request<bool>) -> ServiceResult<()> {
+        let enabled = request.into_inner();
+        log::debug!("set_account_privacy_mode({})", enabled);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetAccountPrivacyMode(tx, enabled))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_identity_rotation_interval(&self, request: Request<u64>) -> ServiceResult<()> {
+        let interval = match request.into_inner() {
+            0 => None,
+            secs => Some(HumanDuration(Duration::from_secs(secs))),
+        };
+        log::debug!("set_identity_rotation_interval({:?})", interval);
+        validate_identity_rotation_interval(interval)
+            .map_err(|error| Status::invalid_argument(error.to_string()))?;
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetIdentityRotationInterval(tx, interval))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_account_expiry_settings(
+        &self,
+        request: Request<types::AccountExpirySettings>,
+    ) -> ServiceResult<()> {
+        let account_expiry = AccountExpirySettings::try_from(request.into_inner())?;
+        log::debug!("set_account_expiry_settings({:?})", account_expiry);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetAccountExpirySettings(tx, account_expiry))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_block_when_disconnected(&self, request: Request<bool>) -> ServiceResult<()> {
+        let block_when_disconnected = request.into_inner();
+        log::debug!("set_block_when_disconnected({})", block_when_disconnected);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetBlockWhenDisconnected(
+            tx,
+            block_when_disconnected,
+        ))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_persistent_lockdown(&self, request: Request<bool>) -> ServiceResult<()> {
+        let persistent_lockdown = request.into_inner();
+        log::debug!("set_persistent_lockdown({})", persistent_lockdown);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetPersistentLockdown(
+            tx,
+            persistent_lockdown,
+        ))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_auto_connect(&self, request: Request<bool>) -> ServiceResult<()> {
+        let auto_connect = request.into_inner();
+        log::debug!("set_auto_connect({})", auto_connect);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetAutoConnect(tx, auto_connect))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn add_network_rule(&self, request: Request<types::NetworkRule>) -> ServiceResult<()> {
+        let rule = NetworkRule::try_from(request.into_inner())?;
+        log::debug!("add_network_rule(.., {})", rule.action);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::AddNetworkRule(tx, rule))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn remove_network_rule(
+        &self,
+        request: Request<types::NetworkMatch>,
+    ) -> ServiceResult<()> {
+        let network = NetworkMatch::try_from(request.into_inner())?;
+        log::debug!("remove_network_rule");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::RemoveNetworkRule(tx, network))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_default_network_action(
+        &self,
+        request: Request<types::DefaultNetworkAction>,
+    ) -> ServiceResult<()> {
+        let action = request
+            .into_inner()
+            .action
+            .map(NetworkAction::try_from)
+            .transpose()?;
+        log::debug!("set_default_network_action({:?})", action);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetDefaultNetworkAction(tx, action))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    #[cfg(not(target_os = "android"))]
+    async fn get_current_network(&self, _: Request<()>) -> ServiceResult<types::NetworkIdentity> {
+        log::debug!("get_current_network");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetCurrentNetwork(tx))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(|network| Response::new(types::NetworkIdentity::from(&network)))
+            .ok_or_else(|| Status::unavailable("the current network is not known yet"))
+    }
+
+    #[cfg(target_os = "android")]
+    async fn get_current_network(&self, _: Request<()>) -> ServiceResult<types::NetworkIdentity> {
+        Err(Status::unimplemented(
+            "The current network cannot be detected on Android",
+        ))
+    }
+
+    async fn set_openvpn_mssfix(&self, request: Request<u32>) -> ServiceResult<()> {
+        let mssfix = request.into_inner();
+        let mssfix = if mssfix != 0 {
+            Some(mssfix as u16)
+        } else {
+            None
+        };
+        log::debug!("set_openvpn_mssfix({:?})", mssfix);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetOpenVpnMssfix(tx, mssfix))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_wireguard_mtu(&self, request: Request<u32>) -> ServiceResult<()> {
+        let mtu = request.into_inner();
+        let mtu = if mtu != 0 { Some(mtu as u16) } else { None };
+        log::debug!("set_wireguard_mtu({:?})", mtu);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetWireguardMtu(tx, mtu))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_wireguard_icmp_check_interval(&self, request: Request<u32>) -> ServiceResult<()> {
+        let interval = request.into_inner();
+        let interval = if interval != 0 {
+            let interval = u16::try_from(interval)
+                .ok()
+                .filter(|interval| ICMP_CHECK_INTERVAL_RANGE.contains(interval))
+                .ok_or_else(|| {
+                    let reason = format!(
+                        "must be between {} and {} seconds",
+                        ICMP_CHECK_INTERVAL_RANGE.start(),
+                        ICMP_CHECK_INTERVAL_RANGE.end()
+                    );
+                    Status::invalid_argument(
+                        FieldError::new("icmp_check_interval", reason).to_string(),
+                    )
+                })?;
+            Some(interval)
+        } else {
+            None
+        };
+        log::debug!("set_wireguard_icmp_check_interval({:?})", interval);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetWireguardIcmpCheckInterval(tx, interval))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_wireguard_packet_loss_threshold(
+        &self,
+        request: Request<u32>,
+    ) -> ServiceResult<()> {
+        let threshold = request.into_inner();
+        let threshold = if threshold != 0 {
+            let threshold = u8::try_from(threshold)
+                .ok()
+                .filter(|threshold| PACKET_LOSS_THRESHOLD_RANGE.contains(threshold))
+                .ok_or_else(|| {
+                    let reason = format!(
+                        "must be between {} and {} percent",
+                        PACKET_LOSS_THRESHOLD_RANGE.start(),
+                        PACKET_LOSS_THRESHOLD_RANGE.end()
+                    );
+                    Status::invalid_argument(
+                        FieldError::new("packet_loss_threshold", reason).to_string(),
+                    )
+                })?;
+            Some(threshold)
+        } else {
+            None
+        };
+        log::debug!("set_wireguard_packet_loss_threshold({:?})", threshold);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetWireguardPacketLossThreshold(
+            tx, threshold,
+        ))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_enable_ipv6(&self, request: Request<bool>) -> ServiceResult<()> {
+        let enable_ipv6 = request.into_inner();
+        log::debug!("set_enable_ipv6({})", enable_ipv6);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetEnableIpv6(tx, enable_ipv6))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    #[cfg(not(target_os = "android"))]
+    async fn set_tunnel_interface_name(&self, request: Request<String>) -> ServiceResult<()> {
+        let name = request.into_inner();
+        let name = if name.is_empty() {
+            None
+        } else {
+            validate_interface_name(&name).map_err(|error| {
+                Status::invalid_argument(FieldError::new("interface_name", error).to_string())
+            })?;
+            Some(name)
+        };
+        log::debug!("set_tunnel_interface_name({:?})", name);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetTunnelInterfaceName(tx, name))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
+    #[cfg(target_os = "android")]
+    async fn set_tunnel_interface_name(&self, _: Request<String>) -> ServiceResult<()> {
+        Err(Status::unimplemented(
+            "The tunnel interface cannot be named on Android",
+        ))
+    }
+
+    #[cfg(not(target_os = "android"))]
+    async fn set_dns_options(&self, request: Request<types::DnsOptions>) -> ServiceResult<()> {
+        let options = DnsOptions::try_from(request.into_inner())?;
+        log::debug!("set_dns_options({:?})", options);
+
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetDnsOptions(tx, options))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+    #[cfg(target_os = "android")]
+    async fn set_dns_options(&self, _: Request<types::DnsOptions>) -> ServiceResult<()> {
+        Ok(Response::new(()))
+    }
+
+    async fn detect_dns_forwarders(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::DnsForwarderList> {
+        log::debug!("detect_dns_forwarders");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::DetectDnsForwarders(tx))?;
+        let forwarders = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::DnsForwarderList {
+            forwarders: forwarders
+                .into_iter()
+                .map(|forwarder| types::DnsForwarder {
+                    address: forwarder.address.to_string(),
+                    program: forwarder.program.unwrap_or_default(),
+                })
+                .collect(),
+        }))
     }
 
-    async fn set_wireguard_mtu(&self, request: Request<u32>) -> ServiceResult<()> {
-        let mtu = request.into_inner();
-        let mtu = if mtu != 0 { Some(mtu as u16) } else { None };
-        log::debug!("set_wireguard_mtu({:?})", mtu);
+    async fn set_log_rotation_settings(
+        &self,
+        request: Request<types::LogRotationSettings>,
+    ) -> ServiceResult<()> {
+        let log_rotation = LogRotationSettings::from(request.into_inner());
+        log::debug!("set_log_rotation_settings({:?})", log_rotation);
+        log_rotation
+            .validate()
+            .map_err(|error| Status::invalid_argument(error.to_string()))?;
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::SetWireguardMtu(tx, mtu))?;
+        self.send_command_to_daemon(DaemonCommand::SetLogRotationSettings(tx, log_rotation))?;
         self.wait_for_result(rx)
             .await?
             .map(Response::new)
             .map_err(map_settings_error)
     }
 
-    async fn set_enable_ipv6(&self, request: Request<bool>) -> ServiceResult<()> {
-        let enable_ipv6 = request.into_inner();
-        log::debug!("set_enable_ipv6({})", enable_ipv6);
+    async fn set_ipv6_failure_policy(
+        &self,
+        request: Request<types::Ipv6FailurePolicy>,
+    ) -> ServiceResult<()> {
+        let policy = talpid_types::net::Ipv6FailurePolicy::try_from(request.into_inner())?;
+        log::debug!("set_ipv6_failure_policy({})", policy);
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::SetEnableIpv6(tx, enable_ipv6))?;
+        self.send_command_to_daemon(DaemonCommand::SetIpv6FailurePolicy(tx, policy))?;
         self.wait_for_result(rx)
             .await?
             .map(Response::new)
             .map_err(map_settings_error)
     }
 
-    #[cfg(not(target_os = "android"))]
-    async fn set_dns_options(&self, request: Request<types::DnsOptions>) -> ServiceResult<()> {
-        let options = DnsOptions::try_from(request.into_inner())?;
-        log::debug!("set_dns_options({:?})", options);
+    async fn set_log_privacy(&self, request: Request<types::LogPrivacy>) -> ServiceResult<()> {
+        let log_privacy = LogPrivacyLevel::try_from(request.into_inner())?;
+        log::debug!("set_log_privacy({})", log_privacy);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetLogPrivacy(tx, log_privacy))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
 
+    async fn set_connection_statistics(&self, request: Request<bool>) -> ServiceResult<()> {
+        let enabled = request.into_inner();
+        log::debug!("set_connection_statistics({})", enabled);
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::SetDnsOptions(tx, options))?;
+        self.send_command_to_daemon(DaemonCommand::SetConnectionStatistics(tx, enabled))?;
         self.wait_for_result(rx)
             .await?
             .map(Response::new)
             .map_err(map_settings_error)
     }
-    #[cfg(target_os = "android")]
-    async fn set_dns_options(&self, _: Request<types::DnsOptions>) -> ServiceResult<()> {
-        Ok(Response::new(()))
+
+    async fn set_system_log(&self, request: Request<bool>) -> ServiceResult<()> {
+        let enabled = request.into_inner();
+        log::debug!("set_system_log({})", enabled);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetSystemLog(tx, enabled))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_json_logs(&self, request: Request<bool>) -> ServiceResult<()> {
+        let enabled = request.into_inner();
+        log::debug!("set_json_logs({})", enabled);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetJsonLogs(tx, enabled))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_server_mode(&self, request: Request<bool>) -> ServiceResult<()> {
+        let enabled = request.into_inner();
+        log::debug!("set_server_mode({})", enabled);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetServerMode(tx, enabled))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_lan_proxy_settings(
+        &self,
+        request: Request<types::LanProxySettings>,
+    ) -> ServiceResult<()> {
+        let lan_proxy = LanProxySettings::try_from(request.into_inner())?;
+        log::debug!("set_lan_proxy_settings({:?})", lan_proxy);
+        lan_proxy
+            .validate()
+            .map_err(|error| Status::invalid_argument(error.to_string()))?;
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetLanProxySettings(tx, lan_proxy))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_local_api_settings(
+        &self,
+        request: Request<types::LocalApiSettings>,
+    ) -> ServiceResult<()> {
+        let local_api = LocalApiSettings::try_from(request.into_inner())?;
+        log::debug!("set_local_api_settings({:?})", local_api);
+        local_api
+            .validate()
+            .map_err(|error| Status::invalid_argument(error.to_string()))?;
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetLocalApiSettings(tx, local_api))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_hook_settings(&self, request: Request<types::HookSettings>) -> ServiceResult<()> {
+        let hooks = HookSettings::from(request.into_inner());
+        log::debug!("set_hook_settings({:?})", hooks);
+        hooks
+            .validate()
+            .map_err(|error| Status::invalid_argument(error.to_string()))?;
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetHookSettings(tx, hooks))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_reconnect_backoff(
+        &self,
+        request: Request<types::ReconnectBackoffSettings>,
+    ) -> ServiceResult<()> {
+        let reconnect_backoff = ReconnectBackoffSettings::try_from(request.into_inner())?;
+        log::debug!("set_reconnect_backoff({:?})", reconnect_backoff);
+        reconnect_backoff
+            .validate()
+            .map_err(|error| Status::invalid_argument(error.to_string()))?;
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetReconnectBackoff(tx, reconnect_backoff))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_exit_ip_check(
+        &self,
+        request: Request<types::ExitIpCheckSettings>,
+    ) -> ServiceResult<()> {
+        let exit_ip_check = ExitIpCheckSettings::try_from(request.into_inner())?;
+        log::debug!("set_exit_ip_check({:?})", exit_ip_check);
+        exit_ip_check
+            .validate()
+            .map_err(|error| Status::invalid_argument(error.to_string()))?;
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetExitIpCheck(tx, exit_ip_check))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn get_beta_features(&self, _: Request<()>) -> ServiceResult<types::BetaFeatureList> {
+        log::debug!("get_beta_features");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetSettings(tx))?;
+        self.wait_for_result(rx)
+            .await
+            .map(|settings| Response::new(types::BetaFeatureList::new(&settings.beta_features)))
+    }
+
+    async fn set_beta_feature(
+        &self,
+        request: Request<types::BetaFeatureState>,
+    ) -> ServiceResult<()> {
+        let state = request.into_inner();
+        log::debug!("set_beta_feature({}, {})", state.name, state.enabled);
+        let feature = state
+            .name
+            .parse::<BetaFeature>()
+            .map_err(|error| Status::invalid_argument(error.to_string()))?;
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetBetaFeature(tx, feature, state.enabled))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn get_local_api_token(&self, _: Request<()>) -> ServiceResult<String> {
+        log::debug!("get_local_api_token");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetLocalApiToken(tx))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
+    async fn set_allowed_system_services(
+        &self,
+        request: Request<types::AllowedSystemServices>,
+    ) -> ServiceResult<()> {
+        let services = AllowedSystemServices::from(request.into_inner());
+        log::debug!("set_allowed_system_services({:?})", services);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetAllowedSystemServices(tx, services))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_tunnel_parameter_overrides(
+        &self,
+        request: Request<types::TunnelParameterOverrides>,
+    ) -> ServiceResult<()> {
+        let overrides = TunnelParameterOverrides::try_from(request.into_inner())?;
+        log::debug!("set_tunnel_parameter_overrides({:?})", overrides);
+        overrides
+            .validate()
+            .map_err(|error| Status::invalid_argument(error.to_string()))?;
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetTunnelParameterOverrides(tx, overrides))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
     }
 
     // Account management
@@ -367,87 +1804,215 @@ impl ManagementService for ManagementServiceImpl {
 
     async fn create_new_account(&self, _: Request<()>) -> ServiceResult<String> {
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::CreateNewAccount(tx))?;
+        self.send_command_to_daemon(DaemonCommand::CreateNewAccount(tx))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
+    async fn set_account(&self, request: Request<AccountToken>) -> ServiceResult<()> {
+        log::debug!("set_account");
+        let account_token = request.into_inner();
+        let account_token = if account_token == "" {
+            None
+        } else {
+            Some(account_token)
+        };
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetAccount(tx, account_token))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn get_account_data(
+        &self,
+        request: Request<AccountToken>,
+    ) -> ServiceResult<types::AccountData> {
+        log::debug!("get_account_data");
+        let account_token = request.into_inner();
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetAccountData(tx, account_token))?;
+        let result = self.wait_for_result(rx).await?;
+        result
+            .map(|account_data| {
+                Response::new(types::AccountData {
+                    expiry: Some(types::Timestamp {
+                        seconds: account_data.expiry.timestamp(),
+                        nanos: 0,
+                    }),
+                })
+            })
+            .map_err(|error: RestError| {
+                log::error!(
+                    "Unable to get account data from API: {}",
+                    error.display_chain()
+                );
+                map_rest_error(error)
+            })
+    }
+
+    async fn get_account_history(&self, _: Request<()>) -> ServiceResult<types::AccountHistory> {
+        log::debug!("get_account_history");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetAccountHistory(tx))?;
+        self.wait_for_result(rx).await.map(|history| {
+            Response::new(types::AccountHistory {
+                token: history.first().cloned(),
+                accounts: history,
+            })
+        })
+    }
+
+    async fn clear_account_history(&self, _: Request<()>) -> ServiceResult<()> {
+        log::debug!("clear_account_history");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ClearAccountHistory(tx))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
+    async fn remove_account_from_history(&self, request: Request<String>) -> ServiceResult<()> {
+        log::debug!("remove_account_from_history");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::RemoveAccountFromHistory(
+            tx,
+            request.into_inner(),
+        ))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
+    async fn get_www_auth_token(&self, _: Request<()>) -> ServiceResult<String> {
+        log::debug!("get_www_auth_token");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetWwwAuthToken(tx))?;
+        let result = self.wait_for_result(rx).await?;
+        result.map(Response::new).map_err(|error| {
+            log::error!(
+                "Unable to get account data from API: {}",
+                error.display_chain()
+            );
+            map_daemon_error(error)
+        })
+    }
+
+    async fn list_devices(&self, _: Request<()>) -> ServiceResult<types::DeviceList> {
+        log::debug!("list_devices");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ListDevices(tx))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(|devices| Response::new(types::DeviceList::from(devices)))
+            .map_err(map_daemon_error)
+    }
+
+    async fn rename_device(&self, request: Request<String>) -> ServiceResult<()> {
+        let name = request.into_inner();
+        log::debug!("rename_device({})", name);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::RenameDevice(tx, name))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
+    async fn remove_device(&self, request: Request<String>) -> ServiceResult<()> {
+        let id = request.into_inner();
+        log::debug!("remove_device({})", id);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::RemoveDevice(tx, id))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
+    async fn get_device_state(&self, _: Request<()>) -> ServiceResult<types::DeviceState> {
+        log::debug!("get_device_state");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetDeviceState(tx))?;
+        self.wait_for_result(rx)
+            .await
+            .map(|state| Response::new(types::DeviceState::from(state)))
+    }
+
+    async fn reregister_device(&self, _: Request<()>) -> ServiceResult<()> {
+        log::debug!("reregister_device");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ReregisterDevice(tx))?;
         self.wait_for_result(rx)
             .await?
             .map(Response::new)
             .map_err(map_daemon_error)
     }
 
-    async fn set_account(&self, request: Request<AccountToken>) -> ServiceResult<()> {
-        log::debug!("set_account");
-        let account_token = request.into_inner();
-        let account_token = if account_token == "" {
-            None
-        } else {
-            Some(account_token)
-        };
+    async fn list_port_forwards(&self, _: Request<()>) -> ServiceResult<types::PortForwardList> {
+        log::debug!("list_port_forwards");
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::SetAccount(tx, account_token))?;
+        self.send_command_to_daemon(DaemonCommand::ListPortForwards(tx))?;
         self.wait_for_result(rx)
             .await?
-            .map(Response::new)
-            .map_err(map_settings_error)
-    }
-
-    async fn get_account_data(
-        &self,
-        request: Request<AccountToken>,
-    ) -> ServiceResult<types::AccountData> {
-        log::debug!("get_account_data");
-        let account_token = request.into_inner();
-        let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::GetAccountData(tx, account_token))?;
-        let result = self.wait_for_result(rx).await?;
-        result
-            .map(|account_data| {
-                Response::new(types::AccountData {
-                    expiry: Some(types::Timestamp {
-                        seconds: account_data.expiry.timestamp(),
-                        nanos: 0,
-                    }),
+            .map(|port_forwards| {
+                Response::new(types::PortForwardList {
+                    port_forwards: port_forwards
+                        .into_iter()
+                        .map(types::PortForward::from)
+                        .collect(),
                 })
             })
-            .map_err(|error: RestError| {
-                log::error!(
-                    "Unable to get account data from API: {}",
-                    error.display_chain()
-                );
-                map_rest_error(error)
-            })
+            .map_err(map_daemon_error)
     }
 
-    async fn get_account_history(&self, _: Request<()>) -> ServiceResult<types::AccountHistory> {
-        log::debug!("get_account_history");
+    async fn add_port_forward(
+        &self,
+        request: Request<types::PortForwardLocation>,
+    ) -> ServiceResult<types::PortForward> {
+        let location = request.into_inner();
+        log::debug!(
+            "add_port_forward({}, {})",
+            location.country_code,
+            location.city_code
+        );
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::GetAccountHistory(tx))?;
+        self.send_command_to_daemon(DaemonCommand::AddPortForward(
+            tx,
+            location.country_code,
+            location.city_code,
+        ))?;
         self.wait_for_result(rx)
-            .await
-            .map(|history| Response::new(types::AccountHistory { token: history }))
+            .await?
+            .map(|port_forward| Response::new(types::PortForward::from(port_forward)))
+            .map_err(map_daemon_error)
     }
 
-    async fn clear_account_history(&self, _: Request<()>) -> ServiceResult<()> {
-        log::debug!("clear_account_history");
+    async fn remove_port_forward(&self, request: Request<String>) -> ServiceResult<()> {
+        let id = request.into_inner();
+        log::debug!("remove_port_forward({})", id);
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::ClearAccountHistory(tx))?;
+        self.send_command_to_daemon(DaemonCommand::RemovePortForward(tx, id))?;
         self.wait_for_result(rx)
             .await?
             .map(Response::new)
             .map_err(map_daemon_error)
     }
 
-    async fn get_www_auth_token(&self, _: Request<()>) -> ServiceResult<String> {
-        log::debug!("get_www_auth_token");
+    async fn set_port_forwarding(&self, request: Request<bool>) -> ServiceResult<()> {
+        let enabled = request.into_inner();
+        log::debug!("set_port_forwarding({})", enabled);
         let (tx, rx) = oneshot::channel();
-        self.send_command_to_daemon(DaemonCommand::GetWwwAuthToken(tx))?;
-        let result = self.wait_for_result(rx).await?;
-        result.map(Response::new).map_err(|error| {
-            log::error!(
-                "Unable to get account data from API: {}",
-                error.display_chain()
-            );
-            map_daemon_error(error)
-        })
+        self.send_command_to_daemon(DaemonCommand::SetPortForwarding(tx, enabled))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
     }
 
     async fn submit_voucher(
@@ -475,6 +2040,13 @@ impl ManagementService for ManagementServiceImpl {
             })
     }
 
+    async fn redeem_voucher(
+        &self,
+        request: Request<String>,
+    ) -> ServiceResult<types::VoucherSubmission> {
+        self.submit_voucher(request).await
+    }
+
     // WireGuard key management
     //
 
@@ -483,10 +2055,15 @@ impl ManagementService for ManagementServiceImpl {
         request: Request<types::Duration>,
     ) -> ServiceResult<()> {
         let interval: RotationInterval = Duration::try_from(request.into_inner())
-            .map_err(|_| Status::invalid_argument("unexpected negative rotation interval"))?
+            .map_err(|_| {
+                Status::invalid_argument(
+                    FieldError::new("rotation_interval", "unexpected negative duration")
+                        .to_string(),
+                )
+            })?
             .try_into()
             .map_err(|error: RotationIntervalError| {
-                Status::invalid_argument(error.display_chain())
+                Status::invalid_argument(FieldError::new("rotation_interval", error).to_string())
             })?;
 
         log::debug!("set_wireguard_rotation_interval({:?})", interval);
@@ -511,6 +2088,52 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn set_quantum_resistant_tunnel(
+        &self,
+        request: Request<types::QuantumResistantState>,
+    ) -> ServiceResult<()> {
+        let state =
+            mullvad_types::wireguard::QuantumResistantState::try_from(request.into_inner())?;
+        log::debug!("set_quantum_resistant_tunnel({})", state);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetQuantumResistantTunnel(tx, state))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_obfuscation_settings(
+        &self,
+        request: Request<types::ObfuscationSettings>,
+    ) -> ServiceResult<()> {
+        let obfuscation_settings = ObfuscationSettings::try_from(request.into_inner())?;
+        log::debug!("set_obfuscation_settings({:?})", obfuscation_settings);
+        obfuscation_settings
+            .validate()
+            .map_err(|error| Status::invalid_argument(error.to_string()))?;
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetObfuscationSettings(
+            tx,
+            obfuscation_settings,
+        ))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_connection_profiles(&self, request: Request<bool>) -> ServiceResult<()> {
+        let enabled = request.into_inner();
+        log::debug!("set_connection_profiles({})", enabled);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetConnectionProfiles(tx, enabled))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
     async fn generate_wireguard_key(&self, _: Request<()>) -> ServiceResult<types::KeygenEvent> {
         // TODO: return error for TooManyKeys, GenerationFailure
         // on success, simply return the new key or nil
@@ -626,7 +2249,7 @@ impl ManagementService for ManagementServiceImpl {
         }
     }
 
-    #[cfg(windows)]
+    #[cfg(any(windows, target_os = "linux"))]
     async fn add_split_tunnel_app(&self, request: Request<String>) -> ServiceResult<()> {
         log::debug!("add_split_tunnel_app");
         let path = PathBuf::from(request.into_inner());
@@ -637,12 +2260,12 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_daemon_error)
             .map(Response::new)
     }
-    #[cfg(not(windows))]
+    #[cfg(not(any(windows, target_os = "linux")))]
     async fn add_split_tunnel_app(&self, _: Request<String>) -> ServiceResult<()> {
         Ok(Response::new(()))
     }
 
-    #[cfg(windows)]
+    #[cfg(any(windows, target_os = "linux"))]
     async fn remove_split_tunnel_app(&self, request: Request<String>) -> ServiceResult<()> {
         log::debug!("remove_split_tunnel_app");
         let path = PathBuf::from(request.into_inner());
@@ -653,12 +2276,12 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_daemon_error)
             .map(Response::new)
     }
-    #[cfg(not(windows))]
+    #[cfg(not(any(windows, target_os = "linux")))]
     async fn remove_split_tunnel_app(&self, _: Request<String>) -> ServiceResult<()> {
         Ok(Response::new(()))
     }
 
-    #[cfg(windows)]
+    #[cfg(any(windows, target_os = "linux"))]
     async fn clear_split_tunnel_apps(&self, _: Request<()>) -> ServiceResult<()> {
         log::debug!("clear_split_tunnel_apps");
         let (tx, rx) = oneshot::channel();
@@ -668,12 +2291,36 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_daemon_error)
             .map(Response::new)
     }
-    #[cfg(not(windows))]
+    #[cfg(not(any(windows, target_os = "linux")))]
     async fn clear_split_tunnel_apps(&self, _: Request<()>) -> ServiceResult<()> {
         Ok(Response::new(()))
     }
 
-    #[cfg(windows)]
+    #[cfg(any(windows, target_os = "linux"))]
+    async fn set_split_tunnel_apps(
+        &self,
+        request: Request<types::SplitTunnelApps>,
+    ) -> ServiceResult<()> {
+        log::debug!("set_split_tunnel_apps");
+        let paths: HashSet<PathBuf> = request
+            .into_inner()
+            .apps
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetSplitTunnelApps(tx, paths))?;
+        self.wait_for_result(rx)
+            .await?
+            .map_err(map_daemon_error)
+            .map(Response::new)
+    }
+    #[cfg(not(any(windows, target_os = "linux")))]
+    async fn set_split_tunnel_apps(&self, _: Request<types::SplitTunnelApps>) -> ServiceResult<()> {
+        Ok(Response::new(()))
+    }
+
+    #[cfg(any(windows, target_os = "linux"))]
     async fn set_split_tunnel_state(&self, request: Request<bool>) -> ServiceResult<()> {
         log::debug!("set_split_tunnel_state");
         let enabled = request.into_inner();
@@ -684,7 +2331,7 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_daemon_error)
             .map(Response::new)
     }
-    #[cfg(not(windows))]
+    #[cfg(not(any(windows, target_os = "linux")))]
     async fn set_split_tunnel_state(&self, _: Request<bool>) -> ServiceResult<()> {
         Ok(Response::new(()))
     }
@@ -704,6 +2351,61 @@ impl ManagementService for ManagementServiceImpl {
     async fn set_use_wireguard_nt(&self, _: Request<bool>) -> ServiceResult<()> {
         Ok(Response::new(()))
     }
+
+    #[cfg(windows)]
+    async fn set_use_openvpn_dco(&self, request: Request<bool>) -> ServiceResult<()> {
+        log::debug!("set_use_openvpn_dco");
+        let state = request.into_inner();
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::UseOvpnDco(tx, state))?;
+        self.wait_for_result(rx)
+            .await?
+            .map_err(map_daemon_error)
+            .map(Response::new)
+    }
+    #[cfg(not(windows))]
+    async fn set_use_openvpn_dco(&self, _: Request<bool>) -> ServiceResult<()> {
+        Ok(Response::new(()))
+    }
+
+    #[cfg(windows)]
+    async fn set_wintun_adapter_policy(
+        &self,
+        request: Request<types::WintunAdapterPolicy>,
+    ) -> ServiceResult<()> {
+        let policy =
+            talpid_types::net::openvpn::WintunAdapterPolicy::try_from(request.into_inner())?;
+        log::debug!("set_wintun_adapter_policy({})", policy);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetWintunAdapterPolicy(tx, policy))?;
+        self.wait_for_result(rx)
+            .await?
+            .map_err(map_daemon_error)
+            .map(Response::new)
+    }
+    #[cfg(not(windows))]
+    async fn set_wintun_adapter_policy(
+        &self,
+        _: Request<types::WintunAdapterPolicy>,
+    ) -> ServiceResult<()> {
+        Ok(Response::new(()))
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn set_use_userspace_wireguard(&self, request: Request<bool>) -> ServiceResult<()> {
+        log::debug!("set_use_userspace_wireguard");
+        let state = request.into_inner();
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::UseUserspaceWireguard(tx, state))?;
+        self.wait_for_result(rx)
+            .await?
+            .map_err(map_daemon_error)
+            .map(Response::new)
+    }
+    #[cfg(not(target_os = "linux"))]
+    async fn set_use_userspace_wireguard(&self, _: Request<bool>) -> ServiceResult<()> {
+        Ok(Response::new(()))
+    }
 }
 
 impl ManagementServiceImpl {
@@ -720,7 +2422,8 @@ impl ManagementServiceImpl {
 }
 
 pub struct ManagementInterfaceServer {
-    subscriptions: Arc<RwLock<Vec<EventsListenerSender>>>,
+    subscriptions: Arc<RwLock<Vec<EventsListener>>>,
+    transition_subscriptions: Arc<RwLock<Vec<TransitionsListenerSender>>>,
     socket_path: String,
     server_abort_tx: triggered::Trigger,
     server_join_handle: Option<
@@ -730,7 +2433,8 @@ pub struct ManagementInterfaceServer {
 
 impl ManagementInterfaceServer {
     pub async fn start(tunnel_tx: DaemonCommandSender) -> Result<Self, Error> {
-        let subscriptions = Arc::<RwLock<Vec<EventsListenerSender>>>::default();
+        let subscriptions = Arc::<RwLock<Vec<EventsListener>>>::default();
+        let transition_subscriptions = Arc::<RwLock<Vec<TransitionsListenerSender>>>::default();
 
         let socket_path = mullvad_paths::get_rpc_socket_path()
             .to_string_lossy()
@@ -741,6 +2445,7 @@ impl ManagementInterfaceServer {
         let server = ManagementServiceImpl {
             daemon_tx: tunnel_tx,
             subscriptions: subscriptions.clone(),
+            transition_subscriptions: transition_subscriptions.clone(),
         };
         let server_join_handle = tokio::spawn(mullvad_management_interface::spawn_rpc_server(
             server,
@@ -758,6 +2463,7 @@ impl ManagementInterfaceServer {
 
         Ok(ManagementInterfaceServer {
             subscriptions,
+            transition_subscriptions,
             socket_path,
             server_abort_tx,
             server_join_handle: Some(server_join_handle),
@@ -771,6 +2477,8 @@ impl ManagementInterfaceServer {
     pub fn event_broadcaster(&self) -> ManagementInterfaceEventBroadcaster {
         ManagementInterfaceEventBroadcaster {
             subscriptions: self.subscriptions.clone(),
+            transition_subscriptions: self.transition_subscriptions.clone(),
+            last_settings: Arc::default(),
             close_handle: self.server_abort_tx.clone(),
         }
     }
@@ -789,30 +2497,140 @@ impl ManagementInterfaceServer {
 /// A handle that allows broadcasting messages to all subscribers of the management interface.
 #[derive(Clone)]
 pub struct ManagementInterfaceEventBroadcaster {
-    subscriptions: Arc<RwLock<Vec<EventsListenerSender>>>,
+    subscriptions: Arc<RwLock<Vec<EventsListener>>>,
+    transition_subscriptions: Arc<RwLock<Vec<TransitionsListenerSender>>>,
+    /// The most recently broadcast settings, used to determine which settings changed.
+    last_settings: Arc<Mutex<Option<Settings>>>,
     close_handle: triggered::Trigger,
 }
 
 impl EventListener for ManagementInterfaceEventBroadcaster {
-    /// Sends a new state update to all `new_state` subscribers of the management interface.
-    fn notify_new_state(&self, new_state: TunnelState) {
+    /// Sends a new state update to all `new_state` subscribers of the management interface, and
+    /// the state along with the reason for the transition to all transition subscribers.
+    fn notify_new_state(&self, new_state: TunnelState, reason: Option<TransitionReason>) {
+        let new_state = types::TunnelState::from(new_state);
+        let transition = types::TunnelTransition {
+            state: Some(new_state.clone()),
+            reason: reason.map(types::TransitionReason::from),
+        };
+        self.transition_subscriptions.write().retain(|tx| {
+            match tx.try_send(Ok(transition.clone())) {
+                Ok(()) => true,
+                Err(tokio::sync::mpsc::error::TrySendError::Full(_)) => {
+                    log::warn!(
+                        "Disconnecting management interface subscriber that is not reading events"
+                    );
+                    false
+                }
+                Err(tokio::sync::mpsc::error::TrySendError::Closed(_)) => false,
+            }
+        });
+
         self.notify(types::DaemonEvent {
-            event: Some(daemon_event::Event::TunnelState(types::TunnelState::from(
-                new_state,
-            ))),
+            event: Some(daemon_event::Event::TunnelState(new_state)),
         })
     }
 
-    /// Sends settings to all `settings` subscribers of the management interface.
+    /// Sends the names of the changed settings to all subscribers of the management interface.
+    /// Subscribers that asked for it also receive the complete settings.
     fn notify_settings(&self, settings: Settings) {
-        log::debug!("Broadcasting new settings");
+        let keys = {
+            let mut last_settings = self.last_settings.lock();
+            let keys = match &*last_settings {
+                Some(last_settings) => Settings::changed_keys(last_settings, &settings),
+                None => settings.keys(),
+            };
+            *last_settings = Some(settings.clone());
+            keys
+        };
+        if keys.is_empty() {
+            return;
+        }
+        log::debug!("Broadcasting changed settings: {}", keys.join(", "));
+
+        let settings_changed_event = |settings| types::DaemonEvent {
+            event: Some(daemon_event::Event::SettingsChanged(
+                types::SettingsChanged {
+                    keys: keys.clone(),
+                    settings,
+                },
+            )),
+        };
+        let keys_only_event = settings_changed_event(None);
+        let full_event = settings_changed_event(Some(types::Settings::from(&settings)));
+
+        let mut subscriptions = self.subscriptions.write();
+        subscriptions.retain(|listener| {
+            let event = if listener.include_full_settings {
+                &full_event
+            } else {
+                &keys_only_event
+            };
+            listener.send(event)
+        });
+    }
+
+    /// Sends the device state to all subscribers of the management interface.
+    fn notify_device_state(&self, device_state: DeviceState) {
+        log::debug!("Broadcasting new device state: {:?}", device_state);
         self.notify(types::DaemonEvent {
-            event: Some(daemon_event::Event::Settings(types::Settings::from(
-                &settings,
+            event: Some(daemon_event::Event::DeviceState(types::DeviceState::from(
+                device_state,
             ))),
         })
     }
 
+    /// Sends the new name of this device to all subscribers of the management interface.
+    fn notify_device_identity(&self, change: DeviceIdentityChange) {
+        log::debug!("Broadcasting device identity change: {:?}", change);
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::DeviceIdentity(
+                types::DeviceIdentityChange::from(change),
+            )),
+        })
+    }
+
+    /// Sends exit IP mismatches to all subscribers of the management interface.
+    fn notify_exit_ip_mismatch(&self, mismatch: ExitIpMismatch) {
+        log::debug!("Broadcasting exit IP mismatch: {:?}", mismatch);
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::ExitIpMismatch(
+                types::ExitIpMismatch::from(mismatch),
+            )),
+        })
+    }
+
+    /// Sends changes of the API access method to all subscribers of the management interface.
+    fn notify_api_access_method(&self, method: ActiveApiAccessMethod) {
+        log::debug!("Broadcasting API access method: {:?}", method);
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::ApiAccessMethod(
+                types::ActiveApiAccessMethod::from(method),
+            )),
+        })
+    }
+
+    /// Sends account expiry warnings to all subscribers of the management interface.
+    fn notify_account_expiry(&self, event: AccountExpiryEvent) {
+        log::debug!("Broadcasting account expiry event: {:?}", event);
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::AccountExpiry(
+                types::AccountExpiryEvent::from(event),
+            )),
+        })
+    }
+
+    /// Sends the other VPN software that was detected to all subscribers of the management
+    /// interface.
+    fn notify_conflicting_software(&self, conflicts: Vec<ConflictingSoftware>) {
+        log::debug!("Broadcasting conflicting software: {:?}", conflicts);
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::ConflictingSoftware(
+                convert_conflicting_software(conflicts),
+            )),
+        })
+    }
+
     /// Sends relays to all subscribers of the management interface.
     fn notify_relay_list(&self, relay_list: RelayList) {
         log::debug!("Broadcasting new relay list");
@@ -853,7 +2671,7 @@ impl ManagementInterfaceEventBroadcaster {
     fn notify(&self, value: types::DaemonEvent) {
         let mut subscriptions = self.subscriptions.write();
         // TODO: using write-lock everywhere. use a mutex instead?
-        subscriptions.retain(|tx| tx.send(Ok(value.clone())).is_ok());
+        subscriptions.retain(|listener| listener.send(&value));
     }
 }
 
@@ -863,6 +2681,96 @@ impl Drop for ManagementInterfaceEventBroadcaster {
     }
 }
 
+fn convert_tunnel_stats(stats: talpid_core::tunnel::TunnelStats) -> types::TunnelStats {
+    types::TunnelStats {
+        rx_bytes: stats.counters.rx_bytes,
+        tx_bytes: stats.counters.tx_bytes,
+        rx_rate: stats.rx_rate,
+        tx_rate: stats.tx_rate,
+    }
+}
+
+fn convert_connect_time_histogram(
+    histogram: connect_times::Histogram,
+) -> types::ConnectTimeHistogram {
+    types::ConnectTimeHistogram {
+        buckets: histogram.buckets,
+        median: Some(types::Duration::from(histogram.median)),
+        p90: Some(types::Duration::from(histogram.p90)),
+        max: Some(types::Duration::from(histogram.max)),
+    }
+}
+
+fn convert_conflicting_software(
+    conflicts: Vec<ConflictingSoftware>,
+) -> types::ConflictingSoftwareList {
+    use types::conflicting_software::Kind;
+
+    types::ConflictingSoftwareList {
+        conflicts: conflicts
+            .into_iter()
+            .map(|conflict| types::ConflictingSoftware {
+                kind: match conflict.kind {
+                    ConflictKind::WfpProvider => Kind::WfpProvider as i32,
+                    ConflictKind::TunnelInterface => Kind::TunnelInterface as i32,
+                    ConflictKind::NetworkManagerVpn => Kind::NetworkManagerVpn as i32,
+                    ConflictKind::SystemVpn => Kind::SystemVpn as i32,
+                },
+                name: conflict.name,
+            })
+            .collect(),
+    }
+}
+
+fn convert_missing_capabilities(
+    missing: Vec<MissingCapability>,
+    read_only_settings: bool,
+) -> types::MissingCapabilityList {
+    use types::missing_capability::Kind;
+
+    types::MissingCapabilityList {
+        capabilities: missing
+            .into_iter()
+            .map(|capability| types::MissingCapability {
+                kind: match capability.kind {
+                    CapabilityKind::Privilege => Kind::Privilege as i32,
+                    CapabilityKind::Device => Kind::Device as i32,
+                    CapabilityKind::Firewall => Kind::Firewall as i32,
+                    CapabilityKind::Driver => Kind::Driver as i32,
+                },
+                name: capability.name,
+                reason: capability.reason,
+            })
+            .collect(),
+        read_only_settings,
+    }
+}
+
+#[cfg(windows)]
+fn convert_migration_report(report: crate::migration::MigrationReport) -> types::MigrationReport {
+    use crate::migration::MigrationStep;
+    use types::migration_report::{Failure, Step};
+
+    let convert_step = |step| match step {
+        MigrationStep::FirewallObjects => Step::FirewallObjects as i32,
+        MigrationStep::SplitTunnelDriver => Step::SplitTunnelDriver as i32,
+        MigrationStep::Settings => Step::Settings as i32,
+    };
+    types::MigrationReport {
+        requested_by: report.requested_by.unwrap_or_default(),
+        daemon_version: report.daemon_version,
+        finished: Some(types::Timestamp {
+            seconds: report.finished.timestamp(),
+            nanos: report.finished.timestamp_subsec_nanos() as i32,
+        }),
+        completed: report.completed.into_iter().map(convert_step).collect(),
+        failure: report.failure.map(|failure| Failure {
+            step: convert_step(failure.step),
+            error: failure.error,
+        }),
+    }
+}
+
 /// Converts [`mullvad_daemon::Error`] into a tonic status.
 fn map_daemon_error(error: crate::Error) -> Status {
     use crate::Error as DaemonError;
@@ -876,10 +2784,51 @@ fn map_daemon_error(error: crate::Error) -> Status {
         DaemonError::NoAccountToken | DaemonError::NoAccountTokenHistory => {
             Status::unauthenticated(error.to_string())
         }
+        DaemonError::NoCurrentDevice => Status::not_found(error.to_string()),
+        DaemonError::RemoveCurrentDevice
+        | DaemonError::DeviceNotRevoked
+        | DaemonError::DeviceRevoked => Status::failed_precondition(error.to_string()),
+        DaemonError::TooManyKeys => Status::resource_exhausted(error.to_string()),
+        DaemonError::EmptyDeviceName | DaemonError::UnknownPortForwardCity(..) => {
+            Status::invalid_argument(error.to_string())
+        }
+        DaemonError::NoKeyAvailable | DaemonError::NoWireguardTunnel => {
+            Status::failed_precondition(error.to_string())
+        }
+        DaemonError::ApiAccessMethodExists(_) | DaemonError::TunnelInterfaceNameInUse(_) => {
+            Status::already_exists(error.to_string())
+        }
+        DaemonError::ApiAccessMethodNotFound(_) => Status::not_found(error.to_string()),
+        DaemonError::ProblemReportOutbox(crate::problem_report_outbox::Error::NotFound(_)) => {
+            Status::not_found(error.to_string())
+        }
+        DaemonError::ApiProxyProbeError(ref io_error)
+        | DaemonError::ApiAccessMethodUnreachable(_, ref io_error) => {
+            Status::unavailable(format!("{}: {}", error, io_error))
+        }
+        DaemonError::ApiForwarderError(ref io_error) => {
+            Status::internal(format!("{}: {}", error, io_error))
+        }
+        DaemonError::BuiltinApiAccessMethod(_) => Status::invalid_argument(error.to_string()),
+        DaemonError::SettingsProfile(ref profile_error) => {
+            Status::invalid_argument(format!("{}: {}", error, profile_error))
+        }
+        DaemonError::ApiAccessMethodSuperseded => Status::aborted(error.to_string()),
         error => Status::unknown(error.to_string()),
     }
 }
 
+/// Converts [`relay_latency::Error`] into a tonic status.
+fn map_relay_latency_error(error: relay_latency::Error) -> Status {
+    match error {
+        #[cfg(not(target_os = "linux"))]
+        relay_latency::Error::TunnelUp => Status::failed_precondition(error.to_string()),
+        relay_latency::Error::AllowRelays(_) | relay_latency::Error::TunnelStateMachineDown => {
+            Status::internal(error.display_chain())
+        }
+    }
+}
+
 #[cfg(windows)]
 /// Converts [`talpid_core::split_tunnel::Error`] into a tonic status.
 fn map_split_tunnel_error(error: talpid_core::split_tunnel::Error) -> Status {
@@ -900,14 +2849,29 @@ fn map_split_tunnel_error(error: talpid_core::split_tunnel::Error) -> Status {
 /// Converts a REST API voucher error into a tonic status.
 fn map_rest_voucher_error(error: RestError) -> Status {
     match error {
-        RestError::ApiError(StatusCode::BAD_REQUEST, message) => match &message.as_str() {
-            &mullvad_rpc::INVALID_VOUCHER => Status::new(Code::NotFound, INVALID_VOUCHER_MESSAGE),
-
-            &mullvad_rpc::VOUCHER_USED => {
-                Status::new(Code::ResourceExhausted, USED_VOUCHER_MESSAGE)
+        RestError::ApiError(StatusCode::BAD_REQUEST, code) => match code {
+            ApiErrorCode::InvalidVoucher => map_api_error(
+                StatusCode::BAD_REQUEST,
+                code,
+                Code::NotFound,
+                INVALID_VOUCHER_MESSAGE,
+            ),
+            ApiErrorCode::VoucherUsed => map_api_error(
+                StatusCode::BAD_REQUEST,
+                code,
+                Code::ResourceExhausted,
+                USED_VOUCHER_MESSAGE,
+            ),
+            ApiErrorCode::VoucherExpired => map_api_error(
+                StatusCode::BAD_REQUEST,
+                code,
+                Code::FailedPrecondition,
+                EXPIRED_VOUCHER_MESSAGE,
+            ),
+            code => {
+                let message = format!("Voucher error: {}", code);
+                map_api_error(StatusCode::BAD_REQUEST, code, Code::Unknown, message)
             }
-
-            error => Status::unknown(format!("Voucher error: {}", error)),
         },
         error => map_rest_error(error),
     }
@@ -916,26 +2880,108 @@ fn map_rest_voucher_error(error: RestError) -> Status {
 /// Converts a REST API error into a tonic status.
 fn map_rest_error(error: RestError) -> Status {
     match error {
-        RestError::ApiError(status, message)
+        RestError::ApiError(status, code)
             if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN =>
         {
-            Status::new(Code::Unauthenticated, message)
+            let message = code.to_string();
+            map_api_error(status, code, Code::Unauthenticated, message)
+        }
+        RestError::ApiError(status, code) => {
+            let message = format!("REST error: {}", RestError::ApiError(status, code.clone()));
+            map_api_error(status, code, Code::Unknown, message)
         }
         RestError::TimeoutError(_elapsed) => Status::deadline_exceeded("API request timed out"),
         RestError::HyperError(_) => Status::unavailable("Cannot reach the API"),
+        RestError::RateLimited(retry_after) => map_rate_limited_error(retry_after),
+        RestError::ClockSkew(skew) => map_clock_skew_error(skew),
         error => Status::unknown(format!("REST error: {}", error)),
     }
 }
 
+/// Converts a rate limited API request into a tonic status. The time to wait before trying again
+/// is attached to the status details.
+fn map_rate_limited_error(retry_after: Option<Duration>) -> Status {
+    // Round up so that clients never try again too early
+    let retry_after = retry_after
+        .map(|duration| duration.as_secs() + u64::from(duration.subsec_nanos() > 0))
+        .unwrap_or(0);
+    let message = if retry_after > 0 {
+        format!("Too many requests. Try again in {} seconds", retry_after)
+    } else {
+        "Too many requests. Try again later".to_string()
+    };
+    types::ApiError {
+        code: i32::from(types::api_error::Code::RateLimited),
+        raw_code: String::new(),
+        http_status: u32::from(StatusCode::TOO_MANY_REQUESTS.as_u16()),
+        retry_after,
+        clock_skew: 0,
+    }
+    .into_status(Code::ResourceExhausted, message)
+}
+
+/// Converts an API request that failed because of a wrong system clock into a tonic status. How
+/// far the clock is off is attached to the status details.
+fn map_clock_skew_error(skew: ClockSkew) -> Status {
+    let message = format!(
+        "The system clock is {} compared to the Mullvad API. Correct the date and time settings \
+         and try again",
+        skew
+    );
+    types::ApiError {
+        code: i32::from(types::api_error::Code::ClockSkew),
+        raw_code: String::new(),
+        http_status: 0,
+        retry_after: 0,
+        clock_skew: skew.seconds(),
+    }
+    .into_status(Code::FailedPrecondition, message)
+}
+
+/// Converts an error response from the API into a tonic status, with the API error code attached
+/// to the status details so that clients do not have to parse the message.
+fn map_api_error(
+    status: StatusCode,
+    code: ApiErrorCode,
+    status_code: Code,
+    message: impl Into<String>,
+) -> Status {
+    use types::api_error::Code as ProtoCode;
+
+    let proto_code = match code {
+        ApiErrorCode::InvalidAccount => ProtoCode::InvalidAccount,
+        ApiErrorCode::InvalidAuth => ProtoCode::InvalidAuth,
+        ApiErrorCode::InvalidVoucher => ProtoCode::InvalidVoucher,
+        ApiErrorCode::VoucherUsed => ProtoCode::VoucherUsed,
+        ApiErrorCode::VoucherExpired => ProtoCode::VoucherExpired,
+        ApiErrorCode::KeyLimitReached => ProtoCode::KeyLimitReached,
+        ApiErrorCode::MaxDevicesReached => ProtoCode::MaxDevicesReached,
+        ApiErrorCode::DeviceNotFound => ProtoCode::DeviceNotFound,
+        ApiErrorCode::NotFound => ProtoCode::NotFound,
+        ApiErrorCode::MethodNotAllowed => ProtoCode::MethodNotAllowed,
+        ApiErrorCode::Other(_) => ProtoCode::Other,
+    };
+    types::ApiError {
+        code: i32::from(proto_code),
+        raw_code: code.to_string(),
+        http_status: u32::from(status.as_u16()),
+        retry_after: 0,
+        clock_skew: 0,
+    }
+    .into_status(status_code, message)
+}
+
 /// Converts an instance of [`mullvad_daemon::settings::Error`] into a tonic status.
 fn map_settings_error(error: settings::Error) -> Status {
     match error {
         settings::Error::DeleteError(..)
         | settings::Error::WriteError(..)
-        | settings::Error::SetPermissions(..) => {
-            Status::new(Code::FailedPrecondition, error.to_string())
-        }
+        | settings::Error::SetPermissions(..)
+        | settings::Error::ReadOnly => Status::new(Code::FailedPrecondition, error.to_string()),
         settings::Error::SerializeError(..) => Status::new(Code::Internal, error.to_string()),
+        settings::Error::EncryptionError(..) | settings::Error::CredentialStoreError(..) => {
+            Status::new(Code::Unavailable, error.to_string())
+        }
     }
 }
 
@@ -950,3 +2996,136 @@ fn map_account_history_error(error: account_history::Error) -> Status {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use types::api_error::Code as ProtoCode;
+
+    fn api_error_details(status: &Status) -> types::ApiError {
+        types::ApiError::from_status(status).expect("missing API error details")
+    }
+
+    #[test]
+    fn test_map_rest_error() {
+        let cases = [
+            (
+                StatusCode::UNAUTHORIZED,
+                ApiErrorCode::InvalidAuth,
+                Code::Unauthenticated,
+                ProtoCode::InvalidAuth,
+            ),
+            (
+                StatusCode::FORBIDDEN,
+                ApiErrorCode::InvalidAccount,
+                Code::Unauthenticated,
+                ProtoCode::InvalidAccount,
+            ),
+            (
+                StatusCode::BAD_REQUEST,
+                ApiErrorCode::KeyLimitReached,
+                Code::Unknown,
+                ProtoCode::KeyLimitReached,
+            ),
+            (
+                StatusCode::FORBIDDEN,
+                ApiErrorCode::MaxDevicesReached,
+                Code::Unauthenticated,
+                ProtoCode::MaxDevicesReached,
+            ),
+            (
+                StatusCode::NOT_FOUND,
+                ApiErrorCode::DeviceNotFound,
+                Code::Unknown,
+                ProtoCode::DeviceNotFound,
+            ),
+            (
+                StatusCode::NOT_FOUND,
+                ApiErrorCode::NotFound,
+                Code::Unknown,
+                ProtoCode::NotFound,
+            ),
+            (
+                StatusCode::METHOD_NOT_ALLOWED,
+                ApiErrorCode::MethodNotAllowed,
+                Code::Unknown,
+                ProtoCode::MethodNotAllowed,
+            ),
+            (
+                StatusCode::BAD_REQUEST,
+                ApiErrorCode::Other("SOME_NEW_ERROR".to_owned()),
+                Code::Unknown,
+                ProtoCode::Other,
+            ),
+        ];
+        for (http_status, api_code, expected_code, expected_proto_code) in cases.iter() {
+            let status = map_rest_error(RestError::ApiError(*http_status, api_code.clone()));
+            assert_eq!(status.code(), *expected_code, "{}", api_code);
+
+            let details = api_error_details(&status);
+            assert_eq!(
+                details.code,
+                i32::from(*expected_proto_code),
+                "{}",
+                api_code
+            );
+            assert_eq!(details.raw_code, api_code.as_str());
+            assert_eq!(details.http_status, u32::from(http_status.as_u16()));
+        }
+    }
+
+    #[test]
+    fn test_map_rest_voucher_error() {
+        let cases = [
+            (
+                ApiErrorCode::InvalidVoucher,
+                Code::NotFound,
+                ProtoCode::InvalidVoucher,
+            ),
+            (
+                ApiErrorCode::VoucherUsed,
+                Code::ResourceExhausted,
+                ProtoCode::VoucherUsed,
+            ),
+            (
+                ApiErrorCode::VoucherExpired,
+                Code::FailedPrecondition,
+                ProtoCode::VoucherExpired,
+            ),
+            (
+                ApiErrorCode::Other("SOME_NEW_ERROR".to_owned()),
+                Code::Unknown,
+                ProtoCode::Other,
+            ),
+        ];
+        for (api_code, expected_code, expected_proto_code) in cases.iter() {
+            let status = map_rest_voucher_error(RestError::ApiError(
+                StatusCode::BAD_REQUEST,
+                api_code.clone(),
+            ));
+            assert_eq!(status.code(), *expected_code, "{}", api_code);
+            assert_eq!(
+                api_error_details(&status).code,
+                i32::from(*expected_proto_code),
+                "{}",
+                api_code
+            );
+        }
+
+        let status = map_rest_voucher_error(RestError::ApiError(
+            StatusCode::UNAUTHORIZED,
+            ApiErrorCode::InvalidAuth,
+        ));
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+
+    #[test]
+    fn test_map_rate_limited_error() {
+        let status = map_rest_error(RestError::RateLimited(Some(Duration::from_millis(1500))));
+        assert_eq!(status.code(), Code::ResourceExhausted);
+
+        let details = api_error_details(&status);
+        assert_eq!(details.code, i32::from(ProtoCode::RateLimited));
+        assert_eq!(details.retry_after, 2);
+    }
+}
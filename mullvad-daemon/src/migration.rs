@@ -0,0 +1,202 @@
+//! Migration that the installer requests after upgrading the app. Some state outlives the daemon:
+//! the objects that the firewall registers with WFP, the configuration of the split tunnel driver
+//! and the settings file. The migration brings all of them in line with the new version, and
+//! writes a report that the installer can read.
+//!
+//! The installer either asks a running daemon to migrate over the management interface, or leaves
+//! a marker file in the settings directory while the daemon is stopped, in which case the daemon
+//! migrates when it starts. The settings are written last, and the marker is only removed once
+//! every step has completed, so a migration that fails halfway is run again from the start.
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{fmt, io, path::Path};
+
+/// Created by the installer to request a migration when the daemon starts. Contains the version
+/// of the installer.
+const MARKER_FILE: &str = "migration-requested";
+/// The report of the last migration.
+const REPORT_FILE: &str = "migration-report.json";
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to write the migration request")]
+    WriteRequest(#[error(source)] io::Error),
+
+    #[error(display = "Failed to remove the migration request")]
+    RemoveRequest(#[error(source)] io::Error),
+
+    #[error(display = "Failed to read the migration report")]
+    ReadReport(#[error(source)] io::Error),
+
+    #[error(display = "Failed to write the migration report")]
+    WriteReport(#[error(source)] io::Error),
+
+    #[error(display = "Failed to serialize the migration report")]
+    SerializeReport(#[error(source)] serde_json::Error),
+
+    #[error(display = "The migration report is malformed")]
+    ParseReport(#[error(source)] serde_json::Error),
+}
+
+/// The steps of a migration, in the order that they are run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MigrationStep {
+    /// Reinstall the objects that the firewall registers with WFP, including the persistent ones.
+    FirewallObjects,
+    /// Pass the excluded applications to the split tunnel driver that was installed.
+    SplitTunnelDriver,
+    /// Write the settings in the format of the current version.
+    Settings,
+}
+
+impl MigrationStep {
+    pub const ALL: [MigrationStep; 3] = [
+        MigrationStep::FirewallObjects,
+        MigrationStep::SplitTunnelDriver,
+        MigrationStep::Settings,
+    ];
+}
+
+impl fmt::Display for MigrationStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            MigrationStep::FirewallObjects => "reinstall WFP objects",
+            MigrationStep::SplitTunnelDriver => "configure split tunnel driver",
+            MigrationStep::Settings => "upgrade settings",
+        };
+        f.write_str(description)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MigrationFailure {
+    pub step: MigrationStep,
+    pub error: String,
+}
+
+/// The outcome of a migration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MigrationReport {
+    /// The version of the installer that requested the migration, if it was given.
+    pub requested_by: Option<String>,
+    /// The version of the daemon that ran the migration.
+    pub daemon_version: String,
+    pub finished: DateTime<Utc>,
+    /// The steps that completed, in order.
+    pub completed: Vec<MigrationStep>,
+    /// The step that failed, if any. The steps after it were not run.
+    pub failure: Option<MigrationFailure>,
+}
+
+impl MigrationReport {
+    pub fn new(requested_by: Option<String>) -> Self {
+        MigrationReport {
+            requested_by,
+            daemon_version: crate::version::PRODUCT_VERSION.to_owned(),
+            finished: Utc::now(),
+            completed: vec![],
+            failure: None,
+        }
+    }
+
+    /// Records the result of `step`. Returns whether the migration should continue.
+    pub fn record(&mut self, step: MigrationStep, result: Result<(), String>) -> bool {
+        self.finished = Utc::now();
+        match result {
+            Ok(()) => {
+                self.completed.push(step);
+                true
+            }
+            Err(error) => {
+                self.failure = Some(MigrationFailure { step, error });
+                false
+            }
+        }
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+impl fmt::Display for MigrationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Migration by {} requested by installer {}, finished {}",
+            self.daemon_version,
+            self.requested_by.as_deref().unwrap_or("(unknown)"),
+            self.finished
+        )?;
+        for step in &self.completed {
+            writeln!(f, "\tCompleted: {}", step)?;
+        }
+        match &self.failure {
+            Some(failure) => write!(f, "\tFailed: {}: {}", failure.step, failure.error),
+            None => write!(f, "\tSucceeded"),
+        }
+    }
+}
+
+/// Requests a migration when the daemon starts. `version` is the version of the installer.
+pub async fn request(settings_dir: &Path, version: &str) -> Result<(), Error> {
+    tokio::fs::write(settings_dir.join(MARKER_FILE), version)
+        .await
+        .map_err(Error::WriteRequest)
+}
+
+/// A migration that was requested while the daemon was stopped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingRequest {
+    /// The version of the installer, if it could be read.
+    pub requested_by: Option<String>,
+}
+
+/// Returns the migration that should run when the daemon starts, if any.
+pub async fn pending_request(settings_dir: &Path) -> Option<PendingRequest> {
+    match tokio::fs::read_to_string(settings_dir.join(MARKER_FILE)).await {
+        Ok(version) => Some(PendingRequest {
+            requested_by: Some(version.trim().to_owned()).filter(|version| !version.is_empty()),
+        }),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => None,
+        // Better to migrate needlessly than to leave a migration undone
+        Err(_) => Some(PendingRequest { requested_by: None }),
+    }
+}
+
+/// Saves `report`. The request is removed if the migration succeeded, and otherwise left in place,
+/// or added, so that the migration runs again when the daemon starts.
+pub async fn finish(settings_dir: &Path, report: &MigrationReport) -> Result<(), Error> {
+    let content = serde_json::to_string_pretty(report).map_err(Error::SerializeReport)?;
+    // The installer may read the report at any time, so it is replaced in one go
+    let temp_path = settings_dir.join(format!("{}.tmp", REPORT_FILE));
+    tokio::fs::write(&temp_path, content)
+        .await
+        .map_err(Error::WriteReport)?;
+    tokio::fs::rename(&temp_path, settings_dir.join(REPORT_FILE))
+        .await
+        .map_err(Error::WriteReport)?;
+
+    if !report.succeeded() {
+        let version = report.requested_by.as_deref().unwrap_or("");
+        return request(settings_dir, version).await;
+    }
+    match tokio::fs::remove_file(settings_dir.join(MARKER_FILE)).await {
+        Ok(()) => Ok(()),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(error) => Err(Error::RemoveRequest(error)),
+    }
+}
+
+/// Returns the report of the last migration, if any.
+pub async fn read_report(settings_dir: &Path) -> Result<Option<MigrationReport>, Error> {
+    match tokio::fs::read(settings_dir.join(REPORT_FILE)).await {
+        Ok(content) => serde_json::from_slice(&content)
+            .map(Some)
+            .map_err(Error::ParseReport),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(Error::ReadReport(error)),
+    }
+}
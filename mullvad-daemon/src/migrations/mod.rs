@@ -0,0 +1,220 @@
+//! Upgrades settings written by earlier versions of the app to the current format, one version at
+//! a time. Each step only knows about the version it reads and the one it writes, and works on the
+//! JSON document rather than on `Settings`, so that old steps keep working as the settings change.
+//!
+//! To change the format, add a version to `SettingsVersion`, make it the current one, and add a
+//! module here that migrates from the previous version, along with a test of that migration.
+//!
+//! This is not to be confused with `crate::migration`, which the installer requests after an
+//! upgrade.
+use mullvad_types::settings::{SettingsVersion, CURRENT_SETTINGS_VERSION};
+use std::path::{Path, PathBuf};
+use talpid_types::ErrorExt;
+use tokio::fs;
+
+mod v1;
+mod v2;
+mod v3;
+mod v4;
+mod v5;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "Malformed settings")]
+    ParseError(#[error(source)] serde_json::Error),
+
+    #[error(display = "Unable to read any version of the settings")]
+    NoMatchingVersion,
+}
+
+trait SettingsMigration {
+    fn version_matches(&self, settings: &mut serde_json::Value) -> bool;
+    fn migrate(&self, settings: &mut serde_json::Value) -> Result<()>;
+}
+
+/// Returns the version of the settings, where the first format, which had no version, is 1.
+pub fn version(settings: &serde_json::Value) -> Option<u64> {
+    match settings.get("settings_version") {
+        Some(version) => version.as_u64(),
+        None => Some(1),
+    }
+}
+
+/// Returns whether the settings have to be migrated before they can be read.
+pub fn needs_migration(settings: &serde_json::Value) -> bool {
+    version(settings) != Some(CURRENT_SETTINGS_VERSION as u64)
+}
+
+/// Migrates the settings to the current version. Settings written by a later version of the app
+/// are read as far as possible: fields that are unknown to this version are dropped, and missing
+/// ones get their default values, so that downgrading the app does not lose every setting.
+pub fn migrate(settings: &mut serde_json::Value) -> Result<()> {
+    if !settings.is_object() {
+        return Err(Error::NoMatchingVersion);
+    }
+
+    if let Some(version) = version(settings) {
+        if version > CURRENT_SETTINGS_VERSION as u64 {
+            log::warn!(
+                "The settings were written by a later version of the app (V{}). Reading them as V{}",
+                version,
+                CURRENT_SETTINGS_VERSION as u64
+            );
+            settings["settings_version"] = serde_json::json!(CURRENT_SETTINGS_VERSION);
+            return Ok(());
+        }
+    }
+
+    let migrations: Vec<Box<dyn SettingsMigration>> = vec![
+        Box::new(v1::Migration),
+        Box::new(v2::Migration),
+        Box::new(v3::Migration),
+        Box::new(v4::Migration),
+        Box::new(v5::Migration),
+    ];
+
+    for migration in &migrations {
+        if !migration.version_matches(settings) {
+            continue;
+        }
+        migration.migrate(settings)?;
+    }
+
+    if needs_migration(settings) {
+        return Err(Error::NoMatchingVersion);
+    }
+    Ok(())
+}
+
+/// Copies the settings file to `<file>.v<version>.bak` before it is replaced by migrated settings.
+/// An earlier backup of the same version is overwritten.
+pub async fn backup(settings_path: &Path, version: Option<u64>) {
+    let backup_path = backup_path(settings_path, version);
+    match fs::copy(settings_path, &backup_path).await {
+        Ok(_) => log::info!(
+            "Saved a copy of the settings before migrating them to {}",
+            backup_path.display()
+        ),
+        Err(error) => log::error!(
+            "{}",
+            error.display_chain_with_msg("Failed to save a copy of the settings before migrating")
+        ),
+    }
+}
+
+fn backup_path(settings_path: &Path, version: Option<u64>) -> PathBuf {
+    let mut file_name = settings_path.file_name().unwrap_or_default().to_os_string();
+    match version {
+        Some(version) => file_name.push(format!(".v{}.bak", version)),
+        None => file_name.push(".unknown.bak"),
+    }
+    settings_path.with_file_name(file_name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_types::settings::Settings;
+
+    const V2_SETTINGS: &str = r#"
+{
+  "account_token": "1234",
+  "relay_settings": {
+    "normal": {
+      "location": {
+        "only": {
+          "country": "se"
+        }
+      },
+      "tunnel_protocol": "any",
+      "wireguard_constraints": {
+        "port": "any"
+      },
+      "openvpn_constraints": {
+        "port": "any",
+        "protocol": "any"
+      }
+    }
+  },
+  "allow_lan": true,
+  "block_when_disconnected": false,
+  "auto_connect": false,
+  "tunnel_options": {
+    "openvpn": {
+      "mssfix": null
+    },
+    "wireguard": {
+      "mtu": null
+    },
+    "generic": {
+      "enable_ipv6": false
+    },
+    "dns_options": {
+      "custom": false,
+      "addresses": []
+    }
+  },
+  "settings_version": 2
+}
+"#;
+
+    #[test]
+    fn test_migrate_to_current_version() {
+        let mut settings: serde_json::Value = serde_json::from_str(V2_SETTINGS).unwrap();
+        assert!(needs_migration(&settings));
+
+        migrate(&mut settings).unwrap();
+
+        assert!(!needs_migration(&settings));
+        let settings = Settings::load_from_value(settings).unwrap();
+        assert!(settings.allow_lan);
+    }
+
+    #[test]
+    fn test_migrate_later_version() {
+        let mut settings = serde_json::json!({
+            "allow_lan": true,
+            "setting_from_the_future": 42,
+            "settings_version": 1000,
+        });
+
+        migrate(&mut settings).unwrap();
+
+        let settings = Settings::load_from_value(settings).unwrap();
+        assert!(settings.allow_lan);
+    }
+
+    #[test]
+    fn test_migrate_invalid_version() {
+        let mut settings = serde_json::json!({ "settings_version": "six" });
+        assert!(migrate(&mut settings).is_err());
+    }
+
+    #[test]
+    fn test_version() {
+        assert_eq!(version(&serde_json::json!({})), Some(1));
+        assert_eq!(
+            version(&serde_json::json!({ "settings_version": 5 })),
+            Some(5)
+        );
+        assert_eq!(
+            version(&serde_json::json!({ "settings_version": "x" })),
+            None
+        );
+    }
+
+    #[test]
+    fn test_backup_path() {
+        let path = Path::new("/etc/mullvad-vpn/settings.json");
+        assert_eq!(
+            backup_path(path, Some(5)),
+            Path::new("/etc/mullvad-vpn/settings.json.v5.bak")
+        );
+        assert_eq!(
+            backup_path(path, None),
+            Path::new("/etc/mullvad-vpn/settings.json.unknown.bak")
+        );
+    }
+}
@@ -1,5 +1,5 @@
 use super::Result;
-use crate::relay_constraints::Constraint;
+use mullvad_types::relay_constraints::Constraint;
 use talpid_types::net::TunnelType;
 
 
@@ -1,5 +1,5 @@
 use super::{Error, Result, SettingsVersion};
-use crate::wireguard::{MAX_ROTATION_INTERVAL, MIN_ROTATION_INTERVAL};
+use mullvad_types::wireguard::{MAX_ROTATION_INTERVAL, MIN_ROTATION_INTERVAL};
 use std::time::Duration;
 
 
@@ -1,5 +1,9 @@
 use super::{Error, Result, SettingsVersion};
-use crate::settings::{CustomDnsOptions, DefaultDnsOptions, DnsOptions, DnsState};
+use mullvad_types::settings::{
+    CustomDnsOptions, DefaultDnsOptions, DnsOptions, DnsState, EncryptedDnsOptions,
+    LocalDnsForwarderOptions,
+};
+use std::net::IpAddr;
 
 
 pub(super) struct Migration;
@@ -30,7 +34,7 @@ impl super::SettingsMigration for Migration {
                 } else {
                     DnsState::Default
                 };
-                let addresses = if let Some(addrs) = options.get("addresses") {
+                let addresses: Vec<IpAddr> = if let Some(addrs) = options.get("addresses") {
                     serde_json::from_value(addrs.clone()).map_err(Error::ParseError)?
                 } else {
                     vec![]
@@ -39,7 +43,9 @@ impl super::SettingsMigration for Migration {
                 settings["tunnel_options"]["dns_options"] = serde_json::json!(DnsOptions {
                     state: new_state,
                     default_options: DefaultDnsOptions::default(),
-                    custom_options: CustomDnsOptions { addresses },
+                    custom_options: CustomDnsOptions::from_addresses_lossy(addresses),
+                    encrypted_options: EncryptedDnsOptions::default(),
+                    local_forwarder_options: LocalDnsForwarderOptions::default(),
                 });
             }
         }
@@ -1,5 +1,5 @@
 use super::{Error, Result, SettingsVersion};
-use crate::relay_constraints::{Constraint, TransportPort};
+use mullvad_types::relay_constraints::{Constraint, TransportPort};
 use talpid_types::net::TransportProtocol;
 
 
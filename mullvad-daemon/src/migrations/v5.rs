@@ -0,0 +1,70 @@
+use super::{Error, Result, SettingsVersion};
+
+
+pub(super) struct Migration;
+
+impl super::SettingsMigration for Migration {
+    fn version_matches(&self, settings: &mut serde_json::Value) -> bool {
+        settings
+            .get("settings_version")
+            .map(|version| version == SettingsVersion::V5 as u64)
+            .unwrap_or(false)
+    }
+
+    fn migrate(&self, settings: &mut serde_json::Value) -> Result<()> {
+        log::info!("Migrating settings format to V6");
+
+        settings
+            .as_object_mut()
+            .ok_or(Error::NoMatchingVersion)?
+            .entry("firewall_exceptions")
+            .or_insert_with(|| serde_json::json!([]));
+
+        settings["settings_version"] = serde_json::json!(SettingsVersion::V6);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{super::SettingsMigration, Migration};
+    use serde_json;
+
+    pub const V5_SETTINGS: &str = r#"
+{
+  "account_token": "1234",
+  "bridge_state": "auto",
+  "allow_lan": true,
+  "block_when_disconnected": false,
+  "auto_connect": false,
+  "settings_version": 5
+}
+"#;
+
+    pub const V6_SETTINGS: &str = r#"
+{
+  "account_token": "1234",
+  "bridge_state": "auto",
+  "allow_lan": true,
+  "block_when_disconnected": false,
+  "auto_connect": false,
+  "firewall_exceptions": [],
+  "settings_version": 6
+}
+"#;
+
+
+    #[test]
+    fn test_v5_migration() {
+        let mut old_settings = serde_json::from_str(V5_SETTINGS).unwrap();
+
+        let migration = Migration;
+        assert!(migration.version_matches(&mut old_settings));
+
+        migration.migrate(&mut old_settings).unwrap();
+        let new_settings: serde_json::Value = serde_json::from_str(V6_SETTINGS).unwrap();
+
+        assert_eq!(&old_settings, &new_settings);
+    }
+}
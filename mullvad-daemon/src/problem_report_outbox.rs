@@ -0,0 +1,287 @@
+//! Problem reports that could not be sent because the API was unreachable. Reports are often
+//! written exactly when the network is broken, so instead of failing they are kept in the cache
+//! directory and sent once the API can be reached again, also after the daemon restarts.
+use crate::ProblemReport;
+use chrono::{DateTime, Utc};
+use mullvad_rpc::rest::{self, IdempotencyToken};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use talpid_types::ErrorExt;
+
+const OUTBOX_DIR: &str = "problem-reports";
+
+/// The oldest reports are dropped when more than this many are waiting to be sent.
+const MAX_PENDING_REPORTS: usize = 10;
+
+/// Reports that could not be sent within this many days are dropped.
+const MAX_REPORT_AGE_DAYS: i64 = 14;
+
+/// Time to wait before the first attempt at sending the queued reports. It is doubled after every
+/// round of failed attempts, up to `MAX_RETRY_DELAY`.
+pub const MIN_RETRY_DELAY: Duration = Duration::from_secs(60);
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to create the problem report outbox directory")]
+    CreateDir(#[error(source)] io::Error),
+
+    #[error(display = "Failed to serialize the problem report")]
+    Serialize(#[error(source)] serde_json::Error),
+
+    #[error(display = "Failed to parse the queued problem report")]
+    Deserialize(#[error(source)] serde_json::Error),
+
+    #[error(display = "Failed to write the problem report to the outbox")]
+    Write(#[error(source)] io::Error),
+
+    #[error(display = "Failed to read the queued problem report")]
+    Read(#[error(source)] io::Error),
+
+    #[error(display = "Failed to remove the queued problem report")]
+    Remove(#[error(source)] io::Error),
+
+    #[error(display = "There is no queued problem report with the ID {}", _0)]
+    NotFound(String),
+}
+
+/// Returns whether a report that failed to be sent with the given error should be queued, rather
+/// than the error being returned.
+pub fn should_queue(error: &rest::Error) -> bool {
+    error.is_network_error() || matches!(error, rest::Error::RateLimited(_))
+}
+
+/// The outcome of an attempt at sending a queued report.
+#[derive(Debug)]
+pub enum RetryResult {
+    Sent,
+    /// The report could not be sent, but may be sent later.
+    Failed(String),
+    /// The report can never be sent, such as if it was rejected by the API.
+    Rejected(String),
+}
+
+/// A report as it is stored in the outbox.
+#[derive(Serialize, Deserialize)]
+pub struct QueuedReport {
+    pub created: DateTime<Utc>,
+    /// Used for every attempt, so that the report is only submitted once even if a response is
+    /// lost.
+    pub idempotency_token: IdempotencyToken,
+    pub report: ProblemReport,
+}
+
+/// Describes a report that is waiting to be sent.
+#[derive(Debug, Clone)]
+pub struct PendingReport {
+    pub id: String,
+    pub created: DateTime<Utc>,
+    /// Number of failed attempts at sending the report since the daemon started.
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    /// Size of the report log, in bytes.
+    pub size: u64,
+}
+
+pub struct Outbox {
+    dir: PathBuf,
+    reports: BTreeMap<String, PendingReport>,
+}
+
+impl Outbox {
+    /// Lists the reports in the outbox. Reports that are too old or cannot be read are removed.
+    pub async fn load(cache_dir: &Path) -> Self {
+        let dir = cache_dir.join(OUTBOX_DIR);
+        let mut outbox = Outbox {
+            dir,
+            reports: BTreeMap::new(),
+        };
+
+        let mut entries = match tokio::fs::read_dir(&outbox.dir).await {
+            Ok(entries) => entries,
+            Err(_) => return outbox,
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            let id = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(id) if path.extension().map(|ext| ext == "json").unwrap_or(false) => {
+                    id.to_owned()
+                }
+                _ => continue,
+            };
+            match read_report(&path).await {
+                Ok(queued) if !is_expired(&queued) => {
+                    outbox.reports.insert(
+                        id.clone(),
+                        PendingReport {
+                            id,
+                            created: queued.created,
+                            attempts: 0,
+                            last_error: None,
+                            size: queued.report.log.len() as u64,
+                        },
+                    );
+                }
+                Ok(_) => {
+                    log::info!("Dropping expired problem report {}", id);
+                    let _ = tokio::fs::remove_file(&path).await;
+                }
+                Err(error) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Dropping unreadable problem report")
+                    );
+                    let _ = tokio::fs::remove_file(&path).await;
+                }
+            }
+        }
+        if !outbox.reports.is_empty() {
+            log::info!(
+                "{} problem report(s) are waiting to be sent",
+                outbox.reports.len()
+            );
+        }
+        outbox
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reports.is_empty()
+    }
+
+    /// Returns the reports that are waiting to be sent, oldest first.
+    pub fn pending(&self) -> Vec<PendingReport> {
+        let mut reports: Vec<_> = self.reports.values().cloned().collect();
+        reports.sort_by_key(|report| report.created);
+        reports
+    }
+
+    /// Returns the ID and path of every queued report.
+    pub fn paths(&self) -> Vec<(String, PathBuf)> {
+        self.pending()
+            .into_iter()
+            .map(|report| {
+                let path = self.path(&report.id);
+                (report.id, path)
+            })
+            .collect()
+    }
+
+    /// Stores a report that failed to be sent and returns its ID.
+    pub async fn add(
+        &mut self,
+        report: ProblemReport,
+        idempotency_token: IdempotencyToken,
+        error: String,
+    ) -> Result<String, Error> {
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(Error::CreateDir)?;
+
+        while self.reports.len() >= MAX_PENDING_REPORTS {
+            let oldest = self.pending().remove(0).id;
+            log::warn!(
+                "Dropping problem report {} since the outbox is full",
+                oldest
+            );
+            self.remove(&oldest).await?;
+        }
+
+        let id = uuid::Uuid::new_v4().to_string();
+        let queued = QueuedReport {
+            created: Utc::now(),
+            idempotency_token,
+            report,
+        };
+        let content = serde_json::to_vec(&queued).map_err(Error::Serialize)?;
+        tokio::fs::write(self.path(&id), content)
+            .await
+            .map_err(Error::Write)?;
+
+        self.reports.insert(
+            id.clone(),
+            PendingReport {
+                id: id.clone(),
+                created: queued.created,
+                attempts: 1,
+                last_error: Some(error),
+                size: queued.report.log.len() as u64,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Removes a report, either because it was sent or because it was cancelled.
+    pub async fn remove(&mut self, id: &str) -> Result<(), Error> {
+        if self.reports.remove(id).is_none() {
+            return Err(Error::NotFound(id.to_owned()));
+        }
+        match tokio::fs::remove_file(self.path(id)).await {
+            Ok(()) => Ok(()),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(error) => Err(Error::Remove(error)),
+        }
+    }
+
+    /// Records that an attempt at sending a report failed.
+    pub fn record_failure(&mut self, id: &str, error: String) {
+        if let Some(report) = self.reports.get_mut(id) {
+            report.attempts += 1;
+            report.last_error = Some(error);
+        }
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+}
+
+pub async fn read_report(path: &Path) -> Result<QueuedReport, Error> {
+    let content = tokio::fs::read(path).await.map_err(Error::Read)?;
+    serde_json::from_slice(&content).map_err(Error::Deserialize)
+}
+
+fn is_expired(queued: &QueuedReport) -> bool {
+    Utc::now() - queued.created > chrono::Duration::days(MAX_REPORT_AGE_DAYS)
+}
+
+/// Returns the time to wait before the next round of attempts, given the previous delay.
+pub fn next_retry_delay(delay: Duration) -> Duration {
+    std::cmp::min(delay * 2, MAX_RETRY_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_delay_is_bounded() {
+        let mut delay = MIN_RETRY_DELAY;
+        for _ in 0..10 {
+            delay = next_retry_delay(delay);
+        }
+        assert_eq!(delay, MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn old_reports_expire() {
+        let mut queued = QueuedReport {
+            created: Utc::now(),
+            idempotency_token: IdempotencyToken::new(),
+            report: ProblemReport {
+                email: String::new(),
+                message: String::new(),
+                log: String::new(),
+                metadata: BTreeMap::new(),
+            },
+        };
+        assert!(!is_expired(&queued));
+        queued.created = Utc::now() - chrono::Duration::days(MAX_REPORT_AGE_DAYS + 1);
+        assert!(is_expired(&queued));
+    }
+}
@@ -0,0 +1,444 @@
+//! Health scores for relays that connections have recently failed to. Each failed handshake or
+//! authentication adds to the score of the relay, and the score decays exponentially over time.
+//! A relay whose score reaches a threshold is temporarily blacklisted, so that the relay selector
+//! avoids it instead of reconnecting to a broken server over and over.
+//!
+//! Successful connections are recorded as well, along with the time it took until traffic was seen
+//! coming back from the relay. Relays with a low score and a low latency are preferred when
+//! picking among the relays that match the constraints.
+use mullvad_types::{auth_failed::AuthFailed, states::TunnelState};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use talpid_types::tunnel::{ActionAfterDisconnect, ErrorStateCause};
+
+/// The time it takes for the score of a relay to decay by half.
+const SCORE_HALF_LIFE: Duration = Duration::from_secs(10 * 60);
+/// Score at which a relay is blacklisted.
+const BLACKLIST_THRESHOLD: f64 = 3.0;
+/// Relays are forgotten once their score decays below this.
+const FORGET_THRESHOLD: f64 = 0.1;
+/// How long a relay is blacklisted the first time. The duration doubles every time the relay is
+/// blacklisted again before it has been forgotten.
+const BLACKLIST_DURATION_INITIAL: Duration = Duration::from_secs(2 * 60);
+const BLACKLIST_DURATION_MAX: Duration = Duration::from_secs(60 * 60);
+/// Latencies below this do not make a relay any more preferred.
+const LATENCY_REFERENCE: Duration = Duration::from_millis(250);
+/// The smallest factor that the weight of a relay is scaled by because of its latency.
+const LATENCY_MIN_FACTOR: f64 = 0.25;
+/// How much a new latency sample contributes to the smoothed latency.
+const LATENCY_SMOOTHING: f64 = 0.3;
+/// Latencies that have not been updated for this long are forgotten.
+const LATENCY_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// The health of a single relay, as reported to frontends.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelayHealthStatus {
+    pub hostname: String,
+    pub score: f64,
+    /// The time remaining until the relay is no longer blacklisted.
+    pub blacklisted_for: Option<Duration>,
+    /// The smoothed time it has taken to connect to the relay, if it has been connected to
+    /// recently.
+    pub latency: Option<Duration>,
+    pub successes: u32,
+    pub failures: u32,
+}
+
+struct Health {
+    score: f64,
+    updated: Instant,
+    times_blacklisted: u32,
+    blacklisted_until: Option<Instant>,
+    latency: Option<(Duration, Instant)>,
+    successes: u32,
+    failures: u32,
+}
+
+impl Health {
+    fn new(now: Instant) -> Self {
+        Health {
+            score: 0.0,
+            updated: now,
+            times_blacklisted: 0,
+            blacklisted_until: None,
+            latency: None,
+            successes: 0,
+            failures: 0,
+        }
+    }
+
+    fn score(&self, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.score * 0.5f64.powf(elapsed / SCORE_HALF_LIFE.as_secs_f64())
+    }
+
+    fn blacklisted_for(&self, now: Instant) -> Option<Duration> {
+        self.blacklisted_until
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+
+    fn latency(&self, now: Instant) -> Option<Duration> {
+        self.latency
+            .filter(|(_, updated)| now.saturating_duration_since(*updated) < LATENCY_MAX_AGE)
+            .map(|(latency, _)| latency)
+    }
+
+    fn is_forgotten(&self, now: Instant) -> bool {
+        self.blacklisted_for(now).is_none()
+            && self.score(now) < FORGET_THRESHOLD
+            && self.latency(now).is_none()
+    }
+
+    /// Returns the factor, between zero and one, that the weight of the relay is scaled by.
+    fn weight_factor(&self, now: Instant) -> f64 {
+        let latency_factor = self
+            .latency(now)
+            .map(|latency| {
+                (LATENCY_REFERENCE.as_secs_f64() / latency.as_secs_f64().max(f64::EPSILON))
+                    .max(LATENCY_MIN_FACTOR)
+                    .min(1.0)
+            })
+            .unwrap_or(1.0);
+        latency_factor / (1.0 + self.score(now))
+    }
+}
+
+pub struct RelayHealth {
+    relays: HashMap<String, Health>,
+    /// The relay that is currently being connected to, and when the attempt started.
+    attempt: Option<(String, Instant)>,
+}
+
+impl RelayHealth {
+    pub fn new() -> Self {
+        RelayHealth {
+            relays: HashMap::new(),
+            attempt: None,
+        }
+    }
+
+    /// Updates the health of relays when the tunnel changes state. `hostname` is the relay that
+    /// is being connected to, if it is known.
+    pub fn record_state(&mut self, state: &TunnelState, hostname: Option<&str>) {
+        self.update_state(state, hostname, Instant::now())
+    }
+
+    fn update_state(&mut self, state: &TunnelState, hostname: Option<&str>, now: Instant) {
+        match state {
+            // The tunnel state machine retries with a new relay when an attempt times out
+            TunnelState::Connecting { .. } => {
+                if let Some((attempt, _)) = self.attempt.take() {
+                    self.record_failure(attempt, now);
+                }
+                self.attempt = hostname.map(|hostname| (hostname.to_owned(), now));
+            }
+            TunnelState::Connected { .. } => {
+                if let Some((attempt, started)) = self.attempt.take() {
+                    self.record_success(attempt, now.saturating_duration_since(started), now);
+                }
+            }
+            // Authentication failures are reported after disconnecting
+            TunnelState::Disconnecting(ActionAfterDisconnect::Block) => (),
+            TunnelState::Error(error_state) => {
+                let attempt = self.attempt.take();
+                if let (Some((attempt, _)), ErrorStateCause::AuthFailed(reason)) =
+                    (attempt, error_state.cause())
+                {
+                    // The relay is not to blame if the account cannot be used
+                    let account_error = reason
+                        .as_ref()
+                        .map(|reason| AuthFailed::from(reason.as_str()).is_account_error())
+                        .unwrap_or(false);
+                    if !account_error {
+                        self.record_failure(attempt, now);
+                    }
+                }
+            }
+            // Attempts that are aborted say nothing about the relay
+            TunnelState::Disconnecting(_) | TunnelState::Disconnected => {
+                self.attempt = None;
+            }
+        }
+    }
+
+    fn record_success(&mut self, hostname: String, latency: Duration, now: Instant) {
+        self.relays.retain(|_, health| !health.is_forgotten(now));
+
+        let health = self
+            .relays
+            .entry(hostname)
+            .or_insert_with(|| Health::new(now));
+        // The relay works, so earlier failures are forgiven
+        health.score = 0.0;
+        health.updated = now;
+        health.times_blacklisted = 0;
+        health.blacklisted_until = None;
+        health.successes = health.successes.saturating_add(1);
+
+        let latency = match health.latency(now) {
+            Some(previous) => {
+                previous.mul_f64(1.0 - LATENCY_SMOOTHING) + latency.mul_f64(LATENCY_SMOOTHING)
+            }
+            None => latency,
+        };
+        health.latency = Some((latency, now));
+    }
+
+    fn record_failure(&mut self, hostname: String, now: Instant) {
+        self.relays.retain(|_, health| !health.is_forgotten(now));
+
+        let health = self
+            .relays
+            .entry(hostname.clone())
+            .or_insert_with(|| Health::new(now));
+        health.score = health.score(now) + 1.0;
+        health.updated = now;
+        health.failures = health.failures.saturating_add(1);
+
+        if health.score >= BLACKLIST_THRESHOLD && health.blacklisted_for(now).is_none() {
+            let duration = BLACKLIST_DURATION_INITIAL
+                .checked_mul(1 << health.times_blacklisted.min(16))
+                .unwrap_or(BLACKLIST_DURATION_MAX)
+                .min(BLACKLIST_DURATION_MAX);
+            health.times_blacklisted += 1;
+            health.blacklisted_until = Some(now + duration);
+            log::info!(
+                "Temporarily blacklisting relay {} for {} seconds after repeated failures",
+                hostname,
+                duration.as_secs()
+            );
+        }
+    }
+
+    /// Records that the connection to a relay was given up on because too much of the traffic was
+    /// lost. Unlike failed attempts, this blacklists the relay right away, so that the relay
+    /// selector picks another one when reconnecting.
+    pub fn record_packet_loss(&mut self, hostname: &str) {
+        self.update_packet_loss(hostname, Instant::now())
+    }
+
+    fn update_packet_loss(&mut self, hostname: &str, now: Instant) {
+        if let Some(health) = self.relays.get_mut(hostname) {
+            health.score = health.score(now).max(BLACKLIST_THRESHOLD - 1.0);
+            health.updated = now;
+        } else {
+            let mut health = Health::new(now);
+            health.score = BLACKLIST_THRESHOLD - 1.0;
+            self.relays.insert(hostname.to_owned(), health);
+        }
+        self.record_failure(hostname.to_owned(), now);
+    }
+
+    /// Returns whether the relay should currently be avoided.
+    pub fn is_blacklisted(&self, hostname: &str) -> bool {
+        self.is_blacklisted_at(hostname, Instant::now())
+    }
+
+    fn is_blacklisted_at(&self, hostname: &str, now: Instant) -> bool {
+        self.relays
+            .get(hostname)
+            .map(|health| health.blacklisted_for(now).is_some())
+            .unwrap_or(false)
+    }
+
+    /// Returns `weight` scaled down by how poorly the relay has performed recently. Relays that
+    /// have a positive weight never end up with a weight of zero.
+    pub fn adjust_weight(&self, hostname: &str, weight: u64) -> u64 {
+        self.adjust_weight_at(hostname, weight, Instant::now())
+    }
+
+    fn adjust_weight_at(&self, hostname: &str, weight: u64, now: Instant) -> u64 {
+        match self.relays.get(hostname) {
+            Some(health) if weight > 0 => {
+                ((weight as f64 * health.weight_factor(now)).round() as u64).max(1)
+            }
+            _ => weight,
+        }
+    }
+
+    /// Forgets all failures, successes and latencies, and lifts all blacklistings.
+    pub fn clear(&mut self) {
+        self.relays.clear();
+    }
+
+    /// Returns the health of every relay that has been connected to recently, ordered by hostname.
+    pub fn status(&self) -> Vec<RelayHealthStatus> {
+        self.status_at(Instant::now())
+    }
+
+    fn status_at(&self, now: Instant) -> Vec<RelayHealthStatus> {
+        let mut status: Vec<_> = self
+            .relays
+            .iter()
+            .filter(|(_, health)| !health.is_forgotten(now))
+            .map(|(hostname, health)| RelayHealthStatus {
+                hostname: hostname.clone(),
+                score: health.score(now),
+                blacklisted_for: health.blacklisted_for(now),
+                latency: health.latency(now),
+                successes: health.successes,
+                failures: health.failures,
+            })
+            .collect();
+        status.sort_by(|a, b| a.hostname.cmp(&b.hostname));
+        status
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use talpid_types::net::{Endpoint, TransportProtocol, TunnelEndpoint, TunnelType};
+
+    const RELAY: &str = "se-got-wg-001";
+
+    fn endpoint() -> TunnelEndpoint {
+        TunnelEndpoint {
+            endpoint: Endpoint::new(std::net::Ipv4Addr::LOCALHOST, 51820, TransportProtocol::Udp),
+            tunnel_type: TunnelType::Wireguard,
+            proxy: None,
+            entry_endpoint: None,
+            obfuscation: None,
+        }
+    }
+
+    fn connecting() -> TunnelState {
+        TunnelState::Connecting {
+            endpoint: endpoint(),
+            location: None,
+            retry_at: None,
+        }
+    }
+
+    fn connected() -> TunnelState {
+        TunnelState::Connected {
+            endpoint: endpoint(),
+            location: None,
+            feature_indicators: Default::default(),
+        }
+    }
+
+    fn fail(health: &mut RelayHealth, times: u32, now: Instant) {
+        for _ in 0..times {
+            health.update_state(&connecting(), Some(RELAY), now);
+            health.update_state(&connecting(), None, now);
+        }
+    }
+
+    #[test]
+    fn test_blacklist_after_repeated_failures() {
+        let mut health = RelayHealth::new();
+        let now = Instant::now();
+
+        fail(&mut health, 2, now);
+        assert!(!health.is_blacklisted_at(RELAY, now));
+        fail(&mut health, 1, now);
+        assert!(health.is_blacklisted_at(RELAY, now));
+        assert!(!health.is_blacklisted_at(RELAY, now + BLACKLIST_DURATION_INITIAL));
+
+        // The score has only decayed slightly, so one more failure blacklists the relay for longer
+        let now = now + BLACKLIST_DURATION_INITIAL;
+        fail(&mut health, 1, now);
+        assert!(health.is_blacklisted_at(RELAY, now + BLACKLIST_DURATION_INITIAL));
+        assert!(!health.is_blacklisted_at(RELAY, now + 2 * BLACKLIST_DURATION_INITIAL));
+    }
+
+    #[test]
+    fn test_blacklist_after_packet_loss() {
+        let mut health = RelayHealth::new();
+        let now = Instant::now();
+
+        health.update_state(&connecting(), Some(RELAY), now);
+        health.update_state(&connected(), None, now);
+        health.update_packet_loss(RELAY, now);
+        assert!(health.is_blacklisted_at(RELAY, now));
+        assert!(!health.is_blacklisted_at(RELAY, now + BLACKLIST_DURATION_INITIAL));
+        assert_eq!(health.status_at(now)[0].failures, 1);
+    }
+
+    #[test]
+    fn test_score_decays() {
+        let mut health = RelayHealth::new();
+        let now = Instant::now();
+
+        fail(&mut health, 2, now);
+        let status = health.status_at(now + SCORE_HALF_LIFE);
+        assert_eq!(status.len(), 1);
+        assert!((status[0].score - 1.0).abs() < 0.001);
+
+        // Failures that are far apart never add up to a blacklisting
+        fail(&mut health, 2, now + 10 * SCORE_HALF_LIFE);
+        fail(&mut health, 2, now + 20 * SCORE_HALF_LIFE);
+        assert!(!health.is_blacklisted_at(RELAY, now + 20 * SCORE_HALF_LIFE));
+
+        assert!(health.status_at(now + 30 * SCORE_HALF_LIFE).is_empty());
+    }
+
+    #[test]
+    fn test_only_relay_failures_count() {
+        let mut health = RelayHealth::new();
+        let now = Instant::now();
+
+        fail(&mut health, 2, now);
+        health.update_state(&connecting(), Some(RELAY), now);
+        health.update_state(&connected(), None, now);
+        let status = health.status_at(now);
+        assert_eq!(status.len(), 1);
+        assert!(status[0].score < FORGET_THRESHOLD);
+        health.clear();
+
+        for _ in 0..3 {
+            health.update_state(&connecting(), Some(RELAY), now);
+            health.update_state(
+                &TunnelState::Disconnecting(ActionAfterDisconnect::Block),
+                None,
+                now,
+            );
+            health.update_state(
+                &TunnelState::Error(talpid_types::tunnel::ErrorState::new(
+                    ErrorStateCause::AuthFailed(Some("[EXPIRED_ACCOUNT]".to_owned())),
+                    None,
+                )),
+                None,
+                now,
+            );
+        }
+        assert!(health.status_at(now).is_empty());
+    }
+
+    #[test]
+    fn test_prefer_fast_and_reliable_relays() {
+        const SLOW_RELAY: &str = "se-got-wg-002";
+        let mut health = RelayHealth::new();
+        let now = Instant::now();
+
+        let connect = |health: &mut RelayHealth, hostname, latency| {
+            health.update_state(&connecting(), Some(hostname), now);
+            health.update_state(&connected(), None, now + latency);
+        };
+        connect(&mut health, RELAY, Duration::from_millis(100));
+        connect(&mut health, SLOW_RELAY, Duration::from_secs(1));
+
+        assert_eq!(health.adjust_weight_at(RELAY, 100, now), 100);
+        assert_eq!(health.adjust_weight_at(SLOW_RELAY, 100, now), 25);
+        assert_eq!(health.adjust_weight_at("unknown", 100, now), 100);
+        assert_eq!(health.adjust_weight_at(RELAY, 0, now), 0);
+
+        fail(&mut health, 1, now);
+        assert_eq!(health.adjust_weight_at(RELAY, 100, now), 50);
+        assert_eq!(
+            health.adjust_weight_at(RELAY, 100, now + 10 * SCORE_HALF_LIFE),
+            100
+        );
+
+        // Latencies are forgotten after a while
+        assert_eq!(
+            health.adjust_weight_at(SLOW_RELAY, 100, now + 2 * LATENCY_MAX_AGE),
+            100
+        );
+        assert!(health.status_at(now + 10 * LATENCY_MAX_AGE).is_empty());
+    }
+}
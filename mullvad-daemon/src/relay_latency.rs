@@ -0,0 +1,292 @@
+//! Latencies to relays, measured with ICMP echo requests that are sent outside the tunnel. The
+//! relays are allowed through the firewall while they are being measured. Measurements are cached,
+//! so that measuring the same relays again soon after is cheap, and the relay selector prefers
+//! relays that have been measured to be close by.
+#[cfg(not(target_os = "android"))]
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant, SystemTime},
+};
+#[cfg(not(target_os = "android"))]
+use std::{
+    net::Ipv4Addr,
+    sync::{Arc, Weak},
+};
+#[cfg(not(target_os = "android"))]
+use talpid_core::{
+    firewall,
+    tunnel_state_machine::{TunnelCommand, TunnelCommandSender},
+};
+
+/// How long to wait for the reply from a relay.
+#[cfg(not(target_os = "android"))]
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+/// The number of relays that are measured at the same time.
+#[cfg(not(target_os = "android"))]
+const MAX_CONCURRENT_PROBES: usize = 32;
+/// Relays that were measured less than this long ago are not measured again.
+const REMEASURE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Measurements older than this are forgotten.
+const MAX_AGE: Duration = Duration::from_secs(60 * 60);
+/// Latencies below this do not make a relay any more preferred.
+const LATENCY_REFERENCE: Duration = Duration::from_millis(50);
+/// The smallest factor that the weight of a relay is scaled by because of its latency. Relays that
+/// did not reply are scaled by this.
+const LATENCY_MIN_FACTOR: f64 = 0.1;
+
+#[cfg(not(target_os = "android"))]
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// The echo requests would be routed through the tunnel.
+    #[cfg(not(target_os = "linux"))]
+    #[error(display = "Relays can only be measured while disconnected on this platform")]
+    TunnelUp,
+
+    /// The relays could not be allowed through the firewall.
+    #[error(display = "Failed to allow the relays through the firewall")]
+    AllowRelays(#[error(source)] firewall::Error),
+
+    /// The tunnel state machine has stopped.
+    #[error(display = "The tunnel state machine is not running")]
+    TunnelStateMachineDown,
+}
+
+/// The measured latency to a relay, as reported to frontends.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelayLatency {
+    pub hostname: String,
+    /// The round-trip time, or `None` if the relay did not reply in time.
+    pub latency: Option<Duration>,
+    pub measured_at: SystemTime,
+}
+
+struct Measurement {
+    latency: Option<Duration>,
+    measured: Instant,
+    measured_at: SystemTime,
+}
+
+pub struct RelayLatencies {
+    measurements: HashMap<String, Measurement>,
+    /// Whether the measurements affect the weights of relays. Relays can be measured either way.
+    enabled: bool,
+}
+
+impl RelayLatencies {
+    pub fn new() -> Self {
+        RelayLatencies {
+            measurements: HashMap::new(),
+            enabled: false,
+        }
+    }
+
+    /// Sets whether relays that have been measured to be close by are preferred.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    fn measurement(&self, hostname: &str, now: Instant) -> Option<&Measurement> {
+        self.measurements
+            .get(hostname)
+            .filter(|measurement| now.saturating_duration_since(measurement.measured) < MAX_AGE)
+    }
+
+    /// Returns whether the relay has not been measured recently.
+    fn needs_measuring(&self, hostname: &str, now: Instant) -> bool {
+        self.measurement(hostname, now)
+            .map(|measurement| {
+                now.saturating_duration_since(measurement.measured) >= REMEASURE_INTERVAL
+            })
+            .unwrap_or(true)
+    }
+
+    fn insert(&mut self, hostname: String, latency: Option<Duration>, now: Instant) {
+        self.measurements
+            .retain(|_, measurement| now.saturating_duration_since(measurement.measured) < MAX_AGE);
+        self.measurements.insert(
+            hostname,
+            Measurement {
+                latency,
+                measured: now,
+                measured_at: SystemTime::now(),
+            },
+        );
+    }
+
+    /// Returns the latencies of the given relays that have been measured, in the same order.
+    pub fn get<'a>(&self, hostnames: impl Iterator<Item = &'a str>) -> Vec<RelayLatency> {
+        let now = Instant::now();
+        hostnames
+            .filter_map(|hostname| {
+                self.measurement(hostname, now)
+                    .map(|measurement| RelayLatency {
+                        hostname: hostname.to_owned(),
+                        latency: measurement.latency,
+                        measured_at: measurement.measured_at,
+                    })
+            })
+            .collect()
+    }
+
+    /// Returns `weight` scaled down by how far away the relay was measured to be. Relays that have
+    /// not been measured are not scaled, and relays that have a positive weight never end up with
+    /// a weight of zero. Nothing is scaled unless latency-based selection is enabled.
+    pub fn adjust_weight(&self, hostname: &str, weight: u64) -> u64 {
+        self.adjust_weight_at(hostname, weight, Instant::now())
+    }
+
+    fn adjust_weight_at(&self, hostname: &str, weight: u64, now: Instant) -> u64 {
+        let measurement = match self.measurement(hostname, now) {
+            Some(measurement) if self.enabled && weight > 0 => measurement,
+            _ => return weight,
+        };
+        let factor = measurement
+            .latency
+            .map(|latency| {
+                (LATENCY_REFERENCE.as_secs_f64() / latency.as_secs_f64().max(f64::EPSILON))
+                    .max(LATENCY_MIN_FACTOR)
+                    .min(1.0)
+            })
+            .unwrap_or(LATENCY_MIN_FACTOR);
+        ((weight as f64 * factor).round() as u64).max(1)
+    }
+}
+
+/// Measures the relays that have not been measured recently, and returns the latencies of all of
+/// the given relays. The relays that are measured are allowed through the firewall for as long as
+/// it takes to measure them.
+#[cfg(not(target_os = "android"))]
+pub async fn measure(
+    latencies: Arc<Mutex<RelayLatencies>>,
+    relays: Vec<(String, Ipv4Addr)>,
+    tunnel_command_tx: Weak<TunnelCommandSender>,
+) -> Result<Vec<RelayLatency>, Error> {
+    use futures::{stream, StreamExt};
+    use talpid_core::ping_monitor::latency;
+    use talpid_types::ErrorExt;
+
+    let now = Instant::now();
+    let stale: Vec<(String, Ipv4Addr)> = {
+        let latencies = latencies.lock();
+        relays
+            .iter()
+            .filter(|(hostname, _)| latencies.needs_measuring(hostname, now))
+            .cloned()
+            .collect()
+    };
+
+    if !stale.is_empty() {
+        log::info!("Measuring the latency to {} relays", stale.len());
+        allow_relays(&stale, &tunnel_command_tx).await?;
+
+        let results: Vec<(String, Option<Duration>)> = stream::iter(stale)
+            .map(|(hostname, addr)| async move {
+                let result =
+                    tokio::task::spawn_blocking(move || latency::measure(addr, PROBE_TIMEOUT))
+                        .await
+                        .expect("Latency measurement panicked");
+                match result {
+                    Ok(latency) => Some((hostname, Some(latency))),
+                    Err(latency::Error::Timeout(_)) => Some((hostname, None)),
+                    Err(error) => {
+                        log::warn!(
+                            "{}",
+                            error.display_chain_with_msg(&format!(
+                                "Failed to measure the latency to {}",
+                                hostname
+                            ))
+                        );
+                        None
+                    }
+                }
+            })
+            .buffer_unordered(MAX_CONCURRENT_PROBES)
+            .filter_map(|result| async move { result })
+            .collect()
+            .await;
+
+        let mut latencies = latencies.lock();
+        let now = Instant::now();
+        for (hostname, latency) in results {
+            latencies.insert(hostname, latency, now);
+        }
+    }
+
+    let latencies = latencies.lock();
+    Ok(latencies.get(relays.iter().map(|(hostname, _)| hostname.as_str())))
+}
+
+/// Allows traffic to and from the relays outside the tunnel for as long as it can take to measure
+/// all of them.
+#[cfg(not(target_os = "android"))]
+async fn allow_relays(
+    relays: &[(String, Ipv4Addr)],
+    tunnel_command_tx: &Weak<TunnelCommandSender>,
+) -> Result<(), Error> {
+    use futures::channel::oneshot;
+    use talpid_types::net::AllowedEndpoint;
+
+    let rounds = (relays.len() + MAX_CONCURRENT_PROBES - 1) / MAX_CONCURRENT_PROBES;
+    let duration = PROBE_TIMEOUT * (rounds as u32 + 1);
+    let endpoints = relays
+        .iter()
+        .map(|(_, addr)| AllowedEndpoint::new(ipnetwork::IpNetwork::from(*addr), None, None))
+        .collect();
+
+    let tunnel_command_tx = tunnel_command_tx
+        .upgrade()
+        .ok_or(Error::TunnelStateMachineDown)?;
+    let (tx, rx) = oneshot::channel();
+    tunnel_command_tx
+        .send(TunnelCommand::AllowTemporarily(endpoints, duration, tx))
+        .await
+        .map_err(|_| Error::TunnelStateMachineDown)?;
+    rx.await
+        .map_err(|_| Error::TunnelStateMachineDown)?
+        .map_err(Error::AllowRelays)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const RELAY: &str = "se-got-wg-001";
+
+    #[test]
+    fn test_adjust_weight() {
+        let mut latencies = RelayLatencies::new();
+        latencies.set_enabled(true);
+        let now = Instant::now();
+        assert_eq!(latencies.adjust_weight_at(RELAY, 100, now), 100);
+
+        latencies.insert(RELAY.to_owned(), Some(Duration::from_millis(10)), now);
+        assert_eq!(latencies.adjust_weight_at(RELAY, 100, now), 100);
+        latencies.insert(RELAY.to_owned(), Some(Duration::from_millis(200)), now);
+        assert_eq!(latencies.adjust_weight_at(RELAY, 100, now), 25);
+        latencies.insert(RELAY.to_owned(), None, now);
+        assert_eq!(latencies.adjust_weight_at(RELAY, 100, now), 10);
+        assert_eq!(latencies.adjust_weight_at(RELAY, 1, now), 1);
+        assert_eq!(latencies.adjust_weight_at(RELAY, 0, now), 0);
+
+        assert_eq!(latencies.adjust_weight_at(RELAY, 100, now + MAX_AGE), 100);
+
+        latencies.set_enabled(false);
+        assert_eq!(latencies.adjust_weight_at(RELAY, 100, now), 100);
+    }
+
+    #[test]
+    fn test_needs_measuring() {
+        let mut latencies = RelayLatencies::new();
+        let now = Instant::now();
+        assert!(latencies.needs_measuring(RELAY, now));
+        latencies.insert(RELAY.to_owned(), None, now);
+        assert!(!latencies.needs_measuring(RELAY, now));
+        assert!(latencies.needs_measuring(RELAY, now + REMEASURE_INTERVAL));
+
+        let measured = latencies.get(vec![RELAY, "se-got-wg-002"].into_iter());
+        assert_eq!(measured.len(), 1);
+        assert_eq!(measured[0].latency, None);
+    }
+}
@@ -1,6 +1,11 @@
 //! When changing relay selection, please verify if `docs/relay-selector.md` needs to be
 //! updated as well.
 
+use crate::{
+    connection_profiles::ConnectionProfileState,
+    relay_health::{RelayHealth, RelayHealthStatus},
+    relay_latency::RelayLatencies,
+};
 use chrono::{DateTime, Local};
 use futures::{
     channel::mpsc,
@@ -17,23 +22,26 @@ use mullvad_types::{
         BridgeState, Constraint, InternalBridgeConstraints, LocationConstraint, Match,
         OpenVpnConstraints, Providers, RelayConstraints, Set, TransportPort, WireguardConstraints,
     },
+    relay_filter::RelayFilter,
     relay_list::{OpenVpnEndpointData, Relay, RelayList, RelayTunnels, WireguardEndpointData},
+    states::TunnelState,
 };
 use parking_lot::Mutex;
 use rand::{self, rngs::ThreadRng, seq::SliceRandom, Rng};
 use std::{
+    collections::HashMap,
     future::Future,
     io,
-    net::{IpAddr, SocketAddr},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
     path::{Path, PathBuf},
     sync::Arc,
-    time::{self, Duration, Instant, SystemTime},
+    time::{self, Duration, SystemTime},
 };
 use talpid_core::future_retry::{retry_future, ExponentialBackoff, Jittered};
 use talpid_types::{
     net::{
-        all_of_the_internet, openvpn::ProxySettings, wireguard, IpVersion, TransportProtocol,
-        TunnelType,
+        all_of_the_internet, openvpn::ProxySettings, wireguard, Connectivity, IpVersion,
+        TransportProtocol, TunnelType,
     },
     ErrorExt,
 };
@@ -41,12 +49,13 @@ use tokio::fs::File;
 
 const DATE_TIME_FORMAT_STR: &str = "%Y-%m-%d %H:%M:%S%.3f";
 const RELAYS_FILENAME: &str = "relays.json";
-/// How often the updater should wake up to check the cache of the in-memory cache of relays.
-/// This check is very cheap. The only reason to not have it very often is because if downloading
-/// constantly fails it will try very often and fill the logs etc.
-const UPDATE_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 15);
-/// How old the cached relays need to be to trigger an update
-const UPDATE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Connectivity to assume until the offline monitor has reported it.
+const ASSUMED_CONNECTIVITY: Connectivity = Connectivity {
+    ipv4: true,
+    ipv6: true,
+};
+/// How often the relay list is updated
+pub(crate) const UPDATE_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
 const EXPONENTIAL_BACKOFF_INITIAL: Duration = Duration::from_secs(16);
 const EXPONENTIAL_BACKOFF_FACTOR: u32 = 8;
@@ -61,6 +70,9 @@ const WIREGUARD_EXIT_CONSTRAINTS: WireguardConstraints = WireguardConstraints {
     entry_location: None,
 };
 const WIREGUARD_TCP_PORTS: [(u16, u16); 3] = [(80, 80), (443, 443), (5001, 5001)];
+/// The number of connection attempts that use the connection profile of the current country, if
+/// there is one, before the default sequence of ports and protocols is tried.
+const CONNECTION_PROFILE_ATTEMPTS: u32 = 2;
 
 
 #[derive(err_derive::Error, Debug)]
@@ -82,19 +94,86 @@ pub enum Error {
     DownloaderShutDown,
 }
 
-struct ParsedRelays {
+/// Indices into `ParsedRelays::relays` of the active relays in some location.
+#[derive(Default)]
+struct RelayIndex {
+    all: Vec<usize>,
+    wireguard: Vec<usize>,
+    openvpn: Vec<usize>,
+}
+
+impl RelayIndex {
+    fn insert(&mut self, index: usize, relay: &Relay) {
+        self.all.push(index);
+        if !relay.tunnels.wireguard.is_empty() {
+            self.wireguard.push(index);
+        }
+        if !relay.tunnels.openvpn.is_empty() {
+            self.openvpn.push(index);
+        }
+    }
+
+    fn get(&self, tunnel_protocol: Constraint<TunnelType>) -> &[usize] {
+        match tunnel_protocol {
+            Constraint::Any => &self.all,
+            Constraint::Only(TunnelType::Wireguard) => &self.wireguard,
+            Constraint::Only(TunnelType::OpenVpn) => &self.openvpn,
+        }
+    }
+}
+
+/// The relay list along with indices for looking up the relays in a location. It is shared
+/// between the selector and the updater, and replaced as a whole when the list is updated, so
+/// that relays can be selected without holding a lock or cloning the list.
+pub struct ParsedRelays {
     last_updated: SystemTime,
     locations: RelayList,
     relays: Vec<Relay>,
+    any_location: RelayIndex,
+    countries: HashMap<String, RelayIndex>,
+    cities: HashMap<(String, String), RelayIndex>,
+    hostnames: HashMap<String, usize>,
 }
 
 impl ParsedRelays {
     pub fn empty() -> Self {
-        ParsedRelays {
-            last_updated: time::UNIX_EPOCH,
-            locations: RelayList::empty(),
-            relays: Vec::new(),
+        Self::from_relays(time::UNIX_EPOCH, RelayList::empty(), Vec::new())
+    }
+
+    fn from_relays(last_updated: SystemTime, locations: RelayList, relays: Vec<Relay>) -> Self {
+        let mut parsed_relays = ParsedRelays {
+            last_updated,
+            locations,
+            relays,
+            any_location: RelayIndex::default(),
+            countries: HashMap::new(),
+            cities: HashMap::new(),
+            hostnames: HashMap::new(),
+        };
+        for (index, relay) in parsed_relays.relays.iter().enumerate() {
+            if !relay.active {
+                continue;
+            }
+            parsed_relays.any_location.insert(index, relay);
+            parsed_relays
+                .hostnames
+                .insert(relay.hostname.clone(), index);
+            if let Some(location) = relay.location.as_ref() {
+                if relay.include_in_country {
+                    parsed_relays
+                        .countries
+                        .entry(location.country_code.clone())
+                        .or_default()
+                        .insert(index, relay);
+                }
+                parsed_relays
+                    .cities
+                    .entry((location.country_code.clone(), location.city_code.clone()))
+                    .or_default()
+                    .insert(index, relay);
+            }
         }
+        parsed_relays
     }
 
     pub fn from_relay_list(relay_list: RelayList, last_updated: SystemTime) -> Self {
@@ -125,6 +204,7 @@ impl ParsedRelays {
                             .push(WireguardEndpointData {
                                 protocol: TransportProtocol::Tcp,
                                 port_ranges: WIREGUARD_TCP_PORTS.to_vec(),
+                                shadowsocks: vec![],
                                 ..wg_tunnel.clone()
                             });
                     }
@@ -133,11 +213,7 @@ impl ParsedRelays {
                 }
             }
         }
-        ParsedRelays {
-            last_updated,
-            locations: relay_list,
-            relays,
-        }
+        Self::from_relays(last_updated, relay_list, relays)
     }
 
     pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
@@ -168,61 +244,106 @@ impl ParsedRelays {
         &self.relays
     }
 
+    /// Returns the active relays that may match the location and tunnel protocol constraints,
+    /// in the order they appear in the relay list. Only the indexed location is considered, so
+    /// the relays must still be matched against the complete constraints.
+    pub fn candidates<'a>(
+        &'a self,
+        location: &Constraint<LocationConstraint>,
+        tunnel_protocol: Constraint<TunnelType>,
+    ) -> impl Iterator<Item = &'a Relay> + 'a {
+        let indices: &[usize] = match location {
+            Constraint::Any => self.any_location.get(tunnel_protocol),
+            Constraint::Only(LocationConstraint::Country(country)) => self
+                .countries
+                .get(country)
+                .map(|index| index.get(tunnel_protocol))
+                .unwrap_or(&[]),
+            Constraint::Only(LocationConstraint::City(country, city)) => self
+                .cities
+                .get(&(country.clone(), city.clone()))
+                .map(|index| index.get(tunnel_protocol))
+                .unwrap_or(&[]),
+            Constraint::Only(LocationConstraint::Hostname(_, _, hostname)) => self
+                .hostnames
+                .get(hostname)
+                .map(std::slice::from_ref)
+                .unwrap_or(&[]),
+        };
+        indices.iter().map(move |index| &self.relays[*index])
+    }
+
     pub fn tag(&self) -> Option<&str> {
         self.locations.etag.as_deref()
     }
 }
 
 pub struct RelaySelector {
-    parsed_relays: Arc<Mutex<ParsedRelays>>,
+    parsed_relays: Arc<Mutex<Arc<ParsedRelays>>>,
+    connectivity: Arc<Mutex<Connectivity>>,
+    health: Arc<Mutex<RelayHealth>>,
+    latencies: Arc<Mutex<RelayLatencies>>,
+    connection_profiles: Arc<Mutex<ConnectionProfileState>>,
     rng: ThreadRng,
     updater: Option<RelayListUpdaterHandle>,
 }
 
 impl RelaySelector {
-    /// Returns a new `RelaySelector` backed by relays cached on disk. Use the `update` method
-    /// to refresh the relay list from the internet.
+    /// Returns a new `RelaySelector` backed by relays cached on disk. The relays are read in the
+    /// background, and `on_loaded` is called once they are available. Until then, no relays are
+    /// selected. Use the `update` method to refresh the relay list from the internet.
     pub fn new(
         rpc_handle: MullvadRestHandle,
         on_update: impl Fn(&RelayList) + Send + 'static,
+        on_loaded: impl FnOnce() + Send + 'static,
         resource_dir: &Path,
         cache_dir: &Path,
         api_availability: ApiAvailabilityHandle,
     ) -> Self {
         let cache_path = cache_dir.join(RELAYS_FILENAME);
         let resource_path = resource_dir.join(RELAYS_FILENAME);
-        let unsynchronized_parsed_relays = Self::read_relays_from_disk(&cache_path, &resource_path)
-            .unwrap_or_else(|error| {
-                error!(
-                    "{}",
-                    error.display_chain_with_msg("Unable to load cached relays")
-                );
-                ParsedRelays::empty()
-            });
-        info!(
-            "Initialized with {} cached relays from {}",
-            unsynchronized_parsed_relays.relays().len(),
-            DateTime::<Local>::from(unsynchronized_parsed_relays.last_updated())
-                .format(DATE_TIME_FORMAT_STR)
-        );
-        let parsed_relays = Arc::new(Mutex::new(unsynchronized_parsed_relays));
+        let parsed_relays = Arc::new(Mutex::new(Arc::new(ParsedRelays::empty())));
+        let health = Arc::new(Mutex::new(RelayHealth::new()));
 
         let updater = RelayListUpdater::new(
             rpc_handle,
             cache_path,
+            resource_path,
             parsed_relays.clone(),
+            health.clone(),
             Box::new(on_update),
+            Box::new(on_loaded),
             api_availability,
         );
 
 
         RelaySelector {
             parsed_relays,
+            connectivity: Arc::new(Mutex::new(ASSUMED_CONNECTIVITY)),
+            health,
+            latencies: Arc::new(Mutex::new(RelayLatencies::new())),
+            connection_profiles: Arc::new(Mutex::new(ConnectionProfileState::default())),
             rng: rand::thread_rng(),
             updater: Some(updater),
         }
     }
 
+    /// Returns a `RelaySelector` that selects from the given relay list and is never updated.
+    pub fn from_relay_list(relay_list: RelayList) -> Self {
+        RelaySelector {
+            parsed_relays: Arc::new(Mutex::new(Arc::new(ParsedRelays::from_relay_list(
+                relay_list,
+                SystemTime::now(),
+            )))),
+            connectivity: Arc::new(Mutex::new(ASSUMED_CONNECTIVITY)),
+            health: Arc::new(Mutex::new(RelayHealth::new())),
+            latencies: Arc::new(Mutex::new(RelayLatencies::new())),
+            connection_profiles: Arc::new(Mutex::new(ConnectionProfileState::default())),
+            rng: rand::thread_rng(),
+            updater: None,
+        }
+    }
+
     /// Download the newest relay list.
     pub fn update(&mut self) -> impl Future<Output = ()> {
         let mut updater = self.updater.as_ref().unwrap().clone();
@@ -238,6 +359,73 @@ impl RelaySelector {
         self.updater.as_ref().unwrap().clone()
     }
 
+    /// Returns a handle used to inform the selector over which IP versions the internet can be
+    /// reached. Endpoints are selected accordingly when the constraints permit it.
+    pub fn connectivity_handle(&self) -> Arc<Mutex<Connectivity>> {
+        self.connectivity.clone()
+    }
+
+    /// Returns a handle to the measured latencies of relays. Relays that have been measured to be
+    /// far away are less likely to be selected.
+    pub fn relay_latencies_handle(&self) -> Arc<Mutex<RelayLatencies>> {
+        self.latencies.clone()
+    }
+
+    /// Returns a handle used to update the connection profiles and the country that the device is
+    /// in. The first connection attempts use the profile of the country, if there is one.
+    pub fn connection_profiles_handle(&self) -> Arc<Mutex<ConnectionProfileState>> {
+        self.connection_profiles.clone()
+    }
+
+    /// Returns whether the connection profile of the current country recommends obfuscation.
+    pub fn profile_recommends_obfuscation(&self) -> bool {
+        self.connection_profiles
+            .lock()
+            .active_profile()
+            .map(|profile| profile.obfuscation)
+            .unwrap_or(false)
+    }
+
+    /// Updates the health of relays when the tunnel changes state. Relays that keep failing are
+    /// temporarily blacklisted. `hostname` is the relay that is being connected to, if it is
+    /// known.
+    pub fn record_tunnel_state(&self, state: &TunnelState, hostname: Option<&str>) {
+        self.health.lock().record_state(state, hostname);
+    }
+
+    /// Temporarily blacklists a relay that the connection was given up on because of packet loss.
+    pub fn record_packet_loss(&self, hostname: &str) {
+        self.health.lock().record_packet_loss(hostname);
+    }
+
+    /// Returns the health of every relay that has been connected to recently.
+    pub fn relay_health(&self) -> Vec<RelayHealthStatus> {
+        self.health.lock().status()
+    }
+
+    /// Forgets the recorded health of all relays.
+    pub fn clear_relay_health(&self) {
+        self.health.lock().clear();
+    }
+
+    /// Returns the hostnames and IPv4 addresses of the active relays with the given hostnames, or
+    /// of all active relays if no hostnames are given.
+    pub fn relay_addresses(&self, hostnames: &[String]) -> Vec<(String, Ipv4Addr)> {
+        self.parsed_relays()
+            .relays()
+            .iter()
+            .filter(|relay| {
+                relay.active && (hostnames.is_empty() || hostnames.contains(&relay.hostname))
+            })
+            .map(|relay| (relay.hostname.clone(), relay.ipv4_addr_in))
+            .collect()
+    }
+
+    /// Returns the current relay list. The lock is only held while the list is being retrieved.
+    fn parsed_relays(&self) -> Arc<ParsedRelays> {
+        self.parsed_relays.lock().clone()
+    }
+
     /// Returns all countries and cities. The cities in the object returned does not have any
     /// relays in them.
     pub fn get_locations(&mut self) -> RelayList {
@@ -379,6 +567,7 @@ impl RelaySelector {
                     retry_attempt,
                     &original_constraints.location,
                     &original_constraints.providers,
+                    &original_constraints.filter,
                     wg_key_exists,
                 )
             } else {
@@ -428,8 +617,9 @@ impl RelaySelector {
                         port: Constraint::Any,
                     });
                 } else if openvpn_constraints.port.is_any() {
-                    let (preferred_port, preferred_protocol) =
-                        Self::preferred_openvpn_constraints(retry_attempt);
+                    let (preferred_port, preferred_protocol) = self
+                        .profile_constraints(retry_attempt)
+                        .unwrap_or_else(|| Self::preferred_openvpn_constraints(retry_attempt));
                     openvpn_constraints.port = Constraint::Only(TransportPort {
                         protocol: preferred_protocol,
                         port: preferred_port,
@@ -439,15 +629,25 @@ impl RelaySelector {
             Constraint::Only(TunnelType::Wireguard) => {
                 relay_constraints.wireguard_constraints =
                     original_constraints.wireguard_constraints.clone();
-                // This ensures that if after the first 2 failed attempts the daemon does not
-                // connect, then afterwards 2 of each 4 successive attempts will try to connect
-                // on port 53.
-                if retry_attempt % 4 > 1 && relay_constraints.wireguard_constraints.port.is_any() {
-                    relay_constraints.wireguard_constraints.port =
-                        Constraint::Only(TransportPort {
-                            protocol: TransportProtocol::Udp,
-                            port: Constraint::Only(53),
-                        });
+                let port_constraint = &mut relay_constraints.wireguard_constraints.port;
+                if bridge_state == BridgeState::On && port_constraint.is_any() {
+                    // Bridges can only relay WireGuard traffic to the TCP endpoints of the relays
+                    *port_constraint = Constraint::Only(TransportPort {
+                        protocol: TransportProtocol::Tcp,
+                        port: Constraint::Any,
+                    });
+                } else if let Some((port, protocol)) = self.profile_constraints(retry_attempt) {
+                    if port_constraint.is_any() {
+                        *port_constraint = Constraint::Only(TransportPort { protocol, port });
+                    }
+                } else if retry_attempt % 4 > 1 && port_constraint.is_any() {
+                    // This ensures that if after the first 2 failed attempts the daemon does not
+                    // connect, then afterwards 2 of each 4 successive attempts will try to
+                    // connect on port 53.
+                    *port_constraint = Constraint::Only(TransportPort {
+                        protocol: TransportProtocol::Udp,
+                        port: Constraint::Only(53),
+                    });
                 }
             }
         }
@@ -473,18 +673,19 @@ impl RelaySelector {
         let entry_constraints =
             self.preferred_constraints(&entry_constraints, BridgeState::Off, retry_attempt, true);
 
-        let matching_relays: Vec<Relay> = self
-            .parsed_relays
-            .lock()
-            .relays()
-            .iter()
-            .filter(|relay| relay.active)
-            .filter_map(|relay| Self::matching_relay(relay, &entry_constraints, exit_peer))
+        let parsed_relays = self.parsed_relays();
+        let matching_relays: Vec<&Relay> = parsed_relays
+            .candidates(
+                &entry_constraints.location,
+                entry_constraints.tunnel_protocol,
+            )
+            .filter(|relay| Self::relay_matches(relay, &entry_constraints, exit_peer))
             .collect();
+        let matching_relays = self.avoid_blacklisted(matching_relays);
 
         let relay = self
             .pick_random_relay(&matching_relays)
-            .map(|relay| relay.clone())?;
+            .and_then(|relay| Self::matching_relay(relay, &entry_constraints, exit_peer))?;
         let endpoint = self.get_random_tunnel(&relay, &entry_constraints)?;
         Some((relay, endpoint))
     }
@@ -538,13 +739,10 @@ impl RelaySelector {
         constraints: &InternalBridgeConstraints,
         location: &Location,
     ) -> Option<(ProxySettings, Relay)> {
-        let mut matching_relays: Vec<Relay> = self
-            .parsed_relays
-            .lock()
-            .relays()
-            .iter()
-            .filter(|relay| relay.active)
-            .filter_map(|relay| Self::matching_bridge_relay(relay, constraints))
+        let parsed_relays = self.parsed_relays();
+        let mut matching_relays: Vec<&Relay> = parsed_relays
+            .candidates(&constraints.location, Constraint::Any)
+            .filter(|relay| Self::bridge_relay_matches(relay, constraints))
             .collect();
 
         if matching_relays.is_empty() {
@@ -554,9 +752,64 @@ impl RelaySelector {
         matching_relays.sort_by_cached_key(|relay| {
             (relay.location.as_ref().unwrap().distance_from(&location) * 1000.0) as i64
         });
-        matching_relays.get(0).and_then(|relay| {
-            self.pick_random_bridge(&relay)
-                .map(|bridge| (bridge, relay.clone()))
+        let relay = Self::matching_bridge_relay(matching_relays[0], constraints)?;
+        self.pick_random_bridge(&relay)
+            .map(|bridge| (bridge, relay))
+    }
+
+    /// Picks a random Shadowsocks bridge that API traffic can be sent through. Bridges are
+    /// reached over TCP.
+    pub fn get_api_bridge(&mut self) -> Option<(ProxySettings, Relay)> {
+        let constraints = InternalBridgeConstraints {
+            location: Constraint::Any,
+            providers: Constraint::Any,
+            transport_protocol: Constraint::Only(TransportProtocol::Tcp),
+        };
+        let parsed_relays = self.parsed_relays();
+        let matching_relays: Vec<&Relay> = parsed_relays
+            .candidates(&constraints.location, Constraint::Any)
+            .filter(|relay| Self::bridge_relay_matches(relay, &constraints))
+            .collect();
+        let relay = self.pick_random_relay(&matching_relays)?;
+        let relay = Self::matching_bridge_relay(relay, &constraints)?;
+        self.pick_random_bridge(&relay)
+            .map(|bridge| (bridge, relay))
+    }
+
+    /// Picks a Shadowsocks endpoint on the relay that `peer` belongs to, for relaying the
+    /// WireGuard traffic to it. Unless a port is given, only the obfuscation ports that the relay
+    /// list allows for the relay are used.
+    pub fn get_shadowsocks_obfuscator(
+        &mut self,
+        peer: &wireguard::PeerConfig,
+        port: Constraint<u16>,
+    ) -> Option<wireguard::ObfuscatorConfig> {
+        let parsed_relays = self.parsed_relays();
+        let peer_ip = peer.endpoint.ip();
+        let relay = parsed_relays.relays().iter().find(|relay| {
+            IpAddr::from(relay.ipv4_addr_in) == peer_ip
+                || relay.ipv6_addr_in.map(IpAddr::from) == Some(peer_ip)
+        })?;
+        let endpoints: Vec<_> = relay
+            .tunnels
+            .wireguard
+            .iter()
+            .filter(|data| data.public_key == peer.public_key)
+            .flat_map(|data| data.shadowsocks.iter())
+            .filter(|endpoint| match port {
+                Constraint::Any => relay.metadata.allows_obfuscation_port(endpoint.port),
+                Constraint::Only(port) => port == endpoint.port,
+            })
+            .collect();
+        let shadowsocks_endpoint = endpoints.choose(&mut self.rng)?;
+        info!(
+            "Selected Shadowsocks obfuscator {} at {}:{}",
+            relay.hostname, peer_ip, shadowsocks_endpoint.port
+        );
+        Some(wireguard::ObfuscatorConfig::Shadowsocks {
+            endpoint: SocketAddr::new(peer_ip, shadowsocks_endpoint.port),
+            password: shadowsocks_endpoint.password.clone(),
+            cipher: shadowsocks_endpoint.cipher.clone(),
         })
     }
 
@@ -567,38 +820,49 @@ impl RelaySelector {
         retry_attempt: u32,
         location_constraint: &Constraint<LocationConstraint>,
         providers_constraint: &Constraint<Providers>,
+        filter: &Constraint<RelayFilter>,
         wg_key_exists: bool,
     ) -> (Constraint<u16>, TransportProtocol, TunnelType) {
         #[cfg(target_os = "windows")]
         {
-            let location_supports_openvpn =
-                self.parsed_relays.lock().relays().iter().any(|relay| {
-                    relay.active
-                        && !relay.tunnels.openvpn.is_empty()
-                        && location_constraint.matches(relay)
+            let location_supports_openvpn = self
+                .parsed_relays()
+                .candidates(location_constraint, Constraint::Only(TunnelType::OpenVpn))
+                .any(|relay| {
+                    location_constraint.matches(relay)
                         && providers_constraint.matches(relay)
+                        && Self::filter_matches(filter, relay, TunnelType::OpenVpn)
                 });
             if location_supports_openvpn {
-                let (preferred_port, preferred_protocol) =
-                    Self::preferred_openvpn_constraints(retry_attempt);
+                let (preferred_port, preferred_protocol) = self
+                    .profile_constraints(retry_attempt)
+                    .unwrap_or_else(|| Self::preferred_openvpn_constraints(retry_attempt));
                 return (preferred_port, preferred_protocol, TunnelType::OpenVpn);
             }
         }
 
-        let location_supports_wireguard = self.parsed_relays.lock().relays().iter().any(|relay| {
-            relay.active
-                && !relay.tunnels.wireguard.is_empty()
-                && location_constraint.matches(relay)
-                && providers_constraint.matches(relay)
-        });
+        let location_supports_wireguard = self
+            .parsed_relays()
+            .candidates(location_constraint, Constraint::Only(TunnelType::Wireguard))
+            .any(|relay| {
+                location_constraint.matches(relay)
+                    && providers_constraint.matches(relay)
+                    && Self::filter_matches(filter, relay, TunnelType::Wireguard)
+            });
         // If location does not support WireGuard, defer to preferred OpenVPN tunnel
         // constraints
         if !location_supports_wireguard || !wg_key_exists {
-            let (preferred_port, preferred_protocol) =
-                Self::preferred_openvpn_constraints(retry_attempt);
+            let (preferred_port, preferred_protocol) = self
+                .profile_constraints(retry_attempt)
+                .unwrap_or_else(|| Self::preferred_openvpn_constraints(retry_attempt));
             return (preferred_port, preferred_protocol, TunnelType::OpenVpn);
         }
 
+        if let Some((preferred_port, preferred_protocol)) = self.profile_constraints(retry_attempt)
+        {
+            return (preferred_port, preferred_protocol, TunnelType::Wireguard);
+        }
+
         // Try out WireGuard in the first two connection attempts, first with any port,
         // afterwards on port 53. Afterwards, connect through OpenVPN alternating between UDP
         // on any port twice and TCP on port 443 once.
@@ -621,6 +885,24 @@ impl RelaySelector {
         }
     }
 
+    /// Returns the port and protocol recommended by the connection profile of the current
+    /// country, during the first connection attempts.
+    fn profile_constraints(
+        &self,
+        retry_attempt: u32,
+    ) -> Option<(Constraint<u16>, TransportProtocol)> {
+        if retry_attempt >= CONNECTION_PROFILE_ATTEMPTS {
+            return None;
+        }
+        let connection_profiles = self.connection_profiles.lock();
+        let profile = connection_profiles.active_profile()?;
+        let port = profile
+            .port
+            .map(Constraint::Only)
+            .unwrap_or(Constraint::Any);
+        Some((port, profile.protocol))
+    }
+
     fn preferred_openvpn_constraints(retry_attempt: u32) -> (Constraint<u16>, TransportProtocol) {
         // Prefer UDP by default. But if that has failed a couple of times, then try TCP port
         // 443, which works for many with UDP problems. After that, just alternate
@@ -640,16 +922,15 @@ impl RelaySelector {
         constraints: &RelayConstraints,
         wg_entry_peer: Option<&wireguard::PeerConfig>,
     ) -> Option<(Relay, MullvadEndpoint)> {
-        let matching_relays: Vec<Relay> = self
-            .parsed_relays
-            .lock()
-            .relays()
-            .iter()
-            .filter(|relay| relay.active)
-            .filter_map(|relay| Self::matching_relay(relay, constraints, wg_entry_peer))
+        let parsed_relays = self.parsed_relays();
+        let matching_relays: Vec<&Relay> = parsed_relays
+            .candidates(&constraints.location, constraints.tunnel_protocol)
+            .filter(|relay| Self::relay_matches(relay, constraints, wg_entry_peer))
             .collect();
+        let matching_relays = self.avoid_blacklisted(matching_relays);
 
         self.pick_random_relay(&matching_relays)
+            .and_then(|relay| Self::matching_relay(relay, constraints, wg_entry_peer))
             .and_then(|selected_relay| {
                 let endpoint = self.get_random_tunnel(&selected_relay, &constraints);
                 let addr_in = endpoint
@@ -657,8 +938,53 @@ impl RelaySelector {
                     .map(|endpoint| endpoint.to_endpoint().address.ip())
                     .unwrap_or(IpAddr::from(selected_relay.ipv4_addr_in));
                 info!("Selected relay {} at {}", selected_relay.hostname, addr_in);
-                endpoint.map(|endpoint| (selected_relay.clone(), endpoint))
+                endpoint.map(|endpoint| (selected_relay, endpoint))
+            })
+    }
+
+    /// Returns whether the given relay matches the constraints, without cloning it.
+    fn relay_matches(
+        relay: &Relay,
+        constraints: &RelayConstraints,
+        skip_wg_peer: Option<&wireguard::PeerConfig>,
+    ) -> bool {
+        if !constraints.location.matches(relay)
+            || !constraints.providers.matches(relay)
+            || !constraints.filter.matches(relay)
+        {
+            return false;
+        }
+
+        let include_wg = skip_wg_peer
+            .map(|wg_peer| {
+                let peer_ip = wg_peer.endpoint.ip();
+                peer_ip != IpAddr::V4(relay.ipv4_addr_in)
+                    && Some(peer_ip) != relay.ipv6_addr_in.map(IpAddr::V6)
             })
+            .unwrap_or(true);
+        let wireguard_matches = || {
+            include_wg
+                && Self::filter_matches(&constraints.filter, relay, TunnelType::Wireguard)
+                && relay
+                    .tunnels
+                    .wireguard
+                    .iter()
+                    .any(|endpoint| constraints.wireguard_constraints.matches(endpoint))
+        };
+        let openvpn_matches = || {
+            Self::filter_matches(&constraints.filter, relay, TunnelType::OpenVpn)
+                && relay
+                    .tunnels
+                    .openvpn
+                    .iter()
+                    .any(|endpoint| constraints.openvpn_constraints.matches(endpoint))
+        };
+
+        match constraints.tunnel_protocol {
+            Constraint::Any => wireguard_matches() || openvpn_matches(),
+            Constraint::Only(TunnelType::Wireguard) => wireguard_matches(),
+            Constraint::Only(TunnelType::OpenVpn) => openvpn_matches(),
+        }
     }
 
     /// Takes a `Relay` and a corresponding `RelayConstraints` and returns a new `Relay` if the
@@ -674,6 +1000,9 @@ impl RelaySelector {
         if !constraints.providers.matches(&relay) {
             return None;
         }
+        if !constraints.filter.matches(relay) {
+            return None;
+        }
 
         let include_wg = if let Some(wg_peer) = skip_wg_peer {
             let peer_ip = wg_peer.endpoint.ip();
@@ -683,7 +1012,7 @@ impl RelaySelector {
             true
         };
 
-        let relay = match constraints.tunnel_protocol {
+        let mut relay = match constraints.tunnel_protocol {
             Constraint::Any => {
                 let mut relay = relay.clone();
                 relay.tunnels = RelayTunnels {
@@ -731,6 +1060,13 @@ impl RelaySelector {
             }
         };
 
+        // The filter may only allow some tunnel types for this relay
+        if !Self::filter_matches(&constraints.filter, &relay, TunnelType::Wireguard) {
+            relay.tunnels.wireguard.clear();
+        }
+        if !Self::filter_matches(&constraints.filter, &relay, TunnelType::OpenVpn) {
+            relay.tunnels.openvpn.clear();
+        }
 
         let relay_matches = match constraints.tunnel_protocol {
             Constraint::Any => {
@@ -747,6 +1083,27 @@ impl RelaySelector {
         }
     }
 
+    fn filter_matches(
+        filter: &Constraint<RelayFilter>,
+        relay: &Relay,
+        tunnel_type: TunnelType,
+    ) -> bool {
+        match filter {
+            Constraint::Any => true,
+            Constraint::Only(filter) => filter.matches_tunnel(relay, tunnel_type),
+        }
+    }
+
+    fn bridge_relay_matches(relay: &Relay, constraints: &InternalBridgeConstraints) -> bool {
+        constraints.location.matches(relay)
+            && constraints.providers.matches(relay)
+            && relay
+                .bridges
+                .shadowsocks
+                .iter()
+                .any(|bridge| constraints.transport_protocol.matches_eq(&bridge.protocol))
+    }
+
     fn matching_bridge_relay(
         relay: &Relay,
         constraints: &InternalBridgeConstraints,
@@ -794,47 +1151,85 @@ impl RelaySelector {
             .collect()
     }
 
+    /// Removes temporarily blacklisted relays from the given relays, unless that would leave no
+    /// relay to pick.
+    fn avoid_blacklisted<'a>(&self, relays: Vec<&'a Relay>) -> Vec<&'a Relay> {
+        let health = self.health.lock();
+        let healthy_relays: Vec<&Relay> = relays
+            .iter()
+            .filter(|relay| !health.is_blacklisted(&relay.hostname))
+            .copied()
+            .collect();
+        if healthy_relays.iter().any(|relay| relay.weight > 0) {
+            healthy_relays
+        } else {
+            relays
+        }
+    }
+
     /// Pick a random relay from the given slice. Will return `None` if the given slice is empty
-    /// or all relays in it has zero weight.
-    fn pick_random_relay<'a>(&mut self, relays: &'a [Relay]) -> Option<&'a Relay> {
-        let total_weight: u64 = relays.iter().map(|relay| relay.weight).sum();
+    /// or all relays in it has zero weight. The weights are scaled down for relays that have
+    /// failed or been slow to connect to recently, and for relays that have been measured to be far
+    /// away.
+    fn pick_random_relay<'a>(&mut self, relays: &[&'a Relay]) -> Option<&'a Relay> {
+        let weights: Vec<u64> = {
+            let health = self.health.lock();
+            let latencies = self.latencies.lock();
+            relays
+                .iter()
+                .map(|relay| {
+                    let weight = health.adjust_weight(&relay.hostname, relay.weight);
+                    latencies.adjust_weight(&relay.hostname, weight)
+                })
+                .collect()
+        };
+        let total_weight: u64 = weights.iter().sum();
         if total_weight == 0 {
             None
         } else {
             // Pick a random number in the range 0 - total_weight. This choses the relay.
             let mut i: u64 = self.rng.gen_range(0, total_weight + 1);
-            Some(
-                relays
-                    .iter()
-                    .find(|relay| {
-                        i = i.saturating_sub(relay.weight);
-                        i == 0
-                    })
-                    .unwrap(),
-            )
+            relays
+                .iter()
+                .zip(weights)
+                .find(|(_, weight)| {
+                    i = i.saturating_sub(*weight);
+                    i == 0
+                })
+                .map(|(relay, _)| *relay)
         }
     }
 
     /// Picks a random bridge from a relay.
     fn pick_random_bridge(&mut self, relay: &Relay) -> Option<ProxySettings> {
+        let address = self.get_address_for_relay(relay);
         relay
             .bridges
             .shadowsocks
             .choose(&mut self.rng)
             .map(|shadowsocks_endpoint| {
                 info!(
-                    "Selected Shadowsocks bridge {} at {}:{}/{}",
+                    "Selected Shadowsocks bridge {} at {}/{}",
                     relay.hostname,
-                    relay.ipv4_addr_in,
-                    shadowsocks_endpoint.port,
+                    SocketAddr::new(address, shadowsocks_endpoint.port),
                     shadowsocks_endpoint.protocol
                 );
-                shadowsocks_endpoint
-                    .clone()
-                    .to_proxy_settings(relay.ipv4_addr_in.into())
+                shadowsocks_endpoint.clone().to_proxy_settings(address)
             })
     }
 
+    /// Returns the address that a relay should be reached on when no IP version is specified.
+    /// This is the IPv6 address if the host only has IPv6 connectivity, and the IPv4 address
+    /// otherwise.
+    fn get_address_for_relay(&self, relay: &Relay) -> IpAddr {
+        // Fall back on IPv4 if the relay has no IPv6 address, since the connectivity may be
+        // inaccurate
+        match relay.ipv6_addr_in {
+            Some(ipv6_addr_in) if self.connectivity.lock().is_ipv6_only() => ipv6_addr_in.into(),
+            _ => relay.ipv4_addr_in.into(),
+        }
+    }
+
     fn get_random_tunnel(
         &mut self,
         relay: &Relay,
@@ -843,13 +1238,15 @@ impl RelaySelector {
         #[cfg(not(target_os = "android"))]
         let mut thread_rng = self.rng.clone();
         #[cfg(not(target_os = "android"))]
+        let openvpn_address = self.get_address_for_relay(relay);
+        #[cfg(not(target_os = "android"))]
         let mut new_openvpn_endpoint = || {
             relay
                 .tunnels
                 .openvpn
                 .choose(&mut thread_rng)
                 .cloned()
-                .map(|endpoint| endpoint.into_mullvad_endpoint(relay.ipv4_addr_in.into()))
+                .map(|endpoint| endpoint.into_mullvad_endpoint(openvpn_address))
         };
 
         let mut new_wg_endpoint = || {
@@ -896,6 +1293,7 @@ impl RelaySelector {
                 .port
                 .map(|port| port.protocol)
                 .unwrap_or(TransportProtocol::Udp),
+            psk: None,
         };
         Some(MullvadEndpoint::Wireguard {
             peer: peer_config,
@@ -911,7 +1309,8 @@ impl RelaySelector {
         constraints: &WireguardConstraints,
     ) -> Option<IpAddr> {
         match constraints.ip_version {
-            Constraint::Any | Constraint::Only(IpVersion::V4) => Some(relay.ipv4_addr_in.into()),
+            Constraint::Any => Some(self.get_address_for_relay(relay)),
+            Constraint::Only(IpVersion::V4) => Some(relay.ipv4_addr_in.into()),
             Constraint::Only(IpVersion::V6) => relay.ipv6_addr_in.map(|addr| addr.into()),
         }
     }
@@ -1014,9 +1413,11 @@ impl RelayListUpdaterHandle {
 struct RelayListUpdater {
     rpc_client: RelayListProxy,
     cache_path: PathBuf,
-    parsed_relays: Arc<Mutex<ParsedRelays>>,
+    resource_path: PathBuf,
+    parsed_relays: Arc<Mutex<Arc<ParsedRelays>>>,
+    health: Arc<Mutex<RelayHealth>>,
     on_update: Box<dyn Fn(&RelayList) + Send + 'static>,
-    earliest_next_try: Instant,
+    on_loaded: Option<Box<dyn FnOnce() + Send + 'static>>,
     api_availability: ApiAvailabilityHandle,
 }
 
@@ -1024,8 +1425,11 @@ impl RelayListUpdater {
     pub fn new(
         rpc_handle: MullvadRestHandle,
         cache_path: PathBuf,
-        parsed_relays: Arc<Mutex<ParsedRelays>>,
+        resource_path: PathBuf,
+        parsed_relays: Arc<Mutex<Arc<ParsedRelays>>>,
+        health: Arc<Mutex<RelayHealth>>,
         on_update: Box<dyn Fn(&RelayList) + Send + 'static>,
+        on_loaded: Box<dyn FnOnce() + Send + 'static>,
         api_availability: ApiAvailabilityHandle,
     ) -> RelayListUpdaterHandle {
         let (tx, cmd_rx) = mpsc::channel(1);
@@ -1034,9 +1438,11 @@ impl RelayListUpdater {
         let updater = RelayListUpdater {
             rpc_client,
             cache_path,
+            resource_path,
             parsed_relays,
+            health,
             on_update,
-            earliest_next_try: Instant::now() + UPDATE_INTERVAL,
+            on_loaded: Some(on_loaded),
             api_availability,
         };
 
@@ -1046,21 +1452,11 @@ impl RelayListUpdater {
     }
 
     async fn run(mut self, mut cmd_rx: mpsc::Receiver<bool>) {
-        let mut check_interval = tokio_stream::wrappers::IntervalStream::new(
-            tokio::time::interval(UPDATE_CHECK_INTERVAL),
-        )
-        .fuse();
+        self.load_cached_relays().await;
+
         let mut download_future = Box::pin(Fuse::terminated());
         loop {
             futures::select! {
-                _check_update = check_interval.next() => {
-                    if download_future.is_terminated() && self.should_update() {
-                        let tag = self.parsed_relays.lock().tag().map(|tag| tag.to_string());
-                        download_future = Box::pin(Self::download_relay_list(self.api_availability.clone(), self.rpc_client.clone(), tag).fuse());
-                        self.earliest_next_try = Instant::now() + UPDATE_INTERVAL;
-                    }
-                },
-
                 new_relay_list = download_future => {
                     self.consume_new_relay_list(new_relay_list).await;
 
@@ -1071,8 +1467,9 @@ impl RelayListUpdater {
                         Some(defer) => {
                             let tag = self.parsed_relays.lock().tag().map(|tag| tag.to_string());
                             if defer {
-                                let download_future = Self::download_relay_list(self.api_availability.clone(), self.rpc_client.clone(), tag);
-                                self.consume_new_relay_list(download_future.await).await;
+                                if download_future.is_terminated() {
+                                    download_future = Box::pin(Self::download_relay_list(self.api_availability.clone(), self.rpc_client.clone(), tag).fuse());
+                                }
                             } else {
                                 self.consume_new_relay_list(self.rpc_client.relay_list(tag).await.map_err(mullvad_rpc::Error::from)).await;
                             }
@@ -1088,6 +1485,44 @@ impl RelayListUpdater {
         }
     }
 
+    /// Reads the cached or bundled relay list from disk without blocking the async runtime.
+    async fn load_cached_relays(&mut self) {
+        let cache_path = self.cache_path.clone();
+        let resource_path = self.resource_path.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            RelaySelector::read_relays_from_disk(&cache_path, &resource_path)
+        })
+        .await;
+        let loaded_relays = match result {
+            Ok(Ok(loaded_relays)) => loaded_relays,
+            Ok(Err(error)) => {
+                error!(
+                    "{}",
+                    error.display_chain_with_msg("Unable to load cached relays")
+                );
+                ParsedRelays::empty()
+            }
+            Err(error) => {
+                error!("Failed to read cached relays: {}", error);
+                ParsedRelays::empty()
+            }
+        };
+        info!(
+            "Initialized with {} cached relays from {}",
+            loaded_relays.relays().len(),
+            DateTime::<Local>::from(loaded_relays.last_updated()).format(DATE_TIME_FORMAT_STR)
+        );
+
+        {
+            let mut parsed_relays = self.parsed_relays.lock();
+            *parsed_relays = Arc::new(loaded_relays);
+            (self.on_update)(parsed_relays.locations());
+        }
+        if let Some(on_loaded) = self.on_loaded.take() {
+            on_loaded();
+        }
+    }
+
     async fn consume_new_relay_list(
         &mut self,
         result: Result<Option<RelayList>, mullvad_rpc::Error>,
@@ -1100,29 +1535,11 @@ impl RelayListUpdater {
             }
             Ok(None) => log::debug!("Relay list is up-to-date"),
             Err(err) => {
-                log::error!(
-                    "Failed to fetch new relay list: {}. Will retry in {} minutes",
-                    err,
-                    self.earliest_next_try
-                        .saturating_duration_since(Instant::now())
-                        .as_secs()
-                        / 60
-                );
+                log::error!("Failed to fetch new relay list: {}", err);
             }
         }
     }
 
-    /// Returns true if the current parsed_relays is older than UPDATE_INTERVAL
-    fn should_update(&mut self) -> bool {
-        match SystemTime::now().duration_since(self.parsed_relays.lock().last_updated()) {
-            Ok(duration) => duration > UPDATE_INTERVAL && self.earliest_next_try <= Instant::now(),
-            // If the clock is skewed we have no idea by how much or when the last update
-            // actually was, better download again to get in sync and get a `last_updated`
-            // timestamp corresponding to the new time.
-            Err(_) => true,
-        }
-    }
-
     fn download_relay_list(
         api_handle: ApiAvailabilityHandle,
         rpc_handle: RelayListProxy,
@@ -1164,7 +1581,9 @@ impl RelayListUpdater {
         );
 
         let mut parsed_relays = self.parsed_relays.lock();
-        *parsed_relays = new_parsed_relays;
+        *parsed_relays = Arc::new(new_parsed_relays);
+        // Relays that were broken may have been fixed or replaced
+        self.health.lock().clear();
         (self.on_update)(parsed_relays.locations());
         Ok(())
     }
@@ -1191,7 +1610,7 @@ mod test {
     use mullvad_types::{
         relay_constraints::RelayConstraints,
         relay_list::{
-            Relay, RelayBridges, RelayListCity, RelayListCountry, RelayTunnels,
+            Relay, RelayBridges, RelayListCity, RelayListCountry, RelayMetadata, RelayTunnels,
             WireguardEndpointData,
         },
     };
@@ -1228,6 +1647,7 @@ mod test {
                                                 ipv4_gateway: "10.64.0.1".parse().unwrap(),
                                                 ipv6_gateway: "fc00:bbbb:bbbb:bb01::1".parse().unwrap(),
                                                 public_key: PublicKey::from_base64("BLNHNoGO88LjV/wDBa7CUUwUzPq/fO2UwcGLy56hKy4=").unwrap(),
+                                                shadowsocks: vec![],
                                                 protocol: TransportProtocol::Udp,
                                             },
                                         ],
@@ -1236,6 +1656,7 @@ mod test {
                                         shadowsocks: vec![],
                                     },
                                     location: None,
+                                    metadata: RelayMetadata::default(),
                                 },
                                 Relay {
                                     hostname: "se10-wireguard".to_string(),
@@ -1254,6 +1675,7 @@ mod test {
                                                 ipv4_gateway: "10.64.0.1".parse().unwrap(),
                                                 ipv6_gateway: "fc00:bbbb:bbbb:bb01::1".parse().unwrap(),
                                                 public_key: PublicKey::from_base64("veGD6/aEY6sMfN3Ls7YWPmNgu3AheO7nQqsFT47YSws=").unwrap(),
+                                                shadowsocks: vec![],
                                                 protocol: TransportProtocol::Udp,
                                             },
                                         ],
@@ -1262,6 +1684,7 @@ mod test {
                                         shadowsocks: vec![],
                                     },
                                     location: None,
+                                    metadata: RelayMetadata::default(),
                                 },
                                 Relay {
                                     hostname: "se-got-001".to_string(),
@@ -1293,6 +1716,7 @@ mod test {
                                         shadowsocks: vec![],
                                     },
                                     location: None,
+                                    metadata: RelayMetadata::default(),
                                 },
                             ],
                         },
@@ -1303,14 +1727,7 @@ mod test {
     }
 
     fn new_relay_selector() -> RelaySelector {
-        RelaySelector {
-            parsed_relays: Arc::new(Mutex::new(ParsedRelays::from_relay_list(
-                RELAYS.clone(),
-                SystemTime::now(),
-            ))),
-            rng: rand::thread_rng(),
-            updater: None,
-        }
+        RelaySelector::from_relay_list(RELAYS.clone())
     }
 
     #[test]
@@ -1396,6 +1813,30 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_wireguard_over_bridge_uses_tcp() {
+        let mut relay_selector = new_relay_selector();
+        let relay_constraints = RelayConstraints {
+            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+            ..RelayConstraints::default()
+        };
+
+        for attempt in 0..10 {
+            match relay_selector.get_tunnel_exit_endpoint(
+                &relay_constraints,
+                BridgeState::On,
+                attempt,
+                true,
+                None,
+            ) {
+                Ok((_, MullvadEndpoint::Wireguard { peer, .. })) => {
+                    assert_eq!(peer.protocol, TransportProtocol::Tcp)
+                }
+                _ => panic!("WireGuard endpoint was not selected"),
+            }
+        }
+    }
+
     #[test]
     fn test_wg_entry_hostname_collision() {
         let mut relay_selector = new_relay_selector();
@@ -1484,4 +1925,194 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_relay_filter() {
+        let mut relay_selector = new_relay_selector();
+
+        let mut relay_constraints = RelayConstraints {
+            filter: Constraint::Only(
+                "hostname == se10-wireguard || protocol == openvpn"
+                    .parse()
+                    .unwrap(),
+            ),
+            ..RelayConstraints::default()
+        };
+
+        for attempt in 0..10 {
+            let (relay, endpoint) = relay_selector
+                .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, attempt, true)
+                .unwrap();
+            match endpoint {
+                MullvadEndpoint::Wireguard { .. } => assert_eq!(relay.hostname, "se10-wireguard"),
+                MullvadEndpoint::OpenVpn(..) => assert_eq!(relay.hostname, "se-got-001"),
+            }
+        }
+
+        // No relay matches both the filter and the tunnel protocol constraint
+        relay_constraints.filter = Constraint::Only("protocol == openvpn".parse().unwrap());
+        relay_constraints.tunnel_protocol = Constraint::Only(TunnelType::Wireguard);
+        assert!(relay_selector
+            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true)
+            .is_err());
+    }
+
+    #[test]
+    fn test_candidates_match_location() {
+        let parsed_relays = ParsedRelays::from_relay_list(RELAYS.clone(), SystemTime::now());
+        let locations = [
+            Constraint::Any,
+            Constraint::Only(LocationConstraint::Country("se".to_string())),
+            Constraint::Only(LocationConstraint::City(
+                "se".to_string(),
+                "got".to_string(),
+            )),
+            Constraint::Only(LocationConstraint::Hostname(
+                "se".to_string(),
+                "got".to_string(),
+                "se9-wireguard".to_string(),
+            )),
+            Constraint::Only(LocationConstraint::Country("xx".to_string())),
+        ];
+        let tunnel_protocols = [
+            Constraint::Any,
+            Constraint::Only(TunnelType::Wireguard),
+            Constraint::Only(TunnelType::OpenVpn),
+        ];
+
+        for location in &locations {
+            for tunnel_protocol in &tunnel_protocols {
+                let expected: Vec<&str> = parsed_relays
+                    .relays()
+                    .iter()
+                    .filter(|relay| relay.active && location.matches(relay))
+                    .filter(|relay| match tunnel_protocol {
+                        Constraint::Any => true,
+                        Constraint::Only(TunnelType::Wireguard) => {
+                            !relay.tunnels.wireguard.is_empty()
+                        }
+                        Constraint::Only(TunnelType::OpenVpn) => !relay.tunnels.openvpn.is_empty(),
+                    })
+                    .map(|relay| relay.hostname.as_str())
+                    .collect();
+                let candidates: Vec<&str> = parsed_relays
+                    .candidates(location, *tunnel_protocol)
+                    .filter(|relay| location.matches(relay))
+                    .map(|relay| relay.hostname.as_str())
+                    .collect();
+                assert_eq!(candidates, expected, "{:?} {:?}", location, tunnel_protocol);
+            }
+        }
+    }
+
+    #[test]
+    fn test_wireguard_endpoint_follows_connectivity() {
+        let mut relay_selector = new_relay_selector();
+        let relay_constraints = RelayConstraints {
+            location: Constraint::Only(LocationConstraint::Hostname(
+                "se".to_string(),
+                "got".to_string(),
+                "se9-wireguard".to_string(),
+            )),
+            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+            ..RelayConstraints::default()
+        };
+        let endpoint_address = |relay_selector: &mut RelaySelector| match relay_selector
+            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true)
+        {
+            Ok((_, MullvadEndpoint::Wireguard { peer, .. })) => peer.endpoint.ip(),
+            result => panic!("Unexpected result: {:?}", result),
+        };
+
+        assert!(endpoint_address(&mut relay_selector).is_ipv4());
+
+        *relay_selector.connectivity_handle().lock() = Connectivity {
+            ipv4: false,
+            ipv6: true,
+        };
+        assert!(endpoint_address(&mut relay_selector).is_ipv6());
+    }
+
+    #[test]
+    fn test_openvpn_endpoint_falls_back_on_ipv4() {
+        let mut relay_selector = new_relay_selector();
+        *relay_selector.connectivity_handle().lock() = Connectivity {
+            ipv4: false,
+            ipv6: true,
+        };
+        let relay_constraints = RelayConstraints {
+            location: Constraint::Only(LocationConstraint::Hostname(
+                "se".to_string(),
+                "got".to_string(),
+                "se-got-001".to_string(),
+            )),
+            tunnel_protocol: Constraint::Only(TunnelType::OpenVpn),
+            ..RelayConstraints::default()
+        };
+
+        // The relay has no IPv6 address
+        match relay_selector.get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true) {
+            Ok((_, MullvadEndpoint::OpenVpn(endpoint))) => assert!(endpoint.address.is_ipv4()),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_connection_profile_first_attempts() {
+        use mullvad_types::connection_profile::{ConnectionProfile, ConnectionProfiles};
+
+        let mut relay_selector = new_relay_selector();
+        {
+            let handle = relay_selector.connection_profiles_handle();
+            let mut state = handle.lock();
+            state.set_profiles(ConnectionProfiles {
+                profiles: vec![ConnectionProfile {
+                    country: "Freedonia".to_string(),
+                    protocol: TransportProtocol::Tcp,
+                    port: Some(443),
+                    obfuscation: false,
+                }],
+            });
+            state.set_enabled(true);
+            state.set_country("Freedonia".to_string());
+        }
+        let relay_constraints = RelayConstraints {
+            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+            ..RelayConstraints::default()
+        };
+        let profile_port = Constraint::Only(TransportPort {
+            protocol: TransportProtocol::Tcp,
+            port: Constraint::Only(443),
+        });
+
+        for attempt in 0..CONNECTION_PROFILE_ATTEMPTS {
+            let preferred = relay_selector.preferred_constraints(
+                &relay_constraints,
+                BridgeState::Off,
+                attempt,
+                true,
+            );
+            assert_eq!(preferred.wireguard_constraints.port, profile_port);
+        }
+        let preferred = relay_selector.preferred_constraints(
+            &relay_constraints,
+            BridgeState::Off,
+            CONNECTION_PROFILE_ATTEMPTS,
+            true,
+        );
+        assert_ne!(preferred.wireguard_constraints.port, profile_port);
+
+        // Explicit constraints take precedence over the profile
+        let mut relay_constraints = relay_constraints;
+        relay_constraints.wireguard_constraints.port = Constraint::Only(TransportPort {
+            protocol: TransportProtocol::Udp,
+            port: Constraint::Only(51820),
+        });
+        let preferred =
+            relay_selector.preferred_constraints(&relay_constraints, BridgeState::Off, 0, true);
+        assert_eq!(
+            preferred.wireguard_constraints.port,
+            relay_constraints.wireguard_constraints.port
+        );
+    }
 }
@@ -0,0 +1,281 @@
+//! Runs periodic background tasks that talk to the API, such as relay list updates and version
+//! checks. The scheduler also owns the daemon's only periodic timer while idle. Components that
+//! need to compare deadlines against the wall clock subscribe to its ticks rather than running
+//! timers of their own, so that the process wakes up once per `CHECK_INTERVAL`.
+use futures::{
+    channel::{mpsc, oneshot},
+    future::BoxFuture,
+    stream::FuturesUnordered,
+    FutureExt, StreamExt,
+};
+use mullvad_rpc::availability::ApiAvailabilityHandle;
+use rand::Rng;
+use std::time::{Duration, SystemTime};
+
+/// How often the scheduler wakes up to check whether any task is due. Deadlines are compared
+/// against the wall clock rather than sleeping for the whole interval, since a sleeping timer does
+/// not advance while the computer is suspended.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Intervals are randomly lengthened or shortened by up to this fraction, so that clients do not
+/// contact the API in lockstep.
+const JITTER_FRACTION: f64 = 0.1;
+/// When the tunnel connects, tasks are only run early if they have not run within this long.
+const MIN_INTERVAL_ON_CONNECT: Duration = Duration::from_secs(5 * 60);
+
+pub type TaskAction = Box<dyn FnMut() -> BoxFuture<'static, ()> + Send>;
+
+/// Describes a scheduled task and when it will run next.
+#[derive(Debug, Clone)]
+pub struct ScheduledTaskInfo {
+    pub name: &'static str,
+    pub interval: Duration,
+    pub next_run: SystemTime,
+    pub last_run: Option<SystemTime>,
+    pub running: bool,
+}
+
+struct ScheduledTask {
+    name: &'static str,
+    interval: Duration,
+    refresh_on_connect: bool,
+    next_run: SystemTime,
+    last_run: Option<SystemTime>,
+    running: bool,
+    action: TaskAction,
+}
+
+impl ScheduledTask {
+    fn is_due(&self, now: SystemTime) -> bool {
+        !self.running && self.next_run <= now
+    }
+}
+
+enum SchedulerCommand {
+    TunnelConnected,
+    GetTasks(oneshot::Sender<Vec<ScheduledTaskInfo>>),
+    RetryTask(&'static str, Duration),
+    Subscribe(mpsc::UnboundedSender<()>),
+}
+
+/// Runs registered tasks at jittered intervals. Due tasks are held back while the host is
+/// offline or on a metered network.
+pub struct Scheduler {
+    tasks: Vec<ScheduledTask>,
+    api_availability: ApiAvailabilityHandle,
+    rx: Option<mpsc::UnboundedReceiver<SchedulerCommand>>,
+    tick_subscribers: Vec<mpsc::UnboundedSender<()>>,
+}
+
+#[derive(Clone)]
+pub struct SchedulerHandle {
+    tx: mpsc::UnboundedSender<SchedulerCommand>,
+}
+
+impl SchedulerHandle {
+    /// Runs tasks that should be refreshed when the tunnel connects.
+    pub fn tunnel_connected(&self) {
+        let _ = self.tx.unbounded_send(SchedulerCommand::TunnelConnected);
+    }
+
+    /// Returns all scheduled tasks.
+    pub async fn scheduled_tasks(&self) -> Vec<ScheduledTaskInfo> {
+        let (tx, rx) = oneshot::channel();
+        if self
+            .tx
+            .unbounded_send(SchedulerCommand::GetTasks(tx))
+            .is_err()
+        {
+            return vec![];
+        }
+        rx.await.unwrap_or_default()
+    }
+
+    /// Runs a task again after `delay` instead of after its regular interval. Used by tasks that
+    /// failed and should be retried sooner.
+    pub fn retry_task(&self, name: &'static str, delay: Duration) {
+        let _ = self
+            .tx
+            .unbounded_send(SchedulerCommand::RetryTask(name, delay));
+    }
+
+    /// Returns a stream that yields every time the scheduler checks for due tasks, which is
+    /// roughly once every `CHECK_INTERVAL`. It ends when the scheduler stops.
+    pub fn ticks(&self) -> mpsc::UnboundedReceiver<()> {
+        let (tx, rx) = mpsc::unbounded();
+        let _ = self.tx.unbounded_send(SchedulerCommand::Subscribe(tx));
+        rx
+    }
+}
+
+impl Scheduler {
+    pub fn new(api_availability: ApiAvailabilityHandle) -> (Self, SchedulerHandle) {
+        let (tx, rx) = mpsc::unbounded();
+        (
+            Self {
+                tasks: vec![],
+                api_availability,
+                rx: Some(rx),
+                tick_subscribers: vec![],
+            },
+            SchedulerHandle { tx },
+        )
+    }
+
+    /// Adds a task that first runs after `initial_delay`, and then every `interval`.
+    pub fn add_task(
+        &mut self,
+        name: &'static str,
+        interval: Duration,
+        initial_delay: Duration,
+        refresh_on_connect: bool,
+        action: TaskAction,
+    ) {
+        self.tasks.push(ScheduledTask {
+            name,
+            interval,
+            refresh_on_connect,
+            next_run: SystemTime::now() + initial_delay,
+            last_run: None,
+            running: false,
+            action,
+        });
+    }
+
+    pub async fn run(mut self) {
+        let mut rx = self.rx.take().unwrap();
+        let mut check_interval =
+            tokio_stream::wrappers::IntervalStream::new(tokio::time::interval(CHECK_INTERVAL))
+                .fuse();
+        let mut running_tasks = FuturesUnordered::new();
+
+        loop {
+            futures::select! {
+                _check = check_interval.next() => {
+                    self.run_due_tasks(&mut running_tasks).await;
+                    self.tick_subscribers
+                        .retain(|subscriber| subscriber.unbounded_send(()).is_ok());
+                }
+
+                index = running_tasks.select_next_some() => {
+                    self.tasks[index].running = false;
+                }
+
+                command = rx.next() => match command {
+                    Some(SchedulerCommand::TunnelConnected) => {
+                        self.on_tunnel_connected();
+                        self.run_due_tasks(&mut running_tasks).await;
+                    }
+                    Some(SchedulerCommand::GetTasks(tx)) => {
+                        let _ = tx.send(self.task_info());
+                    }
+                    Some(SchedulerCommand::RetryTask(name, delay)) => {
+                        self.retry_task(name, delay);
+                    }
+                    Some(SchedulerCommand::Subscribe(subscriber)) => {
+                        self.tick_subscribers.push(subscriber);
+                    }
+                    None => {
+                        log::debug!("Task scheduler shutting down");
+                        return;
+                    }
+                },
+            }
+        }
+    }
+
+    async fn run_due_tasks(
+        &mut self,
+        running_tasks: &mut FuturesUnordered<BoxFuture<'static, usize>>,
+    ) {
+        if self.api_availability.get_state().is_offline() {
+            return;
+        }
+
+        let now = SystemTime::now();
+        if !self.tasks.iter().any(|task| task.is_due(now)) {
+            return;
+        }
+        if is_metered().await {
+            log::debug!("Holding back scheduled tasks while on a metered network");
+            return;
+        }
+
+        for (index, task) in self.tasks.iter_mut().enumerate() {
+            if !task.is_due(now) {
+                continue;
+            }
+            log::debug!("Running scheduled task \"{}\"", task.name);
+            task.running = true;
+            task.last_run = Some(now);
+            task.next_run = now + jittered(task.interval);
+            running_tasks.push((task.action)().map(move |_| index).boxed());
+        }
+    }
+
+    fn on_tunnel_connected(&mut self) {
+        let now = SystemTime::now();
+        for task in self.tasks.iter_mut().filter(|task| task.refresh_on_connect) {
+            let ran_recently = task
+                .last_run
+                .and_then(|last_run| now.duration_since(last_run).ok())
+                .map(|elapsed| elapsed < MIN_INTERVAL_ON_CONNECT)
+                .unwrap_or(false);
+            if !ran_recently {
+                task.next_run = now;
+            }
+        }
+    }
+
+    fn retry_task(&mut self, name: &'static str, delay: Duration) {
+        match self.tasks.iter_mut().find(|task| task.name == name) {
+            Some(task) => task.next_run = SystemTime::now() + delay,
+            None => log::error!("Cannot retry unknown task \"{}\"", name),
+        }
+    }
+
+    fn task_info(&self) -> Vec<ScheduledTaskInfo> {
+        self.tasks
+            .iter()
+            .map(|task| ScheduledTaskInfo {
+                name: task.name,
+                interval: task.interval,
+                next_run: task.next_run,
+                last_run: task.last_run,
+                running: task.running,
+            })
+            .collect()
+    }
+}
+
+#[cfg(not(target_os = "android"))]
+async fn is_metered() -> bool {
+    tokio::task::spawn_blocking(talpid_core::network_identity::is_metered)
+        .await
+        .unwrap_or(false)
+}
+
+/// Metered networks are not detected on Android.
+#[cfg(target_os = "android")]
+async fn is_metered() -> bool {
+    false
+}
+
+fn jittered(interval: Duration) -> Duration {
+    let factor = rand::thread_rng().gen_range(1.0 - JITTER_FRACTION, 1.0 + JITTER_FRACTION);
+    interval.mul_f64(factor)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_jitter_bounds() {
+        let interval = Duration::from_secs(60 * 60);
+        for _ in 0..100 {
+            let jittered = jittered(interval);
+            assert!(jittered >= interval.mul_f64(1.0 - JITTER_FRACTION));
+            assert!(jittered <= interval.mul_f64(1.0 + JITTER_FRACTION));
+        }
+    }
+}
@@ -1,18 +1,38 @@
+use crate::{
+    credential_store::{self, CredentialStore, Credentials},
+    migrations,
+    settings_encryption::{self, SettingsCipher, UndecryptableValues},
+};
 #[cfg(not(target_os = "android"))]
 use futures::TryFutureExt;
 use log::{debug, error, info};
 use mullvad_types::{
+    account::AccountExpirySettings,
+    api_access::{
+        ApiAccessMethod, ApiResolutionStrategy, BRIDGES_ACCESS_METHOD, DIRECT_ACCESS_METHOD,
+    },
+    beta_features::BetaFeature,
+    network_rules::{NetworkAction, NetworkMatch, NetworkRule},
+    obfuscation::ObfuscationSettings,
     relay_constraints::{BridgeSettings, BridgeState, RelaySettingsUpdate},
-    settings::{DnsOptions, Settings},
-    wireguard::{RotationInterval, WireguardData},
+    settings::{
+        AllowedSystemServices, CredentialStorage, DnsOptions, ExitIpCheckSettings, HookSettings,
+        LanProxySettings, LocalApiSettings, LogPrivacyLevel, LogRotationSettings,
+        ReconnectBackoffSettings, Settings, TunnelParameterOverrides,
+    },
+    units::HumanDuration,
+    wireguard::{QuantumResistantState, RotationInterval, WireguardData},
 };
-#[cfg(target_os = "windows")]
+#[cfg(any(windows, target_os = "linux"))]
 use std::collections::HashSet;
 use std::{
     ops::Deref,
     path::{Path, PathBuf},
 };
-use talpid_types::ErrorExt;
+use talpid_types::{
+    net::{AllowedEndpoint, Ipv6FailurePolicy},
+    ErrorExt,
+};
 use tokio::{
     fs,
     io::{self, AsyncWriteExt},
@@ -21,6 +41,9 @@ use tokio::{
 
 const SETTINGS_FILE: &str = "settings.json";
 
+/// File that is briefly created in the settings directory to find out whether it is writable.
+const WRITE_PROBE_FILE: &str = ".write-probe";
+
 
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -34,8 +57,17 @@ pub enum Error {
     #[error(display = "Unable to write settings to {}", _0)]
     WriteError(String, #[error(source)] io::Error),
 
+    #[error(display = "The settings directory is read-only, so settings cannot be changed")]
+    ReadOnly,
+
     #[error(display = "Unable to set settings file permissions")]
     SetPermissions(#[error(source)] io::Error),
+
+    #[error(display = "Unable to encrypt sensitive settings")]
+    EncryptionError(#[error(source)] settings_encryption::Error),
+
+    #[error(display = "Unable to access the credential store")]
+    CredentialStoreError(#[error(source)] credential_store::Error),
 }
 
 #[derive(err_derive::Error, Debug)]
@@ -49,6 +81,9 @@ enum LoadSettingsError {
     #[error(display = "Unable to parse settings file")]
     ParseError(#[error(source)] mullvad_types::settings::Error),
 
+    #[error(display = "Unable to migrate settings file")]
+    Migration(#[error(source)] migrations::Error),
+
     #[cfg(windows)]
     #[error(display = "Failed to restore Windows update backup")]
     WinMigrationError(#[error(source)] windows::Error),
@@ -58,14 +93,72 @@ enum LoadSettingsError {
 #[derive(Debug)]
 pub struct SettingsPersister {
     settings: Settings,
+    /// The settings as they were last loaded or saved. Changes that cannot be saved since the
+    /// settings directory is read-only are reverted to these.
+    saved_settings: Settings,
     path: PathBuf,
+    encryption: EncryptionState,
+    /// The credentials as they were last read from or written to the credential store. `None` if
+    /// they are kept in the settings file, or if they could not be read.
+    stored_credentials: Option<Credentials>,
+    /// Whether the settings directory cannot be written to. All changes are rejected if so.
+    read_only: bool,
+}
+
+/// Keeps track of the key used to encrypt sensitive settings.
+#[derive(Debug, Default)]
+struct EncryptionState {
+    cipher: Option<SettingsCipher>,
+    /// Values that could not be decrypted when loading the settings. They are written back
+    /// unchanged, unless the settings have been replaced.
+    undecryptable_values: UndecryptableValues,
+}
+
+/// Reads only the log rotation and privacy settings, so that they can be applied before the
+/// logger is set up and the settings are loaded. The defaults are returned if they cannot be read.
+pub fn read_logging_settings(settings_dir: &Path) -> (LogRotationSettings, LogPrivacyLevel) {
+    #[derive(Default, serde::Deserialize)]
+    #[serde(default)]
+    struct PartialSettings {
+        log_rotation: LogRotationSettings,
+        log_privacy: LogPrivacyLevel,
+    }
+
+    let settings = std::fs::read(settings_dir.join(SETTINGS_FILE))
+        .ok()
+        .and_then(|bytes| serde_json::from_slice::<PartialSettings>(&bytes).ok())
+        .unwrap_or_default();
+    let log_rotation = Some(settings.log_rotation)
+        .filter(|log_rotation| log_rotation.validate().is_ok())
+        .unwrap_or_default();
+    (log_rotation, settings.log_privacy)
 }
 
 impl SettingsPersister {
     /// Loads user settings from file. If no file is present it returns the defaults.
     pub async fn load(settings_dir: &Path) -> Self {
         let path = settings_dir.join(SETTINGS_FILE);
-        let (mut settings, mut should_save) = Self::load_settings(&path).await;
+        let read_only = is_read_only(settings_dir).await;
+        if read_only {
+            log::warn!(
+                "The settings directory {} is read-only. Settings cannot be changed",
+                settings_dir.display()
+            );
+        }
+        let mut encryption = EncryptionState::default();
+        let mut stored_credentials = None;
+        let (mut settings, mut should_save) =
+            Self::load_settings(&path, &mut encryption, &mut stored_credentials).await;
+
+        if settings.encrypt_sensitive_settings && encryption.cipher.is_none() && !read_only {
+            match Self::load_cipher(settings_dir, true).await {
+                Ok(cipher) => encryption.cipher = cipher,
+                Err(error) => error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to get the settings encryption key")
+                ),
+            }
+        }
 
         // Force IPv6 to be enabled on Android
         if cfg!(target_os = "android") {
@@ -73,9 +166,16 @@ impl SettingsPersister {
                 Self::update_field(&mut settings.tunnel_options.generic.enable_ipv6, true);
         }
 
-        let mut persister = SettingsPersister { settings, path };
+        let mut persister = SettingsPersister {
+            saved_settings: settings.clone(),
+            settings,
+            path,
+            encryption,
+            stored_credentials,
+            read_only,
+        };
 
-        if should_save {
+        if should_save && !read_only {
             if let Err(error) = persister.save().await {
                 error!(
                     "{}",
@@ -87,8 +187,13 @@ impl SettingsPersister {
         persister
     }
 
-    async fn load_settings(path: &Path) -> (Settings, bool) {
-        let error = match Self::load_settings_from_file(path).await {
+    async fn load_settings(
+        path: &Path,
+        encryption: &mut EncryptionState,
+        stored_credentials: &mut Option<Credentials>,
+    ) -> (Settings, bool) {
+        let error = match Self::load_settings_from_file(path, encryption, stored_credentials).await
+        {
             Ok(value) => return value,
             Err(error) => error,
         };
@@ -99,10 +204,13 @@ impl SettingsPersister {
                 "No settings file found. Attempting migration from Windows update backup location"
             );
             match windows::migrate_after_windows_update() {
-                Ok(Some(())) => match Self::load_settings_from_file(path).await {
-                    Ok(value) => return value,
-                    Err(error) => error,
-                },
+                Ok(Some(())) => {
+                    match Self::load_settings_from_file(path, encryption, stored_credentials).await
+                    {
+                        Ok(value) => return value,
+                        Err(error) => error,
+                    }
+                }
                 Ok(None) => LoadSettingsError::FileNotFound,
                 Err(error) => LoadSettingsError::WinMigrationError(error),
             }
@@ -122,7 +230,11 @@ impl SettingsPersister {
         (Settings::default(), true)
     }
 
-    async fn load_settings_from_file(path: &Path) -> Result<(Settings, bool), LoadSettingsError> {
+    async fn load_settings_from_file(
+        path: &Path,
+        encryption: &mut EncryptionState,
+        stored_credentials: &mut Option<Credentials>,
+    ) -> Result<(Settings, bool), LoadSettingsError> {
         info!("Loading settings from {}", path.display());
 
         let settings_bytes = fs::read(path).await.map_err(|error| {
@@ -133,31 +245,225 @@ impl SettingsPersister {
             }
         })?;
 
-        Settings::load_from_bytes(&settings_bytes)
-            .map(|settings| (settings, false))
-            .or_else(|_| {
-                Settings::migrate_from_bytes(&settings_bytes).map(|settings| (settings, true))
-            })
-            .map_err(LoadSettingsError::ParseError)
+        let mut value: serde_json::Value =
+            serde_json::from_slice(&settings_bytes).map_err(|error| {
+                LoadSettingsError::ParseError(mullvad_types::settings::Error::ParseError(error))
+            })?;
+
+        let has_encrypted_values = settings_encryption::contains_encrypted_values(&value);
+        let has_plaintext_values = settings_encryption::contains_plaintext_values(&value);
+        if has_encrypted_values {
+            Self::decrypt_settings(path, &mut value, encryption).await;
+        }
+        let mut has_misplaced_credentials = false;
+        let storage = credential_store::storage(&value);
+        if storage != CredentialStorage::Settings {
+            has_misplaced_credentials = credential_store::contains_credentials(&value);
+            *stored_credentials = Self::read_credentials(path, storage, &mut value).await;
+        }
+
+        // Keep a copy of the file as it was, since the migrated settings replace it.
+        let should_save = migrations::needs_migration(&value);
+        if should_save {
+            migrations::backup(path, migrations::version(&value)).await;
+            migrations::migrate(&mut value).map_err(LoadSettingsError::Migration)?;
+        }
+
+        let settings = Settings::load_from_value(value).map_err(LoadSettingsError::ParseError)?;
+
+        // Transparently encrypt or decrypt the sensitive settings if the file does not match
+        // the current setting.
+        let encryption_changed = if settings.encrypt_sensitive_settings {
+            has_plaintext_values
+        } else {
+            has_encrypted_values
+        };
+
+        Ok((
+            settings,
+            should_save || encryption_changed || has_misplaced_credentials,
+        ))
+    }
+
+    async fn decrypt_settings(
+        path: &Path,
+        settings: &mut serde_json::Value,
+        encryption: &mut EncryptionState,
+    ) {
+        let settings_dir = path.parent().unwrap_or(path);
+        match Self::load_cipher(settings_dir, false).await {
+            Ok(Some(cipher)) => encryption.cipher = Some(cipher),
+            Ok(None) => error!("The settings are encrypted, but no encryption key was found"),
+            Err(error) => error!(
+                "{}",
+                error.display_chain_with_msg("Failed to get the settings encryption key")
+            ),
+        }
+        encryption.undecryptable_values =
+            settings_encryption::decrypt_values(settings, encryption.cipher.as_ref());
+    }
+
+    /// Reads the credentials from the credential store into the serialized `settings`. Returns
+    /// `None` if they could not be read, in which case the credentials in the settings file are
+    /// used, if any.
+    async fn read_credentials(
+        path: &Path,
+        storage: CredentialStorage,
+        settings: &mut serde_json::Value,
+    ) -> Option<Credentials> {
+        let settings_dir = path.parent().unwrap_or(path);
+        let mut stored_settings = settings.clone();
+        let result = Self::with_credential_store(settings_dir, storage, move |store| {
+            credential_store::read_credentials(store, &mut stored_settings)
+                .map(|credentials| (credentials, stored_settings))
+        })
+        .await;
+        match result {
+            Ok(Some((credentials, stored_settings))) => {
+                *settings = stored_settings;
+                Some(credentials)
+            }
+            Ok(None) => None,
+            Err(error) => {
+                error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to read the stored credentials")
+                );
+                None
+            }
+        }
+    }
+
+    /// Runs `f` with the credential store selected by `storage`. Returns `Ok(None)` if the
+    /// credentials are kept in the settings file. Stores may block, so this is done on a
+    /// separate thread.
+    async fn with_credential_store<T: Send + 'static>(
+        settings_dir: &Path,
+        storage: CredentialStorage,
+        f: impl FnOnce(&dyn CredentialStore) -> Result<T, credential_store::Error> + Send + 'static,
+    ) -> Result<Option<T>, credential_store::Error> {
+        let settings_dir = settings_dir.to_owned();
+        tokio::task::spawn_blocking(
+            move || match credential_store::open(storage, &settings_dir)? {
+                Some(store) => f(&*store).map(Some),
+                None => Ok(None),
+            },
+        )
+        .await
+        .expect("Credential store access panicked")
+    }
+
+    /// Moves the credentials out of the serialized `settings` and into the credential store,
+    /// unless they are kept in the settings file. Only credentials that have changed are written.
+    /// If the store cannot be written to, the credentials are not saved at all.
+    async fn save_credentials(&mut self, settings: &mut serde_json::Value) {
+        let storage = self.settings.credential_storage;
+        if storage == CredentialStorage::Settings {
+            return;
+        }
+        let mut credentials = credential_store::take_credentials(settings);
+        if self.settings.account_privacy_mode {
+            // The account number is only kept in memory
+            credentials.insert("account_token", serde_json::Value::Null);
+        }
+        credentials.retain(|setting, value| match &self.stored_credentials {
+            Some(stored) => stored.get(setting) != Some(value),
+            // Credentials that could not be read are never removed
+            None => !value.is_null(),
+        });
+        if credentials.is_empty() {
+            return;
+        }
+
+        let settings_dir = self.path.parent().unwrap_or(&self.path);
+        let written = credentials.clone();
+        let result = Self::with_credential_store(settings_dir, storage, move |store| {
+            credential_store::write_credentials(store, &written)
+        })
+        .await;
+        match result {
+            Ok(_) => {
+                if let Some(stored) = &mut self.stored_credentials {
+                    stored.extend(credentials);
+                }
+            }
+            Err(error) => error!(
+                "{}",
+                error.display_chain_with_msg("Failed to store the credentials. Not saving them")
+            ),
+        }
+    }
+
+    /// Reads the settings encryption key from the keystore, optionally creating it if it does
+    /// not exist. Keystores may block, so this is done on a separate thread.
+    async fn load_cipher(
+        settings_dir: &Path,
+        create: bool,
+    ) -> Result<Option<SettingsCipher>, settings_encryption::Error> {
+        let settings_dir = settings_dir.to_owned();
+        tokio::task::spawn_blocking(move || {
+            if create {
+                SettingsCipher::load_or_create(&settings_dir).map(Some)
+            } else {
+                SettingsCipher::load(&settings_dir)
+            }
+        })
+        .await
+        .expect("Keystore access panicked")
     }
 
     /// Serializes the settings and saves them to the file it was loaded from.
     async fn save(&mut self) -> Result<(), Error> {
+        self.check_writable()?;
         debug!("Writing settings to {}", self.path.display());
 
-        let buffer = serde_json::to_string_pretty(&self.settings).map_err(Error::SerializeError)?;
+        let mut settings = serde_json::to_value(&self.settings).map_err(Error::SerializeError)?;
+        self.save_credentials(&mut settings).await;
+        if self.settings.encrypt_sensitive_settings {
+            match &self.encryption.cipher {
+                Some(cipher) => settings_encryption::encrypt_values(&mut settings, cipher)
+                    .map_err(Error::EncryptionError)?,
+                None => {
+                    error!(
+                        "No settings encryption key is available. Not saving sensitive settings"
+                    );
+                    settings_encryption::remove_plaintext_values(&mut settings);
+                }
+            }
+        }
+        settings_encryption::restore_undecryptable_values(
+            &mut settings,
+            &self.encryption.undecryptable_values,
+        );
+        if self.settings.account_privacy_mode {
+            // The account number is only kept in memory
+            if let Some(settings) = settings.as_object_mut() {
+                settings.remove("account_token");
+            }
+        }
+
+        let buffer = serde_json::to_string_pretty(&settings).map_err(Error::SerializeError)?;
         let mut options = fs::OpenOptions::new();
         #[cfg(unix)]
         {
             options.mode(0o600);
         }
-        let mut file = options
+        let mut file = match options
             .create(true)
             .write(true)
             .truncate(true)
             .open(&self.path)
             .await
-            .map_err(|e| Error::WriteError(self.path.display().to_string(), e))?;
+        {
+            Ok(file) => file,
+            Err(error) if is_read_only_error(&error) => {
+                log::warn!("The settings directory has become read-only");
+                self.read_only = true;
+                self.settings = self.saved_settings.clone();
+                return Err(Error::ReadOnly);
+            }
+            Err(error) => return Err(Error::WriteError(self.path.display().to_string(), error)),
+        };
         file.write_all(&buffer.into_bytes())
             .await
             .map_err(|e| Error::WriteError(self.path.display().to_string(), e))?;
@@ -183,13 +489,41 @@ impl SettingsPersister {
             .await
             .map_err(|e| Error::WriteError(self.path.display().to_string(), e))?;
 
+        self.saved_settings = self.settings.clone();
+        Ok(())
+    }
+
+    /// Rejects changes while the settings directory is read-only, reverting any that were
+    /// already made in memory, so that the settings in use always match the ones on disk.
+    fn check_writable(&mut self) -> Result<(), Error> {
+        if self.read_only {
+            self.settings = self.saved_settings.clone();
+            return Err(Error::ReadOnly);
+        }
         Ok(())
     }
 
     /// Resets default settings
     #[cfg(not(target_os = "android"))]
     pub async fn reset(&mut self) -> Result<(), Error> {
+        self.check_writable()?;
+        let settings_dir = self.path.parent().unwrap_or(&self.path);
+        if let Err(error) = Self::with_credential_store(
+            settings_dir,
+            self.settings.credential_storage,
+            credential_store::remove_credentials,
+        )
+        .await
+        {
+            error!(
+                "{}",
+                error.display_chain_with_msg("Failed to remove the stored credentials")
+            );
+        }
+
         self.settings = Settings::default();
+        self.encryption.undecryptable_values.clear();
+        self.stored_credentials = None;
         let path = self.path.clone();
         self.save()
             .or_else(|e| async move {
@@ -209,6 +543,18 @@ impl SettingsPersister {
         self.settings.clone()
     }
 
+    /// Returns whether the settings directory is read-only, in which case the settings cannot be
+    /// changed.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Writes the settings in the format of the current version, even if they have not changed.
+    #[cfg(windows)]
+    pub async fn rewrite(&mut self) -> Result<(), Error> {
+        self.save().await
+    }
+
     /// Changes account number to the one given. Also saves the new settings to disk.
     /// The boolean in the Result indicates if the account token changed or not
     pub async fn set_account_token(
@@ -248,11 +594,51 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    pub async fn set_persistent_lockdown(
+        &mut self,
+        persistent_lockdown: bool,
+    ) -> Result<bool, Error> {
+        let should_save =
+            Self::update_field(&mut self.settings.persistent_lockdown, persistent_lockdown);
+        self.update(should_save).await
+    }
+
+    pub async fn set_connection_profiles(
+        &mut self,
+        connection_profiles: bool,
+    ) -> Result<bool, Error> {
+        let should_save =
+            Self::update_field(&mut self.settings.connection_profiles, connection_profiles);
+        self.update(should_save).await
+    }
+
     pub async fn set_auto_connect(&mut self, auto_connect: bool) -> Result<bool, Error> {
         let should_save = Self::update_field(&mut self.settings.auto_connect, auto_connect);
         self.update(should_save).await
     }
 
+    /// Adds a network rule, replacing any rule for the same networks.
+    pub async fn add_network_rule(&mut self, rule: NetworkRule) -> Result<bool, Error> {
+        let mut network_rules = self.settings.network_rules.clone();
+        network_rules.add(rule);
+        let should_save = Self::update_field(&mut self.settings.network_rules, network_rules);
+        self.update(should_save).await
+    }
+
+    pub async fn remove_network_rule(&mut self, network: &NetworkMatch) -> Result<bool, Error> {
+        let should_save = self.settings.network_rules.remove(network);
+        self.update(should_save).await
+    }
+
+    pub async fn set_default_network_action(
+        &mut self,
+        action: Option<NetworkAction>,
+    ) -> Result<bool, Error> {
+        let should_save =
+            Self::update_field(&mut self.settings.network_rules.default_action, action);
+        self.update(should_save).await
+    }
+
     pub async fn set_openvpn_mssfix(&mut self, openvpn_mssfix: Option<u16>) -> Result<bool, Error> {
         let should_save = Self::update_field(
             &mut self.settings.tunnel_options.openvpn.mssfix,
@@ -269,6 +655,29 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    pub async fn set_ipv6_failure_policy(
+        &mut self,
+        policy: Ipv6FailurePolicy,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.tunnel_options.generic.ipv6_failure_policy,
+            policy,
+        );
+        self.update(should_save).await
+    }
+
+    #[cfg(not(target_os = "android"))]
+    pub async fn set_tunnel_interface_name(
+        &mut self,
+        interface_name: Option<String>,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.tunnel_options.generic.interface_name,
+            interface_name,
+        );
+        self.update(should_save).await
+    }
+
     pub async fn set_dns_options(&mut self, options: DnsOptions) -> Result<bool, Error> {
         let should_save =
             Self::update_field(&mut self.settings.tunnel_options.dns_options, options);
@@ -281,6 +690,49 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    pub async fn set_wireguard_icmp_check_interval(
+        &mut self,
+        interval: Option<u16>,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self
+                .settings
+                .tunnel_options
+                .wireguard
+                .options
+                .icmp_check_interval,
+            interval,
+        );
+        self.update(should_save).await
+    }
+
+    pub async fn set_wireguard_packet_loss_threshold(
+        &mut self,
+        threshold: Option<u8>,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self
+                .settings
+                .tunnel_options
+                .wireguard
+                .options
+                .packet_loss_threshold,
+            threshold,
+        );
+        self.update(should_save).await
+    }
+
+    pub async fn set_quantum_resistant_tunnel(
+        &mut self,
+        state: QuantumResistantState,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.tunnel_options.wireguard.quantum_resistant,
+            state,
+        );
+        self.update(should_save).await
+    }
+
     pub async fn set_wireguard_rotation_interval(
         &mut self,
         interval: Option<RotationInterval>,
@@ -292,6 +744,266 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    pub async fn set_obfuscation_settings(
+        &mut self,
+        obfuscation_settings: ObfuscationSettings,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.obfuscation_settings,
+            obfuscation_settings,
+        );
+        self.update(should_save).await
+    }
+
+    /// Enables or disables encryption of sensitive settings. The encryption key is created
+    /// before enabling encryption, so that failing to access the keystore is reported.
+    pub async fn set_encrypt_sensitive_settings(&mut self, enabled: bool) -> Result<bool, Error> {
+        self.check_writable()?;
+        if enabled && self.encryption.cipher.is_none() {
+            let settings_dir = self.path.parent().unwrap_or(&self.path).to_owned();
+            self.encryption.cipher = Self::load_cipher(&settings_dir, true)
+                .await
+                .map_err(Error::EncryptionError)?;
+        }
+        let should_save =
+            Self::update_field(&mut self.settings.encrypt_sensitive_settings, enabled);
+        self.update(should_save).await
+    }
+
+    /// Moves the account number and the WireGuard key to another store. They are only removed
+    /// from the previous store once they have been written to the new one.
+    pub async fn set_credential_storage(
+        &mut self,
+        storage: CredentialStorage,
+    ) -> Result<bool, Error> {
+        let old_storage = self.settings.credential_storage;
+        if storage == old_storage {
+            return Ok(false);
+        }
+        self.check_writable()?;
+        let settings_dir = self.path.parent().unwrap_or(&self.path).to_owned();
+
+        let mut settings = serde_json::to_value(&self.settings).map_err(Error::SerializeError)?;
+        let mut credentials = credential_store::take_credentials(&mut settings);
+        if self.settings.account_privacy_mode {
+            credentials.insert("account_token", serde_json::Value::Null);
+        }
+        let written = credentials.clone();
+        let stored = Self::with_credential_store(&settings_dir, storage, move |store| {
+            credential_store::write_credentials(store, &written)
+        })
+        .await
+        .map_err(Error::CredentialStoreError)?;
+
+        self.settings.credential_storage = storage;
+        self.stored_credentials = stored.map(|()| credentials);
+        self.save().await?;
+
+        if let Err(error) = Self::with_credential_store(
+            &settings_dir,
+            old_storage,
+            credential_store::remove_credentials,
+        )
+        .await
+        {
+            error!(
+                "{}",
+                error.display_chain_with_msg(
+                    "Failed to remove the credentials from the previous store"
+                )
+            );
+        }
+        Ok(true)
+    }
+
+    pub async fn set_keep_account_history(
+        &mut self,
+        keep_account_history: bool,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.keep_account_history,
+            keep_account_history,
+        );
+        self.update(should_save).await
+    }
+
+    pub async fn set_connection_statistics(
+        &mut self,
+        connection_statistics: bool,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.connection_statistics,
+            connection_statistics,
+        );
+        self.update(should_save).await
+    }
+
+    pub async fn set_system_log(&mut self, system_log: bool) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.system_log, system_log);
+        self.update(should_save).await
+    }
+
+    pub async fn set_json_logs(&mut self, json_logs: bool) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.json_logs, json_logs);
+        self.update(should_save).await
+    }
+
+    /// Applies the server mode profile. Disabling server mode leaves the settings of the profile
+    /// as they are.
+    pub async fn set_server_mode(&mut self, server_mode: bool) -> Result<bool, Error> {
+        let old_settings = self.settings.clone();
+        if server_mode {
+            self.settings.apply_server_mode();
+        } else {
+            self.settings.server_mode = false;
+        }
+        let should_save = self.settings != old_settings;
+        self.update(should_save).await
+    }
+
+    pub async fn set_api_clock_check(&mut self, api_clock_check: bool) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.api_clock_check, api_clock_check);
+        self.update(should_save).await
+    }
+
+    pub async fn set_lan_proxy_settings(
+        &mut self,
+        lan_proxy: LanProxySettings,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.lan_proxy, lan_proxy);
+        self.update(should_save).await
+    }
+
+    pub async fn set_local_api_settings(
+        &mut self,
+        local_api: LocalApiSettings,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.local_api, local_api);
+        self.update(should_save).await
+    }
+
+    pub async fn set_hook_settings(&mut self, hooks: HookSettings) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.hooks, hooks);
+        self.update(should_save).await
+    }
+
+    pub async fn set_reconnect_backoff(
+        &mut self,
+        reconnect_backoff: ReconnectBackoffSettings,
+    ) -> Result<bool, Error> {
+        let should_save =
+            Self::update_field(&mut self.settings.reconnect_backoff, reconnect_backoff);
+        self.update(should_save).await
+    }
+
+    pub async fn set_exit_ip_check(
+        &mut self,
+        exit_ip_check: ExitIpCheckSettings,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.exit_ip_check, exit_ip_check);
+        self.update(should_save).await
+    }
+
+    pub async fn set_beta_feature(
+        &mut self,
+        feature: BetaFeature,
+        enabled: bool,
+    ) -> Result<bool, Error> {
+        let should_save = self.settings.beta_features.set_enabled(feature, enabled);
+        self.update(should_save).await
+    }
+
+    pub async fn set_mdns_reflector(&mut self, mdns_reflector: bool) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.mdns_reflector, mdns_reflector);
+        self.update(should_save).await
+    }
+
+    pub async fn set_allowed_system_services(
+        &mut self,
+        allowed_system_services: AllowedSystemServices,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.allowed_system_services,
+            allowed_system_services,
+        );
+        self.update(should_save).await
+    }
+
+    pub async fn set_tunnel_parameter_overrides(
+        &mut self,
+        tunnel_parameter_overrides: TunnelParameterOverrides,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.tunnel_parameter_overrides,
+            tunnel_parameter_overrides,
+        );
+        self.update(should_save).await
+    }
+
+    #[cfg(target_os = "linux")]
+    pub async fn set_dbus_service(&mut self, dbus_service: bool) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.dbus_service, dbus_service);
+        self.update(should_save).await
+    }
+
+    #[cfg(windows)]
+    pub async fn set_allow_virtual_networks(
+        &mut self,
+        allow_virtual_networks: bool,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.allow_virtual_networks,
+            allow_virtual_networks,
+        );
+        self.update(should_save).await
+    }
+
+    pub async fn set_identity_rotation_interval(
+        &mut self,
+        interval: Option<HumanDuration>,
+    ) -> Result<bool, Error> {
+        let should_save =
+            Self::update_field(&mut self.settings.identity_rotation_interval, interval);
+        self.update(should_save).await
+    }
+
+    pub async fn set_account_privacy_mode(
+        &mut self,
+        account_privacy_mode: bool,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.account_privacy_mode,
+            account_privacy_mode,
+        );
+        self.update(should_save).await
+    }
+
+    pub async fn set_port_forwarding(&mut self, port_forwarding: bool) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.port_forwarding, port_forwarding);
+        self.update(should_save).await
+    }
+
+    pub async fn set_log_rotation_settings(
+        &mut self,
+        log_rotation: LogRotationSettings,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.log_rotation, log_rotation);
+        self.update(should_save).await
+    }
+
+    pub async fn set_log_privacy(&mut self, log_privacy: LogPrivacyLevel) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.log_privacy, log_privacy);
+        self.update(should_save).await
+    }
+
+    pub async fn set_account_expiry_settings(
+        &mut self,
+        account_expiry: AccountExpirySettings,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.account_expiry, account_expiry);
+        self.update(should_save).await
+    }
+
     pub async fn set_show_beta_releases(
         &mut self,
         show_beta_releases: bool,
@@ -301,6 +1013,14 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    pub async fn set_api_resolution_strategy(
+        &mut self,
+        strategy: ApiResolutionStrategy,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.api_resolution_strategy, strategy);
+        self.update(should_save).await
+    }
+
     pub async fn set_bridge_settings(
         &mut self,
         bridge_settings: BridgeSettings,
@@ -314,7 +1034,7 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
-    #[cfg(windows)]
+    #[cfg(any(windows, target_os = "linux"))]
     pub async fn set_split_tunnel_apps(&mut self, paths: HashSet<PathBuf>) -> Result<bool, Error> {
         let should_save = paths != self.settings.split_tunnel.apps;
         if should_save {
@@ -323,7 +1043,7 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
-    #[cfg(windows)]
+    #[cfg(any(windows, target_os = "linux"))]
     pub async fn set_split_tunnel_state(&mut self, enabled: bool) -> Result<bool, Error> {
         let should_save =
             Self::update_field(&mut self.settings.split_tunnel.enable_exclusions, enabled);
@@ -344,6 +1064,129 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    #[cfg(target_os = "linux")]
+    pub async fn set_use_userspace_wireguard(&mut self, state: bool) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self
+                .settings
+                .tunnel_options
+                .wireguard
+                .options
+                .use_userspace_wireguard,
+            state,
+        );
+        self.update(should_save).await
+    }
+
+    #[cfg(windows)]
+    pub async fn set_use_ovpn_dco(&mut self, state: bool) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.tunnel_options.openvpn.use_ovpn_dco,
+            state,
+        );
+        self.update(should_save).await
+    }
+
+    #[cfg(windows)]
+    pub async fn set_wintun_adapter_policy(
+        &mut self,
+        policy: talpid_types::net::openvpn::WintunAdapterPolicy,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.tunnel_options.openvpn.wintun_adapter_policy,
+            policy,
+        );
+        self.update(should_save).await
+    }
+
+    /// Appends an API access method. The caller is responsible for making sure that the name
+    /// is unique.
+    pub async fn add_api_access_method(&mut self, method: ApiAccessMethod) -> Result<bool, Error> {
+        self.settings.api_access_methods.push(method);
+        self.update(true).await
+    }
+
+    pub async fn remove_api_access_method(&mut self, name: &str) -> Result<bool, Error> {
+        let methods = &mut self.settings.api_access_methods;
+        let num_methods = methods.len();
+        methods.retain(|method| method.name != name);
+        let mut should_save = methods.len() != num_methods;
+        let preferred = &mut self.settings.api_access.preferred;
+        if preferred.as_deref() == Some(name) {
+            *preferred = None;
+            should_save = true;
+        }
+        self.update(should_save).await
+    }
+
+    pub async fn set_api_access_method_enabled(
+        &mut self,
+        name: &str,
+        enabled: bool,
+    ) -> Result<bool, Error> {
+        let should_save = self.set_api_access_method_enabled_inner(name, enabled);
+        self.update(should_save).await
+    }
+
+    /// Enables the API access method named `name`, and makes it the one that is tried first.
+    pub async fn prefer_api_access_method(&mut self, name: &str) -> Result<bool, Error> {
+        let mut should_save = self.set_api_access_method_enabled_inner(name, true);
+        should_save |= Self::update_field(
+            &mut self.settings.api_access.preferred,
+            Some(name.to_owned()),
+        );
+        self.update(should_save).await
+    }
+
+    fn set_api_access_method_enabled_inner(&mut self, name: &str, enabled: bool) -> bool {
+        let api_access = &mut self.settings.api_access;
+        match name {
+            DIRECT_ACCESS_METHOD => Self::update_field(&mut api_access.direct_enabled, enabled),
+            BRIDGES_ACCESS_METHOD => Self::update_field(&mut api_access.bridges_enabled, enabled),
+            _ => match self
+                .settings
+                .api_access_methods
+                .iter_mut()
+                .find(|method| method.name == name)
+            {
+                Some(method) => Self::update_field(&mut method.enabled, enabled),
+                None => false,
+            },
+        }
+    }
+
+    /// Appends a firewall exception, unless an identical one already exists.
+    pub async fn add_firewall_exception(
+        &mut self,
+        exception: AllowedEndpoint,
+    ) -> Result<bool, Error> {
+        let exceptions = &mut self.settings.firewall_exceptions;
+        let should_save = !exceptions.contains(&exception);
+        if should_save {
+            exceptions.push(exception);
+        }
+        self.update(should_save).await
+    }
+
+    pub async fn remove_firewall_exception(
+        &mut self,
+        exception: AllowedEndpoint,
+    ) -> Result<bool, Error> {
+        let exceptions = &mut self.settings.firewall_exceptions;
+        let num_exceptions = exceptions.len();
+        exceptions.retain(|existing| *existing != exception);
+        let should_save = exceptions.len() != num_exceptions;
+        self.update(should_save).await
+    }
+
+    pub async fn set_firewall_exceptions(
+        &mut self,
+        exceptions: Vec<AllowedEndpoint>,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.firewall_exceptions, exceptions);
+        self.update(should_save).await
+    }
+
     fn update_field<T: Eq>(field: &mut T, new_value: T) -> bool {
         if *field != new_value {
             *field = new_value;
@@ -354,6 +1197,10 @@ impl SettingsPersister {
     }
 
     async fn update(&mut self, should_save: bool) -> Result<bool, Error> {
+        if self.settings.server_mode && !self.settings.has_server_mode_profile() {
+            info!("Leaving server mode since one of its settings was turned off");
+            self.settings.server_mode = false;
+        }
         if should_save {
             self.save().await.map(|_| true)
         } else {
@@ -362,6 +1209,27 @@ impl SettingsPersister {
     }
 }
 
+/// Checks whether files can be created in `settings_dir`, such as on immutable distributions or in
+/// containers where it is mounted read-only.
+async fn is_read_only(settings_dir: &Path) -> bool {
+    let probe_path = settings_dir.join(WRITE_PROBE_FILE);
+    match fs::File::create(&probe_path).await {
+        Ok(_) => {
+            let _ = fs::remove_file(&probe_path).await;
+            false
+        }
+        Err(error) => is_read_only_error(&error),
+    }
+}
+
+fn is_read_only_error(error: &io::Error) -> bool {
+    #[cfg(unix)]
+    if error.raw_os_error() == Some(libc::EROFS) {
+        return true;
+    }
+    error.kind() == io::ErrorKind::PermissionDenied
+}
+
 impl Deref for SettingsPersister {
     type Target = Settings;
 
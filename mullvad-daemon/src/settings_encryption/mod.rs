@@ -0,0 +1,340 @@
+//! Encryption of the sensitive parts of the settings file. The account number, the WireGuard
+//! key and the credentials of custom API proxies are encrypted with a data key that is stored in
+//! the keystore of the operating system, so that they cannot be read by simply opening the
+//! settings file.
+use crate::credential_store;
+use rand::{rngs::OsRng, RngCore};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use serde_json::Value;
+use std::{collections::HashMap, path::Path};
+
+/// Prefix of encrypted values in the settings file.
+const ENCRYPTED_PREFIX: &str = "encrypted:v1:";
+const KEY_LEN: usize = 32;
+/// Name of the data key in the keystore.
+const KEY_NAME: &str = "settings-key";
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to access the keystore")]
+    Keystore(#[error(source)] credential_store::Error),
+
+    #[error(display = "The key in the keystore is invalid")]
+    InvalidKey,
+
+    #[error(display = "Failed to encrypt a settings value")]
+    Encrypt,
+
+    #[error(display = "Failed to decrypt a settings value")]
+    Decrypt,
+}
+
+/// Encrypts and decrypts individual settings values.
+pub struct SettingsCipher {
+    key: LessSafeKey,
+}
+
+impl std::fmt::Debug for SettingsCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SettingsCipher").finish()
+    }
+}
+
+impl SettingsCipher {
+    /// Reads the data key from the keystore. Returns `Ok(None)` if no key has been created yet.
+    pub fn load(settings_dir: &Path) -> Result<Option<Self>, Error> {
+        credential_store::keystore(settings_dir)
+            .and_then(|keystore| keystore.read(KEY_NAME))
+            .map_err(Error::Keystore)?
+            .map(|key| Self::from_key(&key))
+            .transpose()
+    }
+
+    /// Reads the data key from the keystore, or generates and stores a new one.
+    pub fn load_or_create(settings_dir: &Path) -> Result<Self, Error> {
+        if let Some(cipher) = Self::load(settings_dir)? {
+            return Ok(cipher);
+        }
+        log::info!("Generating a new settings encryption key");
+        let mut key = [0u8; KEY_LEN];
+        OsRng.fill_bytes(&mut key);
+        credential_store::keystore(settings_dir)
+            .and_then(|keystore| keystore.write(KEY_NAME, &key))
+            .map_err(Error::Keystore)?;
+        Self::from_key(&key)
+    }
+
+    fn from_key(key: &[u8]) -> Result<Self, Error> {
+        if key.len() != KEY_LEN {
+            return Err(Error::InvalidKey);
+        }
+        let key = UnboundKey::new(&AES_256_GCM, key).map_err(|_| Error::InvalidKey)?;
+        Ok(SettingsCipher {
+            key: LessSafeKey::new(key),
+        })
+    }
+
+    fn encrypt(&self, plaintext: &[u8]) -> Result<String, Error> {
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+
+        let mut data = plaintext.to_vec();
+        self.key
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+            .map_err(|_| Error::Encrypt)?;
+
+        let mut output = nonce.to_vec();
+        output.extend_from_slice(&data);
+        Ok(format!("{}{}", ENCRYPTED_PREFIX, base64::encode(&output)))
+    }
+
+    fn decrypt(&self, value: &str) -> Result<Vec<u8>, Error> {
+        let encoded = value.strip_prefix(ENCRYPTED_PREFIX).ok_or(Error::Decrypt)?;
+        let mut data = base64::decode(encoded).map_err(|_| Error::Decrypt)?;
+        if data.len() < NONCE_LEN {
+            return Err(Error::Decrypt);
+        }
+        let mut ciphertext = data.split_off(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(&data).map_err(|_| Error::Decrypt)?;
+        let plaintext = self
+            .key
+            .open_in_place(nonce, Aad::empty(), &mut ciphertext)
+            .map_err(|_| Error::Decrypt)?;
+        Ok(plaintext.to_vec())
+    }
+}
+
+/// Encrypted values that could not be decrypted when the settings were loaded, keyed by their
+/// JSON pointer. They are written back as-is, so that a temporarily unavailable keystore does
+/// not cause them to be lost.
+pub type UndecryptableValues = HashMap<String, Value>;
+
+/// Returns whether a settings value has been encrypted.
+fn is_encrypted(value: &Value) -> bool {
+    matches!(value, Value::String(s) if s.starts_with(ENCRYPTED_PREFIX))
+}
+
+/// Returns JSON pointers to all sensitive values that are present in `settings`.
+fn sensitive_pointers(settings: &Value) -> Vec<String> {
+    let mut pointers = vec![
+        "/account_token".to_owned(),
+        "/wireguard".to_owned(),
+        // Certificates and keys of a server imported from an OpenVPN profile
+        "/relay_settings/custom_tunnel_endpoint/config/openvpn/profile".to_owned(),
+    ];
+
+    if let Some(methods) = settings.get("api_access_methods").and_then(Value::as_array) {
+        for (index, method) in methods.iter().enumerate() {
+            if let Some(proxy) = method.get("proxy").and_then(Value::as_object) {
+                for kind in proxy.keys() {
+                    pointers.push(format!("/api_access_methods/{}/proxy/{}/auth", index, kind));
+                }
+            }
+        }
+    }
+
+    pointers.retain(|pointer| match settings.pointer(pointer) {
+        Some(value) => !value.is_null(),
+        None => false,
+    });
+    pointers
+}
+
+/// Returns whether `settings` contains any encrypted values.
+pub fn contains_encrypted_values(settings: &Value) -> bool {
+    sensitive_pointers(settings)
+        .iter()
+        .any(|pointer| settings.pointer(pointer).map(is_encrypted).unwrap_or(false))
+}
+
+/// Returns whether `settings` contains any sensitive values in plain text.
+pub fn contains_plaintext_values(settings: &Value) -> bool {
+    sensitive_pointers(settings)
+        .iter()
+        .any(|pointer| !settings.pointer(pointer).map(is_encrypted).unwrap_or(true))
+}
+
+/// Encrypts all sensitive values in `settings` that are not already encrypted.
+pub fn encrypt_values(settings: &mut Value, cipher: &SettingsCipher) -> Result<(), Error> {
+    for pointer in sensitive_pointers(settings) {
+        if let Some(value) = settings.pointer_mut(&pointer) {
+            if !is_encrypted(value) {
+                *value = Value::String(cipher.encrypt(value.to_string().as_bytes())?);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Decrypts all encrypted values in `settings`. Values that cannot be decrypted, because there is
+/// no cipher or because decryption fails, are replaced with `null` and returned.
+pub fn decrypt_values(
+    settings: &mut Value,
+    cipher: Option<&SettingsCipher>,
+) -> UndecryptableValues {
+    let mut undecryptable = UndecryptableValues::new();
+
+    for pointer in sensitive_pointers(settings) {
+        let value = match settings.pointer_mut(&pointer) {
+            Some(value) if is_encrypted(value) => value,
+            _ => continue,
+        };
+        let decrypted = cipher.ok_or(Error::InvalidKey).and_then(|cipher| {
+            let plaintext = cipher.decrypt(value.as_str().unwrap_or_default())?;
+            serde_json::from_slice(&plaintext).map_err(|_| Error::Decrypt)
+        });
+        match decrypted {
+            Ok(decrypted) => *value = decrypted,
+            Err(_) => {
+                log::error!("Failed to decrypt the setting at {}", pointer);
+                undecryptable.insert(pointer, value.take());
+            }
+        }
+    }
+
+    undecryptable
+}
+
+/// Puts values that could not be decrypted back into `settings`, unless they have since been
+/// replaced.
+pub fn restore_undecryptable_values(settings: &mut Value, undecryptable: &UndecryptableValues) {
+    for (pointer, encrypted) in undecryptable {
+        if let Some(value) = settings.pointer_mut(pointer) {
+            if value.is_null() {
+                *value = encrypted.clone();
+            }
+        }
+    }
+}
+
+/// Removes all sensitive values that are not encrypted from `settings`.
+pub fn remove_plaintext_values(settings: &mut Value) {
+    for pointer in sensitive_pointers(settings) {
+        if let Some(value) = settings.pointer_mut(&pointer) {
+            if !is_encrypted(value) {
+                *value = Value::Null;
+            }
+        }
+    }
+}
+
+/// Replaces all sensitive values in `settings`, so that they can be shown without revealing them.
+pub fn redact_values(settings: &mut Value) {
+    for pointer in sensitive_pointers(settings) {
+        if let Some(value) = settings.pointer_mut(&pointer) {
+            *value = Value::String("[REDACTED]".to_owned());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn settings() -> Value {
+        json!({
+            "account_token": "1234567890123456",
+            "wireguard": null,
+            "api_access_methods": [
+                {
+                    "name": "proxy",
+                    "proxy": {
+                        "socks5": {
+                            "address": "192.0.2.1:1080",
+                            "auth": { "username": "user", "password": "pass" }
+                        }
+                    }
+                }
+            ],
+            "allow_lan": false
+        })
+    }
+
+    #[test]
+    fn test_encryption_round_trip() {
+        let cipher = SettingsCipher::from_key(&[1u8; KEY_LEN]).unwrap();
+        let original = settings();
+
+        let mut encrypted = original.clone();
+        encrypt_values(&mut encrypted, &cipher).unwrap();
+        assert!(is_encrypted(&encrypted["account_token"]));
+        assert!(is_encrypted(
+            &encrypted["api_access_methods"][0]["proxy"]["socks5"]["auth"]
+        ));
+        assert!(encrypted["wireguard"].is_null());
+        assert_eq!(encrypted["allow_lan"], original["allow_lan"]);
+        assert!(contains_encrypted_values(&encrypted));
+        assert!(!contains_plaintext_values(&encrypted));
+
+        let mut decrypted = encrypted.clone();
+        assert!(decrypt_values(&mut decrypted, Some(&cipher)).is_empty());
+        assert_eq!(decrypted, original);
+    }
+
+    #[test]
+    fn test_undecryptable_values_are_preserved() {
+        let cipher = SettingsCipher::from_key(&[1u8; KEY_LEN]).unwrap();
+        let other_cipher = SettingsCipher::from_key(&[2u8; KEY_LEN]).unwrap();
+        let mut encrypted = settings();
+        encrypt_values(&mut encrypted, &cipher).unwrap();
+
+        let mut loaded = encrypted.clone();
+        let undecryptable = decrypt_values(&mut loaded, Some(&other_cipher));
+        assert_eq!(undecryptable.len(), 2);
+        assert!(loaded["account_token"].is_null());
+
+        restore_undecryptable_values(&mut loaded, &undecryptable);
+        assert_eq!(loaded, encrypted);
+    }
+
+    #[test]
+    fn test_encrypt_openvpn_profile() {
+        let cipher = SettingsCipher::from_key(&[1u8; KEY_LEN]).unwrap();
+        let original = json!({
+            "relay_settings": {
+                "custom_tunnel_endpoint": {
+                    "host": "vpn.example.com",
+                    "config": {
+                        "openvpn": {
+                            "endpoint": { "address": "0.0.0.0:1194", "protocol": "udp" },
+                            "username": "",
+                            "password": "",
+                            "profile": { "ca": "-----BEGIN CERTIFICATE-----" }
+                        }
+                    }
+                }
+            }
+        });
+
+        let mut encrypted = original.clone();
+        encrypt_values(&mut encrypted, &cipher).unwrap();
+        let config = &encrypted["relay_settings"]["custom_tunnel_endpoint"]["config"]["openvpn"];
+        assert!(is_encrypted(&config["profile"]));
+        assert_eq!(
+            config["endpoint"],
+            original["relay_settings"]["custom_tunnel_endpoint"]["config"]["openvpn"]["endpoint"]
+        );
+
+        let mut decrypted = encrypted.clone();
+        assert!(decrypt_values(&mut decrypted, Some(&cipher)).is_empty());
+        assert_eq!(decrypted, original);
+    }
+
+    #[test]
+    fn test_redact_values() {
+        let mut redacted = settings();
+        redact_values(&mut redacted);
+        assert_eq!(redacted["account_token"], "[REDACTED]");
+        assert_eq!(
+            redacted["api_access_methods"][0]["proxy"]["socks5"]["auth"],
+            "[REDACTED]"
+        );
+        assert_eq!(
+            redacted["api_access_methods"][0]["proxy"]["socks5"]["address"],
+            "192.0.2.1:1080"
+        );
+        assert!(redacted["wireguard"].is_null());
+    }
+}
@@ -0,0 +1,367 @@
+//! Profiles of the settings that decide how a device connects, for replicating a configuration
+//! on other devices. A profile holds the relay and bridge constraints, obfuscation, DNS and
+//! firewall settings, but nothing that is specific to the device or the account, such as the
+//! account number, the WireGuard key or custom tunnel endpoints.
+//!
+//! The settings are accompanied by a SHA-256 checksum of their JSON representation, so that a
+//! profile that was truncated or edited by hand is rejected as a whole rather than partially
+//! applied. The checksum does not authenticate the author of the profile.
+use mullvad_types::{
+    obfuscation::ObfuscationSettings,
+    relay_constraints::{
+        BridgeSettings, BridgeState, RelayConstraintsUpdate, RelaySettings, RelaySettingsUpdate,
+    },
+    settings::{AllowedSystemServices, DnsOptions, Settings},
+    units::FieldError,
+};
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use talpid_types::net::AllowedEndpoint;
+
+/// The version of the profile format. Profiles of other versions are rejected.
+const FORMAT_VERSION: u32 = 1;
+
+const CHECKSUM_PREFIX: &str = "sha256:";
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Malformed settings profile")]
+    Parse(#[error(source)] serde_json::Error),
+
+    #[error(display = "Failed to serialize the settings profile")]
+    Serialize(#[error(source)] serde_json::Error),
+
+    #[error(
+        display = "Settings profiles of format version {} are not supported",
+        _0
+    )]
+    UnsupportedVersion(u32),
+
+    #[error(display = "The checksum of the settings profile does not match its settings")]
+    ChecksumMismatch,
+
+    #[error(display = "The settings profile contains an invalid setting")]
+    InvalidSetting(#[error(source)] FieldError),
+}
+
+/// What to do with settings that have been changed on this device to something other than what
+/// the profile has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictResolution {
+    /// Do not apply anything if there are conflicts.
+    Abort,
+    /// Replace the local settings with those of the profile.
+    Overwrite,
+    /// Keep the local settings, and apply the rest of the profile.
+    KeepLocal,
+}
+
+/// The outcome of importing a profile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportReport {
+    /// Whether the profile was applied. It is not applied if there are conflicts and the
+    /// resolution is [`ConflictResolution::Abort`].
+    pub applied: bool,
+    /// The names of the settings that were changed, as returned by [`Settings::changed_keys`].
+    pub changed_settings: Vec<String>,
+    /// The names of the settings in the profile that conflicted with local settings.
+    pub conflicts: Vec<String>,
+}
+
+/// The settings held by a profile. Settings that are missing from a profile are left as they are
+/// when it is imported.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProfileSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relay_settings: Option<RelaySettings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bridge_settings: Option<BridgeSettings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bridge_state: Option<BridgeState>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub obfuscation_settings: Option<ObfuscationSettings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns_options: Option<DnsOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_lan: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_when_disconnected: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub persistent_lockdown: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_system_services: Option<AllowedSystemServices>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub firewall_exceptions: Option<Vec<AllowedEndpoint>>,
+}
+
+impl ProfileSettings {
+    /// Returns the profile settings of `settings`. Custom tunnel endpoints are left out, since
+    /// they hold the private key of the device.
+    pub fn from_settings(settings: &Settings) -> Self {
+        let relay_settings = match settings.get_relay_settings() {
+            RelaySettings::CustomTunnelEndpoint(_) => None,
+            relay_settings => Some(relay_settings),
+        };
+        ProfileSettings {
+            relay_settings,
+            bridge_settings: Some(settings.bridge_settings.clone()),
+            bridge_state: Some(settings.get_bridge_state()),
+            obfuscation_settings: Some(settings.obfuscation_settings.clone()),
+            dns_options: Some(settings.tunnel_options.dns_options.clone()),
+            allow_lan: Some(settings.allow_lan),
+            block_when_disconnected: Some(settings.block_when_disconnected),
+            persistent_lockdown: Some(settings.persistent_lockdown),
+            allowed_system_services: Some(settings.allowed_system_services),
+            firewall_exceptions: Some(settings.firewall_exceptions.clone()),
+        }
+    }
+
+    /// Removes the settings that have been changed from their defaults in `current`, to
+    /// something other than what the profile has, and returns their names.
+    pub fn remove_conflicts(&mut self, current: &Settings) -> Vec<&'static str> {
+        let current = Self::from_settings(current);
+        let default = Self::from_settings(&Settings::default());
+        let mut conflicts = vec![];
+        remove_conflict(
+            "relay_settings",
+            &mut self.relay_settings,
+            &current.relay_settings,
+            &default.relay_settings,
+            &mut conflicts,
+        );
+        remove_conflict(
+            "bridge_settings",
+            &mut self.bridge_settings,
+            &current.bridge_settings,
+            &default.bridge_settings,
+            &mut conflicts,
+        );
+        remove_conflict(
+            "bridge_state",
+            &mut self.bridge_state,
+            &current.bridge_state,
+            &default.bridge_state,
+            &mut conflicts,
+        );
+        remove_conflict(
+            "obfuscation_settings",
+            &mut self.obfuscation_settings,
+            &current.obfuscation_settings,
+            &default.obfuscation_settings,
+            &mut conflicts,
+        );
+        remove_conflict(
+            "dns_options",
+            &mut self.dns_options,
+            &current.dns_options,
+            &default.dns_options,
+            &mut conflicts,
+        );
+        remove_conflict(
+            "allow_lan",
+            &mut self.allow_lan,
+            &current.allow_lan,
+            &default.allow_lan,
+            &mut conflicts,
+        );
+        remove_conflict(
+            "block_when_disconnected",
+            &mut self.block_when_disconnected,
+            &current.block_when_disconnected,
+            &default.block_when_disconnected,
+            &mut conflicts,
+        );
+        remove_conflict(
+            "persistent_lockdown",
+            &mut self.persistent_lockdown,
+            &current.persistent_lockdown,
+            &default.persistent_lockdown,
+            &mut conflicts,
+        );
+        remove_conflict(
+            "allowed_system_services",
+            &mut self.allowed_system_services,
+            &current.allowed_system_services,
+            &default.allowed_system_services,
+            &mut conflicts,
+        );
+        remove_conflict(
+            "firewall_exceptions",
+            &mut self.firewall_exceptions,
+            &current.firewall_exceptions,
+            &default.firewall_exceptions,
+            &mut conflicts,
+        );
+        conflicts
+    }
+
+    fn validate(&self) -> Result<(), FieldError> {
+        if let Some(obfuscation_settings) = &self.obfuscation_settings {
+            obfuscation_settings.validate()?;
+        }
+        Ok(())
+    }
+}
+
+fn remove_conflict<T: PartialEq>(
+    name: &'static str,
+    profile: &mut Option<T>,
+    current: &Option<T>,
+    default: &Option<T>,
+    conflicts: &mut Vec<&'static str>,
+) {
+    if profile.is_some() && profile != current && current != default {
+        *profile = None;
+        conflicts.push(name);
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Profile {
+    format_version: u32,
+    /// The version of the app that exported the profile, for troubleshooting.
+    #[serde(default)]
+    exported_by: String,
+    settings: serde_json::Value,
+    checksum: String,
+}
+
+/// Serializes the profile settings of `settings`.
+pub fn export(settings: &Settings) -> Result<String, Error> {
+    let settings =
+        serde_json::to_value(ProfileSettings::from_settings(settings)).map_err(Error::Serialize)?;
+    let profile = Profile {
+        format_version: FORMAT_VERSION,
+        exported_by: crate::version::PRODUCT_VERSION.to_owned(),
+        checksum: checksum(&settings)?,
+        settings,
+    };
+    serde_json::to_string_pretty(&profile).map_err(Error::Serialize)
+}
+
+/// Parses and validates a profile that was created by [`export`].
+pub fn parse(profile: &str) -> Result<ProfileSettings, Error> {
+    let profile: Profile = serde_json::from_str(profile).map_err(Error::Parse)?;
+    if profile.format_version != FORMAT_VERSION {
+        return Err(Error::UnsupportedVersion(profile.format_version));
+    }
+    if checksum(&profile.settings)? != profile.checksum {
+        return Err(Error::ChecksumMismatch);
+    }
+    let settings: ProfileSettings =
+        serde_json::from_value(profile.settings).map_err(Error::Parse)?;
+    settings.validate().map_err(Error::InvalidSetting)?;
+    Ok(settings)
+}
+
+/// Decides which settings of `profile` to apply. Returns the settings to apply, if any, and the
+/// names of the conflicting settings.
+pub fn resolve_conflicts(
+    profile: ProfileSettings,
+    current: &Settings,
+    resolution: ConflictResolution,
+) -> (Option<ProfileSettings>, Vec<String>) {
+    let mut without_conflicts = profile.clone();
+    let conflicts: Vec<String> = without_conflicts
+        .remove_conflicts(current)
+        .into_iter()
+        .map(String::from)
+        .collect();
+    let profile = match resolution {
+        ConflictResolution::Abort if !conflicts.is_empty() => None,
+        ConflictResolution::Overwrite => Some(profile),
+        _ => Some(without_conflicts),
+    };
+    (profile, conflicts)
+}
+
+/// Returns an update that replaces the relay settings with `relay_settings`.
+pub fn relay_settings_update(relay_settings: RelaySettings) -> RelaySettingsUpdate {
+    match relay_settings {
+        RelaySettings::CustomTunnelEndpoint(endpoint) => {
+            RelaySettingsUpdate::CustomTunnelEndpoint(endpoint)
+        }
+        RelaySettings::Normal(constraints) => RelaySettingsUpdate::Normal(RelayConstraintsUpdate {
+            location: Some(constraints.location),
+            providers: Some(constraints.providers),
+            tunnel_protocol: Some(constraints.tunnel_protocol),
+            wireguard_constraints: Some(constraints.wireguard_constraints),
+            openvpn_constraints: Some(constraints.openvpn_constraints),
+            filter: Some(constraints.filter),
+        }),
+    }
+}
+
+fn checksum(settings: &serde_json::Value) -> Result<String, Error> {
+    let bytes = serde_json::to_vec(settings).map_err(Error::Serialize)?;
+    let hash = digest(&SHA256, &bytes);
+    let hex: String = hash
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    Ok(format!("{}{}", CHECKSUM_PREFIX, hex))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_export_and_parse() {
+        let mut settings = Settings::default();
+        settings.allow_lan = true;
+        let profile = export(&settings).unwrap();
+        assert_eq!(
+            parse(&profile).unwrap(),
+            ProfileSettings::from_settings(&settings)
+        );
+    }
+
+    #[test]
+    fn test_parse_modified_profile() {
+        let profile = export(&Settings::default()).unwrap();
+        let mut profile: serde_json::Value = serde_json::from_str(&profile).unwrap();
+        profile["settings"]["allow_lan"] = serde_json::json!(true);
+        assert!(matches!(
+            parse(&profile.to_string()),
+            Err(Error::ChecksumMismatch)
+        ));
+
+        profile["format_version"] = serde_json::json!(FORMAT_VERSION + 1);
+        assert!(matches!(
+            parse(&profile.to_string()),
+            Err(Error::UnsupportedVersion(_))
+        ));
+    }
+
+    #[test]
+    fn test_resolve_conflicts() {
+        let mut source = Settings::default();
+        source.allow_lan = true;
+        source.block_when_disconnected = true;
+        let profile = ProfileSettings::from_settings(&source);
+
+        // Only settings that differ from both the default and the profile are conflicts
+        let mut current = Settings::default();
+        current.allow_lan = true;
+        current.persistent_lockdown = true;
+
+        let (resolved, conflicts) =
+            resolve_conflicts(profile.clone(), &current, ConflictResolution::Abort);
+        assert_eq!(resolved, None);
+        assert_eq!(conflicts, vec!["persistent_lockdown".to_owned()]);
+
+        let (resolved, _) =
+            resolve_conflicts(profile.clone(), &current, ConflictResolution::KeepLocal);
+        let resolved = resolved.unwrap();
+        assert_eq!(resolved.persistent_lockdown, None);
+        assert_eq!(resolved.block_when_disconnected, Some(true));
+
+        let (resolved, _) =
+            resolve_conflicts(profile.clone(), &current, ConflictResolution::Overwrite);
+        assert_eq!(resolved, Some(profile));
+    }
+}
@@ -0,0 +1,391 @@
+//! Sends important daemon events to the log of the operating system, in addition to the daemon
+//! log file, so that they are picked up by the monitoring that administrators already have in
+//! place. Events are written to journald on Linux, or to syslog on systems without journald, to
+//! the Windows Event Log, and to the unified logging system on macOS.
+use std::fmt;
+
+/// Name that the events are logged under.
+#[cfg(target_os = "linux")]
+const IDENTIFIER: &str = "mullvad-daemon";
+#[cfg(windows)]
+const IDENTIFIER: &str = "Mullvad VPN";
+
+/// An event that is worth the attention of an administrator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SystemLogEvent {
+    /// The tunnel failed and all network traffic is blocked.
+    Blocking { cause: String },
+    /// The firewall policy could not be applied, so traffic may leak.
+    FirewallFailure { cause: String },
+    /// This device was revoked from the account and can no longer connect.
+    DeviceRevoked,
+}
+
+impl SystemLogEvent {
+    /// Identifier of the kind of event, which does not change between versions.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SystemLogEvent::Blocking { .. } => "BLOCKING",
+            SystemLogEvent::FirewallFailure { .. } => "FIREWALL_FAILURE",
+            SystemLogEvent::DeviceRevoked => "DEVICE_REVOKED",
+        }
+    }
+
+    /// Numeric identifier of the kind of event, used as the event ID in the Windows Event Log.
+    #[cfg(windows)]
+    fn id(&self) -> u32 {
+        match self {
+            SystemLogEvent::Blocking { .. } => 1,
+            SystemLogEvent::FirewallFailure { .. } => 2,
+            SystemLogEvent::DeviceRevoked => 3,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, SystemLogEvent::FirewallFailure { .. })
+    }
+
+    #[cfg(target_os = "linux")]
+    fn cause(&self) -> Option<&str> {
+        match self {
+            SystemLogEvent::Blocking { cause } | SystemLogEvent::FirewallFailure { cause } => {
+                Some(cause)
+            }
+            SystemLogEvent::DeviceRevoked => None,
+        }
+    }
+}
+
+impl fmt::Display for SystemLogEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SystemLogEvent::Blocking { cause } => {
+                write!(f, "Blocking all network connections, reason: {}", cause)
+            }
+            SystemLogEvent::FirewallFailure { cause } => write!(
+                f,
+                "Failed to apply the firewall policy, network traffic may leak: {}",
+                cause
+            ),
+            SystemLogEvent::DeviceRevoked => {
+                write!(f, "This device has been revoked from the account")
+            }
+        }
+    }
+}
+
+/// Writes events to the system log while enabled.
+pub struct SystemLog {
+    sink: Option<imp::Sink>,
+}
+
+impl SystemLog {
+    pub fn new(enabled: bool) -> Self {
+        let mut system_log = SystemLog { sink: None };
+        system_log.set_enabled(enabled);
+        system_log
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.sink = None;
+        } else if self.sink.is_none() {
+            match imp::Sink::open() {
+                Ok(sink) => self.sink = Some(sink),
+                Err(error) => log::error!("Failed to open the system log: {}", error),
+            }
+        }
+    }
+
+    pub fn log(&mut self, event: SystemLogEvent) {
+        if let Some(sink) = self.sink.as_mut() {
+            if let Err(error) = sink.write(&event) {
+                log::debug!(
+                    "Failed to write {} event to the system log: {}",
+                    event.name(),
+                    error
+                );
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::{SystemLogEvent, IDENTIFIER};
+    use std::{io, os::unix::net::UnixDatagram};
+
+    const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+    const SYSLOG_SOCKET: &str = "/dev/log";
+
+    /// Priorities as defined by syslog(3).
+    const PRIORITY_ERROR: u8 = 3;
+    const PRIORITY_WARNING: u8 = 4;
+    /// The `LOG_DAEMON` facility, shifted into place as defined by syslog(3).
+    const FACILITY_DAEMON: u8 = 3 << 3;
+
+    /// Writes entries using the native journald protocol, which supports structured fields. Falls
+    /// back on the syslog socket on systems that don't run journald.
+    pub struct Sink {
+        socket: UnixDatagram,
+        format: Format,
+    }
+
+    enum Format {
+        Journald,
+        Syslog,
+    }
+
+    impl Sink {
+        pub fn open() -> io::Result<Self> {
+            let socket = UnixDatagram::unbound()?;
+            match socket.connect(JOURNALD_SOCKET) {
+                Ok(()) => Ok(Sink {
+                    socket,
+                    format: Format::Journald,
+                }),
+                Err(journald_error) => {
+                    log::debug!("Failed to connect to journald: {}", journald_error);
+                    socket.connect(SYSLOG_SOCKET)?;
+                    Ok(Sink {
+                        socket,
+                        format: Format::Syslog,
+                    })
+                }
+            }
+        }
+
+        pub fn write(&mut self, event: &SystemLogEvent) -> io::Result<()> {
+            let entry = match self.format {
+                Format::Journald => journal_entry(event),
+                Format::Syslog => syslog_entry(event),
+            };
+            self.socket.send(&entry).map(|_| ())
+        }
+    }
+
+    fn priority(event: &SystemLogEvent) -> u8 {
+        if event.is_error() {
+            PRIORITY_ERROR
+        } else {
+            PRIORITY_WARNING
+        }
+    }
+
+    /// Formats the event as an RFC 3164 message without a timestamp, which syslog daemons fill in.
+    pub(super) fn syslog_entry(event: &SystemLogEvent) -> Vec<u8> {
+        let message = format!("[{}] {}", event.name(), event).replace('\n', " ");
+        format!(
+            "<{}>{}[{}]: {}",
+            FACILITY_DAEMON | priority(event),
+            IDENTIFIER,
+            std::process::id(),
+            message
+        )
+        .into_bytes()
+    }
+
+    pub(super) fn journal_entry(event: &SystemLogEvent) -> Vec<u8> {
+        let mut entry = Vec::new();
+        add_field(&mut entry, "MESSAGE", &event.to_string());
+        add_field(&mut entry, "PRIORITY", &priority(event).to_string());
+        add_field(&mut entry, "SYSLOG_IDENTIFIER", IDENTIFIER);
+        add_field(&mut entry, "MULLVAD_EVENT", event.name());
+        if let Some(cause) = event.cause() {
+            add_field(&mut entry, "MULLVAD_CAUSE", cause);
+        }
+        entry
+    }
+
+    /// Values that contain newlines must be prefixed by their length rather than terminated by a
+    /// newline.
+    fn add_field(entry: &mut Vec<u8>, name: &str, value: &str) {
+        entry.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            entry.push(b'\n');
+            entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            entry.push(b'=');
+        }
+        entry.extend_from_slice(value.as_bytes());
+        entry.push(b'\n');
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod imp {
+    use super::SystemLogEvent;
+    use std::{ffi::CString, io};
+
+    /// Passed to openlog(3), which keeps a pointer to it.
+    const IDENTIFIER: &[u8] = b"mullvad-daemon\0";
+    const FORMAT: &[u8] = b"%s\0";
+
+    /// Writes entries using syslog(3), which forwards them to the unified logging system.
+    pub struct Sink(());
+
+    impl Sink {
+        pub fn open() -> io::Result<Self> {
+            unsafe {
+                libc::openlog(
+                    IDENTIFIER.as_ptr() as *const libc::c_char,
+                    libc::LOG_PID,
+                    libc::LOG_DAEMON,
+                )
+            };
+            Ok(Sink(()))
+        }
+
+        pub fn write(&mut self, event: &SystemLogEvent) -> io::Result<()> {
+            let priority = if event.is_error() {
+                libc::LOG_ERR
+            } else {
+                libc::LOG_WARNING
+            };
+            let message = format!("[{}] {}", event.name(), event).replace('\0', "");
+            let message = CString::new(message)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+            unsafe {
+                libc::syslog(
+                    priority,
+                    FORMAT.as_ptr() as *const libc::c_char,
+                    message.as_ptr(),
+                )
+            };
+            Ok(())
+        }
+    }
+
+    impl Drop for Sink {
+        fn drop(&mut self) {
+            unsafe { libc::closelog() };
+        }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use super::{SystemLogEvent, IDENTIFIER};
+    use std::{ffi::OsStr, io, os::windows::ffi::OsStrExt, ptr};
+    use winapi::um::{
+        winbase::{DeregisterEventSource, RegisterEventSourceW, ReportEventW},
+        winnt::{EVENTLOG_ERROR_TYPE, EVENTLOG_WARNING_TYPE, HANDLE},
+    };
+
+    /// Writes entries to the Application log of the Windows Event Log. The source is not
+    /// registered with a message file, so the Event Viewer shows the message as an insertion
+    /// string.
+    pub struct Sink {
+        handle: HANDLE,
+    }
+
+    // The event log handle may be used from any thread.
+    unsafe impl Send for Sink {}
+
+    impl Sink {
+        pub fn open() -> io::Result<Self> {
+            let source = to_wide(IDENTIFIER);
+            let handle = unsafe { RegisterEventSourceW(ptr::null(), source.as_ptr()) };
+            if handle.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Sink { handle })
+        }
+
+        pub fn write(&mut self, event: &SystemLogEvent) -> io::Result<()> {
+            let event_type = if event.is_error() {
+                EVENTLOG_ERROR_TYPE
+            } else {
+                EVENTLOG_WARNING_TYPE
+            };
+            let message = to_wide(&format!("[{}] {}", event.name(), event));
+            let mut strings = [message.as_ptr()];
+            let succeeded = unsafe {
+                ReportEventW(
+                    self.handle,
+                    event_type,
+                    0,
+                    event.id(),
+                    ptr::null_mut(),
+                    strings.len() as u16,
+                    0,
+                    strings.as_mut_ptr(),
+                    ptr::null_mut(),
+                )
+            };
+            if succeeded == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+
+    impl Drop for Sink {
+        fn drop(&mut self) {
+            unsafe { DeregisterEventSource(self.handle) };
+        }
+    }
+
+    fn to_wide(value: &str) -> Vec<u16> {
+        OsStr::new(value)
+            .encode_wide()
+            .chain(std::iter::once(0u16))
+            .collect()
+    }
+}
+
+#[cfg(target_os = "android")]
+mod imp {
+    use super::SystemLogEvent;
+    use std::io;
+
+    /// Everything the daemon logs already ends up in logcat.
+    pub struct Sink(());
+
+    impl Sink {
+        pub fn open() -> io::Result<Self> {
+            Ok(Sink(()))
+        }
+
+        pub fn write(&mut self, _event: &SystemLogEvent) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn journal_entry_has_structured_fields() {
+        let entry = imp::journal_entry(&SystemLogEvent::Blocking {
+            cause: "No relays".to_owned(),
+        });
+        let entry = String::from_utf8(entry).unwrap();
+        assert!(entry.contains("PRIORITY=4\n"));
+        assert!(entry.contains("MULLVAD_EVENT=BLOCKING\n"));
+        assert!(entry.contains("MULLVAD_CAUSE=No relays\n"));
+    }
+
+    #[test]
+    fn journal_entry_encodes_multiline_values() {
+        let entry = imp::journal_entry(&SystemLogEvent::FirewallFailure {
+            cause: "a\nb".to_owned(),
+        });
+        let mut expected = b"MULLVAD_CAUSE\n".to_vec();
+        expected.extend_from_slice(&3u64.to_le_bytes());
+        expected.extend_from_slice(b"a\nb\n");
+        assert!(entry.ends_with(&expected));
+    }
+
+    #[test]
+    fn syslog_entry_uses_daemon_facility() {
+        let entry = imp::syslog_entry(&SystemLogEvent::FirewallFailure {
+            cause: "a\nb".to_owned(),
+        });
+        let entry = String::from_utf8(entry).unwrap();
+        assert!(entry.starts_with("<27>mullvad-daemon["));
+        assert!(entry.ends_with("]: [FIREWALL_FAILURE] Failed to apply the firewall policy, network traffic may leak: a b"));
+    }
+}
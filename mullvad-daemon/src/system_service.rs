@@ -1,5 +1,7 @@
 use crate::cli;
-use mullvad_daemon::{runtime::new_runtime_builder, DaemonShutdownHandle};
+use mullvad_daemon::{
+    runtime::new_runtime_builder, DaemonShutdownHandle, DaemonSystemEventHandle, SystemEvent,
+};
 use std::{
     env,
     ffi::{OsStr, OsString},
@@ -119,11 +121,13 @@ pub fn handle_service_main(_arguments: Vec<OsString>) {
     let result = runtime.block_on(crate::create_daemon(log_dir));
     let result = if let Ok(daemon) = result {
         let shutdown_handle = daemon.shutdown_handle();
+        let system_event_handle = daemon.system_event_handle();
 
         // Register monitor that translates `ServiceControl` to Daemon events
         start_event_monitor(
             persistent_service_status.clone(),
             shutdown_handle,
+            system_event_handle,
             event_rx,
             clean_shutdown.clone(),
         );
@@ -162,6 +166,7 @@ pub fn handle_service_main(_arguments: Vec<OsString>) {
 fn start_event_monitor(
     mut persistent_service_status: PersistentServiceStatus,
     shutdown_handle: DaemonShutdownHandle,
+    system_event_handle: DaemonSystemEventHandle,
     event_rx: mpsc::Receiver<ServiceControl>,
     clean_shutdown: Arc<AtomicBool>,
 ) -> thread::JoinHandle<()> {
@@ -180,15 +185,26 @@ fn start_event_monitor(
                 ServiceControl::PowerEvent(details) => match details {
                     PowerEventParam::Suspend => {
                         hibernation_detector.register_suspend();
+                        system_event_handle.send(SystemEvent::Suspend);
+                    }
+                    // Both are sent when the user resumes the machine, but only the former when
+                    // it is woken up without user interaction.
+                    PowerEventParam::ResumeAutomatic => {
+                        hibernation_detector.register_resume();
+                        system_event_handle.send(SystemEvent::Resume);
                     }
-                    PowerEventParam::ResumeAutomatic | PowerEventParam::ResumeSuspend => {
+                    PowerEventParam::ResumeSuspend => {
                         hibernation_detector.register_resume();
                     }
                     _ => (),
                 },
                 ServiceControl::SessionChange(details) => {
+                    let session_id = details.notification.session_id;
                     if details.reason == SessionChangeReason::SessionLogoff {
-                        hibernation_detector.register_logoff(details.notification.session_id);
+                        hibernation_detector.register_logoff(session_id);
+                    }
+                    if let Some(event) = session_event(details.reason, session_id) {
+                        system_event_handle.send(event);
                     }
                 }
                 _ => (),
@@ -197,6 +213,18 @@ fn start_event_monitor(
     })
 }
 
+/// Translates session changes into the events that the daemon handles.
+fn session_event(reason: SessionChangeReason, session_id: u32) -> Option<SystemEvent> {
+    match reason {
+        SessionChangeReason::SessionLogon => Some(SystemEvent::SessionLogon(session_id)),
+        SessionChangeReason::SessionLogoff => Some(SystemEvent::SessionLogoff(session_id)),
+        SessionChangeReason::SessionLock => Some(SystemEvent::SessionLock(session_id)),
+        SessionChangeReason::SessionUnlock => Some(SystemEvent::SessionUnlock(session_id)),
+        SessionChangeReason::RemoteConnect => Some(SystemEvent::RemoteConnect(session_id)),
+        SessionChangeReason::RemoteDisconnect => Some(SystemEvent::RemoteDisconnect(session_id)),
+        _ => None,
+    }
+}
 
 /// Service status helper with persistent checkpoint counter.
 #[derive(Debug, Clone)]
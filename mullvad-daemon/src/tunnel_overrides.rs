@@ -0,0 +1,153 @@
+//! Applies the tunnel parameter overrides in the settings to the parameters generated for a
+//! connection attempt.
+use mullvad_types::settings::TunnelParameterOverrides;
+use talpid_types::net::{TransportProtocol, TunnelParameters};
+
+/// Applies `overrides` to `parameters`, and returns a description of each override that changed
+/// them. Overrides that cannot be applied to these parameters are skipped.
+pub fn apply(
+    overrides: &TunnelParameterOverrides,
+    parameters: &mut TunnelParameters,
+) -> Vec<String> {
+    let connection = match parameters {
+        TunnelParameters::Wireguard(parameters) => &mut parameters.connection,
+        TunnelParameters::OpenVpn(_) => {
+            if !overrides.is_empty() {
+                log::debug!("Tunnel parameter overrides only apply to WireGuard tunnels");
+            }
+            return vec![];
+        }
+    };
+    let mut applied = vec![];
+
+    if let Some(port) = overrides.wireguard_port {
+        if connection.obfuscation.is_some() || connection.peer.protocol != TransportProtocol::Udp {
+            log::warn!("Ignoring the WireGuard port override since the traffic is obfuscated");
+        } else if connection.peer.endpoint.port() != port {
+            connection.peer.endpoint.set_port(port);
+            applied.push(format!("port {}", port));
+        }
+    }
+
+    let exit_peer = connection
+        .exit_peer
+        .as_mut()
+        .unwrap_or(&mut connection.peer);
+    for network in &overrides.wireguard_extra_allowed_ips {
+        if !exit_peer.allowed_ips.contains(network) {
+            exit_peer.allowed_ips.push(*network);
+            applied.push(format!("allowed IP {}", network));
+        }
+    }
+
+    applied
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use talpid_types::net::{
+        wireguard::{
+            ConnectionConfig, ObfuscatorConfig, PeerConfig, PrivateKey, TunnelConfig, TunnelOptions,
+        },
+        GenericTunnelOptions, Ipv6FailurePolicy,
+    };
+
+    fn peer(endpoint: &str) -> PeerConfig {
+        PeerConfig {
+            public_key: PrivateKey::new_from_random().public_key(),
+            allowed_ips: talpid_types::net::all_of_the_internet(),
+            endpoint: endpoint.parse().unwrap(),
+            protocol: TransportProtocol::Udp,
+            psk: None,
+        }
+    }
+
+    fn parameters(exit_peer: Option<PeerConfig>) -> TunnelParameters {
+        talpid_types::net::wireguard::TunnelParameters {
+            connection: ConnectionConfig {
+                tunnel: TunnelConfig {
+                    private_key: PrivateKey::new_from_random(),
+                    addresses: vec![],
+                },
+                peer: peer("1.2.3.4:51820"),
+                exit_peer,
+                ipv4_gateway: "10.64.0.1".parse().unwrap(),
+                ipv6_gateway: None,
+                obfuscation: None,
+                handshake_timeout: None,
+                quantum_resistant: false,
+            },
+            options: TunnelOptions::default(),
+            generic_options: GenericTunnelOptions {
+                enable_ipv6: false,
+                interface_name: None,
+                ipv6_failure_policy: Ipv6FailurePolicy::default(),
+            },
+        }
+        .into()
+    }
+
+    fn connection(parameters: &TunnelParameters) -> &ConnectionConfig {
+        match parameters {
+            TunnelParameters::Wireguard(parameters) => &parameters.connection,
+            TunnelParameters::OpenVpn(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_no_overrides() {
+        let mut params = parameters(None);
+        let original = params.clone();
+        assert!(apply(&TunnelParameterOverrides::default(), &mut params).is_empty());
+        assert_eq!(params, original);
+    }
+
+    #[test]
+    fn test_port_override() {
+        let overrides = TunnelParameterOverrides {
+            wireguard_port: Some(53),
+            ..TunnelParameterOverrides::default()
+        };
+        let mut params = parameters(None);
+        assert_eq!(apply(&overrides, &mut params), vec!["port 53"]);
+        assert_eq!(connection(&params).peer.endpoint.port(), 53);
+
+        let mut params = parameters(None);
+        if let TunnelParameters::Wireguard(ref mut params) = params {
+            params.connection.obfuscation = Some(ObfuscatorConfig::Quic {
+                endpoint: "1.2.3.4:443".parse().unwrap(),
+                server_name: "example.com".to_owned(),
+            });
+        }
+        assert!(apply(&overrides, &mut params).is_empty());
+        assert_eq!(connection(&params).peer.endpoint.port(), 51820);
+    }
+
+    #[test]
+    fn test_allowed_ips_go_to_exit_peer() {
+        let network = "10.0.0.0/8".parse().unwrap();
+        let overrides = TunnelParameterOverrides {
+            wireguard_extra_allowed_ips: vec![network],
+            ..TunnelParameterOverrides::default()
+        };
+
+        let mut params = parameters(None);
+        assert_eq!(
+            apply(&overrides, &mut params),
+            vec!["allowed IP 10.0.0.0/8"]
+        );
+        assert!(connection(&params).peer.allowed_ips.contains(&network));
+
+        let mut params = parameters(Some(peer("5.6.7.8:51820")));
+        apply(&overrides, &mut params);
+        let connection = connection(&params);
+        assert!(!connection.peer.allowed_ips.contains(&network));
+        assert!(connection
+            .exit_peer
+            .as_ref()
+            .unwrap()
+            .allowed_ips
+            .contains(&network));
+    }
+}
@@ -14,5 +14,11 @@ pub fn log_version() {
         env!("CARGO_PKG_NAME"),
         PRODUCT_VERSION,
         COMMIT_DATE,
-    )
+    );
+    #[cfg(windows)]
+    log::info!(
+        "Built for {}, running on {}",
+        talpid_core::windows::process_architecture(),
+        talpid_core::windows::native_architecture()
+    );
 }
@@ -4,17 +4,22 @@ use crate::{
 };
 use futures::{
     channel::{mpsc, oneshot},
+    future::FusedFuture,
     stream::FusedStream,
     FutureExt, SinkExt, StreamExt, TryFutureExt,
 };
-use mullvad_rpc::{availability::ApiAvailabilityHandle, rest::MullvadRestHandle, AppVersionProxy};
+use mullvad_rpc::{
+    availability::ApiAvailabilityHandle,
+    rest::{MullvadRestHandle, RequestPriority},
+    AppVersionProxy,
+};
 use mullvad_types::version::{AppVersionInfo, ParsedAppVersion};
 use serde::{Deserialize, Serialize};
 use std::{
     future::Future,
     io,
     path::{Path, PathBuf},
-    time::{Duration, Instant},
+    time::Duration,
 };
 use talpid_core::mpsc::Sender;
 use talpid_types::ErrorExt;
@@ -28,13 +33,10 @@ lazy_static::lazy_static! {
 }
 
 const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(15);
-/// How often the updater should wake up to check the in-memory cache.
-/// This exist to prevent problems around sleeping. If you set it to sleep
-/// for `UPDATE_INTERVAL` directly and the computer is suspended, that clock
-/// won't tick, and the next update will be after 24 hours of the computer being *on*.
-const UPDATE_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 5);
-/// Wait this long until next check after a successful check
-const UPDATE_INTERVAL: Duration = Duration::from_secs(60 * 60 * 24);
+/// How long to wait after startup before the first version check
+pub(crate) const UPDATE_INITIAL_DELAY: Duration = Duration::from_secs(60 * 5);
+/// How often the version check is run
+pub(crate) const UPDATE_INTERVAL: Duration = Duration::from_secs(60 * 60 * 24);
 /// Wait this long until next try if an update failed
 const UPDATE_INTERVAL_ERROR: Duration = Duration::from_secs(60 * 60 * 6);
 /// Retry interval for `RunVersionCheck`.
@@ -105,7 +107,6 @@ pub(crate) struct VersionUpdater {
     update_sender: DaemonEventSender<AppVersionInfo>,
     last_app_version_info: Option<AppVersionInfo>,
     platform_version: String,
-    next_update_time: Instant,
     show_beta_releases: bool,
     rx: Option<mpsc::Receiver<VersionUpdaterCommand>>,
     availability_handle: ApiAvailabilityHandle,
@@ -120,6 +121,7 @@ pub(crate) struct VersionUpdaterHandle {
 enum VersionUpdaterCommand {
     SetShowBetaReleases(bool),
     RunVersionCheck(oneshot::Sender<AppVersionInfo>),
+    RunBackgroundVersionCheck,
 }
 
 impl VersionUpdaterHandle {
@@ -136,6 +138,18 @@ impl VersionUpdaterHandle {
         }
     }
 
+    /// Starts a version check in the background, unless one is already in progress.
+    pub async fn run_background_version_check(&mut self) {
+        if self
+            .tx
+            .send(VersionUpdaterCommand::RunBackgroundVersionCheck)
+            .await
+            .is_err()
+        {
+            log::error!("Version updater already down, can't run version check");
+        }
+    }
+
     pub async fn run_version_check(&mut self) -> Result<AppVersionInfo, Error> {
         let (done_tx, done_rx) = oneshot::channel();
         if self
@@ -173,7 +187,6 @@ impl VersionUpdater {
                 update_sender,
                 last_app_version_info,
                 platform_version,
-                next_update_time: Instant::now(),
                 show_beta_releases,
                 rx: Some(rx),
                 availability_handle,
@@ -200,6 +213,7 @@ impl VersionUpdater {
                     PRODUCT_VERSION.to_owned(),
                     PLATFORM,
                     platform_version.clone(),
+                    RequestPriority::Interactive,
                 )
                 .map_err(Error::Download)
         };
@@ -238,6 +252,7 @@ impl VersionUpdater {
                 PRODUCT_VERSION.to_owned(),
                 PLATFORM,
                 platform_version.clone(),
+                RequestPriority::Background,
             );
             async move {
                 when_available.await.map_err(Error::ApiCheck)?;
@@ -343,8 +358,6 @@ impl VersionUpdater {
 
     pub async fn run(mut self) {
         let mut rx = self.rx.take().unwrap().fuse();
-        let next_delay = || Box::pin(tokio::time::sleep(UPDATE_CHECK_INTERVAL)).fuse();
-        let mut check_delay = next_delay();
         let mut version_check = futures::future::Fuse::terminated();
 
         // If this is a dev build ,there's no need to pester the API for version checks.
@@ -387,6 +400,14 @@ impl VersionUpdater {
                             let download_future = self.create_update_future(done_tx).fuse();
                             version_check = download_future;
                         }
+                        Some(VersionUpdaterCommand::RunBackgroundVersionCheck) => {
+                            if self.update_sender.is_closed() {
+                                return;
+                            }
+                            if version_check.is_terminated() {
+                                version_check = self.create_update_background_future().fuse();
+                            }
+                        }
                         // time to shut down
                         None => {
                             return;
@@ -394,29 +415,10 @@ impl VersionUpdater {
                     }
                 },
 
-                _sleep = check_delay => {
-                    if rx.is_terminated() || self.update_sender.is_closed() {
-                        return;
-                    }
-                    if self.internal_done_tx.is_some() {
-                        // Sync check in progress
-                        continue;
-                    }
-
-                    if Instant::now() > self.next_update_time {
-                        let download_future = self.create_update_background_future().fuse();
-                        version_check = download_future;
-                    } else {
-                        check_delay = next_delay();
-                    }
-
-                },
-
                 response = version_check => {
                     if rx.is_terminated() || self.update_sender.is_closed() {
                         return;
                     }
-                    self.next_update_time = Instant::now() + UPDATE_INTERVAL;
 
                     match response {
                         Ok(version_info_response) => {
@@ -429,8 +431,6 @@ impl VersionUpdater {
                             self.internal_done_tx = None;
                         },
                     }
-
-                    check_delay = next_delay();
                 },
             }
         }
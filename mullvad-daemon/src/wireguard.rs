@@ -1,14 +1,18 @@
-use crate::{DaemonEventSender, InternalDaemonEvent};
+use crate::{scheduler::SchedulerHandle, DaemonEventSender, InternalDaemonEvent};
 use chrono::offset::Utc;
 use mullvad_rpc::{
     availability::ApiAvailabilityHandle,
-    rest::{Error as RestError, MullvadRestHandle},
+    rest::{ApiErrorCode, Error as RestError, IdempotencyToken, MullvadRestHandle},
 };
 use mullvad_types::account::AccountToken;
 pub use mullvad_types::wireguard::*;
 use std::{future::Future, pin::Pin, time::Duration};
 
-use futures::future::{abortable, AbortHandle};
+use futures::{
+    channel::mpsc,
+    future::{abortable, AbortHandle},
+    StreamExt,
+};
 #[cfg(not(target_os = "android"))]
 use talpid_core::future_retry::constant_interval;
 use talpid_core::{
@@ -17,17 +21,13 @@ use talpid_core::{
 };
 
 pub use talpid_types::net::wireguard::{
-    ConnectionConfig, PrivateKey, TunnelConfig, TunnelParameters,
+    ConnectionConfig, ObfuscatorConfig, PeerConfig, PrivateKey, TunnelConfig, TunnelParameters,
 };
 use talpid_types::ErrorExt;
 
 /// How long to wait before starting key rotation
 const ROTATION_START_DELAY: Duration = Duration::from_secs(60 * 3);
 
-/// How often to check whether the key has expired.
-/// A short interval is used in case the computer is ever suspended.
-const KEY_CHECK_INTERVAL: Duration = Duration::from_secs(60);
-
 const RETRY_INTERVAL_INITIAL: Duration = Duration::from_secs(4);
 const RETRY_INTERVAL_FACTOR: u32 = 5;
 const RETRY_INTERVAL_MAX: Duration = Duration::from_secs(24 * 60 * 60);
@@ -49,10 +49,20 @@ pub enum Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// The tokens of the requests that add the new key during a rotation, or that replace the old key
+/// with it if the account has no room for another key. Every attempt at the same rotation reuses
+/// them.
+#[derive(Clone)]
+struct RotationTokens {
+    add: IdempotencyToken,
+    replace: IdempotencyToken,
+}
+
 pub struct KeyManager {
     daemon_tx: DaemonEventSender,
     availability_handle: ApiAvailabilityHandle,
     http_handle: MullvadRestHandle,
+    scheduler: SchedulerHandle,
     current_job: Option<AbortHandle>,
 
     abort_scheduler_tx: Option<AbortHandle>,
@@ -64,11 +74,13 @@ impl KeyManager {
         daemon_tx: DaemonEventSender,
         availability_handle: ApiAvailabilityHandle,
         http_handle: MullvadRestHandle,
+        scheduler: SchedulerHandle,
     ) -> Self {
         Self {
             daemon_tx,
             availability_handle,
             http_handle,
+            scheduler,
             current_job: None,
             abort_scheduler_tx: None,
             auto_rotation_interval: RotationInterval::default(),
@@ -121,7 +133,30 @@ impl KeyManager {
         self.reset();
 
         let new_key = PrivateKey::new_from_random();
-        Self::replace_key_rpc(self.http_handle.clone(), account, old_key, new_key).await
+        Self::replace_key_rpc(
+            self.http_handle.clone(),
+            account,
+            old_key,
+            new_key,
+            IdempotencyToken::new(),
+        )
+        .await
+    }
+
+    /// Replace a key for an account without cancelling the key generation that is in progress.
+    /// The returned future does not borrow the key manager.
+    pub fn replace_key_in_background(
+        &self,
+        account: AccountToken,
+        old_key: PublicKey,
+    ) -> impl Future<Output = Result<WireguardData>> {
+        Self::replace_key_rpc(
+            self.http_handle.clone(),
+            account,
+            old_key,
+            PrivateKey::new_from_random(),
+            IdempotencyToken::new(),
+        )
     }
 
     /// Verifies whether a key is valid or not.
@@ -134,8 +169,9 @@ impl KeyManager {
         async move {
             match rpc.get_wireguard_key(account, &key).await {
                 Ok(_) => Ok(true),
-                Err(mullvad_rpc::rest::Error::ApiError(status, _code))
-                    if status == mullvad_rpc::StatusCode::NOT_FOUND =>
+                Err(mullvad_rpc::rest::Error::ApiError(status, code))
+                    if status == mullvad_rpc::StatusCode::NOT_FOUND
+                        || code == ApiErrorCode::DeviceNotFound =>
                 {
                     Ok(false)
                 }
@@ -204,7 +240,7 @@ impl KeyManager {
 
     fn should_retry(error: &RestError) -> bool {
         if let RestError::ApiError(_status, code) = &error {
-            code != mullvad_rpc::INVALID_ACCOUNT && code != mullvad_rpc::KEY_LIMIT_REACHED
+            *code != ApiErrorCode::InvalidAccount && *code != ApiErrorCode::KeyLimitReached
         } else {
             true
         }
@@ -307,12 +343,15 @@ impl KeyManager {
     > {
         let mut rpc = mullvad_rpc::WireguardKeyProxy::new(self.http_handle.clone());
         let public_key = private_key.public_key();
+        // Every attempt uses the same token, so that the key is only registered once even if
+        // the response to an earlier attempt was lost.
+        let idempotency_token = IdempotencyToken::new();
 
         let push_future =
             move || -> std::pin::Pin<Box<dyn Future<Output = std::result::Result<WireguardData,  RestError>> + Send >> {
                 let key = private_key.clone();
                 let address_future = rpc
-                    .push_wg_key(account.clone(), public_key.clone(), timeout);
+                    .push_wg_key(account.clone(), public_key.clone(), idempotency_token.clone(), timeout);
                 Box::pin(async move {
                     let addresses = address_future.await?;
                     Ok(WireguardData {
@@ -330,11 +369,12 @@ impl KeyManager {
         account: AccountToken,
         old_key: PublicKey,
         new_key: PrivateKey,
+        idempotency_token: IdempotencyToken,
     ) -> Result<WireguardData> {
         let mut rpc = mullvad_rpc::WireguardKeyProxy::new(http_handle);
         let new_public_key = new_key.public_key();
         let addresses = rpc
-            .replace_wg_key(account, old_key.key, new_public_key)
+            .replace_wg_key(account, old_key.key, new_public_key, idempotency_token)
             .await
             .map_err(Self::map_rpc_error)?;
         Ok(WireguardData {
@@ -347,9 +387,8 @@ impl KeyManager {
     fn map_rpc_error(err: mullvad_rpc::rest::Error) -> Error {
         match &err {
             // TODO: Consider handling the invalid account case too.
-            mullvad_rpc::rest::Error::ApiError(status, message)
-                if *status == mullvad_rpc::StatusCode::BAD_REQUEST
-                    && message == mullvad_rpc::KEY_LIMIT_REACHED =>
+            mullvad_rpc::rest::Error::ApiError(status, ApiErrorCode::KeyLimitReached)
+                if *status == mullvad_rpc::StatusCode::BAD_REQUEST =>
             {
                 Error::TooManyKeys
             }
@@ -357,19 +396,31 @@ impl KeyManager {
         }
     }
 
-    async fn wait_for_key_expiry(key: &PublicKey, rotation_interval_secs: u64) {
-        let mut interval = tokio::time::interval(KEY_CHECK_INTERVAL);
+    /// Waits until the key is older than the rotation interval and `not_before` has passed.
+    /// The wall clock is checked on every scheduler tick, since a sleeping timer does not advance
+    /// while the computer is suspended. Returns `false` if the scheduler has stopped.
+    async fn wait_for_key_expiry(
+        ticks: &mut mpsc::UnboundedReceiver<()>,
+        key: &PublicKey,
+        rotation_interval_secs: u64,
+        not_before: chrono::DateTime<Utc>,
+    ) -> bool {
         loop {
-            interval.tick().await;
-            if (Utc::now().signed_duration_since(key.created)).num_seconds() as u64
-                >= rotation_interval_secs
+            if ticks.next().await.is_none() {
+                return false;
+            }
+            let now = Utc::now();
+            if now >= not_before
+                && (now.signed_duration_since(key.created)).num_seconds() as u64
+                    >= rotation_interval_secs
             {
-                return;
+                return true;
             }
         }
     }
 
     async fn create_automatic_rotation(
+        mut ticks: mpsc::UnboundedReceiver<()>,
         daemon_tx: DaemonEventSender,
         availability_handle: ApiAvailabilityHandle,
         http_handle: MullvadRestHandle,
@@ -377,16 +428,22 @@ impl KeyManager {
         rotation_interval_secs: u64,
         account_token: AccountToken,
     ) {
-        tokio::time::sleep(ROTATION_START_DELAY).await;
+        let not_before = Utc::now()
+            + chrono::Duration::from_std(ROTATION_START_DELAY).expect("Start delay is too large");
 
         let rotate_key_for_account =
-            move |old_key: &PublicKey| -> Pin<Box<dyn Future<Output = Result<PublicKey>> + Send>> {
+            move |old_key: &PublicKey,
+                  new_key: &PrivateKey,
+                  tokens: &RotationTokens|
+                  -> Pin<Box<dyn Future<Output = Result<PublicKey>> + Send>> {
                 let wait_available = availability_handle.wait_available();
                 let rotate = Self::rotate_key(
                     daemon_tx.clone(),
                     http_handle.clone(),
                     account_token.clone(),
                     old_key.clone(),
+                    new_key.clone(),
+                    tokens.clone(),
                 );
                 Box::pin(async move {
                     wait_available.await?;
@@ -395,7 +452,16 @@ impl KeyManager {
             };
 
         loop {
-            Self::wait_for_key_expiry(&public_key, rotation_interval_secs).await;
+            if !Self::wait_for_key_expiry(
+                &mut ticks,
+                &public_key,
+                rotation_interval_secs,
+                not_before,
+            )
+            .await
+            {
+                return;
+            }
 
             let rotate_key_for_account_copy = rotate_key_for_account.clone();
             match Self::rotate_key_with_retries(public_key.clone(), rotate_key_for_account_copy)
@@ -415,19 +481,66 @@ impl KeyManager {
         }
     }
 
+    /// Adds the new key to the account without removing the old one, so that the tunnel keeps
+    /// working until the daemon has reconnected with the new key. The daemon removes the old key
+    /// once that has happened. If the account already has the maximum number of keys, the old key
+    /// is replaced right away instead.
     fn rotate_key(
         daemon_tx: DaemonEventSender,
         http_handle: MullvadRestHandle,
         account_token: AccountToken,
         old_key: PublicKey,
+        new_key: PrivateKey,
+        tokens: RotationTokens,
     ) -> impl Future<Output = Result<PublicKey>> {
-        let new_key = PrivateKey::new_from_random();
-        let rpc_result =
-            Self::replace_key_rpc(http_handle, account_token.clone(), old_key, new_key);
+        let mut rpc = mullvad_rpc::WireguardKeyProxy::new(http_handle.clone());
+        let add_result = rpc.push_wg_key(
+            account_token.clone(),
+            new_key.public_key(),
+            tokens.add,
+            None,
+        );
 
         async move {
-            match rpc_result.await {
-                Ok(data) => {
+            let add_result = add_result.await.map_err(Self::map_rpc_error);
+            let (rpc_result, retired_key) = match add_result {
+                Ok(addresses) => (
+                    Ok(WireguardData {
+                        private_key: new_key,
+                        addresses,
+                        created: Utc::now(),
+                    }),
+                    Some(old_key.key.clone()),
+                ),
+                Err(Error::TooManyKeys) => {
+                    log::info!(
+                        "The account has the maximum number of keys. Replacing the old key \
+                         instead of adding a new one"
+                    );
+                    let result = Self::replace_key_rpc(
+                        http_handle,
+                        account_token.clone(),
+                        old_key,
+                        new_key,
+                        tokens.replace,
+                    )
+                    .await;
+                    (result, None)
+                }
+                Err(error) => (Err(error), None),
+            };
+
+            match (rpc_result, retired_key) {
+                (Ok(data), Some(retired_key)) => {
+                    let public_key = data.get_public_key();
+                    let _ = daemon_tx.send(InternalDaemonEvent::WgKeyRotated(
+                        account_token,
+                        data,
+                        retired_key,
+                    ));
+                    Ok(public_key)
+                }
+                (Ok(data), None) => {
                     // Update account data
                     let _ = daemon_tx.send(InternalDaemonEvent::WgKeyEvent((
                         account_token,
@@ -435,24 +548,35 @@ impl KeyManager {
                     )));
                     Ok(data.get_public_key())
                 }
-                Err(Error::TooManyKeys) => {
+                (Err(Error::TooManyKeys), _) => {
                     let _ = daemon_tx.send(InternalDaemonEvent::WgKeyEvent((
                         account_token,
                         Err(Error::TooManyKeys),
                     )));
                     Err(Error::TooManyKeys)
                 }
-                Err(unknown) => Err(unknown),
+                (Err(unknown), _) => Err(unknown),
             }
         }
     }
 
     async fn rotate_key_with_retries<F>(old_key: PublicKey, rotate_key: F) -> Result<PublicKey>
     where
-        F: FnMut(&PublicKey) -> std::pin::Pin<Box<dyn Future<Output = Result<PublicKey>> + Send>>
+        F: FnMut(
+                &PublicKey,
+                &PrivateKey,
+                &RotationTokens,
+            ) -> std::pin::Pin<Box<dyn Future<Output = Result<PublicKey>> + Send>>
             + Clone
             + 'static,
     {
+        // The same key and tokens are used for every attempt, so that a rotation that went
+        // through despite the request failing is not repeated with yet another key.
+        let new_key = PrivateKey::new_from_random();
+        let tokens = RotationTokens {
+            add: IdempotencyToken::new(),
+            replace: IdempotencyToken::new(),
+        };
         let retry_strategy = Jittered::jitter(
             ExponentialBackoff::new(RETRY_INTERVAL_INITIAL, RETRY_INTERVAL_FACTOR)
                 .max_delay(RETRY_INTERVAL_MAX),
@@ -468,7 +592,7 @@ impl KeyManager {
         };
 
         retry_future(
-            move || rotate_key.clone()(&old_key),
+            move || rotate_key.clone()(&old_key, &new_key, &tokens),
             should_retry,
             retry_strategy,
         )
@@ -481,6 +605,7 @@ impl KeyManager {
         log::debug!("Starting automatic key rotation job");
         // Schedule cancellable series of repeating rotation tasks
         let fut = Self::create_automatic_rotation(
+            self.scheduler.ticks(),
             self.daemon_tx.clone(),
             self.availability_handle.clone(),
             self.http_handle.clone(),
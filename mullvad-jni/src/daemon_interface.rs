@@ -104,7 +104,9 @@ impl DaemonInterface {
 
         self.send_command(DaemonCommand::GetAccountHistory(tx))?;
 
-        block_on(rx).map_err(|_| Error::NoResponse)
+        block_on(rx)
+            .map(|history| history.into_iter().next())
+            .map_err(|_| Error::NoResponse)
     }
 
     pub fn get_www_auth_token(&self) -> Result<String> {
@@ -7,11 +7,19 @@ use jnix::{
 };
 use mullvad_daemon::EventListener;
 use mullvad_types::{
-    relay_list::RelayList, settings::Settings, states::TunnelState, version::AppVersionInfo,
+    account::AccountExpiryEvent,
+    api_access::ActiveApiAccessMethod,
+    device::{DeviceIdentityChange, DeviceState},
+    location::ExitIpMismatch,
+    relay_list::RelayList,
+    settings::Settings,
+    states::TunnelState,
+    version::AppVersionInfo,
     wireguard::KeygenEvent,
 };
 use std::{sync::mpsc, thread};
-use talpid_types::ErrorExt;
+use talpid_core::conflicting_software::ConflictingSoftware;
+use talpid_types::{tunnel::TransitionReason, ErrorExt};
 
 #[derive(Debug, err_derive::Error)]
 #[error(no_from)]
@@ -48,7 +56,7 @@ impl EventListener for JniEventListener {
         let _ = self.0.send(Event::KeygenEvent(key_event));
     }
 
-    fn notify_new_state(&self, state: TunnelState) {
+    fn notify_new_state(&self, state: TunnelState, _reason: Option<TransitionReason>) {
         let _ = self.0.send(Event::Tunnel(state));
     }
 
@@ -63,6 +71,36 @@ impl EventListener for JniEventListener {
     fn notify_app_version(&self, app_version_info: AppVersionInfo) {
         let _ = self.0.send(Event::AppVersionInfo(app_version_info));
     }
+
+    fn notify_device_state(&self, device_state: DeviceState) {
+        // The Android app does not handle revoked devices yet
+        log::debug!("Device state changed: {:?}", device_state);
+    }
+
+    fn notify_device_identity(&self, change: DeviceIdentityChange) {
+        // Identity rotation is not offered on Android
+        log::debug!("Device identity changed: {:?}", change);
+    }
+
+    fn notify_account_expiry(&self, event: AccountExpiryEvent) {
+        // The Android app tracks the account expiry itself
+        log::debug!("Account expiry event: {:?}", event);
+    }
+
+    fn notify_conflicting_software(&self, conflicts: Vec<ConflictingSoftware>) {
+        // Other VPN apps cannot be active at the same time on Android
+        log::debug!("Conflicting software: {:?}", conflicts);
+    }
+
+    fn notify_exit_ip_mismatch(&self, mismatch: ExitIpMismatch) {
+        // The Android app does not show exit IP mismatches yet
+        log::debug!("Exit IP mismatch: {:?}", mismatch);
+    }
+
+    fn notify_api_access_method(&self, method: ActiveApiAccessMethod) {
+        // The Android app does not show how the API is reached yet
+        log::debug!("API access method: {:?}", method);
+    }
 }
 
 struct JniEventHandler<'env> {
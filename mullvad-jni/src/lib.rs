@@ -21,7 +21,10 @@ use jnix::{
 use mullvad_daemon::{
     exception_logging, logging, runtime::new_runtime_builder, version, Daemon, DaemonCommandChannel,
 };
-use mullvad_rpc::{rest::Error as RestError, StatusCode};
+use mullvad_rpc::{
+    rest::{ApiErrorCode, Error as RestError},
+    StatusCode,
+};
 use mullvad_types::{
     account::{AccountData, VoucherSubmission},
     settings::DnsOptions,
@@ -118,13 +121,11 @@ impl From<Result<VoucherSubmission, daemon_interface::Error>> for VoucherSubmiss
 impl From<daemon_interface::Error> for VoucherSubmissionError {
     fn from(error: daemon_interface::Error) -> Self {
         match error {
-            daemon_interface::Error::RpcError(RestError::ApiError(_, code)) => {
-                match code.as_str() {
-                    mullvad_rpc::INVALID_VOUCHER => VoucherSubmissionError::InvalidVoucher,
-                    mullvad_rpc::VOUCHER_USED => VoucherSubmissionError::VoucherAlreadyUsed,
-                    _ => VoucherSubmissionError::RpcError,
-                }
-            }
+            daemon_interface::Error::RpcError(RestError::ApiError(_, code)) => match code {
+                ApiErrorCode::InvalidVoucher => VoucherSubmissionError::InvalidVoucher,
+                ApiErrorCode::VoucherUsed => VoucherSubmissionError::VoucherAlreadyUsed,
+                _ => VoucherSubmissionError::RpcError,
+            },
             daemon_interface::Error::RpcError(_) => VoucherSubmissionError::RpcError,
             _ => VoucherSubmissionError::OtherError,
         }
@@ -1,5 +1,83 @@
+const PACKAGE: &str = ".mullvad_daemon.management_interface";
+
+/// Fields that use the well-known protobuf types, which do not implement `Serialize`.
+const TIMESTAMP_FIELDS: &[&str] = &[
+    "AccountData.expiry",
+    "AccountExpiryEvent.expiry",
+    "VoucherSubmission.new_expiry",
+    "Device.created",
+    "PublicKey.created",
+    "ApiResolution.timestamp",
+    "ApiEndpointSuccess.timestamp",
+    "ScheduledTask.next_run",
+    "ScheduledTask.last_run",
+    "ApiTraceEntry.timestamp",
+    "PendingProblemReport.created",
+    "RelayConnectionStatistics.last_attempt",
+    "ConnectionAttempt.timestamp",
+    "MigrationReport.finished",
+    "StateTransition.timestamp",
+    "TunnelState.Connecting.retry_at",
+    "RelayLatency.measured_at",
+];
+const DURATION_FIELDS: &[&str] = &[
+    "TunnelOptions.WireguardOptions.rotation_interval",
+    "ScheduledTask.interval",
+    "ApiTraceEntry.duration",
+    "RelayConnectionStatistics.mean_connect_time",
+    "RelayHealth.blacklisted_for",
+    "RelayHealth.latency",
+    "RelayLatency.latency",
+    "ConnectionAttemptSpan.offset",
+    "ConnectionAttemptSpan.duration",
+    "ConnectionAttempt.duration",
+    "LogLevelOverrides.revert_after",
+    "ReconnectBackoffSettings.initial_delay",
+    "ReconnectBackoffSettings.max_delay",
+    "ExitIpCheckSettings.interval",
+    "ConnectTimeHistogram.median",
+    "ConnectTimeHistogram.p90",
+    "ConnectTimeHistogram.max",
+];
+const KEY_FIELDS: &[&str] = &[
+    "ConnectionConfig.WireguardConfig.PeerConfig.public_key",
+    "Device.pubkey",
+    "PublicKey.key",
+    "WireguardEndpointData.public_key",
+];
+/// Fields that must never be part of the serialized output.
+const SECRET_FIELDS: &[&str] = &["ConnectionConfig.WireguardConfig.TunnelConfig.private_key"];
+
 fn main() {
     const PROTO_FILE: &str = "proto/management_interface.proto";
-    tonic_build::compile_protos(PROTO_FILE).unwrap();
+
+    let mut builder = tonic_build::configure().type_attribute(
+        PACKAGE,
+        "#[derive(serde::Serialize)] #[serde(rename_all = \"snake_case\")]",
+    );
+    let fields = [
+        (
+            TIMESTAMP_FIELDS,
+            "serialize_with = \"crate::types::serialize_timestamp\"",
+        ),
+        (
+            DURATION_FIELDS,
+            "serialize_with = \"crate::types::serialize_duration\"",
+        ),
+        (
+            KEY_FIELDS,
+            "serialize_with = \"crate::types::serialize_key\"",
+        ),
+        (SECRET_FIELDS, "skip"),
+    ];
+    for (paths, attribute) in &fields {
+        for path in paths.iter() {
+            builder = builder.field_attribute(
+                format!("{}.{}", PACKAGE, path),
+                format!("#[serde({})]", attribute),
+            );
+        }
+    }
+    builder.compile(&[PROTO_FILE], &["proto"]).unwrap();
     println!("cargo:rerun-if-changed={}", PROTO_FILE);
 }
@@ -12,7 +12,7 @@ use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tonic::transport::{server::Connected, Endpoint, Server, Uri};
 use tower::service_fn;
 
-pub use tonic::{async_trait, transport::Channel, Code, Request, Response, Status};
+pub use tonic::{async_trait, transport::Channel, Code, Request, Response, Status, Streaming};
 
 pub type ManagementServiceClient =
     types::management_service_client::ManagementServiceClient<Channel>;
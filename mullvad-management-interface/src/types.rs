@@ -1,11 +1,47 @@
 pub use prost_types::{Duration, Timestamp};
 
 use mullvad_types::relay_constraints::Constraint;
-use std::convert::TryFrom;
+use std::{convert::TryFrom, fmt, path::PathBuf};
 use talpid_types::ErrorExt;
 
 tonic::include_proto!("mullvad_daemon.management_interface");
 
+/// Serializes a timestamp as an RFC 3339 string in UTC.
+pub(crate) fn serialize_timestamp<S: serde::Serializer>(
+    timestamp: &Option<Timestamp>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let timestamp = timestamp.as_ref().and_then(|timestamp| {
+        chrono::NaiveDateTime::from_timestamp_opt(timestamp.seconds, timestamp.nanos as u32)
+    });
+    match timestamp {
+        Some(timestamp) => serializer.serialize_str(
+            &chrono::DateTime::<chrono::Utc>::from_utc(timestamp, chrono::Utc).to_rfc3339(),
+        ),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Serializes a duration as a number of seconds.
+pub(crate) fn serialize_duration<S: serde::Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => serializer
+            .serialize_f64(duration.seconds as f64 + f64::from(duration.nanos) / 1_000_000_000.0),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Serializes a public key as a base64 string.
+pub(crate) fn serialize_key<S: serde::Serializer>(
+    key: &[u8],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&base64::encode(key))
+}
+
 impl From<mullvad_types::location::GeoIpLocation> for GeoIpLocation {
     fn from(geoip: mullvad_types::location::GeoIpLocation) -> GeoIpLocation {
         GeoIpLocation {
@@ -22,8 +58,58 @@ impl From<mullvad_types::location::GeoIpLocation> for GeoIpLocation {
     }
 }
 
+impl From<mullvad_types::location::ConnectionCheck> for ConnectionCheck {
+    fn from(check: mullvad_types::location::ConnectionCheck) -> ConnectionCheck {
+        ConnectionCheck {
+            exit_ip: check.exit_ip.to_string(),
+            mullvad_exit_ip: check.mullvad_exit_ip,
+            hostname_matches: check.hostname_matches(),
+            location_matches: check.location_matches(),
+            exit_hostname: check.exit_hostname.unwrap_or_default(),
+            expected_hostname: check.expected_hostname.unwrap_or_default(),
+            exit_country: check.exit_country,
+            exit_city: check.exit_city.unwrap_or_default(),
+            expected_country: check.expected_country.unwrap_or_default(),
+            expected_city: check.expected_city.unwrap_or_default(),
+            dns_checked: check.dns_servers.is_some(),
+            dns_servers: check
+                .dns_servers
+                .unwrap_or_default()
+                .into_iter()
+                .map(DnsServerCheck::from)
+                .collect(),
+            blacklisted: check.blacklisted,
+            blacklists: check.blacklists,
+        }
+    }
+}
+
+impl From<mullvad_types::location::DnsServerCheck> for DnsServerCheck {
+    fn from(server: mullvad_types::location::DnsServerCheck) -> DnsServerCheck {
+        DnsServerCheck {
+            ip: server.ip.to_string(),
+            mullvad_dns: server.mullvad_dns,
+            hostname: server.hostname.unwrap_or_default(),
+            organization: server.organization.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<mullvad_types::states::FeatureIndicators> for FeatureIndicators {
+    fn from(indicators: mullvad_types::states::FeatureIndicators) -> Self {
+        FeatureIndicators {
+            lockdown_mode: indicators.lockdown_mode,
+            split_tunneling: indicators.split_tunneling,
+            custom_dns: indicators.custom_dns,
+            obfuscation: indicators.obfuscation,
+            multihop: indicators.multihop,
+        }
+    }
+}
+
 impl From<talpid_types::net::TunnelEndpoint> for TunnelEndpoint {
     fn from(endpoint: talpid_types::net::TunnelEndpoint) -> Self {
+        use obfuscation_endpoint::ObfuscationType;
         use talpid_types::net;
 
         TunnelEndpoint {
@@ -45,6 +131,15 @@ impl From<talpid_types::net::TunnelEndpoint> for TunnelEndpoint {
                 address: entry.address.to_string(),
                 protocol: i32::from(TransportProtocol::from(entry.protocol)),
             }),
+            obfuscation: endpoint.obfuscation.map(|obfuscation| ObfuscationEndpoint {
+                address: obfuscation.endpoint.address.to_string(),
+                protocol: i32::from(TransportProtocol::from(obfuscation.endpoint.protocol)),
+                obfuscation_type: match obfuscation.obfuscation_type {
+                    net::ObfuscationType::Udp2Tcp => i32::from(ObfuscationType::Udp2tcp),
+                    net::ObfuscationType::Quic => i32::from(ObfuscationType::Quic),
+                    net::ObfuscationType::Shadowsocks => i32::from(ObfuscationType::Shadowsocks),
+                },
+            }),
         }
     }
 }
@@ -78,28 +173,41 @@ impl From<mullvad_types::states::TunnelState> for TunnelState {
                         lock_name,
                     }
                 }
+                talpid_tunnel::FirewallPolicyError::Unknown => FirewallPolicyError {
+                    r#type: i32::from(PolicyErrorType::Unknown),
+                    ..Default::default()
+                },
             };
 
         let state = match state {
             MullvadTunnelState::Disconnected => {
                 tunnel_state::State::Disconnected(tunnel_state::Disconnected {})
             }
-            MullvadTunnelState::Connecting { endpoint, location } => {
-                tunnel_state::State::Connecting(tunnel_state::Connecting {
-                    relay_info: Some(TunnelStateRelayInfo {
-                        tunnel_endpoint: Some(TunnelEndpoint::from(endpoint)),
-                        location: location.map(GeoIpLocation::from),
-                    }),
-                })
-            }
-            MullvadTunnelState::Connected { endpoint, location } => {
-                tunnel_state::State::Connected(tunnel_state::Connected {
-                    relay_info: Some(TunnelStateRelayInfo {
-                        tunnel_endpoint: Some(TunnelEndpoint::from(endpoint)),
-                        location: location.map(GeoIpLocation::from),
-                    }),
-                })
-            }
+            MullvadTunnelState::Connecting {
+                endpoint,
+                location,
+                retry_at,
+            } => tunnel_state::State::Connecting(tunnel_state::Connecting {
+                relay_info: Some(TunnelStateRelayInfo {
+                    tunnel_endpoint: Some(TunnelEndpoint::from(endpoint)),
+                    location: location.map(GeoIpLocation::from),
+                }),
+                retry_at: retry_at.map(|retry_at| Timestamp {
+                    seconds: retry_at.timestamp(),
+                    nanos: 0,
+                }),
+            }),
+            MullvadTunnelState::Connected {
+                endpoint,
+                location,
+                feature_indicators,
+            } => tunnel_state::State::Connected(tunnel_state::Connected {
+                relay_info: Some(TunnelStateRelayInfo {
+                    tunnel_endpoint: Some(TunnelEndpoint::from(endpoint)),
+                    location: location.map(GeoIpLocation::from),
+                }),
+                feature_indicators: Some(FeatureIndicators::from(feature_indicators)),
+            }),
             MullvadTunnelState::Disconnecting(after_disconnect) => {
                 tunnel_state::State::Disconnecting(tunnel_state::Disconnecting {
                     after_disconnect: match after_disconnect {
@@ -148,6 +256,13 @@ impl From<mullvad_types::states::TunnelState> for TunnelState {
                             talpid_tunnel::ErrorStateCause::SplitTunnelError => {
                                 i32::from(Cause::SplitTunnelError)
                             }
+                            #[cfg(not(target_os = "android"))]
+                            talpid_tunnel::ErrorStateCause::CaptivePortalLogin => {
+                                i32::from(Cause::CaptivePortalLogin)
+                            }
+                            talpid_tunnel::ErrorStateCause::Unknown => {
+                                i32::from(Cause::UnknownCause)
+                            }
                         },
                         blocking_error: error_state.block_failure().map(map_firewall_error),
                         auth_fail_reason: if let talpid_tunnel::ErrorStateCause::AuthFailed(
@@ -175,6 +290,9 @@ impl From<mullvad_types::states::TunnelState> for TunnelState {
                             talpid_tunnel::ParameterGenerationError::CustomTunnelHostResultionError => {
                                 i32::from(GenerationError::CustomTunnelHostResolutionError)
                             }
+                            talpid_tunnel::ParameterGenerationError::Unknown => {
+                                i32::from(GenerationError::UnknownGenerationError)
+                            }
                         }
                             } else {
                                 0
@@ -192,7 +310,54 @@ impl From<mullvad_types::states::TunnelState> for TunnelState {
             }
         };
 
-        TunnelState { state: Some(state) }
+        TunnelState {
+            state: Some(state),
+            version: mullvad_types::states::TUNNEL_STATE_VERSION,
+        }
+    }
+}
+
+impl From<talpid_types::tunnel::TransitionReason> for TransitionReason {
+    fn from(reason: talpid_types::tunnel::TransitionReason) -> Self {
+        use talpid_types::tunnel::TransitionReason as TalpidReason;
+        use transition_reason::ReasonType;
+
+        match reason {
+            TalpidReason::SetDnsFailed { error } => TransitionReason {
+                r#type: i32::from(ReasonType::SetDnsFailed),
+                error,
+                ..Default::default()
+            },
+            TalpidReason::SetFirewallPolicyFailed { error, error_code } => TransitionReason {
+                r#type: i32::from(ReasonType::SetFirewallPolicyFailed),
+                error,
+                error_code,
+                ..Default::default()
+            },
+            TalpidReason::AddressTimeout => TransitionReason {
+                r#type: i32::from(ReasonType::AddressTimeout),
+                ..Default::default()
+            },
+            TalpidReason::ConnectivityChanged { is_offline } => TransitionReason {
+                r#type: i32::from(ReasonType::ConnectivityChanged),
+                is_offline,
+                ..Default::default()
+            },
+            TalpidReason::PacketLoss { percent } => TransitionReason {
+                r#type: i32::from(ReasonType::PacketLoss),
+                packet_loss: u32::from(percent),
+                ..Default::default()
+            },
+            TalpidReason::DefaultInterfaceChanged { interface } => TransitionReason {
+                r#type: i32::from(ReasonType::DefaultInterfaceChanged),
+                interface,
+                ..Default::default()
+            },
+            TalpidReason::Unknown => TransitionReason {
+                r#type: i32::from(ReasonType::UnknownReason),
+                ..Default::default()
+            },
+        }
     }
 }
 
@@ -228,6 +393,87 @@ impl From<mullvad_types::wireguard::PublicKey> for PublicKey {
     }
 }
 
+impl From<mullvad_types::device::Device> for Device {
+    fn from(device: mullvad_types::device::Device) -> Self {
+        Device {
+            id: device.id,
+            name: device.name,
+            pubkey: device.pubkey.as_bytes().to_vec(),
+            created: Some(Timestamp {
+                seconds: device.created.timestamp(),
+                nanos: 0,
+            }),
+        }
+    }
+}
+
+impl From<mullvad_types::device::DeviceList> for DeviceList {
+    fn from(list: mullvad_types::device::DeviceList) -> Self {
+        DeviceList {
+            devices: list.devices.into_iter().map(Device::from).collect(),
+            current_device: list.current.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<mullvad_types::port_forward::PortForward> for PortForward {
+    fn from(port_forward: mullvad_types::port_forward::PortForward) -> Self {
+        PortForward {
+            id: port_forward.id,
+            port: u32::from(port_forward.port),
+            country_code: port_forward.country_code,
+            city_code: port_forward.city_code,
+        }
+    }
+}
+
+impl From<mullvad_types::device::DeviceState> for DeviceState {
+    fn from(state: mullvad_types::device::DeviceState) -> Self {
+        use mullvad_types::device::DeviceState as MullvadDeviceState;
+
+        let state = match state {
+            MullvadDeviceState::Active => device_state::State::Active,
+            MullvadDeviceState::Revoked => device_state::State::Revoked,
+        };
+        DeviceState {
+            state: i32::from(state),
+        }
+    }
+}
+
+impl From<mullvad_types::device::DeviceIdentityChange> for DeviceIdentityChange {
+    fn from(change: mullvad_types::device::DeviceIdentityChange) -> Self {
+        DeviceIdentityChange {
+            id: change.id,
+            previous_name: change.previous_name,
+            name: change.name,
+        }
+    }
+}
+
+impl From<mullvad_types::location::ExitIpMismatch> for ExitIpMismatch {
+    fn from(mismatch: mullvad_types::location::ExitIpMismatch) -> Self {
+        use exit_ip_mismatch::Problem;
+        use mullvad_types::location::ExitIpProblem;
+
+        let (problem, exit_hostname, previous_exit_ip) = match mismatch.problem {
+            ExitIpProblem::NotMullvad => (Problem::NotMullvad, String::new(), String::new()),
+            ExitIpProblem::OtherRelay(hostname) => (Problem::OtherRelay, hostname, String::new()),
+            ExitIpProblem::Changed(previous) => {
+                (Problem::Changed, String::new(), previous.to_string())
+            }
+        };
+        ExitIpMismatch {
+            expected_hostname: mismatch.expected_hostname,
+            exit_ip: mismatch.exit_ip.to_string(),
+            problem: i32::from(problem),
+            exit_hostname,
+            previous_exit_ip,
+            reconnecting: mismatch.reconnecting,
+        }
+    }
+}
+
 impl From<mullvad_types::version::AppVersionInfo> for AppVersionInfo {
     fn from(version_info: mullvad_types::version::AppVersionInfo) -> Self {
         Self {
@@ -239,6 +485,453 @@ impl From<mullvad_types::version::AppVersionInfo> for AppVersionInfo {
     }
 }
 
+impl From<mullvad_types::api_access::ApiAccessStatus> for ApiAccessStatus {
+    fn from(status: mullvad_types::api_access::ApiAccessStatus) -> Self {
+        Self {
+            current_address: status.current_address.to_string(),
+            last_working: status.last_working.map(ApiEndpointSuccess::from),
+            addresses: status
+                .addresses
+                .iter()
+                .map(|address| address.to_string())
+                .collect(),
+            resolution_strategy: Some(ApiResolutionStrategy::from(status.resolution_strategy)),
+            last_resolution: status.last_resolution.map(ApiResolution::from),
+            access_method: status.access_method.map(ActiveApiAccessMethod::from),
+        }
+    }
+}
+
+impl From<mullvad_types::api_access::ActiveApiAccessMethod> for ActiveApiAccessMethod {
+    fn from(method: mullvad_types::api_access::ActiveApiAccessMethod) -> Self {
+        Self {
+            name: method.name,
+            endpoint: method
+                .endpoint
+                .map(|endpoint| endpoint.to_string())
+                .unwrap_or_default(),
+            bridge_hostname: method.bridge_hostname.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<mullvad_types::api_access::ApiResolutionStrategy> for ApiResolutionStrategy {
+    fn from(strategy: mullvad_types::api_access::ApiResolutionStrategy) -> Self {
+        use mullvad_types::api_access::ApiResolutionStrategy;
+        Self {
+            strategy: i32::from(match strategy {
+                ApiResolutionStrategy::Static => api_resolution_strategy::Strategy::Static,
+                ApiResolutionStrategy::System => api_resolution_strategy::Strategy::System,
+                ApiResolutionStrategy::DnsOverHttps => {
+                    api_resolution_strategy::Strategy::DnsOverHttps
+                }
+            }),
+        }
+    }
+}
+
+impl From<mullvad_types::settings::CredentialStorage> for CredentialStorage {
+    fn from(storage: mullvad_types::settings::CredentialStorage) -> Self {
+        use mullvad_types::settings::CredentialStorage;
+        Self {
+            storage: i32::from(match storage {
+                CredentialStorage::Settings => credential_storage::Storage::Settings,
+                CredentialStorage::Keystore => credential_storage::Storage::Keystore,
+                CredentialStorage::File => credential_storage::Storage::File,
+            }),
+        }
+    }
+}
+
+impl From<mullvad_types::settings::LogPrivacyLevel> for LogPrivacy {
+    fn from(level: mullvad_types::settings::LogPrivacyLevel) -> Self {
+        use mullvad_types::settings::LogPrivacyLevel;
+        Self {
+            level: i32::from(match level {
+                LogPrivacyLevel::Full => log_privacy::Level::Full,
+                LogPrivacyLevel::Redacted => log_privacy::Level::Redacted,
+                LogPrivacyLevel::Minimal => log_privacy::Level::Minimal,
+            }),
+        }
+    }
+}
+
+impl From<talpid_types::net::Ipv6FailurePolicy> for Ipv6FailurePolicy {
+    fn from(policy: talpid_types::net::Ipv6FailurePolicy) -> Self {
+        use talpid_types::net::Ipv6FailurePolicy;
+        Self {
+            policy: i32::from(match policy {
+                Ipv6FailurePolicy::Ipv4Only => ipv6_failure_policy::Policy::Ipv4Only,
+                Ipv6FailurePolicy::Block => ipv6_failure_policy::Policy::Block,
+            }),
+        }
+    }
+}
+
+impl From<mullvad_types::wireguard::QuantumResistantState> for QuantumResistantState {
+    fn from(state: mullvad_types::wireguard::QuantumResistantState) -> Self {
+        use mullvad_types::wireguard::QuantumResistantState;
+        Self {
+            state: i32::from(match state {
+                QuantumResistantState::Auto => quantum_resistant_state::State::Auto,
+                QuantumResistantState::On => quantum_resistant_state::State::On,
+                QuantumResistantState::Off => quantum_resistant_state::State::Off,
+            }),
+        }
+    }
+}
+
+impl From<talpid_types::net::openvpn::WintunAdapterPolicy> for WintunAdapterPolicy {
+    fn from(policy: talpid_types::net::openvpn::WintunAdapterPolicy) -> Self {
+        use talpid_types::net::openvpn::WintunAdapterPolicy;
+        Self {
+            policy: i32::from(match policy {
+                WintunAdapterPolicy::Keep => wintun_adapter_policy::Policy::Keep,
+                WintunAdapterPolicy::DeleteOnShutdown => {
+                    wintun_adapter_policy::Policy::DeleteOnShutdown
+                }
+                WintunAdapterPolicy::DeleteAfterUse => {
+                    wintun_adapter_policy::Policy::DeleteAfterUse
+                }
+            }),
+        }
+    }
+}
+
+impl From<&mullvad_types::account::AccountExpirySettings> for AccountExpirySettings {
+    fn from(settings: &mullvad_types::account::AccountExpirySettings) -> Self {
+        use mullvad_types::account::ExpiryAction;
+        Self {
+            warning_thresholds: settings.warning_thresholds.clone(),
+            action: i32::from(match settings.action {
+                ExpiryAction::Block => account_expiry_settings::Action::Block,
+                ExpiryAction::Disconnect => account_expiry_settings::Action::Disconnect,
+            }),
+        }
+    }
+}
+
+impl From<&mullvad_types::settings::LogRotationSettings> for LogRotationSettings {
+    fn from(settings: &mullvad_types::settings::LogRotationSettings) -> Self {
+        Self {
+            max_size: settings.max_size.as_bytes(),
+            generations: settings.generations,
+            max_age: settings
+                .max_age
+                .map(|max_age| max_age.as_duration().as_secs())
+                .unwrap_or(0),
+            compress: settings.compress,
+        }
+    }
+}
+
+impl From<&mullvad_types::settings::LanProxySettings> for LanProxySettings {
+    fn from(settings: &mullvad_types::settings::LanProxySettings) -> Self {
+        Self {
+            enabled: settings.enabled,
+            port: u32::from(settings.port),
+        }
+    }
+}
+
+impl From<&mullvad_types::settings::LocalApiSettings> for LocalApiSettings {
+    fn from(settings: &mullvad_types::settings::LocalApiSettings) -> Self {
+        Self {
+            enabled: settings.enabled,
+            port: u32::from(settings.port),
+        }
+    }
+}
+
+impl From<&mullvad_types::settings::HookSettings> for HookSettings {
+    fn from(settings: &mullvad_types::settings::HookSettings) -> Self {
+        let path = |script: &Option<PathBuf>| {
+            script
+                .as_ref()
+                .map(|script| script.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+        Self {
+            pre_up: path(&settings.pre_up),
+            post_up: path(&settings.post_up),
+            post_down: path(&settings.post_down),
+            timeout: settings.timeout.as_duration().as_secs(),
+        }
+    }
+}
+
+impl From<&mullvad_types::settings::ReconnectBackoffSettings> for ReconnectBackoffSettings {
+    fn from(settings: &mullvad_types::settings::ReconnectBackoffSettings) -> Self {
+        Self {
+            initial_delay: Some(Duration::from(settings.initial_delay.as_duration())),
+            multiplier: settings.multiplier,
+            max_delay: Some(Duration::from(settings.max_delay.as_duration())),
+            jitter_percent: u32::from(settings.jitter_percent),
+        }
+    }
+}
+
+impl From<&mullvad_types::settings::ExitIpCheckSettings> for ExitIpCheckSettings {
+    fn from(settings: &mullvad_types::settings::ExitIpCheckSettings) -> Self {
+        Self {
+            enabled: settings.enabled,
+            interval: Some(Duration::from(settings.interval.as_duration())),
+            reconnect: settings.reconnect,
+        }
+    }
+}
+
+impl From<&mullvad_types::beta_features::BetaFeatures> for BetaFeatures {
+    fn from(features: &mullvad_types::beta_features::BetaFeatures) -> Self {
+        Self {
+            enabled: features.enabled.iter().cloned().collect(),
+        }
+    }
+}
+
+impl BetaFeatureList {
+    /// Lists every feature that can be turned on, and whether it is on in `features`.
+    pub fn new(features: &mullvad_types::beta_features::BetaFeatures) -> Self {
+        Self {
+            features: mullvad_types::beta_features::BetaFeature::ALL
+                .iter()
+                .map(|feature| BetaFeature {
+                    name: feature.name().to_owned(),
+                    description: feature.description().to_owned(),
+                    enabled: features.is_enabled(*feature),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<mullvad_types::settings::AllowedSystemServices> for AllowedSystemServices {
+    fn from(services: mullvad_types::settings::AllowedSystemServices) -> Self {
+        Self {
+            ntp: services.ntp,
+            captive_portal_detection: services.captive_portal_detection,
+        }
+    }
+}
+
+impl From<AllowedSystemServices> for mullvad_types::settings::AllowedSystemServices {
+    fn from(services: AllowedSystemServices) -> Self {
+        Self {
+            ntp: services.ntp,
+            captive_portal_detection: services.captive_portal_detection,
+        }
+    }
+}
+
+impl From<&mullvad_types::settings::TunnelParameterOverrides> for TunnelParameterOverrides {
+    fn from(overrides: &mullvad_types::settings::TunnelParameterOverrides) -> Self {
+        Self {
+            wireguard_port: overrides.wireguard_port.map(u32::from).unwrap_or(0),
+            wireguard_extra_allowed_ips: overrides
+                .wireguard_extra_allowed_ips
+                .iter()
+                .map(|network| network.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl From<&talpid_types::net::AllowedEndpoint> for FirewallException {
+    fn from(exception: &talpid_types::net::AllowedEndpoint) -> Self {
+        Self {
+            network: exception.network.to_string(),
+            port: exception.port.map(u32::from).unwrap_or(0),
+            protocol: exception
+                .protocol
+                .map(|protocol| TransportProtocolConstraint {
+                    protocol: i32::from(TransportProtocol::from(protocol)),
+                }),
+        }
+    }
+}
+
+impl From<mullvad_types::network_rules::NetworkAction> for NetworkAction {
+    fn from(action: mullvad_types::network_rules::NetworkAction) -> Self {
+        use mullvad_types::network_rules::NetworkAction as MullvadAction;
+        Self {
+            action: i32::from(match action {
+                MullvadAction::Connect => network_action::Action::Connect,
+                MullvadAction::Disconnect => network_action::Action::Disconnect,
+            }),
+        }
+    }
+}
+
+impl From<&mullvad_types::network_rules::NetworkMatch> for NetworkMatch {
+    fn from(network: &mullvad_types::network_rules::NetworkMatch) -> Self {
+        use mullvad_types::network_rules::NetworkMatch as MullvadMatch;
+        Self {
+            network: Some(match network {
+                MullvadMatch::Ssid(ssid) => network_match::Network::Ssid(ssid.clone()),
+                MullvadMatch::Subnet(subnet) => network_match::Network::Subnet(subnet.to_string()),
+                MullvadMatch::Gateway(hash) => network_match::Network::Gateway(hash.to_string()),
+            }),
+        }
+    }
+}
+
+impl From<&mullvad_types::network_rules::NetworkRule> for NetworkRule {
+    fn from(rule: &mullvad_types::network_rules::NetworkRule) -> Self {
+        Self {
+            network: Some(NetworkMatch::from(&rule.network)),
+            action: Some(NetworkAction::from(rule.action)),
+        }
+    }
+}
+
+impl From<&mullvad_types::network_rules::NetworkRules> for NetworkRules {
+    fn from(rules: &mullvad_types::network_rules::NetworkRules) -> Self {
+        Self {
+            rules: rules.rules.iter().map(NetworkRule::from).collect(),
+            default_action: Some(DefaultNetworkAction {
+                action: rules.default_action.map(NetworkAction::from),
+            }),
+        }
+    }
+}
+
+impl From<&talpid_types::net::NetworkIdentity> for NetworkIdentity {
+    fn from(network: &talpid_types::net::NetworkIdentity) -> Self {
+        Self {
+            ssids: network.ssids.clone(),
+            networks: network
+                .networks
+                .iter()
+                .map(|network| network.to_string())
+                .collect(),
+            fingerprint: Some(NetworkFingerprint::from(&network.fingerprint)),
+        }
+    }
+}
+
+impl From<&talpid_types::net::fingerprint::NetworkFingerprint> for NetworkFingerprint {
+    fn from(fingerprint: &talpid_types::net::fingerprint::NetworkFingerprint) -> Self {
+        use talpid_types::net::fingerprint::{FingerprintHash, InterfaceType};
+        let to_string =
+            |hash: Option<FingerprintHash>| hash.map(|hash| hash.to_string()).unwrap_or_default();
+        let interface_type = match fingerprint.interface_type {
+            InterfaceType::Wifi => network_fingerprint::InterfaceType::Wifi,
+            InterfaceType::Ethernet => network_fingerprint::InterfaceType::Ethernet,
+            InterfaceType::Cellular => network_fingerprint::InterfaceType::Cellular,
+            InterfaceType::Other => network_fingerprint::InterfaceType::Other,
+        };
+        Self {
+            gateway_mac: to_string(fingerprint.gateway_mac),
+            ssid: to_string(fingerprint.ssid),
+            interface_type: i32::from(interface_type),
+            country: fingerprint.country.clone().unwrap_or_default(),
+        }
+    }
+}
+
+impl From<mullvad_types::account::AccountExpiryEvent> for AccountExpiryEvent {
+    fn from(event: mullvad_types::account::AccountExpiryEvent) -> Self {
+        use mullvad_types::account::AccountExpiryEvent as MullvadEvent;
+        let (kind, expiry, threshold) = match event {
+            MullvadEvent::ExpiresSoon { expiry, threshold } => {
+                (account_expiry_event::Kind::ExpiresSoon, expiry, threshold)
+            }
+            MullvadEvent::Expired { expiry } => (account_expiry_event::Kind::Expired, expiry, 0),
+        };
+        Self {
+            kind: i32::from(kind),
+            expiry: Some(Timestamp {
+                seconds: expiry.timestamp(),
+                nanos: 0,
+            }),
+            threshold,
+        }
+    }
+}
+
+impl From<mullvad_types::api_access::ApiResolution> for ApiResolution {
+    fn from(resolution: mullvad_types::api_access::ApiResolution) -> Self {
+        Self {
+            strategy: Some(ApiResolutionStrategy::from(resolution.strategy)),
+            timestamp: Some(Timestamp {
+                seconds: resolution.timestamp.timestamp(),
+                nanos: 0,
+            }),
+            addresses: resolution
+                .addresses
+                .iter()
+                .map(|address| address.to_string())
+                .collect(),
+            error: resolution.error.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<mullvad_types::api_access::ApiEndpointSuccess> for ApiEndpointSuccess {
+    fn from(success: mullvad_types::api_access::ApiEndpointSuccess) -> Self {
+        Self {
+            address: success.address.to_string(),
+            timestamp: Some(Timestamp {
+                seconds: success.timestamp.timestamp(),
+                nanos: 0,
+            }),
+        }
+    }
+}
+
+impl From<&mullvad_types::api_access::ApiAccessSettings> for ApiAccessSettings {
+    fn from(settings: &mullvad_types::api_access::ApiAccessSettings) -> Self {
+        Self {
+            direct_enabled: settings.direct_enabled,
+            bridges_enabled: settings.bridges_enabled,
+            preferred: settings.preferred.clone().unwrap_or_default(),
+        }
+    }
+}
+
+impl From<mullvad_types::api_access::ApiAccessMethod> for ApiAccessMethod {
+    fn from(method: mullvad_types::api_access::ApiAccessMethod) -> Self {
+        Self {
+            name: method.name,
+            enabled: method.enabled,
+            proxy: Some(ApiProxy::from(method.proxy)),
+        }
+    }
+}
+
+impl ApiAccessMethod {
+    /// Removes the proxy password, which is only accepted when adding an access method and is
+    /// never sent back to clients.
+    fn without_password(mut self) -> Self {
+        if let Some(proxy) = &mut self.proxy {
+            proxy.password.clear();
+        }
+        self
+    }
+}
+
+impl From<mullvad_types::api_access::ApiProxy> for ApiProxy {
+    fn from(proxy: mullvad_types::api_access::ApiProxy) -> Self {
+        use mullvad_types::api_access::ApiProxy as MullvadProxy;
+
+        let (proxy_type, address, auth) = match proxy {
+            MullvadProxy::Socks5 { address, auth } => (api_proxy::ProxyType::Socks5, address, auth),
+            MullvadProxy::HttpConnect { address, auth } => {
+                (api_proxy::ProxyType::HttpConnect, address, auth)
+            }
+        };
+        let (username, password) = auth
+            .map(|auth| (auth.username, auth.password))
+            .unwrap_or_default();
+        Self {
+            r#type: i32::from(proxy_type),
+            address: address.to_string(),
+            username,
+            password,
+        }
+    }
+}
+
 impl From<mullvad_types::ConnectionConfig> for ConnectionConfig {
     fn from(config: mullvad_types::ConnectionConfig) -> Self {
         Self {
@@ -249,6 +942,19 @@ impl From<mullvad_types::ConnectionConfig> for ConnectionConfig {
                         protocol: i32::from(TransportProtocol::from(config.endpoint.protocol)),
                         username: config.username,
                         password: config.password,
+                        profile: config
+                            .profile
+                            .map(|profile| connection_config::ServerProfile {
+                                ca: profile.ca,
+                                cert: profile.cert,
+                                key: profile.key,
+                                tls_auth: profile.tls_auth,
+                                key_direction: profile.key_direction.map(u32::from),
+                                tls_crypt: profile.tls_crypt,
+                                data_ciphers: profile.data_ciphers,
+                                auth: profile.auth,
+                                auth_user_pass: profile.auth_user_pass,
+                            }),
                     })
                 }
                 mullvad_types::ConnectionConfig::Wireguard(config) => {
@@ -363,9 +1069,63 @@ impl From<mullvad_types::relay_constraints::LocationConstraint> for RelayLocatio
     }
 }
 
+impl From<mullvad_types::custom_list::CustomList> for CustomList {
+    fn from(list: mullvad_types::custom_list::CustomList) -> Self {
+        CustomList {
+            name: list.name,
+            locations: list
+                .locations
+                .into_iter()
+                .map(RelayLocation::from)
+                .collect(),
+        }
+    }
+}
+
+impl From<Vec<mullvad_types::settings::schema::SettingDescription>> for SettingsSchema {
+    fn from(settings: Vec<mullvad_types::settings::schema::SettingDescription>) -> Self {
+        SettingsSchema {
+            settings: settings.into_iter().map(SettingDescription::from).collect(),
+        }
+    }
+}
+
+impl From<mullvad_types::settings::schema::SettingDescription> for SettingDescription {
+    fn from(setting: mullvad_types::settings::schema::SettingDescription) -> Self {
+        SettingDescription {
+            name: setting.name,
+            type_name: setting.type_name,
+            description: setting.description,
+            default_value: setting.default.to_string(),
+            requires_reconnect: setting.requires_reconnect,
+            constraints: setting
+                .constraints
+                .into_iter()
+                .map(SettingConstraint::from)
+                .collect(),
+        }
+    }
+}
+
+impl From<mullvad_types::settings::schema::SettingConstraint> for SettingConstraint {
+    fn from(constraint: mullvad_types::settings::schema::SettingConstraint) -> Self {
+        use mullvad_types::settings::schema::SettingConstraint as MullvadConstraint;
+        use setting_constraint::{AllowedValues, Constraint};
+
+        let constraint = match constraint {
+            MullvadConstraint::Min(min) => Constraint::Min(min),
+            MullvadConstraint::Max(max) => Constraint::Max(max),
+            MullvadConstraint::OneOf(values) => Constraint::OneOf(AllowedValues { values }),
+        };
+        SettingConstraint {
+            constraint: Some(constraint),
+        }
+    }
+}
+
 impl From<&mullvad_types::settings::Settings> for Settings {
     fn from(settings: &mullvad_types::settings::Settings) -> Self {
-        #[cfg(windows)]
+        #[cfg(any(windows, target_os = "linux"))]
         let split_tunnel = {
             let mut converted_list = vec![];
             for path in settings.split_tunnel.apps.clone().iter() {
@@ -382,9 +1142,19 @@ impl From<&mullvad_types::settings::Settings> for Settings {
                 apps: converted_list,
             })
         };
-        #[cfg(not(windows))]
+        #[cfg(not(any(windows, target_os = "linux")))]
         let split_tunnel = None;
 
+        #[cfg(target_os = "linux")]
+        let dbus_service = settings.dbus_service;
+        #[cfg(not(target_os = "linux"))]
+        let dbus_service = false;
+
+        #[cfg(windows)]
+        let allow_virtual_networks = settings.allow_virtual_networks;
+        #[cfg(not(windows))]
+        let allow_virtual_networks = false;
+
         Self {
             account_token: settings.get_account_token().unwrap_or_default(),
             relay_settings: Some(RelaySettings::from(settings.get_relay_settings())),
@@ -396,6 +1166,88 @@ impl From<&mullvad_types::settings::Settings> for Settings {
             tunnel_options: Some(TunnelOptions::from(&settings.tunnel_options)),
             show_beta_releases: settings.show_beta_releases,
             split_tunnel,
+            api_access_methods: settings
+                .api_access_methods
+                .iter()
+                .cloned()
+                .map(ApiAccessMethod::from)
+                .map(ApiAccessMethod::without_password)
+                .collect(),
+            api_access: Some(ApiAccessSettings::from(&settings.api_access)),
+            api_resolution_strategy: Some(ApiResolutionStrategy::from(
+                settings.api_resolution_strategy,
+            )),
+            obfuscation_settings: Some(ObfuscationSettings::from(&settings.obfuscation_settings)),
+            encrypt_sensitive_settings: settings.encrypt_sensitive_settings,
+            keep_account_history: settings.keep_account_history,
+            account_expiry: Some(AccountExpirySettings::from(&settings.account_expiry)),
+            port_forwarding: settings.port_forwarding,
+            account_privacy_mode: settings.account_privacy_mode,
+            identity_rotation_interval: settings
+                .identity_rotation_interval
+                .map(|interval| interval.as_duration().as_secs())
+                .unwrap_or(0),
+            log_rotation: Some(LogRotationSettings::from(&settings.log_rotation)),
+            log_privacy: Some(LogPrivacy::from(settings.log_privacy)),
+            network_rules: Some(NetworkRules::from(&settings.network_rules)),
+            connection_statistics: settings.connection_statistics,
+            system_log: settings.system_log,
+            json_logs: settings.json_logs,
+            server_mode: settings.server_mode,
+            lan_proxy: Some(LanProxySettings::from(&settings.lan_proxy)),
+            local_api: Some(LocalApiSettings::from(&settings.local_api)),
+            hooks: Some(HookSettings::from(&settings.hooks)),
+            reconnect_backoff: Some(ReconnectBackoffSettings::from(&settings.reconnect_backoff)),
+            exit_ip_check: Some(ExitIpCheckSettings::from(&settings.exit_ip_check)),
+            beta_features: Some(BetaFeatures::from(&settings.beta_features)),
+            mdns_reflector: settings.mdns_reflector,
+            allowed_system_services: Some(AllowedSystemServices::from(
+                settings.allowed_system_services,
+            )),
+            tunnel_parameter_overrides: Some(TunnelParameterOverrides::from(
+                &settings.tunnel_parameter_overrides,
+            )),
+            dbus_service,
+            api_clock_check: settings.api_clock_check,
+            allow_virtual_networks,
+            firewall_exceptions: settings
+                .firewall_exceptions
+                .iter()
+                .map(FirewallException::from)
+                .collect(),
+            credential_storage: Some(CredentialStorage::from(settings.credential_storage)),
+            persistent_lockdown: settings.persistent_lockdown,
+            connection_profiles: settings.connection_profiles,
+        }
+    }
+}
+
+impl From<&mullvad_types::obfuscation::ObfuscationSettings> for ObfuscationSettings {
+    fn from(settings: &mullvad_types::obfuscation::ObfuscationSettings) -> Self {
+        use mullvad_types::obfuscation::ObfuscationMode;
+        let mode = match settings.mode {
+            ObfuscationMode::Auto => obfuscation_settings::Mode::Auto,
+            ObfuscationMode::Off => obfuscation_settings::Mode::Off,
+            ObfuscationMode::Udp2Tcp => obfuscation_settings::Mode::Udp2tcp,
+            ObfuscationMode::Custom => obfuscation_settings::Mode::Custom,
+            ObfuscationMode::Quic => obfuscation_settings::Mode::Quic,
+            ObfuscationMode::Shadowsocks => obfuscation_settings::Mode::Shadowsocks,
+        };
+        Self {
+            mode: i32::from(mode),
+            udp2tcp: Some(Udp2TcpObfuscationSettings {
+                port: u32::from(settings.udp2tcp.port.unwrap_or(0)),
+            }),
+            shadowsocks: Some(ShadowsocksObfuscationSettings {
+                port: u32::from(settings.shadowsocks.port.unwrap_or(0)),
+            }),
+            custom: settings.custom.map(|custom| CustomObfuscationSettings {
+                endpoint: custom.endpoint.to_string(),
+            }),
+            quic: settings.quic.as_ref().map(|quic| QuicObfuscationSettings {
+                endpoint: quic.endpoint.to_string(),
+                server_name: quic.server_name.clone(),
+            }),
         }
     }
 }
@@ -479,6 +1331,12 @@ impl From<mullvad_types::relay_constraints::RelaySettings> for RelaySettings {
                 relay_settings::Endpoint::Normal(NormalRelaySettings {
                     location: constraints.location.option().map(RelayLocation::from),
                     providers: convert_providers_constraint(&constraints.providers),
+                    filter: constraints
+                        .filter
+                        .as_ref()
+                        .option()
+                        .map(ToString::to_string)
+                        .unwrap_or_default(),
                     tunnel_type: match constraints.tunnel_protocol {
                         Constraint::Any => None,
                         Constraint::Only(talpid_net::TunnelType::Wireguard) => {
@@ -533,6 +1391,12 @@ impl From<&mullvad_types::settings::DnsOptions> for DnsOptions {
             state: match options.state {
                 mullvad_types::settings::DnsState::Default => dns_options::DnsState::Default as i32,
                 mullvad_types::settings::DnsState::Custom => dns_options::DnsState::Custom as i32,
+                mullvad_types::settings::DnsState::Encrypted => {
+                    dns_options::DnsState::Encrypted as i32
+                }
+                mullvad_types::settings::DnsState::LocalForwarder => {
+                    dns_options::DnsState::LocalForwarder as i32
+                }
             },
             default_options: Some(DefaultDnsOptions {
                 block_ads: options.default_options.block_ads,
@@ -546,6 +1410,35 @@ impl From<&mullvad_types::settings::DnsOptions> for DnsOptions {
                     .map(|addr| addr.to_string())
                     .collect(),
             }),
+            encrypted_options: Some(EncryptedDnsOptions {
+                server: options.encrypted_options.server.as_ref().map(|server| {
+                    EncryptedDnsServer {
+                        protocol: match server.protocol {
+                            mullvad_types::settings::EncryptedDnsProtocol::Tls => {
+                                encrypted_dns_server::Protocol::Tls as i32
+                            }
+                            mullvad_types::settings::EncryptedDnsProtocol::Https => {
+                                encrypted_dns_server::Protocol::Https as i32
+                            }
+                        },
+                        address: server.address.to_string(),
+                        hostname: server.hostname.clone(),
+                    }
+                }),
+            }),
+            local_forwarder_options: Some(LocalDnsForwarderOptions {
+                address: options
+                    .local_forwarder_options
+                    .address
+                    .map(|addr| addr.to_string())
+                    .unwrap_or_default(),
+                upstream_servers: options
+                    .local_forwarder_options
+                    .upstream_servers
+                    .iter()
+                    .map(|addr| addr.to_string())
+                    .collect(),
+            }),
         }
     }
 }
@@ -555,9 +1448,33 @@ impl From<&mullvad_types::settings::TunnelOptions> for TunnelOptions {
         Self {
             openvpn: Some(tunnel_options::OpenvpnOptions {
                 mssfix: u32::from(options.openvpn.mssfix.unwrap_or_default()),
+                #[cfg(windows)]
+                use_ovpn_dco: options.openvpn.use_ovpn_dco,
+                #[cfg(not(windows))]
+                use_ovpn_dco: false,
+                #[cfg(windows)]
+                wintun_adapter_policy: Some(WintunAdapterPolicy::from(
+                    options.openvpn.wintun_adapter_policy,
+                )),
+                #[cfg(not(windows))]
+                wintun_adapter_policy: None,
             }),
             wireguard: Some(tunnel_options::WireguardOptions {
                 mtu: u32::from(options.wireguard.options.mtu.unwrap_or_default()),
+                icmp_check_interval: u32::from(
+                    options
+                        .wireguard
+                        .options
+                        .icmp_check_interval
+                        .unwrap_or_default(),
+                ),
+                packet_loss_threshold: u32::from(
+                    options
+                        .wireguard
+                        .options
+                        .packet_loss_threshold
+                        .unwrap_or_default(),
+                ),
                 rotation_interval: options
                     .wireguard
                     .rotation_interval
@@ -566,9 +1483,20 @@ impl From<&mullvad_types::settings::TunnelOptions> for TunnelOptions {
                 use_wireguard_nt: options.wireguard.options.use_wireguard_nt,
                 #[cfg(not(windows))]
                 use_wireguard_nt: false,
+                #[cfg(target_os = "linux")]
+                use_userspace_wireguard: options.wireguard.options.use_userspace_wireguard,
+                #[cfg(not(target_os = "linux"))]
+                use_userspace_wireguard: false,
+                quantum_resistant: Some(QuantumResistantState::from(
+                    options.wireguard.quantum_resistant,
+                )),
             }),
             generic: Some(tunnel_options::GenericOptions {
                 enable_ipv6: options.generic.enable_ipv6,
+                interface_name: options.generic.interface_name.clone().unwrap_or_default(),
+                ipv6_failure_policy: Some(Ipv6FailurePolicy::from(
+                    options.generic.ipv6_failure_policy,
+                )),
             }),
             #[cfg(not(target_os = "android"))]
             dns_options: Some(DnsOptions::from(&options.dns_options)),
@@ -782,6 +1710,7 @@ impl TryFrom<RelaySettings> for mullvad_types::relay_constraints::RelaySettings
                             ))?,
                     );
                 let providers = try_providers_constraint_from_proto(&settings.providers)?;
+                let filter = try_relay_filter_from_proto(&settings.filter)?;
                 let tunnel_protocol = settings
                     .tunnel_type
                     .map(Constraint::<net::TunnelType>::try_from)
@@ -806,6 +1735,7 @@ impl TryFrom<RelaySettings> for mullvad_types::relay_constraints::RelaySettings
                         tunnel_protocol,
                         wireguard_constraints,
                         openvpn_constraints,
+                        filter,
                     },
                 ))
             }
@@ -862,6 +1792,10 @@ impl TryFrom<RelaySettingsUpdate> for mullvad_types::relay_constraints::RelaySet
                 } else {
                     None
                 };
+                let filter = settings
+                    .filter
+                    .map(|update| try_relay_filter_from_proto(&update.filter))
+                    .transpose()?;
                 let tunnel_protocol = if let Some(update) = settings.tunnel_type {
                     Some(
                         update
@@ -896,6 +1830,7 @@ impl TryFrom<RelaySettingsUpdate> for mullvad_types::relay_constraints::RelaySet
                         tunnel_protocol,
                         wireguard_constraints,
                         openvpn_constraints,
+                        filter,
                     },
                 ))
             }
@@ -949,6 +1884,32 @@ impl TryFrom<ConnectionConfig> for mullvad_types::ConnectionConfig {
                         },
                         username: config.username,
                         password: config.password,
+                        profile: config
+                            .profile
+                            .map(|profile| {
+                                let key_direction = match profile.key_direction {
+                                    Some(direction @ 0..=1) => Some(direction as u8),
+                                    Some(_) => {
+                                        return Err(FromProtobufTypeError::InvalidArgument(
+                                            "invalid key direction",
+                                        ))
+                                    }
+                                    None => None,
+                                };
+                                Ok(openvpn::ServerProfile {
+                                    ca: profile.ca,
+                                    cert: profile.cert,
+                                    key: profile.key,
+                                    tls_auth: profile.tls_auth,
+                                    key_direction,
+                                    tls_crypt: profile.tls_crypt,
+                                    data_ciphers: profile.data_ciphers,
+                                    auth: profile.auth,
+                                    auth_user_pass: profile.auth_user_pass,
+                                })
+                            })
+                            .transpose()?,
+                        handshake_timeout: None,
                     },
                 ))
             }
@@ -1022,9 +1983,9 @@ impl TryFrom<ConnectionConfig> for mullvad_types::ConnectionConfig {
 
                 let mut allowed_ips = Vec::new();
                 for address in peer.allowed_ips {
-                    let address = address
-                        .parse()
-                        .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid address"))?;
+                    let address = address.parse().map_err(|_| {
+                        FromProtobufTypeError::InvalidArgument("invalid allowed IP network")
+                    })?;
                     allowed_ips.push(address);
                 }
 
@@ -1039,10 +2000,14 @@ impl TryFrom<ConnectionConfig> for mullvad_types::ConnectionConfig {
                             allowed_ips,
                             endpoint,
                             protocol: try_transport_protocol_from_i32(peer.protocol)?,
+                            psk: None,
                         },
                         exit_peer: None,
                         ipv4_gateway,
                         ipv6_gateway,
+                        obfuscation: None,
+                        handshake_timeout: None,
+                        quantum_resistant: false,
                     },
                 ))
             }
@@ -1070,6 +2035,31 @@ impl From<RelayLocation> for Constraint<mullvad_types::relay_constraints::Locati
     }
 }
 
+impl TryFrom<CustomList> for mullvad_types::custom_list::CustomList {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(list: CustomList) -> Result<Self, Self::Error> {
+        let locations = list
+            .locations
+            .into_iter()
+            .map(|location| {
+                Constraint::<mullvad_types::relay_constraints::LocationConstraint>::from(location)
+                    .option()
+                    .ok_or(FromProtobufTypeError::InvalidArgument(
+                        "custom list locations must not be empty",
+                    ))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let list = mullvad_types::custom_list::CustomList {
+            name: list.name,
+            locations,
+        };
+        list.validate()
+            .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid custom list"))?;
+        Ok(list)
+    }
+}
+
 impl TryFrom<BridgeSettings> for mullvad_types::relay_constraints::BridgeSettings {
     type Error = FromProtobufTypeError;
 
@@ -1161,6 +2151,404 @@ impl TryFrom<BridgeState> for mullvad_types::relay_constraints::BridgeState {
     }
 }
 
+impl TryFrom<ApiResolutionStrategy> for mullvad_types::api_access::ApiResolutionStrategy {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(strategy: ApiResolutionStrategy) -> Result<Self, Self::Error> {
+        use mullvad_types::api_access::ApiResolutionStrategy;
+        match api_resolution_strategy::Strategy::from_i32(strategy.strategy) {
+            Some(api_resolution_strategy::Strategy::Static) => Ok(ApiResolutionStrategy::Static),
+            Some(api_resolution_strategy::Strategy::System) => Ok(ApiResolutionStrategy::System),
+            Some(api_resolution_strategy::Strategy::DnsOverHttps) => {
+                Ok(ApiResolutionStrategy::DnsOverHttps)
+            }
+            None => Err(FromProtobufTypeError::InvalidArgument(
+                "invalid API resolution strategy",
+            )),
+        }
+    }
+}
+
+impl TryFrom<CredentialStorage> for mullvad_types::settings::CredentialStorage {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(storage: CredentialStorage) -> Result<Self, Self::Error> {
+        use mullvad_types::settings::CredentialStorage;
+        match credential_storage::Storage::from_i32(storage.storage) {
+            Some(credential_storage::Storage::Settings) => Ok(CredentialStorage::Settings),
+            Some(credential_storage::Storage::Keystore) => Ok(CredentialStorage::Keystore),
+            Some(credential_storage::Storage::File) => Ok(CredentialStorage::File),
+            None => Err(FromProtobufTypeError::InvalidArgument(
+                "invalid credential storage",
+            )),
+        }
+    }
+}
+
+impl TryFrom<Ipv6FailurePolicy> for talpid_types::net::Ipv6FailurePolicy {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(policy: Ipv6FailurePolicy) -> Result<Self, Self::Error> {
+        use talpid_types::net::Ipv6FailurePolicy;
+        match ipv6_failure_policy::Policy::from_i32(policy.policy) {
+            Some(ipv6_failure_policy::Policy::Ipv4Only) => Ok(Ipv6FailurePolicy::Ipv4Only),
+            Some(ipv6_failure_policy::Policy::Block) => Ok(Ipv6FailurePolicy::Block),
+            None => Err(FromProtobufTypeError::InvalidArgument(
+                "invalid IPv6 failure policy",
+            )),
+        }
+    }
+}
+
+impl TryFrom<WintunAdapterPolicy> for talpid_types::net::openvpn::WintunAdapterPolicy {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(policy: WintunAdapterPolicy) -> Result<Self, Self::Error> {
+        use talpid_types::net::openvpn::WintunAdapterPolicy;
+        match wintun_adapter_policy::Policy::from_i32(policy.policy) {
+            Some(wintun_adapter_policy::Policy::Keep) => Ok(WintunAdapterPolicy::Keep),
+            Some(wintun_adapter_policy::Policy::DeleteOnShutdown) => {
+                Ok(WintunAdapterPolicy::DeleteOnShutdown)
+            }
+            Some(wintun_adapter_policy::Policy::DeleteAfterUse) => {
+                Ok(WintunAdapterPolicy::DeleteAfterUse)
+            }
+            None => Err(FromProtobufTypeError::InvalidArgument(
+                "invalid Wintun adapter policy",
+            )),
+        }
+    }
+}
+
+impl TryFrom<LogPrivacy> for mullvad_types::settings::LogPrivacyLevel {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(privacy: LogPrivacy) -> Result<Self, Self::Error> {
+        use mullvad_types::settings::LogPrivacyLevel;
+        match log_privacy::Level::from_i32(privacy.level) {
+            Some(log_privacy::Level::Full) => Ok(LogPrivacyLevel::Full),
+            Some(log_privacy::Level::Redacted) => Ok(LogPrivacyLevel::Redacted),
+            Some(log_privacy::Level::Minimal) => Ok(LogPrivacyLevel::Minimal),
+            None => Err(FromProtobufTypeError::InvalidArgument(
+                "invalid log privacy level",
+            )),
+        }
+    }
+}
+
+impl TryFrom<QuantumResistantState> for mullvad_types::wireguard::QuantumResistantState {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(state: QuantumResistantState) -> Result<Self, Self::Error> {
+        use mullvad_types::wireguard::QuantumResistantState;
+        match quantum_resistant_state::State::from_i32(state.state) {
+            Some(quantum_resistant_state::State::Auto) => Ok(QuantumResistantState::Auto),
+            Some(quantum_resistant_state::State::On) => Ok(QuantumResistantState::On),
+            Some(quantum_resistant_state::State::Off) => Ok(QuantumResistantState::Off),
+            None => Err(FromProtobufTypeError::InvalidArgument(
+                "invalid quantum-resistant state",
+            )),
+        }
+    }
+}
+
+impl TryFrom<AccountExpirySettings> for mullvad_types::account::AccountExpirySettings {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(settings: AccountExpirySettings) -> Result<Self, Self::Error> {
+        use mullvad_types::account::ExpiryAction;
+        let action = match account_expiry_settings::Action::from_i32(settings.action) {
+            Some(account_expiry_settings::Action::Block) => ExpiryAction::Block,
+            Some(account_expiry_settings::Action::Disconnect) => ExpiryAction::Disconnect,
+            None => {
+                return Err(FromProtobufTypeError::InvalidArgument(
+                    "invalid account expiry action",
+                ))
+            }
+        };
+        Ok(mullvad_types::account::AccountExpirySettings {
+            warning_thresholds: settings.warning_thresholds,
+            action,
+        })
+    }
+}
+
+impl From<LogRotationSettings> for mullvad_types::settings::LogRotationSettings {
+    fn from(settings: LogRotationSettings) -> Self {
+        use mullvad_types::units::{ByteSize, HumanDuration};
+        Self {
+            max_size: ByteSize::from_bytes(settings.max_size),
+            generations: settings.generations,
+            max_age: if settings.max_age == 0 {
+                None
+            } else {
+                Some(HumanDuration(std::time::Duration::from_secs(
+                    settings.max_age,
+                )))
+            },
+            compress: settings.compress,
+        }
+    }
+}
+
+impl TryFrom<LanProxySettings> for mullvad_types::settings::LanProxySettings {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(settings: LanProxySettings) -> Result<Self, Self::Error> {
+        Ok(Self {
+            enabled: settings.enabled,
+            port: u16::try_from(settings.port)
+                .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid LAN proxy port"))?,
+        })
+    }
+}
+
+impl TryFrom<LocalApiSettings> for mullvad_types::settings::LocalApiSettings {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(settings: LocalApiSettings) -> Result<Self, Self::Error> {
+        Ok(Self {
+            enabled: settings.enabled,
+            port: u16::try_from(settings.port)
+                .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid local API port"))?,
+        })
+    }
+}
+
+impl From<HookSettings> for mullvad_types::settings::HookSettings {
+    fn from(settings: HookSettings) -> Self {
+        use mullvad_types::units::HumanDuration;
+        let path = |script: String| {
+            if script.is_empty() {
+                None
+            } else {
+                Some(PathBuf::from(script))
+            }
+        };
+        Self {
+            pre_up: path(settings.pre_up),
+            post_up: path(settings.post_up),
+            post_down: path(settings.post_down),
+            timeout: HumanDuration(std::time::Duration::from_secs(settings.timeout)),
+        }
+    }
+}
+
+impl TryFrom<ReconnectBackoffSettings> for mullvad_types::settings::ReconnectBackoffSettings {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(settings: ReconnectBackoffSettings) -> Result<Self, Self::Error> {
+        use mullvad_types::units::HumanDuration;
+        let duration = |duration: Option<Duration>| {
+            duration
+                .ok_or(FromProtobufTypeError::InvalidArgument("missing delay"))
+                .and_then(|duration| {
+                    std::time::Duration::try_from(duration)
+                        .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid delay"))
+                })
+                .map(HumanDuration)
+        };
+        Ok(Self {
+            initial_delay: duration(settings.initial_delay)?,
+            multiplier: settings.multiplier,
+            max_delay: duration(settings.max_delay)?,
+            jitter_percent: u8::try_from(settings.jitter_percent)
+                .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid jitter percentage"))?,
+        })
+    }
+}
+
+impl TryFrom<ExitIpCheckSettings> for mullvad_types::settings::ExitIpCheckSettings {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(settings: ExitIpCheckSettings) -> Result<Self, Self::Error> {
+        let interval = settings
+            .interval
+            .ok_or(FromProtobufTypeError::InvalidArgument("missing interval"))?;
+        let interval = std::time::Duration::try_from(interval)
+            .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid interval"))?;
+        Ok(Self {
+            enabled: settings.enabled,
+            interval: mullvad_types::units::HumanDuration(interval),
+            reconnect: settings.reconnect,
+        })
+    }
+}
+
+impl TryFrom<TunnelParameterOverrides> for mullvad_types::settings::TunnelParameterOverrides {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(overrides: TunnelParameterOverrides) -> Result<Self, Self::Error> {
+        let wireguard_port = match overrides.wireguard_port {
+            0 => None,
+            port => Some(u16::try_from(port).map_err(|_| {
+                FromProtobufTypeError::InvalidArgument("invalid WireGuard port override")
+            })?),
+        };
+        let wireguard_extra_allowed_ips = overrides
+            .wireguard_extra_allowed_ips
+            .iter()
+            .map(|network| network.parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid allowed IP network"))?;
+        Ok(Self {
+            wireguard_port,
+            wireguard_extra_allowed_ips,
+        })
+    }
+}
+
+impl TryFrom<FirewallException> for talpid_types::net::AllowedEndpoint {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(exception: FirewallException) -> Result<Self, Self::Error> {
+        let network = exception
+            .network
+            .parse()
+            .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid network"))?;
+        let port = match exception.port {
+            0 => None,
+            port => Some(u16::try_from(port).map_err(|_| {
+                FromProtobufTypeError::InvalidArgument("invalid firewall exception port")
+            })?),
+        };
+        let protocol = exception
+            .protocol
+            .map(|constraint| try_transport_protocol_from_i32(constraint.protocol))
+            .transpose()?;
+        Ok(Self::new(network, port, protocol))
+    }
+}
+
+impl TryFrom<NetworkAction> for mullvad_types::network_rules::NetworkAction {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(action: NetworkAction) -> Result<Self, Self::Error> {
+        use mullvad_types::network_rules::NetworkAction as MullvadAction;
+        match network_action::Action::from_i32(action.action) {
+            Some(network_action::Action::Connect) => Ok(MullvadAction::Connect),
+            Some(network_action::Action::Disconnect) => Ok(MullvadAction::Disconnect),
+            None => Err(FromProtobufTypeError::InvalidArgument(
+                "invalid network action",
+            )),
+        }
+    }
+}
+
+impl TryFrom<NetworkMatch> for mullvad_types::network_rules::NetworkMatch {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(network: NetworkMatch) -> Result<Self, Self::Error> {
+        use mullvad_types::network_rules::NetworkMatch as MullvadMatch;
+        match network.network {
+            Some(network_match::Network::Ssid(ssid)) => Ok(MullvadMatch::Ssid(ssid)),
+            Some(network_match::Network::Subnet(subnet)) => subnet
+                .parse()
+                .map(MullvadMatch::Subnet)
+                .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid subnet")),
+            Some(network_match::Network::Gateway(hash)) => hash
+                .parse()
+                .map(MullvadMatch::Gateway)
+                .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid gateway hash")),
+            None => Err(FromProtobufTypeError::InvalidArgument(
+                "missing SSID, subnet or gateway",
+            )),
+        }
+    }
+}
+
+impl TryFrom<NetworkRule> for mullvad_types::network_rules::NetworkRule {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(rule: NetworkRule) -> Result<Self, Self::Error> {
+        let network = rule
+            .network
+            .ok_or(FromProtobufTypeError::InvalidArgument("missing network"))?;
+        let action = rule.action.ok_or(FromProtobufTypeError::InvalidArgument(
+            "missing network action",
+        ))?;
+        Ok(Self {
+            network: mullvad_types::network_rules::NetworkMatch::try_from(network)?,
+            action: mullvad_types::network_rules::NetworkAction::try_from(action)?,
+        })
+    }
+}
+
+impl TryFrom<ObfuscationSettings> for mullvad_types::obfuscation::ObfuscationSettings {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(settings: ObfuscationSettings) -> Result<Self, Self::Error> {
+        use mullvad_types::obfuscation::{
+            CustomObfuscationSettings, ObfuscationMode, ObfuscationSettings,
+            QuicObfuscationSettings, ShadowsocksObfuscationSettings, Udp2TcpObfuscationSettings,
+        };
+
+        let mode = match obfuscation_settings::Mode::from_i32(settings.mode) {
+            Some(obfuscation_settings::Mode::Auto) => ObfuscationMode::Auto,
+            Some(obfuscation_settings::Mode::Off) => ObfuscationMode::Off,
+            Some(obfuscation_settings::Mode::Udp2tcp) => ObfuscationMode::Udp2Tcp,
+            Some(obfuscation_settings::Mode::Custom) => ObfuscationMode::Custom,
+            Some(obfuscation_settings::Mode::Quic) => ObfuscationMode::Quic,
+            Some(obfuscation_settings::Mode::Shadowsocks) => ObfuscationMode::Shadowsocks,
+            None => {
+                return Err(FromProtobufTypeError::InvalidArgument(
+                    "invalid obfuscation mode",
+                ))
+            }
+        };
+
+        let port = settings.udp2tcp.map(|udp2tcp| udp2tcp.port).unwrap_or(0);
+        let port = match port {
+            0 => Constraint::Any,
+            port => Constraint::Only(
+                u16::try_from(port)
+                    .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid udp2tcp port"))?,
+            ),
+        };
+
+        let shadowsocks_port = settings
+            .shadowsocks
+            .map(|shadowsocks| shadowsocks.port)
+            .unwrap_or(0);
+        let shadowsocks_port =
+            match shadowsocks_port {
+                0 => Constraint::Any,
+                port => Constraint::Only(u16::try_from(port).map_err(|_| {
+                    FromProtobufTypeError::InvalidArgument("invalid Shadowsocks port")
+                })?),
+            };
+
+        let custom = match settings.custom {
+            Some(custom) if !custom.endpoint.is_empty() => Some(CustomObfuscationSettings {
+                endpoint: custom.endpoint.parse().map_err(|_| {
+                    FromProtobufTypeError::InvalidArgument("invalid custom obfuscation endpoint")
+                })?,
+            }),
+            _ => None,
+        };
+
+        let quic = match settings.quic {
+            Some(quic) if !quic.endpoint.is_empty() => Some(QuicObfuscationSettings {
+                endpoint: quic.endpoint.parse().map_err(|_| {
+                    FromProtobufTypeError::InvalidArgument("invalid QUIC obfuscation endpoint")
+                })?,
+                server_name: quic.server_name,
+            }),
+            _ => None,
+        };
+
+        Ok(ObfuscationSettings {
+            mode,
+            udp2tcp: Udp2TcpObfuscationSettings { port },
+            shadowsocks: ShadowsocksObfuscationSettings {
+                port: shadowsocks_port,
+            },
+            custom,
+            quic,
+        })
+    }
+}
+
 impl TryFrom<TunnelOptions> for mullvad_types::settings::TunnelOptions {
     type Error = FromProtobufTypeError;
 
@@ -1195,6 +2583,14 @@ impl TryFrom<TunnelOptions> for mullvad_types::settings::TunnelOptions {
                 } else {
                     None
                 },
+                #[cfg(windows)]
+                use_ovpn_dco: openvpn_options.use_ovpn_dco,
+                #[cfg(windows)]
+                wintun_adapter_policy: openvpn_options
+                    .wintun_adapter_policy
+                    .map(net::openvpn::WintunAdapterPolicy::try_from)
+                    .transpose()?
+                    .unwrap_or_default(),
             },
             wireguard: mullvad_types::wireguard::TunnelOptions {
                 options: net::wireguard::TunnelOptions {
@@ -1203,8 +2599,20 @@ impl TryFrom<TunnelOptions> for mullvad_types::settings::TunnelOptions {
                     } else {
                         None
                     },
+                    icmp_check_interval: if wireguard_options.icmp_check_interval != 0 {
+                        Some(wireguard_options.icmp_check_interval as u16)
+                    } else {
+                        None
+                    },
+                    packet_loss_threshold: if wireguard_options.packet_loss_threshold != 0 {
+                        Some(wireguard_options.packet_loss_threshold as u8)
+                    } else {
+                        None
+                    },
                     #[cfg(windows)]
                     use_wireguard_nt: wireguard_options.use_wireguard_nt,
+                    #[cfg(target_os = "linux")]
+                    use_userspace_wireguard: wireguard_options.use_userspace_wireguard,
                 },
                 rotation_interval: wireguard_options
                     .rotation_interval
@@ -1220,9 +2628,21 @@ impl TryFrom<TunnelOptions> for mullvad_types::settings::TunnelOptions {
                         );
                         FromProtobufTypeError::InvalidArgument("invalid rotation interval")
                     })?,
+                quantum_resistant: wireguard_options
+                    .quantum_resistant
+                    .map(mullvad_types::wireguard::QuantumResistantState::try_from)
+                    .transpose()?
+                    .unwrap_or_default(),
             },
             generic: net::GenericTunnelOptions {
                 enable_ipv6: generic_options.enable_ipv6,
+                interface_name: Some(generic_options.interface_name)
+                    .filter(|name| !name.is_empty()),
+                ipv6_failure_policy: generic_options
+                    .ipv6_failure_policy
+                    .map(net::Ipv6FailurePolicy::try_from)
+                    .transpose()?
+                    .unwrap_or_default(),
             },
             #[cfg(not(target_os = "android"))]
             dns_options: mullvad_types::settings::DnsOptions::try_from(dns_options)?,
@@ -1237,12 +2657,17 @@ impl TryFrom<DnsOptions> for mullvad_types::settings::DnsOptions {
         use mullvad_types::settings::{
             CustomDnsOptions as MullvadCustomDnsOptions,
             DefaultDnsOptions as MullvadDefaultDnsOptions, DnsOptions as MullvadDnsOptions,
-            DnsState as MullvadDnsState,
+            DnsServerKind, DnsState as MullvadDnsState,
+            EncryptedDnsOptions as MullvadEncryptedDnsOptions, EncryptedDnsProtocol,
+            EncryptedDnsServer as MullvadEncryptedDnsServer,
+            LocalDnsForwarderOptions as MullvadLocalDnsForwarderOptions,
         };
 
         let state = match dns_options::DnsState::from_i32(options.state) {
             Some(dns_options::DnsState::Default) => MullvadDnsState::Default,
             Some(dns_options::DnsState::Custom) => MullvadDnsState::Custom,
+            Some(dns_options::DnsState::Encrypted) => MullvadDnsState::Encrypted,
+            Some(dns_options::DnsState::LocalForwarder) => MullvadDnsState::LocalForwarder,
             None => {
                 return Err(FromProtobufTypeError::InvalidArgument(
                     "invalid DNS options state",
@@ -1273,17 +2698,98 @@ impl TryFrom<DnsOptions> for mullvad_types::settings::DnsOptions {
                 addresses: custom_options
                     .addresses
                     .into_iter()
-                    .map(|addr| {
-                        addr.parse().map_err(|_| {
-                            FromProtobufTypeError::InvalidArgument("invalid IP address")
+                    .map(|addr| dns_server_address_from_str(&addr))
+                    .collect::<Result<Vec<_>, _>>()?,
+            },
+            encrypted_options: MullvadEncryptedDnsOptions {
+                server: options
+                    .encrypted_options
+                    .and_then(|options| options.server)
+                    .map(|server| {
+                        let protocol =
+                            match encrypted_dns_server::Protocol::from_i32(server.protocol) {
+                                Some(encrypted_dns_server::Protocol::Tls) => {
+                                    EncryptedDnsProtocol::Tls
+                                }
+                                Some(encrypted_dns_server::Protocol::Https) => {
+                                    EncryptedDnsProtocol::Https
+                                }
+                                None => {
+                                    return Err(FromProtobufTypeError::InvalidArgument(
+                                        "invalid encrypted DNS protocol",
+                                    ))
+                                }
+                            };
+                        if server.hostname.is_empty() {
+                            return Err(FromProtobufTypeError::InvalidArgument(
+                                "missing encrypted DNS server hostname",
+                            ));
+                        }
+                        let address = dns_server_address_from_str(&server.address)?;
+                        // The proxy would forward queries to itself
+                        if address.kind() == DnsServerKind::Loopback {
+                            return Err(FromProtobufTypeError::InvalidArgument(
+                                "a loopback address cannot be an encrypted DNS server",
+                            ));
+                        }
+                        Ok(MullvadEncryptedDnsServer {
+                            protocol,
+                            address,
+                            hostname: server.hostname,
                         })
                     })
-                    .collect::<Result<Vec<_>, _>>()?,
+                    .transpose()?,
+            },
+            local_forwarder_options: match options.local_forwarder_options {
+                Some(forwarder_options) => {
+                    let address = if forwarder_options.address.is_empty() {
+                        None
+                    } else {
+                        let address = dns_server_address_from_str(&forwarder_options.address)?;
+                        // Forwarders on other hosts are used as custom DNS servers
+                        if address.kind() != DnsServerKind::Loopback {
+                            return Err(FromProtobufTypeError::InvalidArgument(
+                                "a local DNS forwarder must have a loopback address",
+                            ));
+                        }
+                        Some(address)
+                    };
+                    MullvadLocalDnsForwarderOptions {
+                        address,
+                        upstream_servers: forwarder_options
+                            .upstream_servers
+                            .into_iter()
+                            .map(|addr| dns_server_address_from_str(&addr))
+                            .collect::<Result<Vec<_>, _>>()?,
+                    }
+                }
+                None => MullvadLocalDnsForwarderOptions::default(),
             },
         })
     }
 }
 
+fn dns_server_address_from_str(
+    addr: &str,
+) -> Result<mullvad_types::settings::DnsServerAddress, FromProtobufTypeError> {
+    use mullvad_types::settings::{DnsServerAddress, InvalidDnsServer};
+
+    let addr: std::net::IpAddr = addr
+        .parse()
+        .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid IP address"))?;
+    DnsServerAddress::try_from(addr).map_err(|error| match error {
+        InvalidDnsServer::Unspecified(_) => {
+            FromProtobufTypeError::InvalidArgument("the unspecified address cannot be a DNS server")
+        }
+        InvalidDnsServer::Broadcast(_) => {
+            FromProtobufTypeError::InvalidArgument("a broadcast address cannot be a DNS server")
+        }
+        InvalidDnsServer::Multicast(_) => {
+            FromProtobufTypeError::InvalidArgument("a multicast address cannot be a DNS server")
+        }
+    })
+}
+
 impl TryFrom<TransportPort> for mullvad_types::relay_constraints::TransportPort {
     type Error = FromProtobufTypeError;
 
@@ -1323,6 +2829,18 @@ pub fn try_providers_constraint_from_proto(
     }
 }
 
+pub fn try_relay_filter_from_proto(
+    filter: &str,
+) -> Result<Constraint<mullvad_types::relay_filter::RelayFilter>, FromProtobufTypeError> {
+    if filter.is_empty() {
+        return Ok(Constraint::Any);
+    }
+    filter
+        .parse()
+        .map(Constraint::Only)
+        .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid relay filter expression"))
+}
+
 fn convert_providers_constraint(
     providers: &Constraint<mullvad_types::relay_constraints::Providers>,
 ) -> Vec<String> {
@@ -1332,6 +2850,95 @@ fn convert_providers_constraint(
     }
 }
 
+impl fmt::Display for ApiTraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let timestamp = self.timestamp.as_ref().and_then(|timestamp| {
+            chrono::NaiveDateTime::from_timestamp_opt(timestamp.seconds, timestamp.nanos as u32)
+        });
+        match timestamp {
+            Some(timestamp) => write!(f, "{}", timestamp.format("%Y-%m-%d %H:%M:%S%.3f"))?,
+            None => write!(f, "-")?,
+        }
+        let duration_ms = self
+            .duration
+            .as_ref()
+            .map(|duration| duration.seconds * 1000 + i64::from(duration.nanos) / 1_000_000)
+            .unwrap_or_default();
+        write!(
+            f,
+            " {} {} -> {} ({} ms)",
+            self.method, self.path, self.outcome, duration_ms
+        )?;
+        if self.retried {
+            write!(f, " (retried on a new connection)")?;
+        }
+        Ok(())
+    }
+}
+
+impl ApiError {
+    /// Returns a status with this error encoded in its details.
+    pub fn into_status(self, code: crate::Code, message: impl Into<String>) -> crate::Status {
+        crate::Status::with_details(code, message, prost::Message::encode_to_vec(&self).into())
+    }
+
+    /// Decodes an API error from the details of a status, if there is one.
+    pub fn from_status(status: &crate::Status) -> Option<Self> {
+        if status.details().is_empty() {
+            return None;
+        }
+        <Self as prost::Message>::decode(status.details()).ok()
+    }
+}
+
+impl TryFrom<ApiAccessMethod> for mullvad_types::api_access::ApiAccessMethod {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(method: ApiAccessMethod) -> Result<Self, Self::Error> {
+        if method.name.is_empty() {
+            return Err(FromProtobufTypeError::InvalidArgument(
+                "missing access method name",
+            ));
+        }
+        let proxy = method
+            .proxy
+            .ok_or(FromProtobufTypeError::InvalidArgument("missing proxy"))?;
+        Ok(Self {
+            name: method.name,
+            enabled: method.enabled,
+            proxy: mullvad_types::api_access::ApiProxy::try_from(proxy)?,
+        })
+    }
+}
+
+impl TryFrom<ApiProxy> for mullvad_types::api_access::ApiProxy {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(proxy: ApiProxy) -> Result<Self, Self::Error> {
+        use mullvad_types::api_access::{ApiProxy as MullvadProxy, ProxyAuth};
+
+        let address = proxy
+            .address
+            .parse()
+            .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid proxy address"))?;
+        let auth = if proxy.username.is_empty() {
+            None
+        } else {
+            Some(ProxyAuth {
+                username: proxy.username,
+                password: proxy.password,
+            })
+        };
+        match api_proxy::ProxyType::from_i32(proxy.r#type) {
+            Some(api_proxy::ProxyType::Socks5) => Ok(MullvadProxy::Socks5 { address, auth }),
+            Some(api_proxy::ProxyType::HttpConnect) => {
+                Ok(MullvadProxy::HttpConnect { address, auth })
+            }
+            None => Err(FromProtobufTypeError::InvalidArgument("invalid proxy type")),
+        }
+    }
+}
+
 impl From<FromProtobufTypeError> for crate::Status {
     fn from(err: FromProtobufTypeError) -> Self {
         match err {
@@ -1,26 +1,42 @@
 #![deny(rust_2018_idioms)]
 
-use lazy_static::lazy_static;
-use regex::Regex;
 use std::{
-    borrow::Cow,
     cmp::min,
     collections::{BTreeMap, HashSet},
     ffi::OsStr,
     fs::{self, File},
     io::{self, BufWriter, Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    thread,
 };
 use talpid_types::ErrorExt;
 
 
+pub mod manifest;
 pub mod metadata;
+#[cfg(not(target_os = "android"))]
+mod network_snapshot;
+pub mod redact;
 
 /// Maximum number of bytes to read from each log file
 const LOG_MAX_READ_BYTES: usize = 128 * 1024;
+/// Maximum number of bytes to read from all log files combined. Logs added first get their share
+/// of the budget first.
+const LOGS_MAX_TOTAL_BYTES: usize = 5 * LOG_MAX_READ_BYTES;
+/// Maximum number of threads used to read and redact log files.
+const MAX_LOG_READ_THREADS: usize = 4;
 const EXTRA_BYTES: usize = 32 * 1024;
-/// Fit five logs plus some system information in the report.
-const REPORT_MAX_SIZE: usize = (5 * LOG_MAX_READ_BYTES) + EXTRA_BYTES;
+/// Fit the logs plus some system information and the network snapshot in the report.
+#[cfg(not(target_os = "android"))]
+const REPORT_MAX_SIZE: usize =
+    LOGS_MAX_TOTAL_BYTES + EXTRA_BYTES + network_snapshot::MAX_SNAPSHOT_BYTES;
+/// Fit the logs plus some system information in the report.
+#[cfg(target_os = "android")]
+const REPORT_MAX_SIZE: usize = LOGS_MAX_TOTAL_BYTES + EXTRA_BYTES;
 
 
 /// Field delimeter in generated problem report
@@ -63,6 +79,38 @@ pub enum Error {
         source: io::Error,
     },
 
+    #[cfg(not(target_os = "android"))]
+    #[error(display = "Unable to connect to the daemon")]
+    DaemonConnectionError(#[error(source)] mullvad_management_interface::Error),
+
+    #[cfg(not(target_os = "android"))]
+    #[error(display = "The daemon failed to send the problem report")]
+    DaemonSendError(#[error(source)] mullvad_management_interface::Status),
+
+    #[cfg(not(target_os = "android"))]
+    #[error(display = "The daemon failed to collect the problem report")]
+    DaemonCollectError(#[error(source)] mullvad_management_interface::Status),
+
+    #[cfg(not(target_os = "android"))]
+    #[error(display = "Failed to manage the problem reports that are waiting to be sent")]
+    PendingReportsError(#[error(source)] mullvad_management_interface::Status),
+
+    #[cfg(not(target_os = "android"))]
+    #[error(display = "Failed to obtain the API trace from the daemon")]
+    GetApiTraceError(#[error(source)] mullvad_management_interface::Status),
+
+    #[cfg(not(target_os = "android"))]
+    #[error(display = "Failed to obtain the recent connection attempts from the daemon")]
+    GetConnectionAttemptsError(#[error(source)] mullvad_management_interface::Status),
+
+    #[cfg(not(target_os = "android"))]
+    #[error(display = "Failed to obtain the account numbers from the daemon")]
+    GetAccountNumbersError(#[error(source)] mullvad_management_interface::Status),
+
+    #[cfg(not(target_os = "android"))]
+    #[error(display = "Failed to obtain diagnostics from the daemon")]
+    GetDiagnosticsError(#[error(source)] mullvad_management_interface::Status),
+
     #[error(display = "Unable to create REST client")]
     CreateRpcClientError(#[error(source)] mullvad_rpc::Error),
 
@@ -106,11 +154,39 @@ pub enum LogError {
 pub fn collect_report(
     extra_logs: &[&Path],
     output_path: &Path,
-    redact_custom_strings: Vec<String>,
+    #[cfg_attr(target_os = "android", allow(unused_mut))] mut redact_custom_strings: Vec<String>,
     #[cfg(target_os = "android")] android_log_dir: &Path,
 ) -> Result<(), Error> {
+    // The frontends may not know the account number, for example in account privacy mode
+    #[cfg(not(target_os = "android"))]
+    let account_numbers_error = match collect_account_numbers() {
+        Ok(account_numbers) => {
+            redact_custom_strings.extend(account_numbers);
+            None
+        }
+        Err(Error::DaemonConnectionError(_)) => None,
+        Err(error) => Some(error),
+    };
     let mut problem_report = ProblemReport::new(redact_custom_strings);
 
+    #[cfg(not(target_os = "android"))]
+    let daemon_diagnostics = match collect_diagnostics() {
+        Ok(diagnostics) => Some(diagnostics),
+        Err(Error::DaemonConnectionError(_)) => None,
+        Err(error) => {
+            problem_report.add_error("Failed to collect diagnostics", &error);
+            None
+        }
+    };
+    #[cfg(target_os = "android")]
+    let daemon_diagnostics = None;
+    problem_report.add_manifest(manifest::build(daemon_diagnostics));
+
+    #[cfg(not(target_os = "android"))]
+    if let Some(error) = account_numbers_error {
+        problem_report.add_error("Failed to collect the account numbers to redact", &error);
+    }
+
     let daemon_logs_dir = {
         #[cfg(target_os = "android")]
         {
@@ -121,31 +197,7 @@ pub fn collect_report(
             mullvad_paths::get_log_dir().map_err(LogError::GetLogDir)
         }
     };
-
-    let daemon_logs = daemon_logs_dir.and_then(list_logs);
-    match daemon_logs {
-        Ok(daemon_logs) => {
-            let mut other_logs = Vec::new();
-            for log in daemon_logs {
-                match log {
-                    Ok(path) => {
-                        if is_tunnel_log(&path) {
-                            problem_report.add_log(&path);
-                        } else {
-                            other_logs.push(path);
-                        }
-                    }
-                    Err(error) => problem_report.add_error("Unable to get log path", &error),
-                }
-            }
-            for other_log in other_logs {
-                problem_report.add_log(&other_log);
-            }
-        }
-        Err(error) => {
-            problem_report.add_error("Failed to list logs in daemon log directory", &error)
-        }
-    };
+    add_daemon_logs(&mut problem_report, daemon_logs_dir);
     match frontend_log_dir().map(|dir| dir.and_then(list_logs)) {
         Some(Ok(frontend_logs)) => {
             for log in frontend_logs {
@@ -160,6 +212,20 @@ pub fn collect_report(
         }
         None => {}
     }
+    #[cfg(not(target_os = "android"))]
+    match collect_api_trace() {
+        Ok(Some(trace)) => problem_report.add_section("API trace", &trace),
+        Ok(None) | Err(Error::DaemonConnectionError(_)) => (),
+        Err(error) => problem_report.add_error("Failed to collect API trace", &error),
+    }
+    #[cfg(not(target_os = "android"))]
+    match collect_connection_attempts() {
+        Ok(Some(attempts)) => problem_report.add_section("Connection attempts", &attempts),
+        Ok(None) | Err(Error::DaemonConnectionError(_)) => (),
+        Err(error) => problem_report.add_error("Failed to collect connection attempts", &error),
+    }
+    #[cfg(not(target_os = "android"))]
+    add_network_state(&mut problem_report);
     #[cfg(target_os = "android")]
     match write_logcat_to_file(android_log_dir) {
         Ok(logcat_path) => problem_report.add_log(&logcat_path),
@@ -167,6 +233,49 @@ pub fn collect_report(
     }
 
     problem_report.add_logs(extra_logs);
+    problem_report.read_logs();
+
+    write_problem_report(&output_path, &problem_report).map_err(|source| Error::WriteReportError {
+        path: output_path.display().to_string(),
+        source,
+    })
+}
+
+/// Information that only the daemon has. The tool obtains it over the management interface, but
+/// the daemon passes it in directly when it collects a report itself.
+#[cfg(not(target_os = "android"))]
+#[derive(Debug, Default)]
+pub struct DaemonState {
+    /// Account numbers and device names, which are redacted from the report.
+    pub account_numbers: Vec<String>,
+    /// Manifest fields that describe the settings and the recent tunnel states.
+    pub diagnostics: serde_json::Map<String, serde_json::Value>,
+    /// Additional sections, such as the firewall rules, as pairs of titles and contents.
+    pub sections: Vec<(String, String)>,
+}
+
+/// Collects a problem report from within the daemon, using the given state instead of querying
+/// the daemon. Frontend logs are not included, since they belong to the user running the
+/// frontend.
+#[cfg(not(target_os = "android"))]
+pub fn collect_daemon_report(
+    daemon_state: DaemonState,
+    output_path: &Path,
+    mut redact_custom_strings: Vec<String>,
+) -> Result<(), Error> {
+    redact_custom_strings.extend(daemon_state.account_numbers);
+    let mut problem_report = ProblemReport::new(redact_custom_strings);
+    problem_report.add_manifest(manifest::build(Some(daemon_state.diagnostics)));
+
+    add_daemon_logs(
+        &mut problem_report,
+        mullvad_paths::get_log_dir().map_err(LogError::GetLogDir),
+    );
+    for (title, content) in daemon_state.sections {
+        problem_report.add_section(&title, &content);
+    }
+    add_network_state(&mut problem_report);
+    problem_report.read_logs();
 
     write_problem_report(&output_path, &problem_report).map_err(|source| Error::WriteReportError {
         path: output_path.display().to_string(),
@@ -174,6 +283,84 @@ pub fn collect_report(
     })
 }
 
+/// Asks the daemon to collect a problem report, which includes the state that only the daemon
+/// has, and copies it to `output_path`.
+#[cfg(not(target_os = "android"))]
+pub fn collect_report_through_daemon(
+    output_path: &Path,
+    redact_custom_strings: Vec<String>,
+) -> Result<(), Error> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+        .map_err(Error::CreateRuntime)?;
+
+    let report_path = runtime.block_on(async {
+        let mut rpc = mullvad_management_interface::new_rpc_client()
+            .await
+            .map_err(Error::DaemonConnectionError)?;
+        rpc.collect_problem_report(
+            mullvad_management_interface::types::ProblemReportCollection {
+                redact: redact_custom_strings,
+            },
+        )
+        .await
+        .map_err(Error::DaemonCollectError)
+        .map(|response| PathBuf::from(response.into_inner()))
+    })?;
+
+    fs::copy(&report_path, output_path)
+        .map(|_| ())
+        .map_err(|source| Error::WriteReportError {
+            path: output_path.display().to_string(),
+            source,
+        })
+}
+
+/// Adds the logs in the daemon log directory, with the tunnel logs first.
+fn add_daemon_logs(problem_report: &mut ProblemReport, log_dir: Result<PathBuf, LogError>) {
+    match log_dir.and_then(list_logs) {
+        Ok(daemon_logs) => {
+            let mut other_logs = Vec::new();
+            for log in daemon_logs {
+                match log {
+                    Ok(path) => {
+                        if is_tunnel_log(&path) {
+                            problem_report.add_log(&path);
+                        } else {
+                            other_logs.push(path);
+                        }
+                    }
+                    Err(error) => problem_report.add_error("Unable to get log path", &error),
+                }
+            }
+            for other_log in other_logs {
+                problem_report.add_log(&other_log);
+            }
+        }
+        Err(error) => {
+            problem_report.add_error("Failed to list logs in daemon log directory", &error)
+        }
+    };
+}
+
+/// Adds the network configuration of the host and the Wi-Fi connections.
+#[cfg(not(target_os = "android"))]
+fn add_network_state(problem_report: &mut ProblemReport) {
+    for (title, output) in network_snapshot::collect() {
+        match output {
+            Ok(output) => problem_report.add_section(&title, &output),
+            Err(error) => problem_report.add_error("Failed to collect network snapshot", &error),
+        }
+    }
+    match talpid_platform_metadata::wifi::current_connections() {
+        Ok(connections) if connections.is_empty() => (),
+        Ok(connections) => problem_report.add_section("Wi-Fi", &format_wifi(&connections)),
+        Err(error) => problem_report.add_error("Failed to collect Wi-Fi information", &error),
+    }
+}
+
 /// Returns an iterator over all files in the given directory that has the `.log` extension.
 fn list_logs(
     log_dir: PathBuf,
@@ -254,12 +441,8 @@ fn write_logcat_to_file(log_dir: &Path) -> Result<PathBuf, io::Error> {
         .map(|_| logcat_path)
 }
 
-pub fn send_problem_report(
-    user_email: &str,
-    user_message: &str,
-    report_path: &Path,
-    cache_dir: &Path,
-) -> Result<(), Error> {
+/// Reads a collected report and the metadata that is sent along with it.
+fn read_problem_report(report_path: &Path) -> Result<(String, BTreeMap<String, String>), Error> {
     let report_content = normalize_newlines(
         read_file_lossy(report_path, REPORT_MAX_SIZE).map_err(|source| {
             Error::ReadProblemReportError {
@@ -270,6 +453,35 @@ pub fn send_problem_report(
     );
     let metadata =
         ProblemReport::parse_metadata(&report_content).unwrap_or_else(|| metadata::collect());
+    Ok((report_content, metadata))
+}
+
+/// Prints exactly what `send_problem_report` would send, without sending anything.
+pub fn preview_problem_report(
+    user_email: &str,
+    user_message: &str,
+    report_path: &Path,
+) -> Result<(), Error> {
+    let (report_content, metadata) = read_problem_report(report_path)?;
+
+    println!("Email: {}", user_email);
+    println!("Message: {}", user_message);
+    println!("Metadata:");
+    for (key, value) in &metadata {
+        println!("  {}: {}", key, value);
+    }
+    println!("Report:");
+    println!("{}", report_content);
+    Ok(())
+}
+
+pub fn send_problem_report(
+    user_email: &str,
+    user_message: &str,
+    report_path: &Path,
+    cache_dir: &Path,
+) -> Result<(), Error> {
+    let (report_content, metadata) = read_problem_report(report_path)?;
 
     let runtime = tokio::runtime::Builder::new_multi_thread()
         .worker_threads(2)
@@ -277,6 +489,34 @@ pub fn send_problem_report(
         .build()
         .map_err(Error::CreateRuntime)?;
 
+    // Prefer letting the daemon send the report. It reaches the API the same way as it does for
+    // any other request, which works in blocking states and through the tunnel.
+    #[cfg(not(target_os = "android"))]
+    match runtime.block_on(send_problem_report_through_daemon(
+        user_email,
+        user_message,
+        &report_content,
+        &metadata,
+    )) {
+        Ok(submission) if submission.queued => {
+            println!(
+                "The API could not be reached. The problem report will be sent when it can be \
+                 reached again (ID {}).",
+                submission.id
+            );
+            return Ok(());
+        }
+        Ok(_) => {
+            println!("Problem report sent.");
+            return Ok(());
+        }
+        Err(Error::DaemonConnectionError(_)) => (),
+        Err(error) => eprintln!(
+            "{}",
+            error.display_chain_with_msg("Failed to send problem report through the daemon")
+        ),
+    }
+
     let mut rpc_manager = runtime
         .block_on(mullvad_rpc::MullvadRpcRuntime::with_cache(
             runtime.handle().clone(),
@@ -290,10 +530,18 @@ pub fn send_problem_report(
         .map_err(Error::CreateRpcClientError)?;
     let rpc_client = mullvad_rpc::ProblemReportProxy::new(rpc_manager.mullvad_rest_handle());
 
+    // Reuse the token across attempts so that a report is not submitted twice if a response is lost
+    let idempotency_token = mullvad_rpc::rest::IdempotencyToken::new();
     runtime.block_on(async move {
         for _attempt in 0..MAX_SEND_ATTEMPTS {
             match rpc_client
-                .problem_report(user_email, user_message, &report_content, &metadata)
+                .problem_report(
+                    user_email,
+                    user_message,
+                    &report_content,
+                    &metadata,
+                    idempotency_token.clone(),
+                )
                 .await
             {
                 Ok(()) => {
@@ -315,6 +563,297 @@ pub fn send_problem_report(
     })
 }
 
+#[cfg(not(target_os = "android"))]
+async fn send_problem_report_through_daemon(
+    user_email: &str,
+    user_message: &str,
+    report_content: &str,
+    metadata: &BTreeMap<String, String>,
+) -> Result<mullvad_management_interface::types::ProblemReportSubmission, Error> {
+    let mut rpc = mullvad_management_interface::new_rpc_client()
+        .await
+        .map_err(Error::DaemonConnectionError)?;
+    let submission = rpc
+        .submit_problem_report(mullvad_management_interface::types::ProblemReport {
+            email: user_email.to_owned(),
+            message: user_message.to_owned(),
+            log: report_content.to_owned(),
+            metadata: metadata
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        })
+        .await
+        .map_err(Error::DaemonSendError)?;
+    Ok(submission.into_inner())
+}
+
+/// Prints the problem reports that the daemon is waiting to send.
+#[cfg(not(target_os = "android"))]
+pub fn list_pending_problem_reports() -> Result<(), Error> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+        .map_err(Error::CreateRuntime)?;
+
+    runtime.block_on(async {
+        let mut rpc = mullvad_management_interface::new_rpc_client()
+            .await
+            .map_err(Error::DaemonConnectionError)?;
+        let reports = rpc
+            .get_pending_problem_reports(())
+            .await
+            .map_err(Error::PendingReportsError)?
+            .into_inner()
+            .reports;
+        if reports.is_empty() {
+            println!("No problem reports are waiting to be sent");
+        }
+        for report in reports {
+            println!("{}", report.id);
+            if let Some(created) = report.created {
+                let now = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|now| now.as_secs() as i64)
+                    .unwrap_or(created.seconds);
+                let minutes = (now - created.seconds).max(0) / 60;
+                println!("  Created: {} minutes ago", minutes);
+            }
+            println!("  Size: {} bytes", report.size);
+            println!("  Failed attempts: {}", report.attempts);
+            if !report.last_error.is_empty() {
+                println!("  Last error: {}", report.last_error);
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Removes a problem report that the daemon is waiting to send.
+#[cfg(not(target_os = "android"))]
+pub fn cancel_pending_problem_report(id: &str) -> Result<(), Error> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+        .map_err(Error::CreateRuntime)?;
+
+    runtime.block_on(async {
+        let mut rpc = mullvad_management_interface::new_rpc_client()
+            .await
+            .map_err(Error::DaemonConnectionError)?;
+        rpc.cancel_pending_problem_report(id.to_owned())
+            .await
+            .map_err(Error::PendingReportsError)?;
+        println!("Problem report {} will not be sent", id);
+        Ok(())
+    })
+}
+
+/// Returns the current account number, the accounts in the history and the names of the devices
+/// on the account, so that they can be redacted even if they are formatted in an unexpected way.
+#[cfg(not(target_os = "android"))]
+fn collect_account_numbers() -> Result<Vec<String>, Error> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+        .map_err(Error::CreateRuntime)?;
+
+    runtime.block_on(async {
+        let mut rpc = mullvad_management_interface::new_rpc_client()
+            .await
+            .map_err(Error::DaemonConnectionError)?;
+        let settings = rpc
+            .get_settings(())
+            .await
+            .map_err(Error::GetAccountNumbersError)?
+            .into_inner();
+        let history = rpc
+            .get_account_history(())
+            .await
+            .map_err(Error::GetAccountNumbersError)?
+            .into_inner();
+        let mut account_numbers = history.accounts;
+        account_numbers.push(settings.account_token);
+        // The device list requires an API request, so it is not an error if it is unavailable
+        if let Ok(devices) = rpc.list_devices(()).await {
+            account_numbers.extend(
+                devices
+                    .into_inner()
+                    .devices
+                    .into_iter()
+                    .map(|device| device.name),
+            );
+        }
+        Ok(account_numbers)
+    })
+}
+
+/// Returns the enabled features, the redacted settings and the recent tunnel state transitions
+/// as manifest fields.
+#[cfg(not(target_os = "android"))]
+fn collect_diagnostics() -> Result<serde_json::Map<String, serde_json::Value>, Error> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+        .map_err(Error::CreateRuntime)?;
+
+    let diagnostics = runtime.block_on(async {
+        let mut rpc = mullvad_management_interface::new_rpc_client()
+            .await
+            .map_err(Error::DaemonConnectionError)?;
+        rpc.get_diagnostics(())
+            .await
+            .map_err(Error::GetDiagnosticsError)
+            .map(|response| response.into_inner())
+    })?;
+
+    let state_transitions = diagnostics
+        .state_transitions
+        .into_iter()
+        .map(|transition| {
+            serde_json::json!({
+                "timestamp": transition.timestamp.unwrap_or_default().seconds,
+                "state": transition.state,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut fields = serde_json::Map::new();
+    fields.insert(
+        "enabled_features".to_owned(),
+        serde_json::json!(diagnostics.enabled_features),
+    );
+    fields.insert(
+        "settings".to_owned(),
+        serde_json::from_str(&diagnostics.settings).unwrap_or(serde_json::Value::Null),
+    );
+    fields.insert(
+        "state_transitions".to_owned(),
+        serde_json::Value::Array(state_transitions),
+    );
+    fields.insert(
+        "read_only_settings".to_owned(),
+        serde_json::Value::Bool(diagnostics.read_only_settings),
+    );
+    let network = Some(diagnostics.network).filter(|network| !network.is_empty());
+    fields.insert("network".to_owned(), serde_json::json!(network));
+    Ok(fields)
+}
+
+/// Returns the API requests recorded by the daemon, if API tracing is enabled.
+#[cfg(not(target_os = "android"))]
+/// Describes the Wi-Fi connections without identifying the networks. The SSID is left out and
+/// only the vendor part (OUI) of the BSSID is kept.
+#[cfg(not(target_os = "android"))]
+fn format_wifi(connections: &[talpid_platform_metadata::wifi::WifiConnection]) -> String {
+    let mut output = String::new();
+    for connection in connections {
+        let vendor = connection
+            .bssid
+            .map(|bssid| {
+                talpid_platform_metadata::wifi::format_bssid(&bssid)[..8].to_owned() + ":xx:xx:xx"
+            })
+            .unwrap_or_else(|| "unknown".to_owned());
+        output.push_str(&format!(
+            "{}: security: {}, access point: {}\n",
+            connection.interface, connection.security, vendor
+        ));
+    }
+    output
+}
+
+fn collect_api_trace() -> Result<Option<String>, Error> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+        .map_err(Error::CreateRuntime)?;
+
+    runtime.block_on(async {
+        let mut rpc = mullvad_management_interface::new_rpc_client()
+            .await
+            .map_err(Error::DaemonConnectionError)?;
+        let trace = rpc
+            .get_api_trace(())
+            .await
+            .map_err(Error::GetApiTraceError)?
+            .into_inner();
+        if !trace.enabled {
+            return Ok(None);
+        }
+
+        let lines: Vec<String> = trace.entries.iter().map(ToString::to_string).collect();
+        Ok(Some(lines.join("\n")))
+    })
+}
+
+/// Returns a timeline of the most recent connection attempts, or `None` if there are none.
+#[cfg(not(target_os = "android"))]
+fn collect_connection_attempts() -> Result<Option<String>, Error> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+        .map_err(Error::CreateRuntime)?;
+
+    runtime.block_on(async {
+        let mut rpc = mullvad_management_interface::new_rpc_client()
+            .await
+            .map_err(Error::DaemonConnectionError)?;
+        let attempts = rpc
+            .get_connection_attempts(())
+            .await
+            .map_err(Error::GetConnectionAttemptsError)?
+            .into_inner()
+            .attempts;
+        if attempts.is_empty() {
+            return Ok(None);
+        }
+
+        let as_millis = |duration: Option<mullvad_management_interface::types::Duration>| {
+            let duration = duration.unwrap_or_default();
+            duration.seconds * 1000 + i64::from(duration.nanos) / 1_000_000
+        };
+        let mut lines = vec![];
+        for attempt in attempts {
+            let timestamp = attempt.timestamp.unwrap_or_default();
+            let outcome = if attempt.outcome.is_empty() {
+                "in progress".to_owned()
+            } else {
+                format!(
+                    "{} after {} ms",
+                    attempt.outcome,
+                    as_millis(attempt.duration)
+                )
+            };
+            lines.push(format!(
+                "Attempt {} at {}.{:03}: {}",
+                attempt.id,
+                timestamp.seconds,
+                timestamp.nanos / 1_000_000,
+                outcome
+            ));
+            for span in attempt.spans {
+                let mut line = format!(
+                    "  +{} ms {} ({} ms)",
+                    as_millis(span.offset),
+                    span.phase,
+                    as_millis(span.duration)
+                );
+                if !span.error.is_empty() {
+                    line.push_str(&format!(" failed: {}", span.error));
+                }
+                lines.push(line);
+            }
+        }
+        Ok(Some(lines.join("\n")))
+    })
+}
+
 fn write_problem_report(path: &Path, problem_report: &ProblemReport) -> io::Result<()> {
     let file = File::create(path)?;
     let mut permissions = file.metadata()?.permissions();
@@ -329,25 +868,33 @@ struct ProblemReport {
     metadata: BTreeMap<String, String>,
     logs: Vec<(String, String)>,
     log_paths: HashSet<PathBuf>,
-    redact_custom_strings: Vec<String>,
+    pending_logs: Vec<PendingLog>,
+    redactor: Arc<redact::Redactor>,
+}
+
+/// A log file that has been added to the report but not read yet.
+#[derive(Debug)]
+struct PendingLog {
+    /// Index of the placeholder entry in `ProblemReport::logs`.
+    index: usize,
+    path: PathBuf,
+    expanded_path: PathBuf,
 }
 
 impl ProblemReport {
     /// Creates a new problem report with system information. Logs can be added with `add_log`.
     /// Logs will have all strings in `redact_custom_strings` removed from them.
-    pub fn new(mut redact_custom_strings: Vec<String>) -> Self {
-        redact_custom_strings.retain(|redact| !redact.is_empty());
-
+    pub fn new(redact_custom_strings: Vec<String>) -> Self {
         ProblemReport {
             metadata: metadata::collect(),
             logs: Vec::new(),
             log_paths: HashSet::new(),
-            redact_custom_strings,
+            pending_logs: Vec::new(),
+            redactor: Arc::new(redact::Redactor::new(redact_custom_strings)),
         }
     }
 
-    /// Attach some file logs to this report. This method adds the error chain instead of the log
-    /// contents if an error occurs while reading one of the log files.
+    /// Attach some file logs to this report. The files are read by `read_logs`.
     pub fn add_logs<I>(&mut self, paths: I)
     where
         I: IntoIterator,
@@ -358,87 +905,95 @@ impl ProblemReport {
         }
     }
 
-    /// Attach a file log to this report. This method adds the error chain instead of the log
-    /// contents if an error occurs while reading the log file.
+    /// Attach a file log to this report. The file is read by `read_logs`, which adds the error
+    /// chain instead of the log contents if an error occurs while reading the log file.
     pub fn add_log(&mut self, path: &Path) {
         let expanded_path = path.canonicalize().unwrap_or_else(|_| path.to_owned());
         if self.log_paths.insert(expanded_path.clone()) {
             let redacted_path = self.redact(&expanded_path.to_string_lossy());
-            let content = self.redact(&read_file_lossy(path, LOG_MAX_READ_BYTES).unwrap_or_else(
-                |error| {
-                    error.display_chain_with_msg(&format!(
-                        "Error reading the contents of log file: {}",
-                        expanded_path.display()
-                    ))
-                },
-            ));
-            self.logs.push((redacted_path, content));
+            self.pending_logs.push(PendingLog {
+                index: self.logs.len(),
+                path: path.to_owned(),
+                expanded_path: expanded_path.clone(),
+            });
+            self.logs.push((redacted_path, String::new()));
             println!("Adding {}", expanded_path.display());
         }
     }
 
-    /// Attach an error to the report.
-    pub fn add_error(&mut self, message: &'static str, error: &impl ErrorExt) {
-        let redacted_error = self.redact(&error.display_chain());
-        self.logs.push((message.to_string(), redacted_error));
-    }
+    /// Reads and redacts all logs added since the last call, using a few threads. Each log is
+    /// limited to the last `LOG_MAX_READ_BYTES` bytes, and all logs together to
+    /// `LOGS_MAX_TOTAL_BYTES`, in the order they were added.
+    pub fn read_logs(&mut self) {
+        let pending_logs = std::mem::take(&mut self.pending_logs);
+        let file_sizes: Vec<u64> = pending_logs
+            .iter()
+            .map(|log| {
+                fs::metadata(&log.path)
+                    .map(|metadata| metadata.len())
+                    .unwrap_or(0)
+            })
+            .collect();
+        let read_limits = log_read_limits(&file_sizes, LOG_MAX_READ_BYTES, LOGS_MAX_TOTAL_BYTES);
 
-    fn redact(&self, input: &str) -> String {
-        let out1 = Self::redact_account_number(input);
-        let out2 = Self::redact_home_dir(&out1);
-        let out3 = Self::redact_network_info(&out2);
-        let out4 = Self::redact_guids(&out3);
-        self.redact_custom_strings(&out4).to_string()
-    }
+        let mut jobs = Vec::with_capacity(pending_logs.len());
+        for ((log, file_size), max_bytes) in
+            pending_logs.into_iter().zip(file_sizes).zip(read_limits)
+        {
+            if max_bytes == 0 && file_size > 0 {
+                self.logs[log.index].1 = format!(
+                    "Not included, the size limit for all logs ({} bytes) was reached",
+                    LOGS_MAX_TOTAL_BYTES
+                );
+            } else {
+                jobs.push((log, max_bytes));
+            }
+        }
 
-    fn redact_account_number(input: &str) -> Cow<'_, str> {
-        lazy_static! {
-            static ref RE: Regex = Regex::new("\\d{16}").unwrap();
+        for (index, content) in read_logs_parallel(jobs, &self.redactor) {
+            self.logs[index].1 = content;
         }
-        RE.replace_all(input, "[REDACTED ACCOUNT NUMBER]")
+    }
+    /// Attach a section with the given title and content to the report.
+    pub fn add_section(&mut self, title: &str, content: &str) {
+        let redacted_content = self.redact(content);
+        self.logs.push((title.to_string(), redacted_content));
     }
 
-    fn redact_home_dir(input: &str) -> Cow<'_, str> {
-        match dirs_next::home_dir() {
-            Some(home) => Cow::from(input.replace(home.to_string_lossy().as_ref(), "~")),
-            None => Cow::from(input),
-        }
+    /// Attach the manifest to the report. Every string in it is redacted separately, so that the
+    /// section remains valid JSON.
+    pub fn add_manifest(&mut self, mut manifest: serde_json::Value) {
+        self.redact_json(&mut manifest);
+        let content = serde_json::to_string_pretty(&manifest).unwrap_or_default();
+        self.logs
+            .push((manifest::MANIFEST_TITLE.to_string(), content));
     }
 
-    fn redact_network_info(input: &str) -> Cow<'_, str> {
-        lazy_static! {
-            static ref RE: Regex = {
-                let boundary = "[^0-9a-zA-Z.:]";
-                let combined_pattern = format!(
-                    "(?P<start>^|{})(?:{}|{}|{})",
-                    boundary,
-                    build_ipv4_regex(),
-                    build_ipv6_regex(),
-                    build_mac_regex(),
-                );
-                Regex::new(&combined_pattern).unwrap()
-            };
+    fn redact_json(&self, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::String(string) => *string = self.redact(string),
+            serde_json::Value::Array(values) => {
+                for value in values {
+                    self.redact_json(value);
+                }
+            }
+            serde_json::Value::Object(map) => {
+                for value in map.values_mut() {
+                    self.redact_json(value);
+                }
+            }
+            _ => (),
         }
-        RE.replace_all(input, "$start[REDACTED]")
     }
 
-    fn redact_guids(input: &str) -> Cow<'_, str> {
-        lazy_static! {
-            static ref RE: Regex = Regex::new(
-                r#"(?i)\{?[A-F0-9]{8}-[A-F0-9]{4}-[A-F0-9]{4}-[A-F0-9]{4}-[A-F0-9]{12}\}?"#
-            )
-            .unwrap();
-        }
-        RE.replace_all(input, "[REDACTED]")
+    /// Attach an error to the report.
+    pub fn add_error(&mut self, message: &'static str, error: &impl ErrorExt) {
+        let redacted_error = self.redact(&error.display_chain());
+        self.logs.push((message.to_string(), redacted_error));
     }
 
-    fn redact_custom_strings<'a>(&self, input: &'a str) -> Cow<'a, str> {
-        // Can probably me made a lot faster with aho-corasick if optimization is ever needed.
-        let mut out = Cow::from(input);
-        for redact in &self.redact_custom_strings {
-            out = out.replace(redact, "[REDACTED]").into()
-        }
-        out
+    fn redact(&self, input: &str) -> String {
+        self.redactor.redact(input)
     }
 
     fn write_to<W: Write>(&self, mut output: W) -> io::Result<()> {
@@ -484,74 +1039,65 @@ impl ProblemReport {
     }
 }
 
-fn build_mac_regex() -> String {
-    let octet = "[[:xdigit:]]{2}"; // 0 - ff
-
-    // five pairs of two hexadecimal chars followed by colon or dash
-    // followed by a pair of hexadecimal chars
-    format!("(?:{0}[:-]){{5}}({0})", octet)
+/// Splits the byte budget for logs between files of the given sizes. Files are capped at
+/// `max_file_bytes` each, and earlier files are served first until `max_total_bytes` is used up.
+fn log_read_limits(
+    file_sizes: &[u64],
+    max_file_bytes: usize,
+    max_total_bytes: usize,
+) -> Vec<usize> {
+    let mut remaining = max_total_bytes;
+    file_sizes
+        .iter()
+        .map(|&file_size| {
+            let limit = min(min(file_size, max_file_bytes as u64) as usize, remaining);
+            remaining -= limit;
+            limit
+        })
+        .collect()
 }
 
-fn build_ipv4_regex() -> String {
-    // regex adapted from  https://www.regular-expressions.info/ip.html
-
-    let above_250 = "25[0-5]";
-    let above_200 = "2[0-4][0-9]";
-    let above_100 = "1[0-9][0-9]";
-
-    // 100-119 | 120-126 | 128-129 | 130 - 199
-    let above_100_not_127 = "1(?:[01][0-9]|2[0-6]|2[89]|[3-9][0-9])";
-
-    let above_0 = "0?[0-9][0-9]?";
-
-    // matches 0-255, except 127
-    let first_octet = format!(
-        "(?:{}|{}|{}|{})",
-        above_250, above_200, above_100_not_127, above_0
-    );
+/// Reads and redacts the given logs on up to `MAX_LOG_READ_THREADS` threads. Returns the index
+/// of each log in `ProblemReport::logs` along with its redacted content.
+fn read_logs_parallel(
+    jobs: Vec<(PendingLog, usize)>,
+    redactor: &Arc<redact::Redactor>,
+) -> Vec<(usize, String)> {
+    let num_threads = min(jobs.len(), MAX_LOG_READ_THREADS);
+    let jobs = Arc::new(jobs);
+    let next_job = Arc::new(AtomicUsize::new(0));
+    let (result_tx, result_rx) = mpsc::channel();
+
+    let workers: Vec<_> = (0..num_threads)
+        .map(|_| {
+            let jobs = jobs.clone();
+            let next_job = next_job.clone();
+            let redactor = redactor.clone();
+            let result_tx = result_tx.clone();
+            thread::spawn(move || {
+                while let Some((log, max_bytes)) =
+                    jobs.get(next_job.fetch_add(1, Ordering::Relaxed))
+                {
+                    let content = read_file_lossy(&log.path, *max_bytes).unwrap_or_else(|error| {
+                        error.display_chain_with_msg(&format!(
+                            "Error reading the contents of log file: {}",
+                            log.expanded_path.display()
+                        ))
+                    });
+                    let _ = result_tx.send((log.index, redactor.redact(&content)));
+                }
+            })
+        })
+        .collect();
+    drop(result_tx);
 
-    // matches 0-255
-    let ip_octet = format!("(?:{}|{}|{}|{})", above_250, above_200, above_100, above_0);
-
-    format!("(?:{0}\\.{1}\\.{1}\\.{1})", first_octet, ip_octet)
-}
-
-fn build_ipv6_regex() -> String {
-    // Regular expression obtained from:
-    // https://stackoverflow.com/a/17871737
-    let ipv4_segment = "(25[0-5]|(2[0-4]|1{0,1}[0-9]){0,1}[0-9])";
-    let ipv4_address = format!("({0}\\.){{3,3}}{0}", ipv4_segment);
-
-    let ipv6_segment = "[0-9a-fA-F]{1,4}";
-
-    let long = format!("({0}:){{7,7}}{0}", ipv6_segment);
-    let compressed_1 = format!("({0}:){{1,7}}:", ipv6_segment);
-    let compressed_2 = format!("({0}:){{1,6}}:{0}", ipv6_segment);
-    let compressed_3 = format!("({0}:){{1,5}}(:{0}){{1,2}}", ipv6_segment);
-    let compressed_4 = format!("({0}:){{1,4}}(:{0}){{1,3}}", ipv6_segment);
-    let compressed_5 = format!("({0}:){{1,3}}(:{0}){{1,4}}", ipv6_segment);
-    let compressed_6 = format!("({0}:){{1,2}}(:{0}){{1,5}}", ipv6_segment);
-    let compressed_7 = format!("{0}:((:{0}){{1,6}})", ipv6_segment);
-    let compressed_8 = format!(":((:{0}){{1,7}}|:)", ipv6_segment);
-    let link_local = "[Ff][Ee]80:(:[0-9a-fA-F]{0,4}){0,4}%[0-9a-zA-Z]{1,}";
-    let ipv4_mapped = format!("::([fF]{{4}}(:0{{1,4}}){{0,1}}:){{0,1}}{}", ipv4_address);
-    let ipv4_embedded = format!("({0}:){{1,4}}:{1}", ipv6_segment, ipv4_address);
-
-    format!(
-        "{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}",
-        long,
-        link_local,
-        ipv4_mapped,
-        ipv4_embedded,
-        compressed_8,
-        compressed_7,
-        compressed_6,
-        compressed_5,
-        compressed_4,
-        compressed_3,
-        compressed_2,
-        compressed_1,
-    )
+    let results = result_rx.iter().collect();
+    for worker in workers {
+        if worker.join().is_err() {
+            eprintln!("A log reader thread panicked");
+        }
+    }
+    results
 }
 
 /// Helper to lossily read a file to a `String`. If the file size exceeds the given `max_bytes`,
@@ -585,71 +1131,50 @@ mod tests {
     use super::*;
 
     #[test]
-    fn redacts_ipv4() {
-        assert_redacts("1.2.3.4");
-        assert_redacts("10.127.0.1");
-        assert_redacts("192.168.1.1");
-        assert_redacts("10.0.16.1");
-        assert_redacts("173.54.12.32");
-        assert_redacts("68.4.4.1");
-    }
-
-    #[test]
-    fn does_not_redact_localhost_ipv4() {
-        assert_does_not_redact("127.0.0.1");
-    }
-
-    #[test]
-    fn redacts_ipv6() {
-        assert_redacts("2001:0db8:85a3:0000:0000:8a2e:0370:7334");
-        assert_redacts("2001:db8:85a3:0:0:8a2e:370:7334");
-        assert_redacts("2001:db8:85a3::8a2e:370:7334");
-        assert_redacts("2001:db8:0:0:0:0:2:1");
-        assert_redacts("2001:db8::2:1");
-        assert_redacts("2001:db8:0000:1:1:1:1:1");
-        assert_redacts("2001:db8:0:1:1:1:1:1");
-        assert_redacts("2001:db8:0:0:1:0:0:1");
-        assert_redacts("2001:db8::1:0:0:1");
-        assert_redacts("abcd:dead:beef::");
-        assert_redacts("abcd:dead:beef:1234::");
-        assert_redacts("::dead:beef:1234");
-        assert_redacts("0::0");
-        assert_redacts("0:0:0:0::1");
-    }
-
-    #[test]
-    fn doesnt_redact_not_ipv6() {
-        assert_does_not_redact("[talpid_core::firewall]");
-    }
-
-    #[test]
-    fn redacts_guid() {
-        assert_redacts("6B29FC40-CA47-1067-B31D-00DD010662DA");
-        assert_redacts("123123ab-12ab-89cd-45ef-012345678901");
-        assert_redacts("{123123ab-12ab-89cd-45ef-012345678901}");
+    fn redacts_manifest_strings() {
+        let mut report = ProblemReport::new(vec!["secret".to_owned()]);
+        report.add_manifest(serde_json::json!({
+            "settings": { "address": "10.0.16.1", "words": ["a secret"] },
+            "port": 1234,
+        }));
+
+        let (title, content) = &report.logs[0];
+        assert_eq!(title, manifest::MANIFEST_TITLE);
+        let manifest: serde_json::Value = serde_json::from_str(content).unwrap();
+        assert_eq!(
+            manifest,
+            serde_json::json!({
+                "settings": { "address": "[REDACTED]", "words": ["a [REDACTED]"] },
+                "port": 1234,
+            })
+        );
     }
 
     #[test]
-    fn doesnt_redact_not_guid() {
-        assert_does_not_redact("23123ab-12ab-89cd-45ef-012345678901");
-        assert_does_not_redact("GGGGGGGG-GGGG-GGGG-GGGG-GGGGGGGGGGGG");
+    fn log_read_limits_share_budget_in_order() {
+        assert_eq!(
+            log_read_limits(&[10, 300, 0, 250, 100, 50], 200, 500),
+            vec![10, 200, 0, 200, 90, 0]
+        );
     }
 
     #[test]
-    fn does_not_redact_time() {
-        assert_does_not_redact("09:47:59");
-    }
-
-    fn assert_redacts(input: &str) {
-        let report = ProblemReport::new(vec![]);
-        let actual = report.redact(&format!("pre {} post", input));
-        assert_eq!("pre [REDACTED] post", actual);
-    }
+    fn reads_logs_in_added_order() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        fs::create_dir(&dir).unwrap();
+        let mut report = ProblemReport::new(vec!["secret".to_owned()]);
+        for i in 0..10 {
+            let path = dir.join(format!("{}.log", i));
+            fs::write(&path, format!("log {} secret", i)).unwrap();
+            report.add_log(&path);
+        }
+        report.read_logs();
+        let _ = fs::remove_dir_all(&dir);
 
-    fn assert_does_not_redact(input: &str) {
-        let report = ProblemReport::new(vec![]);
-        let res = report.redact(input);
-        assert_eq!(input, res);
+        assert_eq!(report.logs.len(), 10);
+        for (i, (_, content)) in report.logs.iter().enumerate() {
+            assert_eq!(content, &format!("log {} [REDACTED]", i));
+        }
     }
 
     #[test]
@@ -1,7 +1,12 @@
 #![deny(rust_2018_idioms)]
 
 use clap::{crate_authors, crate_name};
-use mullvad_problem_report::{collect_report, metadata, send_problem_report, Error};
+#[cfg(not(target_os = "android"))]
+use mullvad_problem_report::{cancel_pending_problem_report, list_pending_problem_reports};
+use mullvad_problem_report::{
+    collect_report, collect_report_through_daemon, metadata, preview_problem_report,
+    send_problem_report, Error,
+};
 use std::{env, path::Path, process};
 use talpid_types::ErrorExt;
 
@@ -54,6 +59,15 @@ fn run() -> Result<(), Error> {
                         .value_name("PHRASE")
                         .multiple(true)
                         .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::with_name("daemon")
+                        .help(
+                            "Let the daemon collect the report, which includes the firewall \
+                             rules and more of its state, but not the frontend logs",
+                        )
+                        .long("daemon")
+                        .conflicts_with("extra_logs"),
                 ),
         )
         .subcommand(
@@ -82,6 +96,26 @@ fn run() -> Result<(), Error> {
                         .help("Reporter's message")
                         .takes_value(true)
                         .required(false),
+                )
+                .arg(
+                    clap::Arg::with_name("preview")
+                        .long("preview")
+                        .help("Print exactly what would be sent, without sending it"),
+                ),
+        );
+    #[cfg(not(target_os = "android"))]
+    let app = app
+        .subcommand(
+            clap::SubCommand::with_name("pending")
+                .about("List reports that could not be sent yet and will be sent by the daemon"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("cancel")
+                .about("Stop trying to send a pending report and remove it")
+                .arg(
+                    clap::Arg::with_name("id")
+                        .help("The ID of the report, as listed by the pending subcommand")
+                        .required(true),
                 ),
         );
 
@@ -96,7 +130,11 @@ fn run() -> Result<(), Error> {
             .map(|os_values| os_values.map(Path::new).collect())
             .unwrap_or_else(Vec::new);
         let output_path = Path::new(collect_matches.value_of_os("output").unwrap());
-        collect_report(&extra_logs, output_path, redact_custom_strings)?;
+        if collect_matches.is_present("daemon") {
+            collect_report_through_daemon(output_path, redact_custom_strings)?;
+        } else {
+            collect_report(&extra_logs, output_path, redact_custom_strings)?;
+        }
 
         let expanded_output_path = output_path
             .canonicalize()
@@ -113,9 +151,18 @@ fn run() -> Result<(), Error> {
         let report_path = Path::new(send_matches.value_of_os("report").unwrap());
         let user_email = send_matches.value_of("email").unwrap_or("");
         let user_message = send_matches.value_of("message").unwrap_or("");
+        if send_matches.is_present("preview") {
+            return preview_problem_report(user_email, user_message, report_path);
+        }
         let cache_dir = mullvad_paths::get_cache_dir()?;
         send_problem_report(user_email, user_message, report_path, &cache_dir)
     } else {
+        #[cfg(not(target_os = "android"))]
+        if matches.subcommand_matches("pending").is_some() {
+            return list_pending_problem_reports();
+        } else if let Some(cancel_matches) = matches.subcommand_matches("cancel") {
+            return cancel_pending_problem_report(cancel_matches.value_of("id").unwrap());
+        }
         unreachable!("No sub command given");
     }
 }
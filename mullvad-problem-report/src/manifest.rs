@@ -0,0 +1,34 @@
+//! A machine readable summary of the app and the system, included in problem reports so that
+//! they can be triaged automatically. It is written as the first section of the report.
+use crate::metadata;
+use serde_json::{json, Map, Value};
+
+/// Title of the report section that contains the manifest.
+pub const MANIFEST_TITLE: &str = "manifest.json";
+
+/// Incremented whenever fields are removed from the manifest or change meaning.
+const MANIFEST_VERSION: u32 = 1;
+
+/// Builds the manifest. `daemon_diagnostics` contains the enabled features, the redacted settings
+/// and the recent tunnel state transitions, if the daemon could be reached.
+pub fn build(daemon_diagnostics: Option<Map<String, Value>>) -> Value {
+    let mut manifest = Map::new();
+    manifest.insert("manifest_version".to_owned(), json!(MANIFEST_VERSION));
+    manifest.insert(
+        "app_version".to_owned(),
+        json!(metadata::PRODUCT_VERSION.trim()),
+    );
+    manifest.insert("os".to_owned(), json!(talpid_platform_metadata::version()));
+    manifest.insert(
+        "os_details".to_owned(),
+        json!(talpid_platform_metadata::extra_metadata()),
+    );
+    manifest.insert(
+        "daemon_reachable".to_owned(),
+        json!(daemon_diagnostics.is_some()),
+    );
+    if let Some(daemon_diagnostics) = daemon_diagnostics {
+        manifest.extend(daemon_diagnostics);
+    }
+    Value::Object(manifest)
+}
@@ -0,0 +1,181 @@
+//! Collects the state of the network configuration and the firewall by running the platform's own
+//! tools, so that support does not have to ask users to run them manually when investigating
+//! leaks. The output is redacted like any other section of the report.
+use std::{
+    io,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Maximum number of bytes that are kept from the output of each command.
+const MAX_OUTPUT_BYTES: usize = 32 * 1024;
+/// Maximum number of bytes that the whole snapshot takes up in the report.
+pub const MAX_SNAPSHOT_BYTES: usize = 6 * MAX_OUTPUT_BYTES;
+
+/// Commands that have not finished within this time are killed.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to run \"{}\"", _0)]
+    StartCommand(String, #[error(source)] io::Error),
+
+    #[error(display = "Failed to wait for \"{}\"", _0)]
+    WaitForCommand(String, #[error(source)] io::Error),
+
+    #[error(display = "\"{}\" did not finish within {} seconds", _0, _1)]
+    Timeout(String, u64),
+
+    #[error(display = "Failed to read the output of \"{}\"", _0)]
+    ReadOutput(String, #[error(source)] io::Error),
+}
+
+/// A command whose output is included in the report.
+struct SnapshotCommand {
+    title: String,
+    program: &'static str,
+    args: Vec<String>,
+    /// Set if the command writes its output to this file rather than to stdout.
+    output_file: Option<PathBuf>,
+}
+
+impl SnapshotCommand {
+    fn new(program: &'static str, args: &[&str]) -> Self {
+        let mut title = program.to_owned();
+        for arg in args {
+            title.push(' ');
+            title.push_str(arg);
+        }
+        SnapshotCommand {
+            title,
+            program,
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            output_file: None,
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn commands() -> Vec<SnapshotCommand> {
+    vec![
+        SnapshotCommand::new("ip", &["addr"]),
+        SnapshotCommand::new("ip", &["route", "show", "table", "all"]),
+        SnapshotCommand::new("ip", &["-6", "route", "show", "table", "all"]),
+        SnapshotCommand::new("ip", &["rule"]),
+        SnapshotCommand::new("ip", &["-6", "rule"]),
+        SnapshotCommand::new("nft", &["list", "ruleset"]),
+    ]
+}
+
+#[cfg(target_os = "macos")]
+fn commands() -> Vec<SnapshotCommand> {
+    vec![
+        SnapshotCommand::new("ifconfig", &[]),
+        SnapshotCommand::new("netstat", &["-rn"]),
+        SnapshotCommand::new("pfctl", &["-sr"]),
+    ]
+}
+
+#[cfg(windows)]
+fn commands() -> Vec<SnapshotCommand> {
+    // `netsh wfp show state` can only write to a file
+    let wfp_state_path =
+        std::env::temp_dir().join(format!("mullvad-wfpstate-{}.xml", uuid::Uuid::new_v4()));
+    vec![
+        SnapshotCommand::new("ipconfig", &["/all"]),
+        SnapshotCommand::new("route", &["print"]),
+        SnapshotCommand {
+            title: "netsh wfp show state".to_owned(),
+            program: "netsh",
+            args: vec![
+                "wfp".to_owned(),
+                "show".to_owned(),
+                "state".to_owned(),
+                format!("file={}", wfp_state_path.display()),
+            ],
+            output_file: Some(wfp_state_path),
+        },
+    ]
+}
+
+/// Runs the snapshot commands for this platform. Returns the title of each command along with its
+/// output, or the reason it could not be collected.
+pub fn collect() -> Vec<(String, Result<String, Error>)> {
+    commands()
+        .into_iter()
+        .map(|command| {
+            let output = run(&command);
+            if let Some(ref output_file) = command.output_file {
+                let _ = std::fs::remove_file(output_file);
+            }
+            (command.title, output)
+        })
+        .collect()
+}
+
+fn run(command: &SnapshotCommand) -> Result<String, Error> {
+    let title = command.title.clone();
+    let handle = duct::cmd(command.program, &command.args)
+        .stdin_null()
+        .stderr_to_stdout()
+        .stdout_capture()
+        .unchecked()
+        .start()
+        .map_err(|error| Error::StartCommand(title.clone(), error))?;
+
+    let deadline = Instant::now() + COMMAND_TIMEOUT;
+    let output = loop {
+        match handle.try_wait() {
+            Ok(Some(output)) => break output,
+            Ok(None) if Instant::now() < deadline => thread::sleep(POLL_INTERVAL),
+            Ok(None) => {
+                let _ = handle.kill();
+                return Err(Error::Timeout(title, COMMAND_TIMEOUT.as_secs()));
+            }
+            Err(error) => return Err(Error::WaitForCommand(title, error)),
+        }
+    };
+
+    let mut content = match command.output_file {
+        Some(ref output_file) if output.status.success() => std::fs::read(output_file)
+            .map(|content| String::from_utf8_lossy(&content).into_owned())
+            .map_err(|error| Error::ReadOutput(title, error))?,
+        _ => String::from_utf8_lossy(&output.stdout).into_owned(),
+    };
+    if !output.status.success() {
+        content.push_str(&format!("\n(exited with {})", output.status));
+    }
+    Ok(truncate(content, MAX_OUTPUT_BYTES))
+}
+
+/// Keeps at most `max_bytes` bytes from the start of `content`.
+fn truncate(mut content: String, max_bytes: usize) -> String {
+    if content.len() > max_bytes {
+        let mut end = max_bytes;
+        while !content.is_char_boundary(end) {
+            end -= 1;
+        }
+        content.truncate(end);
+        content.push_str("\n(output truncated)");
+    }
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncates_at_char_boundary() {
+        assert_eq!(truncate("abc".to_owned(), 3), "abc");
+        assert_eq!(truncate("aåb".to_owned(), 2), "a\n(output truncated)");
+    }
+
+    #[test]
+    fn snapshot_fits_in_report() {
+        assert!(commands().len() * MAX_OUTPUT_BYTES <= MAX_SNAPSHOT_BYTES);
+    }
+}
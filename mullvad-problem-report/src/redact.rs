@@ -0,0 +1,515 @@
+//! Removes personal information from the contents of problem reports, and network details from
+//! log messages.
+//!
+//! The report is passed through a list of rules in order. Most rules replace every match of a
+//! pattern, but IP addresses are first matched loosely and then validated, since a pattern that
+//! only matches valid IPv6 addresses cannot tell them apart from times, Rust paths and MAC
+//! addresses.
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use std::{borrow::Cow, net::IpAddr};
+
+const REDACTED: &str = "[REDACTED]";
+const REDACTED_ACCOUNT_NUMBER: &str = "[REDACTED ACCOUNT NUMBER]";
+
+/// Domains whose hosts belong to the service rather than to the user, such as relays and the API.
+const SERVICE_DOMAINS: &[&str] = &["mullvad.net"];
+
+/// File extensions that make file names look like host names.
+const FILE_EXTENSIONS: &[&str] = &[
+    "app", "bak", "cfg", "conf", "crt", "dat", "deb", "dll", "dylib", "exe", "gz", "ini", "json",
+    "key", "lock", "log", "msi", "old", "ovpn", "pem", "pid", "pkg", "plist", "rpm", "rs",
+    "service", "so", "sock", "sys", "tar", "tmp", "toml", "txt", "yaml", "yml", "zip",
+];
+
+/// Names of users and computers that are not personal, and are too common to remove.
+const GENERIC_IDENTIFIERS: &[&str] = &[
+    "root",
+    "admin",
+    "administrator",
+    "user",
+    "system",
+    "localhost",
+];
+
+lazy_static! {
+    /// Account numbers, written either as one number or in groups of four digits.
+    static ref ACCOUNT_NUMBER: Regex =
+        Regex::new(r"\b(?:\d{16}|\d{4}(?:[ -]\d{4}){3})\b").unwrap();
+
+    /// Anything that could be an IPv6 address, optionally with a zone index. It is validated by
+    /// `redact_ip`.
+    static ref IPV6_CANDIDATE: Regex =
+        Regex::new(r"[0-9A-Fa-f:.]*:[0-9A-Fa-f:.]*(?:%[0-9A-Za-z]+)?").unwrap();
+
+    /// Anything that could be an IPv4 address. It is validated by `redact_ip`.
+    static ref IPV4_CANDIDATE: Regex = Regex::new(r"\d{1,3}(?:\.\d{1,3}){3}").unwrap();
+
+    static ref MAC_ADDRESS: Regex = {
+        // six pairs of hexadecimal chars separated by colons or dashes
+        let octet = "[[:xdigit:]]{2}";
+        Regex::new(&format!(r"(?P<start>^|[^0-9a-zA-Z.:-])(?:{0}[:-]){{5}}{0}\b", octet)).unwrap()
+    };
+
+    /// Anything that could be a host name. It is validated by `redact_host_name`.
+    static ref HOST_NAME_CANDIDATE: Regex = Regex::new(
+        r"(?i)\b(?:[a-z0-9](?:[a-z0-9-]{0,61}[a-z0-9])?\.)+[a-z][a-z0-9-]{0,61}[a-z0-9]\b"
+    )
+    .unwrap();
+
+    static ref GUID: Regex = Regex::new(
+        r"(?i)\{?[A-F0-9]{8}-[A-F0-9]{4}-[A-F0-9]{4}-[A-F0-9]{4}-[A-F0-9]{12}\}?"
+    )
+    .unwrap();
+
+    /// The user name in paths to home directories, including escaped Windows paths.
+    static ref USER_PATH: Regex =
+        Regex::new(r#"(?i)(?P<prefix>/home/|/users/|\\users\\{1,2})[^/\\\s"':]+"#).unwrap();
+
+    /// Wi-Fi network names, such as `SSID: Home`, `ssid=Home` or `SSID "Home"`.
+    static ref SSID: Regex = Regex::new(concat!(
+        r#"(?i)(?P<prefix>\bssid"?\s*[:=]\s*)(?:"[^"\n]*"|'[^'\n]*'|[^\s,;"']+)"#,
+        r#"|(?P<quoted_prefix>\bssid\s+)(?:"[^"\n]*"|'[^'\n]*')"#,
+    ))
+    .unwrap();
+}
+
+/// A single step in the redaction.
+#[derive(Debug)]
+enum Rule {
+    /// Replace every match of the regex. The replacement can refer to named capture groups.
+    Pattern {
+        regex: &'static Regex,
+        replacement: &'static str,
+    },
+    /// Replace the matches of the regex that are IP addresses.
+    IpAddress(&'static Regex),
+    /// Replace the matches of the regex that are host names.
+    HostName(&'static Regex),
+    /// Replace every occurrence of the string.
+    Literal { value: String, replacement: String },
+    /// Replace every occurrence of the string that is not part of a longer word, ignoring case.
+    Word(Regex),
+}
+
+impl Rule {
+    fn apply<'a>(&self, input: &'a str) -> Cow<'a, str> {
+        match self {
+            Rule::Pattern { regex, replacement } => regex.replace_all(input, *replacement),
+            Rule::IpAddress(regex) => {
+                regex.replace_all(input, |captures: &Captures<'_>| redact_ip(input, captures))
+            }
+            Rule::HostName(regex) => regex.replace_all(input, |captures: &Captures<'_>| {
+                redact_host_name(input, captures)
+            }),
+            Rule::Literal { value, replacement } => {
+                if input.contains(value.as_str()) {
+                    Cow::Owned(input.replace(value.as_str(), replacement))
+                } else {
+                    Cow::Borrowed(input)
+                }
+            }
+            Rule::Word(regex) => regex.replace_all(input, REDACTED),
+        }
+    }
+}
+
+/// Removes account numbers, IP and MAC addresses, GUIDs, user and computer names, Wi-Fi network
+/// names and any additional strings from text.
+#[derive(Debug)]
+pub struct Redactor {
+    rules: Vec<Rule>,
+}
+
+impl Redactor {
+    /// Creates a redactor that also removes the given strings. Empty strings are ignored.
+    pub fn new(custom_strings: Vec<String>) -> Self {
+        let home_dir = dirs_next::home_dir().map(|home| home.to_string_lossy().into_owned());
+        Self::with_local_identifiers(custom_strings, home_dir, local_identifiers())
+    }
+
+    /// Creates a redactor that only removes IP addresses, MAC addresses and host names, except
+    /// for the hosts of the service. It is cheap enough to apply to every log message.
+    pub fn network_details() -> Self {
+        Redactor {
+            rules: vec![
+                Rule::IpAddress(&IPV6_CANDIDATE),
+                Rule::IpAddress(&IPV4_CANDIDATE),
+                Rule::Pattern {
+                    regex: &MAC_ADDRESS,
+                    replacement: "$start[REDACTED]",
+                },
+                Rule::HostName(&HOST_NAME_CANDIDATE),
+            ],
+        }
+    }
+
+    fn with_local_identifiers(
+        custom_strings: Vec<String>,
+        home_dir: Option<String>,
+        local_identifiers: Vec<String>,
+    ) -> Self {
+        let mut rules = vec![Rule::Pattern {
+            regex: &ACCOUNT_NUMBER,
+            replacement: REDACTED_ACCOUNT_NUMBER,
+        }];
+        if let Some(home_dir) = home_dir.filter(|home_dir| home_dir.len() > 1) {
+            rules.push(Rule::Literal {
+                value: home_dir,
+                replacement: "~".to_owned(),
+            });
+        }
+        rules.extend(vec![
+            Rule::IpAddress(&IPV6_CANDIDATE),
+            Rule::IpAddress(&IPV4_CANDIDATE),
+            Rule::Pattern {
+                regex: &MAC_ADDRESS,
+                replacement: "$start[REDACTED]",
+            },
+            Rule::Pattern {
+                regex: &GUID,
+                replacement: REDACTED,
+            },
+            Rule::Pattern {
+                regex: &USER_PATH,
+                replacement: "$prefix[REDACTED]",
+            },
+            Rule::Pattern {
+                regex: &SSID,
+                replacement: "${prefix}${quoted_prefix}[REDACTED]",
+            },
+        ]);
+        rules.extend(
+            local_identifiers
+                .iter()
+                .filter(|identifier| is_personal_identifier(identifier))
+                .filter_map(|identifier| {
+                    Regex::new(&format!(r"(?i)\b{}\b", regex::escape(identifier))).ok()
+                })
+                .map(Rule::Word),
+        );
+        rules.extend(
+            custom_strings
+                .into_iter()
+                .filter(|value| !value.is_empty())
+                .map(|value| Rule::Literal {
+                    value,
+                    replacement: REDACTED.to_owned(),
+                }),
+        );
+        Redactor { rules }
+    }
+
+    pub fn redact(&self, input: &str) -> String {
+        let mut output = Cow::Borrowed(input);
+        for rule in &self.rules {
+            let redacted = match rule.apply(&output) {
+                Cow::Owned(redacted) => redacted,
+                Cow::Borrowed(_) => continue,
+            };
+            output = Cow::Owned(redacted);
+        }
+        output.into_owned()
+    }
+}
+
+/// Returns the replacement for a candidate IP address. Candidates that are part of a longer
+/// word or number, or that are not valid addresses, are left as they are. Loopback IPv4
+/// addresses are kept, since they are common and do not identify anyone.
+fn redact_ip(input: &str, captures: &Captures<'_>) -> String {
+    let candidate = captures.get(0).unwrap();
+    let before = input[..candidate.start()].chars().next_back();
+    let mut after = input[candidate.end()..].chars();
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+
+    let unchanged = candidate.as_str().to_owned();
+    if before.map(|c| is_word_char(c) || c == '.').unwrap_or(false) {
+        return unchanged;
+    }
+    match after.next() {
+        Some(c) if is_word_char(c) => return unchanged,
+        // A version number such as 1.2.3.4.5
+        Some('.') if after.next().map(|c| c.is_ascii_digit()).unwrap_or(false) => return unchanged,
+        _ => (),
+    }
+
+    // Punctuation at the end of a sentence is matched by the IPv6 pattern
+    let mut address = candidate.as_str();
+    loop {
+        if is_redactable_ip(address) {
+            return format!("{}{}", REDACTED, &candidate.as_str()[address.len()..]);
+        }
+        match address.strip_suffix(|c| c == '.' || c == ':') {
+            Some(shorter) if !shorter.is_empty() => address = shorter,
+            _ => return unchanged,
+        }
+    }
+}
+
+/// Returns the replacement for a candidate host name. File names, paths and hosts of the service
+/// are left as they are.
+fn redact_host_name(input: &str, captures: &Captures<'_>) -> String {
+    let candidate = captures.get(0).unwrap();
+    let host = candidate.as_str();
+    let before = input[..candidate.start()].chars().next_back();
+
+    let is_path_component = before.map(|c| c == '\\' || c == '_').unwrap_or(false)
+        || (before == Some('/') && !input[..candidate.start()].ends_with("//"));
+    let extension = host.rsplit('.').next().unwrap_or(host);
+    let is_file_name = FILE_EXTENSIONS
+        .iter()
+        .any(|file_extension| file_extension.eq_ignore_ascii_case(extension));
+    let is_service_host = SERVICE_DOMAINS.iter().any(|domain| {
+        let host = host.to_ascii_lowercase();
+        host == *domain || host.ends_with(&format!(".{}", domain))
+    });
+
+    if is_path_component || is_file_name || is_service_host {
+        host.to_owned()
+    } else {
+        REDACTED.to_owned()
+    }
+}
+
+fn is_redactable_ip(address: &str) -> bool {
+    // Remove the zone index of link-local IPv6 addresses
+    let address = address.split('%').next().unwrap_or(address);
+    match address.parse::<IpAddr>() {
+        Ok(IpAddr::V4(address)) => !address.is_loopback(),
+        Ok(IpAddr::V6(_)) => true,
+        Err(_) => false,
+    }
+}
+
+fn is_personal_identifier(identifier: &str) -> bool {
+    identifier.chars().count() >= 3
+        && identifier.starts_with(char::is_alphanumeric)
+        && identifier.ends_with(char::is_alphanumeric)
+        && !GENERIC_IDENTIFIERS
+            .iter()
+            .any(|generic| generic.eq_ignore_ascii_case(identifier))
+}
+
+/// Returns the name of the current user and the name of this computer.
+fn local_identifiers() -> Vec<String> {
+    let mut identifiers = Vec::new();
+    for variable in &["USER", "USERNAME", "LOGNAME", "HOSTNAME", "COMPUTERNAME"] {
+        if let Some(value) = std::env::var_os(variable) {
+            identifiers.push(value.to_string_lossy().trim().to_owned());
+        }
+    }
+    #[cfg(target_os = "linux")]
+    if let Ok(hostname) = std::fs::read_to_string("/etc/hostname") {
+        identifiers.push(hostname.trim().to_owned());
+    }
+    #[cfg(target_os = "macos")]
+    for name in &["ComputerName", "LocalHostName"] {
+        if let Ok(output) = std::process::Command::new("scutil")
+            .args(&["--get", name])
+            .output()
+        {
+            identifiers.push(String::from_utf8_lossy(&output.stdout).trim().to_owned());
+        }
+    }
+    identifiers.sort();
+    identifiers.dedup();
+    identifiers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redactor() -> Redactor {
+        Redactor::with_local_identifiers(
+            vec!["Happy Seal".to_owned()],
+            Some("/home/alice".to_owned()),
+            vec![
+                "alice".to_owned(),
+                "alices-laptop".to_owned(),
+                "root".to_owned(),
+            ],
+        )
+    }
+
+    fn assert_redacts(input: &str) {
+        let actual = redactor().redact(&format!("pre {} post", input));
+        assert_eq!("pre [REDACTED] post", actual);
+    }
+
+    fn assert_does_not_redact(input: &str) {
+        assert_eq!(input, redactor().redact(input));
+    }
+
+    fn assert_redacts_to(input: &str, expected: &str) {
+        assert_eq!(expected, redactor().redact(input));
+    }
+
+    #[test]
+    fn redacts_ipv4() {
+        assert_redacts("1.2.3.4");
+        assert_redacts("10.127.0.1");
+        assert_redacts("192.168.1.1");
+        assert_redacts("10.0.16.1");
+        assert_redacts("173.54.12.32");
+        assert_redacts("68.4.4.1");
+    }
+
+    #[test]
+    fn does_not_redact_localhost_ipv4() {
+        assert_does_not_redact("127.0.0.1");
+    }
+
+    #[test]
+    fn redacts_ipv4_with_surroundings() {
+        assert_redacts_to("Endpoint 1.2.3.4:51820.", "Endpoint [REDACTED]:51820.");
+        assert_redacts_to("host:10.0.0.1", "host:[REDACTED]");
+        assert_redacts_to("route 10.64.0.0/10", "route [REDACTED]/10");
+        assert_redacts_to("http://10.0.0.1:8080/", "http://[REDACTED]:8080/");
+    }
+
+    #[test]
+    fn does_not_redact_version_numbers() {
+        assert_does_not_redact("version 1.2.3.4.5");
+        assert_does_not_redact("v1.2.3.4");
+    }
+
+    #[test]
+    fn redacts_ipv6() {
+        assert_redacts("2001:0db8:85a3:0000:0000:8a2e:0370:7334");
+        assert_redacts("2001:db8:85a3:0:0:8a2e:370:7334");
+        assert_redacts("2001:db8:85a3::8a2e:370:7334");
+        assert_redacts("2001:db8:0:0:0:0:2:1");
+        assert_redacts("2001:db8::2:1");
+        assert_redacts("2001:db8:0000:1:1:1:1:1");
+        assert_redacts("2001:db8:0:1:1:1:1:1");
+        assert_redacts("2001:db8:0:0:1:0:0:1");
+        assert_redacts("2001:db8::1:0:0:1");
+        assert_redacts("abcd:dead:beef::");
+        assert_redacts("abcd:dead:beef:1234::");
+        assert_redacts("::dead:beef:1234");
+        assert_redacts("0::0");
+        assert_redacts("0:0:0:0::1");
+        assert_redacts("::ffff:192.168.1.1");
+        assert_redacts("fe80::1%eth0");
+    }
+
+    #[test]
+    fn redacts_ipv6_with_surroundings() {
+        assert_redacts_to(
+            "GET http://[2001:db8::1]:8080/path",
+            "GET http://[[REDACTED]]:8080/path",
+        );
+        assert_redacts_to(
+            "connect to [fe80::1%eth0]:443",
+            "connect to [[REDACTED]]:443",
+        );
+        assert_redacts_to("Connected to 2001:db8::1.", "Connected to [REDACTED].");
+        assert_redacts_to("addr=::ffff:10.0.0.1,", "addr=[REDACTED],");
+        assert_redacts_to("2001:db8::1 and 2001:db8::2", "[REDACTED] and [REDACTED]");
+    }
+
+    #[test]
+    fn doesnt_redact_not_ipv6() {
+        assert_does_not_redact("[talpid_core::firewall]");
+        assert_does_not_redact("std::fmt::Display");
+        assert_does_not_redact("09:47:59");
+        assert_does_not_redact("12:30:00.123");
+    }
+
+    #[test]
+    fn redacts_network_details() {
+        let redactor = Redactor::network_details();
+        assert_eq!(
+            redactor.redact("Resolved example.com to 1.2.3.4 via 2001:db8::1"),
+            "Resolved [REDACTED] to [REDACTED] via [REDACTED]"
+        );
+        assert_eq!(
+            redactor.redact("Captive portal at http://login.example.org/"),
+            "Captive portal at http://[REDACTED]/"
+        );
+        assert_eq!(
+            redactor.redact("Neighbor aa:bb:cc:dd:ee:ff on 127.0.0.1"),
+            "Neighbor [REDACTED] on 127.0.0.1"
+        );
+        for unchanged in &[
+            "Connecting to se-got-wg-001.relays.mullvad.net",
+            "Resolving api.mullvad.net",
+            "Writing /var/log/mullvad-vpn/daemon.log",
+            "Loaded settings.json",
+            "[talpid_core::firewall] Applying policy",
+            "Account 1234567890123456",
+        ] {
+            assert_eq!(redactor.redact(unchanged), *unchanged);
+        }
+    }
+
+    #[test]
+    fn redacts_mac_address() {
+        assert_redacts("aa:bb:cc:dd:ee:ff");
+        assert_redacts("00-1A-2B-3C-4D-5E");
+    }
+
+    #[test]
+    fn redacts_guid() {
+        assert_redacts("6B29FC40-CA47-1067-B31D-00DD010662DA");
+        assert_redacts("123123ab-12ab-89cd-45ef-012345678901");
+        assert_redacts("{123123ab-12ab-89cd-45ef-012345678901}");
+    }
+
+    #[test]
+    fn doesnt_redact_not_guid() {
+        assert_does_not_redact("23123ab-12ab-89cd-45ef-012345678901");
+        assert_does_not_redact("GGGGGGGG-GGGG-GGGG-GGGG-GGGGGGGGGGGG");
+    }
+
+    #[test]
+    fn redacts_account_number() {
+        for account_number in &[
+            "1234567890123456",
+            "1234 5678 9012 3456",
+            "1234-5678-9012-3456",
+        ] {
+            let actual = redactor().redact(&format!("pre {} post", account_number));
+            assert_eq!("pre [REDACTED ACCOUNT NUMBER] post", actual);
+        }
+    }
+
+    #[test]
+    fn does_not_redact_longer_numbers() {
+        assert_does_not_redact("id 12345678901234567890");
+    }
+
+    #[test]
+    fn redacts_home_directories() {
+        assert_redacts_to("/home/alice/.config", "~/.config");
+        assert_redacts_to("/home/bob/.config", "/home/[REDACTED]/.config");
+        assert_redacts_to("/Users/bob/Library", "/Users/[REDACTED]/Library");
+        assert_redacts_to(
+            r"C:\Users\Bob\AppData and C:\\Users\\Bob\\AppData",
+            r"C:\Users\[REDACTED]\AppData and C:\\Users\\[REDACTED]\\AppData",
+        );
+    }
+
+    #[test]
+    fn redacts_local_identifiers() {
+        assert_redacts("alice");
+        assert_redacts("ALICES-LAPTOP");
+        assert_redacts_to("Alice's file", "[REDACTED]'s file");
+        assert_does_not_redact("malice");
+        assert_does_not_redact("running as root");
+    }
+
+    #[test]
+    fn redacts_ssid() {
+        assert_redacts_to(r#"SSID: "My Home" joined"#, "SSID: [REDACTED] joined");
+        assert_redacts_to("ssid=CoffeeShop; signal", "ssid=[REDACTED]; signal");
+        assert_redacts_to("Joined SSID 'Guest Net'", "Joined SSID [REDACTED]");
+        assert_does_not_redact("SSID changed");
+    }
+
+    #[test]
+    fn redacts_custom_strings() {
+        assert_redacts("Happy Seal");
+    }
+}
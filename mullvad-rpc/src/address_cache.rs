@@ -1,4 +1,6 @@
-use super::API_ADDRESS;
+use super::{API_ADDRESS, API_FALLBACK_ADDRESSES};
+use chrono::Utc;
+use mullvad_types::api_access::{ApiAccessStatus, ApiEndpointSuccess, ApiResolutionStrategy};
 use rand::seq::SliceRandom;
 use std::{
     io,
@@ -50,6 +52,7 @@ impl AddressCache {
         change_listener: Arc<Box<CurrentAddressChangeListener>>,
     ) -> Result<Self, Error> {
         let mut cache = AddressCacheInner::from_addresses(addresses)?;
+        cache.append_fallback_addresses();
         cache.shuffle_tail();
         log::trace!("API address cache: {:?}", cache.addresses);
         log::debug!("Using API address: {:?}", Self::get_address_inner(&cache));
@@ -109,6 +112,34 @@ impl AddressCache {
         inner.tried_current
     }
 
+    /// Records that a request to `address` succeeded.
+    pub fn register_success(&self, address: SocketAddr) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.last_working = Some(ApiEndpointSuccess {
+            address,
+            timestamp: Utc::now(),
+        });
+    }
+
+    /// Returns the addresses in the cache, the currently selected address, and the address that
+    /// most recently worked. The cache does not know how the API hostname is resolved, so the
+    /// resolution fields are left at their defaults.
+    pub fn status(&self) -> ApiAccessStatus {
+        let inner = self.inner.lock().unwrap();
+        let mut addresses = inner.addresses.clone();
+        if !addresses.is_empty() {
+            addresses.rotate_left(inner.choice % addresses.len());
+        }
+        ApiAccessStatus {
+            current_address: Self::get_address_inner(&inner),
+            last_working: inner.last_working.clone(),
+            addresses,
+            resolution_strategy: ApiResolutionStrategy::default(),
+            last_resolution: None,
+            access_method: None,
+        }
+    }
+
     pub async fn select_new_address(&self) {
         {
             let mut inner = self.inner.lock().unwrap();
@@ -167,6 +198,7 @@ impl AddressCache {
             let mut inner = self.inner.lock().unwrap();
             let mut transaction = AddressCacheTransaction::new(&mut inner);
 
+            append_fallback_addresses(&mut addresses);
             addresses.sort();
 
             let mut current_sorted = transaction.addresses.clone();
@@ -215,6 +247,20 @@ impl AddressCache {
         Ok(())
     }
 
+    /// Adds `addresses` to the cache, unless they are already present.
+    pub async fn add_addresses(&self, addresses: Vec<SocketAddr>) -> io::Result<()> {
+        let mut all_addresses = self.inner.lock().unwrap().addresses.clone();
+        let new_addresses = addresses
+            .into_iter()
+            .filter(|address| !all_addresses.contains(address))
+            .collect::<Vec<_>>();
+        if new_addresses.is_empty() {
+            return Ok(());
+        }
+        all_addresses.extend(new_addresses);
+        self.set_addresses(all_addresses).await
+    }
+
     async fn save_to_disk(&self) -> io::Result<()> {
         let write_path = match self.write_path.as_ref() {
             Some(write_path) => write_path,
@@ -264,6 +310,7 @@ struct AddressCacheInner {
     addresses: Vec<SocketAddr>,
     choice: usize,
     tried_current: bool,
+    last_working: Option<ApiEndpointSuccess>,
 }
 
 impl AddressCacheInner {
@@ -275,18 +322,33 @@ impl AddressCacheInner {
             addresses,
             choice: 0,
             tried_current: false,
+            last_working: None,
         })
     }
 
+    fn append_fallback_addresses(&mut self) {
+        append_fallback_addresses(&mut self.addresses);
+    }
+
     fn shuffle(&mut self) {
         let mut rng = rand::thread_rng();
         (&mut self.addresses[..]).shuffle(&mut rng);
+        Self::move_fallbacks_last(&mut self.addresses[..]);
     }
 
     /// Shuffle all but the first element
     fn shuffle_tail(&mut self) {
         let mut rng = rand::thread_rng();
         (&mut self.addresses[1..]).shuffle(&mut rng);
+        Self::move_fallbacks_last(&mut self.addresses[1..]);
+    }
+
+    fn move_fallbacks_last(addresses: &mut [SocketAddr]) {
+        addresses.sort_by_key(|address| {
+            API_FALLBACK_ADDRESSES
+                .iter()
+                .any(|fallback| SocketAddr::from(*fallback) == *address)
+        });
     }
 }
 
@@ -322,6 +384,16 @@ impl<'a> DerefMut for AddressCacheTransaction<'a> {
     }
 }
 
+/// Adds the addresses that are bundled with the binary, unless they are already present.
+fn append_fallback_addresses(addresses: &mut Vec<SocketAddr>) {
+    for fallback in API_FALLBACK_ADDRESSES {
+        let fallback = SocketAddr::from(*fallback);
+        if !addresses.contains(&fallback) {
+            addresses.push(fallback);
+        }
+    }
+}
+
 async fn read_address_file(path: &Path) -> Result<Vec<SocketAddr>, Error> {
     let file = fs::File::open(path)
         .await
@@ -342,3 +414,132 @@ async fn read_address_file(path: &Path) -> Result<Vec<SocketAddr>, Error> {
     }
     Ok(addresses)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fallback_addresses() -> Vec<SocketAddr> {
+        API_FALLBACK_ADDRESSES
+            .iter()
+            .map(|address| SocketAddr::from(*address))
+            .collect()
+    }
+
+    fn recording_listener() -> (
+        Arc<Box<CurrentAddressChangeListener>>,
+        Arc<Mutex<Vec<SocketAddr>>>,
+    ) {
+        let selected = Arc::new(Mutex::new(vec![]));
+        let listener_selected = selected.clone();
+        let listener: Arc<Box<CurrentAddressChangeListener>> = Arc::new(Box::new(move |address| {
+            listener_selected.lock().unwrap().push(address);
+            Ok(())
+        }));
+        (listener, selected)
+    }
+
+    #[test]
+    fn test_fallback_addresses_differ_from_seed() {
+        assert!(fallback_addresses()
+            .iter()
+            .any(|address| *address != SocketAddr::from(API_ADDRESS)));
+    }
+
+    #[test]
+    fn test_fallbacks_are_appended_last() {
+        let cached: Vec<SocketAddr> = vec![
+            "10.0.0.1:443".parse().unwrap(),
+            "10.0.0.2:443".parse().unwrap(),
+            "10.0.0.3:443".parse().unwrap(),
+        ];
+        let (listener, _) = recording_listener();
+        let cache = AddressCache::new(cached.clone(), None, listener).unwrap();
+
+        let addresses = cache.status().addresses;
+        assert_eq!(addresses.len(), cached.len() + fallback_addresses().len());
+        assert_eq!(addresses[0], cached[0]);
+        for fallback in &addresses[cached.len()..] {
+            assert!(fallback_addresses().contains(fallback));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_rotation_visits_every_address() {
+        let cached: Vec<SocketAddr> = vec!["10.0.0.1:443".parse().unwrap()];
+        let (listener, selected) = recording_listener();
+        let cache = AddressCache::new(cached.clone(), None, listener).unwrap();
+        let num_addresses = 1 + fallback_addresses().len();
+
+        assert_eq!(cache.get_address(), cached[0]);
+        assert!(cache.has_tried_current_address());
+
+        let mut visited = vec![cache.peek_address()];
+        for _ in 1..num_addresses {
+            cache.select_new_address().await;
+            assert!(!cache.has_tried_current_address());
+            visited.push(cache.peek_address());
+        }
+        assert_eq!(*selected.lock().unwrap(), visited[1..]);
+        for fallback in fallback_addresses() {
+            assert!(visited.contains(&fallback));
+        }
+
+        cache.select_new_address().await;
+        assert_eq!(cache.peek_address(), cached[0]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_selected_address_is_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(crate::API_IP_CACHE_FILENAME);
+        let cached: Vec<SocketAddr> = vec![
+            "10.0.0.1:443".parse().unwrap(),
+            "10.0.0.2:443".parse().unwrap(),
+        ];
+        let (listener, _) = recording_listener();
+        let cache = AddressCache::new(
+            cached,
+            Some(path.clone().into_boxed_path()),
+            listener.clone(),
+        )
+        .unwrap();
+
+        cache.select_new_address().await;
+        let selected = cache.peek_address();
+        let mut addresses = cache.status().addresses;
+
+        let loaded = AddressCache::from_file(&path, None, listener)
+            .await
+            .unwrap();
+        assert_eq!(loaded.peek_address(), selected);
+
+        let mut loaded_addresses = loaded.status().addresses;
+        addresses.sort();
+        loaded_addresses.sort();
+        assert_eq!(loaded_addresses, addresses);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_set_addresses_is_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(crate::API_IP_CACHE_FILENAME);
+        let (listener, _) = recording_listener();
+        let cache = AddressCache::new(
+            vec!["10.0.0.1:443".parse().unwrap()],
+            Some(path.clone().into_boxed_path()),
+            listener,
+        )
+        .unwrap();
+
+        let new_address: SocketAddr = "10.0.0.2:443".parse().unwrap();
+        cache.set_addresses(vec![new_address]).await.unwrap();
+
+        let mut expected = vec![new_address];
+        expected.extend(fallback_addresses());
+        expected.sort();
+        let mut written = read_address_file(&path).await.unwrap();
+        written.sort();
+        assert_eq!(written, expected);
+    }
+}
@@ -0,0 +1,360 @@
+//! Detects system clocks that are so far off that the certificate of the API is rejected, which
+//! otherwise only shows up as the API being unreachable. When a request fails because the
+//! certificate is not valid at the current time, the time is read from the `Date` header of a
+//! response from the API instead. The certificate that the response is received over must still
+//! chain up to a pinned certificate, at some time within [`MAX_CLOCK_SEARCH`] of the system clock.
+//! The time of the API is only used to tell the user that the clock is wrong. It never affects
+//! which certificates are trusted for other requests.
+use crate::{https_client_with_sni::HttpsConnectorWithSni, rest};
+use chrono::{DateTime, Utc};
+use hyper::{
+    header::{self, HeaderMap, HeaderValue},
+    service::Service,
+    Uri,
+};
+use std::{
+    cell::Cell,
+    fmt, io, iter,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use talpid_types::ErrorExt;
+use tokio_rustls::rustls::{self, ProtocolVersion};
+
+/// The system clock is only reported as wrong if it differs from the time of the API by more
+/// than this.
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs(60 * 60);
+
+/// How far from the system clock to look for a time at which the certificate of the API is valid.
+const MAX_CLOCK_SEARCH: Duration = Duration::from_secs(20 * 365 * 24 * 60 * 60);
+/// Distance between the times that the certificate is verified at. This must be shorter than the
+/// validity period of the certificates used by the API.
+const CLOCK_SEARCH_STEP: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// How long to wait for the time of the API.
+const CLOCK_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to connect to the API")]
+    ConnectError(#[error(source)] io::Error),
+
+    #[error(display = "Failed to create request")]
+    RequestError(#[error(source)] http::Error),
+
+    #[error(display = "Request to the API failed")]
+    HyperError(#[error(source)] hyper::Error),
+
+    #[error(display = "Timed out waiting for the API")]
+    TimeoutError,
+
+    #[error(display = "The API response has no valid Date header")]
+    InvalidDate,
+}
+
+/// How far the system clock is from the time of the API. It is positive if the system clock is
+/// ahead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockSkew(pub chrono::Duration);
+
+impl ClockSkew {
+    pub fn seconds(&self) -> i64 {
+        self.0.num_seconds()
+    }
+
+    /// Returns whether the difference is large enough to report the system clock as wrong.
+    pub fn is_significant(&self) -> bool {
+        self.0.num_seconds().abs() as u64 > MAX_CLOCK_SKEW.as_secs()
+    }
+}
+
+impl fmt::Display for ClockSkew {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let skew = if self.0 < chrono::Duration::zero() {
+            -self.0
+        } else {
+            self.0
+        };
+        let (amount, unit) = if skew.num_days() > 0 {
+            (skew.num_days(), "day")
+        } else if skew.num_hours() > 0 {
+            (skew.num_hours(), "hour")
+        } else {
+            (skew.num_minutes(), "minute")
+        };
+        write!(
+            f,
+            "{} {}{} {}",
+            amount,
+            unit,
+            if amount == 1 { "" } else { "s" },
+            if self.0 < chrono::Duration::zero() {
+                "slow"
+            } else {
+                "fast"
+            }
+        )
+    }
+}
+
+/// Whether requests that fail because the certificate of the API is not valid at the current time
+/// are followed by a check of the system clock.
+#[derive(Clone, Default)]
+pub struct ClockCheck {
+    enabled: Arc<AtomicBool>,
+}
+
+impl ClockCheck {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::SeqCst);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+
+    /// Returns [`rest::Error::ClockSkew`] instead of `error` if the request failed because of
+    /// the validity period of the certificate, and the system clock turns out to be wrong.
+    pub(crate) async fn diagnose(
+        &self,
+        error: rest::Error,
+        connector: HttpsConnectorWithSni,
+        address: Option<SocketAddr>,
+        host: Option<HeaderValue>,
+    ) -> rest::Error {
+        let (address, host) = match (address, host) {
+            (Some(address), Some(host))
+                if self.is_enabled() && is_certificate_validity_error(&error) =>
+            {
+                (address, host)
+            }
+            _ => return error,
+        };
+
+        log::debug!("The API certificate is not valid at the current time. Checking the clock");
+        match check_clock(connector, address, host).await {
+            Ok(skew) if skew.is_significant() => {
+                log::error!("The system clock is {} compared to the API", skew);
+                rest::Error::ClockSkew(skew)
+            }
+            Ok(skew) => {
+                log::debug!("The system clock is {} compared to the API", skew);
+                error
+            }
+            Err(check_error) => {
+                log::warn!(
+                    "{}",
+                    check_error.display_chain_with_msg("Failed to check the system clock")
+                );
+                error
+            }
+        }
+    }
+}
+
+/// Returns how far the system clock is from the time of the API at `address`.
+async fn check_clock(
+    connector: HttpsConnectorWithSni,
+    address: SocketAddr,
+    host: HeaderValue,
+) -> Result<ClockSkew, Error> {
+    let api_time = tokio::time::timeout(CLOCK_CHECK_TIMEOUT, api_time(connector, address, host))
+        .await
+        .map_err(|_| Error::TimeoutError)??;
+    Ok(ClockSkew(Utc::now() - api_time))
+}
+
+/// Reads the time of the API from the `Date` header of a response.
+async fn api_time(
+    mut connector: HttpsConnectorWithSni,
+    address: SocketAddr,
+    host: HeaderValue,
+) -> Result<DateTime<Utc>, Error> {
+    let uri = Uri::builder()
+        .scheme("https")
+        .authority(address.to_string().as_str())
+        .path_and_query("/")
+        .build()
+        .map_err(Error::RequestError)?;
+    let request = hyper::Request::head("/")
+        .header(header::HOST, host)
+        .body(hyper::Body::empty())
+        .map_err(Error::RequestError)?;
+
+    let stream = connector.call(uri).await.map_err(Error::ConnectError)?;
+    let (mut sender, connection) = hyper::client::conn::handshake(stream)
+        .await
+        .map_err(Error::HyperError)?;
+    tokio::spawn(async move {
+        let _ = connection.await;
+    });
+    let response = sender
+        .send_request(request)
+        .await
+        .map_err(Error::HyperError)?;
+    parse_date(response.headers()).ok_or(Error::InvalidDate)
+}
+
+fn parse_date(headers: &HeaderMap) -> Option<DateTime<Utc>> {
+    let value = headers.get(header::DATE)?.to_str().ok()?;
+    DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+/// Returns whether `error` was caused by a certificate that is not valid at the current time.
+pub fn is_certificate_validity_error(error: &rest::Error) -> bool {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(error);
+    while let Some(error) = source {
+        if let Some(tls_error) = error.downcast_ref::<rustls::TLSError>() {
+            return is_validity_tls_error(tls_error);
+        }
+        // `io::Error` does not return the error it wraps as its source.
+        if let Some(tls_error) = error
+            .downcast_ref::<io::Error>()
+            .and_then(io::Error::get_ref)
+            .and_then(|inner| inner.downcast_ref::<rustls::TLSError>())
+        {
+            return is_validity_tls_error(tls_error);
+        }
+        source = error.source();
+    }
+    false
+}
+
+fn is_validity_tls_error(error: &rustls::TLSError) -> bool {
+    matches!(
+        error,
+        rustls::TLSError::WebPKIError(webpki::Error::CertExpired)
+            | rustls::TLSError::WebPKIError(webpki::Error::CertNotValidYet)
+    )
+}
+
+/// Creates the TLS configuration used to read the time of the API. It is the same as the one used
+/// for other requests, except that certificates are accepted if they are valid at a time near the
+/// system clock.
+pub(crate) fn tls_config(root_store: rustls::RootCertStore) -> rustls::ClientConfig {
+    let mut config = rustls::ClientConfig::new();
+    config.enable_sni = true;
+    config.root_store = root_store;
+    config.versions = vec![ProtocolVersion::TLSv1_3];
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    config
+        .dangerous()
+        .set_certificate_verifier(Arc::new(SkewedTimeVerifier));
+    config
+}
+
+thread_local! {
+    /// The time that certificates are verified at by [`SkewedTimeVerifier`].
+    static VERIFICATION_TIME: Cell<u64> = Cell::new(0);
+}
+
+fn verification_time() -> Result<webpki::Time, rustls::TLSError> {
+    Ok(webpki::Time::from_seconds_since_unix_epoch(
+        VERIFICATION_TIME.with(Cell::get),
+    ))
+}
+
+/// Verifies certificates like rustls does, except that a certificate that is not valid at the
+/// current time is also accepted if it is valid at a time within [`MAX_CLOCK_SEARCH`].
+struct SkewedTimeVerifier;
+
+impl rustls::ServerCertVerifier for SkewedTimeVerifier {
+    fn verify_server_cert(
+        &self,
+        roots: &rustls::RootCertStore,
+        presented_certs: &[rustls::Certificate],
+        dns_name: webpki::DNSNameRef<'_>,
+        ocsp_response: &[u8],
+    ) -> Result<rustls::ServerCertVerified, rustls::TLSError> {
+        let verifier = rustls::WebPKIVerifier {
+            time: verification_time,
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+
+        let mut result = Err(rustls::TLSError::FailedToGetCurrentTime);
+        for time in search_times(now) {
+            VERIFICATION_TIME.with(|verification_time| verification_time.set(time));
+            result = verifier.verify_server_cert(roots, presented_certs, dns_name, ocsp_response);
+            match &result {
+                Err(error) if is_validity_tls_error(error) => continue,
+                _ => break,
+            }
+        }
+        result
+    }
+}
+
+/// Returns `now`, followed by times that are increasingly far from `now`, alternating between
+/// later and earlier times.
+fn search_times(now: u64) -> impl Iterator<Item = u64> {
+    let step = CLOCK_SEARCH_STEP.as_secs();
+    let steps = MAX_CLOCK_SEARCH.as_secs() / step;
+    iter::once(now).chain((1..=steps).flat_map(move |i| {
+        let offset = i * step;
+        iter::once(now.saturating_add(offset)).chain(now.checked_sub(offset))
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_search_times() {
+        let step = CLOCK_SEARCH_STEP.as_secs();
+        let now = 10 * step;
+        let times: Vec<_> = search_times(now).take(5).collect();
+        assert_eq!(
+            times,
+            vec![now, now + step, now - step, now + 2 * step, now - 2 * step]
+        );
+
+        // Times before the epoch are skipped
+        let times: Vec<_> = search_times(step).take(4).collect();
+        assert_eq!(times, vec![step, 2 * step, 0, 3 * step]);
+    }
+
+    #[test]
+    fn test_clock_skew() {
+        let skew = ClockSkew(chrono::Duration::days(-3));
+        assert!(skew.is_significant());
+        assert_eq!(skew.to_string(), "3 days slow");
+
+        let skew = ClockSkew(chrono::Duration::hours(1) + chrono::Duration::minutes(30));
+        assert!(skew.is_significant());
+        assert_eq!(skew.to_string(), "1 hour fast");
+
+        let skew = ClockSkew(chrono::Duration::minutes(5));
+        assert!(!skew.is_significant());
+    }
+
+    #[test]
+    fn test_parse_date() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::DATE,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(
+            parse_date(&headers).unwrap().to_rfc3339(),
+            "2015-10-21T07:28:00+00:00"
+        );
+
+        headers.insert(header::DATE, HeaderValue::from_static("yesterday"));
+        assert!(parse_date(&headers).is_none());
+    }
+}
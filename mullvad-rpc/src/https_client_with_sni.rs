@@ -1,4 +1,10 @@
-use crate::{rest::RequestCommand, tcp_stream::TcpStream};
+use crate::{
+    clock,
+    pinning::PinStore,
+    proxy::{self, ProxyHandle},
+    rest::RequestCommand,
+    tcp_stream::TcpStream,
+};
 use futures::{
     channel::{mpsc, oneshot},
     sink::SinkExt,
@@ -15,11 +21,10 @@ use std::os::unix::io::{AsRawFd, RawFd};
 use std::{
     fmt,
     future::Future,
-    io::{self, BufReader},
+    io,
     net::{IpAddr, SocketAddr},
     pin::Pin,
     str::{self, FromStr},
-    sync::Arc,
     task::{Context, Poll},
     time::Duration,
 };
@@ -30,11 +35,6 @@ use tokio::{net::TcpStream as TokioTcpStream, runtime::Handle, time::timeout};
 use tokio_rustls::rustls::{self, ProtocolVersion};
 use webpki::DNSNameRef;
 
-// Old LetsEncrypt root certificate
-const OLD_ROOT_CERT: &[u8] = include_bytes!("../old_le_root_cert.pem");
-// New LetsEncrypt root certificate
-const NEW_ROOT_CERT: &[u8] = include_bytes!("../new_le_root_cert.pem");
-
 const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// A Connector for the `https` scheme.
@@ -46,7 +46,11 @@ pub struct HttpsConnectorWithSni {
     service_tx: Option<mpsc::Sender<RequestCommand>>,
     #[cfg(target_os = "android")]
     socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
-    tls: Arc<rustls::ClientConfig>,
+    pin_store: PinStore,
+    proxy: ProxyHandle,
+    /// Whether certificates that are valid near the current time are accepted, so that the time
+    /// of the API can be read.
+    clock_check: bool,
 }
 
 #[cfg(target_os = "android")]
@@ -62,13 +66,10 @@ impl HttpsConnectorWithSni {
     pub fn new(
         handle: Handle,
         sni_hostname: Option<String>,
+        pin_store: PinStore,
+        proxy: ProxyHandle,
         #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
     ) -> Self {
-        let mut config = rustls::ClientConfig::new();
-        config.enable_sni = true;
-        config.root_store = Self::read_cert_store();
-        config.versions = vec![ProtocolVersion::TLSv1_3];
-
         HttpsConnectorWithSni {
             next_socket_id: 0,
             handle,
@@ -76,28 +77,31 @@ impl HttpsConnectorWithSni {
             #[cfg(target_os = "android")]
             socket_bypass_tx,
             service_tx: None,
-            tls: Arc::new(config),
+            pin_store,
+            proxy,
+            clock_check: false,
         }
     }
 
-    fn read_cert_store() -> rustls::RootCertStore {
-        let mut cert_store = rustls::RootCertStore::empty();
-
-        let (num_certs_added, num_failures) = cert_store
-            .add_pem_file(&mut BufReader::new(OLD_ROOT_CERT))
-            .expect("Failed to add old root cert");
-        if num_failures > 0 || num_certs_added != 1 {
-            panic!("Failed to add old root cert");
-        }
-
-        let (num_certs_added, num_failures) = cert_store
-            .add_pem_file(&mut BufReader::new(NEW_ROOT_CERT))
-            .expect("Failed to add new root cert");
-        if num_failures > 0 || num_certs_added != 1 {
-            panic!("Failed to add new root cert");
-        }
+    /// Returns a connector that is used to read the time of the API when its certificate is not
+    /// valid at the current time. See [`clock`].
+    pub(crate) fn for_clock_check(&self) -> Self {
+        let mut connector = self.clone();
+        connector.service_tx = None;
+        connector.clock_check = true;
+        connector
+    }
 
-        cert_store
+    /// Creates the TLS configuration used for connections that trust the certificates in
+    /// `root_store`.
+    fn tls_config(root_store: rustls::RootCertStore) -> rustls::ClientConfig {
+        let mut config = rustls::ClientConfig::new();
+        config.enable_sni = true;
+        config.root_store = root_store;
+        config.versions = vec![ProtocolVersion::TLSv1_3];
+        // Prefer HTTP/2 so that a single connection can be multiplexed for all API requests.
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        config
     }
 
 
@@ -149,7 +153,9 @@ impl HttpsConnectorWithSni {
         ))?;
         let port = uri.port_u16().unwrap_or(443);
 
-        if let Some(addr) = hostname.parse::<IpAddr>().ok() {
+        // IPv6 literals are enclosed in brackets in URIs
+        let literal = hostname.trim_start_matches('[').trim_end_matches(']');
+        if let Some(addr) = literal.parse::<IpAddr>().ok() {
             return Ok(SocketAddr::new(addr, port));
         }
 
@@ -186,7 +192,11 @@ impl Service<Uri> for HttpsConnectorWithSni {
     }
 
     fn call(&mut self, uri: Uri) -> Self::Future {
-        let tls_connector: tokio_rustls::TlsConnector = self.tls.clone().into();
+        let tls_connector: tokio_rustls::TlsConnector = if self.clock_check {
+            self.pin_store.uncached_tls_config(clock::tls_config).into()
+        } else {
+            self.pin_store.tls_config(Self::tls_config).into()
+        };
         let sni_hostname = self
             .sni_hostname
             .clone()
@@ -198,6 +208,7 @@ impl Service<Uri> for HttpsConnectorWithSni {
 
         let socket_id = self.next_id();
         let handle = self.handle.clone();
+        let proxy = self.proxy.get();
         #[cfg(target_os = "android")]
         let socket_bypass_tx = self.socket_bypass_tx.clone();
 
@@ -214,12 +225,31 @@ impl Service<Uri> for HttpsConnectorWithSni {
                 .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid hostname"))?;
             let addr = Self::resolve_address(&uri).await?;
 
-            let tokio_connection = Self::open_socket(
-                addr,
-                #[cfg(target_os = "android")]
-                socket_bypass_tx,
-            )
-            .await?;
+            let tokio_connection = match proxy {
+                Some(proxy) => {
+                    let mut connection = Self::open_socket(
+                        proxy.address(),
+                        #[cfg(target_os = "android")]
+                        socket_bypass_tx,
+                    )
+                    .await?;
+                    timeout(
+                        CONNECT_TIMEOUT,
+                        proxy::handshake(&mut connection, &proxy, addr),
+                    )
+                    .await
+                    .map_err(|err| io::Error::new(io::ErrorKind::TimedOut, err))??;
+                    connection
+                }
+                None => {
+                    Self::open_socket(
+                        addr,
+                        #[cfg(target_os = "android")]
+                        socket_bypass_tx,
+                    )
+                    .await?
+                }
+            };
 
             let (socket_shutdown_tx, socket_shutdown_rx) = oneshot::channel();
 
@@ -261,9 +291,11 @@ impl Service<Uri> for HttpsConnectorWithSni {
 #[cfg(test)]
 mod test {
     use super::HttpsConnectorWithSni;
+    use crate::pinning::PinStore;
 
     #[test]
     fn test_cert_loading() {
-        let _certs = HttpsConnectorWithSni::read_cert_store();
+        let config = PinStore::bundled().tls_config(HttpsConnectorWithSni::tls_config);
+        assert!(!config.root_store.is_empty());
     }
 }
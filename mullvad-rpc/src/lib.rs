@@ -3,23 +3,29 @@
 use chrono::{offset::Utc, DateTime};
 #[cfg(target_os = "android")]
 use futures::channel::mpsc;
-use hyper::Method;
+use hyper::{service::Service, Method};
 use mullvad_types::{
     account::{AccountToken, VoucherSubmission},
+    api_access::ApiAccessStatus,
+    connection_profile::ConnectionProfiles,
+    device::{Device, DeviceId},
+    port_forward::{PortForward, PortForwardId},
     version::AppVersion,
 };
 use std::{
     collections::BTreeMap,
     future::Future,
+    io,
     net::{IpAddr, Ipv4Addr, SocketAddr},
     path::Path,
     sync::Arc,
 };
-use talpid_types::{net::wireguard, ErrorExt};
+use talpid_types::{cancel::CancelToken, net::wireguard, ErrorExt};
 
 
 pub mod availability;
 use availability::{ApiAvailability, ApiAvailabilityHandle};
+pub mod clock;
 pub mod rest;
 
 mod https_client_with_sni;
@@ -27,12 +33,22 @@ use crate::https_client_with_sni::HttpsConnectorWithSni;
 #[cfg(target_os = "android")]
 pub use crate::https_client_with_sni::SocketBypassRequest;
 mod tcp_stream;
+pub mod trace;
 
 mod address_cache;
+pub mod pinning;
+pub mod proxy;
 mod relay_list;
+pub mod resolver;
+mod response_cache;
 pub use address_cache::{AddressCache, CurrentAddressChangeListener};
 pub use hyper::StatusCode;
+pub use pinning::PinStore;
+pub use proxy::ProxyHandle;
 pub use relay_list::RelayListProxy;
+pub use resolver::ApiResolver;
+pub use response_cache::{ResponseCache, RESPONSE_CACHE_DIR};
+pub use trace::ApiTrace;
 
 /// Error code returned by the Mullvad API if the voucher has alreaby been used.
 pub const VOUCHER_USED: &str = "VOUCHER_USED";
@@ -40,23 +56,50 @@ pub const VOUCHER_USED: &str = "VOUCHER_USED";
 /// Error code returned by the Mullvad API if the voucher code is invalid.
 pub const INVALID_VOUCHER: &str = "INVALID_VOUCHER";
 
+/// Error code returned by the Mullvad API if the voucher is no longer valid.
+pub const VOUCHER_EXPIRED: &str = "VOUCHER_EXPIRED";
+
 /// Error code returned by the Mullvad API if the account token is invalid.
 pub const INVALID_ACCOUNT: &str = "INVALID_ACCOUNT";
 
 /// Error code returned by the Mullvad API if the account token is missing or invalid.
 pub const INVALID_AUTH: &str = "INVALID_AUTH";
 
-const API_HOST: &str = "api.mullvad.net";
+/// Error code returned by the Mullvad API if the account has too many devices.
+pub const MAX_DEVICES_REACHED: &str = "MAX_DEVICES_REACHED";
+
+/// Error code returned by the Mullvad API if the device has been removed from the account.
+pub const DEVICE_NOT_FOUND: &str = "DEVICE_NOT_FOUND";
+
+/// Header used to tell the API which version of the OS the app is running on.
+const PLATFORM_VERSION_HEADER: &str = "M-Platform-Version";
+
+/// Hostname of the Mullvad API.
+pub const API_HOST: &str = "api.mullvad.net";
 pub const API_IP_CACHE_FILENAME: &str = "api-ip-address.txt";
 const API_IP: IpAddr = IpAddr::V4(Ipv4Addr::new(193, 138, 218, 78));
 const API_ADDRESS: (IpAddr, u16) = (crate::API_IP, 443);
+/// Another address of the API, which is not used as the initial address.
+const API_ALTERNATE_ADDRESS: (IpAddr, u16) = (IpAddr::V4(Ipv4Addr::new(45, 83, 223, 196)), 443);
+/// Addresses that are bundled with the binary and tried after all cached addresses have failed,
+/// so that the API can still be reached if the cached list only contains blocked addresses.
+const API_FALLBACK_ADDRESSES: &[(IpAddr, u16)] = &[API_ALTERNATE_ADDRESS, API_ADDRESS];
+/// How long to wait for a connection to the API when probing a way of reaching it.
+const API_PROXY_PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(15);
 
 
 /// A type that helps with the creation of RPC connections.
 pub struct MullvadRpcRuntime {
     handle: tokio::runtime::Handle,
     pub address_cache: AddressCache,
+    response_cache: ResponseCache,
+    pin_store: PinStore,
+    api_trace: ApiTrace,
+    clock_check: clock::ClockCheck,
+    proxy: ProxyHandle,
+    resolver: ApiResolver,
     api_availability: availability::ApiAvailability,
+    cancel_token: CancelToken,
     #[cfg(target_os = "android")]
     socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
 }
@@ -76,14 +119,31 @@ pub enum Error {
 impl MullvadRpcRuntime {
     /// Create a new `MullvadRpcRuntime`.
     pub fn new(handle: tokio::runtime::Handle) -> Result<Self, Error> {
+        let address_cache = AddressCache::new(
+            vec![API_ADDRESS.into()],
+            None,
+            Arc::new(Box::new(|_| Ok(()))),
+        )?;
+        let pin_store = PinStore::bundled();
+        let proxy = ProxyHandle::default();
+        let resolver = ApiResolver::new(
+            handle.clone(),
+            address_cache.clone(),
+            proxy.clone(),
+            #[cfg(target_os = "android")]
+            None,
+        );
         Ok(MullvadRpcRuntime {
             handle,
-            address_cache: AddressCache::new(
-                vec![API_ADDRESS.into()],
-                None,
-                Arc::new(Box::new(|_| Ok(()))),
-            )?,
+            address_cache,
+            response_cache: ResponseCache::in_memory(),
+            pin_store,
+            api_trace: ApiTrace::new(),
+            clock_check: clock::ClockCheck::new(),
+            proxy,
+            resolver,
             api_availability: ApiAvailability::new(availability::State::default()),
+            cancel_token: CancelToken::new(),
             #[cfg(target_os = "android")]
             socket_bypass_tx: None,
         })
@@ -146,20 +206,44 @@ impl MullvadRpcRuntime {
             }
         };
 
+        let response_cache = ResponseCache::new(cache_dir.join(RESPONSE_CACHE_DIR), write_changes);
+        let pin_store = PinStore::bundled();
+        let proxy = ProxyHandle::default();
+        let resolver = ApiResolver::new(
+            handle.clone(),
+            address_cache.clone(),
+            proxy.clone(),
+            #[cfg(target_os = "android")]
+            socket_bypass_tx.clone(),
+        );
+
         Ok(MullvadRpcRuntime {
             handle,
             address_cache,
+            response_cache,
+            pin_store,
+            api_trace: ApiTrace::new(),
+            clock_check: clock::ClockCheck::new(),
+            proxy,
+            resolver,
             api_availability: ApiAvailability::new(availability::State::default()),
+            cancel_token: CancelToken::new(),
             #[cfg(target_os = "android")]
             socket_bypass_tx,
         })
     }
 
     /// Creates a new request service and returns a handle to it.
-    fn new_request_service(&mut self, sni_hostname: Option<String>) -> rest::RequestServiceHandle {
+    fn new_request_service(
+        &mut self,
+        sni_hostname: Option<String>,
+        proxy: ProxyHandle,
+    ) -> rest::RequestServiceHandle {
         let https_connector = HttpsConnectorWithSni::new(
             self.handle.clone(),
             sni_hostname,
+            self.pin_store.clone(),
+            proxy.clone(),
             #[cfg(target_os = "android")]
             self.socket_bypass_tx.clone(),
         );
@@ -169,6 +253,10 @@ impl MullvadRpcRuntime {
             self.handle.clone(),
             self.api_availability.handle(),
             self.address_cache.clone(),
+            self.api_trace.clone(),
+            self.clock_check.clone(),
+            proxy,
+            self.cancel_token.child(),
         );
         let handle = service.handle();
         self.handle.spawn(service.into_future());
@@ -177,7 +265,7 @@ impl MullvadRpcRuntime {
 
     /// Returns a request factory initialized to create requests for the master API
     pub fn mullvad_rest_handle(&mut self) -> rest::MullvadRestHandle {
-        let service = self.new_request_service(Some(API_HOST.to_owned()));
+        let service = self.new_request_service(Some(API_HOST.to_owned()), self.proxy.clone());
         let factory = rest::RequestFactory::new(
             API_HOST.to_owned(),
             Box::new(self.address_cache.clone()),
@@ -188,13 +276,14 @@ impl MullvadRpcRuntime {
             service,
             factory,
             self.address_cache.clone(),
-            self.availability_handle(),
+            self.response_cache.clone(),
         )
     }
 
-    /// Returns a new request service handle
+    /// Returns a new request service handle for hosts other than the API. Its requests are never
+    /// sent through the proxy that API traffic uses, so that they leave through the tunnel.
     pub fn rest_handle(&mut self) -> rest::RequestServiceHandle {
-        self.new_request_service(None)
+        self.new_request_service(None, ProxyHandle::default())
     }
 
     pub fn handle(&mut self) -> &mut tokio::runtime::Handle {
@@ -204,6 +293,69 @@ impl MullvadRpcRuntime {
     pub fn availability_handle(&self) -> ApiAvailabilityHandle {
         self.api_availability.handle()
     }
+
+    /// Returns the token that stops every request service created by this runtime. In-flight
+    /// requests fail once it is cancelled, and so does any request made after that.
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel_token.clone()
+    }
+
+    /// Returns the handle used to select a proxy for API traffic. By default, no proxy is used.
+    pub fn proxy_handle(&self) -> ProxyHandle {
+        self.proxy.clone()
+    }
+
+    /// Returns the resolver used to find API addresses by resolving the API hostname.
+    pub fn api_resolver(&self) -> ApiResolver {
+        self.resolver.clone()
+    }
+
+    /// Returns the known API addresses and how the API hostname is resolved.
+    pub fn api_access_status(&self) -> ApiAccessStatus {
+        let mut status = self.address_cache.status();
+        status.resolution_strategy = self.resolver.strategy();
+        status.last_resolution = self.resolver.last_resolution();
+        status
+    }
+
+    /// Checks that the API can be reached through the proxy that `proxy` is set to, or directly
+    /// if it is set to none, by connecting to the current API address and completing a TLS
+    /// handshake. The proxy is read when the returned future is first polled, so it may be set
+    /// after this is called.
+    pub fn probe_api_access(
+        &self,
+        proxy: ProxyHandle,
+    ) -> impl Future<Output = io::Result<()>> + Send + 'static {
+        let mut connector = HttpsConnectorWithSni::new(
+            self.handle.clone(),
+            Some(API_HOST.to_owned()),
+            self.pin_store.clone(),
+            proxy,
+            #[cfg(target_os = "android")]
+            self.socket_bypass_tx.clone(),
+        );
+        let uri = format!("https://{}", self.address_cache.peek_address()).parse::<hyper::Uri>();
+        async move {
+            let uri = uri.map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+            let _connection = tokio::time::timeout(API_PROXY_PROBE_TIMEOUT, connector.call(uri))
+                .await
+                .map_err(|error| io::Error::new(io::ErrorKind::TimedOut, error))??;
+            Ok(())
+        }
+    }
+
+    /// Returns the trace of recent API requests. It is disabled until
+    /// [`ApiTrace::set_enabled`] is called.
+    pub fn api_trace(&self) -> ApiTrace {
+        self.api_trace.clone()
+    }
+
+    /// Returns the handle that controls whether the system clock is checked when the certificate
+    /// of the API is rejected because of its validity period. It is disabled until
+    /// [`clock::ClockCheck::set_enabled`] is called.
+    pub fn clock_check(&self) -> clock::ClockCheck {
+        self.clock_check.clone()
+    }
 }
 
 #[derive(Clone)]
@@ -308,6 +460,137 @@ impl AccountsProxy {
             Ok(response.auth_token)
         }
     }
+
+    pub fn list_devices(
+        &self,
+        account: AccountToken,
+    ) -> impl Future<Output = Result<Vec<Device>, rest::Error>> {
+        let service = self.handle.service.clone();
+        let response = rest::send_request(
+            &self.handle.factory,
+            service,
+            "/v1/devices",
+            Method::GET,
+            Some(account),
+            StatusCode::OK,
+        );
+        async move { rest::deserialize_body(response.await?).await }
+    }
+
+    pub fn rename_device(
+        &self,
+        account: AccountToken,
+        id: DeviceId,
+        name: String,
+    ) -> impl Future<Output = Result<(), rest::Error>> {
+        #[derive(serde::Serialize)]
+        struct RenameRequest {
+            name: String,
+        }
+
+        let service = self.handle.service.clone();
+        let request = self.handle.factory.put_json(
+            &format!("/v1/devices/{}/name", urlencoding::encode(&id)),
+            &RenameRequest { name },
+        );
+        async move {
+            let mut request = request?;
+            request.set_auth(Some(account))?;
+            let response = service.request(request).await?;
+            rest::parse_rest_response(response, StatusCode::NO_CONTENT).await?;
+            Ok(())
+        }
+    }
+
+    pub fn remove_device(
+        &self,
+        account: AccountToken,
+        id: DeviceId,
+    ) -> impl Future<Output = Result<(), rest::Error>> {
+        let service = self.handle.service.clone();
+        let response = rest::send_request(
+            &self.handle.factory,
+            service,
+            &format!("/v1/devices/{}", urlencoding::encode(&id)),
+            Method::DELETE,
+            Some(account),
+            StatusCode::NO_CONTENT,
+        );
+        async move {
+            let _ = response.await?;
+            Ok(())
+        }
+    }
+
+    pub fn list_port_forwards(
+        &self,
+        account: AccountToken,
+    ) -> impl Future<Output = Result<Vec<PortForward>, rest::Error>> {
+        let service = self.handle.service.clone();
+        let response = rest::send_request(
+            &self.handle.factory,
+            service,
+            "/v1/ports",
+            Method::GET,
+            Some(account),
+            StatusCode::OK,
+        );
+        async move { rest::deserialize_body(response.await?).await }
+    }
+
+    /// Forwards a port from the relays in the given city to the device with the given key. The
+    /// port number is chosen by the API.
+    pub fn add_port_forward(
+        &self,
+        account: AccountToken,
+        pubkey: wireguard::PublicKey,
+        country_code: String,
+        city_code: String,
+    ) -> impl Future<Output = Result<PortForward, rest::Error>> {
+        #[derive(serde::Serialize)]
+        struct AddPortRequest {
+            pubkey: wireguard::PublicKey,
+            country_code: String,
+            city_code: String,
+        }
+
+        let service = self.handle.service.clone();
+        let request = self.handle.factory.post_json(
+            "/v1/ports",
+            &AddPortRequest {
+                pubkey,
+                country_code,
+                city_code,
+            },
+        );
+        async move {
+            let mut request = request?;
+            request.set_auth(Some(account))?;
+            let response = service.request(request).await?;
+            rest::deserialize_body(rest::parse_rest_response(response, StatusCode::CREATED).await?)
+                .await
+        }
+    }
+
+    pub fn remove_port_forward(
+        &self,
+        account: AccountToken,
+        id: PortForwardId,
+    ) -> impl Future<Output = Result<(), rest::Error>> {
+        let service = self.handle.service.clone();
+        let response = rest::send_request(
+            &self.handle.factory,
+            service,
+            &format!("/v1/ports/{}", urlencoding::encode(&id)),
+            Method::DELETE,
+            Some(account),
+            StatusCode::NO_CONTENT,
+        );
+        async move {
+            let _ = response.await?;
+            Ok(())
+        }
+    }
 }
 
 pub struct ProblemReportProxy {
@@ -319,12 +602,15 @@ impl ProblemReportProxy {
         Self { handle }
     }
 
+    /// Sends a problem report. Requests that use the same `idempotency_token` only submit the
+    /// report once, so a failed attempt can be retried using the same token.
     pub fn problem_report(
         &self,
         email: &str,
         message: &str,
         log: &str,
         metadata: &BTreeMap<String, String>,
+        idempotency_token: rest::IdempotencyToken,
     ) -> impl Future<Output = Result<(), rest::Error>> {
         #[derive(serde::Serialize)]
         struct ProblemReport {
@@ -342,18 +628,13 @@ impl ProblemReportProxy {
         };
 
         let service = self.handle.service.clone();
-
-        let request = rest::post_request_with_json(
-            &self.handle.factory,
-            service,
-            "/v1/problem-report",
-            &report,
-            None,
-            StatusCode::NO_CONTENT,
-        );
+        let request = self.handle.factory.post_json("/v1/problem-report", &report);
 
         async move {
-            request.await?;
+            let mut request = request?;
+            request.set_idempotency_token(&idempotency_token)?;
+            let response = service.request(request).await?;
+            rest::parse_rest_response(response, StatusCode::NO_CONTENT).await?;
             Ok(())
         }
     }
@@ -382,23 +663,53 @@ impl AppVersionProxy {
         app_version: AppVersion,
         platform: &str,
         platform_version: String,
+        priority: rest::RequestPriority,
     ) -> impl Future<Output = Result<AppVersionResponse, rest::Error>> {
         let service = self.handle.service.clone();
 
+        let response_cache = self.handle.response_cache.clone();
+
         let path = format!("/v1/releases/{}/{}", platform, app_version);
         let request = self.handle.factory.request(&path, Method::GET);
 
         async move {
             let mut request = request?;
-            request.add_header("M-Platform-Version", &platform_version)?;
+            request.add_header(PLATFORM_VERSION_HEADER, &platform_version)?;
+            request.set_priority(priority);
 
-            let response = service.request(request).await?;
+            let response = response_cache.request(&service, request).await?;
             let parsed_response = rest::parse_rest_response(response, StatusCode::OK).await?;
             rest::deserialize_body(parsed_response).await
         }
     }
 }
 
+#[derive(Clone)]
+pub struct ConnectionProfilesProxy {
+    handle: rest::MullvadRestHandle,
+}
+
+impl ConnectionProfilesProxy {
+    pub fn new(handle: rest::MullvadRestHandle) -> Self {
+        Self { handle }
+    }
+
+    /// Fetches the recommended connection parameters for restrictive networks.
+    pub async fn connection_profiles(&self) -> Result<ConnectionProfiles, rest::Error> {
+        let service = self.handle.service.clone();
+
+        let mut request = self.handle.factory.get("/v1/connection-profiles")?;
+        request.set_priority(rest::RequestPriority::Background);
+        let response = self
+            .handle
+            .response_cache
+            .request(&service, request)
+            .await?;
+        let response = rest::parse_rest_response(response, StatusCode::OK).await?;
+
+        rest::deserialize_body(response).await
+    }
+}
 
 /// Error code for when an account has too many keys. Returned when trying to push a new key.
 pub const KEY_LIMIT_REACHED: &str = "KEY_LIMIT_REACHED";
@@ -413,10 +724,13 @@ impl WireguardKeyProxy {
         Self { handle }
     }
 
+    /// Registers `public_key` with the account. Requests that use the same `idempotency_token` only
+    /// register the key once, so a failed attempt can be retried using the same token.
     pub fn push_wg_key(
         &mut self,
         account_token: AccountToken,
         public_key: wireguard::PublicKey,
+        idempotency_token: rest::IdempotencyToken,
         timeout: Option<std::time::Duration>,
     ) -> impl Future<Output = Result<mullvad_types::wireguard::AssociatedAddresses, rest::Error>> + 'static
     {
@@ -435,17 +749,21 @@ impl WireguardKeyProxy {
                 request.set_timeout(timeout);
             }
             request.set_auth(Some(account_token))?;
+            request.set_idempotency_token(&idempotency_token)?;
             let response = service.request(request).await?;
             rest::deserialize_body(rest::parse_rest_response(response, StatusCode::CREATED).await?)
                 .await
         }
     }
 
+    /// Replaces `old` with `new`. Requests that use the same `idempotency_token` only replace the
+    /// key once, so a failed attempt can be retried using the same token.
     pub async fn replace_wg_key(
         &mut self,
         account_token: AccountToken,
         old: wireguard::PublicKey,
         new: wireguard::PublicKey,
+        idempotency_token: rest::IdempotencyToken,
     ) -> Result<mullvad_types::wireguard::AssociatedAddresses, rest::Error> {
         #[derive(serde::Serialize)]
         struct ReplacementRequest {
@@ -456,17 +774,16 @@ impl WireguardKeyProxy {
         let service = self.handle.service.clone();
         let body = ReplacementRequest { old, new };
 
-        let response = rest::post_request_with_json(
-            &self.handle.factory,
-            service,
-            &"/v1/replace-wireguard-key",
-            &body,
-            Some(account_token),
-            StatusCode::CREATED,
-        )
-        .await?;
+        let mut request = self
+            .handle
+            .factory
+            .post_json(&"/v1/replace-wireguard-key", &body)?;
+        request.set_auth(Some(account_token))?;
+        request.set_idempotency_token(&idempotency_token)?;
+        let response = service.request(request).await?;
 
-        rest::deserialize_body(response).await
+        rest::deserialize_body(rest::parse_rest_response(response, StatusCode::CREATED).await?)
+            .await
     }
 
     pub async fn get_wireguard_key(
@@ -529,15 +846,14 @@ impl ApiProxy {
     pub async fn get_api_addrs(&self) -> Result<Vec<SocketAddr>, rest::Error> {
         let service = self.handle.service.clone();
 
-        let response = rest::send_request(
-            &self.handle.factory,
-            service,
-            "/v1/api-addrs",
-            Method::GET,
-            None,
-            StatusCode::OK,
-        )
-        .await?;
+        let mut request = self.handle.factory.get("/v1/api-addrs")?;
+        request.set_priority(rest::RequestPriority::Background);
+        let response = self
+            .handle
+            .response_cache
+            .request(&service, request)
+            .await?;
+        let response = rest::parse_rest_response(response, StatusCode::OK).await?;
 
         rest::deserialize_body(response).await
     }
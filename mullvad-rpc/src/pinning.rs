@@ -0,0 +1,255 @@
+//! Root certificates that connections to the API are pinned to. Every pinned certificate has a
+//! validity window, so that a replacement can be bundled well ahead of the time the current one
+//! is retired.
+use chrono::{DateTime, Utc};
+use std::{
+    io::BufReader,
+    sync::{Arc, Mutex},
+};
+use tokio_rustls::rustls;
+
+// Old LetsEncrypt root certificate
+const OLD_ROOT_CERT: &str = include_str!("../old_le_root_cert.pem");
+// New LetsEncrypt root certificate
+const NEW_ROOT_CERT: &str = include_str!("../new_le_root_cert.pem");
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "Pinned certificate \"{}\" is invalid", _0)]
+    InvalidCertificate(String),
+}
+
+/// A root certificate that API connections may be verified against.
+#[derive(Clone, Debug)]
+pub struct PinnedCertificate {
+    pub name: String,
+    /// PEM-encoded certificate.
+    pub certificate: String,
+    /// The certificate is not trusted before this time.
+    pub valid_from: Option<DateTime<Utc>>,
+    /// The certificate is not trusted after this time.
+    pub valid_until: Option<DateTime<Utc>>,
+}
+
+impl PinnedCertificate {
+    pub fn is_valid_at(&self, time: DateTime<Utc>) -> bool {
+        self.valid_from.map(|from| from <= time).unwrap_or(true)
+            && self.valid_until.map(|until| time < until).unwrap_or(true)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PinSet {
+    pub pins: Vec<PinnedCertificate>,
+}
+
+impl PinSet {
+    /// Returns the pin set that is bundled with the app.
+    pub fn bundled() -> Self {
+        PinSet {
+            pins: vec![
+                PinnedCertificate {
+                    name: "ISRG Root X1".to_owned(),
+                    certificate: NEW_ROOT_CERT.to_owned(),
+                    valid_from: None,
+                    valid_until: Some(Self::timestamp("2035-06-04T11:04:38Z")),
+                },
+                PinnedCertificate {
+                    name: "DST Root CA X3".to_owned(),
+                    certificate: OLD_ROOT_CERT.to_owned(),
+                    valid_from: None,
+                    valid_until: Some(Self::timestamp("2021-09-30T14:01:15Z")),
+                },
+            ],
+        }
+    }
+
+    /// Returns the pin set for the DNS over HTTPS resolver, whose certificates are issued by Let's
+    /// Encrypt. It is kept separate from the API pins so that either can be changed on its own.
+    pub fn doh() -> Self {
+        PinSet {
+            pins: vec![PinnedCertificate {
+                name: "ISRG Root X1".to_owned(),
+                certificate: NEW_ROOT_CERT.to_owned(),
+                valid_from: None,
+                valid_until: Some(Self::timestamp("2035-06-04T11:04:38Z")),
+            }],
+        }
+    }
+
+    fn timestamp(timestamp: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(timestamp)
+            .expect("Invalid timestamp")
+            .with_timezone(&Utc)
+    }
+
+    /// Returns the indices of the pins that are trusted at `time`. If every pin is outside of its
+    /// validity window, all of them are returned. The system clock may be wrong, or the app may
+    /// be too old to bundle a newer pin set, and being unable to reach the API at all would leave
+    /// no way to recover from that.
+    fn active_pins(&self, time: DateTime<Utc>) -> Vec<usize> {
+        let active: Vec<usize> = self
+            .pins
+            .iter()
+            .enumerate()
+            .filter(|(_, pin)| pin.is_valid_at(time))
+            .map(|(index, _)| index)
+            .collect();
+        if active.is_empty() {
+            log::warn!("No pinned API certificate is valid at this time. Trusting all of them");
+            return (0..self.pins.len()).collect();
+        }
+        active
+    }
+
+    fn root_store(&self, pins: &[usize]) -> Result<rustls::RootCertStore, Error> {
+        let mut cert_store = rustls::RootCertStore::empty();
+        for pin in pins.iter().map(|&index| &self.pins[index]) {
+            match cert_store.add_pem_file(&mut BufReader::new(pin.certificate.as_bytes())) {
+                Ok((1, 0)) => (),
+                _ => return Err(Error::InvalidCertificate(pin.name.clone())),
+            }
+        }
+        Ok(cert_store)
+    }
+}
+
+struct PinStoreInner {
+    pin_set: PinSet,
+    /// TLS configuration for the pins that were active when it was created.
+    tls_config: Option<(Vec<usize>, Arc<rustls::ClientConfig>)>,
+}
+
+/// Keeps track of the pin set in use, and creates TLS configurations that trust the pins that
+/// are currently valid.
+#[derive(Clone)]
+pub struct PinStore {
+    inner: Arc<Mutex<PinStoreInner>>,
+}
+
+impl PinStore {
+    /// Creates a store that uses the bundled pin set.
+    pub fn bundled() -> Self {
+        Self::with_pin_set(PinSet::bundled())
+    }
+
+    /// Creates a store that uses the pin set for the DNS over HTTPS resolver.
+    pub fn doh() -> Self {
+        Self::with_pin_set(PinSet::doh())
+    }
+
+    fn with_pin_set(pin_set: PinSet) -> Self {
+        PinStore {
+            inner: Arc::new(Mutex::new(PinStoreInner {
+                pin_set,
+                tls_config: None,
+            })),
+        }
+    }
+
+    /// Returns a TLS configuration that trusts the pins that are currently valid. `make_config`
+    /// is used to create a new configuration when the pins in use change.
+    pub(crate) fn tls_config(
+        &self,
+        make_config: fn(rustls::RootCertStore) -> rustls::ClientConfig,
+    ) -> Arc<rustls::ClientConfig> {
+        let mut inner = self.inner.lock().unwrap();
+        let active_pins = inner.pin_set.active_pins(Utc::now());
+        if let Some((pins, config)) = &inner.tls_config {
+            if *pins == active_pins {
+                return config.clone();
+            }
+        }
+
+        let root_store = inner
+            .pin_set
+            .root_store(&active_pins)
+            .expect("Bundled pin sets are valid");
+        let config = Arc::new(make_config(root_store));
+        inner.tls_config = Some((active_pins, config.clone()));
+        config
+    }
+
+    /// Returns a new TLS configuration that trusts the pins that are currently valid, without
+    /// replacing the one returned by [`PinStore::tls_config`].
+    pub(crate) fn uncached_tls_config(
+        &self,
+        make_config: fn(rustls::RootCertStore) -> rustls::ClientConfig,
+    ) -> Arc<rustls::ClientConfig> {
+        let inner = self.inner.lock().unwrap();
+        let active_pins = inner.pin_set.active_pins(Utc::now());
+        let root_store = inner
+            .pin_set
+            .root_store(&active_pins)
+            .expect("Bundled pin sets are valid");
+        Arc::new(make_config(root_store))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn two_pin_set(primary_expiry: DateTime<Utc>) -> PinSet {
+        PinSet {
+            pins: vec![
+                PinnedCertificate {
+                    name: "primary".to_owned(),
+                    certificate: OLD_ROOT_CERT.to_owned(),
+                    valid_from: None,
+                    valid_until: Some(primary_expiry),
+                },
+                PinnedCertificate {
+                    name: "secondary".to_owned(),
+                    certificate: NEW_ROOT_CERT.to_owned(),
+                    valid_from: Some(primary_expiry - chrono::Duration::days(30)),
+                    valid_until: None,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_bundled_pins() {
+        let pin_set = PinSet::bundled();
+        pin_set
+            .root_store(&(0..pin_set.pins.len()).collect::<Vec<_>>())
+            .unwrap();
+        let now = PinSet::timestamp("2026-01-01T00:00:00Z");
+        assert_eq!(pin_set.active_pins(now), vec![0]);
+    }
+
+    #[test]
+    fn test_doh_pins() {
+        let pin_set = PinSet::doh();
+        pin_set.root_store(&[0]).unwrap();
+        let now = PinSet::timestamp("2026-01-01T00:00:00Z");
+        assert_eq!(pin_set.active_pins(now), vec![0]);
+    }
+
+    #[test]
+    fn test_primary_pin_expiry() {
+        let expiry = PinSet::timestamp("2030-01-01T00:00:00Z");
+        let pin_set = two_pin_set(expiry);
+
+        let before_overlap = expiry - chrono::Duration::days(60);
+        assert_eq!(pin_set.active_pins(before_overlap), vec![0]);
+
+        let during_overlap = expiry - chrono::Duration::days(1);
+        assert_eq!(pin_set.active_pins(during_overlap), vec![0, 1]);
+        assert_eq!(pin_set.root_store(&[0, 1]).unwrap().len(), 2);
+
+        assert_eq!(pin_set.active_pins(expiry), vec![1]);
+        let after_expiry = expiry + chrono::Duration::days(1);
+        assert_eq!(pin_set.active_pins(after_expiry), vec![1]);
+        assert_eq!(pin_set.root_store(&[1]).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_all_pins_expired() {
+        let mut pin_set = two_pin_set(PinSet::timestamp("2030-01-01T00:00:00Z"));
+        pin_set.pins[1].valid_until = Some(PinSet::timestamp("2031-01-01T00:00:00Z"));
+        let now = PinSet::timestamp("2032-01-01T00:00:00Z");
+        assert_eq!(pin_set.active_pins(now), vec![0, 1]);
+    }
+}
@@ -0,0 +1,383 @@
+//! Support for sending API traffic through a user-configured SOCKS5 or HTTP CONNECT proxy.
+use mullvad_types::api_access::{ApiProxy, ProxyAuth};
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    sync::broadcast,
+};
+
+const SOCKS_VERSION: u8 = 5;
+const SOCKS_AUTH_NONE: u8 = 0x00;
+const SOCKS_AUTH_PASSWORD: u8 = 0x02;
+const SOCKS_AUTH_UNACCEPTABLE: u8 = 0xff;
+const SOCKS_PASSWORD_AUTH_VERSION: u8 = 1;
+const SOCKS_CMD_CONNECT: u8 = 1;
+const SOCKS_ATYP_IPV4: u8 = 1;
+const SOCKS_ATYP_DOMAIN: u8 = 3;
+const SOCKS_ATYP_IPV6: u8 = 4;
+const SOCKS_REPLY_SUCCEEDED: u8 = 0;
+
+/// Maximum size of the response headers accepted from an HTTP proxy.
+const MAX_HTTP_RESPONSE_HEADER_SIZE: usize = 8 * 1024;
+
+/// Number of consecutive requests that must fail because of network errors before the API is
+/// considered unreachable through the current proxy, or directly.
+const UNREACHABLE_FAILURE_THRESHOLD: usize = 3;
+
+/// Shared handle to the proxy that new API connections are made through, if any. It also keeps
+/// track of whether the API can be reached the way it is currently accessed.
+#[derive(Clone, Debug)]
+pub struct ProxyHandle {
+    inner: Arc<Mutex<ProxyHandleInner>>,
+    unreachable_tx: broadcast::Sender<()>,
+}
+
+#[derive(Debug)]
+struct ProxyHandleInner {
+    proxy: Option<ApiProxy>,
+    consecutive_failures: usize,
+}
+
+impl Default for ProxyHandle {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl ProxyHandle {
+    pub fn new(proxy: Option<ApiProxy>) -> Self {
+        let (unreachable_tx, _) = broadcast::channel(1);
+        ProxyHandle {
+            inner: Arc::new(Mutex::new(ProxyHandleInner {
+                proxy,
+                consecutive_failures: 0,
+            })),
+            unreachable_tx,
+        }
+    }
+
+    /// Sets the proxy to use for new connections. Existing connections are not affected.
+    pub fn set(&self, proxy: Option<ApiProxy>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.proxy = proxy;
+        inner.consecutive_failures = 0;
+    }
+
+    pub fn get(&self) -> Option<ApiProxy> {
+        self.inner.lock().unwrap().proxy.clone()
+    }
+
+    /// Returns a receiver that is notified when requests keep failing because of network errors,
+    /// which suggests that the API cannot be reached the way it is currently accessed. It is
+    /// notified once until a request succeeds or the proxy is changed.
+    pub fn unreachable_listener(&self) -> broadcast::Receiver<()> {
+        self.unreachable_tx.subscribe()
+    }
+
+    pub(crate) fn register_success(&self) {
+        self.inner.lock().unwrap().consecutive_failures = 0;
+    }
+
+    pub(crate) fn register_network_failure(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.consecutive_failures += 1;
+        if inner.consecutive_failures == UNREACHABLE_FAILURE_THRESHOLD {
+            let _ = self.unreachable_tx.send(());
+        }
+    }
+}
+
+/// Asks the proxy that `stream` is connected to to open a connection to `target`.
+pub async fn handshake(
+    stream: &mut TcpStream,
+    proxy: &ApiProxy,
+    target: SocketAddr,
+) -> io::Result<()> {
+    match proxy {
+        ApiProxy::Socks5 { auth, .. } => socks5_handshake(stream, auth.as_ref(), target).await,
+        ApiProxy::HttpConnect { auth, .. } => {
+            http_connect_handshake(stream, auth.as_ref(), target).await
+        }
+    }
+}
+
+async fn socks5_handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    auth: Option<&ProxyAuth>,
+    target: SocketAddr,
+) -> io::Result<()> {
+    let methods: &[u8] = if auth.is_some() {
+        &[SOCKS_AUTH_NONE, SOCKS_AUTH_PASSWORD]
+    } else {
+        &[SOCKS_AUTH_NONE]
+    };
+    let mut greeting = vec![SOCKS_VERSION, methods.len() as u8];
+    greeting.extend_from_slice(methods);
+    stream.write_all(&greeting).await?;
+
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).await?;
+    if choice[0] != SOCKS_VERSION {
+        return Err(proxy_error("Unexpected SOCKS version in proxy response"));
+    }
+    match (choice[1], auth) {
+        (SOCKS_AUTH_NONE, _) => (),
+        (SOCKS_AUTH_PASSWORD, Some(auth)) => socks5_authenticate(stream, auth).await?,
+        (SOCKS_AUTH_UNACCEPTABLE, _) => {
+            return Err(proxy_error(
+                "The SOCKS proxy did not accept any authentication method",
+            ))
+        }
+        _ => {
+            return Err(proxy_error(
+                "The SOCKS proxy selected an unsupported authentication method",
+            ))
+        }
+    }
+
+    let mut request = vec![SOCKS_VERSION, SOCKS_CMD_CONNECT, 0];
+    match target.ip() {
+        IpAddr::V4(ip) => {
+            request.push(SOCKS_ATYP_IPV4);
+            request.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            request.push(SOCKS_ATYP_IPV6);
+            request.extend_from_slice(&ip.octets());
+        }
+    }
+    request.extend_from_slice(&target.port().to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(proxy_error("Unexpected SOCKS version in proxy response"));
+    }
+    if reply[1] != SOCKS_REPLY_SUCCEEDED {
+        return Err(proxy_error(&format!(
+            "The SOCKS proxy failed to connect to {}: error code {}",
+            target, reply[1]
+        )));
+    }
+
+    // Skip the address that the proxy bound to
+    let address_len = match reply[3] {
+        SOCKS_ATYP_IPV4 => 4,
+        SOCKS_ATYP_IPV6 => 16,
+        SOCKS_ATYP_DOMAIN => stream.read_u8().await? as usize,
+        _ => return Err(proxy_error("Unknown address type in SOCKS proxy response")),
+    };
+    let mut bound_address = vec![0u8; address_len + 2];
+    stream.read_exact(&mut bound_address).await?;
+    Ok(())
+}
+
+async fn socks5_authenticate<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    auth: &ProxyAuth,
+) -> io::Result<()> {
+    if auth.username.len() > 255 || auth.password.len() > 255 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "SOCKS username and password must be at most 255 bytes long",
+        ));
+    }
+    let mut request = vec![SOCKS_PASSWORD_AUTH_VERSION, auth.username.len() as u8];
+    request.extend_from_slice(auth.username.as_bytes());
+    request.push(auth.password.len() as u8);
+    request.extend_from_slice(auth.password.as_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != 0 {
+        return Err(proxy_error("The SOCKS proxy rejected the credentials"));
+    }
+    Ok(())
+}
+
+async fn http_connect_handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    auth: Option<&ProxyAuth>,
+    target: SocketAddr,
+) -> io::Result<()> {
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target);
+    if let Some(auth) = auth {
+        let credentials = base64::encode(format!("{}:{}", auth.username, auth.password));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", credentials));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // Read one byte at a time so that nothing after the headers is consumed
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= MAX_HTTP_RESPONSE_HEADER_SIZE {
+            return Err(proxy_error("The HTTP proxy response is too large"));
+        }
+        response.push(stream.read_u8().await?);
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    let mut parts = status_line.split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some(version), Some(status)) if version.starts_with("HTTP/1.") => {
+            if status.starts_with('2') {
+                Ok(())
+            } else {
+                Err(proxy_error(&format!(
+                    "The HTTP proxy failed to connect to {}: {}",
+                    target, status_line
+                )))
+            }
+        }
+        _ => Err(proxy_error("Invalid HTTP proxy response")),
+    }
+}
+
+fn proxy_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn auth() -> ProxyAuth {
+        ProxyAuth {
+            username: "user".to_owned(),
+            password: "pass".to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_after_consecutive_failures() {
+        let handle = ProxyHandle::default();
+        let mut listener = handle.unreachable_listener();
+
+        handle.register_network_failure();
+        handle.register_success();
+        for _ in 0..UNREACHABLE_FAILURE_THRESHOLD - 1 {
+            handle.register_network_failure();
+        }
+        assert!(listener.try_recv().is_err());
+
+        for _ in 0..UNREACHABLE_FAILURE_THRESHOLD {
+            handle.register_network_failure();
+        }
+        assert!(listener.try_recv().is_ok());
+        assert!(listener.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_socks5_handshake_with_auth() {
+        let target: SocketAddr = "193.138.218.78:443".parse().unwrap();
+        let (mut client, mut server) = tokio::io::duplex(1024);
+
+        let server = tokio::spawn(async move {
+            let mut greeting = [0u8; 4];
+            server.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(
+                greeting,
+                [SOCKS_VERSION, 2, SOCKS_AUTH_NONE, SOCKS_AUTH_PASSWORD]
+            );
+            server
+                .write_all(&[SOCKS_VERSION, SOCKS_AUTH_PASSWORD])
+                .await
+                .unwrap();
+
+            let mut credentials = [0u8; 11];
+            server.read_exact(&mut credentials).await.unwrap();
+            assert_eq!(&credentials, b"\x01\x04user\x04pass");
+            server.write_all(&[1, 0]).await.unwrap();
+
+            let mut request = [0u8; 10];
+            server.read_exact(&mut request).await.unwrap();
+            assert_eq!(
+                request,
+                [5, 1, 0, SOCKS_ATYP_IPV4, 193, 138, 218, 78, 0x01, 0xbb]
+            );
+            server
+                .write_all(&[5, 0, 0, SOCKS_ATYP_IPV4, 10, 0, 0, 1, 0x12, 0x34])
+                .await
+                .unwrap();
+        });
+
+        socks5_handshake(&mut client, Some(&auth()), target)
+            .await
+            .unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_socks5_connect_refused() {
+        let target: SocketAddr = "193.138.218.78:443".parse().unwrap();
+        let (mut client, mut server) = tokio::io::duplex(1024);
+
+        tokio::spawn(async move {
+            let mut greeting = [0u8; 3];
+            server.read_exact(&mut greeting).await.unwrap();
+            server
+                .write_all(&[SOCKS_VERSION, SOCKS_AUTH_NONE])
+                .await
+                .unwrap();
+            let mut request = [0u8; 10];
+            server.read_exact(&mut request).await.unwrap();
+            server
+                .write_all(&[5, 5, 0, SOCKS_ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+        });
+
+        assert!(socks5_handshake(&mut client, None, target).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_http_connect_handshake() {
+        let target: SocketAddr = "193.138.218.78:443".parse().unwrap();
+        let (mut client, mut server) = tokio::io::duplex(1024);
+
+        let server = tokio::spawn(async move {
+            let mut request = vec![0u8; 1024];
+            let len = server.read(&mut request).await.unwrap();
+            let request = String::from_utf8_lossy(&request[..len]).to_string();
+            assert!(request.starts_with("CONNECT 193.138.218.78:443 HTTP/1.1\r\n"));
+            assert!(request.contains("Proxy-Authorization: Basic dXNlcjpwYXNz\r\n"));
+            server
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                .await
+                .unwrap();
+        });
+
+        http_connect_handshake(&mut client, Some(&auth()), target)
+            .await
+            .unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_http_connect_rejected() {
+        let target: SocketAddr = "193.138.218.78:443".parse().unwrap();
+        let (mut client, mut server) = tokio::io::duplex(1024);
+
+        tokio::spawn(async move {
+            let mut request = vec![0u8; 1024];
+            let _ = server.read(&mut request).await.unwrap();
+            server
+                .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+                .await
+                .unwrap();
+        });
+
+        assert!(http_connect_handshake(&mut client, None, target)
+            .await
+            .is_err());
+    }
+}
@@ -32,17 +32,19 @@ impl RelayListProxy {
         etag: Option<String>,
     ) -> impl Future<Output = Result<Option<relay_list::RelayList>, rest::Error>> {
         let service = self.handle.service.clone();
+        let response_cache = self.handle.response_cache.clone();
         let request = self.handle.factory.request("/v1/relays", Method::GET);
 
         let future = async move {
             let mut request = request?;
             request.set_timeout(RELAY_LIST_TIMEOUT);
+            request.set_priority(rest::RequestPriority::Background);
 
             if let Some(ref tag) = etag {
                 request.add_header(header::IF_NONE_MATCH, tag)?;
             }
 
-            let response = service.request(request).await?;
+            let response = response_cache.request(&service, request).await?;
             if etag.is_some() && response.status() == StatusCode::NOT_MODIFIED {
                 return Ok(None);
             }
@@ -133,6 +135,7 @@ impl ServerRelayList {
         let openvpn_endpoint_data = openvpn.ports;
         for mut openvpn_relay in openvpn.relays.into_iter() {
             openvpn_relay.to_lower();
+            let metadata = openvpn_relay.metadata.or(&openvpn.metadata);
             if let Some((country_code, city_code)) = split_location_code(&openvpn_relay.location) {
                 if let Some(country) = countries.get_mut(country_code) {
                     if let Some(city) = country
@@ -153,10 +156,14 @@ impl ServerRelayList {
                             .iter_mut()
                             .find(|r| r.hostname == openvpn_relay.hostname)
                         {
-                            Some(relay) => relay.tunnels.openvpn = openvpn_endpoint_data.clone(),
+                            Some(relay) => {
+                                relay.tunnels.openvpn = openvpn_endpoint_data.clone();
+                                relay.metadata = relay.metadata.or(&metadata);
+                            }
                             None => {
                                 let mut relay = relay(openvpn_relay, location);
                                 relay.tunnels.openvpn = openvpn_endpoint_data.clone();
+                                relay.metadata = metadata;
                                 city.relays.push(relay);
                             }
                         };
@@ -174,20 +181,30 @@ impl ServerRelayList {
             port_ranges,
             ipv4_gateway,
             ipv6_gateway,
+            shadowsocks,
+            metadata,
             relays,
         } = wireguard;
 
+        // Only UDP endpoints can relay WireGuard traffic
+        let shadowsocks: Vec<_> = shadowsocks
+            .into_iter()
+            .filter(|endpoint| endpoint.protocol == TransportProtocol::Udp)
+            .collect();
+
         let wireguard_endpoint_data =
             |public_key: wireguard::PublicKey| relay_list::WireguardEndpointData {
                 port_ranges: port_ranges.clone(),
                 ipv4_gateway,
                 ipv6_gateway,
                 public_key,
+                shadowsocks: shadowsocks.clone(),
                 protocol: TransportProtocol::Udp,
             };
 
         for mut wireguard_relay in relays {
             wireguard_relay.relay.to_lower();
+            let relay_metadata = wireguard_relay.relay.metadata.or(&metadata);
             if let Some((country_code, city_code)) =
                 split_location_code(&wireguard_relay.relay.location)
             {
@@ -210,15 +227,19 @@ impl ServerRelayList {
                             .iter_mut()
                             .find(|r| r.hostname == wireguard_relay.relay.hostname)
                         {
-                            Some(relay) => relay
-                                .tunnels
-                                .wireguard
-                                .push(wireguard_endpoint_data(wireguard_relay.public_key)),
+                            Some(relay) => {
+                                relay
+                                    .tunnels
+                                    .wireguard
+                                    .push(wireguard_endpoint_data(wireguard_relay.public_key));
+                                relay.metadata = relay.metadata.or(&relay_metadata);
+                            }
                             None => {
                                 let mut relay = relay(wireguard_relay.relay, location);
                                 relay.ipv6_addr_in = Some(wireguard_relay.ipv6_addr_in);
                                 relay.tunnels.wireguard =
                                     vec![wireguard_endpoint_data(wireguard_relay.public_key)];
+                                relay.metadata = relay_metadata;
                                 city.relays.push(relay);
                             }
                         };
@@ -318,6 +339,7 @@ fn relay(relay: Relay, location: location::Location) -> relay_list::Relay {
         tunnels: Default::default(),
         bridges: Default::default(),
         location: Some(location),
+        metadata: relay.metadata,
     }
 }
 
@@ -332,6 +354,9 @@ struct Location {
 #[derive(Debug, serde::Deserialize)]
 struct OpenVpn {
     ports: Vec<relay_list::OpenVpnEndpointData>,
+    /// Hints for every OpenVPN relay, unless a relay overrides them.
+    #[serde(default)]
+    metadata: relay_list::RelayMetadata,
     relays: Vec<Relay>,
 }
 
@@ -345,6 +370,8 @@ struct Relay {
     ipv4_addr_in: Ipv4Addr,
     weight: u64,
     include_in_country: bool,
+    #[serde(default)]
+    metadata: relay_list::RelayMetadata,
 }
 
 impl Relay {
@@ -359,6 +386,11 @@ struct Wireguard {
     port_ranges: Vec<(u16, u16)>,
     ipv4_gateway: Ipv4Addr,
     ipv6_gateway: Ipv6Addr,
+    #[serde(default)]
+    shadowsocks: Vec<relay_list::ShadowsocksEndpointData>,
+    /// Hints for every WireGuard relay, unless a relay overrides them.
+    #[serde(default)]
+    metadata: relay_list::RelayMetadata,
     relays: Vec<WireGuardRelay>,
 }
 
@@ -0,0 +1,391 @@
+//! Resolution of the API hostname. System DNS is often the first thing that is blocked on
+//! restrictive networks, so the hostname can also be resolved using DNS over HTTPS, or not at all,
+//! in which case only the addresses that are bundled with the app or received from the API are
+//! used.
+#[cfg(target_os = "android")]
+use crate::https_client_with_sni::SocketBypassRequest;
+use crate::{
+    address_cache::AddressCache, https_client_with_sni::HttpsConnectorWithSni, pinning::PinStore,
+    proxy::ProxyHandle, API_HOST,
+};
+use chrono::Utc;
+#[cfg(target_os = "android")]
+use futures::channel::mpsc;
+use hyper::header::{self, HeaderValue};
+use mullvad_types::api_access::{ApiResolution, ApiResolutionStrategy};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use talpid_types::ErrorExt;
+
+/// Hostname of the DNS over HTTPS resolver. Its certificate is verified using a pin set of its
+/// own, see [`PinStore::doh`].
+const DOH_HOST: &str = "doh.mullvad.net";
+/// Addresses of the DNS over HTTPS resolver, so that it can be reached without resolving its
+/// hostname first. They are tried in order, so that hosts without IPv4 connectivity fall back on
+/// the IPv6 address.
+const DOH_ADDRESSES: [IpAddr; 2] = [
+    IpAddr::V4(Ipv4Addr::new(193, 19, 108, 2)),
+    IpAddr::V6(Ipv6Addr::new(0x2a07, 0xe340, 0, 0, 0, 0, 0, 2)),
+];
+const DOH_MEDIA_TYPE: &str = "application/dns-message";
+
+/// How long to wait for the API hostname to be resolved.
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Port that resolved API addresses are reached on.
+const API_PORT: u16 = 443;
+
+const DNS_TYPE_A: u16 = 1;
+const DNS_TYPE_AAAA: u16 = 28;
+const DNS_CLASS_IN: u16 = 1;
+const DNS_HEADER_SIZE: usize = 12;
+/// Flags for a standard query with recursion desired.
+const DNS_QUERY_FLAGS: u16 = 0x0100;
+
+/// Resolves the API hostname using the selected strategy, and adds the resolved addresses to the
+/// address cache.
+#[derive(Clone)]
+pub struct ApiResolver {
+    inner: Arc<Mutex<ResolverState>>,
+    address_cache: AddressCache,
+    doh_client: hyper::Client<HttpsConnectorWithSni, hyper::Body>,
+}
+
+#[derive(Default)]
+struct ResolverState {
+    strategy: ApiResolutionStrategy,
+    last_resolution: Option<ApiResolution>,
+}
+
+impl ApiResolver {
+    pub(crate) fn new(
+        handle: tokio::runtime::Handle,
+        address_cache: AddressCache,
+        proxy: ProxyHandle,
+        #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
+    ) -> Self {
+        let connector = HttpsConnectorWithSni::new(
+            handle,
+            Some(DOH_HOST.to_owned()),
+            PinStore::doh(),
+            proxy,
+            #[cfg(target_os = "android")]
+            socket_bypass_tx,
+        );
+        ApiResolver {
+            inner: Arc::new(Mutex::new(ResolverState::default())),
+            address_cache,
+            doh_client: hyper::Client::builder().build(connector),
+        }
+    }
+
+    pub fn strategy(&self) -> ApiResolutionStrategy {
+        self.inner.lock().unwrap().strategy
+    }
+
+    /// Sets the strategy used by subsequent resolutions. The result of the previous resolution
+    /// is discarded if the strategy changes.
+    pub fn set_strategy(&self, strategy: ApiResolutionStrategy) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.strategy != strategy {
+            inner.strategy = strategy;
+            inner.last_resolution = None;
+        }
+    }
+
+    pub fn last_resolution(&self) -> Option<ApiResolution> {
+        self.inner.lock().unwrap().last_resolution.clone()
+    }
+
+    /// Resolves the API hostname and adds the result to the address cache. Nothing is done if
+    /// the strategy is [`ApiResolutionStrategy::Static`].
+    pub async fn update_addresses(&self) {
+        let strategy = self.strategy();
+        let result = match strategy {
+            ApiResolutionStrategy::Static => return,
+            ApiResolutionStrategy::System => self.resolve_system().await,
+            ApiResolutionStrategy::DnsOverHttps => self.resolve_doh().await,
+        };
+
+        let resolution = match result {
+            Ok(addresses) => {
+                log::debug!(
+                    "Resolved {} to {:?} using {}",
+                    API_HOST,
+                    addresses,
+                    strategy
+                );
+                ApiResolution {
+                    strategy,
+                    timestamp: Utc::now(),
+                    addresses,
+                    error: None,
+                }
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg(&format!(
+                        "Failed to resolve {} using {}",
+                        API_HOST, strategy
+                    ))
+                );
+                ApiResolution {
+                    strategy,
+                    timestamp: Utc::now(),
+                    addresses: vec![],
+                    error: Some(error.to_string()),
+                }
+            }
+        };
+
+        let addresses = resolution
+            .addresses
+            .iter()
+            .map(|ip| SocketAddr::new(*ip, API_PORT))
+            .collect::<Vec<_>>();
+        {
+            let mut inner = self.inner.lock().unwrap();
+            // Discard the result if the strategy changed while resolving
+            if inner.strategy != strategy {
+                return;
+            }
+            inner.last_resolution = Some(resolution);
+        }
+
+        if !addresses.is_empty() {
+            if let Err(error) = self.address_cache.add_addresses(addresses).await {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to add resolved API addresses")
+                );
+            }
+        }
+    }
+
+    async fn resolve_system(&self) -> io::Result<Vec<IpAddr>> {
+        let addresses = tokio::time::timeout(
+            RESOLVE_TIMEOUT,
+            tokio::net::lookup_host((API_HOST, API_PORT)),
+        )
+        .await
+        .map_err(|error| io::Error::new(io::ErrorKind::TimedOut, error))??;
+        non_empty(addresses.map(|address| address.ip()).collect())
+    }
+
+    async fn resolve_doh(&self) -> io::Result<Vec<IpAddr>> {
+        let (ipv4, ipv6) = tokio::time::timeout(
+            RESOLVE_TIMEOUT,
+            futures::future::join(self.doh_query(DNS_TYPE_A), self.doh_query(DNS_TYPE_AAAA)),
+        )
+        .await
+        .map_err(|error| io::Error::new(io::ErrorKind::TimedOut, error))?;
+
+        let mut addresses = ipv4?;
+        // IPv6 connectivity is optional, so a failed AAAA query is not an error
+        addresses.extend(ipv6.unwrap_or_default());
+        non_empty(addresses)
+    }
+
+    async fn doh_query(&self, record_type: u16) -> io::Result<Vec<IpAddr>> {
+        let query =
+            base64::encode_config(encode_query(API_HOST, record_type), base64::URL_SAFE_NO_PAD);
+        let mut last_error = None;
+        for address in &DOH_ADDRESSES {
+            match self.doh_query_address(*address, &query).await {
+                Ok(addresses) => return Ok(addresses),
+                Err(error) => {
+                    log::debug!(
+                        "{}",
+                        error.display_chain_with_msg(&format!(
+                            "DNS over HTTPS request to {} failed",
+                            address
+                        ))
+                    );
+                    last_error = Some(error);
+                }
+            }
+        }
+        Err(last_error.expect("there is at least one DNS over HTTPS address"))
+    }
+
+    async fn doh_query_address(&self, address: IpAddr, query: &str) -> io::Result<Vec<IpAddr>> {
+        let request = hyper::Request::get(format!(
+            "https://{}/dns-query?dns={}",
+            SocketAddr::new(address, API_PORT),
+            query
+        ))
+        .header(header::HOST, HeaderValue::from_static(DOH_HOST))
+        .header(header::ACCEPT, HeaderValue::from_static(DOH_MEDIA_TYPE))
+        .body(hyper::Body::empty())
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+
+        let response = self
+            .doh_client
+            .request(request)
+            .await
+            .map_err(|error| io::Error::new(io::ErrorKind::Other, error))?;
+        if !response.status().is_success() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("DNS over HTTPS request failed: {}", response.status()),
+            ));
+        }
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|error| io::Error::new(io::ErrorKind::Other, error))?;
+        parse_response(&body)
+    }
+}
+
+fn non_empty(addresses: Vec<IpAddr>) -> io::Result<Vec<IpAddr>> {
+    if addresses.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "The hostname did not resolve to any addresses",
+        ));
+    }
+    Ok(addresses)
+}
+
+/// Encodes a DNS query for records of type `record_type` for `hostname`. The ID is zero, as
+/// recommended for DNS over HTTPS.
+fn encode_query(hostname: &str, record_type: u16) -> Vec<u8> {
+    let mut query = Vec::with_capacity(DNS_HEADER_SIZE + hostname.len() + 6);
+    query.extend_from_slice(&0u16.to_be_bytes());
+    query.extend_from_slice(&DNS_QUERY_FLAGS.to_be_bytes());
+    // One question, no answer, authority or additional records
+    query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in hostname.split('.') {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&record_type.to_be_bytes());
+    query.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+    query
+}
+
+/// Returns the addresses in the A and AAAA records of a DNS response.
+fn parse_response(response: &[u8]) -> io::Result<Vec<IpAddr>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid DNS response");
+
+    if response.len() < DNS_HEADER_SIZE {
+        return Err(invalid());
+    }
+    let read_u16 = |offset: usize| -> io::Result<u16> {
+        response
+            .get(offset..offset + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+            .ok_or_else(invalid)
+    };
+
+    let response_code = read_u16(2)? & 0x000f;
+    if response_code != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("DNS query failed with response code {}", response_code),
+        ));
+    }
+    let question_count = read_u16(4)?;
+    let answer_count = read_u16(6)?;
+
+    let mut offset = DNS_HEADER_SIZE;
+    for _ in 0..question_count {
+        // Skip the name, type and class
+        offset = skip_name(response, offset).ok_or_else(invalid)? + 4;
+    }
+
+    let mut addresses = vec![];
+    for _ in 0..answer_count {
+        offset = skip_name(response, offset).ok_or_else(invalid)?;
+        let record_type = read_u16(offset)?;
+        // Skip the class and TTL
+        let data_len = usize::from(read_u16(offset + 8)?);
+        let data_offset = offset + 10;
+        let data = response
+            .get(data_offset..data_offset + data_len)
+            .ok_or_else(invalid)?;
+        match (record_type, data_len) {
+            (DNS_TYPE_A, 4) => {
+                addresses.push(IpAddr::V4(Ipv4Addr::new(
+                    data[0], data[1], data[2], data[3],
+                )));
+            }
+            (DNS_TYPE_AAAA, 16) => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(data);
+                addresses.push(IpAddr::V6(Ipv6Addr::from(octets)));
+            }
+            // CNAME records and others are not followed
+            _ => (),
+        }
+        offset = data_offset + data_len;
+    }
+    Ok(addresses)
+}
+
+/// Returns the offset of the first byte after the name at `offset`.
+fn skip_name(message: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let len = *message.get(offset)?;
+        if len & 0xc0 == 0xc0 {
+            // A compressed name ends with a pointer
+            return Some(offset + 2);
+        }
+        if len == 0 {
+            return Some(offset + 1);
+        }
+        offset += 1 + usize::from(len);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encode_query() {
+        let query = encode_query("api.mullvad.net", DNS_TYPE_A);
+        assert_eq!(
+            &query[..DNS_HEADER_SIZE],
+            &[0, 0, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            &query[DNS_HEADER_SIZE..],
+            b"\x03api\x07mullvad\x03net\x00\x00\x01\x00\x01"
+        );
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let mut response = encode_query("api.mullvad.net", DNS_TYPE_A);
+        // Mark as a response with two answers
+        response[2] = 0x81;
+        response[3] = 0x80;
+        response[7] = 2;
+        // A CNAME record, which is skipped, followed by an A record. Both refer to the name in
+        // the question.
+        response.extend_from_slice(&[0xc0, 0x0c, 0, 5, 0, 1, 0, 0, 0, 60, 0, 2, 0xc0, 0x0c]);
+        response.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+        response.extend_from_slice(&[193, 138, 218, 78]);
+
+        assert_eq!(
+            parse_response(&response).unwrap(),
+            vec![IpAddr::V4(Ipv4Addr::new(193, 138, 218, 78))]
+        );
+    }
+
+    #[test]
+    fn test_parse_error_response() {
+        let mut response = encode_query("api.mullvad.net", DNS_TYPE_A);
+        // NXDOMAIN
+        response[2] = 0x81;
+        response[3] = 0x83;
+        assert!(parse_response(&response).is_err());
+        assert!(parse_response(&response[..DNS_HEADER_SIZE - 1]).is_err());
+    }
+}
@@ -0,0 +1,270 @@
+//! A disk-backed cache of API responses, used to make conditional GET requests. Responses that
+//! carry an `ETag` header are stored per endpoint, and subsequent requests to the same endpoint
+//! include an `If-None-Match` header. If the API responds with `304 Not Modified`, the cached
+//! body is returned to the caller as if it had been sent by the API.
+use crate::rest::{self, RequestServiceHandle, Response, RestRequest};
+use futures::stream::StreamExt;
+use hyper::{header, StatusCode};
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use talpid_types::ErrorExt;
+use tokio::{fs, io::AsyncWriteExt};
+
+/// Name of the directory, relative to the cache directory, that holds cached responses.
+pub const RESPONSE_CACHE_DIR: &str = "api-responses";
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+struct CachedResponse {
+    etag: String,
+    body: String,
+}
+
+#[derive(Clone)]
+pub struct ResponseCache {
+    entries: Arc<Mutex<HashMap<String, Option<CachedResponse>>>>,
+    dir: Option<Arc<Path>>,
+    write_changes: bool,
+}
+
+impl ResponseCache {
+    /// Creates a cache that reads and, if `write_changes` is set, writes responses to `dir`.
+    pub fn new(dir: PathBuf, write_changes: bool) -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(HashMap::new())),
+            dir: Some(dir.into_boxed_path().into()),
+            write_changes,
+        }
+    }
+
+    /// Creates a cache that only keeps responses in memory.
+    pub fn in_memory() -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(HashMap::new())),
+            dir: None,
+            write_changes: false,
+        }
+    }
+
+    /// Sends `request` using `service`, using a cached response for the same endpoint to make
+    /// the request conditional. If the caller has set an `If-None-Match` header itself, a
+    /// `304 Not Modified` response is passed through unchanged.
+    pub async fn request(
+        &self,
+        service: &RequestServiceHandle,
+        mut request: RestRequest,
+    ) -> rest::Result<Response> {
+        let key = cache_key(&request);
+        let caller_etag = request.has_header(header::IF_NONE_MATCH);
+        let cached = self.get(&key).await;
+
+        if let (Some(cached), false) = (&cached, caller_etag) {
+            request.add_header(header::IF_NONE_MATCH, &cached.etag)?;
+        }
+
+        let response = service.request(request).await?;
+        match response.status() {
+            StatusCode::NOT_MODIFIED if !caller_etag => match cached {
+                Some(cached) => {
+                    log::debug!("Using cached response for {}", key);
+                    cached_response(cached)
+                }
+                None => Ok(response),
+            },
+            StatusCode::OK => self.store_response(key, response).await,
+            _ => Ok(response),
+        }
+    }
+
+    async fn store_response(&self, key: String, response: Response) -> rest::Result<Response> {
+        let etag = match response
+            .headers()
+            .get(header::ETAG)
+            .and_then(|etag| etag.to_str().ok())
+        {
+            Some(etag) => etag.to_owned(),
+            None => return Ok(response),
+        };
+
+        let (parts, mut body) = response.into_parts();
+        let mut bytes = Vec::new();
+        while let Some(chunk) = body.next().await {
+            bytes.extend(&chunk?);
+        }
+
+        if let Ok(body) = String::from_utf8(bytes.clone()) {
+            let entry = CachedResponse { etag, body };
+            if let Err(error) = self.write_entry(&key, &entry).await {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to save cached API response")
+                );
+            }
+            self.entries.lock().unwrap().insert(key, Some(entry));
+        }
+
+        Ok(Response::from_parts(parts, bytes.into()))
+    }
+
+    async fn get(&self, key: &str) -> Option<CachedResponse> {
+        if let Some(entry) = self.entries.lock().unwrap().get(key) {
+            return entry.clone();
+        }
+        let entry = match self.read_entry(key).await {
+            Ok(entry) => entry,
+            Err(error) => {
+                if error.kind() != io::ErrorKind::NotFound {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to read cached API response")
+                    );
+                }
+                None
+            }
+        };
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(key.to_owned(), entry.clone());
+        entry
+    }
+
+    async fn read_entry(&self, key: &str) -> io::Result<Option<CachedResponse>> {
+        let path = match self.entry_path(key) {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let contents = fs::read(path).await?;
+        serde_json::from_slice(&contents)
+            .map(Some)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+
+    async fn write_entry(&self, key: &str, entry: &CachedResponse) -> io::Result<()> {
+        if !self.write_changes {
+            return Ok(());
+        }
+        let path = match self.entry_path(key) {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        let contents = serde_json::to_vec(entry)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        let temp_path = path.with_extension("temp");
+        let mut file = fs::File::create(&temp_path).await?;
+        file.write_all(&contents).await?;
+        file.sync_data().await?;
+
+        fs::rename(&temp_path, path).await
+    }
+
+    fn entry_path(&self, key: &str) -> Option<PathBuf> {
+        let file_name: String = key
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        self.dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.json", file_name.trim_matches('_'))))
+    }
+}
+
+/// Request headers that the API may use to select the response, and which must therefore be part
+/// of the cache key.
+const VARYING_HEADERS: &[&str] = &[crate::PLATFORM_VERSION_HEADER];
+
+/// Identifies the endpoint a request is sent to, along with any headers that affect the response.
+/// The host is left out since it varies with the API address in use.
+fn cache_key(request: &RestRequest) -> String {
+    let mut key = request
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_default();
+    for name in VARYING_HEADERS {
+        if let Some(value) = request.header(*name).and_then(|value| value.to_str().ok()) {
+            key.push_str(&format!(" {}={}", name.to_lowercase(), value));
+        }
+    }
+    key
+}
+
+fn cached_response(cached: CachedResponse) -> rest::Result<Response> {
+    let response = http::response::Builder::new()
+        .status(StatusCode::OK)
+        .header(header::ETAG, cached.etag)
+        .header(header::CONTENT_LENGTH, cached.body.len())
+        .body(cached.body.into())?;
+    Ok(response)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_entry_path() {
+        let cache = ResponseCache::new(PathBuf::from("/cache"), false);
+        assert_eq!(
+            cache.entry_path("/app/v1/releases/linux/2021.4"),
+            Some(PathBuf::from("/cache/app_v1_releases_linux_2021_4.json"))
+        );
+        assert_eq!(
+            ResponseCache::in_memory().entry_path("/app/v1/relays"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let request = |platform_version: Option<&str>| {
+            let mut request = RestRequest::from(
+                hyper::Request::get("https://api.mullvad.net/v1/releases/linux/2021.4")
+                    .body(hyper::Body::empty())
+                    .unwrap(),
+            );
+            if let Some(platform_version) = platform_version {
+                request
+                    .add_header(crate::PLATFORM_VERSION_HEADER, platform_version)
+                    .unwrap();
+            }
+            request
+        };
+
+        assert_eq!(cache_key(&request(None)), "/v1/releases/linux/2021.4");
+        assert_eq!(
+            cache_key(&request(Some("Ubuntu 20.04"))),
+            "/v1/releases/linux/2021.4 m-platform-version=Ubuntu 20.04"
+        );
+        assert_ne!(
+            cache_key(&request(Some("Ubuntu 20.04"))),
+            cache_key(&request(Some("Ubuntu 21.04")))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_written_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ResponseCache::new(dir.path().join(RESPONSE_CACHE_DIR), true);
+        let entry = CachedResponse {
+            etag: "W/\"abc\"".to_owned(),
+            body: "[]".to_owned(),
+        };
+        cache
+            .write_entry("/app/v1/api-addrs", &entry)
+            .await
+            .unwrap();
+
+        let cache = ResponseCache::new(dir.path().join(RESPONSE_CACHE_DIR), false);
+        let read_entry = cache.get("/app/v1/api-addrs").await.unwrap();
+        assert_eq!(read_entry.etag, entry.etag);
+        assert_eq!(read_entry.body, entry.body);
+    }
+}
@@ -1,28 +1,40 @@
 use crate::{
-    address_cache::AddressCache, availability::ApiAvailabilityHandle,
-    https_client_with_sni::HttpsConnectorWithSni, tcp_stream::TcpStreamHandle,
+    address_cache::AddressCache,
+    availability::ApiAvailabilityHandle,
+    clock::{ClockCheck, ClockSkew},
+    https_client_with_sni::HttpsConnectorWithSni,
+    proxy::ProxyHandle,
+    response_cache::ResponseCache,
+    tcp_stream::TcpStreamHandle,
+    trace::{ApiTrace, TraceEntry},
 };
 use futures::{
     channel::{mpsc, oneshot},
-    future::{abortable, AbortHandle, Aborted},
+    future::{abortable, AbortHandle, Aborted, FutureExt},
     sink::SinkExt,
     stream::StreamExt,
     TryFutureExt,
 };
 use hyper::{
+    body::HttpBody,
     client::Client,
     header::{self, HeaderValue},
     Method, Uri,
 };
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, VecDeque},
+    fmt,
     future::Future,
     mem,
     net::{IpAddr, SocketAddr},
     str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
-use talpid_types::ErrorExt;
+use talpid_types::{cancel::CancelToken, ErrorExt};
 use tokio::runtime::Handle;
 
 pub use hyper::StatusCode;
@@ -30,10 +42,22 @@ pub use hyper::StatusCode;
 pub type Request = hyper::Request<hyper::Body>;
 pub type Response = hyper::Response<hyper::Body>;
 
-const TIMER_CHECK_INTERVAL: Duration = Duration::from_secs(60);
-const API_IP_CHECK_DELAY: Duration = Duration::from_secs(15 * 60);
-const API_IP_CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
-const API_IP_CHECK_ERROR_INTERVAL: Duration = Duration::from_secs(15 * 60);
+/// Time to wait after startup before fetching the list of API addresses.
+pub const API_IP_CHECK_DELAY: Duration = Duration::from_secs(15 * 60);
+/// How often the list of API addresses is fetched.
+pub const API_IP_CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// How long to wait before fetching the list of API addresses again after a failed attempt.
+pub const API_IP_CHECK_ERROR_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// How long an idle connection to the API is kept open for reuse by subsequent requests.
+const CONNECTION_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Maximum number of background requests that may be in flight at the same time.
+const MAX_CONCURRENT_BACKGROUND_REQUESTS: usize = 1;
+
+/// Header that makes the API process a request at most once. Repeated requests with the same key
+/// receive the response to the first one.
+const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
 
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -68,11 +92,19 @@ pub enum Error {
 
     /// Unexpected response code
     #[error(display = "Unexpected response status code {} - {}", _0, _1)]
-    ApiError(StatusCode, String),
+    ApiError(StatusCode, ApiErrorCode),
 
     /// The string given was not a valid URI.
     #[error(display = "Not a valid URI")]
     UriError(#[error(source)] http::uri::InvalidUri),
+
+    /// Too many requests have been made. Contains the time to wait before trying again, if known.
+    #[error(display = "Too many requests")]
+    RateLimited(Option<Duration>),
+
+    /// The certificate of the API was rejected because the system clock is wrong.
+    #[error(display = "The system clock is {} compared to the API", _0)]
+    ClockSkew(ClockSkew),
 }
 
 impl Error {
@@ -82,6 +114,89 @@ impl Error {
             _ => false,
         }
     }
+
+    /// Returns the error code sent by the API, if this is an error response from the API.
+    pub fn api_error_code(&self) -> Option<&ApiErrorCode> {
+        match self {
+            Error::ApiError(_status, code) => Some(code),
+            _ => None,
+        }
+    }
+
+    /// Returns how long to wait before trying again, if the request was rate limited.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::RateLimited(retry_after) => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+/// Reason for an error response from the API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiErrorCode {
+    /// The account token is invalid.
+    InvalidAccount,
+    /// The account token is missing or invalid.
+    InvalidAuth,
+    /// The voucher code is invalid.
+    InvalidVoucher,
+    /// The voucher has already been used.
+    VoucherUsed,
+    /// The voucher is no longer valid.
+    VoucherExpired,
+    /// The account has too many WireGuard keys.
+    KeyLimitReached,
+    /// The account has too many devices.
+    MaxDevicesReached,
+    /// The device has been removed from the account.
+    DeviceNotFound,
+    /// The resource does not exist. The API does not include an error code in this case.
+    NotFound,
+    /// The method is not allowed. The API does not include an error code in this case.
+    MethodNotAllowed,
+    /// An error code that is not known to this version of the app.
+    Other(String),
+}
+
+impl ApiErrorCode {
+    /// Interprets an error code in an error response body.
+    pub fn from_code(code: String) -> Self {
+        match code.as_str() {
+            crate::INVALID_ACCOUNT => ApiErrorCode::InvalidAccount,
+            crate::INVALID_AUTH => ApiErrorCode::InvalidAuth,
+            crate::INVALID_VOUCHER => ApiErrorCode::InvalidVoucher,
+            crate::VOUCHER_USED => ApiErrorCode::VoucherUsed,
+            crate::VOUCHER_EXPIRED => ApiErrorCode::VoucherExpired,
+            crate::KEY_LIMIT_REACHED => ApiErrorCode::KeyLimitReached,
+            crate::MAX_DEVICES_REACHED => ApiErrorCode::MaxDevicesReached,
+            crate::DEVICE_NOT_FOUND => ApiErrorCode::DeviceNotFound,
+            _ => ApiErrorCode::Other(code),
+        }
+    }
+
+    /// Returns the error code as sent by the API.
+    pub fn as_str(&self) -> &str {
+        match self {
+            ApiErrorCode::InvalidAccount => crate::INVALID_ACCOUNT,
+            ApiErrorCode::InvalidAuth => crate::INVALID_AUTH,
+            ApiErrorCode::InvalidVoucher => crate::INVALID_VOUCHER,
+            ApiErrorCode::VoucherUsed => crate::VOUCHER_USED,
+            ApiErrorCode::VoucherExpired => crate::VOUCHER_EXPIRED,
+            ApiErrorCode::KeyLimitReached => crate::KEY_LIMIT_REACHED,
+            ApiErrorCode::MaxDevicesReached => crate::MAX_DEVICES_REACHED,
+            ApiErrorCode::DeviceNotFound => crate::DEVICE_NOT_FOUND,
+            ApiErrorCode::NotFound => "Not found",
+            ApiErrorCode::MethodNotAllowed => "Method not allowed",
+            ApiErrorCode::Other(code) => code,
+        }
+    }
+}
+
+impl fmt::Display for ApiErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 /// A service that executes HTTP requests, allowing for on-demand termination of all in-flight
@@ -93,11 +208,26 @@ pub(crate) struct RequestService {
     client: hyper::Client<HttpsConnectorWithSni, hyper::Body>,
     handle: Handle,
     next_id: u64,
-    in_flight_requests: BTreeMap<u64, AbortHandle>,
+    in_flight_requests: BTreeMap<u64, InFlightRequest>,
+    background_queue: VecDeque<(RestRequest, ResponseTx)>,
+    waiting_for_connectivity: bool,
     api_availability: ApiAvailabilityHandle,
     address_cache: AddressCache,
+    api_trace: ApiTrace,
+    clock_check: ClockCheck,
+    clock_check_connector: HttpsConnectorWithSni,
+    proxy: ProxyHandle,
+    /// Aborts all requests and stops the service when cancelled.
+    cancel_token: CancelToken,
+}
+
+struct InFlightRequest {
+    abort_handle: AbortHandle,
+    priority: RequestPriority,
 }
 
+type ResponseTx = oneshot::Sender<std::result::Result<Response, Error>>;
+
 impl RequestService {
     /// Constructs a new request service.
     pub fn new(
@@ -105,11 +235,19 @@ impl RequestService {
         handle: Handle,
         api_availability: ApiAvailabilityHandle,
         address_cache: AddressCache,
+        api_trace: ApiTrace,
+        clock_check: ClockCheck,
+        proxy: ProxyHandle,
+        cancel_token: CancelToken,
     ) -> RequestService {
         let (command_tx, command_rx) = mpsc::channel(1);
 
+        let clock_check_connector = connector.for_clock_check();
         connector.set_service_tx(command_tx.clone());
-        let client = Client::builder().build(connector);
+        let client = Client::builder()
+            .pool_idle_timeout(CONNECTION_IDLE_TIMEOUT)
+            .pool_max_idle_per_host(1)
+            .build(connector);
 
         Self {
             command_tx,
@@ -117,10 +255,17 @@ impl RequestService {
             sockets: BTreeMap::new(),
             client,
             in_flight_requests: BTreeMap::new(),
+            background_queue: VecDeque::new(),
+            waiting_for_connectivity: false,
             next_id: 0,
             handle,
             api_availability,
             address_cache,
+            api_trace,
+            clock_check,
+            clock_check_connector,
+            proxy,
+            cancel_token,
         }
     }
 
@@ -134,67 +279,13 @@ impl RequestService {
 
     fn process_command(&mut self, command: RequestCommand) {
         match command {
-            RequestCommand::NewRequest(request, completion_tx) => {
-                let id = self.id();
-                let mut tx = self.command_tx.clone();
-                let timeout = request.timeout();
-
-                let hyper_request = request.into_request();
-                let host_addr = get_request_socket_addr(&hyper_request);
-
-                let (request_future, abort_handle) =
-                    abortable(self.client.request(hyper_request).map_err(Error::from));
-                let address_cache = self.address_cache.clone();
-                let handle = self.handle.clone();
-                let api_availability = self.api_availability.clone();
-
-                let future = async move {
-                    let response =
-                        tokio::time::timeout(timeout, request_future.map_err(Error::Aborted))
-                            .await
-                            .map_err(Error::TimeoutError);
-
-                    let response = flatten_result(flatten_result(response));
-                    if let Some(host_addr) = host_addr {
-                        if let Err(err) = &response {
-                            if err.is_network_error() {
-                                log::error!(
-                                    "{}",
-                                    err.display_chain_with_msg("HTTP request failed")
-                                );
-                                if !api_availability.get_state().is_offline() {
-                                    let current_address = address_cache.peek_address();
-                                    if current_address == host_addr
-                                        && address_cache.has_tried_current_address()
-                                    {
-                                        handle.spawn(async move {
-                                            address_cache.select_new_address().await;
-                                            let new_address = address_cache.peek_address();
-                                            log::error!(
-                                                "Request failed using address {}. Trying next API address: {}",
-                                                current_address,
-                                                new_address,
-                                            );
-                                        });
-                                    }
-                                }
-                            }
-                        }
-                    }
-
-
-                    if completion_tx.send(response).is_err() {
-                        log::trace!(
-                            "Failed to send response to caller, caller channel is shut down"
-                        );
-                    }
-                    let _ = tx.send(RequestCommand::RequestFinished(id)).await;
-                };
-
-
-                self.handle.spawn(future);
-                self.in_flight_requests.insert(id, abort_handle);
-            }
+            RequestCommand::NewRequest(request, completion_tx) => match request.priority() {
+                RequestPriority::Interactive => self.spawn_request(request, completion_tx),
+                RequestPriority::Background => {
+                    self.background_queue.push_back((request, completion_tx));
+                    self.process_background_queue();
+                }
+            },
 
             RequestCommand::SocketOpened(id, socket) => {
                 self.sockets.insert(id, socket);
@@ -204,6 +295,11 @@ impl RequestService {
             }
             RequestCommand::RequestFinished(id) => {
                 self.in_flight_requests.remove(&id);
+                self.process_background_queue();
+            }
+            RequestCommand::ConnectivityRestored => {
+                self.waiting_for_connectivity = false;
+                self.process_background_queue();
             }
 
             RequestCommand::Reset(tx) => {
@@ -213,10 +309,171 @@ impl RequestService {
         }
     }
 
+    /// Starts as many queued background requests as possible. Background requests are held back
+    /// while any interactive request is in flight, and while the host is offline.
+    fn process_background_queue(&mut self) {
+        while !self.background_queue.is_empty() {
+            if self.api_availability.get_state().is_offline() {
+                self.wait_for_connectivity();
+                return;
+            }
+            let mut in_flight_background = 0;
+            for request in self.in_flight_requests.values() {
+                match request.priority {
+                    RequestPriority::Interactive => return,
+                    RequestPriority::Background => in_flight_background += 1,
+                }
+            }
+            if in_flight_background >= MAX_CONCURRENT_BACKGROUND_REQUESTS {
+                return;
+            }
+
+            let (request, completion_tx) = self.background_queue.pop_front().unwrap();
+            if completion_tx.is_canceled() {
+                continue;
+            }
+            self.spawn_request(request, completion_tx);
+        }
+    }
+
+    fn wait_for_connectivity(&mut self) {
+        if self.waiting_for_connectivity {
+            return;
+        }
+        self.waiting_for_connectivity = true;
+        log::debug!(
+            "Queueing {} background API request(s) until the host comes online",
+            self.background_queue.len()
+        );
+
+        let wait_online = self.api_availability.wait_online();
+        let mut tx = self.command_tx.clone();
+        self.handle.spawn(async move {
+            if wait_online.await.is_ok() {
+                let _ = tx.send(RequestCommand::ConnectivityRestored).await;
+            }
+        });
+    }
+
+    fn spawn_request(&mut self, request: RestRequest, completion_tx: ResponseTx) {
+        let id = self.id();
+        let mut tx = self.command_tx.clone();
+        let timeout = request.timeout();
+        let priority = request.priority();
+
+        let hyper_request = request.into_request();
+        let host_addr = get_request_socket_addr(&hyper_request);
+        let host_header = hyper_request.headers().get(header::HOST).cloned();
+        let retry_request = try_clone_idempotent_request(&hyper_request);
+        let method = hyper_request.method().to_string();
+        let path = hyper_request.uri().path().to_owned();
+
+        let client = self.client.clone();
+        let retried = Arc::new(AtomicBool::new(false));
+        let request_retried = retried.clone();
+        let (request_future, abort_handle) = abortable(
+            async move {
+                match client.request(hyper_request).await {
+                    Err(error) if is_stale_connection_error(&error) => match retry_request {
+                        Some(retry_request) => {
+                            log::debug!("Retrying request on a new connection: {}", error);
+                            request_retried.store(true, Ordering::SeqCst);
+                            client.request(retry_request).await
+                        }
+                        None => Err(error),
+                    },
+                    response => response,
+                }
+            }
+            .map_err(Error::from),
+        );
+        let address_cache = self.address_cache.clone();
+        let handle = self.handle.clone();
+        let api_availability = self.api_availability.clone();
+        let api_trace = self.api_trace.clone();
+        let clock_check = self.clock_check.clone();
+        let clock_check_connector = self.clock_check_connector.clone();
+        let proxy = self.proxy.clone();
+
+        let future = async move {
+            let started = Instant::now();
+            let timestamp = chrono::Utc::now();
+            let response = tokio::time::timeout(timeout, request_future.map_err(Error::Aborted))
+                .await
+                .map_err(Error::TimeoutError);
+
+            let response = match flatten_result(flatten_result(response)) {
+                Err(error) => Err(clock_check
+                    .diagnose(error, clock_check_connector, host_addr, host_header)
+                    .await),
+                response => response,
+            };
+            api_trace.record(TraceEntry {
+                timestamp,
+                method,
+                path,
+                duration: started.elapsed(),
+                outcome: match &response {
+                    Ok(response) => response.status().to_string(),
+                    Err(error) => error.to_string(),
+                },
+                retried: retried.load(Ordering::SeqCst),
+            });
+            if let Some(host_addr) = host_addr {
+                if response.is_ok() {
+                    address_cache.register_success(host_addr);
+                    proxy.register_success();
+                }
+                if let Err(err) = &response {
+                    if err.is_network_error() {
+                        log::error!("{}", err.display_chain_with_msg("HTTP request failed"));
+                        if !api_availability.get_state().is_offline() {
+                            proxy.register_network_failure();
+                            let current_address = address_cache.peek_address();
+                            if current_address == host_addr
+                                && address_cache.has_tried_current_address()
+                            {
+                                handle.spawn(async move {
+                                    address_cache.select_new_address().await;
+                                    let new_address = address_cache.peek_address();
+                                    log::error!(
+                                        "Request failed using address {}. Trying next API address: {}",
+                                        current_address,
+                                        new_address,
+                                    );
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+
+
+            if completion_tx.send(response).is_err() {
+                log::trace!("Failed to send response to caller, caller channel is shut down");
+            }
+            let _ = tx.send(RequestCommand::RequestFinished(id)).await;
+        };
+
+
+        self.handle.spawn(future);
+        self.in_flight_requests.insert(
+            id,
+            InFlightRequest {
+                abort_handle,
+                priority,
+            },
+        );
+    }
+
     fn reset(&mut self) {
         let old_requests = mem::replace(&mut self.in_flight_requests, BTreeMap::new());
-        for (_, abort_handle) in old_requests.into_iter() {
-            abort_handle.abort();
+        for (_, request) in old_requests.into_iter() {
+            request.abort_handle.abort();
+        }
+
+        for (_, completion_tx) in self.background_queue.drain(..) {
+            let _ = completion_tx.send(Err(Error::Aborted(Aborted)));
         }
 
         let old_sockets = mem::replace(&mut self.sockets, BTreeMap::new());
@@ -234,8 +491,18 @@ impl RequestService {
     }
 
     pub async fn into_future(mut self) {
-        while let Some(command) = self.command_rx.next().await {
-            self.process_command(command);
+        let mut cancelled = self.cancel_token.cancelled().fuse();
+        loop {
+            futures::select! {
+                command = self.command_rx.next() => match command {
+                    Some(command) => self.process_command(command),
+                    None => break,
+                },
+                _ = cancelled => {
+                    log::debug!("Aborting all API requests");
+                    break;
+                }
+            }
         }
         self.reset();
     }
@@ -253,6 +520,28 @@ fn get_request_socket_addr(request: &Request) -> Option<SocketAddr> {
     Some(SocketAddr::new(host_addr, port))
 }
 
+/// Returns a copy of `request` if it is idempotent and has no body, meaning that it can safely be
+/// sent again if the connection it was sent on turns out to be dead.
+fn try_clone_idempotent_request(request: &Request) -> Option<Request> {
+    if !request.method().is_idempotent() || !request.body().is_end_stream() {
+        return None;
+    }
+    let mut new_request = http::request::Builder::new()
+        .method(request.method().clone())
+        .uri(request.uri().clone())
+        .version(request.version())
+        .body(hyper::Body::empty())
+        .ok()?;
+    *new_request.headers_mut() = request.headers().clone();
+    Some(new_request)
+}
+
+/// Returns whether the error indicates that a reused connection was closed by the remote end
+/// before a response was received, as opposed to the API being unreachable.
+fn is_stale_connection_error(error: &hyper::Error) -> bool {
+    error.is_closed() || error.is_incomplete_message() || error.is_canceled()
+}
+
 
 #[derive(Clone)]
 /// A handle to interact with a spawned `RequestService`.
@@ -298,9 +587,37 @@ pub(crate) enum RequestCommand {
     RequestFinished(u64),
     SocketOpened(usize, TcpStreamHandle),
     SocketClosed(usize),
+    ConnectivityRestored,
     Reset(oneshot::Sender<()>),
 }
 
+/// Determines how the request service schedules a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestPriority {
+    /// The request is sent immediately. Used for requests that a user is waiting on.
+    Interactive,
+    /// The request is held back while interactive requests are in flight, and queued rather than
+    /// failed while the host is offline.
+    Background,
+}
+
+
+/// A random token that identifies a non-idempotent request across retries. The same token must
+/// be used for every attempt at sending a request, and a new one for every new request.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct IdempotencyToken(String);
+
+impl IdempotencyToken {
+    pub fn new() -> Self {
+        IdempotencyToken(format!("{:032x}", rand::random::<u128>()))
+    }
+}
+
+impl Default for IdempotencyToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 /// A REST request that is sent to the RequestService to be executed.
 #[derive(Debug)]
@@ -308,6 +625,7 @@ pub struct RestRequest {
     request: Request,
     timeout: Duration,
     auth: Option<HeaderValue>,
+    priority: RequestPriority,
 }
 
 impl RestRequest {
@@ -331,6 +649,7 @@ impl RestRequest {
         Ok(RestRequest {
             timeout: DEFAULT_TIMEOUT,
             auth: None,
+            priority: RequestPriority::Interactive,
             request,
         })
     }
@@ -359,12 +678,39 @@ impl RestRequest {
         self.timeout
     }
 
+    /// Sets the scheduling priority of the request. Defaults to
+    /// [`RequestPriority::Interactive`].
+    pub fn set_priority(&mut self, priority: RequestPriority) {
+        self.priority = priority;
+    }
+
+    /// Retrieves the scheduling priority
+    pub fn priority(&self) -> RequestPriority {
+        self.priority
+    }
+
     pub fn add_header<T: header::IntoHeaderName>(&mut self, key: T, value: &str) -> Result<()> {
         let header_value = http::HeaderValue::from_str(value).map_err(Error::InvalidHeaderError)?;
         self.request.headers_mut().insert(key, header_value);
         Ok(())
     }
 
+    /// Identifies the request using `token`, so that it can be sent again without the API acting
+    /// on it twice.
+    pub fn set_idempotency_token(&mut self, token: &IdempotencyToken) -> Result<()> {
+        self.add_header(IDEMPOTENCY_KEY_HEADER, &token.0)
+    }
+
+    /// Returns whether a header with the given name has been set
+    pub fn has_header<T: header::AsHeaderName>(&self, key: T) -> bool {
+        self.request.headers().contains_key(key)
+    }
+
+    /// Returns the value of the header with the given name, if it has been set
+    pub fn header<T: header::AsHeaderName>(&self, key: T) -> Option<&http::HeaderValue> {
+        self.request.headers().get(key)
+    }
+
     /// Converts into a `hyper::Request<hyper::Body>`
     fn into_request(self) -> Request {
         let Self {
@@ -388,6 +734,7 @@ impl From<Request> for RestRequest {
             request,
             timeout: DEFAULT_TIMEOUT,
             auth: None,
+            priority: RequestPriority::Interactive,
         }
     }
 }
@@ -439,7 +786,20 @@ impl RequestFactory {
     }
 
     pub fn post_json<S: serde::Serialize>(&self, path: &str, body: &S) -> Result<RestRequest> {
-        let mut request = self.hyper_request(path, Method::POST)?;
+        self.json_request(path, Method::POST, body)
+    }
+
+    pub fn put_json<S: serde::Serialize>(&self, path: &str, body: &S) -> Result<RestRequest> {
+        self.json_request(path, Method::PUT, body)
+    }
+
+    fn json_request<S: serde::Serialize>(
+        &self,
+        path: &str,
+        method: Method,
+        body: &S,
+    ) -> Result<RestRequest> {
+        let mut request = self.hyper_request(path, method)?;
 
         let json_body = serde_json::to_string(&body)?;
         let body_length = json_body.as_bytes().len() as u64;
@@ -593,23 +953,44 @@ pub async fn parse_rest_response(
 
 
 pub async fn handle_error_response<T>(response: Response) -> Result<T> {
-    let error_message = match response.status() {
-        hyper::StatusCode::NOT_FOUND => "Not found",
-        hyper::StatusCode::METHOD_NOT_ALLOWED => "Method not allowed",
+    let error_code = match response.status() {
+        hyper::StatusCode::TOO_MANY_REQUESTS => {
+            return Err(Error::RateLimited(parse_retry_after(&response)));
+        }
+        hyper::StatusCode::NOT_FOUND => ApiErrorCode::NotFound,
+        hyper::StatusCode::METHOD_NOT_ALLOWED => ApiErrorCode::MethodNotAllowed,
         status => {
             let err: ErrorResponse = deserialize_body(response).await?;
 
-            return Err(Error::ApiError(status, err.code));
+            return Err(Error::ApiError(status, ApiErrorCode::from_code(err.code)));
         }
     };
-    Err(Error::ApiError(response.status(), error_message.to_owned()))
+    Err(Error::ApiError(response.status(), error_code))
+}
+
+/// Reads the `Retry-After` header, which contains either a number of seconds or an HTTP date.
+fn parse_retry_after(response: &Response) -> Option<Duration> {
+    let value = response
+        .headers()
+        .get(header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
 }
 
 #[derive(Clone)]
 pub struct MullvadRestHandle {
     pub(crate) service: RequestServiceHandle,
     pub factory: RequestFactory,
-    availability: ApiAvailabilityHandle,
+    pub(crate) response_cache: ResponseCache,
+    address_cache: AddressCache,
 }
 
 impl MullvadRestHandle {
@@ -617,56 +998,26 @@ impl MullvadRestHandle {
         service: RequestServiceHandle,
         factory: RequestFactory,
         address_cache: AddressCache,
-        availability: ApiAvailabilityHandle,
+        response_cache: ResponseCache,
     ) -> Self {
-        let handle = Self {
+        Self {
             service,
             factory,
-            availability,
-        };
-        handle.spawn_api_address_fetcher(address_cache);
-
-        handle
+            response_cache,
+            address_cache,
+        }
     }
 
-    fn spawn_api_address_fetcher(&self, address_cache: AddressCache) {
-        let handle = self.clone();
-        let availability = self.availability.clone();
-
-        self.service.spawn(async move {
-            // always start the fetch after 15 minutes
-            let api_proxy = crate::ApiProxy::new(handle);
-            let mut next_check = Instant::now() + API_IP_CHECK_DELAY;
-
-            let next_error_check = || Instant::now() + API_IP_CHECK_ERROR_INTERVAL;
-            let next_regular_check = || Instant::now() + API_IP_CHECK_INTERVAL;
-
-            let mut interval = tokio::time::interval_at(next_check.into(), TIMER_CHECK_INTERVAL);
-
-            loop {
-                interval.tick().await;
-                if next_check < Instant::now() {
-                    if let Err(error) = availability.wait_available().await {
-                        log::error!("Failed while waiting for API: {}", error);
-                        next_check = next_error_check();
-                        continue;
-                    }
-                    match api_proxy.clone().get_api_addrs().await {
-                        Ok(new_addrs) => {
-                            log::debug!("Fetched new API addresses {:?}, will fetch again in {} hours", new_addrs, API_IP_CHECK_INTERVAL.as_secs() / ( 60 * 60 ));
-                            if let Err(err) = address_cache.set_addresses(new_addrs).await {
-                                log::error!("Failed to save newly updated API addresses: {}", err);
-                            }
-                            next_check = next_regular_check();
-                        }
-                        Err(err) => {
-                            log::error!("Failed to fetch new API addresses: {}, will retry again in {} seconds", err, API_IP_CHECK_ERROR_INTERVAL.as_secs());
-                            next_check = next_error_check();
-                        }
-                    }
-                }
-            }
-        });
+    /// Fetches the list of API addresses and stores it in the address cache. This is not done
+    /// periodically by the handle itself, so that the caller can schedule it together with other
+    /// background work.
+    pub async fn update_api_addresses(&self) -> Result<()> {
+        let new_addrs = crate::ApiProxy::new(self.clone()).get_api_addrs().await?;
+        log::debug!("Fetched new API addresses {:?}", new_addrs);
+        if let Err(err) = self.address_cache.set_addresses(new_addrs).await {
+            log::error!("Failed to save newly updated API addresses: {}", err);
+        }
+        Ok(())
     }
 
     pub fn service(&self) -> RequestServiceHandle {
@@ -686,3 +1037,104 @@ fn flatten_result<T, E>(
         Err(err) => Err(err),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_api_error_code_parsing() {
+        let cases = [
+            ("INVALID_ACCOUNT", ApiErrorCode::InvalidAccount),
+            ("INVALID_AUTH", ApiErrorCode::InvalidAuth),
+            ("INVALID_VOUCHER", ApiErrorCode::InvalidVoucher),
+            ("VOUCHER_USED", ApiErrorCode::VoucherUsed),
+            ("VOUCHER_EXPIRED", ApiErrorCode::VoucherExpired),
+            ("KEY_LIMIT_REACHED", ApiErrorCode::KeyLimitReached),
+            ("MAX_DEVICES_REACHED", ApiErrorCode::MaxDevicesReached),
+            ("DEVICE_NOT_FOUND", ApiErrorCode::DeviceNotFound),
+            (
+                "SOME_NEW_ERROR",
+                ApiErrorCode::Other("SOME_NEW_ERROR".to_owned()),
+            ),
+            ("", ApiErrorCode::Other(String::new())),
+            (
+                "invalid_account",
+                ApiErrorCode::Other("invalid_account".to_owned()),
+            ),
+        ];
+        for (code, expected) in cases.iter() {
+            let parsed = ApiErrorCode::from_code(code.to_string());
+            assert_eq!(parsed, *expected, "code: {:?}", code);
+            assert_eq!(parsed.as_str(), *code);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_error_response() {
+        let cases = [
+            (
+                StatusCode::BAD_REQUEST,
+                r#"{"code": "INVALID_VOUCHER"}"#,
+                ApiErrorCode::InvalidVoucher,
+            ),
+            (
+                StatusCode::UNAUTHORIZED,
+                r#"{"code": "INVALID_AUTH", "error": "Invalid auth header"}"#,
+                ApiErrorCode::InvalidAuth,
+            ),
+            (
+                StatusCode::FORBIDDEN,
+                r#"{"code": "MAX_DEVICES_REACHED"}"#,
+                ApiErrorCode::MaxDevicesReached,
+            ),
+            (
+                StatusCode::BAD_REQUEST,
+                r#"{"code": "SOME_NEW_ERROR"}"#,
+                ApiErrorCode::Other("SOME_NEW_ERROR".to_owned()),
+            ),
+            (StatusCode::NOT_FOUND, "", ApiErrorCode::NotFound),
+            (
+                StatusCode::METHOD_NOT_ALLOWED,
+                "",
+                ApiErrorCode::MethodNotAllowed,
+            ),
+        ];
+        for (status, body, expected) in cases.iter() {
+            let response = hyper::Response::builder()
+                .status(*status)
+                .body(hyper::Body::from(*body))
+                .unwrap();
+            match handle_error_response::<()>(response).await {
+                Err(Error::ApiError(error_status, code)) => {
+                    assert_eq!(error_status, *status);
+                    assert_eq!(code, *expected);
+                }
+                other => panic!("unexpected result for {}: {:?}", status, other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_rate_limited_response() {
+        let response = hyper::Response::builder()
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .header(header::RETRY_AFTER, "30")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let error = handle_error_response::<()>(response).await.unwrap_err();
+        assert_eq!(error.retry_after(), Some(Duration::from_secs(30)));
+    }
+
+    #[tokio::test]
+    async fn test_handle_malformed_error_response() {
+        let response = hyper::Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(hyper::Body::from("not json"))
+            .unwrap();
+        match handle_error_response::<()>(response).await {
+            Err(Error::DeserializeError(_)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
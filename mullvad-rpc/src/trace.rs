@@ -0,0 +1,156 @@
+//! An opt-in record of recent API requests, used to diagnose problems with reaching the API.
+//! Only the method, path, timing and outcome of each request is recorded. Request and response
+//! bodies and headers are never stored, and path segments that may identify an account or a key
+//! are redacted.
+use chrono::{DateTime, Utc};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+/// Maximum number of requests that are kept in the trace.
+const TRACE_CAPACITY: usize = 256;
+
+/// Path segments at least this long that only contain digits are redacted.
+const MIN_REDACTED_DIGITS: usize = 8;
+/// Path segments at least this long that look like base64 are redacted.
+const MIN_REDACTED_BASE64: usize = 32;
+
+const REDACTED: &str = "[REDACTED]";
+
+/// A single API request in the trace.
+#[derive(Clone, Debug)]
+pub struct TraceEntry {
+    pub timestamp: DateTime<Utc>,
+    pub method: String,
+    /// The redacted request path, without the query string.
+    pub path: String,
+    pub duration: Duration,
+    /// The response status code, or a description of the error if no response was received.
+    pub outcome: String,
+    /// Whether the request was sent again because the connection it was sent on was dead.
+    pub retried: bool,
+}
+
+/// A ring buffer of the most recent API requests. Nothing is recorded unless tracing has been
+/// enabled.
+#[derive(Clone, Default)]
+pub struct ApiTrace {
+    enabled: Arc<AtomicBool>,
+    entries: Arc<Mutex<VecDeque<TraceEntry>>>,
+}
+
+impl ApiTrace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enables or disables tracing. The trace is cleared when it is disabled.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::SeqCst);
+        if !enabled {
+            self.entries.lock().unwrap().clear();
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+
+    /// Returns the recorded requests, oldest first.
+    pub fn entries(&self) -> Vec<TraceEntry> {
+        self.entries.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub(crate) fn record(&self, mut entry: TraceEntry) {
+        if !self.is_enabled() {
+            return;
+        }
+        entry.path = redact_path(&entry.path);
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= TRACE_CAPACITY {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+}
+
+/// Removes the query string and any path segments that may contain account numbers or keys.
+fn redact_path(path: &str) -> String {
+    let path = path.split('?').next().unwrap_or_default();
+    path.split('/')
+        .map(|segment| {
+            if is_sensitive_segment(segment) {
+                REDACTED
+            } else {
+                segment
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn is_sensitive_segment(segment: &str) -> bool {
+    let all_digits = segment.chars().all(|c| c.is_ascii_digit());
+    if all_digits && segment.len() >= MIN_REDACTED_DIGITS {
+        return true;
+    }
+    let base64_like = segment
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "+/=%-_".contains(c));
+    base64_like && segment.len() >= MIN_REDACTED_BASE64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(path: &str) -> TraceEntry {
+        TraceEntry {
+            timestamp: Utc::now(),
+            method: "GET".to_owned(),
+            path: path.to_owned(),
+            duration: Duration::from_millis(10),
+            outcome: "200 OK".to_owned(),
+            retried: false,
+        }
+    }
+
+    #[test]
+    fn test_redact_path() {
+        assert_eq!(redact_path("/app/v1/relays"), "/app/v1/relays");
+        assert_eq!(
+            redact_path("/app/v1/wireguard-keys/9Zr8HcC2rNuCfUNXYWHaIk2smyd9A%2B4FuzLbMKJWx2Y%3D"),
+            "/app/v1/wireguard-keys/[REDACTED]"
+        );
+        assert_eq!(
+            redact_path("/app/v1/accounts/1234567890123456"),
+            "/app/v1/accounts/[REDACTED]"
+        );
+        assert_eq!(
+            redact_path("/app/v1/releases/linux/2021.4?platform_version=5.10"),
+            "/app/v1/releases/linux/2021.4"
+        );
+    }
+
+    #[test]
+    fn test_trace_capacity() {
+        let trace = ApiTrace::new();
+        trace.record(entry("/app/v1/relays"));
+        assert!(trace.entries().is_empty());
+
+        trace.set_enabled(true);
+        for _ in 0..TRACE_CAPACITY + 10 {
+            trace.record(entry("/app/v1/relays"));
+        }
+        assert_eq!(trace.entries().len(), TRACE_CAPACITY);
+
+        trace.set_enabled(false);
+        assert!(trace.entries().is_empty());
+    }
+}
@@ -7,6 +7,8 @@ use talpid_core::{
     firewall::{self, Firewall, FirewallArguments},
     future_retry::{constant_interval, retry_future_n},
 };
+#[cfg(target_os = "linux")]
+use talpid_types::net::{Endpoint, TransportProtocol};
 use talpid_types::ErrorExt;
 
 pub const PRODUCT_VERSION: &str = include_str!(concat!(env!("OUT_DIR"), "/product-version.txt"));
@@ -60,6 +62,9 @@ pub enum Error {
     #[error(display = "Firewall error")]
     FirewallError(#[error(source)] firewall::Error),
 
+    #[error(display = "Failed to remove the persistent firewall policy")]
+    PersistentFirewallError(#[error(source)] firewall::persist::Error),
+
     #[error(display = "Failed to initialize mullvad RPC runtime")]
     RpcInitializationError(#[error(source)] mullvad_rpc::Error),
 
@@ -77,13 +82,22 @@ pub enum Error {
 
     #[error(display = "Cannot parse the version string")]
     ParseVersionStringError,
+
+    #[cfg(windows)]
+    #[error(display = "Failed to request a migration or read its report")]
+    MigrationError(#[error(source)] mullvad_daemon::migration::Error),
+
+    #[cfg(windows)]
+    #[error(display = "The migration failed: {}", _0)]
+    MigrationFailed(String),
 }
 
 #[tokio::main]
 async fn main() {
     env_logger::init();
 
-    let subcommands = vec![
+    #[allow(unused_mut)]
+    let mut subcommands = vec![
         SubCommand::with_name("prepare-restart")
             .about("Move a running daemon into a blocking state and save its target state"),
         SubCommand::with_name("reset-firewall")
@@ -98,6 +112,22 @@ async fn main() {
                     .help("Version string to compare the current version"),
             ),
     ];
+    #[cfg(target_os = "linux")]
+    subcommands.push(
+        SubCommand::with_name("initialize-early-boot-firewall").about(
+            "Block all traffic until the daemon has started. Meant to be run early during boot",
+        ),
+    );
+    #[cfg(windows)]
+    subcommands.push(SubCommand::with_name("migrate").about(
+        "Bring the firewall objects, split tunnel driver and settings in line with this version. \
+         If the daemon is not running, the migration is run when it starts",
+    ));
+    #[cfg(windows)]
+    subcommands.push(
+        SubCommand::with_name("migration-report")
+            .about("Print the report of the last migration. Fails if the migration failed"),
+    );
 
     let app = clap::App::new(crate_name!())
         .version(PRODUCT_VERSION)
@@ -115,6 +145,12 @@ async fn main() {
         ("prepare-restart", _) => prepare_restart().await,
         ("reset-firewall", _) => reset_firewall().await,
         ("remove-wireguard-key", _) => remove_wireguard_key().await,
+        #[cfg(target_os = "linux")]
+        ("initialize-early-boot-firewall", _) => initialize_early_boot_firewall().await,
+        #[cfg(windows)]
+        ("migrate", _) => migrate().await,
+        #[cfg(windows)]
+        ("migration-report", _) => print_migration_report().await,
         ("is-older-version", Some(sub_matches)) => {
             let old_version = sub_matches.value_of("OLDVERSION").unwrap();
             match is_older_version(old_version).await {
@@ -157,16 +193,115 @@ async fn reset_firewall() -> Result<(), Error> {
         return Err(Error::DaemonIsRunning);
     }
 
+    // Otherwise, the policy would be enforced again when the firewall is dropped
+    firewall::persist::uninstall().map_err(Error::PersistentFirewallError)?;
+
     let mut firewall = Firewall::new(FirewallArguments {
         initialize_blocked: false,
         allow_lan: true,
+        lan_networks: vec![],
         allowed_endpoint: None,
+        #[cfg(windows)]
+        command_tx: None,
     })
     .map_err(Error::FirewallError)?;
 
     firewall.reset_policy().map_err(Error::FirewallError)
 }
 
+/// Applies the blocked policy without involving the daemon, so that nothing leaks between boot and
+/// the daemon starting. Only relies on the firewall, which makes it usable from any init system.
+#[cfg(target_os = "linux")]
+async fn initialize_early_boot_firewall() -> Result<(), Error> {
+    // Ensure that the daemon isn't running
+    if let Ok(_) = new_rpc_client().await {
+        return Err(Error::DaemonIsRunning);
+    }
+
+    let (cache_path, settings_path) = get_paths()?;
+    let settings = mullvad_daemon::settings::SettingsPersister::load(&settings_path).await;
+    let rpc_runtime = MullvadRpcRuntime::with_cache(
+        tokio::runtime::Handle::current(),
+        None,
+        &cache_path,
+        false,
+        |_| Ok(()),
+    )
+    .await
+    .map_err(Error::RpcInitializationError)?;
+    let allowed_endpoint = Endpoint::from_socket_address(
+        rpc_runtime.address_cache.peek_address(),
+        TransportProtocol::Tcp,
+    );
+
+    let mut firewall = Firewall::new(FirewallArguments {
+        initialize_blocked: true,
+        allow_lan: settings.allow_lan,
+        lan_networks: firewall::ALLOWED_LAN_NETS.to_vec(),
+        allowed_endpoint: Some(allowed_endpoint),
+    })
+    .map_err(Error::FirewallError)?;
+
+    firewall
+        .apply_policy(firewall::FirewallPolicy::Blocked {
+            allow_lan: settings.allow_lan,
+            // The interfaces are not necessarily configured this early
+            lan_networks: firewall::ALLOWED_LAN_NETS.to_vec(),
+            allowed_endpoint,
+            exemptions: Default::default(),
+            allowed_endpoints: settings.firewall_exceptions.clone(),
+        })
+        .map_err(Error::FirewallError)
+}
+
+#[cfg(windows)]
+async fn migrate() -> Result<(), Error> {
+    let mut rpc = match new_rpc_client().await {
+        Ok(rpc) => rpc,
+        Err(_) => {
+            let (_, settings_path) = get_paths()?;
+            mullvad_daemon::migration::request(&settings_path, PRODUCT_VERSION)
+                .await
+                .map_err(Error::MigrationError)?;
+            println!("The migration will run when the daemon starts");
+            return Ok(());
+        }
+    };
+    let report = rpc
+        .run_migration(PRODUCT_VERSION.to_owned())
+        .await
+        .map_err(Error::DaemonRpcError)?
+        .into_inner();
+    match report.failure {
+        Some(failure) => Err(Error::MigrationFailed(failure.error)),
+        None => {
+            println!("Migration completed");
+            Ok(())
+        }
+    }
+}
+
+#[cfg(windows)]
+async fn print_migration_report() -> Result<(), Error> {
+    let (_, settings_path) = get_paths()?;
+    let report = mullvad_daemon::migration::read_report(&settings_path)
+        .await
+        .map_err(Error::MigrationError)?;
+    match report {
+        Some(report) => {
+            println!("{}", report);
+            match report.failure {
+                Some(failure) => Err(Error::MigrationFailed(failure.error)),
+                None => Ok(()),
+            }
+        }
+        None => {
+            println!("No migration has been run");
+            Ok(())
+        }
+    }
+}
+
 async fn remove_wireguard_key() -> Result<(), Error> {
     let (cache_path, settings_path) = get_paths()?;
     let mut settings = mullvad_daemon::settings::SettingsPersister::load(&settings_path).await;
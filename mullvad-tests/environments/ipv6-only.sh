@@ -0,0 +1,68 @@
+#!/usr/bin/env bash
+
+# Runs a command in a network namespace that only has IPv6 connectivity, for testing the daemon on
+# hosts without IPv4. Requires root, and IPv6 connectivity on the host.
+#
+# Topology:
+#
+#   +------------------------------+            +-----------------------------------+
+#   | namespace mullvad-ipv6-only  |            | host                              |
+#   |                              |            |                                   |
+#   |  lo         127.0.0.1, ::1   |            |  mullvad-v6host  fd00:6::1/64     |
+#   |  mullvad-v6ns  fd00:6::2/64  |<---veth--->|  IPv6 forwarding, masquerading    |
+#   |  default via fd00:6::1       |            |  out of the default IPv6 route    |
+#   |  nameserver $DNS_SERVER      |            |                                   |
+#   |  no IPv4 addresses or routes |            |                                   |
+#   +------------------------------+            +-----------------------------------+
+#
+# Only the loopback interface has an IPv4 address inside the namespace, so the offline monitor
+# should report IPv6-only connectivity, the relay selector should pick IPv6 endpoints, and the API
+# should be reached over IPv6.
+#
+# Usage: [DNS_SERVER=<IPv6 address>] ipv6-only.sh <command> [args...]
+
+set -eu
+
+if [ "$#" -lt 1 ]; then
+    echo "Usage: $0 <command> [args...]" >&2
+    exit 1
+fi
+
+NAMESPACE="mullvad-ipv6-only"
+HOST_IFACE="mullvad-v6host"
+NAMESPACE_IFACE="mullvad-v6ns"
+HOST_ADDRESS="fd00:6::1"
+NAMESPACE_ADDRESS="fd00:6::2"
+PREFIX_LENGTH=64
+DNS_SERVER="${DNS_SERVER:-2001:4860:4860::8888}"
+
+function teardown {
+    ip6tables -t nat -D POSTROUTING -s "$NAMESPACE_ADDRESS/128" -j MASQUERADE 2>/dev/null || true
+    ip link delete "$HOST_IFACE" 2>/dev/null || true
+    ip netns delete "$NAMESPACE" 2>/dev/null || true
+    rm -rf "/etc/netns/$NAMESPACE"
+}
+
+teardown
+trap teardown EXIT
+
+ip netns add "$NAMESPACE"
+# `ip netns exec` bind mounts this over /etc/resolv.conf
+mkdir -p "/etc/netns/$NAMESPACE"
+echo "nameserver $DNS_SERVER" > "/etc/netns/$NAMESPACE/resolv.conf"
+
+ip link add "$HOST_IFACE" type veth peer name "$NAMESPACE_IFACE"
+ip link set "$NAMESPACE_IFACE" netns "$NAMESPACE"
+
+ip -6 address add "$HOST_ADDRESS/$PREFIX_LENGTH" dev "$HOST_IFACE"
+ip link set "$HOST_IFACE" up
+sysctl -q -w net.ipv6.conf.all.forwarding=1
+ip6tables -t nat -A POSTROUTING -s "$NAMESPACE_ADDRESS/128" -j MASQUERADE
+
+ip netns exec "$NAMESPACE" ip link set lo up
+ip netns exec "$NAMESPACE" ip -6 address add "$NAMESPACE_ADDRESS/$PREFIX_LENGTH" \
+    dev "$NAMESPACE_IFACE" nodad
+ip netns exec "$NAMESPACE" ip link set "$NAMESPACE_IFACE" up
+ip netns exec "$NAMESPACE" ip -6 route add default via "$HOST_ADDRESS"
+
+ip netns exec "$NAMESPACE" "$@"
@@ -0,0 +1,145 @@
+#![cfg(all(feature = "integration-tests", target_os = "linux"))]
+
+use ipnetwork::IpNetwork;
+use std::{collections::HashSet, net::IpAddr};
+use talpid_core::routing::{Node, RequiredRoute, RouteManager};
+
+const INTERFACE: &str = "mullvad-rt0";
+const MAIN_TABLE: u32 = 254;
+const TEST_TABLE: u32 = 0x6d72;
+
+/// A dummy interface that has an IPv4 and an IPv6 network, which is removed when dropped.
+struct DummyInterface;
+
+impl DummyInterface {
+    fn create() -> Self {
+        ip(&["link", "add", INTERFACE, "type", "dummy"]);
+        let interface = DummyInterface;
+        ip(&["addr", "add", "10.99.0.1/24", "dev", INTERFACE]);
+        ip(&[
+            "-6",
+            "addr",
+            "add",
+            "fd00:99::1/64",
+            "dev",
+            INTERFACE,
+            "nodad",
+        ]);
+        ip(&["link", "set", INTERFACE, "up"]);
+        interface
+    }
+}
+
+impl Drop for DummyInterface {
+    fn drop(&mut self) {
+        let _ = duct::cmd!("ip", "link", "del", INTERFACE)
+            .stderr_null()
+            .run();
+    }
+}
+
+fn ip(args: &[&str]) -> String {
+    duct::cmd("ip", args)
+        .read()
+        .unwrap_or_else(|error| panic!("ip {} failed: {}", args.join(" "), error))
+}
+
+fn routes(family: &str, table: u32) -> String {
+    ip(&[family, "route", "show", "table", &table.to_string()])
+}
+
+fn route(destination: &str, node: Node, table: u32) -> RequiredRoute {
+    RequiredRoute::new(destination.parse::<IpNetwork>().unwrap(), node).table(table)
+}
+
+fn address(address: &str) -> IpAddr {
+    address.parse().unwrap()
+}
+
+/// Runs `test` with a route manager. The runtime is created outside of any async context, since
+/// the route manager blocks on removing its routes when dropped.
+fn with_route_manager(test: impl FnOnce(&tokio::runtime::Runtime, &mut RouteManager)) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let mut manager = runtime
+        .block_on(RouteManager::new(runtime.handle().clone(), HashSet::new()))
+        .expect("Failed to start the route manager");
+    test(&runtime, &mut manager);
+    drop(manager);
+}
+
+#[test]
+fn adds_and_removes_gateway_routes() {
+    let _interface = DummyInterface::create();
+
+    with_route_manager(|runtime, manager| {
+        let routes_to_add: HashSet<_> = vec![
+            route(
+                "10.98.0.0/16",
+                Node::new(address("10.99.0.2"), INTERFACE.to_owned()),
+                MAIN_TABLE,
+            ),
+            route(
+                "fd00:98::/64",
+                Node::new(address("fd00:99::2"), INTERFACE.to_owned()),
+                MAIN_TABLE,
+            ),
+            route(
+                "fd00:97::1/128",
+                Node::address(address("fd00:99::3")),
+                MAIN_TABLE,
+            ),
+        ]
+        .into_iter()
+        .collect();
+
+        runtime
+            .block_on(manager.add_routes(routes_to_add.clone()))
+            .expect("Failed to add routes");
+        assert_eq!(manager.handle().unwrap().snapshot().routes.len(), 3);
+
+        let v4_routes = routes("-4", MAIN_TABLE);
+        assert!(v4_routes.contains("10.98.0.0/16 via 10.99.0.2 dev mullvad-rt0"));
+        let v6_routes = routes("-6", MAIN_TABLE);
+        assert!(v6_routes.contains("fd00:98::/64 via fd00:99::2 dev mullvad-rt0"));
+        assert!(v6_routes.contains("fd00:97::1 via fd00:99::3 dev mullvad-rt0"));
+
+        runtime
+            .block_on(manager.remove_routes(routes_to_add))
+            .expect("Failed to remove routes");
+        assert!(manager.handle().unwrap().snapshot().routes.is_empty());
+        assert!(!routes("-6", MAIN_TABLE).contains("fd00:98::/64"));
+        assert!(!routes("-6", MAIN_TABLE).contains("fd00:97::1"));
+    });
+}
+
+#[test]
+fn adds_ipv6_routes_to_other_tables() {
+    let _interface = DummyInterface::create();
+
+    with_route_manager(|runtime, manager| {
+        let routes_to_add: HashSet<_> = vec![
+            route("::/0", Node::device(INTERFACE.to_owned()), TEST_TABLE),
+            route(
+                "fd00:96::/48",
+                Node::new(address("fd00:99::2"), INTERFACE.to_owned()),
+                TEST_TABLE,
+            ),
+        ]
+        .into_iter()
+        .collect();
+
+        runtime
+            .block_on(manager.add_routes(routes_to_add))
+            .expect("Failed to add routes");
+        let v6_routes = routes("-6", TEST_TABLE);
+        assert!(v6_routes.contains("default dev mullvad-rt0"));
+        assert!(v6_routes.contains("fd00:96::/48 via fd00:99::2 dev mullvad-rt0"));
+        assert!(!routes("-6", MAIN_TABLE).contains("fd00:96::/48"));
+    });
+
+    // The route manager removes its routes when it stops
+    assert!(routes("-6", TEST_TABLE).is_empty());
+}
@@ -0,0 +1,203 @@
+//! Derive macros for `mullvad-types`.
+//!
+//! The generated code refers to items through `crate::`, so the macros can only be used from
+//! within `mullvad-types` itself.
+#![deny(rust_2018_idioms)]
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, ToTokens};
+use syn::{
+    parse_macro_input, spanned::Spanned, Attribute, Data, DeriveInput, Fields, Lit, Meta,
+    NestedMeta,
+};
+
+/// Implements `SettingsSchema` for a struct with named fields. Every field is described using
+/// its name, type, doc comment and the value it has in the default instance of the struct.
+///
+/// Fields can be annotated with `#[schema(...)]`:
+/// - `skip`: leave the field out of the schema.
+/// - `nested`: describe the fields of the field's type instead, prefixed by the field name.
+/// - `reconnect`: changing the setting causes the tunnel to reconnect.
+/// - `min = N`, `max = N`: inclusive bounds for numeric settings.
+/// - `values = "a|b"`: the values that are accepted by the setting.
+#[proc_macro_derive(SettingsSchema, attributes(schema))]
+pub fn derive_settings_schema(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match impl_settings_schema(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+fn impl_settings_schema(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new(
+                    input.span(),
+                    "SettingsSchema can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new(
+                input.span(),
+                "SettingsSchema can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut statements = Vec::new();
+    for field in fields {
+        let options = FieldOptions::parse(&field.attrs)?;
+        if options.skip {
+            continue;
+        }
+
+        let ident = field.ident.as_ref().unwrap();
+        let name = ident.to_string();
+        let ty = &field.ty;
+        let cfg_attrs = field.attrs.iter().filter(|attr| attr.path.is_ident("cfg"));
+
+        if options.nested {
+            statements.push(quote! {
+                #(#cfg_attrs)*
+                <#ty as crate::settings::schema::SettingsSchema>::describe(
+                    &default.#ident,
+                    &format!("{}{}.", prefix, #name),
+                    settings,
+                );
+            });
+            continue;
+        }
+
+        let type_name = ty.to_token_stream().to_string().replace(' ', "");
+        let description = doc_comment(&field.attrs);
+        let requires_reconnect = options.reconnect;
+        let mut constraints = Vec::new();
+        if let Some(min) = options.min {
+            constraints.push(quote! { crate::settings::schema::SettingConstraint::Min(#min) });
+        }
+        if let Some(max) = options.max {
+            constraints.push(quote! { crate::settings::schema::SettingConstraint::Max(#max) });
+        }
+        if let Some(values) = options.values {
+            constraints.push(quote! {
+                crate::settings::schema::SettingConstraint::OneOf(
+                    vec![#(#values.to_owned()),*]
+                )
+            });
+        }
+
+        statements.push(quote! {
+            #(#cfg_attrs)*
+            settings.push(crate::settings::schema::SettingDescription {
+                name: format!("{}{}", prefix, #name),
+                type_name: #type_name.to_owned(),
+                description: #description.to_owned(),
+                default: serde_json::to_value(&default.#ident)
+                    .unwrap_or(serde_json::Value::Null),
+                requires_reconnect: #requires_reconnect,
+                constraints: vec![#(#constraints),*],
+            });
+        });
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics crate::settings::schema::SettingsSchema for #name #ty_generics
+            #where_clause
+        {
+            fn describe(
+                default: &Self,
+                prefix: &str,
+                settings: &mut Vec<crate::settings::schema::SettingDescription>,
+            ) {
+                #(#statements)*
+            }
+        }
+    })
+}
+
+#[derive(Default)]
+struct FieldOptions {
+    skip: bool,
+    nested: bool,
+    reconnect: bool,
+    min: Option<i64>,
+    max: Option<i64>,
+    values: Option<Vec<String>>,
+}
+
+impl FieldOptions {
+    fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut options = FieldOptions::default();
+
+        for attr in attrs.iter().filter(|attr| attr.path.is_ident("schema")) {
+            let list = match attr.parse_meta()? {
+                Meta::List(list) => list,
+                meta => return Err(syn::Error::new(meta.span(), "Expected #[schema(...)]")),
+            };
+
+            for nested in list.nested {
+                match nested {
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("skip") => {
+                        options.skip = true
+                    }
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("nested") => {
+                        options.nested = true
+                    }
+                    NestedMeta::Meta(Meta::Path(path)) if path.is_ident("reconnect") => {
+                        options.reconnect = true
+                    }
+                    NestedMeta::Meta(Meta::NameValue(pair)) if pair.path.is_ident("min") => {
+                        options.min = Some(parse_int(&pair.lit)?)
+                    }
+                    NestedMeta::Meta(Meta::NameValue(pair)) if pair.path.is_ident("max") => {
+                        options.max = Some(parse_int(&pair.lit)?)
+                    }
+                    NestedMeta::Meta(Meta::NameValue(pair)) if pair.path.is_ident("values") => {
+                        match &pair.lit {
+                            Lit::Str(values) => {
+                                options.values =
+                                    Some(values.value().split('|').map(str::to_owned).collect())
+                            }
+                            lit => return Err(syn::Error::new(lit.span(), "Expected a string")),
+                        }
+                    }
+                    nested => {
+                        return Err(syn::Error::new(nested.span(), "Unknown schema attribute"))
+                    }
+                }
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+fn parse_int(lit: &Lit) -> syn::Result<i64> {
+    match lit {
+        Lit::Int(value) => value.base10_parse(),
+        lit => Err(syn::Error::new(lit.span(), "Expected an integer")),
+    }
+}
+
+/// Joins the lines of the doc comment of an item into a single line.
+fn doc_comment(attrs: &[Attribute]) -> String {
+    attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("doc"))
+        .filter_map(|attr| match attr.parse_meta() {
+            Ok(Meta::NameValue(pair)) => match pair.lit {
+                Lit::Str(line) => Some(line.value().trim().to_owned()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -1,7 +1,9 @@
+use crate::settings::schema::SettingsSchema;
 use chrono::{offset::Utc, DateTime};
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
 /// Identifier used to authenticate or identify a Mullvad account.
 pub type AccountToken = String;
@@ -35,3 +37,66 @@ pub struct VoucherSubmission {
     #[cfg_attr(target_os = "android", jnix(map = "|expiry| expiry.to_string()"))]
     pub new_expiry: DateTime<Utc>,
 }
+
+/// How long before the account runs out of time that warnings are emitted, by default.
+pub const DEFAULT_EXPIRY_WARNING_THRESHOLDS: &[u64] = &[3 * 24 * 60 * 60, 24 * 60 * 60, 60 * 60];
+
+/// Controls the warnings emitted when the account is about to run out of time, and what the
+/// daemon does once it has.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, SettingsSchema)]
+#[serde(default)]
+pub struct AccountExpirySettings {
+    /// Warn when the remaining account time drops below each of these values, in seconds.
+    pub warning_thresholds: Vec<u64>,
+    /// What to do when the account runs out of time.
+    #[schema(values = "block|disconnect")]
+    pub action: ExpiryAction,
+}
+
+impl Default for AccountExpirySettings {
+    fn default() -> Self {
+        AccountExpirySettings {
+            warning_thresholds: DEFAULT_EXPIRY_WARNING_THRESHOLDS.to_vec(),
+            action: ExpiryAction::default(),
+        }
+    }
+}
+
+/// What the daemon does when the account runs out of time.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExpiryAction {
+    /// Keep the tunnel in the secured state. The tunnel cannot connect without account time, so
+    /// traffic is blocked until time is added or the user disconnects.
+    Block,
+    /// Disconnect, allowing traffic outside the tunnel unless lockdown mode is enabled.
+    Disconnect,
+}
+
+impl Default for ExpiryAction {
+    fn default() -> Self {
+        ExpiryAction::Block
+    }
+}
+
+impl fmt::Display for ExpiryAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExpiryAction::Block => f.write_str("block"),
+            ExpiryAction::Disconnect => f.write_str("disconnect"),
+        }
+    }
+}
+
+/// Emitted by the daemon as the account approaches and reaches its expiry.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountExpiryEvent {
+    /// Less than `threshold` seconds of account time remain.
+    ExpiresSoon {
+        expiry: DateTime<Utc>,
+        threshold: u64,
+    },
+    /// The account has run out of time.
+    Expired { expiry: DateTime<Utc> },
+}
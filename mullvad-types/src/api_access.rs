@@ -0,0 +1,177 @@
+use chrono::{offset::Utc, DateTime};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+};
+
+/// Describes how the daemon is currently reaching the Mullvad API.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ApiAccessStatus {
+    /// The API endpoint that will be used for the next request.
+    pub current_address: SocketAddr,
+    /// The most recent API endpoint that a request succeeded against, if any.
+    pub last_working: Option<ApiEndpointSuccess>,
+    /// All known API endpoints, in the order they are tried. Fallback addresses that are
+    /// bundled with the app are always included at the end of the list.
+    pub addresses: Vec<SocketAddr>,
+    /// How the API hostname is resolved.
+    pub resolution_strategy: ApiResolutionStrategy,
+    /// The result of the most recent resolution of the API hostname, if any.
+    pub last_resolution: Option<ApiResolution>,
+    /// The access method that API traffic is sent through.
+    pub access_method: Option<ActiveApiAccessMethod>,
+}
+
+/// The access method that API traffic is currently sent through.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ActiveApiAccessMethod {
+    pub name: String,
+    /// The address of the bridge or proxy that API traffic is sent to, or `None` if it is sent
+    /// directly to the API.
+    pub endpoint: Option<SocketAddr>,
+    /// The hostname of the Mullvad bridge that API traffic is sent through, if any.
+    pub bridge_hostname: Option<String>,
+}
+
+/// How the API hostname is resolved to addresses. Resolved addresses are tried in addition to the
+/// addresses that are bundled with the app or received from the API.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiResolutionStrategy {
+    /// Do not resolve the API hostname. Only the bundled and received addresses are used.
+    Static,
+    /// Resolve the API hostname using the system resolver.
+    System,
+    /// Resolve the API hostname using DNS over HTTPS, with a resolver at a fixed address.
+    DnsOverHttps,
+}
+
+impl Default for ApiResolutionStrategy {
+    fn default() -> Self {
+        ApiResolutionStrategy::Static
+    }
+}
+
+impl fmt::Display for ApiResolutionStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiResolutionStrategy::Static => write!(f, "static"),
+            ApiResolutionStrategy::System => write!(f, "system"),
+            ApiResolutionStrategy::DnsOverHttps => write!(f, "doh"),
+        }
+    }
+}
+
+/// The outcome of resolving the API hostname.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ApiResolution {
+    pub strategy: ApiResolutionStrategy,
+    pub timestamp: DateTime<Utc>,
+    /// The addresses that the hostname resolved to. Empty if the resolution failed.
+    pub addresses: Vec<IpAddr>,
+    /// A description of why the resolution failed, if it did.
+    pub error: Option<String>,
+}
+
+/// A successful request to an API endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ApiEndpointSuccess {
+    pub address: SocketAddr,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Name of the built-in access method that connects to the API directly.
+pub const DIRECT_ACCESS_METHOD: &str = "direct";
+
+/// Name of the built-in access method that connects to the API through a Mullvad bridge.
+pub const BRIDGES_ACCESS_METHOD: &str = "mullvad-bridges";
+
+/// Returns whether `name` is the name of a built-in access method.
+pub fn is_builtin_access_method(name: &str) -> bool {
+    name == DIRECT_ACCESS_METHOD || name == BRIDGES_ACCESS_METHOD
+}
+
+/// Settings for the built-in access methods, and for the order in which access methods are tried.
+/// The preferred method is tried first, followed by the enabled proxies in the order they were
+/// added, direct access and Mullvad bridges. When the API cannot be reached through the method in
+/// use, the next enabled method that the API can be reached through is used instead.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct ApiAccessSettings {
+    /// Whether the API may be reached directly.
+    pub direct_enabled: bool,
+    /// Whether the API may be reached through Mullvad bridges.
+    pub bridges_enabled: bool,
+    /// The name of the access method that is tried first, if any.
+    pub preferred: Option<String>,
+}
+
+impl Default for ApiAccessSettings {
+    fn default() -> Self {
+        ApiAccessSettings {
+            direct_enabled: true,
+            bridges_enabled: true,
+            preferred: None,
+        }
+    }
+}
+
+/// A user-configured way of reaching the API through a proxy.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ApiAccessMethod {
+    /// Unique name that identifies the access method.
+    pub name: String,
+    pub enabled: bool,
+    pub proxy: ApiProxy,
+}
+
+/// A proxy that API traffic can be sent through.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiProxy {
+    Socks5 {
+        address: SocketAddr,
+        auth: Option<ProxyAuth>,
+    },
+    HttpConnect {
+        address: SocketAddr,
+        auth: Option<ProxyAuth>,
+    },
+}
+
+impl ApiProxy {
+    pub fn address(&self) -> SocketAddr {
+        match self {
+            ApiProxy::Socks5 { address, .. } | ApiProxy::HttpConnect { address, .. } => *address,
+        }
+    }
+
+    pub fn auth(&self) -> Option<&ProxyAuth> {
+        match self {
+            ApiProxy::Socks5 { auth, .. } | ApiProxy::HttpConnect { auth, .. } => auth.as_ref(),
+        }
+    }
+}
+
+impl fmt::Display for ApiProxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiProxy::Socks5 { address, .. } => write!(f, "SOCKS5 proxy at {}", address)?,
+            ApiProxy::HttpConnect { address, .. } => {
+                write!(f, "HTTP CONNECT proxy at {}", address)?
+            }
+        }
+        if let Some(auth) = self.auth() {
+            write!(f, " as user {}", auth.username)?;
+        }
+        Ok(())
+    }
+}
+
+/// Credentials used to authenticate with a proxy.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ProxyAuth {
+    pub username: String,
+    pub password: String,
+}
@@ -53,6 +53,18 @@ impl<'a> From<&'a str> for AuthFailed {
     }
 }
 
+impl AuthFailed {
+    /// Returns whether authentication failed because of the account, rather than because of the
+    /// server that was authenticated with.
+    pub fn is_account_error(&self) -> bool {
+        use self::AuthFailedInner::*;
+        match self.reason {
+            InvalidAccount | ExpiredAccount | TooManyConnectons => true,
+            Unknown(..) => false,
+        }
+    }
+}
+
 impl fmt::Display for AuthFailed {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use self::AuthFailedInner::*;
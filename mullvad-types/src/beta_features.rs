@@ -0,0 +1,161 @@
+//! Experimental features that ship turned off, and that each user can turn on by name. This lets
+//! large features be tried out in releases before they are enabled for everyone.
+use crate::{settings::schema::SettingsSchema, units::FieldError};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, fmt, str::FromStr};
+
+/// An experimental feature that can be turned on in [`BetaFeatures`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BetaFeature {
+    /// The `quic` obfuscation mode may be used.
+    QuicObfuscation,
+    /// Relays that have been measured to be close by are preferred.
+    LatencyBasedSelection,
+    /// WireGuard tunnels are made quantum-resistant unless that is turned off explicitly.
+    QuantumResistantTunnel,
+}
+
+impl BetaFeature {
+    /// Every feature that can be turned on.
+    pub const ALL: &'static [BetaFeature] = &[
+        BetaFeature::QuicObfuscation,
+        BetaFeature::LatencyBasedSelection,
+        BetaFeature::QuantumResistantTunnel,
+    ];
+
+    /// The name of the feature in the settings.
+    pub fn name(&self) -> &'static str {
+        match self {
+            BetaFeature::QuicObfuscation => "quic-obfuscation",
+            BetaFeature::LatencyBasedSelection => "latency-based-selection",
+            BetaFeature::QuantumResistantTunnel => "quantum-resistant-tunnel",
+        }
+    }
+
+    /// A short description of what turning the feature on does.
+    pub fn description(&self) -> &'static str {
+        match self {
+            BetaFeature::QuicObfuscation => {
+                "Allow sending WireGuard traffic through a QUIC proxy with the quic obfuscation \
+                 mode. Requires a build with QUIC obfuscation"
+            }
+            BetaFeature::LatencyBasedSelection => {
+                "Prefer relays that have been measured to be close by, such as with \
+                 'mullvad relay list --latency'"
+            }
+            BetaFeature::QuantumResistantTunnel => {
+                "Negotiate a post-quantum preshared key with WireGuard relays when the \
+                 quantum-resistant setting is 'auto'"
+            }
+        }
+    }
+}
+
+impl fmt::Display for BetaFeature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for BetaFeature {
+    type Err = UnknownBetaFeature;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        BetaFeature::ALL
+            .iter()
+            .find(|feature| feature.name() == name)
+            .copied()
+            .ok_or_else(|| UnknownBetaFeature(name.to_owned()))
+    }
+}
+
+/// Returned when parsing the name of a feature that does not exist.
+#[derive(err_derive::Error, Debug, Clone, PartialEq)]
+#[error(display = "Unknown beta feature: {}", _0)]
+pub struct UnknownBetaFeature(pub String);
+
+/// The experimental features that are turned on. All features are off by default.
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize, Deserialize, SettingsSchema)]
+#[serde(default)]
+pub struct BetaFeatures {
+    /// Names of the experimental features that are turned on.
+    pub enabled: BTreeSet<String>,
+}
+
+impl BetaFeatures {
+    pub fn is_enabled(&self, feature: BetaFeature) -> bool {
+        self.enabled.contains(feature.name())
+    }
+
+    /// Turns `feature` on or off. Returns whether that changed anything.
+    pub fn set_enabled(&mut self, feature: BetaFeature, enabled: bool) -> bool {
+        if enabled {
+            self.enabled.insert(feature.name().to_owned())
+        } else {
+            self.enabled.remove(feature.name())
+        }
+    }
+
+    /// Returns an error naming the first feature that does not exist, if any.
+    pub fn validate(&self) -> Result<(), FieldError> {
+        for name in &self.enabled {
+            if let Err(error) = name.parse::<BetaFeature>() {
+                return Err(FieldError::new("beta_features.enabled", error));
+            }
+        }
+        Ok(())
+    }
+
+    /// Turns off the features that do not exist, such as ones that have been removed since the
+    /// settings were saved. Returns their names.
+    pub fn remove_unknown(&mut self) -> Vec<String> {
+        let unknown: Vec<String> = self
+            .enabled
+            .iter()
+            .filter(|name| name.parse::<BetaFeature>().is_err())
+            .cloned()
+            .collect();
+        for name in &unknown {
+            self.enabled.remove(name);
+        }
+        unknown
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_names() {
+        for feature in BetaFeature::ALL {
+            assert_eq!(feature.name().parse::<BetaFeature>(), Ok(*feature));
+        }
+        assert_eq!(
+            "unknown-feature".parse::<BetaFeature>(),
+            Err(UnknownBetaFeature("unknown-feature".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_enabled() {
+        let mut features = BetaFeatures::default();
+        assert!(!features.is_enabled(BetaFeature::QuicObfuscation));
+        assert!(features.set_enabled(BetaFeature::QuicObfuscation, true));
+        assert!(!features.set_enabled(BetaFeature::QuicObfuscation, true));
+        assert!(features.is_enabled(BetaFeature::QuicObfuscation));
+        assert!(!features.is_enabled(BetaFeature::LatencyBasedSelection));
+        assert!(features.validate().is_ok());
+
+        features.enabled.insert("removed-feature".to_owned());
+        assert_eq!(
+            features.validate().unwrap_err().field,
+            "beta_features.enabled"
+        );
+        assert_eq!(
+            features.remove_unknown(),
+            vec!["removed-feature".to_owned()]
+        );
+        assert!(features.is_enabled(BetaFeature::QuicObfuscation));
+    }
+}
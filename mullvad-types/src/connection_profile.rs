@@ -0,0 +1,62 @@
+//! Connection parameters that are known to work better than the defaults on restrictive networks,
+//! such as networks that block UDP. The profiles are shipped with the app and fetched from the
+//! API, and are used for the first connection attempts in the country that the device is in.
+use serde::{Deserialize, Serialize};
+use talpid_types::net::TransportProtocol;
+
+/// Recommended connection parameters for the networks in a country.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionProfile {
+    /// Name of the country, as returned by the GeoIP lookup of the device location.
+    pub country: String,
+    /// The transport protocol to reach the relay over. WireGuard is tunneled over TCP if this is
+    /// TCP.
+    pub protocol: TransportProtocol,
+    /// The port to connect to, if some ports work better than others.
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// Whether to obfuscate WireGuard traffic, if the obfuscation mode is left to the daemon.
+    #[serde(default)]
+    pub obfuscation: bool,
+}
+
+/// All known connection profiles.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionProfiles {
+    pub profiles: Vec<ConnectionProfile>,
+}
+
+impl ConnectionProfiles {
+    /// Returns the profile for `country`, if there is one. Names are compared case-insensitively.
+    pub fn find(&self, country: &str) -> Option<&ConnectionProfile> {
+        self.profiles
+            .iter()
+            .find(|profile| profile.country.eq_ignore_ascii_case(country))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_find_profile() {
+        let profiles: ConnectionProfiles = serde_json::from_str(
+            r#"{
+                "profiles": [
+                    { "country": "Freedonia", "protocol": "tcp", "port": 443 },
+                    { "country": "Sylvania", "protocol": "udp", "obfuscation": true }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let profile = profiles.find("freedonia").unwrap();
+        assert_eq!(profile.protocol, TransportProtocol::Tcp);
+        assert_eq!(profile.port, Some(443));
+        assert!(!profile.obfuscation);
+
+        assert!(profiles.find("Sylvania").unwrap().obfuscation);
+        assert!(profiles.find("Sweden").is_none());
+    }
+}
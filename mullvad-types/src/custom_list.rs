@@ -0,0 +1,383 @@
+//! Custom lists are named collections of locations and relays, such as a list of favorite
+//! servers.
+use crate::{relay_constraints::LocationConstraint, relay_list::RelayList};
+use serde::{Deserialize, Serialize};
+
+/// The maximum length of the name of a custom list, in characters.
+pub const MAX_NAME_LENGTH: usize = 30;
+
+/// Version of the format written by [`export`]. Files with an older version can still be
+/// imported.
+pub const EXPORT_FORMAT_VERSION: u32 = 1;
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "The name of a custom list must not be empty")]
+    EmptyName,
+
+    #[error(
+        display = "The name of a custom list must be at most {} characters",
+        MAX_NAME_LENGTH
+    )]
+    NameTooLong,
+
+    #[error(display = "There is more than one custom list named \"{}\"", _0)]
+    DuplicateName(String),
+
+    #[error(display = "The custom list \"{}\" contains {} more than once", _0, _1)]
+    DuplicateLocation(String, LocationConstraint),
+
+    #[error(display = "The custom list \"{}\" contains an invalid location", _0)]
+    InvalidLocation(String),
+
+    #[error(display = "{} in the custom list \"{}\" does not exist", _1, _0)]
+    UnknownLocation(String, LocationConstraint),
+
+    #[error(display = "Unsupported custom list format version: {}", _0)]
+    UnsupportedVersion(u32),
+
+    #[error(display = "Failed to parse custom lists")]
+    Parse(#[error(source)] serde_json::Error),
+
+    #[error(display = "Failed to serialize custom lists")]
+    Serialize(#[error(source)] serde_json::Error),
+}
+
+/// A named collection of countries, cities and relays.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CustomList {
+    pub name: String,
+    pub locations: Vec<LocationConstraint>,
+}
+
+impl CustomList {
+    /// Creates an empty custom list.
+    pub fn new(name: String) -> Result<Self, Error> {
+        validate_name(&name)?;
+        Ok(CustomList {
+            name,
+            locations: Vec::new(),
+        })
+    }
+
+    /// Adds a location to the list. Returns `false` if the list already contains it.
+    pub fn add_location(&mut self, location: LocationConstraint) -> bool {
+        if self.locations.contains(&location) {
+            return false;
+        }
+        self.locations.push(location);
+        true
+    }
+
+    /// Removes a location from the list. Returns `false` if the list does not contain it.
+    pub fn remove_location(&mut self, location: &LocationConstraint) -> bool {
+        let num_locations = self.locations.len();
+        self.locations.retain(|member| member != location);
+        self.locations.len() != num_locations
+    }
+
+    /// Checks that the name is valid and that no location occurs more than once.
+    pub fn validate(&self) -> Result<(), Error> {
+        validate_name(&self.name)?;
+        for (index, location) in self.locations.iter().enumerate() {
+            if self.locations[..index].contains(location) {
+                return Err(Error::DuplicateLocation(
+                    self.name.clone(),
+                    location.clone(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the locations in the list that do not exist in `relay_list`.
+    pub fn unknown_locations(&self, relay_list: &RelayList) -> Vec<&LocationConstraint> {
+        self.locations
+            .iter()
+            .filter(|location| !location_exists(location, relay_list))
+            .collect()
+    }
+
+    /// Checks that every location in the list exists in `relay_list`.
+    pub fn validate_against(&self, relay_list: &RelayList) -> Result<(), Error> {
+        match self.unknown_locations(relay_list).first() {
+            Some(location) => Err(Error::UnknownLocation(
+                self.name.clone(),
+                (*location).clone(),
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+fn validate_name(name: &str) -> Result<(), Error> {
+    if name.trim().is_empty() {
+        return Err(Error::EmptyName);
+    }
+    if name.chars().count() > MAX_NAME_LENGTH {
+        return Err(Error::NameTooLong);
+    }
+    Ok(())
+}
+
+fn location_exists(location: &LocationConstraint, relay_list: &RelayList) -> bool {
+    let find_country = |country_code: &str| {
+        relay_list
+            .countries
+            .iter()
+            .find(|country| country.code == country_code)
+    };
+    let find_city = |country_code: &str, city_code: &str| {
+        find_country(country_code)
+            .and_then(|country| country.cities.iter().find(|city| city.code == city_code))
+    };
+
+    match location {
+        LocationConstraint::Country(country) => find_country(country).is_some(),
+        LocationConstraint::City(country, city) => find_city(country, city).is_some(),
+        LocationConstraint::Hostname(country, city, hostname) => find_city(country, city)
+            .map(|city| city.relays.iter().any(|relay| relay.hostname == *hostname))
+            .unwrap_or(false),
+    }
+}
+
+/// Checks that every list is valid and that no two lists have the same name.
+pub fn validate_lists(lists: &[CustomList]) -> Result<(), Error> {
+    for (index, list) in lists.iter().enumerate() {
+        list.validate()?;
+        if lists[..index].iter().any(|other| other.name == list.name) {
+            return Err(Error::DuplicateName(list.name.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// The file format used when importing and exporting custom lists. It is kept separate from
+/// [`CustomList`] so that the format stays the same if the internal representation changes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CustomListsFile {
+    format_version: u32,
+    custom_lists: Vec<CustomListEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CustomListEntry {
+    name: String,
+    #[serde(default)]
+    locations: Vec<LocationEntry>,
+}
+
+/// A country, a city within a country, or a relay within a city.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct LocationEntry {
+    country: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    city: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hostname: Option<String>,
+}
+
+impl From<&LocationConstraint> for LocationEntry {
+    fn from(location: &LocationConstraint) -> Self {
+        let (country, city, hostname) = match location.clone() {
+            LocationConstraint::Country(country) => (country, None, None),
+            LocationConstraint::City(country, city) => (country, Some(city), None),
+            LocationConstraint::Hostname(country, city, hostname) => {
+                (country, Some(city), Some(hostname))
+            }
+        };
+        LocationEntry {
+            country,
+            city,
+            hostname,
+        }
+    }
+}
+
+impl LocationEntry {
+    fn into_location(self) -> Option<LocationConstraint> {
+        if self.country.is_empty() {
+            return None;
+        }
+        match (self.city, self.hostname) {
+            (None, None) => Some(LocationConstraint::Country(self.country)),
+            (Some(city), None) if !city.is_empty() => {
+                Some(LocationConstraint::City(self.country, city))
+            }
+            (Some(city), Some(hostname)) if !city.is_empty() && !hostname.is_empty() => {
+                Some(LocationConstraint::Hostname(self.country, city, hostname))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Serializes custom lists into the import/export format.
+pub fn export(lists: &[CustomList]) -> Result<String, Error> {
+    let file = CustomListsFile {
+        format_version: EXPORT_FORMAT_VERSION,
+        custom_lists: lists
+            .iter()
+            .map(|list| CustomListEntry {
+                name: list.name.clone(),
+                locations: list.locations.iter().map(LocationEntry::from).collect(),
+            })
+            .collect(),
+    };
+    serde_json::to_string_pretty(&file).map_err(Error::Serialize)
+}
+
+/// Parses and validates custom lists in the import/export format. The locations are not checked
+/// against the relay list, since relays may be removed after the lists were exported.
+pub fn import(data: &str) -> Result<Vec<CustomList>, Error> {
+    let file: CustomListsFile = serde_json::from_str(data).map_err(Error::Parse)?;
+    if file.format_version == 0 || file.format_version > EXPORT_FORMAT_VERSION {
+        return Err(Error::UnsupportedVersion(file.format_version));
+    }
+
+    let lists = file
+        .custom_lists
+        .into_iter()
+        .map(|entry| {
+            let locations = entry
+                .locations
+                .into_iter()
+                .map(|location| {
+                    location
+                        .into_location()
+                        .ok_or_else(|| Error::InvalidLocation(entry.name.clone()))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(CustomList {
+                name: entry.name,
+                locations,
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    validate_lists(&lists)?;
+    Ok(lists)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::relay_list::{RelayListCity, RelayListCountry};
+
+    fn relay_list() -> RelayList {
+        RelayList {
+            etag: None,
+            countries: vec![RelayListCountry {
+                name: "Sweden".to_owned(),
+                code: "se".to_owned(),
+                cities: vec![RelayListCity {
+                    name: "Gothenburg".to_owned(),
+                    code: "got".to_owned(),
+                    latitude: 57.70887,
+                    longitude: 11.97456,
+                    relays: vec![],
+                }],
+            }],
+        }
+    }
+
+    #[test]
+    fn test_export_import() {
+        let mut list = CustomList::new("Favorites".to_owned()).unwrap();
+        assert!(list.add_location(LocationConstraint::Country("se".to_owned())));
+        assert!(list.add_location(LocationConstraint::City("se".to_owned(), "got".to_owned())));
+        assert!(list.add_location(LocationConstraint::Hostname(
+            "se".to_owned(),
+            "got".to_owned(),
+            "se-got-wg-001".to_owned()
+        )));
+        assert!(!list.add_location(LocationConstraint::Country("se".to_owned())));
+
+        let exported = export(&[list.clone()]).unwrap();
+        assert_eq!(import(&exported).unwrap(), vec![list]);
+    }
+
+    #[test]
+    fn test_import_format() {
+        let lists = import(
+            r#"{
+                "format_version": 1,
+                "custom_lists": [
+                    {
+                        "name": "Nordics",
+                        "locations": [
+                            { "country": "se" },
+                            { "country": "no", "city": "osl" }
+                        ]
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            lists[0].locations,
+            vec![
+                LocationConstraint::Country("se".to_owned()),
+                LocationConstraint::City("no".to_owned(), "osl".to_owned()),
+            ]
+        );
+
+        assert!(matches!(
+            import(r#"{ "format_version": 2, "custom_lists": [] }"#),
+            Err(Error::UnsupportedVersion(2))
+        ));
+        assert!(matches!(
+            import(
+                r#"{
+                    "format_version": 1,
+                    "custom_lists": [{ "name": "a", "locations": [{ "country": "se", "hostname": "x" }] }]
+                }"#
+            ),
+            Err(Error::InvalidLocation(_))
+        ));
+        assert!(matches!(
+            import(
+                r#"{
+                    "format_version": 1,
+                    "custom_lists": [{ "name": "a" }, { "name": "a" }]
+                }"#
+            ),
+            Err(Error::DuplicateName(_))
+        ));
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(matches!(
+            CustomList::new(" ".to_owned()),
+            Err(Error::EmptyName)
+        ));
+        assert!(matches!(
+            CustomList::new("a".repeat(MAX_NAME_LENGTH + 1)),
+            Err(Error::NameTooLong)
+        ));
+
+        let mut list = CustomList::new("Favorites".to_owned()).unwrap();
+        list.add_location(LocationConstraint::City("se".to_owned(), "got".to_owned()));
+        assert!(list.validate_against(&relay_list()).is_ok());
+
+        list.add_location(LocationConstraint::Hostname(
+            "se".to_owned(),
+            "got".to_owned(),
+            "se-got-wg-001".to_owned(),
+        ));
+        list.add_location(LocationConstraint::Country("de".to_owned()));
+        assert_eq!(list.unknown_locations(&relay_list()).len(), 2);
+        assert!(matches!(
+            list.validate_against(&relay_list()),
+            Err(Error::UnknownLocation(_, _))
+        ));
+
+        list.locations
+            .push(LocationConstraint::Country("de".to_owned()));
+        assert!(matches!(
+            list.validate(),
+            Err(Error::DuplicateLocation(_, _))
+        ));
+    }
+}
@@ -0,0 +1,45 @@
+//! Devices are the installations of the app that are registered with an account. Each device has
+//! its own WireGuard key, and an account can only have a limited number of devices.
+use chrono::{offset::Utc, DateTime};
+use serde::{Deserialize, Serialize};
+use talpid_types::net::wireguard::PublicKey;
+
+/// Identifier of a device, assigned by the API.
+pub type DeviceId = String;
+
+/// A device registered with an account.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Device {
+    pub id: DeviceId,
+    pub name: String,
+    pub pubkey: PublicKey,
+    pub created: DateTime<Utc>,
+}
+
+/// The devices registered with an account.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceList {
+    pub devices: Vec<Device>,
+    /// The ID of the device that the daemon is running on, if it is registered with the account.
+    pub current: Option<DeviceId>,
+}
+
+/// Whether this device is still registered with the account that it is logged in to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceState {
+    /// The device has not been found to be revoked.
+    Active,
+    /// The device has been removed from the account, so its WireGuard key can no longer be used
+    /// to connect. The device has to be registered again.
+    Revoked,
+}
+
+/// This device was given a new name by identity rotation. Its WireGuard key was replaced at the
+/// same time, which is announced separately.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceIdentityChange {
+    pub id: DeviceId,
+    pub previous_name: String,
+    pub name: String,
+}
@@ -1,15 +1,27 @@
 #![deny(rust_2018_idioms)]
 
 pub mod account;
+pub mod api_access;
 pub mod auth_failed;
+pub mod beta_features;
+pub mod connection_profile;
+pub mod custom_list;
+pub mod device;
 pub mod endpoint;
 pub mod location;
+pub mod network_rules;
+pub mod obfuscation;
+pub mod port_forward;
 pub mod relay_constraints;
+pub mod relay_filter;
 pub mod relay_list;
 pub mod settings;
 pub mod states;
+pub mod units;
 pub mod version;
 pub mod wireguard;
 
 mod custom_tunnel;
+pub mod openvpn_profile;
+pub mod wireguard_config;
 pub use crate::custom_tunnel::*;
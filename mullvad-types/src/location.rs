@@ -1,7 +1,11 @@
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
 
 pub type CountryCode = String;
 pub type CityCode = String;
@@ -21,7 +25,55 @@ pub struct Location {
 const RAIDUS_OF_EARTH: f64 = 6372.8;
 
 impl Location {
+    pub fn coordinates(&self) -> Coordinates {
+        Coordinates {
+            latitude: self.latitude,
+            longitude: self.longitude,
+        }
+    }
+
+    /// Returns the distance to another location in kilometers.
     pub fn distance_from(&self, other: &Location) -> f64 {
+        self.coordinates().distance_to(&other.coordinates())
+    }
+}
+
+/// A position on the earth, in degrees.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Coordinates {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+#[derive(err_derive::Error, Debug, Clone, PartialEq)]
+pub enum CoordinatesError {
+    #[error(display = "Latitude must be between -90 and 90 degrees: {}", _0)]
+    InvalidLatitude(f64),
+
+    #[error(display = "Longitude must be between -180 and 180 degrees: {}", _0)]
+    InvalidLongitude(f64),
+
+    #[error(display = "Expected coordinates in the form \"<latitude>,<longitude>\"")]
+    Parse,
+}
+
+impl Coordinates {
+    /// Returns coordinates if the latitude and longitude are within their valid ranges.
+    pub fn new(latitude: f64, longitude: f64) -> Result<Self, CoordinatesError> {
+        if !(-90.0..=90.0).contains(&latitude) {
+            return Err(CoordinatesError::InvalidLatitude(latitude));
+        }
+        if !(-180.0..=180.0).contains(&longitude) {
+            return Err(CoordinatesError::InvalidLongitude(longitude));
+        }
+        Ok(Coordinates {
+            latitude,
+            longitude,
+        })
+    }
+
+    /// Returns the great-circle distance to other coordinates in kilometers.
+    pub fn distance_to(&self, other: &Coordinates) -> f64 {
         haversine_dist_deg(
             self.latitude,
             self.longitude,
@@ -31,6 +83,36 @@ impl Location {
     }
 }
 
+impl fmt::Display for Coordinates {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.4}°{}, {:.4}°{}",
+            self.latitude.abs(),
+            if self.latitude < 0.0 { 'S' } else { 'N' },
+            self.longitude.abs(),
+            if self.longitude < 0.0 { 'W' } else { 'E' },
+        )
+    }
+}
+
+impl FromStr for Coordinates {
+    type Err = CoordinatesError;
+
+    /// Parses coordinates in the form `<latitude>,<longitude>`, in decimal degrees.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',');
+        let (latitude, longitude) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(latitude), Some(longitude), None) => (latitude.trim(), longitude.trim()),
+            _ => return Err(CoordinatesError::Parse),
+        };
+        Coordinates::new(
+            latitude.parse().map_err(|_| CoordinatesError::Parse)?,
+            longitude.parse().map_err(|_| CoordinatesError::Parse)?,
+        )
+    }
+}
+
 /// Takes input as latitude and longitude degrees.
 fn haversine_dist_deg(lat: f64, lon: f64, other_lat: f64, other_lon: f64) -> f64 {
     haversine_dist_rad(
@@ -63,6 +145,196 @@ pub struct AmIMullvad {
     pub latitude: f64,
     pub longitude: f64,
     pub mullvad_exit_ip: bool,
+    #[serde(default)]
+    pub mullvad_exit_ip_hostname: Option<String>,
+    #[serde(default)]
+    pub blacklisted: AmIMullvadBlacklists,
+}
+
+/// Blacklist lookups for the exit IP, as returned by am.i.mullvad.net.
+#[derive(Debug, Default, Deserialize)]
+pub struct AmIMullvadBlacklists {
+    pub blacklisted: bool,
+    #[serde(default)]
+    pub results: Vec<AmIMullvadBlacklist>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AmIMullvadBlacklist {
+    pub name: String,
+    pub blacklisted: bool,
+}
+
+/// A DNS server that resolved a hostname of the DNS leak test of am.i.mullvad.net.
+#[derive(Debug, Deserialize)]
+pub struct AmIMullvadDnsServer {
+    pub ip: IpAddr,
+    pub mullvad_dns: bool,
+    #[serde(default)]
+    pub mullvad_dns_hostname: Option<String>,
+    #[serde(default)]
+    pub organization: Option<String>,
+}
+
+/// A DNS server that was seen resolving hostnames during a connection check.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DnsServerCheck {
+    pub ip: IpAddr,
+    /// Whether the server is run by Mullvad.
+    pub mullvad_dns: bool,
+    /// Hostname of the relay that runs the server, if any.
+    pub hostname: Option<Hostname>,
+    /// The organization that the IP of the server belongs to, if known.
+    pub organization: Option<String>,
+}
+
+impl From<AmIMullvadDnsServer> for DnsServerCheck {
+    fn from(server: AmIMullvadDnsServer) -> Self {
+        DnsServerCheck {
+            ip: server.ip,
+            mullvad_dns: server.mullvad_dns,
+            hostname: server.mullvad_dns_hostname,
+            organization: server.organization,
+        }
+    }
+}
+
+/// Result of checking the connection against am.i.mullvad.net from the daemon.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionCheck {
+    /// The IP address that traffic appears to come from.
+    pub exit_ip: IpAddr,
+    /// Whether the exit IP belongs to a Mullvad relay.
+    pub mullvad_exit_ip: bool,
+    /// Hostname of the relay that owns the exit IP, if any.
+    pub exit_hostname: Option<Hostname>,
+    /// Hostname of the relay that the daemon is connected to, if any.
+    pub expected_hostname: Option<Hostname>,
+    /// The country that the exit IP is located in.
+    pub exit_country: String,
+    /// The city that the exit IP is located in, if known.
+    pub exit_city: Option<String>,
+    /// The country of the relay that the daemon is connected to, if any.
+    pub expected_country: Option<String>,
+    /// The city of the relay that the daemon is connected to, if any.
+    pub expected_city: Option<String>,
+    /// The DNS servers that resolved hostnames for the daemon, or `None` if they could not be
+    /// determined.
+    pub dns_servers: Option<Vec<DnsServerCheck>>,
+    /// Whether the exit IP is on any blacklist.
+    pub blacklisted: bool,
+    /// Names of the blacklists that the exit IP is on.
+    pub blacklists: Vec<String>,
+}
+
+impl ConnectionCheck {
+    /// Creates the result of a check. `expected_relay` is the hostname and location of the relay
+    /// that the daemon is connected to, if any.
+    pub fn new(
+        response: AmIMullvad,
+        expected_relay: Option<(Hostname, Option<Location>)>,
+        dns_servers: Option<Vec<DnsServerCheck>>,
+    ) -> Self {
+        let (expected_hostname, expected_location) = match expected_relay {
+            Some((hostname, location)) => (Some(hostname), location),
+            None => (None, None),
+        };
+        ConnectionCheck {
+            exit_ip: response.ip,
+            mullvad_exit_ip: response.mullvad_exit_ip,
+            exit_hostname: response.mullvad_exit_ip_hostname,
+            expected_hostname,
+            exit_country: response.country,
+            exit_city: response.city,
+            expected_country: expected_location
+                .as_ref()
+                .map(|location| location.country.clone()),
+            expected_city: expected_location.map(|location| location.city),
+            dns_servers,
+            blacklisted: response.blacklisted.blacklisted,
+            blacklists: response
+                .blacklisted
+                .results
+                .into_iter()
+                .filter(|result| result.blacklisted)
+                .map(|result| result.name)
+                .collect(),
+        }
+    }
+
+    /// Returns whether traffic exits through the relay that the daemon is connected to.
+    pub fn hostname_matches(&self) -> bool {
+        match (&self.exit_hostname, &self.expected_hostname) {
+            (Some(exit), Some(expected)) => exit.eq_ignore_ascii_case(expected),
+            _ => false,
+        }
+    }
+
+    /// Returns whether the exit IP is located in the country of the relay that the daemon is
+    /// connected to. Cities are not compared, since the GeoIP database and the relay list do not
+    /// always agree on their names.
+    pub fn location_matches(&self) -> bool {
+        match &self.expected_country {
+            Some(expected) => expected.eq_ignore_ascii_case(&self.exit_country),
+            None => false,
+        }
+    }
+
+    /// Returns the DNS servers that are not run by Mullvad. These leak the hostnames that are
+    /// looked up.
+    pub fn leaking_dns_servers(&self) -> impl Iterator<Item = &DnsServerCheck> {
+        self.dns_servers
+            .iter()
+            .flatten()
+            .filter(|server| !server.mullvad_dns)
+    }
+
+    /// Returns why traffic does not exit where it is expected to, if it does not.
+    /// `previous_exit_ip` is the exit IP that the previous check of the same connection found.
+    /// Changes between IPv4 and IPv6 addresses are not considered changes, since the address
+    /// family that am.i.mullvad.net is reached over can vary.
+    pub fn exit_ip_problem(&self, previous_exit_ip: Option<IpAddr>) -> Option<ExitIpProblem> {
+        if !self.mullvad_exit_ip {
+            return Some(ExitIpProblem::NotMullvad);
+        }
+        if let (Some(exit), Some(_)) = (&self.exit_hostname, &self.expected_hostname) {
+            if !self.hostname_matches() {
+                return Some(ExitIpProblem::OtherRelay(exit.clone()));
+            }
+        }
+        match previous_exit_ip {
+            Some(previous)
+                if previous.is_ipv4() == self.exit_ip.is_ipv4() && previous != self.exit_ip =>
+            {
+                Some(ExitIpProblem::Changed(previous))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Why traffic does not exit where it is expected to while connected.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ExitIpProblem {
+    /// The exit IP does not belong to a Mullvad relay.
+    NotMullvad,
+    /// The exit IP belongs to another relay than the one that the daemon is connected to.
+    OtherRelay(Hostname),
+    /// The exit IP differs from the one that was found earlier during the same connection. Holds
+    /// the earlier exit IP.
+    Changed(IpAddr),
+}
+
+/// A periodic check of the exit IP found that traffic does not exit where it is expected to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExitIpMismatch {
+    /// Hostname of the relay that the daemon is connected to.
+    pub expected_hostname: Hostname,
+    /// The IP address that traffic appears to come from.
+    pub exit_ip: IpAddr,
+    pub problem: ExitIpProblem,
+    /// Whether the daemon is reconnecting because of the mismatch.
+    pub reconnecting: bool,
 }
 
 /// GeoIP information exposed from the daemon to frontends.
@@ -106,6 +378,15 @@ impl From<AmIMullvad> for GeoIpLocation {
     }
 }
 
+impl GeoIpLocation {
+    pub fn coordinates(&self) -> Coordinates {
+        Coordinates {
+            latitude: self.latitude,
+            longitude: self.longitude,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -127,4 +408,132 @@ mod tests {
             111.22634257109495
         );
     }
+
+    #[test]
+    fn test_coordinates() {
+        use super::{Coordinates, CoordinatesError};
+
+        let gothenburg: Coordinates = "57.70887, 11.97456".parse().unwrap();
+        let stockholm = Coordinates::new(59.3289, 18.0649).unwrap();
+        assert_eq!(gothenburg.distance_to(&stockholm).round(), 397.0);
+        assert_eq!(gothenburg.distance_to(&gothenburg), 0.0);
+        assert_eq!(gothenburg.to_string(), "57.7089°N, 11.9746°E");
+        assert_eq!(
+            Coordinates::new(-33.8688, -151.2093).unwrap().to_string(),
+            "33.8688°S, 151.2093°W"
+        );
+
+        assert_eq!(
+            Coordinates::new(91.0, 0.0),
+            Err(CoordinatesError::InvalidLatitude(91.0))
+        );
+        assert_eq!(
+            Coordinates::new(0.0, -180.5),
+            Err(CoordinatesError::InvalidLongitude(-180.5))
+        );
+        assert_eq!("57.7".parse::<Coordinates>(), Err(CoordinatesError::Parse));
+
+        let json = serde_json::to_string(&stockholm).unwrap();
+        assert_eq!(json, r#"{"latitude":59.3289,"longitude":18.0649}"#);
+        assert_eq!(
+            serde_json::from_str::<Coordinates>(&json).unwrap(),
+            stockholm
+        );
+    }
+
+    #[test]
+    fn test_connection_check() {
+        use super::{
+            AmIMullvad, AmIMullvadDnsServer, ConnectionCheck, DnsServerCheck, ExitIpProblem,
+            Location,
+        };
+
+        let response: AmIMullvad = serde_json::from_str(
+            r#"{
+                "ip": "185.213.154.68",
+                "country": "Sweden",
+                "city": "Gothenburg",
+                "longitude": 11.9667,
+                "latitude": 57.7072,
+                "mullvad_exit_ip": true,
+                "mullvad_exit_ip_hostname": "se-got-wg-001",
+                "blacklisted": {
+                    "blacklisted": true,
+                    "results": [
+                        {"name": "Spamhaus", "link": "https://example.com", "blacklisted": true},
+                        {"name": "Other", "link": "https://example.com", "blacklisted": false}
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+        let location = Location {
+            country: "Sweden".to_string(),
+            country_code: "se".to_string(),
+            city: "Gothenburg".to_string(),
+            city_code: "got".to_string(),
+            latitude: 57.7072,
+            longitude: 11.9667,
+        };
+        let dns_servers: Vec<AmIMullvadDnsServer> = serde_json::from_str(
+            r#"[
+                {"ip": "10.64.0.1", "country": "Sweden", "mullvad_dns": true,
+                 "mullvad_dns_hostname": "se-got-wg-001", "organization": "M247"},
+                {"ip": "192.0.2.53", "country": "Sweden", "mullvad_dns": false}
+            ]"#,
+        )
+        .unwrap();
+        let check = ConnectionCheck::new(
+            response,
+            Some(("SE-GOT-WG-001".to_string(), Some(location))),
+            Some(dns_servers.into_iter().map(DnsServerCheck::from).collect()),
+        );
+        assert!(check.mullvad_exit_ip);
+        assert!(check.hostname_matches());
+        assert!(check.location_matches());
+        assert_eq!(
+            check
+                .leaking_dns_servers()
+                .map(|server| server.ip.to_string())
+                .collect::<Vec<_>>(),
+            vec!["192.0.2.53".to_string()]
+        );
+        assert!(check.blacklisted);
+        assert_eq!(check.blacklists, vec!["Spamhaus".to_string()]);
+        assert_eq!(check.exit_ip_problem(None), None);
+        assert_eq!(check.exit_ip_problem(Some(check.exit_ip)), None);
+        assert_eq!(
+            check.exit_ip_problem(Some("2a03:1b20:5:f011::a01f".parse().unwrap())),
+            None
+        );
+        let previous = "185.213.154.69".parse().unwrap();
+        assert_eq!(
+            check.exit_ip_problem(Some(previous)),
+            Some(ExitIpProblem::Changed(previous))
+        );
+        let mut other_relay = check.clone();
+        other_relay.exit_hostname = Some("se-got-wg-002".to_string());
+        assert_eq!(
+            other_relay.exit_ip_problem(None),
+            Some(ExitIpProblem::OtherRelay("se-got-wg-002".to_string()))
+        );
+
+        let response: AmIMullvad = serde_json::from_str(
+            r#"{
+                "ip": "192.0.2.1",
+                "country": "Sweden",
+                "latitude": 57.7072,
+                "longitude": 11.9667,
+                "mullvad_exit_ip": false
+            }"#,
+        )
+        .unwrap();
+        let check = ConnectionCheck::new(response, Some(("se-got-wg-001".to_string(), None)), None);
+        assert!(!check.hostname_matches());
+        assert!(!check.location_matches());
+        assert_eq!(check.leaking_dns_servers().count(), 0);
+        assert!(!check.blacklisted);
+        assert!(check.blacklists.is_empty());
+        assert_eq!(check.exit_ip_problem(None), Some(ExitIpProblem::NotMullvad));
+    }
 }
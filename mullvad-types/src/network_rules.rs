@@ -0,0 +1,215 @@
+use ipnetwork::IpNetwork;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+use talpid_types::net::{fingerprint::FingerprintHash, NetworkIdentity};
+
+/// What to do with the tunnel when a network is joined.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NetworkAction {
+    /// Connect the tunnel.
+    Connect,
+    /// Disconnect the tunnel.
+    Disconnect,
+}
+
+impl fmt::Display for NetworkAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkAction::Connect => "connect".fmt(f),
+            NetworkAction::Disconnect => "disconnect".fmt(f),
+        }
+    }
+}
+
+impl FromStr for NetworkAction {
+    type Err = ParseNetworkActionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "connect" => Ok(NetworkAction::Connect),
+            "disconnect" => Ok(NetworkAction::Disconnect),
+            _ => Err(ParseNetworkActionError),
+        }
+    }
+}
+
+#[derive(err_derive::Error, Debug, Clone, PartialEq)]
+#[error(display = "Not a valid network action. Expected \"connect\" or \"disconnect\"")]
+pub struct ParseNetworkActionError;
+
+/// The networks that a rule applies to.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NetworkMatch {
+    /// The Wi-Fi network with this SSID.
+    Ssid(String),
+    /// Networks that lie within this subnet.
+    Subnet(IpNetwork),
+    /// The network whose default gateway has a MAC address with this hash.
+    Gateway(FingerprintHash),
+}
+
+impl NetworkMatch {
+    /// Returns whether `network` is one of the networks that are matched.
+    pub fn matches(&self, network: &NetworkIdentity) -> bool {
+        match self {
+            NetworkMatch::Ssid(ssid) => network.ssids.iter().any(|other| other == ssid),
+            NetworkMatch::Subnet(subnet) => network.networks.iter().any(|on_link| {
+                on_link.is_ipv4() == subnet.is_ipv4()
+                    && on_link.prefix() >= subnet.prefix()
+                    && subnet.contains(on_link.network())
+            }),
+            NetworkMatch::Gateway(hash) => network.fingerprint.gateway_mac == Some(*hash),
+        }
+    }
+}
+
+impl fmt::Display for NetworkMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkMatch::Ssid(ssid) => write!(f, "SSID \"{}\"", ssid),
+            NetworkMatch::Subnet(subnet) => write!(f, "subnet {}", subnet),
+            NetworkMatch::Gateway(hash) => write!(f, "gateway {}", hash),
+        }
+    }
+}
+
+/// What to do with the tunnel when networks that match a rule are joined.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+pub struct NetworkRule {
+    pub network: NetworkMatch,
+    pub action: NetworkAction,
+}
+
+/// Rules for connecting or disconnecting the tunnel depending on which network the host is on.
+/// The rules are only applied when the host joins another network, so the tunnel can still be
+/// connected and disconnected by hand while on a network.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct NetworkRules {
+    /// The rules, in the order that they are checked in.
+    pub rules: Vec<NetworkRule>,
+    /// What to do on networks that no rule matches. Nothing is done if this is not set.
+    pub default_action: Option<NetworkAction>,
+}
+
+impl NetworkRules {
+    /// Returns what to do on `network`: the action of the first rule that matches it, or the
+    /// default action if none does.
+    pub fn action(&self, network: &NetworkIdentity) -> Option<NetworkAction> {
+        self.rules
+            .iter()
+            .find(|rule| rule.network.matches(network))
+            .map(|rule| rule.action)
+            .or(self.default_action)
+    }
+
+    /// Adds a rule, replacing any rule for the same networks.
+    pub fn add(&mut self, rule: NetworkRule) {
+        match self
+            .rules
+            .iter_mut()
+            .find(|existing| existing.network == rule.network)
+        {
+            Some(existing) => existing.action = rule.action,
+            None => self.rules.push(rule),
+        }
+    }
+
+    /// Removes the rule for `network`. Returns whether there was one.
+    pub fn remove(&mut self, network: &NetworkMatch) -> bool {
+        let num_rules = self.rules.len();
+        self.rules.retain(|rule| rule.network != *network);
+        self.rules.len() != num_rules
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use talpid_types::net::fingerprint::{InterfaceType, NetworkFingerprint};
+
+    fn rule(network: NetworkMatch, action: NetworkAction) -> NetworkRule {
+        NetworkRule { network, action }
+    }
+
+    fn home_network() -> NetworkIdentity {
+        NetworkIdentity {
+            ssids: vec!["Home".to_owned()],
+            networks: vec!["192.168.1.0/24".parse().unwrap()],
+            ..NetworkIdentity::default()
+        }
+    }
+
+    #[test]
+    fn test_subnet_match() {
+        let network = home_network();
+        let subnet = |subnet: &str| NetworkMatch::Subnet(subnet.parse().unwrap());
+
+        assert!(subnet("192.168.1.0/24").matches(&network));
+        assert!(subnet("192.168.0.0/16").matches(&network));
+        assert!(!subnet("192.168.1.0/25").matches(&network));
+        assert!(!subnet("10.0.0.0/8").matches(&network));
+        assert!(!subnet("::/0").matches(&network));
+    }
+
+    #[test]
+    fn test_gateway_match() {
+        let mut network = home_network();
+        let gateway = NetworkFingerprint::new(
+            Some(&[0x00, 0x11, 0x22, 0x33, 0x44, 0x55]),
+            None,
+            InterfaceType::Ethernet,
+        );
+        let rule = NetworkMatch::Gateway(gateway.gateway_mac.unwrap());
+        assert!(!rule.matches(&network));
+        network.fingerprint = gateway;
+        assert!(rule.matches(&network));
+    }
+
+    #[test]
+    fn test_first_matching_rule_applies() {
+        let mut rules = NetworkRules {
+            rules: vec![
+                rule(
+                    NetworkMatch::Ssid("Home".to_owned()),
+                    NetworkAction::Disconnect,
+                ),
+                rule(
+                    NetworkMatch::Subnet("192.168.0.0/16".parse().unwrap()),
+                    NetworkAction::Connect,
+                ),
+            ],
+            default_action: None,
+        };
+        assert_eq!(
+            rules.action(&home_network()),
+            Some(NetworkAction::Disconnect)
+        );
+
+        let cafe = NetworkIdentity {
+            ssids: vec!["Cafe".to_owned()],
+            networks: vec!["10.1.0.0/16".parse().unwrap()],
+            ..NetworkIdentity::default()
+        };
+        assert_eq!(rules.action(&cafe), None);
+        rules.default_action = Some(NetworkAction::Connect);
+        assert_eq!(rules.action(&cafe), Some(NetworkAction::Connect));
+    }
+
+    #[test]
+    fn test_add_replaces_rule() {
+        let mut rules = NetworkRules::default();
+        let home = NetworkMatch::Ssid("Home".to_owned());
+        rules.add(rule(home.clone(), NetworkAction::Connect));
+        rules.add(rule(home.clone(), NetworkAction::Disconnect));
+        assert_eq!(
+            rules.rules,
+            vec![rule(home.clone(), NetworkAction::Disconnect)]
+        );
+
+        assert!(rules.remove(&home));
+        assert!(!rules.remove(&home));
+    }
+}
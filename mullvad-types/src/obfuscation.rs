@@ -0,0 +1,259 @@
+use crate::{relay_constraints::Constraint, units::FieldError};
+use serde::{Deserialize, Serialize};
+use std::{fmt, net::SocketAddr};
+
+/// Which obfuscation to use for tunnel traffic, if any.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ObfuscationMode {
+    /// Let the daemon decide when to use obfuscation.
+    Auto,
+    /// Never use obfuscation.
+    Off,
+    /// Always proxy WireGuard traffic over TCP.
+    #[serde(rename = "udp2tcp", alias = "udp2_tcp")]
+    Udp2Tcp,
+    /// Always use the user-specified obfuscation endpoint.
+    Custom,
+    /// Always relay WireGuard traffic through a Shadowsocks server on the relay.
+    Shadowsocks,
+    /// Always send WireGuard traffic through the user-specified QUIC proxy. This is experimental
+    /// and only available in builds with the `quic-obfuscation` feature.
+    Quic,
+}
+
+impl Default for ObfuscationMode {
+    fn default() -> Self {
+        ObfuscationMode::Auto
+    }
+}
+
+impl fmt::Display for ObfuscationMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObfuscationMode::Auto => write!(f, "auto"),
+            ObfuscationMode::Off => write!(f, "off"),
+            ObfuscationMode::Udp2Tcp => write!(f, "udp2tcp"),
+            ObfuscationMode::Custom => write!(f, "custom"),
+            ObfuscationMode::Shadowsocks => write!(f, "shadowsocks"),
+            ObfuscationMode::Quic => write!(f, "quic"),
+        }
+    }
+}
+
+/// Parameters used when WireGuard traffic is proxied over TCP.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct Udp2TcpObfuscationSettings {
+    /// The TCP port on the relay to connect to.
+    pub port: Constraint<u16>,
+}
+
+impl fmt::Display for Udp2TcpObfuscationSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.port {
+            Constraint::Any => write!(f, "any port"),
+            Constraint::Only(port) => write!(f, "port {}", port),
+        }
+    }
+}
+
+/// Parameters used when WireGuard traffic is relayed through Shadowsocks.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct ShadowsocksObfuscationSettings {
+    /// The Shadowsocks port on the relay to send traffic to.
+    pub port: Constraint<u16>,
+}
+
+impl fmt::Display for ShadowsocksObfuscationSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.port {
+            Constraint::Any => write!(f, "any port"),
+            Constraint::Only(port) => write!(f, "port {}", port),
+        }
+    }
+}
+
+/// Parameters for an obfuscation endpoint provided by the user.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+pub struct CustomObfuscationSettings {
+    /// The address of the obfuscation endpoint.
+    pub endpoint: SocketAddr,
+}
+
+/// Parameters for a QUIC proxy provided by the user.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct QuicObfuscationSettings {
+    /// The address of the proxy.
+    pub endpoint: SocketAddr,
+    /// The name used to verify the TLS certificate of the proxy.
+    pub server_name: String,
+}
+
+impl fmt::Display for QuicObfuscationSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.endpoint, self.server_name)
+    }
+}
+
+/// Obfuscation settings. Parameters are kept for every mode, so that switching between modes
+/// does not discard them.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct ObfuscationSettings {
+    #[serde(alias = "selected_obfuscation")]
+    pub mode: ObfuscationMode,
+    pub udp2tcp: Udp2TcpObfuscationSettings,
+    pub shadowsocks: ShadowsocksObfuscationSettings,
+    pub custom: Option<CustomObfuscationSettings>,
+    pub quic: Option<QuicObfuscationSettings>,
+}
+
+impl ObfuscationSettings {
+    /// Returns an error naming the first invalid parameter, if any.
+    pub fn validate(&self) -> Result<(), FieldError> {
+        if self.udp2tcp.port == Constraint::Only(0) {
+            return Err(FieldError::new(
+                "obfuscation_settings.udp2tcp.port",
+                "the port must not be 0",
+            ));
+        }
+        if self.shadowsocks.port == Constraint::Only(0) {
+            return Err(FieldError::new(
+                "obfuscation_settings.shadowsocks.port",
+                "the port must not be 0",
+            ));
+        }
+
+        match self.custom {
+            Some(custom) => {
+                if custom.endpoint.ip().is_unspecified() || custom.endpoint.port() == 0 {
+                    return Err(FieldError::new(
+                        "obfuscation_settings.custom.endpoint",
+                        format!("{} is not a valid endpoint", custom.endpoint),
+                    ));
+                }
+            }
+            None if self.mode == ObfuscationMode::Custom => {
+                return Err(FieldError::new(
+                    "obfuscation_settings.custom",
+                    "an endpoint is required in custom mode",
+                ));
+            }
+            None => (),
+        }
+
+        match &self.quic {
+            Some(quic) => {
+                if quic.endpoint.ip().is_unspecified() || quic.endpoint.port() == 0 {
+                    return Err(FieldError::new(
+                        "obfuscation_settings.quic.endpoint",
+                        format!("{} is not a valid endpoint", quic.endpoint),
+                    ));
+                }
+                if quic.server_name.is_empty() {
+                    return Err(FieldError::new(
+                        "obfuscation_settings.quic.server_name",
+                        "the server name must not be empty",
+                    ));
+                }
+            }
+            None if self.mode == ObfuscationMode::Quic => {
+                return Err(FieldError::new(
+                    "obfuscation_settings.quic",
+                    "a proxy is required in QUIC mode",
+                ));
+            }
+            None => (),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_deserialization() {
+        let settings: ObfuscationSettings = serde_json::from_str(
+            r#"{
+                "selected_obfuscation": "udp2_tcp",
+                "udp2tcp": { "port": { "only": 443 } }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(settings.mode, ObfuscationMode::Udp2Tcp);
+        assert_eq!(settings.udp2tcp.port, Constraint::Only(443));
+        assert_eq!(settings.custom, None);
+
+        let settings: ObfuscationSettings = serde_json::from_str("{}").unwrap();
+        assert_eq!(settings, ObfuscationSettings::default());
+    }
+
+    #[test]
+    fn test_validation() {
+        let mut settings = ObfuscationSettings {
+            mode: ObfuscationMode::Custom,
+            ..ObfuscationSettings::default()
+        };
+        assert_eq!(
+            settings.validate().unwrap_err().field,
+            "obfuscation_settings.custom"
+        );
+
+        settings.custom = Some(CustomObfuscationSettings {
+            endpoint: "0.0.0.0:443".parse().unwrap(),
+        });
+        assert_eq!(
+            settings.validate().unwrap_err().field,
+            "obfuscation_settings.custom.endpoint"
+        );
+
+        settings.custom = Some(CustomObfuscationSettings {
+            endpoint: "192.0.2.1:443".parse().unwrap(),
+        });
+        assert!(settings.validate().is_ok());
+
+        settings.shadowsocks.port = Constraint::Only(0);
+        assert_eq!(
+            settings.validate().unwrap_err().field,
+            "obfuscation_settings.shadowsocks.port"
+        );
+
+        settings.udp2tcp.port = Constraint::Only(0);
+        assert_eq!(
+            settings.validate().unwrap_err().field,
+            "obfuscation_settings.udp2tcp.port"
+        );
+    }
+
+    #[test]
+    fn test_quic_validation() {
+        let mut settings = ObfuscationSettings {
+            mode: ObfuscationMode::Quic,
+            ..ObfuscationSettings::default()
+        };
+        assert_eq!(
+            settings.validate().unwrap_err().field,
+            "obfuscation_settings.quic"
+        );
+
+        settings.quic = Some(QuicObfuscationSettings {
+            endpoint: "192.0.2.1:443".parse().unwrap(),
+            server_name: String::new(),
+        });
+        assert_eq!(
+            settings.validate().unwrap_err().field,
+            "obfuscation_settings.quic.server_name"
+        );
+
+        settings.quic = Some(QuicObfuscationSettings {
+            endpoint: "192.0.2.1:443".parse().unwrap(),
+            server_name: "proxy.example.com".to_owned(),
+        });
+        assert!(settings.validate().is_ok());
+    }
+}
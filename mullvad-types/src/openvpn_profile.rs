@@ -0,0 +1,371 @@
+//! Import and export of OpenVPN client profiles (`.ovpn` files), so that servers not run by
+//! Mullvad can be used as custom relays.
+//!
+//! Only self-contained profiles are supported: certificates and keys must be included inline,
+//! since the daemon cannot be expected to have access to files referenced by the profile.
+use crate::{ConnectionConfig, CustomTunnelEndpoint};
+use std::{fmt::Write, net::Ipv4Addr};
+use talpid_types::net::{openvpn, Endpoint, TransportProtocol};
+
+/// The port used when a profile does not specify one.
+const DEFAULT_PORT: u16 = 1194;
+
+#[derive(err_derive::Error, Debug, PartialEq)]
+pub enum Error {
+    #[error(display = "The profile contains no remote")]
+    MissingRemote,
+
+    #[error(display = "The profile contains no CA certificate")]
+    MissingCa,
+
+    #[error(display = "Invalid port in the profile: {}", _0)]
+    InvalidPort(String),
+
+    #[error(display = "Unsupported protocol in the profile: {}", _0)]
+    UnsupportedProtocol(String),
+
+    #[error(display = "Invalid key direction in the profile: {}", _0)]
+    InvalidKeyDirection(String),
+
+    #[error(
+        display = "The profile refers to the file {} for \"{}\", only inline blocks are supported",
+        _1,
+        _0
+    )]
+    FileReference(String, String),
+
+    #[error(display = "The profile uses a TAP device, only TUN devices are supported")]
+    TapDevice,
+
+    #[error(display = "The block <{}> in the profile is never closed", _0)]
+    UnclosedBlock(String),
+
+    #[error(display = "The profile contains a client certificate but no private key")]
+    MissingKey,
+
+    #[error(display = "The profile requires a username and password")]
+    MissingCredentials,
+}
+
+/// Directives that take a file, which can also be given as an inline block.
+const INLINE_DIRECTIVES: &[&str] = &["ca", "cert", "key", "tls-auth", "tls-crypt"];
+
+/// Parses an OpenVPN client profile into a custom relay. The username and password are only
+/// used if the profile contains `auth-user-pass`.
+pub fn parse(
+    profile: &str,
+    username: Option<String>,
+    password: Option<String>,
+) -> Result<CustomTunnelEndpoint, Error> {
+    let mut host = None;
+    let mut port = None;
+    let mut protocol = None;
+    let mut server_profile = openvpn::ServerProfile {
+        ca: String::new(),
+        cert: None,
+        key: None,
+        tls_auth: None,
+        key_direction: None,
+        tls_crypt: None,
+        data_ciphers: None,
+        auth: None,
+        auth_user_pass: false,
+    };
+
+    let mut lines = profile.lines().map(str::trim);
+    while let Some(line) = lines.next() {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('<').and_then(|l| l.strip_suffix('>')) {
+            let closing_tag = format!("</{}>", name);
+            let mut contents = String::new();
+            loop {
+                match lines.next() {
+                    Some(line) if line == closing_tag => break,
+                    Some(line) => {
+                        contents.push_str(line);
+                        contents.push('\n');
+                    }
+                    None => return Err(Error::UnclosedBlock(name.to_owned())),
+                }
+            }
+            match name {
+                "ca" => server_profile.ca = contents,
+                "cert" => server_profile.cert = Some(contents),
+                "key" => server_profile.key = Some(contents),
+                "tls-auth" => server_profile.tls_auth = Some(contents),
+                "tls-crypt" => server_profile.tls_crypt = Some(contents),
+                _ => log::debug!("Ignoring the block <{}> in the OpenVPN profile", name),
+            }
+            continue;
+        }
+
+        let mut words = line.split_whitespace();
+        let directive = match words.next() {
+            Some(directive) => directive.trim_start_matches("--"),
+            None => continue,
+        };
+        let arguments: Vec<&str> = words.collect();
+
+        match directive {
+            // Only the first remote is used
+            "remote" if host.is_none() => {
+                host = arguments.get(0).map(|host| host.to_string());
+                if let Some(remote_port) = arguments.get(1) {
+                    port = Some(parse_port(remote_port)?);
+                }
+                if let Some(remote_protocol) = arguments.get(2) {
+                    protocol = Some(parse_protocol(remote_protocol)?);
+                }
+            }
+            "port" if port.is_none() => {
+                port = arguments.get(0).map(|port| parse_port(port)).transpose()?;
+            }
+            "proto" if protocol.is_none() => {
+                protocol = arguments
+                    .get(0)
+                    .map(|protocol| parse_protocol(protocol))
+                    .transpose()?;
+            }
+            "dev" | "dev-type" if arguments.get(0).map_or(false, |dev| dev.starts_with("tap")) => {
+                return Err(Error::TapDevice);
+            }
+            directive
+                if INLINE_DIRECTIVES.contains(&directive)
+                    && !arguments.is_empty()
+                    && arguments[0] != "[inline]" =>
+            {
+                return Err(Error::FileReference(
+                    directive.to_owned(),
+                    arguments[0].to_owned(),
+                ));
+            }
+            "tls-auth" => {
+                if let Some(direction) = arguments.get(1) {
+                    server_profile.key_direction = Some(parse_key_direction(direction)?);
+                }
+            }
+            "key-direction" => {
+                server_profile.key_direction = arguments
+                    .get(0)
+                    .map(|direction| parse_key_direction(direction))
+                    .transpose()?;
+            }
+            "data-ciphers" | "ncp-ciphers" => {
+                server_profile.data_ciphers = arguments.get(0).map(|ciphers| ciphers.to_string());
+            }
+            "cipher" if server_profile.data_ciphers.is_none() => {
+                server_profile.data_ciphers = arguments.get(0).map(|cipher| cipher.to_string());
+            }
+            "auth" => server_profile.auth = arguments.get(0).map(|auth| auth.to_string()),
+            "auth-user-pass" => {
+                if let Some(file) = arguments.get(0) {
+                    return Err(Error::FileReference(directive.to_owned(), file.to_string()));
+                }
+                server_profile.auth_user_pass = true;
+            }
+            _ => (),
+        }
+    }
+
+    let host = host.ok_or(Error::MissingRemote)?;
+    if server_profile.ca.is_empty() {
+        return Err(Error::MissingCa);
+    }
+    if server_profile.cert.is_some() && server_profile.key.is_none() {
+        return Err(Error::MissingKey);
+    }
+    let (username, password) = if server_profile.auth_user_pass {
+        match (username, password) {
+            (Some(username), Some(password)) => (username, password),
+            _ => return Err(Error::MissingCredentials),
+        }
+    } else {
+        (String::new(), String::new())
+    };
+
+    // The address is resolved from the host when connecting.
+    let mut config = openvpn::ConnectionConfig::new(
+        Endpoint::new(
+            Ipv4Addr::UNSPECIFIED,
+            port.unwrap_or(DEFAULT_PORT),
+            protocol.unwrap_or(TransportProtocol::Udp),
+        ),
+        username,
+        password,
+    );
+    config.profile = Some(server_profile);
+
+    Ok(CustomTunnelEndpoint::new(
+        host,
+        ConnectionConfig::OpenVpn(config),
+    ))
+}
+
+/// Writes an OpenVPN client profile for a custom relay. Returns `None` if the relay is not an
+/// OpenVPN relay created from a profile. Usernames and passwords are not included.
+pub fn export(relay: &CustomTunnelEndpoint) -> Option<String> {
+    let config = match &relay.config {
+        ConnectionConfig::OpenVpn(config) => config,
+        ConnectionConfig::Wireguard(_) => return None,
+    };
+    let server_profile = config.profile.as_ref()?;
+
+    let mut profile = String::new();
+    let protocol = match config.endpoint.protocol {
+        TransportProtocol::Udp => "udp",
+        TransportProtocol::Tcp => "tcp-client",
+    };
+    let _ = writeln!(profile, "client");
+    let _ = writeln!(profile, "dev tun");
+    let _ = writeln!(profile, "proto {}", protocol);
+    let _ = writeln!(
+        profile,
+        "remote {} {}",
+        relay.host,
+        config.endpoint.address.port()
+    );
+    let _ = writeln!(profile, "nobind");
+    let _ = writeln!(profile, "remote-cert-tls server");
+    if let Some(ref data_ciphers) = server_profile.data_ciphers {
+        let _ = writeln!(profile, "data-ciphers {}", data_ciphers);
+    }
+    if let Some(ref auth) = server_profile.auth {
+        let _ = writeln!(profile, "auth {}", auth);
+    }
+    if server_profile.auth_user_pass {
+        let _ = writeln!(profile, "auth-user-pass");
+    }
+    if let Some(key_direction) = server_profile.key_direction {
+        let _ = writeln!(profile, "key-direction {}", key_direction);
+    }
+
+    let blocks = [
+        ("ca", Some(&server_profile.ca)),
+        ("cert", server_profile.cert.as_ref()),
+        ("key", server_profile.key.as_ref()),
+        ("tls-auth", server_profile.tls_auth.as_ref()),
+        ("tls-crypt", server_profile.tls_crypt.as_ref()),
+    ];
+    for (name, contents) in blocks.iter() {
+        if let Some(contents) = contents {
+            let _ = write!(profile, "<{}>\n{}</{}>\n", name, contents, name);
+        }
+    }
+
+    Some(profile)
+}
+
+fn parse_port(port: &str) -> Result<u16, Error> {
+    port.parse()
+        .map_err(|_| Error::InvalidPort(port.to_owned()))
+}
+
+fn parse_protocol(protocol: &str) -> Result<TransportProtocol, Error> {
+    match protocol {
+        "udp" | "udp4" | "udp6" => Ok(TransportProtocol::Udp),
+        "tcp" | "tcp4" | "tcp6" | "tcp-client" | "tcp4-client" | "tcp6-client" => {
+            Ok(TransportProtocol::Tcp)
+        }
+        _ => Err(Error::UnsupportedProtocol(protocol.to_owned())),
+    }
+}
+
+fn parse_key_direction(direction: &str) -> Result<u8, Error> {
+    match direction {
+        "0" => Ok(0),
+        "1" => Ok(1),
+        _ => Err(Error::InvalidKeyDirection(direction.to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CA: &str = "-----BEGIN CERTIFICATE-----\nMIIB\n-----END CERTIFICATE-----\n";
+    const TLS_AUTH: &str =
+        "-----BEGIN OpenVPN Static key V1-----\nabcd\n-----END OpenVPN Static key V1-----\n";
+
+    fn profile() -> String {
+        format!(
+            "client\ndev tun\nproto tcp\nremote vpn.example.com 443\nremote backup.example.com \
+             1194 udp\ncipher AES-256-GCM\nauth SHA256\nauth-user-pass\nkey-direction 1\n\
+             <ca>\n{}</ca>\n<tls-auth>\n{}</tls-auth>\n",
+            CA, TLS_AUTH
+        )
+    }
+
+    #[test]
+    fn test_parse() {
+        let relay = parse(
+            &profile(),
+            Some("user".to_owned()),
+            Some("secret".to_owned()),
+        )
+        .unwrap();
+        assert_eq!(relay.host, "vpn.example.com");
+
+        let config = match relay.config {
+            ConnectionConfig::OpenVpn(config) => config,
+            _ => panic!("Expected an OpenVPN relay"),
+        };
+        assert_eq!(config.endpoint.address.port(), 443);
+        assert_eq!(config.endpoint.protocol, TransportProtocol::Tcp);
+        assert_eq!(config.username, "user");
+
+        let server_profile = config.profile.unwrap();
+        assert_eq!(server_profile.ca, CA);
+        assert_eq!(server_profile.tls_auth.as_deref(), Some(TLS_AUTH));
+        assert_eq!(server_profile.key_direction, Some(1));
+        assert_eq!(server_profile.data_ciphers.as_deref(), Some("AES-256-GCM"));
+        assert_eq!(server_profile.auth.as_deref(), Some("SHA256"));
+        assert!(server_profile.auth_user_pass);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse(&profile(), None, None).unwrap_err(),
+            Error::MissingCredentials
+        );
+        assert_eq!(
+            parse("client\nremote vpn.example.com\nca ca.crt\n", None, None).unwrap_err(),
+            Error::FileReference("ca".to_owned(), "ca.crt".to_owned())
+        );
+        assert_eq!(
+            parse("client\ndev tap\n", None, None).unwrap_err(),
+            Error::TapDevice
+        );
+        assert_eq!(
+            parse("remote vpn.example.com\n<ca>\nMIIB\n", None, None).unwrap_err(),
+            Error::UnclosedBlock("ca".to_owned())
+        );
+        assert_eq!(
+            parse(&format!("<ca>\n{}</ca>\n", CA), None, None).unwrap_err(),
+            Error::MissingRemote
+        );
+    }
+
+    #[test]
+    fn test_export_roundtrip() {
+        let relay = parse(
+            &profile(),
+            Some("user".to_owned()),
+            Some("secret".to_owned()),
+        )
+        .unwrap();
+        let exported = export(&relay).unwrap();
+        assert!(!exported.contains("secret"));
+
+        let reimported = parse(
+            &exported,
+            Some("user".to_owned()),
+            Some("secret".to_owned()),
+        )
+        .unwrap();
+        assert_eq!(relay, reimported);
+    }
+}
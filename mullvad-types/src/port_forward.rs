@@ -0,0 +1,19 @@
+//! Ports that are forwarded from relays to this device. A forwarded port belongs to a city and to
+//! the WireGuard key of a device, so it is only reachable while connected to a relay in that city
+//! using that key.
+use serde::{Deserialize, Serialize};
+use talpid_types::net::wireguard::PublicKey;
+
+/// Identifier of a forwarded port, assigned by the API.
+pub type PortForwardId = String;
+
+/// A port that is forwarded from the relays in a city to a device of the account.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortForward {
+    pub id: PortForwardId,
+    pub port: u16,
+    pub country_code: String,
+    pub city_code: String,
+    /// The WireGuard key of the device that the port is forwarded to.
+    pub pubkey: PublicKey,
+}
@@ -3,6 +3,7 @@
 
 use crate::{
     location::{CityCode, CountryCode, Hostname},
+    relay_filter::RelayFilter,
     relay_list::{OpenVpnEndpointData, Relay, WireguardEndpointData},
     CustomTunnelEndpoint,
 };
@@ -177,7 +178,11 @@ impl RelaySettings {
     pub(crate) fn ensure_bridge_compatibility(&mut self) {
         match self {
             RelaySettings::Normal(ref mut constraints) => {
-                if constraints.tunnel_protocol == Constraint::Only(TunnelType::Wireguard) {
+                // WireGuard can only be used with bridges over TCP, which is not supported on
+                // Android
+                if cfg!(target_os = "android")
+                    && constraints.tunnel_protocol == Constraint::Only(TunnelType::Wireguard)
+                {
                     constraints.tunnel_protocol = Constraint::Any;
                 }
                 if let Constraint::Only(TransportPort {
@@ -187,6 +192,13 @@ impl RelaySettings {
                 {
                     constraints.openvpn_constraints.port = Constraint::Any;
                 }
+                if let Constraint::Only(TransportPort {
+                    protocol: TransportProtocol::Udp,
+                    ..
+                }) = constraints.wireguard_constraints.port
+                {
+                    constraints.wireguard_constraints.port = Constraint::Any;
+                }
             }
             RelaySettings::CustomTunnelEndpoint(config) => {
                 if config.endpoint().protocol == TransportProtocol::Udp {
@@ -215,6 +227,9 @@ pub struct RelayConstraints {
     pub wireguard_constraints: WireguardConstraints,
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub openvpn_constraints: OpenVpnConstraints,
+    /// An expression that relays must match, in addition to the other constraints.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub filter: Constraint<RelayFilter>,
 }
 
 #[cfg(target_os = "android")]
@@ -226,6 +241,7 @@ impl Default for RelayConstraints {
             providers: Constraint::default(),
             wireguard_constraints: WireguardConstraints::default(),
             openvpn_constraints: OpenVpnConstraints::default(),
+            filter: Constraint::default(),
         }
     }
 }
@@ -244,6 +260,7 @@ impl RelayConstraints {
             openvpn_constraints: update
                 .openvpn_constraints
                 .unwrap_or_else(|| self.openvpn_constraints.clone()),
+            filter: update.filter.unwrap_or_else(|| self.filter.clone()),
         }
     }
 }
@@ -275,9 +292,13 @@ impl fmt::Display for RelayConstraints {
         }
         write!(f, " using ")?;
         match self.providers {
-            Constraint::Any => write!(f, "any provider"),
-            Constraint::Only(ref constraint) => constraint.fmt(f),
+            Constraint::Any => write!(f, "any provider")?,
+            Constraint::Only(ref constraint) => constraint.fmt(f)?,
+        }
+        if let Constraint::Only(ref filter) = self.filter {
+            write!(f, " matching {}", filter)?;
         }
+        Ok(())
     }
 }
 
@@ -627,7 +648,19 @@ impl RelaySettingsUpdate {
                 endpoint.endpoint().protocol == TransportProtocol::Tcp
             }
             RelaySettingsUpdate::Normal(update) => {
-                if let Some(Constraint::Only(TunnelType::Wireguard)) = &update.tunnel_protocol {
+                if cfg!(target_os = "android")
+                    && update.tunnel_protocol == Some(Constraint::Only(TunnelType::Wireguard))
+                {
+                    false
+                } else if let Some(WireguardConstraints {
+                    port:
+                        Constraint::Only(TransportPort {
+                            protocol: TransportProtocol::Udp,
+                            ..
+                        }),
+                    ..
+                }) = &update.wireguard_constraints
+                {
                     false
                 } else if let Some(constraints) = &update.openvpn_constraints {
                     if let Constraint::Only(TransportPort {
@@ -662,4 +695,6 @@ pub struct RelayConstraintsUpdate {
     pub wireguard_constraints: Option<WireguardConstraints>,
     #[cfg_attr(target_os = "android", jnix(default))]
     pub openvpn_constraints: Option<OpenVpnConstraints>,
+    #[cfg_attr(target_os = "android", jnix(default))]
+    pub filter: Option<Constraint<RelayFilter>>,
 }
@@ -0,0 +1,574 @@
+//! Expressions that filter the relays that may be selected, for policies that cannot be expressed
+//! with the other relay constraints. For example:
+//!
+//! ```text
+//! (country == "de" || country == "nl") && owned && protocol == wireguard
+//! ```
+//!
+//! The fields `country`, `city`, `hostname` and `provider` can be compared to a value with `==`
+//! and `!=`. Values are either quoted or bare words, and are compared case-insensitively. The
+//! field `protocol` can be compared to `wireguard` or `openvpn`, and `owned` is true for relays
+//! that are owned by Mullvad. Expressions are combined with `&&`, `||`, `!` and parentheses.
+use crate::{relay_constraints::Match, relay_list::Relay};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr};
+use talpid_types::net::TunnelType;
+
+#[derive(err_derive::Error, Debug, Clone, Eq, PartialEq)]
+pub enum ParseError {
+    #[error(display = "Unexpected end of the expression")]
+    UnexpectedEnd,
+
+    #[error(display = "Unexpected \"{}\" at position {}", _0, _1)]
+    UnexpectedToken(String, usize),
+
+    #[error(display = "Unterminated string starting at position {}", _0)]
+    UnterminatedString(usize),
+
+    #[error(display = "Unknown field \"{}\"", _0)]
+    UnknownField(String),
+
+    #[error(display = "Unknown protocol \"{}\". Expected wireguard or openvpn", _0)]
+    UnknownProtocol(String),
+
+    #[error(display = "The field \"{}\" must be compared to a value", _0)]
+    MissingComparison(String),
+}
+
+/// A parsed relay filter expression.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RelayFilter {
+    expression: Expression,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Expression {
+    Or(Box<Expression>, Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+    Owned,
+    Compare {
+        field: Field,
+        equal: bool,
+        value: String,
+    },
+    Protocol {
+        equal: bool,
+        protocol: TunnelType,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Field {
+    Country,
+    City,
+    Hostname,
+    Provider,
+}
+
+impl RelayFilter {
+    /// Returns whether `relay` may be used with tunnels of type `tunnel_type`.
+    pub fn matches_tunnel(&self, relay: &Relay, tunnel_type: TunnelType) -> bool {
+        self.expression.evaluate(relay, tunnel_type)
+    }
+
+    /// Returns a filter that matches relays in any of `cities`, given as pairs of country and
+    /// city codes, or `None` if there are no cities.
+    pub fn any_city<'a>(cities: impl IntoIterator<Item = (&'a str, &'a str)>) -> Option<Self> {
+        cities
+            .into_iter()
+            .map(|(country_code, city_code)| {
+                Expression::And(
+                    Box::new(Expression::Compare {
+                        field: Field::Country,
+                        equal: true,
+                        value: country_code.to_owned(),
+                    }),
+                    Box::new(Expression::Compare {
+                        field: Field::City,
+                        equal: true,
+                        value: city_code.to_owned(),
+                    }),
+                )
+            })
+            .fold(None, |filter, city| match filter {
+                Some(filter) => Some(Expression::Or(Box::new(filter), Box::new(city))),
+                None => Some(city),
+            })
+            .map(|expression| RelayFilter { expression })
+    }
+
+    /// Returns a filter that matches the relays that are matched by both filters.
+    pub fn and(self, other: RelayFilter) -> Self {
+        RelayFilter {
+            expression: Expression::And(Box::new(self.expression), Box::new(other.expression)),
+        }
+    }
+}
+
+impl Match<Relay> for RelayFilter {
+    /// Returns whether `relay` may be used with any tunnel type.
+    fn matches(&self, relay: &Relay) -> bool {
+        self.matches_tunnel(relay, TunnelType::Wireguard)
+            || self.matches_tunnel(relay, TunnelType::OpenVpn)
+    }
+}
+
+impl Expression {
+    fn evaluate(&self, relay: &Relay, tunnel_type: TunnelType) -> bool {
+        match self {
+            Expression::Or(left, right) => {
+                left.evaluate(relay, tunnel_type) || right.evaluate(relay, tunnel_type)
+            }
+            Expression::And(left, right) => {
+                left.evaluate(relay, tunnel_type) && right.evaluate(relay, tunnel_type)
+            }
+            Expression::Not(expression) => !expression.evaluate(relay, tunnel_type),
+            Expression::Owned => relay.owned,
+            Expression::Compare {
+                field,
+                equal,
+                value,
+            } => field.matches(relay, value) == *equal,
+            Expression::Protocol { equal, protocol } => (tunnel_type == *protocol) == *equal,
+        }
+    }
+
+    /// Binding strength of the expression, used to decide where parentheses are needed.
+    fn precedence(&self) -> u8 {
+        match self {
+            Expression::Or(..) => 0,
+            Expression::And(..) => 1,
+            _ => 2,
+        }
+    }
+
+    fn fmt_operand(&self, f: &mut fmt::Formatter<'_>, min_precedence: u8) -> fmt::Result {
+        if self.precedence() < min_precedence {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expression::Or(left, right) => {
+                left.fmt_operand(f, 0)?;
+                write!(f, " || ")?;
+                right.fmt_operand(f, 1)
+            }
+            Expression::And(left, right) => {
+                left.fmt_operand(f, 1)?;
+                write!(f, " && ")?;
+                right.fmt_operand(f, 2)
+            }
+            Expression::Not(expression) => {
+                write!(f, "!")?;
+                expression.fmt_operand(f, 2)
+            }
+            Expression::Owned => write!(f, "owned"),
+            Expression::Compare {
+                field,
+                equal,
+                value,
+            } => write!(
+                f,
+                "{} {} \"{}\"",
+                field,
+                if *equal { "==" } else { "!=" },
+                value
+            ),
+            Expression::Protocol { equal, protocol } => write!(
+                f,
+                "protocol {} {}",
+                if *equal { "==" } else { "!=" },
+                match protocol {
+                    TunnelType::Wireguard => "wireguard",
+                    TunnelType::OpenVpn => "openvpn",
+                }
+            ),
+        }
+    }
+}
+
+impl Field {
+    fn matches(&self, relay: &Relay, value: &str) -> bool {
+        let location = relay.location.as_ref();
+        match self {
+            Field::Country => {
+                relay.include_in_country
+                    && location.map_or(false, |loc| loc.country_code.eq_ignore_ascii_case(value))
+            }
+            Field::City => location.map_or(false, |loc| loc.city_code.eq_ignore_ascii_case(value)),
+            Field::Hostname => relay.hostname.eq_ignore_ascii_case(value),
+            Field::Provider => relay.provider.eq_ignore_ascii_case(value),
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Field::Country => "country",
+            Field::City => "city",
+            Field::Hostname => "hostname",
+            Field::Provider => "provider",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for RelayFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.expression.fmt(f)
+    }
+}
+
+impl FromStr for RelayFilter {
+    type Err = ParseError;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(expression)?,
+            position: 0,
+        };
+        let expression = parser.parse_or()?;
+        match parser.next() {
+            None => Ok(RelayFilter { expression }),
+            Some((token, position)) => {
+                Err(ParseError::UnexpectedToken(token.to_string(), position))
+            }
+        }
+    }
+}
+
+impl Serialize for RelayFilter {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for RelayFilter {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let expression = String::deserialize(deserializer)?;
+        expression.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Token {
+    LeftParen,
+    RightParen,
+    And,
+    Or,
+    Not,
+    Equal,
+    NotEqual,
+    Word(String),
+    Quoted(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::LeftParen => write!(f, "("),
+            Token::RightParen => write!(f, ")"),
+            Token::And => write!(f, "&&"),
+            Token::Or => write!(f, "||"),
+            Token::Not => write!(f, "!"),
+            Token::Equal => write!(f, "=="),
+            Token::NotEqual => write!(f, "!="),
+            Token::Word(word) => write!(f, "{}", word),
+            Token::Quoted(value) => write!(f, "\"{}\"", value),
+        }
+    }
+}
+
+/// Splits `expression` into tokens, paired with their byte offsets.
+fn tokenize(expression: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let mut tokens = vec![];
+    let mut chars = expression.char_indices().peekable();
+    while let Some((position, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::LeftParen,
+            ')' => Token::RightParen,
+            '&' | '|' | '=' => match chars.next() {
+                Some((_, next)) if next == c => match c {
+                    '&' => Token::And,
+                    '|' => Token::Or,
+                    _ => Token::Equal,
+                },
+                _ => return Err(ParseError::UnexpectedToken(c.to_string(), position)),
+            },
+            '!' => match chars.peek() {
+                Some((_, '=')) => {
+                    chars.next();
+                    Token::NotEqual
+                }
+                _ => Token::Not,
+            },
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, c)) => value.push(c),
+                        None => return Err(ParseError::UnterminatedString(position)),
+                    }
+                }
+                Token::Quoted(value)
+            }
+            c if is_word_char(c) => {
+                let mut word = c.to_string();
+                while let Some((_, c)) = chars.peek() {
+                    if !is_word_char(*c) {
+                        break;
+                    }
+                    word.push(*c);
+                    chars.next();
+                }
+                Token::Word(word)
+            }
+            c => return Err(ParseError::UnexpectedToken(c.to_string(), position)),
+        };
+        tokens.push((token, position));
+    }
+    Ok(tokens)
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '_' || c == '.'
+}
+
+/// A recursive descent parser for the grammar:
+///
+/// ```text
+/// or      = and ("||" and)*
+/// and     = unary ("&&" unary)*
+/// unary   = "!" unary | primary
+/// primary = "(" or ")" | "owned" | field ("==" | "!=") value
+/// ```
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<(Token, usize)> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn parse_or(&mut self) -> Result<Expression, ParseError> {
+        let mut expression = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            expression = Expression::Or(Box::new(expression), Box::new(self.parse_and()?));
+        }
+        Ok(expression)
+    }
+
+    fn parse_and(&mut self) -> Result<Expression, ParseError> {
+        let mut expression = self.parse_unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            expression = Expression::And(Box::new(expression), Box::new(self.parse_unary()?));
+        }
+        Ok(expression)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression, ParseError> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+            return Ok(Expression::Not(Box::new(self.parse_unary()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expression, ParseError> {
+        match self.next().ok_or(ParseError::UnexpectedEnd)? {
+            (Token::LeftParen, _) => {
+                let expression = self.parse_or()?;
+                match self.next() {
+                    Some((Token::RightParen, _)) => Ok(expression),
+                    Some((token, position)) => {
+                        Err(ParseError::UnexpectedToken(token.to_string(), position))
+                    }
+                    None => Err(ParseError::UnexpectedEnd),
+                }
+            }
+            (Token::Word(word), _) if word == "owned" => Ok(Expression::Owned),
+            (Token::Word(word), _) => {
+                let field = match word.as_str() {
+                    "country" => Some(Field::Country),
+                    "city" => Some(Field::City),
+                    "hostname" => Some(Field::Hostname),
+                    "provider" => Some(Field::Provider),
+                    "protocol" => None,
+                    _ => return Err(ParseError::UnknownField(word)),
+                };
+                let equal = match self.peek() {
+                    Some(Token::Equal) => true,
+                    Some(Token::NotEqual) => false,
+                    _ => return Err(ParseError::MissingComparison(word)),
+                };
+                self.next();
+                let value = match self.next().ok_or(ParseError::UnexpectedEnd)? {
+                    (Token::Word(value), _) | (Token::Quoted(value), _) => value,
+                    (token, position) => {
+                        return Err(ParseError::UnexpectedToken(token.to_string(), position))
+                    }
+                };
+                match field {
+                    Some(field) => Ok(Expression::Compare {
+                        field,
+                        equal,
+                        value,
+                    }),
+                    None => {
+                        let protocol = match value.to_lowercase().as_str() {
+                            "wireguard" => TunnelType::Wireguard,
+                            "openvpn" => TunnelType::OpenVpn,
+                            _ => return Err(ParseError::UnknownProtocol(value)),
+                        };
+                        Ok(Expression::Protocol { equal, protocol })
+                    }
+                }
+            }
+            (token, position) => Err(ParseError::UnexpectedToken(token.to_string(), position)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        location::Location,
+        relay_list::{RelayBridges, RelayMetadata, RelayTunnels},
+    };
+
+    fn relay(country_code: &str, owned: bool, provider: &str) -> Relay {
+        Relay {
+            hostname: format!("{}1-wireguard", country_code),
+            ipv4_addr_in: "10.0.0.1".parse().unwrap(),
+            ipv6_addr_in: None,
+            include_in_country: true,
+            active: true,
+            owned,
+            provider: provider.to_owned(),
+            weight: 1,
+            tunnels: RelayTunnels::default(),
+            bridges: RelayBridges::default(),
+            location: Some(Location {
+                country: String::new(),
+                country_code: country_code.to_owned(),
+                city: String::new(),
+                city_code: "xyz".to_owned(),
+                latitude: 0.0,
+                longitude: 0.0,
+            }),
+            metadata: RelayMetadata::default(),
+        }
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        let filter: RelayFilter =
+            r#"(country == "de" || country==NL) && owned && protocol == WireGuard"#
+                .parse()
+                .unwrap();
+        assert_eq!(
+            filter.to_string(),
+            r#"(country == "de" || country == "NL") && owned && protocol == wireguard"#
+        );
+        assert_eq!(filter.to_string().parse::<RelayFilter>().unwrap(), filter);
+
+        let filter: RelayFilter = r#"!(provider != "31173" && !owned)"#.parse().unwrap();
+        assert_eq!(filter.to_string(), r#"!(provider != "31173" && !owned)"#);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            "country == de &&".parse::<RelayFilter>(),
+            Err(ParseError::UnexpectedEnd)
+        );
+        assert_eq!(
+            "speed == fast".parse::<RelayFilter>(),
+            Err(ParseError::UnknownField("speed".to_owned()))
+        );
+        assert_eq!(
+            "protocol == ikev2".parse::<RelayFilter>(),
+            Err(ParseError::UnknownProtocol("ikev2".to_owned()))
+        );
+        assert_eq!(
+            "country".parse::<RelayFilter>(),
+            Err(ParseError::MissingComparison("country".to_owned()))
+        );
+        assert_eq!(
+            "country == \"de".parse::<RelayFilter>(),
+            Err(ParseError::UnterminatedString(11))
+        );
+        assert_eq!(
+            "owned owned".parse::<RelayFilter>(),
+            Err(ParseError::UnexpectedToken("owned".to_owned(), 6))
+        );
+    }
+
+    #[test]
+    fn test_matches() {
+        let filter: RelayFilter =
+            "(country == de || country == nl) && owned && protocol == wireguard"
+                .parse()
+                .unwrap();
+        let owned_de = relay("de", true, "31173");
+        let rented_de = relay("de", false, "M247");
+        let owned_se = relay("se", true, "31173");
+
+        assert!(filter.matches_tunnel(&owned_de, TunnelType::Wireguard));
+        assert!(!filter.matches_tunnel(&owned_de, TunnelType::OpenVpn));
+        assert!(filter.matches(&owned_de));
+        assert!(!filter.matches(&rented_de));
+        assert!(!filter.matches(&owned_se));
+
+        let filter: RelayFilter = "provider != m247".parse().unwrap();
+        assert!(filter.matches(&owned_de));
+        assert!(!filter.matches(&rented_de));
+    }
+
+    #[test]
+    fn test_any_city() {
+        assert_eq!(RelayFilter::any_city(vec![]), None);
+
+        let filter = RelayFilter::any_city(vec![("de", "xyz"), ("nl", "ams")]).unwrap();
+        assert_eq!(
+            filter.to_string(),
+            r#"country == "de" && city == "xyz" || country == "nl" && city == "ams""#
+        );
+        assert!(filter.matches(&relay("de", true, "31173")));
+        assert!(!filter.matches(&relay("nl", true, "31173")));
+
+        let filter = filter.and("owned".parse().unwrap());
+        assert!(filter.matches(&relay("de", true, "31173")));
+        assert!(!filter.matches(&relay("de", false, "M247")));
+    }
+
+    #[test]
+    fn test_serde() {
+        let filter: RelayFilter = "owned || protocol != openvpn".parse().unwrap();
+        let json = serde_json::to_string(&filter).unwrap();
+        assert_eq!(json, r#""owned || protocol != openvpn""#);
+        assert_eq!(serde_json::from_str::<RelayFilter>(&json).unwrap(), filter);
+        assert!(serde_json::from_str::<RelayFilter>(r#""owned &&""#).is_err());
+    }
+}
@@ -1,6 +1,6 @@
 use crate::{
     endpoint::MullvadEndpoint,
-    location::{CityCode, CountryCode, Location},
+    location::{CityCode, Coordinates, CountryCode, Location},
 };
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
@@ -8,6 +8,7 @@ use serde::{Deserialize, Serialize};
 use std::{
     fmt,
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
 };
 use talpid_types::net::{
     openvpn::{ProxySettings, ShadowsocksProxySettings},
@@ -33,6 +34,19 @@ impl RelayList {
             countries: Vec::new(),
         }
     }
+
+    /// Returns the city closest to the given coordinates, along with its country and the distance
+    /// to it in kilometers.
+    pub fn nearest_city(
+        &self,
+        coordinates: &Coordinates,
+    ) -> Option<(&RelayListCountry, &RelayListCity, f64)> {
+        self.countries
+            .iter()
+            .flat_map(|country| country.cities.iter().map(move |city| (country, city)))
+            .map(|(country, city)| (country, city, city.coordinates().distance_to(coordinates)))
+            .min_by(|(_, _, a), (_, _, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+    }
 }
 
 /// A list of [`RelayListCity`]s within a country. Used by [`RelayList`].
@@ -59,6 +73,15 @@ pub struct RelayListCity {
     pub relays: Vec<Relay>,
 }
 
+impl RelayListCity {
+    pub fn coordinates(&self) -> Coordinates {
+        Coordinates {
+            latitude: self.latitude,
+            longitude: self.longitude,
+        }
+    }
+}
+
 /// Stores information for a relay returned by the API at `v1/relays` using
 /// `mullvad_rpc::RelayListProxy`.
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -86,6 +109,50 @@ pub struct Relay {
     pub bridges: RelayBridges,
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub location: Option<Location>,
+    #[serde(skip_serializing_if = "RelayMetadata::is_empty", default)]
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub metadata: RelayMetadata,
+}
+
+/// Optional hints from the API about how to connect to a [`Relay`], so that client behavior can
+/// be tuned without a new release. They can be given for every relay of a tunnel protocol, or for
+/// a single relay, in which case they take precedence.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RelayMetadata {
+    /// Seconds to wait for a tunnel to the relay to start working, for relays that are far away
+    /// or congested.
+    pub handshake_timeout: Option<u16>,
+    /// Ports that obfuscation may use to reach the relay. Any port may be used if this is empty.
+    pub obfuscation_ports: Vec<u16>,
+}
+
+impl RelayMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.handshake_timeout.is_none() && self.obfuscation_ports.is_empty()
+    }
+
+    /// Returns these hints, with the ones that are not set taken from `defaults`.
+    pub fn or(&self, defaults: &RelayMetadata) -> RelayMetadata {
+        RelayMetadata {
+            handshake_timeout: self.handshake_timeout.or(defaults.handshake_timeout),
+            obfuscation_ports: if self.obfuscation_ports.is_empty() {
+                defaults.obfuscation_ports.clone()
+            } else {
+                self.obfuscation_ports.clone()
+            },
+        }
+    }
+
+    pub fn handshake_timeout(&self) -> Option<Duration> {
+        self.handshake_timeout
+            .map(|seconds| Duration::from_secs(u64::from(seconds)))
+    }
+
+    /// Returns whether obfuscation may use `port` to reach the relay.
+    pub fn allows_obfuscation_port(&self, port: u16) -> bool {
+        self.obfuscation_ports.is_empty() || self.obfuscation_ports.contains(&port)
+    }
 }
 
 /// Provides protocol-specific information about a [`Relay`].
@@ -142,6 +209,9 @@ pub struct WireguardEndpointData {
     pub ipv6_gateway: Ipv6Addr,
     /// The peer's public key
     pub public_key: wireguard::PublicKey,
+    /// Shadowsocks endpoints that WireGuard traffic can be relayed through
+    #[serde(default)]
+    pub shadowsocks: Vec<ShadowsocksEndpointData>,
     #[serde(default = "default_wg_transport")]
     #[serde(skip)]
     pub protocol: TransportProtocol,
@@ -1,21 +1,34 @@
 use crate::{
+    account::AccountExpirySettings,
+    api_access::{ApiAccessMethod, ApiAccessSettings, ApiResolutionStrategy},
+    beta_features::BetaFeatures,
+    network_rules::NetworkRules,
+    obfuscation::ObfuscationSettings,
     relay_constraints::{
         BridgeConstraints, BridgeSettings, BridgeState, Constraint, LocationConstraint,
         RelayConstraints, RelaySettings, RelaySettingsUpdate,
     },
+    units::{ByteSize, FieldError, HumanDuration},
     wireguard,
 };
 #[cfg(target_os = "android")]
 use jnix::{jni::objects::JObject, FromJava, IntoJava, JnixEnv};
-use log::{debug, info};
-use serde::{Deserialize, Serialize};
+use log::{debug, info, warn};
+use schema::SettingsSchema;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json;
-use std::net::IpAddr;
-#[cfg(target_os = "windows")]
-use std::{collections::HashSet, path::PathBuf};
-use talpid_types::net::{self, openvpn, GenericTunnelOptions};
+#[cfg(any(windows, target_os = "linux"))]
+use std::collections::HashSet;
+use std::{
+    convert::TryFrom,
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::PathBuf,
+    time::Duration,
+};
+use talpid_types::net::{self, openvpn, GenericTunnelOptions, Ipv6FailurePolicy};
 
-mod migrations;
+pub mod schema;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -27,25 +40,72 @@ pub enum Error {
 
     #[error(display = "Settings version mismatch")]
     VersionMismatch,
+}
 
-    #[error(display = "Unable to read any version of the settings")]
-    NoMatchingVersion,
+/// The version of the format of the settings. Settings of an earlier version are migrated by the
+/// daemon before they are read.
+#[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
+#[repr(u32)]
+pub enum SettingsVersion {
+    V2 = 2,
+    V3 = 3,
+    V4 = 4,
+    V5 = 5,
+    V6 = 6,
+}
+
+pub const CURRENT_SETTINGS_VERSION: SettingsVersion = SettingsVersion::V6;
+
+impl<'de> Deserialize<'de> for SettingsVersion {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match <u32>::deserialize(deserializer)? {
+            v if v == SettingsVersion::V2 as u32 => Ok(SettingsVersion::V2),
+            v if v == SettingsVersion::V3 as u32 => Ok(SettingsVersion::V3),
+            v if v == SettingsVersion::V4 as u32 => Ok(SettingsVersion::V4),
+            v if v == SettingsVersion::V5 as u32 => Ok(SettingsVersion::V5),
+            v if v == SettingsVersion::V6 as u32 => Ok(SettingsVersion::V6),
+            v => Err(serde::de::Error::custom(format!(
+                "{} is not a valid SettingsVersion",
+                v
+            ))),
+        }
+    }
+}
+
+impl Serialize for SettingsVersion {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_u32(*self as u32)
+    }
 }
 
 
 /// Mullvad daemon settings.
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, SettingsSchema)]
 #[serde(default)]
 #[cfg_attr(target_os = "android", derive(IntoJava))]
 #[cfg_attr(target_os = "android", jnix(package = "net.mullvad.mullvadvpn.model"))]
 pub struct Settings {
+    #[schema(skip)]
     account_token: Option<String>,
     #[cfg_attr(target_os = "android", jnix(skip))]
+    #[schema(skip)]
     wireguard: Option<wireguard::WireguardData>,
+    /// Constraints for selecting a relay, or a custom tunnel endpoint.
+    #[schema(reconnect)]
     relay_settings: RelaySettings,
+    /// Constraints for selecting a bridge, or a custom bridge.
     #[cfg_attr(target_os = "android", jnix(skip))]
+    #[schema(reconnect)]
     pub bridge_settings: BridgeSettings,
+    /// Whether to connect through a bridge.
     #[cfg_attr(target_os = "android", jnix(skip))]
+    #[schema(reconnect, values = "auto|on|off")]
     bridge_state: BridgeState,
     /// If the daemon should allow communication with private (LAN) networks.
     pub allow_lan: bool,
@@ -53,22 +113,159 @@ pub struct Settings {
     /// the firewall to not allow any traffic in or out.
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub block_when_disconnected: bool,
+    /// Whether to keep blocking while the daemon is not running, including at boot, when
+    /// `block_when_disconnected` is enabled. LAN access and the firewall exceptions still apply.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub persistent_lockdown: bool,
     /// If the daemon should connect the VPN tunnel directly on start or not.
     pub auto_connect: bool,
+    /// Whether to connect or disconnect the tunnel when joining certain networks.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub network_rules: NetworkRules,
     /// Options that should be applied to tunnels of a specific type regardless of where the relays
     /// might be located.
+    #[schema(nested)]
     pub tunnel_options: TunnelOptions,
     /// Whether to notify users of beta updates.
     pub show_beta_releases: bool,
-    /// Split tunneling settings
+    /// Proxies that API requests may be sent through.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub api_access_methods: Vec<ApiAccessMethod>,
+    /// Which of the built-in ways of reaching the API may be used, and which access method is
+    /// tried first.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub api_access: ApiAccessSettings,
+    /// How the API hostname is resolved.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[schema(values = "static|system|dns_over_https")]
+    pub api_resolution_strategy: ApiResolutionStrategy,
+    /// Obfuscation of tunnel traffic.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub obfuscation_settings: ObfuscationSettings,
+    /// Whether the first connection attempts use the ports, protocols and obfuscation that are
+    /// known to work in the country that the device is in, rather than the defaults.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub connection_profiles: bool,
+    /// Whether the account number, WireGuard key and API proxy credentials are encrypted in the
+    /// settings file, using a key stored in the keystore of the operating system.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub encrypt_sensitive_settings: bool,
+    /// Where the account number and the WireGuard key of the device are stored.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[schema(values = "settings|keystore|file")]
+    pub credential_storage: CredentialStorage,
+    /// Whether to remember several previously used accounts, rather than only the most recently
+    /// used one.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub keep_account_history: bool,
+    /// Whether to keep the account number in memory only. It is then neither written to the
+    /// settings file nor to the account history, and has to be entered again after logging out
+    /// or restarting the daemon. The WireGuard key of the device is still stored.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub account_privacy_mode: bool,
+    /// How often to give this device a new name and WireGuard key, so that it cannot be
+    /// recognized by them for longer than that. The device keeps its name and key if this is not
+    /// set.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub identity_rotation_interval: Option<HumanDuration>,
+    /// Warnings about the account running out of time, and what to do when it has.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[schema(nested)]
+    pub account_expiry: AccountExpirySettings,
+    /// Only connect to relays in the cities that ports are forwarded from, so that the forwarded
+    /// ports are reachable.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[schema(reconnect)]
+    pub port_forwarding: bool,
+    /// Limits on the size and number of the daemon and tunnel logs.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[schema(nested)]
+    pub log_rotation: LogRotationSettings,
+    /// How much network detail, such as IP addresses and host names, is written to the daemon
+    /// log.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[schema(values = "full|redacted|minimal")]
+    pub log_privacy: LogPrivacyLevel,
+    /// Whether to keep statistics about the connections to each relay on this device. The
+    /// statistics are never sent anywhere.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub connection_statistics: bool,
+    /// Whether to send important events, such as when all traffic is blocked, to the log of the
+    /// operating system in addition to the daemon log.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub system_log: bool,
+    /// Whether to write the daemon log as JSON objects, one per line, for log collectors.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub json_logs: bool,
+    /// Whether the server mode profile is applied. See [`Settings::apply_server_mode`]. This is
+    /// turned off when any of the settings of the profile is turned off.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub server_mode: bool,
+    /// Whether to compare the system clock with the time of the API when the certificate of the
+    /// API is rejected because of its validity period, so that a wrong clock can be reported as
+    /// such rather than as the API being unreachable.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub api_clock_check: bool,
+    /// A proxy that lets other devices on the local network use the tunnel.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[schema(nested)]
+    pub lan_proxy: LanProxySettings,
+    /// A read-only HTTP API on localhost for integrations that cannot use the management
+    /// interface.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[schema(nested)]
+    pub local_api: LocalApiSettings,
+    /// Whether to relay mDNS service discovery between the local network and this device while
+    /// connected. This has no effect unless LAN access is allowed.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub mdns_reflector: bool,
+    /// Scripts that are run when the tunnel connects and disconnects.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[schema(nested)]
+    pub hooks: HookSettings,
+    /// How long to wait before retrying after a failed connection attempt.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[schema(nested)]
+    pub reconnect_backoff: ReconnectBackoffSettings,
+    /// Periodic checks, while connected, that traffic exits through the relay that the tunnel is
+    /// connected to.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[schema(nested)]
+    pub exit_ip_check: ExitIpCheckSettings,
+    /// Experimental features that are turned on.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[schema(nested)]
+    pub beta_features: BetaFeatures,
+    /// Traffic of essential system services that is allowed while network access is blocked.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[schema(nested)]
+    pub allowed_system_services: AllowedSystemServices,
+    /// Networks, optionally limited to a port and protocol, that traffic is allowed to and from
+    /// outside the tunnel in every tunnel state. DNS requests are blocked regardless.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub firewall_exceptions: Vec<net::AllowedEndpoint>,
+    /// Changes applied to the tunnel parameters of every connection attempt.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[schema(nested)]
+    pub tunnel_parameter_overrides: TunnelParameterOverrides,
+    /// Whether to provide a D-Bus service on the system bus that desktop integrations can use to
+    /// follow and control the tunnel state.
+    #[cfg(target_os = "linux")]
+    pub dbus_service: bool,
+    /// Whether to allow traffic to and from the subnets of the Hyper-V NAT switches, such as the
+    /// one used by WSL2, regardless of the tunnel state and of whether LAN access is allowed.
     #[cfg(windows)]
+    pub allow_virtual_networks: bool,
+    /// Split tunneling settings
+    #[cfg(any(windows, target_os = "linux"))]
     pub split_tunnel: SplitTunnelSettings,
     /// Specifies settings schema version
     #[cfg_attr(target_os = "android", jnix(skip))]
-    settings_version: migrations::SettingsVersion,
+    #[schema(skip)]
+    settings_version: SettingsVersion,
 }
 
-#[cfg(windows)]
+#[cfg(any(windows, target_os = "linux"))]
 #[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
 pub struct SplitTunnelSettings {
     /// Toggles split tunneling on or off
@@ -77,6 +274,436 @@ pub struct SplitTunnelSettings {
     pub apps: HashSet<PathBuf>,
 }
 
+/// The shortest interval at which the name and WireGuard key of the device can be rotated.
+pub const MIN_IDENTITY_ROTATION_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Returns an error if the identity rotation interval is too short.
+pub fn validate_identity_rotation_interval(
+    interval: Option<HumanDuration>,
+) -> std::result::Result<(), FieldError> {
+    match interval {
+        Some(interval) if interval.as_duration() < MIN_IDENTITY_ROTATION_INTERVAL => {
+            Err(FieldError::new(
+                "identity_rotation_interval",
+                format!(
+                    "the interval must be at least {}",
+                    HumanDuration(MIN_IDENTITY_ROTATION_INTERVAL)
+                ),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The smallest size at which logs are rotated.
+pub const MIN_LOG_ROTATION_SIZE: ByteSize = ByteSize::kib(64);
+/// The largest number of old log files that can be kept for each log.
+pub const MAX_LOG_GENERATIONS: u32 = 20;
+
+/// Limits on how large the logs grow and how many old logs are kept. Logs are rotated when the
+/// daemon starts, when a tunnel is opened, and when a log grows larger than `max_size`.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize, SettingsSchema)]
+#[serde(default)]
+pub struct LogRotationSettings {
+    /// The size at which a log is rotated.
+    pub max_size: ByteSize,
+    /// The number of old logs to keep for each log.
+    #[schema(min = 0, max = 20)]
+    pub generations: u32,
+    /// Remove old logs that have not been written to for this long. Old logs are kept regardless
+    /// of their age if this is not set.
+    pub max_age: Option<HumanDuration>,
+    /// Whether to compress old logs, except for the most recent one.
+    pub compress: bool,
+}
+
+impl Default for LogRotationSettings {
+    fn default() -> Self {
+        LogRotationSettings {
+            max_size: ByteSize::mib(10),
+            generations: 5,
+            max_age: Some(HumanDuration(Duration::from_secs(14 * 24 * 60 * 60))),
+            compress: true,
+        }
+    }
+}
+
+impl LogRotationSettings {
+    /// Returns an error naming the first invalid limit, if any.
+    pub fn validate(&self) -> std::result::Result<(), FieldError> {
+        if self.max_size < MIN_LOG_ROTATION_SIZE {
+            return Err(FieldError::new(
+                "log_rotation.max_size",
+                format!("the size must be at least {}", MIN_LOG_ROTATION_SIZE),
+            ));
+        }
+        if self.generations > MAX_LOG_GENERATIONS {
+            return Err(FieldError::new(
+                "log_rotation.generations",
+                format!("at most {} old logs can be kept", MAX_LOG_GENERATIONS),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A SOCKS5 and HTTP CONNECT proxy that devices on the local network can send their traffic
+/// through the tunnel with. It only runs while local network sharing is enabled, and only forwards
+/// traffic while the tunnel is connected.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize, SettingsSchema)]
+#[serde(default)]
+pub struct LanProxySettings {
+    /// Whether to run the proxy.
+    pub enabled: bool,
+    /// The TCP port to listen on.
+    #[schema(min = 1, max = 65535)]
+    pub port: u16,
+}
+
+impl Default for LanProxySettings {
+    fn default() -> Self {
+        LanProxySettings {
+            enabled: false,
+            port: 1080,
+        }
+    }
+}
+
+/// A read-only HTTP/JSON API on localhost that reports the tunnel state, the relay in use and the
+/// remaining account time. Requests must carry the token that is written to the settings
+/// directory.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize, SettingsSchema)]
+#[serde(default)]
+pub struct LocalApiSettings {
+    /// Whether to run the API.
+    pub enabled: bool,
+    /// The TCP port on the loopback interface to listen on.
+    #[schema(min = 1, max = 65535)]
+    pub port: u16,
+}
+
+impl Default for LocalApiSettings {
+    fn default() -> Self {
+        LocalApiSettings {
+            enabled: false,
+            port: 41280,
+        }
+    }
+}
+
+/// The shortest time that a hook script may run for before it is stopped.
+pub const MIN_HOOK_TIMEOUT: Duration = Duration::from_secs(1);
+/// The longest time that a hook script may run for before it is stopped.
+pub const MAX_HOOK_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Scripts that the daemon runs as the user that it runs as when the tunnel connects and
+/// disconnects, such as for mounting network shares once the tunnel is up. The output of the
+/// scripts is written to the daemon log. Only scripts that cannot be changed by other users than
+/// administrators are run.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize, SettingsSchema)]
+#[serde(default)]
+pub struct HookSettings {
+    /// Script that is run when the tunnel starts connecting after having been disconnected.
+    pub pre_up: Option<PathBuf>,
+    /// Script that is run every time the tunnel has connected. The name of the tunnel interface
+    /// and the IP addresses of the tunnel are passed in environment variables.
+    pub post_up: Option<PathBuf>,
+    /// Script that is run when the tunnel has been disconnected.
+    pub post_down: Option<PathBuf>,
+    /// How long a script may run for before it is stopped.
+    pub timeout: HumanDuration,
+}
+
+impl Default for HookSettings {
+    fn default() -> Self {
+        HookSettings {
+            pre_up: None,
+            post_up: None,
+            post_down: None,
+            timeout: HumanDuration(Duration::from_secs(30)),
+        }
+    }
+}
+
+impl HookSettings {
+    /// Returns an error naming the first invalid parameter, if any.
+    pub fn validate(&self) -> std::result::Result<(), FieldError> {
+        let timeout = self.timeout.as_duration();
+        if timeout < MIN_HOOK_TIMEOUT || timeout > MAX_HOOK_TIMEOUT {
+            return Err(FieldError::new(
+                "hooks.timeout",
+                format!(
+                    "the timeout must be between {} and {}",
+                    HumanDuration(MIN_HOOK_TIMEOUT),
+                    HumanDuration(MAX_HOOK_TIMEOUT)
+                ),
+            ));
+        }
+        let scripts = [
+            ("hooks.pre_up", &self.pre_up),
+            ("hooks.post_up", &self.post_up),
+            ("hooks.post_down", &self.post_down),
+        ];
+        for (field, script) in scripts.iter() {
+            if let Some(script) = script {
+                if !script.is_absolute() {
+                    return Err(FieldError::new(*field, "the path must be absolute"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The longest time that the tunnel may wait before retrying after a failed connection attempt.
+pub const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(10 * 60);
+
+/// How long to wait before retrying after a connection attempt has failed. The first retry is
+/// delayed by `initial_delay`, and every retry after that by `multiplier` times as long as the one
+/// before it, up to `max_delay`. Every delay is shortened by a random part of at most
+/// `jitter_percent` percent. The delays start over once the tunnel has connected and when the
+/// network changes.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize, SettingsSchema)]
+#[serde(default)]
+pub struct ReconnectBackoffSettings {
+    /// The delay before the first retry.
+    pub initial_delay: HumanDuration,
+    /// How many times longer every delay is than the one before it.
+    #[schema(min = 1, max = 10)]
+    pub multiplier: u32,
+    /// The longest delay.
+    pub max_delay: HumanDuration,
+    /// How large a random part of every delay may be cut off.
+    #[schema(min = 0, max = 100)]
+    pub jitter_percent: u8,
+}
+
+impl Default for ReconnectBackoffSettings {
+    fn default() -> Self {
+        ReconnectBackoffSettings {
+            initial_delay: HumanDuration(Duration::from_secs(1)),
+            multiplier: 2,
+            max_delay: HumanDuration(Duration::from_secs(30)),
+            jitter_percent: 20,
+        }
+    }
+}
+
+impl ReconnectBackoffSettings {
+    /// Returns an error naming the first invalid parameter, if any.
+    pub fn validate(&self) -> std::result::Result<(), FieldError> {
+        if self.max_delay.as_duration() > MAX_RECONNECT_DELAY {
+            return Err(FieldError::new(
+                "reconnect_backoff.max_delay",
+                format!(
+                    "the delay must be at most {}",
+                    HumanDuration(MAX_RECONNECT_DELAY)
+                ),
+            ));
+        }
+        if self.initial_delay.as_duration() > self.max_delay.as_duration() {
+            return Err(FieldError::new(
+                "reconnect_backoff.initial_delay",
+                "the initial delay must not be longer than the longest delay",
+            ));
+        }
+        if self.multiplier < 1 || self.multiplier > 10 {
+            return Err(FieldError::new(
+                "reconnect_backoff.multiplier",
+                "the multiplier must be between 1 and 10",
+            ));
+        }
+        if self.jitter_percent > 100 {
+            return Err(FieldError::new(
+                "reconnect_backoff.jitter_percent",
+                "the jitter must be between 0 and 100 percent",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The shortest time between two checks of the exit IP.
+pub const MIN_EXIT_IP_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// The longest time between two checks of the exit IP.
+pub const MAX_EXIT_IP_CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Periodic checks, while the tunnel is connected, of the IP address that traffic exits from. The
+/// address is looked up with am.i.mullvad.net, and is expected to belong to the relay that the
+/// tunnel is connected to and to stay the same for as long as the tunnel is connected to it.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize, SettingsSchema)]
+#[serde(default)]
+pub struct ExitIpCheckSettings {
+    /// Whether to check the exit IP.
+    pub enabled: bool,
+    /// How long to wait between two checks.
+    pub interval: HumanDuration,
+    /// Whether to reconnect when traffic is found to exit somewhere else than expected.
+    pub reconnect: bool,
+}
+
+impl Default for ExitIpCheckSettings {
+    fn default() -> Self {
+        ExitIpCheckSettings {
+            enabled: false,
+            interval: HumanDuration(Duration::from_secs(15 * 60)),
+            reconnect: false,
+        }
+    }
+}
+
+impl ExitIpCheckSettings {
+    /// Returns an error naming the first invalid parameter, if any.
+    pub fn validate(&self) -> std::result::Result<(), FieldError> {
+        let interval = self.interval.as_duration();
+        if interval < MIN_EXIT_IP_CHECK_INTERVAL || interval > MAX_EXIT_IP_CHECK_INTERVAL {
+            return Err(FieldError::new(
+                "exit_ip_check.interval",
+                format!(
+                    "the interval must be between {} and {}",
+                    HumanDuration(MIN_EXIT_IP_CHECK_INTERVAL),
+                    HumanDuration(MAX_EXIT_IP_CHECK_INTERVAL)
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// How much network detail is written to the daemon log. Problem reports are redacted
+/// regardless.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogPrivacyLevel {
+    /// Log messages are written as they are.
+    Full,
+    /// IP addresses, MAC addresses and host names are removed from log messages.
+    Redacted,
+    /// Like `Redacted`, and only warnings and errors are logged by the modules that describe the
+    /// network configuration, such as routes, firewall rules and DNS.
+    Minimal,
+}
+
+impl Default for LogPrivacyLevel {
+    fn default() -> Self {
+        LogPrivacyLevel::Full
+    }
+}
+
+impl fmt::Display for LogPrivacyLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogPrivacyLevel::Full => write!(f, "full"),
+            LogPrivacyLevel::Redacted => write!(f, "redacted"),
+            LogPrivacyLevel::Minimal => write!(f, "minimal"),
+        }
+    }
+}
+
+/// Where the account number and the WireGuard key of the device are stored.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CredentialStorage {
+    /// In the settings file, encrypted if `encrypt_sensitive_settings` is enabled.
+    Settings,
+    /// In the keystore of the operating system.
+    Keystore,
+    /// In a separate file that is only accessible to the daemon.
+    File,
+}
+
+impl Default for CredentialStorage {
+    fn default() -> Self {
+        CredentialStorage::Settings
+    }
+}
+
+impl fmt::Display for CredentialStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CredentialStorage::Settings => write!(f, "settings"),
+            CredentialStorage::Keystore => write!(f, "keystore"),
+            CredentialStorage::File => write!(f, "file"),
+        }
+    }
+}
+
+/// System services whose traffic is let through while all other traffic is blocked, so that
+/// machines that stay blocked for long periods can keep their clock in sync and detect captive
+/// portals. DHCP is always allowed.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Deserialize, Serialize, SettingsSchema)]
+#[serde(default)]
+pub struct AllowedSystemServices {
+    /// Allow NTP requests to any server.
+    pub ntp: bool,
+    /// Allow plain HTTP requests to the captive portal detection hosts of the operating system.
+    pub captive_portal_detection: bool,
+}
+
+/// Changes to the tunnel parameters that are applied last, after the relay has been selected and
+/// the other settings have been applied. Overrides that cannot be applied to a connection are
+/// skipped, and those that are applied are logged for every connection attempt.
+#[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize, Serialize, SettingsSchema)]
+#[serde(default)]
+pub struct TunnelParameterOverrides {
+    /// Port to connect to WireGuard relays on, regardless of the port constraint. It is ignored
+    /// when the traffic is obfuscated.
+    #[schema(reconnect, min = 1, max = 65535)]
+    pub wireguard_port: Option<u16>,
+    /// Networks that are routed to the WireGuard peer that traffic exits through, in addition to
+    /// those it is normally assigned.
+    #[schema(reconnect)]
+    pub wireguard_extra_allowed_ips: Vec<ipnetwork::IpNetwork>,
+}
+
+impl TunnelParameterOverrides {
+    /// Returns whether no overrides are set.
+    pub fn is_empty(&self) -> bool {
+        self.wireguard_port.is_none() && self.wireguard_extra_allowed_ips.is_empty()
+    }
+
+    /// Returns an error naming the first invalid override, if any.
+    pub fn validate(&self) -> std::result::Result<(), FieldError> {
+        if self.wireguard_port == Some(0) {
+            return Err(FieldError::new(
+                "tunnel_parameter_overrides.wireguard_port",
+                "the port must not be 0",
+            ));
+        }
+        if let Some(network) = self
+            .wireguard_extra_allowed_ips
+            .iter()
+            .find(|network| network.prefix() == 0)
+        {
+            return Err(FieldError::new(
+                "tunnel_parameter_overrides.wireguard_extra_allowed_ips",
+                format!("{} is already routed through the tunnel", network),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl LanProxySettings {
+    /// Returns an error naming the first invalid parameter, if any.
+    pub fn validate(&self) -> std::result::Result<(), FieldError> {
+        if self.port == 0 {
+            return Err(FieldError::new("lan_proxy.port", "the port must not be 0"));
+        }
+        Ok(())
+    }
+}
+
+impl LocalApiSettings {
+    /// Returns an error naming the first invalid parameter, if any.
+    pub fn validate(&self) -> std::result::Result<(), FieldError> {
+        if self.port == 0 {
+            return Err(FieldError::new("local_api.port", "the port must not be 0"));
+        }
+        Ok(())
+    }
+}
+
 impl Default for Settings {
     fn default() -> Self {
         Settings {
@@ -90,27 +717,126 @@ impl Default for Settings {
             bridge_state: BridgeState::Auto,
             allow_lan: false,
             block_when_disconnected: false,
+            persistent_lockdown: false,
             auto_connect: false,
+            network_rules: NetworkRules::default(),
             tunnel_options: TunnelOptions::default(),
             show_beta_releases: false,
+            api_access_methods: vec![],
+            api_access: ApiAccessSettings::default(),
+            api_resolution_strategy: ApiResolutionStrategy::default(),
+            obfuscation_settings: ObfuscationSettings::default(),
+            connection_profiles: true,
+            encrypt_sensitive_settings: false,
+            credential_storage: CredentialStorage::default(),
+            keep_account_history: false,
+            account_privacy_mode: false,
+            identity_rotation_interval: None,
+            account_expiry: AccountExpirySettings::default(),
+            port_forwarding: false,
+            log_rotation: LogRotationSettings::default(),
+            log_privacy: LogPrivacyLevel::default(),
+            connection_statistics: false,
+            system_log: false,
+            json_logs: false,
+            server_mode: false,
+            api_clock_check: true,
+            lan_proxy: LanProxySettings::default(),
+            local_api: LocalApiSettings::default(),
+            mdns_reflector: false,
+            hooks: HookSettings::default(),
+            reconnect_backoff: ReconnectBackoffSettings::default(),
+            exit_ip_check: ExitIpCheckSettings::default(),
+            beta_features: BetaFeatures::default(),
+            allowed_system_services: AllowedSystemServices::default(),
+            firewall_exceptions: vec![],
+            tunnel_parameter_overrides: TunnelParameterOverrides::default(),
+            #[cfg(target_os = "linux")]
+            dbus_service: false,
             #[cfg(windows)]
+            allow_virtual_networks: true,
+            #[cfg(any(windows, target_os = "linux"))]
             split_tunnel: SplitTunnelSettings::default(),
-            settings_version: migrations::CURRENT_SETTINGS_VERSION,
+            settings_version: CURRENT_SETTINGS_VERSION,
         }
     }
 }
 
 impl Settings {
     pub fn load_from_bytes(bytes: &[u8]) -> Result<Self> {
-        let settings: Self = serde_json::from_slice(bytes).map_err(Error::ParseError)?;
-        if settings.settings_version < migrations::CURRENT_SETTINGS_VERSION {
+        Self::load_from_value(serde_json::from_slice(bytes).map_err(Error::ParseError)?)
+    }
+
+    /// Reads settings of the current version. Invalid values of some settings are replaced by
+    /// their defaults.
+    pub fn load_from_value(value: serde_json::Value) -> Result<Self> {
+        let mut settings: Self = serde_json::from_value(value).map_err(Error::ParseError)?;
+        if settings.settings_version < CURRENT_SETTINGS_VERSION {
             return Err(Error::VersionMismatch);
         }
+        if let Err(error) = settings.obfuscation_settings.validate() {
+            warn!("{}. Using default obfuscation settings", error);
+            settings.obfuscation_settings = ObfuscationSettings::default();
+        }
+        if let Err(error) = settings.log_rotation.validate() {
+            warn!("{}. Using default log rotation settings", error);
+            settings.log_rotation = LogRotationSettings::default();
+        }
+        if let Err(error) = settings.lan_proxy.validate() {
+            warn!("{}. Using default LAN proxy settings", error);
+            settings.lan_proxy = LanProxySettings::default();
+        }
+        if let Err(error) = settings.local_api.validate() {
+            warn!("{}. Using default local API settings", error);
+            settings.local_api = LocalApiSettings::default();
+        }
+        if let Err(error) = settings.hooks.validate() {
+            warn!("{}. Hook scripts are disabled", error);
+            settings.hooks = HookSettings::default();
+        }
+        if let Err(error) = settings.reconnect_backoff.validate() {
+            warn!("{}. Using default reconnection delays", error);
+            settings.reconnect_backoff = ReconnectBackoffSettings::default();
+        }
+        if let Err(error) = settings.exit_ip_check.validate() {
+            warn!("{}. Using the default exit IP check interval", error);
+            settings.exit_ip_check.interval = ExitIpCheckSettings::default().interval;
+        }
+        for feature in settings.beta_features.remove_unknown() {
+            warn!("Turning off unknown beta feature \"{}\"", feature);
+        }
+        if let Err(error) = validate_identity_rotation_interval(settings.identity_rotation_interval)
+        {
+            warn!("{}. Identity rotation is disabled", error);
+            settings.identity_rotation_interval = None;
+        }
+        if settings.server_mode && !settings.has_server_mode_profile() {
+            warn!("Leaving server mode since some of its settings are turned off");
+            settings.server_mode = false;
+        }
         Ok(settings)
     }
 
-    pub fn migrate_from_bytes(bytes: &[u8]) -> Result<Self> {
-        migrations::try_migrate_settings(&bytes)
+    /// Applies the server mode profile, for headless hosts such as VPSs and seedboxes that nobody
+    /// watches: the tunnel is connected when the daemon starts, traffic is blocked whenever it is
+    /// down, the log is written as JSON, the local API serves its metrics endpoint, and important
+    /// events are sent to the system log. Other settings are left as they are.
+    pub fn apply_server_mode(&mut self) {
+        self.auto_connect = true;
+        self.block_when_disconnected = true;
+        self.json_logs = true;
+        self.local_api.enabled = true;
+        self.system_log = true;
+        self.server_mode = true;
+    }
+
+    /// Returns whether all settings of the server mode profile are turned on.
+    pub fn has_server_mode_profile(&self) -> bool {
+        self.auto_connect
+            && self.block_when_disconnected
+            && self.json_logs
+            && self.local_api.enabled
+            && self.system_log
     }
 
     pub fn get_account_token(&self) -> Option<String> {
@@ -190,21 +916,75 @@ impl Settings {
             false
         }
     }
+
+    /// Returns the names of all settings that differ between `old` and `new`. Nested settings
+    /// are named by their path, with the components separated by dots, such as
+    /// `tunnel_options.generic.enable_ipv6`.
+    pub fn changed_keys(old: &Settings, new: &Settings) -> Vec<String> {
+        let mut keys = Vec::new();
+        if let (Ok(old), Ok(new)) = (serde_json::to_value(old), serde_json::to_value(new)) {
+            diff_values("", &old, &new, &mut keys);
+        }
+        keys
+    }
+
+    /// Returns the names of all top-level settings.
+    pub fn keys(&self) -> Vec<String> {
+        match serde_json::to_value(self) {
+            Ok(serde_json::Value::Object(settings)) => settings.keys().cloned().collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn diff_values(
+    path: &str,
+    old: &serde_json::Value,
+    new: &serde_json::Value,
+    keys: &mut Vec<String>,
+) {
+    use serde_json::Value;
+
+    match (old, new) {
+        (Value::Object(old_fields), Value::Object(new_fields)) => {
+            let added_fields = new_fields
+                .keys()
+                .filter(|name| !old_fields.contains_key(name.as_str()));
+            for name in old_fields.keys().chain(added_fields) {
+                let field_path = if path.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}.{}", path, name)
+                };
+                match (old_fields.get(name), new_fields.get(name)) {
+                    (Some(old_value), Some(new_value)) => {
+                        diff_values(&field_path, old_value, new_value, keys)
+                    }
+                    _ => keys.push(field_path),
+                }
+            }
+        }
+        (old, new) if old != new => keys.push(path.to_owned()),
+        _ => (),
+    }
 }
 
 /// TunnelOptions holds configuration data that applies to all kinds of tunnels.
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, SettingsSchema)]
 #[serde(default)]
 #[cfg_attr(target_os = "android", derive(IntoJava))]
 #[cfg_attr(target_os = "android", jnix(package = "net.mullvad.mullvadvpn.model"))]
 pub struct TunnelOptions {
     /// openvpn holds OpenVPN specific tunnel options.
     #[cfg_attr(target_os = "android", jnix(skip))]
+    #[schema(reconnect)]
     pub openvpn: openvpn::TunnelOptions,
     /// Contains wireguard tunnel options.
+    #[schema(reconnect)]
     pub wireguard: wireguard::TunnelOptions,
     /// Contains generic tunnel options that may apply to more than a single tunnel type.
     #[cfg_attr(target_os = "android", jnix(skip))]
+    #[schema(reconnect)]
     pub generic: GenericTunnelOptions,
     /// DNS options.
     pub dns_options: DnsOptions,
@@ -215,6 +995,10 @@ pub struct TunnelOptions {
 pub enum DnsState {
     Default,
     Custom,
+    /// Queries are forwarded over DNS-over-TLS or DNS-over-HTTPS by a local proxy.
+    Encrypted,
+    /// Queries are sent to a DNS forwarder on this device, such as dnscrypt-proxy or stubby.
+    LocalForwarder,
 }
 
 impl Default for DnsState {
@@ -232,8 +1016,30 @@ pub struct DnsOptions {
     pub state: DnsState,
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub default_options: DefaultDnsOptions,
-    #[cfg_attr(target_os = "android", jnix(map = "|opts| opts.addresses"))]
+    #[cfg_attr(target_os = "android", jnix(map = "|opts| opts.ip_addresses()"))]
     pub custom_options: CustomDnsOptions,
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[serde(default)]
+    pub encrypted_options: EncryptedDnsOptions,
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[serde(default)]
+    pub local_forwarder_options: LocalDnsForwarderOptions,
+}
+
+impl DnsOptions {
+    /// Returns the custom DNS servers that cannot be reached with the given local network sharing
+    /// setting. Always empty unless custom DNS is used.
+    pub fn unreachable_custom_servers(&self, allow_lan: bool) -> Vec<DnsServerAddress> {
+        if self.state != DnsState::Custom || allow_lan {
+            return vec![];
+        }
+        self.custom_options
+            .addresses
+            .iter()
+            .filter(|address| address.kind().requires_allow_lan())
+            .cloned()
+            .collect()
+    }
 }
 
 #[cfg(target_os = "android")]
@@ -258,9 +1064,9 @@ impl From<AndroidDnsOptions> for DnsOptions {
                 DnsState::Default
             },
             default_options: DefaultDnsOptions::default(),
-            custom_options: CustomDnsOptions {
-                addresses: options.addresses,
-            },
+            custom_options: CustomDnsOptions::from_addresses_lossy(options.addresses),
+            encrypted_options: EncryptedDnsOptions::default(),
+            local_forwarder_options: LocalDnsForwarderOptions::default(),
         }
     }
 }
@@ -287,7 +1093,208 @@ pub struct DefaultDnsOptions {
 /// Custom DNS config
 #[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
 pub struct CustomDnsOptions {
-    pub addresses: Vec<IpAddr>,
+    /// Invalid addresses in the settings file are ignored.
+    #[serde(deserialize_with = "deserialize_dns_servers_lossy")]
+    pub addresses: Vec<DnsServerAddress>,
+}
+
+impl CustomDnsOptions {
+    /// Creates options from a list of addresses, leaving out and logging any that cannot be used
+    /// as DNS servers.
+    pub fn from_addresses_lossy(addresses: Vec<IpAddr>) -> Self {
+        let addresses = addresses
+            .into_iter()
+            .filter_map(|address| match DnsServerAddress::try_from(address) {
+                Ok(address) => Some(address),
+                Err(error) => {
+                    warn!("Ignoring custom DNS server: {}", error);
+                    None
+                }
+            })
+            .collect();
+        CustomDnsOptions { addresses }
+    }
+
+    pub fn ip_addresses(&self) -> Vec<IpAddr> {
+        self.addresses.iter().cloned().map(IpAddr::from).collect()
+    }
+}
+
+/// Encrypted DNS config
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
+pub struct EncryptedDnsOptions {
+    /// The resolver that queries are forwarded to. The default DNS servers are used until this
+    /// is set.
+    pub server: Option<EncryptedDnsServer>,
+}
+
+/// A DNS forwarder on this device, such as dnscrypt-proxy or stubby, that encrypts queries
+/// before sending them on through the tunnel.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
+pub struct LocalDnsForwarderOptions {
+    /// The loopback address that the forwarder listens on, on port 53. The default DNS servers
+    /// are used until this is set.
+    pub address: Option<DnsServerAddress>,
+    /// Resolvers that the forwarder sends queries to on port 53, such as its bootstrap
+    /// resolvers. Port 53 is only allowed through the tunnel to the DNS servers in use, so these
+    /// are allowed as well. Forwarders that only use DNS-over-TLS or DNS-over-HTTPS need none.
+    #[serde(deserialize_with = "deserialize_dns_servers_lossy")]
+    pub upstream_servers: Vec<DnsServerAddress>,
+}
+
+impl LocalDnsForwarderOptions {
+    pub fn upstream_ip_addresses(&self) -> Vec<IpAddr> {
+        self.upstream_servers
+            .iter()
+            .cloned()
+            .map(IpAddr::from)
+            .collect()
+    }
+}
+
+/// A resolver that is reached over an encrypted protocol.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
+pub struct EncryptedDnsServer {
+    pub protocol: EncryptedDnsProtocol,
+    pub address: DnsServerAddress,
+    /// Name that the certificate of the resolver is verified against.
+    pub hostname: String,
+}
+
+impl fmt::Display for EncryptedDnsServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} over {} ({})",
+            self.hostname, self.protocol, self.address
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum EncryptedDnsProtocol {
+    /// DNS-over-TLS
+    Tls,
+    /// DNS-over-HTTPS
+    Https,
+}
+
+impl fmt::Display for EncryptedDnsProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncryptedDnsProtocol::Tls => f.write_str("TLS"),
+            EncryptedDnsProtocol::Https => f.write_str("HTTPS"),
+        }
+    }
+}
+
+fn deserialize_dns_servers_lossy<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Vec<DnsServerAddress>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let addresses = Vec::<IpAddr>::deserialize(deserializer)?;
+    Ok(CustomDnsOptions::from_addresses_lossy(addresses).addresses)
+}
+
+/// An address that can be used as a DNS server. The unspecified address, broadcast addresses
+/// and multicast addresses are rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "IpAddr", into = "IpAddr")]
+pub struct DnsServerAddress(IpAddr);
+
+impl DnsServerAddress {
+    /// Returns where the DNS server is located, which determines when it can be reached.
+    pub fn kind(&self) -> DnsServerKind {
+        if self.0.is_loopback() {
+            DnsServerKind::Loopback
+        } else if is_local_network_address(self.0) {
+            DnsServerKind::Lan
+        } else {
+            DnsServerKind::Public
+        }
+    }
+}
+
+impl TryFrom<IpAddr> for DnsServerAddress {
+    type Error = InvalidDnsServer;
+
+    fn try_from(address: IpAddr) -> std::result::Result<Self, Self::Error> {
+        if address.is_unspecified() {
+            return Err(InvalidDnsServer::Unspecified(address));
+        }
+        if address.is_multicast() {
+            return Err(InvalidDnsServer::Multicast(address));
+        }
+        if let IpAddr::V4(v4_address) = address {
+            if v4_address.is_broadcast() {
+                return Err(InvalidDnsServer::Broadcast(address));
+            }
+        }
+        Ok(DnsServerAddress(address))
+    }
+}
+
+impl From<DnsServerAddress> for IpAddr {
+    fn from(address: DnsServerAddress) -> IpAddr {
+        address.0
+    }
+}
+
+impl fmt::Display for DnsServerAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// An address that can never be used as a DNS server.
+#[derive(err_derive::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidDnsServer {
+    #[error(display = "{} is the unspecified address", _0)]
+    Unspecified(IpAddr),
+
+    #[error(display = "{} is a broadcast address", _0)]
+    Broadcast(IpAddr),
+
+    #[error(display = "{} is a multicast address", _0)]
+    Multicast(IpAddr),
+}
+
+/// Where a DNS server is located.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsServerKind {
+    /// A server on the internet. It is reached through the tunnel.
+    Public,
+    /// A server on this device. It can only be used if something is listening on it.
+    Loopback,
+    /// A server on the local network. It can only be reached when local network sharing is
+    /// allowed.
+    Lan,
+}
+
+impl DnsServerKind {
+    pub fn requires_allow_lan(self) -> bool {
+        self == DnsServerKind::Lan
+    }
+}
+
+fn is_local_network_address(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => is_local_network_v4(address),
+        IpAddr::V6(address) => is_local_network_v6(address),
+    }
+}
+
+fn is_local_network_v4(address: Ipv4Addr) -> bool {
+    address.is_private() || address.is_link_local()
+}
+
+fn is_local_network_v6(address: Ipv6Addr) -> bool {
+    let first_segment = address.segments()[0];
+    // Unique local (fc00::/7) and link-local (fe80::/10) addresses
+    (first_segment & 0xfe00) == 0xfc00 || (first_segment & 0xffc0) == 0xfe80
 }
 
 impl Default for TunnelOptions {
@@ -297,10 +1304,13 @@ impl Default for TunnelOptions {
             wireguard: wireguard::TunnelOptions {
                 options: net::wireguard::TunnelOptions::default(),
                 rotation_interval: None,
+                quantum_resistant: wireguard::QuantumResistantState::default(),
             },
             generic: GenericTunnelOptions {
                 // Enable IPv6 be default on Android
                 enable_ipv6: cfg!(target_os = "android"),
+                interface_name: None,
+                ipv6_failure_policy: Ipv6FailurePolicy::default(),
             },
             dns_options: DnsOptions::default(),
         }
@@ -312,6 +1322,71 @@ impl Default for TunnelOptions {
 mod test {
     use super::*;
 
+    #[test]
+    fn test_server_mode() {
+        let mut settings = Settings::default();
+        assert!(!settings.has_server_mode_profile());
+
+        settings.apply_server_mode();
+        assert!(settings.server_mode);
+        assert!(settings.has_server_mode_profile());
+
+        settings.auto_connect = false;
+        let value = serde_json::to_value(&settings).unwrap();
+        let loaded = Settings::load_from_value(value).unwrap();
+        assert!(!loaded.server_mode);
+    }
+
+    #[test]
+    fn test_changed_keys() {
+        let old = Settings::default();
+        assert!(Settings::changed_keys(&old, &old).is_empty());
+
+        let mut new = old.clone();
+        new.allow_lan = true;
+        new.tunnel_options.generic.enable_ipv6 = !old.tunnel_options.generic.enable_ipv6;
+        new.set_account_token(Some("1234".to_owned()));
+
+        let mut keys = Settings::changed_keys(&old, &new);
+        keys.sort();
+        assert_eq!(
+            keys,
+            vec![
+                "account_token",
+                "allow_lan",
+                "tunnel_options.generic.enable_ipv6"
+            ]
+        );
+
+        assert!(old.keys().contains(&"allow_lan".to_owned()));
+        assert!(!old.keys().contains(&"tunnel_options.generic".to_owned()));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_deserialization_failure_version_too_small() {
+        let _version: SettingsVersion = serde_json::from_str("1").expect("Version too small");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_deserialization_failure_version_too_big() {
+        let _version: SettingsVersion = serde_json::from_str("1000").expect("Version too big");
+    }
+
+    #[test]
+    fn test_version_deserialization_success() {
+        let _version: SettingsVersion =
+            serde_json::from_str("2").expect("Failed to deserialize valid version");
+    }
+
+    #[test]
+    fn test_version_serialization_success() {
+        let version = SettingsVersion::V2;
+        let s = serde_json::to_string(&version).expect("Failed to serialize");
+        assert_eq!(s, "2");
+    }
+
     #[test]
     fn test_deserialization() {
         let settings = br#"{
@@ -356,10 +1431,183 @@ mod test {
                   "enable_ipv6": true
                 }
               },
-              "settings_version": 5,
+              "settings_version": 6,
               "show_beta_releases": false
         }"#;
 
         let _ = Settings::load_from_bytes(settings).unwrap();
     }
+
+    #[test]
+    fn test_dns_server_classification() {
+        let kind = |address: &str| {
+            DnsServerAddress::try_from(address.parse::<IpAddr>().unwrap()).map(|addr| addr.kind())
+        };
+        assert_eq!(kind("1.1.1.1"), Ok(DnsServerKind::Public));
+        assert_eq!(kind("192.168.1.1"), Ok(DnsServerKind::Lan));
+        assert_eq!(kind("fd00::1"), Ok(DnsServerKind::Lan));
+        assert_eq!(kind("127.0.0.53"), Ok(DnsServerKind::Loopback));
+        assert_eq!(kind("::1"), Ok(DnsServerKind::Loopback));
+        assert!(matches!(
+            kind("0.0.0.0"),
+            Err(InvalidDnsServer::Unspecified(_))
+        ));
+        assert!(matches!(
+            kind("255.255.255.255"),
+            Err(InvalidDnsServer::Broadcast(_))
+        ));
+        assert!(matches!(
+            kind("ff02::1"),
+            Err(InvalidDnsServer::Multicast(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_dns_servers_are_ignored() {
+        let options: CustomDnsOptions =
+            serde_json::from_str(r#"{ "addresses": ["0.0.0.0", "192.168.1.1"] }"#).unwrap();
+        assert_eq!(
+            options.ip_addresses(),
+            vec!["192.168.1.1".parse::<IpAddr>().unwrap()]
+        );
+
+        let dns_options = DnsOptions {
+            state: DnsState::Custom,
+            default_options: DefaultDnsOptions::default(),
+            custom_options: options,
+            encrypted_options: EncryptedDnsOptions::default(),
+            local_forwarder_options: LocalDnsForwarderOptions::default(),
+        };
+        assert_eq!(dns_options.unreachable_custom_servers(false).len(), 1);
+        assert!(dns_options.unreachable_custom_servers(true).is_empty());
+    }
+
+    #[test]
+    fn test_log_rotation_validation() {
+        assert!(LogRotationSettings::default().validate().is_ok());
+
+        let too_small = LogRotationSettings {
+            max_size: ByteSize::kib(1),
+            ..LogRotationSettings::default()
+        };
+        assert_eq!(
+            too_small.validate().unwrap_err().field,
+            "log_rotation.max_size"
+        );
+
+        let too_many = LogRotationSettings {
+            generations: MAX_LOG_GENERATIONS + 1,
+            ..LogRotationSettings::default()
+        };
+        assert_eq!(
+            too_many.validate().unwrap_err().field,
+            "log_rotation.generations"
+        );
+    }
+
+    #[test]
+    fn test_hook_validation() {
+        assert!(HookSettings::default().validate().is_ok());
+
+        let relative = HookSettings {
+            post_up: Some(PathBuf::from("mount-shares.sh")),
+            ..HookSettings::default()
+        };
+        assert_eq!(relative.validate().unwrap_err().field, "hooks.post_up");
+
+        let too_long = HookSettings {
+            timeout: HumanDuration(MAX_HOOK_TIMEOUT + Duration::from_secs(1)),
+            ..HookSettings::default()
+        };
+        assert_eq!(too_long.validate().unwrap_err().field, "hooks.timeout");
+    }
+
+    #[test]
+    fn test_reconnect_backoff_validation() {
+        assert!(ReconnectBackoffSettings::default().validate().is_ok());
+
+        let inverted = ReconnectBackoffSettings {
+            initial_delay: HumanDuration(Duration::from_secs(60)),
+            ..ReconnectBackoffSettings::default()
+        };
+        assert_eq!(
+            inverted.validate().unwrap_err().field,
+            "reconnect_backoff.initial_delay"
+        );
+
+        let no_growth = ReconnectBackoffSettings {
+            multiplier: 0,
+            ..ReconnectBackoffSettings::default()
+        };
+        assert_eq!(
+            no_growth.validate().unwrap_err().field,
+            "reconnect_backoff.multiplier"
+        );
+    }
+
+    #[test]
+    fn test_exit_ip_check_validation() {
+        assert!(ExitIpCheckSettings::default().validate().is_ok());
+
+        let too_often = ExitIpCheckSettings {
+            interval: HumanDuration(Duration::from_secs(10)),
+            ..ExitIpCheckSettings::default()
+        };
+        assert_eq!(
+            too_often.validate().unwrap_err().field,
+            "exit_ip_check.interval"
+        );
+    }
+
+    #[test]
+    fn test_schema() {
+        let schema = Settings::schema();
+        let setting = |name: &str| schema.iter().find(|setting| setting.name == name);
+
+        assert!(setting("account_token").is_none());
+        assert!(setting("settings_version").is_none());
+        assert!(setting("tunnel_options").is_none());
+
+        let allow_lan = setting("allow_lan").unwrap();
+        assert_eq!(allow_lan.type_name, "bool");
+        assert_eq!(allow_lan.default, serde_json::Value::Bool(false));
+        assert!(!allow_lan.requires_reconnect);
+
+        let wireguard = setting("tunnel_options.wireguard").unwrap();
+        assert!(wireguard.requires_reconnect);
+        assert_eq!(wireguard.description, "Contains wireguard tunnel options.");
+
+        let bridge_state = setting("bridge_state").unwrap();
+        assert_eq!(
+            bridge_state.constraints,
+            vec![schema::SettingConstraint::OneOf(vec![
+                "auto".to_owned(),
+                "on".to_owned(),
+                "off".to_owned()
+            ])]
+        );
+    }
+
+    #[test]
+    fn test_tunnel_parameter_overrides_validation() {
+        let mut overrides = TunnelParameterOverrides::default();
+        assert!(overrides.is_empty());
+        assert!(overrides.validate().is_ok());
+
+        overrides.wireguard_port = Some(0);
+        assert_eq!(
+            overrides.validate().unwrap_err().field,
+            "tunnel_parameter_overrides.wireguard_port"
+        );
+
+        overrides.wireguard_port = Some(53);
+        overrides.wireguard_extra_allowed_ips = vec!["0.0.0.0/0".parse().unwrap()];
+        assert_eq!(
+            overrides.validate().unwrap_err().field,
+            "tunnel_parameter_overrides.wireguard_extra_allowed_ips"
+        );
+
+        overrides.wireguard_extra_allowed_ips = vec!["10.0.0.0/8".parse().unwrap()];
+        assert!(overrides.validate().is_ok());
+    }
 }
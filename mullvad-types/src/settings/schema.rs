@@ -0,0 +1,51 @@
+//! A machine readable description of the settings, used by frontends to display and validate
+//! settings without knowing about every field in advance.
+use serde::{Deserialize, Serialize};
+
+pub use mullvad_types_derive::SettingsSchema;
+
+/// Describes a single setting.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SettingDescription {
+    /// The path to the setting in the settings file, with nested fields separated by dots.
+    pub name: String,
+    /// The Rust type of the setting.
+    pub type_name: String,
+    /// The doc comment of the setting, if any.
+    pub description: String,
+    /// The default value, in the same format as in the settings file.
+    pub default: serde_json::Value,
+    /// Whether changing the setting causes the tunnel to reconnect.
+    pub requires_reconnect: bool,
+    pub constraints: Vec<SettingConstraint>,
+}
+
+/// A restriction on the values a setting may have.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SettingConstraint {
+    /// The smallest allowed value.
+    Min(i64),
+    /// The largest allowed value.
+    Max(i64),
+    /// The value must be one of these.
+    OneOf(Vec<String>),
+}
+
+/// Types that can describe their fields as settings. Implemented with
+/// `#[derive(SettingsSchema)]`.
+pub trait SettingsSchema {
+    /// Appends a description of every field to `settings`. Default values are read from
+    /// `default`, and `prefix` is prepended to every name.
+    fn describe(default: &Self, prefix: &str, settings: &mut Vec<SettingDescription>);
+
+    /// Returns a description of every field, using `Default::default()` for the default values.
+    fn schema() -> Vec<SettingDescription>
+    where
+        Self: Default,
+    {
+        let mut settings = Vec::new();
+        Self::describe(&Self::default(), "", &mut settings);
+        settings
+    }
+}
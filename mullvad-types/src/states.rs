@@ -1,4 +1,5 @@
 use crate::location::GeoIpLocation;
+use chrono::{DateTime, Utc};
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
@@ -27,6 +28,11 @@ impl fmt::Display for TargetState {
     }
 }
 
+/// Version of the tunnel state format used by the management interface. It is only incremented
+/// when a change is made that older frontends cannot safely ignore. New fields and variants do not
+/// require a new version, since they are handled as described in [`talpid_types::tunnel`].
+pub const TUNNEL_STATE_VERSION: u32 = 1;
+
 /// Represents the state the client tunnel is in.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -37,16 +43,43 @@ pub enum TunnelState {
     Disconnected,
     Connecting {
         endpoint: TunnelEndpoint,
+        #[serde(default)]
         location: Option<GeoIpLocation>,
+        /// When the tunnel is started, if the start was put off after a failed attempt.
+        #[serde(default)]
+        #[cfg_attr(target_os = "android", jnix(skip))]
+        retry_at: Option<DateTime<Utc>>,
     },
     Connected {
         endpoint: TunnelEndpoint,
+        #[serde(default)]
         location: Option<GeoIpLocation>,
+        #[serde(default)]
+        #[cfg_attr(target_os = "android", jnix(skip))]
+        feature_indicators: FeatureIndicators,
     },
     Disconnecting(ActionAfterDisconnect),
     Error(ErrorState),
 }
 
+/// Features that are in effect for a connected tunnel. These are determined by the daemon from
+/// what was actually applied when the tunnel was set up, so frontends should use them rather than
+/// derive the same information from the settings.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FeatureIndicators {
+    /// Traffic is blocked while the tunnel is down.
+    pub lockdown_mode: bool,
+    /// Some applications or processes are excluded from the tunnel.
+    pub split_tunneling: bool,
+    /// Custom DNS servers are used inside the tunnel.
+    pub custom_dns: bool,
+    /// The tunnel traffic is obfuscated.
+    pub obfuscation: bool,
+    /// The traffic is routed through an entry relay before reaching the exit relay.
+    pub multihop: bool,
+}
+
 impl TunnelState {
     /// Returns true if the tunnel state is in the error state.
     pub fn is_in_error_state(&self) -> bool {
@@ -56,3 +89,65 @@ impl TunnelState {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use talpid_types::{
+        net::{Endpoint, TransportProtocol, TunnelType},
+        tunnel::ErrorStateCause,
+    };
+
+    #[test]
+    fn test_tunnel_state_round_trip() {
+        let endpoint = TunnelEndpoint {
+            endpoint: Endpoint::new("192.0.2.1".parse().unwrap(), 51820, TransportProtocol::Udp),
+            tunnel_type: TunnelType::Wireguard,
+            proxy: None,
+            entry_endpoint: None,
+            obfuscation: None,
+        };
+        let states = vec![
+            TunnelState::Disconnected,
+            TunnelState::Connecting {
+                endpoint: endpoint.clone(),
+                location: None,
+                retry_at: Some(Utc::now()),
+            },
+            TunnelState::Connected {
+                endpoint,
+                location: None,
+                feature_indicators: FeatureIndicators {
+                    lockdown_mode: true,
+                    multihop: true,
+                    ..FeatureIndicators::default()
+                },
+            },
+            TunnelState::Disconnecting(ActionAfterDisconnect::Reconnect),
+            TunnelState::Error(ErrorState::new(ErrorStateCause::IsOffline, None)),
+        ];
+        for state in states {
+            let json = serde_json::to_string(&state).unwrap();
+            assert_eq!(serde_json::from_str::<TunnelState>(&json).unwrap(), state);
+        }
+    }
+
+    #[test]
+    fn test_error_state_from_newer_version() {
+        let state: TunnelState = serde_json::from_str(
+            r#"{
+                "state": "error",
+                "details": {
+                    "cause": { "reason": "some_new_cause", "details": [1, 2] },
+                    "block_failure": null,
+                    "some_new_field": "value"
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            state,
+            TunnelState::Error(ErrorState::new(ErrorStateCause::Unknown, None))
+        );
+    }
+}
@@ -0,0 +1,344 @@
+//! Durations and byte sizes that are written as human readable strings, such as `"90s"` or
+//! `"10MB"`, in the settings file and when entered by the user.
+use serde::{
+    de::{self, MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::{convert::TryFrom, fmt, str::FromStr, time::Duration};
+
+const DURATION_UNITS: &[(&str, u64)] = &[
+    ("d", 24 * 60 * 60 * 1_000_000_000),
+    ("h", 60 * 60 * 1_000_000_000),
+    ("m", 60 * 1_000_000_000),
+    ("s", 1_000_000_000),
+    ("ms", 1_000_000),
+    ("us", 1_000),
+    ("ns", 1),
+];
+
+const BINARY_SIZE_UNITS: &[(&str, u64)] = &[
+    ("GiB", 1024 * 1024 * 1024),
+    ("MiB", 1024 * 1024),
+    ("KiB", 1024),
+];
+
+const DECIMAL_SIZE_UNITS: &[(&str, u64)] =
+    &[("GB", 1_000_000_000), ("MB", 1_000_000), ("KB", 1_000)];
+
+#[derive(err_derive::Error, Debug, Clone, PartialEq)]
+pub enum ParseError {
+    #[error(display = "No value was given")]
+    Empty,
+
+    #[error(display = "Invalid number: \"{}\"", _0)]
+    InvalidNumber(String),
+
+    #[error(display = "Unknown unit \"{}\"", _0)]
+    UnknownUnit(String),
+
+    #[error(display = "Missing unit after {}, e.g. \"{}s\"", _0, _0)]
+    MissingUnit(u64),
+
+    #[error(display = "The value is too large")]
+    Overflow,
+}
+
+/// A value that is invalid for a specific setting. The name of the setting is included in the
+/// error message so that it can be found in the settings file or in the request.
+#[derive(err_derive::Error, Debug, Clone, PartialEq)]
+#[error(display = "Invalid value for {}: {}", field, reason)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub reason: String,
+}
+
+impl FieldError {
+    pub fn new(field: &'static str, reason: impl fmt::Display) -> Self {
+        FieldError {
+            field,
+            reason: reason.to_string(),
+        }
+    }
+
+    /// Converts this error into a deserialization error.
+    pub fn into_de_error<E: de::Error>(self) -> E {
+        E::custom(self)
+    }
+}
+
+/// A duration written as a sequence of numbers with units, such as `"90s"` or `"1h30m"`.
+/// Supported units are `d`, `h`, `m`, `s`, `ms`, `us` and `ns`.
+///
+/// For backward compatibility, the `{ "secs": ..., "nanos": ... }` representation used by older
+/// settings files is also accepted when deserializing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct HumanDuration(pub Duration);
+
+impl HumanDuration {
+    pub fn as_duration(&self) -> Duration {
+        self.0
+    }
+}
+
+impl From<Duration> for HumanDuration {
+    fn from(duration: Duration) -> Self {
+        HumanDuration(duration)
+    }
+}
+
+impl From<HumanDuration> for Duration {
+    fn from(duration: HumanDuration) -> Self {
+        duration.0
+    }
+}
+
+impl FromStr for HumanDuration {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut remaining = s.trim();
+        if remaining.is_empty() {
+            return Err(ParseError::Empty);
+        }
+
+        let mut total_nanos: u128 = 0;
+        while !remaining.is_empty() {
+            let (value, rest) = split_number(remaining)?;
+            let rest = rest.trim_start();
+            let unit_len = rest
+                .find(|c: char| c.is_ascii_digit() || c.is_whitespace())
+                .unwrap_or_else(|| rest.len());
+            let (unit, rest) = rest.split_at(unit_len);
+            if unit.is_empty() {
+                return Err(ParseError::MissingUnit(value));
+            }
+            let multiplier = DURATION_UNITS
+                .iter()
+                .find(|(name, _)| *name == unit)
+                .map(|(_, multiplier)| *multiplier)
+                .ok_or_else(|| ParseError::UnknownUnit(unit.to_owned()))?;
+
+            total_nanos = total_nanos
+                .checked_add(u128::from(value) * u128::from(multiplier))
+                .ok_or(ParseError::Overflow)?;
+            remaining = rest.trim_start();
+        }
+
+        let secs = u64::try_from(total_nanos / 1_000_000_000).map_err(|_| ParseError::Overflow)?;
+        let nanos = (total_nanos % 1_000_000_000) as u32;
+        Ok(HumanDuration(Duration::new(secs, nanos)))
+    }
+}
+
+impl fmt::Display for HumanDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut remaining = self.0.as_nanos();
+        if remaining == 0 {
+            return write!(f, "0s");
+        }
+        for (unit, multiplier) in DURATION_UNITS {
+            let multiplier = u128::from(*multiplier);
+            if remaining >= multiplier {
+                write!(f, "{}{}", remaining / multiplier, unit)?;
+                remaining %= multiplier;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for HumanDuration {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for HumanDuration {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct DurationVisitor;
+
+        impl<'de> Visitor<'de> for DurationVisitor {
+            type Value = HumanDuration;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+                formatter.write_str("a duration such as \"90s\" or \"1h30m\"")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                value.parse().map_err(E::custom)
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                Duration::deserialize(de::value::MapAccessDeserializer::new(map)).map(HumanDuration)
+            }
+        }
+
+        deserializer.deserialize_any(DurationVisitor)
+    }
+}
+
+/// A number of bytes written with an optional unit, such as `"10MB"` or `"128KiB"`. Both decimal
+/// (`KB`, `MB`, `GB`) and binary (`KiB`, `MiB`, `GiB`) units are supported. A number without a
+/// unit is a number of bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct ByteSize(pub u64);
+
+impl ByteSize {
+    pub const fn from_bytes(bytes: u64) -> Self {
+        ByteSize(bytes)
+    }
+
+    pub const fn kib(kib: u64) -> Self {
+        ByteSize(kib * 1024)
+    }
+
+    pub const fn mib(mib: u64) -> Self {
+        ByteSize(mib * 1024 * 1024)
+    }
+
+    pub fn as_bytes(&self) -> u64 {
+        self.0
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(ParseError::Empty);
+        }
+
+        let (value, unit) = split_number(s)?;
+        let unit = unit.trim();
+        let multiplier = if unit.is_empty() || unit.eq_ignore_ascii_case("b") {
+            1
+        } else {
+            BINARY_SIZE_UNITS
+                .iter()
+                .chain(DECIMAL_SIZE_UNITS)
+                .find(|(name, _)| name.eq_ignore_ascii_case(unit))
+                .map(|(_, multiplier)| *multiplier)
+                .ok_or_else(|| ParseError::UnknownUnit(unit.to_owned()))?
+        };
+
+        value
+            .checked_mul(multiplier)
+            .map(ByteSize)
+            .ok_or(ParseError::Overflow)
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = BINARY_SIZE_UNITS
+            .iter()
+            .chain(DECIMAL_SIZE_UNITS)
+            .find(|(_, multiplier)| self.0 != 0 && self.0 % multiplier == 0);
+        match unit {
+            Some((name, multiplier)) => write!(f, "{}{}", self.0 / multiplier, name),
+            None => write!(f, "{}B", self.0),
+        }
+    }
+}
+
+impl Serialize for ByteSize {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ByteSize {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ByteSizeVisitor;
+
+        impl<'de> Visitor<'de> for ByteSizeVisitor {
+            type Value = ByteSize;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+                formatter.write_str("a size such as \"10MB\" or a number of bytes")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                value.parse().map_err(E::custom)
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+                Ok(ByteSize(value))
+            }
+        }
+
+        deserializer.deserialize_any(ByteSizeVisitor)
+    }
+}
+
+/// Splits a string into a leading unsigned integer and the rest of the string.
+fn split_number(s: &str) -> Result<(u64, &str), ParseError> {
+    let digits_len = s
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or_else(|| s.len());
+    let (digits, rest) = s.split_at(digits_len);
+    if digits.is_empty() {
+        return Err(ParseError::InvalidNumber(s.to_owned()));
+    }
+    let value = digits.parse().map_err(|_| ParseError::Overflow)?;
+    Ok((value, rest))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        let parse = |s: &str| s.parse::<HumanDuration>().map(Duration::from);
+        assert_eq!(parse("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse("1h30m"), Ok(Duration::from_secs(90 * 60)));
+        assert_eq!(parse("1h 30m"), Ok(Duration::from_secs(90 * 60)));
+        assert_eq!(parse("90 s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse("7d"), Ok(Duration::from_secs(7 * 24 * 60 * 60)));
+        assert_eq!(parse("1500ms"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse("90"), Err(ParseError::MissingUnit(90)));
+        assert_eq!(parse("5y"), Err(ParseError::UnknownUnit("y".to_owned())));
+        assert_eq!(parse("h"), Err(ParseError::InvalidNumber("h".to_owned())));
+        assert_eq!(parse(""), Err(ParseError::Empty));
+    }
+
+    #[test]
+    fn test_format_duration() {
+        let format = |duration| HumanDuration(duration).to_string();
+        assert_eq!(format(Duration::from_secs(90)), "1m30s");
+        assert_eq!(format(Duration::from_secs(7 * 24 * 60 * 60)), "7d");
+        assert_eq!(format(Duration::from_millis(1500)), "1s500ms");
+        assert_eq!(format(Duration::from_secs(0)), "0s");
+    }
+
+    #[test]
+    fn test_deserialize_legacy_duration() {
+        let duration: HumanDuration =
+            serde_json::from_str(r#"{ "secs": 86400, "nanos": 0 }"#).unwrap();
+        assert_eq!(duration, HumanDuration(Duration::from_secs(86400)));
+
+        let duration: HumanDuration = serde_json::from_str(r#""1d""#).unwrap();
+        assert_eq!(duration, HumanDuration(Duration::from_secs(86400)));
+        assert_eq!(serde_json::to_string(&duration).unwrap(), r#""1d""#);
+    }
+
+    #[test]
+    fn test_byte_size() {
+        let parse = |s: &str| s.parse::<ByteSize>().map(|size| size.as_bytes());
+        assert_eq!(parse("10MB"), Ok(10_000_000));
+        assert_eq!(parse("128KiB"), Ok(128 * 1024));
+        assert_eq!(parse("128 kib"), Ok(128 * 1024));
+        assert_eq!(parse("512"), Ok(512));
+        assert_eq!(parse("1TB"), Err(ParseError::UnknownUnit("TB".to_owned())));
+
+        assert_eq!(ByteSize::kib(128).to_string(), "128KiB");
+        assert_eq!(ByteSize(10_000_000).to_string(), "10MB");
+        assert_eq!(ByteSize(1000 + 1).to_string(), "1001B");
+
+        let size: ByteSize = serde_json::from_str("2048").unwrap();
+        assert_eq!(size, ByteSize::kib(2));
+    }
+}
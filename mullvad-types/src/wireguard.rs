@@ -1,7 +1,8 @@
+use crate::units::{FieldError, HumanDuration};
 use chrono::{offset::Utc, DateTime};
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
-use serde::{Deserialize, Deserializer, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::{convert::TryFrom, fmt, time::Duration};
 use talpid_types::net::wireguard;
 
@@ -59,7 +60,7 @@ impl fmt::Display for RotationIntervalError {
 
 impl std::error::Error for RotationIntervalError {}
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct RotationInterval(Duration);
 
 impl RotationInterval {
@@ -78,18 +79,30 @@ impl RotationInterval {
     }
 }
 
+impl fmt::Display for RotationInterval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        HumanDuration(self.0).fmt(f)
+    }
+}
+
+impl Serialize for RotationInterval {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        HumanDuration(self.0).serialize(serializer)
+    }
+}
+
 impl<'de> Deserialize<'de> for RotationInterval {
     fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        let ivl = <Duration>::deserialize(deserializer)?;
-        RotationInterval::new(ivl).map_err(|_error| {
-            serde::de::Error::invalid_value(
-                serde::de::Unexpected::Other("Duration"),
-                &"interval within allowed range",
-            )
-        })
+        let ivl = HumanDuration::deserialize(deserializer)
+            .map_err(|error| FieldError::new("rotation_interval", error).into_de_error())?;
+        RotationInterval::new(ivl.into())
+            .map_err(|error| FieldError::new("rotation_interval", error).into_de_error())
     }
 }
 
@@ -125,6 +138,36 @@ pub struct TunnelOptions {
     /// Interval used for automatic key rotation
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub rotation_interval: Option<RotationInterval>,
+    /// Whether to negotiate a post-quantum preshared key with the relay when connecting
+    #[serde(default)]
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub quantum_resistant: QuantumResistantState,
+}
+
+/// Setting indicating whether WireGuard tunnels should be made quantum-resistant. `Auto` turns it
+/// on while the `quantum-resistant-tunnel` beta feature is turned on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuantumResistantState {
+    Auto,
+    On,
+    Off,
+}
+
+impl Default for QuantumResistantState {
+    fn default() -> Self {
+        QuantumResistantState::Auto
+    }
+}
+
+impl fmt::Display for QuantumResistantState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            QuantumResistantState::Auto => "auto",
+            QuantumResistantState::On => "on",
+            QuantumResistantState::Off => "off",
+        })
+    }
 }
 
 /// Represents a published public key
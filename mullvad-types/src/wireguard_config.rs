@@ -0,0 +1,177 @@
+//! Export of the configuration of a WireGuard tunnel in the format used by `wg-quick`, so that
+//! what is applied can be compared with the output of `wg show`.
+
+use ipnetwork::IpNetwork;
+use std::{fmt::Write, net::IpAddr};
+use talpid_types::net::{
+    wireguard::{ObfuscatorConfig, PeerConfig, TunnelParameters},
+    TransportProtocol,
+};
+
+/// Written instead of the private key when it is not included.
+pub const REDACTED_PRIVATE_KEY: &str = "(redacted)";
+
+/// Writes the interface and peer configuration of a tunnel. The private key is replaced with
+/// [`REDACTED_PRIVATE_KEY`] unless `include_private_key` is set.
+pub fn export(params: &TunnelParameters, include_private_key: bool) -> String {
+    let connection = &params.connection;
+    let mut config = String::new();
+
+    let _ = writeln!(config, "[Interface]");
+    if include_private_key {
+        let _ = writeln!(
+            config,
+            "PrivateKey = {}",
+            connection.tunnel.private_key.to_base64()
+        );
+    } else {
+        let _ = writeln!(config, "PrivateKey = {}", REDACTED_PRIVATE_KEY);
+    }
+    let addresses: Vec<_> = connection
+        .tunnel
+        .addresses
+        .iter()
+        .map(|address| host_network(*address).to_string())
+        .collect();
+    let _ = writeln!(config, "Address = {}", addresses.join(", "));
+    if let Some(mtu) = params.options.mtu {
+        let _ = writeln!(config, "MTU = {}", mtu);
+    }
+
+    match &connection.exit_peer {
+        Some(exit_peer) => {
+            write_peer(&mut config, &connection.peer, Some("Entry relay"));
+            write_peer(&mut config, exit_peer, Some("Exit relay"));
+        }
+        None => write_peer(&mut config, &connection.peer, None),
+    }
+
+    match &connection.obfuscation {
+        Some(ObfuscatorConfig::Quic { endpoint, .. }) => {
+            let _ = writeln!(
+                config,
+                "\n# Traffic to the first peer is sent over QUIC through {}. The peer endpoint \
+                 seen by `wg show` is a local proxy.",
+                endpoint
+            );
+        }
+        Some(ObfuscatorConfig::Shadowsocks { endpoint, .. }) => {
+            let _ = writeln!(
+                config,
+                "\n# Traffic to the first peer is relayed by the Shadowsocks server at {}. The \
+                 peer endpoint seen by `wg show` is a local proxy.",
+                endpoint
+            );
+        }
+        Some(ObfuscatorConfig::Bridge(settings)) => {
+            let _ = writeln!(
+                config,
+                "\n# Traffic to the first peer is sent over TCP through the bridge at {}. The \
+                 peer endpoint seen by `wg show` is a local proxy.",
+                settings.get_endpoint().endpoint.address
+            );
+        }
+        None if connection.peer.protocol == TransportProtocol::Tcp => {
+            let _ = writeln!(
+                config,
+                "\n# Traffic to the first peer is sent over TCP. The peer endpoint seen by \
+                 `wg show` is a local proxy."
+            );
+        }
+        None => (),
+    }
+
+    config
+}
+
+fn write_peer(config: &mut String, peer: &PeerConfig, comment: Option<&str>) {
+    let _ = writeln!(config);
+    if let Some(comment) = comment {
+        let _ = writeln!(config, "# {}", comment);
+    }
+    let _ = writeln!(config, "[Peer]");
+    let _ = writeln!(config, "PublicKey = {}", peer.public_key.to_base64());
+    let allowed_ips: Vec<_> = peer.allowed_ips.iter().map(ToString::to_string).collect();
+    let _ = writeln!(config, "AllowedIPs = {}", allowed_ips.join(", "));
+    let _ = writeln!(config, "Endpoint = {}", peer.endpoint);
+}
+
+fn host_network(address: IpAddr) -> IpNetwork {
+    let prefix = if address.is_ipv4() { 32 } else { 128 };
+    IpNetwork::new(address, prefix).expect("Prefix is valid for the address family")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use talpid_types::net::{
+        wireguard::{ConnectionConfig, PrivateKey, TunnelConfig, TunnelOptions},
+        GenericTunnelOptions, Ipv6FailurePolicy,
+    };
+
+    fn params() -> TunnelParameters {
+        let private_key = PrivateKey::from([1; 32]);
+        TunnelParameters {
+            connection: ConnectionConfig {
+                tunnel: TunnelConfig {
+                    private_key,
+                    addresses: vec!["10.64.0.2".parse().unwrap(), "fc00::2".parse().unwrap()],
+                },
+                peer: PeerConfig {
+                    public_key: PrivateKey::from([2; 32]).public_key(),
+                    allowed_ips: vec!["0.0.0.0/0".parse().unwrap(), "::/0".parse().unwrap()],
+                    endpoint: "192.0.2.1:51820".parse().unwrap(),
+                    protocol: TransportProtocol::Udp,
+                    psk: None,
+                },
+                exit_peer: None,
+                ipv4_gateway: "10.64.0.1".parse().unwrap(),
+                ipv6_gateway: None,
+                obfuscation: None,
+                handshake_timeout: None,
+                quantum_resistant: false,
+            },
+            options: TunnelOptions {
+                mtu: Some(1380),
+                ..TunnelOptions::default()
+            },
+            generic_options: GenericTunnelOptions {
+                enable_ipv6: true,
+                interface_name: None,
+                ipv6_failure_policy: Ipv6FailurePolicy::default(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_export_redacts_private_key() {
+        let params = params();
+        let private_key = params.connection.tunnel.private_key.to_base64();
+
+        let config = export(&params, false);
+        assert!(!config.contains(&private_key));
+        assert!(config.contains(&format!("PrivateKey = {}", REDACTED_PRIVATE_KEY)));
+        assert!(config.contains("Address = 10.64.0.2/32, fc00::2/128\n"));
+        assert!(config.contains("MTU = 1380\n"));
+        assert!(config.contains("AllowedIPs = 0.0.0.0/0, ::/0\n"));
+        assert!(config.contains("Endpoint = 192.0.2.1:51820\n"));
+
+        let config = export(&params, true);
+        assert!(config.contains(&format!("PrivateKey = {}\n", private_key)));
+    }
+
+    #[test]
+    fn test_export_multihop() {
+        let mut params = params();
+        let mut exit_peer = params.connection.peer.clone();
+        exit_peer.endpoint = "198.51.100.1:51820".parse().unwrap();
+        params.connection.exit_peer = Some(exit_peer);
+
+        let config = export(&params, false);
+        assert_eq!(config.matches("[Peer]").count(), 2);
+        let entry = config.find("# Entry relay").unwrap();
+        let exit = config.find("# Exit relay").unwrap();
+        assert!(entry < exit);
+        assert!(config[exit..].contains("Endpoint = 198.51.100.1:51820"));
+    }
+}
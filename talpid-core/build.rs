@@ -19,6 +19,7 @@ mod win {
         let target_dir = match target.as_str() {
             "i686-pc-windows-msvc" => format!("Win32-{}", get_build_mode()),
             "x86_64-pc-windows-msvc" => format!("x64-{}", get_build_mode()),
+            "aarch64-pc-windows-msvc" => format!("ARM64-{}", get_build_mode()),
             _ => panic!("uncrecognized target: {}", target),
         };
         target_dir.into()
@@ -55,7 +56,8 @@ fn main() {
     declare_library(WINFW_DIR_VAR, WINFW_BUILD_DIR, "winfw");
     declare_library(WINDNS_DIR_VAR, WINDNS_BUILD_DIR, "windns");
     declare_library(WINNET_DIR_VAR, WINNET_BUILD_DIR, "winnet");
-    let lib_dir = manifest_dir().join("../build/lib/x86_64-pc-windows-msvc");
+    let target = env::var("TARGET").expect("TARGET env var not set");
+    let lib_dir = manifest_dir().join("../build/lib").join(target);
     println!("cargo:rustc-link-search={}", &lib_dir.display());
     println!("cargo:rustc-link-lib=dylib=libwg");
 }
@@ -93,7 +95,12 @@ fn declare_libs_dir(base: &str) {
 }
 
 fn generate_grpc_code() {
-    const PROTO_FILE: &str = "../talpid-openvpn-plugin/proto/openvpn_plugin.proto";
-    tonic_build::compile_protos(PROTO_FILE).unwrap();
-    println!("cargo:rerun-if-changed={}", PROTO_FILE);
+    const PROTO_FILES: &[&str] = &[
+        "../talpid-openvpn-plugin/proto/openvpn_plugin.proto",
+        "proto/tunnel_config.proto",
+    ];
+    for proto_file in PROTO_FILES {
+        tonic_build::compile_protos(proto_file).unwrap();
+        println!("cargo:rerun-if-changed={}", proto_file);
+    }
 }
@@ -0,0 +1,190 @@
+use super::{ConflictKind, ConflictingSoftware};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+use talpid_dbus::network_manager::NetworkManager;
+use talpid_types::ErrorExt;
+
+const SYS_CLASS_NET: &str = "/sys/class/net";
+const TUN_DEVICE: &str = "/dev/net/tun";
+const IFF_UP: u32 = 0x1;
+
+pub fn detect(tunnel_interface: Option<&str>) -> Vec<ConflictingSoftware> {
+    let mut conflicts = match foreign_tunnel_interfaces(tunnel_interface) {
+        Ok(conflicts) => conflicts,
+        Err(error) => {
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg("Failed to list the tunnel interfaces")
+            );
+            vec![]
+        }
+    };
+    conflicts.extend(network_manager_vpn_connections(tunnel_interface));
+    conflicts
+}
+
+/// Returns the tun, tap and WireGuard interfaces that are up and do not belong to this process or
+/// to one of its children, such as OpenVPN.
+fn foreign_tunnel_interfaces(
+    tunnel_interface: Option<&str>,
+) -> io::Result<Vec<ConflictingSoftware>> {
+    let own_pid = std::process::id();
+    let owners = tun_owners();
+    let mut conflicts = vec![];
+
+    for entry in fs::read_dir(SYS_CLASS_NET)? {
+        let entry = entry?;
+        let interface = entry.file_name().to_string_lossy().into_owned();
+        if Some(interface.as_str()) == tunnel_interface
+            || !is_tunnel_interface(&entry.path())
+            || !is_up(&entry.path())
+        {
+            continue;
+        }
+
+        let pids = owners.get(&interface).map(Vec::as_slice).unwrap_or(&[]);
+        if pids
+            .iter()
+            .any(|&pid| pid == own_pid || parent_pid(pid) == Some(own_pid))
+        {
+            continue;
+        }
+        let name = match pids.first().and_then(|&pid| process_name(pid)) {
+            Some(process) => format!("{} (owned by {})", interface, process),
+            None => interface,
+        };
+        conflicts.push(ConflictingSoftware {
+            kind: ConflictKind::TunnelInterface,
+            name,
+        });
+    }
+    Ok(conflicts)
+}
+
+fn is_tunnel_interface(path: &Path) -> bool {
+    path.join("tun_flags").exists()
+        || fs::read_to_string(path.join("uevent"))
+            .map(|uevent| uevent.lines().any(|line| line == "DEVTYPE=wireguard"))
+            .unwrap_or(false)
+}
+
+fn is_up(path: &Path) -> bool {
+    fs::read_to_string(path.join("flags"))
+        .ok()
+        .and_then(|flags| u32::from_str_radix(flags.trim().trim_start_matches("0x"), 16).ok())
+        .map(|flags| flags & IFF_UP != 0)
+        .unwrap_or(false)
+}
+
+/// Maps the names of tun and tap interfaces to the IDs of the processes that have them open. The
+/// interface that an open tun device is attached to is listed in the fdinfo of the file.
+fn tun_owners() -> HashMap<String, Vec<u32>> {
+    let mut owners: HashMap<String, Vec<u32>> = HashMap::new();
+    let processes = match fs::read_dir("/proc") {
+        Ok(processes) => processes,
+        Err(_) => return owners,
+    };
+
+    for process in processes.filter_map(Result::ok) {
+        let pid = match process
+            .file_name()
+            .to_str()
+            .and_then(|pid| pid.parse().ok())
+        {
+            Some(pid) => pid,
+            None => continue,
+        };
+        let fds = match fs::read_dir(process.path().join("fd")) {
+            Ok(fds) => fds,
+            Err(_) => continue,
+        };
+        for fd in fds.filter_map(Result::ok) {
+            let is_tun_device = fs::read_link(fd.path())
+                .map(|target| target == PathBuf::from(TUN_DEVICE))
+                .unwrap_or(false);
+            if !is_tun_device {
+                continue;
+            }
+            let fdinfo = fs::read_to_string(process.path().join("fdinfo").join(fd.file_name()));
+            if let Some(interface) = fdinfo
+                .ok()
+                .and_then(|fdinfo| parse_fdinfo_interface(&fdinfo))
+            {
+                owners.entry(interface).or_default().push(pid);
+            }
+        }
+    }
+    owners
+}
+
+fn parse_fdinfo_interface(fdinfo: &str) -> Option<String> {
+    fdinfo
+        .lines()
+        .find_map(|line| line.strip_prefix("iff:"))
+        .map(|interface| interface.trim().to_owned())
+}
+
+fn parent_pid(pid: u32) -> Option<u32> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    parse_stat_parent_pid(&stat)
+}
+
+/// Reads the parent PID from the contents of `/proc/<pid>/stat`. The command name that precedes it
+/// is in parentheses, and may itself contain spaces and parentheses.
+fn parse_stat_parent_pid(stat: &str) -> Option<u32> {
+    let fields = &stat[stat.rfind(')')? + 1..];
+    fields.split_whitespace().nth(1)?.parse().ok()
+}
+
+fn process_name(pid: u32) -> Option<String> {
+    fs::read_to_string(format!("/proc/{}/comm", pid))
+        .ok()
+        .map(|name| name.trim().to_owned())
+}
+
+fn network_manager_vpn_connections(tunnel_interface: Option<&str>) -> Vec<ConflictingSoftware> {
+    let connections = match NetworkManager::new()
+        .and_then(|network_manager| network_manager.active_vpns())
+    {
+        Ok(connections) => connections,
+        Err(error) => {
+            log::debug!(
+                "{}",
+                error
+                    .display_chain_with_msg("Failed to list the VPN connections of NetworkManager")
+            );
+            return vec![];
+        }
+    };
+    connections
+        .into_iter()
+        .filter(|connection| {
+            tunnel_interface.is_none() || connection.interface.as_deref() != tunnel_interface
+        })
+        .map(|connection| ConflictingSoftware {
+            kind: ConflictKind::NetworkManagerVpn,
+            name: connection.id,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_stat_parent_pid() {
+        let stat = "1234 (openvpn (x) y) S 42 1234 1234 0 -1 4194560";
+        assert_eq!(parse_stat_parent_pid(stat), Some(42));
+    }
+
+    #[test]
+    fn test_parse_fdinfo_interface() {
+        let fdinfo = "pos:\t0\nflags:\t0104002\nmnt_id:\t25\niff:\ttun0\n";
+        assert_eq!(parse_fdinfo_interface(fdinfo), Some("tun0".to_owned()));
+        assert_eq!(parse_fdinfo_interface("pos:\t0\n"), None);
+    }
+}
@@ -0,0 +1,59 @@
+use super::{ConflictKind, ConflictingSoftware};
+use std::process::Command;
+use talpid_types::ErrorExt;
+
+/// Returns the VPN services in the network settings that are connected. These include the VPN
+/// apps that use a network extension, which is how most VPN clients on macOS set up their utun
+/// interface.
+pub fn detect(_tunnel_interface: Option<&str>) -> Vec<ConflictingSoftware> {
+    match Command::new("/usr/sbin/scutil")
+        .args(&["--nc", "list"])
+        .output()
+    {
+        Ok(output) => parse_nc_list(&String::from_utf8_lossy(&output.stdout)),
+        Err(error) => {
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg("Failed to list the VPN services")
+            );
+            vec![]
+        }
+    }
+}
+
+/// Parses the output of `scutil --nc list`, where every service is on a line such as
+/// `* (Connected)  <id> VPN (com.example.vpn) "Example VPN" [VPN:com.example.vpn]`.
+fn parse_nc_list(output: &str) -> Vec<ConflictingSoftware> {
+    output
+        .lines()
+        .filter(|line| line.contains("(Connected)"))
+        .filter_map(|line| {
+            let start = line.find('"')? + 1;
+            let end = start + line[start..].find('"')?;
+            Some(ConflictingSoftware {
+                kind: ConflictKind::SystemVpn,
+                name: line[start..end].to_owned(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_nc_list() {
+        let output = r#"Available network connection services in the current set (*=enabled):
+* (Disconnected)   1E0C7B2A-0000-0000-0000-000000000001 PPP --> L2TP       "Office"                         [PPP/L2TP]
+* (Connected)      1E0C7B2A-0000-0000-0000-000000000002 VPN (com.example.vpn) "Example VPN"              [VPN/com.example.vpn]
+"#;
+        assert_eq!(
+            parse_nc_list(output),
+            vec![ConflictingSoftware {
+                kind: ConflictKind::SystemVpn,
+                name: "Example VPN".to_owned(),
+            }]
+        );
+    }
+}
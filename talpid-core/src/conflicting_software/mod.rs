@@ -0,0 +1,73 @@
+use std::fmt;
+
+#[cfg(target_os = "linux")]
+#[path = "linux.rs"]
+mod imp;
+
+#[cfg(target_os = "macos")]
+#[path = "macos.rs"]
+mod imp;
+
+#[cfg(windows)]
+#[path = "windows.rs"]
+mod imp;
+
+#[cfg(target_os = "android")]
+mod imp {
+    /// Only one VPN service can be active at a time on Android, so there is nothing to detect.
+    pub fn detect(_tunnel_interface: Option<&str>) -> Vec<super::ConflictingSoftware> {
+        vec![]
+    }
+}
+
+/// How conflicting software was detected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ConflictKind {
+    /// A WFP provider that was registered by another VPN client. Its filters may block or
+    /// redirect the traffic that the firewall allows.
+    WfpProvider,
+    /// A tunnel interface that belongs to another VPN client is up. Its routes may take
+    /// precedence over the routes of the tunnel.
+    TunnelInterface,
+    /// A VPN connection that is managed by NetworkManager is active.
+    NetworkManagerVpn,
+    /// A VPN service in the network settings of the system is connected.
+    SystemVpn,
+}
+
+impl fmt::Display for ConflictKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            ConflictKind::WfpProvider => "WFP provider",
+            ConflictKind::TunnelInterface => "tunnel interface",
+            ConflictKind::NetworkManagerVpn => "NetworkManager VPN connection",
+            ConflictKind::SystemVpn => "system VPN service",
+        };
+        f.write_str(kind)
+    }
+}
+
+/// Software that is likely to keep the tunnel from working.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConflictingSoftware {
+    /// How the software was detected.
+    pub kind: ConflictKind,
+    /// The name of the software, or of the object that gave it away, such as an interface.
+    pub name: String,
+}
+
+impl fmt::Display for ConflictingSoftware {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} \"{}\"", self.kind, self.name)
+    }
+}
+
+/// Looks for other VPN software that is running. `tunnel_interface` is the name of our own tunnel
+/// interface, if it is known, so that it is not reported. This blocks while the system is
+/// queried, so it should not be called from an async context.
+pub fn detect(tunnel_interface: Option<&str>) -> Vec<ConflictingSoftware> {
+    let mut conflicts = imp::detect(tunnel_interface);
+    conflicts.sort();
+    conflicts.dedup();
+    conflicts
+}
@@ -0,0 +1,87 @@
+use super::{ConflictKind, ConflictingSoftware};
+use widestring::WideCStr;
+
+/// Vendors and products of VPN clients that register WFP providers. Providers with a name that
+/// contains any of these are reported.
+const KNOWN_VPN_PROVIDERS: &[&str] = &[
+    "anyconnect",
+    "checkpoint",
+    "check point",
+    "cisco",
+    "cyberghost",
+    "expressvpn",
+    "forticlient",
+    "fortinet",
+    "globalprotect",
+    "hotspot shield",
+    "ivpn",
+    "nordvpn",
+    "openvpn",
+    "palo alto",
+    "private internet access",
+    "protonvpn",
+    "pulse secure",
+    "surfshark",
+    "windscribe",
+    "zscaler",
+];
+
+pub fn detect(_tunnel_interface: Option<&str>) -> Vec<ConflictingSoftware> {
+    let mut providers: Vec<String> = vec![];
+    let listed = unsafe {
+        WinFw_DescribeProviders(
+            Some(provider_sink),
+            &mut providers as *mut Vec<String> as *mut libc::c_void,
+        )
+    };
+    if !listed {
+        log::warn!("Failed to list the WFP providers");
+        return vec![];
+    }
+
+    providers
+        .into_iter()
+        .filter(|provider| is_vpn_provider(provider))
+        .map(|provider| ConflictingSoftware {
+            kind: ConflictKind::WfpProvider,
+            name: provider,
+        })
+        .collect()
+}
+
+fn is_vpn_provider(name: &str) -> bool {
+    let name = name.to_lowercase();
+    KNOWN_VPN_PROVIDERS
+        .iter()
+        .any(|vendor| name.contains(vendor))
+}
+
+/// Collects the provider names passed by `WinFw_DescribeProviders` into the `Vec<String>` that
+/// `context` points to.
+extern "system" fn provider_sink(name: *const libc::wchar_t, context: *mut libc::c_void) {
+    if name.is_null() {
+        return;
+    }
+    let providers = unsafe { &mut *(context as *mut Vec<String>) };
+    providers.push(unsafe { WideCStr::from_ptr_str(name) }.to_string_lossy());
+}
+
+type ProviderSink = extern "system" fn(name: *const libc::wchar_t, context: *mut libc::c_void);
+
+extern "system" {
+    /// Calls the sink with the name of every WFP provider except our own.
+    #[link_name = "WinFw_DescribeProviders"]
+    fn WinFw_DescribeProviders(sink: Option<ProviderSink>, context: *mut libc::c_void) -> bool;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_vpn_provider() {
+        assert!(is_vpn_provider("NordVPN WFP Provider"));
+        assert!(is_vpn_provider("Cisco AnyConnect Secure Mobility Client"));
+        assert!(!is_vpn_provider("Microsoft Corporation"));
+    }
+}
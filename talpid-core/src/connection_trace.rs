@@ -0,0 +1,296 @@
+//! A record of the phases of the most recent connection attempts, used to find out why connecting
+//! is slow. Each attempt is given an id, which is also included in the log message that is written
+//! when a phase ends, so that the log can be matched against the recorded attempts.
+use chrono::{DateTime, Utc};
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Maximum number of attempts that are kept in the trace.
+const TRACE_CAPACITY: usize = 10;
+
+/// Maximum number of spans that are kept for a single attempt. Phases such as applying the
+/// firewall policy may be repeated many times if a tunnel keeps failing to come up.
+const MAX_SPANS_PER_ATTEMPT: usize = 64;
+
+/// The outcome of an attempt that ended with the tunnel connected.
+pub const CONNECTED_OUTCOME: &str = "connected";
+
+/// A phase of a connection attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Selecting the relay and bridge to connect to.
+    RelaySelection,
+    /// Generating the tunnel parameters for the selected relay.
+    ParameterGeneration,
+    /// Creating the tunnel device and starting the tunnel.
+    TunnelDevice,
+    /// Setting up the routes of the tunnel. Only recorded for WireGuard tunnels.
+    Routes,
+    /// Waiting for the tunnel to come up after it was started. This includes setting up the
+    /// routes and the handshake with the relay.
+    Handshake,
+    /// Negotiating a quantum-resistant preshared key with the relay, and waiting for the tunnel
+    /// to come up with it. Only recorded for WireGuard tunnels.
+    PskNegotiation,
+    /// Applying the DNS configuration.
+    Dns,
+    /// Applying a firewall policy.
+    Firewall,
+    /// Waiting before starting the tunnel again after a failed attempt.
+    RetryDelay,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let phase = match self {
+            Phase::RelaySelection => "relay selection",
+            Phase::ParameterGeneration => "parameter generation",
+            Phase::TunnelDevice => "tunnel device",
+            Phase::Routes => "routes",
+            Phase::Handshake => "handshake",
+            Phase::PskNegotiation => "PSK negotiation",
+            Phase::Dns => "DNS",
+            Phase::Firewall => "firewall",
+            Phase::RetryDelay => "retry delay",
+        };
+        f.write_str(phase)
+    }
+}
+
+/// A phase of a connection attempt that has ended.
+#[derive(Debug, Clone)]
+pub struct Span {
+    /// The phase.
+    pub phase: Phase,
+    /// The time from the start of the attempt until the phase started.
+    pub offset: Duration,
+    /// How long the phase took.
+    pub duration: Duration,
+    /// A description of the error that ended the phase, if it failed.
+    pub error: Option<String>,
+}
+
+/// A single connection attempt.
+#[derive(Debug, Clone)]
+pub struct Attempt {
+    /// The id of the attempt. Ids are increasing and unique for the lifetime of the process.
+    pub id: u64,
+    /// When the attempt started.
+    pub timestamp: DateTime<Utc>,
+    /// The phases of the attempt that have ended, in the order they ended.
+    pub spans: Vec<Span>,
+    /// How the attempt ended, or `None` if it has not ended.
+    pub outcome: Option<String>,
+    /// How long the attempt took, or `None` if it has not ended.
+    pub duration: Option<Duration>,
+    started: Instant,
+}
+
+/// A ring buffer of the most recent connection attempts.
+#[derive(Clone, Default)]
+pub struct ConnectionTrace {
+    inner: Arc<Mutex<TraceInner>>,
+}
+
+#[derive(Default)]
+struct TraceInner {
+    next_id: u64,
+    attempts: VecDeque<Attempt>,
+}
+
+impl TraceInner {
+    fn current_attempt(&mut self) -> Option<&mut Attempt> {
+        self.attempts
+            .back_mut()
+            .filter(|attempt| attempt.outcome.is_none())
+    }
+}
+
+impl ConnectionTrace {
+    /// Creates an empty trace.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts a new attempt and returns its id. An attempt that has not ended yet is ended as
+    /// superseded.
+    pub fn start_attempt(&self) -> u64 {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(attempt) = inner.current_attempt() {
+            end_attempt(attempt, "superseded by a new attempt");
+        }
+
+        let id = inner.next_id;
+        inner.next_id += 1;
+        if inner.attempts.len() >= TRACE_CAPACITY {
+            inner.attempts.pop_front();
+        }
+        inner.attempts.push_back(Attempt {
+            id,
+            timestamp: Utc::now(),
+            spans: vec![],
+            outcome: None,
+            duration: None,
+            started: Instant::now(),
+        });
+        log::debug!("Started connection attempt {}", id);
+        id
+    }
+
+    /// Ends the current attempt, if there is one.
+    pub fn end_attempt(&self, outcome: impl Into<String>) {
+        if let Some(attempt) = self.inner.lock().unwrap().current_attempt() {
+            end_attempt(attempt, outcome);
+        }
+    }
+
+    /// Enters a phase of the current attempt. The phase ends when the returned guard is dropped.
+    /// Nothing is recorded if there is no current attempt.
+    pub fn enter(&self, phase: Phase) -> SpanGuard {
+        let attempt_id = self
+            .inner
+            .lock()
+            .unwrap()
+            .current_attempt()
+            .map(|attempt| attempt.id);
+        SpanGuard {
+            trace: self.clone(),
+            attempt_id,
+            phase,
+            started: Instant::now(),
+            error: None,
+        }
+    }
+
+    /// Returns the recorded attempts, oldest first.
+    pub fn attempts(&self) -> Vec<Attempt> {
+        self.inner
+            .lock()
+            .unwrap()
+            .attempts
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    fn record(&self, attempt_id: u64, phase: Phase, started: Instant, error: Option<String>) {
+        let duration = started.elapsed();
+        log::debug!(
+            "Connection attempt {}: {} took {} ms{}",
+            attempt_id,
+            phase,
+            duration.as_millis(),
+            if error.is_some() { " and failed" } else { "" }
+        );
+
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(attempt) = inner
+            .attempts
+            .iter_mut()
+            .find(|attempt| attempt.id == attempt_id)
+        {
+            if attempt.spans.len() < MAX_SPANS_PER_ATTEMPT {
+                attempt.spans.push(Span {
+                    phase,
+                    offset: started.saturating_duration_since(attempt.started),
+                    duration,
+                    error,
+                });
+            }
+        }
+    }
+}
+
+fn end_attempt(attempt: &mut Attempt, outcome: impl Into<String>) {
+    let outcome = outcome.into();
+    let duration = attempt.started.elapsed();
+    log::debug!(
+        "Connection attempt {} ended after {} ms: {}",
+        attempt.id,
+        duration.as_millis(),
+        outcome
+    );
+    attempt.outcome = Some(outcome);
+    attempt.duration = Some(duration);
+}
+
+/// A phase of a connection attempt that has not ended yet. The phase is recorded when the guard is
+/// dropped.
+pub struct SpanGuard {
+    trace: ConnectionTrace,
+    attempt_id: Option<u64>,
+    phase: Phase,
+    started: Instant,
+    error: Option<String>,
+}
+
+impl SpanGuard {
+    /// Marks the phase as failed.
+    pub fn set_error(&mut self, error: impl fmt::Display) {
+        self.error = Some(error.to_string());
+    }
+}
+
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        if let Some(attempt_id) = self.attempt_id {
+            self.trace
+                .record(attempt_id, self.phase, self.started, self.error.take());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_spans_of_current_attempt() {
+        let trace = ConnectionTrace::new();
+        drop(trace.enter(Phase::RelaySelection));
+        assert!(trace.attempts().is_empty());
+
+        let first = trace.start_attempt();
+        let stale_span = trace.enter(Phase::Handshake);
+        drop(trace.enter(Phase::RelaySelection));
+
+        let second = trace.start_attempt();
+        let mut span = trace.enter(Phase::Firewall);
+        span.set_error("failed");
+        drop(span);
+        // Spans are recorded in the attempt they were entered in, even if it has ended since.
+        drop(stale_span);
+        trace.end_attempt(CONNECTED_OUTCOME);
+
+        let attempts = trace.attempts();
+        assert_eq!(attempts.len(), 2);
+        assert_eq!(attempts[0].id, first);
+        assert_eq!(
+            attempts[0].outcome.as_deref(),
+            Some("superseded by a new attempt")
+        );
+        let phases: Vec<_> = attempts[0].spans.iter().map(|span| span.phase).collect();
+        assert_eq!(phases, vec![Phase::RelaySelection, Phase::Handshake]);
+
+        assert_eq!(attempts[1].id, second);
+        assert_eq!(attempts[1].outcome.as_deref(), Some("connected"));
+        assert_eq!(attempts[1].spans.len(), 1);
+        assert_eq!(attempts[1].spans[0].error.as_deref(), Some("failed"));
+    }
+
+    #[test]
+    fn keeps_most_recent_attempts() {
+        let trace = ConnectionTrace::new();
+        for _ in 0..TRACE_CAPACITY + 2 {
+            trace.start_attempt();
+        }
+        let attempts = trace.attempts();
+        assert_eq!(attempts.len(), TRACE_CAPACITY);
+        assert_eq!(attempts[0].id, 2);
+        assert!(attempts.last().unwrap().outcome.is_none());
+    }
+}
@@ -118,11 +118,15 @@ impl DnsMonitorHolder {
             .map(DnsMonitorHolder::SystemdResolved)
             .or_else(|err| {
                 match err {
+                    // Expected on systems that don't run systemd, or don't have D-Bus at all
                     systemd_resolved::Error::SystemdResolvedError(
                         systemd_resolved::SystemdDbusError::NoSystemdResolved(_),
+                    )
+                    | systemd_resolved::Error::SystemdResolvedError(
+                        systemd_resolved::SystemdDbusError::ConnectDBus(_),
                     ) => (),
                     other_error => {
-                        log::debug!("NetworkManager is not being used because {}", other_error)
+                        log::debug!("systemd-resolved is not being used because {}", other_error)
                     }
                 }
                 NetworkManager::new().map(DnsMonitorHolder::NetworkManager)
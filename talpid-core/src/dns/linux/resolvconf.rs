@@ -34,9 +34,15 @@ pub enum Error {
     ResolvconfNotInUseError,
 }
 
+/// Directories that resolvconf implementations generate `/etc/resolv.conf` in, when it is a
+/// symlink.
+const RESOLVCONF_RUNTIME_DIRS: [&str; 2] = ["/run/resolvconf", "/var/run/resolvconf"];
+
 pub struct Resolvconf {
     record_names: HashSet<String>,
     resolvconf: PathBuf,
+    /// openresolv is used instead of Debian's resolvconf on, for example, Alpine and Void Linux.
+    is_openresolv: bool,
 }
 
 impl Resolvconf {
@@ -61,12 +67,30 @@ impl Resolvconf {
             return Err(Error::DnsmasqMisconfigurationError);
         }
 
+        let is_openresolv = Self::is_openresolv(&resolvconf_path);
+        if is_openresolv {
+            log::debug!("Detected openresolv");
+        }
+
         Ok(Resolvconf {
             record_names: HashSet::new(),
             resolvconf: resolvconf_path,
+            is_openresolv,
         })
     }
 
+    /// openresolv prints its name when asked for its version, unlike Debian's resolvconf which
+    /// does not support `--version`.
+    fn is_openresolv(resolvconf_path: &Path) -> bool {
+        duct::cmd!(resolvconf_path, "--version")
+            .stdout_capture()
+            .stderr_null()
+            .unchecked()
+            .run()
+            .map(|output| String::from_utf8_lossy(&output.stdout).contains("openresolv"))
+            .unwrap_or(false)
+    }
+
     fn resolvconf_is_resolved_symlink(resolvconf_path: &Path) -> bool {
         fs::read_link(resolvconf_path)
             .map(|resolvconf_target| {
@@ -85,7 +109,15 @@ impl Resolvconf {
             record_contents.push('\n');
         }
 
-        let output = duct::cmd!(&self.resolvconf, "-a", &record_name)
+        // openresolv merges the servers of all interfaces unless the record is exclusive
+        let mut args = vec![];
+        if self.is_openresolv {
+            args.push("-x");
+        }
+        args.push("-a");
+        args.push(record_name.as_str());
+
+        let output = duct::cmd(&self.resolvconf, args)
             .stdin_bytes(record_contents)
             .stderr_capture()
             .unchecked()
@@ -173,7 +205,7 @@ impl Resolvconf {
     }
 
     // Returns true if /etc/resolv.conf is symlinked to resolvconf's runtime directory
-    // (`/var/run/resolvconf`)
+    // (`/run/resolvconf`, or `/var/run/resolvconf` where `/var/run` is not a symlink to `/run`)
     fn check_if_resolvconf_is_symlinked_correctly() -> bool {
         match fs::canonicalize("/etc/resolv.conf") {
             Err(err) => {
@@ -182,7 +214,9 @@ impl Resolvconf {
                 }
                 false
             }
-            Ok(path) => path.starts_with("/var/run/resolvconf"),
+            Ok(path) => RESOLVCONF_RUNTIME_DIRS
+                .iter()
+                .any(|runtime_dir| path.starts_with(runtime_dir)),
         }
     }
 }
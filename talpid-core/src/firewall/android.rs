@@ -1,4 +1,5 @@
-use super::{FirewallArguments, FirewallPolicy, FirewallT};
+use super::{FirewallArguments, FirewallPolicy, FirewallRules, FirewallT};
+use std::io;
 
 /// Stub error type for Firewall errors on Android.
 #[derive(Debug, err_derive::Error)]
@@ -22,4 +23,17 @@ impl FirewallT for Firewall {
     fn reset_policy(&mut self) -> Result<(), Self::Error> {
         Ok(())
     }
+
+    fn describe_policy(&self) -> io::Result<FirewallRules> {
+        // Traffic is only ever routed into the tunnel by the VPN service
+        Ok(FirewallRules {
+            backend: "none".to_owned(),
+            policy: None,
+            rules: vec![],
+        })
+    }
+
+    fn remove_orphaned_objects(&mut self) -> Result<Vec<String>, Self::Error> {
+        Ok(vec![])
+    }
 }
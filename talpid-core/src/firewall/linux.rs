@@ -1,4 +1,4 @@
-use super::{FirewallArguments, FirewallPolicy, FirewallT};
+use super::{FirewallArguments, FirewallPolicy, FirewallRules, FirewallT};
 use crate::{split_tunnel, tunnel};
 use ipnetwork::IpNetwork;
 use lazy_static::lazy_static;
@@ -6,20 +6,38 @@ use libc;
 use nftnl::{
     self,
     expr::{self, IcmpCode, Payload, RejectionType, Verdict},
-    nft_expr, table, Batch, Chain, FinalizedBatch, ProtoFamily, Rule, Table,
+    nft_expr, nftnl_sys as sys, table, Batch, Chain, FinalizedBatch, ProtoFamily, Rule, Table,
 };
 use std::{
     env,
     ffi::{CStr, CString},
-    io,
+    fmt, io,
     net::{IpAddr, Ipv4Addr},
+    os::raw::c_char,
+    process::{Command, Stdio},
 };
-use talpid_types::net::{Endpoint, TransportProtocol};
+use talpid_types::{
+    net::{AllowedEndpoint, Endpoint, SystemServiceExemptions, TransportProtocol},
+    ErrorExt,
+};
+
+#[path = "linux_iptables.rs"]
+mod iptables;
 
 /// Priority for rules that tag split tunneling packets. Equals NF_IP_PRI_MANGLE.
 const MANGLE_CHAIN_PRIORITY: i32 = libc::NF_IP_PRI_MANGLE;
 const PREROUTING_CHAIN_PRIORITY: i32 = libc::NF_IP_PRI_CONNTRACK + 1;
 
+/// Offset of the flags byte in the TCP header.
+const TCP_FLAGS_OFFSET: u32 = 13;
+const TCP_FLAG_SYN: u8 = 0x02;
+const TCP_FLAG_RST: u8 = 0x04;
+/// Kind of the maximum segment size option in the TCP header.
+const TCPOPT_MAXSEG: u8 = 2;
+/// Values from `linux/netfilter/nf_tables.h` that are missing from libc.
+const NFT_RT_TCPMSS: u32 = 3;
+const NFT_EXTHDR_OP_TCPOPT: u32 = 1;
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 /// Errors that can happen when interacting with Linux netfilter.
@@ -53,6 +71,14 @@ pub enum Error {
         _0
     )]
     LookupIfaceIndexError(String, #[error(source)] crate::linux::IfaceIndexLookupError),
+
+    /// Failed to apply or remove rules using the iptables backend.
+    #[error(display = "Failed to apply firewall rules using iptables")]
+    IptablesError(#[error(source)] iptables::Error),
+
+    /// Failed to list the nftables tables using the `nft` tool.
+    #[error(display = "Failed to list the nftables tables")]
+    ListTablesError(#[error(source)] io::Error),
 }
 
 lazy_static! {
@@ -78,6 +104,10 @@ lazy_static! {
         .map(|v| v != "0")
         .unwrap_or(false);
 
+    /// Forces a specific firewall backend instead of detecting one. Either `nftables` or
+    /// `iptables`.
+    static ref FORCED_BACKEND: Option<String> = env::var("TALPID_FIREWALL_BACKEND").ok();
+
     static ref DONT_SET_SRC_VALID_MARK: bool = env::var("TALPID_FIREWALL_DONT_SET_SRC_VALID_MARK")
         .map(|v| v != "0")
         .unwrap_or(false);
@@ -96,7 +126,29 @@ enum End {
 }
 
 /// The Linux implementation for the firewall and DNS.
-pub struct Firewall(());
+pub struct Firewall {
+    backend: Backend,
+    /// The rules applied by the iptables backend, if a policy is applied using it.
+    iptables_rules: Option<iptables::AppliedRules>,
+}
+
+/// The mechanism used to apply the firewall rules.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Backend {
+    /// Rules are sent directly to nftables over netlink.
+    Nftables,
+    /// Rules are applied with `iptables-restore`, for hosts where nftables is unusable.
+    Iptables,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Nftables => f.write_str("nftables"),
+            Backend::Iptables => f.write_str("iptables"),
+        }
+    }
+}
 
 struct FirewallTables {
     main: Table,
@@ -108,22 +160,53 @@ impl FirewallT for Firewall {
     type Error = Error;
 
     fn new(_args: FirewallArguments) -> Result<Self> {
-        Ok(Firewall(()))
+        let backend = Self::select_backend();
+        log::info!("Using {} firewall backend", backend);
+        if backend == Backend::Iptables && super::persist::is_installed() {
+            log::warn!("The persistent firewall policy is not enforced by the iptables backend");
+            // The policy may have been loaded at boot, and would block all traffic alongside the
+            // rules of this backend.
+            let _ = duct::cmd!("nft", "delete", "table", "inet", "mullvad")
+                .stdout_null()
+                .stderr_null()
+                .run();
+        }
+        Ok(Firewall {
+            backend,
+            iptables_rules: None,
+        })
     }
 
     fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<()> {
+        if self.backend == Backend::Iptables {
+            // Replace all rules if applying the policy fails
+            let applied_rules = self.iptables_rules.take();
+            self.iptables_rules = Some(
+                iptables::apply_policy(&policy, applied_rules.as_ref())
+                    .map_err(Error::IptablesError)?,
+            );
+            Self::apply_kernel_config(&policy);
+            return Ok(());
+        }
+
         let tables = FirewallTables {
             main: Table::new(&*TABLE_NAME, ProtoFamily::Inet),
             mangle_v4: Table::new(&*MANGLE_TABLE_NAME_V4, ProtoFamily::Ipv4),
             mangle_v6: Table::new(&*MANGLE_TABLE_NAME_V6, ProtoFamily::Ipv6),
         };
         let batch = PolicyBatch::new(&tables).finalize(&policy)?;
-        self.send_and_process(&batch)?;
+        Self::send_and_process(&batch)?;
         Self::apply_kernel_config(&policy);
-        self.verify_tables(&[&TABLE_NAME, &MANGLE_TABLE_NAME_V4, &MANGLE_TABLE_NAME_V6])
+        Self::verify_tables(&[&TABLE_NAME, &MANGLE_TABLE_NAME_V4, &MANGLE_TABLE_NAME_V6])
     }
 
     fn reset_policy(&mut self) -> Result<()> {
+        if self.backend == Backend::Iptables {
+            log::debug!("Removing chains from iptables");
+            self.iptables_rules = None;
+            return iptables::reset_policy().map_err(Error::IptablesError);
+        }
+
         let tables = [
             Table::new(&*TABLE_NAME, ProtoFamily::Inet),
             Table::new(&*MANGLE_TABLE_NAME_V4, ProtoFamily::Ipv4),
@@ -139,12 +222,185 @@ impl FirewallT for Firewall {
         }
         let batch = batch.finalize();
         log::debug!("Removing table and chain from netfilter");
-        self.send_and_process(&batch)?;
+        Self::send_and_process(&batch)?;
         Ok(())
     }
+
+    fn describe_policy(&self) -> io::Result<FirewallRules> {
+        let rules = match self.backend {
+            Backend::Nftables => Self::list_nftables_rules()?,
+            Backend::Iptables => iptables::list_rules()
+                .map_err(|error| io::Error::new(io::ErrorKind::Other, error.display_chain()))?,
+        };
+        Ok(FirewallRules {
+            backend: self.backend.to_string(),
+            policy: None,
+            rules,
+        })
+    }
+
+    fn remove_orphaned_objects(&mut self) -> Result<Vec<String>> {
+        // Our tables are all prefixed with "mullvad". Tables with other names were created by
+        // other versions, or while probing for nftables, and the tables of the nftables backend
+        // are not used at all by the iptables backend.
+        let tables_in_use = match self.backend {
+            Backend::Nftables => vec![
+                ("inet", TABLE_NAME.to_string_lossy().into_owned()),
+                ("ip", MANGLE_TABLE_NAME_V4.to_string_lossy().into_owned()),
+                ("ip6", MANGLE_TABLE_NAME_V6.to_string_lossy().into_owned()),
+            ],
+            Backend::Iptables => vec![],
+        };
+        let mut removed = vec![];
+        for (family, name) in Self::list_nftables_tables().map_err(Error::ListTablesError)? {
+            if !name.starts_with("mullvad")
+                || tables_in_use.iter().any(|(in_use_family, in_use_name)| {
+                    *in_use_family == family && *in_use_name == name
+                })
+            {
+                continue;
+            }
+            let proto_family = match family.as_str() {
+                "inet" => ProtoFamily::Inet,
+                "ip" => ProtoFamily::Ipv4,
+                "ip6" => ProtoFamily::Ipv6,
+                _ => {
+                    log::warn!("Not removing table {} of family {}", name, family);
+                    continue;
+                }
+            };
+            let table_name = match CString::new(name.as_str()) {
+                Ok(table_name) => table_name,
+                Err(_) => continue,
+            };
+            let mut batch = Batch::new();
+            batch.add(&Table::new(&table_name, proto_family), nftnl::MsgType::Del);
+            Self::send_and_process(&batch.finalize())?;
+
+            let description = format!("nftables table {} {}", family, name);
+            log::info!("Removed orphaned firewall object: {}", description);
+            removed.push(description);
+        }
+
+        // The chains of the iptables backend are left behind if the backend changes
+        if self.backend == Backend::Nftables && iptables::is_available() {
+            let chains = iptables::remove_chains().map_err(Error::IptablesError)?;
+            for chain in chains {
+                let description = format!("iptables chain {}", chain);
+                log::info!("Removed orphaned firewall object: {}", description);
+                removed.push(description);
+            }
+        }
+        Ok(removed)
+    }
+}
+
+impl Drop for Firewall {
+    fn drop(&mut self) {
+        if self.backend != Backend::Nftables {
+            return;
+        }
+        if let Err(error) = super::persist::enforce() {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to enforce the persistent firewall policy")
+            );
+        }
+    }
 }
 
 impl Firewall {
+    fn select_backend() -> Backend {
+        match FORCED_BACKEND.as_deref() {
+            Some("nftables") => return Backend::Nftables,
+            Some("iptables") => return Backend::Iptables,
+            Some(other) => log::warn!("Ignoring unknown firewall backend \"{}\"", other),
+            None => (),
+        }
+
+        match Self::probe_nftables() {
+            Ok(()) => Backend::Nftables,
+            Err(error) => {
+                log::warn!(
+                    "{}",
+                    error.display_chain_with_msg("nftables is not usable on this host")
+                );
+                if iptables::is_available() {
+                    Backend::Iptables
+                } else {
+                    log::error!("iptables is not available either. Falling back on nftables");
+                    Backend::Nftables
+                }
+            }
+        }
+    }
+
+    /// Checks that nftables can be used by adding and removing an empty table.
+    fn probe_nftables() -> Result<()> {
+        let table_name = CString::new("mullvadprobe").unwrap();
+        let table = Table::new(&table_name, ProtoFamily::Inet);
+        let mut batch = Batch::new();
+        batch.add(&table, nftnl::MsgType::Add);
+        batch.add(&table, nftnl::MsgType::Del);
+        Self::send_and_process(&batch.finalize())
+    }
+
+    /// Lists the tables of all families as `(family, name)` using the `nft` tool.
+    fn list_nftables_tables() -> io::Result<Vec<(String, String)>> {
+        let output = match Command::new("nft")
+            .args(&["list", "tables"])
+            .stdin(Stdio::null())
+            .output()
+        {
+            Ok(output) => output,
+            // There can be no tables to remove if nftables is not installed
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(error) => return Err(error),
+        };
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| {
+                let mut tokens = line.split_whitespace();
+                match (tokens.next(), tokens.next(), tokens.next()) {
+                    (Some("table"), Some(family), Some(name)) => {
+                        Some((family.to_owned(), name.to_owned()))
+                    }
+                    _ => None,
+                }
+            })
+            .collect())
+    }
+
+    /// Lists the rules in our tables using the `nft` tool, since nftnl cannot parse the
+    /// expressions of existing rules.
+    fn list_nftables_rules() -> io::Result<Vec<String>> {
+        let tables = [
+            ("inet", &*TABLE_NAME),
+            ("ip", &*MANGLE_TABLE_NAME_V4),
+            ("ip6", &*MANGLE_TABLE_NAME_V6),
+        ];
+        let mut rules = vec![];
+        for (family, table) in &tables {
+            let output = Command::new("nft")
+                .args(&["list", "table", family])
+                .arg(&*table.to_string_lossy())
+                .stdin(Stdio::null())
+                .output()?;
+            // The tables only exist while a policy is applied
+            if !output.status.success() {
+                continue;
+            }
+            rules.extend(
+                String::from_utf8_lossy(&output.stdout)
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(str::to_owned),
+            );
+        }
+        Ok(rules)
+    }
+
     fn apply_kernel_config(policy: &FirewallPolicy) {
         if *DONT_SET_SRC_VALID_MARK {
             log::debug!("Not setting src_valid_mark");
@@ -158,7 +414,7 @@ impl Firewall {
         }
     }
 
-    fn send_and_process(&self, batch: &FinalizedBatch) -> Result<()> {
+    fn send_and_process(batch: &FinalizedBatch) -> Result<()> {
         let socket = mnl::Socket::new(mnl::Bus::Netfilter).map_err(Error::NetlinkOpenError)?;
         socket.send_all(batch).map_err(Error::NetlinkSendError)?;
 
@@ -178,7 +434,7 @@ impl Firewall {
         Ok(())
     }
 
-    fn verify_tables(&self, expected_tables: &[&CStr]) -> Result<()> {
+    fn verify_tables(expected_tables: &[&CStr]) -> Result<()> {
         let socket = mnl::Socket::new(mnl::Bus::Netfilter).map_err(Error::NetlinkOpenError)?;
         let portid = socket.portid();
         let seq = 0;
@@ -524,6 +780,29 @@ impl<'a> PolicyBatch<'a> {
             add_verdict(&mut rule, &Verdict::Accept);
             self.batch.add(&rule, nftnl::MsgType::Add);
         }
+        // Outgoing Neighbor solicitation and advertisement (part of NDP). These replace ARP for
+        // IPv6, so the gateway cannot be reached over IPv6 without them
+        for chain in &[&self.out_chain, &self.forward_chain] {
+            for destination in &[*super::IPV6_LINK_LOCAL, *super::SOLICITED_NODE_MULTICAST] {
+                for icmpv6_type in &[135u8, 136u8] {
+                    let mut rule = Rule::new(chain);
+                    check_net(&mut rule, End::Dst, *destination);
+                    check_icmpv6(&mut rule, *icmpv6_type, 0);
+                    add_verdict(&mut rule, &Verdict::Accept);
+                    self.batch.add(&rule, nftnl::MsgType::Add);
+                }
+            }
+        }
+        // Incoming Neighbor solicitation and advertisement (part of NDP)
+        for chain in &[&self.in_chain, &self.forward_chain] {
+            for icmpv6_type in &[135u8, 136u8] {
+                let mut rule = Rule::new(chain);
+                check_net(&mut rule, End::Src, *super::IPV6_LINK_LOCAL);
+                check_icmpv6(&mut rule, *icmpv6_type, 0);
+                add_verdict(&mut rule, &Verdict::Accept);
+                self.batch.add(&rule, nftnl::MsgType::Add);
+            }
+        }
         // Incoming Redirect (part of NDP)
         for chain in &[&self.in_chain, &self.forward_chain] {
             let mut rule = Rule::new(chain);
@@ -547,12 +826,15 @@ impl<'a> PolicyBatch<'a> {
     }
 
     fn add_policy_specific_rules(&mut self, policy: &FirewallPolicy) -> Result<()> {
-        let allow_lan = match policy {
+        let (allow_lan, lan_networks, allowed_endpoints) = match policy {
             FirewallPolicy::Connecting {
                 peer_endpoint,
                 tunnel,
                 allow_lan,
+                allow_ipv6,
+                lan_networks,
                 allowed_endpoint,
+                allowed_endpoints,
             } => {
                 self.add_allow_tunnel_endpoint_rules(peer_endpoint);
                 self.add_allow_endpoint_rules(allowed_endpoint);
@@ -562,18 +844,21 @@ impl<'a> PolicyBatch<'a> {
                 self.add_drop_dns_rule();
 
                 if let Some(tunnel) = tunnel {
-                    self.add_allow_tunnel_rules(&tunnel.interface)?;
+                    self.add_allow_tunnel_rules(&tunnel.interface, *allow_ipv6)?;
                     if *allow_lan {
                         self.add_block_cve_2019_14899(tunnel);
                     }
                 }
-                *allow_lan
+                (*allow_lan, lan_networks, allowed_endpoints)
             }
             FirewallPolicy::Connected {
                 peer_endpoint,
                 tunnel,
                 allow_lan,
+                allow_ipv6,
+                lan_networks,
                 dns_servers,
+                allowed_endpoints,
             } => {
                 self.add_allow_tunnel_endpoint_rules(peer_endpoint);
                 self.add_allow_dns_rules(tunnel, &dns_servers, TransportProtocol::Udp)?;
@@ -581,27 +866,33 @@ impl<'a> PolicyBatch<'a> {
                 // Important to block DNS *before* we allow the tunnel and allow LAN. So DNS
                 // can't leak to the wrong IPs in the tunnel or on the LAN.
                 self.add_drop_dns_rule();
-                self.add_allow_tunnel_rules(&tunnel.interface)?;
+                self.add_allow_tunnel_rules(&tunnel.interface, *allow_ipv6)?;
                 if *allow_lan {
                     self.add_block_cve_2019_14899(tunnel);
                 }
-                *allow_lan
+                (*allow_lan, lan_networks, allowed_endpoints)
             }
             FirewallPolicy::Blocked {
                 allow_lan,
+                lan_networks,
                 allowed_endpoint,
+                exemptions,
+                allowed_endpoints,
             } => {
                 self.add_allow_endpoint_rules(allowed_endpoint);
+                self.add_system_service_rules(exemptions);
 
                 // Important to drop DNS before allowing LAN (to stop DNS leaking to the LAN)
                 self.add_drop_dns_rule();
-                *allow_lan
+                (*allow_lan, lan_networks, allowed_endpoints)
             }
         };
 
         if allow_lan {
-            self.add_allow_lan_rules();
+            self.add_allow_lan_rules(lan_networks);
         }
+        // Like LAN traffic, this has to come after the rule dropping DNS
+        self.add_user_allowed_endpoint_rules(allowed_endpoints);
 
         // Reject any remaining outgoing traffic
         for chain in &[&self.out_chain, &self.forward_chain] {
@@ -662,6 +953,62 @@ impl<'a> PolicyBatch<'a> {
         self.batch.add(&out_rule, nftnl::MsgType::Add);
     }
 
+    fn add_user_allowed_endpoint_rules(&mut self, allowed_endpoints: &[AllowedEndpoint]) {
+        for endpoint in allowed_endpoints {
+            for protocol in endpoint.rule_protocols() {
+                for chain in &[&self.out_chain, &self.forward_chain] {
+                    let mut out_rule = Rule::new(chain);
+                    check_net(&mut out_rule, End::Dst, endpoint.network);
+                    check_allowed_endpoint_port(&mut out_rule, End::Dst, endpoint, protocol);
+                    add_verdict(&mut out_rule, &Verdict::Accept);
+                    self.batch.add(&out_rule, nftnl::MsgType::Add);
+                }
+
+                let mut in_rule = Rule::new(&self.in_chain);
+                check_net(&mut in_rule, End::Src, endpoint.network);
+                check_allowed_endpoint_port(&mut in_rule, End::Src, endpoint, protocol);
+                add_verdict(&mut in_rule, &Verdict::Accept);
+                self.batch.add(&in_rule, nftnl::MsgType::Add);
+            }
+        }
+    }
+
+    fn add_system_service_rules(&mut self, exemptions: &SystemServiceExemptions) {
+        if exemptions.ntp {
+            use self::TransportProtocol::Udp;
+            let mut out_rule = Rule::new(&self.out_chain);
+            check_port(&mut out_rule, Udp, End::Dst, super::NTP_SERVER_PORT);
+            add_verdict(&mut out_rule, &Verdict::Accept);
+            self.batch.add(&out_rule, nftnl::MsgType::Add);
+
+            let mut in_rule = Rule::new(&self.in_chain);
+            check_port(&mut in_rule, Udp, End::Src, super::NTP_SERVER_PORT);
+            add_verdict(&mut in_rule, &Verdict::Accept);
+            self.batch.add(&in_rule, nftnl::MsgType::Add);
+        }
+        for host in &exemptions.captive_portal_hosts {
+            let endpoint = Endpoint::new(
+                *host,
+                super::CAPTIVE_PORTAL_HTTP_PORT,
+                TransportProtocol::Tcp,
+            );
+            self.add_allow_endpoint_rules(&endpoint);
+        }
+        if exemptions.portal_login {
+            for &(protocol, port) in super::PORTAL_LOGIN_PORTS {
+                let mut out_rule = Rule::new(&self.out_chain);
+                check_port(&mut out_rule, protocol, End::Dst, port);
+                add_verdict(&mut out_rule, &Verdict::Accept);
+                self.batch.add(&out_rule, nftnl::MsgType::Add);
+
+                let mut in_rule = Rule::new(&self.in_chain);
+                check_port(&mut in_rule, protocol, End::Src, port);
+                add_verdict(&mut in_rule, &Verdict::Accept);
+                self.batch.add(&in_rule, nftnl::MsgType::Add);
+            }
+        }
+    }
+
     fn add_allow_dns_rules(
         &mut self,
         tunnel: &tunnel::TunnelMetadata,
@@ -755,7 +1102,26 @@ impl<'a> PolicyBatch<'a> {
         }
     }
 
-    fn add_allow_tunnel_rules(&mut self, tunnel_interface: &str) -> Result<()> {
+    fn add_allow_tunnel_rules(&mut self, tunnel_interface: &str, allow_ipv6: bool) -> Result<()> {
+        if !allow_ipv6 {
+            // NDP and DHCPv6 are allowed by earlier rules, so this only blocks IPv6 that would
+            // otherwise be routed through the tunnel.
+            for (chain, direction) in &[
+                (&self.out_chain, Direction::Out),
+                (&self.forward_chain, Direction::Out),
+                (&self.in_chain, Direction::In),
+            ] {
+                let mut rule = Rule::new(chain);
+                check_iface(&mut rule, *direction, tunnel_interface)?;
+                rule.add_expr(&nft_expr!(meta nfproto));
+                rule.add_expr(&nft_expr!(cmp == libc::NFPROTO_IPV6 as u8));
+                add_verdict(&mut rule, &Verdict::Drop);
+                self.batch.add(&rule, nftnl::MsgType::Add);
+            }
+        }
+
+        self.add_clamp_mss_rules(tunnel_interface)?;
+
         self.batch.add(
             &allow_interface_rule(&self.out_chain, Direction::Out, tunnel_interface)?,
             nftnl::MsgType::Add,
@@ -781,6 +1147,25 @@ impl<'a> PolicyBatch<'a> {
         Ok(())
     }
 
+    /// Lowers the MSS of TCP connections that are opened through the tunnel to what fits in the
+    /// MTU of the tunnel. Hosts behind the device, such as virtual machines, can then connect to
+    /// sites that break path MTU discovery. The kernel never raises the MSS, so this does nothing
+    /// when the tunnel MTU is not the smallest one along the path.
+    fn add_clamp_mss_rules(&mut self, tunnel_interface: &str) -> Result<()> {
+        for chain in &[&self.out_chain, &self.forward_chain] {
+            let mut rule = Rule::new(chain);
+            check_iface(&mut rule, Direction::Out, tunnel_interface)?;
+            check_l4proto(&mut rule, TransportProtocol::Tcp);
+            rule.add_expr(&TcpFlags);
+            rule.add_expr(&nft_expr!(bitwise mask TCP_FLAG_SYN | TCP_FLAG_RST, xor 0u8));
+            rule.add_expr(&nft_expr!(cmp == TCP_FLAG_SYN));
+            rule.add_expr(&RouteTcpMss);
+            rule.add_expr(&SetTcpMss);
+            self.batch.add(&rule, nftnl::MsgType::Add);
+        }
+        Ok(())
+    }
+
     /// Adds rules for stopping [CVE-2019-14899](https://seclists.org/oss-sec/2019/q4/122).
     /// An attacker on the same local network as the VPN connected device could figure out
     /// the tunnel IP the device used if the device was set to not filter reverse path (rp_filter.)
@@ -795,11 +1180,11 @@ impl<'a> PolicyBatch<'a> {
         }
     }
 
-    fn add_allow_lan_rules(&mut self) {
+    fn add_allow_lan_rules(&mut self, lan_networks: &[IpNetwork]) {
         // Output and forward chains
         for chain in &[&self.out_chain, &self.forward_chain] {
             // LAN -> LAN
-            for net in &*super::ALLOWED_LAN_NETS {
+            for net in lan_networks {
                 let mut out_rule = Rule::new(chain);
                 check_net(&mut out_rule, End::Dst, *net);
                 add_verdict(&mut out_rule, &Verdict::Accept);
@@ -817,7 +1202,7 @@ impl<'a> PolicyBatch<'a> {
 
         // Input chain
         // LAN -> LAN
-        for net in &*super::ALLOWED_LAN_NETS {
+        for net in lan_networks {
             let mut in_rule = Rule::new(&self.in_chain);
             check_net(&mut in_rule, End::Src, *net);
             add_verdict(&mut in_rule, &Verdict::Accept);
@@ -916,6 +1301,20 @@ fn check_not_iface(rule: &mut Rule<'_>, direction: Direction, iface: &str) -> Re
     Ok(())
 }
 
+fn check_icmpv6(rule: &mut Rule<'_>, icmpv6_type: u8, code: u8) {
+    rule.add_expr(&nft_expr!(meta l4proto));
+    rule.add_expr(&nft_expr!(cmp == libc::IPPROTO_ICMPV6 as u8));
+
+    rule.add_expr(&Payload::Transport(
+        nftnl::expr::TransportHeaderField::Icmpv6(nftnl::expr::Icmpv6HeaderField::Type),
+    ));
+    rule.add_expr(&nft_expr!(cmp == icmpv6_type));
+    rule.add_expr(&Payload::Transport(
+        nftnl::expr::TransportHeaderField::Icmpv6(nftnl::expr::Icmpv6HeaderField::Code),
+    ));
+    rule.add_expr(&nft_expr!(cmp == code));
+}
+
 fn check_net(rule: &mut Rule<'_>, end: End, net: impl Into<IpNetwork>) {
     let net = net.into();
     // Must check network layer protocol before loading network layer payload
@@ -969,6 +1368,20 @@ fn check_port(rule: &mut Rule<'_>, protocol: TransportProtocol, end: End, port:
     rule.add_expr(&nft_expr!(cmp == port.to_be()));
 }
 
+/// Limits `rule` to the protocol and port of a user allowed endpoint, if it has any.
+fn check_allowed_endpoint_port(
+    rule: &mut Rule<'_>,
+    end: End,
+    endpoint: &AllowedEndpoint,
+    protocol: Option<TransportProtocol>,
+) {
+    match (protocol, endpoint.port) {
+        (Some(protocol), Some(port)) => check_port(rule, protocol, end, port),
+        (Some(protocol), None) => check_l4proto(rule, protocol),
+        (None, _) => (),
+    }
+}
+
 fn check_l3proto(rule: &mut Rule<'_>, ip: IpAddr) {
     rule.add_expr(&nft_expr!(meta nfproto));
     rule.add_expr(&nft_expr!(cmp == l3proto(ip)));
@@ -993,6 +1406,77 @@ fn l4proto(protocol: TransportProtocol) -> u8 {
     }
 }
 
+/// Loads the flags of the TCP header into register 1. nftnl can only load the TCP ports.
+struct TcpFlags;
+
+impl expr::Expression for TcpFlags {
+    fn to_expr(&self, _rule: &Rule<'_>) -> *mut sys::nftnl_expr {
+        let expr = alloc_expr(b"payload\0");
+        unsafe {
+            sys::nftnl_expr_set_u32(
+                expr,
+                sys::NFTNL_EXPR_PAYLOAD_BASE as u16,
+                libc::NFT_PAYLOAD_TRANSPORT_HEADER as u32,
+            );
+            sys::nftnl_expr_set_u32(
+                expr,
+                sys::NFTNL_EXPR_PAYLOAD_OFFSET as u16,
+                TCP_FLAGS_OFFSET,
+            );
+            sys::nftnl_expr_set_u32(expr, sys::NFTNL_EXPR_PAYLOAD_LEN as u16, 1);
+            sys::nftnl_expr_set_u32(
+                expr,
+                sys::NFTNL_EXPR_PAYLOAD_DREG as u16,
+                libc::NFT_REG_1 as u32,
+            );
+        }
+        expr
+    }
+}
+
+/// Loads the largest MSS that fits in the MTU of the route of the packet into register 1.
+struct RouteTcpMss;
+
+impl expr::Expression for RouteTcpMss {
+    fn to_expr(&self, _rule: &Rule<'_>) -> *mut sys::nftnl_expr {
+        let expr = alloc_expr(b"rt\0");
+        unsafe {
+            sys::nftnl_expr_set_u32(expr, sys::NFTNL_EXPR_RT_KEY as u16, NFT_RT_TCPMSS);
+            sys::nftnl_expr_set_u32(expr, sys::NFTNL_EXPR_RT_DREG as u16, libc::NFT_REG_1 as u32);
+        }
+        expr
+    }
+}
+
+/// Writes register 1 to the MSS option of the TCP header, if that lowers the MSS.
+struct SetTcpMss;
+
+impl expr::Expression for SetTcpMss {
+    fn to_expr(&self, _rule: &Rule<'_>) -> *mut sys::nftnl_expr {
+        let expr = alloc_expr(b"exthdr\0");
+        unsafe {
+            sys::nftnl_expr_set_u32(expr, sys::NFTNL_EXPR_EXTHDR_OP as u16, NFT_EXTHDR_OP_TCPOPT);
+            sys::nftnl_expr_set_u8(expr, sys::NFTNL_EXPR_EXTHDR_TYPE as u16, TCPOPT_MAXSEG);
+            sys::nftnl_expr_set_u32(expr, sys::NFTNL_EXPR_EXTHDR_OFFSET as u16, 2);
+            sys::nftnl_expr_set_u32(expr, sys::NFTNL_EXPR_EXTHDR_LEN as u16, 2);
+            sys::nftnl_expr_set_u32(
+                expr,
+                sys::NFTNL_EXPR_EXTHDR_SREG as u16,
+                libc::NFT_REG_1 as u32,
+            );
+        }
+        expr
+    }
+}
+
+fn alloc_expr(name: &'static [u8]) -> *mut sys::nftnl_expr {
+    let expr = unsafe { sys::nftnl_expr_alloc(name.as_ptr() as *const c_char) };
+    if expr.is_null() {
+        panic!("Failed to allocate an nftnl expression");
+    }
+    expr
+}
+
 fn add_verdict(rule: &mut Rule<'_>, verdict: &expr::Verdict) {
     if *ADD_COUNTERS {
         rule.add_expr(&nft_expr!(counter));
@@ -0,0 +1,1145 @@
+//! Fallback firewall backend for systems where nftables cannot be used, such as older kernels
+//! without `nf_tables`. The rules are applied with `iptables-restore` and `ip6tables-restore`,
+//! which works with both the legacy and the nftables based (`iptables-nft`) variants of iptables.
+//!
+//! The rules live in chains of our own, which are jumped to from the beginning of the built-in
+//! chains. The first policy flushes and fills these chains atomically per table. Later policies
+//! only delete and insert the rules that differ from the applied ones, so that the rules both
+//! policies share are never missing.
+
+use super::{
+    super::{
+        ALLOWED_LAN_MULTICAST_NETS, CAPTIVE_PORTAL_HTTP_PORT, DHCPV4_CLIENT_PORT,
+        DHCPV4_SERVER_PORT, DHCPV6_CLIENT_PORT, DHCPV6_SERVER_ADDRS, DHCPV6_SERVER_PORT,
+        IPV6_LINK_LOCAL, NTP_SERVER_PORT, PORTAL_LOGIN_PORTS, ROUTER_SOLICITATION_OUT_DST_ADDR,
+    },
+    is_local_dns_address, FirewallPolicy,
+};
+use crate::{split_tunnel, tunnel};
+use ipnetwork::IpNetwork;
+use std::{
+    cmp,
+    fmt::Write as _,
+    io::{self, Write},
+    net::{IpAddr, Ipv4Addr},
+    process::{Command, Stdio},
+};
+use talpid_types::{
+    net::{AllowedEndpoint, Endpoint, SystemServiceExemptions, TransportProtocol},
+    ErrorExt,
+};
+
+/// Errors that can happen when applying rules with iptables.
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Unable to start an iptables command.
+    #[error(display = "Failed to run {}", _0)]
+    RunCommand(&'static str, #[error(source)] io::Error),
+
+    /// An iptables command returned an error.
+    #[error(display = "{} failed: {}", _0, _1)]
+    CommandFailed(&'static str, String),
+}
+
+const INPUT_CHAIN: &str = "mullvad-input";
+const OUTPUT_CHAIN: &str = "mullvad-output";
+const FORWARD_CHAIN: &str = "mullvad-forward";
+const MANGLE_CHAIN: &str = "mullvad-mangle";
+const PREROUTING_CHAIN: &str = "mullvad-prerouting";
+const NAT_CHAIN: &str = "mullvad-nat";
+
+/// The chains that hold our rules, as `(table, built-in chain, chain)`.
+const CHAINS: [(&str, &str, &str); 6] = [
+    ("filter", "INPUT", INPUT_CHAIN),
+    ("filter", "OUTPUT", OUTPUT_CHAIN),
+    ("filter", "FORWARD", FORWARD_CHAIN),
+    ("mangle", "OUTPUT", MANGLE_CHAIN),
+    ("mangle", "PREROUTING", PREROUTING_CHAIN),
+    ("nat", "POSTROUTING", NAT_CHAIN),
+];
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Family {
+    V4,
+    V6,
+}
+
+impl Family {
+    fn iptables(self) -> &'static str {
+        match self {
+            Family::V4 => "iptables",
+            Family::V6 => "ip6tables",
+        }
+    }
+
+    fn iptables_restore(self) -> &'static str {
+        match self {
+            Family::V4 => "iptables-restore",
+            Family::V6 => "ip6tables-restore",
+        }
+    }
+
+    fn contains(self, ip: IpAddr) -> bool {
+        match self {
+            Family::V4 => ip.is_ipv4(),
+            Family::V6 => ip.is_ipv6(),
+        }
+    }
+
+    fn reject_port_unreachable(self) -> &'static str {
+        match self {
+            Family::V4 => "-j REJECT --reject-with icmp-port-unreachable",
+            Family::V6 => "-j REJECT --reject-with icmp6-port-unreachable",
+        }
+    }
+}
+
+const FAMILIES: [Family; 2] = [Family::V4, Family::V6];
+
+/// Returns whether the iptables tools that this backend needs are installed and working.
+pub fn is_available() -> bool {
+    FAMILIES.iter().all(|family| {
+        [family.iptables(), family.iptables_restore()]
+            .iter()
+            .all(|command| {
+                Command::new(command)
+                    .arg("--version")
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .status()
+                    .map(|status| status.success())
+                    .unwrap_or(false)
+            })
+    })
+}
+
+/// The rules of each address family, as applied by [`apply_policy`].
+pub struct AppliedRules(Vec<Ruleset>);
+
+/// Replaces the rules in our chains with the ones needed for `policy`. If `applied` is given, only
+/// the rules that differ from it are changed.
+pub fn apply_policy(
+    policy: &FirewallPolicy,
+    applied: Option<&AppliedRules>,
+) -> Result<AppliedRules, Error> {
+    let mut rulesets = Vec::with_capacity(FAMILIES.len());
+    for (index, family) in FAMILIES.iter().enumerate() {
+        let ruleset = Ruleset::new(*family, policy);
+        match applied.map(|applied| &applied.0[index]) {
+            Some(applied_ruleset) => {
+                if let Some(input) = ruleset.to_delta_restore_input(applied_ruleset) {
+                    if let Err(error) = restore(*family, &input) {
+                        log::warn!(
+                            "{}",
+                            error.display_chain_with_msg(
+                                "Failed to change the differing rules. Replacing all rules"
+                            )
+                        );
+                        restore(*family, &ruleset.to_restore_input())?;
+                    }
+                }
+            }
+            None => restore(*family, &ruleset.to_restore_input())?,
+        }
+        ensure_jumps(*family)?;
+        rulesets.push(ruleset);
+    }
+    Ok(AppliedRules(rulesets))
+}
+
+/// Removes our chains, and the jumps to them.
+pub fn reset_policy() -> Result<(), Error> {
+    remove_chains().map(|_| ())
+}
+
+/// Removes our chains, and the jumps to them. Returns the chains that existed, as
+/// `<command> -t <table> <chain>`.
+pub fn remove_chains() -> Result<Vec<String>, Error> {
+    let mut removed = vec![];
+    for family in &FAMILIES {
+        for &(table, builtin_chain, chain) in &CHAINS {
+            while iptables(*family, &["-t", table, "-D", builtin_chain, "-j", chain]).is_ok() {}
+            if iptables(*family, &["-t", table, "-n", "-L", chain]).is_ok() {
+                iptables(*family, &["-t", table, "-F", chain])?;
+                iptables(*family, &["-t", table, "-X", chain])?;
+                removed.push(format!("{} -t {} {}", family.iptables(), table, chain));
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// Returns the rules in our chains, in the notation of `iptables -S`.
+pub fn list_rules() -> Result<Vec<String>, Error> {
+    let mut rules = vec![];
+    for family in &FAMILIES {
+        for &(table, _, chain) in &CHAINS {
+            // The chains only exist while a policy is applied
+            let output = match iptables(*family, &["-t", table, "-S", chain]) {
+                Ok(output) => output,
+                Err(Error::CommandFailed(..)) => continue,
+                Err(error) => return Err(error),
+            };
+            rules.extend(
+                output
+                    .lines()
+                    .filter(|line| line.starts_with("-A "))
+                    .map(|line| format!("{} -t {} {}", family.iptables(), table, line)),
+            );
+        }
+    }
+    Ok(rules)
+}
+
+fn ensure_jumps(family: Family) -> Result<(), Error> {
+    for &(table, builtin_chain, chain) in &CHAINS {
+        if iptables(family, &["-t", table, "-C", builtin_chain, "-j", chain]).is_err() {
+            iptables(
+                family,
+                &["-t", table, "-I", builtin_chain, "1", "-j", chain],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Runs an iptables command and returns what it printed.
+fn iptables(family: Family, args: &[&str]) -> Result<String, Error> {
+    let output = Command::new(family.iptables())
+        .arg("-w")
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|error| Error::RunCommand(family.iptables(), error))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(Error::CommandFailed(
+            family.iptables(),
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ))
+    }
+}
+
+fn restore(family: Family, input: &str) -> Result<(), Error> {
+    let command = family.iptables_restore();
+    let mut child = Command::new(command)
+        .arg("--noflush")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|error| Error::RunCommand(command, error))?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(input.as_bytes())
+        .map_err(|error| Error::RunCommand(command, error))?;
+    let output = child
+        .wait_with_output()
+        .map_err(|error| Error::RunCommand(command, error))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(Error::CommandFailed(
+            command,
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ))
+    }
+}
+
+/// The rules of one address family, in the same order as the nftables backend adds them.
+struct Ruleset {
+    family: Family,
+    input: Vec<String>,
+    output: Vec<String>,
+    forward: Vec<String>,
+    mangle: Vec<String>,
+    prerouting: Vec<String>,
+    nat: Vec<String>,
+}
+
+impl Ruleset {
+    fn new(family: Family, policy: &FirewallPolicy) -> Self {
+        let mut ruleset = Ruleset {
+            family,
+            input: vec![],
+            output: vec![],
+            forward: vec![],
+            mangle: vec![],
+            prerouting: vec![],
+            nat: vec![],
+        };
+        ruleset.add_loopback_rules();
+        ruleset.add_split_tunneling_rules(policy);
+        ruleset.add_dhcp_client_rules();
+        ruleset.add_policy_specific_rules(policy);
+        ruleset
+    }
+
+    /// Returns the rules of every chain, as `(table, chain, rules)`.
+    fn chains(&self) -> [(&'static str, &'static str, &Vec<String>); 6] {
+        [
+            ("filter", INPUT_CHAIN, &self.input),
+            ("filter", OUTPUT_CHAIN, &self.output),
+            ("filter", FORWARD_CHAIN, &self.forward),
+            ("mangle", MANGLE_CHAIN, &self.mangle),
+            ("mangle", PREROUTING_CHAIN, &self.prerouting),
+            ("nat", NAT_CHAIN, &self.nat),
+        ]
+    }
+
+    /// Returns the input that turns the rules in `applied` into these rules, or `None` if they are
+    /// the same. The chains are not declared, since that would flush them.
+    fn to_delta_restore_input(&self, applied: &Ruleset) -> Option<String> {
+        let mut input = String::new();
+        for table in &["filter", "mangle", "nat"] {
+            let commands: Vec<String> = self
+                .chains()
+                .iter()
+                .zip(applied.chains().iter())
+                .filter(|((chain_table, ..), _)| chain_table == table)
+                .flat_map(|((_, chain, rules), (_, _, applied_rules))| {
+                    chain_delta(chain, applied_rules, rules)
+                })
+                .collect();
+            if commands.is_empty() {
+                continue;
+            }
+            let _ = writeln!(input, "*{}", table);
+            for command in commands {
+                let _ = writeln!(input, "{}", command);
+            }
+            let _ = writeln!(input, "COMMIT");
+        }
+        if input.is_empty() {
+            None
+        } else {
+            Some(input)
+        }
+    }
+
+    fn to_restore_input(&self) -> String {
+        let mut input = String::new();
+        let tables: [(&str, &[(&str, &Vec<String>)]); 3] = [
+            (
+                "filter",
+                &[
+                    (INPUT_CHAIN, &self.input),
+                    (OUTPUT_CHAIN, &self.output),
+                    (FORWARD_CHAIN, &self.forward),
+                ],
+            ),
+            (
+                "mangle",
+                &[
+                    (MANGLE_CHAIN, &self.mangle),
+                    (PREROUTING_CHAIN, &self.prerouting),
+                ],
+            ),
+            ("nat", &[(NAT_CHAIN, &self.nat)]),
+        ];
+        for (table, chains) in &tables {
+            let _ = writeln!(input, "*{}", table);
+            // With `--noflush`, declaring an existing chain flushes it
+            for (chain, _) in chains.iter() {
+                let _ = writeln!(input, ":{} - [0:0]", chain);
+            }
+            for (chain, rules) in chains.iter() {
+                for rule in rules.iter() {
+                    let _ = writeln!(input, "-A {} {}", chain, rule);
+                }
+            }
+            let _ = writeln!(input, "COMMIT");
+        }
+        input
+    }
+
+    fn add_loopback_rules(&mut self) {
+        self.output.push("-o lo -j ACCEPT".to_owned());
+        self.input.push("-i lo -j ACCEPT".to_owned());
+    }
+
+    fn add_split_tunneling_rules(&mut self, policy: &FirewallPolicy) {
+        let family = self.family;
+        let cgroup = format!("-m cgroup --cgroup {:#x}", split_tunnel::NET_CLS_CLASSID);
+        let connmark = format!("-m connmark --mark {:#x}", split_tunnel::MARK);
+
+        if let FirewallPolicy::Connected {
+            tunnel,
+            dns_servers,
+            ..
+        } = policy
+        {
+            // Send select DNS requests in the tunnel
+            for server in dns_servers
+                .iter()
+                .filter(|server| family.contains(**server))
+                .filter(|server| !is_local_dns_address(&tunnel, server))
+            {
+                for protocol in &[TransportProtocol::Udp, TransportProtocol::Tcp] {
+                    self.mangle.push(format!(
+                        "-o {} -d {} {} -j ACCEPT",
+                        tunnel.interface,
+                        server,
+                        port(*protocol, "--dport", 53)
+                    ));
+                }
+            }
+        }
+
+        self.mangle.push(format!(
+            "{} -j CONNMARK --set-mark {:#x}",
+            cgroup,
+            split_tunnel::MARK
+        ));
+        self.mangle.push(format!(
+            "{} -j MARK --set-mark {:#x}",
+            cgroup,
+            crate::linux::TUNNEL_FW_MARK
+        ));
+
+        // Block remaining marked outgoing in-tunnel traffic. The nat table cannot drop packets,
+        // so this is done before marked traffic is accepted.
+        if let FirewallPolicy::Connected { tunnel, .. } = policy {
+            self.output
+                .push(format!("-o {} {} -j DROP", tunnel.interface, connmark));
+        }
+        self.input.push(format!("{} -j ACCEPT", connmark));
+        self.output.push(format!("{} -j ACCEPT", connmark));
+
+        // Replace source IP address in rerouted packets.
+        // Don't masquerade packets on the loopback device.
+        self.nat.push(format!("! -o lo {} -j MASQUERADE", connmark));
+
+        // Route incoming traffic correctly to prevent strict rpf from rejecting packets
+        // for excluded processes
+        if let FirewallPolicy::Connected { tunnel, .. } = policy {
+            self.prerouting.push(format!(
+                "! -i {} {} -j MARK --set-mark {:#x}",
+                tunnel.interface,
+                connmark,
+                crate::linux::TUNNEL_FW_MARK
+            ));
+        }
+    }
+
+    fn add_dhcp_client_rules(&mut self) {
+        use TransportProtocol::Udp;
+        match self.family {
+            Family::V4 => {
+                // Outgoing DHCPv4 request
+                let out_v4 = format!(
+                    "{} -d {} --dport {} -j ACCEPT",
+                    port(Udp, "--sport", DHCPV4_CLIENT_PORT),
+                    Ipv4Addr::BROADCAST,
+                    DHCPV4_SERVER_PORT
+                );
+                self.output.push(out_v4.clone());
+                self.forward.push(out_v4);
+                // Incoming DHCPv4 response
+                let in_v4 = format!(
+                    "{} --dport {} -j ACCEPT",
+                    port(Udp, "--sport", DHCPV4_SERVER_PORT),
+                    DHCPV4_CLIENT_PORT
+                );
+                self.input.push(in_v4.clone());
+                self.forward.push(in_v4);
+            }
+            Family::V6 => {
+                for dhcpv6_server in &*DHCPV6_SERVER_ADDRS {
+                    let out_v6 = format!(
+                        "-s {} {} -d {} --dport {} -j ACCEPT",
+                        *IPV6_LINK_LOCAL,
+                        port(Udp, "--sport", DHCPV6_CLIENT_PORT),
+                        dhcpv6_server,
+                        DHCPV6_SERVER_PORT
+                    );
+                    self.output.push(out_v6.clone());
+                    self.forward.push(out_v6);
+                }
+                let in_v6 = format!(
+                    "-s {} {} -d {} --dport {} -j ACCEPT",
+                    *IPV6_LINK_LOCAL,
+                    port(Udp, "--sport", DHCPV6_SERVER_PORT),
+                    *IPV6_LINK_LOCAL,
+                    DHCPV6_CLIENT_PORT
+                );
+                self.input.push(in_v6.clone());
+                self.forward.push(in_v6);
+
+                // Outgoing Router solicitation (part of NDP)
+                let solicitation = format!(
+                    "-d {} -p ipv6-icmp --icmpv6-type 133/0 -j ACCEPT",
+                    *ROUTER_SOLICITATION_OUT_DST_ADDR
+                );
+                self.output.push(solicitation.clone());
+                self.forward.push(solicitation);
+                // Incoming Router advertisement and Redirect (part of NDP)
+                for icmp_type in &[134, 137] {
+                    let rule = format!(
+                        "-s {} -p ipv6-icmp --icmpv6-type {}/0 -j ACCEPT",
+                        *IPV6_LINK_LOCAL, icmp_type
+                    );
+                    self.input.push(rule.clone());
+                    self.forward.push(rule);
+                }
+            }
+        }
+    }
+
+    fn add_policy_specific_rules(&mut self, policy: &FirewallPolicy) {
+        let (allow_lan, lan_networks, allowed_endpoints) = match policy {
+            FirewallPolicy::Connecting {
+                peer_endpoint,
+                tunnel,
+                allow_lan,
+                allow_ipv6,
+                lan_networks,
+                allowed_endpoint,
+                allowed_endpoints,
+            } => {
+                self.add_allow_tunnel_endpoint_rules(peer_endpoint);
+                self.add_allow_endpoint_rules(allowed_endpoint);
+
+                // Important to block DNS after allow relay rule (so the relay can operate
+                // over port 53) but before allow LAN (so DNS does not leak to the LAN)
+                self.add_drop_dns_rule();
+
+                if let Some(tunnel) = tunnel {
+                    self.add_allow_tunnel_rules(&tunnel.interface, *allow_ipv6);
+                    if *allow_lan {
+                        self.add_block_cve_2019_14899(tunnel);
+                    }
+                }
+                (*allow_lan, lan_networks, allowed_endpoints)
+            }
+            FirewallPolicy::Connected {
+                peer_endpoint,
+                tunnel,
+                allow_lan,
+                allow_ipv6,
+                lan_networks,
+                dns_servers,
+                allowed_endpoints,
+            } => {
+                self.add_allow_tunnel_endpoint_rules(peer_endpoint);
+                self.add_allow_dns_rules(tunnel, &dns_servers, TransportProtocol::Udp);
+                self.add_allow_dns_rules(tunnel, &dns_servers, TransportProtocol::Tcp);
+                // Important to block DNS *before* we allow the tunnel and allow LAN. So DNS
+                // can't leak to the wrong IPs in the tunnel or on the LAN.
+                self.add_drop_dns_rule();
+                self.add_allow_tunnel_rules(&tunnel.interface, *allow_ipv6);
+                if *allow_lan {
+                    self.add_block_cve_2019_14899(tunnel);
+                }
+                (*allow_lan, lan_networks, allowed_endpoints)
+            }
+            FirewallPolicy::Blocked {
+                allow_lan,
+                lan_networks,
+                allowed_endpoint,
+                exemptions,
+                allowed_endpoints,
+            } => {
+                self.add_allow_endpoint_rules(allowed_endpoint);
+                self.add_system_service_rules(exemptions);
+
+                // Important to drop DNS before allowing LAN (to stop DNS leaking to the LAN)
+                self.add_drop_dns_rule();
+                (*allow_lan, lan_networks, allowed_endpoints)
+            }
+        };
+
+        if allow_lan {
+            self.add_allow_lan_rules(lan_networks);
+        }
+        // Like LAN traffic, this has to come after the rule dropping DNS
+        self.add_user_allowed_endpoint_rules(allowed_endpoints);
+
+        // Reject any remaining outgoing traffic, and drop remaining incoming traffic
+        let reject = self.family.reject_port_unreachable().to_owned();
+        self.output.push(reject.clone());
+        self.forward.push(reject);
+        self.input.push("-j DROP".to_owned());
+    }
+
+    fn add_allow_tunnel_endpoint_rules(&mut self, endpoint: &Endpoint) {
+        if !self.family.contains(endpoint.address.ip()) {
+            return;
+        }
+        self.prerouting.push(format!(
+            "{} -j MARK --set-mark {:#x}",
+            endpoint_match(endpoint, End::Src),
+            crate::linux::TUNNEL_FW_MARK
+        ));
+        self.input.push(format!(
+            "{} -m conntrack --ctstate ESTABLISHED -j ACCEPT",
+            endpoint_match(endpoint, End::Src)
+        ));
+        self.output.push(format!(
+            "{} -m mark --mark {:#x} -j ACCEPT",
+            endpoint_match(endpoint, End::Dst),
+            crate::linux::TUNNEL_FW_MARK
+        ));
+    }
+
+    fn add_allow_endpoint_rules(&mut self, endpoint: &Endpoint) {
+        if !self.family.contains(endpoint.address.ip()) {
+            return;
+        }
+        self.input
+            .push(format!("{} -j ACCEPT", endpoint_match(endpoint, End::Src)));
+        self.output
+            .push(format!("{} -j ACCEPT", endpoint_match(endpoint, End::Dst)));
+    }
+
+    fn add_user_allowed_endpoint_rules(&mut self, allowed_endpoints: &[AllowedEndpoint]) {
+        for endpoint in allowed_endpoints
+            .iter()
+            .filter(|endpoint| self.family.contains(endpoint.network.ip()))
+        {
+            for protocol in endpoint.rule_protocols() {
+                let out_rule = format!(
+                    "-d {}{} -j ACCEPT",
+                    endpoint.network,
+                    allowed_endpoint_port(endpoint, protocol, "--dport")
+                );
+                self.output.push(out_rule.clone());
+                self.forward.push(out_rule);
+                self.input.push(format!(
+                    "-s {}{} -j ACCEPT",
+                    endpoint.network,
+                    allowed_endpoint_port(endpoint, protocol, "--sport")
+                ));
+            }
+        }
+    }
+
+    fn add_system_service_rules(&mut self, exemptions: &SystemServiceExemptions) {
+        if exemptions.ntp {
+            self.output.push(format!(
+                "{} -j ACCEPT",
+                port(TransportProtocol::Udp, "--dport", NTP_SERVER_PORT)
+            ));
+            self.input.push(format!(
+                "{} -j ACCEPT",
+                port(TransportProtocol::Udp, "--sport", NTP_SERVER_PORT)
+            ));
+        }
+        for host in &exemptions.captive_portal_hosts {
+            self.add_allow_endpoint_rules(&Endpoint::new(
+                *host,
+                CAPTIVE_PORTAL_HTTP_PORT,
+                TransportProtocol::Tcp,
+            ));
+        }
+        if exemptions.portal_login {
+            for &(protocol, server_port) in PORTAL_LOGIN_PORTS {
+                self.output.push(format!(
+                    "{} -j ACCEPT",
+                    port(protocol, "--dport", server_port)
+                ));
+                self.input.push(format!(
+                    "{} -j ACCEPT",
+                    port(protocol, "--sport", server_port)
+                ));
+            }
+        }
+    }
+
+    fn add_allow_dns_rules(
+        &mut self,
+        tunnel: &tunnel::TunnelMetadata,
+        dns_servers: &[IpAddr],
+        protocol: TransportProtocol,
+    ) {
+        let family = self.family;
+        for server in dns_servers
+            .iter()
+            .filter(|server| family.contains(**server))
+        {
+            if is_local_dns_address(tunnel, server) {
+                let out_rule = format!(
+                    "! -o {} -d {} {} -j ACCEPT",
+                    tunnel.interface,
+                    server,
+                    port(protocol, "--dport", 53)
+                );
+                self.output.push(out_rule.clone());
+                self.forward.push(out_rule);
+                let in_rule = format!(
+                    "! -i {} -s {} {} -j ACCEPT",
+                    tunnel.interface,
+                    server,
+                    port(protocol, "--sport", 53)
+                );
+                self.input.push(in_rule.clone());
+                self.forward.push(in_rule);
+            } else {
+                let rule = format!(
+                    "-o {} -d {} {} -j ACCEPT",
+                    tunnel.interface,
+                    server,
+                    port(protocol, "--dport", 53)
+                );
+                self.output.push(rule.clone());
+                self.forward.push(rule);
+            }
+        }
+    }
+
+    /// Blocks all outgoing DNS (port 53) on both TCP and UDP
+    fn add_drop_dns_rule(&mut self) {
+        let udp_rule = format!(
+            "{} {}",
+            port(TransportProtocol::Udp, "--dport", 53),
+            self.family.reject_port_unreachable()
+        );
+        let tcp_rule = format!(
+            "{} -j REJECT --reject-with tcp-reset",
+            port(TransportProtocol::Tcp, "--dport", 53)
+        );
+        for chain in &mut [&mut self.output, &mut self.forward] {
+            chain.push(udp_rule.clone());
+            chain.push(tcp_rule.clone());
+        }
+    }
+
+    fn add_allow_tunnel_rules(&mut self, tunnel_interface: &str, allow_ipv6: bool) {
+        // Without IPv6, the tunnel is not allowed in the IPv6 ruleset at all. NDP and DHCPv6 are
+        // allowed by earlier rules.
+        if self.family == Family::V6 && !allow_ipv6 {
+            return;
+        }
+        // Lower the MSS of TCP connections through the tunnel to what fits in its MTU. See the
+        // nftables backend for details.
+        let clamp_mss = format!(
+            "-o {} -p tcp --tcp-flags SYN,RST SYN -j TCPMSS --clamp-mss-to-pmtu",
+            tunnel_interface
+        );
+        self.output.push(clamp_mss.clone());
+        self.forward.push(clamp_mss);
+        self.output
+            .push(format!("-o {} -j ACCEPT", tunnel_interface));
+        self.forward
+            .push(format!("-o {} -j ACCEPT", tunnel_interface));
+        self.input
+            .push(format!("-i {} -j ACCEPT", tunnel_interface));
+        self.forward.push(format!(
+            "-i {} -m conntrack --ctstate ESTABLISHED -j ACCEPT",
+            tunnel_interface
+        ));
+    }
+
+    /// Adds rules for stopping [CVE-2019-14899](https://seclists.org/oss-sec/2019/q4/122).
+    /// See the nftables backend for details.
+    fn add_block_cve_2019_14899(&mut self, tunnel: &tunnel::TunnelMetadata) {
+        let family = self.family;
+        for tunnel_ip in tunnel.ips.iter().filter(|ip| family.contains(**ip)) {
+            self.input.push(format!("-d {} -j DROP", tunnel_ip));
+        }
+    }
+
+    fn add_allow_lan_rules(&mut self, lan_networks: &[IpNetwork]) {
+        let family = self.family;
+        let nets = || {
+            lan_networks
+                .iter()
+                .filter(move |net| family.contains(net.ip()))
+        };
+
+        // Output and forward chains
+        for net in nets().chain(
+            ALLOWED_LAN_MULTICAST_NETS
+                .iter()
+                .filter(|net| family.contains(net.ip())),
+        ) {
+            let rule = format!("-d {} -j ACCEPT", net);
+            self.output.push(rule.clone());
+            self.forward.push(rule);
+        }
+
+        // Input chain
+        for net in nets() {
+            self.input.push(format!("-s {} -j ACCEPT", net));
+        }
+        self.add_dhcp_server_rules();
+    }
+
+    fn add_dhcp_server_rules(&mut self) {
+        use TransportProtocol::Udp;
+        if self.family != Family::V4 {
+            return;
+        }
+        // Outgoing DHCPv4 response
+        self.output.push(format!(
+            "{} --dport {} -j ACCEPT",
+            port(Udp, "--sport", DHCPV4_SERVER_PORT),
+            DHCPV4_CLIENT_PORT
+        ));
+        // Incoming DHCPv4 request
+        self.input.push(format!(
+            "{} -d {} --dport {} -j ACCEPT",
+            port(Udp, "--sport", DHCPV4_CLIENT_PORT),
+            Ipv4Addr::BROADCAST,
+            DHCPV4_SERVER_PORT
+        ));
+    }
+}
+
+/// Returns the commands that turn the rules `old` of `chain` into `new`. The rules in the longest
+/// common subsequence of both are kept. The other old rules are deleted back to front, and then the
+/// other new rules are inserted at their final positions front to back.
+fn chain_delta(chain: &str, old: &[String], new: &[String]) -> Vec<String> {
+    // `lcs[i][j]` is the length of the longest common subsequence of `old[i..]` and `new[j..]`
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                cmp::max(lcs[i + 1][j], lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut keep_old = vec![false; old.len()];
+    let mut keep_new = vec![false; new.len()];
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            keep_old[i] = true;
+            keep_new[j] = true;
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    let deletions = keep_old
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, keep)| !**keep)
+        .map(|(index, _)| format!("-D {} {}", chain, index + 1));
+    let insertions = new
+        .iter()
+        .zip(keep_new.iter())
+        .enumerate()
+        .filter(|(_, (_, keep))| !**keep)
+        .map(|(index, (rule, _))| format!("-I {} {} {}", chain, index + 1, rule));
+    deletions.chain(insertions).collect()
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum End {
+    Src,
+    Dst,
+}
+
+fn endpoint_match(endpoint: &Endpoint, end: End) -> String {
+    let (address_flag, port_flag) = match end {
+        End::Src => ("-s", "--sport"),
+        End::Dst => ("-d", "--dport"),
+    };
+    format!(
+        "{} {} {}",
+        address_flag,
+        IpNetwork::from(endpoint.address.ip()),
+        port(endpoint.protocol, port_flag, endpoint.address.port())
+    )
+}
+
+fn port(protocol: TransportProtocol, flag: &str, port: u16) -> String {
+    let protocol = match protocol {
+        TransportProtocol::Udp => "udp",
+        TransportProtocol::Tcp => "tcp",
+    };
+    format!("-p {} {} {}", protocol, flag, port)
+}
+
+/// Returns the arguments that limit a rule to the protocol and port of a user allowed endpoint,
+/// with a leading space, or an empty string if all traffic is allowed.
+fn allowed_endpoint_port(
+    endpoint: &AllowedEndpoint,
+    protocol: Option<TransportProtocol>,
+    flag: &str,
+) -> String {
+    match (protocol, endpoint.port) {
+        (Some(protocol), Some(server_port)) => format!(" {}", port(protocol, flag, server_port)),
+        (Some(TransportProtocol::Udp), None) => " -p udp".to_owned(),
+        (Some(TransportProtocol::Tcp), None) => " -p tcp".to_owned(),
+        (None, _) => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_blocked_ruleset() {
+        let policy = FirewallPolicy::Blocked {
+            allow_lan: false,
+            lan_networks: vec![],
+            allowed_endpoint: Endpoint::new(
+                Ipv4Addr::new(192, 0, 2, 1),
+                443,
+                TransportProtocol::Tcp,
+            ),
+            exemptions: SystemServiceExemptions::default(),
+            allowed_endpoints: vec![],
+        };
+
+        let input = Ruleset::new(Family::V4, &policy).to_restore_input();
+        assert!(input.starts_with("*filter\n:mullvad-input - [0:0]\n"));
+        assert!(input.contains("-A mullvad-output -d 192.0.2.1/32 -p tcp --dport 443 -j ACCEPT\n"));
+        assert!(input.contains("-A mullvad-input -j DROP\n"));
+        assert!(input.contains(
+            "-A mullvad-forward -j REJECT --reject-with icmp-port-unreachable\nCOMMIT\n"
+        ));
+        assert!(!input.contains("-A mullvad-output -d 10.0.0.0/8 -j ACCEPT"));
+        assert_eq!(input.matches("COMMIT").count(), 3);
+
+        let input = Ruleset::new(Family::V6, &policy).to_restore_input();
+        assert!(!input.contains("192.0.2.1"));
+        assert!(input.contains("icmp6-port-unreachable"));
+    }
+
+    #[test]
+    fn test_allow_lan_ruleset() {
+        let policy = FirewallPolicy::Blocked {
+            allow_lan: true,
+            lan_networks: vec![
+                "192.168.1.0/24".parse().unwrap(),
+                "fe80::/64".parse().unwrap(),
+            ],
+            allowed_endpoint: Endpoint::new(
+                Ipv4Addr::new(192, 0, 2, 1),
+                443,
+                TransportProtocol::Tcp,
+            ),
+            exemptions: SystemServiceExemptions::default(),
+            allowed_endpoints: vec![],
+        };
+
+        let input = Ruleset::new(Family::V4, &policy).to_restore_input();
+        assert!(input.contains("-A mullvad-output -d 192.168.1.0/24 -j ACCEPT\n"));
+        assert!(input.contains("-A mullvad-input -s 192.168.1.0/24 -j ACCEPT\n"));
+        assert!(!input.contains("10.0.0.0/8"));
+        assert!(!input.contains("fe80::/64"));
+
+        let input = Ruleset::new(Family::V6, &policy).to_restore_input();
+        assert!(input.contains("-A mullvad-input -s fe80::/64 -j ACCEPT\n"));
+        assert!(!input.contains("192.168.1.0/24"));
+    }
+
+    #[test]
+    fn test_system_service_ruleset() {
+        let policy = FirewallPolicy::Blocked {
+            allow_lan: false,
+            lan_networks: vec![],
+            allowed_endpoint: Endpoint::new(
+                Ipv4Addr::new(192, 0, 2, 1),
+                443,
+                TransportProtocol::Tcp,
+            ),
+            exemptions: SystemServiceExemptions {
+                ntp: true,
+                captive_portal_hosts: vec![Ipv4Addr::new(198, 51, 100, 7).into()],
+                portal_login: false,
+            },
+            allowed_endpoints: vec![],
+        };
+
+        let input = Ruleset::new(Family::V4, &policy).to_restore_input();
+        assert!(input.contains("-A mullvad-output -p udp --dport 123 -j ACCEPT\n"));
+        assert!(input.contains("-A mullvad-input -p udp --sport 123 -j ACCEPT\n"));
+        assert!(
+            input.contains("-A mullvad-output -d 198.51.100.7/32 -p tcp --dport 80 -j ACCEPT\n")
+        );
+
+        let input = Ruleset::new(Family::V6, &policy).to_restore_input();
+        assert!(input.contains("-A mullvad-output -p udp --dport 123 -j ACCEPT\n"));
+        assert!(!input.contains("198.51.100.7"));
+        assert!(!input.contains("--dport 443 -j ACCEPT"));
+    }
+
+    #[test]
+    fn test_portal_login_ruleset() {
+        let policy = FirewallPolicy::Blocked {
+            allow_lan: false,
+            lan_networks: vec![],
+            allowed_endpoint: Endpoint::new(
+                Ipv4Addr::new(192, 0, 2, 1),
+                443,
+                TransportProtocol::Tcp,
+            ),
+            exemptions: SystemServiceExemptions {
+                portal_login: true,
+                ..SystemServiceExemptions::default()
+            },
+            allowed_endpoints: vec![],
+        };
+
+        let input = Ruleset::new(Family::V4, &policy).to_restore_input();
+        let allow_dns = input
+            .find("-A mullvad-output -p udp --dport 53 -j ACCEPT\n")
+            .expect("DNS is not allowed");
+        let drop_dns = input
+            .find("-A mullvad-output -p udp --dport 53 -j REJECT")
+            .expect("DNS is not blocked");
+        assert!(allow_dns < drop_dns);
+        assert!(input.contains("-A mullvad-output -p tcp --dport 80 -j ACCEPT\n"));
+        assert!(input.contains("-A mullvad-output -p tcp --dport 443 -j ACCEPT\n"));
+        assert!(input.contains("-A mullvad-input -p tcp --sport 443 -j ACCEPT\n"));
+    }
+
+    #[test]
+    fn test_user_allowed_endpoint_ruleset() {
+        let policy = FirewallPolicy::Blocked {
+            allow_lan: false,
+            lan_networks: vec![],
+            allowed_endpoint: Endpoint::new(
+                Ipv4Addr::new(192, 0, 2, 1),
+                443,
+                TransportProtocol::Tcp,
+            ),
+            exemptions: SystemServiceExemptions::default(),
+            allowed_endpoints: vec![
+                AllowedEndpoint {
+                    network: "10.20.0.0/16".parse().unwrap(),
+                    port: None,
+                    protocol: None,
+                },
+                AllowedEndpoint {
+                    network: "172.16.5.0/24".parse().unwrap(),
+                    port: Some(5353),
+                    protocol: Some(TransportProtocol::Udp),
+                },
+            ],
+        };
+
+        let input = Ruleset::new(Family::V4, &policy).to_restore_input();
+        assert!(input.contains("-A mullvad-output -d 10.20.0.0/16 -j ACCEPT\n"));
+        assert!(input.contains("-A mullvad-input -s 10.20.0.0/16 -j ACCEPT\n"));
+        assert!(
+            input.contains("-A mullvad-output -d 172.16.5.0/24 -p udp --dport 5353 -j ACCEPT\n")
+        );
+        assert!(input.contains("-A mullvad-input -s 172.16.5.0/24 -p udp --sport 5353 -j ACCEPT\n"));
+        let drop_dns = input
+            .find("-A mullvad-output -p udp --dport 53 -j REJECT")
+            .expect("DNS is not blocked");
+        assert!(drop_dns < input.find("-A mullvad-output -d 10.20.0.0/16").unwrap());
+
+        let input = Ruleset::new(Family::V6, &policy).to_restore_input();
+        assert!(!input.contains("10.20.0.0/16"));
+    }
+
+    #[test]
+    fn test_clamp_mss_ruleset() {
+        let policy = FirewallPolicy::Connecting {
+            peer_endpoint: Endpoint::new(
+                Ipv4Addr::new(192, 0, 2, 1),
+                51820,
+                TransportProtocol::Udp,
+            ),
+            tunnel: Some(tunnel::TunnelMetadata {
+                interface: "wg-mullvad".to_owned(),
+                ips: vec![Ipv4Addr::new(10, 64, 0, 2).into()],
+                ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+                ipv6_gateway: None,
+            }),
+            allow_lan: false,
+            allow_ipv6: false,
+            lan_networks: vec![],
+            allowed_endpoint: Endpoint::new(
+                Ipv4Addr::new(192, 0, 2, 2),
+                443,
+                TransportProtocol::Tcp,
+            ),
+            allowed_endpoints: vec![],
+        };
+
+        let input = Ruleset::new(Family::V4, &policy).to_restore_input();
+        let clamp_mss =
+            " -o wg-mullvad -p tcp --tcp-flags SYN,RST SYN -j TCPMSS --clamp-mss-to-pmtu\n";
+        let clamp_output = input
+            .find(&format!("-A mullvad-output{}", clamp_mss))
+            .expect("MSS is not clamped for output");
+        let allow_output = input
+            .find("-A mullvad-output -o wg-mullvad -j ACCEPT")
+            .expect("The tunnel is not allowed");
+        assert!(clamp_output < allow_output);
+        assert!(input.contains(&format!("-A mullvad-forward{}", clamp_mss)));
+
+        let input = Ruleset::new(Family::V6, &policy).to_restore_input();
+        assert!(!input.contains("TCPMSS"));
+    }
+
+    #[test]
+    fn test_chain_delta() {
+        let rules = |rules: &[&str]| {
+            rules
+                .iter()
+                .map(|rule| rule.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let old = rules(&["a", "b", "c", "d"]);
+        let new = rules(&["a", "x", "c", "d", "y"]);
+        assert_eq!(
+            chain_delta("chain", &old, &new),
+            vec!["-D chain 2", "-I chain 2 x", "-I chain 5 y"]
+        );
+        assert!(chain_delta("chain", &old, &old).is_empty());
+        assert_eq!(
+            chain_delta("chain", &old, &[]),
+            vec!["-D chain 4", "-D chain 3", "-D chain 2", "-D chain 1"]
+        );
+    }
+
+    #[test]
+    fn test_delta_ruleset() {
+        let endpoint = Endpoint::new(Ipv4Addr::new(192, 0, 2, 1), 443, TransportProtocol::Tcp);
+        let blocked = Ruleset::new(
+            Family::V4,
+            &FirewallPolicy::Blocked {
+                allow_lan: false,
+                lan_networks: vec![],
+                allowed_endpoint: endpoint,
+                exemptions: SystemServiceExemptions::default(),
+                allowed_endpoints: vec![],
+            },
+        );
+        let allow_lan = Ruleset::new(
+            Family::V4,
+            &FirewallPolicy::Blocked {
+                allow_lan: true,
+                lan_networks: vec!["192.168.1.0/24".parse().unwrap()],
+                allowed_endpoint: endpoint,
+                exemptions: SystemServiceExemptions::default(),
+                allowed_endpoints: vec![],
+            },
+        );
+
+        assert_eq!(blocked.to_delta_restore_input(&blocked), None);
+
+        let input = allow_lan.to_delta_restore_input(&blocked).unwrap();
+        assert!(input.starts_with("*filter\n"));
+        assert!(!input.contains(":mullvad-"));
+        assert!(!input.contains("-D "));
+        assert!(input.contains(" -d 192.168.1.0/24 -j ACCEPT\n"));
+        assert_eq!(input.matches("COMMIT").count(), 1);
+
+        let input = blocked.to_delta_restore_input(&allow_lan).unwrap();
+        assert!(!input.contains("-I "));
+        assert!(input.contains("-D mullvad-output "));
+    }
+}
@@ -1,11 +1,12 @@
-use super::{FirewallArguments, FirewallPolicy, FirewallT};
+use super::{FirewallArguments, FirewallPolicy, FirewallRules, FirewallT};
 use ipnetwork::IpNetwork;
 use pfctl::{DropAction, FilterRuleAction, Uid};
 use std::{
-    env,
+    env, io,
     net::{IpAddr, Ipv4Addr},
+    process::{Command, Stdio},
 };
-use talpid_types::net;
+use talpid_types::{net, ErrorExt};
 
 pub use pfctl::Error;
 
@@ -62,6 +63,86 @@ impl FirewallT for Firewall {
         .collect::<Result<Vec<_>>>()
         .map(|_| ())
     }
+
+    fn describe_policy(&self) -> io::Result<FirewallRules> {
+        // The ioctl interface of pf cannot turn rules back into text, so pfctl is used instead
+        let mut rules = vec![];
+        for kind in &["rules", "nat"] {
+            let output = Command::new("pfctl")
+                .args(&["-a", ANCHOR_NAME, "-s", kind])
+                .stdin(Stdio::null())
+                .stderr(Stdio::null())
+                .output()?;
+            if !output.status.success() {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("pfctl exited with {}", output.status),
+                ));
+            }
+            rules.extend(
+                String::from_utf8_lossy(&output.stdout)
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .map(str::to_owned),
+            );
+        }
+        Ok(FirewallRules {
+            backend: "pf".to_owned(),
+            policy: None,
+            rules,
+        })
+    }
+
+    fn remove_orphaned_objects(&mut self) -> Result<Vec<String>> {
+        let anchors = match Self::list_anchors() {
+            Ok(anchors) => anchors,
+            Err(error) => {
+                log::warn!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to list the pf anchors")
+                );
+                return Ok(vec![]);
+            }
+        };
+        // Anchors that are named like ours, but that we do not use, were added by other versions
+        let mut removed = vec![];
+        for anchor in anchors
+            .iter()
+            .filter(|anchor| anchor.starts_with(ANCHOR_NAME) && anchor.as_str() != ANCHOR_NAME)
+        {
+            self.pf.flush_rules(anchor, pfctl::RulesetKind::Filter)?;
+            self.pf
+                .try_remove_anchor(anchor, pfctl::AnchorKind::Filter)?;
+            self.pf
+                .try_remove_anchor(anchor, pfctl::AnchorKind::Redirect)?;
+
+            let description = format!("pf anchor {}", anchor);
+            log::info!("Removed orphaned firewall object: {}", description);
+            removed.push(description);
+        }
+        Ok(removed)
+    }
+}
+
+impl Drop for Firewall {
+    fn drop(&mut self) {
+        if !super::persist::is_installed() {
+            return;
+        }
+        if let Err(error) = self.enable().and_then(|_| self.add_anchor()) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to enforce the persistent firewall policy")
+            );
+            return;
+        }
+        if let Err(error) = super::persist::enforce() {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to enforce the persistent firewall policy")
+            );
+        }
+    }
 }
 
 impl Firewall {
@@ -99,7 +180,10 @@ impl Firewall {
                 peer_endpoint,
                 tunnel,
                 allow_lan,
+                allow_ipv6,
+                lan_networks,
                 allowed_endpoint,
+                allowed_endpoints,
             } => {
                 let mut rules = vec![self.get_allow_relay_rule(peer_endpoint)?];
                 rules.push(self.get_allowed_endpoint_rule(allowed_endpoint)?);
@@ -109,19 +193,23 @@ impl Firewall {
                 rules.append(&mut self.get_block_dns_rules()?);
 
                 if let Some(tunnel) = tunnel {
-                    rules.push(self.get_allow_tunnel_rule(&tunnel.interface)?);
+                    rules.push(self.get_allow_tunnel_rule(&tunnel.interface, allow_ipv6)?);
                 }
 
                 if allow_lan {
-                    rules.append(&mut self.get_allow_lan_rules()?);
+                    rules.append(&mut self.get_allow_lan_rules(&lan_networks)?);
                 }
+                rules.append(&mut self.get_user_allowed_endpoint_rules(&allowed_endpoints)?);
                 Ok(rules)
             }
             FirewallPolicy::Connected {
                 peer_endpoint,
                 tunnel,
                 allow_lan,
+                allow_ipv6,
+                lan_networks,
                 dns_servers,
+                allowed_endpoints,
             } => {
                 let mut rules = vec![];
 
@@ -135,25 +223,33 @@ impl Firewall {
                 // can't leak to the wrong IPs in the tunnel or on the LAN.
                 rules.append(&mut self.get_block_dns_rules()?);
 
-                rules.push(self.get_allow_tunnel_rule(tunnel.interface.as_str())?);
+                rules.push(self.get_allow_tunnel_rule(tunnel.interface.as_str(), allow_ipv6)?);
 
                 if allow_lan {
-                    rules.append(&mut self.get_allow_lan_rules()?);
+                    rules.append(&mut self.get_allow_lan_rules(&lan_networks)?);
                 }
+                rules.append(&mut self.get_user_allowed_endpoint_rules(&allowed_endpoints)?);
 
                 Ok(rules)
             }
             FirewallPolicy::Blocked {
                 allow_lan,
+                lan_networks,
                 allowed_endpoint,
+                exemptions,
+                allowed_endpoints,
             } => {
                 let mut rules = Vec::new();
                 rules.push(self.get_allowed_endpoint_rule(allowed_endpoint)?);
-                if allow_lan {
+                rules.append(&mut self.get_system_service_rules(&exemptions)?);
+                if allow_lan || !allowed_endpoints.is_empty() {
                     // Important to block DNS before allow LAN (so DNS does not leak to the LAN)
                     rules.append(&mut self.get_block_dns_rules()?);
-                    rules.append(&mut self.get_allow_lan_rules()?);
                 }
+                if allow_lan {
+                    rules.append(&mut self.get_allow_lan_rules(&lan_networks)?);
+                }
+                rules.append(&mut self.get_user_allowed_endpoint_rules(&allowed_endpoints)?);
                 Ok(rules)
             }
         }
@@ -274,6 +370,74 @@ impl Firewall {
             .build()?)
     }
 
+    fn get_system_service_rules(
+        &self,
+        exemptions: &net::SystemServiceExemptions,
+    ) -> Result<Vec<pfctl::FilterRule>> {
+        let mut rules = Vec::new();
+        if exemptions.ntp {
+            rules.push(
+                self.create_rule_builder(FilterRuleAction::Pass)
+                    .direction(pfctl::Direction::Out)
+                    .proto(pfctl::Proto::Udp)
+                    .to(pfctl::Port::from(super::NTP_SERVER_PORT))
+                    .keep_state(pfctl::StatePolicy::Keep)
+                    .quick(true)
+                    .build()?,
+            );
+        }
+        for host in &exemptions.captive_portal_hosts {
+            rules.push(self.get_allowed_endpoint_rule(net::Endpoint::new(
+                *host,
+                super::CAPTIVE_PORTAL_HTTP_PORT,
+                net::TransportProtocol::Tcp,
+            ))?);
+        }
+        if exemptions.portal_login {
+            for &(protocol, port) in super::PORTAL_LOGIN_PORTS {
+                rules.push(
+                    self.create_rule_builder(FilterRuleAction::Pass)
+                        .direction(pfctl::Direction::Out)
+                        .proto(as_pfctl_proto(protocol))
+                        .to(pfctl::Port::from(port))
+                        .keep_state(pfctl::StatePolicy::Keep)
+                        .quick(true)
+                        .build()?,
+                );
+            }
+        }
+        Ok(rules)
+    }
+
+    fn get_user_allowed_endpoint_rules(
+        &self,
+        allowed_endpoints: &[net::AllowedEndpoint],
+    ) -> Result<Vec<pfctl::FilterRule>> {
+        let mut rules = vec![];
+        for endpoint in allowed_endpoints {
+            for protocol in endpoint.rule_protocols() {
+                let mut rule_builder = self.create_rule_builder(FilterRuleAction::Pass);
+                rule_builder.quick(true);
+                if let Some(protocol) = protocol {
+                    rule_builder.proto(as_pfctl_proto(protocol));
+                }
+                let allow_out = rule_builder
+                    .direction(pfctl::Direction::Out)
+                    .from(pfctl::Ip::Any)
+                    .to(as_pfctl_endpoint(endpoint))
+                    .build()?;
+                let allow_in = rule_builder
+                    .direction(pfctl::Direction::In)
+                    .from(as_pfctl_endpoint(endpoint))
+                    .to(pfctl::Ip::Any)
+                    .build()?;
+                rules.push(allow_out);
+                rules.push(allow_in);
+            }
+        }
+        Ok(rules)
+    }
+
     fn get_block_dns_rules(&self) -> Result<Vec<pfctl::FilterRule>> {
         let block_tcp_dns_rule = self
             .create_rule_builder(FilterRuleAction::Drop(DropAction::Return))
@@ -293,14 +457,21 @@ impl Firewall {
         Ok(vec![block_tcp_dns_rule, block_udp_dns_rule])
     }
 
-    fn get_allow_tunnel_rule(&self, tunnel_interface: &str) -> Result<pfctl::FilterRule> {
-        Ok(self
-            .create_rule_builder(FilterRuleAction::Pass)
+    fn get_allow_tunnel_rule(
+        &self,
+        tunnel_interface: &str,
+        allow_ipv6: bool,
+    ) -> Result<pfctl::FilterRule> {
+        let mut rule_builder = self.create_rule_builder(FilterRuleAction::Pass);
+        rule_builder
             .quick(true)
             .interface(tunnel_interface)
             .keep_state(pfctl::StatePolicy::Keep)
-            .tcp_flags(Self::get_tcp_flags())
-            .build()?)
+            .tcp_flags(Self::get_tcp_flags());
+        if !allow_ipv6 {
+            rule_builder.af(pfctl::AddrFamily::Ipv4);
+        }
+        Ok(rule_builder.build()?)
     }
 
     fn get_allow_loopback_rules(&self) -> Result<Vec<pfctl::FilterRule>> {
@@ -313,9 +484,9 @@ impl Firewall {
         Ok(vec![lo0_rule])
     }
 
-    fn get_allow_lan_rules(&self) -> Result<Vec<pfctl::FilterRule>> {
+    fn get_allow_lan_rules(&self, lan_networks: &[IpNetwork]) -> Result<Vec<pfctl::FilterRule>> {
         let mut rules = vec![];
-        for net in &*super::ALLOWED_LAN_NETS {
+        for net in lan_networks {
             let mut rule_builder = self.create_rule_builder(FilterRuleAction::Pass);
             rule_builder.quick(true);
             let allow_out = rule_builder
@@ -438,6 +609,19 @@ impl Firewall {
         rules.push(allow_router_solicitation);
         rules.push(allow_router_advertisement_and_redirect);
 
+        // NDP (neighbor solicitation and advertisement). These replace ARP for IPv6, so the
+        // gateway cannot be reached over IPv6 without them
+        for destination in &[*super::IPV6_LINK_LOCAL, *super::SOLICITED_NODE_MULTICAST] {
+            let allow_neighbor_discovery = self
+                .create_rule_builder(FilterRuleAction::Pass)
+                .quick(true)
+                .proto(pfctl::Proto::IcmpV6)
+                .direction(pfctl::Direction::Out)
+                .to(pfctl::Ip::from(IpNetwork::V6(*destination)))
+                .build()?;
+            rules.push(allow_neighbor_discovery);
+        }
+
         Ok(rules)
     }
 
@@ -468,6 +652,27 @@ impl Firewall {
         )
     }
 
+    /// Lists the anchors of the main ruleset using pfctl.
+    fn list_anchors() -> io::Result<Vec<String>> {
+        let output = Command::new("pfctl")
+            .args(&["-s", "Anchors"])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()?;
+        if !output.status.success() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("pfctl exited with {}", output.status),
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_owned)
+            .collect())
+    }
+
     fn remove_rules(&mut self) -> Result<()> {
         // remove_anchor() does not deactivate active rules
         self.pf
@@ -514,6 +719,14 @@ fn as_pfctl_proto(protocol: net::TransportProtocol) -> pfctl::Proto {
     }
 }
 
+fn as_pfctl_endpoint(endpoint: &net::AllowedEndpoint) -> pfctl::Endpoint {
+    let port = match endpoint.port {
+        Some(port) => pfctl::Port::from(port),
+        None => pfctl::Port::Any,
+    };
+    pfctl::Endpoint::new(pfctl::Ip::from(endpoint.network), port)
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 enum RuleLogging {
     None,
@@ -1,15 +1,17 @@
-#[cfg(unix)]
 use ipnetwork::{IpNetwork, Ipv4Network, Ipv6Network};
-#[cfg(unix)]
 use lazy_static::lazy_static;
-use std::fmt;
 #[cfg(not(target_os = "android"))]
 use std::net::IpAddr;
-#[cfg(unix)]
-use std::net::{Ipv4Addr, Ipv6Addr};
 #[cfg(windows)]
 use std::path::PathBuf;
-use talpid_types::net::Endpoint;
+#[cfg(windows)]
+use std::sync::Weak;
+use std::{
+    fmt, io,
+    net::{Ipv4Addr, Ipv6Addr},
+    time::{Duration, Instant},
+};
+use talpid_types::net::{AllowedEndpoint, Endpoint, SystemServiceExemptions};
 
 
 #[cfg(target_os = "macos")]
@@ -30,10 +32,14 @@ mod imp;
 
 pub use self::imp::Error;
 
-#[cfg(unix)]
+#[cfg(not(target_os = "android"))]
+pub mod persist;
+
 lazy_static! {
-    /// When "allow local network" is enabled the app will allow traffic to and from these networks.
-    pub(crate) static ref ALLOWED_LAN_NETS: [IpNetwork; 6] = [
+    /// The private address ranges. When "allow local network" is enabled, traffic to and from
+    /// these networks is allowed where the on-link networks are not known, such as on Android, at
+    /// boot, or if the networks cannot be listed.
+    pub static ref ALLOWED_LAN_NETS: [IpNetwork; 6] = [
         IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(10, 0, 0, 0), 8).unwrap()),
         IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(172, 16, 0, 0), 12).unwrap()),
         IpNetwork::V4(Ipv4Network::new(Ipv4Addr::new(192, 168, 0, 0), 16).unwrap()),
@@ -41,6 +47,10 @@ lazy_static! {
         IpNetwork::V6(Ipv6Network::new(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), 10).unwrap()),
         IpNetwork::V6(Ipv6Network::new(Ipv6Addr::new(0xfc00, 0, 0, 0, 0, 0, 0, 0), 7).unwrap()),
     ];
+}
+
+#[cfg(unix)]
+lazy_static! {
     /// When "allow local network" is enabled the app will allow traffic to these networks.
     pub(crate) static ref ALLOWED_LAN_MULTICAST_NETS: [IpNetwork; 8] = [
         // Local network broadcast. Not routable
@@ -67,6 +77,9 @@ lazy_static! {
         Ipv6Addr::new(0xff05, 0, 0, 0, 0, 0, 1, 3),
     ];
     static ref ROUTER_SOLICITATION_OUT_DST_ADDR: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 2);
+    /// Neighbor solicitations for addresses whose link-layer address is unknown are sent to
+    /// solicited-node multicast addresses in this network
+    static ref SOLICITED_NODE_MULTICAST: Ipv6Network = Ipv6Network::new(Ipv6Addr::new(0xff02, 0, 0, 0, 0, 1, 0xff00, 0), 104).unwrap();
     static ref LOOPBACK_NETS: [IpNetwork; 2] = [
         IpNetwork::V4(ipnetwork::Ipv4Network::new(Ipv4Addr::new(127, 0, 0, 0), 8).unwrap()),
         IpNetwork::V6(ipnetwork::Ipv6Network::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1), 128).unwrap()),
@@ -80,6 +93,19 @@ const DHCPV4_CLIENT_PORT: u16 = 68;
 const DHCPV6_SERVER_PORT: u16 = 547;
 #[cfg(all(unix, not(target_os = "android")))]
 const DHCPV6_CLIENT_PORT: u16 = 546;
+#[cfg(all(unix, not(target_os = "android")))]
+const NTP_SERVER_PORT: u16 = 123;
+#[cfg(all(unix, not(target_os = "android")))]
+const CAPTIVE_PORTAL_HTTP_PORT: u16 = 80;
+/// Traffic that may be sent to any host while logging in to a captive portal: DNS, HTTP and
+/// HTTPS.
+#[cfg(all(unix, not(target_os = "android")))]
+const PORTAL_LOGIN_PORTS: &[(talpid_types::net::TransportProtocol, u16)] = &[
+    (talpid_types::net::TransportProtocol::Udp, 53),
+    (talpid_types::net::TransportProtocol::Tcp, 53),
+    (talpid_types::net::TransportProtocol::Tcp, 80),
+    (talpid_types::net::TransportProtocol::Tcp, 443),
+];
 
 
 #[cfg(all(unix, not(target_os = "android")))]
@@ -108,8 +134,21 @@ pub enum FirewallPolicy {
         tunnel: Option<crate::tunnel::TunnelMetadata>,
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
+        /// Flag setting if IPv6 traffic should be allowed on the tunnel interface. NDP and DHCPv6
+        /// are allowed regardless.
+        allow_ipv6: bool,
+        /// The on-link networks of the physical interfaces. Traffic to and from them is allowed
+        /// if `allow_lan` is set.
+        #[cfg(not(target_os = "android"))]
+        lan_networks: Vec<IpNetwork>,
         /// Host that should be reachable by the tunnel client while connecting.
         allowed_endpoint: Endpoint,
+        /// Networks and ports that the user has chosen to allow outside the tunnel.
+        allowed_endpoints: Vec<AllowedEndpoint>,
+        /// Flag setting if communication with the subnets of the Hyper-V NAT switches, such as the
+        /// one used by WSL2, should be possible.
+        #[cfg(windows)]
+        allow_virtual_networks: bool,
         /// A process that is allowed to send packets to the relay.
         #[cfg(windows)]
         relay_client: PathBuf,
@@ -123,9 +162,22 @@ pub enum FirewallPolicy {
         tunnel: crate::tunnel::TunnelMetadata,
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
+        /// Flag setting if IPv6 traffic should be allowed on the tunnel interface. NDP and DHCPv6
+        /// are allowed regardless.
+        allow_ipv6: bool,
+        /// The on-link networks of the physical interfaces. Traffic to and from them is allowed
+        /// if `allow_lan` is set.
+        #[cfg(not(target_os = "android"))]
+        lan_networks: Vec<IpNetwork>,
         /// Servers that are allowed to respond to DNS requests.
         #[cfg(not(target_os = "android"))]
         dns_servers: Vec<IpAddr>,
+        /// Networks and ports that the user has chosen to allow outside the tunnel.
+        allowed_endpoints: Vec<AllowedEndpoint>,
+        /// Flag setting if communication with the subnets of the Hyper-V NAT switches, such as the
+        /// one used by WSL2, should be possible.
+        #[cfg(windows)]
+        allow_virtual_networks: bool,
         /// A process that is allowed to send packets to the relay.
         #[cfg(windows)]
         relay_client: PathBuf,
@@ -135,11 +187,40 @@ pub enum FirewallPolicy {
     Blocked {
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
+        /// The on-link networks of the physical interfaces. Traffic to and from them is allowed
+        /// if `allow_lan` is set.
+        #[cfg(not(target_os = "android"))]
+        lan_networks: Vec<IpNetwork>,
         /// Host that should be reachable while in the blocked state.
         allowed_endpoint: Endpoint,
+        /// Traffic of system services that should be allowed while in the blocked state.
+        exemptions: SystemServiceExemptions,
+        /// Networks and ports that the user has chosen to allow outside the tunnel.
+        allowed_endpoints: Vec<AllowedEndpoint>,
+        /// Flag setting if communication with the subnets of the Hyper-V NAT switches, such as the
+        /// one used by WSL2, should be possible.
+        #[cfg(windows)]
+        allow_virtual_networks: bool,
     },
 }
 
+impl FirewallPolicy {
+    /// Returns the networks and ports that are allowed outside the tunnel.
+    fn allowed_endpoints_mut(&mut self) -> &mut Vec<AllowedEndpoint> {
+        match self {
+            FirewallPolicy::Connecting {
+                allowed_endpoints, ..
+            }
+            | FirewallPolicy::Connected {
+                allowed_endpoints, ..
+            }
+            | FirewallPolicy::Blocked {
+                allowed_endpoints, ..
+            } => allowed_endpoints,
+        }
+    }
+}
+
 impl fmt::Display for FirewallPolicy {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -147,12 +228,13 @@ impl fmt::Display for FirewallPolicy {
                 peer_endpoint,
                 tunnel,
                 allow_lan,
+                allow_ipv6,
                 ..
             } => {
                 if let Some(tunnel) = tunnel {
                     write!(
                         f,
-                        "Connecting to {} over \"{}\" (ip: {}, v4 gw: {}, v6 gw: {:?}), {} LAN",
+                        "Connecting to {} over \"{}\" (ip: {}, v4 gw: {}, v6 gw: {:?}), {} LAN, {} IPv6",
                         peer_endpoint,
                         tunnel.interface,
                         tunnel
@@ -163,7 +245,8 @@ impl fmt::Display for FirewallPolicy {
                             .join(","),
                         tunnel.ipv4_gateway,
                         tunnel.ipv6_gateway,
-                        if *allow_lan { "Allowing" } else { "Blocking" }
+                        if *allow_lan { "Allowing" } else { "Blocking" },
+                        if *allow_ipv6 { "Allowing" } else { "Blocking" }
                     )
                 } else {
                     write!(
@@ -178,10 +261,11 @@ impl fmt::Display for FirewallPolicy {
                 peer_endpoint,
                 tunnel,
                 allow_lan,
+                allow_ipv6,
                 ..
             } => write!(
                 f,
-                "Connected to {} over \"{}\" (ip: {}, v4 gw: {}, v6 gw: {:?}), {} LAN",
+                "Connected to {} over \"{}\" (ip: {}, v4 gw: {}, v6 gw: {:?}), {} LAN, {} IPv6",
                 peer_endpoint,
                 tunnel.interface,
                 tunnel
@@ -192,17 +276,32 @@ impl fmt::Display for FirewallPolicy {
                     .join(","),
                 tunnel.ipv4_gateway,
                 tunnel.ipv6_gateway,
-                if *allow_lan { "Allowing" } else { "Blocking" }
+                if *allow_lan { "Allowing" } else { "Blocking" },
+                if *allow_ipv6 { "Allowing" } else { "Blocking" }
             ),
             FirewallPolicy::Blocked {
                 allow_lan,
                 allowed_endpoint,
-            } => write!(
-                f,
-                "Blocked. {} LAN. Allowing endpoint {}",
-                if *allow_lan { "Allowing" } else { "Blocking" },
-                allowed_endpoint,
-            ),
+                exemptions,
+                ..
+            } => {
+                write!(
+                    f,
+                    "Blocked. {} LAN. Allowing endpoint {}",
+                    if *allow_lan { "Allowing" } else { "Blocking" },
+                    allowed_endpoint,
+                )?;
+                if exemptions.ntp {
+                    write!(f, ", NTP")?;
+                }
+                for host in &exemptions.captive_portal_hosts {
+                    write!(f, ", HTTP to {}", host)?;
+                }
+                if exemptions.portal_login {
+                    write!(f, ", captive portal login")?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -211,6 +310,25 @@ impl fmt::Display for FirewallPolicy {
 /// by manipulating the OS firewall and DNS settings.
 pub struct Firewall {
     inner: imp::Firewall,
+    /// A description of the policy that was last applied successfully.
+    policy: Option<String>,
+    /// The policy that was last requested, without the temporary exceptions. It is applied again
+    /// whenever the temporary exceptions change.
+    requested_policy: Option<FirewallPolicy>,
+    /// Networks and ports that are allowed outside the tunnel on top of every policy, until the
+    /// point in time that they are paired with.
+    temporary_exceptions: Vec<(AllowedEndpoint, Instant)>,
+}
+
+/// The rules that the firewall has in place, as reported by the system.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FirewallRules {
+    /// The mechanism that enforces the rules, such as nftables or WFP.
+    pub backend: String,
+    /// The policy that the rules were generated from, if one is applied.
+    pub policy: Option<String>,
+    /// The rules, one per entry, in the notation of the backend.
+    pub rules: Vec<String>,
 }
 
 /// Arguments required when first initializing the firewall.
@@ -219,8 +337,15 @@ pub struct FirewallArguments {
     pub initialize_blocked: bool,
     /// This argument is required for the blocked state to configure the firewall correctly.
     pub allow_lan: bool,
+    /// The on-link networks that are reachable in the blocked state if `allow_lan` is set.
+    #[cfg(not(target_os = "android"))]
+    pub lan_networks: Vec<IpNetwork>,
     /// This argument is required for the blocked state to configure the firewall correctly.
     pub allowed_endpoint: Option<Endpoint>,
+    /// Used to block traffic if objects removed from WFP by other software cannot be reinstalled.
+    /// The objects are not monitored if this is `None`.
+    #[cfg(windows)]
+    pub command_tx: Option<Weak<crate::tunnel_state_machine::TunnelCommandSender>>,
 }
 
 impl Firewall {
@@ -228,22 +353,125 @@ impl Firewall {
     pub fn new(args: FirewallArguments) -> Result<Self, Error> {
         Ok(Firewall {
             inner: imp::Firewall::new(args)?,
+            policy: None,
+            requested_policy: None,
+            temporary_exceptions: vec![],
         })
     }
 
     /// Applies and starts enforcing the given `FirewallPolicy` Makes sure it is being kept in place
     /// until this method is called again with another policy, or until `reset_policy` is called.
+    /// Temporary exceptions that have not expired are added to the policy.
     pub fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<(), Error> {
         log::info!("Applying firewall policy: {}", policy);
-        self.inner.apply_policy(policy)
+        self.requested_policy = Some(policy.clone());
+
+        let description = policy.to_string();
+        let mut policy = policy;
+        let now = Instant::now();
+        self.temporary_exceptions
+            .retain(|(_, expires)| *expires > now);
+        let allowed_endpoints = policy.allowed_endpoints_mut();
+        for (endpoint, _) in &self.temporary_exceptions {
+            if !allowed_endpoints.contains(endpoint) {
+                allowed_endpoints.push(*endpoint);
+            }
+        }
+
+        let result = self.inner.apply_policy(policy);
+        self.policy = if result.is_ok() {
+            Some(description)
+        } else {
+            None
+        };
+        result
     }
 
     /// Resets/removes any currently enforced `FirewallPolicy`. Returns the system to the same state
     /// it had before any policy was applied through this `Firewall` instance.
     pub fn reset_policy(&mut self) -> Result<(), Error> {
         log::info!("Resetting firewall policy");
+        self.policy = None;
+        self.requested_policy = None;
         self.inner.reset_policy()
     }
+
+    /// Allows traffic to and from `endpoints` outside the tunnel for `duration`, in every policy.
+    /// Allowing an endpoint that is already allowed extends the time that it is allowed for. The
+    /// policy is only applied once, however many endpoints are allowed.
+    ///
+    /// The exceptions are not removed by themselves once they have expired.
+    /// `remove_expired_exceptions` must be called then.
+    pub fn allow_temporarily(
+        &mut self,
+        endpoints: Vec<AllowedEndpoint>,
+        duration: Duration,
+    ) -> Result<(), Error> {
+        let expires = Instant::now() + duration;
+        for endpoint in endpoints {
+            log::info!(
+                "Allowing {} outside the tunnel for {} seconds",
+                endpoint,
+                duration.as_secs()
+            );
+            match self
+                .temporary_exceptions
+                .iter_mut()
+                .find(|(existing, _)| *existing == endpoint)
+            {
+                Some((_, existing_expires)) => {
+                    *existing_expires = std::cmp::max(*existing_expires, expires)
+                }
+                None => self.temporary_exceptions.push((endpoint, expires)),
+            }
+        }
+        self.apply_requested_policy()
+    }
+
+    /// Removes the temporary exceptions that have expired from the applied policy.
+    pub fn remove_expired_exceptions(&mut self) -> Result<(), Error> {
+        let now = Instant::now();
+        let num_exceptions = self.temporary_exceptions.len();
+        self.temporary_exceptions
+            .retain(|(_, expires)| *expires > now);
+        if self.temporary_exceptions.len() == num_exceptions {
+            return Ok(());
+        }
+        log::info!("Removing expired temporary firewall exceptions");
+        self.apply_requested_policy()
+    }
+
+    /// Applies the last requested policy again, if a policy is applied. Nothing is blocked
+    /// otherwise, so there are no exceptions to add or remove.
+    fn apply_requested_policy(&mut self) -> Result<(), Error> {
+        match self.requested_policy.clone() {
+            Some(policy) => self.apply_policy(policy),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the rules that are currently in place, as reported by the system. Useful for
+    /// debugging the firewall.
+    pub fn describe_policy(&self) -> io::Result<FirewallRules> {
+        let mut rules = self.inner.describe_policy()?;
+        rules.policy = self.policy.clone();
+        Ok(rules)
+    }
+
+    /// Removes the firewall objects that are tagged as ours but are not used by this instance, such
+    /// as the ones of a previous version that crashed. Returns descriptions of the removed objects.
+    pub fn remove_orphaned_objects(&mut self) -> Result<Vec<String>, Error> {
+        log::info!("Removing orphaned firewall objects");
+        self.inner.remove_orphaned_objects()
+    }
+
+    /// Removes the provider, sublayers and filters registered with WFP, including the persistent
+    /// ones, installs them anew and reapplies the current policy.
+    #[cfg(windows)]
+    pub fn repair_objects(&mut self) -> Result<(), Error> {
+        log::info!("Reinstalling the WFP objects");
+        self.inner.repair_objects()
+    }
 }
 
 /// Abstract firewall interaction trait. Used by the OS specific implementations.
@@ -260,4 +488,12 @@ trait FirewallT: Sized {
     /// Revert the system firewall state to what it was before this instance started
     /// modifying the system.
     fn reset_policy(&mut self) -> Result<(), Self::Error>;
+
+    /// Read back the rules that this instance has added to the system firewall. The policy of
+    /// the returned rules is filled in by the caller.
+    fn describe_policy(&self) -> io::Result<FirewallRules>;
+
+    /// Remove the objects that are tagged as belonging to the firewall but are not used by this
+    /// instance, and describe them.
+    fn remove_orphaned_objects(&mut self) -> Result<Vec<String>, Self::Error>;
 }
@@ -0,0 +1,263 @@
+use super::PersistentPolicy;
+use crate::firewall::{
+    ALLOWED_LAN_MULTICAST_NETS, ALLOWED_LAN_NETS, DHCPV4_CLIENT_PORT, DHCPV4_SERVER_PORT,
+    DHCPV6_CLIENT_PORT, DHCPV6_SERVER_ADDRS, DHCPV6_SERVER_PORT, IPV6_LINK_LOCAL,
+};
+use ipnetwork::IpNetwork;
+use std::{fs, io, path::Path};
+use talpid_types::net::TransportProtocol;
+
+/// The ruleset replaces the table of the firewall, which in turn replaces the ruleset when the
+/// daemon applies its first policy.
+const TABLE_NAME: &str = "mullvad";
+
+const RULESET_DIR: &str = "/etc/mullvad-vpn";
+const RULESET_PATH: &str = "/etc/mullvad-vpn/persistent-firewall.nft";
+const UNIT_NAME: &str = "mullvad-persistent-firewall.service";
+const UNIT_PATH: &str = "/etc/systemd/system/mullvad-persistent-firewall.service";
+
+const UNIT: &str = r#"# Generated by the Mullvad VPN daemon. Loads the persistent lockdown policy before the
+# network is brought up.
+[Unit]
+Description=Mullvad persistent lockdown
+DefaultDependencies=no
+Before=network-pre.target mullvad-early-boot-blocking.service mullvad-daemon.service
+Wants=network-pre.target
+
+[Service]
+Type=oneshot
+ExecStart=/usr/bin/env nft -f /etc/mullvad-vpn/persistent-firewall.nft
+
+[Install]
+WantedBy=sysinit.target
+"#;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to create {}", _0)]
+    CreateDir(&'static str, #[error(source)] io::Error),
+
+    #[error(display = "Failed to write {}", _0)]
+    Write(&'static str, #[error(source)] io::Error),
+
+    #[error(display = "Failed to remove {}", _0)]
+    Remove(&'static str, #[error(source)] io::Error),
+
+    #[error(display = "Failed to enable or disable the systemd unit")]
+    Systemctl(#[error(source)] io::Error),
+
+    #[error(display = "Failed to load the ruleset")]
+    LoadRuleset(#[error(source)] io::Error),
+}
+
+pub fn install(policy: &PersistentPolicy) -> Result<(), Error> {
+    fs::create_dir_all(RULESET_DIR).map_err(|e| Error::CreateDir(RULESET_DIR, e))?;
+    fs::write(RULESET_PATH, ruleset(policy)).map_err(|e| Error::Write(RULESET_PATH, e))?;
+    fs::write(UNIT_PATH, UNIT).map_err(|e| Error::Write(UNIT_PATH, e))?;
+    duct::cmd!("systemctl", "enable", UNIT_NAME)
+        .stdout_null()
+        .stderr_null()
+        .run()
+        .map_err(Error::Systemctl)?;
+    Ok(())
+}
+
+pub fn uninstall() -> Result<(), Error> {
+    if Path::new(UNIT_PATH).exists() {
+        duct::cmd!("systemctl", "disable", UNIT_NAME)
+            .stdout_null()
+            .stderr_null()
+            .run()
+            .map_err(Error::Systemctl)?;
+    }
+    remove_file(UNIT_PATH)?;
+    remove_file(RULESET_PATH)
+}
+
+pub fn is_installed() -> bool {
+    Path::new(RULESET_PATH).exists()
+}
+
+/// Loads the installed ruleset, if any.
+pub(crate) fn enforce() -> Result<(), Error> {
+    if !is_installed() {
+        return Ok(());
+    }
+    duct::cmd!("nft", "-f", RULESET_PATH)
+        .stdout_null()
+        .run()
+        .map_err(Error::LoadRuleset)?;
+    Ok(())
+}
+
+fn remove_file(path: &'static str) -> Result<(), Error> {
+    match fs::remove_file(path) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(Error::Remove(path, error)),
+        _ => Ok(()),
+    }
+}
+
+fn ruleset(policy: &PersistentPolicy) -> String {
+    let mut input = vec![r#"iif "lo" accept"#.to_owned()];
+    let mut output = vec![r#"oif "lo" accept"#.to_owned()];
+
+    output.push(format!(
+        "udp sport {} udp dport {} accept",
+        DHCPV4_CLIENT_PORT, DHCPV4_SERVER_PORT
+    ));
+    input.push(format!(
+        "udp sport {} udp dport {} accept",
+        DHCPV4_SERVER_PORT, DHCPV4_CLIENT_PORT
+    ));
+    output.push(format!(
+        "ip6 daddr {{ {}, {} }} udp sport {} udp dport {} accept",
+        DHCPV6_SERVER_ADDRS[0], DHCPV6_SERVER_ADDRS[1], DHCPV6_CLIENT_PORT, DHCPV6_SERVER_PORT
+    ));
+    input.push(format!(
+        "ip6 saddr {} udp sport {} udp dport {} accept",
+        *IPV6_LINK_LOCAL, DHCPV6_SERVER_PORT, DHCPV6_CLIENT_PORT
+    ));
+    output.push(
+        "icmpv6 type { nd-router-solicit, nd-neighbor-solicit, nd-neighbor-advert } accept"
+            .to_owned(),
+    );
+    input.push(
+        "icmpv6 type { nd-router-advert, nd-neighbor-solicit, nd-neighbor-advert, nd-redirect } accept"
+            .to_owned(),
+    );
+
+    // DNS requests must not leak to any of the networks below.
+    output.push("meta l4proto { tcp, udp } th dport 53 drop".to_owned());
+
+    if policy.allow_lan {
+        for net in &*ALLOWED_LAN_NETS {
+            output.push(format!("{} daddr {} accept", family(net), net));
+            input.push(format!("{} saddr {} accept", family(net), net));
+        }
+        for net in &*ALLOWED_LAN_MULTICAST_NETS {
+            output.push(format!("{} daddr {} accept", family(net), net));
+        }
+    }
+
+    for endpoint in &policy.allowed_endpoints {
+        let net = endpoint.network;
+        for protocol in endpoint.rule_protocols() {
+            let (out_match, in_match) = match (protocol, endpoint.port) {
+                (Some(protocol), Some(port)) => (
+                    format!(" {} dport {}", nft_protocol(protocol), port),
+                    format!(" {} sport {}", nft_protocol(protocol), port),
+                ),
+                (Some(protocol), None) => {
+                    let l4proto = format!(" meta l4proto {}", nft_protocol(protocol));
+                    (l4proto.clone(), l4proto)
+                }
+                (None, _) => (String::new(), String::new()),
+            };
+            output.push(format!(
+                "{} daddr {}{} accept",
+                family(&net),
+                net,
+                out_match
+            ));
+            input.push(format!("{} saddr {}{} accept", family(&net), net, in_match));
+        }
+    }
+
+    format!(
+        "# Generated by the Mullvad VPN daemon. Blocks all traffic except what is listed below.\n\
+         table inet {table}\n\
+         delete table inet {table}\n\
+         table inet {table} {{\n\
+         {input}\
+         {output}\
+         {forward}\
+         }}\n",
+        table = TABLE_NAME,
+        input = chain("input", &input),
+        output = chain("output", &output),
+        forward = chain("forward", &[]),
+    )
+}
+
+fn chain(hook: &str, rules: &[String]) -> String {
+    let mut chain = format!(
+        "\tchain {hook} {{\n\t\ttype filter hook {hook} priority 0; policy drop;\n",
+        hook = hook
+    );
+    for rule in rules {
+        chain.push_str("\t\t");
+        chain.push_str(rule);
+        chain.push('\n');
+    }
+    chain.push_str("\t}\n");
+    chain
+}
+
+fn family(net: &IpNetwork) -> &'static str {
+    match net {
+        IpNetwork::V4(_) => "ip",
+        IpNetwork::V6(_) => "ip6",
+    }
+}
+
+fn nft_protocol(protocol: TransportProtocol) -> &'static str {
+    match protocol {
+        TransportProtocol::Udp => "udp",
+        TransportProtocol::Tcp => "tcp",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use talpid_types::net::AllowedEndpoint;
+
+    #[test]
+    fn test_ruleset() {
+        let policy = PersistentPolicy {
+            allow_lan: false,
+            allowed_endpoints: vec![AllowedEndpoint::new(
+                "203.0.113.0/24".parse().unwrap(),
+                Some(443),
+                None,
+            )],
+        };
+        let ruleset = ruleset(&policy);
+
+        assert!(ruleset.starts_with("# Generated"));
+        assert!(ruleset.contains("delete table inet mullvad\n"));
+        assert!(ruleset.contains(
+            "\tchain forward {\n\t\ttype filter hook forward priority 0; policy drop;\n\t}\n"
+        ));
+        assert!(!ruleset.contains("10.0.0.0/8"));
+
+        let dns = ruleset.find("th dport 53 drop").unwrap();
+        for protocol in &["tcp", "udp"] {
+            let outgoing = format!("ip daddr 203.0.113.0/24 {} dport 443 accept", protocol);
+            let incoming = format!("ip saddr 203.0.113.0/24 {} sport 443 accept", protocol);
+            assert!(ruleset.find(&outgoing).unwrap() > dns);
+            assert!(ruleset.contains(&incoming));
+        }
+    }
+
+    #[test]
+    fn test_ruleset_allow_lan() {
+        let policy = PersistentPolicy {
+            allow_lan: true,
+            allowed_endpoints: vec![],
+        };
+        let ruleset = ruleset(&policy);
+
+        assert!(ruleset.contains("ip daddr 10.0.0.0/8 accept"));
+        assert!(ruleset.contains("ip saddr 10.0.0.0/8 accept"));
+        assert!(ruleset.contains("ip6 daddr ff02::/16 accept"));
+        assert!(!ruleset.contains("ip6 saddr ff02::/16 accept"));
+    }
+
+    #[test]
+    fn test_unit_paths() {
+        assert!(RULESET_PATH.starts_with(RULESET_DIR));
+        assert!(UNIT.contains(&format!("ExecStart=/usr/bin/env nft -f {}\n", RULESET_PATH)));
+    }
+}
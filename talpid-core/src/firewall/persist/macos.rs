@@ -0,0 +1,176 @@
+use super::PersistentPolicy;
+use crate::firewall::{
+    ALLOWED_LAN_MULTICAST_NETS, ALLOWED_LAN_NETS, DHCPV4_CLIENT_PORT, DHCPV4_SERVER_PORT,
+    DHCPV6_CLIENT_PORT, DHCPV6_SERVER_ADDRS, DHCPV6_SERVER_PORT, IPV6_LINK_LOCAL,
+};
+use ipnetwork::IpNetwork;
+use std::{fs, io, path::Path, process::Command};
+use talpid_types::net::TransportProtocol;
+
+/// The rules are loaded into the anchor of the firewall, which replaces them when the daemon
+/// applies its first policy.
+const ANCHOR_NAME: &str = "mullvad";
+
+const RULES_PATH: &str = "/etc/mullvad-vpn/persistent-firewall.conf";
+const LAUNCH_DAEMON_PATH: &str = "/Library/LaunchDaemons/net.mullvad.persistent-firewall.plist";
+
+/// Loads the system ruleset with a reference to the anchor, then loads the rules into it. The
+/// launch daemon is not loaded when it is installed, so this only runs at boot.
+const LAUNCH_DAEMON: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>Label</key>
+	<string>net.mullvad.persistent-firewall</string>
+	<key>ProgramArguments</key>
+	<array>
+		<string>/bin/sh</string>
+		<string>-c</string>
+		<string>(cat /etc/pf.conf; echo 'anchor "mullvad"') | /sbin/pfctl -f - &amp;&amp; /sbin/pfctl -a mullvad -f /etc/mullvad-vpn/persistent-firewall.conf &amp;&amp; /sbin/pfctl -E</string>
+	</array>
+	<key>RunAtLoad</key>
+	<true/>
+</dict>
+</plist>
+"#;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to write {}", _0)]
+    Write(&'static str, #[error(source)] io::Error),
+
+    #[error(display = "Failed to remove {}", _0)]
+    Remove(&'static str, #[error(source)] io::Error),
+
+    #[error(display = "Failed to load the rules")]
+    LoadRules(#[error(source)] io::Error),
+}
+
+pub fn install(policy: &PersistentPolicy) -> Result<(), Error> {
+    fs::write(RULES_PATH, rules(policy)).map_err(|e| Error::Write(RULES_PATH, e))?;
+    fs::write(LAUNCH_DAEMON_PATH, LAUNCH_DAEMON).map_err(|e| Error::Write(LAUNCH_DAEMON_PATH, e))
+}
+
+pub fn uninstall() -> Result<(), Error> {
+    remove_file(LAUNCH_DAEMON_PATH)?;
+    remove_file(RULES_PATH)
+}
+
+pub fn is_installed() -> bool {
+    Path::new(RULES_PATH).exists()
+}
+
+/// Loads the installed rules, if any, into the anchor. The anchor must already be referenced by
+/// the main ruleset.
+pub(crate) fn enforce() -> Result<(), Error> {
+    if !is_installed() {
+        return Ok(());
+    }
+    let output = Command::new("/sbin/pfctl")
+        .args(&["-a", ANCHOR_NAME, "-f", RULES_PATH])
+        .output()
+        .map_err(Error::LoadRules)?;
+    if !output.status.success() {
+        return Err(Error::LoadRules(io::Error::new(
+            io::ErrorKind::Other,
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        )));
+    }
+    Ok(())
+}
+
+fn remove_file(path: &'static str) -> Result<(), Error> {
+    match fs::remove_file(path) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(Error::Remove(path, error)),
+        _ => Ok(()),
+    }
+}
+
+fn rules(policy: &PersistentPolicy) -> String {
+    let mut rules = vec![
+        "# Generated by the Mullvad VPN daemon. Blocks all traffic except what is listed below."
+            .to_owned(),
+        "pass quick on lo0 all flags any".to_owned(),
+        format!(
+            "pass out quick inet proto udp from any port {} to any port {}",
+            DHCPV4_CLIENT_PORT, DHCPV4_SERVER_PORT
+        ),
+        format!(
+            "pass in quick inet proto udp from any port {} to any port {}",
+            DHCPV4_SERVER_PORT, DHCPV4_CLIENT_PORT
+        ),
+        format!(
+            "pass out quick inet6 proto udp from any port {} to {{ {}, {} }} port {}",
+            DHCPV6_CLIENT_PORT, DHCPV6_SERVER_ADDRS[0], DHCPV6_SERVER_ADDRS[1], DHCPV6_SERVER_PORT
+        ),
+        format!(
+            "pass in quick inet6 proto udp from {} port {} to any port {}",
+            *IPV6_LINK_LOCAL, DHCPV6_SERVER_PORT, DHCPV6_CLIENT_PORT
+        ),
+        "pass out quick inet6 proto icmp6 icmp6-type { routersol, neighbrsol, neighbradv }"
+            .to_owned(),
+        "pass in quick inet6 proto icmp6 icmp6-type { routeradv, neighbrsol, neighbradv, redir }"
+            .to_owned(),
+        // DNS requests must not leak to any of the networks below.
+        "block drop out quick proto { tcp, udp } from any to any port 53".to_owned(),
+    ];
+
+    if policy.allow_lan {
+        for net in &*ALLOWED_LAN_NETS {
+            rules.push(format!("pass out quick {} to {}", family(net), net));
+            rules.push(format!("pass in quick {} from {}", family(net), net));
+        }
+        for net in &*ALLOWED_LAN_MULTICAST_NETS {
+            rules.push(format!("pass out quick {} to {}", family(net), net));
+        }
+    }
+
+    for endpoint in &policy.allowed_endpoints {
+        let net = endpoint.network;
+        for protocol in endpoint.rule_protocols() {
+            let protocol = match protocol {
+                Some(protocol) => format!(" proto {}", pf_protocol(protocol)),
+                None => String::new(),
+            };
+            let port = match endpoint.port {
+                Some(port) => format!(" port {}", port),
+                None => String::new(),
+            };
+            rules.push(format!(
+                "pass out quick {}{} from any to {}{}",
+                family(&net),
+                protocol,
+                net,
+                port
+            ));
+            rules.push(format!(
+                "pass in quick {}{} from {}{} to any",
+                family(&net),
+                protocol,
+                net,
+                port
+            ));
+        }
+    }
+
+    rules.push("block drop quick all".to_owned());
+
+    let mut rules = rules.join("\n");
+    rules.push('\n');
+    rules
+}
+
+fn family(net: &IpNetwork) -> &'static str {
+    match net {
+        IpNetwork::V4(_) => "inet",
+        IpNetwork::V6(_) => "inet6",
+    }
+}
+
+fn pf_protocol(protocol: TransportProtocol) -> &'static str {
+    match protocol {
+        TransportProtocol::Udp => "udp",
+        TransportProtocol::Tcp => "tcp",
+    }
+}
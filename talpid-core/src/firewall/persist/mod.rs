@@ -0,0 +1,51 @@
+//! A blocking policy that outlives the daemon. Once installed, it is enforced when the firewall is
+//! dropped and when the machine boots, until the daemon applies a policy of its own.
+//!
+//! On Linux, the policy is an nftables ruleset that a systemd unit loads during early boot. On
+//! macOS, it is a PF anchor that a launch daemon loads at boot. On Windows, the persistent and
+//! boot-time WFP filters are added when the firewall is dropped; these block all traffic, so the
+//! exceptions of the policy are not applied there.
+use talpid_types::net::AllowedEndpoint;
+
+#[cfg(target_os = "linux")]
+#[path = "linux.rs"]
+mod imp;
+
+#[cfg(target_os = "macos")]
+#[path = "macos.rs"]
+mod imp;
+
+#[cfg(windows)]
+#[path = "windows.rs"]
+mod imp;
+
+#[cfg(unix)]
+pub(crate) use self::imp::enforce;
+pub use self::imp::Error;
+
+/// The traffic that the persistent policy lets through, in addition to loopback traffic, DHCP and
+/// NDP. DNS requests are always blocked.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct PersistentPolicy {
+    /// Allow traffic to and from private networks.
+    pub allow_lan: bool,
+    /// Networks that traffic is allowed to and from.
+    pub allowed_endpoints: Vec<AllowedEndpoint>,
+}
+
+/// Installs `policy`, replacing any previously installed policy. It is not enforced until the
+/// firewall is dropped or the machine reboots.
+pub fn install(policy: &PersistentPolicy) -> Result<(), Error> {
+    imp::install(policy)
+}
+
+/// Removes the installed policy, if any. A policy that is already enforced is left in place until
+/// the firewall is reset.
+pub fn uninstall() -> Result<(), Error> {
+    imp::uninstall()
+}
+
+/// Returns whether a policy is installed.
+pub fn is_installed() -> bool {
+    imp::is_installed()
+}
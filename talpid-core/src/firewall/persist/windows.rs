@@ -0,0 +1,29 @@
+use super::PersistentPolicy;
+use std::{
+    convert::Infallible,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// The persistent filters that WFP offers block all traffic and are added by the firewall itself,
+/// so there is nothing to write to disk. The policy only has to be remembered until the firewall
+/// is dropped.
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+pub type Error = Infallible;
+
+pub fn install(policy: &PersistentPolicy) -> Result<(), Error> {
+    if policy.allow_lan || !policy.allowed_endpoints.is_empty() {
+        log::debug!("The persistent firewall policy blocks all traffic on Windows");
+    }
+    INSTALLED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+pub fn uninstall() -> Result<(), Error> {
+    INSTALLED.store(false, Ordering::SeqCst);
+    Ok(())
+}
+
+pub fn is_installed() -> bool {
+    INSTALLED.load(Ordering::SeqCst)
+}
@@ -1,14 +1,31 @@
 use crate::{logging::windows::log_sink, tunnel::TunnelMetadata};
 
-use std::{ffi::OsString, iter, net::IpAddr, path::Path, ptr};
+use std::{
+    ffi::OsString,
+    io, iter,
+    net::IpAddr,
+    path::Path,
+    ptr,
+    sync::{mpsc, Arc, Mutex, Weak},
+    thread,
+    time::Duration,
+};
 
 use self::winfw::*;
-use super::{FirewallArguments, FirewallPolicy, FirewallT};
-use crate::winnet;
+use super::{FirewallArguments, FirewallPolicy, FirewallRules, FirewallT};
+use crate::{
+    tunnel_state_machine::{TunnelCommand, TunnelCommandSender},
+    winnet,
+};
+use ipnetwork::IpNetwork;
 use log::{debug, error, trace};
 use std::os::windows::ffi::OsStrExt;
-use talpid_types::{net::Endpoint, tunnel::FirewallPolicyError};
-use widestring::WideCString;
+use talpid_types::{
+    net::{AllowedEndpoint, Endpoint, SystemServiceExemptions, TransportProtocol},
+    tunnel::{ErrorStateCause, FirewallPolicyError},
+    ErrorExt,
+};
+use widestring::{WideCStr, WideCString};
 
 
 /// Errors that can happen when configuring the Windows firewall.
@@ -39,15 +56,52 @@ pub enum Error {
     #[error(display = "Failed to reset firewall policies")]
     ResettingPolicy(#[error(source)] FirewallPolicyError),
 
+    /// Failure to reinstall objects that were removed from WFP
+    #[error(display = "Failed to reinstall removed WFP objects")]
+    RepairingObjects(#[error(source)] FirewallPolicyError),
+
     /// Failure to set virtual adapter metric
     #[error(display = "Unable to set virtual adapter metric")]
     SetTunMetric(#[error(source)] crate::winnet::Error),
 }
 
+impl Error {
+    fn policy_error(&self) -> FirewallPolicyError {
+        match self {
+            Error::ApplyingConnectingPolicy(error)
+            | Error::ApplyingConnectedPolicy(error)
+            | Error::ApplyingBlockedPolicy(error)
+            | Error::ResettingPolicy(error)
+            | Error::RepairingObjects(error) => error.clone(),
+            _ => FirewallPolicyError::Generic,
+        }
+    }
+}
+
 const WINFW_TIMEOUT_SECONDS: u32 = 2;
 
+/// How often to check that the objects added to WFP have not been removed by other software.
+const OBJECT_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// WinFw has a fixed number of filter identifiers for firewall exceptions. Must match
+/// `MullvadGuids::MaxFirewallExceptions`.
+const MAX_FIREWALL_EXCEPTIONS: usize = 64;
+
 /// The Windows implementation for the firewall and DNS.
-pub struct Firewall(());
+pub struct Firewall {
+    /// The policy that was last applied, if any. The lock is held during every call into WinFw
+    /// that changes the policy, so that the object watcher never runs at the same time.
+    policy: Arc<Mutex<ActivePolicy>>,
+    watcher: Option<(mpsc::Sender<()>, thread::JoinHandle<()>)>,
+}
+
+/// A policy that has been applied, along with the subnets of the Hyper-V NAT switches that it
+/// permitted at the time.
+#[derive(Default)]
+struct ActivePolicy {
+    policy: Option<FirewallPolicy>,
+    virtual_networks: Vec<IpNetwork>,
+}
 
 impl FirewallT for Firewall {
     type Error = Error;
@@ -56,7 +110,22 @@ impl FirewallT for Firewall {
         let logging_context = b"WinFw\0".as_ptr();
 
         if args.initialize_blocked {
-            let cfg = &WinFwSettings::new(args.allow_lan);
+            let lan_network_ips: Vec<WideCString> = args
+                .lan_networks
+                .iter()
+                .map(|network| widestring_ip(network.ip()))
+                .collect();
+            // lan_network_ips has to outlive winfw_lan_networks
+            let winfw_lan_networks: Vec<WinFwNetwork> = args
+                .lan_networks
+                .iter()
+                .zip(&lan_network_ips)
+                .map(|(network, ip)| WinFwNetwork {
+                    ip: ip.as_ptr(),
+                    prefix: network.prefix(),
+                })
+                .collect();
+            let cfg = &WinFwSettings::new(args.allow_lan, &winfw_lan_networks, &[], &[]);
             let allowed_endpoint_ip = args
                 .allowed_endpoint
                 .map(|endpoint| (endpoint, widestring_ip(endpoint.address.ip())));
@@ -86,60 +155,132 @@ impl FirewallT for Firewall {
         }
 
         trace!("Successfully initialized windows firewall module");
-        Ok(Firewall(()))
+
+        let initial_policy = if args.initialize_blocked {
+            args.allowed_endpoint
+                .map(|allowed_endpoint| FirewallPolicy::Blocked {
+                    allow_lan: args.allow_lan,
+                    lan_networks: args.lan_networks.clone(),
+                    allowed_endpoint,
+                    exemptions: SystemServiceExemptions::default(),
+                    allowed_endpoints: vec![],
+                    allow_virtual_networks: false,
+                })
+        } else {
+            None
+        };
+        let policy = Arc::new(Mutex::new(ActivePolicy {
+            policy: initial_policy,
+            virtual_networks: vec![],
+        }));
+        let watcher = args.command_tx.map(|command_tx| {
+            let (shutdown_tx, shutdown_rx) = mpsc::channel();
+            let policy = policy.clone();
+            let handle = thread::spawn(move || watch_objects(policy, shutdown_rx, command_tx));
+            (shutdown_tx, handle)
+        });
+
+        Ok(Firewall { policy, watcher })
     }
 
     fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<(), Self::Error> {
-        match policy {
-            FirewallPolicy::Connecting {
-                peer_endpoint,
-                tunnel,
-                allow_lan,
-                allowed_endpoint,
-                relay_client,
-            } => {
-                let cfg = &WinFwSettings::new(allow_lan);
-                self.set_connecting_state(
-                    &peer_endpoint,
-                    &cfg,
-                    &tunnel,
-                    &allowed_endpoint,
-                    &relay_client,
-                )
-            }
-            FirewallPolicy::Connected {
-                peer_endpoint,
-                tunnel,
-                allow_lan,
-                dns_servers,
-                relay_client,
-            } => {
-                let cfg = &WinFwSettings::new(allow_lan);
-                self.set_connected_state(&peer_endpoint, &cfg, &tunnel, &dns_servers, &relay_client)
-            }
-            FirewallPolicy::Blocked {
-                allow_lan,
-                allowed_endpoint,
-            } => {
-                let cfg = &WinFwSettings::new(allow_lan);
-                self.set_blocked_state(&cfg, &allowed_endpoint)
-            }
-        }
+        let mut active_policy = self.policy.lock().unwrap();
+        let virtual_networks = virtual_networks(&policy);
+        apply_policy(&policy, &virtual_networks)?;
+        *active_policy = ActivePolicy {
+            policy: Some(policy),
+            virtual_networks,
+        };
+        Ok(())
     }
 
     fn reset_policy(&mut self) -> Result<(), Self::Error> {
+        let mut active_policy = self.policy.lock().unwrap();
         unsafe { WinFw_Reset().into_result().map_err(Error::ResettingPolicy) }?;
+        *active_policy = ActivePolicy::default();
         Ok(())
     }
+
+    fn describe_policy(&self) -> io::Result<FirewallRules> {
+        // Keeps the watcher from repairing the objects while they are listed
+        let _active_policy = self.policy.lock().unwrap();
+        let mut rules: Vec<String> = vec![];
+        let listed = unsafe {
+            WinFw_DescribeFilters(
+                Some(filter_sink),
+                &mut rules as *mut Vec<String> as *mut libc::c_void,
+            )
+        };
+        if !listed {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Failed to list the WFP filters",
+            ));
+        }
+        Ok(FirewallRules {
+            backend: "WFP".to_owned(),
+            policy: None,
+            rules,
+        })
+    }
+
+    fn remove_orphaned_objects(&mut self) -> Result<Vec<String>, Self::Error> {
+        // All objects are registered under our provider. Reinstalling them removes the filters that
+        // this version does not add, such as the ones of a previous version.
+        let filters_before = self
+            .describe_policy()
+            .map(|rules| rules.rules)
+            .unwrap_or_default();
+        self.repair_objects()?;
+        let filters_after = self
+            .describe_policy()
+            .map(|rules| rules.rules)
+            .unwrap_or_default();
+
+        let mut removed = vec![];
+        for filter in filters_before {
+            if !filters_after.contains(&filter) {
+                let description = format!("WFP filter {}", filter);
+                log::info!("Removed orphaned firewall object: {}", description);
+                removed.push(description);
+            }
+        }
+        Ok(removed)
+    }
+}
+
+impl Firewall {
+    pub fn repair_objects(&mut self) -> Result<(), Error> {
+        let mut active_policy = self.policy.lock().unwrap();
+        repair_objects(&mut active_policy)
+    }
+}
+
+/// Collects the filter descriptions passed by `WinFw_DescribeFilters` into the `Vec<String>` that
+/// `context` points to.
+extern "system" fn filter_sink(description: *const libc::wchar_t, context: *mut libc::c_void) {
+    if description.is_null() {
+        return;
+    }
+    let rules = unsafe { &mut *(context as *mut Vec<String>) };
+    rules.push(unsafe { WideCStr::from_ptr_str(description) }.to_string_lossy());
 }
 
 impl Drop for Firewall {
     fn drop(&mut self) {
-        if unsafe {
-            WinFw_Deinitialize(WinFwCleanupPolicy::ContinueBlocking)
-                .into_result()
-                .is_ok()
-        } {
+        if let Some((shutdown_tx, handle)) = self.watcher.take() {
+            let _ = shutdown_tx.send(());
+            if handle.join().is_err() {
+                error!("WFP object watcher panicked");
+            }
+        }
+
+        let cleanup_policy = if super::persist::is_installed() {
+            WinFwCleanupPolicy::Block
+        } else {
+            WinFwCleanupPolicy::ContinueBlocking
+        };
+        if unsafe { WinFw_Deinitialize(cleanup_policy).into_result().is_ok() } {
             trace!("Successfully deinitialized windows firewall module");
         } else {
             error!("Failed to deinitialize windows firewall module");
@@ -147,144 +288,400 @@ impl Drop for Firewall {
     }
 }
 
-impl Firewall {
-    fn set_connecting_state(
-        &mut self,
-        endpoint: &Endpoint,
-        winfw_settings: &WinFwSettings,
-        tunnel_metadata: &Option<TunnelMetadata>,
-        allowed_endpoint: &Endpoint,
-        relay_client: &Path,
-    ) -> Result<(), Error> {
-        trace!("Applying 'connecting' firewall policy");
-        let ip_str = widestring_ip(endpoint.address.ip());
-        let winfw_relay = WinFwEndpoint {
-            ip: ip_str.as_ptr(),
-            port: endpoint.address.port(),
-            protocol: WinFwProt::from(endpoint.protocol),
-        };
-
-        let mut relay_client: Vec<u16> = relay_client.as_os_str().encode_wide().collect();
-        relay_client.push(0u16);
-
-        let allowed_endpoint_ip = widestring_ip(allowed_endpoint.address.ip());
-        let winfw_allowed_endpoint = Some(WinFwEndpoint {
-            ip: allowed_endpoint_ip.as_ptr(),
-            port: allowed_endpoint.address.port(),
-            protocol: WinFwProt::from(allowed_endpoint.protocol),
-        });
+/// Periodically checks that the objects added to WFP are still in place. Security software
+/// sometimes removes them. If so, they are reinstalled along with the last applied policy. If
+/// that fails, the tunnel state machine is told to enter the error state.
+///
+/// The policy is also reapplied when the subnets of the Hyper-V NAT switches that it permits
+/// have changed, since WSL2 creates its switch when it is first started.
+fn watch_objects(
+    policy: Arc<Mutex<ActivePolicy>>,
+    shutdown_rx: mpsc::Receiver<()>,
+    command_tx: Weak<TunnelCommandSender>,
+) {
+    while let Err(mpsc::RecvTimeoutError::Timeout) = shutdown_rx.recv_timeout(OBJECT_CHECK_INTERVAL)
+    {
+        let mut active_policy = policy.lock().unwrap();
+
+        match unsafe { WinFw_VerifyObjects() } {
+            WinFwObjectStatus::Intact => {
+                if let Err(error) = refresh_virtual_networks(&mut active_policy) {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg(
+                            "Failed to apply the firewall policy for the new virtual networks"
+                        )
+                    );
+                }
+                continue;
+            }
+            WinFwObjectStatus::Missing => (),
+            WinFwObjectStatus::GeneralFailure => {
+                log::warn!("Failed to verify that the WFP objects are in place");
+                continue;
+            }
+        }
 
-        let interface_wstr = tunnel_metadata.as_ref().map(|metadata| {
-            WideCString::new(metadata.interface.encode_utf16().collect::<Vec<_>>()).unwrap()
-        });
-        let interface_wstr_ptr = if let Some(ref wstr) = interface_wstr {
-            wstr.as_ptr()
+        log::warn!("WFP objects have been removed by other software. Reinstalling them");
+
+        let result = repair_objects(&mut active_policy);
+        if let Err(error) = result {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to reinstall removed WFP objects")
+            );
+            *active_policy = ActivePolicy::default();
+            let cause = ErrorStateCause::SetFirewallPolicyError(error.policy_error());
+            if let Some(tx) = command_tx.upgrade() {
+                if tx.try_send(TunnelCommand::Block(cause)).is_err() {
+                    log::error!("Failed to send command to the tunnel state machine");
+                }
+            }
         } else {
-            ptr::null()
-        };
-
-        unsafe {
-            WinFw_ApplyPolicyConnecting(
-                winfw_settings,
-                &winfw_relay,
-                relay_client.as_ptr(),
-                interface_wstr_ptr,
-                winfw_allowed_endpoint.as_ptr(),
-            )
-            .into_result()
-            .map_err(Error::ApplyingConnectingPolicy)
+            log::info!("Reinstalled the removed WFP objects");
         }
     }
+}
 
-    fn set_connected_state(
-        &mut self,
-        endpoint: &Endpoint,
-        winfw_settings: &WinFwSettings,
-        tunnel_metadata: &TunnelMetadata,
-        dns_servers: &[IpAddr],
-        relay_client: &Path,
-    ) -> Result<(), Error> {
-        trace!("Applying 'connected' firewall policy");
-        let ip_str = widestring_ip(endpoint.address.ip());
-        let v4_gateway = widestring_ip(tunnel_metadata.ipv4_gateway.into());
-        let v6_gateway = tunnel_metadata
-            .ipv6_gateway
-            .map(|v6_ip| widestring_ip(v6_ip.into()));
-
-        let tunnel_alias =
-            WideCString::new(tunnel_metadata.interface.encode_utf16().collect::<Vec<_>>()).unwrap();
-
-        // ip_str, gateway_str and tunnel_alias have to outlive winfw_relay
-        let winfw_relay = WinFwEndpoint {
-            ip: ip_str.as_ptr(),
-            port: endpoint.address.port(),
-            protocol: WinFwProt::from(endpoint.protocol),
-        };
+fn repair_objects(active_policy: &mut ActivePolicy) -> Result<(), Error> {
+    unsafe { WinFw_RepairObjects().into_result() }.map_err(Error::RepairingObjects)?;
+    if let Some(policy) = &active_policy.policy {
+        let virtual_networks = virtual_networks(policy);
+        apply_policy(policy, &virtual_networks)?;
+        active_policy.virtual_networks = virtual_networks;
+    }
+    Ok(())
+}
 
-        let metrics_set = winnet::ensure_best_metric_for_interface(&tunnel_metadata.interface)
-            .map_err(Error::SetTunMetric)?;
+/// Reapplies the active policy if the subnets of the Hyper-V NAT switches that it permits have
+/// changed since it was applied.
+fn refresh_virtual_networks(active_policy: &mut ActivePolicy) -> Result<(), Error> {
+    let policy = match &active_policy.policy {
+        Some(policy) => policy,
+        None => return Ok(()),
+    };
+    let virtual_networks = virtual_networks(policy);
+    if virtual_networks == active_policy.virtual_networks {
+        return Ok(());
+    }
+    log::info!(
+        "Virtual networks changed. Permitting: {:?}",
+        virtual_networks
+    );
+    apply_policy(policy, &virtual_networks)?;
+    active_policy.virtual_networks = virtual_networks;
+    Ok(())
+}
 
-        if metrics_set {
-            debug!("Network interface metrics were changed");
-        } else {
-            debug!("Network interface metrics were not changed");
+/// Returns the subnets of the Hyper-V NAT switches if `policy` permits them.
+fn virtual_networks(policy: &FirewallPolicy) -> Vec<IpNetwork> {
+    let allow_virtual_networks = match policy {
+        FirewallPolicy::Connecting {
+            allow_virtual_networks,
+            ..
         }
+        | FirewallPolicy::Connected {
+            allow_virtual_networks,
+            ..
+        }
+        | FirewallPolicy::Blocked {
+            allow_virtual_networks,
+            ..
+        } => *allow_virtual_networks,
+    };
+    if !allow_virtual_networks {
+        return vec![];
+    }
+    crate::windows::nat_switch_networks().unwrap_or_else(|error| {
+        log::error!(
+            "{}",
+            error.display_chain_with_msg("Failed to find the subnets of the Hyper-V NAT switches")
+        );
+        vec![]
+    })
+}
 
-        let v6_gateway_ptr = match &v6_gateway {
-            Some(v6_ip) => v6_ip.as_ptr(),
-            None => ptr::null(),
-        };
-
-        let mut relay_client: Vec<u16> = relay_client.as_os_str().encode_wide().collect();
-        relay_client.push(0u16);
-
-        let dns_servers: Vec<Vec<u16>> = dns_servers
+fn apply_policy(policy: &FirewallPolicy, virtual_networks: &[IpNetwork]) -> Result<(), Error> {
+    let lan_networks = match policy {
+        FirewallPolicy::Connecting { lan_networks, .. }
+        | FirewallPolicy::Connected { lan_networks, .. }
+        | FirewallPolicy::Blocked { lan_networks, .. } => lan_networks,
+    };
+    let lan_network_ips: Vec<WideCString> = lan_networks
+        .iter()
+        .map(|network| widestring_ip(network.ip()))
+        .collect();
+    // lan_network_ips has to outlive winfw_lan_networks
+    let winfw_lan_networks: Vec<WinFwNetwork> = lan_networks
+        .iter()
+        .zip(&lan_network_ips)
+        .map(|(network, ip)| WinFwNetwork {
+            ip: ip.as_ptr(),
+            prefix: network.prefix(),
+        })
+        .collect();
+
+    let virtual_network_ips: Vec<WideCString> = virtual_networks
+        .iter()
+        .map(|network| widestring_ip(network.ip()))
+        .collect();
+    // virtual_network_ips has to outlive winfw_virtual_networks
+    let winfw_virtual_networks: Vec<WinFwNetwork> = virtual_networks
+        .iter()
+        .zip(&virtual_network_ips)
+        .map(|(network, ip)| WinFwNetwork {
+            ip: ip.as_ptr(),
+            prefix: network.prefix(),
+        })
+        .collect();
+
+    let allowed_endpoints = match policy {
+        FirewallPolicy::Connecting {
+            allowed_endpoints, ..
+        }
+        | FirewallPolicy::Connected {
+            allowed_endpoints, ..
+        }
+        | FirewallPolicy::Blocked {
+            allowed_endpoints, ..
+        } => allowed_endpoints,
+    };
+    let exceptions: Vec<(WideCString, &AllowedEndpoint, Option<TransportProtocol>)> =
+        allowed_endpoints
             .iter()
-            .map(|ip| {
-                OsString::from(ip.to_string())
-                    .as_os_str()
-                    .encode_wide()
-                    .chain(iter::once(0u16))
-                    .collect()
+            .flat_map(|endpoint| {
+                endpoint
+                    .rule_protocols()
+                    .into_iter()
+                    .map(move |protocol| (widestring_ip(endpoint.network.ip()), endpoint, protocol))
             })
             .collect();
-        let dns_servers: Vec<*const u16> = dns_servers.iter().map(|ip| ip.as_ptr()).collect();
-
-        unsafe {
-            WinFw_ApplyPolicyConnected(
-                winfw_settings,
-                &winfw_relay,
-                relay_client.as_ptr(),
-                tunnel_alias.as_ptr(),
-                v4_gateway.as_ptr(),
-                v6_gateway_ptr,
-                dns_servers.as_ptr(),
-                dns_servers.len(),
+    if exceptions.len() > MAX_FIREWALL_EXCEPTIONS {
+        log::warn!(
+            "Only the first {} of {} firewall exception rules are applied",
+            MAX_FIREWALL_EXCEPTIONS,
+            exceptions.len()
+        );
+    }
+    // exceptions has to outlive winfw_exceptions
+    let winfw_exceptions: Vec<WinFwFirewallException> = exceptions
+        .iter()
+        .take(MAX_FIREWALL_EXCEPTIONS)
+        .map(|(ip, endpoint, protocol)| WinFwFirewallException {
+            ip: ip.as_ptr(),
+            prefix: endpoint.network.prefix(),
+            port: endpoint.port.unwrap_or(0),
+            protocol: WinFwProt::from(protocol.unwrap_or(TransportProtocol::Tcp)),
+            anyProtocol: protocol.is_none(),
+        })
+        .collect();
+
+    match policy {
+        FirewallPolicy::Connecting {
+            peer_endpoint,
+            tunnel,
+            allow_lan,
+            allow_ipv6,
+            allowed_endpoint,
+            relay_client,
+            ..
+        } => {
+            let cfg = &WinFwSettings::new(
+                *allow_lan,
+                &winfw_lan_networks,
+                &winfw_virtual_networks,
+                &winfw_exceptions,
             )
-            .into_result()
-            .map_err(Error::ApplyingConnectedPolicy)
+            .permit_tunnel_ipv6(*allow_ipv6);
+            set_connecting_state(peer_endpoint, cfg, tunnel, allowed_endpoint, relay_client)
+        }
+        FirewallPolicy::Connected {
+            peer_endpoint,
+            tunnel,
+            allow_lan,
+            allow_ipv6,
+            dns_servers,
+            relay_client,
+            ..
+        } => {
+            let cfg = &WinFwSettings::new(
+                *allow_lan,
+                &winfw_lan_networks,
+                &winfw_virtual_networks,
+                &winfw_exceptions,
+            )
+            .permit_tunnel_ipv6(*allow_ipv6);
+            set_connected_state(peer_endpoint, cfg, tunnel, dns_servers, relay_client)
+        }
+        FirewallPolicy::Blocked {
+            allow_lan,
+            allowed_endpoint,
+            exemptions,
+            ..
+        } => {
+            let cfg = &WinFwSettings::new(
+                *allow_lan,
+                &winfw_lan_networks,
+                &winfw_virtual_networks,
+                &winfw_exceptions,
+            );
+            set_blocked_state(cfg, allowed_endpoint, exemptions)
         }
     }
+}
 
-    fn set_blocked_state(
-        &mut self,
-        winfw_settings: &WinFwSettings,
-        allowed_endpoint: &Endpoint,
-    ) -> Result<(), Error> {
-        trace!("Applying 'blocked' firewall policy");
+fn set_connecting_state(
+    endpoint: &Endpoint,
+    winfw_settings: &WinFwSettings,
+    tunnel_metadata: &Option<TunnelMetadata>,
+    allowed_endpoint: &Endpoint,
+    relay_client: &Path,
+) -> Result<(), Error> {
+    trace!("Applying 'connecting' firewall policy");
+    let ip_str = widestring_ip(endpoint.address.ip());
+    let winfw_relay = WinFwEndpoint {
+        ip: ip_str.as_ptr(),
+        port: endpoint.address.port(),
+        protocol: WinFwProt::from(endpoint.protocol),
+    };
+
+    let mut relay_client: Vec<u16> = relay_client.as_os_str().encode_wide().collect();
+    relay_client.push(0u16);
+
+    let allowed_endpoint_ip = widestring_ip(allowed_endpoint.address.ip());
+    let winfw_allowed_endpoint = Some(WinFwEndpoint {
+        ip: allowed_endpoint_ip.as_ptr(),
+        port: allowed_endpoint.address.port(),
+        protocol: WinFwProt::from(allowed_endpoint.protocol),
+    });
+
+    let interface_wstr = tunnel_metadata.as_ref().map(|metadata| {
+        WideCString::new(metadata.interface.encode_utf16().collect::<Vec<_>>()).unwrap()
+    });
+    let interface_wstr_ptr = if let Some(ref wstr) = interface_wstr {
+        wstr.as_ptr()
+    } else {
+        ptr::null()
+    };
+
+    unsafe {
+        WinFw_ApplyPolicyConnecting(
+            winfw_settings,
+            &winfw_relay,
+            relay_client.as_ptr(),
+            interface_wstr_ptr,
+            winfw_allowed_endpoint.as_ptr(),
+        )
+        .into_result()
+        .map_err(Error::ApplyingConnectingPolicy)
+    }
+}
 
-        let allowed_endpoint_ip = widestring_ip(allowed_endpoint.address.ip());
-        let winfw_allowed_endpoint = Some(WinFwEndpoint {
-            ip: allowed_endpoint_ip.as_ptr(),
-            port: allowed_endpoint.address.port(),
-            protocol: WinFwProt::from(allowed_endpoint.protocol),
-        });
+fn set_connected_state(
+    endpoint: &Endpoint,
+    winfw_settings: &WinFwSettings,
+    tunnel_metadata: &TunnelMetadata,
+    dns_servers: &[IpAddr],
+    relay_client: &Path,
+) -> Result<(), Error> {
+    trace!("Applying 'connected' firewall policy");
+    let ip_str = widestring_ip(endpoint.address.ip());
+    let v4_gateway = widestring_ip(tunnel_metadata.ipv4_gateway.into());
+    let v6_gateway = tunnel_metadata
+        .ipv6_gateway
+        .map(|v6_ip| widestring_ip(v6_ip.into()));
+
+    let tunnel_alias =
+        WideCString::new(tunnel_metadata.interface.encode_utf16().collect::<Vec<_>>()).unwrap();
+
+    // ip_str, gateway_str and tunnel_alias have to outlive winfw_relay
+    let winfw_relay = WinFwEndpoint {
+        ip: ip_str.as_ptr(),
+        port: endpoint.address.port(),
+        protocol: WinFwProt::from(endpoint.protocol),
+    };
+
+    let metrics_set = winnet::ensure_best_metric_for_interface(&tunnel_metadata.interface)
+        .map_err(Error::SetTunMetric)?;
+
+    if metrics_set {
+        debug!("Network interface metrics were changed");
+    } else {
+        debug!("Network interface metrics were not changed");
+    }
 
-        unsafe {
-            WinFw_ApplyPolicyBlocked(winfw_settings, winfw_allowed_endpoint.as_ptr())
-                .into_result()
-                .map_err(Error::ApplyingBlockedPolicy)
-        }
+    let v6_gateway_ptr = match &v6_gateway {
+        Some(v6_ip) => v6_ip.as_ptr(),
+        None => ptr::null(),
+    };
+
+    let mut relay_client: Vec<u16> = relay_client.as_os_str().encode_wide().collect();
+    relay_client.push(0u16);
+
+    let dns_servers: Vec<Vec<u16>> = dns_servers
+        .iter()
+        .map(|ip| {
+            OsString::from(ip.to_string())
+                .as_os_str()
+                .encode_wide()
+                .chain(iter::once(0u16))
+                .collect()
+        })
+        .collect();
+    let dns_servers: Vec<*const u16> = dns_servers.iter().map(|ip| ip.as_ptr()).collect();
+
+    unsafe {
+        WinFw_ApplyPolicyConnected(
+            winfw_settings,
+            &winfw_relay,
+            relay_client.as_ptr(),
+            tunnel_alias.as_ptr(),
+            v4_gateway.as_ptr(),
+            v6_gateway_ptr,
+            dns_servers.as_ptr(),
+            dns_servers.len(),
+        )
+        .into_result()
+        .map_err(Error::ApplyingConnectedPolicy)
+    }
+}
+
+fn set_blocked_state(
+    winfw_settings: &WinFwSettings,
+    allowed_endpoint: &Endpoint,
+    exemptions: &SystemServiceExemptions,
+) -> Result<(), Error> {
+    trace!("Applying 'blocked' firewall policy");
+
+    let allowed_endpoint_ip = widestring_ip(allowed_endpoint.address.ip());
+    let winfw_allowed_endpoint = Some(WinFwEndpoint {
+        ip: allowed_endpoint_ip.as_ptr(),
+        port: allowed_endpoint.address.port(),
+        protocol: WinFwProt::from(allowed_endpoint.protocol),
+    });
+
+    let captive_portal_hosts: Vec<WideCString> = exemptions
+        .captive_portal_hosts
+        .iter()
+        .map(|ip| widestring_ip(*ip))
+        .collect();
+    let captive_portal_hosts: Vec<*const u16> =
+        captive_portal_hosts.iter().map(|ip| ip.as_ptr()).collect();
+    let system_services = WinFwSystemServices {
+        permitNtp: exemptions.ntp,
+        captivePortalHosts: captive_portal_hosts.as_ptr(),
+        numCaptivePortalHosts: captive_portal_hosts.len(),
+        permitPortalLogin: exemptions.portal_login,
+    };
+
+    unsafe {
+        WinFw_ApplyPolicyBlocked(
+            winfw_settings,
+            winfw_allowed_endpoint.as_ptr(),
+            &system_services,
+        )
+        .into_result()
+        .map_err(Error::ApplyingBlockedPolicy)
     }
 }
 
@@ -336,19 +733,69 @@ mod winfw {
         }
     }
 
-    #[repr(C)]
+    #[repr(C, packed)]
+    pub struct WinFwNetwork {
+        pub ip: *const libc::wchar_t,
+        pub prefix: u8,
+    }
+
+    #[repr(C, packed)]
+    pub struct WinFwFirewallException {
+        pub ip: *const libc::wchar_t,
+        pub prefix: u8,
+        pub port: u16,
+        pub protocol: WinFwProt,
+        pub anyProtocol: bool,
+    }
+
+    #[repr(C, packed)]
     pub struct WinFwSettings {
         permitDhcp: bool,
         permitLan: bool,
+        lanNetworks: *const WinFwNetwork,
+        numLanNetworks: usize,
+        virtualNetworks: *const WinFwNetwork,
+        numVirtualNetworks: usize,
+        firewallExceptions: *const WinFwFirewallException,
+        numFirewallExceptions: usize,
+        permitTunnelIpv6: bool,
     }
 
     impl WinFwSettings {
-        pub fn new(permit_lan: bool) -> WinFwSettings {
+        /// `lan_networks`, `virtual_networks` and `firewall_exceptions` have to outlive the
+        /// returned settings.
+        pub fn new(
+            permit_lan: bool,
+            lan_networks: &[WinFwNetwork],
+            virtual_networks: &[WinFwNetwork],
+            firewall_exceptions: &[WinFwFirewallException],
+        ) -> WinFwSettings {
             WinFwSettings {
                 permitDhcp: true,
                 permitLan: permit_lan,
+                lanNetworks: lan_networks.as_ptr(),
+                numLanNetworks: lan_networks.len(),
+                virtualNetworks: virtual_networks.as_ptr(),
+                numVirtualNetworks: virtual_networks.len(),
+                firewallExceptions: firewall_exceptions.as_ptr(),
+                numFirewallExceptions: firewall_exceptions.len(),
+                permitTunnelIpv6: false,
             }
         }
+
+        /// Sets whether IPv6 traffic is permitted on the tunnel interface.
+        pub fn permit_tunnel_ipv6(mut self, permit: bool) -> WinFwSettings {
+            self.permitTunnelIpv6 = permit;
+            self
+        }
+    }
+
+    #[repr(C, packed)]
+    pub struct WinFwSystemServices {
+        pub permitNtp: bool,
+        pub captivePortalHosts: *const *const libc::wchar_t,
+        pub numCaptivePortalHosts: usize,
+        pub permitPortalLogin: bool,
     }
 
     #[allow(dead_code)]
@@ -357,8 +804,12 @@ mod winfw {
     pub enum WinFwCleanupPolicy {
         ContinueBlocking = 0,
         ResetFirewall = 1,
+        Block = 2,
     }
 
+    pub type FilterSink =
+        extern "system" fn(description: *const libc::wchar_t, context: *mut libc::c_void);
+
     ffi_error!(InitializationResult, Error::Initialization);
     ffi_error!(DeinitializationResult, Error::Deinitialization);
 
@@ -384,6 +835,15 @@ mod winfw {
         }
     }
 
+    #[derive(Debug)]
+    #[allow(dead_code)]
+    #[repr(u32)]
+    pub enum WinFwObjectStatus {
+        Intact = 0,
+        Missing = 1,
+        GeneralFailure = 2,
+    }
+
     impl Into<Result<(), super::FirewallPolicyError>> for WinFwPolicyStatus {
         fn into(self) -> Result<(), super::FirewallPolicyError> {
             self.into_result()
@@ -435,9 +895,22 @@ mod winfw {
         pub fn WinFw_ApplyPolicyBlocked(
             settings: &WinFwSettings,
             allowed_endpoint: *const WinFwEndpoint,
+            system_services: &WinFwSystemServices,
         ) -> WinFwPolicyStatus;
 
         #[link_name = "WinFw_Reset"]
         pub fn WinFw_Reset() -> WinFwPolicyStatus;
+
+        #[link_name = "WinFw_VerifyObjects"]
+        pub fn WinFw_VerifyObjects() -> WinFwObjectStatus;
+
+        #[link_name = "WinFw_RepairObjects"]
+        pub fn WinFw_RepairObjects() -> WinFwPolicyStatus;
+
+        #[link_name = "WinFw_DescribeFilters"]
+        pub fn WinFw_DescribeFilters(
+            sink: Option<FilterSink>,
+            sink_context: *mut libc::c_void,
+        ) -> bool;
     }
 }
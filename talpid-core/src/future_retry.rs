@@ -139,6 +139,15 @@ fn jitter(dur: Duration) -> Duration {
     apply_jitter(dur, rand::thread_rng().sample(OpenClosed01))
 }
 
+/// Randomly shortens a duration by at most `max_fraction` of itself.
+pub fn partial_jitter(duration: Duration, max_fraction: f64) -> Duration {
+    if max_fraction <= 0.0 {
+        return duration;
+    }
+    let fraction = rand::thread_rng().gen_range(0.0, max_fraction.min(1.0));
+    apply_jitter(duration, 1.0 - fraction)
+}
+
 fn apply_jitter(duration: Duration, jitter: f64) -> Duration {
     let secs = (duration.as_secs() as f64) * jitter;
     let nanos = (duration.subsec_nanos() as f64) * jitter;
@@ -200,6 +209,17 @@ mod test {
         assert_eq!(apply_jitter(second, 1.0), second);
     }
 
+    #[quickcheck_macros::quickcheck]
+    fn test_partial_jitter(millis: u64, percent: u8) {
+        let unjittered_duration = Duration::from_millis(millis);
+        let max_fraction = f64::from(percent % 101) / 100.0;
+        assert!(partial_jitter(unjittered_duration, max_fraction) <= unjittered_duration);
+        assert_eq!(
+            partial_jitter(unjittered_duration, 0.0),
+            unjittered_duration
+        );
+    }
+
     #[quickcheck_macros::quickcheck]
     fn test_jitter(millis: u64, jitter: u64) {
         let max_num = 2u64.checked_pow(f64::MANTISSA_DIGITS).unwrap();
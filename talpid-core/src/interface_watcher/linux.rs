@@ -0,0 +1,154 @@
+use super::{InterfaceState, Interfaces};
+use futures::{
+    future,
+    stream::{self, BoxStream},
+    FutureExt, StreamExt, TryStreamExt,
+};
+use ipnetwork::IpNetwork;
+use netlink_packet_route::{
+    address::nlas::Nla as AddressNla,
+    constants::{IFF_LOWER_UP, IFF_UP},
+    link::nlas::Nla as LinkNla,
+    AddressMessage, LinkMessage,
+};
+use rtnetlink::{
+    constants::{RTMGRP_IPV4_IFADDR, RTMGRP_IPV6_IFADDR, RTMGRP_LINK},
+    sys::SocketAddr as NetlinkSocketAddr,
+};
+use std::{io, net::IpAddr};
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to open a netlink connection")]
+    NetlinkConnectError(#[error(source)] io::Error),
+
+    #[error(display = "Failed to bind netlink socket")]
+    NetlinkBindError(#[error(source)] io::Error),
+
+    #[error(display = "Failed to list network interfaces")]
+    ListLinksError(#[error(source)] rtnetlink::Error),
+
+    #[error(display = "Failed to list interface addresses")]
+    ListAddressesError(#[error(source)] rtnetlink::Error),
+}
+
+/// Yields whenever a link or an address changes. The netlink connection is polled by the stream
+/// itself, so it is closed when the stream is dropped.
+pub type ChangeStream = BoxStream<'static, ()>;
+
+pub async fn changes() -> Result<ChangeStream, Error> {
+    let (mut connection, _, messages) =
+        rtnetlink::new_connection().map_err(Error::NetlinkConnectError)?;
+    let mgroup_flags = RTMGRP_LINK | RTMGRP_IPV4_IFADDR | RTMGRP_IPV6_IFADDR;
+    connection
+        .socket_mut()
+        .bind(&NetlinkSocketAddr::new(0, mgroup_flags))
+        .map_err(Error::NetlinkBindError)?;
+
+    let connection = connection
+        .into_stream()
+        .filter_map(|()| future::ready(None::<()>));
+    Ok(stream::select(messages.map(|_| ()), connection).boxed())
+}
+
+pub async fn interfaces() -> Result<Interfaces, Error> {
+    let (connection, handle, _) =
+        rtnetlink::new_connection().map_err(Error::NetlinkConnectError)?;
+    let connection = tokio::spawn(connection);
+    let result = list_interfaces(&handle).await;
+    connection.abort();
+    result
+}
+
+async fn list_interfaces(handle: &rtnetlink::Handle) -> Result<Interfaces, Error> {
+    let links: Vec<LinkMessage> = handle
+        .link()
+        .get()
+        .execute()
+        .try_collect()
+        .await
+        .map_err(Error::ListLinksError)?;
+    let addresses: Vec<AddressMessage> = handle
+        .address()
+        .get()
+        .execute()
+        .try_collect()
+        .await
+        .map_err(Error::ListAddressesError)?;
+
+    let mut interfaces = Interfaces::new();
+    for link in links {
+        let mut name = None;
+        let mut state = InterfaceState {
+            up: link.header.flags & IFF_UP != 0 && link.header.flags & IFF_LOWER_UP != 0,
+            ..InterfaceState::default()
+        };
+        for nla in link.nlas {
+            match nla {
+                LinkNla::IfName(ifname) => name = Some(ifname),
+                LinkNla::Mtu(mtu) => state.mtu = mtu,
+                _ => (),
+            }
+        }
+        state.addresses = addresses
+            .iter()
+            .filter(|address| address.header.index == link.header.index)
+            .filter_map(parse_address)
+            .collect();
+        if let Some(name) = name {
+            interfaces.insert(name, state);
+        }
+    }
+    Ok(interfaces)
+}
+
+/// Returns the local address of an interface. On point-to-point interfaces, the address attribute
+/// holds the address of the peer, while the local attribute holds the address of the interface.
+fn parse_address(message: &AddressMessage) -> Option<IpNetwork> {
+    let mut address = None;
+    for nla in &message.nlas {
+        match nla {
+            AddressNla::Local(bytes) => address = parse_ip(bytes),
+            AddressNla::Address(bytes) if address.is_none() => address = parse_ip(bytes),
+            _ => (),
+        }
+    }
+    IpNetwork::new(address?, message.header.prefix_len).ok()
+}
+
+fn parse_ip(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => {
+            let mut ipv4_bytes = [0u8; 4];
+            ipv4_bytes.copy_from_slice(bytes);
+            Some(IpAddr::from(ipv4_bytes))
+        }
+        16 => {
+            let mut ipv6_bytes = [0u8; 16];
+            ipv6_bytes.copy_from_slice(bytes);
+            Some(IpAddr::from(ipv6_bytes))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_address() {
+        let mut message = AddressMessage::default();
+        message.header.prefix_len = 32;
+        message.nlas.push(AddressNla::Address(vec![10, 64, 0, 1]));
+        message.nlas.push(AddressNla::Local(vec![10, 64, 0, 2]));
+        assert_eq!(
+            parse_address(&message),
+            Some("10.64.0.2/32".parse().unwrap())
+        );
+
+        message.nlas.clear();
+        assert_eq!(parse_address(&message), None);
+    }
+}
@@ -0,0 +1,181 @@
+use super::{InterfaceState, Interfaces};
+use futures::{
+    channel::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    Stream, StreamExt,
+};
+use ipnetwork::IpNetwork;
+use nix::sys::socket::{InetAddr, SockAddr};
+use std::{
+    ffi::CStr,
+    io,
+    net::IpAddr,
+    pin::Pin,
+    ptr,
+    sync::{mpsc as sync_mpsc, Arc, Weak},
+    task::{Context, Poll},
+    thread,
+};
+use system_configuration::{
+    core_foundation::{
+        array::CFArray,
+        runloop::{kCFRunLoopCommonModes, CFRunLoop},
+        string::CFString,
+    },
+    dynamic_store::{SCDynamicStore, SCDynamicStoreBuilder, SCDynamicStoreCallBackContext},
+};
+
+/// Keys of the addresses and link states of all interfaces.
+const WATCH_PATTERNS: &[&str] = &[
+    "State:/Network/Interface/[^/]+/IPv4",
+    "State:/Network/Interface/[^/]+/IPv6",
+    "State:/Network/Interface/[^/]+/Link",
+];
+
+/// Key of the list of interfaces, which changes when an interface is added or removed.
+const INTERFACE_LIST_KEY: &str = "State:/Network/Interface";
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "Failed to list network interfaces")]
+    ListInterfaces(#[error(source)] io::Error),
+    #[error(display = "Failed to initialize dynamic store")]
+    DynamicStoreInitError,
+    #[error(display = "Panic during initialization")]
+    InitializationError,
+}
+
+/// Yields whenever a watched key in the dynamic store changes. The notifications stop when the
+/// stream is dropped, but the run loop thread is left running.
+pub struct ChangeStream {
+    changes: UnboundedReceiver<()>,
+    _sender: Arc<UnboundedSender<()>>,
+}
+
+impl Stream for ChangeStream {
+    type Item = ();
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<()>> {
+        self.changes.poll_next_unpin(cx)
+    }
+}
+
+pub async fn changes() -> Result<ChangeStream, Error> {
+    let (result_tx, result_rx) = sync_mpsc::channel();
+    let (sender, changes) = mpsc::unbounded();
+    let sender = Arc::new(sender);
+    let context = Arc::downgrade(&sender);
+    thread::spawn(move || match create_dynamic_store(context) {
+        Ok(dynamic_store) => {
+            CFRunLoop::get_current().add_source(&dynamic_store.create_run_loop_source(), unsafe {
+                kCFRunLoopCommonModes
+            });
+            let _ = result_tx.send(Ok(()));
+            CFRunLoop::run_current()
+        }
+        Err(err) => {
+            let _ = result_tx.send(Err(err));
+        }
+    });
+
+    result_rx.recv().map_err(|_| Error::InitializationError)??;
+    Ok(ChangeStream {
+        changes,
+        _sender: sender,
+    })
+}
+
+fn create_dynamic_store(sender: Weak<UnboundedSender<()>>) -> Result<SCDynamicStore, Error> {
+    let callback_context = SCDynamicStoreCallBackContext {
+        callout: interface_change_callback,
+        info: sender,
+    };
+
+    let store = SCDynamicStoreBuilder::new("talpid-interface-watcher")
+        .callback_context(callback_context)
+        .build();
+
+    let watch_keys = CFArray::from_CFTypes(&[CFString::new(INTERFACE_LIST_KEY)]);
+    let watch_patterns = CFArray::from_CFTypes(
+        &WATCH_PATTERNS
+            .iter()
+            .map(|pattern| CFString::new(pattern))
+            .collect::<Vec<_>>(),
+    );
+
+    if store.set_notification_keys(&watch_keys, &watch_patterns) {
+        log::trace!("Registered for interface notifications");
+        Ok(store)
+    } else {
+        Err(Error::DynamicStoreInitError)
+    }
+}
+
+fn interface_change_callback(
+    _store: SCDynamicStore,
+    _changed_keys: CFArray<CFString>,
+    sender: &mut Weak<UnboundedSender<()>>,
+) {
+    if let Some(sender) = sender.upgrade() {
+        let _ = sender.unbounded_send(());
+    }
+}
+
+pub async fn interfaces() -> Result<Interfaces, Error> {
+    let mut first: *mut libc::ifaddrs = ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut first) } != 0 {
+        return Err(Error::ListInterfaces(io::Error::last_os_error()));
+    }
+
+    let mut interfaces = Interfaces::new();
+    let mut current = first;
+    while let Some(ifaddr) = unsafe { current.as_ref() } {
+        current = ifaddr.ifa_next;
+
+        let name = unsafe { CStr::from_ptr(ifaddr.ifa_name) }
+            .to_string_lossy()
+            .into_owned();
+        let state: &mut InterfaceState = interfaces.entry(name).or_default();
+        let flags = ifaddr.ifa_flags as libc::c_int;
+        state.up = flags & libc::IFF_UP != 0 && flags & libc::IFF_RUNNING != 0;
+
+        let family = match unsafe { ifaddr.ifa_addr.as_ref() } {
+            Some(address) => address.sa_family as libc::c_int,
+            None => continue,
+        };
+        match family {
+            // The link layer entry of an interface carries its statistics, including the MTU
+            libc::AF_LINK => {
+                if let Some(data) = unsafe { (ifaddr.ifa_data as *const libc::if_data).as_ref() } {
+                    state.mtu = data.ifi_mtu;
+                }
+            }
+            libc::AF_INET | libc::AF_INET6 => {
+                let address = unsafe { ip_from_sockaddr(ifaddr.ifa_addr) };
+                let netmask = unsafe { ip_from_sockaddr(ifaddr.ifa_netmask) };
+                if let (Some(address), Some(netmask)) = (address, netmask) {
+                    let prefix = match netmask {
+                        IpAddr::V4(netmask) => u32::from(netmask).count_ones(),
+                        IpAddr::V6(netmask) => u128::from(netmask).count_ones(),
+                    };
+                    if let Ok(network) = IpNetwork::new(address, prefix as u8) {
+                        state.addresses.insert(network);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    unsafe { libc::freeifaddrs(first) };
+    Ok(interfaces)
+}
+
+unsafe fn ip_from_sockaddr(address: *const libc::sockaddr) -> Option<IpAddr> {
+    if address.is_null() {
+        return None;
+    }
+    match SockAddr::from_libc_sockaddr(address)? {
+        SockAddr::Inet(address) => Some(InetAddr::to_std(&address).ip()),
+        _ => None,
+    }
+}
@@ -0,0 +1,264 @@
+//! Watches the network interfaces of the host and reports changes to them as a single stream of
+//! typed events: links that go up or down, addresses that are added or removed, MTU changes and
+//! interfaces that disappear. The platform code only lists the interfaces and signals that they
+//! may have changed. The events are derived by comparing consecutive listings, so they mean the
+//! same thing on every platform.
+use futures::{
+    channel::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    FutureExt, Stream, StreamExt,
+};
+use ipnetwork::IpNetwork;
+use std::{
+    collections::{BTreeMap, HashSet},
+    pin::Pin,
+    task::{Context, Poll},
+};
+use talpid_types::ErrorExt;
+
+#[cfg(target_os = "linux")]
+#[path = "linux.rs"]
+mod imp;
+
+#[cfg(target_os = "macos")]
+#[path = "macos.rs"]
+mod imp;
+
+#[cfg(windows)]
+#[path = "windows.rs"]
+mod imp;
+
+pub use self::imp::Error;
+
+/// A change to a network interface.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InterfaceEvent {
+    /// The interface came up, or was added in the up state.
+    Up { interface: String },
+    /// The interface went down.
+    Down { interface: String },
+    /// An address was added to the interface.
+    AddressAdded {
+        interface: String,
+        address: IpNetwork,
+    },
+    /// An address was removed from the interface. Not reported for interfaces that are removed.
+    AddressRemoved {
+        interface: String,
+        address: IpNetwork,
+    },
+    /// The MTU of the interface changed.
+    MtuChanged { interface: String, mtu: u32 },
+    /// The interface no longer exists.
+    Removed { interface: String },
+}
+
+impl InterfaceEvent {
+    /// Returns the name of the interface that the event concerns.
+    pub fn interface(&self) -> &str {
+        match self {
+            InterfaceEvent::Up { interface }
+            | InterfaceEvent::Down { interface }
+            | InterfaceEvent::AddressAdded { interface, .. }
+            | InterfaceEvent::AddressRemoved { interface, .. }
+            | InterfaceEvent::MtuChanged { interface, .. }
+            | InterfaceEvent::Removed { interface } => interface,
+        }
+    }
+
+    /// Returns whether the event changes whether the interface can carry traffic.
+    pub fn is_link_change(&self) -> bool {
+        matches!(
+            self,
+            InterfaceEvent::Up { .. }
+                | InterfaceEvent::Down { .. }
+                | InterfaceEvent::Removed { .. }
+        )
+    }
+}
+
+/// The state of an interface, as listed by the platform code.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct InterfaceState {
+    up: bool,
+    mtu: u32,
+    addresses: HashSet<IpNetwork>,
+}
+
+/// The interfaces of the host by name.
+type Interfaces = BTreeMap<String, InterfaceState>;
+
+/// A stream of [`InterfaceEvent`]s. The interfaces are watched for as long as it is alive.
+pub struct InterfaceWatcher {
+    events: UnboundedReceiver<InterfaceEvent>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl InterfaceWatcher {
+    /// Lists the interfaces and starts watching them. Only changes that happen after this returns
+    /// are reported.
+    pub async fn new() -> Result<Self, Error> {
+        let interfaces = imp::interfaces().await?;
+        let changes = imp::changes().await?;
+        let (events_tx, events) = mpsc::unbounded();
+        let task = tokio::spawn(watch(changes, interfaces, events_tx));
+        Ok(InterfaceWatcher { events, task })
+    }
+}
+
+impl Stream for InterfaceWatcher {
+    type Item = InterfaceEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<InterfaceEvent>> {
+        self.events.poll_next_unpin(cx)
+    }
+}
+
+impl Drop for InterfaceWatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn watch(
+    mut changes: imp::ChangeStream,
+    mut interfaces: Interfaces,
+    events_tx: UnboundedSender<InterfaceEvent>,
+) {
+    while changes.next().await.is_some() {
+        // Changes often arrive in bursts, which only require a single listing
+        while let Some(Some(())) = changes.next().now_or_never() {}
+
+        let new_interfaces = match imp::interfaces().await {
+            Ok(new_interfaces) => new_interfaces,
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to list the network interfaces")
+                );
+                continue;
+            }
+        };
+        for event in diff(&interfaces, &new_interfaces) {
+            log::trace!("Interface event: {:?}", event);
+            if events_tx.unbounded_send(event).is_err() {
+                return;
+            }
+        }
+        interfaces = new_interfaces;
+    }
+}
+
+/// Returns the events that turn `old` into `new`.
+fn diff(old: &Interfaces, new: &Interfaces) -> Vec<InterfaceEvent> {
+    let mut events = vec![];
+    for interface in old.keys().filter(|name| !new.contains_key(*name)) {
+        events.push(InterfaceEvent::Removed {
+            interface: interface.clone(),
+        });
+    }
+
+    let no_addresses = HashSet::new();
+    for (interface, state) in new {
+        let previous = old.get(interface);
+        let was_up = previous.map(|previous| previous.up).unwrap_or(false);
+        if state.up != was_up {
+            let interface = interface.clone();
+            events.push(if state.up {
+                InterfaceEvent::Up { interface }
+            } else {
+                InterfaceEvent::Down { interface }
+            });
+        }
+        if let Some(previous) = previous {
+            if previous.mtu != state.mtu {
+                events.push(InterfaceEvent::MtuChanged {
+                    interface: interface.clone(),
+                    mtu: state.mtu,
+                });
+            }
+        }
+
+        let previous_addresses = previous
+            .map(|previous| &previous.addresses)
+            .unwrap_or(&no_addresses);
+        for address in sorted(previous_addresses.difference(&state.addresses)) {
+            events.push(InterfaceEvent::AddressRemoved {
+                interface: interface.clone(),
+                address,
+            });
+        }
+        for address in sorted(state.addresses.difference(previous_addresses)) {
+            events.push(InterfaceEvent::AddressAdded {
+                interface: interface.clone(),
+                address,
+            });
+        }
+    }
+    events
+}
+
+fn sorted<'a>(addresses: impl Iterator<Item = &'a IpNetwork>) -> Vec<IpNetwork> {
+    let mut addresses: Vec<IpNetwork> = addresses.cloned().collect();
+    addresses.sort_by_key(|address| (address.ip(), address.prefix()));
+    addresses
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn interface(up: bool, mtu: u32, addresses: &[&str]) -> InterfaceState {
+        InterfaceState {
+            up,
+            mtu,
+            addresses: addresses
+                .iter()
+                .map(|address| address.parse().unwrap())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut old = Interfaces::new();
+        old.insert(
+            "eth0".to_owned(),
+            interface(true, 1500, &["192.168.1.2/24"]),
+        );
+        old.insert("wg0".to_owned(), interface(true, 1380, &["10.64.0.2/32"]));
+        old.insert("wlan0".to_owned(), interface(false, 1500, &[]));
+
+        let mut new = Interfaces::new();
+        new.insert(
+            "eth0".to_owned(),
+            interface(true, 1400, &["192.168.1.2/24", "fe80::1/64"]),
+        );
+        new.insert("wlan0".to_owned(), interface(true, 1500, &[]));
+        new.insert("tun0".to_owned(), interface(false, 1500, &["10.8.0.2/24"]));
+
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                InterfaceEvent::Removed {
+                    interface: "wg0".to_owned()
+                },
+                InterfaceEvent::MtuChanged {
+                    interface: "eth0".to_owned(),
+                    mtu: 1400
+                },
+                InterfaceEvent::AddressAdded {
+                    interface: "eth0".to_owned(),
+                    address: "fe80::1/64".parse().unwrap()
+                },
+                InterfaceEvent::AddressAdded {
+                    interface: "tun0".to_owned(),
+                    address: "10.8.0.2/24".parse().unwrap()
+                },
+                InterfaceEvent::Up {
+                    interface: "wlan0".to_owned()
+                },
+            ]
+        );
+        assert!(diff(&new, &new).is_empty());
+    }
+}
@@ -0,0 +1,62 @@
+use super::{InterfaceState, Interfaces};
+use crate::windows::NetEventStream;
+use futures::{stream::BoxStream, StreamExt};
+use ipnetwork::IpNetwork;
+use std::{collections::HashMap, io};
+use talpid_windows_net::{IpInterface, Luid, UnicastAddress};
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to list IP interfaces")]
+    ListInterfaces(#[error(source)] io::Error),
+
+    #[error(display = "Failed to list unicast addresses")]
+    ListAddresses(#[error(source)] io::Error),
+
+    #[error(display = "Failed to register for network notifications")]
+    RegisterNotifications(#[error(source)] io::Error),
+}
+
+/// Yields whenever an IP interface or a unicast address changes. The notifications are
+/// unregistered when the stream is dropped.
+pub type ChangeStream = BoxStream<'static, ()>;
+
+pub async fn changes() -> Result<ChangeStream, Error> {
+    let events = NetEventStream::new(None).map_err(Error::RegisterNotifications)?;
+    Ok(events.map(|_| ()).boxed())
+}
+
+/// Lists the network interfaces that have an IP interface. An interface is up if it is connected
+/// over either IP version, and its MTU is the largest of the two.
+pub async fn interfaces() -> Result<Interfaces, Error> {
+    let mut interfaces_by_luid: HashMap<Luid, InterfaceState> = HashMap::new();
+    for interface in &IpInterface::table(None).map_err(Error::ListInterfaces)? {
+        let state = interfaces_by_luid.entry(interface.luid()).or_default();
+        state.up |= interface.is_connected();
+        state.mtu = state.mtu.max(interface.mtu());
+    }
+
+    for address in &UnicastAddress::table(None).map_err(Error::ListAddresses)? {
+        let state = match interfaces_by_luid.get_mut(&address.luid()) {
+            Some(state) => state,
+            None => continue,
+        };
+        let ip = match address.address() {
+            Some(ip) => ip,
+            None => continue,
+        };
+        if let Ok(network) = IpNetwork::new(ip, address.prefix_length()) {
+            state.addresses.insert(network);
+        }
+    }
+
+    let mut interfaces = Interfaces::new();
+    for (luid, state) in interfaces_by_luid {
+        // Interfaces that are removed while they are being listed have no alias
+        if let Ok(alias) = luid.alias() {
+            interfaces.insert(alias.to_string_lossy().into_owned(), state);
+        }
+    }
+    Ok(interfaces)
+}
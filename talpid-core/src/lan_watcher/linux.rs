@@ -0,0 +1,12 @@
+use ipnetwork::IpNetwork;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to list network interfaces")]
+    ListInterfaces(#[error(source)] nix::Error),
+}
+
+pub fn networks() -> Result<Vec<IpNetwork>, Error> {
+    super::interface_networks().map_err(Error::ListInterfaces)
+}
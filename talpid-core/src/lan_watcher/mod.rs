@@ -0,0 +1,239 @@
+//! Keeps track of the networks that the physical interfaces of the host are on, so that the
+//! firewall can allow exactly those networks when LAN access is allowed. The networks are derived
+//! from the addresses and prefix lengths of the interfaces. Loopback and tunnel interfaces are
+//! left out.
+use crate::interface_watcher::InterfaceWatcher;
+use futures::{channel::mpsc::UnboundedSender, FutureExt, StreamExt};
+use ipnetwork::IpNetwork;
+use std::net::IpAddr;
+use talpid_types::ErrorExt;
+
+#[cfg(target_os = "linux")]
+#[path = "linux.rs"]
+mod imp;
+
+#[cfg(target_os = "macos")]
+#[path = "macos.rs"]
+mod imp;
+
+#[cfg(windows)]
+#[path = "windows.rs"]
+mod imp;
+
+pub use self::imp::Error;
+
+/// Watches the on-link networks for as long as it is alive.
+pub struct LanWatcher {
+    networks: Vec<IpNetwork>,
+    _handle: Option<WatcherHandle>,
+}
+
+/// Stops the watcher when dropped.
+struct WatcherHandle {
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for WatcherHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl LanWatcher {
+    /// Returns the networks that were found when the watcher was started.
+    pub fn networks(&self) -> Vec<IpNetwork> {
+        self.networks.clone()
+    }
+}
+
+/// Lists the on-link networks of the physical interfaces.
+pub(crate) fn networks() -> Result<Vec<IpNetwork>, Error> {
+    imp::networks()
+}
+
+/// Lists the on-link networks and starts watching them. The networks are sent on `sender` every
+/// time that they change. If they cannot be listed or watched, the private address ranges are used
+/// instead.
+pub async fn spawn_watcher(sender: UnboundedSender<Vec<IpNetwork>>) -> LanWatcher {
+    let networks = match imp::networks() {
+        Ok(networks) => networks,
+        Err(error) => {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg(
+                    "Failed to list the on-link networks. Allowing the private address ranges"
+                )
+            );
+            return fallback_watcher();
+        }
+    };
+    log::debug!("On-link networks: {:?}", networks);
+
+    match InterfaceWatcher::new().await {
+        Ok(interface_events) => {
+            let task = tokio::spawn(watch(interface_events, sender, networks.clone()));
+            LanWatcher {
+                networks,
+                _handle: Some(WatcherHandle { task }),
+            }
+        }
+        Err(error) => {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg(
+                    "Failed to watch the on-link networks. Allowing the private address ranges"
+                )
+            );
+            fallback_watcher()
+        }
+    }
+}
+
+/// Lists the on-link networks again whenever an interface changes, and sends them if they differ
+/// from the previous ones.
+async fn watch(
+    mut interface_events: InterfaceWatcher,
+    sender: UnboundedSender<Vec<IpNetwork>>,
+    mut networks: Vec<IpNetwork>,
+) {
+    while interface_events.next().await.is_some() {
+        // Changes often arrive in bursts, which only require a single listing
+        while let Some(Some(_)) = interface_events.next().now_or_never() {}
+
+        match imp::networks() {
+            Ok(new_networks) => {
+                if new_networks != networks {
+                    log::debug!("On-link networks changed: {:?}", new_networks);
+                    networks = new_networks;
+                    if sender.unbounded_send(networks.clone()).is_err() {
+                        return;
+                    }
+                }
+            }
+            Err(error) => log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to update the on-link networks")
+            ),
+        }
+    }
+}
+
+fn fallback_watcher() -> LanWatcher {
+    LanWatcher {
+        networks: crate::firewall::ALLOWED_LAN_NETS.to_vec(),
+        _handle: None,
+    }
+}
+
+/// Returns the network that an interface address with the given prefix length is on. Addresses
+/// that cannot belong to a LAN, and host routes, which leave no other hosts on the network, are
+/// ignored.
+fn on_link_network(address: IpAddr, prefix: u8) -> Option<IpNetwork> {
+    let (is_special, max_prefix) = match address {
+        IpAddr::V4(address) => (
+            address.is_loopback()
+                || address.is_unspecified()
+                || address.is_multicast()
+                || address.is_broadcast(),
+            32,
+        ),
+        IpAddr::V6(address) => (
+            address.is_loopback() || address.is_unspecified() || address.is_multicast(),
+            128,
+        ),
+    };
+    if is_special || prefix == 0 || prefix >= max_prefix {
+        return None;
+    }
+    let network = IpNetwork::new(address, prefix).ok()?;
+    IpNetwork::new(network.network(), prefix).ok()
+}
+
+/// Sorts the networks and removes duplicates, so that lists of networks can be compared.
+fn normalize(mut networks: Vec<IpNetwork>) -> Vec<IpNetwork> {
+    networks.sort_by_key(|network| (network.ip(), network.prefix()));
+    networks.dedup();
+    networks
+}
+
+/// Lists the on-link networks of the interfaces that are up and are neither loopback nor
+/// point-to-point interfaces. Tunnel interfaces, such as the ones created for WireGuard and
+/// OpenVPN, are point-to-point interfaces.
+#[cfg(unix)]
+fn interface_networks() -> nix::Result<Vec<IpNetwork>> {
+    use nix::{
+        net::if_::InterfaceFlags,
+        sys::socket::{InetAddr, SockAddr},
+    };
+
+    let ip = |address: Option<SockAddr>| match address {
+        Some(SockAddr::Inet(address)) => Some(InetAddr::to_std(&address).ip()),
+        _ => None,
+    };
+
+    let mut networks = vec![];
+    for interface in nix::ifaddrs::getifaddrs()? {
+        if !interface.flags.contains(InterfaceFlags::IFF_UP)
+            || interface
+                .flags
+                .intersects(InterfaceFlags::IFF_LOOPBACK | InterfaceFlags::IFF_POINTOPOINT)
+        {
+            continue;
+        }
+        let (address, netmask) = match (ip(interface.address), ip(interface.netmask)) {
+            (Some(address), Some(netmask)) => (address, netmask),
+            _ => continue,
+        };
+        let prefix = match netmask {
+            IpAddr::V4(netmask) => u32::from(netmask).count_ones(),
+            IpAddr::V6(netmask) => u128::from(netmask).count_ones(),
+        };
+        if let Some(network) = on_link_network(address, prefix as u8) {
+            networks.push(network);
+        }
+    }
+    Ok(normalize(networks))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_on_link_network() {
+        let network = |address: &str, prefix| on_link_network(address.parse().unwrap(), prefix);
+
+        assert_eq!(
+            network("192.168.1.17", 24),
+            Some("192.168.1.0/24".parse().unwrap())
+        );
+        assert_eq!(
+            network("100.64.3.2", 10),
+            Some("100.64.0.0/10".parse().unwrap())
+        );
+        assert_eq!(
+            network("fe80::1c2b:3dff:fe4e:5f60", 64),
+            Some("fe80::/64".parse().unwrap())
+        );
+        assert_eq!(network("127.0.0.1", 8), None);
+        assert_eq!(network("10.64.0.2", 32), None);
+        assert_eq!(network("::1", 128), None);
+        assert_eq!(network("203.0.113.5", 0), None);
+    }
+
+    #[test]
+    fn test_normalize() {
+        let networks = vec![
+            "192.168.1.0/24".parse().unwrap(),
+            "10.0.0.0/8".parse().unwrap(),
+            "192.168.1.0/24".parse().unwrap(),
+        ];
+        assert_eq!(
+            normalize(networks),
+            vec![
+                "10.0.0.0/8".parse().unwrap(),
+                "192.168.1.0/24".parse().unwrap()
+            ]
+        );
+    }
+}
@@ -0,0 +1,41 @@
+use crate::windows::is_nat_switch_alias;
+use ipnetwork::IpNetwork;
+use std::io;
+use talpid_windows_net::{Luid, UnicastAddress};
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to list unicast addresses")]
+    ListAddresses(#[error(source)] io::Error),
+}
+
+pub fn networks() -> Result<Vec<IpNetwork>, Error> {
+    let mut networks = vec![];
+    for address in UnicastAddress::table(None).map_err(Error::ListAddresses)? {
+        if !is_lan_interface(address.luid()) {
+            continue;
+        }
+        let prefix_length = address.prefix_length();
+        let address = match address.address() {
+            Some(address) => address,
+            None => continue,
+        };
+        if let Some(network) = super::on_link_network(address, prefix_length) {
+            networks.push(network);
+        }
+    }
+    Ok(super::normalize(networks))
+}
+
+/// Loopback, PPP and tunnel interfaces, which includes the tunnel adapters, are left out. So are
+/// the Hyper-V NAT switches, whose subnets are covered by the virtual networks setting.
+fn is_lan_interface(luid: Luid) -> bool {
+    if luid.is_virtual() {
+        return false;
+    }
+    match luid.alias() {
+        Ok(alias) => !is_nat_switch_alias(&alias.to_string_lossy()),
+        Err(_) => true,
+    }
+}
@@ -25,6 +25,18 @@ pub mod routing;
 
 mod offline;
 
+/// Typed events for changes to the network interfaces of the host
+#[cfg(not(target_os = "android"))]
+pub mod interface_watcher;
+
+/// Tracks the on-link networks of the host
+#[cfg(not(target_os = "android"))]
+mod lan_watcher;
+
+/// Detection of the network that the host is on
+#[cfg(not(target_os = "android"))]
+pub mod network_identity;
+
 /// Split tunneling
 pub mod split_tunnel;
 
@@ -34,6 +46,9 @@ pub mod process;
 /// Abstracts over different VPN tunnel technologies
 pub mod tunnel;
 
+/// Transports that WireGuard traffic can be wrapped in to get past restrictive networks.
+pub mod obfuscation;
+
 /// Helper function to preserve previous log files.
 pub mod logging;
 
@@ -49,16 +64,29 @@ pub mod dns;
 /// State machine to handle tunnel configuration.
 pub mod tunnel_state_machine;
 
+/// Record of the phases of recent connection attempts.
+pub mod connection_trace;
+
+/// Detection of other VPN software that is known to keep the tunnel from working.
+pub mod conflicting_software;
+
+/// Checks for the privileges, devices and drivers that tunnels and the firewall need.
+pub mod preflight;
+
 /// Future utilities
 pub mod future_retry;
 
 #[cfg(not(target_os = "android"))]
-/// Internal code for managing bundled proxy software.
-mod proxy;
+/// Code for managing bundled proxy software.
+pub mod proxy;
 
 #[cfg(not(target_os = "android"))]
 mod mktemp;
 
+/// Relaying of local network service discovery while connected.
+#[cfg(not(target_os = "android"))]
+mod mdns_reflector;
+
 /// Misc utilities for the Linux platform.
 #[cfg(target_os = "linux")]
 mod linux;
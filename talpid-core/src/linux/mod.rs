@@ -31,6 +31,9 @@ pub enum IfaceIndexLookupError {
 // b"mole" is [ 0x6d, 0x6f 0x6c, 0x65 ]
 pub const TUNNEL_FW_MARK: u32 = 0x6d6f6c65;
 pub const TUNNEL_TABLE_ID: u32 = 0x6d6f6c65;
+/// The routing protocol that routes added by the daemon are tagged with, so that routes left behind
+/// by a daemon that crashed can be told apart from routes added by others. b"m" is 0x6d.
+pub const ROUTE_PROTOCOL: u8 = 0x6d;
 
 pub fn set_src_valid_mark_sysctl() -> io::Result<()> {
     fs::write(PROC_SYS_NET_IPV4_CONF_SRC_VALID_MARK, b"1")
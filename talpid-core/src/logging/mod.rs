@@ -1,29 +1,318 @@
-use std::{fs, io, path::Path};
+use flate2::{write::GzEncoder, Compression};
+use parking_lot::RwLock;
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
 
 /// Types/implementations for logging through a callback.
 #[cfg(windows)]
 pub mod windows;
 
+/// The largest number of old log files that are kept for each log.
+pub const MAX_GENERATIONS: usize = 20;
+
+lazy_static::lazy_static! {
+    static ref ROTATION_LIMITS: RwLock<RotationLimits> = RwLock::new(RotationLimits::default());
+}
+
 /// Unable to create new log file
 #[derive(err_derive::Error, Debug)]
 #[error(display = "Unable to create new log file")]
 pub struct RotateLogError(#[error(source)] io::Error);
 
-/// Create a new log file while backing up a previous version of it.
+/// Limits how large log files grow and how many old log files are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RotationLimits {
+    /// The size in bytes at which a log that is written by this process is rotated.
+    pub max_size: u64,
+    /// The number of old log files to keep. At most `MAX_GENERATIONS` are kept.
+    pub generations: usize,
+    /// Old log files that have not been modified for this long are removed.
+    pub max_age: Option<Duration>,
+    /// Whether to compress old log files, except for the most recent one.
+    pub compress: bool,
+}
+
+impl Default for RotationLimits {
+    fn default() -> Self {
+        RotationLimits {
+            max_size: 10 * 1024 * 1024,
+            generations: 5,
+            max_age: Some(Duration::from_secs(14 * 24 * 60 * 60)),
+            compress: true,
+        }
+    }
+}
+
+/// Sets the limits that are used by all subsequent log rotations.
+pub fn set_rotation_limits(limits: RotationLimits) {
+    *ROTATION_LIMITS.write() = limits;
+}
+
+fn rotation_limits() -> RotationLimits {
+    *ROTATION_LIMITS.read()
+}
+
+/// Create a new log file while backing up previous versions of it.
 ///
-/// A new log file is created with the given file name, but if a file with that name already exists
-/// it is backed up with the extension changed to `.old.log`.
+/// A new log file is created with the given file name. If a file with that name already exists, it
+/// is backed up with the extension changed to `.old.log`. Older backups are named `.old.log.2`,
+/// `.old.log.3` and so on, with `.gz` appended if they are compressed. Backups beyond the
+/// configured number of generations, or older than the configured age, are removed.
 pub fn rotate_log(file: &Path) -> Result<(), RotateLogError> {
+    if let Err(error) = shift_generations(file, &rotation_limits()) {
+        log::warn!("Failed to rotate log file {}: {}", file.display(), error);
+    }
+
+    fs::File::create(file).map(|_| ()).map_err(RotateLogError)
+}
+
+/// Moves the log file and its backups one generation back, and removes the backups that exceed
+/// the limits. Nothing is logged, since this is also called from within the logger.
+fn shift_generations(file: &Path, limits: &RotationLimits) -> io::Result<()> {
+    let generations = limits.generations.min(MAX_GENERATIONS);
+
+    for generation in generations.max(1)..=MAX_GENERATIONS {
+        remove_if_exists(&backup_path(file, generation, false))?;
+        remove_if_exists(&backup_path(file, generation, true))?;
+    }
+
+    for generation in (1..generations).rev() {
+        let plain = backup_path(file, generation, false);
+        let compressed = backup_path(file, generation, true);
+        if plain.exists() {
+            if limits.compress {
+                compress_file(&plain, &backup_path(file, generation + 1, true))?;
+                fs::remove_file(&plain)?;
+            } else {
+                fs::rename(&plain, backup_path(file, generation + 1, false))?;
+            }
+        } else if compressed.exists() {
+            fs::rename(&compressed, backup_path(file, generation + 1, true))?;
+        }
+    }
+
+    if generations > 0 {
+        match fs::rename(file, backup_path(file, 1, false)) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+            _ => (),
+        }
+    } else {
+        remove_if_exists(file)?;
+    }
+
+    if let Some(max_age) = limits.max_age {
+        for generation in 1..=generations {
+            for compressed in &[false, true] {
+                let backup = backup_path(file, generation, *compressed);
+                if is_older_than(&backup, max_age) {
+                    fs::remove_file(&backup)?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the path of a backup of `file`. The most recent backup is never compressed, so that it
+/// can be read like any other log.
+fn backup_path(file: &Path, generation: usize, compressed: bool) -> PathBuf {
     let backup = file.with_extension("old.log");
-    if let Err(error) = fs::rename(file, &backup) {
-        if error.kind() != io::ErrorKind::NotFound {
-            log::warn!(
-                "Failed to rotate log file to {}: {}",
-                backup.display(),
-                error
+    if generation <= 1 {
+        return backup;
+    }
+    let mut name = backup.into_os_string();
+    name.push(format!(".{}", generation));
+    if compressed {
+        name.push(".gz");
+    }
+    PathBuf::from(name)
+}
+
+fn compress_file(source: &Path, destination: &Path) -> io::Result<()> {
+    let mut input = fs::File::open(source)?;
+    let mut encoder = GzEncoder::new(fs::File::create(destination)?, Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.sync_all()
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+fn is_older_than(path: &Path, max_age: Duration) -> bool {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map(|modified| {
+            SystemTime::now()
+                .duration_since(modified)
+                .map(|age| age > max_age)
+                .unwrap_or(false)
+        })
+        .unwrap_or(false)
+}
+
+/// A log file that is rotated when it grows larger than the configured maximum size.
+pub struct RotatingLogFile {
+    path: PathBuf,
+    /// Only `None` while rotating, since an open file cannot be renamed on Windows.
+    file: Option<fs::File>,
+    size: u64,
+}
+
+impl RotatingLogFile {
+    /// Rotates the log file at `path`, and opens a new one.
+    pub fn create(path: &Path) -> Result<Self, RotateLogError> {
+        rotate_log(path)?;
+        let file = fs::OpenOptions::new()
+            .append(true)
+            .open(path)
+            .map_err(RotateLogError)?;
+        Ok(RotatingLogFile {
+            path: path.to_owned(),
+            file: Some(file),
+            size: 0,
+        })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if let Some(mut file) = self.file.take() {
+            file.flush()?;
+        }
+        self.size = 0;
+        let result = shift_generations(&self.path, &rotation_limits());
+        // If the log could not be moved, keep writing to the end of it rather than losing it
+        self.file()?;
+        result
+    }
+
+    /// Returns the log file, opening it if it is not open since the last rotation.
+    fn file(&mut self) -> io::Result<&mut fs::File> {
+        if self.file.is_none() {
+            self.file = Some(
+                fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?,
             );
         }
+        Ok(self.file.as_mut().unwrap())
     }
+}
 
-    fs::File::create(file).map(|_| ()).map_err(RotateLogError)
+impl Write for RotatingLogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file()?.write(buf)?;
+        self.size += written as u64;
+        if self.size >= rotation_limits().max_size {
+            if let Err(error) = self.rotate() {
+                // The logger may be calling this, so the error can only be written to the log
+                if let Ok(file) = self.file() {
+                    let _ = writeln!(file, "Failed to rotate log file: {}", error);
+                }
+            }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file {
+            Some(ref mut file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn limits(generations: usize, compress: bool) -> RotationLimits {
+        RotationLimits {
+            max_size: 1024,
+            generations,
+            max_age: None,
+            compress,
+        }
+    }
+
+    #[test]
+    fn test_backup_path() {
+        let file = Path::new("/var/log/daemon.log");
+        assert_eq!(
+            backup_path(file, 1, true),
+            Path::new("/var/log/daemon.old.log")
+        );
+        assert_eq!(
+            backup_path(file, 2, false),
+            Path::new("/var/log/daemon.old.log.2")
+        );
+        assert_eq!(
+            backup_path(file, 3, true),
+            Path::new("/var/log/daemon.old.log.3.gz")
+        );
+    }
+
+    #[test]
+    fn test_shift_generations() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("daemon.log");
+
+        for content in &["first", "second", "third", "fourth"] {
+            fs::write(&file, content).unwrap();
+            shift_generations(&file, &limits(3, false)).unwrap();
+        }
+
+        assert!(!file.exists());
+        let read = |generation| fs::read_to_string(backup_path(&file, generation, false)).unwrap();
+        assert_eq!(read(1), "fourth");
+        assert_eq!(read(2), "third");
+        assert_eq!(read(3), "second");
+        assert!(!backup_path(&file, 4, false).exists());
+    }
+
+    #[test]
+    fn test_shift_generations_compressed() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("daemon.log");
+
+        for content in &["first", "second"] {
+            fs::write(&file, content).unwrap();
+            shift_generations(&file, &limits(2, true)).unwrap();
+        }
+
+        assert_eq!(
+            fs::read_to_string(backup_path(&file, 1, false)).unwrap(),
+            "second"
+        );
+        assert!(backup_path(&file, 2, true).exists());
+        assert!(!backup_path(&file, 2, false).exists());
+    }
+
+    #[test]
+    fn test_shift_generations_removes_excess() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("daemon.log");
+
+        for content in &["first", "second", "third"] {
+            fs::write(&file, content).unwrap();
+            shift_generations(&file, &limits(3, false)).unwrap();
+        }
+        fs::write(&file, "fourth").unwrap();
+        shift_generations(&file, &limits(1, false)).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(backup_path(&file, 1, false)).unwrap(),
+            "fourth"
+        );
+        assert!(!backup_path(&file, 2, false).exists());
+        assert!(!backup_path(&file, 3, false).exists());
+    }
 }
@@ -0,0 +1,285 @@
+//! Relays mDNS (multicast DNS) traffic between the local network and the host while the tunnel is
+//! up.
+//!
+//! Applications that send their service discovery queries using the default route end up sending
+//! them into the tunnel, where nobody answers. The reflector picks these queries up and sends
+//! them out on every physical LAN interface, and delivers the answers from the local network back
+//! to the querying applications. Reflected packets are never sent into the tunnel: queries only
+//! leave through the LAN interfaces and answers are sent to the host over a local unicast route.
+//!
+//! Hosts running a full mDNS responder (on port 5353) already listen on the physical interfaces,
+//! so only one-shot queries, sent from other ports, are answered through the reflector.
+
+use crate::tunnel::TunnelMetadata;
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use talpid_types::ErrorExt;
+
+const MDNS_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+
+/// How long to keep delivering answers to a querying application after its last query.
+const QUERIER_TIMEOUT: Duration = Duration::from_secs(120);
+/// How often the relay thread checks whether it should stop.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_PACKET_SIZE: usize = 9000;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// The tunnel has no IPv4 address that host queries can be recognized by.
+    #[error(display = "The tunnel interface has no IPv4 address")]
+    NoTunnelAddress,
+
+    /// Failed to list the addresses of the network interfaces.
+    #[error(display = "Failed to list the network interface addresses")]
+    ListAddresses(#[error(source)] io::Error),
+
+    /// There is no physical interface to reflect mDNS traffic on.
+    #[error(display = "Found no local network interfaces")]
+    NoLanInterfaces,
+
+    /// Failed to create or bind the mDNS socket.
+    #[error(display = "Failed to open the mDNS socket")]
+    OpenSocket(#[error(source)] io::Error),
+
+    /// Failed to join the mDNS multicast group on an interface.
+    #[error(display = "Failed to join the mDNS group on {}", _0)]
+    JoinGroup(Ipv4Addr, #[error(source)] io::Error),
+
+    /// Failed to spawn the relay thread.
+    #[error(display = "Failed to spawn the mDNS reflector thread")]
+    SpawnThread(#[error(source)] io::Error),
+}
+
+/// Relays mDNS traffic until dropped.
+pub struct MdnsReflector {
+    stop: Arc<AtomicBool>,
+}
+
+impl MdnsReflector {
+    /// Starts relaying mDNS traffic between the LAN interfaces and the host. The set of LAN
+    /// interfaces is determined once, when the reflector is started.
+    pub fn start(tunnel: &TunnelMetadata) -> Result<Self, Error> {
+        let tunnel_ip = tunnel
+            .ips
+            .iter()
+            .find_map(|ip| match ip {
+                IpAddr::V4(ip) => Some(*ip),
+                IpAddr::V6(_) => None,
+            })
+            .ok_or(Error::NoTunnelAddress)?;
+
+        let lan_ips: Vec<Ipv4Addr> = lan_addresses(&tunnel.interface)
+            .map_err(Error::ListAddresses)?
+            .into_iter()
+            .filter(|ip| !tunnel.ips.contains(&IpAddr::V4(*ip)))
+            .collect();
+        if lan_ips.is_empty() {
+            return Err(Error::NoLanInterfaces);
+        }
+
+        let socket = open_socket().map_err(Error::OpenSocket)?;
+        for interface_ip in lan_ips.iter().chain(std::iter::once(&tunnel_ip)) {
+            socket
+                .join_multicast_v4(&MDNS_ADDR, interface_ip)
+                .map_err(|error| Error::JoinGroup(*interface_ip, error))?;
+        }
+
+        log::info!(
+            "Reflecting mDNS traffic between the host and {}",
+            lan_ips
+                .iter()
+                .map(|ip| ip.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let relay = Relay {
+            socket,
+            tunnel_ip,
+            lan_ips,
+            queriers: HashMap::new(),
+            stop: stop.clone(),
+        };
+        std::thread::Builder::new()
+            .name("mdns-reflector".to_owned())
+            .spawn(move || relay.run())
+            .map_err(Error::SpawnThread)?;
+
+        Ok(MdnsReflector { stop })
+    }
+}
+
+impl Drop for MdnsReflector {
+    fn drop(&mut self) {
+        log::debug!("Stopping the mDNS reflector");
+        self.stop.store(true, Ordering::Release);
+    }
+}
+
+struct Relay {
+    socket: Socket,
+    tunnel_ip: Ipv4Addr,
+    lan_ips: Vec<Ipv4Addr>,
+    /// Source ports of applications on the host that have recently sent queries into the tunnel.
+    queriers: HashMap<u16, Instant>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Relay {
+    fn run(mut self) {
+        let mut buffer = [0u8; MAX_PACKET_SIZE];
+        while !self.stop.load(Ordering::Acquire) {
+            let (length, source) = match self.socket.recv_from(as_uninit(&mut buffer)) {
+                Ok(received) => received,
+                Err(error)
+                    if error.kind() == io::ErrorKind::WouldBlock
+                        || error.kind() == io::ErrorKind::TimedOut =>
+                {
+                    continue;
+                }
+                Err(error) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("mDNS reflector failed to receive")
+                    );
+                    break;
+                }
+            };
+            if let Some(SocketAddr::V4(source)) = source.as_socket() {
+                self.handle_packet(&buffer[..length], source);
+            }
+        }
+        log::debug!("The mDNS reflector has stopped");
+    }
+
+    fn handle_packet(&mut self, packet: &[u8], source: SocketAddrV4) {
+        if *source.ip() == self.tunnel_ip {
+            // A query from the host that was sent into the tunnel.
+            if source.port() != MDNS_PORT {
+                self.queriers.insert(source.port(), Instant::now());
+            }
+            self.send_to_lan(packet);
+        } else if self.lan_ips.contains(source.ip()) || !is_lan_source(*source.ip()) {
+            // Sent by this host on a LAN interface, which includes what the reflector itself
+            // sent, or by something that is not on the local network.
+        } else {
+            self.send_to_host(packet);
+        }
+    }
+
+    fn send_to_lan(&self, packet: &[u8]) {
+        let destination = SockAddr::from(SocketAddrV4::new(MDNS_ADDR, MDNS_PORT));
+        for lan_ip in &self.lan_ips {
+            let result = self
+                .socket
+                .set_multicast_if_v4(lan_ip)
+                .and_then(|()| self.socket.send_to(packet, &destination));
+            if let Err(error) = result {
+                log::debug!(
+                    "{}",
+                    error.display_chain_with_msg(&format!(
+                        "Failed to send mDNS query on {}",
+                        lan_ip
+                    ))
+                );
+            }
+        }
+    }
+
+    fn send_to_host(&mut self, packet: &[u8]) {
+        self.queriers
+            .retain(|_, last_query| last_query.elapsed() < QUERIER_TIMEOUT);
+        for port in self.queriers.keys() {
+            // The tunnel IP is local, so this never leaves the host.
+            let destination = SockAddr::from(SocketAddrV4::new(self.tunnel_ip, *port));
+            if let Err(error) = self.socket.send_to(packet, &destination) {
+                log::debug!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to deliver mDNS answer to the host")
+                );
+            }
+        }
+    }
+}
+
+fn open_socket() -> io::Result<Socket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    // Share the port with any mDNS responder running on the host.
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    // Keep reflected queries out of the routing table of the tunnel.
+    #[cfg(target_os = "linux")]
+    socket.set_mark(crate::linux::TUNNEL_FW_MARK)?;
+    socket.set_multicast_ttl_v4(255)?;
+    socket.set_read_timeout(Some(POLL_INTERVAL))?;
+    socket.bind(&SockAddr::from(SocketAddrV4::new(
+        Ipv4Addr::UNSPECIFIED,
+        MDNS_PORT,
+    )))?;
+    Ok(socket)
+}
+
+fn as_uninit(buffer: &mut [u8]) -> &mut [std::mem::MaybeUninit<u8>] {
+    // SAFETY: `MaybeUninit<u8>` has the same layout as `u8`, and the buffer is already
+    // initialized.
+    unsafe { &mut *(buffer as *mut [u8] as *mut [std::mem::MaybeUninit<u8>]) }
+}
+
+/// Returns whether packets from `ip` may come from the local network.
+fn is_lan_source(ip: Ipv4Addr) -> bool {
+    ip.is_private() || ip.is_link_local()
+}
+
+/// Returns the IPv4 addresses of the interfaces that are up, support multicast and are connected
+/// to a local network.
+#[cfg(unix)]
+fn lan_addresses(tunnel_interface: &str) -> io::Result<Vec<Ipv4Addr>> {
+    use nix::{ifaddrs::getifaddrs, net::if_::InterfaceFlags, sys::socket::SockAddr};
+
+    let required_flags = InterfaceFlags::IFF_UP | InterfaceFlags::IFF_MULTICAST;
+    let addresses = getifaddrs().map_err(|error| match error.as_errno() {
+        Some(errno) => io::Error::from_raw_os_error(errno as i32),
+        None => io::Error::new(io::ErrorKind::Other, error),
+    })?;
+    Ok(addresses
+        .filter(|address| {
+            address.interface_name != tunnel_interface
+                && address.flags.contains(required_flags)
+                && !address.flags.contains(InterfaceFlags::IFF_LOOPBACK)
+        })
+        .filter_map(|address| match address.address? {
+            SockAddr::Inet(address) => match address.ip().to_std() {
+                IpAddr::V4(ip) if is_lan_source(ip) => Some(ip),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect())
+}
+
+/// Returns the IPv4 addresses of the interfaces that are connected to a local network.
+#[cfg(windows)]
+fn lan_addresses(_tunnel_interface: &str) -> io::Result<Vec<Ipv4Addr>> {
+    use talpid_windows_net::{AddressFamily, UnicastAddress};
+
+    Ok(UnicastAddress::table(Some(AddressFamily::Ipv4))?
+        .into_iter()
+        .filter_map(|address| match address.address() {
+            Some(IpAddr::V4(address)) if is_lan_source(address) => Some(address),
+            _ => None,
+        })
+        .collect())
+}
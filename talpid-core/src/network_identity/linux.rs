@@ -0,0 +1,141 @@
+use super::Gateway;
+use std::{fs, io, net::Ipv4Addr, path::Path};
+use talpid_dbus::network_manager::{self, NetworkManager};
+use talpid_types::net::fingerprint::InterfaceType;
+
+pub use network_manager::Error;
+
+/// Returns the SSIDs of the Wi-Fi networks that NetworkManager is connected to. Wi-Fi connections
+/// that are not managed by NetworkManager are not found.
+pub fn ssids() -> Result<Vec<String>, Error> {
+    NetworkManager::new()?.wifi_ssids()
+}
+
+/// Returns whether NetworkManager considers the primary connection to be metered.
+pub fn metered() -> Result<bool, Error> {
+    NetworkManager::new()?.metered()
+}
+
+/// Returns the gateway of the IPv4 default route with the lowest metric in the main routing
+/// table. The tunnel routes are kept in a separate table, so this is never the tunnel.
+pub fn default_gateway() -> io::Result<Option<Gateway>> {
+    let routes = fs::read_to_string("/proc/net/route")?;
+    let (interface, gateway) = match parse_default_route(&routes) {
+        Some(route) => route,
+        None => return Ok(None),
+    };
+    let neighbors = fs::read_to_string("/proc/net/arp")?;
+    Ok(Some(Gateway {
+        mac: parse_neighbor_mac(&neighbors, gateway, &interface),
+        interface_type: interface_type(&interface),
+    }))
+}
+
+/// Returns the interface and gateway of the default route with the lowest metric in the output
+/// of `/proc/net/route`.
+fn parse_default_route(routes: &str) -> Option<(String, Ipv4Addr)> {
+    routes
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 8 || fields[1] != "00000000" || fields[7] != "00000000" {
+                return None;
+            }
+            // The addresses are written in host byte order
+            let gateway = u32::from_str_radix(fields[2], 16).ok()?;
+            let metric: u32 = fields[6].parse().ok()?;
+            Some((metric, fields[0], Ipv4Addr::from(u32::from_be(gateway))))
+        })
+        .filter(|(_, _, gateway)| !gateway.is_unspecified())
+        .min_by_key(|(metric, ..)| *metric)
+        .map(|(_, interface, gateway)| (interface.to_owned(), gateway))
+}
+
+/// Returns the MAC address of a complete entry for `address` on `interface` in the output of
+/// `/proc/net/arp`.
+fn parse_neighbor_mac(neighbors: &str, address: Ipv4Addr, interface: &str) -> Option<[u8; 6]> {
+    const ATF_COM: u32 = 0x2;
+
+    neighbors.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 6
+            || fields[5] != interface
+            || fields[0].parse::<Ipv4Addr>() != Ok(address)
+        {
+            return None;
+        }
+        let flags = u32::from_str_radix(fields[2].trim_start_matches("0x"), 16).ok()?;
+        if flags & ATF_COM == 0 {
+            return None;
+        }
+        parse_mac(fields[3])
+    })
+}
+
+fn parse_mac(mac: &str) -> Option<[u8; 6]> {
+    let mut octets = [0u8; 6];
+    let mut parts = mac.split(':');
+    for octet in octets.iter_mut() {
+        *octet = u8::from_str_radix(parts.next()?, 16).ok()?;
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(octets)
+}
+
+/// Tells the kind of an interface by the device type that the kernel reports for it.
+fn interface_type(interface: &str) -> InterfaceType {
+    const ARPHRD_ETHER: &str = "1";
+
+    let path = Path::new("/sys/class/net").join(interface);
+    let uevent = fs::read_to_string(path.join("uevent")).unwrap_or_default();
+    for line in uevent.lines() {
+        match line {
+            "DEVTYPE=wlan" => return InterfaceType::Wifi,
+            "DEVTYPE=wwan" => return InterfaceType::Cellular,
+            _ => (),
+        }
+    }
+    match fs::read_to_string(path.join("type")) {
+        Ok(device_type) if device_type.trim() == ARPHRD_ETHER => InterfaceType::Ethernet,
+        _ => InterfaceType::Other,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_default_route() {
+        let routes = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+wlan0\t00000000\t0101A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0
+eth0\t00000000\t0100000A\t0003\t0\t0\t100\t00000000\t0\t0\t0
+eth0\t0000000A\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0
+";
+        assert_eq!(
+            parse_default_route(routes),
+            Some(("eth0".to_owned(), Ipv4Addr::new(10, 0, 0, 1)))
+        );
+    }
+
+    #[test]
+    fn test_parse_neighbor_mac() {
+        let neighbors = "\
+IP address       HW type     Flags       HW address            Mask     Device
+192.168.1.1      0x1         0x2         00:11:22:aa:bb:cc     *        wlan0
+10.0.0.1         0x1         0x0         00:00:00:00:00:00     *        eth0
+";
+        assert_eq!(
+            parse_neighbor_mac(neighbors, Ipv4Addr::new(192, 168, 1, 1), "wlan0"),
+            Some([0x00, 0x11, 0x22, 0xaa, 0xbb, 0xcc])
+        );
+        assert_eq!(
+            parse_neighbor_mac(neighbors, Ipv4Addr::new(10, 0, 0, 1), "eth0"),
+            None
+        );
+    }
+}
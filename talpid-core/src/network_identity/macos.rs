@@ -0,0 +1,195 @@
+use super::Gateway;
+use std::{io, net::Ipv4Addr, process::Command};
+use talpid_types::net::fingerprint::InterfaceType;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to run networksetup")]
+    RunNetworksetup(#[error(source)] io::Error),
+
+    #[error(display = "networksetup failed: {}", _0)]
+    NetworksetupFailed(String),
+}
+
+/// Returns the SSIDs of the Wi-Fi networks that the Wi-Fi devices are connected to.
+pub fn ssids() -> Result<Vec<String>, Error> {
+    let hardware_ports = networksetup(&["-listallhardwareports"])?;
+    let mut ssids = vec![];
+    for device in wifi_devices(&hardware_ports) {
+        let network = networksetup(&["-getairportnetwork", device])?;
+        if let Some(ssid) = parse_airport_network(&network) {
+            ssids.push(ssid.to_owned());
+        }
+    }
+    Ok(ssids)
+}
+
+/// Returns the gateway of the IPv4 default route. The tunnel routes are more specific than the
+/// default route, so this is never the tunnel.
+pub fn default_gateway() -> io::Result<Option<Gateway>> {
+    let route = run("/sbin/route", &["-n", "get", "default"])?;
+    let (interface, gateway) = match parse_default_route(&route) {
+        Some(route) => route,
+        None => return Ok(None),
+    };
+    let neighbor = run("/usr/sbin/arp", &["-n", &gateway.to_string()])?;
+    let hardware_ports = run("/usr/sbin/networksetup", &["-listallhardwareports"])?;
+    let interface_type = parse_hardware_ports(&hardware_ports)
+        .find(|(_, device)| *device == interface)
+        .map(|(port, _)| port_type(port))
+        .unwrap_or_default();
+    Ok(Some(Gateway {
+        mac: parse_arp_mac(&neighbor),
+        interface_type,
+    }))
+}
+
+fn run(program: &str, args: &[&str]) -> io::Result<String> {
+    let output = Command::new(program).args(args).output()?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Returns the interface and gateway in the output of `route -n get default`.
+fn parse_default_route(route: &str) -> Option<(&str, Ipv4Addr)> {
+    let mut interface = None;
+    let mut gateway = None;
+    for line in route.lines() {
+        if let Some(value) = line.trim().strip_prefix("interface: ") {
+            interface = Some(value.trim());
+        } else if let Some(value) = line.trim().strip_prefix("gateway: ") {
+            gateway = value.trim().parse().ok();
+        }
+    }
+    Some((interface?, gateway?))
+}
+
+/// Returns the MAC address in the output of `arp -n <address>`. Leading zeros are left out of the
+/// octets.
+fn parse_arp_mac(neighbor: &str) -> Option<[u8; 6]> {
+    let mac = neighbor.split(" at ").nth(1)?.split_whitespace().next()?;
+    let mut octets = [0u8; 6];
+    let mut parts = mac.split(':');
+    for octet in octets.iter_mut() {
+        *octet = u8::from_str_radix(parts.next()?, 16).ok()?;
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(octets)
+}
+
+fn networksetup(args: &[&str]) -> Result<String, Error> {
+    let output = Command::new("/usr/sbin/networksetup")
+        .args(args)
+        .output()
+        .map_err(Error::RunNetworksetup)?;
+    if !output.status.success() {
+        return Err(Error::NetworksetupFailed(
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Returns the names of the Wi-Fi devices in the output of `networksetup -listallhardwareports`.
+fn wifi_devices(hardware_ports: &str) -> Vec<&str> {
+    parse_hardware_ports(hardware_ports)
+        .filter(|(port, _)| port_type(port) == InterfaceType::Wifi)
+        .map(|(_, device)| device)
+        .collect()
+}
+
+/// Returns the hardware ports and their devices in the output of
+/// `networksetup -listallhardwareports`.
+fn parse_hardware_ports(hardware_ports: &str) -> impl Iterator<Item = (&str, &str)> {
+    let mut port = None;
+    hardware_ports.lines().filter_map(move |line| {
+        if let Some(name) = line.strip_prefix("Hardware Port: ") {
+            port = Some(name.trim());
+        } else if let Some(device) = line.strip_prefix("Device: ") {
+            return port.take().map(|port| (port, device.trim()));
+        }
+        None
+    })
+}
+
+/// Tells the kind of interface by the name of its hardware port.
+fn port_type(port: &str) -> InterfaceType {
+    if port == "Wi-Fi" || port == "AirPort" {
+        InterfaceType::Wifi
+    } else if port.starts_with("iPhone USB") {
+        InterfaceType::Cellular
+    } else if port.contains("Ethernet") || port.contains("LAN") {
+        InterfaceType::Ethernet
+    } else {
+        InterfaceType::Other
+    }
+}
+
+/// Returns the SSID in the output of `networksetup -getairportnetwork`, unless the device is not
+/// connected to a network.
+fn parse_airport_network(output: &str) -> Option<&str> {
+    let line = output.lines().next()?;
+    line.strip_prefix("Current Wi-Fi Network: ")
+        .or_else(|| line.strip_prefix("Current AirPort Network: "))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_wifi_devices() {
+        let hardware_ports = "
+Hardware Port: Ethernet
+Device: en1
+Ethernet Address: a0:ce:c8:00:00:01
+
+Hardware Port: Wi-Fi
+Device: en0
+Ethernet Address: 3c:22:fb:00:00:02
+";
+        assert_eq!(wifi_devices(hardware_ports), vec!["en0"]);
+    }
+
+    #[test]
+    fn test_parse_default_route() {
+        let route = "
+   route to: default
+destination: default
+       mask: default
+    gateway: 192.168.1.1
+  interface: en0
+      flags: <UP,GATEWAY,DONE,STATIC,PRCLONING>
+";
+        assert_eq!(
+            parse_default_route(route),
+            Some(("en0", Ipv4Addr::new(192, 168, 1, 1)))
+        );
+    }
+
+    #[test]
+    fn test_parse_arp_mac() {
+        assert_eq!(
+            parse_arp_mac("? (192.168.1.1) at 0:11:22:aa:bb:c on en0 ifscope [ethernet]\n"),
+            Some([0x00, 0x11, 0x22, 0xaa, 0xbb, 0x0c])
+        );
+        assert_eq!(
+            parse_arp_mac("? (192.168.1.1) at (incomplete) on en0 [ethernet]\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_airport_network() {
+        assert_eq!(
+            parse_airport_network("Current Wi-Fi Network: Home network\n"),
+            Some("Home network")
+        );
+        assert_eq!(
+            parse_airport_network("You are not associated with an AirPort network.\n"),
+            None
+        );
+    }
+}
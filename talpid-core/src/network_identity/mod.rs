@@ -0,0 +1,168 @@
+//! Tells which network the host is on, by the SSIDs of the Wi-Fi networks that it is connected to,
+//! the on-link networks of its physical interfaces and the fingerprint of the network that the
+//! default route goes through. The network is looked up again whenever the network interfaces
+//! change, so that joining another network can be acted on.
+use crate::{interface_watcher::InterfaceWatcher, lan_watcher};
+use futures::{channel::mpsc::UnboundedSender, FutureExt, StreamExt};
+use std::time::Duration;
+use talpid_types::{
+    net::{
+        fingerprint::{InterfaceType, NetworkFingerprint},
+        NetworkIdentity,
+    },
+    ErrorExt,
+};
+
+#[cfg(target_os = "linux")]
+#[path = "linux.rs"]
+mod imp;
+
+#[cfg(target_os = "macos")]
+#[path = "macos.rs"]
+mod imp;
+
+#[cfg(windows)]
+#[path = "windows.rs"]
+mod imp;
+
+/// How long to wait after the interfaces have changed before looking up the network, since
+/// joining a network changes the interfaces several times.
+const SETTLE_DELAY: Duration = Duration::from_secs(2);
+
+/// Errors that can occur while finding out which network the host is on.
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Failed to list the on-link networks.
+    #[error(display = "Failed to list the on-link networks")]
+    ListNetworks(#[error(source)] lan_watcher::Error),
+
+    /// Failed to watch the network interfaces for changes.
+    #[error(display = "Failed to watch the network interfaces")]
+    WatchInterfaces(#[error(source)] crate::interface_watcher::Error),
+}
+
+/// The router that the default route goes through.
+struct Gateway {
+    /// The MAC address of the router, if it is in the neighbor table.
+    mac: Option<[u8; 6]>,
+    /// The kind of interface that the default route goes through.
+    interface_type: InterfaceType,
+}
+
+/// Returns the network that the host is on. If the SSIDs or the default gateway cannot be read,
+/// the network is identified by what is left.
+pub fn current() -> Result<NetworkIdentity, Error> {
+    let networks = lan_watcher::networks().map_err(Error::ListNetworks)?;
+    let ssids = imp::ssids().unwrap_or_else(|error| {
+        log::debug!(
+            "{}",
+            error.display_chain_with_msg("Failed to read the SSIDs of the Wi-Fi networks")
+        );
+        vec![]
+    });
+    let fingerprint = fingerprint(&ssids);
+    Ok(NetworkIdentity {
+        ssids,
+        networks,
+        fingerprint,
+    })
+}
+
+/// Returns whether the host is on a metered network, such as a mobile hotspot. Only NetworkManager
+/// reports this, so on other platforms, or if NetworkManager is not running, the network is assumed
+/// not to be metered.
+#[cfg(target_os = "linux")]
+pub fn is_metered() -> bool {
+    imp::metered().unwrap_or_else(|error| {
+        log::debug!(
+            "{}",
+            error.display_chain_with_msg("Failed to check whether the network is metered")
+        );
+        false
+    })
+}
+
+/// Returns whether the host is on a metered network. This is not detected on this platform.
+#[cfg(not(target_os = "linux"))]
+pub fn is_metered() -> bool {
+    false
+}
+
+/// Returns the fingerprint of the network that the default route goes through. The SSID is only
+/// part of it if the default route goes through Wi-Fi, and the interface that is connected to the
+/// SSID cannot be told, so the first SSID is used.
+fn fingerprint(ssids: &[String]) -> NetworkFingerprint {
+    let gateway = imp::default_gateway().unwrap_or_else(|error| {
+        log::debug!(
+            "{}",
+            error.display_chain_with_msg("Failed to look up the default gateway")
+        );
+        None
+    });
+    let interface_type = gateway
+        .as_ref()
+        .map(|gateway| gateway.interface_type)
+        .unwrap_or_default();
+    let ssid = match interface_type {
+        InterfaceType::Wifi => ssids.first().map(|ssid| ssid.as_bytes()),
+        _ => None,
+    };
+    NetworkFingerprint::new(
+        gateway.and_then(|gateway| gateway.mac).as_ref(),
+        ssid,
+        interface_type,
+    )
+}
+
+/// Watches the network that the host is on for as long as it is alive.
+pub struct NetworkWatcher {
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for NetworkWatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Starts watching the network that the host is on. The network is sent on `sender` once it has
+/// been looked up, and every time that it changes afterwards.
+pub async fn spawn_watcher(
+    sender: UnboundedSender<NetworkIdentity>,
+) -> Result<NetworkWatcher, Error> {
+    let interface_events = InterfaceWatcher::new()
+        .await
+        .map_err(Error::WatchInterfaces)?;
+    let task = tokio::spawn(watch(interface_events, sender));
+    Ok(NetworkWatcher { task })
+}
+
+async fn watch(mut interface_events: InterfaceWatcher, sender: UnboundedSender<NetworkIdentity>) {
+    let mut last_network = None;
+    loop {
+        match tokio::task::spawn_blocking(current).await {
+            Ok(Ok(network)) => {
+                if last_network.as_ref() != Some(&network) {
+                    log::debug!("The network changed");
+                    log::trace!("Current network: {:?}", network);
+                    last_network = Some(network.clone());
+                    if sender.unbounded_send(network).is_err() {
+                        return;
+                    }
+                }
+            }
+            Ok(Err(error)) => log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to look up the current network")
+            ),
+            Err(error) => log::error!("Network lookup panicked: {}", error),
+        }
+
+        if interface_events.next().await.is_none() {
+            return;
+        }
+        tokio::time::sleep(SETTLE_DELAY).await;
+        while let Some(Some(_)) = interface_events.next().now_or_never() {}
+    }
+}
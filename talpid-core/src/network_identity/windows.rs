@@ -0,0 +1,201 @@
+use super::Gateway;
+use std::{io, mem, ptr, slice};
+use talpid_types::net::fingerprint::InterfaceType;
+use talpid_windows_net::{try_socketaddr_from_inet_sockaddr, Luid};
+use winapi::{
+    shared::{
+        guiddef::GUID,
+        ifdef::NET_LUID,
+        ipifcons::{IF_TYPE_ETHERNET_CSMACD, IF_TYPE_IEEE80211, IF_TYPE_WWANPP, IF_TYPE_WWANPP2},
+        minwindef::DWORD,
+        netioapi::{
+            FreeMibTable, GetIpForwardTable2, GetIpNetEntry2, MIB_IPNET_ROW2, PMIB_IPFORWARD_TABLE2,
+        },
+        nldef::NlnsIncomplete,
+        winerror::{ERROR_SERVICE_NOT_ACTIVE, ERROR_SUCCESS, NO_ERROR},
+        ws2def::AF_INET,
+    },
+    um::{
+        winnt::{HANDLE, PVOID},
+        wlanapi::{
+            wlan_interface_state_connected, wlan_intf_opcode_current_connection, WlanCloseHandle,
+            WlanEnumInterfaces, WlanFreeMemory, WlanOpenHandle, WlanQueryInterface,
+            PWLAN_CONNECTION_ATTRIBUTES, PWLAN_INTERFACE_INFO_LIST,
+        },
+    },
+};
+
+/// The version of the WLAN API that was introduced in Windows Vista.
+const WLAN_API_VERSION_2_0: DWORD = 2;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to open a handle to the WLAN service")]
+    OpenHandle(#[error(source)] io::Error),
+
+    #[error(display = "Failed to list the WLAN interfaces")]
+    ListInterfaces(#[error(source)] io::Error),
+}
+
+/// Returns the SSIDs of the Wi-Fi networks that the WLAN interfaces are connected to.
+pub fn ssids() -> Result<Vec<String>, Error> {
+    let handle = match WlanHandle::open() {
+        Ok(handle) => handle,
+        // The WLAN service does not run on hosts without Wi-Fi adapters
+        Err(error) if error.raw_os_error() == Some(ERROR_SERVICE_NOT_ACTIVE as i32) => {
+            return Ok(vec![])
+        }
+        Err(error) => return Err(Error::OpenHandle(error)),
+    };
+
+    let mut interfaces: PWLAN_INTERFACE_INFO_LIST = ptr::null_mut();
+    let status = unsafe { WlanEnumInterfaces(handle.0, ptr::null_mut(), &mut interfaces) };
+    if status != ERROR_SUCCESS {
+        return Err(Error::ListInterfaces(io::Error::from_raw_os_error(
+            status as i32,
+        )));
+    }
+    let interfaces = WlanMemory(interfaces);
+    // SAFETY: The list holds `dwNumberOfItems` entries, and lives until `interfaces` is dropped
+    let interfaces = unsafe {
+        let list = &*interfaces.0;
+        std::slice::from_raw_parts(list.InterfaceInfo.as_ptr(), list.dwNumberOfItems as usize)
+    };
+
+    Ok(interfaces
+        .iter()
+        .filter(|interface| interface.isState == wlan_interface_state_connected)
+        .filter_map(|interface| handle.connected_ssid(&interface.InterfaceGuid))
+        .collect())
+}
+
+/// Returns the gateway of the IPv4 default route with the lowest metric. Routes through virtual
+/// adapters, such as the tunnel, are skipped.
+pub fn default_gateway() -> io::Result<Option<Gateway>> {
+    let mut table: PMIB_IPFORWARD_TABLE2 = ptr::null_mut();
+    let status = unsafe { GetIpForwardTable2(AF_INET as u16, &mut table) };
+    if status != NO_ERROR {
+        return Err(io::Error::from_raw_os_error(status as i32));
+    }
+    let table = MibTable(table);
+    // SAFETY: The table holds `NumEntries` rows, and lives until `table` is dropped
+    let routes = unsafe {
+        let table = &*table.0;
+        slice::from_raw_parts(table.Table.as_ptr(), table.NumEntries as usize)
+    };
+
+    let route = routes
+        .iter()
+        .filter(|route| route.DestinationPrefix.PrefixLength == 0)
+        .filter(|route| !Luid::from(route.InterfaceLuid).is_virtual())
+        .filter(|route| {
+            try_socketaddr_from_inet_sockaddr(route.NextHop)
+                .map(|next_hop| !next_hop.ip().is_unspecified())
+                .unwrap_or(false)
+        })
+        .min_by_key(|route| route.Metric);
+    let route = match route {
+        Some(route) => route,
+        None => return Ok(None),
+    };
+
+    let mut neighbor: MIB_IPNET_ROW2 = unsafe { mem::zeroed() };
+    neighbor.Address = route.NextHop;
+    neighbor.InterfaceLuid = route.InterfaceLuid;
+    let status = unsafe { GetIpNetEntry2(&mut neighbor) };
+    let mac = if status == NO_ERROR
+        && neighbor.PhysicalAddressLength == 6
+        && neighbor.State > NlnsIncomplete
+    {
+        let mut mac = [0u8; 6];
+        mac.copy_from_slice(&neighbor.PhysicalAddress[..6]);
+        Some(mac)
+    } else {
+        None
+    };
+
+    Ok(Some(Gateway {
+        mac,
+        interface_type: interface_type(route.InterfaceLuid),
+    }))
+}
+
+fn interface_type(luid: NET_LUID) -> InterfaceType {
+    match u32::from(Luid::from(luid).interface_type()) {
+        IF_TYPE_IEEE80211 => InterfaceType::Wifi,
+        IF_TYPE_ETHERNET_CSMACD => InterfaceType::Ethernet,
+        IF_TYPE_WWANPP | IF_TYPE_WWANPP2 => InterfaceType::Cellular,
+        _ => InterfaceType::Other,
+    }
+}
+
+/// A table returned by one of the `Get*Table2` functions, which is freed when dropped.
+struct MibTable<T>(*mut T);
+
+impl<T> Drop for MibTable<T> {
+    fn drop(&mut self) {
+        unsafe { FreeMibTable(self.0 as PVOID) };
+    }
+}
+
+/// A handle to the WLAN service, which is closed when dropped.
+struct WlanHandle(HANDLE);
+
+impl WlanHandle {
+    fn open() -> io::Result<Self> {
+        let mut negotiated_version = 0;
+        let mut handle = ptr::null_mut();
+        let status = unsafe {
+            WlanOpenHandle(
+                WLAN_API_VERSION_2_0,
+                ptr::null_mut(),
+                &mut negotiated_version,
+                &mut handle,
+            )
+        };
+        if status != ERROR_SUCCESS {
+            return Err(io::Error::from_raw_os_error(status as i32));
+        }
+        Ok(WlanHandle(handle))
+    }
+
+    /// Returns the SSID of the network that the interface is connected to.
+    fn connected_ssid(&self, interface: &GUID) -> Option<String> {
+        let mut size = 0;
+        let mut attributes: PVOID = ptr::null_mut();
+        let status = unsafe {
+            WlanQueryInterface(
+                self.0,
+                interface,
+                wlan_intf_opcode_current_connection,
+                ptr::null_mut(),
+                &mut size,
+                &mut attributes,
+                ptr::null_mut(),
+            )
+        };
+        if status != ERROR_SUCCESS {
+            return None;
+        }
+        let attributes = WlanMemory(attributes as PWLAN_CONNECTION_ATTRIBUTES);
+        let ssid = unsafe { &(*attributes.0).wlanAssociationAttributes.dot11Ssid };
+        let length = (ssid.uSSIDLength as usize).min(ssid.ucSSID.len());
+        Some(String::from_utf8_lossy(&ssid.ucSSID[..length]).into_owned())
+    }
+}
+
+impl Drop for WlanHandle {
+    fn drop(&mut self) {
+        unsafe { WlanCloseHandle(self.0, ptr::null_mut()) };
+    }
+}
+
+/// Memory that was allocated by the WLAN API, which is freed when dropped.
+struct WlanMemory<T>(*mut T);
+
+impl<T> Drop for WlanMemory<T> {
+    fn drop(&mut self) {
+        unsafe { WlanFreeMemory(self.0 as PVOID) };
+    }
+}
@@ -62,10 +62,13 @@ pub struct TunnelDevice {
 }
 
 impl TunnelDevice {
-    /// Creates a new Tunnel device
+    /// Creates a new Tunnel device. The system picks a name for it unless `name` is given.
     #[allow(unused_mut)]
-    pub fn new() -> Result<Self, Error> {
+    pub fn new(name: Option<&str>) -> Result<Self, Error> {
         let mut config = Configuration::default();
+        if let Some(name) = name {
+            config.name(name);
+        }
 
         #[cfg(target_os = "linux")]
         config.platform(|config| {
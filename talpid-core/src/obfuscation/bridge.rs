@@ -0,0 +1,444 @@
+//! Sends WireGuard traffic over TCP through a SOCKS5 proxy, for networks where the relays can only
+//! be reached through a bridge. The proxy connects to the TCP endpoint of the peer, and the
+//! datagrams are framed the same way as by UDP-over-TCP. Shadowsocks bridges are used through the
+//! bundled client, which runs a local SOCKS5 proxy.
+
+use super::{Error, Obfuscator};
+use crate::proxy::{self, ProxyMonitor, ProxyMonitorCloseHandle, ProxyResourceData};
+use std::{
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpStream, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use talpid_types::{
+    net::openvpn::{ProxyAuth, ProxySettings},
+    ErrorExt,
+};
+
+/// How long to wait for the proxy to accept the connection and connect to the peer.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long to wait between attempts to connect to a local proxy that is not listening yet.
+const LOCAL_PROXY_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+/// How often the thread that reads from the local socket checks whether it should stop.
+const RECV_TIMEOUT: Duration = Duration::from_millis(500);
+
+const MAX_DATAGRAM_SIZE: usize = u16::MAX as usize;
+
+const SOCKS_VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0;
+const USERNAME_PASSWORD_AUTHENTICATION: u8 = 2;
+const USERNAME_PASSWORD_VERSION: u8 = 1;
+const CONNECT_COMMAND: u8 = 1;
+const IPV4_ADDRESS: u8 = 1;
+const DOMAIN_NAME_ADDRESS: u8 = 3;
+const IPV6_ADDRESS: u8 = 4;
+
+/// Forwards the datagrams sent to a local UDP socket to a TCP endpoint on the peer, through a
+/// SOCKS5 proxy.
+pub struct BridgeForwarder {
+    local_addr: SocketAddr,
+    bridge_endpoint: SocketAddr,
+    stream: TcpStream,
+    stop: Arc<AtomicBool>,
+    exit_notifier: Arc<Mutex<ExitNotifier>>,
+    _proxy_monitor: Box<dyn ProxyMonitor>,
+    proxy_close_handle: Option<Box<dyn ProxyMonitorCloseHandle>>,
+}
+
+impl BridgeForwarder {
+    /// Connects to the TCP endpoint of the peer at `peer_endpoint` through the proxy in
+    /// `settings`, starting the bundled proxy client first if the bridge requires it.
+    pub fn new(
+        settings: &ProxySettings,
+        peer_endpoint: SocketAddr,
+        resource_data: &ProxyResourceData,
+    ) -> Result<Self, Error> {
+        let mut proxy_monitor =
+            proxy::start_proxy(settings, resource_data).map_err(Error::BridgeProxyError)?;
+        let proxy_close_handle = proxy_monitor.close_handle();
+        let (proxy_addr, auth) = match settings {
+            ProxySettings::Local(local_settings) => (
+                SocketAddr::new(Ipv4Addr::LOCALHOST.into(), local_settings.port),
+                None,
+            ),
+            ProxySettings::Remote(remote_settings) => {
+                (remote_settings.address, remote_settings.auth.as_ref())
+            }
+            ProxySettings::Shadowsocks(_) => (
+                SocketAddr::new(Ipv4Addr::LOCALHOST.into(), proxy_monitor.port()),
+                None,
+            ),
+        };
+
+        let setup = || -> io::Result<(TcpStream, UdpSocket)> {
+            let mut stream = connect_to_proxy(proxy_addr)?;
+            stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+            socks5_connect(&mut stream, auth, peer_endpoint)?;
+            stream.set_read_timeout(None)?;
+            stream.set_nodelay(true)?;
+
+            let socket = UdpSocket::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0))?;
+            socket.set_read_timeout(Some(RECV_TIMEOUT))?;
+            Ok((stream, socket))
+        };
+        let (stream, socket) = match setup() {
+            Ok(result) => result,
+            Err(error) => {
+                if let Err(close_error) = proxy_close_handle.close() {
+                    log::error!(
+                        "{}",
+                        close_error.display_chain_with_msg("Failed to stop bridge proxy")
+                    );
+                }
+                return Err(Error::BridgeConnectError(error));
+            }
+        };
+        let local_addr = socket.local_addr().map_err(Error::BridgeConnectError)?;
+
+        let forwarder = Self {
+            local_addr,
+            bridge_endpoint: settings.get_endpoint().endpoint.address,
+            stream,
+            stop: Arc::new(AtomicBool::new(false)),
+            exit_notifier: Arc::new(Mutex::new(ExitNotifier::default())),
+            _proxy_monitor: proxy_monitor,
+            proxy_close_handle: Some(proxy_close_handle),
+        };
+        forwarder.spawn_forwarding_threads(socket)?;
+        Ok(forwarder)
+    }
+
+    fn spawn_forwarding_threads(&self, socket: UdpSocket) -> Result<(), Error> {
+        let clone_error = Error::BridgeConnectError;
+        let reader = self.stream.try_clone().map_err(clone_error)?;
+        let writer = self.stream.try_clone().map_err(clone_error)?;
+        let local_socket = socket.try_clone().map_err(clone_error)?;
+        // The address that WireGuard sends from, which is only known after it has sent something.
+        let client_addr = Arc::new(Mutex::new(None));
+
+        let stop = self.stop.clone();
+        let client = client_addr.clone();
+        thread::spawn(move || {
+            if let Err(error) = forward_to_bridge(&local_socket, writer, &client, &stop) {
+                if !stop.load(Ordering::SeqCst) {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to send traffic to the bridge")
+                    );
+                }
+            }
+        });
+
+        let stop = self.stop.clone();
+        let exit_notifier = self.exit_notifier.clone();
+        thread::spawn(move || {
+            let result = forward_from_bridge(reader, &socket, &client_addr);
+            if stop.swap(true, Ordering::SeqCst) {
+                return;
+            }
+            let reason = match result {
+                Ok(()) => "The bridge closed the connection".to_owned(),
+                Err(error) => error.display_chain_with_msg("Failed to receive traffic from bridge"),
+            };
+            exit_notifier
+                .lock()
+                .unwrap()
+                .notify(Error::BridgeClosed(reason));
+        });
+        Ok(())
+    }
+}
+
+impl Obfuscator for BridgeForwarder {
+    fn endpoint(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    fn remote_endpoint(&self) -> SocketAddr {
+        self.bridge_endpoint
+    }
+
+    fn monitor_exit(&mut self, on_exit: Box<dyn FnOnce(Error) + Send>) {
+        self.exit_notifier.lock().unwrap().set_callback(on_exit);
+    }
+}
+
+impl Drop for BridgeForwarder {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        let _ = self.stream.shutdown(Shutdown::Both);
+        if let Some(close_handle) = self.proxy_close_handle.take() {
+            if let Err(error) = close_handle.close() {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to stop bridge proxy")
+                );
+            }
+        }
+    }
+}
+
+/// Calls the exit callback once it has been set, even if the connection was closed before that.
+#[derive(Default)]
+struct ExitNotifier {
+    on_exit: Option<Box<dyn FnOnce(Error) + Send>>,
+    error: Option<Error>,
+}
+
+impl ExitNotifier {
+    fn notify(&mut self, error: Error) {
+        match self.on_exit.take() {
+            Some(on_exit) => on_exit(error),
+            None => self.error = Some(error),
+        }
+    }
+
+    fn set_callback(&mut self, on_exit: Box<dyn FnOnce(Error) + Send>) {
+        match self.error.take() {
+            Some(error) => on_exit(error),
+            None => self.on_exit = Some(on_exit),
+        }
+    }
+}
+
+/// Connects to the proxy. A local proxy, such as the bundled Shadowsocks client, may not be
+/// listening yet when it has just been started, so connecting to it is retried for a while.
+fn connect_to_proxy(proxy_addr: SocketAddr) -> io::Result<TcpStream> {
+    let started = Instant::now();
+    loop {
+        match open_stream(proxy_addr) {
+            Err(error)
+                if error.kind() == io::ErrorKind::ConnectionRefused
+                    && proxy_addr.ip().is_loopback()
+                    && started.elapsed() < CONNECT_TIMEOUT =>
+            {
+                thread::sleep(LOCAL_PROXY_RETRY_INTERVAL);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Opens a TCP connection that is not routed through the tunnel.
+#[cfg(target_os = "linux")]
+fn open_stream(addr: SocketAddr) -> io::Result<TcpStream> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_mark(crate::linux::TUNNEL_FW_MARK)?;
+    socket.connect_timeout(&addr.into(), CONNECT_TIMEOUT)?;
+    Ok(socket.into())
+}
+
+/// Opens a TCP connection that is not routed through the tunnel.
+#[cfg(not(target_os = "linux"))]
+fn open_stream(addr: SocketAddr) -> io::Result<TcpStream> {
+    TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)
+}
+
+/// Asks the SOCKS5 proxy on the other end of `stream` to connect to `target`, authenticating with
+/// `auth` if it is set.
+fn socks5_connect(
+    stream: &mut TcpStream,
+    auth: Option<&ProxyAuth>,
+    target: SocketAddr,
+) -> io::Result<()> {
+    let method = if auth.is_some() {
+        USERNAME_PASSWORD_AUTHENTICATION
+    } else {
+        NO_AUTHENTICATION
+    };
+    stream.write_all(&[SOCKS_VERSION, 1, method])?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply)?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(protocol_error("The proxy does not speak SOCKS5"));
+    }
+    if reply[1] != method {
+        return Err(protocol_error(
+            "The proxy does not accept the authentication method",
+        ));
+    }
+
+    if let Some(auth) = auth {
+        let mut request = vec![USERNAME_PASSWORD_VERSION];
+        push_length_prefixed(&mut request, auth.username.as_bytes())?;
+        push_length_prefixed(&mut request, auth.password.as_bytes())?;
+        stream.write_all(&request)?;
+        stream.read_exact(&mut reply)?;
+        if reply[1] != 0 {
+            return Err(protocol_error("The proxy rejected the credentials"));
+        }
+    }
+
+    let mut request = vec![SOCKS_VERSION, CONNECT_COMMAND, 0];
+    match target.ip() {
+        IpAddr::V4(address) => {
+            request.push(IPV4_ADDRESS);
+            request.extend_from_slice(&address.octets());
+        }
+        IpAddr::V6(address) => {
+            request.push(IPV6_ADDRESS);
+            request.extend_from_slice(&address.octets());
+        }
+    }
+    request.extend_from_slice(&target.port().to_be_bytes());
+    stream.write_all(&request)?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply)?;
+    if reply[1] != 0 {
+        return Err(protocol_error(format!(
+            "The proxy failed to connect to {} (reply code {})",
+            target, reply[1]
+        )));
+    }
+    // Skip the address that the proxy bound to.
+    let address_len = match reply[3] {
+        IPV4_ADDRESS => 4,
+        IPV6_ADDRESS => 16,
+        DOMAIN_NAME_ADDRESS => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len)?;
+            usize::from(len[0])
+        }
+        _ => {
+            return Err(protocol_error(
+                "The proxy replied with an unknown address type",
+            ))
+        }
+    };
+    let mut bound_addr = vec![0u8; address_len + 2];
+    stream.read_exact(&mut bound_addr)
+}
+
+fn push_length_prefixed(buffer: &mut Vec<u8>, field: &[u8]) -> io::Result<()> {
+    if field.len() > usize::from(u8::MAX) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The proxy credentials are too long",
+        ));
+    }
+    buffer.push(field.len() as u8);
+    buffer.extend_from_slice(field);
+    Ok(())
+}
+
+fn protocol_error(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, message.into())
+}
+
+/// Sends the datagrams received on `socket` to the bridge, each prefixed by its length.
+fn forward_to_bridge(
+    socket: &UdpSocket,
+    mut stream: TcpStream,
+    client_addr: &Mutex<Option<SocketAddr>>,
+    stop: &AtomicBool,
+) -> io::Result<()> {
+    let mut buffer = vec![0u8; 2 + MAX_DATAGRAM_SIZE];
+    while !stop.load(Ordering::SeqCst) {
+        let (len, from) = match socket.recv_from(&mut buffer[2..]) {
+            Ok(received) => received,
+            Err(error)
+                if error.kind() == io::ErrorKind::WouldBlock
+                    || error.kind() == io::ErrorKind::TimedOut =>
+            {
+                continue
+            }
+            Err(error) => return Err(error),
+        };
+        *client_addr.lock().unwrap() = Some(from);
+        buffer[..2].copy_from_slice(&(len as u16).to_be_bytes());
+        stream.write_all(&buffer[..2 + len])?;
+    }
+    Ok(())
+}
+
+/// Sends the datagrams received from the bridge to the address that WireGuard sends from.
+/// Returns when the bridge closes the connection.
+fn forward_from_bridge(
+    mut stream: TcpStream,
+    socket: &UdpSocket,
+    client_addr: &Mutex<Option<SocketAddr>>,
+) -> io::Result<()> {
+    let mut buffer = vec![0u8; MAX_DATAGRAM_SIZE];
+    loop {
+        let mut header = [0u8; 2];
+        match stream.read_exact(&mut header) {
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            result => result?,
+        }
+        let len = usize::from(u16::from_be_bytes(header));
+        stream.read_exact(&mut buffer[..len])?;
+        if let Some(client_addr) = *client_addr.lock().unwrap() {
+            socket.send_to(&buffer[..len], client_addr)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn connects_through_socks5_proxy_with_credentials() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+        let target: SocketAddr = "10.0.0.1:80".parse().unwrap();
+
+        let proxy = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).unwrap();
+            assert_eq!(greeting, [5, 1, 2]);
+            stream.write_all(&[5, 2]).unwrap();
+
+            let mut credentials = [0u8; 1 + 1 + 4 + 1 + 6];
+            stream.read_exact(&mut credentials).unwrap();
+            assert_eq!(&credentials[..], b"\x01\x04user\x06secret");
+            stream.write_all(&[1, 0]).unwrap();
+
+            let mut request = [0u8; 10];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(request, [5, 1, 0, 1, 10, 0, 0, 1, 0, 80]);
+            stream.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+        });
+
+        let mut stream = TcpStream::connect(proxy_addr).unwrap();
+        let auth = ProxyAuth {
+            username: "user".to_owned(),
+            password: "secret".to_owned(),
+        };
+        socks5_connect(&mut stream, Some(&auth), target).unwrap();
+        proxy.join().unwrap();
+    }
+
+    #[test]
+    fn fails_when_proxy_cannot_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+
+        let proxy = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).unwrap();
+            stream.write_all(&[5, 0]).unwrap();
+            let mut request = [0u8; 22];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(request[3], IPV6_ADDRESS);
+            // Connection refused
+            stream.write_all(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+        });
+
+        let mut stream = TcpStream::connect(proxy_addr).unwrap();
+        let target = "[2001:db8::1]:443".parse().unwrap();
+        assert!(socks5_connect(&mut stream, None, target).is_err());
+        proxy.join().unwrap();
+    }
+}
@@ -0,0 +1,171 @@
+//! Obfuscators wrap the UDP traffic of WireGuard in another transport, for networks that block
+//! or throttle WireGuard. Each obfuscator listens on a local UDP socket and forwards whatever is
+//! sent to it to the remote peer, so the tunnel only has to be pointed at the local socket.
+//! Bridges are handled the same way, since they also forward the traffic for the peer.
+
+#[cfg(not(target_os = "android"))]
+use crate::proxy::ProxyResourceData;
+use std::{io, net::SocketAddr, path::Path};
+#[cfg(not(target_os = "android"))]
+use talpid_types::net::openvpn::ShadowsocksProxySettings;
+use talpid_types::net::wireguard::ObfuscatorConfig;
+
+#[cfg(not(target_os = "android"))]
+mod bridge;
+#[cfg(feature = "quic-obfuscation")]
+mod quic;
+#[cfg(not(target_os = "android"))]
+mod shadowsocks;
+mod udp2tcp;
+
+pub use self::udp2tcp::Udp2Tcp;
+
+/// Errors that can occur when running an obfuscator.
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Failed to set up Udp2Tcp
+    #[error(display = "Failed to start UDP-over-TCP proxy")]
+    Udp2TcpError(#[error(source)] udp_over_tcp::udp2tcp::ConnectError),
+
+    /// Failed to obtain the local UDP socket address
+    #[error(display = "Failed obtain local address for the UDP socket in Udp2Tcp")]
+    GetLocalUdpAddress(#[error(source)] io::Error),
+
+    /// Failed to start the Shadowsocks client
+    #[cfg(not(target_os = "android"))]
+    #[error(display = "Failed to start Shadowsocks client")]
+    ShadowsocksError(#[error(source)] io::Error),
+
+    /// The Shadowsocks client exited unexpectedly
+    #[cfg(not(target_os = "android"))]
+    #[error(display = "Shadowsocks client exited unexpectedly: {}", _0)]
+    ShadowsocksExited(String),
+
+    /// Failed to start the bundled client of the bridge
+    #[cfg(not(target_os = "android"))]
+    #[error(display = "Failed to start the bridge proxy client")]
+    BridgeProxyError(#[error(source)] io::Error),
+
+    /// Failed to connect to the peer through the bridge
+    #[cfg(not(target_os = "android"))]
+    #[error(display = "Failed to connect to the peer through the bridge")]
+    BridgeConnectError(#[error(source)] io::Error),
+
+    /// The connection through the bridge was closed unexpectedly
+    #[cfg(not(target_os = "android"))]
+    #[error(display = "The connection through the bridge was closed: {}", _0)]
+    BridgeClosed(String),
+
+    /// Failed to set up the QUIC proxy
+    #[cfg(feature = "quic-obfuscation")]
+    #[error(display = "Failed to start QUIC proxy")]
+    QuicProxyError(#[error(source)] quic::Error),
+
+    /// The obfuscator is not supported on this platform or in this build
+    #[error(display = "The obfuscator is not supported on this platform")]
+    Unsupported,
+}
+
+impl Error {
+    /// Returns whether starting the obfuscator again may succeed.
+    pub fn is_recoverable(&self) -> bool {
+        match self {
+            Error::Udp2TcpError(_) => true,
+            #[cfg(not(target_os = "android"))]
+            Error::ShadowsocksError(_) => true,
+            #[cfg(not(target_os = "android"))]
+            Error::BridgeProxyError(_) | Error::BridgeConnectError(_) => true,
+            #[cfg(feature = "quic-obfuscation")]
+            Error::QuicProxyError(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// A running obfuscator. Traffic is forwarded until it is dropped.
+pub trait Obfuscator: Send {
+    /// Returns the local address that the traffic for the peer should be sent to.
+    fn endpoint(&self) -> SocketAddr;
+
+    /// Returns the address that the obfuscator sends the traffic to. It must be reachable outside
+    /// the tunnel.
+    fn remote_endpoint(&self) -> SocketAddr;
+
+    /// Returns the MTU to use for the tunnel, given the MTU it would use without obfuscation.
+    fn tunnel_mtu(&self, mtu: u16) -> u16 {
+        mtu
+    }
+
+    /// Calls `on_exit` if the obfuscator stops before it is dropped.
+    fn monitor_exit(&mut self, _on_exit: Box<dyn FnOnce(Error) + Send>) {}
+}
+
+/// Returns whether WireGuard traffic can be sent through the given obfuscator on this platform and
+/// build. Obfuscators that are not supported are ignored when a tunnel is started.
+pub fn is_supported(config: &ObfuscatorConfig) -> bool {
+    match config {
+        // Relies on the bundled Shadowsocks client, which is not shipped on Android
+        ObfuscatorConfig::Shadowsocks { .. } => !cfg!(target_os = "android"),
+        ObfuscatorConfig::Quic { .. } => cfg!(feature = "quic-obfuscation"),
+        // Relies on the proxy clients, which are not used on Android
+        ObfuscatorConfig::Bridge(_) => !cfg!(target_os = "android"),
+    }
+}
+
+/// Starts the obfuscator described by `config`, which forwards traffic to `peer_endpoint`. For
+/// bridges, `peer_endpoint` must be a TCP endpoint.
+/// Bundled proxy software is found in `resource_dir` and logs to `log_dir`.
+#[cfg_attr(
+    any(target_os = "android", not(feature = "quic-obfuscation")),
+    allow(unused_variables)
+)]
+pub fn start_obfuscator(
+    runtime: &tokio::runtime::Handle,
+    config: &ObfuscatorConfig,
+    peer_endpoint: SocketAddr,
+    resource_dir: &Path,
+    log_dir: Option<&Path>,
+) -> Result<Box<dyn Obfuscator>, Error> {
+    match config {
+        #[cfg(not(target_os = "android"))]
+        ObfuscatorConfig::Shadowsocks {
+            endpoint,
+            password,
+            cipher,
+        } => {
+            let settings = ShadowsocksProxySettings {
+                peer: *endpoint,
+                password: password.clone(),
+                cipher: cipher.clone(),
+            };
+            let resource_data = ProxyResourceData {
+                resource_dir: resource_dir.to_path_buf(),
+                log_dir: log_dir.map(Path::to_path_buf),
+            };
+            let proxy =
+                shadowsocks::ShadowsocksProxy::new(&settings, peer_endpoint, &resource_data)?;
+            Ok(Box::new(proxy))
+        }
+        #[cfg(not(target_os = "android"))]
+        ObfuscatorConfig::Bridge(settings) => {
+            let resource_data = ProxyResourceData {
+                resource_dir: resource_dir.to_path_buf(),
+                log_dir: log_dir.map(Path::to_path_buf),
+            };
+            let forwarder = bridge::BridgeForwarder::new(settings, peer_endpoint, &resource_data)?;
+            Ok(Box::new(forwarder))
+        }
+        #[cfg(feature = "quic-obfuscation")]
+        ObfuscatorConfig::Quic {
+            endpoint,
+            server_name,
+        } => {
+            let proxy = quic::QuicProxy::new(runtime, *endpoint, server_name, peer_endpoint)
+                .map_err(Error::QuicProxyError)?;
+            Ok(Box::new(proxy))
+        }
+        #[allow(unreachable_patterns)]
+        _ => Err(Error::Unsupported),
+    }
+}
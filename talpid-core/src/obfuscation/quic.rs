@@ -0,0 +1,245 @@
+//! Experimental obfuscation that tunnels WireGuard datagrams inside a QUIC connection.
+//!
+//! The proxy is told which peer to forward to on the first bidirectional stream of the
+//! connection. After that, every WireGuard datagram is sent as a QUIC datagram (RFC 9221), much
+//! like UDP proxying in MASQUE. The HTTP/3 ALPN is negotiated, so that the connection can pass
+//! through networks that only allow HTTP/3.
+
+use super::Obfuscator;
+use bytes::Bytes;
+use futures::{
+    future::{self, abortable, AbortHandle},
+    StreamExt,
+};
+use std::{
+    cmp, io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex},
+};
+use tokio::net::UdpSocket;
+
+/// ALPN protocol negotiated with the proxy.
+const ALPN_PROTOCOL: &[u8] = b"h3";
+
+/// Bytes added by WireGuard to every packet sent through the tunnel.
+const WIREGUARD_OVERHEAD: usize = 32;
+
+/// Errors that can occur when setting up the QUIC proxy.
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to bind UDP socket")]
+    BindSocket(#[error(source)] io::Error),
+
+    #[error(display = "Failed to create QUIC endpoint")]
+    CreateEndpoint(#[error(source)] quinn::EndpointError),
+
+    #[error(display = "Failed to connect to the QUIC proxy")]
+    Connect(#[error(source)] quinn::ConnectError),
+
+    #[error(display = "The connection to the QUIC proxy failed")]
+    Connection(#[error(source)] quinn::ConnectionError),
+
+    #[error(display = "The QUIC proxy does not accept datagrams")]
+    DatagramsUnsupported,
+
+    #[error(display = "Failed to send the peer address to the QUIC proxy")]
+    SendRequest(#[error(source)] quinn::WriteError),
+}
+
+/// Forwards WireGuard traffic from a local UDP socket to a peer, through a QUIC proxy.
+pub struct QuicProxy {
+    local_addr: SocketAddr,
+    proxy_endpoint: SocketAddr,
+    max_tunnel_mtu: u16,
+    abort_handle: AbortHandle,
+}
+
+impl QuicProxy {
+    /// Connects to the proxy at `proxy_endpoint` and asks it to forward traffic to
+    /// `peer_endpoint`.
+    pub fn new(
+        runtime: &tokio::runtime::Handle,
+        proxy_endpoint: SocketAddr,
+        server_name: &str,
+        peer_endpoint: SocketAddr,
+    ) -> Result<Self, Error> {
+        let (local_socket, connection, datagrams, endpoint) =
+            runtime.block_on(Self::connect(proxy_endpoint, server_name, peer_endpoint))?;
+        let local_addr = local_socket.local_addr().map_err(Error::BindSocket)?;
+        let max_datagram_size = connection
+            .max_datagram_size()
+            .ok_or(Error::DatagramsUnsupported)?;
+        let max_tunnel_mtu = cmp::min(
+            max_datagram_size.saturating_sub(WIREGUARD_OVERHEAD),
+            u16::MAX as usize,
+        ) as u16;
+
+        let (forward_future, abort_handle) = abortable(async move {
+            // The endpoint drives the connection, so it must be kept alive while forwarding
+            let _endpoint = endpoint;
+            Self::forward(local_socket, connection, datagrams).await
+        });
+        runtime.spawn(forward_future);
+
+        Ok(Self {
+            local_addr,
+            proxy_endpoint,
+            max_tunnel_mtu,
+            abort_handle,
+        })
+    }
+
+    async fn connect(
+        proxy_endpoint: SocketAddr,
+        server_name: &str,
+        peer_endpoint: SocketAddr,
+    ) -> Result<
+        (
+            UdpSocket,
+            quinn::Connection,
+            quinn::Datagrams,
+            quinn::Endpoint,
+        ),
+        Error,
+    > {
+        let listen_addr = if peer_endpoint.is_ipv4() {
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)
+        } else {
+            SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 0)
+        };
+        let local_socket = UdpSocket::bind(listen_addr)
+            .await
+            .map_err(Error::BindSocket)?;
+
+        let mut client_config = quinn::ClientConfigBuilder::default();
+        client_config.protocols(&[ALPN_PROTOCOL]);
+        let mut endpoint_builder = quinn::Endpoint::builder();
+        endpoint_builder.default_client_config(client_config.build());
+        let (endpoint, _incoming) = endpoint_builder
+            .with_socket(Self::bind_proxy_socket(proxy_endpoint).map_err(Error::BindSocket)?)
+            .map_err(Error::CreateEndpoint)?;
+
+        let quinn::NewConnection {
+            connection,
+            datagrams,
+            ..
+        } = endpoint
+            .connect(&proxy_endpoint, server_name)
+            .map_err(Error::Connect)?
+            .await
+            .map_err(Error::Connection)?;
+
+        let (mut request, _response) = connection.open_bi().await.map_err(Error::Connection)?;
+        request
+            .write_all(format!("{}\n", peer_endpoint).as_bytes())
+            .await
+            .map_err(Error::SendRequest)?;
+        request.finish().await.map_err(Error::SendRequest)?;
+
+        Ok((local_socket, connection, datagrams, endpoint))
+    }
+
+    /// Returns a socket for talking to the proxy, which is excluded from the tunnel.
+    fn bind_proxy_socket(proxy_endpoint: SocketAddr) -> io::Result<std::net::UdpSocket> {
+        let bind_addr = if proxy_endpoint.is_ipv4() {
+            SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0)
+        } else {
+            SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0)
+        };
+
+        #[cfg(target_os = "linux")]
+        {
+            use socket2::{Domain, Protocol, Socket, Type};
+
+            let socket = Socket::new(
+                Domain::for_address(bind_addr),
+                Type::DGRAM,
+                Some(Protocol::UDP),
+            )?;
+            socket.set_mark(crate::linux::TUNNEL_FW_MARK)?;
+            socket.bind(&bind_addr.into())?;
+            Ok(socket.into())
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        std::net::UdpSocket::bind(bind_addr)
+    }
+
+    async fn forward(
+        local_socket: UdpSocket,
+        connection: quinn::Connection,
+        mut datagrams: quinn::Datagrams,
+    ) {
+        let local_socket = Arc::new(local_socket);
+        let wireguard_addr = Arc::new(Mutex::new(None));
+
+        let upstream_socket = local_socket.clone();
+        let upstream_wireguard_addr = wireguard_addr.clone();
+        let upstream = async move {
+            let mut buffer = vec![0u8; u16::MAX as usize];
+            loop {
+                let (length, source) = match upstream_socket.recv_from(&mut buffer).await {
+                    Ok(result) => result,
+                    Err(error) => {
+                        log::error!("Failed to receive datagram from WireGuard: {}", error);
+                        return;
+                    }
+                };
+                *upstream_wireguard_addr.lock().unwrap() = Some(source);
+
+                match connection.send_datagram(Bytes::copy_from_slice(&buffer[..length])) {
+                    Ok(()) => (),
+                    Err(quinn::SendDatagramError::TooLarge) => {
+                        log::debug!("Dropping datagram of {} bytes that is too large", length);
+                    }
+                    Err(error) => {
+                        log::error!("Failed to send datagram to the QUIC proxy: {}", error);
+                        return;
+                    }
+                }
+            }
+        };
+
+        let downstream = async move {
+            while let Some(datagram) = datagrams.next().await {
+                let datagram = match datagram {
+                    Ok(datagram) => datagram,
+                    Err(error) => {
+                        log::error!("Connection to the QUIC proxy was lost: {}", error);
+                        return;
+                    }
+                };
+                let destination = *wireguard_addr.lock().unwrap();
+                if let Some(destination) = destination {
+                    if let Err(error) = local_socket.send_to(&datagram, destination).await {
+                        log::error!("Failed to send datagram to WireGuard: {}", error);
+                    }
+                }
+            }
+        };
+
+        future::select(Box::pin(upstream), Box::pin(downstream)).await;
+    }
+}
+
+impl Obfuscator for QuicProxy {
+    fn endpoint(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    fn remote_endpoint(&self) -> SocketAddr {
+        self.proxy_endpoint
+    }
+
+    /// Limits the MTU so that the tunnel packets fit into QUIC datagrams.
+    fn tunnel_mtu(&self, mtu: u16) -> u16 {
+        cmp::min(mtu, self.max_tunnel_mtu)
+    }
+}
+
+impl Drop for QuicProxy {
+    fn drop(&mut self) {
+        self.abort_handle.abort();
+    }
+}
@@ -0,0 +1,87 @@
+//! Relays WireGuard traffic over UDP through a Shadowsocks server, using the bundled client.
+
+use super::{Error, Obfuscator};
+use crate::proxy::{self, ProxyMonitor, ProxyMonitorCloseHandle, ProxyResourceData};
+use std::net::SocketAddr;
+use talpid_types::{net::openvpn::ShadowsocksProxySettings, ErrorExt};
+
+/// Bytes added by Shadowsocks to every datagram: the salt, the AEAD tag and the largest target
+/// address header.
+const SHADOWSOCKS_UDP_OVERHEAD: u16 = 32 + 16 + 19;
+
+/// Forwards the datagrams sent to a local UDP socket to the peer, through a Shadowsocks server.
+pub struct ShadowsocksProxy {
+    local_addr: SocketAddr,
+    server_endpoint: SocketAddr,
+    monitor: Option<Box<dyn ProxyMonitor>>,
+    close_handle: Option<Box<dyn ProxyMonitorCloseHandle>>,
+}
+
+impl ShadowsocksProxy {
+    /// Starts the bundled Shadowsocks client, which relays traffic to `peer_endpoint` through
+    /// the server in `settings`.
+    pub fn new(
+        settings: &ShadowsocksProxySettings,
+        peer_endpoint: SocketAddr,
+        resource_data: &ProxyResourceData,
+    ) -> Result<Self, Error> {
+        let mut monitor =
+            proxy::start_shadowsocks_udp_tunnel(settings, peer_endpoint, resource_data)
+                .map_err(Error::ShadowsocksError)?;
+        let close_handle = monitor.close_handle();
+        let local_addr = SocketAddr::new(std::net::Ipv4Addr::LOCALHOST.into(), monitor.port());
+
+        Ok(Self {
+            local_addr,
+            server_endpoint: settings.peer,
+            monitor: Some(monitor),
+            close_handle: Some(close_handle),
+        })
+    }
+}
+
+impl Obfuscator for ShadowsocksProxy {
+    fn endpoint(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    fn remote_endpoint(&self) -> SocketAddr {
+        self.server_endpoint
+    }
+
+    fn tunnel_mtu(&self, mtu: u16) -> u16 {
+        mtu.saturating_sub(SHADOWSOCKS_UDP_OVERHEAD)
+    }
+
+    fn monitor_exit(&mut self, on_exit: Box<dyn FnOnce(Error) + Send>) {
+        let monitor = match self.monitor.take() {
+            Some(monitor) => monitor,
+            None => return,
+        };
+        std::thread::spawn(move || match monitor.wait() {
+            Ok(proxy::WaitResult::ProperShutdown) => (),
+            Ok(proxy::WaitResult::UnexpectedExit(details)) => {
+                on_exit(Error::ShadowsocksExited(details));
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to wait for Shadowsocks client")
+                );
+            }
+        });
+    }
+}
+
+impl Drop for ShadowsocksProxy {
+    fn drop(&mut self) {
+        if let Some(close_handle) = self.close_handle.take() {
+            if let Err(error) = close_handle.close() {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to stop Shadowsocks client")
+                );
+            }
+        }
+    }
+}
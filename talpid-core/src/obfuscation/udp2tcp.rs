@@ -0,0 +1,64 @@
+//! Sends WireGuard traffic over TCP, for networks that block UDP.
+
+use super::{Error, Obfuscator};
+use futures::future::{abortable, AbortHandle};
+use std::net::SocketAddr;
+use udp_over_tcp::TcpOptions;
+
+/// Forwards the datagrams sent to a local UDP socket to a TCP endpoint on the peer.
+pub struct Udp2Tcp {
+    local_addr: SocketAddr,
+    peer_endpoint: SocketAddr,
+    abort_handle: AbortHandle,
+}
+
+impl Udp2Tcp {
+    /// Connects to the TCP endpoint of the peer at `peer_endpoint`.
+    pub fn new(runtime: &tokio::runtime::Handle, peer_endpoint: SocketAddr) -> Result<Self, Error> {
+        let listen_addr = if peer_endpoint.is_ipv4() {
+            SocketAddr::new("127.0.0.1".parse().unwrap(), 0)
+        } else {
+            SocketAddr::new("::1".parse().unwrap(), 0)
+        };
+
+        let udp2tcp = runtime
+            .block_on(udp_over_tcp::Udp2Tcp::new(
+                listen_addr,
+                peer_endpoint,
+                TcpOptions {
+                    #[cfg(target_os = "linux")]
+                    fwmark: Some(crate::linux::TUNNEL_FW_MARK),
+                    ..TcpOptions::default()
+                },
+            ))
+            .map_err(Error::Udp2TcpError)?;
+        let local_addr = udp2tcp
+            .local_udp_addr()
+            .map_err(Error::GetLocalUdpAddress)?;
+
+        let (udp2tcp_future, abort_handle) = abortable(udp2tcp.run());
+        runtime.spawn(udp2tcp_future);
+
+        Ok(Self {
+            local_addr,
+            peer_endpoint,
+            abort_handle,
+        })
+    }
+}
+
+impl Obfuscator for Udp2Tcp {
+    fn endpoint(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    fn remote_endpoint(&self) -> SocketAddr {
+        self.peer_endpoint
+    }
+}
+
+impl Drop for Udp2Tcp {
+    fn drop(&mut self) {
+        self.abort_handle.abort();
+    }
+}
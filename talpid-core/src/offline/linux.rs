@@ -1,10 +1,28 @@
-use crate::routing::{self, RouteManagerHandle};
-use futures::{channel::mpsc::UnboundedSender, StreamExt};
+use crate::{
+    interface_watcher::{self, InterfaceWatcher},
+    routing::{self, CallbackMessage, RouteManagerHandle},
+};
+use futures::{
+    channel::mpsc::UnboundedSender,
+    future,
+    stream::{self, BoxStream},
+    FutureExt, StreamExt, TryStreamExt,
+};
+use netlink_packet_route::{
+    constants::{ARPHRD_LOOPBACK, ARPHRD_NONE, IFF_LOWER_UP, IFF_UP},
+    link::{
+        nlas::{Nla as LinkNla, State as LinkState},
+        LinkMessage,
+    },
+};
 use std::{
-    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream},
+    str::FromStr,
     sync::Arc,
+    time::Duration,
 };
-use talpid_types::ErrorExt;
+use talpid_types::{cancel::CancelToken, net::Connectivity, ErrorExt};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -13,69 +31,172 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub enum Error {
     #[error(display = "The route manager returned an error")]
     RouteManagerError(#[error(source)] routing::Error),
+
+    #[error(display = "Failed to open a netlink connection")]
+    NetlinkConnectError(#[error(source)] io::Error),
+
+    #[error(display = "Failed to watch network interfaces")]
+    InterfaceWatcherError(#[error(source)] interface_watcher::Error),
+
+    #[error(display = "Failed to list network interfaces")]
+    ListLinksError(#[error(source)] rtnetlink::Error),
+}
+
+/// Selects how the offline monitor decides whether the host is offline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OfflineStrategy {
+    /// The host is offline if there is no route to a public address. This is the default.
+    RouteProbe,
+    /// The host is offline if no physical network interface is up and has a carrier. Useful when
+    /// the routing setup makes the route probe unreliable.
+    LinkState,
+}
+
+impl Default for OfflineStrategy {
+    fn default() -> Self {
+        OfflineStrategy::RouteProbe
+    }
+}
+
+impl fmt::Display for OfflineStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OfflineStrategy::RouteProbe => f.write_str("route"),
+            OfflineStrategy::LinkState => f.write_str("link"),
+        }
+    }
+}
+
+impl FromStr for OfflineStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "route" => Ok(OfflineStrategy::RouteProbe),
+            "link" => Ok(OfflineStrategy::LinkState),
+            other => Err(format!("Unknown offline detection strategy \"{}\"", other)),
+        }
+    }
+}
+
+/// Configuration of the offline monitor.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OfflineMonitorConfig {
+    /// The strategy that decides whether the host is offline.
+    pub strategy: OfflineStrategy,
+    /// Endpoints to connect to over TCP when `strategy` considers the host offline. If any of
+    /// them can be reached, the host is considered online anyway. They must be allowed by the
+    /// firewall in the blocking states, or they will never be reachable while offline.
+    pub reachability_endpoints: Vec<SocketAddr>,
 }
 
 pub struct MonitorHandle {
-    route_manager: RouteManagerHandle,
-    _notify_tx: Arc<UnboundedSender<bool>>,
+    strategy: Arc<dyn Strategy>,
+    _notify_tx: Arc<UnboundedSender<Connectivity>>,
 }
 
 const PUBLIC_INTERNET_ADDRESS_V4: IpAddr = IpAddr::V4(Ipv4Addr::new(193, 138, 218, 78));
 const PUBLIC_INTERNET_ADDRESS_V6: IpAddr =
     IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0x1, 0x2, 0x3, 0x4, 0x5, 0x6));
 
+/// The offline state is queried this often even if no route events suggest that it has changed,
+/// since it also depends on things that do not cause route events, such as routing rules.
+const FULL_QUERY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// How long to wait for each endpoint of the reachability check.
+const REACHABILITY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Connectivity that is presumed when it cannot be inferred.
+const PRESUMED_CONNECTIVITY: Connectivity = Connectivity {
+    ipv4: true,
+    ipv6: false,
+};
+
 impl MonitorHandle {
-    pub async fn is_offline(&mut self) -> bool {
-        match public_ip_unreachable(&self.route_manager).await {
-            Ok(is_offline) => is_offline,
+    pub async fn connectivity(&mut self) -> Connectivity {
+        match self.strategy.connectivity().await {
+            Ok(connectivity) => connectivity,
             Err(err) => {
                 log::error!(
                     "Failed to verify offline state: {}. Presuming connectivity",
                     err
                 );
-                false
+                PRESUMED_CONNECTIVITY
             }
         }
     }
 }
 
+/// A way of inferring over which IP versions the host is online.
+#[async_trait::async_trait]
+trait Strategy: Send + Sync {
+    /// Returns over which IP versions the host is online.
+    async fn connectivity(&self) -> Result<Connectivity>;
+
+    /// Returns a stream that yields whenever the result of `connectivity` may have changed.
+    async fn changes(&self) -> Result<BoxStream<'static, ()>>;
+}
+
 pub async fn spawn_monitor(
-    notify_tx: UnboundedSender<bool>,
+    notify_tx: UnboundedSender<Connectivity>,
+    cancel_token: CancelToken,
     route_manager: RouteManagerHandle,
+    config: OfflineMonitorConfig,
 ) -> Result<MonitorHandle> {
-    let mut is_offline = public_ip_unreachable(&route_manager).await?;
+    log::debug!(
+        "Inferring offline state using the {} strategy{}",
+        config.strategy,
+        if config.reachability_endpoints.is_empty() {
+            ""
+        } else {
+            " and a reachability check"
+        }
+    );
+    let strategy = create_strategy(route_manager, config);
 
-    let mut listener = route_manager
-        .change_listener()
-        .await
-        .map_err(Error::RouteManagerError)?;
+    let mut connectivity = strategy.connectivity().await?;
+    let changes = strategy.changes().await?;
 
     let notify_tx = Arc::new(notify_tx);
     let sender = Arc::downgrade(&notify_tx);
     let monitor_handle = MonitorHandle {
-        route_manager: route_manager.clone(),
+        strategy: strategy.clone(),
         _notify_tx: notify_tx,
     };
 
     tokio::spawn(async move {
-        while let Some(_event) = listener.next().await {
-            match sender.upgrade() {
-                Some(sender) => {
-                    let new_offline_state = public_ip_unreachable(&route_manager)
-                        .await
-                        .unwrap_or_else(|err| {
-                            log::error!(
-                                "{}",
-                                err.display_chain_with_msg("Failed to infer offline state")
-                            );
-                            false
-                        });
-                    if new_offline_state != is_offline {
-                        is_offline = new_offline_state;
-                        let _ = sender.unbounded_send(is_offline);
-                    }
-                }
+        let mut changes = changes.fuse();
+        let mut next_full_query = tokio::time::Instant::now() + FULL_QUERY_INTERVAL;
+        loop {
+            futures::select! {
+                change = changes.next() => match change {
+                    // Changes often come in bursts, which only require a single query
+                    Some(()) => while let Some(Some(())) = changes.next().now_or_never() {},
+                    None => return,
+                },
+                _ = tokio::time::sleep_until(next_full_query).fuse() => (),
+                _ = cancel_token.cancelled().fuse() => return,
+            }
+            next_full_query = tokio::time::Instant::now() + FULL_QUERY_INTERVAL;
+
+            let sender = match sender.upgrade() {
+                Some(sender) => sender,
                 None => return,
+            };
+            // The reachability check may take a while, which should not delay a shutdown
+            let new_connectivity = match cancel_token.run(strategy.connectivity()).await {
+                Ok(result) => result.unwrap_or_else(|err| {
+                    log::error!(
+                        "{}",
+                        err.display_chain_with_msg("Failed to infer offline state")
+                    );
+                    PRESUMED_CONNECTIVITY
+                }),
+                Err(_) => return,
+            };
+            if new_connectivity != connectivity {
+                connectivity = new_connectivity;
+                let _ = sender.unbounded_send(connectivity);
             }
         }
     });
@@ -83,16 +204,236 @@ pub async fn spawn_monitor(
     Ok(monitor_handle)
 }
 
+fn create_strategy(
+    route_manager: RouteManagerHandle,
+    config: OfflineMonitorConfig,
+) -> Arc<dyn Strategy> {
+    let strategy: Box<dyn Strategy> = match config.strategy {
+        OfflineStrategy::RouteProbe => Box::new(RouteProbe { route_manager }),
+        OfflineStrategy::LinkState => Box::new(LinkStateWatcher),
+    };
+    if config.reachability_endpoints.is_empty() {
+        Arc::from(strategy)
+    } else {
+        Arc::new(ReachabilityCheck {
+            inner: strategy,
+            endpoints: config.reachability_endpoints,
+        })
+    }
+}
 
-async fn public_ip_unreachable(handle: &RouteManagerHandle) -> Result<bool> {
-    Ok(handle
+/// Considers the host online over an IP version if there is a route to a public address of
+/// that version.
+struct RouteProbe {
+    route_manager: RouteManagerHandle,
+}
+
+#[async_trait::async_trait]
+impl Strategy for RouteProbe {
+    async fn connectivity(&self) -> Result<Connectivity> {
+        public_ip_connectivity(&self.route_manager).await
+    }
+
+    async fn changes(&self) -> Result<BoxStream<'static, ()>> {
+        let listener = self
+            .route_manager
+            .change_listener()
+            .await
+            .map_err(Error::RouteManagerError)?;
+        Ok(listener
+            .filter(|event| future::ready(affects_offline_state(event)))
+            .map(|_| ())
+            .boxed())
+    }
+}
+
+/// Considers the host offline if no physical network interface is up and has a carrier. Link
+/// state says nothing about IP versions, so only IPv4 is considered to be available.
+struct LinkStateWatcher;
+
+#[async_trait::async_trait]
+impl Strategy for LinkStateWatcher {
+    async fn connectivity(&self) -> Result<Connectivity> {
+        let (connection, handle, _) =
+            rtnetlink::new_connection().map_err(Error::NetlinkConnectError)?;
+        tokio::spawn(connection);
+
+        let links: Vec<LinkMessage> = handle
+            .link()
+            .get()
+            .execute()
+            .try_collect()
+            .await
+            .map_err(Error::ListLinksError)?;
+        Ok(Connectivity::from_offline_state(
+            !links.iter().any(is_physical_link_up),
+        ))
+    }
+
+    async fn changes(&self) -> Result<BoxStream<'static, ()>> {
+        let events = InterfaceWatcher::new()
+            .await
+            .map_err(Error::InterfaceWatcherError)?;
+        Ok(events
+            .filter(|event| future::ready(event.is_link_change()))
+            .map(|_| ())
+            .boxed())
+    }
+}
+
+/// Returns whether the link is up, has a carrier and is not a loopback or tunnel device. Tunnel
+/// devices, such as the ones created for WireGuard and OpenVPN, have no link layer.
+fn is_physical_link_up(link: &LinkMessage) -> bool {
+    let header = &link.header;
+    if header.link_layer_type == ARPHRD_LOOPBACK || header.link_layer_type == ARPHRD_NONE {
+        return false;
+    }
+    if header.flags & IFF_UP == 0 || header.flags & IFF_LOWER_UP == 0 {
+        return false;
+    }
+    // Drivers that do not report an operational state leave it as unknown
+    link.nlas.iter().all(|nla| match nla {
+        LinkNla::OperState(state) => *state == LinkState::Up || *state == LinkState::Unknown,
+        _ => true,
+    })
+}
+
+/// Overrides the inner strategy when it considers an IP version unavailable, but one of the
+/// endpoints of that version can be reached.
+struct ReachabilityCheck {
+    inner: Box<dyn Strategy>,
+    endpoints: Vec<SocketAddr>,
+}
+
+#[async_trait::async_trait]
+impl Strategy for ReachabilityCheck {
+    async fn connectivity(&self) -> Result<Connectivity> {
+        let mut connectivity = self.inner.connectivity().await?;
+        for endpoint in &self.endpoints {
+            let available = match endpoint {
+                SocketAddr::V4(_) => &mut connectivity.ipv4,
+                SocketAddr::V6(_) => &mut connectivity.ipv6,
+            };
+            if !*available && is_reachable(*endpoint).await {
+                log::debug!(
+                    "Presuming connectivity since {} is reachable despite the offline state",
+                    endpoint
+                );
+                *available = true;
+            }
+        }
+        Ok(connectivity)
+    }
+
+    async fn changes(&self) -> Result<BoxStream<'static, ()>> {
+        self.inner.changes().await
+    }
+}
+
+async fn is_reachable(endpoint: SocketAddr) -> bool {
+    tokio::task::spawn_blocking(move || {
+        TcpStream::connect_timeout(&endpoint, REACHABILITY_TIMEOUT).is_ok()
+    })
+    .await
+    .unwrap_or(false)
+}
+
+/// Returns whether a route change may change whether the public addresses are reachable. Only
+/// routes that contain one of the addresses can affect the route to it.
+fn affects_offline_state(event: &CallbackMessage) -> bool {
+    let route = match event {
+        CallbackMessage::NewRoute(route) | CallbackMessage::DelRoute(route) => route,
+    };
+    let prefix = route.get_prefix();
+    prefix.contains(PUBLIC_INTERNET_ADDRESS_V4) || prefix.contains(PUBLIC_INTERNET_ADDRESS_V6)
+}
+
+async fn public_ip_connectivity(handle: &RouteManagerHandle) -> Result<Connectivity> {
+    let ipv4 = handle
         .get_destination_route(PUBLIC_INTERNET_ADDRESS_V4, true)
         .await
         .map_err(Error::RouteManagerError)?
-        .is_none()
-        && handle
-            .get_destination_route(PUBLIC_INTERNET_ADDRESS_V6, true)
-            .await
-            .unwrap_or(None)
-            .is_none())
+        .is_some();
+    // Hosts without IPv6 support fail the lookup rather than return no route
+    let ipv6 = handle
+        .get_destination_route(PUBLIC_INTERNET_ADDRESS_V6, true)
+        .await
+        .unwrap_or(None)
+        .is_some();
+    Ok(Connectivity { ipv4, ipv6 })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::routing::{Node, Route};
+
+    #[test]
+    fn ignores_routes_that_do_not_contain_public_addresses() {
+        let gateway = Node::address(Ipv4Addr::new(10, 0, 0, 1).into());
+        let events: Vec<_> = (0..=255u8)
+            .map(|i| {
+                let prefix = format!("10.{}.0.0/16", i).parse().unwrap();
+                CallbackMessage::NewRoute(Route::new(gateway.clone(), prefix))
+            })
+            .collect();
+        assert_eq!(
+            events
+                .iter()
+                .filter(|event| affects_offline_state(event))
+                .count(),
+            0
+        );
+
+        let default_route = Route::new(gateway, "0.0.0.0/0".parse().unwrap());
+        assert!(affects_offline_state(&CallbackMessage::DelRoute(
+            default_route
+        )));
+    }
+
+    #[test]
+    fn only_physical_links_with_carrier_are_up() {
+        let link = |link_layer_type, flags, oper_state| {
+            let mut link = LinkMessage::default();
+            link.header.link_layer_type = link_layer_type;
+            link.header.flags = flags;
+            link.nlas.push(LinkNla::OperState(oper_state));
+            link
+        };
+        const ARPHRD_ETHER: u16 = 1;
+
+        assert!(is_physical_link_up(&link(
+            ARPHRD_ETHER,
+            IFF_UP | IFF_LOWER_UP,
+            LinkState::Up
+        )));
+        assert!(!is_physical_link_up(&link(
+            ARPHRD_ETHER,
+            IFF_UP,
+            LinkState::LowerLayerDown
+        )));
+        assert!(!is_physical_link_up(&link(
+            ARPHRD_NONE,
+            IFF_UP | IFF_LOWER_UP,
+            LinkState::Unknown
+        )));
+        assert!(!is_physical_link_up(&link(
+            ARPHRD_LOOPBACK,
+            IFF_UP | IFF_LOWER_UP,
+            LinkState::Unknown
+        )));
+    }
+
+    #[test]
+    fn parses_strategies() {
+        assert_eq!(
+            "route".parse::<OfflineStrategy>(),
+            Ok(OfflineStrategy::RouteProbe)
+        );
+        assert_eq!(
+            "link".parse::<OfflineStrategy>(),
+            Ok(OfflineStrategy::LinkState)
+        );
+        assert!("ping".parse::<OfflineStrategy>().is_err());
+    }
 }
@@ -1,10 +1,8 @@
 use futures::channel::mpsc::UnboundedSender;
+use parking_lot::Mutex;
 use std::{
-    net::{Ipv4Addr, SocketAddr},
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        mpsc, Arc, Weak,
-    },
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{mpsc, Arc, Weak},
     thread,
 };
 use system_configuration::{
@@ -22,9 +20,11 @@ use system_configuration::{
         ReachabilityFlags, SCNetworkReachability, SchedulingError, SetCallbackError,
     },
 };
+use talpid_types::net::Connectivity;
 
 
-const PRIMARY_INTERFACE_KEY: &str = "State:/Network/Global/IPv4";
+const IPV4_PRIMARY_INTERFACE_KEY: &str = "State:/Network/Global/IPv4";
+const IPV6_PRIMARY_INTERFACE_KEY: &str = "State:/Network/Global/IPv6";
 
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -39,41 +39,41 @@ pub enum Error {
 }
 
 pub struct MonitorHandle {
-    _notify_tx: Arc<UnboundedSender<bool>>,
+    _notify_tx: Arc<UnboundedSender<Connectivity>>,
+    _interface_tx: Arc<UnboundedSender<String>>,
 }
 
 impl MonitorHandle {
-    /// Host is considered to be offline if the IPv4 internet is considered to be unreachable by the
-    /// given reachability flags *or* there are no active physical interfaces.
-    pub async fn is_offline(&self) -> bool {
-        let reachability = SCNetworkReachability::from(ipv4_internet());
+    pub async fn connectivity(&self) -> Connectivity {
         let store = SCDynamicStoreBuilder::new("talpid-offline-check").build();
-        reachability
-            .reachability()
-            .map(|flags| check_offline_state(&store, flags))
-            .unwrap_or(false)
+        current_connectivity(&store)
     }
 }
 
-pub async fn spawn_monitor(notify_tx: UnboundedSender<bool>) -> Result<MonitorHandle, Error> {
+/// Starts monitoring the connectivity of the host. Changes in connectivity are sent on
+/// `notify_tx`, and the name of the physical interface that the default route goes through is sent
+/// on `interface_tx` whenever the default route moves from one interface to another.
+pub async fn spawn_monitor(
+    notify_tx: UnboundedSender<Connectivity>,
+    interface_tx: UnboundedSender<String>,
+) -> Result<MonitorHandle, Error> {
     let (result_tx, result_rx) = mpsc::channel();
     let notify_tx = Arc::new(notify_tx);
     let sender = Arc::downgrade(&notify_tx);
+    let interface_tx = Arc::new(interface_tx);
+    let interface_sender = Arc::downgrade(&interface_tx);
     thread::spawn(move || {
-        let mut reachability_ref = SCNetworkReachability::from(ipv4_internet());
+        let mut reachability_refs = [
+            SCNetworkReachability::from(ipv4_internet()),
+            SCNetworkReachability::from(ipv6_internet()),
+        ];
         let store = SCDynamicStoreBuilder::new("talpid-offline-watcher").build();
 
-        let is_currently_offline = match reachability_ref.reachability() {
-            Ok(flags) => check_offline_state(&store, flags),
-            Err(_) => {
-                log::error!("Failed to obtain current connectivity, assuming machine is online");
-                false
-            }
-        };
-
         let context = OfflineStateContext {
             sender,
-            is_offline: Arc::new(AtomicBool::new(is_currently_offline)),
+            connectivity: Arc::new(Mutex::new(current_connectivity(&store))),
+            interface_sender,
+            primary_interface: Arc::new(Mutex::new(primary_interface(&store))),
         };
 
 
@@ -83,14 +83,17 @@ pub async fn spawn_monitor(notify_tx: UnboundedSender<bool>) -> Result<MonitorHa
                 kCFRunLoopCommonModes
             });
 
-            reachability_ref.set_callback(move |flags| {
-                let store = SCDynamicStoreBuilder::new("talpid-offline-watcher").build();
-                context.new_state(check_offline_state(&store, flags));
-            })?;
+            for reachability_ref in &mut reachability_refs {
+                let context = context.clone();
+                reachability_ref.set_callback(move |_flags| {
+                    let store = SCDynamicStoreBuilder::new("talpid-offline-watcher").build();
+                    context.new_state(current_connectivity(&store));
+                })?;
 
-            reachability_ref.schedule_with_runloop(&CFRunLoop::get_current(), unsafe {
-                kCFRunLoopCommonModes
-            })?;
+                reachability_ref.schedule_with_runloop(&CFRunLoop::get_current(), unsafe {
+                    kCFRunLoopCommonModes
+                })?;
+            }
 
 
             Ok(dynamic_store)
@@ -111,6 +114,7 @@ pub async fn spawn_monitor(notify_tx: UnboundedSender<bool>) -> Result<MonitorHa
     let _ = result_rx.recv().map_err(|_| Error::InitializationError)??;
     Ok(MonitorHandle {
         _notify_tx: notify_tx,
+        _interface_tx: interface_tx,
     })
 }
 
@@ -118,10 +122,54 @@ fn ipv4_internet() -> SocketAddr {
     SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0)
 }
 
-fn check_offline_state(store: &SCDynamicStore, flags: ReachabilityFlags) -> bool {
-    let is_offline =
-        !flags.contains(ReachabilityFlags::REACHABLE) || !exists_active_physical_iface(store);
-    is_offline
+fn ipv6_internet() -> SocketAddr {
+    SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0)
+}
+
+/// An IP version is considered to be available if there is a primary interface for it, the
+/// internet is considered to be reachable over it, and there is an active physical interface.
+/// IPv4 and IPv6 are checked independently, so that hosts without IPv4 are not considered to be
+/// offline.
+fn current_connectivity(store: &SCDynamicStore) -> Connectivity {
+    if !exists_active_physical_iface(store) {
+        return Connectivity::from_offline_state(true);
+    }
+    Connectivity {
+        ipv4: is_reachable(store, ipv4_internet(), IPV4_PRIMARY_INTERFACE_KEY),
+        ipv6: is_reachable(store, ipv6_internet(), IPV6_PRIMARY_INTERFACE_KEY),
+    }
+}
+
+/// Returns the name of the interface that the default route goes through. The IPv4 default route
+/// is preferred, since it is the one that most traffic takes.
+fn primary_interface(store: &SCDynamicStore) -> Option<String> {
+    let interface = |key: &str| -> Option<String> {
+        let properties = store.get(CFString::new(key))?.downcast::<CFDictionary>()?;
+        let interface_ptr = properties.find(CFString::from("PrimaryInterface").to_void())?;
+        if interface_ptr.is_null() {
+            return None;
+        }
+        unsafe { CFType::wrap_under_get_rule(*interface_ptr) }
+            .downcast::<CFString>()
+            .map(|interface| interface.to_string())
+    };
+    interface(IPV4_PRIMARY_INTERFACE_KEY).or_else(|| interface(IPV6_PRIMARY_INTERFACE_KEY))
+}
+
+fn is_reachable(store: &SCDynamicStore, address: SocketAddr, primary_interface_key: &str) -> bool {
+    if store.get(CFString::new(primary_interface_key)).is_none() {
+        return false;
+    }
+    match SCNetworkReachability::from(address).reachability() {
+        Ok(flags) => flags.contains(ReachabilityFlags::REACHABLE),
+        Err(_) => {
+            log::error!(
+                "Failed to obtain reachability of {}, assuming it is reachable",
+                address
+            );
+            true
+        }
+    }
 }
 
 fn exists_active_physical_iface(store: &SCDynamicStore) -> bool {
@@ -173,19 +221,38 @@ fn iface_is_physical(iface: &SCNetworkInterface) -> bool {
 
 #[derive(Clone)]
 struct OfflineStateContext {
-    sender: Weak<UnboundedSender<bool>>,
-    is_offline: Arc<AtomicBool>,
+    sender: Weak<UnboundedSender<Connectivity>>,
+    connectivity: Arc<Mutex<Connectivity>>,
+    interface_sender: Weak<UnboundedSender<String>>,
+    /// The interface that the default route went through the last time it was looked up.
+    primary_interface: Arc<Mutex<Option<String>>>,
 }
 
 impl OfflineStateContext {
-    fn no_primary_interface(&self) {
-        self.new_state(true);
+    fn new_state(&self, connectivity: Connectivity) {
+        let old_connectivity = std::mem::replace(&mut *self.connectivity.lock(), connectivity);
+        if old_connectivity != connectivity {
+            if let Some(sender) = self.sender.upgrade() {
+                let _ = sender.unbounded_send(connectivity);
+            }
+        }
     }
 
-    fn new_state(&self, is_offline: bool) {
-        if self.is_offline.swap(is_offline, Ordering::SeqCst) != is_offline {
-            if let Some(sender) = self.sender.upgrade() {
-                let _ = sender.unbounded_send(is_offline);
+    /// Notifies the listener if the default route moved from one interface to another. Losing or
+    /// gaining the default route altogether is a change in connectivity instead.
+    fn new_primary_interface(&self, interface: Option<String>) {
+        let old_interface =
+            std::mem::replace(&mut *self.primary_interface.lock(), interface.clone());
+        if let (Some(old_interface), Some(interface)) = (old_interface, interface) {
+            if old_interface != interface {
+                log::debug!(
+                    "Default route moved from {} to {}",
+                    old_interface,
+                    interface
+                );
+                if let Some(sender) = self.interface_sender.upgrade() {
+                    let _ = sender.unbounded_send(interface);
+                }
             }
         }
     }
@@ -201,7 +268,10 @@ fn create_dynamic_store(context: OfflineStateContext) -> Result<SCDynamicStore,
         .callback_context(callback_context)
         .build();
 
-    let watch_keys = CFArray::from_CFTypes(&[CFString::new(PRIMARY_INTERFACE_KEY)]);
+    let watch_keys = CFArray::from_CFTypes(&[
+        CFString::new(IPV4_PRIMARY_INTERFACE_KEY),
+        CFString::new(IPV6_PRIMARY_INTERFACE_KEY),
+    ]);
     let watch_patterns: CFArray<CFString> = CFArray::from_CFTypes(&[]);
 
     if store.set_notification_keys(&watch_keys, &watch_patterns) {
@@ -217,9 +287,8 @@ fn primary_interface_change_callback(
     _changed_keys: CFArray<CFString>,
     state: &mut OfflineStateContext,
 ) {
-    let is_offline = store.get(CFString::new(PRIMARY_INTERFACE_KEY)).is_none();
-    if is_offline {
-        log::debug!("No primary interface, considering host to be offline");
-        state.no_primary_interface();
-    }
+    let connectivity = current_connectivity(&store);
+    log::debug!("Primary interface changed, connectivity: {}", connectivity);
+    state.new_state(connectivity);
+    state.new_primary_interface(primary_interface(&store));
 }
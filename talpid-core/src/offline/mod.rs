@@ -3,6 +3,7 @@ use crate::routing::RouteManagerHandle;
 use futures::channel::mpsc::UnboundedSender;
 #[cfg(target_os = "android")]
 use talpid_types::android::AndroidContext;
+use talpid_types::{cancel::CancelToken, net::Connectivity};
 
 #[cfg(target_os = "macos")]
 #[path = "macos.rs"]
@@ -28,29 +29,56 @@ lazy_static::lazy_static! {
 }
 
 pub use self::imp::Error;
+#[cfg(target_os = "linux")]
+pub use self::imp::{OfflineMonitorConfig, OfflineStrategy};
 
-pub struct MonitorHandle(Option<imp::MonitorHandle>);
+pub struct MonitorHandle {
+    monitor: Option<imp::MonitorHandle>,
+    /// Cancelled when the handle is dropped, to stop any ongoing queries.
+    cancel_token: CancelToken,
+}
 
 impl MonitorHandle {
-    pub async fn is_offline(&mut self) -> bool {
-        match self.0.as_mut() {
-            Some(monitor) => monitor.is_offline().await,
-            None => false,
+    pub async fn connectivity(&mut self) -> Connectivity {
+        match self.monitor.as_mut() {
+            #[cfg(not(target_os = "android"))]
+            Some(monitor) => monitor.connectivity().await,
+            #[cfg(target_os = "android")]
+            Some(monitor) => Connectivity::from_offline_state(monitor.is_offline().await),
+            None => Connectivity {
+                ipv4: true,
+                ipv6: true,
+            },
         }
     }
 }
 
+impl Drop for MonitorHandle {
+    fn drop(&mut self) {
+        self.cancel_token.cancel();
+    }
+}
+
 pub async fn spawn_monitor(
-    sender: UnboundedSender<bool>,
+    sender: UnboundedSender<Connectivity>,
+    cancel_token: CancelToken,
+    #[cfg(target_os = "macos")] interface_sender: UnboundedSender<String>,
     #[cfg(target_os = "linux")] route_manager: RouteManagerHandle,
+    #[cfg(target_os = "linux")] config: OfflineMonitorConfig,
     #[cfg(target_os = "android")] android_context: AndroidContext,
 ) -> Result<MonitorHandle, Error> {
     let monitor = if !*FORCE_DISABLE_OFFLINE_MONITOR {
         Some(
             imp::spawn_monitor(
                 sender,
+                #[cfg(target_os = "macos")]
+                interface_sender,
+                #[cfg(target_os = "linux")]
+                cancel_token.clone(),
                 #[cfg(target_os = "linux")]
                 route_manager,
+                #[cfg(target_os = "linux")]
+                config,
                 #[cfg(target_os = "android")]
                 android_context,
             )
@@ -60,5 +88,8 @@ pub async fn spawn_monitor(
         None
     };
 
-    Ok(MonitorHandle(monitor))
+    Ok(MonitorHandle {
+        monitor,
+        cancel_token,
+    })
 }
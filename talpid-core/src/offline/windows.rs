@@ -2,6 +2,7 @@ use crate::{logging::windows::log_sink, winnet};
 use futures::channel::mpsc::UnboundedSender;
 use parking_lot::Mutex;
 use std::{
+    collections::HashSet,
     ffi::c_void,
     io,
     mem::zeroed,
@@ -11,6 +12,8 @@ use std::{
     thread,
     time::Duration,
 };
+use talpid_types::{net::Connectivity, ErrorExt};
+use talpid_windows_net::{IpNotifierHandle, NotificationType, RouteNotifierHandle};
 use winapi::{
     shared::{
         basetsd::LONG_PTR,
@@ -42,6 +45,10 @@ pub enum Error {
     ThreadCreationError(#[error(source)] io::Error),
     #[error(display = "Failed to start connectivity monitor")]
     ConnectivityMonitorError,
+    #[error(display = "Failed to register for route change notifications")]
+    RouteMonitorError(#[error(source)] io::Error),
+    #[error(display = "Failed to register for interface change notifications")]
+    InterfaceMonitorError(#[error(source)] io::Error),
 }
 
 
@@ -49,20 +56,66 @@ pub struct BroadcastListener {
     thread_handle: RawHandle,
     thread_id: DWORD,
     _system_state: Arc<Mutex<SystemState>>,
-    _notify_tx: Arc<UnboundedSender<bool>>,
+    _notify_tx: Arc<UnboundedSender<Connectivity>>,
+    _route_notifier: Box<RouteNotifierHandle<'static>>,
+    _interface_notifier: Box<IpNotifierHandle<'static>>,
 }
 
 unsafe impl Send for BroadcastListener {}
 
 impl BroadcastListener {
-    pub fn start(notify_tx: UnboundedSender<bool>) -> Result<Self, Error> {
+    pub fn start(notify_tx: UnboundedSender<Connectivity>) -> Result<Self, Error> {
         let notify_tx = Arc::new(notify_tx);
         let mut system_state = Arc::new(Mutex::new(SystemState {
             network_connectivity: None,
+            default_routes: default_route_interfaces(),
+            down_interfaces: HashSet::new(),
             suspended: false,
             notify_tx: Arc::downgrade(&notify_tx),
         }));
 
+        // Only re-read the routing table when a default route is added or removed
+        let route_state = Arc::downgrade(&system_state);
+        let route_notifier = talpid_windows_net::notify_route_change(
+            move |row, _notification_type| {
+                if row.DestinationPrefix.PrefixLength != 0 {
+                    return;
+                }
+                if let Some(state) = route_state.upgrade() {
+                    apply_system_state_change(
+                        state,
+                        StateChange::DefaultRoutes(default_route_interfaces()),
+                    );
+                }
+            },
+            None,
+        )
+        .map_err(Error::RouteMonitorError)?;
+
+        // Disabling an adapter, or turning off Wi-Fi, may take a long time to be reflected by
+        // the routing table and the connectivity monitor. React to the interface going down
+        // right away instead.
+        let interface_state = Arc::downgrade(&system_state);
+        let interface_notifier = talpid_windows_net::notify_ip_interface_change(
+            move |interface, notification_type| {
+                let state = match interface_state.upgrade() {
+                    Some(state) => state,
+                    None => return,
+                };
+                let luid = interface.luid().value();
+                let is_down =
+                    notification_type == NotificationType::Delete || !interface.is_connected();
+                let change = if is_down {
+                    StateChange::InterfaceDown(luid)
+                } else {
+                    StateChange::InterfaceUp(luid)
+                };
+                apply_system_state_change(state, change);
+            },
+            None,
+        )
+        .map_err(Error::InterfaceMonitorError)?;
+
         let power_broadcast_state_ref = system_state.clone();
 
         let power_broadcast_callback = move |message: UINT, wparam: WPARAM, _lparam: LPARAM| {
@@ -98,6 +151,8 @@ impl BroadcastListener {
             thread_id: unsafe { GetThreadId(real_handle) },
             _system_state: system_state,
             _notify_tx: notify_tx,
+            _route_notifier: route_notifier,
+            _interface_notifier: interface_notifier,
         })
     }
 
@@ -206,9 +261,9 @@ impl BroadcastListener {
         state.apply_change(StateChange::NetworkConnectivity(connectivity));
     }
 
-    pub async fn is_offline(&self) -> bool {
+    pub async fn connectivity(&self) -> Connectivity {
         let state = self._system_state.lock();
-        state.is_offline_currently().unwrap_or(false)
+        state.connectivity()
     }
 }
 
@@ -226,32 +281,86 @@ impl Drop for BroadcastListener {
 #[derive(Debug)]
 enum StateChange {
     NetworkConnectivity(bool),
+    DefaultRoutes(Option<DefaultRouteInterfaces>),
+    /// An interface was removed or disconnected.
+    InterfaceDown(u64),
+    /// An interface was added or changed while being connected.
+    InterfaceUp(u64),
     Suspended(bool),
 }
 
+/// LUIDs of the interfaces that the best default routes use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DefaultRouteInterfaces {
+    ipv4: Option<u64>,
+    ipv6: Option<u64>,
+}
+
+impl DefaultRouteInterfaces {
+    fn contains(&self, luid: u64) -> bool {
+        self.ipv4 == Some(luid) || self.ipv6 == Some(luid)
+    }
+
+    /// Returns the IP versions for which there is a default route over an interface that is not
+    /// known to be down.
+    fn connectivity(&self, down_interfaces: &HashSet<u64>) -> Connectivity {
+        let is_usable =
+            |luid: Option<u64>| luid.map_or(false, |luid| !down_interfaces.contains(&luid));
+        Connectivity {
+            ipv4: is_usable(self.ipv4),
+            ipv6: is_usable(self.ipv6),
+        }
+    }
+}
+
 struct SystemState {
     network_connectivity: Option<bool>,
+    /// Interfaces of the default routes, or `None` if they could not be determined.
+    default_routes: Option<DefaultRouteInterfaces>,
+    /// Interfaces that carried a default route but have gone down since. The routing table may
+    /// still contain their routes for a while.
+    down_interfaces: HashSet<u64>,
     suspended: bool,
-    notify_tx: Weak<UnboundedSender<bool>>,
+    notify_tx: Weak<UnboundedSender<Connectivity>>,
 }
 
 impl SystemState {
     fn apply_change(&mut self, change: StateChange) {
-        let old_state = self.is_offline_currently();
+        let old_connectivity = self.connectivity();
         match change {
             StateChange::NetworkConnectivity(connectivity) => {
                 self.network_connectivity = Some(connectivity);
             }
 
+            StateChange::DefaultRoutes(default_routes) => {
+                self.default_routes = default_routes;
+            }
+
+            StateChange::InterfaceDown(luid) => {
+                let is_default_route_interface = self
+                    .default_routes
+                    .map_or(false, |routes| routes.contains(luid));
+                if is_default_route_interface && self.down_interfaces.insert(luid) {
+                    log::debug!("Interface of the default route went down: {:#x}", luid);
+                }
+            }
+
+            StateChange::InterfaceUp(luid) => {
+                if self.down_interfaces.remove(&luid) {
+                    log::debug!("Interface came back up: {:#x}", luid);
+                    self.default_routes = default_route_interfaces();
+                }
+            }
+
             StateChange::Suspended(suspended) => {
                 self.suspended = suspended;
             }
         };
 
-        let new_state = self.is_offline_currently();
-        if old_state != new_state {
+        let new_connectivity = self.connectivity();
+        if old_connectivity != new_connectivity {
             if let Some(notify_tx) = self.notify_tx.upgrade() {
-                if let Err(e) = notify_tx.unbounded_send(new_state.unwrap_or(false)) {
+                if let Err(e) = notify_tx.unbounded_send(new_connectivity) {
                     log::error!("Failed to send new offline state to daemon: {}", e);
                 }
             }
@@ -261,11 +370,49 @@ impl SystemState {
     fn is_offline_currently(&self) -> Option<bool> {
         Some(!self.network_connectivity? || self.suspended)
     }
+
+    /// Returns the IP versions over which the internet can be reached. An IP version is
+    /// considered to be reachable if there is a default route for it.
+    fn connectivity(&self) -> Connectivity {
+        if self.is_offline_currently().unwrap_or(false) {
+            return Connectivity::from_offline_state(true);
+        }
+        let default_routes = self
+            .default_routes
+            .map(|routes| routes.connectivity(&self.down_interfaces));
+        match default_routes {
+            Some(default_routes) if !default_routes.is_offline() => default_routes,
+            // The connectivity monitor is slow to notice that the adapter went down
+            Some(_) if !self.down_interfaces.is_empty() => Connectivity::from_offline_state(true),
+            // Assume that IPv4 is available if the routes cannot be determined, or if the
+            // connectivity monitor considers the host to be online even though there are no
+            // default routes
+            _ => Connectivity::from_offline_state(false),
+        }
+    }
+}
+
+/// Reads the interfaces of the best default routes from the routing table.
+fn default_route_interfaces() -> Option<DefaultRouteInterfaces> {
+    let default_route_interface = |family| match winnet::get_best_default_route(family) {
+        Ok(route) => Some(route.map(|route| route.interface_luid)),
+        Err(error) => {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to obtain the default route")
+            );
+            None
+        }
+    };
+    Some(DefaultRouteInterfaces {
+        ipv4: default_route_interface(winnet::WinNetAddrFamily::IPV4)?,
+        ipv6: default_route_interface(winnet::WinNetAddrFamily::IPV6)?,
+    })
 }
 
 pub type MonitorHandle = BroadcastListener;
 
-pub async fn spawn_monitor(sender: UnboundedSender<bool>) -> Result<MonitorHandle, Error> {
+pub async fn spawn_monitor(sender: UnboundedSender<Connectivity>) -> Result<MonitorHandle, Error> {
     BroadcastListener::start(sender)
 }
 
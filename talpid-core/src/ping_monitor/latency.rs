@@ -0,0 +1,160 @@
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::{
+    io::{self, Read},
+    net::{Ipv4Addr, SocketAddrV4},
+    time::{Duration, Instant},
+};
+
+const ECHO_REQUEST: u8 = 8;
+const ECHO_REPLY: u8 = 0;
+const ICMP_HEADER_SIZE: usize = 8;
+const PAYLOAD_SIZE: usize = 32;
+const IPV4_MIN_HEADER_SIZE: usize = 20;
+
+/// Latency measurement errors
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Failed to open raw socket
+    #[error(display = "Failed to open ICMP socket")]
+    OpenError(#[error(source)] io::Error),
+
+    /// Failed to set socket options
+    #[error(display = "Failed to set socket options")]
+    SocketOptError(#[error(source)] io::Error),
+
+    /// Failed to write to raw socket
+    #[error(display = "Failed to send echo request")]
+    WriteError(#[error(source)] io::Error),
+
+    /// Failed to read from raw socket
+    #[error(display = "Failed to read ICMP socket")]
+    ReadError(#[error(source)] io::Error),
+
+    /// No echo reply was received before the timeout
+    #[error(display = "No echo reply was received within {} ms", _0)]
+    Timeout(u128),
+}
+
+/// Sends an ICMP echo request to `addr` and returns the time it took until the reply was received.
+/// The request is sent outside the tunnel on Linux. On other platforms, it is routed through the
+/// tunnel while the tunnel is up. The call blocks until a reply has been received or `timeout`
+/// has passed.
+pub fn measure(addr: Ipv4Addr, timeout: Duration) -> Result<Duration, Error> {
+    let mut socket =
+        Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4)).map_err(Error::OpenError)?;
+    #[cfg(target_os = "linux")]
+    socket
+        .set_mark(crate::linux::TUNNEL_FW_MARK)
+        .map_err(Error::SocketOptError)?;
+    // Raw sockets on Windows do not receive anything until they are bound
+    socket
+        .bind(&SockAddr::from(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)))
+        .map_err(Error::OpenError)?;
+
+    let id = rand::random();
+    let seq = rand::random();
+    let request = echo_request(id, seq);
+    let started = Instant::now();
+    socket
+        .send_to(&request, &SockAddr::from(SocketAddrV4::new(addr, 0)))
+        .map_err(Error::WriteError)?;
+
+    let mut buffer = [0u8; 1500];
+    loop {
+        let remaining = timeout
+            .checked_sub(started.elapsed())
+            .filter(|remaining| *remaining > Duration::from_millis(0))
+            .ok_or_else(|| Error::Timeout(timeout.as_millis()))?;
+        socket
+            .set_read_timeout(Some(remaining))
+            .map_err(Error::SocketOptError)?;
+        match socket.read(&mut buffer) {
+            Ok(len) => {
+                if is_echo_reply(&buffer[..len], addr, id, seq) {
+                    return Ok(started.elapsed());
+                }
+            }
+            Err(error)
+                if error.kind() == io::ErrorKind::WouldBlock
+                    || error.kind() == io::ErrorKind::TimedOut =>
+            {
+                return Err(Error::Timeout(timeout.as_millis()));
+            }
+            Err(error) => return Err(Error::ReadError(error)),
+        }
+    }
+}
+
+fn echo_request(id: u16, seq: u16) -> [u8; ICMP_HEADER_SIZE + PAYLOAD_SIZE] {
+    let mut packet = [0u8; ICMP_HEADER_SIZE + PAYLOAD_SIZE];
+    packet[0] = ECHO_REQUEST;
+    packet[4..6].copy_from_slice(&id.to_be_bytes());
+    packet[6..8].copy_from_slice(&seq.to_be_bytes());
+    let checksum = internet_checksum::checksum(&packet);
+    packet[2..4].copy_from_slice(&checksum);
+    packet
+}
+
+/// Returns whether `packet` is the reply from `addr` to the echo request with the given ID and
+/// sequence number. Raw sockets receive the IPv4 header along with the ICMP message.
+fn is_echo_reply(packet: &[u8], addr: Ipv4Addr, id: u16, seq: u16) -> bool {
+    if packet.len() < IPV4_MIN_HEADER_SIZE {
+        return false;
+    }
+    let source = Ipv4Addr::new(packet[12], packet[13], packet[14], packet[15]);
+    let header_size = usize::from(packet[0] & 0x0f) * 4;
+    let message = match packet.get(header_size..) {
+        Some(message) if message.len() >= ICMP_HEADER_SIZE => message,
+        _ => return false,
+    };
+    source == addr
+        && message[0] == ECHO_REPLY
+        && message[1] == 0
+        && message[4..6] == id.to_be_bytes()
+        && message[6..8] == seq.to_be_bytes()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn reply(source: Ipv4Addr, request: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0u8; IPV4_MIN_HEADER_SIZE];
+        packet[0] = 0x45;
+        packet[12..16].copy_from_slice(&source.octets());
+        packet.extend_from_slice(request);
+        packet[IPV4_MIN_HEADER_SIZE] = ECHO_REPLY;
+        packet
+    }
+
+    #[test]
+    fn test_echo_request() {
+        let request = echo_request(0x1dcd, 0x0001);
+        assert_eq!(
+            request[..8],
+            [0x08, 0x00, 0xda, 0x31, 0x1d, 0xcd, 0x00, 0x01]
+        );
+        assert_eq!(internet_checksum::checksum(&request), [0, 0]);
+    }
+
+    #[test]
+    fn test_is_echo_reply() {
+        let relay = Ipv4Addr::new(185, 213, 154, 68);
+        let request = echo_request(7, 9);
+
+        assert!(is_echo_reply(&reply(relay, &request), relay, 7, 9));
+        assert!(!is_echo_reply(&reply(relay, &request), relay, 7, 10));
+        assert!(!is_echo_reply(
+            &reply(Ipv4Addr::new(10, 0, 0, 1), &request),
+            relay,
+            7,
+            9
+        ));
+        // Requests that are looped back are not replies
+        let mut looped = reply(relay, &request);
+        looped[IPV4_MIN_HEADER_SIZE] = ECHO_REQUEST;
+        assert!(!is_echo_reply(&looped, relay, 7, 9));
+        assert!(!is_echo_reply(&looped[..24], relay, 7, 9));
+    }
+}
@@ -9,6 +9,10 @@ mod imp;
 
 pub use imp::Error;
 
+/// Round-trip time measurements with ICMP echo requests
+#[cfg(not(target_os = "android"))]
+pub mod latency;
+
 /// Trait for sending ICMP requests to get some traffic from a remote server
 pub trait Pinger: Send {
     /// Sends an ICMP packet
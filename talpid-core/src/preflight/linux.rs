@@ -0,0 +1,74 @@
+use super::{CapabilityKind, MissingCapability};
+use std::{fs, io, path::Path};
+
+const TUN_DEVICE: &str = "/dev/net/tun";
+/// The bit of `CAP_NET_ADMIN` in the capability sets. It is needed to configure interfaces,
+/// routes and netfilter.
+const CAP_NET_ADMIN: u32 = 12;
+
+pub fn check(_resource_dir: &Path) -> Vec<MissingCapability> {
+    let mut missing = vec![];
+
+    match fs::read_to_string("/proc/self/status") {
+        Ok(status) => {
+            if parse_effective_capabilities(&status)
+                .map(|capabilities| capabilities & (1 << CAP_NET_ADMIN) == 0)
+                .unwrap_or(false)
+            {
+                missing.push(MissingCapability {
+                    kind: CapabilityKind::Privilege,
+                    name: "CAP_NET_ADMIN".to_owned(),
+                    reason: "not in the effective capability set of the daemon".to_owned(),
+                });
+            }
+        }
+        Err(error) => log::warn!("Failed to read the capabilities of the daemon: {}", error),
+    }
+
+    if let Err(error) = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(TUN_DEVICE)
+    {
+        let reason = match error.kind() {
+            io::ErrorKind::NotFound => {
+                "does not exist. The tun kernel module may not be loaded".to_owned()
+            }
+            _ => format!("cannot be opened: {}", error),
+        };
+        missing.push(MissingCapability {
+            kind: CapabilityKind::Device,
+            name: TUN_DEVICE.to_owned(),
+            reason,
+        });
+    }
+
+    missing
+}
+
+/// Reads the effective capability set from the contents of `/proc/<pid>/status`.
+fn parse_effective_capabilities(status: &str) -> Option<u64> {
+    let capabilities = status
+        .lines()
+        .find_map(|line| line.strip_prefix("CapEff:"))?;
+    u64::from_str_radix(capabilities.trim(), 16).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_effective_capabilities() {
+        let status = "Name:\tmullvad-daemon\nCapInh:\t0000000000000000\n\
+                      CapPrm:\t000001ffffffffff\nCapEff:\t0000000000001000\n";
+        assert_eq!(
+            parse_effective_capabilities(status),
+            Some(1 << CAP_NET_ADMIN)
+        );
+        assert_eq!(
+            parse_effective_capabilities("Name:\tmullvad-daemon\n"),
+            None
+        );
+    }
+}
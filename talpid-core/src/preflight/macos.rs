@@ -0,0 +1,31 @@
+use super::{CapabilityKind, MissingCapability};
+use std::{fs, path::Path};
+
+const PF_DEVICE: &str = "/dev/pf";
+
+pub fn check(_resource_dir: &Path) -> Vec<MissingCapability> {
+    let mut missing = vec![];
+
+    // Routes and utun interfaces can only be set up by root
+    if !nix::unistd::geteuid().is_root() {
+        missing.push(MissingCapability {
+            kind: CapabilityKind::Privilege,
+            name: "root".to_owned(),
+            reason: "the daemon is not running as root".to_owned(),
+        });
+    }
+
+    if let Err(error) = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(PF_DEVICE)
+    {
+        missing.push(MissingCapability {
+            kind: CapabilityKind::Firewall,
+            name: PF_DEVICE.to_owned(),
+            reason: format!("cannot be opened: {}", error),
+        });
+    }
+
+    missing
+}
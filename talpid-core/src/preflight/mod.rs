@@ -0,0 +1,75 @@
+use std::{fmt, path::Path};
+
+#[cfg(target_os = "linux")]
+#[path = "linux.rs"]
+mod imp;
+
+#[cfg(target_os = "macos")]
+#[path = "macos.rs"]
+mod imp;
+
+#[cfg(windows)]
+#[path = "windows.rs"]
+mod imp;
+
+#[cfg(target_os = "android")]
+mod imp {
+    /// The VPN service of the system sets up the tunnel on Android, so nothing is required of the
+    /// process itself.
+    pub fn check(_resource_dir: &std::path::Path) -> Vec<super::MissingCapability> {
+        vec![]
+    }
+}
+
+/// What kind of capability is missing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum CapabilityKind {
+    /// The process lacks a privilege, such as `CAP_NET_ADMIN` or running as root.
+    Privilege,
+    /// A device that tunnels are created with cannot be opened, such as `/dev/net/tun`.
+    Device,
+    /// The firewall cannot be controlled, such as WFP or pf.
+    Firewall,
+    /// A driver that tunnels are created with is missing.
+    Driver,
+}
+
+impl fmt::Display for CapabilityKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            CapabilityKind::Privilege => "privilege",
+            CapabilityKind::Device => "device",
+            CapabilityKind::Firewall => "firewall access",
+            CapabilityKind::Driver => "driver",
+        };
+        f.write_str(kind)
+    }
+}
+
+/// A capability that the daemon needs but does not have. Connecting is expected to fail until it
+/// is fixed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MissingCapability {
+    /// What kind of capability is missing.
+    pub kind: CapabilityKind,
+    /// The name of the capability, such as `CAP_NET_ADMIN` or `/dev/pf`.
+    pub name: String,
+    /// Why the capability is considered missing.
+    pub reason: String,
+}
+
+impl fmt::Display for MissingCapability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} \"{}\": {}", self.kind, self.name, self.reason)
+    }
+}
+
+/// Checks that the process has every capability it needs to set up tunnels and the firewall, and
+/// returns the ones that are missing. `resource_dir` is where the bundled drivers are. This blocks
+/// while the system is queried, so it should not be called from an async context.
+pub fn check(resource_dir: &Path) -> Vec<MissingCapability> {
+    let mut missing = imp::check(resource_dir);
+    missing.sort();
+    missing.dedup();
+    missing
+}
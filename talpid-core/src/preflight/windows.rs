@@ -0,0 +1,42 @@
+use super::{CapabilityKind, MissingCapability};
+use std::path::Path;
+
+/// The libraries that create the tunnel adapters. They are bundled with the app.
+const DRIVER_LIBRARIES: &[&str] = &["wintun.dll", "wireguard.dll"];
+
+pub fn check(resource_dir: &Path) -> Vec<MissingCapability> {
+    let mut missing = vec![];
+
+    // Listing the providers requires a session with the WFP engine, which is what the firewall
+    // needs as well
+    if !unsafe { WinFw_DescribeProviders(Some(ignore_provider), std::ptr::null_mut()) } {
+        missing.push(MissingCapability {
+            kind: CapabilityKind::Firewall,
+            name: "WFP".to_owned(),
+            reason: "failed to open a session with the filter engine".to_owned(),
+        });
+    }
+
+    for library in DRIVER_LIBRARIES {
+        let path = resource_dir.join(library);
+        if !path.is_file() {
+            missing.push(MissingCapability {
+                kind: CapabilityKind::Driver,
+                name: (*library).to_owned(),
+                reason: format!("not found in {}", resource_dir.display()),
+            });
+        }
+    }
+
+    missing
+}
+
+extern "system" fn ignore_provider(_name: *const libc::wchar_t, _context: *mut libc::c_void) {}
+
+type ProviderSink = extern "system" fn(name: *const libc::wchar_t, context: *mut libc::c_void);
+
+extern "system" {
+    /// Calls the sink with the name of every WFP provider except our own.
+    #[link_name = "WinFw_DescribeProviders"]
+    fn WinFw_DescribeProviders(sink: Option<ProviderSink>, context: *mut libc::c_void) -> bool;
+}
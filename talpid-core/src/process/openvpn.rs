@@ -9,6 +9,7 @@ use std::{
     ffi::{OsStr, OsString},
     fmt, io,
     path::{Path, PathBuf},
+    time::Duration,
 };
 use talpid_types::net;
 
@@ -30,7 +31,6 @@ static BASE_ARGUMENTS: &[&[&str]] = &[
     &["--sndbuf", "1048576"],
     &["--fast-io"],
     &["--data-ciphers-fallback", "AES-256-GCM"],
-    &["--tls-version-min", "1.3"],
     &["--verb", "3"],
     #[cfg(windows)]
     &[
@@ -52,6 +52,9 @@ static BASE_ARGUMENTS: &[&[&str]] = &[
 static ALLOWED_TLS1_3_CIPHERS: &[&str] =
     &["TLS_AES_256_GCM_SHA384", "TLS_CHACHA20_POLY1305_SHA256"];
 
+/// Minimum TLS version for servers that are not run by Mullvad, which may not support TLS 1.3.
+static CUSTOM_SERVER_TLS_VERSION_MIN: &str = "1.2";
+
 /// Tun driver to use, specified using `--windows-driver`.
 #[derive(Clone)]
 pub enum WindowsDriver {
@@ -59,6 +62,8 @@ pub enum WindowsDriver {
     TapWindows6,
     /// Wintun driver
     Wintun,
+    /// ovpn-dco-win data channel offload driver
+    OvpnDco,
 }
 
 impl WindowsDriver {
@@ -67,6 +72,7 @@ impl WindowsDriver {
         match self {
             WindowsDriver::TapWindows6 => "tap-windows6",
             WindowsDriver::Wintun => "wintun",
+            WindowsDriver::OvpnDco => "ovpn-dco",
         }
     }
 }
@@ -82,6 +88,14 @@ pub struct OpenVpnCommand {
     proxy_auth_path: Option<PathBuf>,
     ca: Option<PathBuf>,
     crl: Option<PathBuf>,
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+    tls_auth: Option<(PathBuf, Option<u8>)>,
+    tls_crypt: Option<PathBuf>,
+    data_ciphers: Option<String>,
+    auth_digest: Option<String>,
+    custom_server: bool,
+    handshake_timeout: Option<Duration>,
     iproute_bin: Option<OsString>,
     plugin: Option<(PathBuf, Vec<String>)>,
     log: Option<PathBuf>,
@@ -90,6 +104,8 @@ pub struct OpenVpnCommand {
     #[cfg(windows)]
     windows_driver: Option<WindowsDriver>,
     tunnel_alias: Option<OsString>,
+    #[cfg(not(windows))]
+    interface_name: Option<String>,
     enable_ipv6: bool,
     proxy_port: Option<u16>,
 }
@@ -106,6 +122,14 @@ impl OpenVpnCommand {
             proxy_auth_path: None,
             ca: None,
             crl: None,
+            cert: None,
+            key: None,
+            tls_auth: None,
+            tls_crypt: None,
+            data_ciphers: None,
+            auth_digest: None,
+            custom_server: false,
+            handshake_timeout: None,
             iproute_bin: None,
             plugin: None,
             log: None,
@@ -114,6 +138,8 @@ impl OpenVpnCommand {
             #[cfg(windows)]
             windows_driver: None,
             tunnel_alias: None,
+            #[cfg(not(windows))]
+            interface_name: None,
             enable_ipv6: true,
             proxy_port: None,
         }
@@ -157,6 +183,52 @@ impl OpenVpnCommand {
         self
     }
 
+    /// Sets the paths to the client certificate and its private key.
+    pub fn client_cert(&mut self, cert: impl AsRef<Path>, key: impl AsRef<Path>) -> &mut Self {
+        self.cert = Some(cert.as_ref().to_path_buf());
+        self.key = Some(key.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets the path to the static key used to authenticate the control channel, and its key
+    /// direction.
+    pub fn tls_auth(&mut self, path: impl AsRef<Path>, key_direction: Option<u8>) -> &mut Self {
+        self.tls_auth = Some((path.as_ref().to_path_buf(), key_direction));
+        self
+    }
+
+    /// Sets the path to the static key used to authenticate and encrypt the control channel.
+    pub fn tls_crypt(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.tls_crypt = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets the data channel ciphers to negotiate, separated by colons.
+    pub fn data_ciphers(&mut self, data_ciphers: impl Into<String>) -> &mut Self {
+        self.data_ciphers = Some(data_ciphers.into());
+        self
+    }
+
+    /// Sets the message digest used to authenticate data channel packets.
+    pub fn auth_digest(&mut self, auth_digest: impl Into<String>) -> &mut Self {
+        self.auth_digest = Some(auth_digest.into());
+        self
+    }
+
+    /// Sets whether the server is not run by Mullvad. The TLS requirements that all Mullvad
+    /// servers meet are then relaxed.
+    pub fn custom_server(&mut self, custom_server: bool) -> &mut Self {
+        self.custom_server = custom_server;
+        self
+    }
+
+    /// Sets how long the TLS handshake may take, instead of the OpenVPN default. See the
+    /// `--hand-window` OpenVPN documentation for details.
+    pub fn handshake_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.handshake_timeout = Some(timeout);
+        self
+    }
+
     /// Sets the path to the ip route command.
     pub fn iproute_bin(&mut self, iproute_bin: impl Into<OsString>) -> &mut Self {
         self.iproute_bin = Some(iproute_bin.into());
@@ -195,6 +267,14 @@ impl OpenVpnCommand {
         self
     }
 
+    /// Sets the name of the tunnel device that OpenVPN creates. The system picks a name if this
+    /// is not set.
+    #[cfg(not(windows))]
+    pub fn interface_name(&mut self, interface_name: impl Into<String>) -> &mut Self {
+        self.interface_name = Some(interface_name.into());
+        self
+    }
+
     /// Configures if IPv6 should be allowed in the tunnel.
     pub fn enable_ipv6(&mut self, enable_ipv6: bool) -> &mut Self {
         self.enable_ipv6 = enable_ipv6;
@@ -245,6 +325,38 @@ impl OpenVpnCommand {
             args.push(OsString::from("--crl-verify"));
             args.push(OsString::from(crl.as_os_str()));
         }
+        if let Some(ref cert) = self.cert {
+            args.push(OsString::from("--cert"));
+            args.push(OsString::from(cert.as_os_str()));
+        }
+        if let Some(ref key) = self.key {
+            args.push(OsString::from("--key"));
+            args.push(OsString::from(key.as_os_str()));
+        }
+        if let Some((ref tls_auth, key_direction)) = self.tls_auth {
+            args.push(OsString::from("--tls-auth"));
+            args.push(OsString::from(tls_auth.as_os_str()));
+            if let Some(key_direction) = key_direction {
+                args.push(OsString::from(key_direction.to_string()));
+            }
+        }
+        if let Some(ref tls_crypt) = self.tls_crypt {
+            args.push(OsString::from("--tls-crypt"));
+            args.push(OsString::from(tls_crypt.as_os_str()));
+        }
+        if let Some(ref data_ciphers) = self.data_ciphers {
+            args.push(OsString::from("--data-ciphers"));
+            args.push(OsString::from(data_ciphers));
+        }
+        if let Some(ref auth_digest) = self.auth_digest {
+            args.push(OsString::from("--auth"));
+            args.push(OsString::from(auth_digest));
+        }
+
+        if let Some(timeout) = self.handshake_timeout {
+            args.push(OsString::from("--hand-window"));
+            args.push(OsString::from(timeout.as_secs().to_string()));
+        }
 
         if let Some((ref path, ref plugin_args)) = self.plugin {
             args.push(OsString::from("--plugin"));
@@ -277,13 +389,28 @@ impl OpenVpnCommand {
             args.push(tunnel_device.clone());
         }
 
+        // This overrides `--dev tun` in the base arguments. On macOS, a specific utun device is
+        // selected with `--dev-node` instead.
+        #[cfg(target_os = "linux")]
+        if let Some(ref interface_name) = self.interface_name {
+            args.push(OsString::from("--dev"));
+            args.push(OsString::from(interface_name));
+            args.push(OsString::from("--dev-type"));
+            args.push(OsString::from("tun"));
+        }
+        #[cfg(target_os = "macos")]
+        if let Some(ref interface_name) = self.interface_name {
+            args.push(OsString::from("--dev-node"));
+            args.push(OsString::from(interface_name));
+        }
+
         #[cfg(windows)]
         if let Some(ref windows_driver) = self.windows_driver {
             args.push(OsString::from("--windows-driver"));
             args.push(OsString::from(windows_driver.as_str()));
         }
 
-        args.extend(Self::tls_cipher_arguments().iter().map(OsString::from));
+        args.extend(self.tls_cipher_arguments().iter().map(OsString::from));
         args.extend(self.proxy_arguments().iter().map(OsString::from));
 
         #[cfg(target_os = "linux")]
@@ -306,10 +433,16 @@ impl OpenVpnCommand {
         args
     }
 
-    fn tls_cipher_arguments() -> Vec<String> {
+    fn tls_cipher_arguments(&self) -> Vec<String> {
         let mut args = vec![];
-        args.push("--tls-ciphersuites".to_owned());
-        args.push(ALLOWED_TLS1_3_CIPHERS.join(":"));
+        args.push("--tls-version-min".to_owned());
+        if self.custom_server {
+            args.push(CUSTOM_SERVER_TLS_VERSION_MIN.to_owned());
+        } else {
+            args.push("1.3".to_owned());
+            args.push("--tls-ciphersuites".to_owned());
+            args.push(ALLOWED_TLS1_3_CIPHERS.join(":"));
+        }
         args
     }
 
@@ -475,6 +608,17 @@ mod tests {
         assert!(testee_args.contains(&OsString::from("3333")));
     }
 
+    #[test]
+    fn relaxes_tls_version_for_custom_servers() {
+        let args = OpenVpnCommand::new("").get_arguments();
+        assert!(args.contains(&OsString::from("1.3")));
+        assert!(args.contains(&OsString::from("--tls-ciphersuites")));
+
+        let args = OpenVpnCommand::new("").custom_server(true).get_arguments();
+        assert!(args.contains(&OsString::from("1.2")));
+        assert!(!args.contains(&OsString::from("--tls-ciphersuites")));
+    }
+
     #[test]
     fn passes_plugin_path() {
         let path = "./a/path";
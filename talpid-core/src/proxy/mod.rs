@@ -3,14 +3,18 @@ mod shadowsocks;
 pub use std::io::Result;
 
 use self::shadowsocks::ShadowsocksProxyMonitor;
-use std::{fmt, path::PathBuf, sync::mpsc};
+use std::{fmt, net::SocketAddr, path::PathBuf, sync::mpsc};
 use talpid_types::net::openvpn;
 
+/// How a proxy service stopped.
 pub enum WaitResult {
+    /// The service exited without being asked to. Contains a description of the exit status.
     UnexpectedExit(String),
+    /// The service exited after being asked to shut down.
     ProperShutdown,
 }
 
+/// A running proxy service.
 pub trait ProxyMonitor: Send {
     /// Create a handle than can be used to ask the proxy service to shut down.
     fn close_handle(&mut self) -> Box<dyn ProxyMonitorCloseHandle>;
@@ -28,7 +32,9 @@ impl fmt::Debug for dyn ProxyMonitor {
     }
 }
 
+/// Handle that shuts down a proxy service.
 pub trait ProxyMonitorCloseHandle: Send {
+    /// Asks the proxy service to shut down.
     fn close(self: Box<Self>) -> Result<()>;
 }
 
@@ -76,11 +82,16 @@ impl ProxyMonitorCloseHandle for NoopProxyMonitorCloseHandle {
 /// Variables that define the environment to help
 /// proxy implementations find their way around.
 /// TODO: Move struct to wider scope and use more generic name.
+#[derive(Clone)]
 pub struct ProxyResourceData {
+    /// Directory that the bundled proxy software is found in.
     pub resource_dir: PathBuf,
+    /// Directory that the proxy software logs to. The temporary directory is used if unset.
     pub log_dir: Option<PathBuf>,
 }
 
+/// Starts the proxy service described by `settings`. Proxies that are not managed by the app are
+/// only represented by a monitor that reports the port to connect to.
 pub fn start_proxy(
     settings: &openvpn::ProxySettings,
     resource_data: &ProxyResourceData,
@@ -101,3 +112,17 @@ pub fn start_proxy(
         )),
     }
 }
+
+/// Starts a Shadowsocks client that relays UDP datagrams sent to its local port to `forward`,
+/// through the Shadowsocks server in `settings`.
+pub fn start_shadowsocks_udp_tunnel(
+    settings: &openvpn::ShadowsocksProxySettings,
+    forward: SocketAddr,
+    resource_data: &ProxyResourceData,
+) -> Result<Box<dyn ProxyMonitor>> {
+    Ok(Box::new(ShadowsocksProxyMonitor::start_udp_tunnel(
+        settings,
+        forward,
+        resource_data,
+    )?))
+}
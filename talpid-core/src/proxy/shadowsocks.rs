@@ -29,6 +29,8 @@ struct ShadowsocksCommand {
     shadowsocks_bin: OsString,
     local: Option<SocketAddr>,
     peer: Option<SocketAddr>,
+    // Relay UDP traffic to this address instead of running a SOCKS server.
+    forward: Option<SocketAddr>,
     peer_password: Option<String>,
     // This should map to the shadowsocks-rust `CipherType` type.
     cipher: Option<String>,
@@ -40,6 +42,7 @@ impl ShadowsocksCommand {
             shadowsocks_bin,
             local: None,
             peer: None,
+            forward: None,
             peer_password: None,
             cipher: None,
         }
@@ -55,6 +58,11 @@ impl ShadowsocksCommand {
         self
     }
 
+    pub fn forward(&mut self, forward: SocketAddr) -> &mut Self {
+        self.forward = Some(forward);
+        self
+    }
+
     pub fn peer_password(&mut self, password: String) -> &mut Self {
         self.peer_password = Some(password);
         self
@@ -73,8 +81,17 @@ impl ShadowsocksCommand {
     fn get_arguments(&self) -> Vec<String> {
         let mut args: Vec<String> = vec![];
 
-        // Always activate TCP no-delay.
-        args.push("--no-delay".to_owned());
+        if let Some(ref forward) = self.forward {
+            args.push("--protocol".to_owned());
+            args.push("tunnel".to_owned());
+            args.push("--forward-addr".to_owned());
+            args.push(format!("{}:{}", forward.ip(), forward.port()));
+            // Only relay UDP.
+            args.push("-U".to_owned());
+        } else {
+            // Always activate TCP no-delay.
+            args.push("--no-delay".to_owned());
+        }
 
         if let Some(ref local) = self.local {
             args.push("--local-addr".to_owned());
@@ -130,18 +147,40 @@ impl ShadowsocksProxyMonitor {
         settings: &ShadowsocksProxySettings,
         resource_data: &ProxyResourceData,
     ) -> Result<Self> {
-        let binary = resource_data
-            .resource_dir
-            .join(SHADOWSOCKS_BIN_FILENAME)
-            .into_os_string();
+        let cmd = ShadowsocksCommand::new(Self::binary_path(resource_data))
+            .local(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0))
+            .peer(settings.peer)
+            .peer_password(settings.password.clone())
+            .cipher(settings.cipher.clone())
+            .build();
+        Self::start_command(cmd, resource_data)
+    }
 
-        let mut cmd = ShadowsocksCommand::new(binary)
+    /// Starts a client that relays UDP datagrams sent to the bound port to `forward`, through
+    /// the Shadowsocks server.
+    pub fn start_udp_tunnel(
+        settings: &ShadowsocksProxySettings,
+        forward: SocketAddr,
+        resource_data: &ProxyResourceData,
+    ) -> Result<Self> {
+        let cmd = ShadowsocksCommand::new(Self::binary_path(resource_data))
             .local(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0))
             .peer(settings.peer)
+            .forward(forward)
             .peer_password(settings.password.clone())
             .cipher(settings.cipher.clone())
             .build();
+        Self::start_command(cmd, resource_data)
+    }
+
+    fn binary_path(resource_data: &ProxyResourceData) -> OsString {
+        resource_data
+            .resource_dir
+            .join(SHADOWSOCKS_BIN_FILENAME)
+            .into_os_string()
+    }
 
+    fn start_command(mut cmd: duct::Expression, resource_data: &ProxyResourceData) -> Result<Self> {
         let log_dir: PathBuf = if let Some(ref log_dir) = resource_data.log_dir {
             log_dir.clone()
         } else {
@@ -230,7 +269,8 @@ impl ShadowsocksProxyMonitor {
 
     fn parse_port(logline: &str) -> Result<u16> {
         // TODO: Compile once and reuse.
-        let re = Regex::new(r"(?:TCP listening on \d+\.\d+\.\d+\.\d+:)(\d+$)").unwrap();
+        let re = Regex::new(r"(?:(?:TCP|UDP)(?: tunnel)? listening on \d+\.\d+\.\d+\.\d+:)(\d+$)")
+            .unwrap();
 
         if let Some(captures) = re.captures(logline) {
             return Ok(captures[1].parse().map_err(|_| {
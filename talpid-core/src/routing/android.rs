@@ -1,4 +1,4 @@
-use crate::routing::{imp::RouteManagerCommand, RequiredRoute};
+use crate::routing::{imp::RouteManagerCommand, RequiredRoute, SharedSnapshot};
 // use futures01::{stream::Stream, sync::mpsc};
 use futures::{channel::mpsc, stream::StreamExt};
 use std::collections::HashSet;
@@ -13,7 +13,10 @@ pub struct RouteManagerImpl {}
 
 
 impl RouteManagerImpl {
-    pub async fn new(_required_routes: HashSet<RequiredRoute>) -> Result<Self, Error> {
+    pub async fn new(
+        _required_routes: HashSet<RequiredRoute>,
+        _snapshot: SharedSnapshot,
+    ) -> Result<Self, Error> {
         Ok(RouteManagerImpl {})
     }
 
@@ -28,9 +31,15 @@ impl RouteManagerImpl {
                     tx.send(()).map_err(|()| Error)?;
                     break;
                 }
-                RouteManagerCommand::AddRoutes(_routes, tx) => {
+                RouteManagerCommand::AddRoutes(_routes, tx)
+                | RouteManagerCommand::RemoveRoutes(_routes, tx) => {
                     let _ = tx.send(Ok(()));
                 }
+                RouteManagerCommand::NewChangeListener(tx) => {
+                    // No changes are ever reported
+                    let (_listener_tx, listener_rx) = mpsc::unbounded();
+                    let _ = tx.send(listener_rx);
+                }
                 RouteManagerCommand::ClearRoutes => (),
             }
         }
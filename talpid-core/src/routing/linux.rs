@@ -1,6 +1,6 @@
 use crate::routing::{
-    imp::{CallbackMessage, RouteManagerCommand},
-    NetNode, Node, RequiredRoute, Route,
+    imp::RouteManagerCommand, CallbackMessage, NetNode, Node, RequiredRoute, Route, RouteSnapshot,
+    RoutingRules, SharedSnapshot,
 };
 use std::{
     collections::{BTreeMap, HashSet},
@@ -38,6 +38,13 @@ use rtnetlink::{
 
 use libc::{AF_INET, AF_INET6};
 
+/// The `RTA_GATEWAY` attribute of a next hop in an `RTA_MULTIPATH` attribute.
+const RTA_GATEWAY: u16 = 5;
+/// The `RTA_VIA` attribute of a next hop in an `RTA_MULTIPATH` attribute.
+const RTA_VIA: u16 = 18;
+/// The size of `struct rtnexthop`, which precedes the attributes of each next hop.
+const RTNEXTHOP_LEN: usize = 8;
+
 
 lazy_static! {
     static ref SUPPRESS_RULE_V4: RuleMessage = RuleMessage {
@@ -132,10 +139,16 @@ pub struct RouteManagerImpl {
 
     // currently added routes
     added_routes: HashSet<Route>,
+    // currently created routing rules
+    routing_rules: Option<RoutingRules>,
+    snapshot: SharedSnapshot,
 }
 
 impl RouteManagerImpl {
-    pub async fn new(required_routes: HashSet<RequiredRoute>) -> Result<Self> {
+    pub async fn new(
+        required_routes: HashSet<RequiredRoute>,
+        snapshot: SharedSnapshot,
+    ) -> Result<Self> {
         let (mut connection, handle, messages) =
             rtnetlink::new_connection().map_err(Error::ConnectError)?;
 
@@ -156,10 +169,13 @@ impl RouteManagerImpl {
             iface_map,
             listeners: vec![],
             added_routes: HashSet::new(),
+            routing_rules: None,
+            snapshot,
         };
 
         monitor.clear_routing_rules().await?;
         monitor.add_required_routes(required_routes).await?;
+        monitor.publish_snapshot();
 
         Ok(monitor)
     }
@@ -184,6 +200,7 @@ impl RouteManagerImpl {
                 }
             }
         }
+        self.routing_rules = Some(RoutingRules { ipv6: enable_ipv6 });
         Ok(())
     }
 
@@ -224,6 +241,7 @@ impl RouteManagerImpl {
                 self.delete_rule_if_exists((*rule).clone()).await?;
             }
         }
+        self.routing_rules = None;
         Ok(())
     }
 
@@ -270,24 +288,36 @@ impl RouteManagerImpl {
     }
 
     async fn add_required_routes(&mut self, required_routes: HashSet<RequiredRoute>) -> Result<()> {
-        let mut required_normal_routes = HashSet::new();
-
-        for route in required_routes {
-            match route.node {
-                NetNode::RealNode(node) => {
-                    required_normal_routes
-                        .insert(Route::new(node, route.prefix).table(route.table_id));
-                }
-            }
+        for normal_route in Self::to_routes(required_routes) {
+            self.add_route(normal_route).await?;
         }
 
-        for normal_route in required_normal_routes.into_iter() {
-            self.add_route(normal_route).await?;
+        Ok(())
+    }
+
+    async fn remove_required_routes(
+        &mut self,
+        required_routes: HashSet<RequiredRoute>,
+    ) -> Result<()> {
+        for route in Self::to_routes(required_routes) {
+            if self.added_routes.contains(&route) {
+                self.delete_route_if_exists(&route).await?;
+                self.added_routes.remove(&route);
+            }
         }
 
         Ok(())
     }
 
+    fn to_routes(required_routes: HashSet<RequiredRoute>) -> HashSet<Route> {
+        required_routes
+            .into_iter()
+            .map(|route| match route.node {
+                NetNode::RealNode(node) => Route::new(node, route.prefix).table(route.table_id),
+            })
+            .collect()
+    }
+
     async fn initialize_link_map(
         handle: &rtnetlink::Handle,
     ) -> Result<BTreeMap<u32, NetworkInterface>> {
@@ -324,6 +354,48 @@ impl RouteManagerImpl {
     }
 
 
+    /// Removes the routes that are tagged with our routing protocol but were not added by this
+    /// route manager, such as the routes of a daemon that crashed.
+    async fn remove_orphaned_routes(&mut self) -> Result<Vec<Route>> {
+        let mut orphans = vec![];
+        for ip_version in vec![IpVersion::V4, IpVersion::V6] {
+            let mut routes = self.handle.route().get(ip_version).execute();
+            while let Some(msg) = routes.try_next().await.map_err(Error::NetlinkError)? {
+                if msg.header.protocol != crate::linux::ROUTE_PROTOCOL {
+                    continue;
+                }
+                // The kernel may report a metric for routes that were added without one
+                let route = self.parse_route_message(msg.clone()).ok().flatten();
+                let is_ours = route.as_ref().map_or(false, |route| {
+                    self.added_routes.iter().any(|added| {
+                        added.prefix == route.prefix && added.table_id == route.table_id
+                    })
+                });
+                if !is_ours {
+                    orphans.push((msg, route));
+                }
+            }
+        }
+
+        let mut removed = vec![];
+        for (msg, route) in orphans {
+            self.handle
+                .route()
+                .del(msg)
+                .execute()
+                .await
+                .map_err(Error::NetlinkError)?;
+            match route {
+                Some(route) => {
+                    log::info!("Removed orphaned route: {}", route);
+                    removed.push(route);
+                }
+                None => log::info!("Removed an orphaned route that could not be parsed"),
+            }
+        }
+        Ok(removed)
+    }
+
     pub(crate) async fn run(
         mut self,
         manage_rx: UnboundedReceiver<RouteManagerCommand>,
@@ -348,19 +420,32 @@ impl RouteManagerImpl {
             RouteManagerCommand::Shutdown(shutdown_signal) => {
                 log::trace!("Shutting down route manager");
                 self.destructor().await;
+                self.publish_snapshot();
                 log::trace!("Route manager done");
                 let _ = shutdown_signal.send(());
                 return Err(Error::Shutdown);
             }
             RouteManagerCommand::AddRoutes(routes, result_tx) => {
                 log::debug!("Adding routes: {:?}", routes);
-                let _ = result_tx.send(self.add_required_routes(routes.clone()).await);
+                let result = self.add_required_routes(routes.clone()).await;
+                self.publish_snapshot();
+                let _ = result_tx.send(result);
+            }
+            RouteManagerCommand::RemoveRoutes(routes, result_tx) => {
+                log::debug!("Removing routes: {:?}", routes);
+                let result = self.remove_required_routes(routes).await;
+                self.publish_snapshot();
+                let _ = result_tx.send(result);
             }
             RouteManagerCommand::CreateRoutingRules(enable_ipv6, result_tx) => {
-                let _ = result_tx.send(self.create_routing_rules(enable_ipv6).await);
+                let result = self.create_routing_rules(enable_ipv6).await;
+                self.publish_snapshot();
+                let _ = result_tx.send(result);
             }
             RouteManagerCommand::ClearRoutingRules(result_tx) => {
-                let _ = result_tx.send(self.clear_routing_rules().await);
+                let result = self.clear_routing_rules().await;
+                self.publish_snapshot();
+                let _ = result_tx.send(result);
             }
             RouteManagerCommand::NewChangeListener(result_tx) => {
                 let _ = result_tx.send(self.listen());
@@ -371,6 +456,10 @@ impl RouteManagerImpl {
             RouteManagerCommand::ClearRoutes => {
                 log::debug!("Clearing routes");
                 self.cleanup_routes().await;
+                self.publish_snapshot();
+            }
+            RouteManagerCommand::RemoveOrphanedRoutes(result_tx) => {
+                let _ = result_tx.send(self.remove_orphaned_routes().await);
             }
         }
         Ok(())
@@ -396,6 +485,7 @@ impl RouteManagerImpl {
             NetlinkPayload::InnerMessage(RtnlMessage::DelRoute(old_route)) => {
                 if let Some(deletion) = self.parse_route_message(old_route)? {
                     self.process_deleted_route(&deletion)?;
+                    self.publish_snapshot();
                     self.notify_change_listeners(CallbackMessage::DelRoute(deletion));
                 }
             }
@@ -404,6 +494,13 @@ impl RouteManagerImpl {
         Ok(())
     }
 
+    fn publish_snapshot(&self) {
+        self.snapshot.publish(RouteSnapshot {
+            routes: self.added_routes.iter().cloned().collect(),
+            routing_rules: self.routing_rules,
+        });
+    }
+
     fn notify_change_listeners(&mut self, message: CallbackMessage) {
         self.listeners
             .retain(|listener| listener.unbounded_send(message.clone()).is_ok());
@@ -462,7 +559,23 @@ impl RouteManagerImpl {
                 }
 
                 RouteNla::Via(addr) => {
-                    node_addr = Self::parse_ip(&addr).map(Some)?;
+                    node_addr = parse_via(&addr).map(Some)?;
+                }
+
+                // Routes with several next hops, such as IPv6 default routes through several
+                // routers, are reported as if they only went through the first one
+                RouteNla::MultiPath(nexthops) => {
+                    if let Some((device_idx, nexthop_gateway)) = parse_first_nexthop(nexthops) {
+                        if device.is_none() {
+                            device = self
+                                .iface_map
+                                .get(&device_idx)
+                                .filter(|route_device| !route_device.is_loopback());
+                        }
+                        if gateway.is_none() {
+                            gateway = nexthop_gateway;
+                        }
+                    }
                 }
 
                 RouteNla::Destination(addr) => {
@@ -557,22 +670,7 @@ impl RouteManagerImpl {
 
     async fn delete_route(&self, route: &Route) -> Result<()> {
         let compat_table = compat_table_id(route.table_id);
-        let scope = match route.prefix {
-            IpNetwork::V4(v4_prefix) => {
-                if v4_prefix.prefix() > 0 && v4_prefix.prefix() < 32 {
-                    RT_SCOPE_LINK
-                } else {
-                    RT_SCOPE_UNIVERSE
-                }
-            }
-            IpNetwork::V6(v6_prefix) => {
-                if v6_prefix.prefix() > 0 && v6_prefix.prefix() < 128 {
-                    RT_SCOPE_LINK
-                } else {
-                    RT_SCOPE_UNIVERSE
-                }
-            }
-        };
+        let scope = route_scope(route);
 
         let mut route_message = RouteMessage {
             header: RouteHeader {
@@ -603,12 +701,7 @@ impl RouteManagerImpl {
         }
 
         if let Some(gateway) = route.node.get_address() {
-            let gateway_nla = if route.node.get_device().is_some() {
-                RouteNla::Gateway(ip_to_bytes(gateway))
-            } else {
-                RouteNla::Via(ip_to_bytes(gateway))
-            };
-            route_message.nlas.push(gateway_nla);
+            route_message.nlas.push(gateway_nla(route.prefix, gateway));
         }
 
         if let Some(metric) = route.metric {
@@ -631,11 +724,8 @@ impl RouteManagerImpl {
                     .route()
                     .add()
                     .v4()
-                    .destination_prefix(v4_prefix.ip(), v4_prefix.prefix());
-
-                if v4_prefix.prefix() > 0 && v4_prefix.prefix() < 32 {
-                    add_message = add_message.scope(RT_SCOPE_LINK);
-                }
+                    .destination_prefix(v4_prefix.ip(), v4_prefix.prefix())
+                    .scope(route_scope(&route));
 
                 if let Some(IpAddr::V4(node_address)) = route.node.get_address() {
                     add_message = add_message.gateway(node_address);
@@ -656,11 +746,8 @@ impl RouteManagerImpl {
                     .route()
                     .add()
                     .v6()
-                    .destination_prefix(v6_prefix.ip(), v6_prefix.prefix());
-
-                if v6_prefix.prefix() > 0 && v6_prefix.prefix() < 128 {
-                    add_message = add_message.scope(RT_SCOPE_LINK);
-                }
+                    .destination_prefix(v6_prefix.ip(), v6_prefix.prefix())
+                    .scope(route_scope(&route));
 
                 if let Some(IpAddr::V6(node_address)) = route.node.get_address() {
                     add_message = add_message.gateway(node_address);
@@ -676,8 +763,16 @@ impl RouteManagerImpl {
             }
         };
 
+        // Gateways of the other address family can only be given with `RTA_VIA`
+        if let Some(gateway) = route.node.get_address() {
+            if gateway.is_ipv4() != route.prefix.is_ipv4() {
+                add_message.nlas.push(gateway_nla(route.prefix, gateway));
+            }
+        }
+
         let compat_table = compat_table_id(route.table_id);
         add_message.header.table = compat_table;
+        add_message.header.protocol = crate::linux::ROUTE_PROTOCOL;
         if compat_table == RT_TABLE_COMPAT {
             add_message.nlas.push(RouteNla::Table(route.table_id));
         }
@@ -765,6 +860,72 @@ fn ip_to_bytes(addr: IpAddr) -> Vec<u8> {
     }
 }
 
+/// Returns the scope of a route. Routes that go straight out of an interface are link routes,
+/// except for default routes and host routes. Routes through a gateway must have universe scope.
+fn route_scope(route: &Route) -> u8 {
+    let host_prefix = if route.prefix.is_ipv4() { 32 } else { 128 };
+    let prefix = route.prefix.prefix();
+    if route.node.get_address().is_none() && prefix > 0 && prefix < host_prefix {
+        RT_SCOPE_LINK
+    } else {
+        RT_SCOPE_UNIVERSE
+    }
+}
+
+/// Returns the attribute that sets the gateway of a route to `prefix`. Gateways of another address
+/// family than the destination, such as IPv6 next hops of IPv4 routes, are given with `RTA_VIA`.
+fn gateway_nla(prefix: IpNetwork, gateway: IpAddr) -> RouteNla {
+    if prefix.is_ipv4() == gateway.is_ipv4() {
+        return RouteNla::Gateway(ip_to_bytes(gateway));
+    }
+    let family = match gateway {
+        IpAddr::V4(_) => AF_INET as u16,
+        IpAddr::V6(_) => AF_INET6 as u16,
+    };
+    let mut via = family.to_ne_bytes().to_vec();
+    via.extend_from_slice(&ip_to_bytes(gateway));
+    RouteNla::Via(via)
+}
+
+/// Parses the address in an `RTA_VIA` attribute, which is preceded by its address family.
+fn parse_via(bytes: &[u8]) -> Result<IpAddr> {
+    match bytes.get(2..) {
+        Some(address) => RouteManagerImpl::parse_ip(address),
+        None => Err(Error::InvalidIpBytes),
+    }
+}
+
+/// Parses the interface index and gateway of the first next hop in an `RTA_MULTIPATH` attribute,
+/// which is a list of `struct rtnexthop`, each followed by the attributes of the next hop.
+fn parse_first_nexthop(bytes: &[u8]) -> Option<(u32, Option<IpAddr>)> {
+    let length = usize::from(u16::from_ne_bytes([*bytes.get(0)?, *bytes.get(1)?]));
+    if length < RTNEXTHOP_LEN {
+        return None;
+    }
+    let nexthop = bytes.get(..length)?;
+    let device_idx = u32::from_ne_bytes([nexthop[4], nexthop[5], nexthop[6], nexthop[7]]);
+
+    let mut gateway = None;
+    let mut attributes = &nexthop[RTNEXTHOP_LEN..];
+    while attributes.len() >= 4 {
+        let attribute_length = usize::from(u16::from_ne_bytes([attributes[0], attributes[1]]));
+        let attribute_type = u16::from_ne_bytes([attributes[2], attributes[3]]);
+        if attribute_length < 4 {
+            break;
+        }
+        let payload = attributes.get(4..attribute_length)?;
+        match attribute_type {
+            RTA_GATEWAY => gateway = RouteManagerImpl::parse_ip(payload).ok(),
+            RTA_VIA => gateway = parse_via(payload).ok(),
+            _ => (),
+        }
+        // Attributes are padded to a multiple of four bytes
+        let padded_length = (attribute_length + 3) & !3;
+        attributes = attributes.get(padded_length.min(attributes.len())..)?;
+    }
+    Some((device_idx, gateway))
+}
+
 fn compat_table_id(id: u32) -> u8 {
     // RT_TABLE_COMPAT must be combined with nla Table(id)
     if id > 255 {
@@ -830,19 +991,71 @@ mod test {
     fn test_drop_in_executor() {
         let runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
         runtime.block_on(async {
-            let manager = RouteManagerImpl::new(HashSet::new())
+            let manager = RouteManagerImpl::new(HashSet::new(), SharedSnapshot::default())
                 .await
                 .expect("Failed to initialize route manager");
             std::mem::drop(manager);
         });
     }
 
+    #[test]
+    fn test_route_scope() {
+        let device = Node::device("wg-mullvad".to_owned());
+        let gateway = Node::new("fd00::1".parse().unwrap(), "eth0".to_owned());
+        let scope = |node: &Node, prefix: &str| {
+            route_scope(&Route::new(node.clone(), prefix.parse().unwrap()))
+        };
+
+        assert_eq!(scope(&device, "10.0.0.0/8"), RT_SCOPE_LINK);
+        assert_eq!(scope(&device, "fc00::/7"), RT_SCOPE_LINK);
+        assert_eq!(scope(&device, "::/0"), RT_SCOPE_UNIVERSE);
+        assert_eq!(scope(&device, "fc00::1/128"), RT_SCOPE_UNIVERSE);
+        assert_eq!(scope(&gateway, "2000::/3"), RT_SCOPE_UNIVERSE);
+    }
+
+    #[test]
+    fn test_gateway_nla() {
+        let gateway: IpAddr = "fe80::1".parse().unwrap();
+        assert_eq!(
+            gateway_nla("::/0".parse().unwrap(), gateway),
+            RouteNla::Gateway(ip_to_bytes(gateway))
+        );
+        let via = gateway_nla("0.0.0.0/0".parse().unwrap(), gateway);
+        match &via {
+            RouteNla::Via(bytes) => assert_eq!(parse_via(bytes).unwrap(), gateway),
+            _ => panic!("Expected RTA_VIA, got {:?}", via),
+        }
+    }
+
+    #[test]
+    fn test_parse_first_nexthop() {
+        let gateway: Ipv6Addr = "fe80::1".parse().unwrap();
+        let mut nexthops = vec![];
+        // struct rtnexthop, followed by an RTA_GATEWAY attribute
+        nexthops.extend_from_slice(&(RTNEXTHOP_LEN as u16 + 20).to_ne_bytes());
+        nexthops.extend_from_slice(&[0, 0]);
+        nexthops.extend_from_slice(&3u32.to_ne_bytes());
+        nexthops.extend_from_slice(&20u16.to_ne_bytes());
+        nexthops.extend_from_slice(&RTA_GATEWAY.to_ne_bytes());
+        nexthops.extend_from_slice(&gateway.octets());
+        // A second next hop without attributes
+        nexthops.extend_from_slice(&(RTNEXTHOP_LEN as u16).to_ne_bytes());
+        nexthops.extend_from_slice(&[0, 0]);
+        nexthops.extend_from_slice(&4u32.to_ne_bytes());
+
+        assert_eq!(
+            parse_first_nexthop(&nexthops),
+            Some((3, Some(IpAddr::V6(gateway))))
+        );
+        assert_eq!(parse_first_nexthop(&nexthops[..4]), None);
+    }
+
     /// Tests if dropping outside a runtime panics
     #[test]
     fn test_drop() {
         let runtime = tokio::runtime::Runtime::new().expect("Failed to initialize runtime");
         let manager = runtime.block_on(async {
-            RouteManagerImpl::new(HashSet::new())
+            RouteManagerImpl::new(HashSet::new(), SharedSnapshot::default())
                 .await
                 .expect("Failed to initialize route manager")
         });
@@ -1,4 +1,7 @@
-use crate::routing::{imp::RouteManagerCommand, NetNode, Node, RequiredRoute, Route};
+use crate::routing::{
+    imp::RouteManagerCommand, CallbackMessage, NetNode, Node, RequiredRoute, Route, RouteSnapshot,
+    SharedSnapshot,
+};
 
 use futures::{
     channel::mpsc,
@@ -9,7 +12,7 @@ use ipnetwork::IpNetwork;
 use std::{
     collections::HashSet,
     io,
-    net::IpAddr,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
     process::{ExitStatus, Stdio},
 };
 use tokio::{io::AsyncBufReadExt, process::Command};
@@ -41,6 +44,14 @@ pub enum Error {
     /// Unexpected output from netstat
     #[error(display = "Unexpected output from netstat")]
     BadOutputFromNetstat,
+
+    /// Error while listing routes with `netstat -rn`
+    #[error(display = "Error while running \"netstat -rn\"")]
+    FailedToListRoutes(#[error(source)] io::Error),
+
+    /// A scoped route must be routed through a network interface.
+    #[error(display = "A scoped route must have a network interface")]
+    ScopedRouteWithoutInterface,
 }
 
 /// Route manager can be in 1 of 4 states -
@@ -57,36 +68,48 @@ pub enum Error {
 pub struct RouteManagerImpl {
     default_destinations: HashSet<IpNetwork>,
     applied_routes: HashSet<Route>,
+    /// Applied routes that are scoped to the interface of their node.
+    scoped_routes: HashSet<Route>,
     v4_gateway: Option<Node>,
     v6_gateway: Option<Node>,
-    connectivity_change:
-        Option<Box<dyn FusedStream<Item = std::io::Result<()>> + Unpin + Send + Sync>>,
+    route_changes: Option<
+        Box<dyn FusedStream<Item = std::io::Result<RouteMonitorMessage>> + Unpin + Send + Sync>,
+    >,
+    listeners: Vec<mpsc::UnboundedSender<CallbackMessage>>,
+    snapshot: SharedSnapshot,
 }
 
 
 impl RouteManagerImpl {
-    pub async fn new(required_routes: HashSet<RequiredRoute>) -> Result<Self> {
+    pub async fn new(
+        required_routes: HashSet<RequiredRoute>,
+        snapshot: SharedSnapshot,
+    ) -> Result<Self> {
         let v4_gateway = Self::get_default_node_cmd("-inet").await?;
         let v6_gateway = Self::get_default_node_cmd("-inet6").await?;
 
-        let monitor = listen_for_default_route_changes().await?;
+        let monitor = listen_for_route_changes().await?;
 
         let mut manager = Self {
             default_destinations: HashSet::new(),
             applied_routes: HashSet::new(),
-            connectivity_change: Some(Box::new(monitor.fuse())),
+            scoped_routes: HashSet::new(),
+            route_changes: Some(Box::new(monitor.fuse())),
             v4_gateway,
             v6_gateway,
+            listeners: vec![],
+            snapshot,
         };
 
         manager.add_required_routes(required_routes).await?;
+        manager.publish_snapshot();
 
         Ok(manager)
     }
 
     pub(crate) async fn run(mut self, manage_rx: mpsc::UnboundedReceiver<RouteManagerCommand>) {
         let mut manage_rx = manage_rx.fuse();
-        let mut connectivity_change = self.connectivity_change.take().unwrap();
+        let mut route_changes = self.route_changes.take().unwrap();
 
         loop {
             futures::select! {
@@ -94,16 +117,32 @@ impl RouteManagerImpl {
                     match command {
                         Some(RouteManagerCommand::Shutdown(tx)) => {
                             self.cleanup_routes().await;
+                            self.publish_snapshot();
                             let _ = tx.send(());
                             return;
                         },
 
                         Some(RouteManagerCommand::AddRoutes(routes, result_tx)) => {
                             let result = self.add_required_routes(routes).await;
+                            self.publish_snapshot();
+                            let _ = result_tx.send(result);
+                        },
+                        Some(RouteManagerCommand::RemoveRoutes(routes, result_tx)) => {
+                            let result = self.remove_required_routes(routes).await;
+                            self.publish_snapshot();
                             let _ = result_tx.send(result);
                         },
+                        Some(RouteManagerCommand::NewChangeListener(result_tx)) => {
+                            let (listener_tx, listener_rx) = mpsc::unbounded();
+                            self.listeners.push(listener_tx);
+                            let _ = result_tx.send(listener_rx);
+                        },
                         Some(RouteManagerCommand::ClearRoutes) => {
                             self.cleanup_routes().await;
+                            self.publish_snapshot();
+                        },
+                        Some(RouteManagerCommand::RemoveOrphanedRoutes(result_tx)) => {
+                            let _ = result_tx.send(self.remove_orphaned_routes().await);
                         },
                         None => {
                             break;
@@ -111,19 +150,25 @@ impl RouteManagerImpl {
                     }
                 },
 
-                _result = connectivity_change.select_next_some() => {
-                    let v4_gateway = Self::get_default_node_cmd("-inet").await.unwrap_or(None);
-                    let v6_gateway = Self::get_default_node_cmd("-inet6").await.unwrap_or(None);
-
-                    if v4_gateway != self.v4_gateway {
-                        self.v4_gateway = v4_gateway;
-                        self.apply_new_default_route(&self.v4_gateway, true).await;
+                message = route_changes.select_next_some() => {
+                    let message = match message {
+                        Ok(message) => message,
+                        Err(_) => continue,
+                    };
+
+                    if let Some(route) = message.route {
+                        self.notify_change_listeners(if message.added {
+                            CallbackMessage::NewRoute(route)
+                        } else {
+                            CallbackMessage::DelRoute(route)
+                        });
                     }
 
-                    if v6_gateway != self.v6_gateway {
-                        self.v6_gateway = v6_gateway;
-                        self.apply_new_default_route(&self.v6_gateway, false).await;
+                    if !message.is_default {
+                        continue;
                     }
+
+                    self.update_default_nodes().await;
                 },
                 complete => {
                     break;
@@ -135,6 +180,7 @@ impl RouteManagerImpl {
 
     async fn add_required_routes(&mut self, required_routes: HashSet<RequiredRoute>) -> Result<()> {
         let mut routes_to_apply = vec![];
+        let mut scoped_routes_to_apply = vec![];
         let mut default_destinations = HashSet::new();
 
         for route in required_routes {
@@ -143,31 +189,105 @@ impl RouteManagerImpl {
                     default_destinations.insert(route.prefix);
                 }
 
+                NetNode::RealNode(node) if route.scoped => {
+                    if node.get_device().is_none() {
+                        return Err(Error::ScopedRouteWithoutInterface);
+                    }
+                    scoped_routes_to_apply.push(Route::new(node, route.prefix))
+                }
                 NetNode::RealNode(node) => routes_to_apply.push(Route::new(node, route.prefix)),
             }
         }
 
         for route in routes_to_apply {
-            Self::add_route(&route).await?;
+            Self::add_route(&route, None).await?;
             self.applied_routes.insert(route);
         }
 
+        for route in scoped_routes_to_apply {
+            Self::add_route(&route, route.node.get_device()).await?;
+            self.scoped_routes.insert(route);
+        }
+
+        if !default_destinations.is_empty() {
+            // The route monitor may not have reported that the default route moved to another
+            // interface yet, such as when reconnecting right after switching from Wi-Fi to Ethernet
+            self.update_default_nodes().await;
+        }
+
         for destination in default_destinations.iter() {
             match (&self.v4_gateway, &self.v6_gateway, destination.is_ipv4()) {
                 (Some(gateway), _, true) | (_, Some(gateway), false) => {
                     let route = Route::new(gateway.clone(), *destination);
-                    Self::add_route(&route).await?;
+                    Self::add_route(&route, None).await?;
                     self.applied_routes.insert(route);
                 }
-                _ => (),
+                // The route is added once a default route appears
+                _ => log::warn!(
+                    "No default route for {}, so it cannot be routed outside the tunnel yet",
+                    destination
+                ),
             };
         }
 
-        self.default_destinations = default_destinations;
+        self.default_destinations.extend(default_destinations);
 
         Ok(())
     }
 
+    async fn remove_required_routes(
+        &mut self,
+        required_routes: HashSet<RequiredRoute>,
+    ) -> Result<()> {
+        for route in required_routes {
+            if route.scoped {
+                if let NetNode::RealNode(node) = route.node {
+                    let route = Route::new(node, route.prefix);
+                    if self.scoped_routes.remove(&route) {
+                        let status =
+                            Self::delete_route(route.prefix, route.node.get_device()).await?;
+                        if !status.success() {
+                            return Err(Error::FailedToRemoveRoute(io::Error::new(
+                                io::ErrorKind::Other,
+                                format!("Failed to remove scoped route to {}", route.prefix),
+                            )));
+                        }
+                    }
+                }
+                continue;
+            }
+
+            let is_applied = match route.node {
+                // Routes through the default node are reapplied whenever the default route changes,
+                // so the gateway they were applied with may differ from the current one
+                NetNode::DefaultNode => self.default_destinations.remove(&route.prefix),
+                NetNode::RealNode(node) => self
+                    .applied_routes
+                    .contains(&Route::new(node, route.prefix)),
+            };
+            if !is_applied {
+                continue;
+            }
+
+            let status = Self::delete_route(route.prefix, None).await?;
+            if !status.success() {
+                return Err(Error::FailedToRemoveRoute(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("Failed to remove route to {}", route.prefix),
+                )));
+            }
+            self.applied_routes
+                .retain(|applied_route| applied_route.prefix != route.prefix);
+        }
+
+        Ok(())
+    }
+
+    fn notify_change_listeners(&mut self, message: CallbackMessage) {
+        self.listeners
+            .retain(|listener| listener.unbounded_send(message.clone()).is_ok());
+    }
+
     // Retrieves the node that's currently used to reach 0.0.0.0/0
     // Arguments can be either -inet or -inet6
     async fn get_default_node_cmd(if_family: &'static str) -> Result<Option<Node>> {
@@ -219,45 +339,114 @@ impl RouteManagerImpl {
             .and_then(|ip_str| ip_str.parse().ok())
     }
 
-    async fn delete_route(destination: IpNetwork) -> Result<ExitStatus> {
+    /// Deletes the route to `destination`. If `scope` is given, the route that is scoped to that
+    /// interface is deleted instead of the unscoped one.
+    async fn delete_route(destination: IpNetwork, scope: Option<&str>) -> Result<ExitStatus> {
         let mut cmd = Command::new("route");
         cmd.arg("-q")
             .arg("-n")
             .arg("delete")
             .arg(ip_vers(destination))
-            .arg(destination.to_string())
-            .stderr(Stdio::null());
+            .arg(destination_arg(destination));
+        if let Some(scope) = scope {
+            cmd.arg("-ifscope").arg(scope);
+        }
+        cmd.stderr(Stdio::null());
 
         cmd.status().await.map_err(Error::FailedToRemoveRoute)
     }
 
 
-    async fn add_route(route: &Route) -> Result<ExitStatus> {
+    /// Adds a route. If `scope` is given, the route is scoped to that interface.
+    async fn add_route(route: &Route, scope: Option<&str>) -> Result<ExitStatus> {
         let mut cmd = Command::new("route");
         cmd.arg("-q")
             .arg("-n")
             .arg("add")
             .arg(ip_vers(route.prefix))
-            .arg(route.prefix.to_string());
+            .arg(destination_arg(route.prefix));
 
         if let Some(addr) = route.node.get_address() {
-            cmd.arg("-gateway").arg(addr.to_string());
+            cmd.arg("-gateway")
+                .arg(gateway_arg(addr, route.node.get_device()));
         } else if let Some(device) = route.node.get_device() {
             cmd.arg("-interface").arg(device);
         }
+        if let Some(scope) = scope {
+            cmd.arg("-ifscope").arg(scope);
+        }
+        // Tag the route, so that it can be removed if the daemon crashes
+        cmd.arg("-proto1");
 
         cmd.status().await.map_err(Error::FailedToAddRoute)
     }
 
-    async fn cleanup_routes(&self) -> () {
-        let destinations_to_remove = self
+    /// Removes the routes that are tagged with `RTF_PROTO1` but were not added by this route
+    /// manager, such as the routes of a daemon that crashed.
+    async fn remove_orphaned_routes(&mut self) -> Result<Vec<Route>> {
+        let output = Command::new("netstat")
+            .arg("-rn")
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .map_err(Error::FailedToListRoutes)?;
+        let output = String::from_utf8(output.stdout).map_err(|e| {
+            log::error!("Failed to parse utf-8 bytes from output of netstat - {}", e);
+            Error::BadOutputFromNetstat
+        })?;
+
+        let mut removed = vec![];
+        for listed in parse_netstat_routes(&output) {
+            if !listed.flags.contains('1') {
+                continue;
+            }
+            let prefix = listed.route.prefix;
+            let is_ours = self.default_destinations.contains(&prefix)
+                || self
+                    .applied_routes
+                    .iter()
+                    .chain(self.scoped_routes.iter())
+                    .any(|route| route.prefix == prefix);
+            if is_ours {
+                continue;
+            }
+            let scope = if listed.flags.contains('I') {
+                listed.route.node.get_device()
+            } else {
+                None
+            };
+            let status = Self::delete_route(prefix, scope).await?;
+            if status.success() {
+                log::info!("Removed orphaned route: {}", listed.route);
+                removed.push(listed.route);
+            } else {
+                log::warn!("Failed to remove orphaned route: {}", listed.route);
+            }
+        }
+        Ok(removed)
+    }
+
+    async fn cleanup_routes(&mut self) -> () {
+        for route in self.scoped_routes.drain().collect::<Vec<_>>() {
+            match Self::delete_route(route.prefix, route.node.get_device()).await {
+                Ok(status) => {
+                    if !status.success() {
+                        log::debug!("Failed to remove scoped route during shutdown");
+                    }
+                }
+                Err(e) => log::error!("Failed to remove scoped route during shutdown - {}", e),
+            };
+        }
+
+        let destinations_to_remove: HashSet<IpNetwork> = self
             .applied_routes
-            .iter()
-            .map(|route| &route.prefix)
-            .chain(self.default_destinations.iter());
+            .drain()
+            .map(|route| route.prefix)
+            .chain(self.default_destinations.drain())
+            .collect();
 
         for destination in destinations_to_remove {
-            match Self::delete_route(*destination).await {
+            match Self::delete_route(destination, None).await {
                 Ok(status) => {
                     if !status.success() {
                         log::debug!("Failed to remove route during shutdown");
@@ -268,14 +457,43 @@ impl RouteManagerImpl {
         }
     }
 
+    fn publish_snapshot(&self) {
+        self.snapshot.publish(RouteSnapshot {
+            routes: self
+                .applied_routes
+                .iter()
+                .chain(self.scoped_routes.iter())
+                .cloned()
+                .collect(),
+            default_node_destinations: self.default_destinations.iter().cloned().collect(),
+        });
+    }
+
+    /// Looks up the nodes that the default routes go through, and moves the routes through the
+    /// default node if they have changed.
+    async fn update_default_nodes(&mut self) {
+        let v4_gateway = Self::get_default_node_cmd("-inet").await.unwrap_or(None);
+        let v6_gateway = Self::get_default_node_cmd("-inet6").await.unwrap_or(None);
+
+        if v4_gateway != self.v4_gateway {
+            self.v4_gateway = v4_gateway;
+            self.apply_new_default_route(&self.v4_gateway, true).await;
+        }
+
+        if v6_gateway != self.v6_gateway {
+            self.v6_gateway = v6_gateway;
+            self.apply_new_default_route(&self.v6_gateway, false).await;
+        }
+    }
+
     async fn apply_new_default_route(&self, new_node: &Option<Node>, v4: bool) {
         for destination in self.default_destinations.iter() {
             if destination.is_ipv4() == v4 {
-                let _ = Self::delete_route(*destination).await;
+                let _ = Self::delete_route(*destination, None).await;
 
                 if let Some(node) = new_node {
                     log::error!("Resetting default route for {}", destination);
-                    match Self::add_route(&Route::new(node.clone(), *destination)).await {
+                    match Self::add_route(&Route::new(node.clone(), *destination), None).await {
                         Ok(status) => {
                             if !status.success() {
                                 log::error!("Failed to reapply route");
@@ -298,10 +516,43 @@ fn ip_vers(prefix: IpNetwork) -> &'static str {
     }
 }
 
+/// Formats a gateway for `route`. Link-local IPv6 gateways are only unique together with their
+/// interface, which is appended as the scope of the address.
+fn gateway_arg(gateway: IpAddr, device: Option<&str>) -> String {
+    match (gateway, device) {
+        (IpAddr::V6(address), Some(device)) if is_link_local_v6(&address) => {
+            format!("{}%{}", address, device)
+        }
+        _ => gateway.to_string(),
+    }
+}
+
+fn is_link_local_v6(address: &Ipv6Addr) -> bool {
+    (address.segments()[0] & 0xffc0) == 0xfe80
+}
+
+fn destination_arg(prefix: IpNetwork) -> String {
+    if prefix.prefix() == 0 {
+        "default".to_string()
+    } else {
+        prefix.to_string()
+    }
+}
+
 
-/// Returns a stream that produces an item whenever a default route is either added or deleted from
-/// the routing table.
-async fn listen_for_default_route_changes() -> Result<impl Stream<Item = std::io::Result<()>>> {
+/// A route that was added to or deleted from the routing table.
+struct RouteMonitorMessage {
+    added: bool,
+    /// Whether the message concerns a default route.
+    is_default: bool,
+    /// The route, if it could be parsed from the message.
+    route: Option<Route>,
+}
+
+/// Returns a stream that produces an item whenever a route is either added or deleted from the
+/// routing table.
+async fn listen_for_route_changes(
+) -> Result<impl Stream<Item = std::io::Result<RouteMonitorMessage>>> {
     let mut cmd = Command::new("route");
     cmd.arg("-n")
         .arg("monitor")
@@ -325,28 +576,22 @@ async fn listen_for_default_route_changes() -> Result<impl Stream<Item = std::io
     //  default 192.168.44.1 default  192.168.44.90
     // ```
     // On the second line of the message, the message type is specified. Only messages with the
-    // type 'RTM_ADD' or 'RTM_DELETE' are considered. On the 6th line, message attribute values are
-    // shown. To detect a change for a default route in the routing table, check whether this line
-    // contains 'default'.  Whenever an empty line is encountered, the message has been sent, so
-    // the state can be reset.
+    // type 'RTM_ADD' or 'RTM_DELETE' are considered. On the 5th line, the attributes that are
+    // present in the message are listed, and their values are shown on the 6th line. To detect a
+    // change for a default route in the routing table, check whether the message contains
+    // 'default'. Whenever an empty line is encountered, the message has been sent, so the state
+    // can be reset.
 
-    let mut add_or_delete_message = false;
-    let mut contains_default = false;
+    let mut message: Option<MonitorMessageBuilder> = None;
 
     let monitor = LinesStream::new(lines).try_filter_map(move |line| {
-        if add_or_delete_message {
-            if line.contains("default") {
-                contains_default = true;
-            }
+        if let Some(builder) = message.as_mut() {
             if line.trim().is_empty() {
-                add_or_delete_message = false;
-                if contains_default {
-                    contains_default = false;
-                    return future::ready(Ok(Some(())));
-                }
+                return future::ready(Ok(message.take().map(MonitorMessageBuilder::build)));
             }
-        } else {
-            add_or_delete_message = line.starts_with("RTM_ADD:") || line.starts_with("RTM_DELETE:");
+            builder.add_line(&line);
+        } else if line.starts_with("RTM_ADD:") || line.starts_with("RTM_DELETE:") {
+            message = Some(MonitorMessageBuilder::new(line.starts_with("RTM_ADD:")));
         }
         future::ready(Ok(None))
     });
@@ -354,3 +599,334 @@ async fn listen_for_default_route_changes() -> Result<impl Stream<Item = std::io
 
     Ok(monitor)
 }
+
+/// Collects the lines of a message from `route -n monitor`.
+struct MonitorMessageBuilder {
+    added: bool,
+    contains_default: bool,
+    attribute_names: Option<Vec<String>>,
+    attribute_values: Option<Vec<String>>,
+}
+
+impl MonitorMessageBuilder {
+    fn new(added: bool) -> Self {
+        Self {
+            added,
+            contains_default: false,
+            attribute_names: None,
+            attribute_values: None,
+        }
+    }
+
+    fn add_line(&mut self, line: &str) {
+        if line.contains("default") {
+            self.contains_default = true;
+        }
+        if let Some(names) = line.trim().strip_prefix("sockaddrs:") {
+            self.attribute_names = Some(
+                names
+                    .trim()
+                    .trim_start_matches('<')
+                    .trim_end_matches('>')
+                    .split(',')
+                    .map(str::to_owned)
+                    .collect(),
+            );
+        } else if self.attribute_names.is_some() && self.attribute_values.is_none() {
+            self.attribute_values = Some(line.split_whitespace().map(str::to_owned).collect());
+        }
+    }
+
+    fn build(self) -> RouteMonitorMessage {
+        let route = match (&self.attribute_names, &self.attribute_values) {
+            (Some(names), Some(values)) => parse_monitored_route(names, values),
+            _ => None,
+        };
+        RouteMonitorMessage {
+            added: self.added,
+            is_default: self.contains_default,
+            route,
+        }
+    }
+}
+
+/// Parses a route from the attributes of a message from `route -n monitor`. Attributes without a
+/// value are left out of the output, which shifts the remaining values, so `None` is returned
+/// unless the values make sense for their attributes.
+fn parse_monitored_route(names: &[String], values: &[String]) -> Option<Route> {
+    if names.len() != values.len() {
+        return None;
+    }
+
+    let mut destination = None;
+    let mut gateway = None;
+    let mut netmask = None;
+    let mut interface = None;
+    for (name, value) in names.iter().zip(values) {
+        match name.as_str() {
+            "DST" => destination = Some(value.as_str()),
+            "GATEWAY" => gateway = Some(value.as_str()),
+            "NETMASK" => netmask = Some(value.as_str()),
+            "IFP" => interface = Some(value.as_str()),
+            _ => (),
+        }
+    }
+
+    // Link-local IPv6 gateways are scoped to their interface, as in `fe80::1%en0`
+    let gateway_scope = gateway.and_then(|gateway| gateway.splitn(2, '%').nth(1));
+    // The gateway may also be a link-layer address or an interface index, such as `link#4`
+    let gateway = gateway.and_then(RouteManagerImpl::parse_gateway_line);
+    let interface = match interface {
+        Some(interface) if interface.parse::<IpAddr>().is_err() && interface != "default" => {
+            Some(interface.to_owned())
+        }
+        Some(_) => return None,
+        None => gateway_scope.map(str::to_owned),
+    };
+
+    let prefix = match destination? {
+        "default" => {
+            let unspecified = match gateway? {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            };
+            IpNetwork::new(unspecified, 0).ok()?
+        }
+        destination => {
+            let destination = RouteManagerImpl::parse_gateway_line(destination)?;
+            let prefix_length = match netmask {
+                Some(netmask) => prefix_length_from_netmask(destination, netmask)?,
+                // Host routes have no netmask
+                None => match destination {
+                    IpAddr::V4(_) => 32,
+                    IpAddr::V6(_) => 128,
+                },
+            };
+            IpNetwork::new(destination, prefix_length).ok()?
+        }
+    };
+
+    let node = match (gateway, interface) {
+        (Some(gateway), Some(interface)) => Node::new(gateway, interface),
+        (Some(gateway), None) => Node::address(gateway),
+        (None, Some(interface)) => Node::device(interface),
+        (None, None) => return None,
+    };
+    Some(Route::new(node, prefix))
+}
+
+/// A route that was listed by `netstat -rn`.
+struct ListedRoute {
+    route: Route,
+    flags: String,
+}
+
+/// Parses the routes in the output of `netstat -rn`, which lists the IPv4 routes under
+/// `Internet:` and the IPv6 routes under `Internet6:`, one route per line:
+/// ```text
+/// Destination        Gateway            Flags        Netif Expire
+/// 0/1                10.64.0.1          UGSc1        utun3
+/// ```
+fn parse_netstat_routes(output: &str) -> Vec<ListedRoute> {
+    let mut is_ipv4 = None;
+    let mut routes = vec![];
+    for line in output.lines() {
+        match line.trim() {
+            "Internet:" => is_ipv4 = Some(true),
+            "Internet6:" => is_ipv4 = Some(false),
+            line => {
+                if let Some(route) = is_ipv4.and_then(|is_ipv4| parse_netstat_line(line, is_ipv4)) {
+                    routes.push(route);
+                }
+            }
+        }
+    }
+    routes
+}
+
+fn parse_netstat_line(line: &str, is_ipv4: bool) -> Option<ListedRoute> {
+    let tokens: Vec<_> = line.split_whitespace().collect();
+    if tokens.len() < 4 {
+        return None;
+    }
+    let (destination, gateway, flags, interface) = (tokens[0], tokens[1], tokens[2], tokens[3]);
+
+    let prefix = match destination {
+        "default" if is_ipv4 => IpNetwork::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0).ok()?,
+        "default" => IpNetwork::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0).ok()?,
+        destination if is_ipv4 => parse_netstat_ipv4_destination(destination)?,
+        destination => {
+            let mut parts = destination.splitn(2, '/');
+            let address = RouteManagerImpl::parse_gateway_line(parts.next()?)?;
+            let prefix_length = match parts.next() {
+                Some(prefix_length) => prefix_length.parse().ok()?,
+                None => 128,
+            };
+            IpNetwork::new(address, prefix_length).ok()?
+        }
+    };
+
+    // The gateway may also be a link-layer address or an interface index, such as `link#4`
+    let node = match RouteManagerImpl::parse_gateway_line(gateway) {
+        Some(gateway) => Node::new(gateway, interface.to_owned()),
+        None => Node::device(interface.to_owned()),
+    };
+    Some(ListedRoute {
+        route: Route::new(node, prefix),
+        flags: flags.to_owned(),
+    })
+}
+
+/// Parses an IPv4 destination, which netstat abbreviates by leaving out trailing zero octets, as
+/// in `128.0/1`. Destinations without a prefix length cover the octets that are given.
+fn parse_netstat_ipv4_destination(destination: &str) -> Option<IpNetwork> {
+    let mut parts = destination.splitn(2, '/');
+    let octets: Vec<u8> = parts
+        .next()?
+        .split('.')
+        .map(|octet| octet.parse().ok())
+        .collect::<Option<_>>()?;
+    if octets.is_empty() || octets.len() > 4 {
+        return None;
+    }
+    let prefix_length = match parts.next() {
+        Some(prefix_length) => prefix_length.parse().ok()?,
+        None => 8 * octets.len() as u8,
+    };
+    let mut address = [0u8; 4];
+    address[..octets.len()].copy_from_slice(&octets);
+    IpNetwork::new(IpAddr::from(address), prefix_length).ok()
+}
+
+fn prefix_length_from_netmask(destination: IpAddr, netmask: &str) -> Option<u8> {
+    if netmask == "default" {
+        return Some(0);
+    }
+    let ones = match (destination, netmask.parse::<IpAddr>().ok()?) {
+        (IpAddr::V4(_), IpAddr::V4(netmask)) => u32::from(netmask).leading_ones(),
+        (IpAddr::V6(_), IpAddr::V6(netmask)) => u128::from(netmask).leading_ones(),
+        _ => return None,
+    };
+    Some(ones as u8)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(names: &str, values: &str) -> Option<Route> {
+        let names: Vec<_> = names.split(',').map(str::to_owned).collect();
+        let values: Vec<_> = values.split_whitespace().map(str::to_owned).collect();
+        parse_monitored_route(&names, &values)
+    }
+
+    #[test]
+    fn parses_network_route() {
+        let route = parse(
+            "DST,GATEWAY,NETMASK,IFP",
+            "10.1.0.0 192.168.1.1 255.255.0.0 en0",
+        )
+        .unwrap();
+        assert_eq!(
+            route.get_prefix(),
+            "10.1.0.0/16".parse::<IpNetwork>().unwrap()
+        );
+        assert_eq!(
+            route.get_node(),
+            &Node::new("192.168.1.1".parse().unwrap(), "en0".to_owned())
+        );
+    }
+
+    #[test]
+    fn parses_default_route() {
+        let route = parse("DST,GATEWAY,NETMASK", "default fe80::1%en0 default").unwrap();
+        assert_eq!(route.get_prefix(), "::/0".parse::<IpNetwork>().unwrap());
+        assert_eq!(
+            route.get_node(),
+            &Node::new("fe80::1".parse().unwrap(), "en0".to_owned())
+        );
+
+        let route = parse("DST,GATEWAY,NETMASK", "default 192.168.1.1 default").unwrap();
+        assert_eq!(
+            route.get_node(),
+            &Node::address("192.168.1.1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn formats_link_local_gateways() {
+        assert_eq!(
+            gateway_arg("fe80::1".parse().unwrap(), Some("en0")),
+            "fe80::1%en0"
+        );
+        assert_eq!(gateway_arg("fe80::1".parse().unwrap(), None), "fe80::1");
+        assert_eq!(
+            gateway_arg("2001:db8::1".parse().unwrap(), Some("en0")),
+            "2001:db8::1"
+        );
+        assert_eq!(
+            gateway_arg("192.168.1.1".parse().unwrap(), Some("en0")),
+            "192.168.1.1"
+        );
+    }
+
+    #[test]
+    fn parses_host_route() {
+        let route = parse("DST,GATEWAY,IFP", "10.1.2.3 link#4 en0").unwrap();
+        assert_eq!(
+            route.get_prefix(),
+            "10.1.2.3/32".parse::<IpNetwork>().unwrap()
+        );
+        assert_eq!(route.get_node(), &Node::device("en0".to_owned()));
+    }
+
+    #[test]
+    fn parses_netstat_routes() {
+        let output = "Routing tables\n\
+                      \n\
+                      Internet:\n\
+                      Destination        Gateway            Flags        Netif Expire\n\
+                      default            192.168.1.1        UGScg          en0\n\
+                      128.0/1            10.64.0.1          UGSc1        utun3\n\
+                      185.65.135.1       192.168.1.1        UGHS1          en0\n\
+                      \n\
+                      Internet6:\n\
+                      Destination        Gateway            Flags        Netif Expire\n\
+                      8000::/1           fe80::1%utun3      UGcI1        utun3\n";
+        let routes = parse_netstat_routes(output);
+        assert_eq!(routes.len(), 4);
+
+        assert_eq!(
+            routes[1].route.get_prefix(),
+            "128.0.0.0/1".parse::<IpNetwork>().unwrap()
+        );
+        assert_eq!(
+            routes[1].route.get_node(),
+            &Node::new("10.64.0.1".parse().unwrap(), "utun3".to_owned())
+        );
+        assert_eq!(routes[1].flags, "UGSc1");
+        assert_eq!(
+            routes[2].route.get_prefix(),
+            "185.65.135.1/32".parse::<IpNetwork>().unwrap()
+        );
+        assert_eq!(
+            routes[3].route.get_prefix(),
+            "8000::/1".parse::<IpNetwork>().unwrap()
+        );
+    }
+
+    #[test]
+    fn ignores_missing_values() {
+        // The IFP value is missing, so the IFA value is misattributed to it
+        assert!(parse(
+            "DST,GATEWAY,NETMASK,IFP,IFA",
+            "default 192.168.1.1 default 192.168.1.2"
+        )
+        .is_none());
+        assert!(parse(
+            "DST,GATEWAY,NETMASK,IFP",
+            "default 192.168.1.1 default 192.168.1.2"
+        )
+        .is_none());
+    }
+}
@@ -2,7 +2,8 @@
 #![cfg_attr(target_os = "windows", allow(dead_code))]
 
 use ipnetwork::IpNetwork;
-use std::{fmt, net::IpAddr};
+use parking_lot::Mutex;
+use std::{fmt, net::IpAddr, sync::Arc};
 
 #[cfg(target_os = "windows")]
 #[path = "windows.rs"]
@@ -19,6 +20,78 @@ pub use imp::{Error, RouteManager};
 
 pub use imp::RouteManagerHandle;
 
+/// A change to the routing table, as reported to the listeners returned by
+/// `RouteManagerHandle::change_listener`. Changes are reported regardless of whether the route was
+/// added by the route manager or by someone else.
+#[derive(Debug, Clone)]
+pub enum CallbackMessage {
+    /// A route was added.
+    NewRoute(Route),
+    /// A route was removed.
+    DelRoute(Route),
+}
+
+/// The routes and routing rules that a route manager is responsible for, as recorded by the route
+/// manager itself rather than as read from the routing table. Comparing the two reveals routes
+/// that were changed or removed by someone else, and tests can check which routes a component
+/// intended to apply without touching the routing table.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RouteSnapshot {
+    /// The routes that have been applied and will be removed when the route manager stops,
+    /// ordered by destination.
+    pub routes: Vec<Route>,
+    /// Destinations that are routed through the node of the current default route, and that
+    /// follow it when it changes.
+    #[cfg(not(target_os = "linux"))]
+    pub default_node_destinations: Vec<IpNetwork>,
+    /// The routing rules that send traffic to the tunnel table, if they are in place.
+    #[cfg(target_os = "linux")]
+    pub routing_rules: Option<RoutingRules>,
+}
+
+impl RouteSnapshot {
+    /// Orders the routes and destinations, so that equal snapshots compare equal.
+    fn sort(&mut self) {
+        self.routes.sort_by(|a, b| {
+            (a.prefix.ip(), a.prefix.prefix(), a.to_string()).cmp(&(
+                b.prefix.ip(),
+                b.prefix.prefix(),
+                b.to_string(),
+            ))
+        });
+        #[cfg(not(target_os = "linux"))]
+        self.default_node_destinations
+            .sort_by_key(|destination| (destination.ip(), destination.prefix()));
+    }
+}
+
+/// The routing rules that the route manager creates on Linux. Traffic that is not marked as
+/// belonging to the tunnel is routed with the tunnel table, except for non-default routes in the
+/// main table, which take precedence.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoutingRules {
+    /// Whether the rules also apply to IPv6 traffic.
+    pub ipv6: bool,
+}
+
+/// A snapshot that is updated by a route manager and read by its handles.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SharedSnapshot(Arc<Mutex<RouteSnapshot>>);
+
+impl SharedSnapshot {
+    /// Replaces the snapshot. Called by the route manager whenever its routes or rules change,
+    /// before any response to the command that changed them is sent.
+    pub(crate) fn publish(&self, mut snapshot: RouteSnapshot) {
+        snapshot.sort();
+        *self.0.lock() = snapshot;
+    }
+
+    pub(crate) fn get(&self) -> RouteSnapshot {
+        self.0.lock().clone()
+    }
+}
+
 /// A netowrk route with a specific network node, destinaiton and an optional metric.
 #[derive(Debug, Hash, Eq, PartialEq, Clone)]
 pub struct Route {
@@ -30,7 +103,7 @@ pub struct Route {
 }
 
 impl Route {
-    fn new(node: Node, prefix: IpNetwork) -> Self {
+    pub(crate) fn new(node: Node, prefix: IpNetwork) -> Self {
         Self {
             node,
             prefix,
@@ -50,6 +123,11 @@ impl Route {
     pub fn get_node(&self) -> &Node {
         &self.node
     }
+
+    /// Returns the destination prefix of the route.
+    pub fn get_prefix(&self) -> IpNetwork {
+        self.prefix
+    }
 }
 
 impl fmt::Display for Route {
@@ -74,6 +152,8 @@ pub struct RequiredRoute {
     node: NetNode,
     #[cfg(target_os = "linux")]
     table_id: u32,
+    #[cfg(target_os = "macos")]
+    scoped: bool,
 }
 
 impl RequiredRoute {
@@ -84,6 +164,8 @@ impl RequiredRoute {
             prefix,
             #[cfg(target_os = "linux")]
             table_id: u32::from(crate::linux::TUNNEL_TABLE_ID),
+            #[cfg(target_os = "macos")]
+            scoped: false,
         }
     }
 
@@ -93,6 +175,14 @@ impl RequiredRoute {
         self.table_id = new_id;
         self
     }
+
+    /// Scopes the route to the network interface of its node. A scoped route is only used by
+    /// traffic that is bound to the interface, or that is sent from one of its addresses.
+    #[cfg(target_os = "macos")]
+    pub fn scoped(mut self) -> Self {
+        self.scoped = true;
+        self
+    }
 }
 
 /// A NetNode represents a network node - either a real one or a symbolic default one.
@@ -170,3 +260,43 @@ impl fmt::Display for Node {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_order() {
+        let gateway = Node::address("192.168.1.1".parse().unwrap());
+        let route = |prefix: &str| Route::new(gateway.clone(), prefix.parse().unwrap());
+
+        let first = SharedSnapshot::default();
+        first.publish(RouteSnapshot {
+            routes: vec![
+                route("10.0.0.0/8"),
+                route("0.0.0.0/0"),
+                route("10.0.0.0/16"),
+            ],
+            ..RouteSnapshot::default()
+        });
+        let second = SharedSnapshot::default();
+        second.publish(RouteSnapshot {
+            routes: vec![
+                route("10.0.0.0/16"),
+                route("10.0.0.0/8"),
+                route("0.0.0.0/0"),
+            ],
+            ..RouteSnapshot::default()
+        });
+
+        assert_eq!(first.get(), second.get());
+        assert_eq!(
+            first.get().routes,
+            vec![
+                route("0.0.0.0/0"),
+                route("10.0.0.0/8"),
+                route("10.0.0.0/16"),
+            ]
+        );
+    }
+}
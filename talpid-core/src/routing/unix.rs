@@ -1,19 +1,19 @@
 #![cfg_attr(target_os = "android", allow(dead_code))]
 #![cfg_attr(target_os = "windows", allow(dead_code))]
 // TODO: remove the allow(dead_code) for android once it's up to scratch.
-use super::RequiredRoute;
-#[cfg(target_os = "linux")]
+#[cfg(any(target_os = "linux", target_os = "macos"))]
 use super::Route;
+use super::{CallbackMessage, RequiredRoute, RouteSnapshot, SharedSnapshot};
 
-use futures::channel::{
-    mpsc::{self, UnboundedSender},
-    oneshot,
+use futures::{
+    channel::{
+        mpsc::{self, UnboundedSender},
+        oneshot,
+    },
+    stream::Stream,
 };
 use std::{collections::HashSet, io};
 
-#[cfg(target_os = "linux")]
-use futures::stream::Stream;
-
 #[cfg(target_os = "linux")]
 use std::net::IpAddr;
 
@@ -55,9 +55,17 @@ pub enum Error {
 #[derive(Clone)]
 pub struct RouteManagerHandle {
     tx: UnboundedSender<RouteManagerCommand>,
+    snapshot: SharedSnapshot,
 }
 
 impl RouteManagerHandle {
+    /// Returns the routes and routing rules that the route manager has applied, as of the last
+    /// command that it completed. This does not read the routing table, so it does not reflect
+    /// changes that were made by others.
+    pub fn snapshot(&self) -> RouteSnapshot {
+        self.snapshot.get()
+    }
+
     /// Applies the given routes while the route manager is running.
     pub async fn add_routes(&self, routes: HashSet<RequiredRoute>) -> Result<(), Error> {
         let (response_tx, response_rx) = oneshot::channel();
@@ -70,6 +78,19 @@ impl RouteManagerHandle {
             .map_err(Error::PlatformError)
     }
 
+    /// Removes the given routes, if they were previously added with [`Self::add_routes`] or when
+    /// the route manager was created. Other routes are left in place.
+    pub async fn remove_routes(&self, routes: HashSet<RequiredRoute>) -> Result<(), Error> {
+        let (response_tx, response_rx) = oneshot::channel();
+        self.tx
+            .unbounded_send(RouteManagerCommand::RemoveRoutes(routes, response_tx))
+            .map_err(|_| Error::RouteManagerDown)?;
+        response_rx
+            .await
+            .map_err(|_| Error::ManagerChannelDown)?
+            .map_err(Error::PlatformError)
+    }
+
     /// Ensure that packets are routed using the correct tables.
     #[cfg(target_os = "linux")]
     pub async fn create_routing_rules(&self, enable_ipv6: bool) -> Result<(), Error> {
@@ -100,7 +121,6 @@ impl RouteManagerHandle {
     }
 
     /// Listen for route changes.
-    #[cfg(target_os = "linux")]
     pub async fn change_listener(&self) -> Result<impl Stream<Item = CallbackMessage>, Error> {
         let (response_tx, response_rx) = oneshot::channel();
         self.tx
@@ -109,6 +129,20 @@ impl RouteManagerHandle {
         response_rx.await.map_err(|_| Error::ManagerChannelDown)
     }
 
+    /// Removes the routes that were added by a previous instance of the route manager, such as the
+    /// one of a daemon that crashed. Returns the routes that were removed.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub async fn remove_orphaned_routes(&self) -> Result<Vec<Route>, Error> {
+        let (response_tx, response_rx) = oneshot::channel();
+        self.tx
+            .unbounded_send(RouteManagerCommand::RemoveOrphanedRoutes(response_tx))
+            .map_err(|_| Error::RouteManagerDown)?;
+        response_rx
+            .await
+            .map_err(|_| Error::ManagerChannelDown)?
+            .map_err(Error::PlatformError)
+    }
+
     /// Listen for route changes.
     #[cfg(target_os = "linux")]
     pub async fn get_destination_route(
@@ -138,15 +172,20 @@ pub(crate) enum RouteManagerCommand {
         HashSet<RequiredRoute>,
         oneshot::Sender<Result<(), PlatformError>>,
     ),
+    RemoveRoutes(
+        HashSet<RequiredRoute>,
+        oneshot::Sender<Result<(), PlatformError>>,
+    ),
     ClearRoutes,
     Shutdown(oneshot::Sender<()>),
+    NewChangeListener(oneshot::Sender<mpsc::UnboundedReceiver<CallbackMessage>>),
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    RemoveOrphanedRoutes(oneshot::Sender<Result<Vec<Route>, PlatformError>>),
     #[cfg(target_os = "linux")]
     CreateRoutingRules(bool, oneshot::Sender<Result<(), PlatformError>>),
     #[cfg(target_os = "linux")]
     ClearRoutingRules(oneshot::Sender<Result<(), PlatformError>>),
     #[cfg(target_os = "linux")]
-    NewChangeListener(oneshot::Sender<mpsc::UnboundedReceiver<CallbackMessage>>),
-    #[cfg(target_os = "linux")]
     GetDestinationRoute(
         IpAddr,
         bool,
@@ -154,18 +193,12 @@ pub(crate) enum RouteManagerCommand {
     ),
 }
 
-#[cfg(target_os = "linux")]
-#[derive(Debug, Clone)]
-pub enum CallbackMessage {
-    NewRoute(Route),
-    DelRoute(Route),
-}
-
 /// RouteManager applies a set of routes to the route table.
 /// If a destination has to be routed through the default node,
 /// the route will be adjusted dynamically when the default route changes.
 pub struct RouteManager {
     manage_tx: Option<UnboundedSender<RouteManagerCommand>>,
+    snapshot: SharedSnapshot,
     runtime: tokio::runtime::Handle,
 }
 
@@ -178,12 +211,14 @@ impl RouteManager {
         required_routes: HashSet<RequiredRoute>,
     ) -> Result<Self, Error> {
         let (manage_tx, manage_rx) = mpsc::unbounded();
-        let manager = imp::RouteManagerImpl::new(required_routes).await?;
+        let snapshot = SharedSnapshot::default();
+        let manager = imp::RouteManagerImpl::new(required_routes, snapshot.clone()).await?;
         runtime.spawn(manager.run(manage_rx));
 
         Ok(Self {
             runtime,
             manage_tx: Some(manage_tx),
+            snapshot,
         })
     }
 
@@ -226,6 +261,11 @@ impl RouteManager {
         }
     }
 
+    /// Removes the given routes, if they were previously added.
+    pub async fn remove_routes(&mut self, routes: HashSet<RequiredRoute>) -> Result<(), Error> {
+        self.handle()?.remove_routes(routes).await
+    }
+
     /// Removes all routes previously applied in [`RouteManager::new`] or
     /// [`RouteManager::add_routes`].
     pub fn clear_routes(&mut self) -> Result<(), Error> {
@@ -239,6 +279,12 @@ impl RouteManager {
         }
     }
 
+    /// Removes the routes that were added by a previous instance of the route manager.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub async fn remove_orphaned_routes(&mut self) -> Result<Vec<Route>, Error> {
+        self.handle()?.remove_orphaned_routes().await
+    }
+
     /// Ensure that packets are routed using the correct tables.
     #[cfg(target_os = "linux")]
     pub async fn create_routing_rules(&mut self, enable_ipv6: bool) -> Result<(), Error> {
@@ -254,7 +300,10 @@ impl RouteManager {
     /// Retrieve a sender directly to the command channel.
     pub fn handle(&self) -> Result<RouteManagerHandle, Error> {
         if let Some(tx) = &self.manage_tx {
-            Ok(RouteManagerHandle { tx: tx.clone() })
+            Ok(RouteManagerHandle {
+                tx: tx.clone(),
+                snapshot: self.snapshot.clone(),
+            })
         } else {
             Err(Error::RouteManagerDown)
         }
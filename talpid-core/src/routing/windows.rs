@@ -1,13 +1,17 @@
-use super::NetNode;
+use super::{CallbackMessage, NetNode, Node, Route, RouteSnapshot, SharedSnapshot};
 use crate::{routing::RequiredRoute, winnet};
 use futures::{
     channel::{
         mpsc::{self, UnboundedReceiver, UnboundedSender},
         oneshot,
     },
+    stream::Stream,
     StreamExt,
 };
-use std::collections::HashSet;
+use ipnetwork::IpNetwork;
+use std::{collections::HashSet, io, net::IpAddr};
+use talpid_windows_net::{try_socketaddr_from_inet_sockaddr, Luid, NotificationType};
+use winapi::shared::netioapi::MIB_IPFORWARD_ROW2;
 
 /// Windows routing errors.
 #[derive(err_derive::Error, Debug)]
@@ -21,9 +25,15 @@ pub enum Error {
     /// Failure to add routes
     #[error(display = "Failed to add routes")]
     AddRoutesFailed(#[error(source)] winnet::Error),
+    /// Failure to remove routes
+    #[error(display = "Failed to remove routes")]
+    RemoveRoutesFailed,
     /// Failure to clear routes
     #[error(display = "Failed to clear applied routes")]
     ClearRoutesFailed,
+    /// Failure to listen for route changes
+    #[error(display = "Failed to register for route change notifications")]
+    RouteChangeListenerFailed(#[error(source)] io::Error),
     /// WinNet returned an error while adding default route callback
     #[error(display = "Failed to set callback for default route")]
     FailedToAddDefaultRouteCallback,
@@ -38,15 +48,24 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub struct RouteManager {
     runtime: tokio::runtime::Handle,
     manage_tx: Option<UnboundedSender<RouteManagerCommand>>,
+    snapshot: SharedSnapshot,
 }
 
 /// Handle to a route manager.
 #[derive(Clone)]
 pub struct RouteManagerHandle {
     tx: UnboundedSender<RouteManagerCommand>,
+    snapshot: SharedSnapshot,
 }
 
 impl RouteManagerHandle {
+    /// Returns the routes that the route manager has applied, as of the last command that it
+    /// completed. This does not read the routing table, so it does not reflect changes that were
+    /// made by others.
+    pub fn snapshot(&self) -> RouteSnapshot {
+        self.snapshot.get()
+    }
+
     /// Applies the given routes while the route manager is running.
     pub async fn add_routes(&self, routes: HashSet<RequiredRoute>) -> Result<()> {
         let (response_tx, response_rx) = oneshot::channel();
@@ -55,11 +74,32 @@ impl RouteManagerHandle {
             .map_err(|_| Error::RouteManagerDown)?;
         response_rx.await.map_err(|_| Error::ManagerChannelDown)?
     }
+
+    /// Removes the given routes, if they were previously added with [`Self::add_routes`] or when
+    /// the route manager was created. Other routes are left in place.
+    pub async fn remove_routes(&self, routes: HashSet<RequiredRoute>) -> Result<()> {
+        let (response_tx, response_rx) = oneshot::channel();
+        self.tx
+            .unbounded_send(RouteManagerCommand::RemoveRoutes(routes, response_tx))
+            .map_err(|_| Error::RouteManagerDown)?;
+        response_rx.await.map_err(|_| Error::ManagerChannelDown)?
+    }
+
+    /// Listen for route changes.
+    pub async fn change_listener(&self) -> Result<impl Stream<Item = CallbackMessage>> {
+        let (response_tx, response_rx) = oneshot::channel();
+        self.tx
+            .unbounded_send(RouteManagerCommand::NewChangeListener(response_tx))
+            .map_err(|_| Error::RouteManagerDown)?;
+        response_rx.await.map_err(|_| Error::ManagerChannelDown)?
+    }
 }
 
 #[derive(Debug)]
 pub enum RouteManagerCommand {
     AddRoutes(HashSet<RequiredRoute>, oneshot::Sender<Result<()>>),
+    RemoveRoutes(HashSet<RequiredRoute>, oneshot::Sender<Result<()>>),
+    NewChangeListener(oneshot::Sender<Result<UnboundedReceiver<CallbackMessage>>>),
     Shutdown,
 }
 
@@ -74,11 +114,13 @@ impl RouteManager {
             return Err(Error::FailedToStartManager);
         }
         let (manage_tx, manage_rx) = mpsc::unbounded();
+        let snapshot = SharedSnapshot::default();
         let manager = Self {
             runtime: runtime.clone(),
             manage_tx: Some(manage_tx),
+            snapshot: snapshot.clone(),
         };
-        runtime.spawn(RouteManager::listen(manage_rx));
+        runtime.spawn(RouteManager::listen(manage_rx, snapshot));
         manager.add_routes(required_routes).await?;
 
         Ok(manager)
@@ -87,7 +129,10 @@ impl RouteManager {
     /// Retrieve a sender directly to the command channel.
     pub fn handle(&self) -> Result<RouteManagerHandle> {
         if let Some(tx) = &self.manage_tx {
-            Ok(RouteManagerHandle { tx: tx.clone() })
+            Ok(RouteManagerHandle {
+                tx: tx.clone(),
+                snapshot: self.snapshot.clone(),
+            })
         } else {
             Err(Error::RouteManagerDown)
         }
@@ -98,35 +143,111 @@ impl RouteManager {
         self.runtime.clone()
     }
 
-    async fn listen(mut manage_rx: UnboundedReceiver<RouteManagerCommand>) {
-        while let Some(command) = manage_rx.next().await {
-            match command {
-                RouteManagerCommand::AddRoutes(routes, tx) => {
-                    let routes: Vec<_> = routes
-                        .iter()
-                        .map(|route| {
-                            let destination = winnet::WinNetIpNetwork::from(route.prefix);
-                            match &route.node {
-                                NetNode::DefaultNode => {
-                                    winnet::WinNetRoute::through_default_node(destination)
+    async fn listen(
+        mut manage_rx: UnboundedReceiver<RouteManagerCommand>,
+        snapshot: SharedSnapshot,
+    ) {
+        let mut listeners: Vec<UnboundedSender<CallbackMessage>> = vec![];
+        // Only registered once someone is interested in route changes
+        let mut route_notifier = None;
+        let (route_change_tx, mut route_change_rx) = mpsc::unbounded();
+
+        loop {
+            futures::select! {
+                command = manage_rx.next() => match command {
+                    Some(RouteManagerCommand::AddRoutes(routes, tx)) => {
+                        let winnet_routes = Self::to_winnet_routes(&routes);
+                        let result = winnet::routing_manager_add_routes(&winnet_routes)
+                            .map_err(Error::AddRoutesFailed);
+                        if result.is_ok() {
+                            Self::update_snapshot(&snapshot, &routes, true);
+                        }
+                        let _ = tx.send(result);
+                    }
+                    Some(RouteManagerCommand::RemoveRoutes(routes, tx)) => {
+                        let winnet_routes = Self::to_winnet_routes(&routes);
+                        let _ = tx.send(if winnet::routing_manager_delete_routes(&winnet_routes) {
+                            Self::update_snapshot(&snapshot, &routes, false);
+                            Ok(())
+                        } else {
+                            Err(Error::RemoveRoutesFailed)
+                        });
+                    }
+                    Some(RouteManagerCommand::NewChangeListener(tx)) => {
+                        if route_notifier.is_none() {
+                            let route_change_tx = route_change_tx.clone();
+                            match talpid_windows_net::notify_route_change(
+                                move |row, notification_type| {
+                                    let _ = route_change_tx
+                                        .unbounded_send((route_from_row(row), notification_type));
+                                },
+                                None,
+                            ) {
+                                Ok(notifier) => route_notifier = Some(notifier),
+                                Err(error) => {
+                                    let _ = tx.send(Err(Error::RouteChangeListenerFailed(error)));
+                                    continue;
                                 }
-                                NetNode::RealNode(node) => winnet::WinNetRoute::new(
-                                    winnet::WinNetNode::from(node),
-                                    destination,
-                                ),
                             }
-                        })
-                        .collect();
+                        }
+                        let (listener_tx, listener_rx) = mpsc::unbounded();
+                        listeners.push(listener_tx);
+                        let _ = tx.send(Ok(listener_rx));
+                    }
+                    Some(RouteManagerCommand::Shutdown) | None => {
+                        break;
+                    }
+                },
+                change = route_change_rx.select_next_some() => {
+                    let message = match change {
+                        (Some(route), NotificationType::Add) => CallbackMessage::NewRoute(route),
+                        (Some(route), NotificationType::Delete) => CallbackMessage::DelRoute(route),
+                        _ => continue,
+                    };
+                    listeners.retain(|listener| listener.unbounded_send(message.clone()).is_ok());
+                }
+            }
+        }
+    }
 
-                    let _ = tx.send(
-                        winnet::routing_manager_add_routes(&routes).map_err(Error::AddRoutesFailed),
-                    );
+    /// Adds the given routes to the snapshot, or removes them from it.
+    fn update_snapshot(snapshot: &SharedSnapshot, routes: &HashSet<RequiredRoute>, add: bool) {
+        let mut current = snapshot.get();
+        for route in routes {
+            match &route.node {
+                NetNode::DefaultNode => {
+                    current
+                        .default_node_destinations
+                        .retain(|destination| *destination != route.prefix);
+                    if add {
+                        current.default_node_destinations.push(route.prefix);
+                    }
                 }
-                RouteManagerCommand::Shutdown => {
-                    break;
+                NetNode::RealNode(node) => {
+                    let route = Route::new(node.clone(), route.prefix);
+                    current.routes.retain(|applied| *applied != route);
+                    if add {
+                        current.routes.push(route);
+                    }
                 }
             }
         }
+        snapshot.publish(current);
+    }
+
+    fn to_winnet_routes(routes: &HashSet<RequiredRoute>) -> Vec<winnet::WinNetRoute> {
+        routes
+            .iter()
+            .map(|route| {
+                let destination = winnet::WinNetIpNetwork::from(route.prefix);
+                match &route.node {
+                    NetNode::DefaultNode => winnet::WinNetRoute::through_default_node(destination),
+                    NetNode::RealNode(node) => {
+                        winnet::WinNetRoute::new(winnet::WinNetNode::from(node), destination)
+                    }
+                }
+            })
+            .collect()
     }
 
     /// Sets a callback that is called whenever the default route changes.
@@ -151,6 +272,7 @@ impl RouteManager {
             }
 
             winnet::deactivate_routing_manager();
+            self.snapshot.publish(RouteSnapshot::default());
         }
     }
 
@@ -170,10 +292,16 @@ impl RouteManager {
         }
     }
 
+    /// Removes the given routes, if they were previously added.
+    pub async fn remove_routes(&self, routes: HashSet<RequiredRoute>) -> Result<()> {
+        self.handle()?.remove_routes(routes).await
+    }
+
     /// Removes all routes previously applied in [`RouteManager::new`] or
     /// [`RouteManager::add_routes`].
     pub fn clear_routes(&self) -> Result<()> {
         if winnet::routing_manager_delete_applied_routes() {
+            self.snapshot.publish(RouteSnapshot::default());
             Ok(())
         } else {
             Err(Error::ClearRoutesFailed)
@@ -186,3 +314,28 @@ impl Drop for RouteManager {
         self.stop();
     }
 }
+
+/// Converts a row from the routing table to a route. Returns `None` if the row contains an address
+/// of an unknown family.
+fn route_from_row(row: &MIB_IPFORWARD_ROW2) -> Option<Route> {
+    let destination = try_socketaddr_from_inet_sockaddr(row.DestinationPrefix.Prefix)
+        .ok()?
+        .ip();
+    let prefix = IpNetwork::new(destination, row.DestinationPrefix.PrefixLength).ok()?;
+    let next_hop = try_socketaddr_from_inet_sockaddr(row.NextHop)
+        .ok()
+        .map(|address| address.ip())
+        .filter(|address: &IpAddr| !address.is_unspecified());
+    let device = Luid::from(row.InterfaceLuid)
+        .alias()
+        .ok()
+        .map(|alias| alias.to_string_lossy().into_owned());
+
+    let node = match (next_hop, device) {
+        (Some(next_hop), Some(device)) => Node::new(next_hop, device),
+        (Some(next_hop), None) => Node::address(next_hop),
+        (None, Some(device)) => Node::device(device),
+        (None, None) => return None,
+    };
+    Some(Route::new(node, prefix))
+}
@@ -1,13 +1,24 @@
+use parking_lot::Mutex;
 use std::{
+    collections::HashSet,
     env, fs,
     io::{self, BufRead, BufReader, BufWriter, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::{Arc, Once},
+    thread,
+    time::Duration,
+};
+use talpid_types::{
+    cgroup::{find_net_cls_mount, SPLIT_TUNNEL_CGROUP_NAME},
+    ErrorExt,
 };
-use talpid_types::cgroup::{find_net_cls_mount, SPLIT_TUNNEL_CGROUP_NAME};
 
 const DEFAULT_NET_CLS_DIR: &str = "/sys/fs/cgroup/net_cls";
 const NET_CLS_DIR_OVERRIDE_ENV_VAR: &str = "TALPID_NET_CLS_MOUNT_DIR";
 
+/// How often running processes are checked against the excluded applications.
+const APP_SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
 /// Identifies packets coming from the cgroup.
 /// This should be an arbitrary but unique integer.
 pub const NET_CLS_CLASSID: u32 = 0x4d9f41;
@@ -46,11 +57,17 @@ pub enum Error {
     /// Unable to read /proc/mounts
     #[error(display = "Failed to read /proc/mounts")]
     ListMounts(#[error(source)] io::Error),
+
+    /// Unable to list running processes.
+    #[error(display = "Failed to list processes in /proc")]
+    ListProcesses(#[error(source)] io::Error),
 }
 
 /// Manages PIDs to exclude from the tunnel.
 pub struct PidManager {
     net_cls_path: PathBuf,
+    excluded_apps: Arc<Mutex<HashSet<PathBuf>>>,
+    start_app_scanner: Once,
 }
 
 impl PidManager {
@@ -58,6 +75,8 @@ impl PidManager {
     pub fn new() -> Result<PidManager, Error> {
         let manager = PidManager {
             net_cls_path: Self::create_cgroup()?,
+            excluded_apps: Arc::new(Mutex::new(HashSet::new())),
+            start_app_scanner: Once::new(),
         };
         manager.setup_exclusion_group()?;
         Ok(manager)
@@ -179,4 +198,89 @@ impl PidManager {
 
         Ok(())
     }
+
+    /// Replace the set of applications to exclude from the tunnel. Processes that run one of
+    /// the executables are added to the cgroup shortly after they are started, and their
+    /// children inherit the exclusion. Processes that are already excluded stay excluded when
+    /// their application is removed from the set.
+    pub fn set_paths<T: AsRef<Path>>(&self, paths: &[T]) -> Result<(), Error> {
+        // The executable of a process is always a resolved path
+        let paths: HashSet<PathBuf> = paths
+            .iter()
+            .map(|path| {
+                let path = path.as_ref();
+                fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+            })
+            .collect();
+        let is_empty = paths.is_empty();
+        *self.excluded_apps.lock() = paths;
+
+        if is_empty {
+            return Ok(());
+        }
+        self.start_app_scanner
+            .call_once(|| self.spawn_app_scanner());
+        exclude_app_processes(&self.net_cls_path, &self.excluded_apps.lock())
+    }
+
+    /// Periodically moves processes that run an excluded application to the cgroup, until the
+    /// `PidManager` is dropped.
+    fn spawn_app_scanner(&self) {
+        let net_cls_path = self.net_cls_path.clone();
+        let excluded_apps = Arc::downgrade(&self.excluded_apps);
+
+        thread::spawn(move || loop {
+            thread::sleep(APP_SCAN_INTERVAL);
+
+            let excluded_apps = match excluded_apps.upgrade() {
+                Some(excluded_apps) => excluded_apps.lock().clone(),
+                None => return,
+            };
+            if excluded_apps.is_empty() {
+                continue;
+            }
+            if let Err(error) = exclude_app_processes(&net_cls_path, &excluded_apps) {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to exclude application processes")
+                );
+            }
+        });
+    }
+}
+
+/// Adds every running process whose executable is one of `apps` to the cgroup.
+fn exclude_app_processes(net_cls_path: &Path, apps: &HashSet<PathBuf>) -> Result<(), Error> {
+    let exclusions_path = net_cls_path
+        .join(SPLIT_TUNNEL_CGROUP_NAME)
+        .join("cgroup.procs");
+    let excluded_pids = fs::read_to_string(&exclusions_path).map_err(Error::ListCGroupPids)?;
+    let excluded_pids: HashSet<&str> = excluded_pids.lines().collect();
+
+    for entry in fs::read_dir("/proc").map_err(Error::ListProcesses)? {
+        let entry = entry.map_err(Error::ListProcesses)?;
+        let pid = entry.file_name();
+        let pid = match pid.to_str() {
+            Some(pid) if pid.bytes().all(|byte| byte.is_ascii_digit()) => pid,
+            _ => continue,
+        };
+        if excluded_pids.contains(pid) {
+            continue;
+        }
+        // Kernel threads have no executable, and other processes may exit at any time
+        let exe = match fs::read_link(entry.path().join("exe")) {
+            Ok(exe) => exe,
+            Err(_) => continue,
+        };
+        if !apps.contains(&exe) {
+            continue;
+        }
+
+        log::debug!("Excluding {} (PID {}) from the tunnel", exe.display(), pid);
+        // Each write may only contain a single PID
+        if let Err(error) = fs::write(&exclusions_path, pid) {
+            log::debug!("Failed to exclude PID {}: {}", pid, error);
+        }
+    }
+    Ok(())
 }
@@ -4,12 +4,11 @@ mod windows;
 
 use crate::{
     tunnel::TunnelMetadata,
-    tunnel_state_machine::TunnelCommand,
+    tunnel_state_machine::{TunnelCommand, TunnelCommandSender},
     winnet::{
         self, get_best_default_route, interface_luid_to_ip, WinNetAddrFamily, WinNetCallbackHandle,
     },
 };
-use futures::channel::mpsc;
 use std::{
     convert::TryFrom,
     ffi::{OsStr, OsString},
@@ -94,7 +93,7 @@ pub struct SplitTunnel {
     event_thread: Option<std::thread::JoinHandle<()>>,
     quit_event: RawHandle,
     _route_change_callback: Option<WinNetCallbackHandle>,
-    daemon_tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
+    daemon_tx: Weak<TunnelCommandSender>,
 }
 
 enum Request {
@@ -120,7 +119,7 @@ unsafe impl Send for EventThreadContext {}
 
 impl SplitTunnel {
     /// Initialize the driver.
-    pub fn new(daemon_tx: Weak<mpsc::UnboundedSender<TunnelCommand>>) -> Result<Self, Error> {
+    pub fn new(daemon_tx: Weak<TunnelCommandSender>) -> Result<Self, Error> {
         let (request_tx, handle) = Self::spawn_request_thread()?;
 
         let mut event_overlapped: OVERLAPPED = unsafe { mem::zeroed() };
@@ -490,7 +489,7 @@ impl Drop for SplitTunnel {
 
 struct SplitTunnelDefaultRouteChangeHandlerContext {
     request_tx: RequestTx,
-    pub daemon_tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
+    pub daemon_tx: Weak<TunnelCommandSender>,
     pub tunnel_ipv4: Option<Ipv4Addr>,
     pub tunnel_ipv6: Option<Ipv6Addr>,
     pub internet_ipv4: Option<Ipv4Addr>,
@@ -500,7 +499,7 @@ struct SplitTunnelDefaultRouteChangeHandlerContext {
 impl SplitTunnelDefaultRouteChangeHandlerContext {
     pub fn new(
         request_tx: RequestTx,
-        daemon_tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
+        daemon_tx: Weak<TunnelCommandSender>,
         tunnel_ipv4: Option<Ipv4Addr>,
         tunnel_ipv6: Option<Ipv6Addr>,
     ) -> Self {
@@ -564,7 +563,9 @@ unsafe extern "system" fn split_tunnel_default_route_change_handler(
     let daemon_tx = ctx.daemon_tx.upgrade();
     let maybe_send = move |content| {
         if let Some(tx) = daemon_tx {
-            let _ = tx.unbounded_send(content);
+            if tx.try_send(content).is_err() {
+                log::error!("Failed to send command to the tunnel state machine");
+            }
         }
     };
 
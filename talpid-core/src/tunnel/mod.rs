@@ -1,13 +1,17 @@
 use self::tun_provider::TunProvider;
-use crate::{logging, routing::RouteManager};
+use crate::{connection_trace::ConnectionTrace, logging, routing::RouteManager};
 use std::{
+    borrow::Cow,
     io,
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
     path::{Path, PathBuf},
 };
 #[cfg(not(target_os = "android"))]
 use talpid_types::net::openvpn as openvpn_types;
-use talpid_types::net::{wireguard as wireguard_types, TunnelParameters};
+use talpid_types::{
+    cancel::CancelToken,
+    net::{wireguard as wireguard_types, Ipv6FailurePolicy, TunnelParameters},
+};
 
 #[cfg(target_os = "android")]
 pub use self::tun_provider::TunConfig;
@@ -22,6 +26,25 @@ pub mod wireguard;
 /// A module for low level platform specific tunnel device management.
 pub(crate) mod tun_provider;
 
+/// Traffic counters of the tunnel interface.
+mod traffic;
+pub use self::traffic::{traffic_counters, TrafficCounters, TunnelStats, TunnelStatsMonitor};
+
+/// Name of the tunnel interface that is used unless another name is set in
+/// [`talpid_types::net::GenericTunnelOptions::interface_name`]. On Linux, this is only used by the
+/// in-kernel WireGuard implementation, and on macOS the system picks a free utun device.
+#[cfg(target_os = "linux")]
+pub const DEFAULT_INTERFACE_NAME: Option<&str> =
+    Some(wireguard::wireguard_kernel::MULLVAD_INTERFACE_NAME);
+#[cfg(windows)]
+pub const DEFAULT_INTERFACE_NAME: Option<&str> = Some(DEFAULT_ADAPTER_ALIAS);
+#[cfg(not(any(target_os = "linux", windows)))]
+pub const DEFAULT_INTERFACE_NAME: Option<&str> = None;
+
+/// Alias of the tunnel adapter that is used unless another name is set.
+#[cfg(windows)]
+pub(crate) const DEFAULT_ADAPTER_ALIAS: &str = "Mullvad";
+
 const OPENVPN_LOG_FILENAME: &str = "openvpn.log";
 const WIREGUARD_LOG_FILENAME: &str = "wireguard.log";
 
@@ -72,6 +95,12 @@ pub enum TunnelEvent {
     InterfaceUp(TunnelMetadata),
     /// Sent when the tunnel comes up and is ready for traffic.
     Up(TunnelMetadata),
+    /// Sent when the addresses of the tunnel interface are not usable in time, because duplicate
+    /// address detection did not finish. Only sent on Windows.
+    AddressTimeout,
+    /// Sent when the estimated packet loss in the tunnel, in percent, has stayed above the
+    /// configured threshold for a while. Only sent for WireGuard tunnels.
+    PacketLoss(u8),
     /// Sent when the tunnel goes down.
     Down,
 }
@@ -107,6 +136,8 @@ impl TunnelMonitor {
         on_event: L,
         tun_provider: &mut TunProvider,
         route_manager: &mut RouteManager,
+        connection_trace: &ConnectionTrace,
+        cancel_token: &CancelToken,
     ) -> Result<Self>
     where
         L: (Fn(TunnelEvent) -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>>)
@@ -115,14 +146,19 @@ impl TunnelMonitor {
             + Sync
             + 'static,
     {
-        Self::ensure_ipv6_can_be_used_if_enabled(&tunnel_parameters)?;
+        let tunnel_parameters = Self::ensure_ipv6_can_be_used_if_enabled(tunnel_parameters)?;
         let log_file = Self::prepare_tunnel_log_file(&tunnel_parameters, log_dir)?;
 
-        match tunnel_parameters {
+        match &*tunnel_parameters {
             #[cfg(not(target_os = "android"))]
-            TunnelParameters::OpenVpn(config) => {
-                Self::start_openvpn_tunnel(&config, log_file, resource_dir, on_event, route_manager)
-            }
+            TunnelParameters::OpenVpn(config) => Self::start_openvpn_tunnel(
+                &config,
+                log_file,
+                resource_dir,
+                on_event,
+                route_manager,
+                cancel_token,
+            ),
             #[cfg(target_os = "android")]
             TunnelParameters::OpenVpn(_) => Err(Error::UnsupportedPlatform),
 
@@ -134,6 +170,8 @@ impl TunnelMonitor {
                 on_event,
                 tun_provider,
                 route_manager,
+                connection_trace,
+                cancel_token,
             ),
         }
     }
@@ -153,7 +191,13 @@ impl TunnelMonitor {
                     "openvpn.exe"
                 }
             }
-            _ => return std::env::current_exe().unwrap(),
+            TunnelParameters::Wireguard(params) => match params.connection.obfuscation {
+                Some(wireguard_types::ObfuscatorConfig::Shadowsocks { .. })
+                | Some(wireguard_types::ObfuscatorConfig::Bridge(
+                    openvpn_types::ProxySettings::Shadowsocks(..),
+                )) => "sslocal.exe",
+                _ => return std::env::current_exe().unwrap(),
+            },
         };
         resource_dir.join(process_string)
     }
@@ -166,6 +210,8 @@ impl TunnelMonitor {
         on_event: L,
         tun_provider: &mut TunProvider,
         route_manager: &mut RouteManager,
+        connection_trace: &ConnectionTrace,
+        cancel_token: &CancelToken,
     ) -> Result<Self>
     where
         L: (Fn(TunnelEvent) -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>>)
@@ -183,6 +229,8 @@ impl TunnelMonitor {
             on_event,
             tun_provider,
             route_manager,
+            connection_trace.clone(),
+            cancel_token,
         )?;
         Ok(TunnelMonitor {
             monitor: InternalTunnelMonitor::Wireguard(monitor),
@@ -196,6 +244,7 @@ impl TunnelMonitor {
         resource_dir: &Path,
         on_event: L,
         route_manager: &mut RouteManager,
+        cancel_token: &CancelToken,
     ) -> Result<Self>
     where
         L: (Fn(TunnelEvent) -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>>)
@@ -203,27 +252,40 @@ impl TunnelMonitor {
             + Sync
             + 'static,
     {
-        let monitor =
-            openvpn::OpenVpnMonitor::start(on_event, config, log, resource_dir, route_manager)?;
+        let monitor = openvpn::OpenVpnMonitor::start(
+            on_event,
+            config,
+            log,
+            resource_dir,
+            route_manager,
+            cancel_token,
+        )?;
         Ok(TunnelMonitor {
             monitor: InternalTunnelMonitor::OpenVpn(monitor),
         })
     }
 
-    fn ensure_ipv6_can_be_used_if_enabled(tunnel_parameters: &TunnelParameters) -> Result<()> {
+    /// Returns the parameters to start the tunnel with. If IPv6 is enabled but disabled in the OS,
+    /// the tunnel is started without IPv6 unless the IPv6 failure policy is to block.
+    fn ensure_ipv6_can_be_used_if_enabled(
+        tunnel_parameters: &TunnelParameters,
+    ) -> Result<Cow<'_, TunnelParameters>> {
         let options = tunnel_parameters.get_generic_options();
-        if options.enable_ipv6 {
-            if is_ipv6_enabled_in_os() {
-                Ok(())
-            } else {
-                Err(Error::EnableIpv6Error)
+        if !options.enable_ipv6 || is_ipv6_enabled_in_os() {
+            return Ok(Cow::Borrowed(tunnel_parameters));
+        }
+        match options.ipv6_failure_policy {
+            Ipv6FailurePolicy::Block => Err(Error::EnableIpv6Error),
+            Ipv6FailurePolicy::Ipv4Only => {
+                log::warn!("IPv6 is disabled in the OS. Connecting with IPv4 only");
+                let mut tunnel_parameters = tunnel_parameters.clone();
+                tunnel_parameters.get_generic_options_mut().enable_ipv6 = false;
+                Ok(Cow::Owned(tunnel_parameters))
             }
-        } else {
-            Ok(())
         }
     }
 
-    #[cfg(not(target_os = "windows"))]
+    /// Rotates the OpenVPN log. The WireGuard log is rotated when the tunnel starts logging to it.
     fn prepare_tunnel_log_file(
         parameters: &TunnelParameters,
         log_dir: &Option<PathBuf>,
@@ -242,24 +304,6 @@ impl TunnelMonitor {
         }
     }
 
-    #[cfg(target_os = "windows")]
-    fn prepare_tunnel_log_file(
-        parameters: &TunnelParameters,
-        log_dir: &Option<PathBuf>,
-    ) -> Result<Option<PathBuf>> {
-        if let Some(ref log_dir) = log_dir {
-            let filename = match parameters {
-                TunnelParameters::OpenVpn(_) => OPENVPN_LOG_FILENAME,
-                TunnelParameters::Wireguard(_) => WIREGUARD_LOG_FILENAME,
-            };
-            let tunnel_log = log_dir.join(filename);
-            logging::rotate_log(&tunnel_log)?;
-            Ok(Some(tunnel_log))
-        } else {
-            Ok(None)
-        }
-    }
-
     /// Creates a handle to this monitor, allowing the tunnel to be closed while some other
     /// thread
     /// is blocked in `wait`.
@@ -324,6 +368,18 @@ impl InternalTunnelMonitor {
 }
 
 
+/// Returns whether a network interface with the given name exists.
+#[cfg(unix)]
+pub fn interface_exists(name: &str) -> bool {
+    nix::net::if_::if_nametoindex(name).is_ok()
+}
+
+/// Returns whether a network interface with the given alias exists.
+#[cfg(windows)]
+pub fn interface_exists(name: &str) -> bool {
+    talpid_windows_net::Luid::from_alias(name).is_ok()
+}
+
 #[cfg(target_os = "windows")]
 fn is_ipv6_enabled_in_os() -> bool {
     use winreg::{enums::*, RegKey};
@@ -0,0 +1,84 @@
+//! Support for the ovpn-dco-win data channel offload driver, which moves the OpenVPN data channel
+//! into the kernel. Unlike Wintun adapters, which are created for each tunnel, the DCO adapter is
+//! created when the driver is installed and is reused by every tunnel.
+
+use std::{
+    ffi::{OsStr, OsString},
+    fmt, io,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use talpid_windows_net::Luid;
+use winreg::{enums::HKEY_LOCAL_MACHINE, RegKey};
+
+/// Registry key of the ovpn-dco driver service.
+const DRIVER_SERVICE_KEY: &str = r"SYSTEM\CurrentControlSet\Services\ovpn-dco";
+/// Alias of the adapter that is created when the driver is installed.
+const ADAPTER_ALIAS: &str = "OpenVPN Data Channel Offload";
+
+/// Set once OpenVPN has failed to set up a tunnel using the DCO adapter. Wintun is used for the
+/// remaining lifetime of the process.
+static DCO_FAILED: AtomicBool = AtomicBool::new(false);
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// The ovpn-dco driver is not installed.
+    #[error(display = "The ovpn-dco driver is not installed")]
+    DriverNotInstalled,
+
+    /// A previous tunnel using the DCO adapter failed.
+    #[error(display = "A previous tunnel using the DCO adapter failed")]
+    PreviouslyFailed,
+
+    /// The DCO adapter was not found.
+    #[error(display = "Failed to find the DCO adapter")]
+    AdapterNotFound(#[error(source)] io::Error),
+}
+
+/// The adapter of the ovpn-dco driver.
+pub struct DcoAdapter {
+    alias: OsString,
+    luid: Luid,
+}
+
+impl fmt::Debug for DcoAdapter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DcoAdapter")
+            .field("alias", &self.alias)
+            .field("luid", &self.luid)
+            .finish()
+    }
+}
+
+impl DcoAdapter {
+    /// Finds the DCO adapter, if the driver is installed and has not failed before.
+    pub fn open() -> Result<Self, Error> {
+        if DCO_FAILED.load(Ordering::SeqCst) {
+            return Err(Error::PreviouslyFailed);
+        }
+        if RegKey::predef(HKEY_LOCAL_MACHINE)
+            .open_subkey(DRIVER_SERVICE_KEY)
+            .is_err()
+        {
+            return Err(Error::DriverNotInstalled);
+        }
+        let alias = OsString::from(ADAPTER_ALIAS);
+        let luid = Luid::from_alias(&alias).map_err(Error::AdapterNotFound)?;
+        Ok(DcoAdapter { alias, luid })
+    }
+
+    pub fn alias(&self) -> &OsStr {
+        &self.alias
+    }
+
+    pub fn luid(&self) -> Luid {
+        self.luid
+    }
+}
+
+/// Stops DCO from being used by subsequent tunnels.
+pub fn disable_after_failure() {
+    if !DCO_FAILED.swap(true, Ordering::SeqCst) {
+        log::warn!("OpenVPN failed to use the DCO adapter. Falling back on Wintun");
+    }
+}
@@ -1,4 +1,6 @@
 use super::TunnelEvent;
+#[cfg(windows)]
+use crate::process::openvpn::WindowsDriver;
 #[cfg(target_os = "linux")]
 use crate::routing::RequiredRoute;
 use crate::{
@@ -28,23 +30,32 @@ use std::{
     thread,
     time::Duration,
 };
-use talpid_types::{net::openvpn, ErrorExt};
+#[cfg(windows)]
+use talpid_types::net::Ipv6FailurePolicy;
+use talpid_types::{cancel::CancelToken, net::openvpn, ErrorExt};
+#[cfg(windows)]
+use talpid_windows_net::Luid;
 use tokio::task;
 #[cfg(target_os = "linux")]
 use which;
 #[cfg(windows)]
 use widestring::U16CString;
 #[cfg(windows)]
-use winapi::shared::{guiddef::GUID, ifdef::NET_LUID};
+use winapi::shared::guiddef::GUID;
 
+#[cfg(windows)]
+mod dco;
 #[cfg(windows)]
 mod wintun;
 
 
 #[cfg(windows)]
 lazy_static! {
-    static ref ADAPTER_ALIAS: U16CString = U16CString::from_str("Mullvad").unwrap();
-    static ref ADAPTER_POOL: U16CString = U16CString::from_str("Mullvad").unwrap();
+    static ref DEFAULT_ADAPTER_ALIAS: U16CString =
+        U16CString::from_str(crate::tunnel::DEFAULT_ADAPTER_ALIAS).unwrap();
+    static ref ADAPTER_POOL: U16CString = U16CString::from_str("Mullvad OpenVPN").unwrap();
+    /// Pool that adapters were created in by older versions
+    static ref LEGACY_ADAPTER_POOL: U16CString = U16CString::from_str("Mullvad").unwrap();
 }
 
 #[cfg(windows)]
@@ -56,6 +67,13 @@ const ADAPTER_GUID: GUID = GUID {
 };
 
 
+/// Deletes the Wintun adapter that was kept when the last tunnel closed, if its policy is to
+/// delete it on shutdown.
+#[cfg(windows)]
+pub fn release_wintun_adapter() {
+    wintun::release_kept_adapter();
+}
+
 /// Results from fallible operations on the OpenVPN tunnel.
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -187,35 +205,43 @@ pub struct OpenVpnMonitor<C: OpenVpnBuilder = OpenVpnCommand> {
     child: Arc<Mutex<Option<Arc<C::ProcessHandle>>>>,
     proxy_monitor: Option<Box<dyn ProxyMonitor>>,
     closed: Arc<AtomicBool>,
+    /// Cancelled when the tunnel is closed or OpenVPN exits, so that event handlers that are
+    /// still waiting, such as for duplicate address detection, give up.
+    cancel_token: CancelToken,
     /// Keep the `TempFile` for the user-pass file in the struct, so it's removed on drop.
     _user_pass_file: mktemp::TempFile,
     /// Keep the 'TempFile' for the proxy user-pass file in the struct, so it's removed on drop.
     _proxy_auth_file: Option<mktemp::TempFile>,
+    /// Keep the `TempFile`s for the certificates and keys of a custom server in the struct, so
+    /// they're removed on drop.
+    _profile_files: ProfileFiles,
 
     runtime: tokio::runtime::Runtime,
     event_server_abort_tx: triggered::Trigger,
     server_join_handle: Option<task::JoinHandle<std::result::Result<(), event_server::Error>>>,
 
     #[cfg(windows)]
-    _wintun: Arc<Box<dyn WintunContext>>,
+    adapter: Arc<Box<dyn AdapterContext>>,
 }
 
 #[cfg(windows)]
 #[async_trait::async_trait]
-trait WintunContext: Send + Sync {
-    fn luid(&self) -> NET_LUID;
+trait AdapterContext: Send + Sync {
+    fn luid(&self) -> Luid;
     fn ipv6(&self) -> bool;
     async fn wait_for_interfaces(&self) -> io::Result<()>;
     fn disable_unused_features(&self) {}
+    /// Called when OpenVPN exits unexpectedly.
+    fn process_failed(&self) {}
 }
 
 #[cfg(windows)]
-impl std::fmt::Debug for dyn WintunContext {
+impl std::fmt::Debug for dyn AdapterContext {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "WintunContext {{ luid: {}, ipv6: {} }}",
-            self.luid().Value,
+            "AdapterContext {{ luid: {}, ipv6: {} }}",
+            self.luid(),
             self.ipv6()
         )
     }
@@ -224,15 +250,15 @@ impl std::fmt::Debug for dyn WintunContext {
 #[cfg(windows)]
 #[derive(Debug)]
 struct WintunContextImpl {
-    adapter: wintun::TemporaryWintunAdapter,
+    adapter: wintun::PooledWintunAdapter,
     wait_v6_interface: bool,
     _logger: wintun::WintunLoggerHandle,
 }
 
 #[cfg(windows)]
 #[async_trait::async_trait]
-impl WintunContext for WintunContextImpl {
-    fn luid(&self) -> NET_LUID {
+impl AdapterContext for WintunContextImpl {
+    fn luid(&self) -> Luid {
         self.adapter.adapter().luid()
     }
 
@@ -250,6 +276,39 @@ impl WintunContext for WintunContextImpl {
     }
 }
 
+#[cfg(windows)]
+#[derive(Debug)]
+struct DcoContextImpl {
+    adapter: dco::DcoAdapter,
+    wait_v6_interface: bool,
+    /// Set once the tunnel interface has come up.
+    interface_up: Arc<AtomicBool>,
+}
+
+#[cfg(windows)]
+#[async_trait::async_trait]
+impl AdapterContext for DcoContextImpl {
+    fn luid(&self) -> Luid {
+        self.adapter.luid()
+    }
+
+    fn ipv6(&self) -> bool {
+        self.wait_v6_interface
+    }
+
+    async fn wait_for_interfaces(&self) -> io::Result<()> {
+        let luid = self.adapter.luid();
+        crate::windows::wait_for_interfaces(luid, true, self.wait_v6_interface).await
+    }
+
+    fn process_failed(&self) {
+        // Failures after the tunnel has come up are unlikely to be caused by the driver.
+        if !self.interface_up.load(Ordering::SeqCst) {
+            dco::disable_after_failure();
+        }
+    }
+}
+
 
 impl OpenVpnMonitor<OpenVpnCommand> {
     /// Creates a new `OpenVpnMonitor` with the given listener and using the plugin at the given
@@ -261,6 +320,7 @@ impl OpenVpnMonitor<OpenVpnCommand> {
         resource_dir: &Path,
         #[cfg(target_os = "linux")] route_manager: &mut routing::RouteManager,
         #[cfg(not(target_os = "linux"))] _route_manager: &mut routing::RouteManager,
+        cancel_token: &CancelToken,
     ) -> Result<Self>
     where
         L: (Fn(TunnelEvent) -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>>)
@@ -273,6 +333,12 @@ impl OpenVpnMonitor<OpenVpnCommand> {
                 .map_err(Error::CredentialsWriteError)?;
         let proxy_auth_file =
             Self::create_proxy_auth_file(&params.proxy).map_err(Error::CredentialsWriteError)?;
+        let profile_files = match params.config.profile {
+            Some(ref profile) => {
+                ProfileFiles::create(profile).map_err(Error::CredentialsWriteError)?
+            }
+            None => ProfileFiles::default(),
+        };
         let user_pass_file_path = user_pass_file.to_path_buf();
         let proxy_auth_file_path = match proxy_auth_file {
             Some(ref file) => Some(file.to_path_buf()),
@@ -293,24 +359,10 @@ impl OpenVpnMonitor<OpenVpnCommand> {
         let proxy_monitor = Self::start_proxy(&params.proxy, &proxy_resources)?;
 
         #[cfg(windows)]
-        let dll = wintun::WintunDll::instance(resource_dir).map_err(Error::WintunDllError)?;
+        let interface_up = Arc::new(AtomicBool::new(false));
         #[cfg(windows)]
-        let wintun_logger = dll.activate_logging();
-
-        #[cfg(windows)]
-        let (wintun_adapter, _reboot_required) = wintun::TemporaryWintunAdapter::create(
-            dll.clone(),
-            &*ADAPTER_ALIAS,
-            &*ADAPTER_POOL,
-            Some(ADAPTER_GUID.clone()),
-        )
-        .map_err(Error::WintunCreateAdapterError)?;
-
-        #[cfg(windows)]
-        let adapter_alias = wintun_adapter
-            .adapter()
-            .name()
-            .map_err(Error::WintunFindAlias)?;
+        let (adapter, adapter_alias, windows_driver) =
+            Self::create_adapter(params, resource_dir, interface_up.clone())?;
         #[cfg(windows)]
         log::debug!("Adapter alias: {}", adapter_alias.to_string_lossy());
 
@@ -318,10 +370,13 @@ impl OpenVpnMonitor<OpenVpnCommand> {
             params,
             user_pass_file.as_ref(),
             proxy_auth_file.as_ref().map(AsRef::as_ref),
+            &profile_files,
             resource_dir,
             &proxy_monitor,
             #[cfg(windows)]
-            adapter_alias.to_os_string(),
+            adapter_alias,
+            #[cfg(windows)]
+            windows_driver,
         )?;
 
         let plugin_path = Self::get_plugin_path(resource_dir)?;
@@ -332,6 +387,15 @@ impl OpenVpnMonitor<OpenVpnCommand> {
         let route_manager_handle = route_manager.handle().map_err(Error::SetupRoutingError)?;
 
         let (event_server_abort_tx, event_server_abort_rx) = triggered::trigger();
+        let cancel_token = cancel_token.child();
+
+        #[cfg(windows)]
+        let on_event = move |event: TunnelEvent| {
+            if let TunnelEvent::InterfaceUp(..) = event {
+                interface_up.store(true, Ordering::SeqCst);
+            }
+            on_event(event)
+        };
 
         Self::new_internal(
             cmd,
@@ -342,23 +406,89 @@ impl OpenVpnMonitor<OpenVpnCommand> {
                 user_pass_file_path: user_pass_file_path.clone(),
                 proxy_auth_file_path: proxy_auth_file_path.clone(),
                 abort_server_tx: event_server_abort_tx,
+                cancel_token: cancel_token.clone(),
                 #[cfg(target_os = "linux")]
                 route_manager_handle,
                 #[cfg(target_os = "linux")]
                 ipv6_enabled,
+                #[cfg(windows)]
+                ipv6_optional: params.generic_options.ipv6_failure_policy
+                    == Ipv6FailurePolicy::Ipv4Only,
             },
             plugin_path,
             log_path,
             user_pass_file,
             proxy_auth_file,
+            profile_files,
             proxy_monitor,
+            cancel_token,
             #[cfg(windows)]
-            Box::new(WintunContextImpl {
-                adapter: wintun_adapter,
-                wait_v6_interface: params.generic_options.enable_ipv6,
-                _logger: wintun_logger,
-            }),
+            adapter,
+        )
+    }
+
+    /// Opens the DCO adapter if it is enabled and available, and otherwise reuses or creates a
+    /// Wintun adapter.
+    #[cfg(windows)]
+    fn create_adapter(
+        params: &openvpn::TunnelParameters,
+        resource_dir: &Path,
+        interface_up: Arc<AtomicBool>,
+    ) -> Result<(Box<dyn AdapterContext>, OsString, WindowsDriver)> {
+        let wait_v6_interface = params.generic_options.enable_ipv6;
+
+        if params.options.use_ovpn_dco {
+            match dco::DcoAdapter::open() {
+                Ok(adapter) => {
+                    let alias = adapter.alias().to_os_string();
+                    let context = DcoContextImpl {
+                        adapter,
+                        wait_v6_interface,
+                        interface_up,
+                    };
+                    return Ok((Box::new(context), alias, WindowsDriver::OvpnDco));
+                }
+                Err(error) => log::warn!(
+                    "{}",
+                    error.display_chain_with_msg("Using Wintun instead of the DCO adapter")
+                ),
+            }
+        }
+
+        let adapter_alias = match &params.generic_options.interface_name {
+            Some(name) => U16CString::from_str(name).map_err(|_| {
+                Error::WintunCreateAdapterError(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Adapter alias contains a null character",
+                ))
+            })?,
+            None => DEFAULT_ADAPTER_ALIAS.clone(),
+        };
+
+        let dll = wintun::WintunDll::instance(resource_dir).map_err(Error::WintunDllError)?;
+        let wintun_logger = dll.activate_logging();
+
+        wintun::delete_abandoned_adapter(dll.clone(), &*LEGACY_ADAPTER_POOL, &adapter_alias);
+        let (wintun_adapter, _reboot_required) = wintun::PooledWintunAdapter::acquire(
+            dll.clone(),
+            &*ADAPTER_POOL,
+            &adapter_alias,
+            Some(ADAPTER_GUID.clone()),
+            params.options.wintun_adapter_policy,
         )
+        .map_err(Error::WintunCreateAdapterError)?;
+
+        let alias = wintun_adapter
+            .adapter()
+            .name()
+            .map_err(Error::WintunFindAlias)?
+            .to_os_string();
+        let context = WintunContextImpl {
+            adapter: wintun_adapter,
+            wait_v6_interface,
+            _logger: wintun_logger,
+        };
+        Ok((Box::new(context), alias, WindowsDriver::Wintun))
     }
 }
 
@@ -383,8 +513,10 @@ impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
         log_path: Option<PathBuf>,
         user_pass_file: mktemp::TempFile,
         proxy_auth_file: Option<mktemp::TempFile>,
+        profile_files: ProfileFiles,
         proxy_monitor: Option<Box<dyn ProxyMonitor>>,
-        #[cfg(windows)] wintun: Box<dyn WintunContext>,
+        cancel_token: CancelToken,
+        #[cfg(windows)] adapter: Box<dyn AdapterContext>,
     ) -> Result<OpenVpnMonitor<C>>
     where
         L: event_server::OpenvpnEventProxy + Send + Sync + 'static,
@@ -418,14 +550,14 @@ impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
         }
 
         #[cfg(windows)]
-        let wintun = Arc::new(wintun);
+        let adapter = Arc::new(adapter);
 
         cmd.plugin(plugin_path, vec![ipc_path])
             .log(log_path.as_ref().map(|p| p.as_path()));
         let (spawn_task, abort_spawn) = futures::future::abortable(Self::prepare_process(
             cmd,
             #[cfg(windows)]
-            wintun.clone(),
+            adapter.clone(),
         ));
         let spawn_task = runtime.spawn(spawn_task);
 
@@ -435,27 +567,29 @@ impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
             child: Arc::new(Mutex::new(None)),
             proxy_monitor,
             closed: Arc::new(AtomicBool::new(false)),
+            cancel_token,
             _user_pass_file: user_pass_file,
             _proxy_auth_file: proxy_auth_file,
+            _profile_files: profile_files,
 
             runtime,
             event_server_abort_tx,
             server_join_handle: Some(server_join_handle),
 
             #[cfg(windows)]
-            _wintun: wintun,
+            adapter,
         })
     }
 
     async fn prepare_process(
         cmd: C,
-        #[cfg(windows)] wintun: Arc<Box<dyn WintunContext>>,
+        #[cfg(windows)] adapter: Arc<Box<dyn AdapterContext>>,
     ) -> io::Result<C::ProcessHandle> {
         #[cfg(windows)]
         {
             log::debug!("Wait for IP interfaces");
-            wintun.wait_for_interfaces().await?;
-            wintun.disable_unused_features();
+            adapter.wait_for_interfaces().await?;
+            adapter.disable_unused_features();
         }
         cmd.start()
     }
@@ -467,6 +601,7 @@ impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
             child: self.child.clone(),
             abort_spawn: self.abort_spawn.clone(),
             closed: self.closed.clone(),
+            cancel_token: self.cancel_token.clone(),
         }
     }
 
@@ -519,6 +654,8 @@ impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
 
     /// Supplement `inner_wait_tunnel()` with logging and error handling.
     fn wait_tunnel(self) -> Result<()> {
+        #[cfg(windows)]
+        let adapter = self.adapter.clone();
         let result = self.inner_wait_tunnel();
         match result {
             WaitResult::Preparation(result) => match result {
@@ -540,6 +677,8 @@ impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
                     Ok(())
                 } else {
                     log::error!("OpenVPN died unexpectedly with status: {}", exit_status);
+                    #[cfg(windows)]
+                    adapter.process_failed();
                     Err(Error::ChildProcessDied)
                 }
             }
@@ -586,11 +725,14 @@ impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
         let dispatcher_tx = child_tx.clone();
 
         let event_server_abort_tx = self.event_server_abort_tx.clone();
+        let cancel_token = self.cancel_token.clone();
 
         thread::spawn(move || {
             let result = child.wait();
             let closed = closed_handle.load(Ordering::SeqCst);
             child_tx.send(WaitResult::Child(result, closed)).unwrap();
+            // The event server only shuts down once the requests it is handling are done
+            cancel_token.cancel();
             event_server_abort_tx.trigger();
         });
 
@@ -640,24 +782,12 @@ impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
         let temp_file = mktemp::TempFile::new();
         log::debug!("Writing credentials to {}", temp_file.as_ref().display());
         let mut file = fs::File::create(&temp_file)?;
-        Self::set_user_pass_file_permissions(&file)?;
+        set_user_pass_file_permissions(&file)?;
         write!(file, "{}\n{}\n", username, password)?;
         Ok(temp_file)
     }
 
 
-    #[cfg(unix)]
-    fn set_user_pass_file_permissions(file: &fs::File) -> io::Result<()> {
-        use std::os::unix::fs::PermissionsExt;
-        file.set_permissions(PermissionsExt::from_mode(0o400))
-    }
-
-    #[cfg(windows)]
-    fn set_user_pass_file_permissions(_file: &fs::File) -> io::Result<()> {
-        // TODO(linus): Lock permissions correctly on Windows.
-        Ok(())
-    }
-
     fn get_plugin_path(resource_dir: &Path) -> Result<PathBuf> {
         let path = resource_dir.join(OPENVPN_PLUGIN_FILENAME);
         if path.exists() {
@@ -672,9 +802,11 @@ impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
         params: &openvpn::TunnelParameters,
         user_pass_file: &Path,
         proxy_auth_file: Option<&Path>,
+        profile_files: &ProfileFiles,
         resource_dir: &Path,
         proxy_monitor: &Option<Box<dyn ProxyMonitor>>,
         #[cfg(windows)] alias: OsString,
+        #[cfg(windows)] windows_driver: WindowsDriver,
     ) -> Result<OpenVpnCommand> {
         let mut cmd = OpenVpnCommand::new(Self::get_openvpn_bin(resource_dir)?);
         if let Some(config) = Self::get_config_path(resource_dir) {
@@ -683,14 +815,38 @@ impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
         #[cfg(target_os = "linux")]
         cmd.iproute_bin(which::which("ip").map_err(Error::IpRouteNotFound)?);
         cmd.remote(params.config.endpoint)
-            .user_pass(user_pass_file)
             .tunnel_options(&params.options)
-            .enable_ipv6(params.generic_options.enable_ipv6)
-            .ca(resource_dir.join("ca.crt"));
+            .enable_ipv6(params.generic_options.enable_ipv6);
+        if let Some(timeout) = params.config.handshake_timeout {
+            cmd.handshake_timeout(timeout);
+        }
+        match params.config.profile {
+            Some(ref profile) => {
+                cmd.custom_server(true);
+                if profile.auth_user_pass {
+                    cmd.user_pass(user_pass_file);
+                }
+                if let Some(ref data_ciphers) = profile.data_ciphers {
+                    cmd.data_ciphers(data_ciphers.as_str());
+                }
+                if let Some(ref auth) = profile.auth {
+                    cmd.auth_digest(auth.as_str());
+                }
+                profile_files.apply(profile, &mut cmd);
+            }
+            None => {
+                cmd.user_pass(user_pass_file)
+                    .ca(resource_dir.join("ca.crt"));
+            }
+        }
         #[cfg(windows)]
         {
             cmd.tunnel_alias(Some(alias));
-            cmd.windows_driver(Some(crate::process::openvpn::WindowsDriver::Wintun));
+            cmd.windows_driver(Some(windows_driver));
+        }
+        #[cfg(not(windows))]
+        if let Some(ref interface_name) = params.generic_options.interface_name {
+            cmd.interface_name(interface_name);
         }
         if let Some(proxy_settings) = params.proxy.clone().take() {
             cmd.proxy_settings(proxy_settings);
@@ -725,18 +881,86 @@ impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
     }
 }
 
+/// Temporary files holding the certificates and keys of a custom server profile.
+#[derive(Default)]
+struct ProfileFiles {
+    ca: Option<mktemp::TempFile>,
+    cert: Option<mktemp::TempFile>,
+    key: Option<mktemp::TempFile>,
+    tls_auth: Option<mktemp::TempFile>,
+    tls_crypt: Option<mktemp::TempFile>,
+}
+
+impl ProfileFiles {
+    fn create(profile: &openvpn::ServerProfile) -> io::Result<Self> {
+        Ok(ProfileFiles {
+            ca: Some(Self::create_file(&profile.ca)?),
+            cert: profile.cert.as_deref().map(Self::create_file).transpose()?,
+            key: profile.key.as_deref().map(Self::create_file).transpose()?,
+            tls_auth: profile
+                .tls_auth
+                .as_deref()
+                .map(Self::create_file)
+                .transpose()?,
+            tls_crypt: profile
+                .tls_crypt
+                .as_deref()
+                .map(Self::create_file)
+                .transpose()?,
+        })
+    }
+
+    fn create_file(contents: &str) -> io::Result<mktemp::TempFile> {
+        let temp_file = mktemp::TempFile::new();
+        log::debug!("Writing server profile to {}", temp_file.as_ref().display());
+        let mut file = fs::File::create(&temp_file)?;
+        set_user_pass_file_permissions(&file)?;
+        file.write_all(contents.as_bytes())?;
+        Ok(temp_file)
+    }
+
+    fn apply(&self, profile: &openvpn::ServerProfile, cmd: &mut OpenVpnCommand) {
+        if let Some(ref ca) = self.ca {
+            cmd.ca(ca);
+        }
+        if let (Some(cert), Some(key)) = (&self.cert, &self.key) {
+            cmd.client_cert(cert, key);
+        }
+        if let Some(ref tls_auth) = self.tls_auth {
+            cmd.tls_auth(tls_auth, profile.key_direction);
+        }
+        if let Some(ref tls_crypt) = self.tls_crypt {
+            cmd.tls_crypt(tls_crypt);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn set_user_pass_file_permissions(file: &fs::File) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(PermissionsExt::from_mode(0o400))
+}
+
+#[cfg(windows)]
+fn set_user_pass_file_permissions(_file: &fs::File) -> io::Result<()> {
+    // TODO(linus): Lock permissions correctly on Windows.
+    Ok(())
+}
+
 /// A handle to an `OpenVpnMonitor` for closing it.
 #[derive(Debug, Clone)]
 pub struct OpenVpnCloseHandle<H: ProcessHandle = OpenVpnProcHandle> {
     child: Arc<Mutex<Option<Arc<H>>>>,
     abort_spawn: futures::future::AbortHandle,
     closed: Arc<AtomicBool>,
+    cancel_token: CancelToken,
 }
 
 impl<H: ProcessHandle> OpenVpnCloseHandle<H> {
     /// Kills the underlying OpenVPN process, making the `OpenVpnMonitor::wait` method return.
     pub fn close(self) -> io::Result<()> {
         if !self.closed.swap(true, Ordering::SeqCst) {
+            self.cancel_token.cancel();
             self.abort_spawn.abort();
             if let Some(child) = self.child.lock().unwrap().as_ref() {
                 child.kill()
@@ -862,10 +1086,14 @@ mod event_server {
         pub user_pass_file_path: super::PathBuf,
         pub proxy_auth_file_path: Option<super::PathBuf>,
         pub abort_server_tx: triggered::Trigger,
+        pub cancel_token: super::CancelToken,
         #[cfg(target_os = "linux")]
         pub route_manager_handle: super::routing::RouteManagerHandle,
         #[cfg(target_os = "linux")]
         pub ipv6_enabled: bool,
+        /// Whether to connect with IPv4 only if the IPv6 addresses of the tunnel are unusable.
+        #[cfg(windows)]
+        pub ipv6_optional: bool,
     }
 
     impl<
@@ -925,24 +1153,47 @@ mod event_server {
                 }
             }
 
-            let metadata = Self::get_tunnel_metadata(&env)?;
+            #[cfg_attr(not(windows), allow(unused_mut))]
+            let mut metadata = Self::get_tunnel_metadata(&env)?;
 
             #[cfg(windows)]
             {
                 let tunnel_device = metadata.interface.clone();
-                let luid = crate::windows::luid_from_alias(tunnel_device).map_err(|error| {
-                    log::error!("{}", error.display_chain_with_msg("luid_from_alias failed"));
+                let luid = Luid::from_alias(tunnel_device).map_err(|error| {
+                    log::error!("{}", error.display_chain_with_msg("Failed to get LUID"));
                     tonic::Status::unavailable("failed to obtain interface luid")
                 })?;
-                crate::windows::wait_for_addresses(luid)
-                    .await
-                    .map_err(|error| {
+                let result = crate::windows::wait_for_addresses(
+                    luid,
+                    crate::windows::DAD_CHECK_TIMEOUT,
+                    self.ipv6_optional,
+                    &self.cancel_token,
+                )
+                .await;
+                match result {
+                    Ok(true) => (),
+                    Ok(false) => {
+                        log::warn!(
+                            "The IPv6 address of the tunnel is unusable. Connecting with IPv4 only"
+                        );
+                        metadata.ips.retain(|ip| ip.is_ipv4());
+                        metadata.ipv6_gateway = None;
+                    }
+                    Err(crate::windows::Error::Cancelled) => {
+                        log::debug!("Stopped waiting for the tunnel addresses");
+                        return Err(tonic::Status::cancelled("the tunnel is closing"));
+                    }
+                    Err(error) => {
                         log::error!(
                             "{}",
                             error.display_chain_with_msg("wait_for_addresses failed")
                         );
-                        tonic::Status::unavailable("wait_for_addresses failed")
-                    })?;
+                        if matches!(error, crate::windows::Error::DeviceReadyTimeout) {
+                            (self.on_event)(super::TunnelEvent::AddressTimeout).await;
+                        }
+                        return Err(tonic::Status::unavailable("wait_for_addresses failed"));
+                    }
+                }
             }
 
             (self.on_event)(super::TunnelEvent::Up(metadata)).await;
@@ -1123,13 +1374,13 @@ mod tests {
 
     #[cfg(windows)]
     #[derive(Debug)]
-    struct TestWintunContext {}
+    struct TestAdapterContext {}
 
     #[cfg(windows)]
     #[async_trait::async_trait]
-    impl WintunContext for TestWintunContext {
-        fn luid(&self) -> NET_LUID {
-            NET_LUID { Value: 0u64 }
+    impl AdapterContext for TestAdapterContext {
+        fn luid(&self) -> Luid {
+            Luid::from_value(0)
         }
         fn ipv6(&self) -> bool {
             false
@@ -1229,9 +1480,11 @@ mod tests {
             None,
             TempFile::new(),
             None,
+            ProfileFiles::default(),
             None,
+            CancelToken::new(),
             #[cfg(windows)]
-            Box::new(TestWintunContext {}),
+            Box::new(TestAdapterContext {}),
         );
         assert_eq!(
             Some(PathBuf::from("./my_test_plugin")),
@@ -1252,9 +1505,11 @@ mod tests {
             Some(PathBuf::from("./my_test_log_file")),
             TempFile::new(),
             None,
+            ProfileFiles::default(),
             None,
+            CancelToken::new(),
             #[cfg(windows)]
-            Box::new(TestWintunContext {}),
+            Box::new(TestAdapterContext {}),
         );
         assert_eq!(
             Some(PathBuf::from("./my_test_log_file")),
@@ -1276,9 +1531,11 @@ mod tests {
             None,
             TempFile::new(),
             None,
+            ProfileFiles::default(),
             None,
+            CancelToken::new(),
             #[cfg(windows)]
-            Box::new(TestWintunContext {}),
+            Box::new(TestAdapterContext {}),
         )
         .unwrap();
         assert!(testee.wait().is_ok());
@@ -1298,9 +1555,11 @@ mod tests {
             None,
             TempFile::new(),
             None,
+            ProfileFiles::default(),
             None,
+            CancelToken::new(),
             #[cfg(windows)]
-            Box::new(TestWintunContext {}),
+            Box::new(TestAdapterContext {}),
         )
         .unwrap();
         assert!(testee.wait().is_err());
@@ -1320,9 +1579,11 @@ mod tests {
             None,
             TempFile::new(),
             None,
+            ProfileFiles::default(),
             None,
+            CancelToken::new(),
             #[cfg(windows)]
-            Box::new(TestWintunContext {}),
+            Box::new(TestAdapterContext {}),
         )
         .unwrap();
         testee.close_handle().close().unwrap();
@@ -1342,9 +1603,11 @@ mod tests {
             None,
             TempFile::new(),
             None,
+            ProfileFiles::default(),
             None,
+            CancelToken::new(),
             #[cfg(windows)]
-            Box::new(TestWintunContext {}),
+            Box::new(TestAdapterContext {}),
         )
         .unwrap();
         match result.wait() {
@@ -1,14 +1,15 @@
-use crate::windows::{get_ip_interface_entry, set_ip_interface_entry, AddressFamily};
 use lazy_static::lazy_static;
 use std::{
     ffi::CStr,
     fmt, io, iter, mem,
+    net::IpAddr,
     os::windows::{ffi::OsStrExt, io::RawHandle},
     path::Path,
     ptr,
     sync::{Arc, Mutex},
 };
-use talpid_types::ErrorExt;
+use talpid_types::{net::openvpn::WintunAdapterPolicy, ErrorExt};
+use talpid_windows_net::{AddressFamily, IpInterface, Luid, UnicastAddress};
 use widestring::{U16CStr, U16CString};
 use winapi::{
     shared::{
@@ -16,8 +17,6 @@ use winapi::{
         ifdef::NET_LUID,
         minwindef::{BOOL, FARPROC, HINSTANCE, HMODULE},
         netioapi::ConvertInterfaceLuidToGuid,
-        nldef::RouterDiscoveryDisabled,
-        ntdef::FALSE,
         winerror::NO_ERROR,
     },
     um::{
@@ -35,6 +34,8 @@ use winreg::{
 lazy_static! {
     /// Shared `WintunDll` instance
     static ref WINTUN_DLL: Mutex<Option<Arc<WintunDll>>> = Mutex::new(None);
+    /// The adapter that was kept when the last tunnel closed, if any
+    static ref KEPT_ADAPTER: Mutex<Option<KeptAdapter>> = Mutex::new(None);
 }
 
 /// Longest possible adapter name (in characters), including null terminator
@@ -92,44 +93,110 @@ unsafe impl Sync for WintunDll {}
 
 type RebootRequired = bool;
 
-/// A new Wintun adapter that is destroyed when dropped.
+struct KeptAdapter {
+    adapter: WintunAdapter,
+    policy: WintunAdapterPolicy,
+}
+
+/// A Wintun adapter that is either kept for the next tunnel or deleted when dropped, depending on
+/// its [`WintunAdapterPolicy`].
 #[derive(Debug)]
-pub struct TemporaryWintunAdapter {
-    pub adapter: WintunAdapter,
+pub struct PooledWintunAdapter {
+    adapter: Option<WintunAdapter>,
+    policy: WintunAdapterPolicy,
 }
 
-impl TemporaryWintunAdapter {
-    pub fn create(
+impl PooledWintunAdapter {
+    /// Reuses the adapter that was kept by the last tunnel, or the adapter that is left in `pool`
+    /// since the daemon last ran, if it is named `name`. Creates a new adapter otherwise.
+    pub fn acquire(
         dll_handle: Arc<WintunDll>,
         pool: &U16CStr,
         name: &U16CStr,
         requested_guid: Option<GUID>,
+        policy: WintunAdapterPolicy,
     ) -> io::Result<(Self, RebootRequired)> {
+        let kept = KEPT_ADAPTER.lock().expect("Wintun mutex poisoned").take();
+        let existing = match kept {
+            Some(kept) => Some(kept.adapter),
+            None => WintunAdapter::open(dll_handle.clone(), pool, name).ok(),
+        };
+        if let Some(adapter) = existing {
+            match adapter.name() {
+                Ok(adapter_name) if &*adapter_name == name => {
+                    log::debug!("Reusing existing Wintun adapter");
+                    adapter.remove_addresses();
+                    let adapter = Self {
+                        adapter: Some(adapter),
+                        policy,
+                    };
+                    return Ok((adapter, false));
+                }
+                // The adapter has the GUID that the new adapter would get
+                _ => delete_adapter(adapter),
+            }
+        }
+
         let (adapter, reboot_required) =
             WintunAdapter::create(dll_handle, pool, name, requested_guid)?;
-        Ok((TemporaryWintunAdapter { adapter }, reboot_required))
+        let adapter = Self {
+            adapter: Some(adapter),
+            policy,
+        };
+        Ok((adapter, reboot_required))
     }
 
     pub fn adapter(&self) -> &WintunAdapter {
-        &self.adapter
+        self.adapter
+            .as_ref()
+            .expect("The adapter is only taken when dropped")
     }
 }
 
-impl Drop for TemporaryWintunAdapter {
+impl Drop for PooledWintunAdapter {
     fn drop(&mut self) {
-        if let Err(error) = unsafe {
-            self.adapter
-                .dll_handle
-                .delete_adapter(self.adapter.handle, true)
-        } {
-            log::error!(
-                "{}",
-                error.display_chain_with_msg("Failed to delete Wintun adapter")
-            );
+        let adapter = match self.adapter.take() {
+            Some(adapter) => adapter,
+            None => return,
+        };
+        match self.policy {
+            WintunAdapterPolicy::DeleteAfterUse => delete_adapter(adapter),
+            policy => {
+                *KEPT_ADAPTER.lock().expect("Wintun mutex poisoned") =
+                    Some(KeptAdapter { adapter, policy })
+            }
+        }
+    }
+}
+
+/// Deletes the adapter that was kept by the last tunnel, if its policy is to delete it on
+/// shutdown. Must be called once no more tunnels will be started.
+pub fn release_kept_adapter() {
+    let kept = KEPT_ADAPTER.lock().expect("Wintun mutex poisoned").take();
+    if let Some(kept) = kept {
+        if kept.policy == WintunAdapterPolicy::DeleteOnShutdown {
+            delete_adapter(kept.adapter);
         }
     }
 }
 
+/// Deletes the adapter named `name` in `pool`, if there is one.
+pub fn delete_abandoned_adapter(dll_handle: Arc<WintunDll>, pool: &U16CStr, name: &U16CStr) {
+    if let Ok(adapter) = WintunAdapter::open(dll_handle, pool, name) {
+        log::debug!("Deleting abandoned Wintun adapter");
+        delete_adapter(adapter);
+    }
+}
+
+fn delete_adapter(adapter: WintunAdapter) {
+    if let Err(error) = adapter.delete(true) {
+        log::error!(
+            "{}",
+            error.display_chain_with_msg("Failed to delete Wintun adapter")
+        );
+    }
+}
+
 /// Represents a Wintun adapter.
 pub struct WintunAdapter {
     dll_handle: Arc<WintunDll>,
@@ -162,7 +229,7 @@ impl WintunAdapter {
         requested_guid: Option<GUID>,
     ) -> io::Result<(Self, RebootRequired)> {
         {
-            if let Ok(adapter) = Self::open(dll_handle.clone(), name, pool) {
+            if let Ok(adapter) = Self::open(dll_handle.clone(), pool, name) {
                 // Delete existing adapter in case it has residual config
                 adapter.delete(false).map_err(|error| {
                     log::error!(
@@ -189,14 +256,10 @@ impl WintunAdapter {
         // Disable DAD, DHCP, and router discovery
         let luid = self.luid();
         for family in &[AddressFamily::Ipv4, AddressFamily::Ipv6] {
-            if let Ok(mut row) = get_ip_interface_entry(*family, &luid) {
-                row.SitePrefixLength = 0;
-                row.RouterDiscoveryBehavior = RouterDiscoveryDisabled;
-                row.DadTransmits = 0;
-                row.ManagedAddressConfigurationSupported = FALSE;
-                row.OtherStatefulConfigurationSupported = FALSE;
-
-                if let Err(error) = set_ip_interface_entry(&row) {
+            if let Ok(mut interface) = IpInterface::get(*family, luid) {
+                interface.disable_autoconfiguration();
+
+                if let Err(error) = interface.apply() {
                     log::error!(
                         "{} (family: {})",
                         error.display_chain_with_msg("Failed to update Wintun interface"),
@@ -207,6 +270,34 @@ impl WintunAdapter {
         }
     }
 
+    /// Removes the IP addresses that an earlier tunnel assigned to the adapter. Link-local
+    /// addresses are left in place.
+    pub fn remove_addresses(&self) {
+        let addresses = match UnicastAddress::for_interface(self.luid()) {
+            Ok(addresses) => addresses,
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to list addresses of Wintun adapter")
+                );
+                return;
+            }
+        };
+        for address in addresses {
+            if let Some(IpAddr::V6(addr)) = address.address() {
+                if addr.segments()[0] & 0xffc0 == 0xfe80 {
+                    continue;
+                }
+            }
+            if let Err(error) = address.delete() {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to remove address of Wintun adapter")
+                );
+            }
+        }
+    }
+
     pub fn delete(self, force_close_sessions: bool) -> io::Result<RebootRequired> {
         unsafe {
             self.dll_handle
@@ -218,13 +309,13 @@ impl WintunAdapter {
         unsafe { self.dll_handle.get_adapter_name(self.handle) }
     }
 
-    pub fn luid(&self) -> NET_LUID {
-        unsafe { self.dll_handle.get_adapter_luid(self.handle) }
+    pub fn luid(&self) -> Luid {
+        Luid::from(unsafe { self.dll_handle.get_adapter_luid(self.handle) })
     }
 
     pub fn guid(&self) -> io::Result<GUID> {
         let mut guid = mem::MaybeUninit::zeroed();
-        let result = unsafe { ConvertInterfaceLuidToGuid(&self.luid(), guid.as_mut_ptr()) };
+        let result = unsafe { ConvertInterfaceLuidToGuid(self.luid().as_raw(), guid.as_mut_ptr()) };
         if result != NO_ERROR {
             return Err(io::Error::from_raw_os_error(result as i32));
         }
@@ -296,6 +387,8 @@ impl WintunDll {
     }
 
     fn new(resource_dir: &Path) -> io::Result<Self> {
+        crate::windows::check_driver_architecture()?;
+
         let wintun_dll: Vec<u16> = resource_dir
             .join("wintun.dll")
             .as_os_str()
@@ -0,0 +1,185 @@
+use std::{io, time::Instant};
+
+/// The number of bytes received and sent on a network interface since it was created.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TrafficCounters {
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+}
+
+/// The traffic through a tunnel, as of the latest sample of its traffic counters.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TunnelStats {
+    /// The number of bytes received and sent since the tunnel interface was created.
+    pub counters: TrafficCounters,
+    /// Bytes per second received between the two latest samples.
+    pub rx_rate: u64,
+    /// Bytes per second sent between the two latest samples.
+    pub tx_rate: u64,
+}
+
+/// Keeps the two latest samples of the traffic counters of a tunnel, to tell how much traffic
+/// currently passes through it.
+#[derive(Debug, Default)]
+pub struct TunnelStatsMonitor {
+    latest: Option<(TrafficCounters, Instant)>,
+    previous: Option<(TrafficCounters, Instant)>,
+}
+
+impl TunnelStatsMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the traffic counters read at `time`.
+    pub fn record(&mut self, counters: TrafficCounters, time: Instant) {
+        self.previous = self.latest.replace((counters, time));
+    }
+
+    /// Forgets all samples, such as when the tunnel is replaced.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Returns the latest counters and the rates between the two latest samples. The rates are
+    /// zero until two samples have been recorded, or if the counters wrapped around.
+    pub fn stats(&self) -> Option<TunnelStats> {
+        let (counters, time) = self.latest?;
+        let mut stats = TunnelStats {
+            counters,
+            ..TunnelStats::default()
+        };
+        if let Some((previous, previous_time)) = self.previous {
+            let elapsed = time.saturating_duration_since(previous_time).as_secs_f64();
+            if elapsed > 0.0 {
+                let rate = |current: u64, previous: u64| {
+                    current
+                        .checked_sub(previous)
+                        .map(|bytes| (bytes as f64 / elapsed) as u64)
+                        .unwrap_or(0)
+                };
+                stats.rx_rate = rate(counters.rx_bytes, previous.rx_bytes);
+                stats.tx_rate = rate(counters.tx_bytes, previous.tx_bytes);
+            }
+        }
+        Some(stats)
+    }
+}
+
+/// Reads the traffic counters of the interface with the given name.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn traffic_counters(interface: &str) -> io::Result<TrafficCounters> {
+    let read_counter = |name: &str| -> io::Result<u64> {
+        let path = format!("/sys/class/net/{}/statistics/{}", interface, name);
+        std::fs::read_to_string(path)?
+            .trim()
+            .parse()
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    };
+    Ok(TrafficCounters {
+        rx_bytes: read_counter("rx_bytes")?,
+        tx_bytes: read_counter("tx_bytes")?,
+    })
+}
+
+/// Reads the traffic counters of the interface with the given name. The counters wrap around
+/// after 4 GiB.
+#[cfg(target_os = "macos")]
+pub fn traffic_counters(interface: &str) -> io::Result<TrafficCounters> {
+    use std::ffi::CStr;
+
+    let mut addrs: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut addrs) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut counters = None;
+    let mut addr = addrs;
+    while !addr.is_null() {
+        let entry = unsafe { &*addr };
+        addr = entry.ifa_next;
+        if entry.ifa_addr.is_null()
+            || entry.ifa_data.is_null()
+            || i32::from(unsafe { (*entry.ifa_addr).sa_family }) != libc::AF_LINK
+        {
+            continue;
+        }
+        let name = unsafe { CStr::from_ptr(entry.ifa_name) };
+        if name.to_bytes() == interface.as_bytes() {
+            let data = unsafe { &*(entry.ifa_data as *const libc::if_data) };
+            counters = Some(TrafficCounters {
+                rx_bytes: u64::from(data.ifi_ibytes),
+                tx_bytes: u64::from(data.ifi_obytes),
+            });
+            break;
+        }
+    }
+    unsafe { libc::freeifaddrs(addrs) };
+
+    counters.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Interface not found"))
+}
+
+/// Reads the traffic counters of the interface with the given alias.
+#[cfg(windows)]
+pub fn traffic_counters(interface: &str) -> io::Result<TrafficCounters> {
+    use winapi::shared::{
+        netioapi::{GetIfEntry2, MIB_IF_ROW2},
+        winerror::NO_ERROR,
+    };
+
+    let mut row: MIB_IF_ROW2 = unsafe { std::mem::zeroed() };
+    row.InterfaceLuid = talpid_windows_net::Luid::from_alias(interface)?.into();
+    let status = unsafe { GetIfEntry2(&mut row) };
+    if status != NO_ERROR {
+        return Err(io::Error::from_raw_os_error(status as i32));
+    }
+    Ok(TrafficCounters {
+        rx_bytes: row.InOctets,
+        tx_bytes: row.OutOctets,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn counters(rx_bytes: u64, tx_bytes: u64) -> TrafficCounters {
+        TrafficCounters { rx_bytes, tx_bytes }
+    }
+
+    #[test]
+    fn test_no_rates_from_single_sample() {
+        let mut monitor = TunnelStatsMonitor::new();
+        assert_eq!(monitor.stats(), None);
+
+        monitor.record(counters(100, 200), Instant::now());
+        let stats = monitor.stats().unwrap();
+        assert_eq!(stats.counters, counters(100, 200));
+        assert_eq!((stats.rx_rate, stats.tx_rate), (0, 0));
+    }
+
+    #[test]
+    fn test_rates_between_latest_samples() {
+        let mut monitor = TunnelStatsMonitor::new();
+        let start = Instant::now();
+        monitor.record(counters(0, 0), start);
+        monitor.record(counters(1000, 500), start + Duration::from_secs(10));
+        monitor.record(counters(3000, 600), start + Duration::from_secs(20));
+
+        let stats = monitor.stats().unwrap();
+        assert_eq!(stats.counters, counters(3000, 600));
+        assert_eq!((stats.rx_rate, stats.tx_rate), (200, 10));
+    }
+
+    #[test]
+    fn test_wrapped_counters() {
+        let mut monitor = TunnelStatsMonitor::new();
+        let start = Instant::now();
+        monitor.record(counters(1000, 1000), start);
+        monitor.record(counters(10, 2000), start + Duration::from_secs(1));
+
+        let stats = monitor.stats().unwrap();
+        assert_eq!((stats.rx_rate, stats.tx_rate), (0, 1000));
+    }
+}
@@ -59,6 +59,10 @@ pub struct TunConfig {
     /// Maximum Transmission Unit in the tunnel.
     #[cfg_attr(target_os = "android", jnix(map = "|mtu| mtu as i32"))]
     pub mtu: u16,
+
+    /// Name of the tunnel device. The system picks a name if this is not set.
+    #[cfg(not(target_os = "android"))]
+    pub name: Option<String>,
 }
 
 #[cfg(target_os = "android")]
@@ -28,7 +28,8 @@ impl UnixTunProvider {
     }
 
     pub fn get_tun(&mut self, config: TunConfig) -> Result<UnixTun, Error> {
-        let mut tunnel_device = TunnelDevice::new().map_err(Error::CreateTunnelDevice)?;
+        let mut tunnel_device =
+            TunnelDevice::new(config.name.as_deref()).map_err(Error::CreateTunnelDevice)?;
 
         for ip in config.addresses.iter() {
             tunnel_device
@@ -2,7 +2,10 @@ use std::{
     borrow::Cow,
     ffi::CString,
     net::{Ipv4Addr, Ipv6Addr},
+    time::Duration,
 };
+#[cfg(target_os = "windows")]
+use talpid_types::net::Ipv6FailurePolicy;
 use talpid_types::net::{wireguard, GenericTunnelOptions};
 
 /// Config required to set up a single WireGuard tunnel
@@ -17,15 +20,35 @@ pub struct Config {
     pub ipv6_gateway: Option<Ipv6Addr>,
     /// Maximum transmission unit for the tunnel
     pub mtu: u16,
+    /// Obfuscation applied to the traffic sent to the first peer
+    pub obfuscator: Option<wireguard::ObfuscatorConfig>,
+    /// Interval at which to ping the gateway to check connectivity, if not inferred from the
+    /// traffic
+    pub icmp_check_interval: Option<Duration>,
+    /// How long to wait for the tunnel to start working, if not the default
+    pub handshake_timeout: Option<Duration>,
+    /// Packet loss, in percent, above which to give up on the tunnel
+    pub packet_loss_threshold: Option<u8>,
+    /// Whether to negotiate a post-quantum preshared key with the relay before the tunnel is up
+    pub quantum_resistant: bool,
+    /// Name of the tunnel interface, if it should not have the default name
+    #[cfg(not(target_os = "android"))]
+    pub interface_name: Option<String>,
     /// Firewall mark
     #[cfg(target_os = "linux")]
     pub fwmark: u32,
-    /// Enable IPv6 routing rules
-    #[cfg(target_os = "linux")]
+    /// Whether IPv6 is set up in the tunnel, and IPv6 routing rules are enabled on Linux
+    #[cfg(any(target_os = "linux", target_os = "windows"))]
     pub enable_ipv6: bool,
+    /// What to do if IPv6 cannot be set up on the tunnel interface
+    #[cfg(target_os = "windows")]
+    pub ipv6_failure_policy: Ipv6FailurePolicy,
     /// Temporary switch for wireguard-nt
     #[cfg(target_os = "windows")]
     pub use_wireguard_nt: bool,
+    /// Use wireguard-go even if the WireGuard kernel module is available
+    #[cfg(target_os = "linux")]
+    pub use_userspace_wireguard: bool,
 }
 
 const DEFAULT_MTU: u16 = 1380;
@@ -108,12 +131,25 @@ impl Config {
             ipv4_gateway: connection_config.ipv4_gateway,
             ipv6_gateway,
             mtu,
+            obfuscator: connection_config.obfuscation.clone(),
+            icmp_check_interval: wg_options
+                .icmp_check_interval
+                .map(|seconds| Duration::from_secs(u64::from(seconds))),
+            handshake_timeout: connection_config.handshake_timeout,
+            packet_loss_threshold: wg_options.packet_loss_threshold,
+            quantum_resistant: connection_config.quantum_resistant,
+            #[cfg(not(target_os = "android"))]
+            interface_name: generic_options.interface_name.clone(),
             #[cfg(target_os = "linux")]
             fwmark: crate::linux::TUNNEL_FW_MARK,
-            #[cfg(target_os = "linux")]
+            #[cfg(any(target_os = "linux", target_os = "windows"))]
             enable_ipv6: generic_options.enable_ipv6,
             #[cfg(target_os = "windows")]
+            ipv6_failure_policy: generic_options.ipv6_failure_policy,
+            #[cfg(target_os = "windows")]
             use_wireguard_nt: wg_options.use_wireguard_nt,
+            #[cfg(target_os = "linux")]
+            use_userspace_wireguard: wg_options.use_userspace_wireguard,
         })
     }
 
@@ -136,6 +172,9 @@ impl Config {
                 .add("public_key", peer.public_key.as_bytes().as_ref())
                 .add("endpoint", peer.endpoint.to_string().as_str())
                 .add("replace_allowed_ips", "true");
+            if let Some(psk) = &peer.psk {
+                wg_conf.add("preshared_key", psk.as_bytes().as_ref());
+            }
             for addr in &peer.allowed_ips {
                 wg_conf.add("allowed_ip", addr.to_string().as_str());
             }
@@ -3,10 +3,13 @@ use crate::{
     tunnel::wireguard::stats::StatsMap,
 };
 use std::{
+    cmp,
+    collections::VecDeque,
     net::Ipv4Addr,
-    sync::{mpsc, Mutex, Weak},
+    sync::{Mutex, Weak},
     time::{Duration, Instant},
 };
+use talpid_types::cancel::CancelToken;
 
 use super::{Tunnel, TunnelError};
 
@@ -28,6 +31,17 @@ const TRAFFIC_TIMEOUT: Duration = Duration::from_secs(120);
 const PING_TIMEOUT: Duration = Duration::from_secs(15);
 /// Number of seconds to wait between sending ICMP packets
 const SECONDS_PER_PING: Duration = Duration::from_secs(3);
+/// Number of ping intervals without any incoming traffic after which the connection is assumed to
+/// be lost, when pinging at a fixed interval. `PING_TIMEOUT` is used if it is longer.
+const ICMP_CHECK_TIMEOUT_INTERVALS: u32 = 3;
+/// Interval at which probes are sent to estimate the packet loss, if enabled.
+const LOSS_PROBE_INTERVAL: Duration = Duration::from_secs(2);
+/// Only probes sent within this long are used to estimate the packet loss.
+const LOSS_WINDOW: Duration = Duration::from_secs(20);
+/// Number of answered or unanswered probes required before the packet loss is estimated.
+const LOSS_MIN_PROBES: usize = 5;
+/// How long the packet loss must stay above the threshold before the connection is given up on.
+const LOSS_SUSTAIN_PERIOD: Duration = Duration::from_secs(30);
 
 /// Connectivity monitor errors
 #[derive(err_derive::Error, Debug)]
@@ -64,13 +78,32 @@ pub enum Error {
 ///
 /// Once a connection established, a connection is only considered broken once the connectivity
 /// monitor has started pinging and no traffic has been received for a duration of `PING_TIMEOUT`.
+///
+/// On some networks, the traffic counters are not a reliable sign of a broken connection. The
+/// monitor can then instead ping the gateway at a fixed interval, regardless of the traffic, and
+/// consider the connection broken once no traffic has been received for
+/// `ICMP_CHECK_TIMEOUT_INTERVALS` intervals after an unanswered ping.
+///
+/// If a packet loss threshold is set, the monitor also probes the gateway every
+/// `LOSS_PROBE_INTERVAL` once connected, and gives up on the connection once the estimated packet
+/// loss has stayed above the threshold for `LOSS_SUSTAIN_PERIOD`.
 pub struct ConnectivityMonitor {
     tunnel_handle: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
     conn_state: ConnState,
     initial_ping_timestamp: Option<Instant>,
     num_pings_sent: u32,
+    /// Interval at which to ping the gateway, if pinging regardless of the traffic.
+    icmp_check_interval: Option<Duration>,
+    /// How long to wait for the tunnel to start working when it is set up.
+    establish_timeout: Duration,
+    last_ping_timestamp: Option<Instant>,
+    /// Estimates the packet loss, if the connection should be given up on when it is too high.
+    packet_loss: Option<PacketLossEstimator>,
+    /// The packet loss, in percent, that the connection was given up on because of.
+    excessive_packet_loss: Option<u8>,
     pinger: Box<dyn Pinger>,
-    close_receiver: mpsc::Receiver<()>,
+    /// Shuts down the monitor when cancelled.
+    cancel_token: CancelToken,
 }
 
 
@@ -78,8 +111,11 @@ impl ConnectivityMonitor {
     pub(super) fn new(
         addr: Ipv4Addr,
         #[cfg(not(target_os = "windows"))] interface: String,
+        icmp_check_interval: Option<Duration>,
+        establish_timeout: Option<Duration>,
+        packet_loss_threshold: Option<u8>,
         tunnel_handle: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
-        close_receiver: mpsc::Receiver<()>,
+        cancel_token: CancelToken,
     ) -> Result<Self, Error> {
         let pinger = new_pinger(
             addr,
@@ -95,8 +131,13 @@ impl ConnectivityMonitor {
             conn_state: ConnState::new(now, Default::default()),
             initial_ping_timestamp: None,
             num_pings_sent: 0,
+            icmp_check_interval,
+            establish_timeout: establish_timeout.unwrap_or(PING_TIMEOUT),
+            last_ping_timestamp: None,
+            packet_loss: packet_loss_threshold.map(PacketLossEstimator::new),
+            excessive_packet_loss: None,
             pinger,
-            close_receiver,
+            cancel_token,
         })
     }
 
@@ -108,7 +149,7 @@ impl ConnectivityMonitor {
         }
 
         let start = Instant::now();
-        while start.elapsed() < PING_TIMEOUT {
+        while start.elapsed() < self.establish_timeout {
             if self.check_connectivity(Instant::now())? {
                 return Ok(true);
             }
@@ -119,16 +160,25 @@ impl ConnectivityMonitor {
         Ok(false)
     }
 
-    pub(super) fn run(&mut self) -> Result<(), Error> {
-        self.wait_loop(REGULAR_LOOP_SLEEP)
+    /// Forgets that the tunnel has worked, so that connectivity has to be established again. Used
+    /// after the peers have been reconfigured, since that requires a new handshake.
+    pub(super) fn reset(&mut self) {
+        self.conn_state = ConnState::new(Instant::now(), Default::default());
+        self.initial_ping_timestamp = None;
+        self.num_pings_sent = 0;
+        self.last_ping_timestamp = None;
+    }
+
+    /// Monitors the connection until it is lost or the monitor is shut down. Returns the
+    /// estimated packet loss, in percent, if the connection was given up on because of it.
+    pub(super) fn run(&mut self) -> Result<Option<u8>, Error> {
+        self.wait_loop(REGULAR_LOOP_SLEEP)?;
+        Ok(self.excessive_packet_loss.take())
     }
 
     /// Returns true if monitor should be shut down
     fn should_shut_down(&mut self, timeout: Duration) -> bool {
-        match self.close_receiver.recv_timeout(timeout) {
-            Ok(()) | Err(mpsc::RecvTimeoutError::Disconnected) => true,
-            Err(mpsc::RecvTimeoutError::Timeout) => false,
-        }
+        self.cancel_token.wait_timeout(timeout)
     }
 
     fn wait_loop(&mut self, iter_delay: Duration) -> Result<(), Error> {
@@ -150,6 +200,9 @@ impl ConnectivityMonitor {
                 // connectivity.
                 self.reset_pinger();
                 self.conn_state.reset_after_suspension(current_iteration);
+                if let Some(packet_loss) = &mut self.packet_loss {
+                    packet_loss.reset();
+                }
             }
             last_iteration = current_iteration;
         }
@@ -163,8 +216,15 @@ impl ConnectivityMonitor {
             Some(new_stats) => {
                 let new_stats = new_stats?;
 
-                if self.conn_state.update(now, new_stats) {
+                let rx_incremented = self.conn_state.update(now, new_stats);
+                if rx_incremented {
                     self.reset_pinger();
+                }
+                if let Some(loss) = self.check_packet_loss(now, rx_incremented)? {
+                    self.excessive_packet_loss = Some(loss);
+                    return Ok(false);
+                }
+                if rx_incremented {
                     return Ok(true);
                 }
 
@@ -186,6 +246,10 @@ impl ConnectivityMonitor {
     }
 
     fn maybe_send_ping(&mut self, now: Instant) -> Result<(), Error> {
+        if let Some(interval) = self.icmp_check_interval {
+            return self.maybe_send_periodic_ping(now, interval);
+        }
+
         // Only send out a ping if we haven't received a byte in a while or no traffic has flowed
         // in the last 2 minutes, but if a ping already has been sent out, only send one out every
         // 3 seconds.
@@ -206,9 +270,62 @@ impl ConnectivityMonitor {
         Ok(())
     }
 
+    /// Sends a ping every `interval` once connected, regardless of the traffic. Until then,
+    /// pings are sent every `SECONDS_PER_PING` at most.
+    fn maybe_send_periodic_ping(&mut self, now: Instant, interval: Duration) -> Result<(), Error> {
+        let interval = if self.conn_state.connected() {
+            interval
+        } else {
+            cmp::min(interval, SECONDS_PER_PING)
+        };
+        let ping_due = self
+            .last_ping_timestamp
+            .map(|last_ping_timestamp| {
+                now.saturating_duration_since(last_ping_timestamp) >= interval
+            })
+            .unwrap_or(true);
+        if ping_due {
+            self.pinger.send_icmp().map_err(Error::PingError)?;
+            if self.initial_ping_timestamp.is_none() {
+                self.initial_ping_timestamp = Some(now);
+            }
+            self.last_ping_timestamp = Some(now);
+            self.num_pings_sent += 1;
+        }
+        Ok(())
+    }
+
+    /// Updates the packet loss estimate and sends a probe if one is due. Returns the estimated
+    /// packet loss if it has stayed above the threshold for long enough.
+    fn check_packet_loss(
+        &mut self,
+        now: Instant,
+        rx_incremented: bool,
+    ) -> Result<Option<u8>, Error> {
+        let packet_loss = match &mut self.packet_loss {
+            Some(packet_loss) if self.conn_state.connected() => packet_loss,
+            _ => return Ok(None),
+        };
+        if rx_incremented {
+            packet_loss.traffic_received();
+        }
+        if let Some(loss) = packet_loss.excessive_loss(now) {
+            return Ok(Some(loss));
+        }
+        if packet_loss.probe_due(now) {
+            self.pinger.send_icmp().map_err(Error::PingError)?;
+            packet_loss.probe_sent(now);
+        }
+        Ok(None)
+    }
+
     fn ping_timed_out(&self) -> bool {
+        let timeout = match self.icmp_check_interval {
+            Some(interval) => cmp::max(PING_TIMEOUT, interval * ICMP_CHECK_TIMEOUT_INTERVALS),
+            None => PING_TIMEOUT,
+        };
         self.initial_ping_timestamp
-            .map(|initial_ping_timestamp| initial_ping_timestamp.elapsed() > PING_TIMEOUT)
+            .map(|initial_ping_timestamp| initial_ping_timestamp.elapsed() > timeout)
             .unwrap_or(false)
     }
 
@@ -220,6 +337,91 @@ impl ConnectivityMonitor {
     }
 }
 
+/// Estimates the packet loss in the tunnel from how many probes go unanswered. A probe is a ping
+/// sent to the gateway, and it counts as answered if any incoming traffic is seen before the next
+/// probe is sent, be it the echo reply or a keepalive. The estimate is thus a lower bound when
+/// there is other traffic in the tunnel.
+struct PacketLossEstimator {
+    /// Packet loss, in percent, above which the loss is considered excessive.
+    threshold: u8,
+    /// When each probe within `LOSS_WINDOW` was sent, and whether it was answered.
+    probes: VecDeque<(Instant, bool)>,
+    /// Since when the estimated packet loss has been above the threshold.
+    exceeded_since: Option<Instant>,
+}
+
+impl PacketLossEstimator {
+    fn new(threshold: u8) -> Self {
+        PacketLossEstimator {
+            threshold,
+            probes: VecDeque::new(),
+            exceeded_since: None,
+        }
+    }
+
+    fn probe_due(&self, now: Instant) -> bool {
+        self.probes
+            .back()
+            .map(|(sent, _)| now.saturating_duration_since(*sent) >= LOSS_PROBE_INTERVAL)
+            .unwrap_or(true)
+    }
+
+    fn probe_sent(&mut self, now: Instant) {
+        while let Some((sent, _)) = self.probes.front() {
+            if now.saturating_duration_since(*sent) <= LOSS_WINDOW {
+                break;
+            }
+            self.probes.pop_front();
+        }
+        self.probes.push_back((now, false));
+    }
+
+    fn traffic_received(&mut self) {
+        if let Some((_, answered)) = self.probes.back_mut() {
+            *answered = true;
+        }
+    }
+
+    /// Returns the estimated packet loss in percent. The latest probe is only counted once it has
+    /// been answered, since the answer may still be on its way.
+    fn loss(&self) -> Option<u8> {
+        let mut probes: Vec<bool> = self.probes.iter().map(|(_, answered)| *answered).collect();
+        if probes.last() == Some(&false) {
+            probes.pop();
+        }
+        if probes.len() < LOSS_MIN_PROBES {
+            return None;
+        }
+        let lost = probes.iter().filter(|answered| !**answered).count();
+        Some((lost * 100 / probes.len()) as u8)
+    }
+
+    /// Returns the estimated packet loss if it has stayed above the threshold for
+    /// `LOSS_SUSTAIN_PERIOD`.
+    fn excessive_loss(&mut self, now: Instant) -> Option<u8> {
+        match self.loss() {
+            Some(loss) if loss > self.threshold => {
+                let exceeded_since = *self.exceeded_since.get_or_insert(now);
+                if now.saturating_duration_since(exceeded_since) >= LOSS_SUSTAIN_PERIOD {
+                    Some(loss)
+                } else {
+                    None
+                }
+            }
+            _ => {
+                self.exceeded_since = None;
+                None
+            }
+        }
+    }
+
+    /// Forgets all probes, such as when the host has been suspended.
+    fn reset(&mut self) {
+        self.probes.clear();
+        self.exceeded_since = None;
+    }
+}
+
 enum ConnState {
     Connecting {
         start: Instant,
@@ -364,7 +566,7 @@ mod test {
     use std::{
         sync::{
             atomic::{AtomicBool, Ordering},
-            Arc, Mutex,
+            mpsc, Arc, Mutex,
         },
         time::{Duration, Instant},
     };
@@ -574,14 +776,19 @@ mod test {
         now: Instant,
         pinger: Box<dyn Pinger>,
         tunnel_handle: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
-        close_receiver: mpsc::Receiver<()>,
+        cancel_token: CancelToken,
     ) -> ConnectivityMonitor {
         ConnectivityMonitor {
             conn_state: ConnState::new(now, Default::default()),
             initial_ping_timestamp: None,
             num_pings_sent: 0,
+            icmp_check_interval: None,
+            establish_timeout: PING_TIMEOUT,
+            last_ping_timestamp: None,
+            packet_loss: None,
+            excessive_packet_loss: None,
             pinger,
-            close_receiver,
+            cancel_token,
             tunnel_handle,
         }
     }
@@ -609,11 +816,11 @@ mod test {
     /// not flowing after `BYTES_RX_TIMEOUT` and `PING_TIMEOUT`.
     fn test_ping_times_out() {
         let (_tunnel_anchor, tunnel) = MockTunnel::never_incrementing().into_locked();
-        let (_tx, rx) = mpsc::channel();
+        let token = CancelToken::new();
         let pinger = MockPinger::default();
         let now = Instant::now();
         let start = now - (BYTES_RX_TIMEOUT + PING_TIMEOUT + Duration::from_secs(10));
-        let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, rx);
+        let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, token);
 
         // Mock the state - connectivity has been established
         monitor.conn_state = connected_state(start);
@@ -622,16 +829,81 @@ mod test {
         assert!(!monitor.check_connectivity(now).unwrap())
     }
 
+    #[test]
+    /// Verify that pings are sent at the ICMP check interval even while traffic is flowing, and
+    /// that the connection times out after `ICMP_CHECK_TIMEOUT_INTERVALS` unanswered intervals.
+    fn test_icmp_check_interval() {
+        let (_tunnel_anchor, tunnel) = MockTunnel::never_incrementing().into_locked();
+        let token = CancelToken::new();
+        let pings_sent = Arc::new(Mutex::new(0));
+        let pings_sent_inner = pings_sent.clone();
+        let pinger = MockPinger {
+            on_send_ping: Some(Box::new(move || *pings_sent_inner.lock().unwrap() += 1)),
+        };
+        let interval = Duration::from_secs(10);
+        let now = Instant::now();
+        let start = now - interval * (ICMP_CHECK_TIMEOUT_INTERVALS + 1);
+        let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, token);
+        monitor.icmp_check_interval = Some(interval);
+        monitor.conn_state = connected_state(start);
+
+        monitor.maybe_send_ping(start).unwrap();
+        monitor.maybe_send_ping(start + interval / 2).unwrap();
+        assert_eq!(*pings_sent.lock().unwrap(), 1);
+        monitor.maybe_send_ping(start + interval).unwrap();
+        assert_eq!(*pings_sent.lock().unwrap(), 2);
+
+        assert!(!monitor.check_connectivity(now).unwrap());
+    }
+
+    #[test]
+    /// Verify that the packet loss is only reported once it has stayed above the threshold for
+    /// `LOSS_SUSTAIN_PERIOD`, and that answered probes bring it back down.
+    fn test_packet_loss_estimate() {
+        let start = Instant::now();
+        let mut estimator = PacketLossEstimator::new(50);
+
+        // Every other probe is answered
+        let mut now = start;
+        for probe in 0..10 {
+            estimator.probe_sent(now);
+            if probe % 2 == 0 {
+                estimator.traffic_received();
+            }
+            now += LOSS_PROBE_INTERVAL;
+        }
+        assert_eq!(estimator.loss(), Some(44));
+        assert_eq!(estimator.excessive_loss(now), None);
+
+        // No probes are answered from here on
+        while estimator.loss().unwrap() <= 50 {
+            estimator.probe_sent(now);
+            assert_eq!(estimator.excessive_loss(now), None);
+            now += LOSS_PROBE_INTERVAL;
+        }
+        let exceeded_at = now - LOSS_PROBE_INTERVAL;
+        while now < exceeded_at + LOSS_SUSTAIN_PERIOD {
+            estimator.probe_sent(now);
+            assert_eq!(estimator.excessive_loss(now), None);
+            now += LOSS_PROBE_INTERVAL;
+        }
+        estimator.probe_sent(now);
+        assert!(estimator.excessive_loss(now).unwrap() > 50);
+
+        estimator.reset();
+        assert_eq!(estimator.loss(), None);
+    }
+
     #[test]
     /// Verify that `check_connectivity()` returns `true` if the tunnel is connected and traffic is
     /// flowing constantly.
     fn test_no_connection_on_start() {
         let (_tunnel_anchor, tunnel) = MockTunnel::never_incrementing().into_locked();
-        let (_tx, rx) = mpsc::channel();
+        let token = CancelToken::new();
         let pinger = MockPinger::default();
         let now = Instant::now();
         let start = now - Duration::from_secs(1);
-        let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, rx);
+        let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, token);
 
         assert!(!monitor.check_connectivity(now).unwrap())
     }
@@ -641,11 +913,11 @@ mod test {
     /// flowing constantly.
     fn test_connection_works() {
         let (_tunnel_anchor, tunnel) = MockTunnel::always_incrementing().into_locked();
-        let (_tx, rx) = mpsc::channel();
+        let token = CancelToken::new();
         let pinger = MockPinger::default();
         let now = Instant::now();
         let start = now - Duration::from_secs(1);
-        let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, rx);
+        let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, token);
 
         // Mock the state - connectivity has been established
         monitor.conn_state = connected_state(start);
@@ -660,11 +932,12 @@ mod test {
         let (result_tx, result_rx) = mpsc::channel();
         let (_tunnel_anchor, tunnel) = MockTunnel::always_incrementing().into_locked();
         let pinger = MockPinger::default();
-        let (stop_tx, stop_rx) = mpsc::channel();
+        let stop_token = CancelToken::new();
+        let monitor_token = stop_token.clone();
         std::thread::spawn(move || {
             let now = Instant::now();
             let start = now - Duration::from_secs(1);
-            let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, stop_rx);
+            let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, monitor_token);
 
             let start_result = monitor.establish_connectivity();
             result_tx.send(start_result).unwrap();
@@ -675,7 +948,7 @@ mod test {
 
         std::thread::sleep(Duration::from_secs(1));
         assert_eq!(true, result_rx.try_recv().unwrap().unwrap());
-        stop_tx.send(()).unwrap();
+        stop_token.cancel();
         std::thread::sleep(Duration::from_secs(1));
         assert!(result_rx.try_recv().unwrap().is_ok());
     }
@@ -714,11 +987,11 @@ mod test {
 
         let (result_tx, result_rx) = mpsc::channel();
 
-        let (_stop_tx, stop_rx) = mpsc::channel();
+        let token = CancelToken::new();
         std::thread::spawn(move || {
             let now = Instant::now();
             let start = now - Duration::from_secs(1);
-            let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, stop_rx);
+            let mut monitor = mock_monitor(start, Box::new(pinger), tunnel, token);
             let start_result = monitor.establish_connectivity();
             result_tx.send(start_result).unwrap();
             let end_result = monitor.run().map(|_| true);
@@ -2,8 +2,11 @@ use self::config::Config;
 #[cfg(not(windows))]
 use super::tun_provider;
 use super::{tun_provider::TunProvider, TunnelEvent, TunnelMetadata};
-use crate::routing::{self, RequiredRoute};
-use futures::future::abortable;
+use crate::{
+    connection_trace::{ConnectionTrace, Phase},
+    obfuscation::{self, Obfuscator},
+    routing::{self, RequiredRoute},
+};
 #[cfg(target_os = "linux")]
 use lazy_static::lazy_static;
 #[cfg(target_os = "linux")]
@@ -11,17 +14,24 @@ use std::env;
 #[cfg(windows)]
 use std::io;
 use std::{
-    net::{IpAddr, SocketAddr},
+    net::IpAddr,
     path::Path,
-    sync::{mpsc, Arc, Mutex},
+    sync::{mpsc, Arc, Mutex, Weak},
+    time::Instant,
+};
+#[cfg(windows)]
+use talpid_types::net::Ipv6FailurePolicy;
+use talpid_types::{
+    cancel::CancelToken,
+    net::{wireguard::ObfuscatorConfig, TransportProtocol},
+    ErrorExt,
 };
-use talpid_types::{net::TransportProtocol, ErrorExt};
-use udp_over_tcp::{TcpOptions, Udp2Tcp};
 
 /// WireGuard config data-types
 pub mod config;
 mod connectivity_check;
 mod logging;
+mod psk_negotiation;
 mod stats;
 mod wireguard_go;
 #[cfg(target_os = "linux")]
@@ -49,13 +59,13 @@ pub enum Error {
     #[error(display = "Tunnel failed")]
     TunnelError(#[error(source)] TunnelError),
 
-    /// Failed to set up Udp2Tcp
-    #[error(display = "Failed to start UDP-over-TCP proxy")]
-    Udp2TcpError(#[error(source)] udp_over_tcp::udp2tcp::ConnectError),
+    /// Failed to run an obfuscator
+    #[error(display = "Obfuscation failed")]
+    ObfuscationError(#[error(source)] obfuscation::Error),
 
-    /// Failed to obtain the local UDP socket address
-    #[error(display = "Failed obtain local address for the UDP socket in Udp2Tcp")]
-    GetLocalUdpAddress(#[error(source)] std::io::Error),
+    /// A bridge can only be used when the peer is reached over TCP
+    #[error(display = "The peer must be reached over TCP to use a bridge")]
+    BridgeRequiresTcp,
 
     /// Failed to set up connectivity monitor
     #[error(display = "Connectivity monitor failed")]
@@ -70,6 +80,18 @@ pub enum Error {
     #[cfg(target_os = "windows")]
     #[error(display = "Failed to set IP addresses on WireGuard interface")]
     SetIpAddressesError,
+
+    /// Failed to negotiate a post-quantum preshared key with the relay
+    #[error(display = "Failed to negotiate a quantum-resistant preshared key")]
+    PskNegotiationError(#[error(source)] psk_negotiation::Error),
+
+    /// Multihop tunnels cannot negotiate a preshared key
+    #[error(display = "Quantum-resistant tunnels are not supported with multihop")]
+    PskMultihopError,
+
+    /// The tunnel did not work after switching to the negotiated keys
+    #[error(display = "Tunnel timed out after switching to the negotiated preshared key")]
+    PskTimeoutError,
 }
 
 
@@ -87,10 +109,9 @@ pub struct WireguardMonitor {
     >,
     close_msg_sender: mpsc::Sender<CloseMsg>,
     close_msg_receiver: mpsc::Receiver<CloseMsg>,
-    #[cfg(target_os = "windows")]
-    stop_setup_tx: Option<futures::channel::oneshot::Sender<()>>,
-    pinger_stop_sender: mpsc::Sender<()>,
-    _tcp_proxies: Vec<TcpProxy>,
+    /// Stops the tunnel setup and the connectivity monitor
+    cancel_token: CancelToken,
+    _obfuscators: Vec<Box<dyn Obfuscator>>,
 }
 
 #[cfg(target_os = "linux")]
@@ -105,54 +126,6 @@ lazy_static! {
         .unwrap_or(false);
 }
 
-struct TcpProxy {
-    local_addr: SocketAddr,
-    abort_handle: futures::future::AbortHandle,
-}
-
-impl TcpProxy {
-    pub fn new(runtime: &tokio::runtime::Handle, endpoint: SocketAddr) -> Result<Self> {
-        let listen_addr = if endpoint.is_ipv4() {
-            SocketAddr::new("127.0.0.1".parse().unwrap(), 0)
-        } else {
-            SocketAddr::new("::1".parse().unwrap(), 0)
-        };
-
-        let udp2tcp = runtime
-            .block_on(Udp2Tcp::new(
-                listen_addr,
-                endpoint,
-                TcpOptions {
-                    #[cfg(target_os = "linux")]
-                    fwmark: Some(crate::linux::TUNNEL_FW_MARK),
-                    ..TcpOptions::default()
-                },
-            ))
-            .map_err(Error::Udp2TcpError)?;
-        let local_addr = udp2tcp
-            .local_udp_addr()
-            .map_err(Error::GetLocalUdpAddress)?;
-
-        let (udp2tcp_future, abort_handle) = abortable(udp2tcp.run());
-        runtime.spawn(udp2tcp_future);
-
-        Ok(Self {
-            local_addr,
-            abort_handle,
-        })
-    }
-
-    pub fn local_udp_addr(&self) -> SocketAddr {
-        self.local_addr
-    }
-}
-
-impl Drop for TcpProxy {
-    fn drop(&mut self) {
-        self.abort_handle.abort();
-    }
-}
-
 impl WireguardMonitor {
     /// Starts a WireGuard tunnel with the given config
     pub fn start<
@@ -169,20 +142,41 @@ impl WireguardMonitor {
         on_event: F,
         tun_provider: &mut TunProvider,
         route_manager: &mut routing::RouteManager,
+        connection_trace: ConnectionTrace,
+        cancel_token: &CancelToken,
     ) -> Result<WireguardMonitor> {
-        let mut tcp_proxies = vec![];
+        let mut obfuscators: Vec<Box<dyn Obfuscator>> = vec![];
         let mut endpoint_addrs = vec![];
 
-        for peer in &mut config.peers {
+        if let Some(obfuscator) = &config.obfuscator {
+            if !obfuscation::is_supported(obfuscator) {
+                log::warn!("Ignoring obfuscation since it is not supported on this platform");
+                config.obfuscator = None;
+            }
+        }
+
+        // A bridge forwards the TCP traffic for the first peer itself
+        let uses_bridge = matches!(config.obfuscator, Some(ObfuscatorConfig::Bridge(_)));
+        for (index, peer) in config.peers.iter_mut().enumerate() {
             endpoint_addrs.push(peer.endpoint.ip());
-            if peer.protocol == TransportProtocol::Tcp {
-                let udp2tcp = TcpProxy::new(&runtime, peer.endpoint.clone())?;
+            if peer.protocol == TransportProtocol::Tcp && !(index == 0 && uses_bridge) {
+                let udp2tcp = obfuscation::Udp2Tcp::new(&runtime, peer.endpoint)
+                    .map_err(Error::ObfuscationError)?;
 
                 // Replace remote peer with proxy
-                peer.endpoint = udp2tcp.local_udp_addr();
-                tcp_proxies.push(udp2tcp);
+                peer.endpoint = udp2tcp.endpoint();
+                obfuscators.push(Box::new(udp2tcp));
             }
         }
+        if let Some(obfuscator) = Self::start_obfuscator(
+            &runtime,
+            &mut config,
+            &mut endpoint_addrs,
+            log_path,
+            resource_dir,
+        )? {
+            obfuscators.push(obfuscator);
+        }
 
         let tunnel =
             Self::open_tunnel(&config, log_path, resource_dir, tun_provider, route_manager)?;
@@ -192,19 +186,21 @@ impl WireguardMonitor {
 
         let event_callback = Box::new(on_event.clone());
         let (close_msg_sender, close_msg_receiver) = mpsc::channel();
-        let (pinger_tx, pinger_rx) = mpsc::channel();
-        #[cfg(target_os = "windows")]
-        let (stop_setup_tx, stop_setup_rx) = futures::channel::oneshot::channel();
+        for obfuscator in &mut obfuscators {
+            let close_sender = close_msg_sender.clone();
+            obfuscator.monitor_exit(Box::new(move |error| {
+                let _ = close_sender.send(CloseMsg::SetupError(Error::ObfuscationError(error)));
+            }));
+        }
+        let cancel_token = cancel_token.child();
         let monitor = WireguardMonitor {
             runtime: runtime.clone(),
             tunnel: Arc::new(Mutex::new(Some(tunnel))),
             event_callback,
             close_msg_sender,
             close_msg_receiver,
-            #[cfg(target_os = "windows")]
-            stop_setup_tx: Some(stop_setup_tx),
-            pinger_stop_sender: pinger_tx,
-            _tcp_proxies: tcp_proxies,
+            cancel_token: cancel_token.clone(),
+            _obfuscators: obfuscators,
         };
 
         let gateway = config.ipv4_gateway;
@@ -213,84 +209,154 @@ impl WireguardMonitor {
             gateway,
             #[cfg(not(target_os = "windows"))]
             iface_name.clone(),
+            config.icmp_check_interval,
+            config.handshake_timeout,
+            config.packet_loss_threshold,
             Arc::downgrade(&monitor.tunnel),
-            pinger_rx,
+            cancel_token.clone(),
         )
         .map_err(Error::ConnectivityMonitorError)?;
 
         let route_handle = route_manager.handle().map_err(Error::SetupRoutingError)?;
 
         let metadata = Self::tunnel_metadata(&iface_name, &config);
+        let tunnel_handle = Arc::downgrade(&monitor.tunnel);
 
         std::thread::spawn(move || {
-            runtime.block_on((on_event)(TunnelEvent::InterfaceUp(metadata.clone())));
-
-            #[cfg(windows)]
-            {
-                let iface_close_sender = close_sender.clone();
-                let enable_ipv6 = config.ipv6_gateway.is_some();
+            let setup_started = Instant::now();
+
+            // The state machine applies the firewall policy for the tunnel interface when it
+            // handles `InterfaceUp`. That does not depend on the routes, so both are done at the
+            // same time. Connectivity is only checked once both are done.
+            let interface_up = async {
+                let started = Instant::now();
+                (on_event)(TunnelEvent::InterfaceUp(metadata.clone())).await;
+                log::debug!(
+                    "Handled tunnel interface up event in {} ms",
+                    started.elapsed().as_millis()
+                );
+            };
+            let setup_iface_routes = async {
+                let started = Instant::now();
+                let _span = connection_trace.enter(Phase::Routes);
 
-                let result = runtime.block_on(async move {
+                // Whether IPv6 is set up on the tunnel interface
+                #[cfg(windows)]
+                let ipv6 = {
                     use futures::future::FutureExt;
-                    use winapi::shared::ifdef::NET_LUID;
-                    let luid = NET_LUID { Value: iface_luid };
-                    let setup_future = crate::windows::wait_for_interfaces(luid, true, enable_ipv6);
+                    let luid = talpid_windows_net::Luid::from_value(iface_luid);
+                    let enable_ipv6 = config.enable_ipv6;
+                    let ipv6_optional = config.ipv6_failure_policy == Ipv6FailurePolicy::Ipv4Only;
+                    let setup_future = async move {
+                        if enable_ipv6 && ipv6_optional {
+                            crate::windows::wait_for_optional_ipv6_interface(
+                                luid,
+                                crate::windows::DAD_CHECK_TIMEOUT,
+                            )
+                            .await
+                        } else {
+                            crate::windows::wait_for_interfaces(luid, true, enable_ipv6)
+                                .await
+                                .map(|()| enable_ipv6)
+                        }
+                    };
 
-                    futures::select! {
+                    let ipv6 = futures::select! {
                         result = setup_future.fuse() => {
-                            result.map_err(|error|
-                                iface_close_sender.send(CloseMsg::SetupError(
-                                    Error::IpInterfacesError(error)
-                                ))
-                                .unwrap_or(())
-                            )
+                            result.map_err(|error| Some(Error::IpInterfacesError(error)))?
                         }
-                        _ = stop_setup_rx.fuse() => Err(()),
+                        _ = cancel_token.cancelled().fuse() => return Err(None),
+                    };
+                    if enable_ipv6 && !ipv6 {
+                        log::warn!(
+                            "IPv6 is unavailable on the tunnel interface. Connecting with IPv4 only"
+                        );
                     }
-                });
 
-                if result.is_err() {
-                    return;
-                }
-            }
+                    let addresses = config
+                        .tunnel
+                        .addresses
+                        .iter()
+                        .copied()
+                        .filter(|address| ipv6 || address.is_ipv4())
+                        .collect();
+                    if !crate::winnet::add_device_ip_addresses(&iface_name, &addresses) {
+                        return Err(Some(Error::SetIpAddressesError));
+                    }
+                    ipv6
+                };
 
-            let setup_iface_routes = || -> Result<()> {
-                #[cfg(target_os = "windows")]
-                if !crate::winnet::add_device_ip_addresses(&iface_name, &config.tunnel.addresses) {
-                    return Err(Error::SetIpAddressesError);
-                }
+                #[cfg(target_os = "linux")]
+                route_handle
+                    .create_routing_rules(config.enable_ipv6)
+                    .await
+                    .map_err(|error| Some(Error::SetupRoutingError(error)))?;
 
-                runtime.block_on(async move {
-                    #[cfg(target_os = "linux")]
-                    route_handle
-                        .create_routing_rules(config.enable_ipv6)
-                        .await
-                        .map_err(Error::SetupRoutingError)?;
-
-                    let routes = Self::get_in_tunnel_routes(&iface_name, &config)
-                        .chain(Self::get_tunnel_traffic_routes(&endpoint_addrs));
-
-                    route_handle
-                        .add_routes(routes.collect())
-                        .await
-                        .map_err(Error::SetupRoutingError)
-                })
+                let routes = Self::get_in_tunnel_routes(&iface_name, &config)
+                    .chain(Self::get_tunnel_traffic_routes(&endpoint_addrs));
+                #[cfg(windows)]
+                let routes = routes.filter(|route| ipv6 || route.prefix.is_ipv4());
+
+                route_handle
+                    .add_routes(routes.collect())
+                    .await
+                    .map_err(|error| Some(Error::SetupRoutingError(error)))?;
+
+                log::debug!(
+                    "Set up tunnel interface and routes in {} ms",
+                    started.elapsed().as_millis()
+                );
+                Ok::<(), Option<Error>>(())
             };
 
-            if let Err(error) = setup_iface_routes() {
-                let _ = close_sender.send(CloseMsg::SetupError(error));
-                return;
+            let (_, result) =
+                runtime.block_on(futures::future::join(interface_up, setup_iface_routes));
+            match result {
+                Ok(()) => (),
+                Err(Some(error)) => {
+                    let _ = close_sender.send(CloseMsg::SetupError(error));
+                    return;
+                }
+                // Setup was cancelled because the tunnel is closing
+                Err(None) => return,
             }
+            log::debug!(
+                "Tunnel setup finished in {} ms",
+                setup_started.elapsed().as_millis()
+            );
 
             match connectivity_monitor.establish_connectivity() {
                 Ok(true) => {
+                    if config.quantum_resistant {
+                        let _span = connection_trace.enter(Phase::PskNegotiation);
+                        if let Err(error) = Self::negotiate_psk(
+                            &runtime,
+                            &tunnel_handle,
+                            &mut config,
+                            &mut connectivity_monitor,
+                            &cancel_token,
+                        ) {
+                            let _ = close_sender.send(CloseMsg::SetupError(error));
+                            return;
+                        }
+                    }
+
                     runtime.block_on((on_event)(TunnelEvent::Up(metadata)));
 
-                    if let Err(error) = connectivity_monitor.run() {
-                        log::error!(
-                            "{}",
-                            error.display_chain_with_msg("Connectivity monitor failed")
-                        );
+                    match connectivity_monitor.run() {
+                        Ok(Some(loss)) => {
+                            log::warn!("Packet loss in the tunnel is {}%", loss);
+                            // The state machine closes the tunnel when it handles the event
+                            runtime.block_on((on_event)(TunnelEvent::PacketLoss(loss)));
+                            return;
+                        }
+                        Ok(None) => (),
+                        Err(error) => {
+                            log::error!(
+                                "{}",
+                                error.display_chain_with_msg("Connectivity monitor failed")
+                            );
+                        }
                     }
                 }
                 Ok(false) => log::warn!("Timeout while checking tunnel connection"),
@@ -308,6 +374,105 @@ impl WireguardMonitor {
         Ok(monitor)
     }
 
+    /// Switches the tunnel to an ephemeral key and a preshared key that is negotiated with the
+    /// relay using post-quantum KEMs. The peers perform a new handshake with the new keys, so
+    /// connectivity is established again afterwards.
+    fn negotiate_psk(
+        runtime: &tokio::runtime::Handle,
+        tunnel_handle: &Weak<Mutex<Option<Box<dyn Tunnel>>>>,
+        config: &mut Config,
+        connectivity_monitor: &mut connectivity_check::ConnectivityMonitor,
+        cancel_token: &CancelToken,
+    ) -> Result<()> {
+        use talpid_types::net::wireguard::PrivateKey;
+
+        if config.peers.len() > 1 {
+            return Err(Error::PskMultihopError);
+        }
+
+        let started = Instant::now();
+        let ephemeral_private_key = PrivateKey::new_from_random();
+        let negotiation = psk_negotiation::negotiate_psk(
+            IpAddr::V4(config.ipv4_gateway),
+            config.tunnel.private_key.public_key(),
+            ephemeral_private_key.public_key(),
+        );
+        let psk = match runtime.block_on(cancel_token.run(negotiation)) {
+            Ok(result) => result.map_err(Error::PskNegotiationError)?,
+            // The tunnel is closing
+            Err(_) => return Ok(()),
+        };
+
+        config.tunnel.private_key = ephemeral_private_key;
+        config.peers[0].psk = Some(psk);
+        {
+            let tunnel = match tunnel_handle.upgrade() {
+                Some(tunnel) => tunnel,
+                None => return Ok(()),
+            };
+            let mut tunnel = tunnel.lock().expect("Tunnel lock poisoned");
+            match tunnel.as_mut() {
+                Some(tunnel) => tunnel.set_config(config).map_err(Error::TunnelError)?,
+                None => return Ok(()),
+            }
+        }
+
+        connectivity_monitor.reset();
+        match connectivity_monitor.establish_connectivity() {
+            Ok(true) => {
+                log::debug!(
+                    "Negotiated a quantum-resistant preshared key in {} ms",
+                    started.elapsed().as_millis()
+                );
+                Ok(())
+            }
+            Ok(false) => Err(Error::PskTimeoutError),
+            Err(error) => Err(Error::ConnectivityMonitorError(error)),
+        }
+    }
+
+    /// Sends the traffic for the first peer through the selected obfuscator, if any.
+    fn start_obfuscator(
+        runtime: &tokio::runtime::Handle,
+        config: &mut Config,
+        endpoint_addrs: &mut [IpAddr],
+        log_path: Option<&Path>,
+        resource_dir: &Path,
+    ) -> Result<Option<Box<dyn Obfuscator>>> {
+        let obfuscator_config = match &config.obfuscator {
+            Some(obfuscator_config) => obfuscator_config.clone(),
+            None => return Ok(None),
+        };
+        let peer = &mut config.peers[0];
+        if let ObfuscatorConfig::Bridge(_) = obfuscator_config {
+            if peer.protocol != TransportProtocol::Tcp {
+                return Err(Error::BridgeRequiresTcp);
+            }
+        } else if peer.protocol == TransportProtocol::Tcp {
+            log::warn!("Not using obfuscation since the peer is reached over TCP");
+            return Ok(None);
+        }
+
+        let obfuscator = obfuscation::start_obfuscator(
+            runtime,
+            &obfuscator_config,
+            peer.endpoint,
+            resource_dir,
+            log_path.and_then(Path::parent),
+        )
+        .map_err(Error::ObfuscationError)?;
+        log::debug!(
+            "Sending WireGuard traffic through obfuscation server at {}",
+            obfuscator.remote_endpoint()
+        );
+
+        // Replace remote peer with proxy
+        peer.endpoint = obfuscator.endpoint();
+        endpoint_addrs[0] = obfuscator.remote_endpoint().ip();
+        config.mtu = obfuscator.tunnel_mtu(config.mtu);
+        Ok(Some(obfuscator))
+    }
+
     #[allow(unused_variables)]
     fn open_tunnel(
         config: &Config,
@@ -317,7 +482,7 @@ impl WireguardMonitor {
         route_manager: &mut routing::RouteManager,
     ) -> Result<Box<dyn Tunnel>> {
         #[cfg(target_os = "linux")]
-        if !*FORCE_USERSPACE_WIREGUARD {
+        if !*FORCE_USERSPACE_WIREGUARD && !config.use_userspace_wireguard {
             if crate::dns::will_use_nm() {
                 match wireguard_kernel::NetworkManagerTunnel::new(
                     route_manager.runtime_handle(),
@@ -403,11 +568,7 @@ impl WireguardMonitor {
             Err(_) => Ok(()),
         };
 
-        #[cfg(windows)]
-        if let Some(stop_tx) = self.stop_setup_tx.take() {
-            let _ = stop_tx.send(());
-        }
-        let _ = self.pinger_stop_sender.send(());
+        self.cancel_token.cancel();
 
         self.stop_tunnel();
 
@@ -528,7 +689,31 @@ impl WireguardMonitor {
             }))
     }
 
-    #[cfg(all(not(target_os = "linux"), not(windows)))]
+    #[cfg(target_os = "macos")]
+    fn get_in_tunnel_routes<'a>(
+        iface_name: &str,
+        config: &'a Config,
+    ) -> impl Iterator<Item = RequiredRoute> + 'a {
+        let node = routing::Node::device(iface_name.to_string());
+        let scoped_node = node.clone();
+
+        // Route traffic from the tunnel addresses through the tunnel, even if another interface,
+        // such as that of another VPN, claims the default route
+        let mut scoped_destinations: Vec<ipnetwork::IpNetwork> = vec!["0.0.0.0/0".parse().unwrap()];
+        if config.ipv6_gateway.is_some() {
+            scoped_destinations.push("::/0".parse().unwrap());
+        }
+
+        Self::get_tunnel_destinations(config)
+            .map(move |network| RequiredRoute::new(network, node.clone()))
+            .chain(
+                scoped_destinations
+                    .into_iter()
+                    .map(move |network| RequiredRoute::new(network, scoped_node.clone()).scoped()),
+            )
+    }
+
+    #[cfg(target_os = "android")]
     fn get_in_tunnel_routes<'a>(
         iface_name: &str,
         config: &'a Config,
@@ -548,6 +733,12 @@ impl WireguardMonitor {
     }
 }
 
+impl Drop for WireguardMonitor {
+    fn drop(&mut self) {
+        self.cancel_token.cancel();
+    }
+}
+
 enum CloseMsg {
     Stop,
     PingErr,
@@ -575,6 +766,7 @@ pub(crate) trait Tunnel: Send {
     fn get_interface_luid(&self) -> u64;
     fn stop(self: Box<Self>) -> std::result::Result<(), TunnelError>;
     fn get_tunnel_stats(&self) -> std::result::Result<stats::StatsMap, TunnelError>;
+    fn set_config(&mut self, config: &Config) -> std::result::Result<(), TunnelError>;
 }
 
 /// Errors to be returned from WireGuard implementations, namely implementers of the Tunnel trait
@@ -611,6 +803,10 @@ pub enum TunnelError {
     #[error(display = "Failed to get config of WireGuard tunnel")]
     GetConfigError,
 
+    /// Error whilst trying to update the config of a WireGuard tunnel
+    #[error(display = "Failed to set config of WireGuard tunnel")]
+    SetConfigError,
+
     /// Failed to duplicate tunnel file descriptor for wireguard-go
     #[cfg(any(target_os = "linux", target_os = "macos", target_os = "android"))]
     #[error(display = "Failed to duplicate tunnel file descriptor for wireguard-go")]
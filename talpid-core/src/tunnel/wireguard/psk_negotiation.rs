@@ -0,0 +1,142 @@
+//! Negotiation of a WireGuard preshared key with the relay, over a gRPC call inside the tunnel.
+//! The preshared key is combined from secrets that the relay encapsulates with post-quantum KEMs,
+//! so that recorded traffic stays secret even if X25519 is broken by a quantum computer later.
+use std::{
+    convert::TryFrom,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+use talpid_types::net::wireguard::{PresharedKey, PublicKey};
+use tonic::transport::Endpoint;
+use zeroize::Zeroize;
+
+mod proto {
+    tonic::include_proto!("tunnel_config");
+}
+use proto::{tunnel_config_client::TunnelConfigClient, KemPubkeyV1, PskRequestV1};
+
+/// Port of the config service on the tunnel gateway.
+const CONFIG_SERVICE_PORT: u16 = 1337;
+
+/// How long to wait for the relay to reply, including the time it takes to connect.
+const NEGOTIATION_TIMEOUT: Duration = Duration::from_secs(8);
+
+const CLASSIC_MCELIECE_ALGORITHM: &str = "Classic-McEliece-460896f";
+const KYBER_ALGORITHM: &str = "Kyber1024";
+
+/// Errors that can happen while negotiating a preshared key.
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Failed to connect to the config service.
+    #[error(display = "Failed to connect to the config service on the relay")]
+    ConnectError(#[error(source)] tonic::transport::Error),
+
+    /// The config service returned an error.
+    #[error(display = "The relay failed to negotiate a preshared key")]
+    RequestError(#[error(source)] tonic::Status),
+
+    /// The relay did not reply in time.
+    #[error(display = "Timed out while negotiating a preshared key")]
+    Timeout,
+
+    /// The relay did not return one ciphertext per KEM public key.
+    #[error(display = "Expected 2 ciphertexts from the relay, got {}", _0)]
+    InvalidCiphertextCount(usize),
+
+    /// A ciphertext could not be decapsulated.
+    #[error(display = "Invalid {} ciphertext", _0)]
+    InvalidCiphertext(&'static str),
+
+    /// The key generation task panicked.
+    #[error(display = "Failed to generate KEM keys")]
+    KeyGenerationError,
+}
+
+/// Negotiates a preshared key for `ephemeral_pubkey` with the relay that has the tunnel gateway
+/// `gateway`. The tunnel must be up and use the key `current_pubkey`. Once the relay has replied,
+/// it expects the tunnel to be reconfigured to use the ephemeral key and the returned preshared
+/// key.
+pub async fn negotiate_psk(
+    gateway: IpAddr,
+    current_pubkey: PublicKey,
+    ephemeral_pubkey: PublicKey,
+) -> Result<PresharedKey, Error> {
+    tokio::time::timeout(
+        NEGOTIATION_TIMEOUT,
+        negotiate_psk_inner(gateway, current_pubkey, ephemeral_pubkey),
+    )
+    .await
+    .map_err(|_| Error::Timeout)?
+}
+
+async fn negotiate_psk_inner(
+    gateway: IpAddr,
+    current_pubkey: PublicKey,
+    ephemeral_pubkey: PublicKey,
+) -> Result<PresharedKey, Error> {
+    // Generating a Classic McEliece key pair takes a while and uses a lot of stack
+    let (mceliece_pubkey, mceliece_secret) =
+        tokio::task::spawn_blocking(|| classic_mceliece_rust::keypair_boxed(&mut rand_core::OsRng))
+            .await
+            .map_err(|_| Error::KeyGenerationError)?;
+    let kyber_keypair = pqc_kyber::keypair(&mut rand_core::OsRng);
+
+    let uri = format!("http://{}", SocketAddr::new(gateway, CONFIG_SERVICE_PORT));
+    let channel = Endpoint::from_shared(uri)
+        .map_err(Error::ConnectError)?
+        .connect()
+        .await
+        .map_err(Error::ConnectError)?;
+    let mut client = TunnelConfigClient::new(channel);
+
+    let response = client
+        .psk_exchange_v1(PskRequestV1 {
+            wg_pubkey: current_pubkey.as_bytes().to_vec(),
+            wg_psk_pubkey: ephemeral_pubkey.as_bytes().to_vec(),
+            kem_pubkeys: vec![
+                KemPubkeyV1 {
+                    algorithm_name: CLASSIC_MCELIECE_ALGORITHM.to_owned(),
+                    key_data: mceliece_pubkey.as_array().to_vec(),
+                },
+                KemPubkeyV1 {
+                    algorithm_name: KYBER_ALGORITHM.to_owned(),
+                    key_data: kyber_keypair.public.to_vec(),
+                },
+            ],
+        })
+        .await
+        .map_err(Error::RequestError)?;
+
+    let ciphertexts = response.into_inner().ciphertexts;
+    let (mceliece_ciphertext, kyber_ciphertext) = match ciphertexts.as_slice() {
+        [mceliece_ciphertext, kyber_ciphertext] => (mceliece_ciphertext, kyber_ciphertext),
+        _ => return Err(Error::InvalidCiphertextCount(ciphertexts.len())),
+    };
+
+    let mut psk = Box::new([0u8; 32]);
+
+    let mceliece_ciphertext = <[u8; classic_mceliece_rust::CRYPTO_CIPHERTEXTBYTES]>::try_from(
+        mceliece_ciphertext.as_slice(),
+    )
+    .map_err(|_| Error::InvalidCiphertext(CLASSIC_MCELIECE_ALGORITHM))?;
+    let mceliece_shared_secret = classic_mceliece_rust::decapsulate_boxed(
+        &classic_mceliece_rust::Ciphertext::from(mceliece_ciphertext),
+        &mceliece_secret,
+    );
+    xor_into(&mut psk, mceliece_shared_secret.as_array());
+
+    let mut kyber_shared_secret = pqc_kyber::decapsulate(kyber_ciphertext, &kyber_keypair.secret)
+        .map_err(|_| Error::InvalidCiphertext(KYBER_ALGORITHM))?;
+    xor_into(&mut psk, &kyber_shared_secret);
+    kyber_shared_secret.zeroize();
+
+    Ok(PresharedKey::from(psk))
+}
+
+/// Combines the secrets, so that the preshared key is secret as long as either KEM is unbroken.
+fn xor_into(psk: &mut [u8; 32], secret: &[u8; 32]) {
+    for (psk_byte, secret_byte) in psk.iter_mut().zip(secret.iter()) {
+        *psk_byte ^= secret_byte;
+    }
+}
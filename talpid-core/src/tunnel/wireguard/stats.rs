@@ -1,5 +1,5 @@
 #[cfg(target_os = "linux")]
-use super::wireguard_kernel::wg_message::{DeviceMessage, DeviceNla, PeerNla};
+use super::wireguard_kernel::wg_message::DeviceStatsMessage;
 
 
 #[derive(err_derive::Error, Debug, PartialEq)]
@@ -93,32 +93,20 @@ impl Stats {
     }
 
     #[cfg(target_os = "linux")]
-    pub fn parse_device_message(message: &DeviceMessage) -> StatsMap {
-        let mut map = StatsMap::new();
-
-        for nla in &message.nlas {
-            if let DeviceNla::Peers(peers) = nla {
-                for msg in peers {
-                    let mut tx_bytes = 0;
-                    let mut rx_bytes = 0;
-                    let mut pub_key = None;
-
-                    for nla in &msg.0 {
-                        match nla {
-                            PeerNla::TxBytes(bytes) => tx_bytes = *bytes,
-                            PeerNla::RxBytes(bytes) => rx_bytes = *bytes,
-                            PeerNla::PublicKey(key) => pub_key = Some(*key),
-                            _ => continue,
-                        }
-                    }
-                    if let Some(key) = pub_key {
-                        map.insert(key, Stats { tx_bytes, rx_bytes });
-                    }
-                }
-            }
-        }
-
-        map
+    pub fn parse_device_stats_message(message: &DeviceStatsMessage) -> StatsMap {
+        message
+            .peers
+            .iter()
+            .map(|peer| {
+                (
+                    peer.public_key,
+                    Stats {
+                        tx_bytes: peer.tx_bytes,
+                        rx_bytes: peer.rx_bytes,
+                    },
+                )
+            })
+            .collect()
     }
 }
 
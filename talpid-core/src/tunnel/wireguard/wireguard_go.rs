@@ -120,19 +120,17 @@ impl WgGoTunnel {
         }
 
         let wg_config_str = config.to_userspace_format();
-        let iface_name: String = "Mullvad".to_string();
+        let iface_name = config
+            .interface_name
+            .clone()
+            .unwrap_or_else(|| crate::tunnel::DEFAULT_ADAPTER_ALIAS.to_string());
         let cstr_iface_name =
             CString::new(iface_name.as_bytes()).map_err(TunnelError::InterfaceNameError)?;
         let logging_context = initialize_logging(log_path)
             .map(LoggingContext)
             .map_err(TunnelError::LoggingError)?;
 
-        let wait_on_ipv6 = config.ipv6_gateway.is_some()
-            || config.tunnel.addresses.iter().any(|ip| ip.is_ipv6())
-            || config
-                .peers
-                .iter()
-                .any(|config| config.allowed_ips.iter().any(|ip| ip.is_ipv6()));
+        let wait_on_ipv6 = config.enable_ipv6;
 
         let mut alias_ptr = std::ptr::null_mut();
         let mut interface_luid = 0u64;
@@ -218,6 +216,8 @@ impl WgGoTunnel {
             #[cfg(target_os = "android")]
             required_routes: Self::create_required_routes(config),
             mtu: config.mtu,
+            #[cfg(not(target_os = "android"))]
+            name: config.interface_name.clone(),
         }
     }
 
@@ -335,6 +335,16 @@ impl Tunnel for WgGoTunnel {
     fn stop(mut self: Box<Self>) -> Result<()> {
         self.stop_tunnel()
     }
+
+    fn set_config(&mut self, config: &Config) -> Result<()> {
+        let handle = self.handle.ok_or(TunnelError::SetConfigError)?;
+        let wg_config_str = config.to_userspace_format();
+        let status = unsafe { wgSetConfig(handle, wg_config_str.as_ptr() as *const i8) };
+        if status != 0 {
+            return Err(TunnelError::SetConfigError);
+        }
+        Ok(())
+    }
 }
 
 fn check_wg_status(wg_code: i32) -> Result<()> {
@@ -401,9 +411,12 @@ extern "C" {
     // Pass a handle that was created by wgTurnOn to stop a wireguard tunnel.
     fn wgTurnOff(handle: i32) -> i32;
 
-    // Returns the file descriptor of the tunnel IPv4 socket.
+    // Returns the config of the tunnel, in the userspace format.
     fn wgGetConfig(handle: i32) -> *mut std::os::raw::c_char;
 
+    // Sets the config of the tunnel, in the userspace format. Returns 0 on success.
+    fn wgSetConfig(handle: i32, settings: *const i8) -> i32;
+
     // Frees a pointer allocated by the go runtime - useful to free return value of wgGetConfig
     fn wgFreePtr(ptr: *mut c_void);
 
@@ -20,7 +20,7 @@ use tokio_stream::StreamExt;
 mod parsers;
 
 pub mod wg_message;
-use wg_message::{DeviceMessage, DeviceNla};
+use wg_message::{DeviceMessage, DeviceNla, DeviceStatsMessage};
 pub mod nl_message;
 use nl_message::{ControlNla, NetlinkControlMessage};
 
@@ -72,6 +72,9 @@ pub enum Error {
     #[error(display = "Send request error")]
     SendRequestError(#[error(source)] NetlinkError<DeviceMessage>),
 
+    #[error(display = "Send stats request error")]
+    SendStatsRequestError(#[error(source)] NetlinkError<DeviceStatsMessage>),
+
     #[error(display = "Create device error")]
     NetlinkCreateDeviceError(#[error(source)] rtnetlink::Error),
 
@@ -87,11 +90,20 @@ pub enum Error {
 
 pub(crate) const MULLVAD_INTERFACE_NAME: &str = "wg-mullvad";
 
+/// Returns the name of the WireGuard interface to create for the given config.
+fn interface_name(config: &Config) -> String {
+    config
+        .interface_name
+        .clone()
+        .unwrap_or_else(|| MULLVAD_INTERFACE_NAME.to_string())
+}
+
 #[derive(Debug)]
 pub struct Handle {
     pub wg_handle: WireguardConnection,
     route_handle: rtnetlink::Handle,
     wg_abort_handle: AbortHandle,
+    wg_stats_abort_handle: AbortHandle,
     route_abort_handle: AbortHandle,
 }
 
@@ -101,12 +113,17 @@ impl Handle {
         let message_type = Self::get_wireguard_message_type().await?;
         let (conn, wireguard_connection, _messages) =
             netlink_proto::new_connection(NETLINK_GENERIC).map_err(Error::NetlinkSocketError)?;
+        let (abortable_connection, wg_abort_handle) = abortable(conn);
+        tokio::spawn(abortable_connection);
+        let (conn, stats_connection, _messages) =
+            netlink_proto::new_connection(NETLINK_GENERIC).map_err(Error::NetlinkSocketError)?;
+        let (abortable_connection, wg_stats_abort_handle) = abortable(conn);
+        tokio::spawn(abortable_connection);
         let wg_handle = WireguardConnection {
             message_type,
             connection: wireguard_connection,
+            stats_connection,
         };
-        let (abortable_connection, wg_abort_handle) = abortable(conn);
-        tokio::spawn(abortable_connection);
         let (conn, route_handle, _messages) =
             rtnetlink::new_connection().map_err(Error::NetlinkSocketError)?;
         let (abortable_connection, route_abort_handle) = abortable(conn);
@@ -117,6 +134,7 @@ impl Handle {
             wg_handle,
             route_handle,
             wg_abort_handle,
+            wg_stats_abort_handle,
             route_abort_handle,
         })
     }
@@ -243,6 +261,7 @@ impl Handle {
 impl Drop for Handle {
     fn drop(&mut self) {
         self.wg_abort_handle.abort();
+        self.wg_stats_abort_handle.abort();
         self.route_abort_handle.abort();
     }
 }
@@ -250,6 +269,7 @@ impl Drop for Handle {
 #[derive(Debug, Clone)]
 pub struct WireguardConnection {
     connection: ConnectionHandle<DeviceMessage>,
+    stats_connection: ConnectionHandle<DeviceStatsMessage>,
     message_type: u16,
 }
 
@@ -294,6 +314,48 @@ impl WireguardConnection {
         }
     }
 
+    pub async fn get_stats_by_name(&mut self, name: String) -> Result<DeviceStatsMessage, Error> {
+        self.fetch_stats(DeviceStatsMessage::get_by_name(self.message_type, name)?)
+            .await
+    }
+
+    pub async fn get_stats_by_index(&mut self, index: u32) -> Result<DeviceStatsMessage, Error> {
+        self.fetch_stats(DeviceStatsMessage::get_by_index(self.message_type, index))
+            .await
+    }
+
+    /// Fetches only the traffic counters of each peer, which is much cheaper than fetching and
+    /// decoding the whole device configuration with `fetch_device`.
+    pub async fn fetch_stats(
+        &mut self,
+        stats_message: DeviceStatsMessage,
+    ) -> Result<DeviceStatsMessage, Error> {
+        let mut netlink_message = NetlinkMessage::from(stats_message);
+        netlink_message.header.flags = NLM_F_REQUEST | NLM_F_ACK | NLM_F_DUMP;
+
+        let mut response = self
+            .stats_connection
+            .request(netlink_message, SocketAddr::new(0, 0))
+            .map_err(Error::SendStatsRequestError)?;
+        match response.next().await {
+            Some(received_message) => match received_message.payload {
+                NetlinkPayload::InnerMessage(inner) => Ok(inner),
+                NetlinkPayload::Error(err) => {
+                    if err.code == -libc::ENODEV {
+                        Err(Error::NoDevice)
+                    } else {
+                        Err(Error::WgGetConfError(err))
+                    }
+                }
+                anything_else => {
+                    log::error!("Received unexpected response - {:?}", anything_else);
+                    Err(Error::NoResponse)
+                }
+            },
+            None => Err(Error::NoResponse),
+        }
+    }
+
     pub async fn set_config(&mut self, interface_index: u32, config: &Config) -> Result<(), Error> {
         let message = DeviceMessage::reset_config(self.message_type, interface_index, config);
         let mut netlink_message = NetlinkMessage::from(message);
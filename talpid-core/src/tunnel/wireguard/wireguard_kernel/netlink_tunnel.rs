@@ -1,12 +1,14 @@
 use super::{
     super::stats::{Stats, StatsMap},
+    interface_name,
     wg_message::DeviceNla,
-    Config, Error, Handle, Tunnel, TunnelError, MULLVAD_INTERFACE_NAME,
+    Config, Error, Handle, Tunnel, TunnelError,
 };
 
 
 pub struct NetlinkTunnel {
     interface_index: u32,
+    interface_name: String,
     netlink_connections: Handle,
     tokio_handle: tokio::runtime::Handle,
 }
@@ -15,12 +17,14 @@ impl NetlinkTunnel {
     pub fn new(tokio_handle: tokio::runtime::Handle, config: &Config) -> Result<Self, Error> {
         tokio_handle.clone().block_on(async {
             let mut netlink_connections = Handle::connect().await?;
+            let interface_name = interface_name(config);
             let interface_index = netlink_connections
-                .create_device(MULLVAD_INTERFACE_NAME.to_string(), config.mtu as u32)
+                .create_device(interface_name.clone(), config.mtu as u32)
                 .await?;
 
             let mut tunnel = Self {
                 interface_index,
+                interface_name,
                 netlink_connections,
                 tokio_handle,
             };
@@ -76,8 +80,8 @@ impl Tunnel for NetlinkTunnel {
         match result {
             Ok(name) => name.to_string_lossy().to_string(),
             Err(err) => {
-                log::error!("Failed to deduce interface name at runtime, will attempt to use the default name. {}", err);
-                MULLVAD_INTERFACE_NAME.to_string()
+                log::error!("Failed to deduce interface name at runtime, will attempt to use the configured name. {}", err);
+                self.interface_name.clone()
             }
         }
     }
@@ -87,6 +91,7 @@ impl Tunnel for NetlinkTunnel {
             mut netlink_connections,
             interface_index,
             tokio_handle,
+            ..
         } = *self;
         tokio_handle.block_on(async move {
             if let Err(err) = netlink_connections.delete_device(interface_index).await {
@@ -102,13 +107,27 @@ impl Tunnel for NetlinkTunnel {
         let mut wg = self.netlink_connections.wg_handle.clone();
         let interface_index = self.interface_index;
         let result = self.tokio_handle.block_on(async move {
-            let device = wg.get_by_index(interface_index).await.map_err(|err| {
-                log::error!("Failed to fetch WireGuard device config: {}", err);
-                TunnelError::GetConfigError
-            })?;
-            Ok(Stats::parse_device_message(&device))
+            let device = wg
+                .get_stats_by_index(interface_index)
+                .await
+                .map_err(|err| {
+                    log::error!("Failed to fetch WireGuard device stats: {}", err);
+                    TunnelError::GetConfigError
+                })?;
+            Ok(Stats::parse_device_stats_message(&device))
         });
 
         result
     }
+
+    fn set_config(&mut self, config: &Config) -> std::result::Result<(), TunnelError> {
+        let mut wg = self.netlink_connections.wg_handle.clone();
+        let interface_index = self.interface_index;
+        self.tokio_handle.block_on(async move {
+            wg.set_config(interface_index, config).await.map_err(|err| {
+                log::error!("Failed to set WireGuard device config: {}", err);
+                TunnelError::SetConfigError
+            })
+        })
+    }
 }
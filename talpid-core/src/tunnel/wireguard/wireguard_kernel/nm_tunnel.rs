@@ -1,6 +1,8 @@
 use super::{
     super::stats::{Stats, StatsMap},
-    Config, Error as WgKernelError, Handle, Tunnel, TunnelError, MULLVAD_INTERFACE_NAME,
+    interface_name,
+    wg_message::DeviceNla,
+    Config, Error as WgKernelError, Handle, Tunnel, TunnelError,
 };
 use std::collections::HashMap;
 use talpid_dbus::{
@@ -47,7 +49,7 @@ impl NetworkManagerTunnel {
             Ok(name) => name,
             Err(error) => {
                 log::error!("Failed to fetch interface name from NM: {}", error);
-                MULLVAD_INTERFACE_NAME.to_string()
+                interface_name(config)
             }
         };
         let netlink_connections = tokio_handle.block_on(Handle::connect())?;
@@ -84,13 +86,38 @@ impl Tunnel for NetworkManagerTunnel {
         let mut wg = self.netlink_connections.wg_handle.clone();
         self.tokio_handle.block_on(async move {
             let device = wg
-                .get_by_name(self.interface_name.clone())
+                .get_stats_by_name(self.interface_name.clone())
                 .await
                 .map_err(|err| {
-                    log::error!("Failed to fetch WireGuard device config: {}", err);
+                    log::error!("Failed to fetch WireGuard device stats: {}", err);
                     TunnelError::GetConfigError
                 })?;
-            Ok(Stats::parse_device_message(&device))
+            Ok(Stats::parse_device_stats_message(&device))
+        })
+    }
+
+    fn set_config(&mut self, config: &Config) -> std::result::Result<(), TunnelError> {
+        // NetworkManager only applies a config when the connection is activated, so the device
+        // that it created is updated directly
+        let mut wg = self.netlink_connections.wg_handle.clone();
+        let interface_name = self.interface_name.clone();
+        self.tokio_handle.block_on(async move {
+            let device = wg.get_by_name(interface_name).await.map_err(|err| {
+                log::error!("Failed to fetch WireGuard device: {}", err);
+                TunnelError::SetConfigError
+            })?;
+            let interface_index = device
+                .nlas
+                .iter()
+                .find_map(|nla| match nla {
+                    DeviceNla::IfIndex(index) => Some(*index),
+                    _ => None,
+                })
+                .ok_or(TunnelError::SetConfigError)?;
+            wg.set_config(interface_index, config).await.map_err(|err| {
+                log::error!("Failed to set WireGuard device config: {}", err);
+                TunnelError::SetConfigError
+            })
         })
     }
 }
@@ -135,13 +162,10 @@ fn convert_config_to_dbus(config: &Config) -> DeviceConfig {
     wireguard_config.insert("peers".into(), Variant(Box::new(peer_configs)));
 
     connection_config.insert("type".into(), Variant(Box::new("wireguard".to_string())));
-    connection_config.insert(
-        "id".into(),
-        Variant(Box::new(MULLVAD_INTERFACE_NAME.to_string())),
-    );
+    connection_config.insert("id".into(), Variant(Box::new(interface_name(config))));
     connection_config.insert(
         "interface-name".into(),
-        Variant(Box::new(MULLVAD_INTERFACE_NAME.to_string())),
+        Variant(Box::new(interface_name(config))),
     );
     connection_config.insert("autoconnect".into(), Variant(Box::new(true)));
 
@@ -82,12 +82,16 @@ impl DeviceMessage {
         for peer in config.peers.iter() {
             let peer_endpoint = InetAddr::from_std(&peer.endpoint);
             let allowed_ips = peer.allowed_ips.iter().map(From::from).collect();
-            peers.push(PeerMessage(vec![
+            let mut peer_nlas = vec![
                 PeerNla::PublicKey(*peer.public_key.as_bytes()),
                 PeerNla::Endpoint(peer_endpoint),
                 PeerNla::AllowedIps(allowed_ips),
                 PeerNla::Flags(WGPEER_F_REPLACE_ALLOWEDIPS),
-            ]));
+            ];
+            if let Some(psk) = &peer.psk {
+                peer_nlas.push(PeerNla::PresharedKey(*psk.as_bytes()));
+            }
+            peers.push(PeerMessage(peer_nlas));
         }
 
         let nlas = vec![
@@ -188,6 +192,117 @@ impl NetlinkDeserializable<DeviceMessage> for DeviceMessage {
     }
 }
 
+/// Reply to a `WG_CMD_GET_DEVICE` request that only keeps the public key and traffic counters of
+/// each peer. All other attributes, including the private key and the allowed IPs, are skipped
+/// without being decoded, which keeps polling the counters cheap.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DeviceStatsMessage {
+    /// Attributes that identify the device in a request. Empty in replies.
+    pub device: Vec<DeviceNla>,
+    pub peers: Vec<PeerStats>,
+    pub message_type: u16,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PeerStats {
+    pub public_key: PublicKey,
+    pub tx_bytes: u64,
+    pub rx_bytes: u64,
+}
+
+impl DeviceStatsMessage {
+    pub fn get_by_name(message_type: u16, name: String) -> Result<Self, Error> {
+        Ok(Self {
+            device: DeviceMessage::get_by_name(message_type, name)?.nlas,
+            peers: vec![],
+            message_type,
+        })
+    }
+
+    pub fn get_by_index(message_type: u16, index: u32) -> Self {
+        Self {
+            device: vec![DeviceNla::IfIndex(index)],
+            peers: vec![],
+            message_type,
+        }
+    }
+
+    fn parse_peers(payload: &[u8]) -> Result<Vec<PeerStats>, DecodeError> {
+        let mut peers = vec![];
+        for peer in NlasIterator::new(payload) {
+            let peer = peer?;
+            let mut public_key = None;
+            let mut tx_bytes = 0;
+            let mut rx_bytes = 0;
+            for nla in NlasIterator::new(peer.value()) {
+                let nla = nla?;
+                match nla.kind() {
+                    WGPEER_A_PUBLIC_KEY => {
+                        public_key = Some(parsers::parse_wg_key(nla.value())?.into())
+                    }
+                    WGPEER_A_TX_BYTES => tx_bytes = parsers::parse_u64(nla.value())?,
+                    WGPEER_A_RX_BYTES => rx_bytes = parsers::parse_u64(nla.value())?,
+                    _ => continue,
+                }
+            }
+            if let Some(public_key) = public_key {
+                peers.push(PeerStats {
+                    public_key,
+                    tx_bytes,
+                    rx_bytes,
+                });
+            }
+        }
+        Ok(peers)
+    }
+}
+
+impl NetlinkSerializable<DeviceStatsMessage> for DeviceStatsMessage {
+    fn message_type(&self) -> u16 {
+        self.message_type
+    }
+
+    fn buffer_len(&self) -> usize {
+        mem::size_of::<libc::genlmsghdr>() + self.device.as_slice().buffer_len()
+    }
+
+    fn serialize(&self, mut buffer: &mut [u8]) {
+        let command_buf = [WG_CMD_GET_DEVICE, WG_GENL_VERSION, 0u8, 0u8];
+        let _ = buffer.write(&command_buf).unwrap();
+        self.device.as_slice().emit(&mut buffer)
+    }
+}
+
+impl Into<NetlinkPayload<DeviceStatsMessage>> for DeviceStatsMessage {
+    fn into(self) -> NetlinkPayload<DeviceStatsMessage> {
+        NetlinkPayload::InnerMessage(self)
+    }
+}
+
+impl NetlinkDeserializable<DeviceStatsMessage> for DeviceStatsMessage {
+    type Error = Error;
+    fn deserialize(
+        header: &NetlinkHeader,
+        payload: &[u8],
+    ) -> Result<DeviceStatsMessage, Self::Error> {
+        DeviceMessage::read_genlmsghdr(payload)?;
+        let new_payload = &payload[mem::size_of::<libc::genlmsghdr>()..];
+        let mut peers = vec![];
+        for buf in NlasIterator::new(new_payload) {
+            let buf = buf.map_err(Error::DecodeError)?;
+            if buf.kind() == WGDEVICE_A_PEERS {
+                peers.extend(Self::parse_peers(buf.value()).map_err(Error::DecodeError)?);
+            }
+        }
+
+        Ok(DeviceStatsMessage {
+            device: vec![],
+            peers,
+            message_type: header.message_type,
+        })
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum DeviceNla {
     IfIndex(u32),
@@ -595,6 +710,65 @@ mod test {
 
     #[test]
     fn deserialize_netlink_message() {
+        let payload = sample_get_payload();
+        let header = NetlinkHeader {
+            length: payload.len() as u32,
+            message_type: 0,
+            flags: 0,
+            sequence_number: 0,
+            port_number: 0,
+        };
+        let message = DeviceMessage::deserialize(&header, &payload).unwrap();
+
+        let mut serialized_message = vec![0u8; payload.len()];
+
+        message.serialize(&mut serialized_message);
+
+        assert_eq!(message, sample_get_message());
+        assert_eq!(&payload, &serialized_message)
+    }
+
+    #[test]
+    fn deserialize_stats_message() {
+        let payload = sample_get_payload();
+        let header = NetlinkHeader {
+            length: payload.len() as u32,
+            message_type: 0,
+            flags: 0,
+            sequence_number: 0,
+            port_number: 0,
+        };
+        let message = DeviceStatsMessage::deserialize(&header, &payload).unwrap();
+
+        let expected_peers: Vec<_> = sample_get_message()
+            .nlas
+            .into_iter()
+            .filter_map(|nla| match nla {
+                DeviceNla::Peers(peers) => Some(peers),
+                _ => None,
+            })
+            .flatten()
+            .map(|peer| {
+                let public_key = peer
+                    .0
+                    .into_iter()
+                    .find_map(|nla| match nla {
+                        PeerNla::PublicKey(key) => Some(key),
+                        _ => None,
+                    })
+                    .unwrap();
+                PeerStats {
+                    public_key,
+                    tx_bytes: 0,
+                    rx_bytes: 0,
+                }
+            })
+            .collect();
+        assert_eq!(message.peers, expected_peers);
+        assert!(message.device.is_empty());
+    }
+
+    fn sample_get_payload() -> Vec<u8> {
         #[rustfmt::skip]
         let payload = vec![
             0x00, 0x01, 0x00, 0x00,
@@ -685,21 +859,7 @@ mod test {
                                     // 8 bytes of WGALLOWEDIP_A_IPADDR 192.168.40.2
                                     0x08, 0x00, 0x02, 0x00, 0xc0, 0xa8, 0x27, 0x02,
         ];
-        let header = NetlinkHeader {
-            length: payload.len() as u32,
-            message_type: 0,
-            flags: 0,
-            sequence_number: 0,
-            port_number: 0,
-        };
-        let message = DeviceMessage::deserialize(&header, &payload).unwrap();
-
-        let mut serialized_message = vec![0u8; payload.len()];
-
-        message.serialize(&mut serialized_message);
-
-        assert_eq!(message, sample_get_message());
-        assert_eq!(&payload, &serialized_message)
+        payload
     }
 
     fn sample_get_message() -> DeviceMessage {
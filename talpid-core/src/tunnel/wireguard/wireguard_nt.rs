@@ -4,7 +4,6 @@ use super::{
     stats::{Stats, StatsMap},
     Tunnel,
 };
-use crate::windows;
 use bitflags::bitflags;
 use ipnetwork::IpNetwork;
 use lazy_static::lazy_static;
@@ -17,6 +16,10 @@ use std::{
     sync::{Arc, Mutex},
 };
 use talpid_types::ErrorExt;
+use talpid_windows_net::{
+    in6addr_from_ipaddr, inaddr_from_ipaddr, inet_sockaddr_from_socketaddr, ipaddr_from_in6addr,
+    ipaddr_from_inaddr, try_socketaddr_from_inet_sockaddr, AddressFamily, IpInterface, Luid,
+};
 use widestring::{U16CStr, U16CString};
 use winapi::{
     shared::{
@@ -25,8 +28,6 @@ use winapi::{
         in6addr::IN6_ADDR,
         inaddr::IN_ADDR,
         minwindef::{BOOL, FARPROC, HINSTANCE, HMODULE},
-        nldef::RouterDiscoveryDisabled,
-        ntdef::FALSE,
         winerror::ERROR_MORE_DATA,
         ws2def::{ADDRESS_FAMILY, AF_INET, AF_INET6},
         ws2ipdef::SOCKADDR_INET,
@@ -40,7 +41,8 @@ use winapi::{
 lazy_static! {
     static ref WG_NT_DLL: Mutex<Option<Arc<WgNtDll>>> = Mutex::new(None);
     static ref ADAPTER_POOL: U16CString = U16CString::from_str("Mullvad").unwrap();
-    static ref ADAPTER_ALIAS: U16CString = U16CString::from_str("Mullvad").unwrap();
+    static ref DEFAULT_ADAPTER_ALIAS: U16CString =
+        U16CString::from_str(crate::tunnel::DEFAULT_ADAPTER_ALIAS).unwrap();
 }
 
 const ADAPTER_GUID: GUID = GUID {
@@ -179,7 +181,7 @@ pub enum Error {
 
 pub struct WgNtTunnel {
     device: Option<WgNtAdapter>,
-    interface_luid: NET_LUID,
+    interface_luid: Luid,
     interface_name: String,
     _logger_handle: LoggerHandle,
 }
@@ -250,12 +252,12 @@ impl PartialEq for WgAllowedIp {
         }
         match self.address_family as i32 {
             AF_INET => {
-                windows::ipaddr_from_inaddr(unsafe { self.address.v4 })
-                    == windows::ipaddr_from_inaddr(unsafe { other.address.v4 })
+                ipaddr_from_inaddr(unsafe { self.address.v4 })
+                    == ipaddr_from_inaddr(unsafe { other.address.v4 })
             }
             AF_INET6 => {
-                windows::ipaddr_from_in6addr(unsafe { self.address.v6 })
-                    == windows::ipaddr_from_in6addr(unsafe { other.address.v6 })
+                ipaddr_from_in6addr(unsafe { self.address.v6 })
+                    == ipaddr_from_in6addr(unsafe { other.address.v6 })
             }
             _ => {
                 log::error!("Allowed IP uses unknown address family");
@@ -270,14 +272,8 @@ impl fmt::Debug for WgAllowedIp {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut s = f.debug_struct("WgAllowedIp");
         match self.address_family as i32 {
-            AF_INET => s.field(
-                "address",
-                &windows::ipaddr_from_inaddr(unsafe { self.address.v4 }),
-            ),
-            AF_INET6 => s.field(
-                "address",
-                &windows::ipaddr_from_in6addr(unsafe { self.address.v6 }),
-            ),
+            AF_INET => s.field("address", &ipaddr_from_inaddr(unsafe { self.address.v4 })),
+            AF_INET6 => s.field("address", &ipaddr_from_in6addr(unsafe { self.address.v6 })),
             _ => s.field("address", &"<unknown>"),
         };
         s.field("address_family", &self.address_family)
@@ -328,7 +324,7 @@ impl From<SOCKADDR_INET> for SockAddrInet {
 }
 impl PartialEq for SockAddrInet {
     fn eq(&self, other: &Self) -> bool {
-        let self_addr = match windows::try_socketaddr_from_inet_sockaddr(self.addr) {
+        let self_addr = match try_socketaddr_from_inet_sockaddr(self.addr) {
             Ok(addr) => addr,
             Err(error) => {
                 log::error!(
@@ -338,7 +334,7 @@ impl PartialEq for SockAddrInet {
                 return true;
             }
         };
-        let other_addr = match windows::try_socketaddr_from_inet_sockaddr(other.addr) {
+        let other_addr = match try_socketaddr_from_inet_sockaddr(other.addr) {
             Ok(addr) => addr,
             Err(error) => {
                 log::error!(
@@ -356,7 +352,7 @@ impl Eq for SockAddrInet {}
 impl fmt::Debug for SockAddrInet {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut s = f.debug_struct("SockAddrInet");
-        let self_addr = windows::try_socketaddr_from_inet_sockaddr(self.addr)
+        let self_addr = try_socketaddr_from_inet_sockaddr(self.addr)
             .map(|addr| addr.to_string())
             .unwrap_or("<unknown>".to_string());
         s.field("addr", &self_addr).finish()
@@ -404,8 +400,18 @@ impl WgNtTunnel {
 
         let logger_handle = LoggerHandle::new(dll.clone(), log_path)?;
 
+        let adapter_alias = match &config.interface_name {
+            Some(name) => U16CString::from_str(name).map_err(|_| {
+                Error::CreateTunnelDeviceError(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Adapter alias contains a null character",
+                ))
+            })?,
+            None => DEFAULT_ADAPTER_ALIAS.clone(),
+        };
+
         {
-            if let Ok(device) = WgNtAdapter::open(dll.clone(), &*ADAPTER_POOL, &*ADAPTER_ALIAS) {
+            if let Ok(device) = WgNtAdapter::open(dll.clone(), &*ADAPTER_POOL, &adapter_alias) {
                 device.delete().map_err(Error::DeleteExistingTunnelError)?;
             }
         }
@@ -413,7 +419,7 @@ impl WgNtTunnel {
         let (device, reboot_required) = WgNtAdapter::create(
             dll.clone(),
             &*ADAPTER_POOL,
-            &*ADAPTER_ALIAS,
+            &adapter_alias,
             Some(ADAPTER_GUID.clone()),
         )
         .map_err(Error::CreateTunnelDeviceError)?;
@@ -464,10 +470,10 @@ impl WgNtTunnel {
             );
         }
         device.set_config(config)?;
-        prepare_interface(&device.luid(), AF_INET as u16, u32::from(config.mtu))
+        prepare_interface(device.luid(), AddressFamily::Ipv4, u32::from(config.mtu))
             .map_err(Error::SetTunnelIpv4MtuError)?;
         if config.tunnel.addresses.iter().any(|addr| addr.is_ipv6()) {
-            prepare_interface(&device.luid(), AF_INET6 as u16, u32::from(config.mtu))
+            prepare_interface(device.luid(), AddressFamily::Ipv6, u32::from(config.mtu))
                 .map_err(Error::SetTunnelIpv6MtuError)?;
         }
         device
@@ -573,8 +579,8 @@ impl WgNtAdapter {
         unsafe { self.dll_handle.get_adapter_name(self.handle) }
     }
 
-    fn luid(&self) -> NET_LUID {
-        unsafe { self.dll_handle.get_adapter_luid(self.handle) }
+    fn luid(&self) -> Luid {
+        Luid::from(unsafe { self.dll_handle.get_adapter_luid(self.handle) })
     }
 
     fn set_config(&self, config: &Config) -> Result<()> {
@@ -632,6 +638,8 @@ unsafe impl Sync for WgNtDll {}
 
 impl WgNtDll {
     pub fn new(resource_dir: &Path) -> io::Result<Self> {
+        crate::windows::check_driver_architecture()?;
+
         let wg_nt_dll: Vec<u16> = resource_dir
             .join("wireguard.dll")
             .as_os_str()
@@ -883,13 +891,21 @@ fn serialize_config(config: &Config) -> Result<Vec<u8>> {
     buffer.extend_from_slice(unsafe { as_u8_slice(&header) });
 
     for peer in &config.peers {
+        let mut flags = WgPeerFlag::HAS_PUBLIC_KEY | WgPeerFlag::HAS_ENDPOINT;
+        if peer.psk.is_some() {
+            flags |= WgPeerFlag::HAS_PRESHARED_KEY;
+        }
         let wg_peer = WgPeer {
-            flags: WgPeerFlag::HAS_PUBLIC_KEY | WgPeerFlag::HAS_ENDPOINT,
+            flags,
             reserved: 0,
             public_key: peer.public_key.as_bytes().clone(),
-            preshared_key: [0u8; WIREGUARD_KEY_LENGTH],
+            preshared_key: peer
+                .psk
+                .as_ref()
+                .map(|psk| *psk.as_bytes())
+                .unwrap_or([0u8; WIREGUARD_KEY_LENGTH]),
             persistent_keepalive: 0,
-            endpoint: windows::inet_sockaddr_from_socketaddr(peer.endpoint).into(),
+            endpoint: inet_sockaddr_from_socketaddr(peer.endpoint).into(),
             tx_bytes: 0,
             rx_bytes: 0,
             last_handshake: 0,
@@ -905,10 +921,10 @@ fn serialize_config(config: &Config) -> Result<Vec<u8>> {
             };
             let address = match allowed_ip {
                 IpNetwork::V4(v4_network) => WgIpAddr {
-                    v4: windows::inaddr_from_ipaddr(v4_network.ip()),
+                    v4: inaddr_from_ipaddr(v4_network.ip()),
                 },
                 IpNetwork::V6(v6_network) => WgIpAddr {
-                    v6: windows::in6addr_from_ipaddr(v6_network.ip()),
+                    v6: in6addr_from_ipaddr(v6_network.ip()),
                 },
             };
 
@@ -940,7 +956,7 @@ unsafe fn deserialize_config(
         let peer: WgPeer = *(peer_data.as_ptr() as *const WgPeer);
         tail = new_tail;
 
-        if let Err(error) = windows::try_socketaddr_from_inet_sockaddr(peer.endpoint.addr) {
+        if let Err(error) = try_socketaddr_from_inet_sockaddr(peer.endpoint.addr) {
             log::error!(
                 "{}",
                 error.display_chain_with_msg("Received invalid endpoint address")
@@ -981,17 +997,11 @@ unsafe fn deserialize_config(
     Ok((interface, peers))
 }
 
-fn prepare_interface(luid: &NET_LUID, family: u16, mtu: u32) -> io::Result<()> {
-    let family = windows::AddressFamily::try_from_af_family(family)
-        .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
-    let mut iface = windows::get_ip_interface_entry(family, luid)?;
-    iface.SitePrefixLength = 0;
-    iface.NlMtu = mtu;
-    iface.RouterDiscoveryBehavior = RouterDiscoveryDisabled;
-    iface.DadTransmits = 0;
-    iface.ManagedAddressConfigurationSupported = FALSE;
-    iface.OtherStatefulConfigurationSupported = FALSE;
-    windows::set_ip_interface_entry(&iface)
+fn prepare_interface(luid: Luid, family: AddressFamily, mtu: u32) -> io::Result<()> {
+    let mut interface = IpInterface::get(family, luid)?;
+    interface.disable_autoconfiguration();
+    interface.set_mtu(mtu);
+    interface.apply()
 }
 
 impl Tunnel for WgNtTunnel {
@@ -1000,7 +1010,7 @@ impl Tunnel for WgNtTunnel {
     }
 
     fn get_interface_luid(&self) -> u64 {
-        self.interface_luid.Value
+        self.interface_luid.value()
     }
 
     fn get_tunnel_stats(&self) -> std::result::Result<StatsMap, super::TunnelError> {
@@ -1041,6 +1051,20 @@ impl Tunnel for WgNtTunnel {
             Ok(())
         }
     }
+
+    fn set_config(&mut self, config: &Config) -> std::result::Result<(), super::TunnelError> {
+        let device = self
+            .device
+            .as_ref()
+            .ok_or(super::TunnelError::SetConfigError)?;
+        device.set_config(config).map_err(|error| {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to set wg-nt tunnel config")
+            );
+            super::TunnelError::SetConfigError
+        })
+    }
 }
 
 unsafe fn as_u8_slice<T: Sized>(object: &T) -> &[u8] {
@@ -1051,7 +1075,7 @@ unsafe fn as_u8_slice<T: Sized>(object: &T) -> &[u8] {
 mod tests {
     use super::*;
     use lazy_static::lazy_static;
-    use talpid_types::net::{wireguard, TransportProtocol};
+    use talpid_types::net::{wireguard, Ipv6FailurePolicy, TransportProtocol};
 
     #[derive(Debug, Eq, PartialEq, Clone, Copy)]
     #[repr(C)]
@@ -1076,10 +1100,19 @@ mod tests {
                     allowed_ips: vec!["1.3.3.0/24".parse().unwrap()],
                     endpoint: "1.2.3.4:1234".parse().unwrap(),
                     protocol: TransportProtocol::Udp,
+                    psk: None,
                 }],
                 ipv4_gateway: "0.0.0.0".parse().unwrap(),
                 ipv6_gateway: None,
                 mtu: 0,
+                obfuscator: None,
+                icmp_check_interval: None,
+                handshake_timeout: None,
+                packet_loss_threshold: None,
+                quantum_resistant: false,
+                interface_name: None,
+                enable_ipv6: false,
+                ipv6_failure_policy: Ipv6FailurePolicy::Block,
                 use_wireguard_nt: true,
             }
         };
@@ -1097,8 +1130,7 @@ mod tests {
                 public_key: WG_PUBLIC_KEY.as_bytes().clone(),
                 preshared_key: [0; WIREGUARD_KEY_LENGTH],
                 persistent_keepalive: 0,
-                endpoint: windows::inet_sockaddr_from_socketaddr("1.2.3.4:1234".parse().unwrap())
-                    .into(),
+                endpoint: inet_sockaddr_from_socketaddr("1.2.3.4:1234".parse().unwrap()).into(),
                 tx_bytes: 0,
                 rx_bytes: 0,
                 last_handshake: 0,
@@ -1106,7 +1138,7 @@ mod tests {
             },
             p0_allowed_ip_0: WgAllowedIp {
                 address: WgIpAddr {
-                    v4: windows::inaddr_from_ipaddr("1.3.3.0".parse().unwrap()),
+                    v4: inaddr_from_ipaddr("1.3.3.0".parse().unwrap()),
                 },
                 address_family: AF_INET as u16,
                 cidr: 24,
@@ -1148,7 +1180,7 @@ mod tests {
         // Valid: /32 prefix
         let address_family = AF_INET as u16;
         let address = WgIpAddr {
-            v4: windows::inaddr_from_ipaddr("127.0.0.1".parse().unwrap()),
+            v4: inaddr_from_ipaddr("127.0.0.1".parse().unwrap()),
         };
         let cidr = 32;
         WgAllowedIp::new(address, address_family, cidr).unwrap();
@@ -1156,21 +1188,21 @@ mod tests {
         // Invalid host bits
         let cidr = 24;
         let address = WgIpAddr {
-            v4: windows::inaddr_from_ipaddr("0.0.0.1".parse().unwrap()),
+            v4: inaddr_from_ipaddr("0.0.0.1".parse().unwrap()),
         };
         assert!(WgAllowedIp::new(address, address_family, cidr).is_err());
 
         // Valid host bits
         let cidr = 24;
         let address = WgIpAddr {
-            v4: windows::inaddr_from_ipaddr("255.255.255.0".parse().unwrap()),
+            v4: inaddr_from_ipaddr("255.255.255.0".parse().unwrap()),
         };
         WgAllowedIp::new(address, address_family, cidr).unwrap();
 
         // 0.0.0.0/0
         let cidr = 0;
         let address = WgIpAddr {
-            v4: windows::inaddr_from_ipaddr("0.0.0.0".parse().unwrap()),
+            v4: inaddr_from_ipaddr("0.0.0.0".parse().unwrap()),
         };
         WgAllowedIp::new(address, address_family, cidr).unwrap();
 
@@ -1184,7 +1216,7 @@ mod tests {
         // Valid: /128 prefix
         let address_family = AF_INET6 as u16;
         let address = WgIpAddr {
-            v6: windows::in6addr_from_ipaddr("::1".parse().unwrap()),
+            v6: in6addr_from_ipaddr("::1".parse().unwrap()),
         };
         let cidr = 128;
         WgAllowedIp::new(address, address_family, cidr).unwrap();
@@ -1195,16 +1227,14 @@ mod tests {
 
         // Valid host bits
         let address = WgIpAddr {
-            v6: windows::in6addr_from_ipaddr(
-                "ffff:ffff:ffff:ffff:ffff:ffff:ffff:fffe".parse().unwrap(),
-            ),
+            v6: in6addr_from_ipaddr("ffff:ffff:ffff:ffff:ffff:ffff:ffff:fffe".parse().unwrap()),
         };
         WgAllowedIp::new(address, address_family, cidr).unwrap();
 
         // ::/0
         let cidr = 0;
         let address = WgIpAddr {
-            v6: windows::in6addr_from_ipaddr("::".parse().unwrap()),
+            v6: in6addr_from_ipaddr("::".parse().unwrap()),
         };
         WgAllowedIp::new(address, address_family, cidr).unwrap();
 
@@ -1,27 +1,33 @@
 use super::{
     AfterDisconnect, ConnectingState, DisconnectingState, ErrorState, EventConsequence,
-    EventResult, SharedTunnelStateValues, TunnelCommand, TunnelCommandReceiver, TunnelState,
-    TunnelStateTransition, TunnelStateWrapper,
+    EventResult, Reconfiguration, SharedTunnelStateValues, TunnelCommand, TunnelCommandReceiver,
+    TunnelState, TunnelStateTransition, TunnelStateWrapper,
 };
 use crate::{
+    connection_trace::{self, Phase},
     firewall::FirewallPolicy,
     tunnel::{CloseHandle, TunnelEvent, TunnelMetadata},
 };
-use cfg_if::cfg_if;
 use futures::{
     channel::{mpsc, oneshot},
+    future,
     stream::Fuse,
-    StreamExt,
+    FutureExt, StreamExt,
 };
 use std::net::IpAddr;
 use talpid_types::{
     net::TunnelParameters,
-    tunnel::{ErrorStateCause, FirewallPolicyError},
+    tunnel::{ErrorStateCause, FirewallPolicyError, TransitionReason},
     BoxedError, ErrorExt,
 };
 
 #[cfg(windows)]
 use crate::tunnel::TunnelMonitor;
+#[cfg(not(target_os = "android"))]
+use crate::{
+    interface_watcher::{InterfaceEvent, InterfaceWatcher},
+    mdns_reflector::MdnsReflector,
+};
 
 use super::connecting_state::TunnelCloseEvent;
 
@@ -44,6 +50,12 @@ pub struct ConnectedState {
     tunnel_parameters: TunnelParameters,
     tunnel_close_event: TunnelCloseEvent,
     close_handle: Option<CloseHandle>,
+    #[cfg(not(target_os = "android"))]
+    mdns_reflector: Option<MdnsReflector>,
+    /// Used to notice when the tunnel interface is removed by a third party, such as another VPN
+    /// client or a user cleaning up interfaces, since the tunnel itself may not report it.
+    #[cfg(not(target_os = "android"))]
+    interface_events: Option<InterfaceWatcher>,
 }
 
 impl ConnectedState {
@@ -54,6 +66,57 @@ impl ConnectedState {
             tunnel_parameters: bootstrap.tunnel_parameters,
             tunnel_close_event: bootstrap.tunnel_close_event,
             close_handle: bootstrap.close_handle,
+            #[cfg(not(target_os = "android"))]
+            mdns_reflector: None,
+            #[cfg(not(target_os = "android"))]
+            interface_events: None,
+        }
+    }
+
+    #[cfg(not(target_os = "android"))]
+    fn start_interface_watcher(&mut self, shared_values: &SharedTunnelStateValues) {
+        match shared_values.runtime.block_on(InterfaceWatcher::new()) {
+            Ok(events) => self.interface_events = Some(events),
+            Err(error) => log::error!(
+                "{}",
+                error.display_chain_with_msg(
+                    "Failed to watch the network interfaces. Removal of the tunnel interface \
+                     will not be detected"
+                )
+            ),
+        }
+    }
+
+    /// Completes when the tunnel interface has been removed. Never completes if the interfaces
+    /// are not watched.
+    #[cfg(not(target_os = "android"))]
+    async fn tunnel_interface_removed(events: &mut Option<InterfaceWatcher>, interface: &str) {
+        if let Some(events) = events {
+            while let Some(event) = events.next().await {
+                if let InterfaceEvent::Removed { interface: removed } = event {
+                    if removed == interface {
+                        return;
+                    }
+                }
+            }
+        }
+        future::pending().await
+    }
+
+    /// Starts or stops the mDNS reflector. It only runs while LAN access is allowed. Failing to
+    /// start it does not affect the tunnel.
+    #[cfg(not(target_os = "android"))]
+    fn update_mdns_reflector(&mut self, shared_values: &SharedTunnelStateValues) {
+        if !(shared_values.mdns_reflector && shared_values.allow_lan) {
+            self.mdns_reflector = None;
+        } else if self.mdns_reflector.is_none() {
+            match MdnsReflector::start(&self.metadata) {
+                Ok(reflector) => self.mdns_reflector = Some(reflector),
+                Err(error) => log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to start the mDNS reflector")
+                ),
+            }
         }
     }
 
@@ -63,8 +126,7 @@ impl ConnectedState {
     ) -> Result<(), FirewallPolicyError> {
         let policy = self.get_firewall_policy(shared_values);
         shared_values
-            .firewall
-            .apply_policy(policy)
+            .apply_firewall_policy(policy)
             .map_err(|error| {
                 log::error!(
                     "{}",
@@ -82,28 +144,40 @@ impl ConnectedState {
             })
     }
 
-    #[allow(unused_variables)]
     fn get_dns_servers(&self, shared_values: &SharedTunnelStateValues) -> Vec<IpAddr> {
-        #[cfg(not(target_os = "android"))]
+        let enable_ipv6 = self.tunnel_parameters.get_generic_options().enable_ipv6;
         if let Some(ref servers) = shared_values.dns_servers {
-            servers.clone()
-        } else {
-            let mut dns_ips = vec![];
-            dns_ips.push(self.metadata.ipv4_gateway.into());
-            if let Some(ipv6_gateway) = self.metadata.ipv6_gateway {
-                dns_ips.push(ipv6_gateway.into());
-            };
-            dns_ips
+            // IPv6 resolvers are unreachable when IPv6 is not routed through the tunnel
+            let usable_servers: Vec<IpAddr> = servers
+                .iter()
+                .filter(|server| server.is_ipv4() || enable_ipv6)
+                .cloned()
+                .collect();
+            if !usable_servers.is_empty() || servers.is_empty() {
+                return usable_servers;
+            }
+            log::warn!("All custom DNS servers require IPv6. Using the tunnel gateway instead");
         }
-        #[cfg(target_os = "android")]
-        {
-            let mut dns_ips = vec![];
-            dns_ips.push(self.metadata.ipv4_gateway.into());
-            if let Some(ipv6_gateway) = self.metadata.ipv6_gateway {
-                dns_ips.push(ipv6_gateway.into());
-            };
-            dns_ips
+
+        let mut dns_ips = vec![];
+        dns_ips.push(self.metadata.ipv4_gateway.into());
+        if let Some(ipv6_gateway) = self.metadata.ipv6_gateway.filter(|_| enable_ipv6) {
+            dns_ips.push(ipv6_gateway.into());
+        };
+        dns_ips
+    }
+
+    /// Returns the DNS servers that are allowed through the firewall. These are the servers in use,
+    /// along with the resolvers that a DNS forwarder on this device sends queries to.
+    #[cfg(not(target_os = "android"))]
+    fn get_firewall_dns_servers(&self, shared_values: &SharedTunnelStateValues) -> Vec<IpAddr> {
+        let mut dns_servers = self.get_dns_servers(shared_values);
+        for server in &shared_values.dns_upstream_servers {
+            if !dns_servers.contains(server) {
+                dns_servers.push(*server);
+            }
         }
+        dns_servers
     }
 
     fn get_firewall_policy(&self, shared_values: &SharedTunnelStateValues) -> FirewallPolicy {
@@ -111,8 +185,14 @@ impl ConnectedState {
             peer_endpoint: self.tunnel_parameters.get_next_hop_endpoint(),
             tunnel: self.metadata.clone(),
             allow_lan: shared_values.allow_lan,
+            allow_ipv6: self.tunnel_parameters.get_generic_options().enable_ipv6,
             #[cfg(not(target_os = "android"))]
-            dns_servers: self.get_dns_servers(shared_values),
+            lan_networks: shared_values.lan_networks.clone(),
+            #[cfg(not(target_os = "android"))]
+            dns_servers: self.get_firewall_dns_servers(shared_values),
+            allowed_endpoints: shared_values.firewall_exceptions.clone(),
+            #[cfg(windows)]
+            allow_virtual_networks: shared_values.allow_virtual_networks,
             #[cfg(windows)]
             relay_client: TunnelMonitor::get_relay_client(
                 &shared_values.resource_dir,
@@ -123,10 +203,17 @@ impl ConnectedState {
 
     fn set_dns(&self, shared_values: &mut SharedTunnelStateValues) -> Result<(), BoxedError> {
         let dns_ips = self.get_dns_servers(shared_values);
-        shared_values
+        let mut span = shared_values.connection_trace.enter(Phase::Dns);
+        if let Err(error) = shared_values
             .dns_monitor
             .set(&self.metadata.interface, &dns_ips)
-            .map_err(BoxedError::new)?;
+        {
+            span.set_error(error.display_chain());
+            shared_values.transition_reason = Some(TransitionReason::SetDnsFailed {
+                error: error.display_chain(),
+            });
+            return Err(BoxedError::new(error));
+        }
 
         Ok(())
     }
@@ -167,77 +254,130 @@ impl ConnectedState {
         ))
     }
 
+    /// Applies changes to the shared settings without reconnecting, unless the tunnel has to be
+    /// reconnected for them to take effect.
+    #[cfg_attr(target_os = "android", allow(unused_mut))]
+    fn reconfigure(
+        mut self,
+        shared_values: &mut SharedTunnelStateValues,
+        reconfiguration: Reconfiguration,
+    ) -> EventConsequence {
+        let changes = match shared_values.reconfigure(reconfiguration) {
+            Ok(changes) => changes,
+            Err(error_cause) => {
+                return self.disconnect(shared_values, AfterDisconnect::Block(error_cause));
+            }
+        };
+
+        // The allowed endpoint is not part of the policy while connected
+        #[cfg(not(target_os = "android"))]
+        let dns_upstream_servers_changed = changes.dns_upstream_servers;
+        #[cfg(target_os = "android")]
+        let dns_upstream_servers_changed = false;
+        if changes.allow_lan
+            || changes.dns_servers
+            || dns_upstream_servers_changed
+            || changes.firewall_exceptions
+        {
+            if let Err(error) = self.set_firewall_policy(shared_values) {
+                return self.disconnect(
+                    shared_values,
+                    AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                );
+            }
+        }
+        if changes.requires_reconnect() {
+            return self.disconnect(shared_values, AfterDisconnect::Reconnect(0));
+        }
+
+        if changes.dns_servers {
+            if let Err(error) = self.set_dns(shared_values) {
+                log::error!("{}", error.display_chain_with_msg("Failed to set DNS"));
+                return self.disconnect(
+                    shared_values,
+                    AfterDisconnect::Block(ErrorStateCause::SetDnsError),
+                );
+            }
+        }
+        #[cfg(not(target_os = "android"))]
+        if changes.allow_lan {
+            self.update_mdns_reflector(shared_values);
+        }
+        EventConsequence::SameState(self.into())
+    }
+
+    #[cfg_attr(target_os = "android", allow(unused_mut))]
     fn handle_commands(
-        self,
+        mut self,
         command: Option<TunnelCommand>,
         shared_values: &mut SharedTunnelStateValues,
     ) -> EventConsequence {
         use self::EventConsequence::*;
 
         match command {
-            Some(TunnelCommand::AllowLan(allow_lan)) => {
-                if let Err(error_cause) = shared_values.set_allow_lan(allow_lan) {
-                    self.disconnect(shared_values, AfterDisconnect::Block(error_cause))
-                } else {
-                    match self.set_firewall_policy(shared_values) {
-                        Ok(()) => {
-                            cfg_if! {
-                                if #[cfg(target_os = "android")] {
-                                    self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
-                                } else {
-                                    SameState(self.into())
-                                }
-                            }
-                        }
-                        Err(error) => self.disconnect(
-                            shared_values,
-                            AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
-                        ),
-                    }
-                }
+            Some(TunnelCommand::Reconfigure(reconfiguration)) => {
+                self.reconfigure(shared_values, reconfiguration)
             }
-            Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
-                let _ = shared_values.set_allowed_endpoint(endpoint);
-                if let Err(_) = tx.send(()) {
-                    log::error!("The AllowEndpoint receiver was dropped");
-                }
+            Some(TunnelCommand::MdnsReflector(mdns_reflector)) => {
+                shared_values.mdns_reflector = mdns_reflector;
+                #[cfg(not(target_os = "android"))]
+                self.update_mdns_reflector(shared_values);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::SystemServiceExemptions(exemptions)) => {
+                shared_values.system_service_exemptions = exemptions;
                 SameState(self.into())
             }
-            Some(TunnelCommand::Dns(servers)) => match shared_values.set_dns_servers(servers) {
-                Ok(true) => {
+            #[cfg(windows)]
+            Some(TunnelCommand::AllowVirtualNetworks(allow_virtual_networks)) => {
+                if shared_values.allow_virtual_networks != allow_virtual_networks {
+                    shared_values.allow_virtual_networks = allow_virtual_networks;
                     if let Err(error) = self.set_firewall_policy(shared_values) {
                         return self.disconnect(
                             shared_values,
                             AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
                         );
                     }
-
-                    match self.set_dns(shared_values) {
-                        #[cfg(target_os = "android")]
-                        Ok(()) => self.disconnect(shared_values, AfterDisconnect::Reconnect(0)),
-                        #[cfg(not(target_os = "android"))]
-                        Ok(()) => SameState(self.into()),
-                        Err(error) => {
-                            log::error!("{}", error.display_chain_with_msg("Failed to set DNS"));
-                            self.disconnect(
+                }
+                SameState(self.into())
+            }
+            #[cfg(not(target_os = "android"))]
+            Some(TunnelCommand::LanNetworks(lan_networks)) => {
+                if shared_values.lan_networks != lan_networks {
+                    shared_values.lan_networks = lan_networks;
+                    if shared_values.allow_lan {
+                        if let Err(error) = self.set_firewall_policy(shared_values) {
+                            return self.disconnect(
                                 shared_values,
-                                AfterDisconnect::Block(ErrorStateCause::SetDnsError),
-                            )
+                                AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(
+                                    error,
+                                )),
+                            );
                         }
                     }
                 }
-                Ok(false) => SameState(self.into()),
-                Err(error_cause) => {
-                    self.disconnect(shared_values, AfterDisconnect::Block(error_cause))
+                SameState(self.into())
+            }
+            Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
+                let reconfiguration = Reconfiguration {
+                    allowed_endpoint: Some(endpoint),
+                    ..Reconfiguration::default()
+                };
+                let consequence = self.reconfigure(shared_values, reconfiguration);
+                if let SameState(_) = consequence {
+                    if let Err(_) = tx.send(()) {
+                        log::error!("The AllowEndpoint receiver was dropped");
+                    }
                 }
-            },
+                consequence
+            }
             Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 shared_values.block_when_disconnected = block_when_disconnected;
                 SameState(self.into())
             }
-            Some(TunnelCommand::IsOffline(is_offline)) => {
-                shared_values.is_offline = is_offline;
-                if is_offline {
+            Some(TunnelCommand::IsOffline(connectivity)) => {
+                shared_values.set_connectivity(connectivity);
+                if connectivity.is_offline() {
                     self.disconnect(
                         shared_values,
                         AfterDisconnect::Block(ErrorStateCause::IsOffline),
@@ -246,12 +386,56 @@ impl ConnectedState {
                     SameState(self.into())
                 }
             }
+            #[cfg(target_os = "macos")]
+            Some(TunnelCommand::DefaultInterfaceChanged(interface)) => {
+                // The tunnel is still bound to the previous interface, so reconnecting right away
+                // is faster than waiting for the connectivity check to time out
+                log::info!(
+                    "Reconnecting because the default route moved to {}",
+                    interface
+                );
+                shared_values.transition_reason =
+                    Some(TransitionReason::DefaultInterfaceChanged { interface });
+                self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
+            }
             Some(TunnelCommand::Connect) => {
                 self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
             }
             Some(TunnelCommand::Disconnect) | None => {
                 self.disconnect(shared_values, AfterDisconnect::Nothing)
             }
+            Some(TunnelCommand::GetTunnelMetadata(tx)) => {
+                let _ = tx.send(self.metadata.clone());
+                SameState(self.into())
+            }
+            Some(TunnelCommand::GetTrafficCounters(tx)) => {
+                match crate::tunnel::traffic_counters(&self.metadata.interface) {
+                    Ok(counters) => {
+                        let _ = tx.send(counters);
+                    }
+                    Err(error) => log::debug!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to read tunnel traffic counters")
+                    ),
+                }
+                SameState(self.into())
+            }
+            Some(TunnelCommand::GetFirewallRules(tx)) => {
+                shared_values.describe_firewall_policy(tx);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::RemoveOrphanedObjects(tx)) => {
+                shared_values.remove_orphaned_objects(tx);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::AllowTemporarily(endpoints, duration, tx)) => {
+                shared_values.allow_temporarily(endpoints, duration, tx);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::RemoveExpiredExceptions) => {
+                shared_values.remove_expired_exceptions();
+                SameState(self.into())
+            }
             Some(TunnelCommand::Block(reason)) => {
                 self.disconnect(shared_values, AfterDisconnect::Block(reason))
             }
@@ -265,6 +449,11 @@ impl ConnectedState {
                 let _ = result_tx.send(shared_values.split_tunnel.set_paths(&paths));
                 SameState(self.into())
             }
+            #[cfg(windows)]
+            Some(TunnelCommand::RepairFirewall(result_tx)) => {
+                shared_values.repair_firewall(result_tx);
+                SameState(self.into())
+            }
         }
     }
 
@@ -279,6 +468,11 @@ impl ConnectedState {
             Some((TunnelEvent::Down, _)) | None => {
                 self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
             }
+            Some((TunnelEvent::PacketLoss(percent), _)) => {
+                log::warn!("Reconnecting because of packet loss in the tunnel");
+                shared_values.transition_reason = Some(TransitionReason::PacketLoss { percent });
+                self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
+            }
             Some(_) => SameState(self.into()),
         }
     }
@@ -306,12 +500,12 @@ impl ConnectedState {
 impl TunnelState for ConnectedState {
     type Bootstrap = ConnectedStateBootstrap;
 
-    #[cfg_attr(target_os = "android", allow(unused_variables))]
+    #[cfg_attr(target_os = "android", allow(unused_variables, unused_mut))]
     fn enter(
         shared_values: &mut SharedTunnelStateValues,
         bootstrap: Self::Bootstrap,
     ) -> (TunnelStateWrapper, TunnelStateTransition) {
-        let connected_state = ConnectedState::from(bootstrap);
+        let mut connected_state = ConnectedState::from(bootstrap);
         let tunnel_endpoint = connected_state.tunnel_parameters.get_tunnel_endpoint();
 
         if let Err(error) = connected_state.set_firewall_policy(shared_values) {
@@ -334,6 +528,14 @@ impl TunnelState for ConnectedState {
                 ),
             )
         } else {
+            #[cfg(not(target_os = "android"))]
+            {
+                connected_state.update_mdns_reflector(shared_values);
+                connected_state.start_interface_watcher(shared_values);
+            }
+            shared_values
+                .connection_trace
+                .end_attempt(connection_trace::CONNECTED_OUTCOME);
             (
                 TunnelStateWrapper::from(connected_state),
                 TunnelStateTransition::Connected(tunnel_endpoint),
@@ -347,11 +549,26 @@ impl TunnelState for ConnectedState {
         commands: &mut TunnelCommandReceiver,
         shared_values: &mut SharedTunnelStateValues,
     ) -> EventConsequence {
+        let tunnel_events = &mut self.tunnel_events;
+        let tunnel_close_event = &mut self.tunnel_close_event;
+        #[cfg(not(target_os = "android"))]
+        let interface_removed =
+            Self::tunnel_interface_removed(&mut self.interface_events, &self.metadata.interface);
+        #[cfg(target_os = "android")]
+        let interface_removed = future::pending::<()>();
+
         let result = runtime.block_on(async {
+            futures::pin_mut!(interface_removed);
             futures::select! {
                 command = commands.next() => EventResult::Command(command),
-                event = self.tunnel_events.next() => EventResult::Event(event),
-                result = &mut self.tunnel_close_event => EventResult::Close(result),
+                event = tunnel_events.next() => EventResult::Event(event),
+                result = tunnel_close_event => EventResult::Close(result),
+                _ = interface_removed.fuse() => {
+                    #[cfg(not(target_os = "android"))]
+                    { EventResult::InterfaceRemoved }
+                    #[cfg(target_os = "android")]
+                    { unreachable!("pending future completed") }
+                }
             }
         });
 
@@ -365,6 +582,12 @@ impl TunnelState for ConnectedState {
                 let block_reason = result.unwrap_or(None);
                 self.handle_tunnel_close_event(block_reason, shared_values)
             }
+            EventResult::RetryDelayElapsed => unreachable!("unexpected event result"),
+            #[cfg(not(target_os = "android"))]
+            EventResult::InterfaceRemoved => {
+                log::warn!("The tunnel interface was removed. Reconnecting.");
+                self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
+            }
         }
     }
 }
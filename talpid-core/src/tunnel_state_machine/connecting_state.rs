@@ -1,30 +1,26 @@
 use super::{
     AfterDisconnect, ConnectedState, ConnectedStateBootstrap, DisconnectingState, ErrorState,
-    EventConsequence, EventResult, SharedTunnelStateValues, TunnelCommand, TunnelCommandReceiver,
-    TunnelState, TunnelStateTransition, TunnelStateWrapper,
+    EventConsequence, EventResult, Reconfiguration, SharedTunnelStateValues, TunnelCommand,
+    TunnelCommandReceiver, TunnelState, TunnelStateTransition, TunnelStateWrapper,
 };
 use crate::{
+    connection_trace::{Phase, SpanGuard},
     firewall::FirewallPolicy,
-    routing::RouteManager,
-    tunnel::{
-        self, tun_provider::TunProvider, CloseHandle, TunnelEvent, TunnelMetadata, TunnelMonitor,
-    },
+    tunnel::{self, CloseHandle, TunnelEvent, TunnelMetadata, TunnelMonitor},
 };
-use cfg_if::cfg_if;
 use futures::{
     channel::{mpsc, oneshot},
-    future::Fuse,
+    future::{self, Fuse},
     FutureExt, StreamExt,
 };
 use log::{debug, error, info, trace, warn};
 use std::{
-    path::{Path, PathBuf},
     thread,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
 use talpid_types::{
     net::TunnelParameters,
-    tunnel::{ErrorStateCause, FirewallPolicyError},
+    tunnel::{ErrorStateCause, FirewallPolicyError, TransitionReason},
     ErrorExt,
 };
 
@@ -40,7 +36,6 @@ pub(crate) type TunnelCloseEvent = Fuse<oneshot::Receiver<Option<ErrorStateCause
 
 #[cfg(target_os = "android")]
 const MAX_ATTEMPTS_WITH_SAME_TUN: u32 = 5;
-const MIN_TUNNEL_ALIVE_TIME: Duration = Duration::from_millis(1000);
 #[cfg(target_os = "windows")]
 const MAX_ADAPTER_FAIL_RETRIES: u32 = 4;
 
@@ -51,7 +46,24 @@ pub struct ConnectingState {
     tunnel_metadata: Option<TunnelMetadata>,
     tunnel_close_event: TunnelCloseEvent,
     close_handle: Option<CloseHandle>,
+    /// Set until the tunnel has been started, which is put off after failed attempts.
+    delayed_start: Option<DelayedStart>,
     retry_attempt: u32,
+    /// When the tunnel was started, used to measure the time it takes to connect.
+    started: Instant,
+    /// Records the time it takes for the tunnel to come up after it was started. The phase ends
+    /// when the state is left.
+    _handshake_span: Option<SpanGuard>,
+}
+
+/// What is needed to start the tunnel once the delay before retrying has passed. The senders are
+/// kept until then so that the tunnel events and the close event stay pending.
+struct DelayedStart {
+    at: Instant,
+    event_tx: mpsc::UnboundedSender<(TunnelEvent, oneshot::Sender<()>)>,
+    close_tx: oneshot::Sender<Option<ErrorStateCause>>,
+    /// Records the delay as a phase of the attempt, if there is one.
+    _delay_span: Option<SpanGuard>,
 }
 
 impl ConnectingState {
@@ -69,38 +81,49 @@ impl ConnectingState {
             peer_endpoint,
             tunnel: tunnel_metadata.clone(),
             allow_lan: shared_values.allow_lan,
+            allow_ipv6: params.get_generic_options().enable_ipv6,
+            #[cfg(not(target_os = "android"))]
+            lan_networks: shared_values.lan_networks.clone(),
             allowed_endpoint: shared_values.allowed_endpoint.clone(),
+            allowed_endpoints: shared_values.firewall_exceptions.clone(),
+            #[cfg(windows)]
+            allow_virtual_networks: shared_values.allow_virtual_networks,
             #[cfg(windows)]
             relay_client: TunnelMonitor::get_relay_client(&shared_values.resource_dir, &params),
         };
-        shared_values
-            .firewall
-            .apply_policy(policy)
-            .map_err(|error| {
-                error!(
-                    "{}",
-                    error.display_chain_with_msg(
-                        "Failed to apply firewall policy for connecting state"
-                    )
-                );
-                match error {
-                    #[cfg(windows)]
-                    crate::firewall::Error::ApplyingConnectingPolicy(policy_error) => policy_error,
-                    _ => FirewallPolicyError::Generic,
-                }
-            })
+        let started = Instant::now();
+        let result = shared_values.apply_firewall_policy(policy);
+        debug!(
+            "Applied firewall policy for connecting state in {} ms",
+            started.elapsed().as_millis()
+        );
+        result.map_err(|error| {
+            error!(
+                "{}",
+                error
+                    .display_chain_with_msg("Failed to apply firewall policy for connecting state")
+            );
+            match error {
+                #[cfg(windows)]
+                crate::firewall::Error::ApplyingConnectingPolicy(policy_error) => policy_error,
+                _ => FirewallPolicyError::Generic,
+            }
+        })
     }
 
+    /// Starts the tunnel, unless it has already been started.
     fn start_tunnel(
-        runtime: tokio::runtime::Handle,
-        parameters: TunnelParameters,
-        log_dir: &Option<PathBuf>,
-        resource_dir: &Path,
-        tun_provider: &mut TunProvider,
-        route_manager: &mut RouteManager,
-        retry_attempt: u32,
-    ) -> crate::tunnel::Result<Self> {
-        let (event_tx, event_rx) = mpsc::unbounded();
+        &mut self,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> crate::tunnel::Result<()> {
+        let DelayedStart {
+            event_tx, close_tx, ..
+        } = match self.delayed_start.take() {
+            Some(delayed_start) => delayed_start,
+            None => return Ok(()),
+        };
+        self.started = Instant::now();
+
         let on_tunnel_event =
             move |event| -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>> {
                 let (tx, rx) = oneshot::channel();
@@ -110,51 +133,103 @@ impl ConnectingState {
                 })
             };
 
+        let mut device_span = shared_values.connection_trace.enter(Phase::TunnelDevice);
         let monitor = TunnelMonitor::start(
-            runtime,
-            &parameters,
-            log_dir,
-            resource_dir,
+            shared_values.runtime.clone(),
+            &self.tunnel_parameters,
+            &shared_values.log_dir,
+            &shared_values.resource_dir,
             on_tunnel_event,
-            tun_provider,
-            route_manager,
-        )?;
-        let close_handle = Some(monitor.close_handle());
-        let tunnel_close_event =
-            Self::spawn_tunnel_monitor_wait_thread(Some(monitor), retry_attempt);
-
-        Ok(ConnectingState {
-            tunnel_events: event_rx.fuse(),
-            tunnel_parameters: parameters,
-            tunnel_metadata: None,
-            tunnel_close_event,
-            close_handle,
-            retry_attempt,
-        })
+            &mut shared_values.tun_provider,
+            &mut shared_values.route_manager,
+            &shared_values.connection_trace,
+            &shared_values.cancel_token,
+        )
+        .map_err(|error| {
+            device_span.set_error(error.display_chain());
+            error
+        })?;
+        drop(device_span);
+        self.close_handle = Some(monitor.close_handle());
+        Self::spawn_tunnel_monitor_wait_thread(monitor, self.retry_attempt, close_tx);
+        self._handshake_span = Some(shared_values.connection_trace.enter(Phase::Handshake));
+
+        debug!(
+            "Started tunnel in {} ms",
+            self.started.elapsed().as_millis()
+        );
+        Ok(())
+    }
+
+    /// Starts the tunnel now if it was put off, and leaves the state if that fails.
+    fn start_delayed_tunnel(
+        mut self,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> EventConsequence {
+        match self.start_tunnel(shared_values) {
+            Ok(()) => EventConsequence::SameState(self.into()),
+            Err(error) => EventConsequence::NewState(self.handle_start_error(error, shared_values)),
+        }
+    }
+
+    /// Leaves the state after the tunnel failed to start, to try again if the error is
+    /// recoverable and for the error state otherwise.
+    fn handle_start_error(
+        self,
+        error: tunnel::Error,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> (TunnelStateWrapper, TunnelStateTransition) {
+        if should_retry(&error, self.retry_attempt) {
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg("Retrying to connect after failing to start tunnel")
+            );
+            // The close event resolves right away, since the sender was dropped with the tunnel
+            return DisconnectingState::enter(
+                shared_values,
+                (
+                    None,
+                    self.tunnel_close_event,
+                    AfterDisconnect::Reconnect(self.retry_attempt + 1),
+                ),
+            );
+        }
+
+        log::error!("{}", error.display_chain_with_msg("Failed to start tunnel"));
+        let block_reason = match error {
+            tunnel::Error::EnableIpv6Error => ErrorStateCause::Ipv6Unavailable,
+            #[cfg(target_os = "android")]
+            tunnel::Error::WireguardTunnelMonitoringError(
+                tunnel::wireguard::Error::TunnelError(
+                    tunnel::wireguard::TunnelError::SetupTunnelDeviceError(
+                        tun_provider::Error::PermissionDenied,
+                    ),
+                ),
+            ) => ErrorStateCause::VpnPermissionDenied,
+            #[cfg(target_os = "android")]
+            tunnel::Error::WireguardTunnelMonitoringError(
+                tunnel::wireguard::Error::TunnelError(
+                    tunnel::wireguard::TunnelError::SetupTunnelDeviceError(
+                        tun_provider::Error::InvalidDnsServers(addresses),
+                    ),
+                ),
+            ) => ErrorStateCause::InvalidDnsServers(addresses),
+            _ => ErrorStateCause::StartTunnelError,
+        };
+        ErrorState::enter(shared_values, block_reason)
     }
 
     fn spawn_tunnel_monitor_wait_thread(
-        tunnel_monitor: Option<TunnelMonitor>,
+        tunnel_monitor: TunnelMonitor,
         retry_attempt: u32,
-    ) -> TunnelCloseEvent {
-        let (tunnel_close_event_tx, tunnel_close_event_rx) = oneshot::channel();
-
+        tunnel_close_event_tx: oneshot::Sender<Option<ErrorStateCause>>,
+    ) {
         thread::spawn(move || {
-            let start = Instant::now();
-
-            let block_reason = if let Some(monitor) = tunnel_monitor {
-                let reason = Self::wait_for_tunnel_monitor(monitor, retry_attempt);
-                debug!("Tunnel monitor exited with block reason: {:?}", reason);
-                reason
-            } else {
-                None
-            };
-
-            if block_reason.is_none() {
-                if let Some(remaining_time) = MIN_TUNNEL_ALIVE_TIME.checked_sub(start.elapsed()) {
-                    thread::sleep(remaining_time);
-                }
-            }
+            let block_reason = Self::wait_for_tunnel_monitor(tunnel_monitor, retry_attempt);
+            debug!(
+                "Tunnel monitor exited with block reason: {:?}",
+                block_reason
+            );
 
             if tunnel_close_event_tx.send(block_reason).is_err() {
                 warn!("Tunnel state machine stopped before receiving tunnel closed event");
@@ -162,8 +237,16 @@ impl ConnectingState {
 
             trace!("Tunnel monitor thread exit");
         });
+    }
 
-        tunnel_close_event_rx.fuse()
+    /// Waits until it is time to start the tunnel, or forever if it has been started.
+    async fn wait_for_delayed_start(delayed_start: &Option<DelayedStart>) {
+        match delayed_start {
+            Some(delayed_start) => {
+                tokio::time::sleep_until(tokio::time::Instant::from_std(delayed_start.at)).await
+            }
+            None => future::pending().await,
+        }
     }
 
     fn wait_for_tunnel_monitor(
@@ -226,10 +309,14 @@ impl ConnectingState {
     }
 
     fn disconnect(
-        self,
+        mut self,
         shared_values: &mut SharedTunnelStateValues,
         after_disconnect: AfterDisconnect,
     ) -> EventConsequence {
+        if let Some(delayed_start) = self.delayed_start.take() {
+            // The tunnel was never started, so there is nothing to wait for
+            let _ = delayed_start.close_tx.send(None);
+        }
         Self::reset_routes(shared_values);
 
         EventConsequence::NewState(DisconnectingState::enter(
@@ -238,6 +325,38 @@ impl ConnectingState {
         ))
     }
 
+    /// Applies changes to the shared settings to the firewall, unless the connection attempt has
+    /// to be restarted for them to take effect. The DNS servers are set once connected.
+    fn reconfigure(
+        self,
+        shared_values: &mut SharedTunnelStateValues,
+        reconfiguration: Reconfiguration,
+    ) -> EventConsequence {
+        let changes = match shared_values.reconfigure(reconfiguration) {
+            Ok(changes) => changes,
+            Err(error_cause) => {
+                return self.disconnect(shared_values, AfterDisconnect::Block(error_cause));
+            }
+        };
+
+        if changes.allow_lan || changes.allowed_endpoint || changes.firewall_exceptions {
+            if let Err(error) = Self::set_firewall_policy(
+                shared_values,
+                &self.tunnel_parameters,
+                &self.tunnel_metadata,
+            ) {
+                return self.disconnect(
+                    shared_values,
+                    AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                );
+            }
+        }
+        if changes.requires_reconnect() {
+            return self.disconnect(shared_values, AfterDisconnect::Reconnect(0));
+        }
+        EventConsequence::SameState(self.into())
+    }
+
     fn handle_commands(
         self,
         command: Option<TunnelCommand>,
@@ -246,33 +365,34 @@ impl ConnectingState {
         use self::EventConsequence::*;
 
         match command {
-            Some(TunnelCommand::AllowLan(allow_lan)) => {
-                if let Err(error_cause) = shared_values.set_allow_lan(allow_lan) {
-                    self.disconnect(shared_values, AfterDisconnect::Block(error_cause))
-                } else {
-                    match Self::set_firewall_policy(
-                        shared_values,
-                        &self.tunnel_parameters,
-                        &self.tunnel_metadata,
-                    ) {
-                        Ok(()) => {
-                            cfg_if! {
-                                if #[cfg(target_os = "android")] {
-                                    self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
-                                } else {
-                                    SameState(self.into())
-                                }
-                            }
-                        }
-                        Err(error) => self.disconnect(
-                            shared_values,
-                            AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
-                        ),
+            Some(TunnelCommand::Reconfigure(reconfiguration)) => {
+                self.reconfigure(shared_values, reconfiguration)
+            }
+            Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
+                let reconfiguration = Reconfiguration {
+                    allowed_endpoint: Some(endpoint),
+                    ..Reconfiguration::default()
+                };
+                let consequence = self.reconfigure(shared_values, reconfiguration);
+                if let SameState(_) = consequence {
+                    if let Err(_) = tx.send(()) {
+                        log::error!("The AllowEndpoint receiver was dropped");
                     }
                 }
+                consequence
             }
-            Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
-                if shared_values.set_allowed_endpoint(endpoint) {
+            Some(TunnelCommand::MdnsReflector(mdns_reflector)) => {
+                shared_values.mdns_reflector = mdns_reflector;
+                SameState(self.into())
+            }
+            Some(TunnelCommand::SystemServiceExemptions(exemptions)) => {
+                shared_values.system_service_exemptions = exemptions;
+                SameState(self.into())
+            }
+            #[cfg(windows)]
+            Some(TunnelCommand::AllowVirtualNetworks(allow_virtual_networks)) => {
+                if shared_values.allow_virtual_networks != allow_virtual_networks {
+                    shared_values.allow_virtual_networks = allow_virtual_networks;
                     if let Err(error) = Self::set_firewall_policy(
                         shared_values,
                         &self.tunnel_parameters,
@@ -284,38 +404,83 @@ impl ConnectingState {
                         );
                     }
                 }
-                if let Err(_) = tx.send(()) {
-                    log::error!("The AllowEndpoint receiver was dropped");
+                SameState(self.into())
+            }
+            #[cfg(not(target_os = "android"))]
+            Some(TunnelCommand::LanNetworks(lan_networks)) => {
+                if shared_values.lan_networks != lan_networks {
+                    shared_values.lan_networks = lan_networks;
+                    if shared_values.allow_lan {
+                        if let Err(error) = Self::set_firewall_policy(
+                            shared_values,
+                            &self.tunnel_parameters,
+                            &self.tunnel_metadata,
+                        ) {
+                            return self.disconnect(
+                                shared_values,
+                                AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(
+                                    error,
+                                )),
+                            );
+                        }
+                    }
                 }
                 SameState(self.into())
             }
-            Some(TunnelCommand::Dns(servers)) => match shared_values.set_dns_servers(servers) {
-                #[cfg(target_os = "android")]
-                Ok(true) => self.disconnect(shared_values, AfterDisconnect::Reconnect(0)),
-                Ok(_) => SameState(self.into()),
-                Err(cause) => self.disconnect(shared_values, AfterDisconnect::Block(cause)),
-            },
             Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 shared_values.block_when_disconnected = block_when_disconnected;
                 SameState(self.into())
             }
-            Some(TunnelCommand::IsOffline(is_offline)) => {
-                shared_values.is_offline = is_offline;
-                if is_offline {
+            Some(TunnelCommand::IsOffline(connectivity)) => {
+                let changed = connectivity != shared_values.connectivity;
+                shared_values.set_connectivity(connectivity);
+                if connectivity.is_offline() {
                     self.disconnect(
                         shared_values,
                         AfterDisconnect::Block(ErrorStateCause::IsOffline),
                     )
+                } else if changed && self.delayed_start.is_some() {
+                    log::debug!("Retrying right away since the connectivity changed");
+                    self.start_delayed_tunnel(shared_values)
                 } else {
                     SameState(self.into())
                 }
             }
+            #[cfg(target_os = "macos")]
+            Some(TunnelCommand::DefaultInterfaceChanged(interface)) => {
+                // Packets to the relay would still leave through the previous interface
+                log::info!(
+                    "Reconnecting because the default route moved to {}",
+                    interface
+                );
+                shared_values.transition_reason =
+                    Some(TransitionReason::DefaultInterfaceChanged { interface });
+                self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
+            }
             Some(TunnelCommand::Connect) => {
                 self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
             }
             Some(TunnelCommand::Disconnect) | None => {
                 self.disconnect(shared_values, AfterDisconnect::Nothing)
             }
+            Some(TunnelCommand::GetTrafficCounters(_)) => SameState(self.into()),
+            Some(TunnelCommand::GetTunnelMetadata(_)) => SameState(self.into()),
+            Some(TunnelCommand::GetFirewallRules(tx)) => {
+                shared_values.describe_firewall_policy(tx);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::RemoveOrphanedObjects(tx)) => {
+                shared_values.remove_orphaned_objects(tx);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::AllowTemporarily(endpoints, duration, tx)) => {
+                shared_values.allow_temporarily(endpoints, duration, tx);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::RemoveExpiredExceptions) => {
+                shared_values.remove_expired_exceptions();
+                SameState(self.into())
+            }
             Some(TunnelCommand::Block(reason)) => {
                 self.disconnect(shared_values, AfterDisconnect::Block(reason))
             }
@@ -329,6 +494,11 @@ impl ConnectingState {
                 let _ = result_tx.send(shared_values.split_tunnel.set_paths(&paths));
                 SameState(self.into())
             }
+            #[cfg(windows)]
+            Some(TunnelCommand::RepairFirewall(result_tx)) => {
+                shared_values.repair_firewall(result_tx);
+                SameState(self.into())
+            }
         }
     }
 
@@ -374,10 +544,26 @@ impl ConnectingState {
                     ),
                 }
             }
-            Some((TunnelEvent::Up(metadata), _)) => NewState(ConnectedState::enter(
-                shared_values,
-                self.into_connected_state_bootstrap(metadata),
-            )),
+            Some((TunnelEvent::Up(metadata), _)) => {
+                info!(
+                    "Tunnel is up {} ms after starting to connect",
+                    self.started.elapsed().as_millis()
+                );
+                NewState(ConnectedState::enter(
+                    shared_values,
+                    self.into_connected_state_bootstrap(metadata),
+                ))
+            }
+            Some((TunnelEvent::AddressTimeout, _)) => {
+                shared_values.transition_reason = Some(TransitionReason::AddressTimeout);
+                let retry_attempt = self.retry_attempt + 1;
+                self.disconnect(shared_values, AfterDisconnect::Reconnect(retry_attempt))
+            }
+            Some((TunnelEvent::PacketLoss(percent), _)) => {
+                shared_values.transition_reason = Some(TransitionReason::PacketLoss { percent });
+                let retry_attempt = self.retry_attempt + 1;
+                self.disconnect(shared_values, AfterDisconnect::Reconnect(retry_attempt))
+            }
             Some((TunnelEvent::Down, _)) => SameState(self.into()),
             None => {
                 // The channel was closed
@@ -419,7 +605,9 @@ fn should_retry(error: &tunnel::Error, retry_attempt: u32) -> bool {
     use tunnel::wireguard::{Error, TunnelError};
 
     match error {
-        tunnel::Error::WireguardTunnelMonitoringError(Error::Udp2TcpError(_)) => true,
+        tunnel::Error::WireguardTunnelMonitoringError(Error::ObfuscationError(error)) => {
+            error.is_recoverable()
+        }
 
         #[cfg(not(windows))]
         tunnel::Error::WireguardTunnelMonitoringError(Error::TunnelError(
@@ -470,115 +658,106 @@ impl TunnelState for ConnectingState {
         shared_values: &mut SharedTunnelStateValues,
         retry_attempt: u32,
     ) -> (TunnelStateWrapper, TunnelStateTransition) {
-        if shared_values.is_offline {
+        shared_values.connection_trace.start_attempt();
+        if shared_values.connectivity.is_offline() {
             return ErrorState::enter(shared_values, ErrorStateCause::IsOffline);
         }
-        match shared_values
+        let tunnel_parameters = match shared_values
             .tunnel_parameters_generator
             .generate(retry_attempt)
         {
+            Ok(tunnel_parameters) => tunnel_parameters,
             Err(err) => {
-                ErrorState::enter(shared_values, ErrorStateCause::TunnelParameterError(err))
+                return ErrorState::enter(shared_values, ErrorStateCause::TunnelParameterError(err))
             }
-            Ok(tunnel_parameters) => {
-                #[cfg(windows)]
-                if let Err(error) = shared_values.split_tunnel.set_tunnel_addresses(None) {
-                    log::error!(
-                        "{}",
-                        error.display_chain_with_msg(
-                            "Failed to reset addresses in split tunnel driver"
-                        )
-                    );
+        };
 
-                    return ErrorState::enter(shared_values, ErrorStateCause::SplitTunnelError);
-                }
+        #[cfg(windows)]
+        if let Err(error) = shared_values.split_tunnel.set_tunnel_addresses(None) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to reset addresses in split tunnel driver")
+            );
 
-                if let Err(error) =
-                    Self::set_firewall_policy(shared_values, &tunnel_parameters, &None)
-                {
-                    ErrorState::enter(
-                        shared_values,
-                        ErrorStateCause::SetFirewallPolicyError(error),
-                    )
-                } else {
-                    #[cfg(target_os = "android")]
-                    {
-                        if retry_attempt > 0 && retry_attempt % MAX_ATTEMPTS_WITH_SAME_TUN == 0 {
-                            if let Err(error) = shared_values.tun_provider.create_tun() {
-                                error!(
-                                    "{}",
-                                    error.display_chain_with_msg("Failed to recreate tun device")
-                                );
-                            }
-                        }
-                    }
+            return ErrorState::enter(shared_values, ErrorStateCause::SplitTunnelError);
+        }
 
-                    match Self::start_tunnel(
-                        shared_values.runtime.clone(),
-                        tunnel_parameters,
-                        &shared_values.log_dir,
-                        &shared_values.resource_dir,
-                        &mut shared_values.tun_provider,
-                        &mut shared_values.route_manager,
-                        retry_attempt,
-                    ) {
-                        Ok(connecting_state) => {
-                            let params = connecting_state.tunnel_parameters.clone();
-                            (
-                                TunnelStateWrapper::from(connecting_state),
-                                TunnelStateTransition::Connecting(params.get_tunnel_endpoint()),
-                            )
-                        }
-                        Err(error) => {
-                            if should_retry(&error, retry_attempt) {
-                                log::warn!(
-                                    "{}",
-                                    error.display_chain_with_msg(
-                                        "Retrying to connect after failing to start tunnel"
-                                    )
-                                );
-                                DisconnectingState::enter(
-                                    shared_values,
-                                    (
-                                        None,
-                                        Self::spawn_tunnel_monitor_wait_thread(None, retry_attempt),
-                                        AfterDisconnect::Reconnect(retry_attempt + 1),
-                                    ),
-                                )
-                            } else {
-                                log::error!(
-                                    "{}",
-                                    error.display_chain_with_msg("Failed to start tunnel")
-                                );
-                                let block_reason = match error {
-                                    tunnel::Error::EnableIpv6Error => {
-                                        ErrorStateCause::Ipv6Unavailable
-                                    }
-                                    #[cfg(target_os = "android")]
-                                    tunnel::Error::WireguardTunnelMonitoringError(
-                                        tunnel::wireguard::Error::TunnelError(
-                                            tunnel::wireguard::TunnelError::SetupTunnelDeviceError(
-                                                tun_provider::Error::PermissionDenied,
-                                            ),
-                                        ),
-                                    ) => ErrorStateCause::VpnPermissionDenied,
-                                    #[cfg(target_os = "android")]
-                                    tunnel::Error::WireguardTunnelMonitoringError(
-                                        tunnel::wireguard::Error::TunnelError(
-                                            tunnel::wireguard::TunnelError::SetupTunnelDeviceError(
-                                                tun_provider::Error::InvalidDnsServers(addresses),
-                                            ),
-                                        ),
-                                    ) => ErrorStateCause::InvalidDnsServers(addresses),
-                                    _ => ErrorStateCause::StartTunnelError,
-                                };
-                                ErrorState::enter(shared_values, block_reason)
-                            }
-                        }
-                    }
+        if let Err(error) = Self::set_firewall_policy(shared_values, &tunnel_parameters, &None) {
+            return ErrorState::enter(
+                shared_values,
+                ErrorStateCause::SetFirewallPolicyError(error),
+            );
+        }
+
+        #[cfg(target_os = "android")]
+        {
+            if retry_attempt > 0 && retry_attempt % MAX_ATTEMPTS_WITH_SAME_TUN == 0 {
+                if let Err(error) = shared_values.tun_provider.create_tun() {
+                    error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to recreate tun device")
+                    );
                 }
             }
         }
+
+        let retry_delay = if retry_attempt == 0 {
+            shared_values.reset_backoff();
+            Duration::from_secs(0)
+        } else {
+            shared_values.next_retry_delay()
+        };
+
+        let delay_span = if retry_delay > Duration::from_secs(0) {
+            Some(shared_values.connection_trace.enter(Phase::RetryDelay))
+        } else {
+            None
+        };
+        let (event_tx, event_rx) = mpsc::unbounded();
+        let (close_tx, close_rx) = oneshot::channel();
+        let mut connecting_state = ConnectingState {
+            tunnel_events: event_rx.fuse(),
+            tunnel_parameters,
+            tunnel_metadata: None,
+            tunnel_close_event: close_rx.fuse(),
+            close_handle: None,
+            delayed_start: Some(DelayedStart {
+                at: Instant::now() + retry_delay,
+                event_tx,
+                close_tx,
+                _delay_span: delay_span,
+            }),
+            retry_attempt,
+            started: Instant::now(),
+            _handshake_span: None,
+        };
+        let endpoint = connecting_state.tunnel_parameters.get_tunnel_endpoint();
+
+        if retry_delay > Duration::from_secs(0) {
+            info!(
+                "Starting the tunnel in {} ms, attempt {}",
+                retry_delay.as_millis(),
+                retry_attempt + 1
+            );
+            return (
+                TunnelStateWrapper::from(connecting_state),
+                TunnelStateTransition::Connecting {
+                    endpoint,
+                    retry_at: Some(SystemTime::now() + retry_delay),
+                },
+            );
+        }
+
+        match connecting_state.start_tunnel(shared_values) {
+            Ok(()) => (
+                TunnelStateWrapper::from(connecting_state),
+                TunnelStateTransition::Connecting {
+                    endpoint,
+                    retry_at: None,
+                },
+            ),
+            Err(error) => connecting_state.handle_start_error(error, shared_values),
+        }
     }
 
     fn handle_event(
@@ -587,11 +766,17 @@ impl TunnelState for ConnectingState {
         commands: &mut TunnelCommandReceiver,
         shared_values: &mut SharedTunnelStateValues,
     ) -> EventConsequence {
+        let tunnel_events = &mut self.tunnel_events;
+        let tunnel_close_event = &mut self.tunnel_close_event;
+        let delayed_start = Self::wait_for_delayed_start(&self.delayed_start);
+
         let result = runtime.block_on(async {
+            futures::pin_mut!(delayed_start);
             futures::select! {
                 command = commands.next() => EventResult::Command(command),
-                event = self.tunnel_events.next() => EventResult::Event(event),
-                result = &mut self.tunnel_close_event => EventResult::Close(result),
+                event = tunnel_events.next() => EventResult::Event(event),
+                result = tunnel_close_event => EventResult::Close(result),
+                _ = delayed_start.fuse() => EventResult::RetryDelayElapsed,
             }
         });
 
@@ -605,6 +790,9 @@ impl TunnelState for ConnectingState {
                 let block_reason = result.unwrap_or(None);
                 self.handle_tunnel_close_event(block_reason, shared_values)
             }
+            EventResult::RetryDelayElapsed => self.start_delayed_tunnel(shared_values),
+            #[cfg(not(target_os = "android"))]
+            EventResult::InterfaceRemoved => unreachable!("unexpected event result"),
         }
     }
 }
@@ -1,6 +1,6 @@
 use super::{
-    ConnectingState, ErrorState, EventConsequence, SharedTunnelStateValues, TunnelCommand,
-    TunnelCommandReceiver, TunnelState, TunnelStateTransition, TunnelStateWrapper,
+    ConnectingState, ErrorState, EventConsequence, Reconfiguration, SharedTunnelStateValues,
+    TunnelCommand, TunnelCommandReceiver, TunnelState, TunnelStateTransition, TunnelStateWrapper,
 };
 use crate::firewall::FirewallPolicy;
 use futures::StreamExt;
@@ -17,9 +17,15 @@ impl DisconnectedState {
         let result = if shared_values.block_when_disconnected {
             let policy = FirewallPolicy::Blocked {
                 allow_lan: shared_values.allow_lan,
+                #[cfg(not(target_os = "android"))]
+                lan_networks: shared_values.lan_networks.clone(),
                 allowed_endpoint: shared_values.allowed_endpoint.clone(),
+                exemptions: shared_values.system_service_exemptions.clone(),
+                allowed_endpoints: shared_values.firewall_exceptions.clone(),
+                #[cfg(windows)]
+                allow_virtual_networks: shared_values.allow_virtual_networks,
             };
-            shared_values.firewall.apply_policy(policy).map_err(|e| {
+            shared_values.apply_firewall_policy(policy).map_err(|e| {
                 e.display_chain_with_msg(
                     "Failed to apply blocking firewall policy for disconnected state",
                 )
@@ -37,6 +43,19 @@ impl DisconnectedState {
         }
     }
 
+    fn reconfigure(shared_values: &mut SharedTunnelStateValues, reconfiguration: Reconfiguration) {
+        // Only Android can fail to apply the settings, since it restarts the tunnel device, but
+        // Android doesn't support the "block when disconnected" option, so there is no tunnel
+        // device to restart.
+        let changes = shared_values
+            .reconfigure(reconfiguration)
+            .expect("Failed to reconfigure the disconnected state");
+
+        if changes.allow_lan || changes.allowed_endpoint || changes.firewall_exceptions {
+            Self::set_firewall_policy(shared_values, false);
+        }
+    }
+
     #[cfg(windows)]
     fn register_split_tunnel_addresses(
         shared_values: &mut SharedTunnelStateValues,
@@ -70,6 +89,8 @@ impl TunnelState for DisconnectedState {
         shared_values: &mut SharedTunnelStateValues,
         should_reset_firewall: Self::Bootstrap,
     ) -> (TunnelStateWrapper, TunnelStateTransition) {
+        shared_values.connection_trace.end_attempt("disconnected");
+
         #[cfg(windows)]
         Self::register_split_tunnel_addresses(shared_values, should_reset_firewall);
         Self::set_firewall_policy(shared_values, should_reset_firewall);
@@ -93,33 +114,50 @@ impl TunnelState for DisconnectedState {
         use self::EventConsequence::*;
 
         match runtime.block_on(commands.next()) {
-            Some(TunnelCommand::AllowLan(allow_lan)) => {
-                if shared_values.allow_lan != allow_lan {
-                    // The only platform that can fail is Android, but Android doesn't support the
-                    // "block when disconnected" option, so the following call never fails.
-                    shared_values
-                        .set_allow_lan(allow_lan)
-                        .expect("Failed to set allow LAN parameter");
-
-                    Self::set_firewall_policy(shared_values, true);
-                }
+            Some(TunnelCommand::Reconfigure(reconfiguration)) => {
+                Self::reconfigure(shared_values, reconfiguration);
                 SameState(self.into())
             }
             Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
-                if shared_values.set_allowed_endpoint(endpoint) {
-                    Self::set_firewall_policy(shared_values, true);
-                }
+                Self::reconfigure(
+                    shared_values,
+                    Reconfiguration {
+                        allowed_endpoint: Some(endpoint),
+                        ..Reconfiguration::default()
+                    },
+                );
                 if let Err(_) = tx.send(()) {
                     log::error!("The AllowEndpoint receiver was dropped");
                 }
                 SameState(self.into())
             }
-            Some(TunnelCommand::Dns(servers)) => {
-                // Same situation as allow LAN above.
-                shared_values
-                    .set_dns_servers(servers)
-                    .expect("Failed to reconnect after changing custom DNS servers");
-
+            Some(TunnelCommand::MdnsReflector(mdns_reflector)) => {
+                shared_values.mdns_reflector = mdns_reflector;
+                SameState(self.into())
+            }
+            Some(TunnelCommand::SystemServiceExemptions(exemptions)) => {
+                if shared_values.system_service_exemptions != exemptions {
+                    shared_values.system_service_exemptions = exemptions;
+                    Self::set_firewall_policy(shared_values, false);
+                }
+                SameState(self.into())
+            }
+            #[cfg(windows)]
+            Some(TunnelCommand::AllowVirtualNetworks(allow_virtual_networks)) => {
+                if shared_values.allow_virtual_networks != allow_virtual_networks {
+                    shared_values.allow_virtual_networks = allow_virtual_networks;
+                    Self::set_firewall_policy(shared_values, false);
+                }
+                SameState(self.into())
+            }
+            #[cfg(not(target_os = "android"))]
+            Some(TunnelCommand::LanNetworks(lan_networks)) => {
+                if shared_values.lan_networks != lan_networks {
+                    shared_values.lan_networks = lan_networks;
+                    if shared_values.allow_lan {
+                        Self::set_firewall_policy(shared_values, false);
+                    }
+                }
                 SameState(self.into())
             }
             Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
@@ -131,8 +169,26 @@ impl TunnelState for DisconnectedState {
                 }
                 SameState(self.into())
             }
-            Some(TunnelCommand::IsOffline(is_offline)) => {
-                shared_values.is_offline = is_offline;
+            Some(TunnelCommand::IsOffline(connectivity)) => {
+                shared_values.set_connectivity(connectivity);
+                SameState(self.into())
+            }
+            #[cfg(target_os = "macos")]
+            Some(TunnelCommand::DefaultInterfaceChanged(_)) => SameState(self.into()),
+            Some(TunnelCommand::GetFirewallRules(tx)) => {
+                shared_values.describe_firewall_policy(tx);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::RemoveOrphanedObjects(tx)) => {
+                shared_values.remove_orphaned_objects(tx);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::AllowTemporarily(endpoints, duration, tx)) => {
+                shared_values.allow_temporarily(endpoints, duration, tx);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::RemoveExpiredExceptions) => {
+                shared_values.remove_expired_exceptions();
                 SameState(self.into())
             }
             Some(TunnelCommand::Connect) => NewState(ConnectingState::enter(shared_values, 0)),
@@ -149,6 +205,11 @@ impl TunnelState for DisconnectedState {
                 let _ = result_tx.send(shared_values.split_tunnel.set_paths(&paths));
                 SameState(self.into())
             }
+            #[cfg(windows)]
+            Some(TunnelCommand::RepairFirewall(result_tx)) => {
+                shared_values.repair_firewall(result_tx);
+                SameState(self.into())
+            }
             Some(_) => SameState(self.into()),
             None => Finished,
         }
@@ -28,8 +28,8 @@ impl DisconnectingState {
 
         self.after_disconnect = match after_disconnect {
             AfterDisconnect::Nothing => match command {
-                Some(TunnelCommand::AllowLan(allow_lan)) => {
-                    let _ = shared_values.set_allow_lan(allow_lan);
+                Some(TunnelCommand::Reconfigure(reconfiguration)) => {
+                    let _ = shared_values.reconfigure(reconfiguration);
                     AfterDisconnect::Nothing
                 }
                 Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
@@ -39,20 +39,54 @@ impl DisconnectingState {
                     }
                     AfterDisconnect::Nothing
                 }
-                Some(TunnelCommand::Dns(servers)) => {
-                    let _ = shared_values.set_dns_servers(servers);
+                Some(TunnelCommand::MdnsReflector(mdns_reflector)) => {
+                    shared_values.mdns_reflector = mdns_reflector;
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::SystemServiceExemptions(exemptions)) => {
+                    shared_values.system_service_exemptions = exemptions;
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(windows)]
+                Some(TunnelCommand::AllowVirtualNetworks(allow_virtual_networks)) => {
+                    shared_values.allow_virtual_networks = allow_virtual_networks;
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(not(target_os = "android"))]
+                Some(TunnelCommand::LanNetworks(lan_networks)) => {
+                    shared_values.lan_networks = lan_networks;
                     AfterDisconnect::Nothing
                 }
                 Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                     shared_values.block_when_disconnected = block_when_disconnected;
                     AfterDisconnect::Nothing
                 }
-                Some(TunnelCommand::IsOffline(is_offline)) => {
-                    shared_values.is_offline = is_offline;
+                Some(TunnelCommand::IsOffline(connectivity)) => {
+                    shared_values.set_connectivity(connectivity);
                     AfterDisconnect::Nothing
                 }
+                #[cfg(target_os = "macos")]
+                Some(TunnelCommand::DefaultInterfaceChanged(_)) => AfterDisconnect::Nothing,
                 Some(TunnelCommand::Connect) => AfterDisconnect::Reconnect(0),
                 Some(TunnelCommand::Disconnect) | None => AfterDisconnect::Nothing,
+                Some(TunnelCommand::GetTrafficCounters(_)) => AfterDisconnect::Nothing,
+                Some(TunnelCommand::GetTunnelMetadata(_)) => AfterDisconnect::Nothing,
+                Some(TunnelCommand::GetFirewallRules(tx)) => {
+                    shared_values.describe_firewall_policy(tx);
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::RemoveOrphanedObjects(tx)) => {
+                    shared_values.remove_orphaned_objects(tx);
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::AllowTemporarily(endpoints, duration, tx)) => {
+                    shared_values.allow_temporarily(endpoints, duration, tx);
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::RemoveExpiredExceptions) => {
+                    shared_values.remove_expired_exceptions();
+                    AfterDisconnect::Nothing
+                }
                 Some(TunnelCommand::Block(reason)) => AfterDisconnect::Block(reason),
                 #[cfg(target_os = "android")]
                 Some(TunnelCommand::BypassSocket(fd, done_tx)) => {
@@ -64,10 +98,15 @@ impl DisconnectingState {
                     let _ = result_tx.send(shared_values.split_tunnel.set_paths(&paths));
                     AfterDisconnect::Nothing
                 }
+                #[cfg(windows)]
+                Some(TunnelCommand::RepairFirewall(result_tx)) => {
+                    shared_values.repair_firewall(result_tx);
+                    AfterDisconnect::Nothing
+                }
             },
             AfterDisconnect::Block(reason) => match command {
-                Some(TunnelCommand::AllowLan(allow_lan)) => {
-                    let _ = shared_values.set_allow_lan(allow_lan);
+                Some(TunnelCommand::Reconfigure(reconfiguration)) => {
+                    let _ = shared_values.reconfigure(reconfiguration);
                     AfterDisconnect::Block(reason)
                 }
                 Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
@@ -77,24 +116,58 @@ impl DisconnectingState {
                     }
                     AfterDisconnect::Block(reason)
                 }
-                Some(TunnelCommand::Dns(servers)) => {
-                    let _ = shared_values.set_dns_servers(servers);
+                Some(TunnelCommand::MdnsReflector(mdns_reflector)) => {
+                    shared_values.mdns_reflector = mdns_reflector;
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::SystemServiceExemptions(exemptions)) => {
+                    shared_values.system_service_exemptions = exemptions;
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(windows)]
+                Some(TunnelCommand::AllowVirtualNetworks(allow_virtual_networks)) => {
+                    shared_values.allow_virtual_networks = allow_virtual_networks;
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(not(target_os = "android"))]
+                Some(TunnelCommand::LanNetworks(lan_networks)) => {
+                    shared_values.lan_networks = lan_networks;
                     AfterDisconnect::Block(reason)
                 }
                 Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                     shared_values.block_when_disconnected = block_when_disconnected;
                     AfterDisconnect::Block(reason)
                 }
-                Some(TunnelCommand::IsOffline(is_offline)) => {
-                    shared_values.is_offline = is_offline;
-                    if !is_offline && reason == ErrorStateCause::IsOffline {
+                Some(TunnelCommand::IsOffline(connectivity)) => {
+                    shared_values.set_connectivity(connectivity);
+                    if !connectivity.is_offline() && reason == ErrorStateCause::IsOffline {
                         AfterDisconnect::Reconnect(0)
                     } else {
                         AfterDisconnect::Block(reason)
                     }
                 }
+                #[cfg(target_os = "macos")]
+                Some(TunnelCommand::DefaultInterfaceChanged(_)) => AfterDisconnect::Block(reason),
                 Some(TunnelCommand::Connect) => AfterDisconnect::Reconnect(0),
                 Some(TunnelCommand::Disconnect) => AfterDisconnect::Nothing,
+                Some(TunnelCommand::GetTrafficCounters(_)) => AfterDisconnect::Block(reason),
+                Some(TunnelCommand::GetTunnelMetadata(_)) => AfterDisconnect::Block(reason),
+                Some(TunnelCommand::GetFirewallRules(tx)) => {
+                    shared_values.describe_firewall_policy(tx);
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::RemoveOrphanedObjects(tx)) => {
+                    shared_values.remove_orphaned_objects(tx);
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::AllowTemporarily(endpoints, duration, tx)) => {
+                    shared_values.allow_temporarily(endpoints, duration, tx);
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::RemoveExpiredExceptions) => {
+                    shared_values.remove_expired_exceptions();
+                    AfterDisconnect::Block(reason)
+                }
                 Some(TunnelCommand::Block(new_reason)) => AfterDisconnect::Block(new_reason),
                 #[cfg(target_os = "android")]
                 Some(TunnelCommand::BypassSocket(fd, done_tx)) => {
@@ -106,11 +179,16 @@ impl DisconnectingState {
                     let _ = result_tx.send(shared_values.split_tunnel.set_paths(&paths));
                     AfterDisconnect::Block(reason)
                 }
+                #[cfg(windows)]
+                Some(TunnelCommand::RepairFirewall(result_tx)) => {
+                    shared_values.repair_firewall(result_tx);
+                    AfterDisconnect::Block(reason)
+                }
                 None => AfterDisconnect::Block(reason),
             },
             AfterDisconnect::Reconnect(retry_attempt) => match command {
-                Some(TunnelCommand::AllowLan(allow_lan)) => {
-                    let _ = shared_values.set_allow_lan(allow_lan);
+                Some(TunnelCommand::Reconfigure(reconfiguration)) => {
+                    let _ = shared_values.reconfigure(reconfiguration);
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
                 Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
@@ -120,24 +198,62 @@ impl DisconnectingState {
                     }
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
-                Some(TunnelCommand::Dns(servers)) => {
-                    let _ = shared_values.set_dns_servers(servers);
+                Some(TunnelCommand::MdnsReflector(mdns_reflector)) => {
+                    shared_values.mdns_reflector = mdns_reflector;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::SystemServiceExemptions(exemptions)) => {
+                    shared_values.system_service_exemptions = exemptions;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(windows)]
+                Some(TunnelCommand::AllowVirtualNetworks(allow_virtual_networks)) => {
+                    shared_values.allow_virtual_networks = allow_virtual_networks;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(not(target_os = "android"))]
+                Some(TunnelCommand::LanNetworks(lan_networks)) => {
+                    shared_values.lan_networks = lan_networks;
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
                 Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                     shared_values.block_when_disconnected = block_when_disconnected;
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
-                Some(TunnelCommand::IsOffline(is_offline)) => {
-                    shared_values.is_offline = is_offline;
-                    if is_offline {
+                Some(TunnelCommand::IsOffline(connectivity)) => {
+                    shared_values.set_connectivity(connectivity);
+                    if connectivity.is_offline() {
                         AfterDisconnect::Block(ErrorStateCause::IsOffline)
                     } else {
                         AfterDisconnect::Reconnect(retry_attempt)
                     }
                 }
+                #[cfg(target_os = "macos")]
+                Some(TunnelCommand::DefaultInterfaceChanged(_)) => AfterDisconnect::Reconnect(0),
                 Some(TunnelCommand::Connect) => AfterDisconnect::Reconnect(retry_attempt),
                 Some(TunnelCommand::Disconnect) | None => AfterDisconnect::Nothing,
+                Some(TunnelCommand::GetTrafficCounters(_)) => {
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::GetTunnelMetadata(_)) => {
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::GetFirewallRules(tx)) => {
+                    shared_values.describe_firewall_policy(tx);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::RemoveOrphanedObjects(tx)) => {
+                    shared_values.remove_orphaned_objects(tx);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::AllowTemporarily(endpoints, duration, tx)) => {
+                    shared_values.allow_temporarily(endpoints, duration, tx);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::RemoveExpiredExceptions) => {
+                    shared_values.remove_expired_exceptions();
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
                 Some(TunnelCommand::Block(reason)) => AfterDisconnect::Block(reason),
                 #[cfg(target_os = "android")]
                 Some(TunnelCommand::BypassSocket(fd, done_tx)) => {
@@ -149,6 +265,11 @@ impl DisconnectingState {
                     let _ = result_tx.send(shared_values.split_tunnel.set_paths(&paths));
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
+                #[cfg(windows)]
+                Some(TunnelCommand::RepairFirewall(result_tx)) => {
+                    shared_values.repair_firewall(result_tx);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
             },
         };
 
@@ -1,6 +1,6 @@
 use super::{
-    ConnectingState, DisconnectedState, EventConsequence, SharedTunnelStateValues, TunnelCommand,
-    TunnelCommandReceiver, TunnelState, TunnelStateTransition, TunnelStateWrapper,
+    ConnectingState, DisconnectedState, EventConsequence, Reconfiguration, SharedTunnelStateValues,
+    TunnelCommand, TunnelCommandReceiver, TunnelState, TunnelStateTransition, TunnelStateWrapper,
 };
 use crate::firewall::FirewallPolicy;
 use futures::StreamExt;
@@ -21,15 +21,20 @@ impl ErrorState {
     ) -> Result<(), FirewallPolicyError> {
         let policy = FirewallPolicy::Blocked {
             allow_lan: shared_values.allow_lan,
+            #[cfg(not(target_os = "android"))]
+            lan_networks: shared_values.lan_networks.clone(),
             allowed_endpoint: shared_values.allowed_endpoint.clone(),
+            exemptions: shared_values.system_service_exemptions.clone(),
+            allowed_endpoints: shared_values.firewall_exceptions.clone(),
+            #[cfg(windows)]
+            allow_virtual_networks: shared_values.allow_virtual_networks,
         };
 
         #[cfg(target_os = "linux")]
         shared_values.disable_connectivity_check();
 
         shared_values
-            .firewall
-            .apply_policy(policy)
+            .apply_firewall_policy(policy)
             .map_err(|error| {
                 log::error!(
                     "{}",
@@ -70,6 +75,10 @@ impl TunnelState for ErrorState {
         shared_values: &mut SharedTunnelStateValues,
         block_reason: Self::Bootstrap,
     ) -> (TunnelStateWrapper, TunnelStateTransition) {
+        shared_values
+            .connection_trace
+            .end_attempt(format!("failed: {}", block_reason));
+
         #[cfg(windows)]
         if let Err(error) = shared_values.split_tunnel.set_tunnel_addresses(None) {
             log::error!(
@@ -99,6 +108,31 @@ impl TunnelState for ErrorState {
         )
     }
 
+    fn reconfigure(
+        self,
+        shared_values: &mut SharedTunnelStateValues,
+        reconfiguration: Reconfiguration,
+    ) -> EventConsequence {
+        let changes = match shared_values.reconfigure(reconfiguration) {
+            Ok(changes) => changes,
+            Err(error_state_cause) => {
+                return EventConsequence::NewState(Self::enter(shared_values, error_state_cause));
+            }
+        };
+
+        if changes.allow_lan || changes.allowed_endpoint || changes.firewall_exceptions {
+            let _ = Self::set_firewall_policy(shared_values);
+        }
+        #[cfg(target_os = "android")]
+        if changes.allowed_endpoint && !Self::create_blocking_tun(shared_values) {
+            return EventConsequence::NewState(Self::enter(
+                shared_values,
+                ErrorStateCause::SetFirewallPolicyError(FirewallPolicyError::Generic),
+            ));
+        }
+        EventConsequence::SameState(self.into())
+    }
+
     fn handle_event(
         self,
         runtime: &tokio::runtime::Handle,
@@ -108,56 +142,89 @@ impl TunnelState for ErrorState {
         use self::EventConsequence::*;
 
         match runtime.block_on(commands.next()) {
-            Some(TunnelCommand::AllowLan(allow_lan)) => {
-                if let Err(error_state_cause) = shared_values.set_allow_lan(allow_lan) {
-                    NewState(Self::enter(shared_values, error_state_cause))
-                } else {
-                    let _ = Self::set_firewall_policy(shared_values);
-                    SameState(self.into())
-                }
+            Some(TunnelCommand::Reconfigure(reconfiguration)) => {
+                self.reconfigure(shared_values, reconfiguration)
             }
             Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
-                if shared_values.set_allowed_endpoint(endpoint) {
-                    let _ = Self::set_firewall_policy(shared_values);
-
-                    #[cfg(target_os = "android")]
-                    if !Self::create_blocking_tun(shared_values) {
-                        return NewState(Self::enter(
-                            shared_values,
-                            ErrorStateCause::SetFirewallPolicyError(FirewallPolicyError::Generic),
-                        ));
+                let reconfiguration = Reconfiguration {
+                    allowed_endpoint: Some(endpoint),
+                    ..Reconfiguration::default()
+                };
+                let consequence = self.reconfigure(shared_values, reconfiguration);
+                if let SameState(_) = consequence {
+                    if let Err(_) = tx.send(()) {
+                        log::error!("The AllowEndpoint receiver was dropped");
                     }
                 }
-                if let Err(_) = tx.send(()) {
-                    log::error!("The AllowEndpoint receiver was dropped");
+                consequence
+            }
+            Some(TunnelCommand::MdnsReflector(mdns_reflector)) => {
+                shared_values.mdns_reflector = mdns_reflector;
+                SameState(self.into())
+            }
+            Some(TunnelCommand::SystemServiceExemptions(exemptions)) => {
+                if shared_values.system_service_exemptions != exemptions {
+                    shared_values.system_service_exemptions = exemptions;
+                    let _ = Self::set_firewall_policy(shared_values);
+                }
+                SameState(self.into())
+            }
+            #[cfg(windows)]
+            Some(TunnelCommand::AllowVirtualNetworks(allow_virtual_networks)) => {
+                if shared_values.allow_virtual_networks != allow_virtual_networks {
+                    shared_values.allow_virtual_networks = allow_virtual_networks;
+                    let _ = Self::set_firewall_policy(shared_values);
                 }
                 SameState(self.into())
             }
-            Some(TunnelCommand::Dns(servers)) => {
-                if let Err(error_state_cause) = shared_values.set_dns_servers(servers) {
-                    NewState(Self::enter(shared_values, error_state_cause))
-                } else {
-                    SameState(self.into())
+            #[cfg(not(target_os = "android"))]
+            Some(TunnelCommand::LanNetworks(lan_networks)) => {
+                if shared_values.lan_networks != lan_networks {
+                    shared_values.lan_networks = lan_networks;
+                    if shared_values.allow_lan {
+                        let _ = Self::set_firewall_policy(shared_values);
+                    }
                 }
+                SameState(self.into())
             }
             Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 shared_values.block_when_disconnected = block_when_disconnected;
                 SameState(self.into())
             }
-            Some(TunnelCommand::IsOffline(is_offline)) => {
-                shared_values.is_offline = is_offline;
-                if !is_offline && self.block_reason == ErrorStateCause::IsOffline {
+            Some(TunnelCommand::IsOffline(connectivity)) => {
+                shared_values.set_connectivity(connectivity);
+                if !connectivity.is_offline() && self.block_reason == ErrorStateCause::IsOffline {
                     NewState(ConnectingState::enter(shared_values, 0))
                 } else {
                     SameState(self.into())
                 }
             }
+            #[cfg(target_os = "macos")]
+            Some(TunnelCommand::DefaultInterfaceChanged(_)) => SameState(self.into()),
             Some(TunnelCommand::Connect) => NewState(ConnectingState::enter(shared_values, 0)),
             Some(TunnelCommand::Disconnect) | None => {
                 #[cfg(target_os = "linux")]
                 shared_values.reset_connectivity_check();
                 NewState(DisconnectedState::enter(shared_values, true))
             }
+            Some(TunnelCommand::GetTrafficCounters(_)) => SameState(self.into()),
+            Some(TunnelCommand::GetTunnelMetadata(_)) => SameState(self.into()),
+            Some(TunnelCommand::GetFirewallRules(tx)) => {
+                shared_values.describe_firewall_policy(tx);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::RemoveOrphanedObjects(tx)) => {
+                shared_values.remove_orphaned_objects(tx);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::AllowTemporarily(endpoints, duration, tx)) => {
+                shared_values.allow_temporarily(endpoints, duration, tx);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::RemoveExpiredExceptions) => {
+                shared_values.remove_expired_exceptions();
+                SameState(self.into())
+            }
             Some(TunnelCommand::Block(reason)) => {
                 NewState(ErrorState::enter(shared_values, reason))
             }
@@ -171,6 +238,11 @@ impl TunnelState for ErrorState {
                 let _ = result_tx.send(shared_values.split_tunnel.set_paths(&paths));
                 SameState(self.into())
             }
+            #[cfg(windows)]
+            Some(TunnelCommand::RepairFirewall(result_tx)) => {
+                shared_values.repair_firewall(result_tx);
+                SameState(self.into())
+            }
         }
     }
 }
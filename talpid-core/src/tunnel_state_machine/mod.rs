@@ -11,23 +11,31 @@ use self::{
     disconnecting_state::{AfterDisconnect, DisconnectingState},
     error_state::ErrorState,
 };
+#[cfg(not(target_os = "android"))]
+use crate::lan_watcher;
+#[cfg(target_os = "linux")]
+pub use crate::offline::{OfflineMonitorConfig, OfflineStrategy};
 #[cfg(windows)]
 use crate::split_tunnel;
 use crate::{
+    connection_trace::{ConnectionTrace, Phase},
     dns::DnsMonitor,
-    firewall::{Firewall, FirewallArguments},
+    firewall::{self, Firewall, FirewallArguments, FirewallPolicy, FirewallRules},
+    future_retry::{self, ExponentialBackoff},
     mpsc::Sender,
     offline,
     routing::RouteManager,
-    tunnel::{tun_provider::TunProvider, TunnelEvent},
+    tunnel::{tun_provider::TunProvider, TrafficCounters, TunnelEvent, TunnelMetadata},
 };
 #[cfg(windows)]
 use std::ffi::OsString;
 
 use futures::{
     channel::{mpsc, oneshot},
-    stream, StreamExt,
+    stream, FutureExt, StreamExt,
 };
+#[cfg(not(target_os = "android"))]
+use ipnetwork::IpNetwork;
 #[cfg(target_os = "android")]
 use std::os::unix::io::RawFd;
 use std::{
@@ -36,13 +44,28 @@ use std::{
     net::IpAddr,
     path::{Path, PathBuf},
     sync::{mpsc as sync_mpsc, Arc},
+    time::Duration,
 };
 #[cfg(target_os = "android")]
-use talpid_types::{android::AndroidContext, ErrorExt};
+use talpid_types::android::AndroidContext;
 use talpid_types::{
-    net::{Endpoint, TunnelParameters},
-    tunnel::{ErrorStateCause, ParameterGenerationError, TunnelStateTransition},
+    cancel::CancelToken,
+    net::{AllowedEndpoint, Connectivity, Endpoint, SystemServiceExemptions, TunnelParameters},
+    tunnel::{ErrorStateCause, ParameterGenerationError, TransitionReason, TunnelStateTransition},
+    ErrorExt,
 };
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Maximum number of commands that may be waiting to be handled by the state machine. Commands
+/// are sent at a human pace, or by producers that wait for room in the queue, so a full queue
+/// means that the state machine is stuck.
+const COMMAND_QUEUE_SIZE: usize = 64;
+
+/// Sends commands to the tunnel state machine.
+pub type TunnelCommandSender = tokio::sync::mpsc::Sender<TunnelCommand>;
+
+/// A state transition, along with what caused it if that is known.
+pub type TunnelStateChange = (TunnelStateTransition, Option<TransitionReason>);
 
 /// Errors that can happen when setting up or using the state machine.
 #[derive(err_derive::Error, Debug)]
@@ -81,10 +104,20 @@ pub enum Error {
 pub struct InitialTunnelState {
     /// Whether to allow LAN traffic when not in the (non-blocking) disconnected state.
     pub allow_lan: bool,
+    /// Whether to relay mDNS traffic between the LAN and the host while connected.
+    pub mdns_reflector: bool,
+    /// Traffic of system services that is allowed in the blocking states.
+    pub system_service_exemptions: SystemServiceExemptions,
+    /// Networks and ports that are allowed outside the tunnel in every state.
+    pub firewall_exceptions: Vec<AllowedEndpoint>,
     /// Block traffic unless connected to the VPN.
     pub block_when_disconnected: bool,
     /// DNS servers to use. If `None`, the tunnel gateway is used.
     pub dns_servers: Option<Vec<IpAddr>>,
+    /// Resolvers that a DNS forwarder on this device sends queries to. DNS to them is allowed
+    /// through the tunnel, but they are not used as system resolvers.
+    #[cfg(not(target_os = "android"))]
+    pub dns_upstream_servers: Vec<IpAddr>,
     /// A single endpoint that is allowed to communicate outside the tunnel, i.e.
     /// in any of the blocking states.
     pub allowed_endpoint: Endpoint,
@@ -93,6 +126,65 @@ pub struct InitialTunnelState {
     /// Programs to exclude from the tunnel using the split tunnel driver.
     #[cfg(windows)]
     pub exclude_paths: Vec<OsString>,
+    /// Whether to allow traffic to and from the subnets of the Hyper-V NAT switches, such as the
+    /// one used by WSL2, in every state.
+    #[cfg(windows)]
+    pub allow_virtual_networks: bool,
+    /// How to infer whether the host is offline.
+    #[cfg(target_os = "linux")]
+    pub offline_monitor: OfflineMonitorConfig,
+    /// Where the phases of the connection attempts are recorded.
+    pub connection_trace: ConnectionTrace,
+    /// How long to wait before retrying after a failed connection attempt.
+    pub reconnect_backoff: ReconnectBackoff,
+}
+
+/// How long to wait before starting the tunnel again after a connection attempt has failed. The
+/// first retry is delayed by `initial_delay`, and every retry after that by `multiplier` times as
+/// long as the one before it, up to `max_delay`. Every delay is then shortened by a random part of
+/// at most `jitter_percent` percent, so that clients that lose their connections at the same time
+/// don't retry in lockstep. The delays start over once a tunnel has connected, when a connection
+/// is asked for, and when the connectivity of the host changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectBackoff {
+    pub initial_delay: Duration,
+    pub multiplier: u32,
+    pub max_delay: Duration,
+    pub jitter_percent: u8,
+}
+
+impl Default for ReconnectBackoff {
+    fn default() -> Self {
+        ReconnectBackoff {
+            initial_delay: Duration::from_secs(1),
+            multiplier: 2,
+            max_delay: Duration::from_secs(30),
+            jitter_percent: 20,
+        }
+    }
+}
+
+impl ReconnectBackoff {
+    /// Returns the delay before the retry that follows `retries` retries, with jitter applied.
+    pub fn delay(&self, retries: u32) -> Duration {
+        let delay = ExponentialBackoff::new(self.initial_delay, self.multiplier)
+            .max_delay(self.max_delay)
+            .nth(retries as usize)
+            .unwrap_or(self.max_delay);
+        future_retry::partial_jitter(delay, f64::from(self.jitter_percent) / 100.0)
+    }
+}
+
+/// The objects left behind by a previous instance, such as one that crashed, that were removed by
+/// [`TunnelCommand::RemoveOrphanedObjects`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct OrphanedObjects {
+    /// The removed routes. Routes are only tagged on Linux and macOS.
+    pub routes: Vec<String>,
+    /// The removed firewall objects, such as tables, chains, anchors or filters.
+    pub firewall_objects: Vec<String>,
+    /// The errors that kept some of the objects from being removed.
+    pub errors: Vec<String>,
 }
 
 /// Spawn the tunnel state machine thread, returning a channel for sending tunnel commands.
@@ -103,12 +195,13 @@ pub async fn spawn(
     log_dir: Option<PathBuf>,
     resource_dir: PathBuf,
     cache_dir: impl AsRef<Path> + Send + 'static,
-    state_change_listener: impl Sender<TunnelStateTransition> + Send + 'static,
-    offline_state_listener: mpsc::UnboundedSender<bool>,
+    state_change_listener: impl Sender<TunnelStateChange> + Send + 'static,
+    offline_state_listener: mpsc::UnboundedSender<Connectivity>,
     shutdown_tx: oneshot::Sender<()>,
+    cancel_token: CancelToken,
     #[cfg(target_os = "android")] android_context: AndroidContext,
-) -> Result<Arc<mpsc::UnboundedSender<TunnelCommand>>, Error> {
-    let (command_tx, command_rx) = mpsc::unbounded();
+) -> Result<Arc<TunnelCommandSender>, Error> {
+    let (command_tx, command_rx) = tokio::sync::mpsc::channel(COMMAND_QUEUE_SIZE);
     let command_tx = Arc::new(command_tx);
 
     let tun_provider = TunProvider::new(
@@ -136,6 +229,7 @@ pub async fn spawn(
             resource_dir,
             cache_dir,
             command_rx,
+            cancel_token,
             #[cfg(target_os = "android")]
             android_context,
         ));
@@ -151,6 +245,8 @@ pub async fn spawn(
         };
 
         state_machine.run(state_change_listener);
+        #[cfg(windows)]
+        crate::tunnel::openvpn::release_wintun_adapter();
 
         if shutdown_tx.send(()).is_err() {
             log::error!("Can't send shutdown completion to daemon");
@@ -165,23 +261,57 @@ pub async fn spawn(
 
 /// Representation of external commands for the tunnel state machine.
 pub enum TunnelCommand {
-    /// Enable or disable LAN access in the firewall.
-    AllowLan(bool),
-    /// Endpoint that should never be blocked.
+    /// Change settings that are shared by every state, and apply them to the firewall, DNS and
+    /// routes in place.
+    Reconfigure(Reconfiguration),
+    /// Enable or disable relaying of mDNS traffic between the LAN and the host while connected.
+    /// This has no effect unless LAN access is allowed.
+    MdnsReflector(bool),
+    /// Set the traffic of system services that is allowed in the blocking states.
+    SystemServiceExemptions(SystemServiceExemptions),
+    /// Allow traffic to and from networks outside the tunnel in every state, for some time. The
+    /// state machine removes the exceptions once they have expired.
+    AllowTemporarily(
+        Vec<AllowedEndpoint>,
+        Duration,
+        oneshot::Sender<Result<(), firewall::Error>>,
+    ),
+    /// Remove the temporary firewall exceptions that have expired.
+    RemoveExpiredExceptions,
+    /// Enable or disable access to the subnets of the Hyper-V NAT switches in the firewall.
+    #[cfg(windows)]
+    AllowVirtualNetworks(bool),
+    /// Notify the state machine of the current on-link networks of the host.
+    #[cfg(not(target_os = "android"))]
+    LanNetworks(Vec<IpNetwork>),
+    /// Endpoint that should never be blocked. This is applied like a [`Reconfiguration`], but
+    /// the sender is notified once it is done.
     /// If an error occurs, the sender is dropped.
     AllowEndpoint(Endpoint, oneshot::Sender<()>),
-    /// Set DNS servers to use.
-    Dns(Option<Vec<IpAddr>>),
     /// Enable or disable the block_when_disconnected feature.
     BlockWhenDisconnected(bool),
     /// Notify the state machine of the connectivity of the device.
-    IsOffline(bool),
+    IsOffline(Connectivity),
+    /// Notify the state machine that the default route moved to another physical interface.
+    #[cfg(target_os = "macos")]
+    DefaultInterfaceChanged(String),
     /// Open tunnel connection.
     Connect,
     /// Close tunnel connection.
     Disconnect,
     /// Disconnect any open tunnel and block all network access
     Block(ErrorStateCause),
+    /// Get the traffic counters of the tunnel interface. The sender is dropped unless the tunnel
+    /// is connected and the counters could be read.
+    GetTrafficCounters(oneshot::Sender<TrafficCounters>),
+    /// Get the interface and addresses of the tunnel. The sender is dropped unless the tunnel is
+    /// connected.
+    GetTunnelMetadata(oneshot::Sender<TunnelMetadata>),
+    /// Get the rules that the firewall currently has in place.
+    GetFirewallRules(oneshot::Sender<io::Result<FirewallRules>>),
+    /// Remove the routes and firewall objects that are tagged as ours but are not in use, such as
+    /// the ones of a daemon that crashed.
+    RemoveOrphanedObjects(oneshot::Sender<OrphanedObjects>),
     /// Bypass a socket, allowing traffic to flow through outside the tunnel.
     #[cfg(target_os = "android")]
     BypassSocket(RawFd, oneshot::Sender<()>),
@@ -191,14 +321,69 @@ pub enum TunnelCommand {
         oneshot::Sender<Result<(), split_tunnel::Error>>,
         Vec<OsString>,
     ),
+    /// Reinstall the objects that the firewall has registered with WFP and reapply the current
+    /// policy. Used after an upgrade, since the objects outlive the daemon.
+    #[cfg(windows)]
+    RepairFirewall(oneshot::Sender<Result<(), firewall::Error>>),
+}
+
+/// Changes to settings that are shared by every tunnel state. They are applied by updating the
+/// firewall, DNS and routes in place, so that an established tunnel is kept, and the firewall
+/// policy is applied once however many settings change. Settings that are `None` are left as they
+/// are.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Reconfiguration {
+    /// Whether traffic to and from the LAN is allowed.
+    pub allow_lan: Option<bool>,
+    /// The DNS servers to use instead of the default ones. `Some(None)` resets them.
+    pub dns_servers: Option<Option<Vec<IpAddr>>>,
+    /// The resolvers that a DNS forwarder on this device sends queries to.
+    #[cfg(not(target_os = "android"))]
+    pub dns_upstream_servers: Option<Vec<IpAddr>>,
+    /// The endpoint that should never be blocked.
+    pub allowed_endpoint: Option<Endpoint>,
+    /// The networks and ports that are allowed outside the tunnel in every state.
+    pub firewall_exceptions: Option<Vec<AllowedEndpoint>>,
+    /// How long to wait before retrying after a failed connection attempt. Takes effect on the
+    /// next retry.
+    pub reconnect_backoff: Option<ReconnectBackoff>,
+}
+
+/// What a [`Reconfiguration`] changed, so that the current state knows what to apply.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct ReconfigurationChanges {
+    /// LAN access was allowed or blocked.
+    allow_lan: bool,
+    /// The DNS servers changed.
+    dns_servers: bool,
+    /// The resolvers of a local DNS forwarder changed.
+    #[cfg(not(target_os = "android"))]
+    dns_upstream_servers: bool,
+    /// The endpoint that should never be blocked changed.
+    allowed_endpoint: bool,
+    /// The firewall exceptions changed.
+    firewall_exceptions: bool,
 }
 
-type TunnelCommandReceiver = stream::Fuse<mpsc::UnboundedReceiver<TunnelCommand>>;
+impl ReconfigurationChanges {
+    /// Whether an established tunnel has to be reconnected. On Android, the tunnel device is
+    /// recreated when LAN access or the DNS servers change, which closes the tunnel.
+    fn requires_reconnect(&self) -> bool {
+        cfg!(target_os = "android") && (self.allow_lan || self.dns_servers)
+    }
+}
+
+type TunnelCommandReceiver = stream::Fuse<ReceiverStream<TunnelCommand>>;
 
 enum EventResult {
     Command(Option<TunnelCommand>),
     Event(Option<(TunnelEvent, oneshot::Sender<()>)>),
     Close(Result<Option<ErrorStateCause>, oneshot::Canceled>),
+    /// The delay before starting the tunnel again has passed.
+    RetryDelayElapsed,
+    /// The tunnel interface was removed by something other than the tunnel.
+    #[cfg(not(target_os = "android"))]
+    InterfaceRemoved,
 }
 
 /// Asynchronous handling of the tunnel state machine.
@@ -217,24 +402,52 @@ impl TunnelStateMachine {
     async fn new(
         runtime: tokio::runtime::Handle,
         settings: InitialTunnelState,
-        command_tx: std::sync::Weak<mpsc::UnboundedSender<TunnelCommand>>,
-        offline_state_tx: mpsc::UnboundedSender<bool>,
+        command_tx: std::sync::Weak<TunnelCommandSender>,
+        offline_state_tx: mpsc::UnboundedSender<Connectivity>,
         tunnel_parameters_generator: impl TunnelParametersGenerator,
         tun_provider: TunProvider,
         log_dir: Option<PathBuf>,
         resource_dir: PathBuf,
         cache_dir: impl AsRef<Path>,
-        commands_rx: mpsc::UnboundedReceiver<TunnelCommand>,
+        commands_rx: tokio::sync::mpsc::Receiver<TunnelCommand>,
+        cancel_token: CancelToken,
         #[cfg(target_os = "android")] android_context: AndroidContext,
     ) -> Result<Self, Error> {
         #[cfg(windows)]
         let split_tunnel = split_tunnel::SplitTunnel::new(command_tx.clone())
             .map_err(Error::InitSplitTunneling)?;
 
+        #[cfg(not(target_os = "android"))]
+        let (lan_watcher, lan_networks) = {
+            let (lan_tx, mut lan_rx) = mpsc::unbounded();
+            let command_tx = command_tx.clone();
+            tokio::spawn(async move {
+                while let Some(mut networks) = lan_rx.next().await {
+                    while let Some(Some(next_networks)) = lan_rx.next().now_or_never() {
+                        networks = next_networks;
+                    }
+                    let tx = match command_tx.upgrade() {
+                        Some(tx) => tx,
+                        None => break,
+                    };
+                    if tx.send(TunnelCommand::LanNetworks(networks)).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            let lan_watcher = lan_watcher::spawn_watcher(lan_tx).await;
+            let lan_networks = lan_watcher.networks();
+            (lan_watcher, lan_networks)
+        };
+
         let args = FirewallArguments {
             initialize_blocked: settings.block_when_disconnected || !settings.reset_firewall,
             allow_lan: settings.allow_lan,
+            #[cfg(not(target_os = "android"))]
+            lan_networks: lan_networks.clone(),
             allowed_endpoint: Some(settings.allowed_endpoint),
+            #[cfg(windows)]
+            command_tx: Some(command_tx.clone()),
         };
 
         let firewall = Firewall::new(args).map_err(Error::InitFirewallError)?;
@@ -251,31 +464,78 @@ impl TunnelStateMachine {
         )
         .map_err(Error::InitDnsMonitorError)?;
 
+        #[cfg(target_os = "macos")]
+        let (interface_tx, mut interface_rx) = mpsc::unbounded();
+        #[cfg(target_os = "macos")]
+        {
+            let command_tx = command_tx.clone();
+            tokio::spawn(async move {
+                while let Some(mut interface) = interface_rx.next().await {
+                    while let Some(Some(next_interface)) = interface_rx.next().now_or_never() {
+                        interface = next_interface;
+                    }
+                    let tx = match command_tx.upgrade() {
+                        Some(tx) => tx,
+                        None => break,
+                    };
+                    if tx
+                        .send(TunnelCommand::DefaultInterfaceChanged(interface))
+                        .await
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            });
+        }
+
         let (offline_tx, mut offline_rx) = mpsc::unbounded();
         let initial_offline_state_tx = offline_state_tx.clone();
+        let offline_command_tx = command_tx.clone();
         tokio::spawn(async move {
-            while let Some(offline) = offline_rx.next().await {
-                if let Some(tx) = command_tx.upgrade() {
-                    let _ = tx.unbounded_send(TunnelCommand::IsOffline(offline));
-                } else {
+            let mut last_connectivity = None;
+            while let Some(mut connectivity) = offline_rx.next().await {
+                // Only the latest state matters if the monitor reported several in a row
+                while let Some(Some(next_connectivity)) = offline_rx.next().now_or_never() {
+                    connectivity = next_connectivity;
+                }
+                if last_connectivity == Some(connectivity) {
+                    continue;
+                }
+                last_connectivity = Some(connectivity);
+
+                let tx = match offline_command_tx.upgrade() {
+                    Some(tx) => tx,
+                    None => break,
+                };
+                if tx
+                    .send(TunnelCommand::IsOffline(connectivity))
+                    .await
+                    .is_err()
+                {
                     break;
                 }
-                let _ = offline_state_tx.unbounded_send(offline);
+                let _ = offline_state_tx.unbounded_send(connectivity);
             }
         });
         let mut offline_monitor = offline::spawn_monitor(
             offline_tx,
+            cancel_token.child(),
+            #[cfg(target_os = "macos")]
+            interface_tx,
             #[cfg(target_os = "linux")]
             route_manager
                 .handle()
                 .map_err(Error::InitRouteManagerError)?,
+            #[cfg(target_os = "linux")]
+            settings.offline_monitor.clone(),
             #[cfg(target_os = "android")]
             android_context,
         )
         .await
         .map_err(Error::OfflineMonitorError)?;
-        let is_offline = offline_monitor.is_offline().await;
-        let _ = initial_offline_state_tx.unbounded_send(is_offline);
+        let connectivity = offline_monitor.connectivity().await;
+        let _ = initial_offline_state_tx.unbounded_send(connectivity);
 
         #[cfg(windows)]
         split_tunnel
@@ -290,12 +550,29 @@ impl TunnelStateMachine {
             dns_monitor,
             route_manager,
             _offline_monitor: offline_monitor,
+            #[cfg(not(target_os = "android"))]
+            _lan_watcher: lan_watcher,
             allow_lan: settings.allow_lan,
+            #[cfg(not(target_os = "android"))]
+            lan_networks,
+            mdns_reflector: settings.mdns_reflector,
+            system_service_exemptions: settings.system_service_exemptions,
+            firewall_exceptions: settings.firewall_exceptions,
+            #[cfg(windows)]
+            allow_virtual_networks: settings.allow_virtual_networks,
             block_when_disconnected: settings.block_when_disconnected,
-            is_offline,
+            connectivity,
+            transition_reason: None,
             dns_servers: settings.dns_servers,
+            #[cfg(not(target_os = "android"))]
+            dns_upstream_servers: settings.dns_upstream_servers,
             allowed_endpoint: settings.allowed_endpoint,
             tunnel_parameters_generator: Box::new(tunnel_parameters_generator),
+            connection_trace: settings.connection_trace,
+            reconnect_backoff: settings.reconnect_backoff,
+            backoff_retries: 0,
+            cancel_token,
+            command_tx,
             tun_provider,
             log_dir,
             resource_dir,
@@ -308,12 +585,12 @@ impl TunnelStateMachine {
 
         Ok(TunnelStateMachine {
             current_state: Some(initial_state),
-            commands: commands_rx.fuse(),
+            commands: ReceiverStream::new(commands_rx).fuse(),
             shared_values,
         })
     }
 
-    fn run(mut self, change_listener: impl Sender<TunnelStateTransition> + Send + 'static) {
+    fn run(mut self, change_listener: impl Sender<TunnelStateChange> + Send + 'static) {
         use EventConsequence::*;
 
         let runtime = self.shared_values.runtime.clone();
@@ -323,9 +600,10 @@ impl TunnelStateMachine {
             {
                 NewState((state, transition)) => {
                     self.current_state = Some(state);
+                    let reason = self.shared_values.transition_reason.take();
 
                     if let Err(error) = change_listener
-                        .send(transition)
+                        .send((transition, reason))
                         .map_err(|_| Error::SendStateChange)
                     {
                         log::error!("{}", error);
@@ -334,6 +612,7 @@ impl TunnelStateMachine {
                 }
                 SameState(state) => {
                     self.current_state = Some(state);
+                    self.shared_values.transition_reason = None;
                 }
                 Finished => (),
             }
@@ -366,18 +645,48 @@ struct SharedTunnelStateValues {
     dns_monitor: DnsMonitor,
     route_manager: RouteManager,
     _offline_monitor: offline::MonitorHandle,
+    #[cfg(not(target_os = "android"))]
+    _lan_watcher: lan_watcher::LanWatcher,
     /// Should LAN access be allowed outside the tunnel.
     allow_lan: bool,
+    /// The on-link networks of the host, which are reachable if LAN access is allowed.
+    #[cfg(not(target_os = "android"))]
+    lan_networks: Vec<IpNetwork>,
+    /// Should mDNS traffic be relayed between the LAN and the host while connected.
+    mdns_reflector: bool,
+    /// Traffic of system services that should be allowed in the blocking states.
+    system_service_exemptions: SystemServiceExemptions,
+    /// Networks and ports that should be allowed outside the tunnel in every state.
+    firewall_exceptions: Vec<AllowedEndpoint>,
+    /// Should the subnets of the Hyper-V NAT switches be reachable in every state.
+    #[cfg(windows)]
+    allow_virtual_networks: bool,
     /// Should network access be allowed when in the disconnected state.
     block_when_disconnected: bool,
-    /// True when the computer is known to be offline.
-    is_offline: bool,
+    /// The IP versions over which the computer is known to be online.
+    connectivity: Connectivity,
+    /// What caused the state transition that is about to be made, if it is known.
+    transition_reason: Option<TransitionReason>,
     /// DNS servers to use (overriding default).
     dns_servers: Option<Vec<IpAddr>>,
+    /// Resolvers that a DNS forwarder on this device sends queries to.
+    #[cfg(not(target_os = "android"))]
+    dns_upstream_servers: Vec<IpAddr>,
     /// Endpoint that should not be blocked by the firewall.
     allowed_endpoint: Endpoint,
     /// The generator of new `TunnelParameter`s
     tunnel_parameters_generator: Box<dyn TunnelParametersGenerator>,
+    /// Where the phases of the connection attempts are recorded.
+    connection_trace: ConnectionTrace,
+    /// How long to wait before retrying after a failed connection attempt.
+    reconnect_backoff: ReconnectBackoff,
+    /// The number of retries since the backoff delays were last started over.
+    backoff_retries: u32,
+    /// Cancelled when the state machine is shut down. The tunnels and monitors that are started
+    /// by the state machine stop waiting on anything once it is cancelled.
+    cancel_token: CancelToken,
+    /// Used to remove temporary firewall exceptions once they have expired.
+    command_tx: std::sync::Weak<TunnelCommandSender>,
     /// The provider of tunnel devices.
     tun_provider: TunProvider,
     /// Directory to store tunnel log file.
@@ -391,7 +700,44 @@ struct SharedTunnelStateValues {
 }
 
 impl SharedTunnelStateValues {
-    pub fn set_allow_lan(&mut self, allow_lan: bool) -> Result<(), ErrorStateCause> {
+    /// Updates the settings that are shared by every state, without applying them. Returns what
+    /// changed, so that the current state can apply it. Settings that come before one that fails
+    /// to be set are kept.
+    fn reconfigure(
+        &mut self,
+        reconfiguration: Reconfiguration,
+    ) -> Result<ReconfigurationChanges, ErrorStateCause> {
+        let mut changes = ReconfigurationChanges::default();
+        if let Some(endpoint) = reconfiguration.allowed_endpoint {
+            changes.allowed_endpoint = self.set_allowed_endpoint(endpoint);
+        }
+        if let Some(exceptions) = reconfiguration.firewall_exceptions {
+            if self.firewall_exceptions != exceptions {
+                self.firewall_exceptions = exceptions;
+                changes.firewall_exceptions = true;
+            }
+        }
+        if let Some(allow_lan) = reconfiguration.allow_lan {
+            changes.allow_lan = self.allow_lan != allow_lan;
+            self.set_allow_lan(allow_lan)?;
+        }
+        if let Some(dns_servers) = reconfiguration.dns_servers {
+            changes.dns_servers = self.set_dns_servers(dns_servers)?;
+        }
+        #[cfg(not(target_os = "android"))]
+        if let Some(servers) = reconfiguration.dns_upstream_servers {
+            if self.dns_upstream_servers != servers {
+                self.dns_upstream_servers = servers;
+                changes.dns_upstream_servers = true;
+            }
+        }
+        if let Some(reconnect_backoff) = reconfiguration.reconnect_backoff {
+            self.reconnect_backoff = reconnect_backoff;
+        }
+        Ok(changes)
+    }
+
+    fn set_allow_lan(&mut self, allow_lan: bool) -> Result<(), ErrorStateCause> {
         if self.allow_lan != allow_lan {
             self.allow_lan = allow_lan;
 
@@ -427,7 +773,7 @@ impl SharedTunnelStateValues {
         }
     }
 
-    pub fn set_dns_servers(
+    fn set_dns_servers(
         &mut self,
         dns_servers: Option<Vec<IpAddr>>,
     ) -> Result<bool, ErrorStateCause> {
@@ -479,6 +825,121 @@ impl SharedTunnelStateValues {
         }
     }
 
+    pub fn set_connectivity(&mut self, connectivity: Connectivity) {
+        if connectivity.is_offline() != self.connectivity.is_offline() {
+            self.transition_reason = Some(TransitionReason::ConnectivityChanged {
+                is_offline: connectivity.is_offline(),
+            });
+        }
+        if connectivity != self.connectivity {
+            // The routes or interfaces changed, so earlier failures say little about the next try
+            self.reset_backoff();
+        }
+        self.connectivity = connectivity;
+    }
+
+    /// Returns how long to wait before the next retry, and counts the retry.
+    pub fn next_retry_delay(&mut self) -> Duration {
+        let delay = self.reconnect_backoff.delay(self.backoff_retries);
+        self.backoff_retries = self.backoff_retries.saturating_add(1);
+        delay
+    }
+
+    /// Starts the retry delays over from the initial delay.
+    pub fn reset_backoff(&mut self) {
+        self.backoff_retries = 0;
+    }
+
+    /// Applies the firewall policy, recording the failure as the reason for the next transition.
+    pub fn apply_firewall_policy(&mut self, policy: FirewallPolicy) -> Result<(), firewall::Error> {
+        let mut span = self.connection_trace.enter(Phase::Firewall);
+        let result = self.firewall.apply_policy(policy);
+        if let Err(error) = &result {
+            span.set_error(error.display_chain());
+            self.transition_reason = Some(TransitionReason::SetFirewallPolicyFailed {
+                error: error.display_chain(),
+                error_code: os_error_code(error),
+            });
+        }
+        result
+    }
+
+    pub fn describe_firewall_policy(&self, tx: oneshot::Sender<io::Result<FirewallRules>>) {
+        let _ = tx.send(self.firewall.describe_policy());
+    }
+
+    pub fn remove_orphaned_objects(&mut self, tx: oneshot::Sender<OrphanedObjects>) {
+        let mut orphans = OrphanedObjects::default();
+
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        match self
+            .runtime
+            .block_on(self.route_manager.remove_orphaned_routes())
+        {
+            Ok(routes) => orphans.routes = routes.iter().map(ToString::to_string).collect(),
+            Err(error) => {
+                let error = error.display_chain_with_msg("Failed to remove orphaned routes");
+                log::error!("{}", error);
+                orphans.errors.push(error);
+            }
+        }
+
+        match self.firewall.remove_orphaned_objects() {
+            Ok(objects) => orphans.firewall_objects = objects,
+            Err(error) => {
+                let error =
+                    error.display_chain_with_msg("Failed to remove orphaned firewall objects");
+                log::error!("{}", error);
+                orphans.errors.push(error);
+            }
+        }
+
+        let _ = tx.send(orphans);
+    }
+
+    /// Allows traffic to and from `endpoint` outside the tunnel for `duration`. The timer that
+    /// removes the exception belongs to the state machine, so the exception expires even if
+    /// whatever asked for it goes away, and even if the exception could not be applied.
+    pub fn allow_temporarily(
+        &mut self,
+        endpoints: Vec<AllowedEndpoint>,
+        duration: Duration,
+        tx: oneshot::Sender<Result<(), firewall::Error>>,
+    ) {
+        let result = self.firewall.allow_temporarily(endpoints, duration);
+        if let Err(error) = &result {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to add a temporary firewall exception")
+            );
+        }
+        let _ = tx.send(result);
+
+        let command_tx = self.command_tx.clone();
+        self.runtime.spawn(async move {
+            tokio::time::sleep(duration).await;
+            if let Some(command_tx) = command_tx.upgrade() {
+                let _ = command_tx
+                    .send(TunnelCommand::RemoveExpiredExceptions)
+                    .await;
+            }
+        });
+    }
+
+    pub fn remove_expired_exceptions(&mut self) {
+        if let Err(error) = self.firewall.remove_expired_exceptions() {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to remove expired firewall exceptions")
+            );
+        }
+    }
+
+    #[cfg(windows)]
+    pub fn repair_firewall(&mut self, tx: oneshot::Sender<Result<(), firewall::Error>>) {
+        let _ = tx.send(self.firewall.repair_objects());
+    }
+
     #[cfg(target_os = "android")]
     pub fn bypass_socket(&mut self, fd: RawFd, tx: oneshot::Sender<()>) {
         if let Err(err) = self.tun_provider.bypass(fd) {
@@ -488,6 +949,21 @@ impl SharedTunnelStateValues {
     }
 }
 
+/// Finds the OS error code of the first I/O error in the chain of sources of `error`.
+fn os_error_code(error: &(dyn std::error::Error + 'static)) -> Option<i32> {
+    let mut source = Some(error);
+    while let Some(error) = source {
+        if let Some(code) = error
+            .downcast_ref::<io::Error>()
+            .and_then(io::Error::raw_os_error)
+        {
+            return Some(code);
+        }
+        source = error.source();
+    }
+    None
+}
+
 /// Asynchronous result of an attempt to progress a state.
 enum EventConsequence {
     /// Transition to a new state.
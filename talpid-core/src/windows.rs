@@ -1,34 +1,35 @@
+use futures::{
+    channel::mpsc::{self, UnboundedReceiver},
+    stream::Stream,
+    StreamExt,
+};
+use ipnetwork::IpNetwork;
 use std::{
     ffi::OsStr,
     fmt, io, mem,
-    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
-    os::windows::{ffi::OsStrExt, io::RawHandle},
-    ptr,
-    sync::Mutex,
-    time::{Duration, Instant},
+    net::IpAddr,
+    os::windows::ffi::OsStrExt,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use talpid_types::cancel::CancelToken;
+use talpid_windows_net::{
+    notify_ip_interface_change, notify_unicast_address_change, AddressFamily, DadState,
+    IpInterface, IpNotifierHandle, Luid, NotificationType, UnicastAddress, UnicastNotifierHandle,
 };
-use winapi::shared::{
-    ifdef::NET_LUID,
-    in6addr::IN6_ADDR,
-    inaddr::IN_ADDR,
-    netioapi::{
-        CancelMibChangeNotify2, ConvertInterfaceAliasToLuid, FreeMibTable, GetIpInterfaceEntry,
-        GetUnicastIpAddressEntry, GetUnicastIpAddressTable, MibAddInstance,
-        NotifyIpInterfaceChange, SetIpInterfaceEntry, MIB_IPINTERFACE_ROW,
-        MIB_UNICASTIPADDRESS_ROW, MIB_UNICASTIPADDRESS_TABLE,
-    },
-    nldef::{IpDadStatePreferred, IpDadStateTentative, NL_DAD_STATE},
-    ntdef::FALSE,
-    winerror::{ERROR_NOT_FOUND, NO_ERROR},
-    ws2def::{AF_INET, AF_INET6, AF_UNSPEC},
-    ws2ipdef::SOCKADDR_INET,
+use winapi::um::{
+    libloaderapi::{GetModuleHandleW, GetProcAddress},
+    processthreadsapi::GetCurrentProcess,
+    sysinfoapi::{GetNativeSystemInfo, SYSTEM_INFO},
+    winnt::HANDLE,
 };
 
 /// Result type for this module.
 pub type Result<T> = std::result::Result<T, Error>;
 
-const DAD_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
-const DAD_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+/// How long [`wait_for_addresses`] usually waits for duplicate address detection to finish.
+pub const DAD_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// Errors returned by some functions in this module.
 #[derive(err_derive::Error, Debug)]
@@ -59,180 +60,134 @@ pub enum Error {
     #[error(display = "Timed out waiting on tunnel device")]
     DeviceReadyTimeout,
 
-    /// Unicast DAD check fail.
+    /// The wait for the tunnel device was cancelled.
     #[cfg(windows)]
-    #[error(display = "Unicast channel sender was unexpectedly dropped")]
-    UnicastSenderDropped,
+    #[error(display = "Stopped waiting on tunnel device since the wait was cancelled")]
+    Cancelled,
 
-    /// Unknown address family
-    #[error(display = "Unknown address family: {}", _0)]
-    UnknownAddressFamily(i32),
+    /// Failed to register for network change notifications.
+    #[cfg(windows)]
+    #[error(display = "Failed to register for network change notifications")]
+    NotifyChange(#[error(source)] io::Error),
 }
 
-/// Address family. These correspond to the `AF_*` constants.
+/// A change reported by [`NetEventStream`].
 #[derive(Debug, Clone, Copy)]
-pub enum AddressFamily {
-    /// IPv4 address family
-    Ipv4 = AF_INET as isize,
-    /// IPv6 address family
-    Ipv6 = AF_INET6 as isize,
-}
-
-impl fmt::Display for AddressFamily {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
-            AddressFamily::Ipv4 => write!(f, "IPv4 (AF_INET)"),
-            AddressFamily::Ipv6 => write!(f, "IPv6 (AF_INET6)"),
-        }
-    }
-}
-
-impl AddressFamily {
-    /// Convert an [`AddressFamily`] to one of the `AF_*` constants.
-    pub fn try_from_af_family(family: u16) -> Result<AddressFamily> {
-        match family as i32 {
-            AF_INET => Ok(AddressFamily::Ipv4),
-            AF_INET6 => Ok(AddressFamily::Ipv6),
-            family => Err(Error::UnknownAddressFamily(family)),
-        }
-    }
+pub enum NetEvent {
+    /// An IP interface was added, removed, or changed.
+    Interface(IpInterface, NotificationType),
+    /// A unicast IP address was added, removed, or changed.
+    UnicastAddress(UnicastAddress, NotificationType),
 }
 
-/// Context for [`notify_ip_interface_change`]. When it is dropped,
-/// the callback is unregistered.
-pub struct IpNotifierHandle<'a> {
-    callback: Mutex<Box<dyn FnMut(&MIB_IPINTERFACE_ROW, u32) + Send + 'a>>,
-    handle: RawHandle,
+/// Stream of changes to IP interfaces and unicast IP addresses. The notifications are
+/// unregistered when the stream is dropped, so dropping a future that waits on it cancels the
+/// wait.
+pub struct NetEventStream {
+    events: UnboundedReceiver<NetEvent>,
+    _interface_notifier: Box<IpNotifierHandle<'static>>,
+    _address_notifier: Box<UnicastNotifierHandle<'static>>,
 }
 
-unsafe impl Send for IpNotifierHandle<'_> {}
-
-impl<'a> Drop for IpNotifierHandle<'a> {
-    fn drop(&mut self) {
-        unsafe { CancelMibChangeNotify2(self.handle as *mut _) };
+impl NetEventStream {
+    /// Starts listening for changes. If `luid` is given, only changes to that network interface
+    /// are reported.
+    pub fn new(luid: Option<Luid>) -> io::Result<Self> {
+        let (tx, events) = mpsc::unbounded();
+        let is_relevant = move |row_luid: Luid| luid.map(|luid| luid == row_luid).unwrap_or(true);
+
+        let interface_tx = tx.clone();
+        let interface_notifier = notify_ip_interface_change(
+            move |interface, notification_type| {
+                if is_relevant(interface.luid()) {
+                    let _ = interface_tx
+                        .unbounded_send(NetEvent::Interface(*interface, notification_type));
+                }
+            },
+            None,
+        )?;
+        let address_notifier = notify_unicast_address_change(
+            move |address, notification_type| {
+                if is_relevant(address.luid()) {
+                    let _ =
+                        tx.unbounded_send(NetEvent::UnicastAddress(*address, notification_type));
+                }
+            },
+            None,
+        )?;
+
+        Ok(NetEventStream {
+            events,
+            _interface_notifier: interface_notifier,
+            _address_notifier: address_notifier,
+        })
     }
-}
-
-unsafe extern "system" fn inner_callback(
-    context: *mut winapi::ctypes::c_void,
-    row: *mut MIB_IPINTERFACE_ROW,
-    notify_type: u32,
-) {
-    let context = &mut *(context as *mut IpNotifierHandle<'_>);
-    context
-        .callback
-        .lock()
-        .expect("NotifyIpInterfaceChange mutex poisoned")(&*row, notify_type);
-}
-
-/// Registers a callback function that is invoked when an interface is added, removed,
-/// or changed.
-pub fn notify_ip_interface_change<'a, T: FnMut(&MIB_IPINTERFACE_ROW, u32) + Send + 'a>(
-    callback: T,
-    family: Option<AddressFamily>,
-) -> io::Result<Box<IpNotifierHandle<'a>>> {
-    let mut context = Box::new(IpNotifierHandle {
-        callback: Mutex::new(Box::new(callback)),
-        handle: std::ptr::null_mut(),
-    });
 
-    let status = unsafe {
-        NotifyIpInterfaceChange(
-            af_family_from_family(family),
-            Some(inner_callback),
-            &mut *context as *mut _ as *mut _,
-            FALSE,
-            (&mut context.handle) as *mut _,
-        )
-    };
-
-    if status == NO_ERROR {
-        Ok(context)
-    } else {
-        Err(io::Error::from_raw_os_error(status as i32))
-    }
-}
-
-/// Returns information about a network IP interface.
-pub fn get_ip_interface_entry(
-    family: AddressFamily,
-    luid: &NET_LUID,
-) -> io::Result<MIB_IPINTERFACE_ROW> {
-    let mut row: MIB_IPINTERFACE_ROW = unsafe { mem::zeroed() };
-    row.Family = family as u16;
-    row.InterfaceLuid = *luid;
-
-    let result = unsafe { GetIpInterfaceEntry(&mut row) };
-    if result == NO_ERROR {
-        Ok(row)
-    } else {
-        Err(io::Error::from_raw_os_error(result as i32))
+    /// Waits until `condition` returns `true`. It is checked once immediately, and then again
+    /// after every change. Returns `false` if `timeout` elapses first.
+    pub async fn wait_until<F, E>(
+        &mut self,
+        timeout: Option<Duration>,
+        mut condition: F,
+    ) -> std::result::Result<bool, E>
+    where
+        F: FnMut() -> std::result::Result<bool, E>,
+    {
+        let events = &mut self.events;
+        let wait = async move {
+            loop {
+                if condition()? {
+                    return Ok(true);
+                }
+                if events.next().await.is_none() {
+                    return Ok(false);
+                }
+            }
+        };
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, wait)
+                .await
+                .unwrap_or(Ok(false)),
+            None => wait.await,
+        }
     }
 }
 
-/// Set the properties of an IP interface.
-pub fn set_ip_interface_entry(row: &MIB_IPINTERFACE_ROW) -> io::Result<()> {
-    let result = unsafe { SetIpInterfaceEntry(row as *const _ as *mut _) };
-    if result == NO_ERROR {
-        Ok(())
-    } else {
-        Err(io::Error::from_raw_os_error(result as i32))
-    }
-}
+impl Stream for NetEventStream {
+    type Item = NetEvent;
 
-fn ip_interface_entry_exists(family: AddressFamily, luid: &NET_LUID) -> io::Result<bool> {
-    match get_ip_interface_entry(family, luid) {
-        Ok(_) => Ok(true),
-        Err(error) if error.raw_os_error() == Some(ERROR_NOT_FOUND as i32) => Ok(false),
-        Err(error) => Err(error),
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<NetEvent>> {
+        self.events.poll_next_unpin(cx)
     }
 }
 
 /// Waits until the specified IP interfaces have attached to a given network interface.
-pub async fn wait_for_interfaces(luid: NET_LUID, ipv4: bool, ipv6: bool) -> io::Result<()> {
-    let (tx, rx) = futures::channel::oneshot::channel();
-
-    let mut found_ipv4 = if ipv4 { false } else { true };
-    let mut found_ipv6 = if ipv6 { false } else { true };
-
-    let mut tx = Some(tx);
-
-    let _handle = notify_ip_interface_change(
-        move |row, notification_type| {
-            if found_ipv4 && found_ipv6 {
-                return;
-            }
-            if notification_type != MibAddInstance {
-                return;
-            }
-            if row.InterfaceLuid.Value != luid.Value {
-                return;
-            }
-            match row.Family as i32 {
-                AF_INET => found_ipv4 = true,
-                AF_INET6 => found_ipv6 = true,
-                _ => (),
-            }
-            if found_ipv4 && found_ipv6 {
-                if let Some(tx) = tx.take() {
-                    let _ = tx.send(());
-                }
-            }
-        },
-        None,
-    )?;
-
-    // Make sure they don't already exist
-    if (!ipv4 || ip_interface_entry_exists(AddressFamily::Ipv4, &luid)?)
-        && (!ipv6 || ip_interface_entry_exists(AddressFamily::Ipv6, &luid)?)
-    {
-        return Ok(());
-    }
-
-    let _ = rx.await;
+pub async fn wait_for_interfaces(luid: Luid, ipv4: bool, ipv6: bool) -> io::Result<()> {
+    let mut events = NetEventStream::new(Some(luid))?;
+    events
+        .wait_until(None, || -> io::Result<bool> {
+            Ok((!ipv4 || IpInterface::exists(AddressFamily::Ipv4, luid)?)
+                && (!ipv6 || IpInterface::exists(AddressFamily::Ipv6, luid)?))
+        })
+        .await?;
     Ok(())
 }
 
+/// Waits until the IPv4 interface has attached to a given network interface, and then waits at
+/// most `ipv6_timeout` for the IPv6 interface. Returns whether the IPv6 interface has attached.
+pub async fn wait_for_optional_ipv6_interface(
+    luid: Luid,
+    ipv6_timeout: Duration,
+) -> io::Result<bool> {
+    wait_for_interfaces(luid, true, false).await?;
+    let mut events = NetEventStream::new(Some(luid))?;
+    events
+        .wait_until(Some(ipv6_timeout), || {
+            IpInterface::exists(AddressFamily::Ipv6, luid)
+        })
+        .await
+}
+
 /// Handles cases where there DAD state is neither tentative nor preferred.
 #[cfg(windows)]
 #[derive(err_derive::Error, Debug)]
@@ -255,200 +210,239 @@ pub enum DadStateError {
 }
 
 #[cfg(windows)]
-#[allow(non_upper_case_globals)]
-impl From<NL_DAD_STATE> for DadStateError {
-    fn from(state: NL_DAD_STATE) -> DadStateError {
-        use winapi::shared::nldef::*;
+impl DadStateError {
+    /// Returns the error for a DAD state that means that the address cannot be used, or `None`
+    /// if it can be used, or will be once DAD has finished.
+    fn from_state(state: DadState) -> Option<Self> {
         match state {
-            IpDadStateInvalid => DadStateError::Invalid,
-            IpDadStateDuplicate => DadStateError::Duplicate,
-            IpDadStateDeprecated => DadStateError::Deprecated,
-            other => DadStateError::Unknown(other),
+            DadState::Tentative | DadState::Preferred => None,
+            DadState::Invalid => Some(DadStateError::Invalid),
+            DadState::Duplicate => Some(DadStateError::Duplicate),
+            DadState::Deprecated => Some(DadStateError::Deprecated),
+            DadState::Unknown(state) => Some(DadStateError::Unknown(state)),
         }
     }
 }
 
-/// Wait for addresses to be usable on an network adapter.
-pub async fn wait_for_addresses(luid: NET_LUID) -> Result<()> {
-    // Obtain unicast IP addresses
-    let mut unicast_rows: Vec<MIB_UNICASTIPADDRESS_ROW> = get_unicast_table(None)
-        .map_err(Error::ObtainUnicastAddress)?
-        .into_iter()
-        .filter(|row| row.InterfaceLuid.Value == luid.Value)
-        .collect();
-    if unicast_rows.is_empty() {
+/// Wait for addresses to be usable on an network adapter. Fails if duplicate address detection
+/// does not finish within `timeout`, or if `cancel_token` is cancelled before then.
+///
+/// If `ipv6_optional` is set, IPv6 addresses that fail duplicate address detection, or that have
+/// not finished it by the timeout, are ignored. Returns whether all IPv6 addresses are usable.
+pub async fn wait_for_addresses(
+    luid: Luid,
+    timeout: Duration,
+    ipv6_optional: bool,
+    cancel_token: &CancelToken,
+) -> Result<bool> {
+    let mut addresses = UnicastAddress::for_interface(luid).map_err(Error::ObtainUnicastAddress)?;
+    if addresses.is_empty() {
         return Err(Error::NoUnicastAddress);
     }
 
-    let (tx, rx) = futures::channel::oneshot::channel();
-    let mut addr_check_thread = move || {
-        // Poll DAD status using GetUnicastIpAddressEntry
-        // https://docs.microsoft.com/en-us/windows/win32/api/netioapi/nf-netioapi-createunicastipaddressentry
-
-        let deadline = Instant::now() + DAD_CHECK_TIMEOUT;
-        while Instant::now() < deadline {
-            let mut ready = true;
-
-            for row in &mut unicast_rows {
-                let status = unsafe { GetUnicastIpAddressEntry(row) };
-                if status != NO_ERROR {
-                    return Err(Error::ObtainUnicastAddress(io::Error::from_raw_os_error(
-                        status as i32,
-                    )));
-                }
-                if row.DadState == IpDadStateTentative {
-                    ready = false;
-                    break;
-                }
-                if row.DadState != IpDadStatePreferred {
-                    return Err(Error::DadStateError(DadStateError::from(row.DadState)));
+    let mut required_ready = false;
+    let mut ipv6_failed = false;
+
+    // Check the DAD status using GetUnicastIpAddressEntry whenever an address changes
+    // https://docs.microsoft.com/en-us/windows/win32/api/netioapi/nf-netioapi-createunicastipaddressentry
+    let mut events = NetEventStream::new(Some(luid)).map_err(Error::NotifyChange)?;
+    let wait = events.wait_until(Some(timeout), || {
+        let mut ready = true;
+        required_ready = true;
+        for address in &mut addresses {
+            address.refresh().map_err(Error::ObtainUnicastAddress)?;
+            let is_optional = ipv6_optional && matches!(address.address(), Some(IpAddr::V6(_)));
+            let state = address.dad_state();
+            if let Some(error) = DadStateError::from_state(state) {
+                if is_optional {
+                    ipv6_failed = true;
+                    continue;
                 }
+                return Err(Error::DadStateError(error));
             }
-
-            if ready {
-                return Ok(());
+            if state == DadState::Tentative {
+                ready = false;
+                required_ready &= is_optional;
             }
-            std::thread::sleep(DAD_CHECK_INTERVAL);
         }
-
-        Err(Error::DeviceReadyTimeout)
-    };
-    std::thread::spawn(move || {
-        let _ = tx.send(addr_check_thread());
+        Ok(ready)
     });
-    rx.await.map_err(|_| Error::UnicastSenderDropped)?
+    let ready = cancel_token
+        .run(wait)
+        .await
+        .map_err(|_| Error::Cancelled)??;
+
+    if ready {
+        Ok(!ipv6_failed)
+    } else if required_ready {
+        Ok(false)
+    } else {
+        Err(Error::DeviceReadyTimeout)
+    }
 }
 
-/// Returns the unicast IP address table. If `family` is `None`, then addresses for all families are
-/// returned.
-pub fn get_unicast_table(
-    family: Option<AddressFamily>,
-) -> io::Result<Vec<MIB_UNICASTIPADDRESS_ROW>> {
-    let mut unicast_rows = vec![];
-    let mut unicast_table: *mut MIB_UNICASTIPADDRESS_TABLE = std::ptr::null_mut();
-
-    let status =
-        unsafe { GetUnicastIpAddressTable(af_family_from_family(family), &mut unicast_table) };
-    if status != NO_ERROR {
-        return Err(io::Error::from_raw_os_error(status as i32));
-    }
-    let first_row = unsafe { &(*unicast_table).Table[0] } as *const MIB_UNICASTIPADDRESS_ROW;
-    for i in 0..unsafe { *unicast_table }.NumEntries {
-        unicast_rows.push(unsafe { *(first_row.offset(i as isize)) });
-    }
-    unsafe { FreeMibTable(unicast_table as *mut _) };
+/// Prefixes of the aliases of the virtual switches that Hyper-V creates to share the connection of
+/// the host with WSL2 and virtual machines through NAT.
+const NAT_SWITCH_ALIAS_PREFIXES: &[&str] = &["vEthernet (WSL", "vEthernet (Default Switch)"];
 
-    Ok(unicast_rows)
+/// Returns whether `alias` belongs to a Hyper-V NAT switch, such as the one used by WSL2.
+/// External switches are bridged to a physical adapter and do not match.
+pub fn is_nat_switch_alias(alias: &str) -> bool {
+    NAT_SWITCH_ALIAS_PREFIXES
+        .iter()
+        .any(|prefix| alias.starts_with(prefix))
 }
 
-/// Returns the LUID of an interface given its alias.
-pub fn luid_from_alias<T: AsRef<OsStr>>(alias: T) -> io::Result<NET_LUID> {
-    let alias_wide: Vec<u16> = alias
-        .as_ref()
-        .encode_wide()
-        .chain(std::iter::once(0u16))
-        .collect();
-    let mut luid: NET_LUID = unsafe { std::mem::zeroed() };
-    let status = unsafe { ConvertInterfaceAliasToLuid(alias_wide.as_ptr(), &mut luid) };
-    if status != NO_ERROR {
-        return Err(io::Error::from_raw_os_error(status as i32));
+/// Returns the subnets that the Hyper-V NAT switches of this host are on. These are assigned
+/// randomly when the switches are created, usually when the host boots or WSL2 starts.
+/// Link-local subnets are left out.
+pub fn nat_switch_networks() -> io::Result<Vec<IpNetwork>> {
+    let mut networks = vec![];
+    for address in UnicastAddress::table(None)? {
+        let is_nat_switch = address
+            .luid()
+            .alias()
+            .map(|alias| is_nat_switch_alias(&alias.to_string_lossy()))
+            .unwrap_or(false);
+        if !is_nat_switch {
+            continue;
+        }
+        let prefix_length = address.prefix_length();
+        let address = match address.address() {
+            Some(address) => address,
+            None => continue,
+        };
+        if let IpAddr::V6(address) = address {
+            if address.segments()[0] & 0xffc0 == 0xfe80 {
+                continue;
+            }
+        }
+        let network = match IpNetwork::new(address, prefix_length) {
+            Ok(network) => IpNetwork::new(network.network(), network.prefix()).unwrap(),
+            Err(_) => continue,
+        };
+        if !networks.contains(&network) {
+            networks.push(network);
+        }
     }
-    Ok(luid)
+    Ok(networks)
 }
 
-fn af_family_from_family(family: Option<AddressFamily>) -> u16 {
-    family
-        .map(|family| family as u16)
-        .unwrap_or(AF_UNSPEC as u16)
-}
-
-/// Converts an `Ipv4Addr` to `IN_ADDR`
-pub fn inaddr_from_ipaddr(addr: Ipv4Addr) -> IN_ADDR {
-    let mut in_addr: IN_ADDR = unsafe { mem::zeroed() };
-    let addr_octets = addr.octets();
-    unsafe {
-        ptr::copy_nonoverlapping(
-            &addr_octets as *const _,
-            in_addr.S_un.S_addr_mut() as *mut _ as *mut u8,
-            addr_octets.len(),
-        );
-    }
-    in_addr
+/// Processor architecture of a machine or of an executable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Architecture {
+    /// 32-bit x86
+    X86,
+    /// x86-64
+    X64,
+    /// 64-bit ARM
+    Arm64,
+    /// Architecture not known to this version
+    Unknown,
 }
 
-/// Converts an `Ipv6Addr` to `IN6_ADDR`
-pub fn in6addr_from_ipaddr(addr: Ipv6Addr) -> IN6_ADDR {
-    let mut in_addr: IN6_ADDR = unsafe { mem::zeroed() };
-    let addr_octets = addr.octets();
-    unsafe {
-        ptr::copy_nonoverlapping(
-            &addr_octets as *const _,
-            in_addr.u.Byte_mut() as *mut _,
-            addr_octets.len(),
-        );
+impl fmt::Display for Architecture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Architecture::X86 => write!(f, "x86"),
+            Architecture::X64 => write!(f, "x64"),
+            Architecture::Arm64 => write!(f, "ARM64"),
+            Architecture::Unknown => write!(f, "unknown"),
+        }
     }
-    in_addr
 }
 
-/// Converts an `IN_ADDR` to `Ipv4Addr`
-pub fn ipaddr_from_inaddr(addr: IN_ADDR) -> Ipv4Addr {
-    Ipv4Addr::from(unsafe { *(addr.S_un.S_addr()) }.to_be())
-}
+const IMAGE_FILE_MACHINE_I386: u16 = 0x014c;
+const IMAGE_FILE_MACHINE_AMD64: u16 = 0x8664;
+const IMAGE_FILE_MACHINE_ARM64: u16 = 0xaa64;
 
-/// Converts an `IN6_ADDR` to `Ipv6Addr`
-pub fn ipaddr_from_in6addr(addr: IN6_ADDR) -> Ipv6Addr {
-    Ipv6Addr::from(*unsafe { addr.u.Byte() })
-}
+const PROCESSOR_ARCHITECTURE_INTEL: u16 = 0;
+const PROCESSOR_ARCHITECTURE_AMD64: u16 = 9;
+const PROCESSOR_ARCHITECTURE_ARM64: u16 = 12;
 
-/// Converts a `SocketAddr` to `SOCKADDR_INET`
-pub fn inet_sockaddr_from_socketaddr(addr: SocketAddr) -> SOCKADDR_INET {
-    let mut sockaddr: SOCKADDR_INET = unsafe { mem::zeroed() };
+type IsWow64Process2Fn = unsafe extern "system" fn(HANDLE, *mut u16, *mut u16) -> i32;
 
-    match addr {
-        SocketAddr::V4(v4_addr) => {
-            unsafe {
-                *sockaddr.si_family_mut() = AF_INET as u16;
-            }
+/// Returns the architecture that this process is built for.
+pub fn process_architecture() -> Architecture {
+    if cfg!(target_arch = "x86") {
+        Architecture::X86
+    } else if cfg!(target_arch = "x86_64") {
+        Architecture::X64
+    } else if cfg!(target_arch = "aarch64") {
+        Architecture::Arm64
+    } else {
+        Architecture::Unknown
+    }
+}
 
-            let mut v4sockaddr = unsafe { sockaddr.Ipv4_mut() };
-            v4sockaddr.sin_family = AF_INET as u16;
-            v4sockaddr.sin_port = v4_addr.port().to_be();
-            v4sockaddr.sin_addr = inaddr_from_ipaddr(*v4_addr.ip());
+/// Returns the architecture of the machine. This differs from [`process_architecture`] if the
+/// process is emulated, e.g. when x64 code runs on ARM64 Windows.
+pub fn native_architecture() -> Architecture {
+    // `IsWow64Process2` is only available on Windows 10 1709 and later. `GetNativeSystemInfo`
+    // does not see through x64 emulation on ARM64, but it is enough where the former is missing.
+    if let Some(is_wow64_process2) = is_wow64_process2() {
+        let mut process_machine = 0u16;
+        let mut native_machine = 0u16;
+        if unsafe {
+            is_wow64_process2(
+                GetCurrentProcess(),
+                &mut process_machine,
+                &mut native_machine,
+            )
+        } != 0
+        {
+            return match native_machine {
+                IMAGE_FILE_MACHINE_I386 => Architecture::X86,
+                IMAGE_FILE_MACHINE_AMD64 => Architecture::X64,
+                IMAGE_FILE_MACHINE_ARM64 => Architecture::Arm64,
+                _ => Architecture::Unknown,
+            };
         }
-        SocketAddr::V6(v6_addr) => {
-            unsafe {
-                *sockaddr.si_family_mut() = AF_INET6 as u16;
-            }
+        log::warn!("IsWow64Process2 failed: {}", io::Error::last_os_error());
+    }
 
-            let mut v6sockaddr = unsafe { sockaddr.Ipv6_mut() };
-            v6sockaddr.sin6_family = AF_INET6 as u16;
-            v6sockaddr.sin6_port = v6_addr.port().to_be();
-            v6sockaddr.sin6_addr = in6addr_from_ipaddr(*v6_addr.ip());
-            v6sockaddr.sin6_flowinfo = v6_addr.flowinfo();
-            *unsafe { v6sockaddr.u.sin6_scope_id_mut() } = v6_addr.scope_id();
-        }
+    let mut system_info: SYSTEM_INFO = unsafe { mem::zeroed() };
+    unsafe { GetNativeSystemInfo(&mut system_info) };
+    match unsafe { system_info.u.s().wProcessorArchitecture } {
+        PROCESSOR_ARCHITECTURE_INTEL => Architecture::X86,
+        PROCESSOR_ARCHITECTURE_AMD64 => Architecture::X64,
+        PROCESSOR_ARCHITECTURE_ARM64 => Architecture::Arm64,
+        _ => Architecture::Unknown,
     }
+}
 
-    sockaddr
+fn is_wow64_process2() -> Option<IsWow64Process2Fn> {
+    let kernel32: Vec<u16> = OsStr::new("kernel32.dll")
+        .encode_wide()
+        .chain(std::iter::once(0u16))
+        .collect();
+    let module = unsafe { GetModuleHandleW(kernel32.as_ptr()) };
+    if module.is_null() {
+        return None;
+    }
+    let address = unsafe { GetProcAddress(module, b"IsWow64Process2\0".as_ptr() as *const i8) };
+    if address.is_null() {
+        return None;
+    }
+    Some(unsafe { mem::transmute::<_, IsWow64Process2Fn>(address) })
 }
 
-/// Converts a `SOCKADDR_INET` to `SocketAddr`. Returns an error if the address family is invalid.
-pub fn try_socketaddr_from_inet_sockaddr(addr: SOCKADDR_INET) -> Result<SocketAddr> {
-    unsafe {
-        match *addr.si_family() as i32 {
-            AF_INET => Ok(SocketAddr::V4(SocketAddrV4::new(
-                ipaddr_from_inaddr(addr.Ipv4().sin_addr),
-                u16::from_be(addr.Ipv4().sin_port),
-            ))),
-            AF_INET6 => Ok(SocketAddr::V6(SocketAddrV6::new(
-                ipaddr_from_in6addr(addr.Ipv6().sin6_addr),
-                u16::from_be(addr.Ipv6().sin6_port),
-                addr.Ipv6().sin6_flowinfo,
-                *addr.Ipv6().u.sin6_scope_id(),
-            ))),
-            family => Err(Error::UnknownAddressFamily(family)),
-        }
+/// Returns an error if kernel drivers cannot be installed from this process. The drivers that are
+/// bundled with the user mode libraries are built for the architecture of the process, so this is
+/// the case when the process is emulated, e.g. when the x64 build runs on ARM64 Windows.
+pub fn check_driver_architecture() -> io::Result<()> {
+    let native = native_architecture();
+    let process = process_architecture();
+    if native == process || native == Architecture::Unknown {
+        return Ok(());
     }
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        format!(
+            "Drivers for {} cannot be installed from an emulated {} process. Use the {} build",
+            native, process, native
+        ),
+    ))
 }
 
 #[cfg(test)]
@@ -456,25 +450,11 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_sockaddr_v4() {
-        let addr_v4 = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(1, 2, 3, 4), 1234));
-        assert_eq!(
-            addr_v4,
-            try_socketaddr_from_inet_sockaddr(inet_sockaddr_from_socketaddr(addr_v4)).unwrap()
-        );
-    }
-
-    #[test]
-    fn test_sockaddr_v6() {
-        let addr_v6 = SocketAddr::V6(SocketAddrV6::new(
-            Ipv6Addr::new(1, 2, 3, 4, 5, 6, 7, 8),
-            1234,
-            0xa,
-            0xb,
-        ));
-        assert_eq!(
-            addr_v6,
-            try_socketaddr_from_inet_sockaddr(inet_sockaddr_from_socketaddr(addr_v6)).unwrap()
-        );
+    fn test_nat_switch_alias() {
+        assert!(is_nat_switch_alias("vEthernet (WSL)"));
+        assert!(is_nat_switch_alias("vEthernet (WSL (Hyper-V firewall))"));
+        assert!(is_nat_switch_alias("vEthernet (Default Switch)"));
+        assert!(!is_nat_switch_alias("vEthernet (External)"));
+        assert!(!is_nat_switch_alias("Ethernet"));
     }
 }
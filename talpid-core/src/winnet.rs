@@ -361,6 +361,12 @@ pub fn routing_manager_add_routes(routes: &[WinNetRoute]) -> Result<(), Error> {
     }
 }
 
+pub fn routing_manager_delete_routes(routes: &[WinNetRoute]) -> bool {
+    let ptr = routes.as_ptr();
+    let length: u32 = routes.len() as u32;
+    unsafe { WinNet_DeleteRoutes(ptr, length) }
+}
+
 pub fn routing_manager_delete_applied_routes() -> bool {
     unsafe { WinNet_DeleteAppliedRoutes() }
 }
@@ -369,8 +375,6 @@ pub fn deactivate_routing_manager() {
     unsafe { WinNet_DeactivateRouteManager() }
 }
 
-// TODO: Remove attribute once this is in use.
-#[allow(dead_code)]
 pub fn get_best_default_route(
     family: WinNetAddrFamily,
 ) -> Result<Option<WinNetDefaultRoute>, Error> {
@@ -460,8 +464,8 @@ mod api {
         // #[link_name = "WinNet_AddRoute"]
         // pub fn WinNet_AddRoute(route: *const super::WinNetRoute) -> WinNetAddRouteStatus;
 
-        // #[link_name = "WinNet_DeleteRoutes"]
-        // pub fn WinNet_DeleteRoutes(routes: *const super::WinNetRoute, num_routes: u32) -> bool;
+        #[link_name = "WinNet_DeleteRoutes"]
+        pub fn WinNet_DeleteRoutes(routes: *const super::WinNetRoute, num_routes: u32) -> bool;
 
         // #[link_name = "WinNet_DeleteRoute"]
         // pub fn WinNet_DeleteRoute(route: *const super::WinNetRoute) -> bool;
@@ -43,9 +43,14 @@ const NM_SETTINGS_INTERFACE: &str = "org.freedesktop.NetworkManager.Settings";
 const NM_SETTINGS_CONNECTION_INTERFACE: &str = "org.freedesktop.NetworkManager.Settings.Connection";
 const NM_SETTINGS_PATH: &str = "/org/freedesktop/NetworkManager/Settings";
 const NM_CONNECTION_ACTIVE: &str = "org.freedesktop.NetworkManager.Connection.Active";
+const NM_ACCESS_POINT: &str = "org.freedesktop.NetworkManager.AccessPoint";
+const NM_WIFI_CONNECTION_TYPE: &str = "802-11-wireless";
 
 const NM_ADD_CONNECTION_VOLATILE: u32 = 0x2;
 
+const NM_METERED_YES: u32 = 1;
+const NM_METERED_GUESS_YES: u32 = 3;
+
 const RPC_TIMEOUT: std::time::Duration = Duration::from_secs(3);
 
 const DBUS_UNKNOWN_METHOD: &str = "org.freedesktop.DBus.Error.UnknownMethod";
@@ -149,6 +154,94 @@ impl NetworkManager {
             .map_err(Error::Dbus)
     }
 
+    /// Returns the active connections that are provided by a VPN plugin or are WireGuard tunnels.
+    pub fn active_vpns(&self) -> Result<Vec<ActiveVpn>> {
+        let connection_paths: Vec<dbus::Path<'static>> = self
+            .as_manager()
+            .get(NM_MANAGER, "ActiveConnections")
+            .map_err(Error::Dbus)?;
+
+        let mut vpns = vec![];
+        for connection_path in connection_paths {
+            let connection = self.as_path(&connection_path);
+            let is_plugin_vpn: bool = connection
+                .get(NM_CONNECTION_ACTIVE, "Vpn")
+                .map_err(Error::Dbus)?;
+            let connection_type: String = connection
+                .get(NM_CONNECTION_ACTIVE, "Type")
+                .map_err(Error::Dbus)?;
+            if !is_plugin_vpn && connection_type != "wireguard" {
+                continue;
+            }
+
+            let id: String = connection
+                .get(NM_CONNECTION_ACTIVE, "Id")
+                .map_err(Error::Dbus)?;
+            // The devices of a plugin VPN are the ones that the VPN runs on top of
+            let interface = if is_plugin_vpn {
+                None
+            } else {
+                let device_paths: Vec<dbus::Path<'static>> = connection
+                    .get(NM_CONNECTION_ACTIVE, "Devices")
+                    .map_err(Error::Dbus)?;
+                match device_paths.first() {
+                    Some(device_path) => Some(
+                        self.as_path(device_path)
+                            .get(NM_DEVICE, "Interface")
+                            .map_err(Error::Dbus)?,
+                    ),
+                    None => None,
+                }
+            };
+            vpns.push(ActiveVpn { id, interface });
+        }
+        Ok(vpns)
+    }
+
+    /// Returns the SSIDs of the Wi-Fi networks that the active connections are made to. Access
+    /// points that are provided by this host are left out.
+    pub fn wifi_ssids(&self) -> Result<Vec<String>> {
+        let connection_paths: Vec<dbus::Path<'static>> = self
+            .as_manager()
+            .get(NM_MANAGER, "ActiveConnections")
+            .map_err(Error::Dbus)?;
+
+        let mut ssids = vec![];
+        for connection_path in connection_paths {
+            let connection = self.as_path(&connection_path);
+            let connection_type: String = connection
+                .get(NM_CONNECTION_ACTIVE, "Type")
+                .map_err(Error::Dbus)?;
+            if connection_type != NM_WIFI_CONNECTION_TYPE {
+                continue;
+            }
+
+            // The specific object of a Wi-Fi connection is the access point that it is made to
+            let access_point_path: dbus::Path<'static> = connection
+                .get(NM_CONNECTION_ACTIVE, "SpecificObject")
+                .map_err(Error::Dbus)?;
+            if &*access_point_path == "/" {
+                continue;
+            }
+            let ssid: Vec<u8> = self
+                .as_path(&access_point_path)
+                .get(NM_ACCESS_POINT, "Ssid")
+                .map_err(Error::Dbus)?;
+            ssids.push(String::from_utf8_lossy(&ssid).into_owned());
+        }
+        Ok(ssids)
+    }
+
+    /// Returns whether NetworkManager considers the primary connection to be metered, either
+    /// because it is configured as such or because it guesses so from the kind of device.
+    pub fn metered(&self) -> Result<bool> {
+        let metered: u32 = self
+            .as_manager()
+            .get(NM_MANAGER, "Metered")
+            .map_err(Error::Dbus)?;
+        Ok(metered == NM_METERED_YES || metered == NM_METERED_GUESS_YES)
+    }
+
     pub fn get_device_state(&self, device: &dbus::Path<'_>) -> Result<u32> {
         self.as_path(device)
             .get(NM_DEVICE, "State")
@@ -675,6 +768,15 @@ impl dbus::message::SignalArgs for DeviceStateChange {
     const INTERFACE: &'static str = NM_DEVICE;
 }
 
+/// An active NetworkManager connection that sets up a VPN tunnel.
+#[derive(Debug)]
+pub struct ActiveVpn {
+    /// The name of the connection.
+    pub id: String,
+    /// The tunnel interface, unless the connection is provided by a VPN plugin.
+    pub interface: Option<String>,
+}
+
 #[derive(Debug)]
 pub struct WireguardTunnel {
     config_path: dbus::Path<'static>,
@@ -0,0 +1,218 @@
+//! A DNS stub resolver that listens on a local address and forwards queries to an upstream
+//! resolver over DNS-over-TLS or DNS-over-HTTPS. The system resolver is pointed at the stub while
+//! the tunnel is connected, so that DNS is encrypted even inside the tunnel.
+
+#![deny(rust_2018_idioms)]
+
+use futures::future::{abortable, AbortHandle};
+use parking_lot::Mutex;
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use talpid_types::ErrorExt;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+mod message;
+mod upstream;
+
+pub use upstream::{Protocol, Upstream};
+
+/// Largest DNS message that can be received over UDP.
+const MAX_UDP_MESSAGE_SIZE: usize = u16::MAX as usize;
+
+/// TCP clients that do not send a query within this time are disconnected.
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to listen on {}", _0)]
+    Listen(SocketAddr, #[error(source)] io::Error),
+
+    #[error(display = "Invalid upstream hostname: {}", _0)]
+    InvalidHostname(String),
+
+    #[error(display = "Failed to load the trusted root certificates")]
+    LoadRootCertificates(#[error(source)] io::Error),
+}
+
+/// A running proxy. It stops listening when dropped.
+pub struct DnsProxy {
+    local_address: SocketAddr,
+    upstream: Upstream,
+    shared: Arc<Shared>,
+    abort_handles: [AbortHandle; 2],
+}
+
+impl DnsProxy {
+    /// Listens for queries over UDP and TCP on `local_address`, and forwards them to `upstream`.
+    /// Queries are refused until forwarding is turned on with [`DnsProxy::set_forwarding`].
+    pub async fn start(local_address: SocketAddr, upstream: Upstream) -> Result<Self, Error> {
+        let client = upstream::Client::new(upstream.clone())?;
+        let udp_socket = UdpSocket::bind(local_address)
+            .await
+            .map_err(|error| Error::Listen(local_address, error))?;
+        let tcp_listener = TcpListener::bind(local_address)
+            .await
+            .map_err(|error| Error::Listen(local_address, error))?;
+
+        let shared = Arc::new(Shared {
+            client: Mutex::new(Arc::new(client)),
+            forwarding: AtomicBool::new(false),
+        });
+        let (udp_future, udp_abort_handle) = abortable(serve_udp(udp_socket, shared.clone()));
+        let (tcp_future, tcp_abort_handle) = abortable(serve_tcp(tcp_listener, shared.clone()));
+        tokio::spawn(udp_future);
+        tokio::spawn(tcp_future);
+
+        log::info!("Started DNS proxy on {} for {}", local_address, upstream);
+
+        Ok(DnsProxy {
+            local_address,
+            upstream,
+            shared,
+            abort_handles: [udp_abort_handle, tcp_abort_handle],
+        })
+    }
+
+    pub fn local_address(&self) -> SocketAddr {
+        self.local_address
+    }
+
+    pub fn upstream(&self) -> &Upstream {
+        &self.upstream
+    }
+
+    /// Forwards subsequent queries to `upstream`.
+    pub fn set_upstream(&mut self, upstream: Upstream) -> Result<(), Error> {
+        let client = upstream::Client::new(upstream.clone())?;
+        *self.shared.client.lock() = Arc::new(client);
+        log::info!("DNS proxy now forwards to {}", upstream);
+        self.upstream = upstream;
+        Ok(())
+    }
+
+    /// Sets whether queries are forwarded or refused. Forwarding should only be turned on while
+    /// the tunnel is connected, so that queries never leave outside of it.
+    pub fn set_forwarding(&self, forwarding: bool) {
+        self.shared.forwarding.store(forwarding, Ordering::Release);
+    }
+}
+
+impl Drop for DnsProxy {
+    fn drop(&mut self) {
+        for abort_handle in &self.abort_handles {
+            abort_handle.abort();
+        }
+        log::info!("Stopped DNS proxy");
+    }
+}
+
+/// State shared by the tasks that serve clients.
+struct Shared {
+    client: Mutex<Arc<upstream::Client>>,
+    forwarding: AtomicBool,
+}
+
+impl Shared {
+    /// Returns the response to `query`. Queries that cannot be forwarded are answered with an
+    /// error, and malformed queries are not answered at all.
+    async fn resolve(&self, query: &[u8]) -> Option<Vec<u8>> {
+        if !self.forwarding.load(Ordering::Acquire) {
+            return message::error_response(query, message::RCODE_REFUSED);
+        }
+        let client = self.client.lock().clone();
+        match client.query(query).await {
+            Ok(response) => Some(response),
+            Err(error) => {
+                log::debug!(
+                    "{}",
+                    error.display_chain_with_msg("DNS proxy: Query failed")
+                );
+                message::error_response(query, message::RCODE_SERVFAIL)
+            }
+        }
+    }
+}
+
+async fn serve_udp(socket: UdpSocket, shared: Arc<Shared>) {
+    let socket = Arc::new(socket);
+    let mut buffer = vec![0u8; MAX_UDP_MESSAGE_SIZE];
+    loop {
+        let (length, client_addr) = match socket.recv_from(&mut buffer).await {
+            Ok(result) => result,
+            Err(error) => {
+                log::debug!(
+                    "{}",
+                    error.display_chain_with_msg("DNS proxy: Failed to receive query")
+                );
+                continue;
+            }
+        };
+        let query = buffer[..length].to_vec();
+        let socket = socket.clone();
+        let shared = shared.clone();
+
+        tokio::spawn(async move {
+            let response = match shared.resolve(&query).await {
+                Some(response) => response,
+                None => return,
+            };
+            // Tell the client to retry over TCP if the response is too large
+            let response = if response.len() > message::max_udp_response_size(&query) {
+                match message::truncated_response(&query) {
+                    Some(response) => response,
+                    None => return,
+                }
+            } else {
+                response
+            };
+            if let Err(error) = socket.send_to(&response, client_addr).await {
+                log::debug!(
+                    "{}",
+                    error.display_chain_with_msg("DNS proxy: Failed to send response")
+                );
+            }
+        });
+    }
+}
+
+async fn serve_tcp(listener: TcpListener, shared: Arc<Shared>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(serve_tcp_client(stream, shared.clone()));
+            }
+            Err(error) => {
+                log::debug!(
+                    "{}",
+                    error.display_chain_with_msg("DNS proxy: Failed to accept connection")
+                );
+            }
+        }
+    }
+}
+
+/// Answers the queries of a TCP client until it disconnects or is idle for too long.
+async fn serve_tcp_client(mut stream: TcpStream, shared: Arc<Shared>) {
+    loop {
+        let query =
+            match tokio::time::timeout(TCP_IDLE_TIMEOUT, message::read_tcp(&mut stream)).await {
+                Ok(Ok(query)) => query,
+                Ok(Err(_)) | Err(_) => return,
+            };
+        let response = match shared.resolve(&query).await {
+            Some(response) => response,
+            None => return,
+        };
+        if message::write_tcp(&mut stream, &response).await.is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,183 @@
+//! The few parts of the DNS wire format that the proxy has to understand.
+
+use std::{convert::TryFrom, io};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const HEADER_LEN: usize = 12;
+/// Maximum size of a response over UDP unless the query says otherwise (RFC 1035).
+const DEFAULT_UDP_PAYLOAD_SIZE: usize = 512;
+/// Record type of the EDNS(0) pseudo-record, whose class is the UDP payload size of the sender.
+const TYPE_OPT: u16 = 41;
+
+const FLAG_QR: u8 = 0x80;
+const MASK_OPCODE: u8 = 0x78;
+const FLAG_TC: u8 = 0x02;
+const FLAG_RD: u8 = 0x01;
+const FLAG_RA: u8 = 0x80;
+
+pub const RCODE_SERVFAIL: u8 = 2;
+pub const RCODE_REFUSED: u8 = 5;
+
+/// Returns a response with the given error code to `query`, or `None` if the query is malformed.
+pub fn error_response(query: &[u8], rcode: u8) -> Option<Vec<u8>> {
+    empty_response(query, 0, rcode)
+}
+
+/// Returns an empty response to `query` with the truncation flag set, which tells the client to
+/// retry over TCP. Returns `None` if the query is malformed.
+pub fn truncated_response(query: &[u8]) -> Option<Vec<u8>> {
+    empty_response(query, FLAG_TC, 0)
+}
+
+/// Returns a response that contains the question of `query` but no records.
+fn empty_response(query: &[u8], flags: u8, rcode: u8) -> Option<Vec<u8>> {
+    let mut response = query[..question_end(query)?].to_vec();
+    response[2] = FLAG_QR | (query[2] & (MASK_OPCODE | FLAG_RD)) | flags;
+    response[3] = FLAG_RA | rcode;
+    for count in &mut response[6..HEADER_LEN] {
+        *count = 0;
+    }
+    Some(response)
+}
+
+/// Returns the largest response that the sender of `query` accepts over UDP.
+pub fn max_udp_response_size(query: &[u8]) -> usize {
+    edns_payload_size(query)
+        .unwrap_or(DEFAULT_UDP_PAYLOAD_SIZE)
+        .max(DEFAULT_UDP_PAYLOAD_SIZE)
+}
+
+/// Returns the UDP payload size in the EDNS(0) record of `query`, if there is one.
+fn edns_payload_size(query: &[u8]) -> Option<usize> {
+    let mut offset = question_end(query)?;
+    // Queries do not contain answers or authority records
+    if read_u16(query, 6)? != 0 || read_u16(query, 8)? != 0 {
+        return None;
+    }
+    for _ in 0..read_u16(query, 10)? {
+        offset = skip_name(query, offset)?;
+        if read_u16(query, offset)? == TYPE_OPT {
+            return Some(usize::from(read_u16(query, offset + 2)?));
+        }
+        let data_length = read_u16(query, offset + 8)?;
+        offset += 10 + usize::from(data_length);
+    }
+    None
+}
+
+/// Returns the offset of the end of the question section.
+fn question_end(message: &[u8]) -> Option<usize> {
+    let mut offset = HEADER_LEN;
+    for _ in 0..read_u16(message, 4)? {
+        // The name is followed by the type and the class
+        offset = skip_name(message, offset)? + 4;
+    }
+    if offset > message.len() {
+        return None;
+    }
+    Some(offset)
+}
+
+/// Returns the offset of the end of the domain name that starts at `offset`.
+fn skip_name(message: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let length = *message.get(offset)?;
+        match length & 0xc0 {
+            0x00 if length == 0 => return Some(offset + 1),
+            0x00 => offset += 1 + usize::from(length),
+            // A pointer to a name elsewhere in the message ends the name
+            0xc0 => {
+                message.get(offset + 1)?;
+                return Some(offset + 2);
+            }
+            _ => return None,
+        }
+    }
+}
+
+fn read_u16(message: &[u8], offset: usize) -> Option<u16> {
+    let bytes = message.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+/// Reads a message that is prefixed by its length, as is done over TCP and TLS.
+pub async fn read_tcp<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<Vec<u8>> {
+    let mut length = [0u8; 2];
+    stream.read_exact(&mut length).await?;
+    let mut message = vec![0u8; usize::from(u16::from_be_bytes(length))];
+    stream.read_exact(&mut message).await?;
+    Ok(message)
+}
+
+/// Writes a message prefixed by its length, as is done over TCP and TLS.
+pub async fn write_tcp<S: AsyncWrite + Unpin>(stream: &mut S, message: &[u8]) -> io::Result<()> {
+    let length = u16::try_from(message.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "DNS message is too large"))?;
+    let mut buffer = Vec::with_capacity(2 + message.len());
+    buffer.extend_from_slice(&length.to_be_bytes());
+    buffer.extend_from_slice(message);
+    stream.write_all(&buffer).await?;
+    stream.flush().await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A query for the A record of "example.com" with recursion desired.
+    fn query() -> Vec<u8> {
+        let mut query = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        query.extend_from_slice(b"\x07example\x03com\x00");
+        query.extend_from_slice(&[0, 1, 0, 1]);
+        query
+    }
+
+    fn query_with_edns(payload_size: u16) -> Vec<u8> {
+        let mut query = query();
+        query[11] = 1;
+        query.push(0);
+        query.extend_from_slice(&TYPE_OPT.to_be_bytes());
+        query.extend_from_slice(&payload_size.to_be_bytes());
+        query.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        query
+    }
+
+    #[test]
+    fn error_response_keeps_question() {
+        let query = query_with_edns(4096);
+        let response = error_response(&query, RCODE_SERVFAIL).unwrap();
+
+        assert_eq!(&response[..2], &[0x12, 0x34]);
+        assert_eq!(response[2], FLAG_QR | FLAG_RD);
+        assert_eq!(response[3] & 0x0f, RCODE_SERVFAIL);
+        assert_eq!(&response[4..HEADER_LEN], &[0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&response[HEADER_LEN..], &query()[HEADER_LEN..]);
+    }
+
+    #[test]
+    fn truncated_response_sets_flag() {
+        let response = truncated_response(&query()).unwrap();
+        assert_eq!(response[2] & FLAG_TC, FLAG_TC);
+        assert_eq!(response[3] & 0x0f, 0);
+    }
+
+    #[test]
+    fn reads_udp_payload_size() {
+        assert_eq!(max_udp_response_size(&query()), DEFAULT_UDP_PAYLOAD_SIZE);
+        assert_eq!(max_udp_response_size(&query_with_edns(1232)), 1232);
+        assert_eq!(
+            max_udp_response_size(&query_with_edns(100)),
+            DEFAULT_UDP_PAYLOAD_SIZE
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_query() {
+        let query = query();
+        assert_eq!(
+            error_response(&query[..HEADER_LEN + 4], RCODE_SERVFAIL),
+            None
+        );
+        assert_eq!(error_response(&query[..4], RCODE_SERVFAIL), None);
+    }
+}
@@ -0,0 +1,215 @@
+use crate::{message, Error};
+use futures::future::poll_fn;
+use hyper::{
+    client::conn::SendRequest,
+    header::{ACCEPT, CONTENT_TYPE, HOST},
+    Body, Request, StatusCode,
+};
+use std::{fmt, future::Future, io, net::IpAddr, sync::Arc, time::Duration};
+use talpid_types::ErrorExt;
+use tokio::{net::TcpStream, sync::Mutex};
+use tokio_rustls::{client::TlsStream, rustls, TlsConnector};
+use webpki::DNSName;
+
+/// How long to wait for a connection to be established or for a response to arrive.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Path of the DNS-over-HTTPS endpoint. This is the path suggested by RFC 8484.
+const DOH_PATH: &str = "/dns-query";
+const DNS_MESSAGE_CONTENT_TYPE: &str = "application/dns-message";
+
+/// Protocol used to reach the upstream resolver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// DNS-over-TLS (RFC 7858).
+    Tls,
+    /// DNS-over-HTTPS (RFC 8484).
+    Https,
+}
+
+impl Protocol {
+    fn port(self) -> u16 {
+        match self {
+            Protocol::Tls => 853,
+            Protocol::Https => 443,
+        }
+    }
+}
+
+/// The resolver that queries are forwarded to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Upstream {
+    pub protocol: Protocol,
+    pub address: IpAddr,
+    /// Name that the certificate of the resolver is verified against.
+    pub hostname: String,
+}
+
+impl fmt::Display for Upstream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = match self.protocol {
+            Protocol::Tls => "tls",
+            Protocol::Https => "https",
+        };
+        write!(f, "{}://{} ({})", scheme, self.hostname, self.address)
+    }
+}
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum QueryError {
+    #[error(display = "Failed to connect to the upstream resolver")]
+    Connect(#[error(source)] io::Error),
+
+    #[error(display = "TLS handshake with the upstream resolver failed")]
+    Handshake(#[error(source)] io::Error),
+
+    #[error(display = "Failed to exchange messages with the upstream resolver")]
+    Exchange(#[error(source)] io::Error),
+
+    #[error(display = "HTTP request to the upstream resolver failed")]
+    Http(#[error(source)] hyper::Error),
+
+    #[error(display = "The upstream resolver responded with status {}", _0)]
+    HttpStatus(StatusCode),
+
+    #[error(display = "The upstream resolver did not respond in time")]
+    Timeout,
+}
+
+/// A connection that is kept open between queries.
+enum Connection {
+    Tls(TlsStream<TcpStream>),
+    Https(SendRequest<Body>),
+}
+
+/// Forwards queries to an upstream resolver over a single connection. Queries are sent one at a
+/// time.
+pub struct Client {
+    upstream: Upstream,
+    server_name: DNSName,
+    connector: TlsConnector,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl Client {
+    pub fn new(upstream: Upstream) -> Result<Self, Error> {
+        let server_name = webpki::DNSNameRef::try_from_ascii_str(&upstream.hostname)
+            .map_err(|_| Error::InvalidHostname(upstream.hostname.clone()))?
+            .to_owned();
+
+        let mut config = rustls::ClientConfig::new();
+        config.root_store = rustls_native_certs::load_native_certs()
+            .map_err(|(_, error)| Error::LoadRootCertificates(error))?;
+        if upstream.protocol == Protocol::Https {
+            config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        }
+
+        Ok(Client {
+            upstream,
+            server_name,
+            connector: TlsConnector::from(Arc::new(config)),
+            connection: Mutex::new(None),
+        })
+    }
+
+    /// Forwards `query` and returns the response.
+    pub async fn query(&self, query: &[u8]) -> Result<Vec<u8>, QueryError> {
+        let mut connection = self.connection.lock().await;
+
+        // The resolver may have closed a kept connection, so the query is retried once on a new
+        // connection
+        if let Some(existing) = connection.as_mut() {
+            match with_timeout(self.exchange(existing, query)).await {
+                Ok(response) => return Ok(response),
+                Err(error) => log::debug!(
+                    "{}",
+                    error.display_chain_with_msg("DNS proxy: Reconnecting to upstream resolver")
+                ),
+            }
+        }
+        *connection = None;
+
+        let mut new_connection = with_timeout(self.connect()).await?;
+        let response = with_timeout(self.exchange(&mut new_connection, query)).await?;
+        *connection = Some(new_connection);
+        Ok(response)
+    }
+
+    async fn connect(&self) -> Result<Connection, QueryError> {
+        let stream = TcpStream::connect((self.upstream.address, self.upstream.protocol.port()))
+            .await
+            .map_err(QueryError::Connect)?;
+        let stream = self
+            .connector
+            .connect(self.server_name.as_ref(), stream)
+            .await
+            .map_err(QueryError::Handshake)?;
+
+        match self.upstream.protocol {
+            Protocol::Tls => Ok(Connection::Tls(stream)),
+            Protocol::Https => {
+                let (send_request, connection) = hyper::client::conn::handshake(stream)
+                    .await
+                    .map_err(QueryError::Http)?;
+                tokio::spawn(async move {
+                    if let Err(error) = connection.await {
+                        log::debug!(
+                            "{}",
+                            error.display_chain_with_msg("DNS proxy: Upstream connection closed")
+                        );
+                    }
+                });
+                Ok(Connection::Https(send_request))
+            }
+        }
+    }
+
+    async fn exchange(
+        &self,
+        connection: &mut Connection,
+        query: &[u8],
+    ) -> Result<Vec<u8>, QueryError> {
+        match connection {
+            Connection::Tls(stream) => {
+                message::write_tcp(stream, query)
+                    .await
+                    .map_err(QueryError::Exchange)?;
+                message::read_tcp(stream)
+                    .await
+                    .map_err(QueryError::Exchange)
+            }
+            Connection::Https(send_request) => {
+                poll_fn(|cx| send_request.poll_ready(cx))
+                    .await
+                    .map_err(QueryError::Http)?;
+
+                let request = Request::post(DOH_PATH)
+                    .header(HOST, self.upstream.hostname.as_str())
+                    .header(CONTENT_TYPE, DNS_MESSAGE_CONTENT_TYPE)
+                    .header(ACCEPT, DNS_MESSAGE_CONTENT_TYPE)
+                    .body(Body::from(query.to_vec()))
+                    .expect("the hostname is a valid header value");
+                let response = send_request
+                    .send_request(request)
+                    .await
+                    .map_err(QueryError::Http)?;
+                if !response.status().is_success() {
+                    return Err(QueryError::HttpStatus(response.status()));
+                }
+                let body = hyper::body::to_bytes(response.into_body())
+                    .await
+                    .map_err(QueryError::Http)?;
+                Ok(body.to_vec())
+            }
+        }
+    }
+}
+
+async fn with_timeout<T>(
+    future: impl Future<Output = Result<T, QueryError>>,
+) -> Result<T, QueryError> {
+    tokio::time::timeout(QUERY_TIMEOUT, future)
+        .await
+        .map_err(|_| QueryError::Timeout)?
+}
@@ -14,6 +14,9 @@ mod imp;
 #[path = "android.rs"]
 mod imp;
 
+#[cfg(not(target_os = "android"))]
+pub mod wifi;
+
 pub use self::imp::{extra_metadata, short_version, version};
 
 #[cfg(target_os = "windows")]
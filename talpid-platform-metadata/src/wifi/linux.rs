@@ -0,0 +1,441 @@
+//! Queries nl80211 over generic netlink. Only the few messages and attributes that are needed are
+//! implemented here, since the netlink crates in use do not support nl80211.
+use super::{Security, WifiConnection};
+use netlink_sys::{protocols::NETLINK_GENERIC, Socket, SocketAddr};
+use std::{convert::TryInto, io};
+
+const NLMSG_HDR_LEN: usize = 16;
+const GENL_HDR_LEN: usize = 4;
+const NLA_HDR_LEN: usize = 4;
+/// Removes the nested and byte order flags from an attribute type.
+const NLA_TYPE_MASK: u16 = 0x3fff;
+
+const NLM_F_REQUEST: u16 = 0x1;
+const NLM_F_ACK: u16 = 0x4;
+const NLM_F_DUMP: u16 = 0x300;
+const NLMSG_ERROR: u16 = 0x2;
+const NLMSG_DONE: u16 = 0x3;
+
+const GENL_ID_CTRL: u16 = 0x10;
+const CTRL_CMD_GETFAMILY: u8 = 3;
+const CTRL_ATTR_FAMILY_ID: u16 = 1;
+const CTRL_ATTR_FAMILY_NAME: u16 = 2;
+
+const NL80211_CMD_GET_INTERFACE: u8 = 5;
+const NL80211_CMD_GET_SCAN: u8 = 32;
+const NL80211_ATTR_IFINDEX: u16 = 3;
+const NL80211_ATTR_IFNAME: u16 = 4;
+const NL80211_ATTR_BSS: u16 = 47;
+const NL80211_ATTR_SSID: u16 = 52;
+const NL80211_BSS_BSSID: u16 = 1;
+const NL80211_BSS_CAPABILITY: u16 = 5;
+const NL80211_BSS_INFORMATION_ELEMENTS: u16 = 6;
+const NL80211_BSS_STATUS: u16 = 9;
+const NL80211_BSS_STATUS_ASSOCIATED: u32 = 1;
+
+const WLAN_CAPABILITY_PRIVACY: u16 = 0x10;
+const WLAN_EID_SSID: u8 = 0;
+const WLAN_EID_RSN: u8 = 48;
+const WLAN_EID_VENDOR_SPECIFIC: u8 = 221;
+const RSN_OUI: [u8; 3] = [0x00, 0x0f, 0xac];
+const WPA_OUI: [u8; 3] = [0x00, 0x50, 0xf2];
+
+/// Large enough for any message in a dump.
+const RECV_BUFFER_SIZE: usize = 64 * 1024;
+
+pub fn current_connections() -> io::Result<Vec<WifiConnection>> {
+    let mut socket = GenlSocket::new()?;
+    let family = match socket.resolve_family("nl80211") {
+        Ok(family) => family,
+        // The family is missing if there is no wireless driver
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(error) => return Err(error),
+    };
+
+    let mut connections = vec![];
+    for interface in socket.request(family, NL80211_CMD_GET_INTERFACE, NLM_F_DUMP, &[])? {
+        let attributes = parse_attributes(&interface);
+        let index = match find_attribute(&attributes, NL80211_ATTR_IFINDEX).and_then(read_u32) {
+            Some(index) => index,
+            None => continue,
+        };
+        let name = find_attribute(&attributes, NL80211_ATTR_IFNAME)
+            .map(read_string)
+            .unwrap_or_else(|| index.to_string());
+        let ssid = find_attribute(&attributes, NL80211_ATTR_SSID).map(<[u8]>::to_vec);
+
+        if let Some(bss) = socket.associated_bss(family, index)? {
+            connections.push(WifiConnection {
+                interface: name,
+                ssid: ssid.or(bss.ssid).unwrap_or_default(),
+                bssid: Some(bss.bssid),
+                security: bss.security,
+            });
+        } else if let Some(ssid) = ssid {
+            connections.push(WifiConnection {
+                interface: name,
+                ssid,
+                bssid: None,
+                security: Security::Unknown,
+            });
+        }
+    }
+    Ok(connections)
+}
+
+/// The access point that an interface is associated with.
+struct AssociatedBss {
+    bssid: [u8; 6],
+    ssid: Option<Vec<u8>>,
+    security: Security,
+}
+
+struct GenlSocket {
+    socket: Socket,
+    sequence_number: u32,
+}
+
+impl GenlSocket {
+    fn new() -> io::Result<Self> {
+        let mut socket = Socket::new(NETLINK_GENERIC)?;
+        socket.bind_auto()?;
+        socket.connect(&SocketAddr::new(0, 0))?;
+        Ok(GenlSocket {
+            socket,
+            sequence_number: 0,
+        })
+    }
+
+    fn resolve_family(&mut self, name: &str) -> io::Result<u16> {
+        let mut family_name = name.as_bytes().to_vec();
+        family_name.push(0);
+        let attributes = attribute(CTRL_ATTR_FAMILY_NAME, &family_name);
+
+        self.request(GENL_ID_CTRL, CTRL_CMD_GETFAMILY, NLM_F_ACK, &attributes)?
+            .iter()
+            .find_map(|response| {
+                find_attribute(&parse_attributes(response), CTRL_ATTR_FAMILY_ID)
+                    .and_then(|id| id.get(..2))
+                    .map(|id| u16::from_ne_bytes([id[0], id[1]]))
+            })
+            .ok_or_else(|| invalid_data("Missing generic netlink family ID"))
+    }
+
+    fn associated_bss(&mut self, family: u16, index: u32) -> io::Result<Option<AssociatedBss>> {
+        let attributes = attribute(NL80211_ATTR_IFINDEX, &index.to_ne_bytes());
+        for response in self.request(family, NL80211_CMD_GET_SCAN, NLM_F_DUMP, &attributes)? {
+            let bss = match find_attribute(&parse_attributes(&response), NL80211_ATTR_BSS) {
+                Some(bss) => parse_attributes(bss),
+                None => continue,
+            };
+            let status = find_attribute(&bss, NL80211_BSS_STATUS).and_then(read_u32);
+            if status != Some(NL80211_BSS_STATUS_ASSOCIATED) {
+                continue;
+            }
+            let bssid = match find_attribute(&bss, NL80211_BSS_BSSID)
+                .and_then(|bssid| bssid.try_into().ok())
+            {
+                Some(bssid) => bssid,
+                None => continue,
+            };
+            let capability = find_attribute(&bss, NL80211_BSS_CAPABILITY)
+                .and_then(|capability| capability.get(..2))
+                .map(|capability| u16::from_ne_bytes([capability[0], capability[1]]))
+                .unwrap_or(0);
+            let elements = find_attribute(&bss, NL80211_BSS_INFORMATION_ELEMENTS).unwrap_or(&[]);
+
+            return Ok(Some(AssociatedBss {
+                bssid,
+                ssid: parse_elements(elements)
+                    .find(|(id, _)| *id == WLAN_EID_SSID)
+                    .map(|(_, ssid)| ssid.to_vec()),
+                security: security_from_elements(elements, capability),
+            }));
+        }
+        Ok(None)
+    }
+
+    /// Sends a request and returns the attributes of every response. Requests that are not dumps
+    /// must set `NLM_F_ACK`, so that the end of the responses can be detected.
+    fn request(
+        &mut self,
+        family: u16,
+        command: u8,
+        flags: u16,
+        attributes: &[u8],
+    ) -> io::Result<Vec<Vec<u8>>> {
+        self.sequence_number = self.sequence_number.wrapping_add(1);
+        let length = NLMSG_HDR_LEN + GENL_HDR_LEN + attributes.len();
+
+        let mut message = Vec::with_capacity(length);
+        message.extend_from_slice(&(length as u32).to_ne_bytes());
+        message.extend_from_slice(&family.to_ne_bytes());
+        message.extend_from_slice(&(NLM_F_REQUEST | flags).to_ne_bytes());
+        message.extend_from_slice(&self.sequence_number.to_ne_bytes());
+        message.extend_from_slice(&0u32.to_ne_bytes());
+        // Version 1 is accepted by both the controller and nl80211
+        message.extend_from_slice(&[command, 1, 0, 0]);
+        message.extend_from_slice(attributes);
+        self.socket.send(&message, 0)?;
+
+        let mut responses = vec![];
+        let mut buffer = vec![0u8; RECV_BUFFER_SIZE];
+        loop {
+            let received = self.socket.recv(&mut buffer[..], 0)?;
+            let mut data = &buffer[..received];
+
+            while data.len() >= NLMSG_HDR_LEN {
+                let message_length = read_u32(&data[0..4]).unwrap() as usize;
+                let message_type = u16::from_ne_bytes([data[4], data[5]]);
+                let sequence_number = read_u32(&data[8..12]).unwrap();
+                if message_length < NLMSG_HDR_LEN || message_length > data.len() {
+                    return Err(invalid_data("Truncated netlink message"));
+                }
+                let payload = &data[NLMSG_HDR_LEN..message_length];
+                data = &data[align(message_length).min(data.len())..];
+
+                if sequence_number != self.sequence_number {
+                    continue;
+                }
+                match message_type {
+                    NLMSG_DONE => return Ok(responses),
+                    NLMSG_ERROR => {
+                        let code = payload
+                            .get(..4)
+                            .and_then(read_u32)
+                            .ok_or_else(|| invalid_data("Truncated netlink error"))?
+                            as i32;
+                        // An error code of zero acknowledges the request
+                        if code == 0 {
+                            return Ok(responses);
+                        }
+                        return Err(io::Error::from_raw_os_error(-code));
+                    }
+                    _ => {
+                        if let Some(attributes) = payload.get(GENL_HDR_LEN..) {
+                            responses.push(attributes.to_vec());
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Infers the security type from the information elements of an access point.
+fn security_from_elements(elements: &[u8], capability: u16) -> Security {
+    let mut wpa_security = None;
+    for (id, data) in parse_elements(elements) {
+        match id {
+            // RSN is used by WPA2 and WPA3, and takes precedence over WPA
+            WLAN_EID_RSN => return rsn_security(data.get(2..).unwrap_or(&[]), RSN_OUI),
+            WLAN_EID_VENDOR_SPECIFIC if data.starts_with(&[0x00, 0x50, 0xf2, 0x01]) => {
+                wpa_security = Some(rsn_security(data.get(6..).unwrap_or(&[]), WPA_OUI));
+            }
+            _ => (),
+        }
+    }
+    match wpa_security {
+        Some(Security::Wpa2Personal) => Security::WpaPersonal,
+        Some(security) => security,
+        None if capability & WLAN_CAPABILITY_PRIVACY != 0 => Security::Wep,
+        None => Security::Open,
+    }
+}
+
+/// Infers the security type from the authentication and key management (AKM) suites of an RSN
+/// element, or of a WPA element, which has the same layout but another OUI. `data` starts at
+/// the group cipher suite.
+fn rsn_security(data: &[u8], oui: [u8; 3]) -> Security {
+    let pairwise_count = match data.get(4..6) {
+        Some(count) => u16::from_le_bytes([count[0], count[1]]) as usize,
+        None => return Security::Unknown,
+    };
+    let akm_offset = 6 + 4 * pairwise_count;
+    let akm_count = match data.get(akm_offset..akm_offset + 2) {
+        Some(count) => u16::from_le_bytes([count[0], count[1]]) as usize,
+        None => return Security::Unknown,
+    };
+    let akm_suites = match data.get(akm_offset + 2..akm_offset + 2 + 4 * akm_count) {
+        Some(suites) => suites,
+        None => return Security::Unknown,
+    };
+
+    let mut security = Security::Unknown;
+    for suite in akm_suites.chunks_exact(4) {
+        if suite[..3] != oui {
+            continue;
+        }
+        security = match suite[3] {
+            // 802.1X, FT over 802.1X, 802.1X with SHA-256 and Suite B
+            1 | 3 | 5 | 11 | 12 | 13 => return Security::Enterprise,
+            // PSK, FT with PSK and PSK with SHA-256. Preferred over SAE in transition mode
+            2 | 4 | 6 => Security::Wpa2Personal,
+            // SAE and FT with SAE
+            8 | 9 | 24 | 25 if security != Security::Wpa2Personal => Security::Wpa3Personal,
+            // Opportunistic wireless encryption
+            18 if security == Security::Unknown => Security::Open,
+            _ => security,
+        };
+    }
+    security
+}
+
+/// Returns the ID and data of each information element.
+fn parse_elements(mut elements: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    std::iter::from_fn(move || {
+        let id = *elements.get(0)?;
+        let length = *elements.get(1)? as usize;
+        let data = elements.get(2..2 + length)?;
+        elements = &elements[2 + length..];
+        Some((id, data))
+    })
+}
+
+fn parse_attributes(mut data: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut attributes = vec![];
+    while data.len() >= NLA_HDR_LEN {
+        let length = u16::from_ne_bytes([data[0], data[1]]) as usize;
+        let kind = u16::from_ne_bytes([data[2], data[3]]) & NLA_TYPE_MASK;
+        if length < NLA_HDR_LEN || length > data.len() {
+            break;
+        }
+        attributes.push((kind, &data[NLA_HDR_LEN..length]));
+        data = &data[align(length).min(data.len())..];
+    }
+    attributes
+}
+
+fn find_attribute<'a>(attributes: &[(u16, &'a [u8])], kind: u16) -> Option<&'a [u8]> {
+    attributes
+        .iter()
+        .find(|(attribute_kind, _)| *attribute_kind == kind)
+        .map(|(_, payload)| *payload)
+}
+
+fn attribute(kind: u16, payload: &[u8]) -> Vec<u8> {
+    let length = NLA_HDR_LEN + payload.len();
+    let mut attribute = Vec::with_capacity(align(length));
+    attribute.extend_from_slice(&(length as u16).to_ne_bytes());
+    attribute.extend_from_slice(&kind.to_ne_bytes());
+    attribute.extend_from_slice(payload);
+    attribute.resize(align(length), 0);
+    attribute
+}
+
+fn read_u32(data: &[u8]) -> Option<u32> {
+    Some(u32::from_ne_bytes(data.get(..4)?.try_into().ok()?))
+}
+
+fn read_string(data: &[u8]) -> String {
+    let end = data
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+/// Netlink messages and attributes are aligned to four bytes.
+fn align(length: usize) -> usize {
+    (length + 3) & !3
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Returns an RSN element with a CCMP group and pairwise cipher and the given AKM suites.
+    fn rsn_element(akm_suites: &[u8]) -> Vec<u8> {
+        let mut data = vec![1, 0, 0x00, 0x0f, 0xac, 4, 1, 0, 0x00, 0x0f, 0xac, 4];
+        data.extend_from_slice(&[akm_suites.len() as u8, 0]);
+        for suite in akm_suites {
+            data.extend_from_slice(&[0x00, 0x0f, 0xac, *suite]);
+        }
+        data.extend_from_slice(&[0, 0]);
+
+        let mut element = vec![WLAN_EID_RSN, data.len() as u8];
+        element.extend(data);
+        element
+    }
+
+    #[test]
+    fn infers_security_from_rsn_element() {
+        assert_eq!(
+            security_from_elements(&rsn_element(&[2]), WLAN_CAPABILITY_PRIVACY),
+            Security::Wpa2Personal
+        );
+        assert_eq!(
+            security_from_elements(&rsn_element(&[8]), WLAN_CAPABILITY_PRIVACY),
+            Security::Wpa3Personal
+        );
+        assert_eq!(
+            security_from_elements(&rsn_element(&[8, 2]), WLAN_CAPABILITY_PRIVACY),
+            Security::Wpa2Personal
+        );
+        assert_eq!(
+            security_from_elements(&rsn_element(&[2, 1]), WLAN_CAPABILITY_PRIVACY),
+            Security::Enterprise
+        );
+    }
+
+    #[test]
+    fn infers_security_without_rsn_element() {
+        let ssid = [WLAN_EID_SSID, 4, b't', b'e', b's', b't'];
+        assert_eq!(security_from_elements(&ssid, 0), Security::Open);
+        assert_eq!(
+            security_from_elements(&ssid, WLAN_CAPABILITY_PRIVACY),
+            Security::Wep
+        );
+
+        let wpa = [
+            WLAN_EID_VENDOR_SPECIFIC,
+            22,
+            0x00,
+            0x50,
+            0xf2,
+            0x01,
+            1,
+            0,
+            0x00,
+            0x50,
+            0xf2,
+            2,
+            1,
+            0,
+            0x00,
+            0x50,
+            0xf2,
+            2,
+            1,
+            0,
+            0x00,
+            0x50,
+            0xf2,
+            2,
+        ];
+        assert_eq!(
+            security_from_elements(&wpa, WLAN_CAPABILITY_PRIVACY),
+            Security::WpaPersonal
+        );
+    }
+
+    #[test]
+    fn parses_attributes() {
+        let mut data = attribute(NL80211_ATTR_IFINDEX, &3u32.to_ne_bytes());
+        data.extend(attribute(NL80211_ATTR_IFNAME, b"wlan0\0"));
+
+        let attributes = parse_attributes(&data);
+        assert_eq!(
+            find_attribute(&attributes, NL80211_ATTR_IFINDEX).and_then(read_u32),
+            Some(3)
+        );
+        assert_eq!(
+            find_attribute(&attributes, NL80211_ATTR_IFNAME).map(read_string),
+            Some("wlan0".to_owned())
+        );
+    }
+}
@@ -0,0 +1,58 @@
+//! Reads the state that the AirPort configuration agent publishes in the dynamic store. The store
+//! does not contain the security type of the network.
+use super::{Security, WifiConnection};
+use std::{convert::TryInto, io};
+use system_configuration::{
+    core_foundation::{
+        base::{CFType, TCFType, ToVoid},
+        data::CFData,
+        dictionary::CFDictionary,
+        propertylist::CFPropertyList,
+        string::CFString,
+    },
+    dynamic_store::SCDynamicStoreBuilder,
+};
+
+const AIRPORT_PATH_PATTERN: &str = "State:/Network/Interface/[^/]+/AirPort";
+
+pub fn current_connections() -> io::Result<Vec<WifiConnection>> {
+    let store = SCDynamicStoreBuilder::new("talpid-wifi").build();
+    let paths = match store.get_keys(AIRPORT_PATH_PATTERN) {
+        Some(paths) => paths,
+        None => return Ok(vec![]),
+    };
+
+    let mut connections = vec![];
+    for path in paths.iter() {
+        let path = path.to_string();
+        let state = match store
+            .get(path.as_str())
+            .and_then(CFPropertyList::downcast_into::<CFDictionary>)
+        {
+            Some(state) => state,
+            None => continue,
+        };
+        // The SSID is absent when the interface is not associated
+        let ssid = match find_data(&state, "SSID") {
+            Some(ssid) if !ssid.is_empty() => ssid,
+            _ => continue,
+        };
+        let interface = path.split('/').nth(3).unwrap_or_default().to_owned();
+
+        connections.push(WifiConnection {
+            interface,
+            ssid,
+            bssid: find_data(&state, "BSSID").and_then(|bssid| bssid.as_slice().try_into().ok()),
+            security: Security::Unknown,
+        });
+    }
+    Ok(connections)
+}
+
+fn find_data(dictionary: &CFDictionary, key: &str) -> Option<Vec<u8>> {
+    dictionary
+        .find(CFString::new(key).to_void())
+        .map(|value| unsafe { CFType::wrap_under_get_rule(*value) })
+        .and_then(|value| value.downcast::<CFData>())
+        .map(|data| data.bytes().to_vec())
+}
@@ -0,0 +1,79 @@
+//! Reports the Wi-Fi networks that the host is connected to.
+use std::{fmt, io};
+
+#[cfg(target_os = "linux")]
+#[path = "linux.rs"]
+mod imp;
+
+#[cfg(target_os = "macos")]
+#[path = "macos.rs"]
+mod imp;
+
+#[cfg(windows)]
+#[path = "windows.rs"]
+mod imp;
+
+/// A Wi-Fi network that an interface is connected to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WifiConnection {
+    /// The name of the interface. On Windows, this is the description of the adapter.
+    pub interface: String,
+    /// The SSID of the network. It is not necessarily valid UTF-8.
+    pub ssid: Vec<u8>,
+    /// The BSSID of the access point, if it is known.
+    pub bssid: Option<[u8; 6]>,
+    /// How the network is secured.
+    pub security: Security,
+}
+
+impl WifiConnection {
+    /// Returns the SSID with any invalid UTF-8 replaced.
+    pub fn ssid_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.ssid).into_owned()
+    }
+}
+
+/// How a Wi-Fi network is secured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Security {
+    /// No encryption, or opportunistic wireless encryption (OWE) without authentication.
+    Open,
+    Wep,
+    WpaPersonal,
+    Wpa2Personal,
+    Wpa3Personal,
+    /// WPA, WPA2 or WPA3 with 802.1X authentication.
+    Enterprise,
+    /// The security type could not be determined.
+    Unknown,
+}
+
+impl fmt::Display for Security {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Security::Open => "open",
+            Security::Wep => "WEP",
+            Security::WpaPersonal => "WPA-Personal",
+            Security::Wpa2Personal => "WPA2-Personal",
+            Security::Wpa3Personal => "WPA3-Personal",
+            Security::Enterprise => "Enterprise",
+            Security::Unknown => "unknown",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Returns the Wi-Fi networks that the host is currently connected to. Interfaces that are not
+/// connected to a network are left out.
+pub fn current_connections() -> io::Result<Vec<WifiConnection>> {
+    imp::current_connections()
+}
+
+/// Formats a BSSID as colon-separated hexadecimal octets.
+pub fn format_bssid(bssid: &[u8; 6]) -> String {
+    bssid
+        .iter()
+        .map(|octet| format!("{:02x}", octet))
+        .collect::<Vec<_>>()
+        .join(":")
+}
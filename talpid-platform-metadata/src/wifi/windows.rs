@@ -0,0 +1,154 @@
+use super::{Security, WifiConnection};
+use std::{ffi::OsString, io, os::windows::ffi::OsStringExt, ptr, slice};
+use winapi::{
+    shared::{
+        winerror::{ERROR_SERVICE_NOT_ACTIVE, ERROR_SUCCESS},
+        wlantypes::{
+            DOT11_AUTH_ALGORITHM, DOT11_AUTH_ALGO_80211_OPEN, DOT11_AUTH_ALGO_80211_SHARED_KEY,
+            DOT11_AUTH_ALGO_RSNA, DOT11_AUTH_ALGO_RSNA_PSK, DOT11_AUTH_ALGO_WPA,
+            DOT11_AUTH_ALGO_WPA_PSK,
+        },
+    },
+    um::{
+        winnt::HANDLE,
+        wlanapi::{
+            wlan_interface_state_connected, wlan_intf_opcode_current_connection, WlanCloseHandle,
+            WlanEnumInterfaces, WlanFreeMemory, WlanOpenHandle, WlanQueryInterface,
+            PWLAN_CONNECTION_ATTRIBUTES, PWLAN_INTERFACE_INFO_LIST,
+        },
+    },
+};
+
+/// Client version for Windows Vista and later.
+const WLAN_API_VERSION_2_0: u32 = 2;
+
+// Missing from `winapi`
+const DOT11_AUTH_ALGO_WPA3_ENT_192: DOT11_AUTH_ALGORITHM = 8;
+const DOT11_AUTH_ALGO_WPA3_SAE: DOT11_AUTH_ALGORITHM = 9;
+const DOT11_AUTH_ALGO_OWE: DOT11_AUTH_ALGORITHM = 10;
+const DOT11_AUTH_ALGO_WPA3_ENT: DOT11_AUTH_ALGORITHM = 11;
+
+pub fn current_connections() -> io::Result<Vec<WifiConnection>> {
+    let client = match WlanClient::open() {
+        Ok(client) => client,
+        // The WLAN AutoConfig service does not run on hosts without wireless adapters
+        Err(error) if error.raw_os_error() == Some(ERROR_SERVICE_NOT_ACTIVE as i32) => {
+            return Ok(vec![])
+        }
+        Err(error) => return Err(error),
+    };
+
+    let mut interface_list: PWLAN_INTERFACE_INFO_LIST = ptr::null_mut();
+    check_status(unsafe { WlanEnumInterfaces(client.0, ptr::null_mut(), &mut interface_list) })?;
+    let _interface_list_guard = WlanMemory(interface_list as *mut _);
+
+    let interfaces = unsafe {
+        slice::from_raw_parts(
+            (*interface_list).InterfaceInfo.as_ptr(),
+            (*interface_list).dwNumberOfItems as usize,
+        )
+    };
+
+    let mut connections = vec![];
+    for interface in interfaces {
+        if interface.isState != wlan_interface_state_connected {
+            continue;
+        }
+
+        let mut size = 0;
+        let mut attributes: PWLAN_CONNECTION_ATTRIBUTES = ptr::null_mut();
+        let status = unsafe {
+            WlanQueryInterface(
+                client.0,
+                &interface.InterfaceGuid,
+                wlan_intf_opcode_current_connection,
+                ptr::null_mut(),
+                &mut size,
+                &mut attributes as *mut _ as *mut _,
+                ptr::null_mut(),
+            )
+        };
+        // The interface may have disconnected since it was enumerated
+        if status != ERROR_SUCCESS {
+            continue;
+        }
+        let _attributes_guard = WlanMemory(attributes as *mut _);
+
+        let association = unsafe { &(*attributes).wlanAssociationAttributes };
+        let security = unsafe { &(*attributes).wlanSecurityAttributes };
+        let ssid_length =
+            (association.dot11Ssid.uSSIDLength as usize).min(association.dot11Ssid.ucSSID.len());
+
+        connections.push(WifiConnection {
+            interface: wide_to_string(&interface.strInterfaceDescription),
+            ssid: association.dot11Ssid.ucSSID[..ssid_length].to_vec(),
+            bssid: Some(association.dot11Bssid),
+            security: security_from_auth_algorithm(security.dot11AuthAlgorithm),
+        });
+    }
+    Ok(connections)
+}
+
+fn security_from_auth_algorithm(algorithm: DOT11_AUTH_ALGORITHM) -> Security {
+    match algorithm {
+        DOT11_AUTH_ALGO_80211_OPEN | DOT11_AUTH_ALGO_OWE => Security::Open,
+        DOT11_AUTH_ALGO_80211_SHARED_KEY => Security::Wep,
+        DOT11_AUTH_ALGO_WPA_PSK => Security::WpaPersonal,
+        DOT11_AUTH_ALGO_RSNA_PSK => Security::Wpa2Personal,
+        DOT11_AUTH_ALGO_WPA3_SAE => Security::Wpa3Personal,
+        DOT11_AUTH_ALGO_WPA
+        | DOT11_AUTH_ALGO_RSNA
+        | DOT11_AUTH_ALGO_WPA3_ENT_192
+        | DOT11_AUTH_ALGO_WPA3_ENT => Security::Enterprise,
+        _ => Security::Unknown,
+    }
+}
+
+fn wide_to_string(wide: &[u16]) -> String {
+    let end = wide.iter().position(|c| *c == 0).unwrap_or(wide.len());
+    OsString::from_wide(&wide[..end])
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn check_status(status: u32) -> io::Result<()> {
+    if status == ERROR_SUCCESS {
+        Ok(())
+    } else {
+        Err(io::Error::from_raw_os_error(status as i32))
+    }
+}
+
+/// Closes the WLAN client handle when dropped.
+struct WlanClient(HANDLE);
+
+impl WlanClient {
+    fn open() -> io::Result<Self> {
+        let mut negotiated_version = 0;
+        let mut handle = ptr::null_mut();
+        check_status(unsafe {
+            WlanOpenHandle(
+                WLAN_API_VERSION_2_0,
+                ptr::null_mut(),
+                &mut negotiated_version,
+                &mut handle,
+            )
+        })?;
+        Ok(WlanClient(handle))
+    }
+}
+
+impl Drop for WlanClient {
+    fn drop(&mut self) {
+        unsafe { WlanCloseHandle(self.0, ptr::null_mut()) };
+    }
+}
+
+/// Frees memory allocated by the WLAN API when dropped.
+struct WlanMemory(*mut winapi::ctypes::c_void);
+
+impl Drop for WlanMemory {
+    fn drop(&mut self) {
+        unsafe { WlanFreeMemory(self.0) };
+    }
+}
@@ -0,0 +1,256 @@
+//! Cancellation of long-running tasks.
+//!
+//! A [`CancelToken`] is shared by the tasks that should stop at the same time, such as the ones
+//! that belong to a tunnel. Tokens form a tree: cancelling a token also cancels every token that
+//! was derived from it using [`CancelToken::child`], but not the token it was derived from. Both
+//! blocking threads and futures can wait for a token to be cancelled.
+use std::{
+    collections::BTreeMap,
+    fmt,
+    future::Future,
+    mem,
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex, MutexGuard, Weak},
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+/// Returned by [`CancelToken::run`] if the token was cancelled before the future completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("The operation was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// A token that tasks check or wait on to find out that they should stop.
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    state: Mutex<State>,
+    cancelled_cond: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    cancelled: bool,
+    wakers: BTreeMap<u64, Waker>,
+    next_waker_id: u64,
+    children: Vec<Weak<Inner>>,
+}
+
+impl CancelToken {
+    /// Creates a token that is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a new token that is cancelled when this token is cancelled. Cancelling the new
+    /// token does not affect this one.
+    pub fn child(&self) -> CancelToken {
+        let child = CancelToken::new();
+        let mut state = self.inner.lock();
+        if state.cancelled {
+            drop(state);
+            child.cancel();
+        } else {
+            state.children.retain(|child| child.strong_count() > 0);
+            state.children.push(Arc::downgrade(&child.inner));
+        }
+        child
+    }
+
+    /// Cancels this token and all tokens derived from it.
+    pub fn cancel(&self) {
+        self.inner.cancel();
+    }
+
+    /// Returns whether the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.lock().cancelled
+    }
+
+    /// Blocks until the token is cancelled or `timeout` has passed. Returns whether the token was
+    /// cancelled.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.inner.lock();
+        while !state.cancelled {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            state = self
+                .inner
+                .cancelled_cond
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(|error| error.into_inner())
+                .0;
+        }
+        true
+    }
+
+    /// Returns a future that completes when the token is cancelled.
+    pub fn cancelled(&self) -> WaitForCancellation {
+        WaitForCancellation {
+            token: self.clone(),
+            waker_id: None,
+        }
+    }
+
+    /// Runs `future` until it completes or the token is cancelled, whichever happens first.
+    pub async fn run<F: Future>(&self, future: F) -> Result<F::Output, Cancelled> {
+        Cancellable {
+            future: Box::pin(future),
+            cancelled: self.cancelled(),
+        }
+        .await
+    }
+}
+
+impl fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancelToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+impl Inner {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|error| error.into_inner())
+    }
+
+    fn cancel(&self) {
+        let (wakers, children) = {
+            let mut state = self.lock();
+            if state.cancelled {
+                return;
+            }
+            state.cancelled = true;
+            (mem::take(&mut state.wakers), mem::take(&mut state.children))
+        };
+        self.cancelled_cond.notify_all();
+        for (_, waker) in wakers {
+            waker.wake();
+        }
+        for child in children.iter().filter_map(Weak::upgrade) {
+            child.cancel();
+        }
+    }
+}
+
+/// Future returned by [`CancelToken::cancelled`].
+pub struct WaitForCancellation {
+    token: CancelToken,
+    waker_id: Option<u64>,
+}
+
+impl Future for WaitForCancellation {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        let mut state = this.token.inner.lock();
+        if state.cancelled {
+            return Poll::Ready(());
+        }
+        let waker_id = match this.waker_id {
+            Some(waker_id) => waker_id,
+            None => {
+                let waker_id = state.next_waker_id;
+                state.next_waker_id += 1;
+                this.waker_id = Some(waker_id);
+                waker_id
+            }
+        };
+        state.wakers.insert(waker_id, cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for WaitForCancellation {
+    fn drop(&mut self) {
+        if let Some(waker_id) = self.waker_id {
+            self.token.inner.lock().wakers.remove(&waker_id);
+        }
+    }
+}
+
+struct Cancellable<F: Future> {
+    future: Pin<Box<F>>,
+    cancelled: WaitForCancellation,
+}
+
+impl<F: Future> Future for Cancellable<F> {
+    type Output = Result<F::Output, Cancelled>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if Pin::new(&mut self.cancelled).poll(cx).is_ready() {
+            return Poll::Ready(Err(Cancelled));
+        }
+        self.future.as_mut().poll(cx).map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_children() {
+        let parent = CancelToken::new();
+        let child = parent.child();
+        let grandchild = child.child();
+        let sibling = parent.child();
+
+        child.cancel();
+        assert!(child.is_cancelled());
+        assert!(grandchild.is_cancelled());
+        assert!(!parent.is_cancelled());
+        assert!(!sibling.is_cancelled());
+
+        parent.cancel();
+        assert!(sibling.is_cancelled());
+        assert!(parent.child().is_cancelled());
+    }
+
+    #[test]
+    fn test_wait_timeout() {
+        let token = CancelToken::new();
+        assert!(!token.wait_timeout(Duration::from_millis(10)));
+
+        let child = token.child();
+        let waiter = thread::spawn(move || child.wait_timeout(Duration::from_secs(10)));
+        thread::sleep(Duration::from_millis(50));
+        let started = Instant::now();
+        token.cancel();
+        assert!(waiter.join().unwrap());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_run() {
+        let token = CancelToken::new();
+        assert_eq!(futures::executor::block_on(token.run(async { 1 })), Ok(1));
+
+        let canceller = token.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            canceller.cancel();
+        });
+        assert_eq!(
+            futures::executor::block_on(token.run(futures::future::pending::<()>())),
+            Err(Cancelled)
+        );
+    }
+}
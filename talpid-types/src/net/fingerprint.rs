@@ -0,0 +1,173 @@
+//! Identifies the network that the host is on by properties that stay the same between visits,
+//! without storing the properties themselves.
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr};
+
+/// Hash of a property of a network. The hash function is FNV-1a (64 bits), over a label for the
+/// kind of property followed by the property itself. The function and the labels must never
+/// change, since hashes are stored in the settings and compared with newly computed ones.
+///
+/// The hash keeps a property out of the settings and the logs, but a property that can be
+/// guessed, such as a common SSID, can be recovered from its hash. Hashes must therefore be left
+/// out of anything that is shared, such as problem reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FingerprintHash(u64);
+
+impl FingerprintHash {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    /// Hashes a property of the kind `label`.
+    fn new(label: &str, property: &[u8]) -> Self {
+        let mut hash = Self::FNV_OFFSET_BASIS;
+        for byte in label.as_bytes().iter().chain(&[0]).chain(property) {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(Self::FNV_PRIME);
+        }
+        FingerprintHash(hash)
+    }
+}
+
+impl fmt::Display for FingerprintHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl FromStr for FingerprintHash {
+    type Err = ParseFingerprintHashError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 16 || !s.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(ParseFingerprintHashError);
+        }
+        u64::from_str_radix(s, 16)
+            .map(FingerprintHash)
+            .map_err(|_| ParseFingerprintHashError)
+    }
+}
+
+impl Serialize for FingerprintHash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for FingerprintHash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(err_derive::Error, Debug, Clone, PartialEq)]
+#[error(display = "Not a valid network fingerprint hash. Expected 16 hexadecimal digits")]
+pub struct ParseFingerprintHashError;
+
+/// The kind of interface that the default route goes through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InterfaceType {
+    Wifi,
+    Ethernet,
+    Cellular,
+    /// Some other kind of interface, or the kind could not be determined.
+    Other,
+}
+
+impl Default for InterfaceType {
+    fn default() -> Self {
+        InterfaceType::Other
+    }
+}
+
+impl fmt::Display for InterfaceType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterfaceType::Wifi => "Wi-Fi".fmt(f),
+            InterfaceType::Ethernet => "Ethernet".fmt(f),
+            InterfaceType::Cellular => "cellular".fmt(f),
+            InterfaceType::Other => "other".fmt(f),
+        }
+    }
+}
+
+/// Properties of the network that the host is on, which tell it apart from other networks.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkFingerprint {
+    /// Hash of the MAC address of the default gateway, if it is known.
+    pub gateway_mac: Option<FingerprintHash>,
+    /// Hash of the SSID, if the default route goes through a Wi-Fi network.
+    pub ssid: Option<FingerprintHash>,
+    /// The kind of interface that the default route goes through.
+    pub interface_type: InterfaceType,
+    /// The country of the public IP address outside the tunnel, if it is known.
+    pub country: Option<String>,
+}
+
+impl NetworkFingerprint {
+    /// Creates a fingerprint from the unhashed properties of a network. The country is not set,
+    /// since it is learned separately.
+    pub fn new(
+        gateway_mac: Option<&[u8; 6]>,
+        ssid: Option<&[u8]>,
+        interface_type: InterfaceType,
+    ) -> Self {
+        NetworkFingerprint {
+            gateway_mac: gateway_mac.map(|mac| FingerprintHash::new("gateway-mac", mac)),
+            ssid: ssid.map(|ssid| FingerprintHash::new("ssid", ssid)),
+            interface_type,
+            country: None,
+        }
+    }
+
+    /// Returns a description of the fingerprint that leaves out the hashes, so that it can be
+    /// shared without identifying the network.
+    pub fn redacted(&self) -> String {
+        let known = |hash: &Option<FingerprintHash>| match hash {
+            Some(_) => "known",
+            None => "unknown",
+        };
+        format!(
+            "interface: {}, gateway: {}, SSID: {}, country: {}",
+            self.interface_type,
+            known(&self.gateway_mac),
+            known(&self.ssid),
+            self.country.as_deref().unwrap_or("unknown"),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hashes_are_stable() {
+        // Stored hashes stop matching if these change
+        let fingerprint = NetworkFingerprint::new(
+            Some(&[0x00, 0x11, 0x22, 0x33, 0x44, 0x55]),
+            Some(&b"Home"[..]),
+            InterfaceType::Wifi,
+        );
+        assert_eq!(
+            fingerprint.gateway_mac.unwrap().to_string(),
+            "c9b6c025c80a7236"
+        );
+        assert_eq!(fingerprint.ssid.unwrap().to_string(), "0415411848c72595");
+        assert_eq!(
+            serde_json::to_string(&fingerprint.ssid).unwrap(),
+            "\"0415411848c72595\""
+        );
+    }
+
+    #[test]
+    fn test_parse_hash() {
+        let hash = FingerprintHash::new("ssid", b"Home");
+        assert_eq!(hash.to_string().parse(), Ok(hash));
+        assert!("0415411848c7259".parse::<FingerprintHash>().is_err());
+        assert!("+415411848c72595".parse::<FingerprintHash>().is_err());
+    }
+}
@@ -7,6 +7,7 @@ use std::{
     str::FromStr,
 };
 
+pub mod fingerprint;
 pub mod openvpn;
 pub mod proxy;
 pub mod wireguard;
@@ -29,6 +30,7 @@ impl TunnelParameters {
                 endpoint: params.config.endpoint,
                 proxy: params.proxy.as_ref().map(|proxy| proxy.get_endpoint()),
                 entry_endpoint: None,
+                obfuscation: None,
             },
             TunnelParameters::Wireguard(params) => TunnelEndpoint {
                 tunnel_type: TunnelType::Wireguard,
@@ -36,11 +38,17 @@ impl TunnelParameters {
                     .connection
                     .get_exit_endpoint()
                     .unwrap_or(params.connection.get_endpoint()),
-                proxy: None,
+                proxy: match &params.connection.obfuscation {
+                    Some(wireguard::ObfuscatorConfig::Bridge(settings)) => {
+                        Some(settings.get_endpoint())
+                    }
+                    _ => None,
+                },
                 entry_endpoint: params
                     .connection
                     .get_exit_endpoint()
                     .map(|_| params.connection.get_endpoint()),
+                obfuscation: params.connection.get_obfuscation_endpoint(),
             },
         }
     }
@@ -53,7 +61,7 @@ impl TunnelParameters {
                 .as_ref()
                 .map(|proxy| proxy.get_endpoint().endpoint)
                 .unwrap_or(params.config.endpoint),
-            TunnelParameters::Wireguard(params) => params.connection.get_endpoint(),
+            TunnelParameters::Wireguard(params) => params.connection.get_next_hop_endpoint(),
         }
     }
 
@@ -71,6 +79,13 @@ impl TunnelParameters {
             TunnelParameters::Wireguard(params) => &params.generic_options,
         }
     }
+
+    pub fn get_generic_options_mut(&mut self) -> &mut GenericTunnelOptions {
+        match self {
+            TunnelParameters::OpenVpn(params) => &mut params.generic_options,
+            TunnelParameters::Wireguard(params) => &mut params.generic_options,
+        }
+    }
 }
 
 impl From<wireguard::TunnelParameters> for TunnelParameters {
@@ -119,6 +134,10 @@ pub struct TunnelEndpoint {
     pub proxy: Option<proxy::ProxyEndpoint>,
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub entry_endpoint: Option<Endpoint>,
+    /// The obfuscation server that WireGuard traffic is sent through, if any.
+    #[serde(default)]
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub obfuscation: Option<ObfuscationEndpoint>,
 }
 
 impl fmt::Display for TunnelEndpoint {
@@ -138,12 +157,63 @@ impl fmt::Display for TunnelEndpoint {
                 if let Some(ref entry_endpoint) = self.entry_endpoint {
                     write!(f, " via {}", entry_endpoint)?;
                 }
+                if let Some(ref obfuscation) = self.obfuscation {
+                    write!(f, " via {}", obfuscation)?;
+                }
+                if let Some(ref proxy) = self.proxy {
+                    write!(
+                        f,
+                        " via {} {} over {}",
+                        proxy.proxy_type, proxy.endpoint.address, proxy.endpoint.protocol
+                    )?;
+                }
             }
         }
         Ok(())
     }
 }
 
+/// The kind of obfuscation used to reach a WireGuard relay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ObfuscationType {
+    /// WireGuard datagrams are sent over TCP to the relay.
+    Udp2Tcp,
+    /// WireGuard datagrams are sent as QUIC datagrams to a proxy.
+    Quic,
+    /// WireGuard datagrams are relayed by a Shadowsocks server.
+    Shadowsocks,
+}
+
+impl fmt::Display for ObfuscationType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        let obfuscation = match self {
+            ObfuscationType::Udp2Tcp => "udp2tcp",
+            ObfuscationType::Quic => "QUIC",
+            ObfuscationType::Shadowsocks => "Shadowsocks",
+        };
+        write!(f, "{}", obfuscation)
+    }
+}
+
+/// Obfuscation server, broadcast as part of a [`TunnelEndpoint`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ObfuscationEndpoint {
+    #[serde(flatten)]
+    pub endpoint: Endpoint,
+    pub obfuscation_type: ObfuscationType,
+}
+
+impl fmt::Display for ObfuscationEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "{} {} over {}",
+            self.obfuscation_type, self.endpoint.address, self.endpoint.protocol
+        )
+    }
+}
+
 
 /// Represents a network layer IP address together with the transport layer protocol and port.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -199,6 +269,133 @@ impl fmt::Display for IpVersion {
     }
 }
 
+/// The IP versions over which the internet can be reached.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Connectivity {
+    /// Whether the internet can be reached over IPv4.
+    pub ipv4: bool,
+    /// Whether the internet can be reached over IPv6.
+    pub ipv6: bool,
+}
+
+impl Connectivity {
+    /// Returns the connectivity for platforms that can only tell whether the host is offline.
+    /// IPv4 is presumed to be available when it is not, since it is the most widely available.
+    pub fn from_offline_state(is_offline: bool) -> Self {
+        Connectivity {
+            ipv4: !is_offline,
+            ipv6: false,
+        }
+    }
+
+    /// Returns whether the internet cannot be reached over any IP version.
+    pub fn is_offline(&self) -> bool {
+        !self.ipv4 && !self.ipv6
+    }
+
+    /// Returns whether the internet can only be reached over IPv6.
+    pub fn is_ipv6_only(&self) -> bool {
+        !self.ipv4 && self.ipv6
+    }
+}
+
+impl fmt::Display for Connectivity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.ipv4, self.ipv6) {
+            (true, true) => f.write_str("IPv4 and IPv6"),
+            (true, false) => f.write_str("IPv4 only"),
+            (false, true) => f.write_str("IPv6 only"),
+            (false, false) => f.write_str("offline"),
+        }
+    }
+}
+
+/// Traffic of essential system services that is let through while all other traffic is blocked.
+/// DHCP is always allowed and is not listed here.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SystemServiceExemptions {
+    /// Allow NTP requests to any server, so that the clock can be kept in sync.
+    pub ntp: bool,
+    /// Hosts that plain HTTP requests may be sent to, in order to detect captive portals.
+    pub captive_portal_hosts: Vec<IpAddr>,
+    /// Allow DNS requests and web traffic to any host, so that the user can log in to a captive
+    /// portal.
+    pub portal_login: bool,
+}
+
+impl SystemServiceExemptions {
+    /// Returns whether any traffic is exempted.
+    pub fn is_empty(&self) -> bool {
+        !self.ntp && self.captive_portal_hosts.is_empty() && !self.portal_login
+    }
+}
+
+/// Traffic to and from a network that the user has chosen to let through the firewall outside
+/// the tunnel, in every tunnel state. DNS requests are never let through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct AllowedEndpoint {
+    /// The network that traffic is allowed to and from.
+    pub network: ipnetwork::IpNetwork,
+    /// Only allow traffic to and from this port of the hosts in the network.
+    pub port: Option<u16>,
+    /// Only allow this transport protocol. If a port is given without a protocol, the port is
+    /// allowed for both TCP and UDP.
+    pub protocol: Option<TransportProtocol>,
+}
+
+impl AllowedEndpoint {
+    /// Creates an allowed endpoint. Any host bits of `network` are cleared, since the firewall
+    /// rules match the network address.
+    pub fn new(
+        network: ipnetwork::IpNetwork,
+        port: Option<u16>,
+        protocol: Option<TransportProtocol>,
+    ) -> Self {
+        let network =
+            ipnetwork::IpNetwork::new(network.network(), network.prefix()).unwrap_or(network);
+        Self {
+            network,
+            port,
+            protocol,
+        }
+    }
+
+    /// Returns the transport protocols that firewall rules have to be added for. `None` means
+    /// that all traffic is allowed, regardless of protocol.
+    pub fn rule_protocols(&self) -> Vec<Option<TransportProtocol>> {
+        match (self.protocol, self.port) {
+            (Some(protocol), _) => vec![Some(protocol)],
+            (None, Some(_)) => vec![Some(TransportProtocol::Tcp), Some(TransportProtocol::Udp)],
+            (None, None) => vec![None],
+        }
+    }
+}
+
+impl fmt::Display for AllowedEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.network)?;
+        if let Some(protocol) = self.protocol {
+            write!(f, " {}", protocol)?;
+        }
+        if let Some(port) = self.port {
+            write!(f, " port {}", port)?;
+        }
+        Ok(())
+    }
+}
+
+/// The network that the host is on, as far as it can be told apart from other networks.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkIdentity {
+    /// The SSIDs of the Wi-Fi networks that the host is connected to.
+    pub ssids: Vec<String>,
+    /// The on-link networks of the physical interfaces that are up.
+    pub networks: Vec<ipnetwork::IpNetwork>,
+    /// Properties of the network that the default route goes through.
+    #[serde(default)]
+    pub fingerprint: fingerprint::NetworkFingerprint,
+}
+
 /// Representation of a transport protocol, either UDP or TCP.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -250,6 +447,89 @@ pub struct GenericTunnelOptions {
     /// Enable configuration of IPv6 on the tunnel interface, allowing IPv6 communication to be
     /// forwarded through the tunnel.
     pub enable_ipv6: bool,
+    /// Name of the tunnel interface. A platform-specific default is used if this is not set.
+    /// See [`validate_interface_name`] for the names that are allowed.
+    #[serde(default)]
+    pub interface_name: Option<String>,
+    /// What to do when IPv6 is enabled but cannot be set up on the tunnel interface.
+    #[serde(default)]
+    pub ipv6_failure_policy: Ipv6FailurePolicy,
+}
+
+/// What to do when IPv6 cannot be set up on the tunnel interface, such as when IPv6 is disabled
+/// in the OS or duplicate address detection fails for the tunnel address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ipv6FailurePolicy {
+    /// Fail the connection attempt. Traffic is blocked until an attempt succeeds.
+    Block,
+    /// Connect without IPv6 in the tunnel. IPv6 traffic is blocked, as when IPv6 is disabled.
+    Ipv4Only,
+}
+
+impl Default for Ipv6FailurePolicy {
+    fn default() -> Self {
+        Ipv6FailurePolicy::Ipv4Only
+    }
+}
+
+impl fmt::Display for Ipv6FailurePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            Ipv6FailurePolicy::Block => f.write_str("block"),
+            Ipv6FailurePolicy::Ipv4Only => f.write_str("ipv4-only"),
+        }
+    }
+}
+
+/// Longest tunnel interface name that is accepted. On Linux and macOS, this is `IFNAMSIZ` bytes
+/// minus the terminating null character. On Windows, the name is an adapter alias, which Wintun
+/// and WireGuardNT limit to 127 UTF-16 characters.
+#[cfg(not(windows))]
+pub const MAX_INTERFACE_NAME_LENGTH: usize = 15;
+#[cfg(windows)]
+pub const MAX_INTERFACE_NAME_LENGTH: usize = 127;
+
+/// Reasons for why a name cannot be used for the tunnel interface.
+#[derive(err_derive::Error, Debug, Clone, PartialEq, Eq)]
+pub enum InterfaceNameError {
+    #[error(display = "The interface name is empty")]
+    Empty,
+    #[error(display = "The interface name is too long")]
+    TooLong,
+    #[error(display = "The interface name contains an invalid character: {:?}", _0)]
+    InvalidCharacter(char),
+    /// Only utun devices can be created by the app on macOS.
+    #[error(display = "The interface name must be \"utun\" followed by a number")]
+    NotUtun,
+}
+
+/// Checks whether `name` can be used as the name of the tunnel interface on this platform.
+pub fn validate_interface_name(name: &str) -> Result<(), InterfaceNameError> {
+    if name.is_empty() {
+        return Err(InterfaceNameError::Empty);
+    }
+    #[cfg(windows)]
+    let length = name.encode_utf16().count();
+    #[cfg(not(windows))]
+    let length = name.len();
+    if length > MAX_INTERFACE_NAME_LENGTH {
+        return Err(InterfaceNameError::TooLong);
+    }
+    // Windows allows most characters in adapter aliases, but `/` and `:` have special meaning
+    // to `ip` and `ifconfig` on other platforms
+    let is_invalid = |c: char| {
+        c.is_control() || (cfg!(not(windows)) && (c.is_whitespace() || c == '/' || c == ':'))
+    };
+    if let Some(c) = name.chars().find(|c| is_invalid(*c)) {
+        return Err(InterfaceNameError::InvalidCharacter(c));
+    }
+    #[cfg(target_os = "macos")]
+    match name.strip_prefix("utun") {
+        Some(unit) if !unit.is_empty() && unit.chars().all(|c| c.is_ascii_digit()) => (),
+        _ => return Err(InterfaceNameError::NotUtun),
+    }
+    Ok(())
 }
 
 /// Returns a vector of IP networks representing all of the internet, 0.0.0.0/0.
@@ -261,3 +541,105 @@ pub fn all_of_the_internet() -> Vec<ipnetwork::IpNetwork> {
         "::0/0".parse().expect("Failed to parse ipv6 network"),
     ]
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_allowed_endpoint_rule_protocols() {
+        let mut endpoint = AllowedEndpoint {
+            network: "10.1.0.0/16".parse().unwrap(),
+            port: None,
+            protocol: None,
+        };
+        assert_eq!(endpoint.rule_protocols(), vec![None]);
+        assert_eq!(endpoint.to_string(), "10.1.0.0/16");
+
+        endpoint.port = Some(5353);
+        assert_eq!(
+            endpoint.rule_protocols(),
+            vec![Some(TransportProtocol::Tcp), Some(TransportProtocol::Udp)]
+        );
+        assert_eq!(endpoint.to_string(), "10.1.0.0/16 port 5353");
+
+        endpoint.protocol = Some(TransportProtocol::Udp);
+        assert_eq!(
+            endpoint.rule_protocols(),
+            vec![Some(TransportProtocol::Udp)]
+        );
+        assert_eq!(endpoint.to_string(), "10.1.0.0/16 UDP port 5353");
+
+        let endpoint = AllowedEndpoint::new("10.1.2.3/16".parse().unwrap(), None, None);
+        assert_eq!(endpoint.network.to_string(), "10.1.0.0/16");
+    }
+
+    #[test]
+    fn test_tunnel_endpoint_display() {
+        let mut endpoint = TunnelEndpoint {
+            endpoint: Endpoint::new([185, 213, 154, 68], 51820, TransportProtocol::Udp),
+            tunnel_type: TunnelType::Wireguard,
+            proxy: None,
+            entry_endpoint: None,
+            obfuscation: Some(ObfuscationEndpoint {
+                endpoint: Endpoint::new([185, 213, 154, 68], 443, TransportProtocol::Tcp),
+                obfuscation_type: ObfuscationType::Udp2Tcp,
+            }),
+        };
+        assert_eq!(
+            endpoint.to_string(),
+            "WireGuard - 185.213.154.68:51820 over UDP via udp2tcp 185.213.154.68:443 over TCP"
+        );
+
+        endpoint.obfuscation = None;
+        endpoint.proxy = Some(proxy::ProxyEndpoint {
+            endpoint: Endpoint::new([185, 65, 134, 115], 443, TransportProtocol::Tcp),
+            proxy_type: proxy::ProxyType::Shadowsocks,
+        });
+        assert_eq!(
+            endpoint.to_string(),
+            "WireGuard - 185.213.154.68:51820 over UDP via Shadowsocks 185.65.134.115:443 over TCP"
+        );
+    }
+
+    #[test]
+    fn test_validate_interface_name() {
+        assert_eq!(validate_interface_name(""), Err(InterfaceNameError::Empty));
+        assert_eq!(
+            validate_interface_name(&"a".repeat(MAX_INTERFACE_NAME_LENGTH + 1)),
+            Err(InterfaceNameError::TooLong)
+        );
+        assert_eq!(
+            validate_interface_name("wg\nmullvad"),
+            Err(InterfaceNameError::InvalidCharacter('\n'))
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_validate_linux_interface_name() {
+        assert!(validate_interface_name("wg-mullvad").is_ok());
+        assert_eq!(
+            validate_interface_name("wg/mullvad"),
+            Err(InterfaceNameError::InvalidCharacter('/'))
+        );
+        assert_eq!(
+            validate_interface_name("wg mullvad"),
+            Err(InterfaceNameError::InvalidCharacter(' '))
+        );
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn test_validate_macos_interface_name() {
+        assert!(validate_interface_name("utun7").is_ok());
+        assert_eq!(
+            validate_interface_name("utun"),
+            Err(InterfaceNameError::NotUtun)
+        );
+        assert_eq!(
+            validate_interface_name("wg-mullvad"),
+            Err(InterfaceNameError::NotUtun)
+        );
+    }
+}
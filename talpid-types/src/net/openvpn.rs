@@ -3,7 +3,7 @@ use crate::net::{
     Endpoint, GenericTunnelOptions, TransportProtocol,
 };
 use serde::{Deserialize, Serialize};
-use std::net::SocketAddr;
+use std::{fmt, net::SocketAddr, time::Duration};
 
 /// Information needed by `OpenVpnMonitor` to establish a tunnel connection.
 /// See [`crate::net::TunnelParameters`].
@@ -21,6 +21,13 @@ pub struct ConnectionConfig {
     pub endpoint: Endpoint,
     pub username: String,
     pub password: String,
+    /// TLS material and options for servers that are not run by Mullvad. The bundled Mullvad CA
+    /// is used if this is `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<ServerProfile>,
+    /// How long to wait for the TLS handshake to complete, if not the OpenVPN default.
+    #[serde(default)]
+    pub handshake_timeout: Option<Duration>,
 }
 
 impl ConnectionConfig {
@@ -29,10 +36,50 @@ impl ConnectionConfig {
             endpoint,
             username,
             password,
+            profile: None,
+            handshake_timeout: None,
         }
     }
 }
 
+/// Server specific options, as read from an OpenVPN client profile. Certificates and keys are
+/// PEM encoded.
+#[derive(Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub struct ServerProfile {
+    /// CA certificates that the server certificate is verified against.
+    pub ca: String,
+    /// Client certificate, for servers that authenticate clients by certificate.
+    pub cert: Option<String>,
+    /// Private key of the client certificate.
+    pub key: Option<String>,
+    /// Static key used to authenticate the TLS control channel.
+    pub tls_auth: Option<String>,
+    /// Key direction used with `tls_auth`.
+    pub key_direction: Option<u8>,
+    /// Static key used to authenticate and encrypt the TLS control channel.
+    pub tls_crypt: Option<String>,
+    /// Data channel ciphers to negotiate, separated by colons.
+    pub data_ciphers: Option<String>,
+    /// Message digest used to authenticate data channel packets.
+    pub auth: Option<String>,
+    /// Whether the server authenticates clients by username and password.
+    pub auth_user_pass: bool,
+}
+
+impl fmt::Debug for ServerProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerProfile")
+            .field("cert", &self.cert.is_some())
+            .field("tls_auth", &self.tls_auth.is_some())
+            .field("key_direction", &self.key_direction)
+            .field("tls_crypt", &self.tls_crypt.is_some())
+            .field("data_ciphers", &self.data_ciphers)
+            .field("auth", &self.auth)
+            .field("auth_user_pass", &self.auth_user_pass)
+            .finish()
+    }
+}
+
 /// `TunnelOptions` contains options for an OpenVPN tunnel that should be applied
 /// irrespective of the relay parameters - i.e. have nothing to do with the particular
 /// OpenVPN server, but do affect the connection.
@@ -42,6 +89,43 @@ pub struct TunnelOptions {
     /// Optional argument for openvpn to try and limit TCP packet size,
     /// as discussed [here](https://openvpn.net/archive/openvpn-users/2003-11/msg00154.html)
     pub mssfix: Option<u16>,
+    /// Use the ovpn-dco-win data channel offload driver instead of Wintun, if it is installed
+    #[cfg(windows)]
+    #[serde(default)]
+    pub use_ovpn_dco: bool,
+    /// What to do with the Wintun adapter once the tunnel has closed
+    #[cfg(windows)]
+    #[serde(default)]
+    pub wintun_adapter_policy: WintunAdapterPolicy,
+}
+
+/// What to do with the Wintun adapter once an OpenVPN tunnel that used it has closed. Adapters
+/// that are kept are reused by later tunnels, so that the adapter keeps its GUID and name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WintunAdapterPolicy {
+    /// Keep the adapter, also after the daemon has stopped.
+    Keep,
+    /// Keep the adapter while the daemon is running, and delete it when the daemon stops.
+    DeleteOnShutdown,
+    /// Delete the adapter, so that each tunnel creates a new one.
+    DeleteAfterUse,
+}
+
+impl Default for WintunAdapterPolicy {
+    fn default() -> Self {
+        WintunAdapterPolicy::Keep
+    }
+}
+
+impl fmt::Display for WintunAdapterPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WintunAdapterPolicy::Keep => "keep".fmt(f),
+            WintunAdapterPolicy::DeleteOnShutdown => "delete-on-shutdown".fmt(f),
+            WintunAdapterPolicy::DeleteAfterUse => "delete-after-use".fmt(f),
+        }
+    }
 }
 
 /// Proxy server options to be used by `OpenVpnMonitor` when starting a tunnel.
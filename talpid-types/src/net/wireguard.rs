@@ -1,4 +1,7 @@
-use crate::net::{Endpoint, GenericTunnelOptions, TransportProtocol};
+use crate::net::{
+    openvpn::ProxySettings, Endpoint, GenericTunnelOptions, ObfuscationEndpoint, ObfuscationType,
+    TransportProtocol,
+};
 use ipnetwork::IpNetwork;
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
@@ -8,7 +11,9 @@ use std::{
     cmp, fmt,
     hash::{Hash, Hasher},
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
 };
+use zeroize::Zeroize;
 
 
 /// Tunnel parameters required to start a `WireguardMonitor`.
@@ -29,6 +34,17 @@ pub struct ConnectionConfig {
     /// Gateway used by the tunnel (a private address).
     pub ipv4_gateway: Ipv4Addr,
     pub ipv6_gateway: Option<Ipv6Addr>,
+    /// Obfuscation applied to the traffic sent to `peer`, if any.
+    #[serde(default)]
+    pub obfuscation: Option<ObfuscatorConfig>,
+    /// How long to wait for the tunnel to start working before the connection attempt fails, if
+    /// not the default.
+    #[serde(default)]
+    pub handshake_timeout: Option<Duration>,
+    /// Whether to negotiate a preshared key with the peer using post-quantum key encapsulation
+    /// before the tunnel is considered to be up.
+    #[serde(default)]
+    pub quantum_resistant: bool,
 }
 
 impl ConnectionConfig {
@@ -45,6 +61,64 @@ impl ConnectionConfig {
             protocol: peer.protocol,
         })
     }
+
+    /// Returns the endpoint that traffic is sent to. This is the obfuscator, if one is used.
+    pub fn get_next_hop_endpoint(&self) -> Endpoint {
+        match &self.obfuscation {
+            Some(ObfuscatorConfig::Quic { endpoint, .. })
+            | Some(ObfuscatorConfig::Shadowsocks { endpoint, .. }) => Endpoint {
+                address: *endpoint,
+                protocol: TransportProtocol::Udp,
+            },
+            Some(ObfuscatorConfig::Bridge(settings)) => settings.get_endpoint().endpoint,
+            None => self.get_endpoint(),
+        }
+    }
+
+    /// Returns the obfuscation server that traffic to `peer` is sent through, if any. Peers that
+    /// are reached over TCP are obfuscated with udp2tcp, unless a bridge is used, and are never
+    /// obfuscated in other ways.
+    pub fn get_obfuscation_endpoint(&self) -> Option<ObfuscationEndpoint> {
+        match &self.obfuscation {
+            Some(ObfuscatorConfig::Bridge(_)) => None,
+            _ if self.peer.protocol == TransportProtocol::Tcp => Some(ObfuscationEndpoint {
+                endpoint: self.get_endpoint(),
+                obfuscation_type: ObfuscationType::Udp2Tcp,
+            }),
+            Some(ObfuscatorConfig::Quic { endpoint, .. }) => Some(ObfuscationEndpoint {
+                endpoint: Endpoint::from_socket_address(*endpoint, TransportProtocol::Udp),
+                obfuscation_type: ObfuscationType::Quic,
+            }),
+            Some(ObfuscatorConfig::Shadowsocks { endpoint, .. }) => Some(ObfuscationEndpoint {
+                endpoint: Endpoint::from_socket_address(*endpoint, TransportProtocol::Udp),
+                obfuscation_type: ObfuscationType::Shadowsocks,
+            }),
+            None => None,
+        }
+    }
+}
+
+/// Obfuscation of the traffic sent to the first peer.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub enum ObfuscatorConfig {
+    /// WireGuard datagrams are sent as QUIC datagrams to a proxy, which forwards them to the
+    /// peer. This is experimental.
+    Quic {
+        endpoint: SocketAddr,
+        /// Name used to verify the TLS certificate of the proxy.
+        server_name: String,
+    },
+    /// WireGuard datagrams are relayed over UDP by a Shadowsocks server, which forwards them to
+    /// the peer.
+    Shadowsocks {
+        endpoint: SocketAddr,
+        password: String,
+        /// Should map to a cipher supported by shadowsocks-rust.
+        cipher: String,
+    },
+    /// WireGuard datagrams are sent over TCP through a SOCKS5 proxy or a Shadowsocks bridge,
+    /// which connects to the TCP endpoint of the peer. The peer must be reached over TCP.
+    Bridge(ProxySettings),
 }
 
 #[derive(Clone, Eq, PartialEq, Deserialize, Serialize, Debug, Hash)]
@@ -59,6 +133,9 @@ pub struct PeerConfig {
     /// If this is set to TCP, then traffic is proxied using [`udp_to_tcp::Udp2Tcp`].
     #[serde(default = "default_peer_transport")]
     pub protocol: TransportProtocol,
+    /// Preshared key negotiated with the peer, if any. It is ephemeral and never serialized.
+    #[serde(skip)]
+    pub psk: Option<PresharedKey>,
 }
 
 fn default_peer_transport() -> TransportProtocol {
@@ -86,12 +163,34 @@ pub struct TunnelOptions {
         jnix(map = "|maybe_mtu| maybe_mtu.map(|mtu| mtu as i32)")
     )]
     pub mtu: Option<u16>,
+    /// Interval, in seconds, at which to ping the tunnel gateway to check that the tunnel is
+    /// working. If this is `None`, connectivity is instead inferred from the traffic and pings
+    /// are only sent when the traffic stalls.
+    #[serde(default)]
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub icmp_check_interval: Option<u16>,
+    /// Packet loss, in percent, above which to reconnect to another relay. The loss must stay
+    /// above the threshold for a while before reconnecting. If this is `None`, packet loss never
+    /// causes a reconnect.
+    #[serde(default)]
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub packet_loss_threshold: Option<u8>,
     /// Temporary switch for wireguard-nt
     #[cfg(windows)]
     #[serde(default)]
     pub use_wireguard_nt: bool,
+    /// Use wireguard-go even if the WireGuard kernel module is available
+    #[cfg(target_os = "linux")]
+    #[serde(default)]
+    pub use_userspace_wireguard: bool,
 }
 
+/// Range of valid values for [`TunnelOptions::icmp_check_interval`], in seconds.
+pub const ICMP_CHECK_INTERVAL_RANGE: std::ops::RangeInclusive<u16> = 1..=60;
+
+/// Range of valid values for [`TunnelOptions::packet_loss_threshold`], in percent.
+pub const PACKET_LOSS_THRESHOLD_RANGE: std::ops::RangeInclusive<u8> = 1..=99;
+
 /// Wireguard x25519 private key
 #[derive(Clone)]
 pub struct PrivateKey(x25519_dalek::StaticSecret);
@@ -190,6 +289,35 @@ impl PublicKey {
 }
 
 
+/// WireGuard preshared key, which is mixed into the handshake on top of the key exchange
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct PresharedKey(Box<[u8; 32]>);
+
+impl PresharedKey {
+    /// Get the preshared key as bytes
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl From<Box<[u8; 32]>> for PresharedKey {
+    fn from(key: Box<[u8; 32]>) -> PresharedKey {
+        PresharedKey(key)
+    }
+}
+
+impl Drop for PresharedKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl fmt::Debug for PresharedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PresharedKey(..)")
+    }
+}
+
 impl<'a> From<&'a x25519_dalek::StaticSecret> for PublicKey {
     fn from(private_key: &'a x25519_dalek::StaticSecret) -> PublicKey {
         PublicKey(x25519_dalek::PublicKey::from(private_key))
@@ -1,10 +1,19 @@
+//! Types describing the state of the tunnel.
+//!
+//! These types are sent to frontends that may be older or newer than the daemon, so the
+//! serialized format has to stay compatible in both directions:
+//! - New fields must be optional, or have a default, so that older input can be deserialized.
+//! - Unknown fields are ignored, so that newer input can be deserialized.
+//! - Enums have an `Unknown` variant that any unrecognized variant deserializes into. It is never
+//!   emitted by the daemon itself.
+//! - Existing variants and fields must not be renamed or removed.
 use crate::net::TunnelEndpoint;
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
-use serde::{Deserialize, Serialize};
-use std::fmt;
+use serde::{Deserialize, Deserializer, Serialize};
 #[cfg(target_os = "android")]
 use std::net::IpAddr;
+use std::{fmt, time::SystemTime};
 
 /// Event emitted from the states in `talpid_core::tunnel_state_machine` when the tunnel state
 /// machine enters a new state.
@@ -15,7 +24,13 @@ pub enum TunnelStateTransition {
     /// No connection is established and network is unsecured.
     Disconnected,
     /// Network is secured but tunnel is still connecting.
-    Connecting(TunnelEndpoint),
+    Connecting {
+        #[serde(flatten)]
+        endpoint: TunnelEndpoint,
+        /// When the tunnel is started, if the start was put off after a failed attempt.
+        #[serde(default)]
+        retry_at: Option<SystemTime>,
+    },
     /// Tunnel is connected.
     Connected(TunnelEndpoint),
     /// Disconnecting tunnel.
@@ -42,12 +57,14 @@ pub enum ActionAfterDisconnect {
 #[cfg_attr(target_os = "android", jnix(package = "net.mullvad.talpid.tunnel"))]
 pub struct ErrorState {
     /// Reason why the tunnel state machine ended up in the error state
+    #[serde(deserialize_with = "deserialize_cause")]
     cause: ErrorStateCause,
     /// Indicates whether the daemon is currently blocking all traffic. This _should_ always
     /// succeed - in the case it does not, the user should be notified that no traffic is being
     /// blocked.
     /// An error value means there was a serious error and the intended security properties are not
     /// being upheld.
+    #[serde(default, deserialize_with = "deserialize_block_failure")]
     #[cfg_attr(
         target_os = "android",
         jnix(map = "|block_failure| block_failure.is_none()")
@@ -76,6 +93,33 @@ impl ErrorState {
     }
 }
 
+/// Either a value known to this version, or something added in a newer version.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KnownOrUnknown<T> {
+    Known(T),
+    Unknown(serde::de::IgnoredAny),
+}
+
+/// Deserializes an [`ErrorStateCause`], falling back on [`ErrorStateCause::Unknown`] if the
+/// cause, or any of its details, is not recognized.
+fn deserialize_cause<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<ErrorStateCause, D::Error> {
+    Ok(match KnownOrUnknown::deserialize(deserializer)? {
+        KnownOrUnknown::Known(cause) => cause,
+        KnownOrUnknown::Unknown(_) => ErrorStateCause::Unknown,
+    })
+}
+
+fn deserialize_block_failure<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<FirewallPolicyError>, D::Error> {
+    Ok(match KnownOrUnknown::deserialize(deserializer)? {
+        KnownOrUnknown::Known(block_failure) => block_failure,
+        KnownOrUnknown::Unknown(_) => Some(FirewallPolicyError::Unknown),
+    })
+}
 
 /// Reason for the tunnel state machine entering an [`ErrorState`].
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -107,6 +151,12 @@ pub enum ErrorStateCause {
     /// Error reported by split tunnel module.
     #[cfg(target_os = "windows")]
     SplitTunnelError,
+    /// Traffic is blocked, except for what is needed to log in to a captive portal.
+    #[cfg(not(target_os = "android"))]
+    CaptivePortalLogin,
+    /// A cause added in a newer version, that this version does not know about.
+    #[serde(other)]
+    Unknown,
 }
 
 /// Errors that can occur when generating tunnel parameters.
@@ -127,6 +177,10 @@ pub enum ParameterGenerationError {
     /// Failure to resolve the hostname of a custom tunnel configuration
     #[error(display = "Can't resolve hostname for custom tunnel host")]
     CustomTunnelHostResultionError,
+    /// An error added in a newer version, that this version does not know about.
+    #[serde(other)]
+    #[error(display = "Unknown error")]
+    Unknown,
 }
 
 /// Application that prevents setting the firewall policy.
@@ -151,6 +205,67 @@ pub enum FirewallPolicyError {
     #[cfg(windows)]
     #[error(display = "An application prevented the firewall policy from being set")]
     Locked(Option<BlockingApplication>),
+    /// An error added in a newer version, that this version does not know about.
+    #[serde(other)]
+    #[error(display = "Failed to set firewall policy for an unknown reason")]
+    Unknown,
+}
+
+/// What made the tunnel state machine leave its previous state, if it was something other than a
+/// command. Sent along with the [`TunnelStateTransition`] it caused.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "reason", content = "details")]
+pub enum TransitionReason {
+    /// Failed to set the DNS servers of the system.
+    SetDnsFailed { error: String },
+    /// Failed to apply the firewall policy. The error code is the one returned by the OS, if any.
+    SetFirewallPolicyFailed {
+        error: String,
+        error_code: Option<i32>,
+    },
+    /// The addresses of the tunnel interface did not become usable in time, because duplicate
+    /// address detection never finished.
+    AddressTimeout,
+    /// The host went offline, or came back online.
+    ConnectivityChanged { is_offline: bool },
+    /// The estimated packet loss in the tunnel exceeded the configured threshold, in percent.
+    PacketLoss { percent: u8 },
+    /// The default route moved to another physical interface, such as from Wi-Fi to Ethernet.
+    DefaultInterfaceChanged { interface: String },
+    /// A reason added in a newer version, that this version does not know about.
+    #[serde(other)]
+    Unknown,
+}
+
+impl fmt::Display for TransitionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::TransitionReason::*;
+        match self {
+            SetDnsFailed { error } => write!(f, "Failed to set DNS: {}", error),
+            SetFirewallPolicyFailed {
+                error,
+                error_code: Some(code),
+            } => write!(
+                f,
+                "Failed to apply firewall policy: {} (code {})",
+                error, code
+            ),
+            SetFirewallPolicyFailed { error, .. } => {
+                write!(f, "Failed to apply firewall policy: {}", error)
+            }
+            AddressTimeout => write!(f, "Timed out waiting for the tunnel addresses"),
+            ConnectivityChanged { is_offline: true } => write!(f, "The device went offline"),
+            ConnectivityChanged { is_offline: false } => write!(f, "The device came back online"),
+            PacketLoss { percent } => {
+                write!(f, "{}% of the packets in the tunnel were lost", percent)
+            }
+            DefaultInterfaceChanged { interface } => {
+                write!(f, "The default route moved to {}", interface)
+            }
+            Unknown => write!(f, "Unknown reason"),
+        }
+    }
 }
 
 impl fmt::Display for ErrorStateCause {
@@ -199,8 +314,99 @@ impl fmt::Display for ErrorStateCause {
             VpnPermissionDenied => "The Android VPN permission was denied when creating the tunnel",
             #[cfg(target_os = "windows")]
             SplitTunnelError => "The split tunneling module reported an error",
+            #[cfg(not(target_os = "android"))]
+            CaptivePortalLogin => "Waiting for the captive portal login to complete",
+            Unknown => "Unknown error",
         };
 
         write!(f, "{}", description)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_error_state_round_trip() {
+        let states = vec![
+            ErrorState::new(
+                ErrorStateCause::AuthFailed(Some("expired".to_owned())),
+                None,
+            ),
+            ErrorState::new(
+                ErrorStateCause::SetFirewallPolicyError(FirewallPolicyError::Generic),
+                Some(FirewallPolicyError::Generic),
+            ),
+            ErrorState::new(
+                ErrorStateCause::TunnelParameterError(ParameterGenerationError::NoWireguardKey),
+                None,
+            ),
+            ErrorState::new(ErrorStateCause::IsOffline, None),
+        ];
+        for state in states {
+            let json = serde_json::to_string(&state).unwrap();
+            assert_eq!(serde_json::from_str::<ErrorState>(&json).unwrap(), state);
+        }
+    }
+
+    #[test]
+    fn test_error_state_from_older_version() {
+        let state: ErrorState =
+            serde_json::from_str(r#"{ "cause": { "reason": "is_offline" } }"#).unwrap();
+        assert_eq!(state, ErrorState::new(ErrorStateCause::IsOffline, None));
+    }
+
+    #[test]
+    fn test_error_state_from_newer_version() {
+        let state: ErrorState = serde_json::from_str(
+            r#"{
+                "cause": { "reason": "some_new_cause", "details": { "detail": 1 } },
+                "block_failure": { "reason": "some_new_policy_error" },
+                "some_new_field": true
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(state.cause(), &ErrorStateCause::Unknown);
+        assert_eq!(state.block_failure(), Some(&FirewallPolicyError::Unknown));
+
+        let cause: ErrorStateCause = serde_json::from_str(
+            r#"{ "reason": "tunnel_parameter_error", "details": "some_new_error" }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            cause,
+            ErrorStateCause::TunnelParameterError(ParameterGenerationError::Unknown)
+        );
+    }
+
+    #[test]
+    fn test_transition_reason_round_trip() {
+        let reasons = vec![
+            TransitionReason::SetDnsFailed {
+                error: "busy".to_owned(),
+            },
+            TransitionReason::SetFirewallPolicyFailed {
+                error: "denied".to_owned(),
+                error_code: Some(13),
+            },
+            TransitionReason::AddressTimeout,
+            TransitionReason::ConnectivityChanged { is_offline: true },
+            TransitionReason::PacketLoss { percent: 30 },
+            TransitionReason::DefaultInterfaceChanged {
+                interface: "en0".to_owned(),
+            },
+        ];
+        for reason in reasons {
+            let json = serde_json::to_string(&reason).unwrap();
+            assert_eq!(
+                serde_json::from_str::<TransitionReason>(&json).unwrap(),
+                reason
+            );
+        }
+
+        let reason: TransitionReason =
+            serde_json::from_str(r#"{ "reason": "some_new_reason", "details": 1 }"#).unwrap();
+        assert_eq!(reason, TransitionReason::Unknown);
+    }
+}
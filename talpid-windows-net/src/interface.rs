@@ -0,0 +1,117 @@
+use crate::{af_family_from_family, AddressFamily, Luid, Table};
+use std::{fmt, io, mem, ptr};
+use winapi::shared::{
+    netioapi::{
+        GetIpInterfaceEntry, GetIpInterfaceTable, SetIpInterfaceEntry, MIB_IPINTERFACE_ROW,
+        MIB_IPINTERFACE_TABLE,
+    },
+    nldef::RouterDiscoveryDisabled,
+    ntdef::FALSE,
+    winerror::{ERROR_NOT_FOUND, NO_ERROR},
+};
+
+/// The IPv4 or IPv6 interface of a network interface.
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+pub struct IpInterface(MIB_IPINTERFACE_ROW);
+
+impl IpInterface {
+    /// Returns the IP interface of the given family on a network interface.
+    pub fn get(family: AddressFamily, luid: Luid) -> io::Result<Self> {
+        // The row is filled in by `GetIpInterfaceEntry`, which only reads the family and LUID
+        let mut row: MIB_IPINTERFACE_ROW = unsafe { mem::zeroed() };
+        row.Family = family as u16;
+        row.InterfaceLuid = luid.into();
+
+        let status = unsafe { GetIpInterfaceEntry(&mut row) };
+        if status == NO_ERROR {
+            Ok(IpInterface(row))
+        } else {
+            Err(io::Error::from_raw_os_error(status as i32))
+        }
+    }
+
+    /// Returns whether a network interface has an IP interface of the given family.
+    pub fn exists(family: AddressFamily, luid: Luid) -> io::Result<bool> {
+        match Self::get(family, luid) {
+            Ok(_) => Ok(true),
+            Err(error) if error.raw_os_error() == Some(ERROR_NOT_FOUND as i32) => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Returns the IP interface table. If `family` is `None`, then the IP interfaces of all
+    /// families are returned.
+    pub fn table(family: Option<AddressFamily>) -> io::Result<Table<IpInterface>> {
+        let mut table: *mut MIB_IPINTERFACE_TABLE = ptr::null_mut();
+        let status = unsafe { GetIpInterfaceTable(af_family_from_family(family), &mut table) };
+        if status != NO_ERROR {
+            return Err(io::Error::from_raw_os_error(status as i32));
+        }
+        // `IpInterface` is a transparent wrapper around the row
+        Ok(unsafe {
+            Table::from_raw(
+                table as *mut _,
+                (*table).Table.as_ptr() as *const IpInterface,
+                (*table).NumEntries as usize,
+            )
+        })
+    }
+
+    /// Applies the changes that have been made to the properties of the IP interface.
+    pub fn apply(&self) -> io::Result<()> {
+        let status = unsafe { SetIpInterfaceEntry(&self.0 as *const _ as *mut _) };
+        if status == NO_ERROR {
+            Ok(())
+        } else {
+            Err(io::Error::from_raw_os_error(status as i32))
+        }
+    }
+
+    /// Returns the LUID of the network interface.
+    pub fn luid(&self) -> Luid {
+        Luid::from(self.0.InterfaceLuid)
+    }
+
+    /// Returns the address family of the IP interface.
+    pub fn family(&self) -> Option<AddressFamily> {
+        AddressFamily::try_from_af_family(self.0.Family).ok()
+    }
+
+    /// Returns whether the interface is connected to a network.
+    pub fn is_connected(&self) -> bool {
+        self.0.Connected != 0
+    }
+
+    /// Returns the MTU of the IP interface.
+    pub fn mtu(&self) -> u32 {
+        self.0.NlMtu
+    }
+
+    /// Sets the MTU of the IP interface.
+    pub fn set_mtu(&mut self, mtu: u32) {
+        self.0.NlMtu = mtu;
+    }
+
+    /// Disables duplicate address detection, DHCP and router discovery, which are of no use on
+    /// tunnel interfaces.
+    pub fn disable_autoconfiguration(&mut self) {
+        // A site prefix length other than zero is rejected by `SetIpInterfaceEntry` for IPv4
+        self.0.SitePrefixLength = 0;
+        self.0.RouterDiscoveryBehavior = RouterDiscoveryDisabled;
+        self.0.DadTransmits = 0;
+        self.0.ManagedAddressConfigurationSupported = FALSE;
+        self.0.OtherStatefulConfigurationSupported = FALSE;
+    }
+}
+
+impl fmt::Debug for IpInterface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IpInterface")
+            .field("luid", &self.luid())
+            .field("family", &self.family())
+            .field("connected", &self.is_connected())
+            .field("mtu", &self.mtu())
+            .finish()
+    }
+}
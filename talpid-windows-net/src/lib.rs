@@ -0,0 +1,81 @@
+#![cfg(windows)]
+//! Safe wrappers around the parts of the IP Helper API that are used to inspect and configure
+//! network interfaces on Windows.
+//!
+//! The wrappers own a copy of a row that Windows has filled in, so they cannot be created from
+//! uninitialized rows, and addresses are read through typed accessors instead of the unions of
+//! the underlying structs.
+
+#![deny(missing_docs)]
+#![deny(rust_2018_idioms)]
+
+use std::fmt;
+use winapi::shared::ws2def::{AF_INET, AF_INET6, AF_UNSPEC};
+
+mod addr;
+mod interface;
+mod luid;
+mod notify;
+mod table;
+mod unicast;
+
+pub use addr::{
+    in6addr_from_ipaddr, inaddr_from_ipaddr, inet_sockaddr_from_socketaddr, ipaddr_from_in6addr,
+    ipaddr_from_inaddr, try_socketaddr_from_inet_sockaddr,
+};
+pub use interface::IpInterface;
+pub use luid::Luid;
+pub use notify::{
+    notify_ip_interface_change, notify_route_change, notify_unicast_address_change,
+    IpNotifierHandle, NotificationType, RouteNotifierHandle, UnicastNotifierHandle,
+};
+pub use table::{IntoIter, Table};
+pub use unicast::{DadState, UnicastAddress};
+
+/// Result type for this crate.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors returned by the conversions in this crate.
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Unknown address family
+    #[error(display = "Unknown address family: {}", _0)]
+    UnknownAddressFamily(i32),
+}
+
+/// Address family. These correspond to the `AF_*` constants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressFamily {
+    /// IPv4 address family
+    Ipv4 = AF_INET as isize,
+    /// IPv6 address family
+    Ipv6 = AF_INET6 as isize,
+}
+
+impl fmt::Display for AddressFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            AddressFamily::Ipv4 => write!(f, "IPv4 (AF_INET)"),
+            AddressFamily::Ipv6 => write!(f, "IPv6 (AF_INET6)"),
+        }
+    }
+}
+
+impl AddressFamily {
+    /// Convert one of the `AF_*` constants to an [`AddressFamily`].
+    pub fn try_from_af_family(family: u16) -> Result<AddressFamily> {
+        match family as i32 {
+            AF_INET => Ok(AddressFamily::Ipv4),
+            AF_INET6 => Ok(AddressFamily::Ipv6),
+            family => Err(Error::UnknownAddressFamily(family)),
+        }
+    }
+}
+
+/// Returns the `AF_*` constant for `family`, or `AF_UNSPEC` if it is `None`.
+fn af_family_from_family(family: Option<AddressFamily>) -> u16 {
+    family
+        .map(|family| family as u16)
+        .unwrap_or(AF_UNSPEC as u16)
+}
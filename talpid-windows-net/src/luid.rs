@@ -0,0 +1,121 @@
+use std::{
+    ffi::{OsStr, OsString},
+    fmt,
+    hash::{Hash, Hasher},
+    io,
+    os::windows::ffi::{OsStrExt, OsStringExt},
+};
+use winapi::shared::{
+    ifdef::NET_LUID,
+    netioapi::{ConvertInterfaceAliasToLuid, ConvertInterfaceLuidToAlias},
+    winerror::NO_ERROR,
+};
+
+// Interface types, from ipifcons.h
+const IF_TYPE_PPP: u16 = 23;
+const IF_TYPE_SOFTWARE_LOOPBACK: u16 = 24;
+const IF_TYPE_PROP_VIRTUAL: u16 = 53;
+const IF_TYPE_TUNNEL: u16 = 131;
+
+/// Locally unique identifier of a network interface. Unlike the interface index, it does not
+/// change when the interface is disabled and enabled again.
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+pub struct Luid(NET_LUID);
+
+impl Luid {
+    /// Creates a LUID from its numeric value.
+    pub fn from_value(value: u64) -> Self {
+        Luid(NET_LUID { Value: value })
+    }
+
+    /// Returns the numeric value of the LUID.
+    pub fn value(&self) -> u64 {
+        self.0.Value
+    }
+
+    /// Returns the LUID of an interface given its alias.
+    pub fn from_alias<T: AsRef<OsStr>>(alias: T) -> io::Result<Self> {
+        let alias_wide: Vec<u16> = alias
+            .as_ref()
+            .encode_wide()
+            .chain(std::iter::once(0u16))
+            .collect();
+        let mut luid = NET_LUID { Value: 0 };
+        let status = unsafe { ConvertInterfaceAliasToLuid(alias_wide.as_ptr(), &mut luid) };
+        if status != NO_ERROR {
+            return Err(io::Error::from_raw_os_error(status as i32));
+        }
+        Ok(Luid(luid))
+    }
+
+    /// Returns the alias of the interface.
+    pub fn alias(&self) -> io::Result<OsString> {
+        // IF_MAX_STRING_SIZE + 1
+        let mut alias = [0u16; 257];
+        let status =
+            unsafe { ConvertInterfaceLuidToAlias(&self.0, alias.as_mut_ptr(), alias.len()) };
+        if status != NO_ERROR {
+            return Err(io::Error::from_raw_os_error(status as i32));
+        }
+        let length = alias.iter().position(|&c| c == 0).unwrap_or(alias.len());
+        Ok(OsString::from_wide(&alias[..length]))
+    }
+
+    /// Returns the `IF_TYPE_*` constant of the interface, which is stored in bits 48 to 63.
+    pub fn interface_type(&self) -> u16 {
+        (self.0.Value >> 48) as u16
+    }
+
+    /// Returns whether the interface is a loopback, PPP or tunnel interface, which includes the
+    /// tunnel adapters.
+    pub fn is_virtual(&self) -> bool {
+        matches!(
+            self.interface_type(),
+            IF_TYPE_PPP | IF_TYPE_SOFTWARE_LOOPBACK | IF_TYPE_PROP_VIRTUAL | IF_TYPE_TUNNEL
+        )
+    }
+
+    /// Returns a reference to the underlying `NET_LUID`, for use with the Windows API.
+    pub fn as_raw(&self) -> &NET_LUID {
+        &self.0
+    }
+}
+
+impl From<NET_LUID> for Luid {
+    fn from(luid: NET_LUID) -> Self {
+        Luid(luid)
+    }
+}
+
+impl From<Luid> for NET_LUID {
+    fn from(luid: Luid) -> Self {
+        luid.0
+    }
+}
+
+impl PartialEq for Luid {
+    fn eq(&self, other: &Self) -> bool {
+        self.value() == other.value()
+    }
+}
+
+impl Eq for Luid {}
+
+impl Hash for Luid {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.value().hash(state)
+    }
+}
+
+impl fmt::Debug for Luid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Luid").field(&self.value()).finish()
+    }
+}
+
+impl fmt::Display for Luid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.value())
+    }
+}
@@ -0,0 +1,227 @@
+use crate::{af_family_from_family, AddressFamily, IpInterface, UnicastAddress};
+use std::{io, os::windows::io::RawHandle, sync::Mutex};
+use winapi::{
+    ctypes::c_void,
+    shared::{
+        netioapi::{
+            CancelMibChangeNotify2, MibAddInstance, MibDeleteInstance, MibInitialNotification,
+            NotifyIpInterfaceChange, NotifyRouteChange2, NotifyUnicastIpAddressChange,
+            MIB_IPFORWARD_ROW2, MIB_IPINTERFACE_ROW, MIB_NOTIFICATION_TYPE,
+            MIB_UNICASTIPADDRESS_ROW,
+        },
+        ntdef::FALSE,
+        winerror::NO_ERROR,
+    },
+};
+
+/// The kind of change that a notification is sent for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationType {
+    /// A parameter was changed.
+    ParameterChange,
+    /// An entry was added.
+    Add,
+    /// An entry was deleted.
+    Delete,
+    /// The notification that is sent right after registering, if requested.
+    Initial,
+}
+
+#[allow(non_upper_case_globals)]
+impl From<MIB_NOTIFICATION_TYPE> for NotificationType {
+    fn from(notification_type: MIB_NOTIFICATION_TYPE) -> Self {
+        match notification_type {
+            MibAddInstance => NotificationType::Add,
+            MibDeleteInstance => NotificationType::Delete,
+            MibInitialNotification => NotificationType::Initial,
+            _ => NotificationType::ParameterChange,
+        }
+    }
+}
+
+/// Context for [`notify_ip_interface_change`]. When it is dropped,
+/// the callback is unregistered.
+pub struct IpNotifierHandle<'a> {
+    callback: Mutex<Box<dyn FnMut(&IpInterface, NotificationType) + Send + 'a>>,
+    handle: RawHandle,
+}
+
+unsafe impl Send for IpNotifierHandle<'_> {}
+
+impl<'a> Drop for IpNotifierHandle<'a> {
+    fn drop(&mut self) {
+        unsafe { CancelMibChangeNotify2(self.handle as *mut _) };
+    }
+}
+
+unsafe extern "system" fn inner_callback(
+    context: *mut c_void,
+    row: *mut MIB_IPINTERFACE_ROW,
+    notify_type: MIB_NOTIFICATION_TYPE,
+) {
+    // The row is null for the initial notification, which is not requested
+    if row.is_null() {
+        return;
+    }
+    let context = &mut *(context as *mut IpNotifierHandle<'_>);
+    // `IpInterface` is a transparent wrapper around the row
+    let interface = &*(row as *const IpInterface);
+    let mut callback = context
+        .callback
+        .lock()
+        .expect("NotifyIpInterfaceChange mutex poisoned");
+    callback(interface, NotificationType::from(notify_type));
+}
+
+/// Registers a callback function that is invoked when an interface is added, removed,
+/// or changed.
+pub fn notify_ip_interface_change<'a, T: FnMut(&IpInterface, NotificationType) + Send + 'a>(
+    callback: T,
+    family: Option<AddressFamily>,
+) -> io::Result<Box<IpNotifierHandle<'a>>> {
+    let mut context = Box::new(IpNotifierHandle {
+        callback: Mutex::new(Box::new(callback)),
+        handle: std::ptr::null_mut(),
+    });
+
+    let status = unsafe {
+        NotifyIpInterfaceChange(
+            af_family_from_family(family),
+            Some(inner_callback),
+            &mut *context as *mut _ as *mut _,
+            FALSE,
+            (&mut context.handle) as *mut _,
+        )
+    };
+
+    if status == NO_ERROR {
+        Ok(context)
+    } else {
+        Err(io::Error::from_raw_os_error(status as i32))
+    }
+}
+
+/// Context for [`notify_route_change`]. When it is dropped,
+/// the callback is unregistered.
+pub struct RouteNotifierHandle<'a> {
+    callback: Mutex<Box<dyn FnMut(&MIB_IPFORWARD_ROW2, NotificationType) + Send + 'a>>,
+    handle: RawHandle,
+}
+
+unsafe impl Send for RouteNotifierHandle<'_> {}
+
+impl<'a> Drop for RouteNotifierHandle<'a> {
+    fn drop(&mut self) {
+        unsafe { CancelMibChangeNotify2(self.handle as *mut _) };
+    }
+}
+
+unsafe extern "system" fn inner_route_callback(
+    context: *mut c_void,
+    row: *mut MIB_IPFORWARD_ROW2,
+    notify_type: MIB_NOTIFICATION_TYPE,
+) {
+    // The row is null for the initial notification, which is not requested
+    if row.is_null() {
+        return;
+    }
+    let context = &mut *(context as *mut RouteNotifierHandle<'_>);
+    let mut callback = context
+        .callback
+        .lock()
+        .expect("NotifyRouteChange2 mutex poisoned");
+    callback(&*row, NotificationType::from(notify_type));
+}
+
+/// Registers a callback function that is invoked when a route is added, removed,
+/// or changed.
+pub fn notify_route_change<'a, T: FnMut(&MIB_IPFORWARD_ROW2, NotificationType) + Send + 'a>(
+    callback: T,
+    family: Option<AddressFamily>,
+) -> io::Result<Box<RouteNotifierHandle<'a>>> {
+    let mut context = Box::new(RouteNotifierHandle {
+        callback: Mutex::new(Box::new(callback)),
+        handle: std::ptr::null_mut(),
+    });
+
+    let status = unsafe {
+        NotifyRouteChange2(
+            af_family_from_family(family),
+            Some(inner_route_callback),
+            &mut *context as *mut _ as *mut _,
+            FALSE,
+            (&mut context.handle) as *mut _,
+        )
+    };
+
+    if status == NO_ERROR {
+        Ok(context)
+    } else {
+        Err(io::Error::from_raw_os_error(status as i32))
+    }
+}
+
+/// Context for [`notify_unicast_address_change`]. When it is dropped,
+/// the callback is unregistered.
+pub struct UnicastNotifierHandle<'a> {
+    callback: Mutex<Box<dyn FnMut(&UnicastAddress, NotificationType) + Send + 'a>>,
+    handle: RawHandle,
+}
+
+unsafe impl Send for UnicastNotifierHandle<'_> {}
+
+impl<'a> Drop for UnicastNotifierHandle<'a> {
+    fn drop(&mut self) {
+        unsafe { CancelMibChangeNotify2(self.handle as *mut _) };
+    }
+}
+
+unsafe extern "system" fn inner_unicast_callback(
+    context: *mut c_void,
+    row: *mut MIB_UNICASTIPADDRESS_ROW,
+    notify_type: MIB_NOTIFICATION_TYPE,
+) {
+    // The row is null for the initial notification, which is not requested
+    if row.is_null() {
+        return;
+    }
+    let context = &mut *(context as *mut UnicastNotifierHandle<'_>);
+    // `UnicastAddress` is a transparent wrapper around the row
+    let address = &*(row as *const UnicastAddress);
+    let mut callback = context
+        .callback
+        .lock()
+        .expect("NotifyUnicastIpAddressChange mutex poisoned");
+    callback(address, NotificationType::from(notify_type));
+}
+
+/// Registers a callback function that is invoked when a unicast IP address is added, removed,
+/// or changed.
+pub fn notify_unicast_address_change<
+    'a,
+    T: FnMut(&UnicastAddress, NotificationType) + Send + 'a,
+>(
+    callback: T,
+    family: Option<AddressFamily>,
+) -> io::Result<Box<UnicastNotifierHandle<'a>>> {
+    let mut context = Box::new(UnicastNotifierHandle {
+        callback: Mutex::new(Box::new(callback)),
+        handle: std::ptr::null_mut(),
+    });
+
+    let status = unsafe {
+        NotifyUnicastIpAddressChange(
+            af_family_from_family(family),
+            Some(inner_unicast_callback),
+            &mut *context as *mut _ as *mut _,
+            FALSE,
+            (&mut context.handle) as *mut _,
+        )
+    };
+
+    if status == NO_ERROR {
+        Ok(context)
+    } else {
+        Err(io::Error::from_raw_os_error(status as i32))
+    }
+}
@@ -0,0 +1,92 @@
+use std::{fmt, ops::Deref, slice};
+use winapi::{ctypes::c_void, shared::netioapi::FreeMibTable};
+
+/// A table returned by one of the `Get*Table` functions of the Windows API. The table is freed
+/// when it is dropped.
+pub struct Table<T> {
+    table: *mut c_void,
+    rows: *const T,
+    len: usize,
+}
+
+unsafe impl<T: Send> Send for Table<T> {}
+
+impl<T> Table<T> {
+    /// Takes ownership of a table returned by a `Get*Table` function.
+    ///
+    /// # Safety
+    ///
+    /// `table` must be a table that has been returned by a `Get*Table` function and not yet been
+    /// freed. `rows` must point to its first row, it must contain `len` rows, and `T` must have
+    /// the same layout as a row.
+    pub(crate) unsafe fn from_raw(table: *mut c_void, rows: *const T, len: usize) -> Self {
+        Table { table, rows, len }
+    }
+
+    /// Returns an iterator over the rows of the table.
+    pub fn iter(&self) -> slice::Iter<'_, T> {
+        self.deref().iter()
+    }
+}
+
+impl<T> Deref for Table<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.rows, self.len) }
+    }
+}
+
+impl<T> Drop for Table<T> {
+    fn drop(&mut self) {
+        unsafe { FreeMibTable(self.table) };
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Table<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Table<T> {
+    type Item = &'a T;
+    type IntoIter = slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<T: Copy> IntoIterator for Table<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            table: self,
+            index: 0,
+        }
+    }
+}
+
+/// An iterator that copies the rows out of a [`Table`], and frees the table when it is dropped.
+pub struct IntoIter<T> {
+    table: Table<T>,
+    index: usize,
+}
+
+impl<T: Copy> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let row = self.table.get(self.index).copied();
+        self.index += 1;
+        row
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.table.len().saturating_sub(self.index);
+        (remaining, Some(remaining))
+    }
+}
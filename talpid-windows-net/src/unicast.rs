@@ -0,0 +1,130 @@
+use crate::{af_family_from_family, try_socketaddr_from_inet_sockaddr, AddressFamily, Luid, Table};
+use std::{fmt, io, net::IpAddr, ptr};
+use winapi::shared::{
+    netioapi::{
+        DeleteUnicastIpAddressEntry, GetUnicastIpAddressEntry, GetUnicastIpAddressTable,
+        MIB_UNICASTIPADDRESS_ROW, MIB_UNICASTIPADDRESS_TABLE,
+    },
+    nldef::{
+        IpDadStateDeprecated, IpDadStateDuplicate, IpDadStateInvalid, IpDadStatePreferred,
+        IpDadStateTentative, NL_DAD_STATE,
+    },
+    winerror::NO_ERROR,
+};
+
+/// State of duplicate address detection (DAD) for a unicast address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DadState {
+    /// The address is not valid.
+    Invalid,
+    /// DAD has not finished, so the address cannot be used yet.
+    Tentative,
+    /// Another host on the link uses the same address.
+    Duplicate,
+    /// The address is valid but should not be used for new connections.
+    Deprecated,
+    /// The address is valid and can be used.
+    Preferred,
+    /// A state that is not known to this version.
+    Unknown(u32),
+}
+
+#[allow(non_upper_case_globals)]
+impl From<NL_DAD_STATE> for DadState {
+    fn from(state: NL_DAD_STATE) -> Self {
+        match state {
+            IpDadStateInvalid => DadState::Invalid,
+            IpDadStateTentative => DadState::Tentative,
+            IpDadStateDuplicate => DadState::Duplicate,
+            IpDadStateDeprecated => DadState::Deprecated,
+            IpDadStatePreferred => DadState::Preferred,
+            other => DadState::Unknown(other),
+        }
+    }
+}
+
+/// A unicast IP address of a network interface.
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+pub struct UnicastAddress(MIB_UNICASTIPADDRESS_ROW);
+
+impl UnicastAddress {
+    /// Returns the unicast IP address table. If `family` is `None`, then addresses for all
+    /// families are returned.
+    pub fn table(family: Option<AddressFamily>) -> io::Result<Table<UnicastAddress>> {
+        let mut table: *mut MIB_UNICASTIPADDRESS_TABLE = ptr::null_mut();
+        let status = unsafe { GetUnicastIpAddressTable(af_family_from_family(family), &mut table) };
+        if status != NO_ERROR {
+            return Err(io::Error::from_raw_os_error(status as i32));
+        }
+        // `UnicastAddress` is a transparent wrapper around the row
+        Ok(unsafe {
+            Table::from_raw(
+                table as *mut _,
+                (*table).Table.as_ptr() as *const UnicastAddress,
+                (*table).NumEntries as usize,
+            )
+        })
+    }
+
+    /// Returns the addresses of the interface with the given LUID.
+    pub fn for_interface(luid: Luid) -> io::Result<Vec<UnicastAddress>> {
+        Ok(Self::table(None)?
+            .into_iter()
+            .filter(|address| address.luid() == luid)
+            .collect())
+    }
+
+    /// Reads the current state of the address, such as its DAD state, again.
+    pub fn refresh(&mut self) -> io::Result<()> {
+        let status = unsafe { GetUnicastIpAddressEntry(&mut self.0) };
+        if status == NO_ERROR {
+            Ok(())
+        } else {
+            Err(io::Error::from_raw_os_error(status as i32))
+        }
+    }
+
+    /// Removes the address from its interface.
+    pub fn delete(&self) -> io::Result<()> {
+        let status = unsafe { DeleteUnicastIpAddressEntry(&self.0) };
+        if status == NO_ERROR {
+            Ok(())
+        } else {
+            Err(io::Error::from_raw_os_error(status as i32))
+        }
+    }
+
+    /// Returns the LUID of the interface that the address belongs to.
+    pub fn luid(&self) -> Luid {
+        Luid::from(self.0.InterfaceLuid)
+    }
+
+    /// Returns the address, or `None` if it is of an unknown family.
+    pub fn address(&self) -> Option<IpAddr> {
+        try_socketaddr_from_inet_sockaddr(self.0.Address)
+            .ok()
+            .map(|address| address.ip())
+    }
+
+    /// Returns the prefix length of the on-link network of the address.
+    pub fn prefix_length(&self) -> u8 {
+        self.0.OnLinkPrefixLength
+    }
+
+    /// Returns the state of duplicate address detection for the address.
+    pub fn dad_state(&self) -> DadState {
+        DadState::from(self.0.DadState)
+    }
+}
+
+impl fmt::Debug for UnicastAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UnicastAddress")
+            .field("luid", &self.luid())
+            .field("address", &self.address())
+            .field("prefix_length", &self.prefix_length())
+            .field("dad_state", &self.dad_state())
+            .finish()
+    }
+}
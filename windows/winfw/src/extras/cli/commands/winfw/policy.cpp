@@ -13,10 +13,11 @@ namespace detail
 
 WinFwSettings CreateSettings(const std::wstring &dhcp, const std::wstring &lan)
 {
-	WinFwSettings s;
+	WinFwSettings s{};
 
 	s.permitDhcp = (0 == _wcsicmp(dhcp.c_str(), L"yes"));
 	s.permitLan = (0 == _wcsicmp(lan.c_str(), L"yes"));
+	s.permitTunnelIpv6 = true;
 
 	return s;
 }
@@ -168,7 +169,7 @@ void Policy::processBlocked(const KeyValuePairs &arguments)
 		GetArgumentValue(arguments, L"lan")
 	);
 
-	auto success = WINFW_POLICY_STATUS_SUCCESS == WinFw_ApplyPolicyBlocked(&settings, nullptr);
+	auto success = WINFW_POLICY_STATUS_SUCCESS == WinFw_ApplyPolicyBlocked(&settings, nullptr, nullptr);
 
 	m_messageSink((success
 		? L"Successfully applied policy."
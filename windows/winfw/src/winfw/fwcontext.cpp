@@ -15,6 +15,9 @@
 #include "rules/baseline/permitvpntunnelservice.h"
 #include "rules/baseline/permitdns.h"
 #include "rules/baseline/permitendpoint.h"
+#include "rules/baseline/permitfirewallexceptions.h"
+#include "rules/baseline/permitsystemservices.h"
+#include "rules/baseline/permitvirtualnetworks.h"
 #include "rules/dns/blockall.h"
 #include "rules/dns/permittunnel.h"
 #include "rules/dns/permitnontunnel.h"
@@ -23,6 +26,7 @@
 #include <libwfp/filterengine.h>
 #include <libcommon/error.h>
 #include <functional>
+#include <vector>
 #include <utility>
 
 using namespace rules;
@@ -30,6 +34,30 @@ using namespace rules;
 namespace
 {
 
+//
+// Splits networks passed through the interface by address family.
+//
+std::pair<std::vector<wfp::IpNetwork>, std::vector<wfp::IpNetwork>> SplitNetworks
+(
+	const WinFwNetwork *networks,
+	size_t numNetworks
+)
+{
+	std::vector<wfp::IpNetwork> networksIpv4;
+	std::vector<wfp::IpNetwork> networksIpv6;
+
+	for (size_t i = 0; i < numNetworks; ++i)
+	{
+		const auto &network = networks[i];
+		const wfp::IpAddress address(network.ip);
+
+		auto &familyNetworks = (wfp::IpAddress::Type::Ipv4 == address.type() ? networksIpv4 : networksIpv6);
+		familyNetworks.emplace_back(address, network.prefix);
+	}
+
+	return std::make_pair(std::move(networksIpv4), std::move(networksIpv6));
+}
+
 //
 // Since the PermitLan rule doesn't specifically address DNS, it will allow DNS requests targetting
 // a local resolver to leave the machine. From the local resolver the request will either be
@@ -59,11 +87,57 @@ void AppendSettingsRules
 
 	if (settings.permitLan)
 	{
-		ruleset.emplace_back(std::make_unique<baseline::PermitLan>());
-		ruleset.emplace_back(std::make_unique<baseline::PermitLanService>());
+		if (0 != settings.numLanNetworks && nullptr == settings.lanNetworks)
+		{
+			THROW_ERROR("Invalid argument: lanNetworks");
+		}
+
+		const auto [networksIpv4, networksIpv6] = SplitNetworks(settings.lanNetworks, settings.numLanNetworks);
+
+		ruleset.emplace_back(std::make_unique<baseline::PermitLan>(networksIpv4, networksIpv6));
+		ruleset.emplace_back(std::make_unique<baseline::PermitLanService>(networksIpv4, networksIpv6));
 		ruleset.emplace_back(baseline::PermitDhcpServer::WithExtent(baseline::PermitDhcpServer::Extent::IPv4Only));
 	}
 
+	if (0 != settings.numVirtualNetworks)
+	{
+		if (nullptr == settings.virtualNetworks)
+		{
+			THROW_ERROR("Invalid argument: virtualNetworks");
+		}
+
+		const auto [networksIpv4, networksIpv6] = SplitNetworks(settings.virtualNetworks, settings.numVirtualNetworks);
+
+		ruleset.emplace_back(std::make_unique<baseline::PermitVirtualNetworks>(networksIpv4, networksIpv6));
+	}
+
+	if (0 != settings.numFirewallExceptions)
+	{
+		if (nullptr == settings.firewallExceptions)
+		{
+			THROW_ERROR("Invalid argument: firewallExceptions");
+		}
+
+		std::vector<baseline::PermitFirewallExceptions::Exception> exceptions;
+
+		for (size_t i = 0; i < settings.numFirewallExceptions; ++i)
+		{
+			const auto &exception = settings.firewallExceptions[i];
+
+			exceptions.push_back(baseline::PermitFirewallExceptions::Exception
+			{
+				wfp::IpAddress(exception.ip),
+				exception.prefix,
+				exception.port,
+				exception.anyProtocol
+					? std::nullopt
+					: std::make_optional(exception.protocol)
+			});
+		}
+
+		ruleset.emplace_back(std::make_unique<baseline::PermitFirewallExceptions>(exceptions));
+	}
+
 	//
 	// DNS management
 	//
@@ -195,11 +269,13 @@ bool FwContext::applyPolicyConnecting
 	if (tunnelInterfaceAlias.has_value())
 	{
 		ruleset.emplace_back(std::make_unique<baseline::PermitVpnTunnel>(
-			*tunnelInterfaceAlias
+			*tunnelInterfaceAlias,
+			settings.permitTunnelIpv6
 		));
 
 		ruleset.emplace_back(std::make_unique<baseline::PermitVpnTunnelService>(
-			*tunnelInterfaceAlias
+			*tunnelInterfaceAlias,
+			settings.permitTunnelIpv6
 		));
 	}
 
@@ -243,11 +319,13 @@ bool FwContext::applyPolicyConnected
 	}
 
 	ruleset.emplace_back(std::make_unique<baseline::PermitVpnTunnel>(
-		tunnelInterfaceAlias
+		tunnelInterfaceAlias,
+		settings.permitTunnelIpv6
 	));
 
 	ruleset.emplace_back(std::make_unique<baseline::PermitVpnTunnelService>(
-		tunnelInterfaceAlias
+		tunnelInterfaceAlias,
+		settings.permitTunnelIpv6
 	));
 
 	const auto status = applyRuleset(ruleset);
@@ -260,9 +338,14 @@ bool FwContext::applyPolicyConnected
 	return status;
 }
 
-bool FwContext::applyPolicyBlocked(const WinFwSettings &settings, const std::optional<WinFwEndpoint> &allowedEndpoint)
+bool FwContext::applyPolicyBlocked
+(
+	const WinFwSettings &settings,
+	const std::optional<WinFwEndpoint> &allowedEndpoint,
+	const SystemServiceExemptions &systemServices
+)
 {
-	const auto status = applyRuleset(composePolicyBlocked(settings, allowedEndpoint));
+	const auto status = applyRuleset(composePolicyBlocked(settings, allowedEndpoint, systemServices));
 
 	if (status)
 	{
@@ -287,12 +370,58 @@ bool FwContext::reset()
 	return status;
 }
 
+bool FwContext::verifyObjects()
+{
+	return m_sessionController->verify();
+}
+
+bool FwContext::repairObjects()
+{
+	uint32_t checkpoint = 0;
+
+	const auto status = m_sessionController->executeTransaction([&](SessionController &controller, wfp::FilterEngine &engine)
+	{
+		//
+		// Some of the recorded objects no longer exist, so they cannot be purged one by one.
+		// Any remaining objects are removed when the base configuration is applied.
+		//
+		controller.discard();
+
+		if (false == applyCommonBaseConfiguration(controller, engine))
+		{
+			return false;
+		}
+
+		checkpoint = controller.peekCheckpoint();
+
+		return true;
+	});
+
+	if (status)
+	{
+		m_baseline = checkpoint;
+		m_activePolicy = Policy::None;
+	}
+
+	return status;
+}
+
+std::vector<std::wstring> FwContext::describeFilters()
+{
+	return m_sessionController->describeFilters();
+}
+
 FwContext::Policy FwContext::activePolicy() const
 {
 	return m_activePolicy;
 }
 
-FwContext::Ruleset FwContext::composePolicyBlocked(const WinFwSettings &settings, const std::optional<WinFwEndpoint> &allowedEndpoint)
+FwContext::Ruleset FwContext::composePolicyBlocked
+(
+	const WinFwSettings &settings,
+	const std::optional<WinFwEndpoint> &allowedEndpoint,
+	const SystemServiceExemptions &systemServices
+)
 {
 	Ruleset ruleset;
 
@@ -304,6 +433,17 @@ FwContext::Ruleset FwContext::composePolicyBlocked(const WinFwSettings &settings
 		AppendAllowedEndpointRules(ruleset, allowedEndpoint.value());
 	}
 
+	if (systemServices.permitNtp
+		|| false == systemServices.captivePortalHosts.empty()
+		|| systemServices.permitPortalLogin)
+	{
+		ruleset.emplace_back(std::make_unique<baseline::PermitSystemServices>(
+			systemServices.permitNtp,
+			systemServices.captivePortalHosts,
+			systemServices.permitPortalLogin
+		));
+	}
+
 	return ruleset;
 }
 
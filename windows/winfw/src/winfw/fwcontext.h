@@ -10,6 +10,13 @@
 #include <string>
 #include <optional>
 
+struct SystemServiceExemptions
+{
+	bool permitNtp = false;
+	std::vector<wfp::IpAddress> captivePortalHosts;
+	bool permitPortalLogin = false;
+};
+
 class FwContext
 {
 public:
@@ -45,11 +52,28 @@ public:
 
 	bool applyPolicyBlocked(
 		const WinFwSettings &settings,
-		const std::optional<WinFwEndpoint> &allowedEndpoint
+		const std::optional<WinFwEndpoint> &allowedEndpoint,
+		const SystemServiceExemptions &systemServices
 	);
 
 	bool reset();
 
+	//
+	// Check whether all objects that have been registered with WFP are still in place.
+	//
+	bool verifyObjects();
+
+	//
+	// Remove what is left of the objects and reinstall the structural objects.
+	// The active policy is cleared and has to be applied again.
+	//
+	bool repairObjects();
+
+	//
+	// Describe the filters of the base configuration and the active policy.
+	//
+	std::vector<std::wstring> describeFilters();
+
 	enum class Policy
 	{
 		Connecting,
@@ -67,7 +91,11 @@ private:
 	FwContext(const FwContext &) = delete;
 	FwContext &operator=(const FwContext &) = delete;
 
-	Ruleset composePolicyBlocked(const WinFwSettings &settings, const std::optional<WinFwEndpoint> &allowedEndpoint);
+	Ruleset composePolicyBlocked(
+		const WinFwSettings &settings,
+		const std::optional<WinFwEndpoint> &allowedEndpoint,
+		const SystemServiceExemptions &systemServices = {}
+	);
 
 	bool applyBaseConfiguration();
 	bool applyBlockedBaseConfiguration(const WinFwSettings &settings, const std::optional<WinFwEndpoint> &allowedEndpoint, uint32_t &checkpoint);
@@ -1,5 +1,6 @@
 #include "stdafx.h"
 #include "mullvadguids.h"
+#include <libcommon/error.h>
 #include <algorithm>
 #include <iterator>
 
@@ -130,6 +131,23 @@ MullvadGuids::DetailedIdentityRegistry MullvadGuids::DetailedRegistry(IdentityQu
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitDhcpServer_Outbound_Response_Ipv4()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitVpnRelay()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitEndpoint()));
+
+	for (size_t i = 0; i < MaxFirewallExceptions; ++i)
+	{
+		registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitFirewallException_Outbound(i)));
+		registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitFirewallException_Inbound(i)));
+	}
+
+	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitSystemServices_Ntp_Outbound_Ipv4()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitSystemServices_Ntp_Outbound_Ipv6()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitSystemServices_CaptivePortal_Outbound_Ipv4()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitSystemServices_CaptivePortal_Outbound_Ipv6()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitSystemServices_PortalLogin_Outbound_Ipv4()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitSystemServices_PortalLogin_Outbound_Ipv6()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitVirtualNetworks_Outbound_Ipv4()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitVirtualNetworks_Inbound_Ipv4()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitVirtualNetworks_Outbound_Ipv6()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitVirtualNetworks_Inbound_Ipv6()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitVpnTunnel_Outbound_Ipv4()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitVpnTunnel_Outbound_Ipv6()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitVpnTunnelService_Ipv4()));
@@ -145,6 +163,8 @@ MullvadGuids::DetailedIdentityRegistry MullvadGuids::DetailedRegistry(IdentityQu
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Dns_PermitNonTunnel_Outbound_Ipv6()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Dns_PermitTunnel_Outbound_Ipv4()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Dns_PermitTunnel_Outbound_Ipv6()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Dns_PermitSystemServices_PortalLogin_Outbound_Ipv4()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Dns_PermitSystemServices_PortalLogin_Outbound_Ipv6()));
 
 	if (IdentityQualifier::IncludePersistent == (qualifier & IdentityQualifier::IncludePersistent))
 	{
@@ -656,6 +676,188 @@ const GUID &MullvadGuids::Filter_Baseline_PermitEndpoint()
 	return g;
 }
 
+//static
+GUID MullvadGuids::Filter_Baseline_PermitFirewallException_Outbound(size_t index)
+{
+	if (index >= MaxFirewallExceptions)
+	{
+		THROW_ERROR("Invalid firewall exception index");
+	}
+
+	GUID g =
+	{
+		0xba6eea4a,
+		0x4bb5,
+		0x4656,
+		{ 0x9b, 0x90, 0xa8, 0x5f, 0x27, 0x44, 0x13, 0x00 }
+	};
+
+	g.Data4[7] = static_cast<unsigned char>(index);
+
+	return g;
+}
+
+//static
+GUID MullvadGuids::Filter_Baseline_PermitFirewallException_Inbound(size_t index)
+{
+	if (index >= MaxFirewallExceptions)
+	{
+		THROW_ERROR("Invalid firewall exception index");
+	}
+
+	GUID g =
+	{
+		0x5320bbe8,
+		0x6802,
+		0x46b4,
+		{ 0x98, 0xb3, 0x06, 0x00, 0x12, 0xf0, 0xd3, 0x00 }
+	};
+
+	g.Data4[7] = static_cast<unsigned char>(index);
+
+	return g;
+}
+
+//static
+const GUID &MullvadGuids::Filter_Baseline_PermitSystemServices_Ntp_Outbound_Ipv4()
+{
+	static const GUID g =
+	{
+		0xbec754e6,
+		0x384e,
+		0x42ad,
+		{ 0x8e, 0x87, 0x14, 0xb0, 0xb0, 0x34, 0x72, 0x9e }
+	};
+
+	return g;
+}
+
+//static
+const GUID &MullvadGuids::Filter_Baseline_PermitSystemServices_Ntp_Outbound_Ipv6()
+{
+	static const GUID g =
+	{
+		0x582844ac,
+		0x5a43,
+		0x47a2,
+		{ 0x89, 0x45, 0xee, 0xfe, 0x3d, 0x5d, 0x8e, 0x15 }
+	};
+
+	return g;
+}
+
+//static
+const GUID &MullvadGuids::Filter_Baseline_PermitSystemServices_CaptivePortal_Outbound_Ipv4()
+{
+	static const GUID g =
+	{
+		0x3fc508b7,
+		0x761f,
+		0x461c,
+		{ 0x9c, 0xbd, 0xf0, 0x9b, 0xf6, 0x52, 0xd9, 0xab }
+	};
+
+	return g;
+}
+
+//static
+const GUID &MullvadGuids::Filter_Baseline_PermitSystemServices_CaptivePortal_Outbound_Ipv6()
+{
+	static const GUID g =
+	{
+		0xbb88417d,
+		0x3ee9,
+		0x49bf,
+		{ 0x91, 0xe4, 0x55, 0x0b, 0xc3, 0x61, 0x7e, 0x74 }
+	};
+
+	return g;
+}
+
+//static
+const GUID &MullvadGuids::Filter_Baseline_PermitSystemServices_PortalLogin_Outbound_Ipv4()
+{
+	static const GUID g =
+	{
+		0xe5e658b4,
+		0xd3b1,
+		0x4ae6,
+		{ 0x87, 0x40, 0xbb, 0x59, 0xb2, 0x0b, 0xd0, 0x60 }
+	};
+
+	return g;
+}
+
+//static
+const GUID &MullvadGuids::Filter_Baseline_PermitSystemServices_PortalLogin_Outbound_Ipv6()
+{
+	static const GUID g =
+	{
+		0x1f4bd27b,
+		0x042e,
+		0x4a30,
+		{ 0xa9, 0xe6, 0x8f, 0x91, 0xeb, 0xb7, 0x78, 0x25 }
+	};
+
+	return g;
+}
+
+//static
+const GUID &MullvadGuids::Filter_Baseline_PermitVirtualNetworks_Outbound_Ipv4()
+{
+	static const GUID g =
+	{
+		0xa829b360,
+		0x013b,
+		0x49cb,
+		{ 0x98, 0x7d, 0x9a, 0x21, 0xf1, 0x44, 0xcc, 0xeb }
+	};
+
+	return g;
+}
+
+//static
+const GUID &MullvadGuids::Filter_Baseline_PermitVirtualNetworks_Inbound_Ipv4()
+{
+	static const GUID g =
+	{
+		0x669f8053,
+		0x334f,
+		0x467e,
+		{ 0x9f, 0xb9, 0x88, 0xaa, 0x62, 0x23, 0x9e, 0xd6 }
+	};
+
+	return g;
+}
+
+//static
+const GUID &MullvadGuids::Filter_Baseline_PermitVirtualNetworks_Outbound_Ipv6()
+{
+	static const GUID g =
+	{
+		0x696e8421,
+		0xb6b0,
+		0x41b2,
+		{ 0x81, 0x5b, 0x24, 0x18, 0xc5, 0x64, 0x35, 0xbc }
+	};
+
+	return g;
+}
+
+//static
+const GUID &MullvadGuids::Filter_Baseline_PermitVirtualNetworks_Inbound_Ipv6()
+{
+	static const GUID g =
+	{
+		0xbe8de9e3,
+		0x54b2,
+		0x43ca,
+		{ 0x8f, 0x90, 0x36, 0x53, 0xd6, 0x0b, 0x94, 0x42 }
+	};
+
+	return g;
+}
+
 //static
 const GUID &MullvadGuids::Filter_Baseline_PermitVpnTunnel_Outbound_Ipv4()
 {
@@ -865,3 +1067,31 @@ const GUID &MullvadGuids::Filter_Dns_PermitTunnel_Outbound_Ipv6()
 
 	return g;
 }
+
+//static
+const GUID &MullvadGuids::Filter_Dns_PermitSystemServices_PortalLogin_Outbound_Ipv4()
+{
+	static const GUID g =
+	{
+		0xd64cbaf8,
+		0x5387,
+		0x482f,
+		{ 0xa4, 0x60, 0x63, 0xc4, 0x8b, 0x8a, 0xab, 0xb8 }
+	};
+
+	return g;
+}
+
+//static
+const GUID &MullvadGuids::Filter_Dns_PermitSystemServices_PortalLogin_Outbound_Ipv6()
+{
+	static const GUID g =
+	{
+		0x2cf028e3,
+		0x22f7,
+		0x427e,
+		{ 0xba, 0x36, 0x78, 0xe1, 0xd0, 0x04, 0xd6, 0xac }
+	};
+
+	return g;
+}
@@ -71,6 +71,25 @@ public:
 
 	static const GUID &Filter_Baseline_PermitEndpoint();
 
+	//
+	// Each firewall exception gets its own filters, identified by the index of the exception.
+	//
+	static constexpr size_t MaxFirewallExceptions = 64;
+	static GUID Filter_Baseline_PermitFirewallException_Outbound(size_t index);
+	static GUID Filter_Baseline_PermitFirewallException_Inbound(size_t index);
+
+	static const GUID &Filter_Baseline_PermitSystemServices_Ntp_Outbound_Ipv4();
+	static const GUID &Filter_Baseline_PermitSystemServices_Ntp_Outbound_Ipv6();
+	static const GUID &Filter_Baseline_PermitSystemServices_CaptivePortal_Outbound_Ipv4();
+	static const GUID &Filter_Baseline_PermitSystemServices_CaptivePortal_Outbound_Ipv6();
+	static const GUID &Filter_Baseline_PermitSystemServices_PortalLogin_Outbound_Ipv4();
+	static const GUID &Filter_Baseline_PermitSystemServices_PortalLogin_Outbound_Ipv6();
+
+	static const GUID &Filter_Baseline_PermitVirtualNetworks_Outbound_Ipv4();
+	static const GUID &Filter_Baseline_PermitVirtualNetworks_Inbound_Ipv4();
+	static const GUID &Filter_Baseline_PermitVirtualNetworks_Outbound_Ipv6();
+	static const GUID &Filter_Baseline_PermitVirtualNetworks_Inbound_Ipv6();
+
 	static const GUID &Filter_Baseline_PermitVpnTunnel_Outbound_Ipv4();
 	static const GUID &Filter_Baseline_PermitVpnTunnel_Outbound_Ipv6();
 
@@ -90,6 +109,8 @@ public:
 	static const GUID &Filter_Dns_PermitNonTunnel_Outbound_Ipv6();
 	static const GUID &Filter_Dns_PermitTunnel_Outbound_Ipv4();
 	static const GUID &Filter_Dns_PermitTunnel_Outbound_Ipv6();
+	static const GUID &Filter_Dns_PermitSystemServices_PortalLogin_Outbound_Ipv4();
+	static const GUID &Filter_Dns_PermitSystemServices_PortalLogin_Outbound_Ipv6();
 
 	//
 	// Persistent and boot-time filters
@@ -0,0 +1,114 @@
+#include "stdafx.h"
+#include "permitfirewallexceptions.h"
+#include <winfw/mullvadguids.h>
+#include <libwfp/filterbuilder.h>
+#include <libwfp/conditionbuilder.h>
+#include <libwfp/conditions/conditionip.h>
+#include <libwfp/conditions/conditionport.h>
+#include <libwfp/conditions/conditionprotocol.h>
+#include <libcommon/error.h>
+
+using namespace wfp::conditions;
+
+namespace rules::baseline
+{
+
+namespace
+{
+
+void AddExceptionConditions
+(
+	wfp::ConditionBuilder &conditionBuilder,
+	const PermitFirewallExceptions::Exception &exception
+)
+{
+	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(exception.address, exception.prefix)));
+
+	if (exception.protocol.has_value())
+	{
+		conditionBuilder.add_condition(WinFwProtocol::Tcp == exception.protocol.value()
+			? ConditionProtocol::Tcp()
+			: ConditionProtocol::Udp());
+	}
+
+	if (0 != exception.port)
+	{
+		conditionBuilder.add_condition(ConditionPort::Remote(exception.port));
+	}
+}
+
+} // anonymous namespace
+
+PermitFirewallExceptions::PermitFirewallExceptions(const std::vector<Exception> &exceptions)
+	: m_exceptions(exceptions)
+{
+	// There is a fixed set of filter identifiers.
+	if (m_exceptions.size() > MullvadGuids::MaxFirewallExceptions)
+	{
+		THROW_ERROR("Too many firewall exceptions");
+	}
+}
+
+bool PermitFirewallExceptions::apply(IObjectInstaller &objectInstaller)
+{
+	wfp::FilterBuilder filterBuilder;
+
+	filterBuilder
+		.description(L"This filter is part of a rule that permits traffic on networks chosen by the user")
+		.provider(MullvadGuids::Provider())
+		.sublayer(MullvadGuids::SublayerBaseline())
+		.weight(wfp::FilterBuilder::WeightClass::Medium)
+		.permit();
+
+	for (size_t i = 0; i < m_exceptions.size(); ++i)
+	{
+		const auto &exception = m_exceptions[i];
+		const bool ipv4 = (wfp::IpAddress::Type::Ipv4 == exception.address.type());
+
+		//
+		// #1 Permit outbound connections to the network.
+		//
+
+		const auto &outboundLayer = (ipv4 ? FWPM_LAYER_ALE_AUTH_CONNECT_V4 : FWPM_LAYER_ALE_AUTH_CONNECT_V6);
+
+		filterBuilder
+			.key(MullvadGuids::Filter_Baseline_PermitFirewallException_Outbound(i))
+			.name(L"Permit outbound connections to a network chosen by the user")
+			.layer(outboundLayer);
+
+		{
+			wfp::ConditionBuilder conditionBuilder(outboundLayer);
+			AddExceptionConditions(conditionBuilder, exception);
+
+			if (false == objectInstaller.addFilter(filterBuilder, conditionBuilder))
+			{
+				return false;
+			}
+		}
+
+		//
+		// #2 Permit inbound connections from the network.
+		//
+
+		const auto &inboundLayer = (ipv4 ? FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4 : FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6);
+
+		filterBuilder
+			.key(MullvadGuids::Filter_Baseline_PermitFirewallException_Inbound(i))
+			.name(L"Permit inbound connections from a network chosen by the user")
+			.layer(inboundLayer);
+
+		{
+			wfp::ConditionBuilder conditionBuilder(inboundLayer);
+			AddExceptionConditions(conditionBuilder, exception);
+
+			if (false == objectInstaller.addFilter(filterBuilder, conditionBuilder))
+			{
+				return false;
+			}
+		}
+	}
+
+	return true;
+}
+
+}
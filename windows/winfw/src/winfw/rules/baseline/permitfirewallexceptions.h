@@ -0,0 +1,44 @@
+#pragma once
+
+#include <winfw/rules/ifirewallrule.h>
+#include <winfw/winfw.h>
+#include <libwfp/ipaddress.h>
+#include <libwfp/ipnetwork.h>
+#include <optional>
+#include <vector>
+
+namespace rules::baseline
+{
+
+//
+// Permits traffic to and from networks that the user has chosen to let through
+// the firewall, optionally limited to a protocol and remote port.
+// Each exception gets its own pair of filters, since conditions on different
+// fields in a single filter would be combined for all exceptions.
+//
+class PermitFirewallExceptions : public IFirewallRule
+{
+public:
+
+	struct Exception
+	{
+		wfp::IpAddress address;
+		uint8_t prefix;
+
+		// Zero permits all ports.
+		uint16_t port;
+
+		// No protocol permits all protocols.
+		std::optional<WinFwProtocol> protocol;
+	};
+
+	PermitFirewallExceptions(const std::vector<Exception> &exceptions);
+
+	bool apply(IObjectInstaller &objectInstaller) override;
+
+private:
+
+	const std::vector<Exception> m_exceptions;
+};
+
+}
@@ -12,6 +12,16 @@ using namespace wfp::conditions;
 namespace rules::baseline
 {
 
+PermitLan::PermitLan
+(
+	const std::vector<wfp::IpNetwork> &networksIpv4,
+	const std::vector<wfp::IpNetwork> &networksIpv6
+)
+	: m_networksIpv4(networksIpv4)
+	, m_networksIpv6(networksIpv6)
+{
+}
+
 bool PermitLan::apply(IObjectInstaller &objectInstaller)
 {
 	return applyIpv4(objectInstaller) && applyIpv6(objectInstaller);
@@ -37,12 +47,16 @@ bool PermitLan::applyIpv4(IObjectInstaller &objectInstaller) const
 
 	wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_CONNECT_V4);
 
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 10, 0, 0, 0 }), 8)));
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 172, 16, 0, 0 }), 12)));
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 192, 168, 0, 0 }), 16)));
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 169, 254, 0, 0 }), 16)));
+	for (const auto &network : m_networksIpv4)
+	{
+		conditionBuilder.add_condition(ConditionIp::Remote(network));
+	}
 
-	if (!objectInstaller.addFilter(filterBuilder, conditionBuilder))
+	//
+	// A filter without conditions would match all traffic.
+	//
+
+	if (false == m_networksIpv4.empty() && !objectInstaller.addFilter(filterBuilder, conditionBuilder))
 	{
 		return false;
 	}
@@ -89,13 +103,12 @@ bool PermitLan::applyIpv6(IObjectInstaller &objectInstaller) const
 
 	wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_CONNECT_V6);
 
-	const wfp::IpNetwork linkLocal(wfp::IpAddress::Literal6({ 0xFE80, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0 }), 10);
-	const wfp::IpNetwork uniqueLocal(wfp::IpAddress::Literal6({ 0xFC00, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0 }), 7);
-
-	conditionBuilder.add_condition(ConditionIp::Remote(linkLocal));
-	conditionBuilder.add_condition(ConditionIp::Remote(uniqueLocal));
+	for (const auto &network : m_networksIpv6)
+	{
+		conditionBuilder.add_condition(ConditionIp::Remote(network));
+	}
 
-	if (!objectInstaller.addFilter(filterBuilder, conditionBuilder))
+	if (false == m_networksIpv6.empty() && !objectInstaller.addFilter(filterBuilder, conditionBuilder))
 	{
 		return false;
 	}
@@ -1,23 +1,36 @@
 #pragma once
 
 #include <winfw/rules/ifirewallrule.h>
+#include <libwfp/ipnetwork.h>
+#include <vector>
 
 namespace rules::baseline
 {
 
+//
+// Permits outbound traffic to the on-link networks of the physical interfaces,
+// as well as to link-local and site-local multicast.
+// The networks change as the host moves between networks, so they are passed in rather than fixed.
+//
 class PermitLan : public IFirewallRule
 {
 public:
 
-	PermitLan() = default;
-	~PermitLan() = default;
-	
+	PermitLan
+	(
+		const std::vector<wfp::IpNetwork> &networksIpv4,
+		const std::vector<wfp::IpNetwork> &networksIpv6
+	);
+
 	bool apply(IObjectInstaller &objectInstaller) override;
 
 private:
 
 	bool applyIpv4(IObjectInstaller &objectInstaller) const;
 	bool applyIpv6(IObjectInstaller &objectInstaller) const;
+
+	const std::vector<wfp::IpNetwork> m_networksIpv4;
+	const std::vector<wfp::IpNetwork> m_networksIpv6;
 };
 
 }
@@ -12,6 +12,16 @@ using namespace wfp::conditions;
 namespace rules::baseline
 {
 
+PermitLanService::PermitLanService
+(
+	const std::vector<wfp::IpNetwork> &networksIpv4,
+	const std::vector<wfp::IpNetwork> &networksIpv6
+)
+	: m_networksIpv4(networksIpv4)
+	, m_networksIpv6(networksIpv6)
+{
+}
+
 bool PermitLanService::apply(IObjectInstaller &objectInstaller)
 {
 	return applyIpv4(objectInstaller) && applyIpv6(objectInstaller);
@@ -19,6 +29,11 @@ bool PermitLanService::apply(IObjectInstaller &objectInstaller)
 
 bool PermitLanService::applyIpv4(IObjectInstaller &objectInstaller) const
 {
+	if (m_networksIpv4.empty())
+	{
+		return true;
+	}
+
 	wfp::FilterBuilder filterBuilder;
 
 	//
@@ -37,16 +52,21 @@ bool PermitLanService::applyIpv4(IObjectInstaller &objectInstaller) const
 
 	wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4);
 
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 10, 0, 0, 0 }), 8)));
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 172, 16, 0, 0 }), 12)));
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 192, 168, 0, 0 }), 16)));
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 169, 254, 0, 0 }), 16)));
+	for (const auto &network : m_networksIpv4)
+	{
+		conditionBuilder.add_condition(ConditionIp::Remote(network));
+	}
 
 	return objectInstaller.addFilter(filterBuilder, conditionBuilder);
 }
 
 bool PermitLanService::applyIpv6(IObjectInstaller &objectInstaller) const
 {
+	if (m_networksIpv6.empty())
+	{
+		return true;
+	}
+
 	wfp::FilterBuilder filterBuilder;
 
 	//
@@ -65,11 +85,10 @@ bool PermitLanService::applyIpv6(IObjectInstaller &objectInstaller) const
 
 	wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6);
 
-	const wfp::IpNetwork linkLocal(wfp::IpAddress::Literal6{ 0xFE80, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0 }, 10);
-	const wfp::IpNetwork uniqueLocal(wfp::IpAddress::Literal6({ 0xFC00, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0 }), 7);
-
-	conditionBuilder.add_condition(ConditionIp::Remote(linkLocal));
-	conditionBuilder.add_condition(ConditionIp::Remote(uniqueLocal));
+	for (const auto &network : m_networksIpv6)
+	{
+		conditionBuilder.add_condition(ConditionIp::Remote(network));
+	}
 
 	return objectInstaller.addFilter(filterBuilder, conditionBuilder);
 }
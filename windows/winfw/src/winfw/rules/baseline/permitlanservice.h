@@ -1,23 +1,34 @@
 #pragma once
 
 #include <winfw/rules/ifirewallrule.h>
+#include <libwfp/ipnetwork.h>
+#include <vector>
 
 namespace rules::baseline
 {
 
+//
+// Permits inbound traffic from the on-link networks of the physical interfaces.
+//
 class PermitLanService : public IFirewallRule
 {
 public:
 
-	PermitLanService() = default;
-	~PermitLanService() = default;
-	
+	PermitLanService
+	(
+		const std::vector<wfp::IpNetwork> &networksIpv4,
+		const std::vector<wfp::IpNetwork> &networksIpv6
+	);
+
 	bool apply(IObjectInstaller &objectInstaller) override;
 
 private:
 
 	bool applyIpv4(IObjectInstaller &objectInstaller) const;
 	bool applyIpv6(IObjectInstaller &objectInstaller) const;
+
+	const std::vector<wfp::IpNetwork> m_networksIpv4;
+	const std::vector<wfp::IpNetwork> m_networksIpv6;
 };
 
 }
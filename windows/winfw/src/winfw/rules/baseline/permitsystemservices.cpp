@@ -0,0 +1,237 @@
+#include "stdafx.h"
+#include "permitsystemservices.h"
+#include <winfw/mullvadguids.h>
+#include <winfw/rules/ports.h>
+#include <winfw/rules/shared.h>
+#include <libwfp/filterbuilder.h>
+#include <libwfp/conditionbuilder.h>
+#include <libwfp/conditions/conditionprotocol.h>
+#include <libwfp/conditions/conditionport.h>
+#include <libwfp/conditions/conditionip.h>
+
+using namespace wfp::conditions;
+
+namespace rules::baseline
+{
+
+PermitSystemServices::PermitSystemServices
+(
+	bool permitNtp,
+	const std::vector<wfp::IpAddress> &captivePortalHosts,
+	bool permitPortalLogin
+)
+	: m_permitNtp(permitNtp)
+	, m_permitPortalLogin(permitPortalLogin)
+{
+	SplitAddresses(captivePortalHosts, m_captivePortalHostsIpv4, m_captivePortalHostsIpv6);
+}
+
+bool PermitSystemServices::apply(IObjectInstaller &objectInstaller)
+{
+	wfp::FilterBuilder filterBuilder;
+
+	if (m_permitNtp)
+	{
+		//
+		// #1 Permit outbound NTP, IPv4.
+		//
+
+		filterBuilder
+			.key(MullvadGuids::Filter_Baseline_PermitSystemServices_Ntp_Outbound_Ipv4())
+			.name(L"Permit outbound NTP requests (IPv4)")
+			.description(L"This filter is part of a rule that permits traffic of essential system services")
+			.provider(MullvadGuids::Provider())
+			.layer(FWPM_LAYER_ALE_AUTH_CONNECT_V4)
+			.sublayer(MullvadGuids::SublayerBaseline())
+			.weight(wfp::FilterBuilder::WeightClass::Max)
+			.permit();
+
+		{
+			wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_CONNECT_V4);
+
+			conditionBuilder.add_condition(ConditionProtocol::Udp());
+			conditionBuilder.add_condition(ConditionPort::Remote(NTP_SERVER_PORT));
+
+			if (false == objectInstaller.addFilter(filterBuilder, conditionBuilder))
+			{
+				return false;
+			}
+		}
+
+		//
+		// #2 Permit outbound NTP, IPv6.
+		//
+
+		filterBuilder
+			.key(MullvadGuids::Filter_Baseline_PermitSystemServices_Ntp_Outbound_Ipv6())
+			.name(L"Permit outbound NTP requests (IPv6)")
+			.layer(FWPM_LAYER_ALE_AUTH_CONNECT_V6);
+
+		{
+			wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_CONNECT_V6);
+
+			conditionBuilder.add_condition(ConditionProtocol::Udp());
+			conditionBuilder.add_condition(ConditionPort::Remote(NTP_SERVER_PORT));
+
+			if (false == objectInstaller.addFilter(filterBuilder, conditionBuilder))
+			{
+				return false;
+			}
+		}
+	}
+
+	//
+	// #3 Permit outbound HTTP to captive portal detection hosts, IPv4.
+	//
+
+	if (false == m_captivePortalHostsIpv4.empty())
+	{
+		filterBuilder
+			.key(MullvadGuids::Filter_Baseline_PermitSystemServices_CaptivePortal_Outbound_Ipv4())
+			.name(L"Permit outbound captive portal detection (IPv4)")
+			.description(L"This filter is part of a rule that permits traffic of essential system services")
+			.provider(MullvadGuids::Provider())
+			.layer(FWPM_LAYER_ALE_AUTH_CONNECT_V4)
+			.sublayer(MullvadGuids::SublayerBaseline())
+			.weight(wfp::FilterBuilder::WeightClass::Max)
+			.permit();
+
+		wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_CONNECT_V4);
+
+		conditionBuilder.add_condition(ConditionProtocol::Tcp());
+		conditionBuilder.add_condition(ConditionPort::Remote(HTTP_SERVER_PORT));
+
+		for (const auto &host : m_captivePortalHostsIpv4)
+		{
+			conditionBuilder.add_condition(ConditionIp::Remote(host));
+		}
+
+		if (false == objectInstaller.addFilter(filterBuilder, conditionBuilder))
+		{
+			return false;
+		}
+	}
+
+	//
+	// #4 Permit outbound HTTP to captive portal detection hosts, IPv6.
+	//
+
+	if (false == m_captivePortalHostsIpv6.empty())
+	{
+		filterBuilder
+			.key(MullvadGuids::Filter_Baseline_PermitSystemServices_CaptivePortal_Outbound_Ipv6())
+			.name(L"Permit outbound captive portal detection (IPv6)")
+			.description(L"This filter is part of a rule that permits traffic of essential system services")
+			.provider(MullvadGuids::Provider())
+			.layer(FWPM_LAYER_ALE_AUTH_CONNECT_V6)
+			.sublayer(MullvadGuids::SublayerBaseline())
+			.weight(wfp::FilterBuilder::WeightClass::Max)
+			.permit();
+
+		wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_CONNECT_V6);
+
+		conditionBuilder.add_condition(ConditionProtocol::Tcp());
+		conditionBuilder.add_condition(ConditionPort::Remote(HTTP_SERVER_PORT));
+
+		for (const auto &host : m_captivePortalHostsIpv6)
+		{
+			conditionBuilder.add_condition(ConditionIp::Remote(host));
+		}
+
+		if (false == objectInstaller.addFilter(filterBuilder, conditionBuilder))
+		{
+			return false;
+		}
+	}
+
+	if (false == m_permitPortalLogin)
+	{
+		return true;
+	}
+
+	return applyPortalLogin(objectInstaller);
+}
+
+bool PermitSystemServices::applyPortalLogin(IObjectInstaller &objectInstaller)
+{
+	wfp::FilterBuilder filterBuilder;
+
+	//
+	// #1 Permit outbound HTTP and HTTPS to any host, IPv4.
+	//
+
+	filterBuilder
+		.key(MullvadGuids::Filter_Baseline_PermitSystemServices_PortalLogin_Outbound_Ipv4())
+		.name(L"Permit outbound web traffic for captive portal login (IPv4)")
+		.description(L"This filter is part of a rule that permits traffic of essential system services")
+		.provider(MullvadGuids::Provider())
+		.layer(FWPM_LAYER_ALE_AUTH_CONNECT_V4)
+		.sublayer(MullvadGuids::SublayerBaseline())
+		.weight(wfp::FilterBuilder::WeightClass::Max)
+		.permit();
+
+	wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_CONNECT_V4);
+
+	conditionBuilder.add_condition(ConditionProtocol::Tcp());
+	conditionBuilder.add_condition(ConditionPort::Remote(HTTP_SERVER_PORT));
+	conditionBuilder.add_condition(ConditionPort::Remote(HTTPS_SERVER_PORT));
+
+	if (false == objectInstaller.addFilter(filterBuilder, conditionBuilder))
+	{
+		return false;
+	}
+
+	//
+	// #2 Permit outbound HTTP and HTTPS to any host, IPv6.
+	//
+
+	filterBuilder
+		.key(MullvadGuids::Filter_Baseline_PermitSystemServices_PortalLogin_Outbound_Ipv6())
+		.name(L"Permit outbound web traffic for captive portal login (IPv6)")
+		.layer(FWPM_LAYER_ALE_AUTH_CONNECT_V6);
+
+	conditionBuilder.reset(FWPM_LAYER_ALE_AUTH_CONNECT_V6);
+	conditionBuilder.add_condition(ConditionProtocol::Tcp());
+	conditionBuilder.add_condition(ConditionPort::Remote(HTTP_SERVER_PORT));
+	conditionBuilder.add_condition(ConditionPort::Remote(HTTPS_SERVER_PORT));
+
+	if (false == objectInstaller.addFilter(filterBuilder, conditionBuilder))
+	{
+		return false;
+	}
+
+	//
+	// #3 Permit outbound DNS to any server, IPv4.
+	//
+
+	filterBuilder
+		.key(MullvadGuids::Filter_Dns_PermitSystemServices_PortalLogin_Outbound_Ipv4())
+		.name(L"Permit outbound DNS for captive portal login (IPv4)")
+		.layer(FWPM_LAYER_ALE_AUTH_CONNECT_V4)
+		.sublayer(MullvadGuids::SublayerDns())
+		.weight(wfp::FilterBuilder::WeightClass::Medium);
+
+	conditionBuilder.reset(FWPM_LAYER_ALE_AUTH_CONNECT_V4);
+	conditionBuilder.add_condition(ConditionPort::Remote(DNS_SERVER_PORT));
+
+	if (false == objectInstaller.addFilter(filterBuilder, conditionBuilder))
+	{
+		return false;
+	}
+
+	//
+	// #4 Permit outbound DNS to any server, IPv6.
+	//
+
+	filterBuilder
+		.key(MullvadGuids::Filter_Dns_PermitSystemServices_PortalLogin_Outbound_Ipv6())
+		.name(L"Permit outbound DNS for captive portal login (IPv6)")
+		.layer(FWPM_LAYER_ALE_AUTH_CONNECT_V6);
+
+	conditionBuilder.reset(FWPM_LAYER_ALE_AUTH_CONNECT_V6);
+	conditionBuilder.add_condition(ConditionPort::Remote(DNS_SERVER_PORT));
+
+	return objectInstaller.addFilter(filterBuilder, conditionBuilder);
+}
+
+}
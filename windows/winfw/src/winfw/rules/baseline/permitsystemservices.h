@@ -0,0 +1,40 @@
+#pragma once
+
+#include <winfw/rules/ifirewallrule.h>
+#include <libwfp/ipaddress.h>
+#include <vector>
+
+namespace rules::baseline
+{
+
+//
+// Permits traffic of essential system services while all other traffic is blocked.
+// DHCP is permitted by a separate rule.
+//
+// Logging in to a captive portal additionally permits DNS and web traffic to any host.
+// DNS is restricted in the DNS sublayer, so that part of the rule is installed there.
+//
+class PermitSystemServices : public IFirewallRule
+{
+public:
+
+	PermitSystemServices
+	(
+		bool permitNtp,
+		const std::vector<wfp::IpAddress> &captivePortalHosts,
+		bool permitPortalLogin
+	);
+
+	bool apply(IObjectInstaller &objectInstaller) override;
+
+private:
+
+	const bool m_permitNtp;
+	std::vector<wfp::IpAddress> m_captivePortalHostsIpv4;
+	std::vector<wfp::IpAddress> m_captivePortalHostsIpv6;
+	const bool m_permitPortalLogin;
+
+	bool applyPortalLogin(IObjectInstaller &objectInstaller);
+};
+
+}
@@ -0,0 +1,135 @@
+#include "stdafx.h"
+#include "permitvirtualnetworks.h"
+#include <winfw/mullvadguids.h>
+#include <libwfp/filterbuilder.h>
+#include <libwfp/conditionbuilder.h>
+#include <libwfp/conditions/conditionip.h>
+
+using namespace wfp::conditions;
+
+namespace rules::baseline
+{
+
+PermitVirtualNetworks::PermitVirtualNetworks
+(
+	const std::vector<wfp::IpNetwork> &networksIpv4,
+	const std::vector<wfp::IpNetwork> &networksIpv6
+)
+	: m_networksIpv4(networksIpv4)
+	, m_networksIpv6(networksIpv6)
+{
+}
+
+bool PermitVirtualNetworks::apply(IObjectInstaller &objectInstaller)
+{
+	wfp::FilterBuilder filterBuilder;
+
+	filterBuilder
+		.description(L"This filter is part of a rule that permits traffic on virtual networks of the host")
+		.provider(MullvadGuids::Provider())
+		.sublayer(MullvadGuids::SublayerBaseline())
+		.weight(wfp::FilterBuilder::WeightClass::Medium)
+		.permit();
+
+	if (false == m_networksIpv4.empty())
+	{
+		//
+		// #1 Permit outbound connections to the virtual networks, IPv4.
+		//
+
+		filterBuilder
+			.key(MullvadGuids::Filter_Baseline_PermitVirtualNetworks_Outbound_Ipv4())
+			.name(L"Permit outbound connections on virtual networks (IPv4)")
+			.layer(FWPM_LAYER_ALE_AUTH_CONNECT_V4);
+
+		{
+			wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_CONNECT_V4);
+
+			for (const auto &network : m_networksIpv4)
+			{
+				conditionBuilder.add_condition(ConditionIp::Remote(network));
+			}
+
+			if (false == objectInstaller.addFilter(filterBuilder, conditionBuilder))
+			{
+				return false;
+			}
+		}
+
+		//
+		// #2 Permit inbound connections from the virtual networks, IPv4.
+		//
+
+		filterBuilder
+			.key(MullvadGuids::Filter_Baseline_PermitVirtualNetworks_Inbound_Ipv4())
+			.name(L"Permit inbound connections on virtual networks (IPv4)")
+			.layer(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4);
+
+		{
+			wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4);
+
+			for (const auto &network : m_networksIpv4)
+			{
+				conditionBuilder.add_condition(ConditionIp::Remote(network));
+			}
+
+			if (false == objectInstaller.addFilter(filterBuilder, conditionBuilder))
+			{
+				return false;
+			}
+		}
+	}
+
+	if (false == m_networksIpv6.empty())
+	{
+		//
+		// #3 Permit outbound connections to the virtual networks, IPv6.
+		//
+
+		filterBuilder
+			.key(MullvadGuids::Filter_Baseline_PermitVirtualNetworks_Outbound_Ipv6())
+			.name(L"Permit outbound connections on virtual networks (IPv6)")
+			.layer(FWPM_LAYER_ALE_AUTH_CONNECT_V6);
+
+		{
+			wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_CONNECT_V6);
+
+			for (const auto &network : m_networksIpv6)
+			{
+				conditionBuilder.add_condition(ConditionIp::Remote(network));
+			}
+
+			if (false == objectInstaller.addFilter(filterBuilder, conditionBuilder))
+			{
+				return false;
+			}
+		}
+
+		//
+		// #4 Permit inbound connections from the virtual networks, IPv6.
+		//
+
+		filterBuilder
+			.key(MullvadGuids::Filter_Baseline_PermitVirtualNetworks_Inbound_Ipv6())
+			.name(L"Permit inbound connections on virtual networks (IPv6)")
+			.layer(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6);
+
+		{
+			wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6);
+
+			for (const auto &network : m_networksIpv6)
+			{
+				conditionBuilder.add_condition(ConditionIp::Remote(network));
+			}
+
+			if (false == objectInstaller.addFilter(filterBuilder, conditionBuilder))
+			{
+				return false;
+			}
+		}
+	}
+
+	return true;
+}
+
+}
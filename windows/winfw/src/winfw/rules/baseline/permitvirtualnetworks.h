@@ -0,0 +1,33 @@
+#pragma once
+
+#include <winfw/rules/ifirewallrule.h>
+#include <libwfp/ipnetwork.h>
+#include <vector>
+
+namespace rules::baseline
+{
+
+//
+// Permits traffic to and from the networks of virtual switches that share the
+// connection of the host, such as the NAT switches of Hyper-V and WSL2.
+// These networks change on every boot, so they are passed in rather than fixed.
+//
+class PermitVirtualNetworks : public IFirewallRule
+{
+public:
+
+	PermitVirtualNetworks
+	(
+		const std::vector<wfp::IpNetwork> &networksIpv4,
+		const std::vector<wfp::IpNetwork> &networksIpv6
+	);
+
+	bool apply(IObjectInstaller &objectInstaller) override;
+
+private:
+
+	const std::vector<wfp::IpNetwork> m_networksIpv4;
+	const std::vector<wfp::IpNetwork> m_networksIpv6;
+};
+
+}
@@ -10,8 +10,9 @@ using namespace wfp::conditions;
 namespace rules::baseline
 {
 
-PermitVpnTunnel::PermitVpnTunnel(const std::wstring &tunnelInterfaceAlias)
+PermitVpnTunnel::PermitVpnTunnel(const std::wstring &tunnelInterfaceAlias, bool permitIpv6)
 	: m_tunnelInterfaceAlias(tunnelInterfaceAlias)
+	, m_permitIpv6(permitIpv6)
 {
 }
 
@@ -44,6 +45,11 @@ bool PermitVpnTunnel::apply(IObjectInstaller &objectInstaller)
 		}
 	}
 
+	if (!m_permitIpv6)
+	{
+		return true;
+	}
+
 	//
 	// #2 Permit outbound connections, IPv6.
 	//
@@ -10,13 +10,14 @@ class PermitVpnTunnel : public IFirewallRule
 {
 public:
 
-	PermitVpnTunnel(const std::wstring &tunnelInterfaceAlias);
+	PermitVpnTunnel(const std::wstring &tunnelInterfaceAlias, bool permitIpv6);
 	
 	bool apply(IObjectInstaller &objectInstaller) override;
 
 private:
 
 	const std::wstring m_tunnelInterfaceAlias;
+	const bool m_permitIpv6;
 };
 
 }
@@ -10,13 +10,14 @@ class PermitVpnTunnelService : public IFirewallRule
 {
 public:
 
-	PermitVpnTunnelService(const std::wstring &tunnelInterfaceAlias);
+	PermitVpnTunnelService(const std::wstring &tunnelInterfaceAlias, bool permitIpv6);
 
 	bool apply(IObjectInstaller &objectInstaller) override;
 
 private:
 
 	const std::wstring m_tunnelInterfaceAlias;
+	const bool m_permitIpv6;
 };
 
 }
@@ -14,6 +14,10 @@ enum Ports : uint16_t
 	DHCPV6_SERVER_PORT = 547,
 
 	DNS_SERVER_PORT = 53,
+
+	NTP_SERVER_PORT = 123,
+	HTTP_SERVER_PORT = 80,
+	HTTPS_SERVER_PORT = 443,
 };
 
 }
@@ -236,6 +236,69 @@ void SessionController::reset()
 	rewindState(m_transactionRecords.size());
 }
 
+void SessionController::discard()
+{
+	if (false == m_activeTransaction)
+	{
+		THROW_ERROR("Cannot discard session state outside transaction");
+	}
+
+	m_transactionRecords.clear();
+}
+
+bool SessionController::verify()
+{
+	bool intact = true;
+
+	const auto status = executeReadOnlyTransaction([&intact](SessionController &controller, wfp::FilterEngine &engine)
+	{
+		for (const auto &record : controller.m_records)
+		{
+			if (false == record.exists(engine))
+			{
+				intact = false;
+				break;
+			}
+		}
+
+		return true;
+	});
+
+	if (false == status)
+	{
+		THROW_ERROR("Failed to read session state from BFE");
+	}
+
+	return intact;
+}
+
+std::vector<std::wstring> SessionController::describeFilters()
+{
+	std::vector<std::wstring> descriptions;
+
+	const auto status = executeReadOnlyTransaction([&descriptions](SessionController &controller, wfp::FilterEngine &engine)
+	{
+		for (const auto &record : controller.m_records)
+		{
+			auto description = record.describe(engine);
+
+			if (description.has_value())
+			{
+				descriptions.emplace_back(std::move(description.value()));
+			}
+		}
+
+		return true;
+	});
+
+	if (false == status)
+	{
+		THROW_ERROR("Failed to read session state from BFE");
+	}
+
+	return descriptions;
+}
+
 void SessionController::rewindState(size_t steps)
 {
 	auto purged = 0;
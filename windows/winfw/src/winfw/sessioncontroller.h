@@ -8,6 +8,7 @@
 #include <functional>
 #include <atomic>
 #include <memory>
+#include <string>
 #include <vector>
 
 class SessionController : public IObjectInstaller
@@ -48,6 +49,24 @@ public:
 	//
 	void reset();
 
+	//
+	// Forget all objects in the stack without purging them
+	// Use only inside active transaction, when the objects have been removed by other means
+	//
+	void discard();
+
+	//
+	// Check that all objects in the stack are still registered with WFP
+	// This should be done outside of an active transaction
+	//
+	bool verify();
+
+	//
+	// Describe the filters in the stack that are registered with WFP
+	// This should be done outside of an active transaction
+	//
+	std::vector<std::wstring> describeFilters();
+
 private:
 
 	SessionController(const SessionController &) = delete;
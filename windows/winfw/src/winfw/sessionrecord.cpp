@@ -1,6 +1,7 @@
 #include "stdafx.h"
 #include "sessionrecord.h"
 #include "libwfp/objectdeleter.h"
+#include <fwpmu.h>
 #include <libcommon/error.h>
 #include <atomic>
 #include <cstdint>
@@ -52,6 +53,106 @@ void SessionRecord::purge(wfp::FilterEngine &engine)
 	};
 }
 
+bool SessionRecord::exists(wfp::FilterEngine &engine) const
+{
+	void *object = nullptr;
+	DWORD status = ERROR_SUCCESS;
+
+	switch (m_type)
+	{
+		case WfpObjectType::Provider:
+		{
+			status = FwpmProviderGetByKey0(engine.session(), &m_id,
+				reinterpret_cast<FWPM_PROVIDER0 **>(&object));
+			break;
+		}
+		case WfpObjectType::Sublayer:
+		{
+			status = FwpmSubLayerGetByKey0(engine.session(), &m_id,
+				reinterpret_cast<FWPM_SUBLAYER0 **>(&object));
+			break;
+		}
+		case WfpObjectType::Filter:
+		{
+			status = FwpmFilterGetById0(engine.session(), m_filterId,
+				reinterpret_cast<FWPM_FILTER0 **>(&object));
+			break;
+		}
+		default:
+		{
+			THROW_ERROR("Missing case handler in switch clause");
+		}
+	};
+
+	switch (status)
+	{
+		case ERROR_SUCCESS:
+		{
+			FwpmFreeMemory0(&object);
+			return true;
+		}
+		case FWP_E_PROVIDER_NOT_FOUND:
+		case FWP_E_SUBLAYER_NOT_FOUND:
+		case FWP_E_FILTER_NOT_FOUND:
+		{
+			return false;
+		}
+		default:
+		{
+			THROW_WINDOWS_ERROR(status, "Query WFP object");
+		}
+	};
+}
+
+std::optional<std::wstring> SessionRecord::describe(wfp::FilterEngine &engine) const
+{
+	if (WfpObjectType::Filter != m_type)
+	{
+		return std::nullopt;
+	}
+
+	FWPM_FILTER0 *filter = nullptr;
+
+	const auto status = FwpmFilterGetById0(engine.session(), m_filterId, &filter);
+
+	if (FWP_E_FILTER_NOT_FOUND == status)
+	{
+		return std::nullopt;
+	}
+
+	if (ERROR_SUCCESS != status)
+	{
+		THROW_WINDOWS_ERROR(status, "Query WFP filter");
+	}
+
+	const wchar_t *action = L"callout";
+
+	switch (filter->action.type)
+	{
+		case FWP_ACTION_PERMIT:
+		{
+			action = L"permit";
+			break;
+		}
+		case FWP_ACTION_BLOCK:
+		{
+			action = L"block";
+			break;
+		}
+	};
+
+	std::wstring description = std::to_wstring(m_filterId);
+	description.append(L": ");
+	description.append(nullptr != filter->displayData.name ? filter->displayData.name : L"(unnamed)");
+	description.append(L" (");
+	description.append(action);
+	description.append(L")");
+
+	FwpmFreeMemory0(reinterpret_cast<void **>(&filter));
+
+	return description;
+}
+
 uint32_t SessionRecord::key() const
 {
 	return m_key;
@@ -4,6 +4,8 @@
 #include "wfpobjecttype.h"
 #include <guiddef.h>
 #include <windows.h>
+#include <optional>
+#include <string>
 
 class SessionRecord
 {
@@ -18,6 +20,17 @@ public:
 
 	void purge(wfp::FilterEngine &engine);
 
+	//
+	// Check whether the object is still registered with WFP.
+	// Objects can be removed behind our back by other software.
+	//
+	bool exists(wfp::FilterEngine &engine) const;
+
+	//
+	// Describe the filter, if this is a record of a filter that is still registered with WFP.
+	//
+	std::optional<std::wstring> describe(wfp::FilterEngine &engine) const;
+
 	uint32_t key() const;
 
 private:
@@ -3,8 +3,11 @@
 #include "fwcontext.h"
 #include "objectpurger.h"
 #include "mullvadobjects.h"
+#include "mullvadguids.h"
 #include "rules/persistent/blockall.h"
 #include "libwfp/ipnetwork.h"
+#include "libwfp/filterengine.h"
+#include <fwpmu.h>
 #include <windows.h>
 #include <libcommon/error.h>
 #include <libcommon/string.h>
@@ -186,11 +189,13 @@ WinFw_Deinitialize(WINFW_CLEANUP_POLICY cleanupPolicy)
 
 	//
 	// Continue blocking if this is what the caller requested
-	// and if the current policy is "(net) blocked".
+	// and if the current policy is "(net) blocked", or if the
+	// caller requested blocking regardless of the policy.
 	//
 
-	if (WINFW_CLEANUP_POLICY_CONTINUE_BLOCKING == cleanupPolicy
+	if ((WINFW_CLEANUP_POLICY_CONTINUE_BLOCKING == cleanupPolicy
 		&& FwContext::Policy::Blocked == activePolicy)
+		|| WINFW_CLEANUP_POLICY_BLOCK == cleanupPolicy)
 	{
 		try
 		{
@@ -433,7 +438,8 @@ WINFW_POLICY_STATUS
 WINFW_API
 WinFw_ApplyPolicyBlocked(
 	const WinFwSettings *settings,
-	const WinFwEndpoint *allowedEndpoint
+	const WinFwEndpoint *allowedEndpoint,
+	const WinFwSystemServices *systemServices
 )
 {
 	if (nullptr == g_fwContext)
@@ -448,7 +454,25 @@ WinFw_ApplyPolicyBlocked(
 			THROW_ERROR("Invalid argument: settings");
 		}
 
-		return g_fwContext->applyPolicyBlocked(*settings, MakeOptional(allowedEndpoint))
+		SystemServiceExemptions exemptions;
+
+		if (nullptr != systemServices)
+		{
+			if (nullptr == systemServices->captivePortalHosts && 0 != systemServices->numCaptivePortalHosts)
+			{
+				THROW_ERROR("Invalid argument: captivePortalHosts");
+			}
+
+			exemptions.permitNtp = systemServices->permitNtp;
+			exemptions.permitPortalLogin = systemServices->permitPortalLogin;
+
+			for (size_t i = 0; i < systemServices->numCaptivePortalHosts; ++i)
+			{
+				exemptions.captivePortalHosts.emplace_back(systemServices->captivePortalHosts[i]);
+			}
+		}
+
+		return g_fwContext->applyPolicyBlocked(*settings, MakeOptional(allowedEndpoint), exemptions)
 			? WINFW_POLICY_STATUS_SUCCESS
 			: WINFW_POLICY_STATUS_GENERAL_FAILURE;
 	}
@@ -507,3 +531,177 @@ WinFw_Reset()
 		return WINFW_POLICY_STATUS_GENERAL_FAILURE;
 	}
 }
+
+WINFW_LINKAGE
+WINFW_OBJECT_STATUS
+WINFW_API
+WinFw_VerifyObjects()
+{
+	if (nullptr == g_fwContext)
+	{
+		return WINFW_OBJECT_STATUS_GENERAL_FAILURE;
+	}
+
+	try
+	{
+		return g_fwContext->verifyObjects()
+			? WINFW_OBJECT_STATUS_INTACT
+			: WINFW_OBJECT_STATUS_MISSING;
+	}
+	catch (std::exception &err)
+	{
+		if (nullptr != g_logSink)
+		{
+			g_logSink(MULLVAD_LOG_LEVEL_ERROR, err.what(), g_logSinkContext);
+		}
+
+		return WINFW_OBJECT_STATUS_GENERAL_FAILURE;
+	}
+	catch (...)
+	{
+		return WINFW_OBJECT_STATUS_GENERAL_FAILURE;
+	}
+}
+
+WINFW_LINKAGE
+WINFW_POLICY_STATUS
+WINFW_API
+WinFw_RepairObjects()
+{
+	if (nullptr == g_fwContext)
+	{
+		return WINFW_POLICY_STATUS_GENERAL_FAILURE;
+	}
+
+	try
+	{
+		return g_fwContext->repairObjects()
+			? WINFW_POLICY_STATUS_SUCCESS
+			: WINFW_POLICY_STATUS_GENERAL_FAILURE;
+	}
+	catch (common::error::WindowsException &err)
+	{
+		return HandlePolicyException(err);
+	}
+	catch (std::exception &err)
+	{
+		if (nullptr != g_logSink)
+		{
+			g_logSink(MULLVAD_LOG_LEVEL_ERROR, err.what(), g_logSinkContext);
+		}
+
+		return WINFW_POLICY_STATUS_GENERAL_FAILURE;
+	}
+	catch (...)
+	{
+		return WINFW_POLICY_STATUS_GENERAL_FAILURE;
+	}
+}
+
+WINFW_LINKAGE
+bool
+WINFW_API
+WinFw_DescribeFilters(WinFwFilterSink filterSink, void *filterSinkContext)
+{
+	if (nullptr == g_fwContext || nullptr == filterSink)
+	{
+		return false;
+	}
+
+	try
+	{
+		for (const auto &description : g_fwContext->describeFilters())
+		{
+			filterSink(description.c_str(), filterSinkContext);
+		}
+
+		return true;
+	}
+	catch (std::exception &err)
+	{
+		if (nullptr != g_logSink)
+		{
+			g_logSink(MULLVAD_LOG_LEVEL_ERROR, err.what(), g_logSinkContext);
+		}
+
+		return false;
+	}
+	catch (...)
+	{
+		return false;
+	}
+}
+
+WINFW_LINKAGE
+bool
+WINFW_API
+WinFw_DescribeProviders(WinFwFilterSink providerSink, void *providerSinkContext)
+{
+	if (nullptr == providerSink)
+	{
+		return false;
+	}
+
+	try
+	{
+		auto engine = wfp::FilterEngine::StandardSession();
+
+		HANDLE enumHandle = nullptr;
+
+		auto status = FwpmProviderCreateEnumHandle0(engine->session(), nullptr, &enumHandle);
+
+		if (ERROR_SUCCESS != status)
+		{
+			THROW_WINDOWS_ERROR(status, "Create WFP provider enumeration handle");
+		}
+
+		FWPM_PROVIDER0 **providers = nullptr;
+		UINT32 numProviders = 0;
+
+		status = FwpmProviderEnum0(engine->session(), enumHandle, INFINITE, &providers, &numProviders);
+
+		FwpmProviderDestroyEnumHandle0(engine->session(), enumHandle);
+
+		if (ERROR_SUCCESS != status)
+		{
+			THROW_WINDOWS_ERROR(status, "Enumerate WFP providers");
+		}
+
+		for (UINT32 i = 0; i < numProviders; ++i)
+		{
+			const auto provider = providers[i];
+
+			if (MullvadGuids::Provider() == provider->providerKey
+				|| MullvadGuids::ProviderPersistent() == provider->providerKey)
+			{
+				continue;
+			}
+
+			const auto name = nullptr != provider->displayData.name
+				? provider->displayData.name
+				: provider->displayData.description;
+
+			if (nullptr != name)
+			{
+				providerSink(name, providerSinkContext);
+			}
+		}
+
+		FwpmFreeMemory0(reinterpret_cast<void **>(&providers));
+
+		return true;
+	}
+	catch (std::exception &err)
+	{
+		if (nullptr != g_logSink)
+		{
+			g_logSink(MULLVAD_LOG_LEVEL_ERROR, err.what(), g_logSinkContext);
+		}
+
+		return false;
+	}
+	catch (...)
+	{
+		return false;
+	}
+}
@@ -2,6 +2,7 @@
 
 #include <libshared/logging/logsink.h>
 #include <stdint.h>
+#include <stddef.h>
 
 //
 // WINFW public API
@@ -21,22 +22,62 @@
 
 #pragma pack(push, 1)
 
+enum WinFwProtocol : uint8_t
+{
+	Tcp = 0,
+	Udp = 1
+};
+
+typedef struct tag_WinFwNetwork
+{
+	const wchar_t *ip;
+	uint8_t prefix;
+}
+WinFwNetwork;
+
+typedef struct tag_WinFwFirewallException
+{
+	const wchar_t *ip;
+	uint8_t prefix;
+
+	// Remote port to permit. Zero permits all ports.
+	uint16_t port;
+
+	// Protocol to permit. Ignored if `anyProtocol` is set, which requires `port` to be zero.
+	WinFwProtocol protocol;
+	bool anyProtocol;
+}
+WinFwFirewallException;
+
 typedef struct tag_WinFwSettings
 {
 	// Permit outbound DHCP requests and inbound DHCP responses on all interfaces.
 	bool permitDhcp;
 
-	// Permit all traffic to and from private address ranges.
+	// Permit all traffic to and from `lanNetworks`, as well as outbound local multicast.
 	bool permitLan;
+
+	// The on-link networks of the physical interfaces of the host.
+	// Ignored unless `permitLan` is set.
+	const WinFwNetwork *lanNetworks;
+	size_t numLanNetworks;
+
+	// Permit all traffic to and from these networks, regardless of `permitLan`.
+	// They belong to virtual switches of the host, such as the NAT switch of WSL2.
+	const WinFwNetwork *virtualNetworks;
+	size_t numVirtualNetworks;
+
+	// Permit traffic to and from these networks, regardless of `permitLan`.
+	// They have been chosen by the user. DNS requests are blocked regardless.
+	const WinFwFirewallException *firewallExceptions;
+	size_t numFirewallExceptions;
+
+	// Permit IPv6 traffic on the tunnel interface.
+	// Only used by the connecting and connected policies.
+	bool permitTunnelIpv6;
 }
 WinFwSettings;
 
-enum WinFwProtocol : uint8_t
-{
-	Tcp = 0,
-	Udp = 1
-};
-
 typedef struct tag_WinFwEndpoint
 {
 	const wchar_t *ip;
@@ -45,6 +86,20 @@ typedef struct tag_WinFwEndpoint
 }
 WinFwEndpoint;
 
+typedef struct tag_WinFwSystemServices
+{
+	// Permit outbound NTP requests to any server.
+	bool permitNtp;
+
+	// Permit outbound HTTP requests to these hosts, used for captive portal detection.
+	const wchar_t * const *captivePortalHosts;
+	size_t numCaptivePortalHosts;
+
+	// Permit outbound DNS, HTTP and HTTPS to any host, so that a captive portal can be logged in to.
+	bool permitPortalLogin;
+}
+WinFwSystemServices;
+
 #pragma pack(pop)
 
 ///////////////////////////////////////////////////////////////////////////////
@@ -103,6 +158,10 @@ enum WINFW_CLEANUP_POLICY : uint32_t
 
 	// Remove all objects that have been registered with WFP.
 	WINFW_CLEANUP_POLICY_RESET_FIREWALL = 1,
+
+	// Add the persistent blocking filters regardless of the active policy.
+	// They are active until WinFw is reinitialized.
+	WINFW_CLEANUP_POLICY_BLOCK = 2,
 };
 
 //
@@ -182,6 +241,8 @@ WinFw_ApplyPolicyConnected(
 //
 // Apply restrictions in the firewall that block all traffic, except:
 // - What is specified by settings
+// - Traffic to the allowed endpoint, if one is specified
+// - Traffic of the system services in `systemServices`, if specified
 //
 extern "C"
 WINFW_LINKAGE
@@ -189,7 +250,8 @@ WINFW_POLICY_STATUS
 WINFW_API
 WinFw_ApplyPolicyBlocked(
 	const WinFwSettings *settings,
-	const WinFwEndpoint *allowedEndpoint
+	const WinFwEndpoint *allowedEndpoint,
+	const WinFwSystemServices *systemServices
 );
 
 //
@@ -202,3 +264,68 @@ WINFW_LINKAGE
 WINFW_POLICY_STATUS
 WINFW_API
 WinFw_Reset();
+
+enum WINFW_OBJECT_STATUS : uint32_t
+{
+	WINFW_OBJECT_STATUS_INTACT = 0,
+	WINFW_OBJECT_STATUS_MISSING = 1,
+	WINFW_OBJECT_STATUS_GENERAL_FAILURE = 2,
+};
+
+//
+// VerifyObjects:
+//
+// Check whether the provider, sublayers and filters that have been registered with WFP
+// are still in place. Other software may remove them while WINFW is initialized.
+//
+extern "C"
+WINFW_LINKAGE
+WINFW_OBJECT_STATUS
+WINFW_API
+WinFw_VerifyObjects();
+
+//
+// RepairObjects:
+//
+// Remove any remaining objects and reinstall the provider and sublayers.
+// This clears the policy in effect, which has to be applied again.
+//
+extern "C"
+WINFW_LINKAGE
+WINFW_POLICY_STATUS
+WINFW_API
+WinFw_RepairObjects();
+
+typedef void (WINFW_API *WinFwFilterSink)(const wchar_t *description, void *context);
+
+//
+// DescribeFilters:
+//
+// Call the sink once for every filter that has been registered with WFP,
+// with a description of the filter. The description is only valid during the call.
+//
+extern "C"
+WINFW_LINKAGE
+bool
+WINFW_API
+WinFw_DescribeFilters(
+	WinFwFilterSink filterSink,
+	void *filterSinkContext
+);
+
+//
+// DescribeProviders:
+//
+// Call the sink once for every WFP provider that is not registered by us,
+// with the name of the provider. The name is only valid during the call.
+//
+// This does not require the firewall to be initialized.
+//
+extern "C"
+WINFW_LINKAGE
+bool
+WINFW_API
+WinFw_DescribeProviders(
+	WinFwFilterSink providerSink,
+	void *providerSinkContext
+);
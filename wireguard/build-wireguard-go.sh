@@ -40,6 +40,8 @@ function win_gather_export_symbols {
 }
 
 function win_create_lib_file {
+    local machine=$1
+
     echo "LIBRARY libwg" > exports.def
     echo "EXPORTS" >> exports.def
 
@@ -51,17 +53,36 @@ function win_create_lib_file {
     "$lib_path" \
         "/def:exports.def" \
         "/out:libwg.lib" \
-        "/machine:X64"
+        "/machine:$machine"
 
 }
 
 function build_windows {
-    echo "Building wireguard-go for Windows"
+    echo "Building wireguard-go for $1"
+
+    local machine
+    case "$1" in
+        x86_64-pc-windows-msvc)
+            machine="X64"
+            ;;
+        aarch64-pc-windows-msvc)
+            # Cross compiling requires a C compiler for ARM64, such as llvm-mingw
+            export CGO_ENABLED=1
+            export GOOS=windows
+            export GOARCH=arm64
+            machine="ARM64"
+            ;;
+        *)
+            echo "Unsupported Windows target: $1"
+            return 1
+            ;;
+    esac
+
     pushd libwg
         go build -v -o libwg.dll -buildmode c-shared
-        win_create_lib_file
+        win_create_lib_file $machine
 
-        target_dir=../../build/lib/x86_64-pc-windows-msvc/
+        target_dir=../../build/lib/$1/
         mkdir -p $target_dir
         mv libwg.dll libwg.lib $target_dir
     popd
@@ -131,7 +152,7 @@ function build_wireguard_go {
     local platform="$(uname -s)";
     case  "$platform" in
         Linux*|Darwin*) build_unix ${1:-$(unix_target_triple)};;
-        MINGW*|MSYS_NT*) build_windows;;
+        MINGW*|MSYS_NT*) build_windows ${1:-x86_64-pc-windows-msvc};;
     esac
 }
 